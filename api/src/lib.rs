@@ -24,11 +24,14 @@
 #![deny(warnings)]
 
 mod config;
+mod logging;
 
 pub use crate::config::WebSocketConfig;
+use crate::logging::handle_request as handle_log_request;
+pub use crate::logging::{LogControl, LogHandle, LogRequest, LogResponse};
 use failure::Error;
 use futures::sync::mpsc::UnboundedReceiver;
-use futures::sync::oneshot;
+use futures::sync::oneshot::{self, Canceled};
 use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
 use log::*;
 use serde::Serialize;
@@ -36,8 +39,13 @@ use serde_derive::Deserialize;
 use serde_derive::Serialize;
 use serde_json;
 use std::net::SocketAddr;
-use stegos_node::{BlockAdded, EpochChanged, Node, NodeRequest, NodeResponse};
-use stegos_wallet::{Wallet, WalletNotification, WalletRequest, WalletResponse};
+use stegos_node::{
+    BlockAdded, EpochChanged, FinalityCommitment, Node, NodeRequest, NodeResponse, RandomBeacon,
+};
+use stegos_wallet::{
+    Wallet, WalletNotification, WalletRequest, WalletResponse, Wallets, WalletsRequest,
+    WalletsResponse,
+};
 use tokio::net::TcpListener;
 use tokio::runtime::TaskExecutor;
 use websocket::message::OwnedMessage;
@@ -59,6 +67,8 @@ pub type RequestId = u64;
 enum RequestKind {
     WalletRequest(WalletRequest),
     NodeRequest(NodeRequest),
+    LogRequest(LogRequest),
+    WalletsRequest(WalletsRequest),
 }
 
 #[derive(Debug, Deserialize)]
@@ -68,6 +78,9 @@ struct Request {
     kind: RequestKind,
     #[serde(default)]
     id: u64,
+    /// Name of the wallet for wallet requests, the default wallet if omitted.
+    #[serde(default)]
+    wallet: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -75,6 +88,8 @@ struct Request {
 enum ResponseKind {
     WalletResponse(WalletResponse),
     NodeResponse(NodeResponse),
+    LogResponse(LogResponse),
+    WalletsResponse(WalletsResponse),
 }
 
 fn is_default(id: &RequestId) -> bool {
@@ -88,6 +103,16 @@ struct Response {
     kind: ResponseKind,
     #[serde(skip_serializing_if = "is_default")]
     id: RequestId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wallet: Option<String>,
+}
+
+/// A notification of a named wallet.
+#[derive(Debug, Serialize)]
+struct NamedWalletNotification {
+    wallet: String,
+    #[serde(flatten)]
+    notification: WalletNotification,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -96,6 +121,9 @@ struct Response {
 pub enum NodeNotification {
     BlockAdded(BlockAdded),
     EpochChanged(EpochChanged),
+    /// A macro block became irreversible.
+    FinalityChanged(FinalityCommitment),
+    RandomBeacon(RandomBeacon),
 }
 
 /// Handler of incoming connections.
@@ -113,7 +141,13 @@ struct WebSocketHandler {
     /// Wallet events.
     wallet_notifications: UnboundedReceiver<WalletNotification>,
     /// Wallet RPC responses.
-    wallet_responses: Vec<(RequestId, oneshot::Receiver<WalletResponse>)>,
+    wallet_responses: Vec<(RequestId, Option<String>, oneshot::Receiver<WalletResponse>)>,
+    /// Named wallets API.
+    wallets: Wallets,
+    /// Events of named wallets used by this connection.
+    named_wallet_notifications: Vec<(String, UnboundedReceiver<WalletNotification>)>,
+    /// Responses to requests to manage named wallets.
+    wallets_responses: Vec<(RequestId, oneshot::Receiver<WalletsResponse>)>,
     /// Node API.
     node: Node,
     /// Node RPC responses.
@@ -122,16 +156,34 @@ struct WebSocketHandler {
     node_block_added: UnboundedReceiver<BlockAdded>,
     /// Epoch Changed Notification.
     node_epoch_changed: UnboundedReceiver<EpochChanged>,
+    /// Irreversible macro blocks.
+    node_finality_changed: UnboundedReceiver<FinalityCommitment>,
+    /// Randomness of new blocks.
+    node_random_beacon: UnboundedReceiver<RandomBeacon>,
+    /// Logging API.
+    log: LogHandle,
 }
 
 impl WebSocketHandler {
-    fn new(peer: SocketAddr, sink: WsSink, stream: WsStream, wallet: Wallet, node: Node) -> Self {
+    fn new(
+        peer: SocketAddr,
+        sink: WsSink,
+        stream: WsStream,
+        wallet: Wallet,
+        wallets: Wallets,
+        node: Node,
+        log: LogHandle,
+    ) -> Self {
         let need_flush = false;
         let wallet_notifications = wallet.subscribe();
         let wallet_responses = Vec::new();
+        let named_wallet_notifications = Vec::new();
+        let wallets_responses = Vec::new();
         let node_responses = Vec::new();
         let node_block_added = node.subscribe_block_added();
         let node_epoch_changed = node.subscribe_epoch_changed();
+        let node_finality_changed = node.subscribe_finality_changed();
+        let node_random_beacon = node.subscribe_random_beacon();
         WebSocketHandler {
             peer,
             sink,
@@ -140,10 +192,16 @@ impl WebSocketHandler {
             wallet,
             wallet_notifications,
             wallet_responses,
+            wallets,
+            named_wallet_notifications,
+            wallets_responses,
             node,
             node_responses,
             node_block_added,
             node_epoch_changed,
+            node_finality_changed,
+            node_random_beacon,
+            log,
         }
     }
 
@@ -156,14 +214,42 @@ impl WebSocketHandler {
             }
         };
         match request.kind {
-            RequestKind::WalletRequest(wallet_request) => {
-                self.wallet_responses
-                    .push((request.id, self.wallet.request(wallet_request)));
+            RequestKind::WalletRequest(wallet_request) => match request.wallet {
+                Some(name) => {
+                    // Start forwarding notifications of the wallet on its first use.
+                    if !self
+                        .named_wallet_notifications
+                        .iter()
+                        .any(|(n, _)| *n == name)
+                    {
+                        let rx = self.wallets.subscribe(name.clone());
+                        self.named_wallet_notifications.push((name.clone(), rx));
+                    }
+                    let rx = self.wallets.wallet_request(name.clone(), wallet_request);
+                    self.wallet_responses.push((request.id, Some(name), rx));
+                }
+                None => {
+                    let rx = self.wallet.request(wallet_request);
+                    self.wallet_responses.push((request.id, None, rx));
+                }
+            },
+            RequestKind::WalletsRequest(wallets_request) => {
+                self.wallets_responses
+                    .push((request.id, self.wallets.request(wallets_request)));
             }
             RequestKind::NodeRequest(node_request) => {
                 self.node_responses
                     .push((request.id, self.node.request(node_request)));
             }
+            RequestKind::LogRequest(log_request) => {
+                let response = handle_log_request(&self.log, log_request);
+                let response = Response {
+                    kind: ResponseKind::LogResponse(response),
+                    id: request.id,
+                    wallet: None,
+                };
+                self.send(response);
+            }
         }
         Ok(())
     }
@@ -245,18 +331,75 @@ impl Future for WebSocketHandler {
             }
         }
 
+        // Events of named wallets, the stream ends when the wallet is removed.
+        let named_wallet_notifications =
+            std::mem::replace(&mut self.named_wallet_notifications, Vec::new());
+        for (name, mut rx) in named_wallet_notifications {
+            loop {
+                match rx.poll() {
+                    Ok(Async::Ready(Some(notification))) => {
+                        let msg = NamedWalletNotification {
+                            wallet: name.clone(),
+                            notification,
+                        };
+                        self.send(msg);
+                    }
+                    Ok(Async::Ready(None)) => break,
+                    Ok(Async::NotReady) => {
+                        self.named_wallet_notifications.push((name, rx));
+                        break;
+                    }
+                    Err(()) => panic!("Wallet failure"),
+                }
+            }
+        }
+
         let wallet_responses = std::mem::replace(&mut self.wallet_responses, Vec::new());
-        for (id, mut rx) in wallet_responses {
+        for (id, wallet, mut rx) in wallet_responses {
             match rx.poll() {
                 Ok(Async::Ready(response)) => {
                     let response = Response {
                         kind: ResponseKind::WalletResponse(response),
                         id,
+                        wallet,
+                    };
+                    self.send(response);
+                }
+                Ok(Async::NotReady) => self.wallet_responses.push((id, wallet, rx)),
+                Err(Canceled) => {
+                    // The wallet has been closed or has failed.
+                    let error = "Wallet is disconnected".to_string();
+                    let response = Response {
+                        kind: ResponseKind::WalletResponse(WalletResponse::Error { error }),
+                        id,
+                        wallet,
+                    };
+                    self.send(response);
+                }
+            }
+        }
+
+        let wallets_responses = std::mem::replace(&mut self.wallets_responses, Vec::new());
+        for (id, mut rx) in wallets_responses {
+            match rx.poll() {
+                Ok(Async::Ready(response)) => {
+                    let response = Response {
+                        kind: ResponseKind::WalletsResponse(response),
+                        id,
+                        wallet: None,
+                    };
+                    self.send(response);
+                }
+                Ok(Async::NotReady) => self.wallets_responses.push((id, rx)),
+                Err(Canceled) => {
+                    let error = "Wallet manager is disconnected".to_string();
+                    let response = Response {
+                        kind: ResponseKind::WalletsResponse(WalletsResponse::Error { error }),
+                        id,
+                        wallet: None,
                     };
                     self.send(response);
                 }
-                Ok(Async::NotReady) => self.wallet_responses.push((id, rx)),
-                Err(_) => panic!("disconnected"),
             }
         }
 
@@ -267,11 +410,20 @@ impl Future for WebSocketHandler {
                     let response = Response {
                         kind: ResponseKind::NodeResponse(response),
                         id,
+                        wallet: None,
                     };
                     self.send(response)
                 }
                 Ok(Async::NotReady) => self.node_responses.push((id, rx)),
-                Err(_) => panic!("disconnected"),
+                Err(Canceled) => {
+                    let error = "Node is disconnected".to_string();
+                    let response = Response {
+                        kind: ResponseKind::NodeResponse(NodeResponse::Error { error }),
+                        id,
+                        wallet: None,
+                    };
+                    self.send(response)
+                }
             }
         }
 
@@ -299,6 +451,30 @@ impl Future for WebSocketHandler {
             }
         }
 
+        // Finality changes.
+        loop {
+            match self.node_finality_changed.poll().expect("connected") {
+                Async::Ready(Some(msg)) => {
+                    let msg = NodeNotification::FinalityChanged(msg);
+                    self.send(msg);
+                }
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => break, // fall through
+            }
+        }
+
+        // Randomness beacon.
+        loop {
+            match self.node_random_beacon.poll().expect("connected") {
+                Async::Ready(Some(msg)) => {
+                    let msg = NodeNotification::RandomBeacon(msg);
+                    self.send(msg);
+                }
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => break, // fall through
+            }
+        }
+
         // Flush output buffer.
         if self.need_flush {
             match self.sink.poll_complete()? {
@@ -318,10 +494,13 @@ impl WebSocketAPI {
         cfg: WebSocketConfig,
         executor: TaskExecutor,
         wallet: Wallet,
+        wallets: Wallets,
         node: Node,
+        log: LogHandle,
     ) -> Result<(), Error> {
         let executor2 = executor.clone();
         let wallet2 = wallet.clone();
+        let wallets2 = wallets.clone();
         let node2 = node.clone();
         let addr: SocketAddr = format!("{}:{}", cfg.bind_ip, cfg.bind_port).parse()?;
         info!("Starting WebSocket API on {}", &addr);
@@ -332,7 +511,9 @@ impl WebSocketAPI {
             })
             .for_each(move |s| {
                 let wallet3 = wallet2.clone();
+                let wallets3 = wallets2.clone();
                 let node3 = node2.clone();
+                let log3 = log.clone();
                 let peer = s.peer_addr().expect("has peer address");
                 debug!("[{}] accepted", peer);
                 let s = s
@@ -355,7 +536,9 @@ impl WebSocketAPI {
                                     sink,
                                     stream,
                                     wallet3.clone(),
+                                    wallets3.clone(),
                                     node3.clone(),
+                                    log3.clone(),
                                 )
                             })
                             .map_err(move |e| {
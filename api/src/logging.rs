@@ -0,0 +1,84 @@
+//! WebSocket API - Logging.
+
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Logging API requests.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "request")]
+#[serde(rename_all = "snake_case")]
+pub enum LogRequest {
+    /// Change the level of a log target, e.g. `stegos_network::kad`.
+    SetLogLevel { target: String, level: String },
+    /// Remove a runtime override and restore the default level of a target.
+    ResetLogLevel { target: String },
+    /// Show the current levels of all log targets.
+    LogLevels {},
+}
+
+/// Logging API responses.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "response")]
+#[serde(rename_all = "snake_case")]
+pub enum LogResponse {
+    LogLevels {
+        root: String,
+        levels: BTreeMap<String, String>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// An interface to the node-wide logging subsystem.
+pub trait LogControl: Send + Sync {
+    /// Sets the level of the log target.
+    fn set_level(&self, target: &str, level: &str) -> Result<(), String>;
+    /// Resets the level of the log target to the configured default.
+    fn reset_level(&self, target: &str) -> Result<(), String>;
+    /// Returns the root level and the levels of all configured targets.
+    fn levels(&self) -> (String, BTreeMap<String, String>);
+}
+
+/// A shared reference to the logging subsystem.
+pub type LogHandle = Arc<dyn LogControl>;
+
+/// Executes a logging request.
+pub(crate) fn handle_request(log: &LogHandle, request: LogRequest) -> LogResponse {
+    let result = match request {
+        LogRequest::SetLogLevel { target, level } => log.set_level(&target, &level),
+        LogRequest::ResetLogLevel { target } => log.reset_level(&target),
+        LogRequest::LogLevels {} => Ok(()),
+    };
+    match result {
+        Ok(()) => {
+            let (root, levels) = log.levels();
+            LogResponse::LogLevels { root, levels }
+        }
+        Err(error) => LogResponse::Error { error },
+    }
+}
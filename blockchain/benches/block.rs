@@ -0,0 +1,82 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+#![cfg_attr(test, feature(test))]
+use std::time::{Duration, SystemTime};
+use stegos_blockchain::{
+    create_micro_block_with_coinbase, genesis, Block, Blockchain, BlockchainConfig, ListDb,
+    ListDbBatch,
+};
+use stegos_crypto::hash::Hash;
+use stegos_keychain::KeyChain;
+extern crate test;
+use test::Bencher;
+
+fn write_block(db: &ListDb, block: &Block, batched: bool) {
+    let block_hash = Hash::digest(block);
+    let mut batch = ListDbBatch::default();
+    batch.insert(1, block).unwrap();
+    if !batched {
+        db.write(batch).unwrap();
+        batch = ListDbBatch::default();
+    }
+    batch.set_tip(2, &block_hash).unwrap();
+    db.write(batch).unwrap();
+}
+
+#[bench]
+fn write_block_separately(b: &mut Bencher) {
+    let keychains = [KeyChain::new_mem()];
+    let block = Block::MacroBlock(genesis(&keychains, 100, 1000, SystemTime::now()));
+    let db = ListDb::testing();
+    b.iter(|| write_block(&db, &block, false));
+}
+
+#[bench]
+fn write_block_batched(b: &mut Bencher) {
+    let keychains = [KeyChain::new_mem()];
+    let block = Block::MacroBlock(genesis(&keychains, 100, 1000, SystemTime::now()));
+    let db = ListDb::testing();
+    b.iter(|| write_block(&db, &block, true));
+}
+
+#[bench]
+fn push_pop_micro_block(b: &mut Bencher) {
+    let keychains = [KeyChain::new_mem()];
+    let mut timestamp = SystemTime::now();
+    let cfg: BlockchainConfig = Default::default();
+    let genesis = genesis(
+        &keychains,
+        cfg.min_stake_amount,
+        10 * cfg.min_stake_amount,
+        timestamp,
+    );
+    let mut chain = Blockchain::testing(cfg, genesis, timestamp).expect("genesis is valid");
+    timestamp += Duration::from_millis(1);
+    let block =
+        create_micro_block_with_coinbase(&chain, &keychains, timestamp).expect("block is valid");
+    b.iter(|| {
+        chain
+            .push_micro_block(block.clone(), timestamp)
+            .expect("block is valid");
+        chain.pop_micro_block().expect("block can be reverted");
+    });
+}
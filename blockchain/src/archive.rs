@@ -0,0 +1,360 @@
+//! Blockchain - Portable Archives.
+
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Layout of an archive:
+//!
+//! ```text
+//! magic        8 bytes  "STGCHAIN"
+//! version      u32
+//! chain_id     32 bytes
+//! blocks       u64      the number of blocks, starting from the genesis
+//! last_block   32 bytes hash of the last block
+//! frames       (u32 length, snappy-compressed protobuf of Block)*
+//! end          u32      zero
+//! checksum     32 bytes hash of all uncompressed frames
+//! ```
+//!
+//! All integers are big-endian.
+//!
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::config::Checkpoint;
+use crate::error::ArchiveError;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use failure::Error;
+use log::*;
+use std::io::{Read, Write};
+use std::time::SystemTime;
+use stegos_crypto::hash::{Hash, Hasher, HASH_SIZE};
+use stegos_serialization::traits::ProtoConvert;
+
+const ARCHIVE_MAGIC: &'static [u8; 8] = b"STGCHAIN";
+const ARCHIVE_VERSION: u32 = 1;
+/// Upper bound of a compressed or decompressed frame.
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Header of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveHeader {
+    /// Identifier of the chain, see `BlockchainConfig::chain_id`.
+    pub chain_id: Hash,
+    /// The number of blocks, i.e. the height of the exported chain.
+    pub blocks: u64,
+    /// Hash of the last block.
+    pub last_block: Hash,
+}
+
+impl ArchiveHeader {
+    ///
+    /// A checkpoint on the last block, which allows to skip verification of
+    /// block signatures on import together with `assume_valid`.
+    ///
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        if self.blocks == 0 {
+            return None;
+        }
+        Some(Checkpoint {
+            height: self.blocks - 1,
+            block_hash: self.last_block,
+        })
+    }
+}
+
+fn read_hash<R: Read>(reader: &mut R) -> Result<Hash, Error> {
+    let mut buf = [0u8; HASH_SIZE];
+    reader.read_exact(&mut buf)?;
+    Ok(Hash::try_from_bytes(&buf)?)
+}
+
+///
+/// Write blocks of the chain up to `height` (exclusive), or all blocks if None.
+///
+pub fn export_chain<W: Write>(
+    chain: &Blockchain,
+    height: Option<u64>,
+    mut writer: W,
+) -> Result<ArchiveHeader, Error> {
+    let blocks = height.unwrap_or(chain.height()).min(chain.height());
+    let last_block = if blocks > 0 {
+        Hash::digest(&chain.block_by_height(blocks - 1)?)
+    } else {
+        Hash::zero()
+    };
+    let header = ArchiveHeader {
+        chain_id: chain.chain_id(),
+        blocks,
+        last_block,
+    };
+
+    writer.write_all(ARCHIVE_MAGIC)?;
+    writer.write_u32::<BigEndian>(ARCHIVE_VERSION)?;
+    writer.write_all(&header.chain_id.to_bytes())?;
+    writer.write_u64::<BigEndian>(header.blocks)?;
+    writer.write_all(&header.last_block.to_bytes())?;
+
+    let mut hasher = Hasher::new();
+    for block in chain.blocks().take(blocks as usize) {
+        let data = block.into_buffer()?;
+        hasher.input(&data);
+        let compressed = snap::Encoder::new().compress_vec(&data)?;
+        writer.write_u32::<BigEndian>(compressed.len() as u32)?;
+        writer.write_all(&compressed)?;
+    }
+    writer.write_u32::<BigEndian>(0)?;
+    writer.write_all(&hasher.result().to_bytes())?;
+    writer.flush()?;
+    info!(
+        "Exported chain: blocks={}, last_block={}",
+        header.blocks, header.last_block
+    );
+    Ok(header)
+}
+
+///
+/// Reads blocks from an archive.
+///
+/// The checksum is verified after the last block, so the iterator returns
+/// an error instead of the end if the archive is corrupted.
+///
+pub struct ArchiveReader<R: Read> {
+    reader: R,
+    header: ArchiveHeader,
+    hasher: Hasher,
+    /// The number of blocks read.
+    read: u64,
+    done: bool,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    /// Read the header of the archive.
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != ARCHIVE_MAGIC {
+            return Err(ArchiveError::InvalidMagic.into());
+        }
+        let version = reader.read_u32::<BigEndian>()?;
+        if version != ARCHIVE_VERSION {
+            return Err(ArchiveError::UnsupportedVersion(version).into());
+        }
+        let chain_id = read_hash(&mut reader)?;
+        let blocks = reader.read_u64::<BigEndian>()?;
+        let last_block = read_hash(&mut reader)?;
+        let header = ArchiveHeader {
+            chain_id,
+            blocks,
+            last_block,
+        };
+        Ok(ArchiveReader {
+            reader,
+            header,
+            hasher: Hasher::new(),
+            read: 0,
+            done: false,
+        })
+    }
+
+    pub fn header(&self) -> &ArchiveHeader {
+        &self.header
+    }
+
+    fn read_block(&mut self) -> Result<Option<Block>, Error> {
+        let len = self.reader.read_u32::<BigEndian>()? as usize;
+        if len == 0 {
+            let checksum = read_hash(&mut self.reader)?;
+            let expected = self.hasher.result();
+            if checksum != expected {
+                return Err(ArchiveError::ChecksumMismatch(expected, checksum).into());
+            }
+            if self.read != self.header.blocks {
+                return Err(ArchiveError::BlockCountMismatch(self.header.blocks, self.read).into());
+            }
+            return Ok(None);
+        }
+        if len > MAX_FRAME_SIZE {
+            return Err(ArchiveError::FrameTooLarge(len).into());
+        }
+        let mut compressed = vec![0u8; len];
+        self.reader.read_exact(&mut compressed)?;
+        if snap::decompress_len(&compressed)? > MAX_FRAME_SIZE {
+            return Err(ArchiveError::FrameTooLarge(len).into());
+        }
+        let data = snap::Decoder::new().decompress_vec(&compressed)?;
+        self.hasher.input(&data);
+        self.read += 1;
+        Ok(Some(Block::from_buffer(&data)?))
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = Result<Block, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_block() {
+            Ok(Some(block)) => Some(Ok(block)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+///
+/// Apply blocks from an archive to the chain with full validation.
+///
+/// Blocks which are already in the chain are skipped after comparing hashes.
+/// Construct the chain with `ArchiveHeader::checkpoint()` and `assume_valid`
+/// to skip verification of block signatures for trusted archives.
+/// Returns the number of applied blocks.
+///
+pub fn import_chain<R: Read>(
+    chain: &mut Blockchain,
+    archive: ArchiveReader<R>,
+) -> Result<u64, Error> {
+    if archive.header().chain_id != chain.chain_id() {
+        return Err(
+            ArchiveError::ChainIdMismatch(chain.chain_id(), archive.header().chain_id).into(),
+        );
+    }
+    let mut height: u64 = 0;
+    let mut applied: u64 = 0;
+    for block in archive {
+        let block = block?;
+        let block_hash = Hash::digest(&block);
+        if height < chain.height() {
+            let local_hash = Hash::digest(&chain.block_by_height(height)?);
+            if local_hash != block_hash {
+                return Err(ArchiveError::BlockMismatch(height, local_hash, block_hash).into());
+            }
+        } else {
+            let timestamp = SystemTime::now();
+            match block {
+                Block::MacroBlock(block) => {
+                    chain.push_macro_block(block, timestamp)?;
+                }
+                Block::MicroBlock(block) => {
+                    chain.push_micro_block(block, timestamp)?;
+                }
+            }
+            applied += 1;
+            if applied % 1000 == 0 {
+                info!("Importing chain: height={}", chain.height());
+            }
+        }
+        height += 1;
+    }
+    info!(
+        "Imported chain: applied={}, height={}, last_block={}",
+        applied,
+        chain.height(),
+        chain.last_block_hash()
+    );
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::create_fake_micro_block;
+    use crate::config::BlockchainConfig;
+    use crate::genesis::genesis;
+    use std::time::Duration;
+    use stegos_keychain::KeyChain;
+
+    #[test]
+    fn export_import() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let mut chain = Blockchain::testing(cfg.clone(), genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+        for _ in 0..3 {
+            timestamp += Duration::from_millis(1);
+            let (block, _input_hashes, _output_hashes) =
+                create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+            chain
+                .push_micro_block(block, timestamp)
+                .expect("block is valid");
+        }
+
+        let mut buf = Vec::new();
+        let header = export_chain(&chain, None, &mut buf).unwrap();
+        assert_eq!(header.blocks, chain.height());
+        assert_eq!(header.last_block, chain.last_block_hash());
+
+        // Full validation.
+        let archive = ArchiveReader::new(&buf[..]).unwrap();
+        assert_eq!(archive.header(), &header);
+        let mut chain2 = Blockchain::testing(cfg.clone(), genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+        let applied = import_chain(&mut chain2, archive).unwrap();
+        assert_eq!(applied, chain.height() - 1);
+        assert_eq!(chain2.last_block_hash(), chain.last_block_hash());
+
+        // Trusted mode.
+        let archive = ArchiveReader::new(&buf[..]).unwrap();
+        let mut trusted_cfg = cfg.clone();
+        trusted_cfg.checkpoints = vec![archive.header().checkpoint().unwrap()];
+        trusted_cfg.assume_valid = true;
+        let mut chain3 = Blockchain::testing(trusted_cfg, genesis, timestamp)
+            .expect("Failed to create blockchain");
+        import_chain(&mut chain3, archive).unwrap();
+        assert_eq!(chain3.last_block_hash(), chain.last_block_hash());
+
+        // Partial export.
+        let mut partial = Vec::new();
+        let header = export_chain(&chain, Some(2), &mut partial).unwrap();
+        assert_eq!(header.blocks, 2);
+        let blocks: Vec<Block> = ArchiveReader::new(&partial[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(blocks.len(), 2);
+
+        // Corrupted checksum.
+        let len = buf.len();
+        buf[len - 1] ^= 0xFF;
+        let result: Result<Vec<Block>, Error> = ArchiveReader::new(&buf[..]).unwrap().collect();
+        assert!(result.is_err());
+    }
+}
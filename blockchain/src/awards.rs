@@ -55,8 +55,17 @@ impl Awards {
         }
     }
 
+    /// Returns the current service award budget.
+    pub fn budget(&self) -> i64 {
+        self.budget
+    }
+
     fn add_reward(&mut self, piece: i64) {
-        assert!(piece > 0);
+        assert!(piece >= 0);
+        if piece == 0 {
+            // The reward has decayed completely.
+            return;
+        }
         self.budget += piece;
         debug!(
             "Adding reward to service awards budget: amount={}, total_budget={}",
@@ -21,18 +21,21 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::error::TransactionError;
+use crate::error::{BlockBuilderError, TransactionError};
 use crate::merkle::*;
 use crate::output::*;
 use crate::transaction::Transaction;
 use crate::view_changes::ViewChangeProof;
 use bitvector::BitVector;
+use serde::de::{Deserialize, Deserializer, Error as SerdeError};
+use serde::ser::{Error as SerError, Serialize, Serializer};
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::time::SystemTime;
 use stegos_crypto::curve1174::Fr;
 use stegos_crypto::hash::{Hash, Hashable, Hasher};
 use stegos_crypto::pbc;
+use stegos_serialization::traits::ProtoConvert;
 
 /// Blockchain version.
 pub const VERSION: u64 = 1;
@@ -49,6 +52,10 @@ pub struct BaseBlockHeader {
     /// Version number.
     pub version: u64,
 
+    /// Identifier of the chain, see `BlockchainConfig::chain_id`.
+    /// Zero only in genesis blocks created before chain identifiers were introduced.
+    pub chain_id: Hash,
+
     /// Hash of the block previous to this in the chain.
     pub previous: Hash,
 
@@ -68,6 +75,7 @@ pub struct BaseBlockHeader {
 impl BaseBlockHeader {
     pub fn new(
         version: u64,
+        chain_id: Hash,
         previous: Hash,
         height: u64,
         view_change: u32,
@@ -78,6 +86,7 @@ impl BaseBlockHeader {
 
         BaseBlockHeader {
             version,
+            chain_id,
             previous,
             height,
             view_change,
@@ -90,6 +99,10 @@ impl BaseBlockHeader {
 impl Hashable for BaseBlockHeader {
     fn hash(&self, state: &mut Hasher) {
         self.version.hash(state);
+        // Keep hashes of legacy genesis blocks.
+        if self.chain_id != Hash::zero() {
+            self.chain_id.hash(state);
+        }
         self.previous.hash(state);
         self.height.hash(state);
         self.view_change.hash(state);
@@ -175,11 +188,40 @@ impl MicroBlock {
     }
 
     /// Sign block using leader's signature.
-    pub fn sign(&mut self, skey: &pbc::SecretKey, pkey: &pbc::PublicKey) {
-        assert_eq!(&self.pkey, pkey);
+    pub fn sign(
+        &mut self,
+        skey: &pbc::SecretKey,
+        pkey: &pbc::PublicKey,
+    ) -> Result<(), BlockBuilderError> {
+        if &self.pkey != pkey {
+            return Err(BlockBuilderError::SignerMismatch(self.pkey, *pkey));
+        }
         let hash = Hash::digest(self);
         let sig = pbc::sign_hash(&hash, &skey);
         self.sig = sig;
+        Ok(())
+    }
+}
+
+/// Serialized as a hex-encoded protobuf, suitable for exporting.
+impl Serialize for MicroBlock {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let buf = self.into_buffer().map_err(S::Error::custom)?;
+        serializer.serialize_str(&hex::encode(&buf))
+    }
+}
+
+impl<'de> Deserialize<'de> for MicroBlock {
+    fn deserialize<D>(deserializer: D) -> Result<MicroBlock, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let buf = hex::decode(&s).map_err(D::Error::custom)?;
+        MicroBlock::from_buffer(&buf).map_err(D::Error::custom)
     }
 }
 
@@ -272,7 +314,7 @@ impl MacroBlock {
         block_reward: i64,
         activity_map: BitVector,
         pkey: pbc::PublicKey,
-    ) -> Result<MacroBlock, TransactionError> {
+    ) -> Result<MacroBlock, BlockBuilderError> {
         //
         // Collect transactions.
         //
@@ -312,7 +354,7 @@ impl MacroBlock {
         //
         let inputs: Vec<Hash> = inputs.into_iter().collect();
         let outputs: Vec<Output> = outputs.into_iter().map(|(_, o)| o).collect();
-        let block = MacroBlock::new(
+        MacroBlock::try_new(
             base,
             gamma,
             block_reward,
@@ -320,10 +362,16 @@ impl MacroBlock {
             &inputs,
             &outputs,
             pkey,
-        );
-        Ok(block)
+        )
     }
 
+    ///
+    /// Create a new macro block from the list of inputs and outputs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if inputs or outputs are not unique. Use `try_new()` for untrusted data.
+    ///
     pub fn new(
         base: BaseBlockHeader,
         gamma: Fr,
@@ -333,14 +381,43 @@ impl MacroBlock {
         outputs: &[Output],
         pkey: pbc::PublicKey,
     ) -> MacroBlock {
+        match MacroBlock::try_new(
+            base,
+            gamma,
+            block_reward,
+            activity_map,
+            inputs,
+            outputs,
+            pkey,
+        ) {
+            Ok(block) => block,
+            Err(e) => panic!("Failed to create a macro block: {}", e),
+        }
+    }
+
+    ///
+    /// Create a new macro block from the list of inputs and outputs.
+    /// Fails if inputs or outputs are not unique.
+    ///
+    pub fn try_new(
+        base: BaseBlockHeader,
+        gamma: Fr,
+        block_reward: i64,
+        activity_map: BitVector,
+        inputs: &[Hash],
+        outputs: &[Output],
+        pkey: pbc::PublicKey,
+    ) -> Result<MacroBlock, BlockBuilderError> {
         // Re-order all inputs to blur transaction boundaries.
         // Current algorithm just sorts this list.
         // Since Hash is random, it has the same effect as shuffling.
-        let inputs_len = inputs.len();
         let mut inputs: Vec<Hash> = inputs.iter().cloned().collect();
         inputs.sort();
-        inputs.dedup(); // should do nothing
-        assert_eq!(inputs.len(), inputs_len, "inputs must be unique");
+        for pair in inputs.windows(2) {
+            if pair[0] == pair[1] {
+                return Err(BlockBuilderError::DuplicateInput(pair[0]));
+            }
+        }
 
         // Calculate input_range_hash.
         let inputs_range_hash: Hash = {
@@ -354,14 +431,16 @@ impl MacroBlock {
         };
 
         // Re-order all outputs to blur transaction boundaries.
-        let outputs_len = outputs.len();
         let mut outputs: Vec<(Hash, Box<Output>)> = outputs
             .iter()
             .map(|o| (Hash::digest(o), Box::<Output>::new(o.clone())))
             .collect();
         outputs.sort_by(|(h1, _o1), (h2, _o2)| h1.cmp(h2));
-        outputs.dedup_by(|(h1, _o1), (h2, _o2)| h1 == h2); // should do nothing
-        assert_eq!(outputs.len(), outputs_len, "outputs must be unique");
+        for pair in outputs.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(BlockBuilderError::DuplicateOutput(pair[0].0));
+            }
+        }
         let outputs: Vec<Box<Output>> = outputs.into_iter().map(|(_h, o)| o).collect();
 
         // Create Merkle Tree and calculate outputs_range_hash.
@@ -390,7 +469,7 @@ impl MacroBlock {
         };
 
         // Create the block.
-        MacroBlock { header, body }
+        Ok(MacroBlock { header, body })
     }
 }
 
@@ -23,24 +23,35 @@
 
 use crate::awards::{Awards, ValidatorAwardState};
 use crate::block::*;
+use crate::clock::{Clock, SystemClock};
 use crate::config::*;
-use crate::election::ElectionInfo;
 use crate::election::{self, mix, ElectionResult};
+use crate::election::{ElectionInfo, LeaderSelectionAudit, RandomBeacon};
+use crate::epoch_summary::{EpochBlock, EpochSummary};
 use crate::error::*;
 use crate::escrow::*;
+use crate::explorer::ExplorerIndex;
+use crate::finality::{Finality, FinalityCommitment};
 use crate::merkle::*;
 use crate::metrics;
 use crate::multisignature::create_multi_signature;
 use crate::mvcc::MultiVersionedMap;
 use crate::output::*;
-use crate::storage::ListDb;
+use crate::proof::*;
+use crate::snapshot::UtxoSnapshot;
+use crate::storage::{ListDb, ListDbBatch};
+use crate::stream::ValidatedMicroBlock;
 use crate::transaction::{CoinbaseTransaction, PaymentTransaction, Transaction};
+use crate::utxo_filter::OutputFilter;
 use crate::view_changes::ViewChangeProof;
 use bitvector::BitVector;
-use failure::Error;
+use failure::{format_err, Error};
 use log::*;
+use serde_derive::Serialize;
 use std::collections::BTreeMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use stegos_crypto::bulletproofs::fee_a;
 use stegos_crypto::curve1174::{ECp, Fr, PublicKey, SecretKey, G};
 use stegos_crypto::hash::*;
@@ -54,18 +65,24 @@ pub type ValidatorId = u32;
 /// Information of current chain, that is used as proof of viewchange.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct ChainInfo {
+    /// Identifier of the chain, see `BlockchainConfig::chain_id`.
+    pub chain_id: Hash,
+    /// Prevents replaying messages from other epochs with the same height.
+    pub epoch: u64,
     pub height: u64,
     pub view_change: ViewCounter,
     pub last_block: Hash,
 }
 
 impl ChainInfo {
-    /// Create ChainInfo from micro block.
+    /// Create ChainInfo from micro block of the given epoch.
     /// ## Panics
     /// if view_change is equal to 0
-    pub fn from_micro_block(micro_block: &MicroBlock) -> Self {
+    pub fn from_micro_block(micro_block: &MicroBlock, epoch: u64) -> Self {
         assert_ne!(micro_block.base.view_change, 0);
         ChainInfo {
+            chain_id: micro_block.base.chain_id,
+            epoch,
             height: micro_block.base.height,
             view_change: micro_block.base.view_change - 1,
             last_block: micro_block.base.previous,
@@ -75,6 +92,8 @@ impl ChainInfo {
     /// Create ChainInfo from blockchain.
     pub fn from_blockchain(blockchain: &Blockchain) -> Self {
         ChainInfo {
+            chain_id: blockchain.chain_id(),
+            epoch: blockchain.epoch(),
             height: blockchain.height(),
             view_change: blockchain.view_change(),
             last_block: blockchain.last_block_hash(),
@@ -84,6 +103,8 @@ impl ChainInfo {
 
 impl Hashable for ChainInfo {
     fn hash(&self, hasher: &mut Hasher) {
+        self.chain_id.hash(hasher);
+        self.epoch.hash(hasher);
         self.height.hash(hasher);
         self.view_change.hash(hasher);
         self.last_block.hash(hasher);
@@ -92,7 +113,7 @@ impl Hashable for ChainInfo {
 
 /// A helper to find UTXO in this blockchain.
 #[derive(Debug, Clone)]
-enum OutputKey {
+pub(crate) enum OutputKey {
     MacroBlock {
         /// Block Height.
         height: u64,
@@ -109,6 +130,85 @@ enum OutputKey {
     },
 }
 
+impl OutputKey {
+    /// Height of the block which created the output.
+    pub(crate) fn height(&self) -> u64 {
+        match self {
+            OutputKey::MacroBlock { height, .. } => *height,
+            OutputKey::MicroBlock { height, .. } => *height,
+        }
+    }
+
+    /// Resolve the output in the block at `self.height()`.
+    /// Returns None if the output has been pruned.
+    pub(crate) fn resolve(&self, block: &Block) -> Option<Output> {
+        match (self, block) {
+            (OutputKey::MacroBlock { path, .. }, Block::MacroBlock(MacroBlock { body, .. })) => {
+                body.outputs
+                    .lookup(path)
+                    .map(|output| output.as_ref().clone())
+            }
+            (
+                OutputKey::MicroBlock {
+                    tx_id, txout_id, ..
+                },
+                Block::MicroBlock(MicroBlock { transactions, .. }),
+            ) => {
+                let tx = transactions
+                    .get(*tx_id as usize)
+                    .expect("Corrupted outputs_by_hash (Micro-2)");
+                let output = tx
+                    .txouts()
+                    .get(*txout_id as usize)
+                    .expect("Corrupted outputs_by_hash (Micro-3)");
+                Some(output.clone())
+            }
+            (OutputKey::MacroBlock { .. }, Block::MicroBlock(_)) => {
+                panic!("Corrupted outputs_by_hash (Macro-0)")
+            }
+            (OutputKey::MicroBlock { .. }, Block::MacroBlock(_)) => {
+                panic!("Corrupted outputs_by_hash (Micro-0)")
+            }
+        }
+    }
+}
+
+/// A helper to find committed transactions in this blockchain.
+#[derive(Debug, Clone)]
+pub(crate) struct TransactionKey {
+    /// Height of micro block.
+    pub(crate) height: u64,
+    /// Transaction number.
+    pub(crate) tx_id: u32,
+}
+
+/// Status of a transaction in this blockchain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status")]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// Transaction hasn't been committed and none of its inputs were spent.
+    Unknown,
+    /// Transaction has been committed.
+    Committed {
+        /// Height of the block which contains the transaction.
+        height: u64,
+        /// Hash of the block which contains the transaction.
+        block_hash: Hash,
+        /// Transaction number inside the block.
+        tx_id: u32,
+    },
+    /// Transaction hasn't been committed, but some of its inputs were spent elsewhere.
+    Conflicted {
+        /// Hash of the spent input.
+        input: Hash,
+        /// Height of the block which spent the input.
+        height: u64,
+        /// Hash of the block which spent the input.
+        block_hash: Hash,
+    },
+}
+
 /// A helper to store the global monetary balance in MultiVersionedMap.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Balance {
@@ -127,7 +227,7 @@ pub(crate) struct LSN(u64); // use `struct` to disable explicit casts.
 const INITIAL_LSN: LSN = LSN(0);
 
 /// Create LSN for MultiVersionedMap from height.
-fn lsn_for_height(height: u64) -> LSN {
+pub(crate) fn lsn_for_height(height: u64) -> LSN {
     // The first block has height=0.
     // Shift LSN to reserve 0 value for a point before the first block.
     LSN(height + 1)
@@ -137,6 +237,8 @@ type BlockByHashMap = MultiVersionedMap<Hash, u64, LSN>;
 type OutputByHashMap = MultiVersionedMap<Hash, OutputKey, LSN>;
 type BalanceMap = MultiVersionedMap<(), Balance, LSN>;
 type ValidatorsActivity = MultiVersionedMap<pbc::PublicKey, ValidatorAwardState, LSN>;
+type EpochBlocks = MultiVersionedMap<u64, EpochBlock, LSN>;
+type ServiceAwardMap = MultiVersionedMap<(), (PublicKey, i64), LSN>;
 
 /// The blockchain database.
 pub struct Blockchain {
@@ -144,6 +246,8 @@ pub struct Blockchain {
     // Configuration.
     //
     cfg: BlockchainConfig,
+    /// Source of the current time.
+    clock: Arc<dyn Clock>,
 
     //
     // Storage.
@@ -154,10 +258,14 @@ pub struct Blockchain {
     block_by_hash: BlockByHashMap,
     /// In-memory index to lookup UTXO by its hash.
     output_by_hash: OutputByHashMap,
+    /// Filter of UTXO hashes to skip lookups of unknown outputs.
+    output_filter: Option<OutputFilter>,
     /// Global monetary balance.
     balance: BalanceMap,
     /// In-memory storage of stakes.
     escrow: Escrow,
+    /// Optional secondary indexes for block explorers.
+    explorer: Option<ExplorerIndex>,
 
     //
     // Epoch Information.
@@ -168,10 +276,14 @@ pub struct Blockchain {
     epoch: u64,
     /// Zero-indexed identifier of the last macro block.
     last_macro_block_height: u64,
+    /// Hash of the last macro block.
+    last_macro_block_hash: Hash,
     /// A timestamp from the last macro block.
     last_macro_block_timestamp: SystemTime,
     /// Last election result.
     election_result: ElectionResult,
+    /// The last irreversible macro block.
+    finality: Finality,
     //
     // Consensus information.
     //
@@ -183,6 +295,15 @@ pub struct Blockchain {
     //
     awards: Awards,
     epoch_activity: ValidatorsActivity,
+    /// Winner and amount of the service award paid in the last macro block, if any.
+    last_service_award: ServiceAwardMap,
+
+    //
+    // Epoch summaries.
+    //
+    /// Micro blocks of the current epoch, by height.
+    /// Summaries of finalized epochs are kept on the disk.
+    epoch_blocks: EpochBlocks,
 
     //
     // Height Information.
@@ -191,6 +312,8 @@ pub struct Blockchain {
     height: u64,
     /// Copy of a block hash from the latest registered block.
     last_block_hash: Hash,
+    /// A timestamp from the latest registered block.
+    last_block_timestamp: SystemTime,
 }
 
 impl Blockchain {
@@ -205,6 +328,21 @@ impl Blockchain {
         timestamp: SystemTime,
     ) -> Result<Blockchain, Error> {
         let database = ListDb::new(&storage_cfg.database_path);
+        if storage_cfg.compaction_interval > 0 {
+            database.start_compaction(Duration::from_secs(storage_cfg.compaction_interval));
+        }
+        Self::with_db(cfg, database, genesis, timestamp)
+    }
+
+    /// Opens an existing database without modifying it, e.g. for inspection.
+    /// Fails if the database doesn't exist or is empty.
+    pub fn open_read_only(
+        cfg: BlockchainConfig,
+        storage_cfg: StorageConfig,
+        genesis: MacroBlock,
+        timestamp: SystemTime,
+    ) -> Result<Blockchain, Error> {
+        let database = ListDb::open_read_only(&storage_cfg.database_path)?;
         Self::with_db(cfg, database, genesis, timestamp)
     }
 
@@ -228,6 +366,11 @@ impl Blockchain {
         //
         let block_by_hash: BlockByHashMap = BlockByHashMap::new();
         let output_by_hash: OutputByHashMap = OutputByHashMap::new();
+        let output_filter = if cfg.utxo_filter_false_positive_rate > 0.0 {
+            Some(OutputFilter::new(0, cfg.utxo_filter_false_positive_rate))
+        } else {
+            None
+        };
         let mut balance: BalanceMap = BalanceMap::new();
         let initial_balance = Balance {
             created: ECp::inf(),
@@ -237,14 +380,21 @@ impl Blockchain {
         };
         balance.insert(INITIAL_LSN, (), initial_balance);
         let escrow = Escrow::new();
+        let explorer = if cfg.explorer_index {
+            Some(ExplorerIndex::new())
+        } else {
+            None
+        };
 
         //
         // Epoch Information.
         //
         let epoch: u64 = 0;
         let last_macro_block_height: u64 = 0;
+        let last_macro_block_hash = Hash::digest("genesis");
         let last_macro_block_timestamp = UNIX_EPOCH;
         let election_result = ElectionResult::default();
+        let finality = Finality::new();
 
         //
         // Consensus information.
@@ -256,29 +406,44 @@ impl Blockchain {
         //
         let awards = Awards::new(cfg.awards_difficulty);
         let epoch_activity = MultiVersionedMap::new();
+        let last_service_award = ServiceAwardMap::new();
+
+        //
+        // Epoch summaries.
+        //
+        let epoch_blocks = EpochBlocks::new();
 
         //
         // Height Information.
         //
         let height: u64 = 0;
         let last_block_hash = Hash::digest("genesis");
+        let last_block_timestamp = UNIX_EPOCH;
 
         let mut blockchain = Blockchain {
             cfg,
+            clock: Arc::new(SystemClock),
             database,
             block_by_hash,
             output_by_hash,
+            output_filter,
             balance,
             escrow,
+            explorer,
             epoch,
             last_macro_block_height,
+            last_macro_block_hash,
             last_macro_block_timestamp,
             election_result,
+            finality,
             view_change_proof,
             awards,
             epoch_activity,
+            last_service_award,
+            epoch_blocks,
             height,
             last_block_hash,
+            last_block_timestamp,
         };
 
         blockchain.recover(genesis, timestamp)?;
@@ -290,15 +455,20 @@ impl Blockchain {
     //----------------------------------------------------------------------------------------------
 
     fn recover(&mut self, genesis: MacroBlock, timestamp: SystemTime) -> Result<(), Error> {
-        let mut blocks = self.database.iter();
+        let mut blocks = self.database.iter_checked();
 
         let genesis_hash = Hash::digest(&genesis);
 
         let block = blocks.next();
-        let block = if let Some(block) = block {
-            block
+        let block = if let Some((_height, block)) = block {
+            block?
+        } else if self.database.is_read_only() {
+            return Err(format_err!("The database is empty"));
         } else {
             debug!("Creating a new blockchain...");
+            let mut batch = ListDbBatch::default();
+            batch.set_indexes()?;
+            self.database.write(batch)?;
             self.push_macro_block(genesis, timestamp)?;
             info!(
                 "Initialized a new blockchain: height={}, last_block={}",
@@ -319,11 +489,74 @@ impl Blockchain {
             );
         }
 
-        // Recover remaining blocks.
-        for block in blocks {
+        // Recover remaining blocks, up to the first damaged one.
+        let mut damaged_height: Option<u64> = None;
+        for (height, block) in blocks {
+            let block = match block {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!("Damaged block on the disk: height={}, error={}", height, e);
+                    damaged_height = Some(self.height);
+                    break;
+                }
+            };
+            let header = block.base_header();
+            if height != self.height
+                || header.height != height
+                || header.previous != self.last_block_hash
+            {
+                warn!(
+                    "Inconsistent block on the disk: height={}, block={}, previous={}, expected_previous={}",
+                    height,
+                    Hash::digest(&block),
+                    header.previous,
+                    self.last_block_hash
+                );
+                damaged_height = Some(self.height);
+                break;
+            }
             self.recover_block(block, timestamp)?;
         }
 
+        match self.database.tip() {
+            Ok(Some((height, last_block_hash)))
+                if damaged_height.is_none()
+                    && (height != self.height || last_block_hash != self.last_block_hash) =>
+            {
+                warn!(
+                    "The last block on the disk differs from the recorded tip: height={}, last_block={}, tip_height={}, tip_block={}",
+                    self.height, self.last_block_hash, height, last_block_hash
+                );
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Damaged tip record on the disk: error={}", e),
+        }
+
+        // Roll back to the last consistent height.
+        if let Some(height) = damaged_height {
+            if self.database.is_read_only() {
+                warn!(
+                    "Ignored the damaged tail of the read-only blockchain: height={}",
+                    height
+                );
+            } else {
+                let removed = self.database.truncate(height)?;
+                warn!(
+                    "Rolled back the damaged tail of the blockchain: height={}, removed_blocks={}",
+                    height, removed
+                );
+            }
+        }
+
+        // Rebuild on-disk indexes after damaged blocks or for databases created by older versions.
+        if !self.database.has_indexes()? {
+            if self.database.is_read_only() {
+                warn!("The read-only database has no indexes, historical outputs and transactions won't be found");
+            } else {
+                self.build_indexes()?;
+            }
+        }
+
         info!(
             "Recovered blockchain from the disk: height={}, last_block={}",
             self.height, self.last_block_hash
@@ -332,6 +565,21 @@ impl Blockchain {
         Ok(())
     }
 
+    fn build_indexes(&self) -> Result<(), Error> {
+        info!("Indexing blocks on the disk: height={}", self.height);
+        self.database.drop_indexes()?;
+        for (height, block) in self.database.iter().take(self.height as usize).enumerate() {
+            let mut batch = ListDbBatch::default();
+            batch.index(height as u64, &block)?;
+            self.database.write(batch)?;
+        }
+        let mut batch = ListDbBatch::default();
+        batch.set_indexes()?;
+        self.database.write_sync(batch)?;
+        info!("Indexed blocks on the disk: height={}", self.height);
+        Ok(())
+    }
+
     fn recover_block(&mut self, block: Block, timestamp: SystemTime) -> Result<(), Error> {
         debug!(
             "Recovering a block from the disk: height={}, block={}",
@@ -356,6 +604,81 @@ impl Blockchain {
         Ok(())
     }
 
+    ///
+    /// Checks that blocks on the disk form the same hash chain as the in-memory state.
+    /// Returns the height of the first damaged or inconsistent block, if any.
+    ///
+    pub fn check_database(&self) -> Option<u64> {
+        let check = self.database.check();
+        if check.height < self.height {
+            Some(check.height)
+        } else if check.last_block_hash != Some(self.last_block_hash) {
+            // The disk has a different tip.
+            Some(self.height - 1)
+        } else if check.records > self.height {
+            // Leftovers beyond the tip.
+            Some(self.height)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Returns true if the block exists on the disk and can be decoded.
+    ///
+    pub fn is_block_readable(&self, height: u64) -> bool {
+        if let Ok(Some(_)) = self.database.get(height) {
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// Removes blocks on the disk beyond the current height.
+    /// Returns the number of removed blocks.
+    ///
+    pub fn truncate_database(&self) -> Result<u64, Error> {
+        self.database.truncate(self.height)
+    }
+
+    ///
+    /// Compacts the database in the background.
+    ///
+    pub fn compact_database(&self) {
+        let database = self.database.clone();
+        thread::spawn(move || database.compact());
+    }
+
+    ///
+    /// Rebuild the explorer indexes from the disk.
+    /// Enables the indexes if they were disabled.
+    ///
+    pub fn reindex(&mut self) -> Result<(), Error> {
+        info!("Rebuilding explorer indexes...");
+        let mut explorer = ExplorerIndex::new();
+        let mut epoch: u64 = 0;
+        for block in self.database.iter_starting(0) {
+            let height = block.base_header().height;
+            let lsn = lsn_for_height(height);
+            let block_hash = Hash::digest(&block);
+            match block {
+                Block::MacroBlock(block) => {
+                    explorer.register_macro_block(lsn, epoch, block_hash, &block);
+                    explorer.checkpoint();
+                    epoch += 1;
+                }
+                Block::MicroBlock(block) => {
+                    explorer.register_micro_block(lsn, epoch, block_hash, &block);
+                }
+            }
+        }
+        assert_eq!(epoch, self.epoch);
+        self.explorer = Some(explorer);
+        info!("Rebuilt explorer indexes: height={}", self.height);
+        Ok(())
+    }
+
     /// Helper for recover_wallet()
     fn check_wallet_output(&self, skey: &SecretKey, pkey: &PublicKey, output: &Output) -> bool {
         let output_hash = Hash::digest(&output);
@@ -431,46 +754,240 @@ impl Blockchain {
 
     /// Returns true if blockchain contains unspent output.
     pub fn contains_output(&self, output_hash: &Hash) -> bool {
-        self.output_by_hash.get(output_hash).is_some()
+        self.may_contain_output(output_hash) && self.output_by_hash.get(output_hash).is_some()
+    }
+
+    /// Returns false if the output is definitely not in UTXO.
+    fn may_contain_output(&self, output_hash: &Hash) -> bool {
+        match &self.output_filter {
+            Some(filter) => filter.may_contain(output_hash),
+            None => true,
+        }
+    }
+
+    /// Rebuild the filter of UTXO hashes from scratch.
+    /// Called when the filter is full and after rollbacks.
+    fn rebuild_output_filter(&mut self) {
+        let false_positive_rate = match &self.output_filter {
+            Some(filter) => filter.false_positive_rate(),
+            None => return,
+        };
+        let mut filter = OutputFilter::new(2 * self.output_by_hash.len(), false_positive_rate);
+        for output_hash in self.output_by_hash.keys() {
+            filter.insert(output_hash);
+        }
+        debug!(
+            "Rebuilt UTXO filter: len={}, capacity={}",
+            filter.len(),
+            filter.capacity()
+        );
+        self.output_filter = Some(filter);
     }
 
     /// Resolve UTXO by hash.
     pub fn output_by_hash(&self, output_hash: &Hash) -> Result<Option<Output>, Error> {
+        if !self.may_contain_output(output_hash) {
+            return Ok(None);
+        }
         match self.output_by_hash.get(output_hash) {
-            Some(OutputKey::MacroBlock { height, path }) => {
-                let block = &self.block_by_height(*height)?;
-                match block {
-                    Block::MacroBlock(MacroBlock { ref body, .. }) => {
-                        if let Some(output) = body.outputs.lookup(path) {
-                            Ok(Some(output.as_ref().clone()))
-                        } else {
-                            Ok(None) // Pruned.
-                        }
-                    }
-                    Block::MicroBlock(_) => panic!("Corrupted outputs_by_hash (Macro-0)"),
+            Some(key) => self.output_by_key(key),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the time-lock which must be expired to spend the unspent input by the transaction.
+    /// Unlike Transaction::input_lock(), takes into account the dispute period of channel inputs.
+    /// Fails if the input is not unspent or the dispute period can never expire
+    /// because the unlock height overflows.
+    pub fn input_lock(
+        &self,
+        tx: &Transaction,
+        input_hash: &Hash,
+        input: &Output,
+    ) -> Result<Option<LockTime>, TransactionError> {
+        if let Some(dispute_period) = tx.input_dispute_period(input_hash, input) {
+            let key = self
+                .output_by_hash
+                .get(input_hash)
+                .ok_or_else(|| TransactionError::MissingInput(Hash::digest(tx), *input_hash))?;
+            let locked_until = key.height().checked_add(dispute_period).ok_or_else(|| {
+                TransactionError::InvalidChannelWitness(Hash::digest(tx), *input_hash)
+            })?;
+            return Ok(Some(LockTime::Height(locked_until)));
+        }
+        Ok(tx.input_lock(input_hash, input))
+    }
+
+    /// Returns the time-lock which must be expired to spend the unspent input by a macro block.
+    /// Macro blocks carry neither preimages nor channel witnesses, so hash-locked inputs
+    /// can only be refunded and dispute outputs can only be spent after the dispute period.
+    pub(crate) fn macro_block_input_lock(
+        &self,
+        input_hash: &Hash,
+        input: &Output,
+    ) -> Option<LockTime> {
+        if let Some(ChannelLock::Dispute { dispute_period, .. }) = input.channel_lock() {
+            let height = self
+                .output_by_hash
+                .get(input_hash)
+                .map(|key| key.height())
+                .unwrap_or(self.height);
+            return Some(LockTime::Height(height.saturating_add(*dispute_period)));
+        }
+        if let Some(hashlock) = input.hashlock() {
+            return Some(hashlock.timeout);
+        }
+        input.locked_until()
+    }
+
+    /// Resolve any output ever created by hash, including already spent ones.
+    /// Returns the height of the block which created the output.
+    pub(crate) fn historical_output_by_hash(
+        &self,
+        output_hash: &Hash,
+    ) -> Result<Option<(u64, Output)>, Error> {
+        match self.output_history(output_hash)? {
+            Some(key) => {
+                let height = key.height();
+                Ok(self.output_by_key(&key)?.map(|output| (height, output)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the location of any output ever created by hash, including spent ones.
+    fn output_history(&self, output_hash: &Hash) -> Result<Option<OutputKey>, Error> {
+        // Records of blocks removed from the disk can remain if the blocks were damaged.
+        let key = self.database.output_key(output_hash)?;
+        Ok(key.filter(|key| key.height() < self.height))
+    }
+
+    fn output_by_key(&self, key: &OutputKey) -> Result<Option<Output>, Error> {
+        let block = self.block_by_height(key.height())?;
+        Ok(key.resolve(&block))
+    }
+
+    ///
+    /// Take a consistent snapshot of the UTXO set at the current height.
+    ///
+    /// The snapshot is not affected by blocks applied or reverted later,
+    /// so it can be iterated by wallet rescans and audit jobs without
+    /// holding a reference to the blockchain.
+    ///
+    pub fn snapshot(&self) -> Result<UtxoSnapshot, Error> {
+        let outputs: Vec<(Hash, OutputKey)> = self
+            .output_by_hash
+            .iter()
+            .map(|(hash, key)| (*hash, key.clone()))
+            .collect();
+        // Micro blocks of the current epoch can be reverted and replaced on disk.
+        let mut micro_blocks = Vec::new();
+        for height in self.last_macro_block_height + 1..self.height {
+            micro_blocks.push((height, self.block_by_height(height)?));
+        }
+        Ok(UtxoSnapshot::new(
+            self.height,
+            outputs,
+            micro_blocks,
+            self.database.clone(),
+        ))
+    }
+
+    ///
+    /// Returns the status of a transaction.
+    ///
+    /// `txins` are inputs of the transaction, used to detect conflicting spends
+    /// of transactions which haven't been committed.
+    ///
+    pub fn tx_status(&self, tx_hash: &Hash, txins: &[Hash]) -> Result<TransactionStatus, Error> {
+        if let Some(TransactionKey { height, tx_id }) = self.database.tx_key(tx_hash)? {
+            if height < self.height {
+                let block_hash = Hash::digest(&self.block_by_height(height)?);
+                return Ok(TransactionStatus::Committed {
+                    height,
+                    block_hash,
+                    tx_id,
+                });
+            }
+        }
+        for input_hash in txins {
+            match self.database.spent_height(input_hash)? {
+                Some(height) if height < self.height => {
+                    let block_hash = Hash::digest(&self.block_by_height(height)?);
+                    return Ok(TransactionStatus::Conflicted {
+                        input: *input_hash,
+                        height,
+                        block_hash,
+                    });
                 }
+                _ => {}
+            }
+        }
+        Ok(TransactionStatus::Unknown)
+    }
+
+    ///
+    /// Create a proof of existence for an output, including already spent ones.
+    ///
+    /// Returns None if the output is unknown or the epoch which created it
+    /// hasn't been finalized by a macro block yet.
+    ///
+    pub fn output_proof(&self, output_hash: &Hash) -> Result<Option<OutputProof>, Error> {
+        match self.output_history(output_hash)? {
+            Some(OutputKey::MacroBlock { height, path }) => {
+                let block = match self.block_by_height(height)? {
+                    Block::MacroBlock(block) => block,
+                    Block::MicroBlock(_) => panic!("Corrupted output_history (Macro-0)"),
+                };
+                let output = match block.body.outputs.lookup(path) {
+                    Some(output) => output.as_ref().clone(),
+                    None => return Ok(None), // Pruned.
+                };
+                let proof = block
+                    .body
+                    .outputs
+                    .proof(path)
+                    .expect("Corrupted output_history (Macro-1)");
+                Ok(Some(OutputProof {
+                    output,
+                    inclusion: OutputInclusion::MacroBlock(proof),
+                    checkpoint: block.header,
+                }))
             }
             Some(OutputKey::MicroBlock {
                 height,
                 tx_id,
                 txout_id,
             }) => {
-                let block = &self.block_by_height(*height)?;
-                match block {
-                    Block::MacroBlock(_) => panic!("Corrupted outputs_by_hash (Micro-0)"),
-                    Block::MicroBlock(MicroBlock {
-                        ref transactions, ..
-                    }) => {
-                        let tx = transactions
-                            .get(*tx_id as usize)
-                            .expect("Corrupted outputs_by_hash (Micro-2)");
-                        let output = tx
-                            .txouts()
-                            .get(*txout_id as usize)
-                            .expect("Corrupted outputs_by_hash (Micro-3)");
-                        Ok(Some(output.clone()))
+                let mut blocks: Vec<MicroBlock> = Vec::new();
+                for block in self.database.iter_starting(height) {
+                    match block {
+                        Block::MicroBlock(block) => blocks.push(block),
+                        Block::MacroBlock(checkpoint) => {
+                            let output = blocks
+                                .first()
+                                .expect("Corrupted output_history (Micro-1)")
+                                .transactions
+                                .get(tx_id as usize)
+                                .expect("Corrupted output_history (Micro-2)")
+                                .txouts()
+                                .get(txout_id as usize)
+                                .expect("Corrupted output_history (Micro-3)")
+                                .clone();
+                            return Ok(Some(OutputProof {
+                                output,
+                                inclusion: OutputInclusion::MicroBlock {
+                                    blocks,
+                                    tx_id,
+                                    txout_id,
+                                },
+                                checkpoint: checkpoint.header,
+                            }));
+                        }
                     }
                 }
+                // The epoch is not finalized yet.
+                Ok(None)
             }
             None => Ok(None),
         }
@@ -490,6 +1007,34 @@ impl Blockchain {
         Ok((self.database.get(height)?).expect("block exists"))
     }
 
+    /// Returns the verifiable randomness of the block at the given height.
+    /// Returns None if the block doesn't exist yet.
+    pub fn random_at(&self, height: u64) -> Result<Option<RandomBeacon>, Error> {
+        if height >= self.height {
+            return Ok(None);
+        }
+        let block = self.block_by_height(height)?;
+        let previous_random = if height > 0 {
+            self.block_by_height(height - 1)?.base_header().random.rand
+        } else {
+            // The genesis block is created from the initial randomness of election.
+            ElectionResult::default().random.rand
+        };
+        let block_hash = Hash::digest(&block);
+        let (base, leader) = match &block {
+            Block::MacroBlock(block) => (&block.header.base, block.body.pkey),
+            Block::MicroBlock(block) => (&block.base, block.pkey),
+        };
+        Ok(Some(RandomBeacon::new(
+            height,
+            block_hash,
+            base.view_change,
+            leader,
+            previous_random,
+            &base.random,
+        )))
+    }
+
     /// Return iterator over saved blocks.
     pub fn blocks(&self) -> impl Iterator<Item = Block> {
         self.database.iter()
@@ -503,6 +1048,11 @@ impl Blockchain {
             .collect()
     }
 
+    /// Flush all pending writes to the disk.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.database.flush()
+    }
+
     /// Return the last block.
     pub fn last_block(&self) -> Result<Block, Error> {
         assert!(self.height > 0);
@@ -514,6 +1064,35 @@ impl Blockchain {
         self.election_result.select_leader(view_change)
     }
 
+    /// Returns true if the validator can create a micro block for specific view_change number.
+    /// `random` is the VRF of the block, which reveals eligibility in the secret leader election.
+    pub fn is_micro_block_leader(
+        &self,
+        pkey: &pbc::PublicKey,
+        view_change: ViewCounter,
+        random: &pbc::VRF,
+    ) -> bool {
+        if self.cfg.secret_leader_election {
+            self.election_result
+                .is_secret_leader(pkey, random, self.cfg.secret_leader_candidates)
+        } else {
+            self.select_leader(view_change) == *pkey
+        }
+    }
+
+    /// Returns the full computation of the leader selection for specific view_change number.
+    /// Only the current epoch is supported, because stakers of past epochs are not kept.
+    pub fn audit_leader(
+        &self,
+        epoch: u64,
+        view_change: ViewCounter,
+    ) -> Option<LeaderSelectionAudit> {
+        if epoch != self.epoch {
+            return None;
+        }
+        self.election_result.audit_leader(epoch, view_change)
+    }
+
     /// Returns public key of the active leader.
     pub fn leader(&self) -> pbc::PublicKey {
         self.select_leader(self.view_change())
@@ -546,6 +1125,24 @@ impl Blockchain {
         self.last_macro_block_height
     }
 
+    /// Returns the hash of the last macro block.
+    #[inline]
+    pub fn last_macro_block_hash(&self) -> Hash {
+        self.last_macro_block_hash
+    }
+
+    /// Returns the height of the last irreversible block.
+    #[inline]
+    pub fn finalized_height(&self) -> u64 {
+        self.finality.height()
+    }
+
+    /// Returns the commitment of the last irreversible macro block.
+    #[inline]
+    pub fn finality_commitment(&self) -> Option<&FinalityCommitment> {
+        self.finality.last()
+    }
+
     /// Return the timestamp from the last macro block.
     #[inline]
     pub fn last_macro_block_timestamp(&self) -> SystemTime {
@@ -565,6 +1162,29 @@ impl Blockchain {
         self.last_block_hash
     }
 
+    /// Return the timestamp from the last block.
+    #[inline]
+    pub fn last_block_timestamp(&self) -> SystemTime {
+        self.last_block_timestamp
+    }
+
+    /// Return the source of the current time.
+    #[inline]
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Replace the source of the current time, e.g. with a mock clock for tests.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Return a timestamp for a new block.
+    /// It is the current time, but never earlier than the last block.
+    pub fn block_timestamp(&self) -> SystemTime {
+        std::cmp::max(self.clock.now(), self.last_block_timestamp)
+    }
+
     /// Return the current blockchain height.
     #[inline(always)]
     pub fn height(&self) -> u64 {
@@ -593,8 +1213,8 @@ impl Blockchain {
     /// Returns (active_balance, expired_balance) stake.
     ///
     #[inline]
-    pub(crate) fn staker_outputs(&self, validator_pkey: &pbc::PublicKey) -> (Vec<Hash>, i64) {
-        self.escrow.staker_outputs(validator_pkey, self.epoch)
+    pub(crate) fn staker_outputs(&self, consensus_pkey: &pbc::PublicKey) -> (Vec<Hash>, i64) {
+        self.escrow.staker_outputs(consensus_pkey, self.epoch)
     }
     /// Return information about escrow.
     #[inline]
@@ -602,6 +1222,74 @@ impl Blockchain {
         self.escrow.info(self.epoch)
     }
 
+    #[inline]
+    pub(crate) fn escrow(&self) -> &Escrow {
+        &self.escrow
+    }
+
+    /// Returns all stakes owned by the wallet.
+    pub fn stakes_by_wallet(&self, wallet_pkey: &PublicKey) -> Vec<WalletStakeInfo> {
+        let mut stakes = self.escrow.stakes_by_recipient(wallet_pkey, self.epoch);
+        for stake in &mut stakes {
+            stake.unlock_height = self.unlock_height(stake.active_until_epoch);
+        }
+        stakes
+    }
+
+    /// Returns the wallet which receives block rewards of the validator,
+    /// if stakes of the validator override the destination of rewards.
+    pub fn reward_key(&self, validator_pkey: &pbc::PublicKey) -> Option<PublicKey> {
+        self.escrow.reward_key(validator_pkey)
+    }
+
+    /// Returns stakes of the validator which override the destination of block rewards.
+    pub(crate) fn reward_keys(&self, validator_pkey: &pbc::PublicKey) -> Vec<(Hash, PublicKey)> {
+        self.escrow.reward_keys(validator_pkey)
+    }
+
+    /// Returns the wallet which receives service awards won by the validator wallet.
+    /// Stakes of the validator can override the destination, as for block rewards.
+    pub fn award_recipient(&self, wallet: &PublicKey) -> PublicKey {
+        self.validators()
+            .iter()
+            .filter(|(validator, _)| self.validator_wallet(validator).as_ref() == Some(wallet))
+            .find_map(|(validator, _)| self.reward_key(validator))
+            .unwrap_or(*wallet)
+    }
+
+    /// Returns the first height when the stake can be unstaked.
+    /// Returns None if the output is not an unspent stake.
+    pub fn stake_unlock_height(&self, output_hash: &Hash) -> Result<Option<u64>, Error> {
+        let validator_pkey = match self.output_by_hash(output_hash)? {
+            Some(Output::StakeOutput(o)) => o.validator,
+            _ => return Ok(None),
+        };
+        let active_until_epoch = self
+            .escrow
+            .active_until_epoch(&validator_pkey, output_hash)
+            .expect("stake exists");
+        Ok(Some(self.unlock_height(active_until_epoch)))
+    }
+
+    /// Returns summary of stakes bonded to the validator.
+    #[inline]
+    pub fn validator_stake_summary(
+        &self,
+        validator_pkey: &pbc::PublicKey,
+    ) -> ValidatorStakeSummary {
+        self.escrow.validator_summary(validator_pkey, self.epoch)
+    }
+
+    /// Returns the height of the first block after the macro block which ends `active_until_epoch`.
+    /// Already expired stakes are unlocked at the current height.
+    fn unlock_height(&self, active_until_epoch: u64) -> u64 {
+        if active_until_epoch < self.epoch {
+            return self.height;
+        }
+        let epochs_left = active_until_epoch - self.epoch + 1;
+        self.last_macro_block_height + epochs_left * self.cfg.blocks_in_epoch + 1
+    }
+
     /// Returns balance.
     #[inline]
     pub(crate) fn balance(&self) -> &Balance {
@@ -624,6 +1312,21 @@ impl Blockchain {
         &self.awards
     }
 
+    /// Returns the remaining service awards budget.
+    pub fn service_awards_budget(&self) -> i64 {
+        self.awards.budget()
+    }
+
+    /// Returns the winner and the amount of the service award paid in the last macro block.
+    pub fn last_service_award(&self) -> Option<(PublicKey, i64)> {
+        self.last_service_award.get(&()).cloned()
+    }
+
+    /// Returns the summary of a finalized epoch.
+    pub fn epoch_summary(&self, epoch: u64) -> Result<Option<EpochSummary>, Error> {
+        self.database.epoch_summary(epoch)
+    }
+
     /// Returns current service awards state.
     pub fn epoch_activity(&self) -> &BTreeMap<pbc::PublicKey, ValidatorAwardState> {
         self.epoch_activity.inner()
@@ -655,7 +1358,8 @@ impl Blockchain {
         let validators_activity = epoch_activity
             .iter()
             .map(|(k, v)| (self.validator_wallet(k).expect("validator has wallet"), *v));
-        service_awards.finalize_epoch(self.cfg().service_award_per_epoch, validators_activity);
+        let award = self.service_award_per_epoch(self.height());
+        service_awards.finalize_epoch(award, validators_activity);
         (activity_map, service_awards.check_winners(random.rand))
     }
 
@@ -694,12 +1398,68 @@ impl Blockchain {
         Ok(validators_activity)
     }
 
+    /// Returns the trusted hash of the block at the height, if any.
+    pub fn checkpoint(&self, height: u64) -> Option<&Hash> {
+        self.cfg
+            .checkpoints
+            .iter()
+            .find(|c| c.height == height)
+            .map(|c| &c.block_hash)
+    }
+
+    /// Returns the height of the last checkpoint, if any.
+    pub fn last_checkpoint_height(&self) -> Option<u64> {
+        self.cfg.checkpoints.iter().map(|c| c.height).max()
+    }
+
+    /// True if verification of block signatures can be skipped at the height.
+    pub(crate) fn is_assumed_valid(&self, height: u64) -> bool {
+        self.cfg.assume_valid
+            && self
+                .last_checkpoint_height()
+                .map_or(false, |last| height < last)
+    }
+
+    /// Checks that blocks starting from the height can be reverted,
+    /// i.e. none of them is protected by a checkpoint.
+    pub fn validate_rollback(&self, height: u64) -> Result<(), BlockchainError> {
+        for height in height..self.height {
+            if let Some(block_hash) = self.checkpoint(height) {
+                return Err(BlockError::CheckpointRevert(height, *block_hash).into());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn explorer_index(&self) -> Option<&ExplorerIndex> {
+        self.explorer.as_ref()
+    }
+
     /// Returns current blockchain config.
     pub fn cfg(&self) -> &BlockchainConfig {
         &self.cfg
     }
 
-    /// Returns number of total slots in current epoch.
+    /// Returns the identifier of this chain, see `BlockchainConfig::chain_id`.
+    #[inline]
+    pub fn chain_id(&self) -> Hash {
+        Hash::digest(&self.cfg.chain_id)
+    }
+
+    /// Returns the maximal block reward at the given height.
+    pub fn block_reward(&self, height: u64) -> i64 {
+        self.cfg.emission.block_reward(height)
+    }
+
+    /// Returns the service award added by the macro block at the given height,
+    /// it decays together with the block reward.
+    pub fn service_award_per_epoch(&self, height: u64) -> i64 {
+        self.cfg
+            .emission
+            .decayed(self.cfg.service_award_per_epoch, height)
+    }
+
+    /// Returns number of total slots in current epoch.
     /// Internally always return cfg.max_slot_count
     pub fn total_slots(&self) -> i64 {
         self.cfg.max_slot_count
@@ -727,6 +1487,19 @@ impl Blockchain {
     pub fn validator_wallet(&self, peer: &pbc::PublicKey) -> Option<PublicKey> {
         self.escrow
             .get_first_output(peer)
+            .or_else(|| self.escrow.get_first_delegated_output(peer))
+            .map(|hash| match self.output_by_hash(&hash) {
+                Ok(Some(Output::StakeOutput(s))) => s.recipient,
+                e => panic!("Expected stake output, found = {:?}", e),
+            })
+    }
+
+    ///
+    /// Returns the wallet key of validator's own stakes, ignoring stakes delegated to it.
+    ///
+    pub(crate) fn staker_wallet(&self, validator: &pbc::PublicKey) -> Option<PublicKey> {
+        self.escrow
+            .get_first_output(validator)
             .map(|hash| match self.output_by_hash(&hash) {
                 Ok(Some(Output::StakeOutput(s))) => s.recipient,
                 e => panic!("Expected stake output, found = {:?}", e),
@@ -771,10 +1544,19 @@ impl Blockchain {
         self.validate_macro_block(&block, timestamp)?;
 
         //
-        // Write the macro block to the disk.
+        // Write the macro block, its indexes, the summary of the epoch
+        // and the new tip to the disk in one batch.
         //
-        self.database
-            .insert(self.height, Block::MacroBlock(block.clone()))?;
+        let block_hash = Hash::digest(&block);
+        let stored_block = Block::MacroBlock(block.clone());
+        let mut batch = ListDbBatch::default();
+        batch.insert(self.height, &stored_block)?;
+        batch.index(self.height, &stored_block)?;
+        if self.height > 0 {
+            batch.insert_epoch_summary(&self.new_epoch_summary(block_hash, &block)?)?;
+        }
+        batch.set_tip(self.height + 1, &block_hash)?;
+        self.database.write(batch)?;
 
         //
         // Update in-memory indexes and metadata.
@@ -784,6 +1566,31 @@ impl Blockchain {
         Ok((inputs, outputs))
     }
 
+    ///
+    /// Aggregate the epoch finalized by the macro block, before it is registered.
+    ///
+    fn new_epoch_summary(
+        &self,
+        block_hash: Hash,
+        block: &MacroBlock,
+    ) -> Result<EpochSummary, BlockchainError> {
+        // Same service award as in register_macro_block().
+        let mut service_awards = self.service_awards().clone();
+        let validators_activity = self.epoch_activity_from_macro_block(&block.body.activity_map)?;
+        let award = self.service_award_per_epoch(block.header.base.height);
+        service_awards.finalize_epoch(award, validators_activity);
+        let service_award = service_awards.check_winners(block.header.base.random.rand);
+        Ok(EpochSummary::new(
+            self.epoch,
+            block_hash,
+            &block.header,
+            &block.body.activity_map,
+            self.epoch_blocks.iter(),
+            self.validators(),
+            service_award,
+        ))
+    }
+
     ///
     /// Update indexes and metadata.
     ///
@@ -797,6 +1604,21 @@ impl Blockchain {
         let height = self.height;
         let lsn = lsn_for_height(height);
 
+        if let Some(explorer) = &mut self.explorer {
+            explorer.register_macro_block(lsn, self.epoch, block_hash, &block);
+        }
+
+        // The block is signed by validators of the finished epoch.
+        let trusted = height == 0 || self.is_assumed_valid(height);
+        let commitment = FinalityCommitment::from_macro_block(
+            self.epoch,
+            block_hash,
+            &block,
+            self.validators(),
+            self.total_slots(),
+            trusted,
+        );
+
         //
         // Prepare inputs.
         //
@@ -827,10 +1649,23 @@ impl Blockchain {
             let validators_activity = self
                 .epoch_activity_from_macro_block(&block.body.activity_map)
                 .unwrap();
-            self.awards
-                .finalize_epoch(self.cfg.service_award_per_epoch, validators_activity);
-            let _ = self.awards.check_winners(block.header.base.random.rand);
+            let award = self.service_award_per_epoch(height);
+            self.awards.finalize_epoch(award, validators_activity);
+            let service_award = self.awards.check_winners(block.header.base.random.rand);
+            match service_award {
+                Some(service_award) => {
+                    self.last_service_award.insert(lsn, (), service_award);
+                }
+                None => {
+                    self.last_service_award.remove(lsn, &());
+                }
+            }
         }
+        let epoch_heights: Vec<u64> = self.epoch_blocks.keys().cloned().collect();
+        for epoch_height in epoch_heights {
+            self.epoch_blocks.remove(lsn, &epoch_height);
+        }
+
         //
         // Register block.
         //
@@ -847,12 +1682,24 @@ impl Blockchain {
             timestamp,
         );
 
+        //
+        // Update finality.
+        //
+        if !self.finality.advance(commitment) {
+            warn!(
+                "Macro block is not final: height={}, block={}",
+                height, block_hash
+            );
+        }
+
         //
         // Update metadata.
         //
         self.epoch += 1;
         self.last_macro_block_height = height;
+        self.last_macro_block_hash = block_hash;
         self.last_macro_block_timestamp = block.header.base.timestamp;
+        self.last_block_timestamp = block.header.base.timestamp;
         self.election_result = election::select_validators_slots(
             self.escrow
                 .get_stakers_majority(self.epoch, self.cfg.min_stake_amount),
@@ -880,6 +1727,11 @@ impl Blockchain {
         self.output_by_hash.checkpoint();
         self.balance.checkpoint();
         self.escrow.checkpoint();
+        self.epoch_blocks.checkpoint();
+        self.last_service_award.checkpoint();
+        if let Some(explorer) = &mut self.explorer {
+            explorer.checkpoint();
+        }
 
         Ok((inputs, outputs))
     }
@@ -900,12 +1752,45 @@ impl Blockchain {
         // Validate the micro block.
         //
         self.validate_micro_block(&block, timestamp)?;
+        self.write_micro_block(block, timestamp)
+    }
+
+    ///
+    /// Add a micro block validated by `validate_micro_block_stream()` to the blockchain.
+    /// The block is validated again only if the chain has changed since then.
+    ///
+    pub fn push_validated_micro_block(
+        &mut self,
+        validated: ValidatedMicroBlock,
+        timestamp: SystemTime,
+    ) -> Result<(Vec<Output>, Vec<Output>), BlockchainError> {
+        if validated.last_block != self.last_block_hash()
+            || validated.view_change != self.view_change()
+        {
+            return self.push_micro_block(validated.block, timestamp);
+        }
+        debug!(
+            "The micro block has been already validated: height={}, block={}",
+            validated.block.base.height, validated.block_hash
+        );
+        self.write_micro_block(validated.block, timestamp)
+    }
 
+    /// Writes a valid micro block to the disk and registers it.
+    fn write_micro_block(
+        &mut self,
+        block: MicroBlock,
+        timestamp: SystemTime,
+    ) -> Result<(Vec<Output>, Vec<Output>), BlockchainError> {
         //
-        // Write the micro block to the disk.
+        // Write the micro block, its indexes and the new tip to the disk in one batch.
         //
-        self.database
-            .insert(self.height, Block::MicroBlock(block.clone()))?;
+        let stored_block = Block::MicroBlock(block.clone());
+        let mut batch = ListDbBatch::default();
+        batch.insert(self.height, &stored_block)?;
+        batch.index(self.height, &stored_block)?;
+        batch.set_tip(self.height + 1, &Hash::digest(&block))?;
+        self.database.write(batch)?;
 
         //
         // Update in-memory indexes and metadata.
@@ -958,6 +1843,9 @@ impl Blockchain {
                     height, block_hash, &input_hash
                 );
             }
+            if let Some(filter) = &mut self.output_filter {
+                filter.remove(input_hash);
+            }
 
             input.validate().expect("valid UTXO");
             burned += input
@@ -997,6 +1885,9 @@ impl Blockchain {
                 );
             }
             assert_eq!(self.output_by_hash.current_lsn(), lsn);
+            if let Some(filter) = &mut self.output_filter {
+                filter.insert(&output_hash);
+            }
 
             output.validate().expect("valid UTXO");
             created += output
@@ -1014,6 +1905,9 @@ impl Blockchain {
                         self.epoch,
                         self.cfg.stake_epochs,
                         o.amount,
+                        o.recipient,
+                        o.operator,
+                        o.reward.as_ref().map(|reward| reward.pkey),
                     );
                     assert_eq!(self.escrow.current_lsn(), lsn);
                 }
@@ -1024,6 +1918,11 @@ impl Blockchain {
                 height, &block_hash, &output_hash
             );
         }
+        if let Some(filter) = &self.output_filter {
+            if filter.len() > filter.capacity() {
+                self.rebuild_output_filter();
+            }
+        }
 
         //
         // Update monetary balance.
@@ -1076,8 +1975,13 @@ impl Blockchain {
         assert_eq!(self.height, block.base.height);
         let height = self.height;
         let block_hash = Hash::digest(&block);
+        let block_timestamp = block.base.timestamp;
         let lsn = lsn_for_height(self.height());
 
+        if let Some(explorer) = &mut self.explorer {
+            explorer.register_micro_block(lsn, self.epoch, block_hash, &block);
+        }
+
         //
         // Prepare inputs && outputs.
         //
@@ -1087,6 +1991,8 @@ impl Blockchain {
         let mut outputs: Vec<Output> = Vec::new();
         let mut gamma = Fr::zero();
         let mut block_reward: i64 = 0;
+        let mut block_fee: i64 = 0;
+        let transactions = block.transactions.len() as u64;
         // Regular transactions.
         for (tx_id, tx) in block.transactions.into_iter().enumerate() {
             assert!(tx_id < std::u32::MAX as usize);
@@ -1108,12 +2014,14 @@ impl Blockchain {
             match tx {
                 Transaction::CoinbaseTransaction(tx) => {
                     block_reward += tx.block_reward;
+                    block_fee += tx.block_fee;
                     gamma += tx.gamma;
                 }
                 Transaction::PaymentTransaction(tx) => {
                     gamma += tx.gamma;
                 }
                 Transaction::RestakeTransaction(_tx) => {}
+                Transaction::RevokeDelegationTransaction(_tx) => {}
                 Transaction::SlashingTransaction(tx) => {
                     info!(
                         "Found slashing transaction, removing validator, from list: cheater={}",
@@ -1135,22 +2043,47 @@ impl Blockchain {
         // Update service awards
         //
         // Set skipped validators to inactive.
-        for skiped_view_change in 0..block.base.view_change {
+        // Leaders of skipped view changes are unknown in the secret leader election.
+        let skipped_view_changes = if self.cfg.secret_leader_election {
+            0
+        } else {
+            block.base.view_change
+        };
+        let mut skipped_leaders = Vec::with_capacity(skipped_view_changes as usize);
+        for skiped_view_change in 0..skipped_view_changes {
             let leader = self.election_result.select_leader(skiped_view_change);
             self.epoch_activity.insert(
                 lsn,
                 leader,
                 ValidatorAwardState::FailedAt(self.epoch(), self.height()),
             );
+            skipped_leaders.push(leader);
         }
 
         // set current leader to active, if it was unknown.
-        let leader = self.election_result.select_leader(block.base.view_change);
+        let leader = if self.cfg.secret_leader_election {
+            block.pkey
+        } else {
+            self.election_result.select_leader(block.base.view_change)
+        };
         if self.epoch_activity.get(&leader).is_none() {
             self.epoch_activity
                 .insert(lsn, leader, ValidatorAwardState::Active);
         }
 
+        //
+        // Update epoch summary.
+        //
+        let epoch_block = EpochBlock {
+            leader,
+            view_change: block.base.view_change,
+            skipped_leaders,
+            transactions,
+            fee: block_fee,
+            reward: block_reward,
+        };
+        self.epoch_blocks.insert(lsn, height, epoch_block);
+
         //
         // Register block.
         //
@@ -1170,6 +2103,7 @@ impl Blockchain {
         //
         // Update metadata.
         //
+        self.last_block_timestamp = block_timestamp;
         self.election_result.view_change = 0;
         self.election_result.random = block.base.random;
 
@@ -1192,18 +2126,23 @@ impl Blockchain {
             "attempt to revert the macro block"
         );
         let lsn = lsn_for_height(height - 1);
+        self.validate_rollback(height)?;
 
         //
         // Remove from the disk.
         //
-        let block = self.block_by_height(height)?;
-        let block = if let Block::MicroBlock(block) = block {
+        let stored_block = self.block_by_height(height)?;
+        let previous = self.block_by_height(height - 1)?;
+        let mut batch = ListDbBatch::default();
+        batch.remove(height)?;
+        batch.unindex(&stored_block)?;
+        batch.set_tip(height, &Hash::digest(&previous))?;
+        self.database.write(batch)?;
+        let block = if let Block::MicroBlock(block) = stored_block {
             block
         } else {
             panic!("Expected micro block");
         };
-        let previous = self.block_by_height(height - 1)?;
-        self.database.remove(height)?;
         let block_hash = Hash::digest(&block);
 
         //
@@ -1211,11 +2150,20 @@ impl Blockchain {
         //
         self.block_by_hash.rollback_to_lsn(lsn);
         self.output_by_hash.rollback_to_lsn(lsn);
+        self.rebuild_output_filter();
         self.balance.rollback_to_lsn(lsn);
         self.escrow.rollback_to_lsn(lsn);
         self.epoch_activity.rollback_to_lsn(lsn);
+        self.epoch_blocks.rollback_to_lsn(lsn);
+        self.last_service_award.rollback_to_lsn(lsn);
+        if let Some(explorer) = &mut self.explorer {
+            explorer.rollback_to_lsn(lsn);
+            assert!(explorer.current_lsn() <= lsn);
+        }
         assert_eq!(self.block_by_hash.current_lsn(), lsn);
         assert!(self.epoch_activity.current_lsn() <= lsn);
+        assert!(self.epoch_blocks.current_lsn() <= lsn);
+        assert!(self.last_service_award.current_lsn() <= lsn);
         assert!(self.output_by_hash.current_lsn() <= lsn);
         assert!(self.balance.current_lsn() <= lsn);
         assert!(self.escrow.current_lsn() <= lsn);
@@ -1223,6 +2171,7 @@ impl Blockchain {
         assert_eq!(self.height, height);
         assert_eq!(lsn_for_height(self.height - 1), lsn);
         self.last_block_hash = Hash::digest(&previous);
+        self.last_block_timestamp = previous.base_header().timestamp;
         self.election_result.random = previous.base_header().random;
         self.reset_view_change();
         metrics::HEIGHT.set(self.height as i64);
@@ -1278,29 +2227,40 @@ pub fn create_fake_macro_block(
     chain: &Blockchain,
     keychains: &[KeyChain],
     timestamp: SystemTime,
-) -> MacroBlock {
+) -> Result<MacroBlock, BlockBuilderError> {
     let version = VERSION;
+    let chain_id = chain.chain_id();
     let previous = chain.last_block_hash().clone();
     let height = chain.height();
     let view_change = chain.view_change();
     let key = chain.select_leader(view_change);
-    let keys = keychains.iter().find(|p| p.network_pkey == key).unwrap();
+    let keys = keychains
+        .iter()
+        .find(|p| p.network_pkey == key)
+        .ok_or(BlockBuilderError::LeaderKeysNotFound(key))?;
     let seed = mix(chain.last_random(), view_change);
     let random = pbc::make_VRF(&keys.network_skey, &seed);
-    let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
-    let mut block_reward = chain.cfg().block_reward;
+    let base = BaseBlockHeader::new(
+        version,
+        chain_id,
+        previous,
+        height,
+        view_change,
+        timestamp,
+        random,
+    );
+    let mut block_reward = chain.block_reward(height);
 
     let data = PaymentPayloadData::Comment(format!("Block reward"));
-    let (output, gamma) =
-        PaymentOutput::with_payload(&keys.wallet_pkey, block_reward, data).expect("invalid keys");
-    let mut txs = vec![output.into()];
+    let (output, gamma) = fake_reward_output(chain, keys, block_reward, data)?;
+    let mut txs = vec![output];
 
     if let Some((tx, reward)) = try_add_award(chain, &random) {
         txs.push(tx);
         block_reward += reward;
     }
 
-    let mut block = MacroBlock::new(
+    let mut block = MacroBlock::try_new(
         base,
         -gamma,
         block_reward,
@@ -1308,9 +2268,9 @@ pub fn create_fake_macro_block(
         &[],
         &txs,
         keys.network_pkey,
-    );
+    )?;
     sign_fake_macro_block(&mut block, chain, keychains);
-    block
+    Ok(block)
 }
 
 pub fn try_add_award(chain: &Blockchain, random: &VRF) -> Option<(Output, i64)> {
@@ -1318,38 +2278,60 @@ pub fn try_add_award(chain: &Blockchain, random: &VRF) -> Option<(Output, i64)>
         .awards_from_active_epoch(&random)
         .1
         .map(|(k, reward)| {
-            let output = PublicPaymentOutput::new(&k, reward);
+            let output = PublicPaymentOutput::new(&chain.award_recipient(&k), reward);
             (output.into(), reward)
         })
 }
 
+/// Pays a block reward to the destination set by stakes of the leader, or to its wallet.
+fn fake_reward_output(
+    chain: &Blockchain,
+    keys: &KeyChain,
+    amount: i64,
+    data: PaymentPayloadData,
+) -> Result<(Output, Fr), BlockBuilderError> {
+    match chain.reward_key(&keys.network_pkey) {
+        Some(reward_pkey) => Ok((
+            PublicPaymentOutput::new(&reward_pkey, amount).into(),
+            Fr::zero(),
+        )),
+        None => {
+            let (output, gamma) = PaymentOutput::with_payload(&keys.wallet_pkey, amount, data)?;
+            Ok((output.into(), gamma))
+        }
+    }
+}
+
 pub fn create_fake_micro_block(
     chain: &Blockchain,
     keychains: &[KeyChain],
     timestamp: SystemTime,
-) -> (MicroBlock, Vec<Hash>, Vec<Hash>) {
+) -> Result<(MicroBlock, Vec<Hash>, Vec<Hash>), BlockBuilderError> {
     let version: u64 = VERSION;
+    let chain_id = chain.chain_id();
     let height = chain.height();
     let view_change = chain.view_change();
     let key = chain.select_leader(view_change);
-    let keys = keychains.iter().find(|p| p.network_pkey == key).unwrap();
+    let keys = keychains
+        .iter()
+        .find(|p| p.network_pkey == key)
+        .ok_or(BlockBuilderError::LeaderKeysNotFound(key))?;
     let previous = chain.last_block_hash().clone();
     let seed = mix(chain.last_random(), view_change);
     let random = pbc::make_VRF(&keys.network_skey, &seed);
-    let block_reward = chain.cfg().block_reward;
+    let block_reward = chain.block_reward(height);
     let mut input_hashes: Vec<Hash> = Vec::new();
     let mut inputs: Vec<Output> = Vec::new();
     let mut monetary_balance: i64 = 0;
     let mut staking_balance: i64 = 0;
     for input_hash in chain.unspent() {
         let input = chain
-            .output_by_hash(&input_hash)
-            .expect("no disk errors")
-            .expect("exists");
-        input.validate().expect("Valid input");
+            .output_by_hash(&input_hash)?
+            .ok_or(BlockBuilderError::MissingOutput(input_hash.clone()))?;
+        input.validate()?;
         match input {
             Output::PaymentOutput(ref o) => {
-                let payload = o.decrypt_payload(&keys.wallet_skey).unwrap();
+                let payload = o.decrypt_payload(&keys.wallet_skey)?;
                 monetary_balance += payload.amount;
             }
             Output::PublicPaymentOutput(ref o) => {
@@ -1367,8 +2349,7 @@ pub fn create_fake_micro_block(
     let mut outputs_gamma = Fr::zero();
     // Payments.
     if monetary_balance > 0 {
-        let (output, output_gamma) =
-            PaymentOutput::new(&keys.wallet_pkey, monetary_balance).expect("keys are valid");
+        let (output, output_gamma) = PaymentOutput::new(&keys.wallet_pkey, monetary_balance)?;
         outputs.push(Output::PaymentOutput(output));
         outputs_gamma += output_gamma;
     }
@@ -1380,63 +2361,81 @@ pub fn create_fake_micro_block(
             &keys.network_skey,
             &keys.network_pkey,
             staking_balance,
-        )
-        .expect("keys are valid");
+        )?;
         outputs.push(Output::StakeOutput(output));
     }
 
     let output_hashes: Vec<Hash> = outputs.iter().map(Hash::digest).collect();
     let block_fee: i64 = 0;
     let tx = PaymentTransaction::new(
+        &chain_id,
         &keys.wallet_skey,
         &inputs,
         &outputs,
         &outputs_gamma,
         block_fee,
-    )
-    .expect("Invalid keys");
-    tx.validate(&inputs).expect("Invalid transaction");
+    )?;
+    tx.validate(&inputs)?;
 
     let coinbase_tx = {
         let data = PaymentPayloadData::Comment(format!("Block reward"));
-        let (output, gamma) = PaymentOutput::with_payload(&keys.wallet_pkey, block_reward, data)
-            .expect("invalid keys");
+        let (output, gamma) = fake_reward_output(chain, keys, block_reward, data)?;
         CoinbaseTransaction {
             block_reward,
             block_fee,
             gamma: -gamma,
-            txouts: vec![Output::PaymentOutput(output)],
+            txouts: vec![output],
         }
     };
-    coinbase_tx.validate().expect("Invalid transaction");
+    coinbase_tx.validate()?;
 
     let transactions: Vec<Transaction> = vec![coinbase_tx.into(), tx.into()];
 
-    let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+    let base = BaseBlockHeader::new(
+        version,
+        chain_id,
+        previous,
+        height,
+        view_change,
+        timestamp,
+        random,
+    );
     let mut block = MicroBlock::new(base, None, transactions, keys.network_pkey);
-    block.sign(&keys.network_skey, &keys.network_pkey);
-    (block, input_hashes, output_hashes)
+    block.sign(&keys.network_skey, &keys.network_pkey)?;
+    Ok((block, input_hashes, output_hashes))
 }
 
 pub fn create_micro_block_with_coinbase(
     chain: &Blockchain,
     keychains: &[KeyChain],
     timestamp: SystemTime,
-) -> MicroBlock {
+) -> Result<MicroBlock, BlockBuilderError> {
     let version = VERSION;
+    let chain_id = chain.chain_id();
     let previous = chain.last_block_hash().clone();
     let height = chain.height();
     let view_change = chain.view_change();
     let key = chain.select_leader(view_change);
-    let keys = keychains.iter().find(|p| p.network_pkey == key).unwrap();
+    let keys = keychains
+        .iter()
+        .find(|p| p.network_pkey == key)
+        .ok_or(BlockBuilderError::LeaderKeysNotFound(key))?;
     let seed = mix(chain.last_random(), view_change);
     let random = pbc::make_VRF(&keys.network_skey, &seed);
-    let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+    let base = BaseBlockHeader::new(
+        version,
+        chain_id,
+        previous,
+        height,
+        view_change,
+        timestamp,
+        random,
+    );
     let mut txouts: Vec<Output> = Vec::new();
     let mut gamma = Fr::zero();
 
     let block_fee = 0;
-    let block_reward = chain.cfg().block_reward;
+    let block_reward = chain.block_reward(height);
     // Create outputs for fee and rewards.
     for (amount, comment) in vec![(block_fee, "fee"), (block_reward, "reward")] {
         if amount <= 0 {
@@ -1444,9 +2443,7 @@ pub fn create_micro_block_with_coinbase(
         }
 
         let data = PaymentPayloadData::Comment(format!("Block {}", comment));
-        let (output_fee, gamma_fee) =
-            PaymentOutput::with_payload(&keys.wallet_pkey, amount, data.clone())
-                .expect("invalid keys");
+        let (output_fee, gamma_fee) = fake_reward_output(chain, keys, amount, data.clone())?;
         gamma -= gamma_fee;
 
         info!(
@@ -1456,7 +2453,7 @@ pub fn create_micro_block_with_coinbase(
             amount,
             data
         );
-        txouts.push(Output::PaymentOutput(output_fee));
+        txouts.push(output_fee);
     }
 
     let coinbase = CoinbaseTransaction {
@@ -1465,21 +2462,24 @@ pub fn create_micro_block_with_coinbase(
         gamma,
         txouts,
     };
+    coinbase.validate()?;
     let txs = vec![coinbase.into()];
     let mut block = MicroBlock::new(base, None, txs, keys.network_pkey);
-    block.sign(&keys.network_skey, &keys.network_pkey);
-    block
+    block.sign(&keys.network_skey, &keys.network_pkey)?;
+    Ok(block)
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
 
+    use crate::clock::MockClock;
     use crate::genesis::genesis;
     use rand::distributions::Alphanumeric;
     use rand::{thread_rng, Rng};
     use simple_logger;
     use std::time::{Duration, SystemTime};
+    use stegos_serialization::traits::ProtoConvert;
     use tempdir::TempDir;
 
     #[test]
@@ -1553,6 +2553,51 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn recover_damaged() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let database = ListDb::testing();
+        let mut chain =
+            Blockchain::with_db(cfg.clone(), database.clone(), genesis.clone(), timestamp)
+                .expect("Failed to create blockchain");
+        let mut hashes = vec![chain.last_block_hash()];
+        for _height in 1..4 {
+            timestamp += Duration::from_millis(1);
+            let block = create_micro_block_with_coinbase(&mut chain, &keychains, timestamp)
+                .expect("block is valid");
+            chain
+                .push_micro_block(block, timestamp)
+                .expect("block is valid");
+            hashes.push(chain.last_block_hash());
+        }
+        assert_eq!(chain.height(), 4);
+        assert_eq!(chain.check_database(), None);
+
+        // Break the hash chain at height 2.
+        database
+            .insert(2, Block::MacroBlock(genesis.clone()))
+            .unwrap();
+        assert_eq!(chain.check_database(), Some(2));
+        drop(chain);
+
+        let chain = Blockchain::with_db(cfg, database.clone(), genesis, timestamp)
+            .expect("Failed to recover blockchain");
+        assert_eq!(chain.height(), 2);
+        assert_eq!(chain.last_block_hash(), hashes[1]);
+        assert_eq!(chain.check_database(), None);
+        assert_eq!(database.iter().count(), 2);
+    }
+
     #[test]
     fn iterate() {
         simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
@@ -1579,7 +2624,7 @@ pub mod tests {
             //
             timestamp += Duration::from_millis(1);
             let (block, input_hashes, output_hashes) =
-                create_fake_micro_block(&mut chain, &keychains, timestamp);
+                create_fake_micro_block(&mut chain, &keychains, timestamp).expect("block is valid");
             let hash = Hash::digest(&block);
             let height = chain.height();
             chain
@@ -1598,7 +2643,8 @@ pub mod tests {
             // Empty block.
             //
             timestamp += Duration::from_millis(1);
-            let block = create_micro_block_with_coinbase(&mut chain, &keychains, timestamp);
+            let block = create_micro_block_with_coinbase(&mut chain, &keychains, timestamp)
+                .expect("block is valid");
             let hash = Hash::digest(&block);
             let height = chain.height();
             chain
@@ -1611,7 +2657,8 @@ pub mod tests {
             // Key block.
             //
             timestamp += Duration::from_millis(1);
-            let block = create_fake_macro_block(&chain, &keychains, timestamp);
+            let block =
+                create_fake_macro_block(&chain, &keychains, timestamp).expect("block is valid");
             let hash = Hash::digest(&block);
             let height = chain.height();
             chain
@@ -1637,6 +2684,86 @@ pub mod tests {
         assert_eq!(&balance, chain.balance());
     }
 
+    #[test]
+    fn output_proofs() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let genesis_hash = Hash::digest(&genesis);
+        let mut chain = Blockchain::testing(cfg, genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+
+        // Outputs of the macro block.
+        for (output, _path) in genesis.body.outputs.leafs() {
+            let output_hash = Hash::digest(&output);
+            let proof = chain
+                .output_proof(&output_hash)
+                .expect("no disk errors")
+                .expect("exists");
+            assert_eq!(Hash::digest(&proof.output), output_hash);
+            proof.validate(&genesis_hash).expect("proof is valid");
+            proof
+                .validate(&Hash::digest("test"))
+                .expect_err("wrong checkpoint");
+        }
+        assert!(chain
+            .output_proof(&Hash::digest("test"))
+            .expect("no disk errors")
+            .is_none());
+
+        // Outputs of micro blocks, including spent ones.
+        timestamp += Duration::from_millis(1);
+        let (block, input_hashes, output_hashes) =
+            create_fake_micro_block(&mut chain, &keychains, timestamp).expect("block is valid");
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        for output_hash in &output_hashes {
+            // The epoch is not finalized yet.
+            assert!(chain
+                .output_proof(output_hash)
+                .expect("no disk errors")
+                .is_none());
+        }
+        timestamp += Duration::from_millis(1);
+        let block = create_micro_block_with_coinbase(&mut chain, &keychains, timestamp)
+            .expect("block is valid");
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        timestamp += Duration::from_millis(1);
+        let block = create_fake_macro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let checkpoint_hash = Hash::digest(&block);
+        chain
+            .push_macro_block(block, timestamp)
+            .expect("block is valid");
+        for output_hash in &output_hashes {
+            let proof = chain
+                .output_proof(output_hash)
+                .expect("no disk errors")
+                .expect("exists");
+            let proof = OutputProof::from_buffer(&proof.into_buffer().unwrap()).unwrap();
+            assert_eq!(&Hash::digest(&proof.output), output_hash);
+            proof.validate(&checkpoint_hash).expect("proof is valid");
+        }
+        for input_hash in &input_hashes {
+            assert!(!chain.contains_output(input_hash));
+            let proof = chain
+                .output_proof(input_hash)
+                .expect("no disk errors")
+                .expect("spent outputs are provable");
+            proof.validate(&genesis_hash).expect("proof is valid");
+        }
+    }
+
     #[test]
     fn rollback() {
         simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
@@ -1665,7 +2792,7 @@ pub mod tests {
         // Register a micro block.
         timestamp += Duration::from_millis(1);
         let (block1, input_hashes1, output_hashes1) =
-            create_fake_micro_block(&mut chain, &keychains, timestamp);
+            create_fake_micro_block(&mut chain, &keychains, timestamp).expect("block is valid");
         chain
             .push_micro_block(block1, timestamp)
             .expect("block is valid");
@@ -1688,7 +2815,7 @@ pub mod tests {
         // Register one more micro block.
         timestamp += Duration::from_millis(1);
         let (block2, input_hashes2, output_hashes2) =
-            create_fake_micro_block(&mut chain, &keychains, timestamp);
+            create_fake_micro_block(&mut chain, &keychains, timestamp).expect("block is valid");
         chain
             .push_micro_block(block2, timestamp)
             .expect("block is valid");
@@ -1748,6 +2875,471 @@ pub mod tests {
         assert_eq!(&balance0, chain.balance());
     }
 
+    #[test]
+    fn snapshot() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+
+        timestamp += Duration::from_millis(1);
+        let (block1, _input_hashes1, output_hashes1) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        chain
+            .push_micro_block(block1, timestamp)
+            .expect("block is valid");
+        let mut unspent1: Vec<Hash> = chain.unspent().cloned().collect();
+        unspent1.sort();
+        let snapshot = chain.snapshot().expect("no disk errors");
+        assert_eq!(snapshot.height(), chain.height());
+        assert_eq!(snapshot.len(), unspent1.len());
+
+        // Apply and revert blocks after the snapshot has been taken.
+        timestamp += Duration::from_millis(1);
+        let (block2, input_hashes2, output_hashes2) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        chain
+            .push_micro_block(block2, timestamp)
+            .expect("block is valid");
+        chain.pop_micro_block().expect("no disk errors");
+        chain.pop_micro_block().expect("no disk errors");
+
+        for input_hash in &input_hashes2 {
+            assert!(snapshot.contains(input_hash));
+        }
+        for output_hash in &output_hashes2 {
+            assert!(!snapshot.contains(output_hash));
+        }
+        for output_hash in &output_hashes1 {
+            assert!(!chain.contains_output(output_hash));
+            let output = snapshot
+                .output_by_hash(output_hash)
+                .expect("no disk errors")
+                .expect("exists");
+            assert_eq!(&Hash::digest(&output), output_hash);
+        }
+
+        let mut unspent2: Vec<Hash> = Vec::new();
+        for item in snapshot.iter() {
+            let (output_hash, output) = item.expect("no disk errors");
+            assert_eq!(Hash::digest(&output), output_hash);
+            unspent2.push(output_hash);
+        }
+        unspent2.sort();
+        assert_eq!(unspent1, unspent2);
+
+        let tree = snapshot.utxo_tree();
+        assert_eq!(tree.len(), unspent1.len() as u64);
+        for output_hash in &unspent1 {
+            let proof = snapshot.utxo_proof(&tree, output_hash).expect("exists");
+            assert!(proof.verify(output_hash, &tree.root()));
+        }
+        for output_hash in &output_hashes2 {
+            assert!(snapshot.utxo_proof(&tree, output_hash).is_none());
+        }
+    }
+
+    #[test]
+    fn tx_status() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+
+        timestamp += Duration::from_millis(1);
+        let (block, input_hashes, _output_hashes) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let tx_hash = Hash::digest(&block.transactions[1]);
+        let height = chain.height();
+        let other_tx_hash = Hash::digest("other");
+        assert_eq!(
+            chain.tx_status(&tx_hash, &input_hashes).unwrap(),
+            TransactionStatus::Unknown
+        );
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        let block_hash = chain.last_block_hash();
+
+        // Committed.
+        assert_eq!(
+            chain.tx_status(&tx_hash, &input_hashes).unwrap(),
+            TransactionStatus::Committed {
+                height,
+                block_hash,
+                tx_id: 1,
+            }
+        );
+
+        // Conflicted.
+        assert_eq!(
+            chain.tx_status(&other_tx_hash, &input_hashes).unwrap(),
+            TransactionStatus::Conflicted {
+                input: input_hashes[0],
+                height,
+                block_hash,
+            }
+        );
+        assert_eq!(
+            chain.tx_status(&other_tx_hash, &[]).unwrap(),
+            TransactionStatus::Unknown
+        );
+
+        // Rollback.
+        chain.pop_micro_block().expect("no disk errors");
+        assert_eq!(
+            chain.tx_status(&tx_hash, &input_hashes).unwrap(),
+            TransactionStatus::Unknown
+        );
+        assert_eq!(
+            chain.tx_status(&other_tx_hash, &input_hashes).unwrap(),
+            TransactionStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn escrow_queries() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem(), KeyChain::new_mem()];
+        let timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let stake = cfg.min_stake_amount;
+        let blocks_in_epoch = cfg.blocks_in_epoch;
+        let genesis = genesis(&keychains, stake, 10 * stake, timestamp);
+        let chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+
+        for keys in &keychains {
+            let stakes = chain.stakes_by_wallet(&keys.wallet_pkey);
+            assert_eq!(stakes.len(), 1);
+            let info = &stakes[0];
+            assert_eq!(info.validator, keys.network_pkey);
+            assert_eq!(info.operator, None);
+            assert_eq!(info.reward, None);
+            assert_eq!(chain.reward_key(&keys.network_pkey), None);
+            assert_eq!(info.amount, stake);
+            assert!(info.is_active);
+            let unlock_height = chain.last_macro_block_height()
+                + (info.active_until_epoch - chain.epoch() + 1) * blocks_in_epoch
+                + 1;
+            assert_eq!(info.unlock_height, unlock_height);
+            assert_eq!(
+                chain.stake_unlock_height(&info.utxo).unwrap(),
+                Some(unlock_height)
+            );
+
+            let summary = chain.validator_stake_summary(&keys.network_pkey);
+            assert_eq!(summary.own_stake, stake);
+            assert_eq!(summary.consensus_stake, stake);
+            assert_eq!(summary.delegated_in, 0);
+            assert_eq!(summary.delegated_out, 0);
+            assert_eq!(summary.outputs, 1);
+        }
+
+        // Unknown output.
+        assert_eq!(
+            chain.stake_unlock_height(&Hash::digest("test")).unwrap(),
+            None
+        );
+        // Unknown wallet.
+        let (_skey, pkey) = stegos_crypto::curve1174::make_random_keys();
+        assert!(chain.stakes_by_wallet(&pkey).is_empty());
+    }
+
+    #[test]
+    fn reward_recipient() {
+        use crate::transaction::RestakeTransaction;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let keys = &keychains[0];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let stake = cfg.min_stake_amount;
+        let genesis = genesis(&keychains, stake, 10 * stake, timestamp);
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        let chain_id = chain.chain_id();
+
+        // Redirect rewards to another wallet.
+        let (_reward_skey, reward_pkey) = stegos_crypto::curve1174::make_random_keys();
+        let stake_hash = chain.stakes_by_wallet(&keys.wallet_pkey)[0].utxo;
+        let input = chain.output_by_hash(&stake_hash).unwrap().unwrap();
+        let mut output = match &input {
+            Output::StakeOutput(o) => o.clone(),
+            _ => panic!("expected a stake"),
+        };
+        let reward = RewardDestination::new(
+            &keys.wallet_skey,
+            &keys.wallet_pkey,
+            &keys.network_pkey,
+            &reward_pkey,
+        );
+        output.set_reward(Some(reward), &keys.network_skey);
+        let restake = RestakeTransaction::new(
+            &chain_id,
+            &keys.network_skey,
+            &keys.network_pkey,
+            &[input],
+            &[output.into()],
+        )
+        .unwrap();
+        timestamp += Duration::from_millis(1);
+        let mut block = create_micro_block_with_coinbase(&chain, &keychains, timestamp).unwrap();
+        block.transactions.push(restake.into());
+        block.sign(&keys.network_skey, &keys.network_pkey).unwrap();
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        assert_eq!(chain.reward_key(&keys.network_pkey), Some(reward_pkey));
+        assert_eq!(chain.award_recipient(&keys.wallet_pkey), reward_pkey);
+
+        // Rewards are paid publicly to the destination.
+        timestamp += Duration::from_millis(1);
+        let block = create_micro_block_with_coinbase(&chain, &keychains, timestamp).unwrap();
+        match &block.transactions[0].txouts()[0] {
+            Output::PublicPaymentOutput(o) => assert_eq!(o.recipient, reward_pkey),
+            _ => panic!("expected a public output"),
+        }
+        chain
+            .validate_micro_block(&block, timestamp)
+            .expect("block is valid");
+
+        // Rewards paid to the wallet of the validator are rejected.
+        let block_reward = chain.block_reward(chain.height());
+        let bad_coinbase = |output: Output, gamma: Fr| {
+            let coinbase = CoinbaseTransaction {
+                block_reward,
+                block_fee: 0,
+                gamma: -gamma,
+                txouts: vec![output],
+            };
+            let mut bad = block.clone();
+            bad.transactions[0] = coinbase.into();
+            bad.sign(&keys.network_skey, &keys.network_pkey).unwrap();
+            bad
+        };
+        let (cloaked, gamma) = PaymentOutput::new(&reward_pkey, block_reward).unwrap();
+        let public = PublicPaymentOutput::new(&keys.wallet_pkey, block_reward);
+        for bad in vec![
+            bad_coinbase(cloaked.into(), gamma),
+            bad_coinbase(public.into(), Fr::zero()),
+        ] {
+            match chain.validate_micro_block(&bad, timestamp).unwrap_err() {
+                BlockchainError::BlockError(BlockError::InvalidRewardRecipient(..)) => {}
+                e => panic!("{}", e),
+            }
+        }
+
+        // New stakes can't pay rewards to another wallet.
+        let (_other_skey, other_pkey) = stegos_crypto::curve1174::make_random_keys();
+        let mut output = StakeOutput::new(
+            &keys.wallet_pkey,
+            &keys.network_skey,
+            &keys.network_pkey,
+            stake,
+        )
+        .unwrap();
+        let reward = RewardDestination::new(
+            &keys.wallet_skey,
+            &keys.wallet_pkey,
+            &keys.network_pkey,
+            &other_pkey,
+        );
+        output.set_reward(Some(reward), &keys.network_skey);
+        let tx = PaymentTransaction::new(
+            &chain_id,
+            &keys.wallet_skey,
+            &[],
+            &[output.into()],
+            &Fr::zero(),
+            0,
+        )
+        .unwrap();
+        let tx: Transaction = tx.into();
+        match chain.validate_staker(&tx, &[]).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::ConflictingRewardDestination(
+                validator,
+                ..,
+            )) => assert_eq!(validator, keys.network_pkey),
+            e => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn slash_delegated_stake() {
+        use crate::slashing::{confiscate_tx, SlashingProof};
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [
+            KeyChain::new_mem(),
+            KeyChain::new_mem(),
+            KeyChain::new_mem(),
+        ];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let stake = cfg.min_stake_amount;
+        let genesis = genesis(&keychains, stake, 10 * stake, timestamp);
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+
+        // The leader signs two different blocks at the same height.
+        timestamp += Duration::from_millis(1);
+        let (block1, _, _) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let cheater = block1.pkey;
+        let cheater_keys = keychains
+            .iter()
+            .find(|k| k.network_pkey == cheater)
+            .unwrap();
+        let mut block2 = block1.clone();
+        block2.base.timestamp += Duration::from_millis(1);
+        block2.sign(&cheater_keys.network_skey, &cheater_keys.network_pkey);
+        chain
+            .push_micro_block(block1.clone(), timestamp)
+            .expect("block is valid");
+
+        // The cheater has no own stake, only a stake delegated by other validator.
+        let owner = keychains
+            .iter()
+            .find(|k| k.network_pkey != cheater)
+            .unwrap();
+        let lsn = lsn_for_height(chain.height());
+        let epoch = chain.epoch();
+        let stake_epochs = chain.cfg().stake_epochs;
+        let (own_outputs, _) = chain.staker_outputs(&cheater);
+        assert_eq!(own_outputs.len(), 1);
+        chain.escrow.unstake(lsn, cheater, own_outputs[0], epoch);
+        let delegated = Hash::digest("delegated");
+        chain.escrow.stake(
+            lsn,
+            owner.network_pkey,
+            delegated,
+            epoch,
+            stake_epochs,
+            stake,
+            owner.wallet_pkey,
+            Some(cheater),
+        );
+
+        let proof = SlashingProof::new_unchecked(block1, block2);
+        let tx = confiscate_tx(&chain, &owner.network_pkey, proof).expect("cheater is slashed");
+        assert_eq!(tx.txins, vec![delegated]);
+        let amount: i64 = tx
+            .txouts
+            .iter()
+            .map(|o| match o {
+                Output::PublicPaymentOutput(o) => o.amount,
+                _ => panic!("Expected public payment output"),
+            })
+            .sum();
+        assert_eq!(amount, stake);
+
+        // The stake delegated to the cheater doesn't belong to its owner anymore.
+        let (owner_outputs, owner_stake) = chain.staker_outputs(&owner.network_pkey);
+        assert!(!owner_outputs.contains(&delegated));
+        assert_eq!(owner_stake, stake);
+    }
+
+    #[test]
+    fn checkpoints() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keychains = [KeyChain::new_mem()];
+
+        let mut timestamp = SystemTime::now();
+        let mut cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let chain = Blockchain::testing(cfg.clone(), genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+        timestamp += Duration::from_millis(1);
+        let (block1, _, _) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let (other1, _, _) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let block1_hash = Hash::digest(&block1);
+        cfg.checkpoints = vec![Checkpoint {
+            height: 1,
+            block_hash: block1_hash,
+        }];
+
+        // Blocks contradicting the checkpoint are rejected.
+        let mut chain = Blockchain::testing(cfg.clone(), genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+        match chain.push_micro_block(other1, timestamp).unwrap_err() {
+            BlockchainError::BlockError(BlockError::CheckpointMismatch(height, _, hash)) => {
+                assert_eq!(height, 1);
+                assert_eq!(hash, block1_hash);
+            }
+            e => panic!("{}", e),
+        }
+        chain
+            .push_micro_block(block1.clone(), timestamp)
+            .expect("block is valid");
+
+        // Blocks above the checkpoint can be reverted, checkpointed ones can't.
+        timestamp += Duration::from_millis(1);
+        let (block2, _, _) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        chain
+            .push_micro_block(block2, timestamp)
+            .expect("block is valid");
+        chain.pop_micro_block().expect("block can be reverted");
+        match chain.pop_micro_block().unwrap_err() {
+            BlockchainError::BlockError(BlockError::CheckpointRevert(height, hash)) => {
+                assert_eq!(height, 1);
+                assert_eq!(hash, block1_hash);
+            }
+            e => panic!("{}", e),
+        }
+        assert_eq!(chain.height(), 2);
+
+        // Signatures are not verified below the last checkpoint with assume_valid.
+        let mut forged = block1;
+        forged.sig = pbc::sign_hash(&Hash::digest("forged"), &keychains[0].network_skey);
+        cfg.checkpoints[0].height = 2;
+        let mut chain = Blockchain::testing(cfg.clone(), genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+        match chain
+            .push_micro_block(forged.clone(), timestamp)
+            .unwrap_err()
+        {
+            BlockchainError::BlockError(BlockError::InvalidLeaderSignature(height, _)) => {
+                assert_eq!(height, 1);
+            }
+            e => panic!("{}", e),
+        }
+        cfg.assume_valid = true;
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        chain
+            .push_micro_block(forged, timestamp)
+            .expect("signature is not verified");
+    }
+
     #[test]
     fn block_range_limit() {
         simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
@@ -1764,7 +3356,8 @@ pub mod tests {
         assert!(blockchain.height() > 0);
         for _height in 2..12 {
             timestamp += Duration::from_millis(1);
-            let block = create_micro_block_with_coinbase(&blockchain, &keychains, timestamp);
+            let block = create_micro_block_with_coinbase(&blockchain, &keychains, timestamp)
+                .expect("block is valid");
             blockchain
                 .push_micro_block(block, timestamp)
                 .expect("Invalid block");
@@ -1778,4 +3371,266 @@ pub mod tests {
         // empty
         assert_eq!(blockchain.blocks_range(blockchain.height(), 1).len(), 0);
     }
+
+    #[test]
+    fn epoch_summaries() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keychains = [KeyChain::new_mem()];
+
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let database = ListDb::testing();
+        let mut chain =
+            Blockchain::with_db(cfg.clone(), database.clone(), genesis.clone(), timestamp)
+                .expect("Failed to create blockchain");
+        let epoch = chain.epoch();
+        assert!(chain.epoch_summary(epoch).unwrap().is_none());
+
+        // Reverted micro blocks are not included in the summary.
+        let mut heights = Vec::new();
+        let mut rewards: i64 = 0;
+        for _ in 0..3 {
+            timestamp += Duration::from_millis(1);
+            let (block, _, _) =
+                create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+            heights.push(chain.height());
+            rewards += chain.block_reward(chain.height());
+            chain
+                .push_micro_block(block, timestamp)
+                .expect("block is valid");
+        }
+        chain.pop_micro_block().expect("no disk errors");
+        heights.pop();
+        rewards -= chain.block_reward(chain.height());
+
+        timestamp += Duration::from_millis(1);
+        let block = create_fake_macro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let block_hash = Hash::digest(&block);
+        let block_height = chain.height();
+        chain
+            .push_macro_block(block, timestamp)
+            .expect("block is valid");
+        assert!(chain.epoch_summary(chain.epoch()).unwrap().is_none());
+
+        let summary = chain
+            .epoch_summary(epoch)
+            .unwrap()
+            .expect("epoch is finalized");
+        assert_eq!(summary.epoch, epoch);
+        assert_eq!(summary.macro_block_height, block_height);
+        assert_eq!(summary.macro_block_hash, block_hash);
+        let leader_heights: Vec<u64> = summary.leaders.iter().map(|l| l.height).collect();
+        assert_eq!(leader_heights, heights);
+        for leader in &summary.leaders {
+            assert_eq!(leader.leader, keychains[0].network_pkey);
+        }
+        assert_eq!(summary.view_changes, 0);
+        // A coinbase and a payment per block.
+        assert_eq!(summary.transactions, 2 * heights.len() as u64);
+        assert_eq!(summary.fees, 0);
+        assert_eq!(summary.micro_block_rewards, rewards);
+        assert_eq!(summary.validators.len(), 1);
+        let uptime = &summary.validators[0];
+        assert_eq!(uptime.network_pkey, keychains[0].network_pkey);
+        assert_eq!(uptime.produced, heights.len() as u64);
+        assert_eq!(uptime.missed, 0);
+        assert!(uptime.active);
+
+        // Summaries are kept on the disk.
+        drop(chain);
+        let chain = Blockchain::with_db(cfg, database, genesis, timestamp)
+            .expect("Failed to recover blockchain");
+        let recovered = chain
+            .epoch_summary(epoch)
+            .unwrap()
+            .expect("epoch is finalized");
+        assert_eq!(recovered.macro_block_hash, summary.macro_block_hash);
+        assert_eq!(recovered.leaders, summary.leaders);
+        assert_eq!(recovered.validators, summary.validators);
+    }
+
+    #[test]
+    fn finality() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keychains = [KeyChain::new_mem()];
+
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let database = ListDb::testing();
+        let mut chain =
+            Blockchain::with_db(cfg.clone(), database.clone(), genesis.clone(), timestamp)
+                .expect("Failed to create blockchain");
+        assert_eq!(chain.finalized_height(), 0);
+        let commitment = chain.finality_commitment().expect("genesis is final");
+        assert!(commitment.trusted);
+        assert_eq!(commitment.block_hash, chain.last_block_hash());
+
+        // Micro blocks are reversible.
+        for _ in 0..2 {
+            timestamp += Duration::from_millis(1);
+            let (block, _, _) =
+                create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+            chain
+                .push_micro_block(block, timestamp)
+                .expect("block is valid");
+        }
+        assert_eq!(chain.finalized_height(), 0);
+
+        // Macro blocks are committed by the supermajority of stake.
+        let epoch = chain.epoch();
+        timestamp += Duration::from_millis(1);
+        let block = create_fake_macro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let block_hash = Hash::digest(&block);
+        let block_height = chain.height();
+        chain
+            .push_macro_block(block, timestamp)
+            .expect("block is valid");
+        assert_eq!(chain.finalized_height(), block_height);
+        let commitment = chain.finality_commitment().unwrap().clone();
+        assert_eq!(commitment.epoch, epoch);
+        assert_eq!(commitment.block_hash, block_hash);
+        assert!(!commitment.trusted);
+        assert_eq!(commitment.signed_slots, commitment.total_slots);
+        assert!(commitment.is_final());
+
+        // Recovery.
+        drop(chain);
+        let chain = Blockchain::with_db(cfg, database, genesis, timestamp)
+            .expect("Failed to recover blockchain");
+        assert_eq!(chain.finalized_height(), block_height);
+        assert_eq!(chain.finality_commitment(), Some(&commitment));
+    }
+
+    #[test]
+    fn random_beacon() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keychains = [KeyChain::new_mem()];
+
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        timestamp += Duration::from_millis(1);
+        let block = create_micro_block_with_coinbase(&mut chain, &keychains, timestamp)
+            .expect("block is valid");
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        timestamp += Duration::from_millis(1);
+        let block = create_fake_macro_block(&chain, &keychains, timestamp).expect("block is valid");
+        chain
+            .push_macro_block(block, timestamp)
+            .expect("block is valid");
+
+        let mut previous_random = None;
+        for height in 0..chain.height() {
+            let beacon = chain
+                .random_at(height)
+                .expect("no disk errors")
+                .expect("block exists");
+            assert!(beacon.verify());
+            assert_eq!(beacon.height, height);
+            assert_eq!(beacon.leader, keychains[0].network_pkey);
+            let block = chain.block_by_height(height).expect("no disk errors");
+            assert_eq!(beacon.block_hash, Hash::digest(&block));
+            assert_eq!(beacon.random, block.base_header().random.rand);
+            if let Some(previous_random) = previous_random {
+                assert_eq!(beacon.previous_random, previous_random);
+            }
+            previous_random = Some(beacon.random);
+
+            let mut forged = beacon.clone();
+            forged.view_change += 1;
+            assert!(!forged.verify());
+        }
+        assert_eq!(chain.last_random(), previous_random.unwrap());
+        assert!(chain
+            .random_at(chain.height())
+            .expect("no disk errors")
+            .is_none());
+    }
+
+    #[test]
+    fn block_timestamps() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keychains = [KeyChain::new_mem()];
+
+        let timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let max_time_drift = cfg.max_time_drift;
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        let clock = MockClock::new(timestamp);
+        chain.set_clock(Arc::new(clock.clone()));
+        assert_eq!(chain.last_block_timestamp(), timestamp);
+
+        // Blocks earlier than the previous block are rejected.
+        let past = timestamp - Duration::from_secs(1);
+        let (block, _, _) =
+            create_fake_micro_block(&chain, &keychains, past).expect("block is valid");
+        match chain.push_micro_block(block, timestamp).unwrap_err() {
+            BlockchainError::BlockError(BlockError::TimestampBeforePrevious(height, _, t, p)) => {
+                assert_eq!(height, 1);
+                assert_eq!(t, past);
+                assert_eq!(p, timestamp);
+            }
+            e => panic!("{}", e),
+        }
+
+        // Blocks too far ahead of the local clock are rejected.
+        let future = timestamp + max_time_drift + Duration::from_secs(1);
+        let (block, _, _) =
+            create_fake_micro_block(&chain, &keychains, future).expect("block is valid");
+        match chain
+            .push_micro_block(block.clone(), timestamp)
+            .unwrap_err()
+        {
+            BlockchainError::BlockError(BlockError::TimestampInFuture(height, _, t, now)) => {
+                assert_eq!(height, 1);
+                assert_eq!(t, future);
+                assert_eq!(now, timestamp);
+            }
+            e => panic!("{}", e),
+        }
+
+        // ... until the clock catches up.
+        clock.advance(Duration::from_secs(1));
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        assert_eq!(chain.last_block_timestamp(), future);
+
+        // New blocks never go back in time.
+        assert_eq!(chain.block_timestamp(), future);
+        clock.set(future + Duration::from_secs(1));
+        assert_eq!(chain.block_timestamp(), future + Duration::from_secs(1));
+
+        // The timestamp is restored on revert.
+        chain.pop_micro_block().expect("block can be reverted");
+        assert_eq!(chain.last_block_timestamp(), timestamp);
+    }
 }
@@ -0,0 +1,411 @@
+//! Payment Channels.
+
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::{BlockchainError, TransactionError};
+use crate::output::{ChannelLock, Output, PublicPaymentOutput};
+use crate::transaction::PaymentTransaction;
+use std::collections::HashSet;
+use stegos_crypto::curve1174::{sign_hash, validate_sig, PublicKey, SchnorrSig, SecretKey};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::CryptoError;
+
+/// A state of a payment channel, exchanged off-chain by the parties.
+/// Each update increments `sequence` and must be signed by both parties.
+#[derive(Debug, Clone)]
+pub struct ChannelState {
+    /// Identifier of the channel, the hash of the funding output.
+    pub channel: Hash,
+    /// Uncloaked public key of the party which funded the channel.
+    pub funder: PublicKey,
+    /// Uncloaked public key of the other party.
+    pub counterparty: PublicKey,
+    /// The sequence number of the state.
+    pub sequence: u64,
+    pub funder_balance: i64,
+    pub counterparty_balance: i64,
+    /// Signature of the funder.
+    pub funder_sig: SchnorrSig,
+    /// Signature of the counterparty.
+    pub counterparty_sig: SchnorrSig,
+}
+
+impl Hashable for ChannelState {
+    fn hash(&self, state: &mut Hasher) {
+        "ChannelState".hash(state);
+        self.channel.hash(state);
+        self.funder.hash(state);
+        self.counterparty.hash(state);
+        self.sequence.hash(state);
+        self.funder_balance.hash(state);
+        self.counterparty_balance.hash(state);
+    }
+}
+
+impl ChannelState {
+    /// Create a new unsigned state.
+    pub fn new(
+        channel: Hash,
+        funder: PublicKey,
+        counterparty: PublicKey,
+        sequence: u64,
+        funder_balance: i64,
+        counterparty_balance: i64,
+    ) -> Self {
+        ChannelState {
+            channel,
+            funder,
+            counterparty,
+            sequence,
+            funder_balance,
+            counterparty_balance,
+            funder_sig: SchnorrSig::new(),
+            counterparty_sig: SchnorrSig::new(),
+        }
+    }
+
+    /// Sign the state by one of the parties.
+    pub fn sign(&mut self, skey: &SecretKey, pkey: &PublicKey) {
+        let hash = Hash::digest(self);
+        let sig = sign_hash(&hash, skey);
+        if pkey == &self.funder {
+            self.funder_sig = sig;
+        } else {
+            assert_eq!(pkey, &self.counterparty);
+            self.counterparty_sig = sig;
+        }
+    }
+
+    /// Checks signature of one of the parties.
+    pub fn validate_sig(&self, pkey: &PublicKey) -> Result<(), CryptoError> {
+        let hash = Hash::digest(self);
+        if pkey == &self.funder {
+            validate_sig(&hash, &self.funder_sig, &self.funder)
+        } else if pkey == &self.counterparty {
+            validate_sig(&hash, &self.counterparty_sig, &self.counterparty)
+        } else {
+            Err(CryptoError::BadKeyingSignature)
+        }
+    }
+
+    /// Checks that the state is signed by both parties.
+    pub fn validate(&self) -> Result<(), CryptoError> {
+        self.validate_sig(&self.funder)?;
+        self.validate_sig(&self.counterparty)?;
+        Ok(())
+    }
+
+    /// Returns the balance of the party.
+    pub fn balance(&self, pkey: &PublicKey) -> Option<i64> {
+        if pkey == &self.funder {
+            Some(self.funder_balance)
+        } else if pkey == &self.counterparty {
+            Some(self.counterparty_balance)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the other party of the channel.
+    pub fn other(&self, pkey: &PublicKey) -> Option<&PublicKey> {
+        if pkey == &self.funder {
+            Some(&self.counterparty)
+        } else if pkey == &self.counterparty {
+            Some(&self.funder)
+        } else {
+            None
+        }
+    }
+}
+
+/// A proof which allows to spend a channel input.
+#[derive(Debug, Clone)]
+pub enum ChannelWitness {
+    /// Cooperative close of a funding output.
+    /// The transaction is signed by the funder, `sig` is the signature
+    /// of the counterparty on the hash of transaction.
+    Cooperative { txin: Hash, sig: SchnorrSig },
+    /// Unilateral close of a funding output by `closer` with the latest known state.
+    /// The transaction must pay the balance of the other party immediately
+    /// and lock the balance of the closer for the dispute period.
+    Close {
+        txin: Hash,
+        state: ChannelState,
+        closer: PublicKey,
+    },
+    /// Spending of a dispute output by the counterparty,
+    /// which proves that the closer has published an outdated state.
+    Penalty { txin: Hash, state: ChannelState },
+}
+
+impl ChannelWitness {
+    /// Returns the input spent by this witness.
+    pub fn txin(&self) -> &Hash {
+        match self {
+            ChannelWitness::Cooperative { txin, .. } => txin,
+            ChannelWitness::Close { txin, .. } => txin,
+            ChannelWitness::Penalty { txin, .. } => txin,
+        }
+    }
+}
+
+/// The signature of a cooperative close isn't hashed, because it signs the transaction.
+impl Hashable for ChannelWitness {
+    fn hash(&self, state: &mut Hasher) {
+        match self {
+            ChannelWitness::Cooperative { txin, sig: _ } => {
+                "Cooperative".hash(state);
+                txin.hash(state);
+            }
+            ChannelWitness::Close {
+                txin,
+                state: channel_state,
+                closer,
+            } => {
+                "Close".hash(state);
+                txin.hash(state);
+                channel_state.hash(state);
+                closer.hash(state);
+            }
+            ChannelWitness::Penalty {
+                txin,
+                state: channel_state,
+            } => {
+                "Penalty".hash(state);
+                txin.hash(state);
+                channel_state.hash(state);
+            }
+        }
+    }
+}
+
+impl PaymentTransaction {
+    /// Returns the channel witness for the input, if any.
+    pub fn channel_witness(&self, txin_hash: &Hash) -> Option<&ChannelWitness> {
+        self.channels
+            .iter()
+            .find(|witness| witness.txin() == txin_hash)
+    }
+
+    /// Returns the key which must sign the spending of a channel input.
+    pub(crate) fn channel_input_signer(
+        &self,
+        txin_hash: &Hash,
+        input: &PublicPaymentOutput,
+        lock: &ChannelLock,
+    ) -> PublicKey {
+        match (lock, self.channel_witness(txin_hash)) {
+            (ChannelLock::Funding { .. }, Some(ChannelWitness::Close { closer, .. })) => *closer,
+            (ChannelLock::Dispute { counterparty, .. }, Some(ChannelWitness::Penalty { .. })) => {
+                *counterparty
+            }
+            _ => input.recipient,
+        }
+    }
+
+    /// Checks channel witnesses against channel inputs and settlement outputs.
+    ///
+    /// # Arguments
+    ///
+    /// * - `inputs` - UTXOs referred by self.body.txins, in the same order as in self.body.txins.
+    ///
+    pub(crate) fn validate_channels(
+        &self,
+        tx_hash: &Hash,
+        inputs: &[Output],
+    ) -> Result<(), BlockchainError> {
+        let mut witnesses_set: HashSet<Hash> = HashSet::new();
+        // Each output can settle only one channel.
+        let mut payouts_set: HashSet<usize> = HashSet::new();
+        for witness in &self.channels {
+            let txin_hash = witness.txin();
+            let invalid_witness = || TransactionError::InvalidChannelWitness(*tx_hash, *txin_hash);
+            if !witnesses_set.insert(*txin_hash) {
+                return Err(invalid_witness().into());
+            }
+            let input = match self
+                .txins
+                .iter()
+                .position(|hash| hash == txin_hash)
+                .map(|i| &inputs[i])
+            {
+                Some(Output::PublicPaymentOutput(o)) if o.channel.is_some() => o,
+                _ => return Err(invalid_witness().into()),
+            };
+            let lock = input.channel.as_ref().unwrap();
+
+            match (lock, witness) {
+                (
+                    ChannelLock::Funding { counterparty, .. },
+                    ChannelWitness::Cooperative { sig, .. },
+                ) => {
+                    validate_sig(tx_hash, sig, counterparty).map_err(|_e| invalid_witness())?;
+                }
+                (
+                    ChannelLock::Funding {
+                        counterparty,
+                        dispute_period,
+                    },
+                    ChannelWitness::Close { state, closer, .. },
+                ) => {
+                    if state.channel != *txin_hash
+                        || state.funder != input.recipient
+                        || state.counterparty != *counterparty
+                        || state.funder_balance < 0
+                        || state.counterparty_balance < 0
+                        || state.funder_balance.checked_add(state.counterparty_balance)
+                            != Some(input.amount)
+                        || state.validate().is_err()
+                    {
+                        return Err(invalid_witness().into());
+                    }
+                    let other = state.other(closer).ok_or_else(invalid_witness)?;
+                    let closer_balance = state.balance(closer).unwrap();
+                    let other_balance = state.balance(other).unwrap();
+                    let invalid_settlement =
+                        || TransactionError::InvalidChannelSettlement(*tx_hash, *txin_hash);
+
+                    // The other party receives its balance immediately.
+                    if other_balance > 0 {
+                        let payout = self.find_payout(&payouts_set, |o| {
+                            o.recipient == *other
+                                && o.amount == other_balance
+                                && o.hashlock.is_none()
+                                && o.channel.is_none()
+                        });
+                        match payout {
+                            Some(i) => payouts_set.insert(i),
+                            None => return Err(invalid_settlement().into()),
+                        };
+                    }
+
+                    // The closer receives its balance after the dispute period.
+                    let dispute = ChannelLock::Dispute {
+                        channel: *txin_hash,
+                        sequence: state.sequence,
+                        counterparty: *other,
+                        dispute_period: *dispute_period,
+                    };
+                    if closer_balance > self.fee {
+                        let payout = self.find_payout(&payouts_set, |o| {
+                            o.recipient == *closer
+                                && o.amount >= closer_balance - self.fee
+                                && o.channel.as_ref() == Some(&dispute)
+                        });
+                        match payout {
+                            Some(i) => payouts_set.insert(i),
+                            None => return Err(invalid_settlement().into()),
+                        };
+                    } else {
+                        // The fee consumes the balance of the closer, nothing to dispute.
+                        let payout = self.find_payout(&payouts_set, |o| match &o.channel {
+                            Some(ChannelLock::Dispute { channel, .. }) => channel == txin_hash,
+                            _ => false,
+                        });
+                        if payout.is_some() {
+                            return Err(invalid_settlement().into());
+                        }
+                    }
+                }
+                (
+                    ChannelLock::Dispute {
+                        channel,
+                        sequence,
+                        counterparty,
+                        ..
+                    },
+                    ChannelWitness::Penalty { state, .. },
+                ) => {
+                    // The state must be newer than the state published by the closer.
+                    let parties_match = (state.funder == input.recipient
+                        && state.counterparty == *counterparty)
+                        || (state.funder == *counterparty && state.counterparty == input.recipient);
+                    if state.channel != *channel
+                        || state.sequence <= *sequence
+                        || !parties_match
+                        || state.validate().is_err()
+                    {
+                        return Err(invalid_witness().into());
+                    }
+                }
+                _ => return Err(invalid_witness().into()),
+            }
+        }
+
+        // Funding outputs can't be spent without a witness.
+        for (txin_hash, txin) in self.txins.iter().zip(inputs) {
+            if let Some(ChannelLock::Funding { .. }) = txin.channel_lock() {
+                if !witnesses_set.contains(txin_hash) {
+                    return Err(
+                        TransactionError::MissingChannelWitness(*tx_hash, *txin_hash).into(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the first public output not claimed yet which matches the predicate.
+    fn find_payout<F>(&self, payouts_set: &HashSet<usize>, predicate: F) -> Option<usize>
+    where
+        F: Fn(&PublicPaymentOutput) -> bool,
+    {
+        self.txouts
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !payouts_set.contains(i))
+            .find_map(|(i, txout)| match txout {
+                Output::PublicPaymentOutput(o) if predicate(o) => Some(i),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::make_random_keys;
+
+    #[test]
+    fn state_signatures() {
+        let (skey1, pkey1) = make_random_keys();
+        let (skey2, pkey2) = make_random_keys();
+        let (_skey3, pkey3) = make_random_keys();
+        let mut state = ChannelState::new(Hash::digest("channel"), pkey1, pkey2, 1, 70, 30);
+        assert!(state.validate().is_err());
+        state.sign(&skey1, &pkey1);
+        state.validate_sig(&pkey1).expect("signed by funder");
+        assert!(state.validate().is_err());
+        state.sign(&skey2, &pkey2);
+        state.validate().expect("signed by both parties");
+        assert!(state.validate_sig(&pkey3).is_err());
+        assert_eq!(state.balance(&pkey1), Some(70));
+        assert_eq!(state.other(&pkey2), Some(&pkey1));
+        assert_eq!(state.balance(&pkey3), None);
+
+        // Signatures are invalidated by any update.
+        state.funder_balance += 1;
+        assert!(state.validate().is_err());
+    }
+}
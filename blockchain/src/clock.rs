@@ -0,0 +1,94 @@
+//! Source of the current time.
+
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// A source of the current time, used for block production and validation.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The operating system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A manually driven clock for tests and simulations.
+/// All clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    time: Arc<Mutex<SystemTime>>,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at `time`.
+    pub fn new(time: SystemTime) -> Self {
+        MockClock {
+            time: Arc::new(Mutex::new(time)),
+        }
+    }
+
+    /// Sets the current time.
+    pub fn set(&self, time: SystemTime) {
+        *self.time.lock().unwrap() = time;
+    }
+
+    /// Moves the current time forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.time.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.time.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn mock_clock() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let clock = MockClock::new(start);
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        assert_eq!(shared.now(), start);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(shared.now(), start + Duration::from_secs(10));
+        clock.set(start);
+        assert_eq!(shared.now(), start);
+    }
+}
@@ -21,50 +21,109 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::emission::EmissionConfig;
 use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+use stegos_crypto::hash::Hash;
 
 /// Blockchain configuration.
 #[derive(Debug, Clone)]
 pub struct BlockchainConfig {
+    /// Name of the chain, e.g. "mainnet", "testnet" or "devnet".
+    /// Blocks of other chains are rejected.
+    pub chain_id: String,
     /// Maximal number of slots for election.
     pub max_slot_count: i64,
     /// Minimal stake amount.
     pub min_stake_amount: i64,
     /// How many epochs stake is valid.
     pub stake_epochs: u64,
+    /// The number of blocks per epoch, including the macro block.
+    pub blocks_in_epoch: u64,
     /// Difficulty in bits, of service awards.
     pub awards_difficulty: usize,
-    /// Block reward for creating block.
-    pub block_reward: i64,
-    /// Service award part of block reward.
+    /// Emission schedule of block rewards.
+    pub emission: EmissionConfig,
+    /// Service award part of block reward, for the initial reward of the emission schedule.
     pub service_award_per_epoch: i64,
+    /// Maximal size of a serialized micro block in bytes.
+    pub max_block_size: usize,
+    /// Maximal size of a serialized transaction in bytes.
+    pub max_tx_size: usize,
+    /// Maximal number of inputs in a transaction.
+    pub max_inputs_per_tx: usize,
+    /// The number of shards of the transaction topic.
+    /// Transactions are published to the shard derived from their hash,
+    /// so all nodes of the chain must agree on this value.
+    pub tx_topic_shards: u32,
+    /// Maintain secondary indexes for block explorers.
+    pub explorer_index: bool,
+    /// Trusted block hashes, blocks contradicting them are rejected.
+    pub checkpoints: Vec<Checkpoint>,
+    /// Skip verification of block signatures up to the last checkpoint.
+    /// Invalid blocks below the last checkpoint are detected only when the checkpoint is reached.
+    pub assume_valid: bool,
+    /// Each validator privately checks its eligibility to create micro blocks
+    /// instead of following the public leader schedule.
+    pub secret_leader_election: bool,
+    /// The expected number of eligible validators per view change in the secret leader election.
+    pub secret_leader_candidates: i64,
+    /// False positive rate of the in-memory filter of UTXO hashes, zero disables the filter.
+    pub utxo_filter_false_positive_rate: f64,
+    /// Maximal difference between a block timestamp and the local clock.
+    pub max_time_drift: Duration,
 }
 
 impl Default for BlockchainConfig {
     fn default() -> Self {
         BlockchainConfig {
+            chain_id: "testnet".to_string(),
             max_slot_count: 1000,
             min_stake_amount: 1_000_000_000, // 1000 STG
             stake_epochs: 2,
+            blocks_in_epoch: 5,
             awards_difficulty: 3,
-            block_reward: 40_000_000,                // 40 STG
+            emission: Default::default(),
             service_award_per_epoch: 20_000_000 * 5, // 20 STG for 5 blocks
+            max_block_size: 4 * 1024 * 1024,         // 4 MB
+            max_tx_size: 128 * 1024,                 // 128 KB
+            max_inputs_per_tx: 100,
+            tx_topic_shards: 1,
+            explorer_index: false,
+            checkpoints: Vec::new(),
+            assume_valid: false,
+            secret_leader_election: false,
+            secret_leader_candidates: 3,
+            utxo_filter_false_positive_rate: 0.01,
+            max_time_drift: Duration::from_secs(30),
         }
     }
 }
 
+/// A trusted block hash at some height.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Block height.
+    pub height: u64,
+    /// Expected block hash.
+    pub block_hash: Hash,
+}
+
 /// Storage configuration.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct StorageConfig {
     /// Database path
     pub database_path: String,
+    /// Interval of background database compaction (secs), 0 disables
+    pub compaction_interval: u64,
 }
 
 impl Default for StorageConfig {
     fn default() -> Self {
         StorageConfig {
             database_path: "database".to_string(),
+            compaction_interval: 24 * 60 * 60,
         }
     }
 }
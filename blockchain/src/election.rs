@@ -39,6 +39,126 @@ pub struct ElectionInfo {
     pub next_leader: pbc::PublicKey,
 }
 
+/// A validator's range of tickets used by leader selection.
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct LeaderCandidate {
+    pub pkey: pbc::PublicKey,
+    /// Number of slots owned by the validator.
+    pub slots: i64,
+    /// First ticket owned by the validator (inclusive).
+    pub range_start: i64,
+    /// Last ticket owned by the validator (exclusive).
+    pub range_end: i64,
+}
+
+/// The full deterministic computation of `ElectionResult::select_leader()`.
+/// Contains everything needed to verify the choice of a leader independently.
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct LeaderSelectionAudit {
+    pub epoch: u64,
+    pub view_change: u32,
+    /// VRF output of the macro block which has started the epoch.
+    pub seed: Hash,
+    /// mix(seed, view_change).
+    pub mixed: Hash,
+    /// shrink_hash(mixed).
+    pub random: i64,
+    /// Sum of slots of all validators.
+    pub total_slots: i64,
+    /// abs(random) % total_slots.
+    pub ticket: i64,
+    /// Validators in the selection order.
+    pub candidates: Vec<LeaderCandidate>,
+    /// Index of the candidate whose range contains the ticket.
+    pub leader_index: usize,
+    pub leader: pbc::PublicKey,
+}
+
+impl LeaderSelectionAudit {
+    /// Re-computes every step of the selection and checks that it matches the recorded one.
+    pub fn verify(&self) -> bool {
+        let mixed = mix(self.seed, self.view_change);
+        let random = shrink_hash(mixed);
+        let total_slots: i64 = self.candidates.iter().map(|c| c.slots).sum();
+        if mixed != self.mixed || random != self.random || total_slots != self.total_slots {
+            return false;
+        }
+        if total_slots <= 0 || random.checked_abs().unwrap_or(0) % total_slots != self.ticket {
+            return false;
+        }
+        let mut accumulator: i64 = 0;
+        for candidate in &self.candidates {
+            if candidate.slots < 0
+                || candidate.range_start != accumulator
+                || candidate.range_end != accumulator + candidate.slots
+            {
+                return false;
+            }
+            accumulator = candidate.range_end;
+        }
+        match self.candidates.get(self.leader_index) {
+            Some(c) => {
+                c.pkey == self.leader && c.range_start <= self.ticket && self.ticket < c.range_end
+            }
+            None => false,
+        }
+    }
+}
+
+/// Verifiable randomness of a block, published as a public randomness beacon.
+///
+/// Each block carries the VRF of `mix(previous_random, view_change)` evaluated by its leader,
+/// so the randomness can be checked by anyone who knows the randomness of the previous block.
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct RandomBeacon {
+    pub height: u64,
+    pub block_hash: Hash,
+    pub view_change: u32,
+    /// Network key of the leader of the block.
+    pub leader: pbc::PublicKey,
+    /// Randomness of the previous block.
+    pub previous_random: Hash,
+    /// VRF output.
+    pub random: Hash,
+    /// VRF proof, hex-encoded G1 point.
+    pub proof: String,
+}
+
+impl RandomBeacon {
+    pub fn new(
+        height: u64,
+        block_hash: Hash,
+        view_change: u32,
+        leader: pbc::PublicKey,
+        previous_random: Hash,
+        random: &pbc::VRF,
+    ) -> Self {
+        RandomBeacon {
+            height,
+            block_hash,
+            view_change,
+            leader,
+            previous_random,
+            random: random.rand,
+            proof: random.proof.to_hex(),
+        }
+    }
+
+    /// Checks that the randomness has been produced by the leader from the previous randomness.
+    pub fn verify(&self) -> bool {
+        let proof = match pbc::G1::try_from_hex(&self.proof) {
+            Ok(proof) => proof,
+            Err(_e) => return false,
+        };
+        let random = pbc::VRF {
+            rand: self.random,
+            proof,
+        };
+        let seed = mix(self.previous_random, self.view_change);
+        pbc::validate_VRF_source(&random, &self.leader, &seed)
+    }
+}
+
 /// Result of election.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ElectionResult {
@@ -77,6 +197,84 @@ impl ElectionResult {
             select_winner(self.validators.iter().map(|(_k, slots)| slots), random).unwrap();
         self.validators[leader_id].0
     }
+
+    /// Checks eligibility of the validator in the secret leader election.
+    ///
+    /// `random` is the VRF of `mix(seed, view_change)` evaluated by the validator.
+    /// The validator is eligible if a ticket drawn from the VRF output hits its slots,
+    /// scaled by the expected number of eligible validators per view change.
+    /// Nobody else can predict the result until the validator reveals the VRF with a block.
+    pub fn is_secret_leader(
+        &self,
+        pkey: &pbc::PublicKey,
+        random: &pbc::VRF,
+        candidates: i64,
+    ) -> bool {
+        let slots = match self.validators.iter().find(|(k, _slots)| k == pkey) {
+            Some((_k, slots)) => *slots,
+            None => return false,
+        };
+        let total_slots: i64 = self.validators.iter().map(|(_k, slots)| slots).sum();
+        if total_slots <= 0 {
+            return false;
+        }
+        let ticket = shrink_hash(random.rand).checked_abs().unwrap_or(0) % total_slots;
+        ticket < slots.saturating_mul(candidates)
+    }
+
+    /// Returns the full computation of `select_leader(view_change)` for the audit purposes.
+    /// Returns None if there are no validators.
+    pub fn audit_leader(&self, epoch: u64, view_change: u32) -> Option<LeaderSelectionAudit> {
+        let seed = self.random.rand;
+        let mixed = mix(seed, view_change);
+        let random = shrink_hash(mixed);
+        let leader_index = select_winner(self.validators.iter().map(|(_k, slots)| slots), random)?;
+        let mut candidates = Vec::with_capacity(self.validators.len());
+        let mut accumulator: i64 = 0;
+        for (pkey, slots) in &self.validators {
+            candidates.push(LeaderCandidate {
+                pkey: *pkey,
+                slots: *slots,
+                range_start: accumulator,
+                range_end: accumulator + slots,
+            });
+            accumulator += slots;
+        }
+        let total_slots = accumulator;
+        let ticket = random.checked_abs().unwrap_or(0) % total_slots;
+        Some(LeaderSelectionAudit {
+            epoch,
+            view_change,
+            seed,
+            mixed,
+            random,
+            total_slots,
+            ticket,
+            candidates,
+            leader_index,
+            leader: self.validators[leader_index].0,
+        })
+    }
+}
+
+/// Generates test vectors of leader selection for the given seed and validators.
+/// External implementations can use them to check their results against this one.
+pub fn leader_selection_test_vectors(
+    seed: Hash,
+    validators: StakersGroup,
+    view_changes: u32,
+) -> Vec<LeaderSelectionAudit> {
+    let election_result = ElectionResult {
+        random: pbc::VRF {
+            rand: seed,
+            proof: pbc::G1::zero(),
+        },
+        validators,
+        ..Default::default()
+    };
+    (0..view_changes)
+        .filter_map(|view_change| election_result.audit_leader(0, view_change))
+        .collect()
 }
 
 /// Choose random validator, based on `random_number`.
@@ -183,7 +381,7 @@ fn generate_u64(seed: Hash, index: u32) -> i64 {
 
 #[cfg(test)]
 mod test {
-    use super::{select_validators_slots, select_winner};
+    use super::{leader_selection_test_vectors, select_validators_slots, select_winner};
     use std::collections::{HashMap, HashSet};
 
     use stegos_crypto::hash::Hash;
@@ -310,4 +508,89 @@ mod test {
             assert_eq!(acc, *i as usize)
         }
     }
+
+    /// Audit trail should match select_leader() and be verifiable.
+    #[test]
+    fn test_leader_selection_audit() {
+        let keys: Vec<_> = (0..4).map(|_| pbc::make_random_keys().1).collect();
+        let validators = vec![(keys[0], 1), (keys[1], 2), (keys[2], 3), (keys[3], 4)];
+        let seed = Hash::digest("leader_selection");
+        let vectors = leader_selection_test_vectors(seed, validators.clone(), 20);
+        assert_eq!(vectors.len(), 20);
+        for (view_change, audit) in vectors.iter().enumerate() {
+            assert_eq!(audit.view_change, view_change as u32);
+            assert_eq!(audit.seed, seed);
+            assert_eq!(audit.total_slots, 10);
+            assert_eq!(audit.candidates.len(), 4);
+            assert_eq!(audit.candidates[3].range_start, 6);
+            assert_eq!(audit.candidates[3].range_end, 10);
+            assert!(audit.verify());
+        }
+
+        // Results must be deterministic and match select_leader().
+        let election_result = super::ElectionResult {
+            random: pbc::VRF {
+                rand: seed,
+                proof: pbc::G1::zero(),
+            },
+            validators,
+            ..Default::default()
+        };
+        for audit in &vectors {
+            assert_eq!(
+                audit.leader,
+                election_result.select_leader(audit.view_change)
+            );
+        }
+
+        // Tampering must be detected.
+        let mut audit = vectors[0].clone();
+        audit.leader_index = (audit.leader_index + 1) % audit.candidates.len();
+        audit.leader = audit.candidates[audit.leader_index].pkey;
+        assert!(!audit.verify());
+        let mut audit = vectors[0].clone();
+        audit.candidates[0].slots += 1;
+        assert!(!audit.verify());
+
+        // No validators - no leader.
+        assert!(leader_selection_test_vectors(seed, Vec::new(), 1).is_empty());
+    }
+
+    /// Eligibility in the secret leader election should depend on the VRF and stake.
+    #[test]
+    fn test_secret_leader() {
+        let keys: Vec<_> = (0..4).map(|_| pbc::make_random_keys()).collect();
+        let validators: Vec<_> = keys.iter().map(|(_skey, pkey)| (*pkey, 1)).collect();
+        let election_result = super::ElectionResult {
+            validators,
+            ..Default::default()
+        };
+        let (outsider_skey, outsider_pkey) = pbc::make_random_keys();
+
+        let mut eligible = 0;
+        let mut nobody = 0;
+        for view_change in 0..100 {
+            let seed = super::mix(Hash::digest("secret_leader"), view_change);
+            let mut leaders = 0;
+            for (skey, pkey) in &keys {
+                let random = pbc::make_VRF(skey, &seed);
+                if election_result.is_secret_leader(pkey, &random, 1) {
+                    leaders += 1;
+                }
+                // Everybody is eligible when candidates cover all slots.
+                assert!(election_result.is_secret_leader(pkey, &random, 4));
+                // Nobody is eligible without candidates.
+                assert!(!election_result.is_secret_leader(pkey, &random, 0));
+            }
+            eligible += leaders;
+            if leaders == 0 {
+                nobody += 1;
+            }
+            let random = pbc::make_VRF(&outsider_skey, &seed);
+            assert!(!election_result.is_secret_leader(&outsider_pkey, &random, 4));
+        }
+        // One leader per view change is expected.
+        assert!(eligible > 50 && eligible < 150);
+        assert!(nobody < 100);
+    }
 }
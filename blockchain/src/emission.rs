@@ -0,0 +1,188 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Emission schedule.
+
+use failure::{bail, Error};
+use serde_derive::{Deserialize, Serialize};
+
+/// Emission schedule of block rewards.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct EmissionConfig {
+    /// Reward for the first blocks.
+    pub initial_reward: i64,
+    /// The number of blocks between reward reductions, zero disables reductions.
+    pub decay_interval: u64,
+    /// How much reward is reduced every interval, in percents (50 is halving).
+    pub decay_percent: u64,
+    /// The minimal reward, paid forever once decayed reward drops below it.
+    pub tail_reward: i64,
+}
+
+impl Default for EmissionConfig {
+    fn default() -> Self {
+        EmissionConfig {
+            initial_reward: 40_000_000, // 40 STG
+            decay_interval: 0,
+            decay_percent: 50,
+            tail_reward: 0,
+        }
+    }
+}
+
+impl EmissionConfig {
+    /// A fixed reward for every block.
+    pub fn fixed(reward: i64) -> Self {
+        EmissionConfig {
+            initial_reward: reward,
+            decay_interval: 0,
+            decay_percent: 0,
+            tail_reward: reward,
+        }
+    }
+
+    /// Checks that the schedule is consistent.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.decay_percent > 100 {
+            bail!(
+                "Invalid emission: decay_percent={} is greater than 100",
+                self.decay_percent
+            );
+        }
+        if self.initial_reward < 0 || self.tail_reward < 0 {
+            bail!(
+                "Invalid emission: negative reward, initial_reward={}, tail_reward={}",
+                self.initial_reward,
+                self.tail_reward
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the maximal reward for the block at the given height.
+    pub fn block_reward(&self, height: u64) -> i64 {
+        let mut reward = self.initial_reward;
+        if self.decay_interval > 0 && self.decay_percent > 0 {
+            let intervals = height / self.decay_interval;
+            // The reduction is rounded up, so the reward drops to the tail
+            // after a logarithmic number of intervals, whatever the height is.
+            let mut interval = 0;
+            while interval < intervals && reward > self.tail_reward && reward > 0 {
+                let reduction = (reward as i128 * self.decay_percent as i128 + 99) / 100;
+                reward -= reduction as i64;
+                interval += 1;
+            }
+        }
+        std::cmp::max(reward, self.tail_reward)
+    }
+
+    /// Scales an amount which is defined for the initial reward, e.g. the service award,
+    /// by the decay of the reward at the given height.
+    pub fn decayed(&self, amount: i64, height: u64) -> i64 {
+        if self.initial_reward <= 0 {
+            return amount;
+        }
+        let reward = self.block_reward(height);
+        (amount as i128 * reward as i128 / self.initial_reward as i128) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emission() {
+        let fixed = EmissionConfig::fixed(100);
+        assert_eq!(fixed.block_reward(0), 100);
+        assert_eq!(fixed.block_reward(u64::max_value()), 100);
+
+        let halving = EmissionConfig {
+            initial_reward: 1000,
+            decay_interval: 10,
+            decay_percent: 50,
+            tail_reward: 100,
+        };
+        assert_eq!(halving.block_reward(0), 1000);
+        assert_eq!(halving.block_reward(9), 1000);
+        assert_eq!(halving.block_reward(10), 500);
+        assert_eq!(halving.block_reward(25), 250);
+        assert_eq!(halving.block_reward(30), 125);
+        assert_eq!(halving.block_reward(40), 100);
+        assert_eq!(halving.block_reward(u64::max_value()), 100);
+
+        let decay = EmissionConfig {
+            initial_reward: 1000,
+            decay_interval: 1,
+            decay_percent: 10,
+            tail_reward: 0,
+        };
+        assert_eq!(decay.block_reward(1), 900);
+        assert_eq!(decay.block_reward(2), 810);
+        assert_eq!(decay.block_reward(u64::max_value()), 0);
+        assert!(decay.validate().is_ok());
+
+        // Small rewards still decay, the reduction is rounded up.
+        let small = EmissionConfig {
+            initial_reward: 9,
+            decay_interval: 1,
+            decay_percent: 10,
+            tail_reward: 0,
+        };
+        assert_eq!(small.block_reward(1), 8);
+        assert_eq!(small.block_reward(9), 0);
+        assert_eq!(small.block_reward(u64::max_value()), 0);
+
+        let slow = EmissionConfig {
+            initial_reward: i64::max_value(),
+            decay_interval: 1,
+            decay_percent: 1,
+            tail_reward: 1,
+        };
+        assert_eq!(slow.block_reward(u64::max_value()), 1);
+    }
+
+    #[test]
+    fn decayed() {
+        let halving = EmissionConfig {
+            initial_reward: 1000,
+            decay_interval: 10,
+            decay_percent: 50,
+            tail_reward: 100,
+        };
+        assert_eq!(halving.decayed(5000, 0), 5000);
+        assert_eq!(halving.decayed(5000, 10), 2500);
+        assert_eq!(halving.decayed(5000, u64::max_value()), 500);
+        assert_eq!(EmissionConfig::fixed(0).decayed(5000, 10), 5000);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(EmissionConfig::default().validate().is_ok());
+        let mut cfg = EmissionConfig::default();
+        cfg.decay_percent = 101;
+        assert!(cfg.validate().is_err());
+        let mut cfg = EmissionConfig::default();
+        cfg.tail_reward = -1;
+        assert!(cfg.validate().is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Epoch Summaries.
+
+use crate::block::MacroBlockHeader;
+use bitvector::BitVector;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+/// A micro block of the current epoch, kept until the epoch is finalized.
+#[derive(Debug, Clone)]
+pub(crate) struct EpochBlock {
+    pub leader: pbc::PublicKey,
+    pub view_change: u32,
+    /// Leaders who failed to produce this block, in order of view changes.
+    pub skipped_leaders: Vec<pbc::PublicKey>,
+    pub transactions: u64,
+    pub fee: i64,
+    pub reward: i64,
+}
+
+/// Leader of a micro block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EpochLeader {
+    pub height: u64,
+    pub leader: pbc::PublicKey,
+    pub view_change: u32,
+}
+
+/// Uptime of a validator during the epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidatorUptime {
+    pub network_pkey: pbc::PublicKey,
+    pub slots: i64,
+    /// The number of micro blocks produced by the validator.
+    pub produced: u64,
+    /// The number of micro blocks the validator failed to produce as a leader.
+    pub missed: u64,
+    /// True if the validator is marked as active in the macro block.
+    pub active: bool,
+}
+
+/// Summary of a finalized epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EpochSummary {
+    pub epoch: u64,
+    pub macro_block_height: u64,
+    pub macro_block_hash: Hash,
+    pub timestamp: SystemTime,
+    /// Leaders of micro blocks, by height.
+    pub leaders: Vec<EpochLeader>,
+    /// The total number of view changes, including the macro block.
+    pub view_changes: u64,
+    /// The total number of transactions in micro blocks.
+    pub transactions: u64,
+    /// The total fees paid in micro blocks.
+    pub fees: i64,
+    /// The total rewards paid to leaders of micro blocks.
+    pub micro_block_rewards: i64,
+    /// The reward paid in the macro block, including the service award.
+    pub macro_block_reward: i64,
+    /// Winner and amount of the service award, if any.
+    pub service_award: Option<(PublicKey, i64)>,
+    pub validators: Vec<ValidatorUptime>,
+}
+
+impl EpochSummary {
+    ///
+    /// Aggregate micro blocks of the epoch finalized by the macro block.
+    ///
+    pub(crate) fn new<'a, I>(
+        epoch: u64,
+        block_hash: Hash,
+        header: &MacroBlockHeader,
+        activity_map: &BitVector,
+        blocks: I,
+        validators: &[(pbc::PublicKey, i64)],
+        service_award: Option<(PublicKey, i64)>,
+    ) -> Self
+    where
+        I: IntoIterator<Item = (&'a u64, &'a EpochBlock)>,
+    {
+        let mut leaders = Vec::new();
+        let mut view_changes = header.base.view_change as u64;
+        let mut transactions: u64 = 0;
+        let mut fees: i64 = 0;
+        let mut micro_block_rewards: i64 = 0;
+        let mut produced: BTreeMap<pbc::PublicKey, u64> = BTreeMap::new();
+        let mut missed: BTreeMap<pbc::PublicKey, u64> = BTreeMap::new();
+        for (height, epoch_block) in blocks {
+            leaders.push(EpochLeader {
+                height: *height,
+                leader: epoch_block.leader,
+                view_change: epoch_block.view_change,
+            });
+            view_changes += epoch_block.view_change as u64;
+            transactions += epoch_block.transactions;
+            fees += epoch_block.fee;
+            micro_block_rewards += epoch_block.reward;
+            *produced.entry(epoch_block.leader).or_insert(0) += 1;
+            for leader in &epoch_block.skipped_leaders {
+                *missed.entry(*leader).or_insert(0) += 1;
+            }
+        }
+
+        let validators = validators
+            .iter()
+            .enumerate()
+            .map(|(id, (network_pkey, slots))| ValidatorUptime {
+                network_pkey: *network_pkey,
+                slots: *slots,
+                produced: produced.get(network_pkey).cloned().unwrap_or(0),
+                missed: missed.get(network_pkey).cloned().unwrap_or(0),
+                active: activity_map.contains(id),
+            })
+            .collect();
+
+        EpochSummary {
+            epoch,
+            macro_block_height: header.base.height,
+            macro_block_hash: block_hash,
+            timestamp: header.base.timestamp,
+            leaders,
+            view_changes,
+            transactions,
+            fees,
+            micro_block_rewards,
+            macro_block_reward: header.block_reward,
+            service_award,
+            validators,
+        }
+    }
+}
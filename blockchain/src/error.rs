@@ -22,9 +22,10 @@
 // SOFTWARE.
 
 use crate::view_changes::ViewChangeProof;
-use crate::OutputError;
+use crate::{LockTime, OutputError};
 use failure::Fail;
 use std::str::Utf8Error;
+use std::time::SystemTime;
 use stegos_crypto::curve1174::PublicKey;
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
@@ -73,6 +74,25 @@ pub enum TransactionError {
     MissingInput(Hash, Hash),
     #[fail(display = "Duplicate input: tx={}, utxo={}", _0, _1)]
     DuplicateInput(Hash, Hash),
+    #[fail(
+        display = "Input is time-locked: tx={}, utxo={}, locked_until={}",
+        _0, _1, _2
+    )]
+    LockedInput(Hash, Hash, LockTime),
+    #[fail(
+        display = "Invalid preimage of hash-locked input: tx={}, utxo={}",
+        _0, _1
+    )]
+    InvalidPreimage(Hash, Hash),
+    #[fail(display = "Invalid payment channel witness: tx={}, utxo={}", _0, _1)]
+    InvalidChannelWitness(Hash, Hash),
+    #[fail(display = "Missing payment channel witness: tx={}, utxo={}", _0, _1)]
+    MissingChannelWitness(Hash, Hash),
+    #[fail(
+        display = "Invalid settlement of payment channel: tx={}, utxo={}",
+        _0, _1
+    )]
+    InvalidChannelSettlement(Hash, Hash),
     #[fail(display = "Duplicate output: tx={}, utxo={}", _0, _1)]
     DuplicateOutput(Hash, Hash),
     #[fail(display = "Output hash collision: tx={}, utxo={}", _0, _1)]
@@ -84,7 +104,7 @@ pub enum TransactionError {
     ReceivedInvalidTransaction(&'static str),
 
     #[fail(
-        display = "CoinbaseTransaction must contain only PaymentUTXOs: tx={}, utxo={}",
+        display = "CoinbaseTransaction must contain only PaymentUTXOs and PublicPaymentUTXOs: tx={}, utxo={}",
         _0, _1
     )]
     NonPaymentOutputInCoinbase(Hash, Hash),
@@ -107,6 +127,15 @@ pub enum TransactionError {
     #[fail(display = "Non-StakeUTXO found in TXOUTs: tx={}, utxo={}", _0, _1)]
     InvalidRestakingOutput(Hash, Hash),
 
+    #[fail(display = "StakeUTXO is not delegated: tx={}, utxo={}", _0, _1)]
+    NotDelegatedStake(Hash, Hash),
+
+    #[fail(
+        display = "Delegated StakeUTXO found in TXOUTs: tx={}, utxo={}",
+        _0, _1
+    )]
+    DelegatedStakeOutput(Hash, Hash),
+
     #[fail(display = "TXOUTs with mixed validator keys: tx={}, utxo={}", _0, _1)]
     MixedTxoutValidators(Hash, Hash),
 
@@ -116,6 +145,12 @@ pub enum TransactionError {
         _0, _1, _2, _3
     )]
     StakeOutputWithDifferentWalletKey(PublicKey, PublicKey, Hash, Hash),
+    #[fail(
+        display = "Stakes of the validator pay block rewards to different wallets: validator={}, \
+                   tx={}, utxo={}",
+        _0, _1, _2
+    )]
+    ConflictingRewardDestination(pbc::PublicKey, Hash, Hash),
     #[fail(display = "Unexpected transaction type in MicroBlock.")]
     UnexpectedTxType,
 
@@ -124,6 +159,33 @@ pub enum TransactionError {
 
     #[fail(display = "Slashing error ={}", _0)]
     SlashingError(SlashingError),
+
+    #[fail(
+        display = "Transaction is too big: tx={}, size={}, max_size={}",
+        _0, _1, _2
+    )]
+    TooBigTransaction(Hash, usize, usize),
+
+    #[fail(
+        display = "Too many inputs in transaction: tx={}, inputs={}, max_inputs={}",
+        _0, _1, _2
+    )]
+    TooManyInputs(Hash, usize, usize),
+
+    #[fail(
+        display = "Signed for another transaction: expected={}, got={}",
+        _0, _1
+    )]
+    UnsignedHashMismatch(Hash, Hash),
+
+    #[fail(display = "Signed transaction differs from unsigned: tx={}", _0)]
+    SignedTransactionMismatch(Hash),
+
+    #[fail(
+        display = "Transaction from another chain: tx={}, got_chain_id={}, expected_chain_id={}",
+        _0, _1, _2
+    )]
+    InvalidChainId(Hash, Hash, Hash),
 }
 
 #[derive(Debug, Fail)]
@@ -144,11 +206,28 @@ pub enum MultisignatureError {
 
 #[derive(Debug, Fail)]
 pub enum BlockError {
+    #[fail(
+        display = "Block is too big: height={}, block={}, size={}, max_size={}",
+        _0, _1, _2, _3
+    )]
+    TooBigBlock(u64, Hash, usize, usize),
+    #[fail(display = "Malformed block: error={}", _0)]
+    MalformedBlock(String),
     #[fail(
         display = "Previous hash mismatch: height={}, block={}, block_previous={}, our_previous={}",
         _0, _1, _2, _3
     )]
     InvalidPreviousHash(u64, Hash, Hash, Hash),
+    #[fail(
+        display = "Block timestamp is too far in the future: height={}, block={}, block_timestamp={:?}, our_timestamp={:?}",
+        _0, _1, _2, _3
+    )]
+    TimestampInFuture(u64, Hash, SystemTime, SystemTime),
+    #[fail(
+        display = "Block timestamp is earlier than the previous block: height={}, block={}, block_timestamp={:?}, previous_timestamp={:?}",
+        _0, _1, _2, _3
+    )]
+    TimestampBeforePrevious(u64, Hash, SystemTime, SystemTime),
     #[fail(display = "Block hash collision: height={}, block={}", _0, _1)]
     BlockHashCollision(u64, Hash),
     #[fail(
@@ -190,6 +269,11 @@ pub enum BlockError {
         _0, _1, _2
     )]
     AwardDifferentReward(Hash, i64, i64),
+    #[fail(
+        display = "Block reward is paid to a wrong destination: height={}, block={}, utxo={}",
+        _0, _1, _2
+    )]
+    InvalidRewardRecipient(u64, Hash, Hash),
     #[fail(
         display = "Invalid block monetary balance: height={}, block={}",
         _0, _1
@@ -215,6 +299,11 @@ pub enum BlockError {
         _0, _1, _1
     )]
     DuplicateBlockInput(u64, Hash, Hash),
+    #[fail(
+        display = "Block input is time-locked: height={}, block={}, utxo={}, locked_until={}",
+        _0, _1, _2, _3
+    )]
+    LockedBlockInput(u64, Hash, Hash, LockTime),
     #[fail(
         display = "Duplicate block output: height={}, block={}, utxo={}",
         _0, _1, _2
@@ -227,6 +316,11 @@ pub enum BlockError {
     OutputHashCollision(u64, Hash, Hash),
     #[fail(display = "The leader must be validator: height={}, block={}", _0, _1)]
     LeaderIsNotValidator(u64, Hash),
+    #[fail(
+        display = "The leader is not eligible in the secret leader election: height={}, block={}, leader={}",
+        _0, _1, _2
+    )]
+    NotEligibleLeader(u64, Hash, pbc::PublicKey),
     #[fail(
         display = "Found propose with more than one signature: height={}, block={}",
         _0, _1
@@ -242,6 +336,16 @@ pub enum BlockError {
         _0, _1
     )]
     InvalidLeaderSignature(u64, Hash),
+    #[fail(
+        display = "Block doesn't match the checkpoint: height={}, block={}, checkpoint={}",
+        _0, _1, _2
+    )]
+    CheckpointMismatch(u64, Hash, Hash),
+    #[fail(
+        display = "Attempt to revert a checkpointed block: height={}, block={}",
+        _0, _1
+    )]
+    CheckpointRevert(u64, Hash),
     #[fail(
         display = "Invalid block BLS multisignature: height={}, block={}, error={}",
         _1, _2, _0
@@ -252,6 +356,11 @@ pub enum BlockError {
         _0, _1, _2, _3
     )]
     InvalidBlockVersion(u64, Hash, u64, u64),
+    #[fail(
+        display = "Received block from another chain: height={}, block={}, got_chain_id={}, expected_chain_id={}",
+        _0, _1, _2, _3
+    )]
+    InvalidChainId(u64, Hash, Hash, Hash),
     #[fail(
         display = "Received block with invalid random: height={}, block={}",
         _0, _1
@@ -284,7 +393,7 @@ pub enum BlockError {
     )]
     ElectionResultForFutureBlock(u64, u64),
     #[fail(
-        display = "Unexpected block reward: height={}, block={}, got={}, expected={}",
+        display = "Block reward exceeds the emission schedule: height={}, block={}, got={}, max={}",
         _0, _1, _2, _3
     )]
     InvalidBlockReward(u64, Hash, i64, i64),
@@ -355,6 +464,113 @@ pub enum SlashingError {
     IncorrectTxouts(Hash),
 }
 
+/// Errors raised while constructing new blocks.
+#[derive(Debug, Fail)]
+pub enum BlockBuilderError {
+    #[fail(display = "Keys of the leader are not found: leader={}", _0)]
+    LeaderKeysNotFound(pbc::PublicKey),
+    #[fail(display = "Block signer mismatch: expected={}, got={}", _0, _1)]
+    SignerMismatch(pbc::PublicKey, pbc::PublicKey),
+    #[fail(display = "Duplicate input in block: input={}", _0)]
+    DuplicateInput(Hash),
+    #[fail(display = "Duplicate output in block: output={}", _0)]
+    DuplicateOutput(Hash),
+    #[fail(display = "Missing unspent output: utxo={}", _0)]
+    MissingOutput(Hash),
+    #[fail(display = "Invalid block contents: {}", _0)]
+    BlockchainError(BlockchainError),
+    #[fail(display = "Internal storage error={}", _0)]
+    StorageError(failure::Error),
+}
+
+/// Errors of genesis specification.
+#[derive(Debug, Fail)]
+pub enum GenesisError {
+    #[fail(display = "Failed to parse genesis specification: {}", _0)]
+    ParseError(String),
+    #[fail(
+        display = "Invalid signature of genesis specification: authority={}",
+        _0
+    )]
+    InvalidSignature(pbc::PublicKey),
+    #[fail(display = "Genesis specification has an empty chain id")]
+    EmptyChainId,
+    #[fail(display = "Genesis specification has no stakes")]
+    NoStakes,
+    #[fail(display = "Invalid amount in genesis specification: amount={}", _0)]
+    InvalidAmount(i64),
+    #[fail(display = "Invalid wallet key in genesis specification: pkey={}", _0)]
+    InvalidWalletKey(PublicKey),
+    #[fail(
+        display = "Invalid stake signature in genesis specification: validator={}",
+        _0
+    )]
+    InvalidStakeSignature(pbc::PublicKey),
+    #[fail(
+        display = "Duplicate validator in genesis specification: validator={}",
+        _0
+    )]
+    DuplicateValidator(pbc::PublicKey),
+    #[fail(display = "Invalid random in genesis specification")]
+    InvalidRandom,
+}
+
+#[derive(Debug, Fail)]
+pub enum OutputProofError {
+    #[fail(display = "Unexpected checkpoint: expected={}, got={}", _0, _1)]
+    InvalidCheckpoint(Hash, Hash),
+    #[fail(display = "Invalid Merkle proof: utxo={}", _0)]
+    InvalidMerkleProof(Hash),
+    #[fail(display = "Proof doesn't contain any blocks: utxo={}", _0)]
+    NoBlocks(Hash),
+    #[fail(display = "Output is not found in block: height={}, utxo={}", _0, _1)]
+    MissingOutput(u64, Hash),
+    #[fail(
+        display = "Broken chain of blocks: height={}, expected_previous={}, got_previous={}",
+        _0, _1, _2
+    )]
+    BrokenChain(u64, Hash, Hash),
+}
+
+#[derive(Debug, Fail)]
+pub enum ArchiveError {
+    #[fail(display = "Not a chain archive")]
+    InvalidMagic,
+    #[fail(display = "Unsupported archive version: version={}", _0)]
+    UnsupportedVersion(u32),
+    #[fail(display = "Archive of another chain: expected={}, got={}", _0, _1)]
+    ChainIdMismatch(Hash, Hash),
+    #[fail(display = "Archive is corrupted: expected_checksum={}, got={}", _0, _1)]
+    ChecksumMismatch(Hash, Hash),
+    #[fail(display = "Archive is truncated: expected_blocks={}, got={}", _0, _1)]
+    BlockCountMismatch(u64, u64),
+    #[fail(display = "Archive frame is too large: len={}", _0)]
+    FrameTooLarge(usize),
+    #[fail(
+        display = "Archive contradicts the chain: height={}, local={}, archive={}",
+        _0, _1, _2
+    )]
+    BlockMismatch(u64, Hash, Hash),
+}
+
+#[derive(Debug, Fail)]
+pub enum ReserveProofError {
+    #[fail(display = "Output is not found in UTXO: utxo={}", _0)]
+    MissingOutput(Hash),
+    #[fail(display = "Duplicate output in reserve proof: utxo={}", _0)]
+    DuplicateOutput(Hash),
+    #[fail(display = "Invalid reserve proof signature: amount={}", _0)]
+    InvalidSignature(i64),
+    #[fail(display = "Crypto error: {}", _0)]
+    CryptoError(CryptoError),
+}
+
+impl From<CryptoError> for ReserveProofError {
+    fn from(error: CryptoError) -> ReserveProofError {
+        ReserveProofError::CryptoError(error)
+    }
+}
+
 impl From<failure::Error> for BlockchainError {
     fn from(error: failure::Error) -> BlockchainError {
         BlockchainError::StorageError(error)
@@ -396,3 +612,27 @@ impl From<Utf8Error> for BlockchainError {
         BlockchainError::UtfError(error)
     }
 }
+
+impl From<BlockchainError> for BlockBuilderError {
+    fn from(error: BlockchainError) -> BlockBuilderError {
+        BlockBuilderError::BlockchainError(error)
+    }
+}
+
+impl From<TransactionError> for BlockBuilderError {
+    fn from(error: TransactionError) -> BlockBuilderError {
+        BlockBuilderError::BlockchainError(error.into())
+    }
+}
+
+impl From<OutputError> for BlockBuilderError {
+    fn from(error: OutputError) -> BlockBuilderError {
+        BlockBuilderError::BlockchainError(error.into())
+    }
+}
+
+impl From<failure::Error> for BlockBuilderError {
+    fn from(error: failure::Error) -> BlockBuilderError {
+        BlockBuilderError::StorageError(error)
+    }
+}
@@ -23,12 +23,16 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::blockchain::lsn_for_height;
+use crate::election::StakersGroup;
 use crate::mvcc::MultiVersionedMap;
+use crate::output::Output;
 use log::*;
 use serde_derive::Serialize;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use stegos_crypto::hash::Hash;
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
 use stegos_crypto::pbc;
 
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
@@ -41,6 +45,19 @@ struct EscrowKey {
 struct EscrowValue {
     active_until_epoch: u64,
     amount: i64,
+    /// Wallet which owns this stake.
+    recipient: PublicKey,
+    /// Operator which participates in consensus with this stake, if delegated.
+    operator: Option<pbc::PublicKey>,
+    /// Wallet which receives block rewards, if not the owner.
+    reward: Option<PublicKey>,
+}
+
+impl EscrowValue {
+    /// Returns the network key which participates in consensus with this stake.
+    fn consensus_key(&self, validator_pkey: &pbc::PublicKey) -> pbc::PublicKey {
+        self.operator.unwrap_or(*validator_pkey)
+    }
 }
 
 use crate::LSN;
@@ -71,6 +88,39 @@ pub struct StakeInfo {
     pub active_until_epoch: u64,
     pub is_active: bool,
     pub amount: i64,
+    pub operator: Option<pbc::PublicKey>,
+}
+
+/// A stake owned by some wallet.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct WalletStakeInfo {
+    pub utxo: Hash,
+    pub validator: pbc::PublicKey,
+    pub operator: Option<pbc::PublicKey>,
+    /// Wallet which receives block rewards, if not the owner.
+    pub reward: Option<PublicKey>,
+    pub amount: i64,
+    pub active_until_epoch: u64,
+    pub is_active: bool,
+    /// The first height when the stake can be unstaked.
+    pub unlock_height: u64,
+}
+
+/// Summary of stakes bonded to a validator.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct ValidatorStakeSummary {
+    /// Active stakes of the validator itself, excluding delegated to others.
+    pub own_stake: i64,
+    /// Active stakes of the validator delegated to other operators.
+    pub delegated_out: i64,
+    /// Active stakes of other validators delegated to this one.
+    pub delegated_in: i64,
+    /// Expired stakes of the validator.
+    pub expired_stake: i64,
+    /// Stake which counts for the election: own_stake + delegated_in.
+    pub consensus_stake: i64,
+    /// Number of stake UTXO of the validator.
+    pub outputs: usize,
 }
 
 impl Escrow {
@@ -92,6 +142,9 @@ impl Escrow {
         epoch: u64,
         stakes_epoch: u64,
         amount: i64,
+        recipient: PublicKey,
+        operator: Option<pbc::PublicKey>,
+        reward: Option<PublicKey>,
     ) {
         let active_until_epoch = epoch + stakes_epoch;
         let key = EscrowKey {
@@ -101,6 +154,9 @@ impl Escrow {
         let value = EscrowValue {
             active_until_epoch,
             amount,
+            recipient,
+            operator,
+            reward,
         };
 
         if let Some(v) = self.escrow.insert(lsn, key, value) {
@@ -112,8 +168,8 @@ impl Escrow {
 
         let (active_balance, expired_balance) = self.get(&validator_pkey, epoch);
         info!(
-            "Staked: utxo={}, validator={}, amount={}, active_until_epoch={}, active_balance={}, expired_balance={}",
-            output_hash, &validator_pkey, amount, active_until_epoch, active_balance, expired_balance
+            "Staked: utxo={}, validator={}, operator={:?}, amount={}, active_until_epoch={}, active_balance={}, expired_balance={}",
+            output_hash, &validator_pkey, operator, amount, active_until_epoch, active_balance, expired_balance
         );
     }
 
@@ -171,27 +227,20 @@ impl Escrow {
     }
 
     ///
-    /// Returns list of utxos for specific validator.
+    /// Returns list of active utxos which participate in consensus with the key,
+    /// i.e. own stakes of the validator and stakes delegated to it.
     ///
     pub(crate) fn staker_outputs(
         &self,
-        validator_pkey: &pbc::PublicKey,
+        consensus_pkey: &pbc::PublicKey,
         epoch: u64,
     ) -> (Vec<Hash>, i64) {
-        let (hash_min, hash_max) = Hash::bounds();
-        let key_min = EscrowKey {
-            validator_pkey: validator_pkey.clone(),
-            output_hash: hash_min,
-        };
-        let key_max = EscrowKey {
-            validator_pkey: validator_pkey.clone(),
-            output_hash: hash_max,
-        };
-
         let mut result = Vec::new();
         let mut stake = 0;
-        for (key, value) in self.escrow.range(&key_min..=&key_max) {
-            assert_eq!(&key.validator_pkey, validator_pkey);
+        for (key, value) in self.escrow.iter() {
+            if &value.consensus_key(&key.validator_pkey) != consensus_pkey {
+                continue;
+            }
             if value.active_until_epoch >= epoch {
                 stake += value.amount;
                 result.push(key.output_hash)
@@ -219,8 +268,119 @@ impl Escrow {
             .map(|(k, _)| k.output_hash)
     }
 
+    /// Returns Hash of the first output delegated to operator.
+    /// If no output was found, return None.
+    pub(crate) fn get_first_delegated_output(
+        &self,
+        operator_pkey: &pbc::PublicKey,
+    ) -> Option<Hash> {
+        self.escrow
+            .iter()
+            .find(|(_k, v)| v.operator.as_ref() == Some(operator_pkey))
+            .map(|(k, _)| k.output_hash)
+    }
+
+    ///
+    /// Returns the epoch until which the stake is locked, if the stake exists.
+    ///
+    pub(crate) fn active_until_epoch(
+        &self,
+        validator_pkey: &pbc::PublicKey,
+        output_hash: &Hash,
+    ) -> Option<u64> {
+        let key = EscrowKey {
+            validator_pkey: validator_pkey.clone(),
+            output_hash: output_hash.clone(),
+        };
+        self.escrow.get(&key).map(|v| v.active_until_epoch)
+    }
+
+    ///
+    /// Returns the wallet which receives block rewards of the validator.
+    /// Returns None if stakes of the validator don't override the destination of rewards.
+    ///
+    pub(crate) fn reward_key(&self, validator_pkey: &pbc::PublicKey) -> Option<PublicKey> {
+        self.reward_keys(validator_pkey)
+            .into_iter()
+            .map(|(_output_hash, reward)| reward)
+            .next()
+    }
+
+    ///
+    /// Returns stakes of the validator which override the destination of block rewards.
+    ///
+    pub(crate) fn reward_keys(&self, validator_pkey: &pbc::PublicKey) -> Vec<(Hash, PublicKey)> {
+        let (hash_min, hash_max) = Hash::bounds();
+        let key_min = EscrowKey {
+            validator_pkey: validator_pkey.clone(),
+            output_hash: hash_min,
+        };
+        let key_max = EscrowKey {
+            validator_pkey: validator_pkey.clone(),
+            output_hash: hash_max,
+        };
+        self.escrow
+            .range(&key_min..=&key_max)
+            .filter_map(|(k, v)| v.reward.map(|reward| (k.output_hash, reward)))
+            .collect()
+    }
+
+    ///
+    /// Returns all stakes owned by the wallet.
+    /// `unlock_height` is filled by the caller.
+    ///
+    pub(crate) fn stakes_by_recipient(
+        &self,
+        recipient: &PublicKey,
+        epoch: u64,
+    ) -> Vec<WalletStakeInfo> {
+        self.escrow
+            .iter()
+            .filter(|(_k, v)| &v.recipient == recipient)
+            .map(|(k, v)| WalletStakeInfo {
+                utxo: k.output_hash,
+                validator: k.validator_pkey,
+                operator: v.operator,
+                reward: v.reward,
+                amount: v.amount,
+                active_until_epoch: v.active_until_epoch,
+                is_active: v.active_until_epoch >= epoch,
+                unlock_height: 0,
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns summary of stakes bonded to the validator.
+    ///
+    pub(crate) fn validator_summary(
+        &self,
+        validator_pkey: &pbc::PublicKey,
+        epoch: u64,
+    ) -> ValidatorStakeSummary {
+        let mut summary = ValidatorStakeSummary::default();
+        for (k, v) in self.escrow.iter() {
+            let is_active = v.active_until_epoch >= epoch;
+            if &k.validator_pkey == validator_pkey {
+                summary.outputs += 1;
+                if !is_active {
+                    summary.expired_stake += v.amount;
+                } else if v.operator.is_some() {
+                    summary.delegated_out += v.amount;
+                } else {
+                    summary.own_stake += v.amount;
+                }
+            } else if is_active && v.operator.as_ref() == Some(validator_pkey) {
+                summary.delegated_in += v.amount;
+            }
+        }
+        summary.consensus_stake = summary.own_stake + summary.delegated_in;
+        summary
+    }
+
     ///
     /// Get all staked values of all validators.
+    /// Delegated stakes are counted toward their operators.
     /// Filter out stakers with stake lower than min_stake_amount.
     ///
     pub fn get_stakers_majority(
@@ -234,7 +394,9 @@ impl Escrow {
                 // Skip expired stakes.
                 continue;
             }
-            let entry = stakes.entry(k.validator_pkey).or_insert(0);
+            let entry = stakes
+                .entry(v.consensus_key(&k.validator_pkey))
+                .or_insert(0);
             *entry += v.amount;
         }
 
@@ -263,6 +425,7 @@ impl Escrow {
                 active_until_epoch: v.active_until_epoch,
                 is_active,
                 amount: v.amount,
+                operator: v.operator,
             };
             (*entry).stakes.push(stake);
             if is_active {
@@ -291,3 +454,94 @@ impl Escrow {
         self.escrow.rollback_to_lsn(to_lsn);
     }
 }
+
+/// Covers all entries of the escrow, in the order of keys.
+impl Hashable for Escrow {
+    fn hash(&self, state: &mut Hasher) {
+        for (k, v) in self.escrow.iter() {
+            k.validator_pkey.hash(state);
+            k.output_hash.hash(state);
+            v.active_until_epoch.hash(state);
+            v.amount.hash(state);
+            v.recipient.hash(state);
+            v.operator.hash(state);
+            v.reward.hash(state);
+        }
+    }
+}
+
+///
+/// Stakes replayed from inputs and outputs of blocks.
+/// Used by light nodes to elect validators without keeping the UTXO set.
+///
+pub struct StakeReplay {
+    escrow: Escrow,
+    /// Validators of stake UTXOs.
+    validators: MultiVersionedMap<Hash, pbc::PublicKey, LSN>,
+    /// How many epochs stake is valid.
+    stake_epochs: u64,
+}
+
+impl StakeReplay {
+    pub fn new(stake_epochs: u64) -> Self {
+        StakeReplay {
+            escrow: Escrow::new(),
+            validators: MultiVersionedMap::new(),
+            stake_epochs,
+        }
+    }
+
+    ///
+    /// Applies inputs and outputs of the block.
+    /// Same updates of the escrow as in Blockchain::register_block().
+    ///
+    pub fn apply(&mut self, height: u64, epoch: u64, inputs: &[Hash], outputs: &[Output]) {
+        let lsn = lsn_for_height(height);
+        for input_hash in inputs {
+            if let Some(validator) = self.validators.remove(lsn, input_hash) {
+                self.escrow.unstake(lsn, validator, *input_hash, epoch);
+            }
+        }
+        for output in outputs {
+            if let Output::StakeOutput(o) = output {
+                let output_hash = Hash::digest(output);
+                self.validators.insert(lsn, output_hash, o.validator);
+                self.escrow.stake(
+                    lsn,
+                    o.validator,
+                    output_hash,
+                    epoch,
+                    self.stake_epochs,
+                    o.amount,
+                    o.recipient,
+                    o.operator,
+                    o.reward.as_ref().map(|reward| reward.pkey),
+                );
+            }
+        }
+    }
+
+    /// Reverts blocks starting from the height.
+    pub fn rollback(&mut self, height: u64) {
+        assert!(height > 0);
+        let lsn = lsn_for_height(height - 1);
+        self.validators.rollback_to_lsn(lsn);
+        self.escrow.rollback_to_lsn(lsn);
+    }
+
+    /// Finalizes applied blocks, called for macro blocks.
+    pub fn checkpoint(&mut self) {
+        self.validators.checkpoint();
+        self.escrow.checkpoint();
+    }
+
+    #[inline]
+    pub(crate) fn escrow(&self) -> &Escrow {
+        &self.escrow
+    }
+
+    /// Returns stakers of the epoch, see `Escrow::get_stakers_majority()`.
+    pub fn get_stakers_majority(&self, epoch: u64, min_stake_amount: i64) -> StakersGroup {
+        self.escrow.get_stakers_majority(epoch, min_stake_amount)
+    }
+}
@@ -0,0 +1,431 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Block Explorer Indexes.
+
+use crate::block::*;
+use crate::blockchain::Blockchain;
+use crate::inspector::OutputInfo;
+use crate::mvcc::MultiVersionedMap;
+use crate::output::Output;
+use failure::{format_err, Error};
+use serde_derive::{Deserialize, Serialize};
+use std::time::SystemTime;
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+use crate::LSN;
+
+/// A query against the explorer indexes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "query")]
+#[serde(rename_all = "snake_case")]
+pub enum ExplorerQuery {
+    /// Find transactions with payment outputs carrying the cloaking hint.
+    TransactionsByHint { hint: PublicKey },
+    /// Find outputs sent to the recipient key, including spent ones.
+    OutputsByRecipient { recipient: PublicKey },
+    /// List blocks of the epoch.
+    BlocksByEpoch { epoch: u64 },
+    /// List blocks created by the leader.
+    BlocksByLeader { leader: pbc::PublicKey },
+}
+
+/// A result of `ExplorerQuery`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum ExplorerResponse {
+    Transactions {
+        transactions: Vec<ExplorerTransaction>,
+    },
+    Outputs {
+        outputs: Vec<OutputInfo>,
+    },
+    Blocks {
+        blocks: Vec<ExplorerBlock>,
+    },
+}
+
+/// Location of a committed transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExplorerTransaction {
+    pub tx_hash: Hash,
+    /// Height of the micro block which contains the transaction.
+    pub height: u64,
+    /// Transaction number inside the block.
+    pub tx_id: u32,
+}
+
+/// Summary of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExplorerBlock {
+    pub height: u64,
+    pub hash: Hash,
+    pub epoch: u64,
+    #[serde(rename = "type")]
+    pub block_type: &'static str,
+    /// Leader for micro blocks, aggregated key for macro blocks.
+    pub leader: pbc::PublicKey,
+    pub view_change: u32,
+    pub timestamp: SystemTime,
+}
+
+type TransactionsByHintMap = MultiVersionedMap<(PublicKey, Hash), (u64, u32), LSN>;
+type OutputsByRecipientMap = MultiVersionedMap<(PublicKey, Hash), u64, LSN>;
+type BlocksByEpochMap = MultiVersionedMap<(u64, u64), ExplorerBlock, LSN>;
+type BlocksByLeaderMap = MultiVersionedMap<(pbc::PublicKey, u64), u64, LSN>;
+
+/// Secondary indexes for block explorers.
+#[derive(Debug, Clone)]
+pub(crate) struct ExplorerIndex {
+    /// (cloaking hint, tx_hash) => (height, tx_id).
+    transactions_by_hint: TransactionsByHintMap,
+    /// (recipient, output_hash) => height.
+    outputs_by_recipient: OutputsByRecipientMap,
+    /// (epoch, height) => block.
+    blocks_by_epoch: BlocksByEpochMap,
+    /// (leader, height) => epoch.
+    blocks_by_leader: BlocksByLeaderMap,
+}
+
+impl ExplorerIndex {
+    ///
+    /// Create empty indexes.
+    ///
+    pub(crate) fn new() -> Self {
+        ExplorerIndex {
+            transactions_by_hint: TransactionsByHintMap::new(),
+            outputs_by_recipient: OutputsByRecipientMap::new(),
+            blocks_by_epoch: BlocksByEpochMap::new(),
+            blocks_by_leader: BlocksByLeaderMap::new(),
+        }
+    }
+
+    fn register_block(&mut self, lsn: LSN, block: ExplorerBlock) {
+        self.blocks_by_leader
+            .insert(lsn, (block.leader, block.height), block.epoch);
+        self.blocks_by_epoch
+            .insert(lsn, (block.epoch, block.height), block);
+    }
+
+    fn register_output(&mut self, lsn: LSN, height: u64, output: &Output) {
+        let recipient = match output {
+            Output::PaymentOutput(o) => o.recipient,
+            Output::PublicPaymentOutput(o) => o.recipient,
+            Output::StakeOutput(o) => o.recipient,
+        };
+        self.outputs_by_recipient
+            .insert(lsn, (recipient, Hash::digest(output)), height);
+    }
+
+    ///
+    /// Index a macro block.
+    ///
+    pub(crate) fn register_macro_block(
+        &mut self,
+        lsn: LSN,
+        epoch: u64,
+        block_hash: Hash,
+        block: &MacroBlock,
+    ) {
+        let height = block.header.base.height;
+        for (output, _path) in block.body.outputs.leafs() {
+            self.register_output(lsn, height, output.as_ref());
+        }
+        self.register_block(
+            lsn,
+            ExplorerBlock {
+                height,
+                hash: block_hash,
+                epoch,
+                block_type: "macro",
+                leader: block.body.pkey,
+                view_change: block.header.base.view_change,
+                timestamp: block.header.base.timestamp,
+            },
+        );
+    }
+
+    ///
+    /// Index a micro block.
+    ///
+    pub(crate) fn register_micro_block(
+        &mut self,
+        lsn: LSN,
+        epoch: u64,
+        block_hash: Hash,
+        block: &MicroBlock,
+    ) {
+        let height = block.base.height;
+        for (tx_id, tx) in block.transactions.iter().enumerate() {
+            let tx_hash = Hash::digest(tx);
+            for output in tx.txouts() {
+                self.register_output(lsn, height, output);
+                if let Output::PaymentOutput(o) = output {
+                    let hint: PublicKey = o.cloaking_hint.into();
+                    self.transactions_by_hint
+                        .insert(lsn, (hint, tx_hash), (height, tx_id as u32));
+                }
+            }
+        }
+        self.register_block(
+            lsn,
+            ExplorerBlock {
+                height,
+                hash: block_hash,
+                epoch,
+                block_type: "micro",
+                leader: block.pkey,
+                view_change: block.base.view_change,
+                timestamp: block.base.timestamp,
+            },
+        );
+    }
+
+    #[inline]
+    pub(crate) fn current_lsn(&self) -> LSN {
+        self.transactions_by_hint
+            .current_lsn()
+            .max(self.outputs_by_recipient.current_lsn())
+            .max(self.blocks_by_epoch.current_lsn())
+            .max(self.blocks_by_leader.current_lsn())
+    }
+
+    #[inline]
+    pub(crate) fn checkpoint(&mut self) {
+        self.transactions_by_hint.checkpoint();
+        self.outputs_by_recipient.checkpoint();
+        self.blocks_by_epoch.checkpoint();
+        self.blocks_by_leader.checkpoint();
+    }
+
+    #[inline]
+    pub(crate) fn rollback_to_lsn(&mut self, to_lsn: LSN) {
+        self.transactions_by_hint.rollback_to_lsn(to_lsn);
+        self.outputs_by_recipient.rollback_to_lsn(to_lsn);
+        self.blocks_by_epoch.rollback_to_lsn(to_lsn);
+        self.blocks_by_leader.rollback_to_lsn(to_lsn);
+    }
+
+    fn transactions_by_hint(&self, hint: &PublicKey) -> Vec<ExplorerTransaction> {
+        let (hash_min, hash_max) = Hash::bounds();
+        let key_min = (*hint, hash_min);
+        let key_max = (*hint, hash_max);
+        let mut transactions: Vec<ExplorerTransaction> = self
+            .transactions_by_hint
+            .range(&key_min..=&key_max)
+            .map(|((_, tx_hash), (height, tx_id))| ExplorerTransaction {
+                tx_hash: *tx_hash,
+                height: *height,
+                tx_id: *tx_id,
+            })
+            .collect();
+        transactions.sort_by_key(|tx| (tx.height, tx.tx_id));
+        transactions
+    }
+
+    fn outputs_by_recipient(&self, recipient: &PublicKey) -> Vec<(Hash, u64)> {
+        let (hash_min, hash_max) = Hash::bounds();
+        let key_min = (*recipient, hash_min);
+        let key_max = (*recipient, hash_max);
+        let mut outputs: Vec<(Hash, u64)> = self
+            .outputs_by_recipient
+            .range(&key_min..=&key_max)
+            .map(|((_, output_hash), height)| (*output_hash, *height))
+            .collect();
+        outputs.sort_by_key(|(_, height)| *height);
+        outputs
+    }
+
+    fn blocks_by_epoch(&self, epoch: u64) -> Vec<ExplorerBlock> {
+        self.blocks_by_epoch
+            .range(&(epoch, 0)..=&(epoch, std::u64::MAX))
+            .map(|(_, block)| block.clone())
+            .collect()
+    }
+
+    fn blocks_by_leader(&self, leader: &pbc::PublicKey) -> Vec<ExplorerBlock> {
+        self.blocks_by_leader
+            .range(&(*leader, 0)..=&(*leader, std::u64::MAX))
+            .filter_map(|((_, height), epoch)| self.blocks_by_epoch.get(&(*epoch, *height)))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Blockchain {
+    ///
+    /// Evaluate a query against the explorer indexes.
+    ///
+    pub fn explore(&self, query: &ExplorerQuery) -> Result<ExplorerResponse, Error> {
+        let index = self
+            .explorer_index()
+            .ok_or_else(|| format_err!("Explorer index is disabled"))?;
+        let response = match query {
+            ExplorerQuery::TransactionsByHint { hint } => ExplorerResponse::Transactions {
+                transactions: index.transactions_by_hint(hint),
+            },
+            ExplorerQuery::OutputsByRecipient { recipient } => {
+                let mut outputs = Vec::new();
+                for (output_hash, _height) in index.outputs_by_recipient(recipient) {
+                    // Skip pruned outputs.
+                    if let Some((height, output)) = self.historical_output_by_hash(&output_hash)? {
+                        let unspent = self.contains_output(&output_hash);
+                        outputs.push(OutputInfo::new(&output, Some(height), unspent));
+                    }
+                }
+                ExplorerResponse::Outputs { outputs }
+            }
+            ExplorerQuery::BlocksByEpoch { epoch } => ExplorerResponse::Blocks {
+                blocks: index.blocks_by_epoch(*epoch),
+            },
+            ExplorerQuery::BlocksByLeader { leader } => ExplorerResponse::Blocks {
+                blocks: index.blocks_by_leader(leader),
+            },
+        };
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::create_fake_micro_block;
+    use crate::config::BlockchainConfig;
+    use crate::genesis::genesis;
+    use std::time::Duration;
+    use stegos_keychain::KeyChain;
+
+    #[test]
+    fn explore() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let mut timestamp = SystemTime::now();
+        let mut cfg: BlockchainConfig = Default::default();
+        cfg.explorer_index = true;
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let mut chain = Blockchain::testing(cfg.clone(), genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+
+        timestamp += Duration::from_millis(1);
+        let (block, _input_hashes, output_hashes) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        let tx = block.transactions.last().unwrap().clone();
+        let tx_hash = Hash::digest(&tx);
+        let hint: PublicKey = match &tx.txouts()[0] {
+            Output::PaymentOutput(o) => o.cloaking_hint.into(),
+            _ => panic!("expected payment output"),
+        };
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+
+        // Blocks.
+        let leader = keychains[0].network_pkey;
+        let blocks_by_epoch = |chain: &Blockchain, epoch| match chain
+            .explore(&ExplorerQuery::BlocksByEpoch { epoch })
+            .unwrap()
+        {
+            ExplorerResponse::Blocks { blocks } => blocks,
+            _ => panic!("invalid response"),
+        };
+        let genesis_blocks = blocks_by_epoch(&chain, 0);
+        assert_eq!(genesis_blocks.len(), 1);
+        assert_eq!(genesis_blocks[0].block_type, "macro");
+        let blocks = blocks_by_epoch(&chain, 1);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].height, 1);
+        assert_eq!(blocks[0].hash, chain.last_block_hash());
+        assert_eq!(blocks[0].leader, leader);
+        match chain
+            .explore(&ExplorerQuery::BlocksByLeader { leader })
+            .unwrap()
+        {
+            ExplorerResponse::Blocks { blocks } => {
+                assert!(blocks.iter().any(|b| b.height == 1));
+            }
+            _ => panic!("invalid response"),
+        }
+
+        // Transactions.
+        match chain
+            .explore(&ExplorerQuery::TransactionsByHint { hint })
+            .unwrap()
+        {
+            ExplorerResponse::Transactions { transactions } => {
+                assert_eq!(transactions.len(), 1);
+                assert_eq!(transactions[0].tx_hash, tx_hash);
+                assert_eq!(transactions[0].height, 1);
+            }
+            _ => panic!("invalid response"),
+        }
+
+        // Outputs.
+        for output_hash in &output_hashes {
+            let output = chain.output_by_hash(output_hash).unwrap().unwrap();
+            let recipient = match &output {
+                Output::PaymentOutput(o) => o.recipient,
+                Output::PublicPaymentOutput(o) => o.recipient,
+                Output::StakeOutput(o) => o.recipient,
+            };
+            match chain
+                .explore(&ExplorerQuery::OutputsByRecipient { recipient })
+                .unwrap()
+            {
+                ExplorerResponse::Outputs { outputs } => {
+                    let info = outputs.iter().find(|o| &o.utxo == output_hash).unwrap();
+                    assert_eq!(info.height, Some(1));
+                    assert!(info.unspent);
+                }
+                _ => panic!("invalid response"),
+            }
+        }
+
+        // Rollback.
+        chain.pop_micro_block().expect("no disk errors");
+        assert!(blocks_by_epoch(&chain, 1).is_empty());
+        match chain
+            .explore(&ExplorerQuery::TransactionsByHint { hint })
+            .unwrap()
+        {
+            ExplorerResponse::Transactions { transactions } => assert!(transactions.is_empty()),
+            _ => panic!("invalid response"),
+        }
+
+        // Reindex.
+        cfg.explorer_index = false;
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        assert!(chain
+            .explore(&ExplorerQuery::BlocksByEpoch { epoch: 0 })
+            .is_err());
+        chain.reindex().expect("no disk errors");
+        assert_eq!(blocks_by_epoch(&chain, 0).len(), 1);
+    }
+}
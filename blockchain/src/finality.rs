@@ -0,0 +1,170 @@
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Finality of macro blocks.
+//!
+//! A macro block, together with all blocks before it, is irreversible once it is
+//! committed by the multi-signature of the supermajority of stake. Micro blocks of
+//! the current epoch can still be reverted.
+
+use crate::block::MacroBlock;
+use crate::multisignature::check_supermajority;
+use serde_derive::Serialize;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+/// A macro block committed by validators.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FinalityCommitment {
+    /// The epoch finalized by this block.
+    pub epoch: u64,
+    pub height: u64,
+    pub block_hash: Hash,
+    /// Slots of validators which signed the block.
+    pub signed_slots: i64,
+    /// Slots of all validators of the epoch.
+    pub total_slots: i64,
+    /// True for the genesis and blocks below the last checkpoint,
+    /// which are final by configuration.
+    pub trusted: bool,
+}
+
+impl FinalityCommitment {
+    /// Create a commitment from the multi-signature of a macro block.
+    pub fn from_macro_block(
+        epoch: u64,
+        block_hash: Hash,
+        block: &MacroBlock,
+        validators: &[(pbc::PublicKey, i64)],
+        total_slots: i64,
+        trusted: bool,
+    ) -> Self {
+        let signed_slots: i64 = block
+            .body
+            .multisigmap
+            .iter()
+            .filter_map(|bit| validators.get(bit))
+            .map(|(_network_pkey, slots)| slots)
+            .sum();
+        FinalityCommitment {
+            epoch,
+            height: block.header.base.height,
+            block_hash,
+            signed_slots,
+            total_slots,
+            trusted,
+        }
+    }
+
+    /// True if the block is signed by the supermajority of stake or trusted.
+    pub fn is_final(&self) -> bool {
+        if self.trusted {
+            return true;
+        }
+        self.total_slots > 0
+            && self.signed_slots <= self.total_slots
+            && check_supermajority(self.signed_slots, self.total_slots)
+    }
+}
+
+/// Tracks the last irreversible macro block.
+#[derive(Debug, Clone)]
+pub(crate) struct Finality {
+    last: Option<FinalityCommitment>,
+}
+
+impl Finality {
+    pub fn new() -> Self {
+        Finality { last: None }
+    }
+
+    /// Advance finality to the committed block.
+    /// Returns false if the block is not final or doesn't advance finality.
+    pub fn advance(&mut self, commitment: FinalityCommitment) -> bool {
+        if !commitment.is_final() {
+            return false;
+        }
+        if let Some(last) = &self.last {
+            if commitment.height <= last.height {
+                return false;
+            }
+        }
+        self.last = Some(commitment);
+        true
+    }
+
+    /// The height of the last irreversible block.
+    pub fn height(&self) -> u64 {
+        self.last.as_ref().map_or(0, |last| last.height)
+    }
+
+    /// The commitment of the last irreversible block.
+    pub fn last(&self) -> Option<&FinalityCommitment> {
+        self.last.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commitment(height: u64, signed_slots: i64, trusted: bool) -> FinalityCommitment {
+        FinalityCommitment {
+            epoch: height,
+            height,
+            block_hash: Hash::digest(&height),
+            signed_slots,
+            total_slots: 12,
+            trusted,
+        }
+    }
+
+    #[test]
+    fn advance() {
+        let mut finality = Finality::new();
+        assert_eq!(finality.height(), 0);
+        assert!(finality.last().is_none());
+
+        // Genesis.
+        assert!(finality.advance(commitment(0, 0, true)));
+        assert_eq!(finality.height(), 0);
+
+        // Not enough stake.
+        assert!(!finality.advance(commitment(10, 8, false)));
+        assert_eq!(finality.height(), 0);
+
+        // Supermajority.
+        assert!(finality.advance(commitment(10, 9, false)));
+        assert_eq!(finality.height(), 10);
+        assert_eq!(finality.last().unwrap().block_hash, Hash::digest(&10u64));
+
+        // Finality never goes back.
+        assert!(!finality.advance(commitment(10, 12, false)));
+        assert!(!finality.advance(commitment(5, 12, true)));
+        assert_eq!(finality.height(), 10);
+
+        // Below the last checkpoint.
+        assert!(finality.advance(commitment(20, 0, true)));
+        assert_eq!(finality.height(), 20);
+    }
+}
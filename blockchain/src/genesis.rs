@@ -22,13 +22,19 @@
 // SOFTWARE.
 
 use crate::block::*;
+use crate::error::GenesisError;
 use crate::mix;
 use crate::multisignature::create_multi_signature;
 use crate::output::*;
 use bitvector::BitVector;
-use std::collections::BTreeMap;
-use std::time::SystemTime;
-use stegos_crypto::hash::Hash;
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stegos_crypto::curve1174::{Fr, PublicKey};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
 use stegos_crypto::pbc;
 use stegos_keychain::KeyChain;
 
@@ -53,7 +59,15 @@ pub fn genesis(
         let previous = Hash::digest(&"genesis".to_string());
         let seed = mix(init_random, view_change);
         let random = pbc::make_VRF(&keychains[0].network_skey, &seed);
-        let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+        let base = BaseBlockHeader::new(
+            version,
+            Hash::zero(),
+            previous,
+            height,
+            view_change,
+            timestamp,
+            random,
+        );
         //
         // Genesis has one PaymentOutput + N * StakeOutput, where N is the number of validators.
         //
@@ -112,3 +126,318 @@ pub fn genesis(
 
     block1
 }
+
+/// Stake of a genesis validator.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GenesisStake {
+    /// Network key of the validator.
+    pub network_pkey: pbc::PublicKey,
+    /// Wallet key of the stake owner.
+    pub wallet_pkey: PublicKey,
+    pub amount: i64,
+    /// BLS signature of the stake output, made by the network key.
+    pub signature: pbc::Signature,
+}
+
+/// Initial coins, created as public payments.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GenesisCoins {
+    pub recipient: PublicKey,
+    pub amount: i64,
+}
+
+/// Genesis specification, loaded from a TOML or JSON file.
+///
+/// All outputs are built deterministically from the specification, so every node
+/// which loads the same file gets the same genesis block.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GenesisSpec {
+    /// Name of the chain.
+    pub chain_id: String,
+    /// Seconds since UNIX epoch.
+    pub timestamp: u64,
+    /// VRF randomness of the genesis block.
+    pub random: Hash,
+    /// Hex-encoded VRF proof of `random`.
+    pub random_proof: String,
+    pub stakes: Vec<GenesisStake>,
+    pub coins: Vec<GenesisCoins>,
+}
+
+impl GenesisSpec {
+    /// Creates a specification where the first keychain receives all coins except stakes
+    /// and every keychain stakes `stake`, same as `genesis()`.
+    pub fn generate(
+        chain_id: &str,
+        keychains: &[KeyChain],
+        stake: i64,
+        coins: i64,
+        timestamp: SystemTime,
+    ) -> GenesisSpec {
+        assert!(!keychains.is_empty());
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .expect("valid timestamp")
+            .as_secs();
+        let seed = mix(Hash::digest("random"), 0);
+        let random = pbc::make_VRF(&keychains[0].network_skey, &seed);
+        let mut spec = GenesisSpec {
+            chain_id: chain_id.to_string(),
+            timestamp,
+            random: random.rand,
+            random_proof: random.proof.to_hex(),
+            stakes: Vec::with_capacity(keychains.len()),
+            coins: vec![GenesisCoins {
+                recipient: keychains[0].wallet_pkey,
+                amount: coins - keychains.len() as i64 * stake,
+            }],
+        };
+        for (index, keys) in keychains.iter().enumerate() {
+            let mut output = spec.stake_output(
+                index,
+                &GenesisStake {
+                    network_pkey: keys.network_pkey,
+                    wallet_pkey: keys.wallet_pkey,
+                    amount: stake,
+                    signature: pbc::Signature::zero(),
+                },
+            );
+            output.signature = pbc::sign_hash(&Hash::digest(&output), &keys.network_skey);
+            spec.stakes.push(GenesisStake {
+                network_pkey: keys.network_pkey,
+                wallet_pkey: keys.wallet_pkey,
+                amount: stake,
+                signature: output.signature,
+            });
+        }
+        spec
+    }
+
+    /// Parses a TOML specification.
+    pub fn from_toml(data: &str) -> Result<GenesisSpec, GenesisError> {
+        toml::from_str(data).map_err(|e| GenesisError::ParseError(e.to_string()))
+    }
+
+    /// Parses a JSON specification.
+    pub fn from_json(data: &str) -> Result<GenesisSpec, GenesisError> {
+        serde_json::from_str(data).map_err(|e| GenesisError::ParseError(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("serializable")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serializable")
+    }
+
+    ///
+    /// Loads a specification file and checks its detached signature.
+    ///
+    /// The signature file contains a hex-encoded BLS signature of the file contents
+    /// made by `authority`. Files with `.json` extension are parsed as JSON, others as TOML.
+    ///
+    pub fn load(
+        path: &Path,
+        signature_path: &Path,
+        authority: &pbc::PublicKey,
+    ) -> Result<GenesisSpec, Error> {
+        let data = fs::read(path)?;
+        let signature = fs::read_to_string(signature_path)?;
+        let signature = pbc::Signature::try_from_hex(signature.trim())
+            .map_err(|_| GenesisError::InvalidSignature(*authority))?;
+        let hash = Hash::from_vector(&data);
+        if pbc::check_hash(&hash, &signature, authority).is_err() {
+            return Err(GenesisError::InvalidSignature(*authority).into());
+        }
+        let data = String::from_utf8(data).map_err(|e| GenesisError::ParseError(e.to_string()))?;
+        let spec = match path.extension() {
+            Some(ext) if ext == "json" => Self::from_json(&data)?,
+            _ => Self::from_toml(&data)?,
+        };
+        Ok(spec)
+    }
+
+    /// Signs the contents of a specification file.
+    pub fn sign_file(data: &[u8], skey: &pbc::SecretKey) -> pbc::Signature {
+        pbc::sign_hash(&Hash::from_vector(data), skey)
+    }
+
+    /// Returns the stake output for the stake with the given index.
+    fn stake_output(&self, index: usize, stake: &GenesisStake) -> StakeOutput {
+        StakeOutput {
+            recipient: stake.wallet_pkey,
+            validator: stake.network_pkey,
+            operator: None,
+            reward: None,
+            amount: stake.amount,
+            serno: self.serno("stake", index),
+            signature: stake.signature,
+        }
+    }
+
+    /// Deterministic serial number of an output.
+    fn serno(&self, kind: &str, index: usize) -> i64 {
+        let mut hasher = Hasher::new();
+        self.chain_id.hash(&mut hasher);
+        self.timestamp.hash(&mut hasher);
+        kind.hash(&mut hasher);
+        (index as u64).hash(&mut hasher);
+        let hash = hasher.result();
+        let mut serno = [0u8; 8];
+        serno.copy_from_slice(&hash.base_vector()[0..8]);
+        i64::from_le_bytes(serno)
+    }
+
+    /// Validates the specification and builds the genesis block.
+    pub fn genesis_block(&self) -> Result<MacroBlock, GenesisError> {
+        if self.chain_id.is_empty() {
+            return Err(GenesisError::EmptyChainId);
+        }
+        if self.stakes.is_empty() {
+            return Err(GenesisError::NoStakes);
+        }
+        let proof =
+            pbc::G1::try_from_hex(&self.random_proof).map_err(|_| GenesisError::InvalidRandom)?;
+        let random = pbc::VRF {
+            rand: self.random,
+            proof,
+        };
+        if !pbc::validate_VRF_randomness(&random) {
+            return Err(GenesisError::InvalidRandom);
+        }
+
+        let mut outputs: Vec<Output> = Vec::with_capacity(self.coins.len() + self.stakes.len());
+        let mut total: i64 = 0;
+        for (index, coins) in self.coins.iter().enumerate() {
+            if coins.amount <= 0 {
+                return Err(GenesisError::InvalidAmount(coins.amount));
+            }
+            if coins.recipient.decompress().is_err() {
+                return Err(GenesisError::InvalidWalletKey(coins.recipient));
+            }
+            total = total
+                .checked_add(coins.amount)
+                .ok_or(GenesisError::InvalidAmount(coins.amount))?;
+            let output = PublicPaymentOutput {
+                recipient: coins.recipient,
+                serno: self.serno("coins", index),
+                amount: coins.amount,
+                hashlock: None,
+                channel: None,
+            };
+            outputs.push(Output::PublicPaymentOutput(output));
+        }
+        let mut validators = BTreeSet::new();
+        for (index, stake) in self.stakes.iter().enumerate() {
+            if stake.amount <= 0 {
+                return Err(GenesisError::InvalidAmount(stake.amount));
+            }
+            if stake.wallet_pkey.decompress().is_err() {
+                return Err(GenesisError::InvalidWalletKey(stake.wallet_pkey));
+            }
+            if !validators.insert(stake.network_pkey) {
+                return Err(GenesisError::DuplicateValidator(stake.network_pkey));
+            }
+            total = total
+                .checked_add(stake.amount)
+                .ok_or(GenesisError::InvalidAmount(stake.amount))?;
+            let output = self.stake_output(index, stake);
+            if output.validate().is_err() {
+                return Err(GenesisError::InvalidStakeSignature(stake.network_pkey));
+            }
+            outputs.push(Output::StakeOutput(output));
+        }
+
+        let version: u64 = 1;
+        let chain_id = Hash::digest(&self.chain_id);
+        let previous = Hash::digest(&"genesis".to_string());
+        let timestamp = UNIX_EPOCH + Duration::from_secs(self.timestamp);
+        let base = BaseBlockHeader::new(version, chain_id, previous, 0, 0, timestamp, random);
+        Ok(MacroBlock::new(
+            base,
+            Fr::zero(),
+            total,
+            BitVector::ones(self.stakes.len()),
+            &[],
+            &outputs,
+            self.stakes[0].network_pkey,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Blockchain, BlockchainConfig};
+    use tempdir::TempDir;
+
+    #[test]
+    fn genesis_spec() {
+        let cfg: BlockchainConfig = Default::default();
+        let timestamp = SystemTime::now();
+        let keychains: Vec<KeyChain> = (0..3).map(|_| KeyChain::new_mem()).collect();
+        let spec = GenesisSpec::generate(
+            &cfg.chain_id,
+            &keychains,
+            cfg.min_stake_amount,
+            1_000_000 * cfg.min_stake_amount,
+            timestamp,
+        );
+
+        // Deterministic.
+        let block = spec.genesis_block().expect("valid spec");
+        let toml = spec.to_toml();
+        let spec2 = GenesisSpec::from_toml(&toml).expect("valid toml");
+        assert_eq!(spec, spec2);
+        assert_eq!(
+            Hash::digest(&block),
+            Hash::digest(&spec2.genesis_block().unwrap())
+        );
+        let spec3 = GenesisSpec::from_json(&spec.to_json()).expect("valid json");
+        assert_eq!(spec, spec3);
+
+        // Accepted by the blockchain.
+        let chain = Blockchain::testing(cfg, block.clone(), timestamp).expect("valid genesis");
+        assert_eq!(chain.last_block_hash(), Hash::digest(&block));
+        assert_eq!(block.header.base.chain_id, chain.chain_id());
+        assert!(!chain.validators().is_empty());
+
+        // Detached signature.
+        let dir = TempDir::new("genesis").unwrap();
+        let path = dir.path().join("genesis.toml");
+        let signature_path = dir.path().join("genesis.toml.sig");
+        let authority = &keychains[0];
+        fs::write(&path, &toml).unwrap();
+        let signature = GenesisSpec::sign_file(toml.as_bytes(), &authority.network_skey);
+        fs::write(&signature_path, signature.to_hex()).unwrap();
+        let loaded = GenesisSpec::load(&path, &signature_path, &authority.network_pkey).unwrap();
+        assert_eq!(loaded, spec);
+        assert!(GenesisSpec::load(&path, &signature_path, &keychains[1].network_pkey).is_err());
+        fs::write(&path, toml.replace("dev", "mainnet")).unwrap();
+        assert!(GenesisSpec::load(&path, &signature_path, &authority.network_pkey).is_err());
+
+        // Tampered stakes are rejected.
+        let mut bad = spec.clone();
+        bad.stakes[1].amount += 1;
+        match bad.genesis_block() {
+            Err(GenesisError::InvalidStakeSignature(pkey)) => {
+                assert_eq!(pkey, keychains[1].network_pkey)
+            }
+            _ => panic!(),
+        }
+        let mut bad = spec.clone();
+        let stake = bad.stakes[0].clone();
+        bad.stakes.push(stake);
+        match bad.genesis_block() {
+            Err(GenesisError::DuplicateValidator(_)) => {}
+            _ => panic!(),
+        }
+        let mut bad = spec.clone();
+        bad.random = Hash::digest("bad");
+        match bad.genesis_block() {
+            Err(GenesisError::InvalidRandom) => {}
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Canonical hashing of transactions and blocks.
+//!
+//! Hashes of outputs, transactions and blocks are never computed from their
+//! protobuf encoding. Every type feeds its fields into SHA3-256 explicitly via
+//! the `Hashable` trait, so the result doesn't depend on how a message was
+//! serialized or in which order its fields arrived on the wire.
+//!
+//! The scheme is:
+//!
+//! - integers are fed as little-endian bytes of their declared width;
+//!   `SystemTime` as `u64` milliseconds since UNIX epoch;
+//! - strings, byte arrays and hashes are fed as raw bytes, without a length;
+//! - variable-length lists are prefixed by their length as `u64`;
+//! - keys, points and scalars are prefixed by a type tag (e.g. "PKey", "Fr");
+//! - outputs and block headers are prefixed by a type tag (e.g. "Payment", "Micro");
+//! - optional fields are fed only if present, prefixed by a tag, so adding
+//!   a new optional field doesn't change hashes of existing objects;
+//! - fields are fed in the order of declaration in the `Hashable` impl,
+//!   which is the only source of truth and must never be reordered.
+//!
+//! `hashing_test_vectors()` builds objects from fixed inputs, and
+//! `data/hashing_test_vectors.json` keeps their expected hashes.
+//! External implementations can use them to check their results against this one.
+
+use crate::block::{BaseBlockHeader, MacroBlockHeader, MicroBlock, VERSION};
+use crate::output::{HashLock, LockTime, Output, PublicPaymentOutput, StakeOutput};
+use crate::transaction::{CoinbaseTransaction, PaymentTransaction, RestakeTransaction};
+use serde_derive::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stegos_crypto::curve1174::{Fr, PublicKey, SchnorrSig};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+/// Expected hash of a well-known object.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HashingTestVector {
+    /// Name of the object.
+    pub name: String,
+    /// Hash of the object.
+    pub hash: Hash,
+}
+
+/// Timestamp used by test vectors.
+fn test_timestamp() -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(1_500_000_000_000)
+}
+
+fn test_base_header(chain_id: Hash, height: u64) -> BaseBlockHeader {
+    // Bypass BaseBlockHeader::new(), which validates VRF.
+    BaseBlockHeader {
+        version: VERSION,
+        chain_id,
+        previous: Hash::digest("previous"),
+        height,
+        view_change: 0,
+        timestamp: test_timestamp(),
+        random: pbc::VRF {
+            rand: Hash::digest("random"),
+            proof: pbc::G1::zero(),
+        },
+    }
+}
+
+/// Generates test vectors of canonical hashing.
+/// Objects are built from fixed inputs and zero keys, which are never validated by hashing.
+pub fn hashing_test_vectors() -> Vec<HashingTestVector> {
+    let recipient = PublicKey::zero();
+    let validator = pbc::PublicKey::from(pbc::G2::zero());
+    let secret = "secret";
+    let txin = Hash::digest("input");
+    let chain_id = Hash::digest("chain");
+
+    let payment = PublicPaymentOutput {
+        recipient,
+        serno: 1,
+        amount: 100,
+        hashlock: None,
+        channel: None,
+    };
+    let hashlocked = PublicPaymentOutput {
+        hashlock: Some(HashLock {
+            hash: Hash::digest(secret),
+            refund: recipient,
+            timeout: LockTime::Height(100),
+        }),
+        ..payment.clone()
+    };
+    let timestamp_lock = LockTime::Timestamp(test_timestamp());
+    let stake = StakeOutput {
+        recipient,
+        validator,
+        operator: None,
+        reward: None,
+        amount: 1000,
+        serno: 2,
+        signature: pbc::Signature::zero(),
+    };
+    let delegated_stake = StakeOutput {
+        operator: Some(validator),
+        ..stake.clone()
+    };
+
+    let payment_tx = PaymentTransaction {
+        chain_id,
+        txins: vec![txin],
+        txouts: vec![Output::PublicPaymentOutput(payment.clone())],
+        gamma: Fr::zero(),
+        fee: 1,
+        sig: SchnorrSig::new(),
+        preimages: Vec::new(),
+        channels: Vec::new(),
+    };
+    let redeem_tx = PaymentTransaction {
+        preimages: vec![(txin, secret.as_bytes().to_vec())],
+        ..payment_tx.clone()
+    };
+    let coinbase_tx = CoinbaseTransaction {
+        block_reward: 60,
+        block_fee: 1,
+        gamma: Fr::zero(),
+        txouts: vec![Output::PublicPaymentOutput(payment.clone())],
+    };
+    let restake_tx = RestakeTransaction {
+        chain_id,
+        txins: vec![Hash::digest(&stake)],
+        txouts: vec![Output::StakeOutput(delegated_stake.clone())],
+        sig: pbc::Signature::zero(),
+    };
+
+    let legacy_header = test_base_header(Hash::zero(), 0);
+    let macro_header = MacroBlockHeader {
+        base: test_base_header(chain_id, 100),
+        gamma: Fr::zero(),
+        block_reward: 60,
+        inputs_range_hash: Hash::digest("inputs"),
+        outputs_range_hash: Hash::digest("outputs"),
+    };
+    let micro_block = MicroBlock::new(test_base_header(chain_id, 101), None, Vec::new(), validator);
+
+    let vectors = vec![
+        ("public_payment_output", Hash::digest(&payment)),
+        (
+            "hashlocked_public_payment_output",
+            Hash::digest(&hashlocked),
+        ),
+        ("timestamp_lock", Hash::digest(&timestamp_lock)),
+        ("stake_output", Hash::digest(&stake)),
+        ("delegated_stake_output", Hash::digest(&delegated_stake)),
+        ("payment_transaction", Hash::digest(&payment_tx)),
+        ("redeem_payment_transaction", Hash::digest(&redeem_tx)),
+        ("coinbase_transaction", Hash::digest(&coinbase_tx)),
+        ("restake_transaction", Hash::digest(&restake_tx)),
+        ("legacy_base_header", Hash::digest(&legacy_header)),
+        ("macro_block_header", Hash::digest(&macro_header)),
+        ("micro_block", Hash::digest(&micro_block)),
+    ];
+    vectors
+        .into_iter()
+        .map(|(name, hash)| HashingTestVector {
+            name: name.to_string(),
+            hash,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Golden values, checked into the repository.
+    const GOLDEN: &str = include_str!("../data/hashing_test_vectors.json");
+
+    #[test]
+    fn golden_vectors() {
+        let expected: Vec<HashingTestVector> = serde_json::from_str(GOLDEN).unwrap();
+        let actual = hashing_test_vectors();
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            assert_eq!(actual.name, expected.name);
+            assert_eq!(
+                actual.hash, expected.hash,
+                "hash of {} has changed",
+                actual.name
+            );
+        }
+    }
+
+    #[test]
+    fn distinct_vectors() {
+        let vectors = hashing_test_vectors();
+        let hashes: HashSet<Hash> = vectors.iter().map(|v| v.hash).collect();
+        assert_eq!(hashes.len(), vectors.len());
+    }
+}
@@ -0,0 +1,596 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Chain State Inspector.
+
+use crate::block::*;
+use crate::blockchain::Blockchain;
+use crate::escrow::{Escrow, StakeReplay, ValidatorInfo};
+use crate::output::Output;
+use crate::transaction::Transaction;
+use failure::{format_err, Error};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::time::SystemTime;
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc;
+
+/// A structured query against the chain state.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "query")]
+#[serde(rename_all = "snake_case")]
+pub enum InspectorQuery {
+    /// Fetch an output by hash, including already spent ones.
+    Output { utxo: Hash },
+    /// Decode a block by height.
+    Block { height: u64 },
+    /// Dump escrow entries of a validator.
+    Escrow { validator: pbc::PublicKey },
+    /// Calculate the state hash at height.
+    StateHash { height: u64 },
+}
+
+/// Parses the textual form used by the CLI, e.g. `block 10` or `output HASH`.
+impl FromStr for InspectorQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut args = s.split_whitespace();
+        let command = args.next().unwrap_or_default();
+        let arg = args
+            .next()
+            .ok_or_else(|| format_err!("Missing argument: query={}", s))?;
+        if let Some(extra) = args.next() {
+            return Err(format_err!("Unexpected argument: arg={}", extra));
+        }
+        let query = match command {
+            "output" => InspectorQuery::Output {
+                utxo: Hash::try_from_hex(arg)?,
+            },
+            "block" => InspectorQuery::Block {
+                height: arg.parse()?,
+            },
+            "escrow" => InspectorQuery::Escrow {
+                validator: pbc::PublicKey::try_from_hex(arg)?,
+            },
+            "state" => InspectorQuery::StateHash {
+                height: arg.parse()?,
+            },
+            _ => return Err(format_err!("Unknown query: {}", command)),
+        };
+        Ok(query)
+    }
+}
+
+/// A result of `InspectorQuery`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum InspectorResponse {
+    Output {
+        utxo: Hash,
+        /// None if the output is unknown or pruned.
+        output: Option<OutputInfo>,
+    },
+    Block(BlockInfo),
+    Escrow {
+        validator: pbc::PublicKey,
+        /// None if the validator has no stakes.
+        escrow: Option<ValidatorInfo>,
+    },
+    StateHash(StateHash),
+}
+
+/// Printable representation of an output.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputInfo {
+    pub utxo: Hash,
+    #[serde(rename = "type")]
+    pub output_type: &'static str,
+    pub recipient: PublicKey,
+    /// Height of the block which created this output.
+    pub height: Option<u64>,
+    /// True if the output is still unspent.
+    pub unspent: bool,
+    /// Only public payments and stakes disclose amounts.
+    pub amount: Option<i64>,
+    pub validator: Option<pbc::PublicKey>,
+}
+
+impl OutputInfo {
+    pub(crate) fn new(output: &Output, height: Option<u64>, unspent: bool) -> Self {
+        let utxo = Hash::digest(output);
+        match output {
+            Output::PaymentOutput(o) => OutputInfo {
+                utxo,
+                output_type: "payment",
+                recipient: o.recipient,
+                height,
+                unspent,
+                amount: None,
+                validator: None,
+            },
+            Output::PublicPaymentOutput(o) => OutputInfo {
+                utxo,
+                output_type: "public_payment",
+                recipient: o.recipient,
+                height,
+                unspent,
+                amount: Some(o.amount),
+                validator: None,
+            },
+            Output::StakeOutput(o) => OutputInfo {
+                utxo,
+                output_type: "stake",
+                recipient: o.recipient,
+                height,
+                unspent,
+                amount: Some(o.amount),
+                validator: Some(o.validator),
+            },
+        }
+    }
+}
+
+/// Printable representation of a transaction.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionInfo {
+    pub tx_hash: Hash,
+    #[serde(rename = "type")]
+    pub tx_type: &'static str,
+    pub fee: i64,
+    pub inputs: Vec<Hash>,
+    pub outputs: Vec<OutputInfo>,
+}
+
+impl TransactionInfo {
+    fn new(tx: &Transaction, chain: &Blockchain, height: u64) -> Self {
+        TransactionInfo {
+            tx_hash: Hash::digest(tx),
+            tx_type: tx.to_type_str(),
+            fee: tx.fee(),
+            inputs: tx.txins().to_vec(),
+            outputs: tx
+                .txouts()
+                .iter()
+                .map(|o| OutputInfo::new(o, Some(height), chain.contains_output(&Hash::digest(o))))
+                .collect(),
+        }
+    }
+}
+
+/// Printable representation of a block.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockInfo {
+    pub height: u64,
+    pub hash: Hash,
+    pub previous: Hash,
+    #[serde(rename = "type")]
+    pub block_type: &'static str,
+    pub version: u64,
+    pub view_change: u32,
+    pub timestamp: SystemTime,
+    /// Leader for micro blocks, aggregated key for macro blocks.
+    pub pkey: pbc::PublicKey,
+    /// Macro blocks only.
+    pub block_reward: Option<i64>,
+    /// Macro blocks only.
+    pub inputs: Vec<Hash>,
+    /// Macro blocks only.
+    pub outputs: Vec<OutputInfo>,
+    /// Micro blocks only.
+    pub transactions: Vec<TransactionInfo>,
+}
+
+impl BlockInfo {
+    fn new(block: &Block, chain: &Blockchain) -> Self {
+        let base = block.base_header();
+        let height = base.height;
+        let mut info = BlockInfo {
+            height,
+            hash: Hash::digest(block),
+            previous: base.previous,
+            block_type: "",
+            version: base.version,
+            view_change: base.view_change,
+            timestamp: base.timestamp,
+            pkey: pbc::PublicKey::dum(),
+            block_reward: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            transactions: Vec::new(),
+        };
+        match block {
+            Block::MacroBlock(block) => {
+                info.block_type = "macro";
+                info.pkey = block.body.pkey;
+                info.block_reward = Some(block.header.block_reward);
+                info.inputs = block.body.inputs.clone();
+                info.outputs = block
+                    .body
+                    .outputs
+                    .leafs()
+                    .into_iter()
+                    .map(|(o, _path)| {
+                        let unspent = chain.contains_output(&Hash::digest(o.as_ref()));
+                        OutputInfo::new(o, Some(height), unspent)
+                    })
+                    .collect();
+            }
+            Block::MicroBlock(block) => {
+                info.block_type = "micro";
+                info.pkey = block.pkey;
+                info.transactions = block
+                    .transactions
+                    .iter()
+                    .map(|tx| TransactionInfo::new(tx, chain, height))
+                    .collect();
+            }
+        }
+        info
+    }
+}
+
+/// State hash at some height.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StateHash {
+    pub height: u64,
+    /// Hash of the block at this height, which commits to the whole history.
+    pub block_hash: Hash,
+    /// Hash of the UTXO set and escrow.
+    pub state_hash: Hash,
+}
+
+/// Result of comparing two databases at some height.
+#[derive(Debug, Clone, Serialize)]
+pub struct StateComparison {
+    pub left: StateHash,
+    pub right: StateHash,
+    pub equal: bool,
+}
+
+impl Blockchain {
+    ///
+    /// Evaluate a query against the chain state.
+    ///
+    pub fn inspect(&self, query: &InspectorQuery) -> Result<InspectorResponse, Error> {
+        let response = match query {
+            InspectorQuery::Output { utxo } => {
+                let output = self
+                    .historical_output_by_hash(utxo)?
+                    .map(|(height, output)| {
+                        OutputInfo::new(&output, Some(height), self.contains_output(utxo))
+                    });
+                InspectorResponse::Output {
+                    utxo: *utxo,
+                    output,
+                }
+            }
+            InspectorQuery::Block { height } => {
+                self.check_height(*height)?;
+                let block = self.block_by_height(*height)?;
+                InspectorResponse::Block(BlockInfo::new(&block, self))
+            }
+            InspectorQuery::Escrow { validator } => {
+                let escrow = self
+                    .escrow_info()
+                    .validators
+                    .into_iter()
+                    .find(|info| &info.network_pkey == validator);
+                InspectorResponse::Escrow {
+                    validator: *validator,
+                    escrow,
+                }
+            }
+            InspectorQuery::StateHash { height } => {
+                InspectorResponse::StateHash(self.state_hash(*height)?)
+            }
+        };
+        Ok(response)
+    }
+
+    ///
+    /// Calculate the state hash at the specified height.
+    /// The state of the latest block is taken from indexes, older states are
+    /// replayed from blocks.
+    ///
+    pub fn state_hash(&self, height: u64) -> Result<StateHash, Error> {
+        self.check_height(height)?;
+        let block_hash = Hash::digest(&self.block_by_height(height)?);
+        let state = if height + 1 == self.height() {
+            self.current_state()
+        } else {
+            self.replay_state(height)?
+        };
+        Ok(StateHash {
+            height,
+            block_hash,
+            state_hash: state,
+        })
+    }
+
+    /// Returns the hash of the UTXO set and escrow from indexes.
+    fn current_state(&self) -> Hash {
+        state_hash(self.unspent(), self.escrow())
+    }
+
+    /// Replays the UTXO set and escrow from blocks up to the height.
+    fn replay_state(&self, height: u64) -> Result<Hash, Error> {
+        let mut unspent: BTreeSet<Hash> = BTreeSet::new();
+        let mut stakes = StakeReplay::new(self.cfg().stake_epochs);
+        let mut epoch: u64 = 0;
+        let mut expected_height: u64 = 0;
+        for block in self.blocks().take((height + 1) as usize) {
+            if block.base_header().height != expected_height {
+                return Err(format_err!(
+                    "Missing block in the database: height={}",
+                    expected_height
+                ));
+            }
+            // Same order as in Blockchain::register_block(): inputs, then outputs.
+            let (inputs, outputs): (Vec<Hash>, Vec<Output>) = match &block {
+                Block::MacroBlock(block) => {
+                    let outputs = block.body.outputs.leafs();
+                    let outputs = outputs.into_iter().map(|(o, _path)| (**o).clone());
+                    (block.body.inputs.clone(), outputs.collect())
+                }
+                Block::MicroBlock(block) => {
+                    let inputs = block.transactions.iter().flat_map(|tx| tx.txins());
+                    let outputs = block.transactions.iter().flat_map(|tx| tx.txouts());
+                    (inputs.cloned().collect(), outputs.cloned().collect())
+                }
+            };
+            for input in &inputs {
+                unspent.remove(input);
+            }
+            for output in &outputs {
+                unspent.insert(Hash::digest(output));
+            }
+            stakes.apply(expected_height, epoch, &inputs, &outputs);
+            if let Block::MacroBlock(_) = block {
+                stakes.checkpoint();
+                epoch += 1;
+            }
+            expected_height += 1;
+        }
+        if expected_height != height + 1 {
+            return Err(format_err!(
+                "Missing block in the database: height={}",
+                expected_height
+            ));
+        }
+        Ok(state_hash(unspent.iter(), stakes.escrow()))
+    }
+
+    fn check_height(&self, height: u64) -> Result<(), Error> {
+        if height >= self.height() {
+            return Err(format_err!(
+                "Block not found: height={}, chain_height={}",
+                height,
+                self.height()
+            ));
+        }
+        Ok(())
+    }
+}
+
+///
+/// Compare the state of two databases at the specified height.
+///
+pub fn compare_state(
+    left: &Blockchain,
+    right: &Blockchain,
+    height: u64,
+) -> Result<StateComparison, Error> {
+    let left = left.state_hash(height)?;
+    let right = right.state_hash(height)?;
+    let equal = left.block_hash == right.block_hash && left.state_hash == right.state_hash;
+    Ok(StateComparison { left, right, equal })
+}
+
+/// Hashes the UTXO set and escrow, which are covered by the state hash.
+fn state_hash<'a>(unspent: impl Iterator<Item = &'a Hash>, escrow: &Escrow) -> Hash {
+    let mut hasher = Hasher::new();
+    for utxo in unspent {
+        utxo.hash(&mut hasher);
+    }
+    escrow.hash(&mut hasher);
+    hasher.result()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::create_fake_micro_block;
+    use crate::config::BlockchainConfig;
+    use crate::genesis::genesis;
+    use std::time::Duration;
+    use stegos_keychain::KeyChain;
+
+    #[test]
+    fn inspect() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let genesis = genesis(
+            &keychains,
+            cfg.min_stake_amount,
+            10 * cfg.min_stake_amount,
+            timestamp,
+        );
+        let mut chain = Blockchain::testing(cfg.clone(), genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+        let mut other = Blockchain::testing(cfg, genesis.clone(), timestamp)
+            .expect("Failed to create blockchain");
+        assert!(compare_state(&chain, &other, 0).unwrap().equal);
+
+        timestamp += Duration::from_millis(1);
+        let (block, input_hashes, output_hashes) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+
+        // Blocks.
+        match chain.inspect(&InspectorQuery::Block { height: 1 }).unwrap() {
+            InspectorResponse::Block(info) => {
+                assert_eq!(info.height, 1);
+                assert_eq!(info.block_type, "micro");
+                assert_eq!(info.hash, chain.last_block_hash());
+            }
+            _ => panic!("invalid response"),
+        }
+        assert!(chain.inspect(&InspectorQuery::Block { height: 2 }).is_err());
+
+        // Outputs, including spent ones.
+        for (utxo, unspent) in input_hashes
+            .iter()
+            .map(|h| (h, false))
+            .chain(output_hashes.iter().map(|h| (h, true)))
+        {
+            match chain
+                .inspect(&InspectorQuery::Output { utxo: *utxo })
+                .unwrap()
+            {
+                InspectorResponse::Output {
+                    output: Some(info), ..
+                } => {
+                    assert_eq!(&info.utxo, utxo);
+                    assert_eq!(info.unspent, unspent);
+                }
+                _ => panic!("invalid response"),
+            }
+        }
+
+        // Escrow.
+        let validator = keychains[0].network_pkey;
+        match chain
+            .inspect(&InspectorQuery::Escrow { validator })
+            .unwrap()
+        {
+            InspectorResponse::Escrow {
+                escrow: Some(info), ..
+            } => assert_eq!(info.network_pkey, validator),
+            _ => panic!("invalid response"),
+        }
+
+        // State hashes, the replayed state must match the state from indexes.
+        let state = chain.state_hash(0).unwrap();
+        assert_eq!(state, other.state_hash(0).unwrap());
+        assert_ne!(state.state_hash, chain.state_hash(1).unwrap().state_hash);
+        assert!(compare_state(&chain, &other, 0).unwrap().equal);
+        assert!(compare_state(&chain, &other, 1).is_err());
+
+        // A different block at the same height.
+        timestamp += Duration::from_millis(1);
+        let (block, _input_hashes, _output_hashes) =
+            create_fake_micro_block(&other, &keychains, timestamp).expect("block is valid");
+        other
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        assert!(!compare_state(&chain, &other, 1).unwrap().equal);
+        assert!(compare_state(&chain, &other, 0).unwrap().equal);
+    }
+
+    #[test]
+    fn replay_restake() {
+        use crate::blockchain::create_micro_block_with_coinbase;
+        use crate::output::RewardDestination;
+        use crate::transaction::RestakeTransaction;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let keys = &keychains[0];
+        let mut timestamp = SystemTime::now();
+        let cfg: BlockchainConfig = Default::default();
+        let stake = cfg.min_stake_amount;
+        let genesis = genesis(&keychains, stake, 10 * stake, timestamp);
+        let mut chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        let chain_id = chain.chain_id();
+
+        // Restake with a reward destination, which is covered by the state hash.
+        let (_reward_skey, reward_pkey) = stegos_crypto::curve1174::make_random_keys();
+        let stake_hash = chain.stakes_by_wallet(&keys.wallet_pkey)[0].utxo;
+        let input = chain.output_by_hash(&stake_hash).unwrap().unwrap();
+        let mut output = match &input {
+            Output::StakeOutput(o) => o.clone(),
+            _ => panic!("expected a stake"),
+        };
+        let reward = RewardDestination::new(
+            &keys.wallet_skey,
+            &keys.wallet_pkey,
+            &keys.network_pkey,
+            &reward_pkey,
+        );
+        output.set_reward(Some(reward), &keys.network_skey);
+        let restake = RestakeTransaction::new(
+            &chain_id,
+            &keys.network_skey,
+            &keys.network_pkey,
+            &[input],
+            &[output.into()],
+        )
+        .unwrap();
+        timestamp += Duration::from_millis(1);
+        let mut block = create_micro_block_with_coinbase(&chain, &keychains, timestamp).unwrap();
+        block.transactions.push(restake.into());
+        block.sign(&keys.network_skey, &keys.network_pkey).unwrap();
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+        timestamp += Duration::from_millis(1);
+        let (block, _input_hashes, _output_hashes) =
+            create_fake_micro_block(&chain, &keychains, timestamp).expect("block is valid");
+        chain
+            .push_micro_block(block, timestamp)
+            .expect("block is valid");
+
+        // The replayed state must match the state from indexes.
+        assert_eq!(chain.replay_state(2).unwrap(), chain.current_state());
+        assert_ne!(chain.replay_state(1).unwrap(), chain.current_state());
+    }
+
+    #[test]
+    fn parse_queries() {
+        let hash = Hash::digest("test");
+        assert_eq!(
+            InspectorQuery::from_str(&format!("output {}", hash.to_hex())).unwrap(),
+            InspectorQuery::Output { utxo: hash }
+        );
+        assert_eq!(
+            InspectorQuery::from_str("block 10").unwrap(),
+            InspectorQuery::Block { height: 10 }
+        );
+        assert_eq!(
+            InspectorQuery::from_str("  state  5 ").unwrap(),
+            InspectorQuery::StateHash { height: 5 }
+        );
+        assert!(InspectorQuery::from_str("block").is_err());
+        assert!(InspectorQuery::from_str("block 1 2").is_err());
+        assert!(InspectorQuery::from_str("block abc").is_err());
+        assert!(InspectorQuery::from_str("unknown 1").is_err());
+    }
+}
@@ -21,36 +21,70 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod archive;
 mod awards;
 mod block;
 mod blockchain;
+mod channel;
+mod clock;
 mod config;
 pub mod election;
+mod emission;
+mod epoch_summary;
 mod error;
 mod escrow;
+mod explorer;
+mod finality;
 mod genesis;
+mod hashing;
+mod inspector;
 mod merkle;
 mod metrics;
+mod mmr;
 mod multisignature;
 pub mod mvcc;
+mod offline;
 mod output;
+mod proof;
 pub mod protos;
+mod reserves;
 mod slashing;
+mod snapshot;
 mod storage;
+mod stream;
 mod transaction;
+mod utxo_filter;
 mod validation;
 pub mod view_changes;
 
+pub use crate::archive::*;
 pub use crate::block::*;
 pub use crate::blockchain::*;
+pub use crate::channel::{ChannelState, ChannelWitness};
+pub use crate::clock::{Clock, MockClock, SystemClock};
 pub use crate::config::*;
-pub use crate::election::{mix, ElectionInfo, ElectionResult, StakersGroup};
+pub use crate::election::{
+    leader_selection_test_vectors, mix, ElectionInfo, ElectionResult, LeaderCandidate,
+    LeaderSelectionAudit, RandomBeacon, StakersGroup,
+};
+pub use crate::emission::EmissionConfig;
+pub use crate::epoch_summary::{EpochLeader, EpochSummary, ValidatorUptime};
 pub use crate::error::*;
 pub use crate::escrow::*;
+pub use crate::explorer::{ExplorerBlock, ExplorerQuery, ExplorerResponse, ExplorerTransaction};
+pub use crate::finality::FinalityCommitment;
 pub use crate::genesis::*;
+pub use crate::hashing::{hashing_test_vectors, HashingTestVector};
+pub use crate::inspector::*;
 pub use crate::merkle::*;
+pub use crate::mmr::{mmr_leaf_hash, MerkleMountainRange, MmrProof};
 pub use crate::multisignature::*;
+pub use crate::offline::*;
 pub use crate::output::*;
+pub use crate::proof::*;
+pub use crate::reserves::*;
 pub use crate::slashing::*;
+pub use crate::snapshot::*;
 pub use crate::storage::*;
+pub use crate::stream::{MicroBlockStream, ValidatedMicroBlock};
 pub use crate::transaction::*;
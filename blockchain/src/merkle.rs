@@ -83,6 +83,9 @@ pub enum MerkleError {
     /// Validation error
     #[fail(display = "Validation error: expected={}, got={}", _0, _1)]
     ValidationError(Hash, Hash),
+    /// Leaf doesn't exist
+    #[fail(display = "Leaf index out of range: index={}, leaves={}", _0, _1)]
+    OutOfRange(u64, u64),
 }
 
 /// 2**256 is more than anyone needed.
@@ -103,7 +106,18 @@ type Path = u32;
 /// 1 bit - go to the right subtree
 /// Stored in inverted order - from leaf to root
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub struct MerklePath(Path);
+pub struct MerklePath(pub(crate) Path);
+
+/// Proof of inclusion of a leaf into Merkle Tree.
+/// See Merkle::proof() and MerkleProof::verify().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Path to the leaf.
+    pub path: MerklePath,
+    /// Hashes of siblings, ordered from the root to the leaf.
+    /// None means that the node doesn't have a right sibling and was paired with itself.
+    pub siblings: Vec<Option<Hash>>,
+}
 
 // -------------------------------------
 
@@ -418,6 +432,60 @@ impl<T: Hashable + fmt::Debug> Merkle<T> {
         r
     }
 
+    ///
+    /// Create a proof of inclusion for an element by path.
+    /// Returns None if the element doesn't exist or has been pruned.
+    ///
+    pub fn proof(&self, path: &MerklePath) -> Option<MerkleProof> {
+        let mut node = &self.root;
+        let mut bits = path.0;
+        let mut siblings: Vec<Option<Hash>> = Vec::new();
+
+        // Traverse via inner nodes
+        loop {
+            // true - go left, false - go right
+            let left_direction = (bits & 1) == 0;
+            bits >>= 1;
+
+            node = match **node {
+                Node {
+                    left: Some(ref left),
+                    ref right,
+                    value: None, // node is not a leaf
+                    ..
+                } if left_direction => {
+                    siblings.push(right.as_ref().map(|right| right.hash));
+                    left
+                }
+                Node {
+                    left: Some(ref left),
+                    right: Some(ref right),
+                    value: None, // node is not a leaf
+                    ..
+                } if !left_direction => {
+                    siblings.push(Some(left.hash));
+                    right
+                }
+                Node {
+                    left: None,
+                    right: None,
+                    value: Some(_),
+                    ..
+                } => {
+                    return Some(MerkleProof {
+                        path: *path,
+                        siblings,
+                    });
+                }
+                Node {
+                    value: None, // node is not a leaf
+                    ..
+                } => return None, // missing or pruned subtree
+                _ => unreachable!(), // a leaf, doesn't happen in this algorithm
+            };
+        }
+    }
+
     /// A recursive helper for validate().
     fn validate_r(node: &Node<T>) -> Result<(), MerkleError> {
         match node {
@@ -716,6 +784,38 @@ impl<T: Hashable + fmt::Debug> Merkle<T> {
     }
 }
 
+impl MerkleProof {
+    ///
+    /// Check that the proof links a leaf with the specified hash to the root hash.
+    ///
+    pub fn verify(&self, leaf_hash: &Hash, roothash: &Hash) -> bool {
+        if self.siblings.len() > 8 * std::mem::size_of::<Path>() {
+            return false;
+        }
+
+        // Go up from the leaf to the root.
+        let mut hash = *leaf_hash;
+        for (h, sibling) in self.siblings.iter().enumerate().rev() {
+            let left_direction = ((self.path.0 >> h) & 1) == 0;
+            let mut hasher = Hasher::new();
+            if left_direction {
+                hash.hash(&mut hasher);
+                // Pair node with itself if it doesn't have right sibling.
+                sibling.as_ref().unwrap_or(&hash).hash(&mut hasher);
+            } else {
+                match sibling {
+                    Some(left) => left.hash(&mut hasher),
+                    None => return false, // Left subtree always exists
+                }
+                hash.hash(&mut hasher);
+            }
+            hash = hasher.result();
+        }
+
+        &hash == roothash
+    }
+}
+
 impl<T: Hashable + fmt::Debug> fmt::Debug for Merkle<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.fmt(f)
@@ -1153,6 +1253,40 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn proofs() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        for size in 1..20 {
+            let data: Vec<u32> = (0..size).collect();
+            let mut tree = Merkle::from_array(&data);
+            let paths = tree
+                .leafs()
+                .iter()
+                .map(|(_elem, path)| *path)
+                .collect::<Vec<MerklePath>>();
+
+            for (value, path) in data.iter().zip(paths.iter()) {
+                let proof = tree.proof(path).expect("exists");
+                assert_eq!(proof.siblings.len(), expected_height(data.len()) as usize);
+                assert!(proof.verify(&Hash::digest(value), tree.roothash()));
+                // Wrong leaf.
+                assert!(!proof.verify(&Hash::digest(&(size + 1)), tree.roothash()));
+                // Wrong root.
+                assert!(!proof.verify(&Hash::digest(value), &Hash::digest(&0u32)));
+            }
+
+            // Pruning keeps proofs of remaining leafs.
+            let pruned = tree.prune(&paths[0]).unwrap();
+            assert_eq!(pruned, data[0]);
+            assert_eq!(tree.proof(&paths[0]), None);
+            for (value, path) in data.iter().zip(paths.iter()).skip(1) {
+                let proof = tree.proof(path).expect("exists");
+                assert!(proof.verify(&Hash::digest(value), tree.roothash()));
+            }
+        }
+    }
+
     #[test]
     fn serialize_errors() {
         simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
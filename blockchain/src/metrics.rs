@@ -37,4 +37,14 @@ lazy_static! {
         register_int_gauge!("stegos_blockchain_height", "Blockchain blocks count").unwrap();
     pub static ref UTXO_LEN: IntGauge =
         register_int_gauge!("stegos_blockchain_utxo", "Size of UTXO map").unwrap();
+    pub static ref OVERSIZED_BLOCKS: IntCounter = register_int_counter!(
+        "stegos_blockchain_oversized_blocks",
+        "The number of rejected oversized blocks"
+    )
+    .unwrap();
+    pub static ref OVERSIZED_TRANSACTIONS: IntCounter = register_int_counter!(
+        "stegos_blockchain_oversized_transactions",
+        "The number of rejected oversized transactions"
+    )
+    .unwrap();
 }
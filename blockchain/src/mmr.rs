@@ -0,0 +1,356 @@
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Merkle Mountain Range.
+//!
+//! An append-only Merkle tree, which is a list of perfect binary trees ("mountains")
+//! of decreasing heights. Appending or updating a leaf recalculates only the nodes
+//! on its way to the peak, so the tree can be maintained incrementally.
+//!
+//! ```text
+//!              h6
+//!          /        \
+//!        h2          h5
+//!       /  \        /  \
+//!     h0    h1    h3    h4    h7     <- five leaves, the peaks are h6 and h7
+//! ```
+//!
+//! The root commits to the number of leaves and to the peaks from left to right.
+//! Leaves, inner nodes and the root are hashed with different tags, so a leaf can't
+//! be passed off as an inner node and vice versa.
+//!
+//! The range is used for commitments which aren't part of the consensus yet,
+//! such as UTXO commitments of snapshots. Outputs of macro blocks and proofs of
+//! outputs keep using `merkle::Merkle`, because its root is hashed into block
+//! headers and switching the tree would change hashes of all blocks.
+//!
+
+use crate::merkle::MerkleError;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+
+const LEAF_TAG: &'static str = "MmrLeaf";
+const NODE_TAG: &'static str = "MmrNode";
+const ROOT_TAG: &'static str = "MmrRoot";
+
+/// Returns the hash of the leaf with the value.
+pub fn mmr_leaf_hash<T: Hashable + ?Sized>(value: &T) -> Hash {
+    let mut hasher = Hasher::new();
+    LEAF_TAG.hash(&mut hasher);
+    value.hash(&mut hasher);
+    hasher.result()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Hasher::new();
+    NODE_TAG.hash(&mut hasher);
+    left.hash(&mut hasher);
+    right.hash(&mut hasher);
+    hasher.result()
+}
+
+fn root_hash(leaves: u64, peaks: &[Hash]) -> Hash {
+    let mut hasher = Hasher::new();
+    ROOT_TAG.hash(&mut hasher);
+    leaves.hash(&mut hasher);
+    for peak in peaks {
+        peak.hash(&mut hasher);
+    }
+    hasher.result()
+}
+
+/// Merkle Mountain Range of leaf hashes, see the module documentation.
+#[derive(Clone, Debug, Default)]
+pub struct MerkleMountainRange {
+    /// Nodes by height, leaves first.
+    /// levels[h][i] is the parent of levels[h - 1][2 * i] and levels[h - 1][2 * i + 1].
+    levels: Vec<Vec<Hash>>,
+}
+
+/// Proof of inclusion of a leaf into Merkle Mountain Range.
+/// See MerkleMountainRange::proof() and MmrProof::verify().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MmrProof {
+    /// Index of the leaf.
+    pub index: u64,
+    /// The number of leaves in the tree.
+    pub leaves: u64,
+    /// Hashes of siblings, ordered from the leaf to its peak.
+    pub siblings: Vec<Hash>,
+    /// Other peaks, ordered from left to right.
+    pub peaks: Vec<Hash>,
+}
+
+impl MerkleMountainRange {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        MerkleMountainRange { levels: Vec::new() }
+    }
+
+    /// Returns the number of leaves.
+    pub fn len(&self) -> u64 {
+        self.levels
+            .first()
+            .map(|level| level.len() as u64)
+            .unwrap_or(0)
+    }
+
+    /// Returns true if the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a leaf with the value.
+    /// Returns the index of the leaf.
+    pub fn push<T: Hashable + ?Sized>(&mut self, value: &T) -> u64 {
+        let index = self.len();
+        let mut hash = mmr_leaf_hash(value);
+        let mut height = 0;
+        loop {
+            if self.levels.len() == height {
+                self.levels.push(Vec::new());
+            }
+            let level = &mut self.levels[height];
+            level.push(hash);
+            if level.len() % 2 == 1 {
+                break;
+            }
+            // The new node has a left sibling, add their parent.
+            hash = node_hash(&level[level.len() - 2], &level[level.len() - 1]);
+            height += 1;
+        }
+        index
+    }
+
+    /// Replaces the value of the leaf.
+    pub fn update<T: Hashable + ?Sized>(
+        &mut self,
+        index: u64,
+        value: &T,
+    ) -> Result<(), MerkleError> {
+        let leaves = self.len();
+        if index >= leaves {
+            return Err(MerkleError::OutOfRange(index, leaves));
+        }
+        let mut pos = index as usize;
+        self.levels[0][pos] = mmr_leaf_hash(value);
+        for height in 1..self.levels.len() {
+            let parent = pos / 2;
+            if parent >= self.levels[height].len() {
+                break; // Reached the peak.
+            }
+            let children = &self.levels[height - 1];
+            let hash = node_hash(&children[2 * parent], &children[2 * parent + 1]);
+            self.levels[height][parent] = hash;
+            pos = parent;
+        }
+        Ok(())
+    }
+
+    /// Returns the hash of the root.
+    pub fn root(&self) -> Hash {
+        let peaks: Vec<Hash> = self.peaks().map(|(_height, peak)| *peak).collect();
+        root_hash(self.len(), &peaks)
+    }
+
+    /// Returns the peaks with their heights, ordered from left to right.
+    ///
+    /// A level has a peak if it has an odd number of nodes, i.e. the corresponding bit
+    /// of the number of leaves is set.
+    fn peaks(&self) -> impl Iterator<Item = (usize, &Hash)> {
+        self.levels
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_height, level)| level.len() % 2 == 1)
+            .map(|(height, level)| (height, level.last().unwrap()))
+    }
+
+    /// Returns the proof of inclusion of the leaf.
+    pub fn proof(&self, index: u64) -> Option<MmrProof> {
+        if index >= self.len() {
+            return None;
+        }
+
+        // Go up from the leaf to its peak.
+        let mut siblings = Vec::new();
+        let mut pos = index as usize;
+        let mut height = 0;
+        while height + 1 < self.levels.len() && pos / 2 < self.levels[height + 1].len() {
+            siblings.push(self.levels[height][pos ^ 1]);
+            pos /= 2;
+            height += 1;
+        }
+
+        let peaks = self
+            .peaks()
+            .filter(|(peak_height, _peak)| *peak_height != height)
+            .map(|(_height, peak)| *peak)
+            .collect();
+        Some(MmrProof {
+            index,
+            leaves: self.len(),
+            siblings,
+            peaks,
+        })
+    }
+}
+
+impl MmrProof {
+    ///
+    /// Check that the proof links a leaf with the value to the root hash.
+    ///
+    pub fn verify<T: Hashable + ?Sized>(&self, value: &T, root: &Hash) -> bool {
+        if self.index >= self.leaves || self.peaks.len() + 1 != self.leaves.count_ones() as usize {
+            return false;
+        }
+
+        // Find the mountain of the leaf, the highest mountains go first.
+        let mut first_leaf = 0;
+        let mut mountain = 0;
+        let mut height = None;
+        for h in (0..64).rev() {
+            let size = 1u64 << h;
+            if self.leaves & size == 0 {
+                continue;
+            }
+            if self.index < first_leaf + size {
+                height = Some(h);
+                break;
+            }
+            first_leaf += size;
+            mountain += 1;
+        }
+        match height {
+            Some(height) if height == self.siblings.len() => {}
+            _ => return false,
+        }
+
+        // Go up from the leaf to its peak.
+        // Mountains start at multiples of their sizes, so the index gives the directions.
+        let mut hash = mmr_leaf_hash(value);
+        let mut pos = self.index;
+        for sibling in &self.siblings {
+            hash = if pos % 2 == 0 {
+                node_hash(&hash, sibling)
+            } else {
+                node_hash(sibling, &hash)
+            };
+            pos /= 2;
+        }
+
+        let mut peaks = self.peaks.clone();
+        peaks.insert(mountain, hash);
+        &root_hash(self.leaves, &peaks) == root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_of(values: &[u64]) -> MerkleMountainRange {
+        let mut tree = MerkleMountainRange::new();
+        for (index, value) in values.iter().enumerate() {
+            assert_eq!(tree.push(value), index as u64);
+        }
+        tree
+    }
+
+    #[test]
+    fn structure() {
+        let tree = MerkleMountainRange::new();
+        assert!(tree.is_empty());
+        assert_eq!(tree.root(), root_hash(0, &[]));
+        assert!(tree.proof(0).is_none());
+
+        let values: Vec<u64> = (0..5).collect();
+        let tree = tree_of(&values);
+        assert_eq!(tree.len(), 5);
+        let h: Vec<Hash> = values.iter().map(mmr_leaf_hash).collect();
+        let h6 = node_hash(&node_hash(&h[0], &h[1]), &node_hash(&h[2], &h[3]));
+        assert_eq!(tree.root(), root_hash(5, &[h6, h[4]]));
+
+        // Domain separation.
+        assert_ne!(mmr_leaf_hash(&0u64), Hash::digest(&0u64));
+        assert_ne!(tree_of(&[0, 1]).root(), tree_of(&[0]).root());
+    }
+
+    #[test]
+    fn proofs() {
+        let values: Vec<u64> = (0..35).collect();
+        let mut tree = MerkleMountainRange::new();
+        for value in &values {
+            tree.push(value);
+            let root = tree.root();
+            for index in 0..tree.len() {
+                let proof = tree.proof(index).expect("exists");
+                assert_eq!(proof.leaves, tree.len());
+                assert!(proof.verify(&values[index as usize], &root));
+                assert!(!proof.verify(&(values[index as usize] + 100), &root));
+            }
+            assert!(tree.proof(tree.len()).is_none());
+        }
+
+        let root = tree.root();
+        let proof = tree.proof(5).unwrap();
+        let mut other = proof.clone();
+        other.index = 4;
+        assert!(!other.verify(&values[5], &root));
+        let mut other = proof.clone();
+        other.leaves += 1;
+        assert!(!other.verify(&values[5], &root));
+        let mut other = proof.clone();
+        other.siblings.pop();
+        assert!(!other.verify(&values[5], &root));
+        let mut other = proof.clone();
+        other.peaks.swap(0, 1);
+        assert!(!other.verify(&values[5], &root));
+        // A proof for the previous state doesn't match the new root.
+        tree.push(&35u64);
+        assert!(!proof.verify(&values[5], &tree.root()));
+    }
+
+    #[test]
+    fn update() {
+        let mut values: Vec<u64> = (0..13).collect();
+        let mut tree = tree_of(&values);
+        let old_proof = tree.proof(6).unwrap();
+
+        values[6] = 106;
+        tree.update(6, &values[6]).unwrap();
+        values[12] = 112;
+        tree.update(12, &values[12]).unwrap();
+        assert_eq!(tree.root(), tree_of(&values).root());
+        assert!(!old_proof.verify(&6u64, &tree.root()));
+        for index in 0..tree.len() {
+            let proof = tree.proof(index).unwrap();
+            assert!(proof.verify(&values[index as usize], &tree.root()));
+        }
+
+        match tree.update(13, &0u64) {
+            Err(MerkleError::OutOfRange(13, 13)) => {}
+            _ => panic!(),
+        }
+    }
+}
@@ -24,6 +24,7 @@
 use crate::error::MultisignatureError;
 use crate::VALIDATORS_MAX;
 use bitvector::BitVector;
+use rayon::prelude::*;
 use std::collections::BTreeMap;
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
@@ -67,24 +68,84 @@ pub fn create_multi_signature_index<'a, I>(signatures: I) -> (pbc::Signature, Bi
 where
     I: Iterator<Item = (u32, &'a pbc::Signature)>,
 {
-    let mut multisig = pbc::G1::zero();
     let mut multisigmap = BitVector::new(VALIDATORS_MAX);
     let mut vec: Vec<_> = signatures.collect();
     vec.sort_by_key(|i| i.0);
 
-    for (bit, sig) in vec {
-        assert!(bit < VALIDATORS_MAX as u32);
-        let sig: pbc::G1 = sig.clone().into();
-        multisig += sig;
-        let ok = multisigmap.insert(bit as usize);
+    for (bit, _sig) in &vec {
+        assert!(*bit < VALIDATORS_MAX as u32);
+        let ok = multisigmap.insert(*bit as usize);
         assert!(ok);
     }
 
+    // Point additions dominate, so sum signatures in parallel.
+    let multisig = vec
+        .par_iter()
+        .map(|(_bit, sig)| pbc::G1::from((*sig).clone()))
+        .reduce(pbc::G1::zero, |a, b| a + b);
     let multisig: pbc::Signature = multisig.into();
 
     (multisig, multisigmap)
 }
 
+///
+/// Multi-signature aggregated incrementally as individual signatures arrive,
+/// so finishing it is O(1).
+///
+#[derive(Debug, Clone)]
+pub struct MultiSignatureAccumulator {
+    multisig: pbc::G1,
+    multisigmap: BitVector,
+}
+
+impl MultiSignatureAccumulator {
+    pub fn new() -> Self {
+        MultiSignatureAccumulator {
+            multisig: pbc::G1::zero(),
+            multisigmap: BitVector::new(VALIDATORS_MAX),
+        }
+    }
+
+    ///
+    /// Add a signature of the validator with index `id`.
+    /// Returns false if a signature of this validator has already been added.
+    ///
+    pub fn add(&mut self, id: u32, sig: &pbc::Signature) -> bool {
+        assert!(id < VALIDATORS_MAX as u32);
+        if !self.multisigmap.insert(id as usize) {
+            return false;
+        }
+        self.multisig += pbc::G1::from(sig.clone());
+        true
+    }
+
+    /// Returns true if a signature of the validator with index `id` has been added.
+    pub fn contains(&self, id: u32) -> bool {
+        self.multisigmap.contains(id as usize)
+    }
+
+    /// Number of aggregated signatures.
+    pub fn len(&self) -> usize {
+        self.multisigmap.len()
+    }
+
+    /// Returns true if no signatures have been added.
+    pub fn is_empty(&self) -> bool {
+        self.multisigmap.is_empty()
+    }
+
+    /// Returns the aggregated multi-signature and its bitmap.
+    pub fn finish(&self) -> (pbc::Signature, BitVector) {
+        (self.multisig.into(), self.multisigmap.clone())
+    }
+}
+
+impl Default for MultiSignatureAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 ///
 /// Check multi-signature of group, each signature is weighted by stake.
 ///
@@ -103,17 +164,12 @@ pub fn check_multi_signature(
         ));
     };
 
-    let mut multisigpkey = pbc::G2::zero();
+    let bits: Vec<usize> = multisigmap.iter().collect();
     // total count of group slots
     let mut group_total_slots = 0;
-
-    for bit in multisigmap.iter() {
-        let validator = &validators[bit];
-        let pkey: pbc::G2 = validator.0.into();
-        let slots = validator.1;
+    for bit in &bits {
+        let slots = validators[*bit].1;
         assert!(slots > 0);
-
-        multisigpkey += pkey;
         group_total_slots += slots;
     }
 
@@ -126,6 +182,10 @@ pub fn check_multi_signature(
     }
 
     // The hash must match the signature.
+    let multisigpkey = bits
+        .par_iter()
+        .map(|bit| pbc::G2::from(validators[*bit].0))
+        .reduce(pbc::G2::zero, |a, b| a + b);
     let multipkey: pbc::PublicKey = multisigpkey.into();
     if let Err(_e) = pbc::check_hash(&hash, &multisig, &multipkey) {
         return Err(MultisignatureError::InvalidSignature(*hash));
@@ -161,4 +221,37 @@ mod tests {
         let multisig = create_multi_signature_index(signatures.iter().map(|p| (p.1, &p.0)));
         assert!(check_multi_signature(hash, &multisig.0, &multisig.1, &validators, 1).is_ok())
     }
+
+    #[test]
+    fn test_multisig_accumulator() {
+        const NUM_VALIDATORS: usize = 7;
+        let mut skeys = Vec::new();
+        let mut validators = Vec::new();
+        for _i in 0..NUM_VALIDATORS {
+            let (s, p) = pbc::make_random_keys();
+            validators.push((p, 1));
+            skeys.push(s);
+        }
+
+        let ref hash = Hash::digest("test");
+        let mut signatures = BTreeMap::new();
+        let mut accumulator = MultiSignatureAccumulator::new();
+        // Signatures arrive out of order, some of them twice.
+        for i in [5, 0, 3, 5, 6, 1].iter() {
+            let sign = pbc::sign_hash(hash, &skeys[*i]);
+            let fresh = signatures.insert(validators[*i].0, sign).is_none();
+            assert_eq!(accumulator.add(*i as u32, &sign), fresh);
+        }
+        assert_eq!(accumulator.len(), signatures.len());
+        assert!(accumulator.contains(3));
+        assert!(!accumulator.contains(2));
+
+        let (multisig, multisigmap) = accumulator.finish();
+        let expected = create_multi_signature(&validators, &signatures);
+        assert_eq!(multisig, expected.0);
+        let bits: Vec<usize> = multisigmap.iter().collect();
+        assert_eq!(bits, expected.1.iter().collect::<Vec<usize>>());
+        let total_slots = NUM_VALIDATORS as i64;
+        check_multi_signature(hash, &multisig, &multisigmap, &validators, total_slots).unwrap();
+    }
 }
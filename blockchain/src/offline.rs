@@ -0,0 +1,239 @@
+//! Offline signing of payment transactions.
+
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::TransactionError;
+use crate::output::Output;
+use crate::transaction::PaymentTransaction;
+use failure::Error;
+use serde::de::{Deserialize, Deserializer, Error as SerdeError};
+use serde::ser::{Error as SerError, Serialize, Serializer};
+use stegos_crypto::curve1174::{Fr, SecretKey};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_serialization::traits::ProtoConvert;
+
+/// A payment transaction prepared by an online wallet,
+/// to be signed on a machine which holds the secret key.
+#[derive(Debug, Clone)]
+pub struct UnsignedTransaction {
+    /// Identifier of the chain, see `BlockchainConfig::chain_id`.
+    pub chain_id: Hash,
+    /// UTXOs to spend.
+    pub inputs: Vec<Output>,
+    /// UTXOs to create.
+    pub outputs: Vec<Output>,
+    /// The sum of gammas of outputs.
+    pub outputs_gamma: Fr,
+    /// Total fee.
+    pub fee: i64,
+}
+
+impl Hashable for UnsignedTransaction {
+    fn hash(&self, state: &mut Hasher) {
+        "UnsignedTransaction".hash(state);
+        self.chain_id.hash(state);
+        (self.inputs.len() as u64).hash(state);
+        for input in &self.inputs {
+            input.hash(state);
+        }
+        (self.outputs.len() as u64).hash(state);
+        for output in &self.outputs {
+            output.hash(state);
+        }
+        self.outputs_gamma.hash(state);
+        (self.fee as u64).hash(state);
+    }
+}
+
+impl UnsignedTransaction {
+    /// Sign the transaction.
+    pub fn sign(&self, skey: &SecretKey) -> Result<SignedTransaction, Error> {
+        let tx = PaymentTransaction::new(
+            &self.chain_id,
+            skey,
+            &self.inputs,
+            &self.outputs,
+            &self.outputs_gamma,
+            self.fee,
+        )?;
+        let unsigned_hash = Hash::digest(self);
+        Ok(SignedTransaction { unsigned_hash, tx })
+    }
+}
+
+/// A payment transaction signed offline, to be imported back for broadcast.
+#[derive(Debug, Clone)]
+pub struct SignedTransaction {
+    /// Hash of the transaction which was signed.
+    pub unsigned_hash: Hash,
+    /// The signed transaction.
+    pub tx: PaymentTransaction,
+}
+
+impl SignedTransaction {
+    /// Check that this transaction was signed for `unsigned` and is valid.
+    pub fn validate(&self, unsigned: &UnsignedTransaction) -> Result<(), Error> {
+        let tx_hash = Hash::digest(&self.tx);
+        let unsigned_hash = Hash::digest(unsigned);
+        if self.unsigned_hash != unsigned_hash {
+            return Err(
+                TransactionError::UnsignedHashMismatch(unsigned_hash, self.unsigned_hash).into(),
+            );
+        }
+
+        // The signed body must be exactly what was exported.
+        let same_inputs = self.tx.txins.len() == unsigned.inputs.len()
+            && self
+                .tx
+                .txins
+                .iter()
+                .zip(unsigned.inputs.iter())
+                .all(|(txin, input)| *txin == Hash::digest(input));
+        let same_outputs = self.tx.txouts.len() == unsigned.outputs.len()
+            && self
+                .tx
+                .txouts
+                .iter()
+                .zip(unsigned.outputs.iter())
+                .all(|(txout, output)| Hash::digest(txout) == Hash::digest(output));
+        if !same_inputs
+            || !same_outputs
+            || self.tx.fee != unsigned.fee
+            || self.tx.chain_id != unsigned.chain_id
+        {
+            return Err(TransactionError::SignedTransactionMismatch(tx_hash).into());
+        }
+
+        self.tx.validate(&unsigned.inputs)?;
+        Ok(())
+    }
+}
+
+/// Serialized as a hex-encoded protobuf, suitable for exporting.
+impl Serialize for UnsignedTransaction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let buf = self.into_buffer().map_err(S::Error::custom)?;
+        serializer.serialize_str(&hex::encode(&buf))
+    }
+}
+
+impl<'de> Deserialize<'de> for UnsignedTransaction {
+    fn deserialize<D>(deserializer: D) -> Result<UnsignedTransaction, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let buf = hex::decode(&s).map_err(D::Error::custom)?;
+        UnsignedTransaction::from_buffer(&buf).map_err(D::Error::custom)
+    }
+}
+
+/// Serialized as a hex-encoded protobuf, suitable for exporting.
+impl Serialize for SignedTransaction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let buf = self.into_buffer().map_err(S::Error::custom)?;
+        serializer.serialize_str(&hex::encode(&buf))
+    }
+}
+
+impl<'de> Deserialize<'de> for SignedTransaction {
+    fn deserialize<D>(deserializer: D) -> Result<SignedTransaction, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let buf = hex::decode(&s).map_err(D::Error::custom)?;
+        SignedTransaction::from_buffer(&buf).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::make_random_keys;
+
+    #[test]
+    fn offline_signing() {
+        let (skey, pkey) = make_random_keys();
+        let (_skey2, pkey2) = make_random_keys();
+
+        let (input, _gamma) = Output::new_payment(&pkey, 100).expect("keys are valid");
+        let (output1, gamma1) = Output::new_payment(&pkey2, 90).expect("keys are valid");
+        let (output2, gamma2) = Output::new_payment(&pkey, 9).expect("keys are valid");
+        let mut outputs_gamma = gamma1;
+        outputs_gamma += gamma2;
+        let unsigned = UnsignedTransaction {
+            chain_id: Hash::digest("test"),
+            inputs: vec![input],
+            outputs: vec![output1, output2],
+            outputs_gamma,
+            fee: 1,
+        };
+
+        let signed = unsigned.sign(&skey).expect("keys are valid");
+        signed.validate(&unsigned).expect("valid");
+
+        // Roundtrip.
+        let signed2 = SignedTransaction::from_buffer(&signed.into_buffer().unwrap()).unwrap();
+        assert_eq!(signed2.unsigned_hash, signed.unsigned_hash);
+        assert_eq!(Hash::digest(&signed2.tx), Hash::digest(&signed.tx));
+        let unsigned2 = UnsignedTransaction::from_buffer(&unsigned.into_buffer().unwrap()).unwrap();
+        assert_eq!(Hash::digest(&unsigned2), Hash::digest(&unsigned));
+        signed2.validate(&unsigned2).expect("valid");
+
+        // Signed for another transaction.
+        let mut other = unsigned.clone();
+        other.fee = 2;
+        let e = signed.validate(&other).unwrap_err();
+        match e.downcast::<TransactionError>().unwrap() {
+            TransactionError::UnsignedHashMismatch(expected, got) => {
+                assert_eq!(expected, Hash::digest(&other));
+                assert_eq!(got, signed.unsigned_hash);
+            }
+            e => panic!("{}", e),
+        }
+
+        // Tampered body.
+        let mut tampered = signed.clone();
+        tampered.tx.fee = 2;
+        let e = tampered.validate(&unsigned).unwrap_err();
+        match e.downcast::<TransactionError>().unwrap() {
+            TransactionError::SignedTransactionMismatch(_) => {}
+            e => panic!("{}", e),
+        }
+
+        // Moved to another chain.
+        let mut tampered = signed.clone();
+        tampered.tx.chain_id = Hash::digest("other");
+        let e = tampered.validate(&unsigned).unwrap_err();
+        match e.downcast::<TransactionError>().unwrap() {
+            TransactionError::SignedTransactionMismatch(_) => {}
+            e => panic!("{}", e),
+        }
+    }
+}
@@ -24,12 +24,15 @@
 use crate::BlockchainError;
 use failure::{Error, Fail};
 use rand::random;
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
 use std::mem::transmute;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use stegos_crypto::bulletproofs::{fee_a, make_range_proof, validate_range_proof, BulletProof};
 use stegos_crypto::curve1174::zap_bytes;
 use stegos_crypto::curve1174::{
-    aes_decrypt, aes_encrypt, ECp, EncryptedPayload, Fq, Fr, Pt, PublicKey, SecretKey, G, UNIQ,
+    aes_decrypt, aes_encrypt, sign_hash, validate_sig, ECp, EncryptedPayload, Fq, Fr, Pt,
+    PublicKey, SchnorrSig, SecretKey, G, UNIQ,
 };
 use stegos_crypto::hash::{Hash, Hashable, Hasher, HASH_SIZE};
 use stegos_crypto::pbc;
@@ -45,6 +48,15 @@ pub const PAYMENT_PAYLOAD_LEN: usize = 1024;
 /// Equals to PAYMENT_PAYLOAD_LEN - magic - delta - gamma - amount.
 pub const PAYMENT_DATA_LEN: usize = PAYMENT_PAYLOAD_LEN - 4 - 32 - 32 - 8;
 
+/// The current version of PaymentMemo encoding.
+pub const PAYMENT_MEMO_VERSION: u8 = 1;
+
+/// Field tags of PaymentMemo encoding.
+const MEMO_INVOICE_ID: u8 = 1;
+const MEMO_REFUND_ADDRESS: u8 = 2;
+const MEMO_CONTENT_TYPE: u8 = 3;
+const MEMO_COMMENT: u8 = 4;
+
 /// UTXO errors.
 #[derive(Debug, Fail)]
 pub enum OutputError {
@@ -69,6 +81,58 @@ pub enum OutputError {
     NegativeAmount(Hash, i64),
     #[fail(display = "Invalid signature on validator pkey: utxo={}", _0)]
     InvalidStakeSignature(Hash),
+    #[fail(display = "Invalid signature on reward destination: utxo={}", _0)]
+    InvalidRewardSignature(Hash),
+    #[fail(display = "Invalid memo in payload: utxo={}", _0)]
+    InvalidMemo(Hash),
+    #[fail(display = "Unsupported memo version: utxo={}, version={}", _0, _1)]
+    UnsupportedMemoVersion(Hash, u8),
+    #[fail(display = "Invalid payment channel lock: utxo={}", _0)]
+    InvalidChannelLock(Hash),
+}
+
+/// A time-lock of UTXO.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LockTime {
+    /// UTXO can't be spent in blocks below this height.
+    Height(u64),
+    /// UTXO can't be spent in blocks with an earlier timestamp.
+    /// Has a millisecond precision.
+    Timestamp(SystemTime),
+}
+
+impl LockTime {
+    /// Create a timestamp lock, truncated to milliseconds.
+    pub fn timestamp(timestamp: SystemTime) -> Self {
+        LockTime::Timestamp(UNIX_EPOCH + Duration::from_millis(lock_millis(timestamp)))
+    }
+
+    /// Checks that UTXO can be spent in a block with given height and timestamp.
+    pub fn is_unlocked(&self, height: u64, timestamp: SystemTime) -> bool {
+        match self {
+            LockTime::Height(locked_until) => height >= *locked_until,
+            LockTime::Timestamp(locked_until) => {
+                timestamp >= UNIX_EPOCH + Duration::from_millis(lock_millis(*locked_until))
+            }
+        }
+    }
+}
+
+/// Milliseconds since UNIX_EPOCH, the precision of timestamp locks.
+pub(crate) fn lock_millis(timestamp: SystemTime) -> u64 {
+    let since_the_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_the_epoch.as_secs() * 1000 + since_the_epoch.subsec_millis() as u64
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockTime::Height(height) => write!(f, "height={}", height),
+            LockTime::Timestamp(timestamp) => {
+                write!(f, "timestamp={}ms", lock_millis(*timestamp))
+            }
+        }
+    }
 }
 
 /// Payment UTXO.
@@ -93,6 +157,9 @@ pub struct PaymentOutput {
     /// Size is approx 137 Bytes =
     ///     (R-val 65B, crypto-text 72B = (amount 8B, gamma 32B, delta 32B))
     pub payload: EncryptedPayload,
+
+    /// Optional time-lock, UTXO can't be spent until it is expired.
+    pub locked_until: Option<LockTime>,
 }
 
 /// PublicPayment UTXO.
@@ -106,6 +173,66 @@ pub struct PublicPaymentOutput {
 
     /// Uncloaked amount
     pub amount: i64,
+
+    /// Optional hashed time-lock contract.
+    pub hashlock: Option<HashLock>,
+
+    /// Optional lock of a payment channel.
+    pub channel: Option<ChannelLock>,
+}
+
+/// Hashed time-lock contract (HTLC) of PublicPaymentOutput.
+///
+/// UTXO can be redeemed by the recipient by revealing the preimage of `hash`,
+/// or refunded back to the sender after `timeout`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HashLock {
+    /// SHA3-256 of the secret preimage.
+    pub hash: Hash,
+
+    /// Uncloaked public key of sender, who can refund UTXO.
+    pub refund: PublicKey,
+
+    /// Refund is allowed starting from this height or timestamp.
+    pub timeout: LockTime,
+}
+
+/// Payment channel lock of PublicPaymentOutput.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ChannelLock {
+    /// 2-of-2 funding output of a channel, `recipient` is the funder.
+    /// The hash of this output is the identifier of the channel.
+    /// UTXO can be spent only by a cooperative close, signed by both parties,
+    /// or by an unilateral close, which publishes the channel state signed by both parties.
+    Funding {
+        /// Uncloaked public key of the other party.
+        counterparty: PublicKey,
+        /// The number of blocks to wait for a dispute after an unilateral close.
+        dispute_period: u64,
+    },
+    /// The balance of a party which has closed the channel unilaterally, `recipient` is this party.
+    /// UTXO can be spent by the recipient after `dispute_period` blocks since its creation,
+    /// or by the counterparty at any time with a state newer than `sequence`.
+    Dispute {
+        /// Identifier of the channel.
+        channel: Hash,
+        /// The sequence number of the published state.
+        sequence: u64,
+        /// Uncloaked public key of the other party.
+        counterparty: PublicKey,
+        /// The number of blocks to wait for a dispute.
+        dispute_period: u64,
+    },
+}
+
+impl ChannelLock {
+    /// Returns the public key of the other party of the channel.
+    pub fn counterparty(&self) -> &PublicKey {
+        match self {
+            ChannelLock::Funding { counterparty, .. } => counterparty,
+            ChannelLock::Dispute { counterparty, .. } => counterparty,
+        }
+    }
 }
 
 /// Stake UTXO.
@@ -117,16 +244,80 @@ pub struct StakeOutput {
     /// Uncloaked network key of validator.
     pub validator: pbc::PublicKey,
 
+    /// Network key of the operator which participates in consensus on behalf of
+    /// the validator, if the stake is delegated. The validator keeps spend control.
+    pub operator: Option<pbc::PublicKey>,
+
+    /// Wallet key which receives block rewards instead of the recipient, if set.
+    pub reward: Option<RewardDestination>,
+
     /// Amount to stake.
     pub amount: i64,
 
     // some randomization to prevent hash collisions
     pub serno: i64,
 
-    /// BLS signature of recipient, validator and payload.
+    /// BLS signature of recipient, validator, operator, reward and payload.
     pub signature: pbc::Signature,
 }
 
+/// Destination of block rewards of a stake, authorized by the owner of the stake.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RewardDestination {
+    /// Wallet key which receives block rewards.
+    pub pkey: PublicKey,
+    /// Schnorr signature of the recipient of the stake.
+    pub sig: SchnorrSig,
+}
+
+impl RewardDestination {
+    /// Authorize `reward_pkey` to receive block rewards of stakes of the wallet.
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient_skey` - Wallet's secret key
+    /// * `recipient_pkey` - Wallet's public key
+    /// * `validator_pkey` - Network key of the validator
+    /// * `reward_pkey` - Wallet key which receives rewards
+    ///
+    pub fn new(
+        recipient_skey: &SecretKey,
+        recipient_pkey: &PublicKey,
+        validator_pkey: &pbc::PublicKey,
+        reward_pkey: &PublicKey,
+    ) -> Self {
+        let h = Self::message(recipient_pkey, validator_pkey, reward_pkey);
+        RewardDestination {
+            pkey: reward_pkey.clone(),
+            sig: sign_hash(&h, recipient_skey),
+        }
+    }
+
+    /// Checks that the destination is authorized by the recipient of the stake.
+    pub fn validate(
+        &self,
+        recipient_pkey: &PublicKey,
+        validator_pkey: &pbc::PublicKey,
+    ) -> Result<(), CryptoError> {
+        self.pkey.decompress()?;
+        let h = Self::message(recipient_pkey, validator_pkey, &self.pkey);
+        validate_sig(&h, &self.sig, recipient_pkey)
+    }
+
+    fn message(
+        recipient_pkey: &PublicKey,
+        validator_pkey: &pbc::PublicKey,
+        reward_pkey: &PublicKey,
+    ) -> Hash {
+        let mut hasher = Hasher::new();
+        "Reward".hash(&mut hasher);
+        recipient_pkey.hash(&mut hasher);
+        validator_pkey.hash(&mut hasher);
+        reward_pkey.hash(&mut hasher);
+        hasher.result()
+    }
+}
+
 /// Blockchain UTXO.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Output {
@@ -170,6 +361,92 @@ pub enum PaymentPayloadData {
     Comment(String),
     /// A hash of secret content.
     ContentHash(Hash),
+    /// Typed metadata.
+    Memo(PaymentMemo),
+}
+
+/// Typed metadata of a payment.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Default)]
+pub struct PaymentMemo {
+    /// Identifier of the invoice paid by this output.
+    #[serde(default)]
+    pub invoice_id: Option<String>,
+    /// Wallet key to send refunds to.
+    #[serde(default)]
+    pub refund_address: Option<PublicKey>,
+    /// MIME type of the comment.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub comment: String,
+}
+
+impl PaymentMemo {
+    /// Serialize as a version byte followed by (tag, u16 length, value) fields.
+    fn encode(&self) -> Vec<u8> {
+        fn field(buf: &mut Vec<u8>, tag: u8, value: &[u8]) {
+            buf.push(tag);
+            buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            buf.extend_from_slice(value);
+        }
+
+        let mut buf = vec![PAYMENT_MEMO_VERSION];
+        if let Some(invoice_id) = &self.invoice_id {
+            field(&mut buf, MEMO_INVOICE_ID, invoice_id.as_bytes());
+        }
+        if let Some(refund_address) = &self.refund_address {
+            field(&mut buf, MEMO_REFUND_ADDRESS, &refund_address.to_bytes());
+        }
+        if let Some(content_type) = &self.content_type {
+            field(&mut buf, MEMO_CONTENT_TYPE, content_type.as_bytes());
+        }
+        if !self.comment.is_empty() {
+            field(&mut buf, MEMO_COMMENT, self.comment.as_bytes());
+        }
+        buf
+    }
+
+    /// Deserialize from the data field of payload.
+    /// Returns the memo and the number of consumed bytes.
+    fn decode(output_hash: Hash, data: &[u8]) -> Result<(Self, usize), BlockchainError> {
+        if data.is_empty() {
+            return Err(OutputError::InvalidMemo(output_hash).into());
+        }
+        let version = data[0];
+        if version != PAYMENT_MEMO_VERSION {
+            return Err(OutputError::UnsupportedMemoVersion(output_hash, version).into());
+        }
+
+        let mut memo = PaymentMemo::default();
+        let mut pos: usize = 1;
+        // Fields are terminated by zero padding.
+        while pos < data.len() && data[pos] != 0 {
+            let tag = data[pos];
+            if pos + 3 > data.len() {
+                return Err(OutputError::InvalidMemo(output_hash).into());
+            }
+            let len = u16::from_le_bytes([data[pos + 1], data[pos + 2]]) as usize;
+            pos += 3;
+            if pos + len > data.len() {
+                return Err(OutputError::InvalidMemo(output_hash).into());
+            }
+            let value = &data[pos..pos + len];
+            pos += len;
+            match tag {
+                MEMO_INVOICE_ID => memo.invoice_id = Some(std::str::from_utf8(value)?.to_string()),
+                MEMO_REFUND_ADDRESS => {
+                    memo.refund_address = Some(PublicKey::try_from_bytes(value)?)
+                }
+                MEMO_CONTENT_TYPE => {
+                    memo.content_type = Some(std::str::from_utf8(value)?.to_string())
+                }
+                MEMO_COMMENT => memo.comment = std::str::from_utf8(value)?.to_string(),
+                // Fields added by newer revisions of this version are skipped.
+                _ => {}
+            }
+        }
+        Ok((memo, pos))
+    }
 }
 
 impl PaymentPayloadData {
@@ -177,6 +454,24 @@ impl PaymentPayloadData {
         match self {
             PaymentPayloadData::Comment(_) => 0,
             PaymentPayloadData::ContentHash(_) => 1,
+            PaymentPayloadData::Memo(_) => 2,
+        }
+    }
+
+    /// Free-form text of the payload, if any.
+    pub fn comment(&self) -> &str {
+        match self {
+            PaymentPayloadData::Comment(comment) => comment,
+            PaymentPayloadData::ContentHash(_) => "",
+            PaymentPayloadData::Memo(memo) => &memo.comment,
+        }
+    }
+
+    /// Invoice paid by this output, if any.
+    pub fn invoice_id(&self) -> Option<&str> {
+        match self {
+            PaymentPayloadData::Memo(memo) => memo.invoice_id.as_ref().map(String::as_str),
+            _ => None,
         }
     }
 
@@ -191,6 +486,12 @@ impl PaymentPayloadData {
                 }
             }
             PaymentPayloadData::ContentHash(_hash) => {}
+            PaymentPayloadData::Memo(memo) => {
+                let data_len = memo.encode().len();
+                if data_len > PAYMENT_DATA_LEN - 2 {
+                    return Err(OutputError::DataIsTooLong(PAYMENT_DATA_LEN - 2, data_len).into());
+                }
+            }
         }
         Ok(())
     }
@@ -246,6 +547,12 @@ impl PaymentPayload {
                 payload[pos..pos + data_bytes.len()].copy_from_slice(data_bytes);
                 pos += data_bytes.len();
             }
+            PaymentPayloadData::Memo(memo) => {
+                let data_bytes = memo.encode();
+                assert!(data_bytes.len() <= PAYMENT_DATA_LEN - 2);
+                payload[pos..pos + data_bytes.len()].copy_from_slice(&data_bytes);
+                pos += data_bytes.len();
+            }
         }
 
         // The rest is zeros.
@@ -324,6 +631,11 @@ impl PaymentPayload {
                 pos += HASH_SIZE;
                 PaymentPayloadData::ContentHash(hash)
             }
+            2 => {
+                let (memo, len) = PaymentMemo::decode(output_hash, &payload[pos..])?;
+                pos += len;
+                PaymentPayloadData::Memo(memo)
+            }
             code @ _ => return Err(OutputError::UnsupportedDataType(output_hash, code).into()),
         };
 
@@ -375,6 +687,7 @@ impl PaymentOutput {
             cloaking_hint: hint.compress(),
             proof,
             payload,
+            locked_until: None,
         };
 
         Ok((output, gamma))
@@ -386,6 +699,17 @@ impl PaymentOutput {
         Self::with_payload(recipient_pkey, amount, data)
     }
 
+    /// Create a new PaymentOutput which can't be spent until `locked_until`.
+    pub fn new_locked(
+        recipient_pkey: &PublicKey,
+        amount: i64,
+        locked_until: LockTime,
+    ) -> Result<(Self, Fr), BlockchainError> {
+        let (mut output, gamma) = Self::new(recipient_pkey, amount)?;
+        output.locked_until = Some(locked_until);
+        Ok((output, gamma))
+    }
+
     /// Decrypt payload.
     pub fn decrypt_payload(&self, skey: &SecretKey) -> Result<PaymentPayload, BlockchainError> {
         let output_hash = Hash::digest(&self);
@@ -441,9 +765,76 @@ impl PublicPaymentOutput {
             recipient: recipient_pkey.clone(),
             serno,
             amount,
+            hashlock: None,
+            channel: None,
         }
     }
 
+    /// Create a new hash-locked PublicPaymentOutput.
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient_pkey` - the key which can redeem UTXO using the preimage of `hash`.
+    /// * `refund_pkey` - the key which can refund UTXO after `timeout`.
+    ///
+    pub fn new_hashlocked(
+        recipient_pkey: &PublicKey,
+        refund_pkey: &PublicKey,
+        amount: i64,
+        hash: Hash,
+        timeout: LockTime,
+    ) -> Self {
+        let mut output = Self::new(recipient_pkey, amount);
+        output.hashlock = Some(HashLock {
+            hash,
+            refund: refund_pkey.clone(),
+            timeout,
+        });
+        output
+    }
+
+    /// Create a new funding output of a payment channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `funder_pkey` - the key of the party which funds the channel.
+    /// * `counterparty_pkey` - the key of the other party.
+    /// * `dispute_period` - the number of blocks to wait for a dispute after an unilateral close.
+    ///
+    pub fn new_channel_funding(
+        funder_pkey: &PublicKey,
+        counterparty_pkey: &PublicKey,
+        amount: i64,
+        dispute_period: u64,
+    ) -> Self {
+        let mut output = Self::new(funder_pkey, amount);
+        output.channel = Some(ChannelLock::Funding {
+            counterparty: counterparty_pkey.clone(),
+            dispute_period,
+        });
+        output
+    }
+
+    /// Create a new output which holds the balance of the party which closed
+    /// a payment channel unilaterally until the end of the dispute period.
+    pub fn new_channel_dispute(
+        closer_pkey: &PublicKey,
+        counterparty_pkey: &PublicKey,
+        amount: i64,
+        channel: Hash,
+        sequence: u64,
+        dispute_period: u64,
+    ) -> Self {
+        let mut output = Self::new(closer_pkey, amount);
+        output.channel = Some(ChannelLock::Dispute {
+            channel,
+            sequence,
+            counterparty: counterparty_pkey.clone(),
+            dispute_period,
+        });
+        output
+    }
+
     /// Validates UTXO structure and keying.
     pub fn validate(&self) -> Result<(), BlockchainError> {
         self.recipient.decompress()?;
@@ -451,6 +842,16 @@ impl PublicPaymentOutput {
             let h = Hash::digest(self);
             return Err(OutputError::InvalidStake(h).into());
         }
+        if let Some(hashlock) = &self.hashlock {
+            hashlock.refund.decompress()?;
+        }
+        if let Some(channel) = &self.channel {
+            if self.hashlock.is_some() || channel.counterparty() == &self.recipient {
+                let h = Hash::digest(self);
+                return Err(OutputError::InvalidChannelLock(h).into());
+            }
+            channel.counterparty().decompress()?;
+        }
         Ok(())
     }
 
@@ -460,8 +861,16 @@ impl PublicPaymentOutput {
     }
 
     /// Checks that UTXO belongs to given key.
+    /// Hash-locked UTXO also belongs to the sender, who can refund it.
+    /// Channel UTXO also belongs to the counterparty.
     pub fn is_my_utxo(&self, pkey: &PublicKey) -> bool {
-        &self.recipient == pkey
+        if let Some(channel) = &self.channel {
+            return &self.recipient == pkey || channel.counterparty() == pkey;
+        }
+        match &self.hashlock {
+            Some(hashlock) => &self.recipient == pkey || &hashlock.refund == pkey,
+            None => &self.recipient == pkey,
+        }
     }
 }
 
@@ -472,6 +881,34 @@ impl StakeOutput {
         validator_skey: &pbc::SecretKey,
         validator_pkey: &pbc::PublicKey,
         amount: i64,
+    ) -> Result<Self, Error> {
+        Self::with_operator(recipient_pkey, validator_skey, validator_pkey, None, amount)
+    }
+
+    /// Create a new StakeOutput, delegated to the operator.
+    pub fn new_delegated(
+        recipient_pkey: &PublicKey,
+        validator_skey: &pbc::SecretKey,
+        validator_pkey: &pbc::PublicKey,
+        operator_pkey: &pbc::PublicKey,
+        amount: i64,
+    ) -> Result<Self, Error> {
+        let operator = Some(operator_pkey.clone());
+        Self::with_operator(
+            recipient_pkey,
+            validator_skey,
+            validator_pkey,
+            operator,
+            amount,
+        )
+    }
+
+    fn with_operator(
+        recipient_pkey: &PublicKey,
+        validator_skey: &pbc::SecretKey,
+        validator_pkey: &pbc::PublicKey,
+        operator: Option<pbc::PublicKey>,
+        amount: i64,
     ) -> Result<Self, Error> {
         assert!(amount > 0);
 
@@ -480,6 +917,8 @@ impl StakeOutput {
         let mut output = StakeOutput {
             recipient: recipient_pkey.clone(),
             validator: validator_pkey.clone(),
+            operator,
+            reward: None,
             amount,
             serno,
             signature: pbc::Signature::zero(),
@@ -504,14 +943,50 @@ impl StakeOutput {
         if let Err(_e) = pbc::check_hash(&output_hash, &self.signature, &self.validator) {
             return Err(OutputError::InvalidStakeSignature(output_hash).into());
         }
+
+        // Validate Schnorr signature of recipient_pkey on the reward destination.
+        if let Some(reward) = &self.reward {
+            if let Err(_e) = reward.validate(&self.recipient, &self.validator) {
+                return Err(OutputError::InvalidRewardSignature(output_hash).into());
+            }
+        }
         Ok(())
     }
 
+    /// Changes the destination of block rewards and signs the stake again.
+    pub fn set_reward(
+        &mut self,
+        reward: Option<RewardDestination>,
+        validator_skey: &pbc::SecretKey,
+    ) {
+        self.reward = reward;
+        let h = Hash::digest(self);
+        self.signature = pbc::sign_hash(&h, validator_skey);
+    }
+
+    /// Returns the wallet key which receives block rewards of this stake.
+    pub fn reward_key(&self) -> PublicKey {
+        match &self.reward {
+            Some(reward) => reward.pkey,
+            None => self.recipient,
+        }
+    }
+
     /// Returns Pedersen commitment.
     pub fn pedersen_commitment(&self) -> Result<ECp, CryptoError> {
         Ok(fee_a(self.amount))
     }
 
+    /// Returns the network key which participates in consensus with this stake.
+    pub fn consensus_key(&self) -> pbc::PublicKey {
+        self.operator.unwrap_or(self.validator)
+    }
+
+    /// Returns true if the stake is delegated to an operator.
+    pub fn is_delegated(&self) -> bool {
+        self.operator.is_some()
+    }
+
     /// Checks that UTXO belongs to given key.
     pub fn is_my_utxo(&self, pkey: &PublicKey) -> bool {
         &self.recipient == pkey
@@ -564,6 +1039,33 @@ impl Output {
         }
     }
 
+    /// Returns the time-lock of UTXO, if any.
+    pub fn locked_until(&self) -> Option<LockTime> {
+        match self {
+            Output::PaymentOutput(o) => o.locked_until,
+            Output::PublicPaymentOutput(_o) => None,
+            Output::StakeOutput(_o) => None,
+        }
+    }
+
+    /// Returns the hashed time-lock contract of UTXO, if any.
+    pub fn hashlock(&self) -> Option<&HashLock> {
+        match self {
+            Output::PaymentOutput(_o) => None,
+            Output::PublicPaymentOutput(o) => o.hashlock.as_ref(),
+            Output::StakeOutput(_o) => None,
+        }
+    }
+
+    /// Returns the payment channel lock of UTXO, if any.
+    pub fn channel_lock(&self) -> Option<&ChannelLock> {
+        match self {
+            Output::PaymentOutput(_o) => None,
+            Output::PublicPaymentOutput(o) => o.channel.as_ref(),
+            Output::StakeOutput(_o) => None,
+        }
+    }
+
     /// Checks that UTXO belongs to given key.
     pub fn is_my_utxo(&self, skey: &SecretKey, pkey: &PublicKey) -> bool {
         match self {
@@ -599,6 +1101,26 @@ impl Hashable for PaymentOutput {
         self.cloaking_hint.hash(state);
         self.proof.hash(state);
         self.payload.hash(state);
+        if let Some(locked_until) = &self.locked_until {
+            "LockTime".hash(state);
+            locked_until.hash(state);
+        }
+    }
+}
+
+impl Hashable for LockTime {
+    fn hash(&self, state: &mut Hasher) {
+        match self {
+            LockTime::Height(height) => {
+                "Height".hash(state);
+                height.hash(state);
+            }
+            LockTime::Timestamp(timestamp) => {
+                // Only milliseconds are serialized.
+                "Timestamp".hash(state);
+                lock_millis(*timestamp).hash(state);
+            }
+        }
     }
 }
 
@@ -608,6 +1130,42 @@ impl Hashable for PublicPaymentOutput {
         self.recipient.hash(state);
         self.serno.hash(state);
         self.amount.hash(state);
+        if let Some(hashlock) = &self.hashlock {
+            "HashLock".hash(state);
+            hashlock.hash.hash(state);
+            hashlock.refund.hash(state);
+            hashlock.timeout.hash(state);
+        }
+        if let Some(channel) = &self.channel {
+            channel.hash(state);
+        }
+    }
+}
+
+impl Hashable for ChannelLock {
+    fn hash(&self, state: &mut Hasher) {
+        match self {
+            ChannelLock::Funding {
+                counterparty,
+                dispute_period,
+            } => {
+                "ChannelFunding".hash(state);
+                counterparty.hash(state);
+                dispute_period.hash(state);
+            }
+            ChannelLock::Dispute {
+                channel,
+                sequence,
+                counterparty,
+                dispute_period,
+            } => {
+                "ChannelDispute".hash(state);
+                channel.hash(state);
+                sequence.hash(state);
+                counterparty.hash(state);
+                dispute_period.hash(state);
+            }
+        }
     }
 }
 
@@ -616,6 +1174,14 @@ impl Hashable for StakeOutput {
         "Stake".hash(state);
         self.recipient.hash(state);
         self.validator.hash(state);
+        if let Some(operator) = &self.operator {
+            "Operator".hash(state);
+            operator.hash(state);
+        }
+        if let Some(reward) = &self.reward {
+            "Reward".hash(state);
+            reward.pkey.hash(state);
+        }
         self.amount.hash(state);
         self.serno.hash(state);
     }
@@ -720,6 +1286,76 @@ pub mod tests {
             _ => unreachable!(),
         }
 
+        // With memo.
+        let gamma: Fr = Fr::random();
+        let delta: Fr = Fr::random();
+        let amount: i64 = 100500;
+        let (_refund_skey, refund_pkey) = make_random_keys();
+        let memo = PaymentMemo {
+            invoice_id: Some("INV-100500".to_string()),
+            refund_address: Some(refund_pkey),
+            content_type: Some("text/plain".to_string()),
+            comment: "Thanks".to_string(),
+        };
+        let data = PaymentPayloadData::Memo(memo);
+        assert_eq!(data.invoice_id(), Some("INV-100500"));
+        assert_eq!(data.comment(), "Thanks");
+        let payload = PaymentPayload {
+            delta,
+            gamma,
+            amount,
+            data,
+        };
+        rt(&payload, &skey, &pkey);
+
+        // With empty memo.
+        let gamma: Fr = Fr::random();
+        let delta: Fr = Fr::random();
+        let data = PaymentPayloadData::Memo(PaymentMemo::default());
+        let payload = PaymentPayload {
+            delta,
+            gamma,
+            amount,
+            data,
+        };
+        rt(&payload, &skey, &pkey);
+
+        // Memo overflow.
+        let memo = PaymentMemo {
+            comment: random_string(PAYMENT_DATA_LEN),
+            ..Default::default()
+        };
+        let data = PaymentPayloadData::Memo(memo);
+        match data
+            .validate()
+            .unwrap_err()
+            .downcast::<OutputError>()
+            .unwrap()
+        {
+            OutputError::DataIsTooLong(max, _got) => assert_eq!(max, PAYMENT_DATA_LEN - 2),
+            _ => unreachable!(),
+        }
+
+        // Unknown fields are skipped, unknown versions are rejected.
+        let mut data = vec![PAYMENT_MEMO_VERSION, 100, 2, 0, 1, 2];
+        data.extend_from_slice(&[MEMO_COMMENT, 1, 0, b'x', 0, 0]);
+        let (memo, len) = PaymentMemo::decode(output_hash, &data).expect("valid memo");
+        assert_eq!(memo.comment, "x");
+        assert_eq!(len, data.len() - 2);
+        match PaymentMemo::decode(output_hash, &[PAYMENT_MEMO_VERSION + 1]) {
+            Err(BlockchainError::OutputError(OutputError::UnsupportedMemoVersion(_, version))) => {
+                assert_eq!(version, PAYMENT_MEMO_VERSION + 1)
+            }
+            _ => unreachable!(),
+        }
+        match PaymentMemo::decode(
+            output_hash,
+            &[PAYMENT_MEMO_VERSION, MEMO_COMMENT, 10, 0, b'x'],
+        ) {
+            Err(BlockchainError::OutputError(OutputError::InvalidMemo(_))) => {}
+            _ => unreachable!(),
+        }
+
         // With content hash.
         let gamma: Fr = Fr::random();
         let delta: Fr = Fr::random();
@@ -817,6 +1453,26 @@ pub mod tests {
     ///
     /// Tests PaymentOutput encryption/decryption.
     ///
+    #[test]
+    fn lock_time() {
+        let locked_until = LockTime::Height(10);
+        let now = SystemTime::now();
+        assert!(!locked_until.is_unlocked(9, now));
+        assert!(locked_until.is_unlocked(10, now));
+        assert!(locked_until.is_unlocked(11, now));
+
+        let locked_until = LockTime::timestamp(now + Duration::from_secs(60));
+        assert!(!locked_until.is_unlocked(100, now));
+        assert!(locked_until.is_unlocked(0, now + Duration::from_secs(60)));
+
+        // The lock changes the hash of UTXO.
+        let (_skey, pkey) = make_random_keys();
+        let (mut output, _gamma) = PaymentOutput::new(&pkey, 100).unwrap();
+        let hash = Hash::digest(&output);
+        output.locked_until = Some(locked_until);
+        assert_ne!(Hash::digest(&output), hash);
+    }
+
     #[test]
     pub fn payment_encrypt_decrypt() {
         let (skey1, _pkey1) = make_random_keys();
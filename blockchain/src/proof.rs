@@ -0,0 +1,150 @@
+//! Proofs of existence of historical outputs.
+
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::block::{MacroBlockHeader, MicroBlock};
+use crate::error::OutputProofError;
+use crate::merkle::MerkleProof;
+use crate::output::Output;
+use serde::de::{Deserialize, Deserializer, Error as SerdeError};
+use serde::ser::{Error as SerError, Serialize, Serializer};
+use stegos_crypto::hash::Hash;
+use stegos_serialization::traits::ProtoConvert;
+
+/// Inclusion of an output into the epoch.
+#[derive(Debug, Clone)]
+pub enum OutputInclusion {
+    /// The output was created by the macro block itself.
+    MacroBlock(MerkleProof),
+    /// The output was created by a micro block.
+    MicroBlock {
+        /// Micro blocks from the block with the output up to the end of epoch.
+        blocks: Vec<MicroBlock>,
+        /// Transaction number in the first block.
+        tx_id: u32,
+        /// Output number in the transaction.
+        txout_id: u32,
+    },
+}
+
+/// Self-contained proof that an output was included into the blockchain.
+/// Remains valid after the output has been spent and pruned from UTXO.
+#[derive(Debug, Clone)]
+pub struct OutputProof {
+    /// The output.
+    pub output: Output,
+    /// Inclusion of the output into the epoch.
+    pub inclusion: OutputInclusion,
+    /// Header of the macro block which finalized the epoch.
+    pub checkpoint: MacroBlockHeader,
+}
+
+impl OutputProof {
+    /// Returns the hash of the macro block this proof is anchored to.
+    pub fn checkpoint_hash(&self) -> Hash {
+        Hash::digest(&self.checkpoint)
+    }
+
+    /// Validate the proof against the hash of a trusted macro block.
+    pub fn validate(&self, checkpoint_hash: &Hash) -> Result<(), OutputProofError> {
+        let output_hash = Hash::digest(&self.output);
+        let actual_checkpoint_hash = self.checkpoint_hash();
+        if &actual_checkpoint_hash != checkpoint_hash {
+            return Err(OutputProofError::InvalidCheckpoint(
+                *checkpoint_hash,
+                actual_checkpoint_hash,
+            ));
+        }
+
+        match &self.inclusion {
+            OutputInclusion::MacroBlock(merkle_proof) => {
+                if !merkle_proof.verify(&output_hash, &self.checkpoint.outputs_range_hash) {
+                    return Err(OutputProofError::InvalidMerkleProof(output_hash));
+                }
+            }
+            OutputInclusion::MicroBlock {
+                blocks,
+                tx_id,
+                txout_id,
+            } => {
+                let first = blocks
+                    .first()
+                    .ok_or(OutputProofError::NoBlocks(output_hash))?;
+                let found = first
+                    .transactions
+                    .get(*tx_id as usize)
+                    .and_then(|tx| tx.txouts().get(*txout_id as usize))
+                    .map(|output| Hash::digest(output) == output_hash)
+                    .unwrap_or(false);
+                if !found {
+                    return Err(OutputProofError::MissingOutput(
+                        first.base.height,
+                        output_hash,
+                    ));
+                }
+
+                // Check that blocks form a chain up to the checkpoint.
+                let mut previous = Hash::digest(first);
+                for block in blocks.iter().skip(1) {
+                    if block.base.previous != previous {
+                        return Err(OutputProofError::BrokenChain(
+                            block.base.height,
+                            previous,
+                            block.base.previous,
+                        ));
+                    }
+                    previous = Hash::digest(block);
+                }
+                if self.checkpoint.base.previous != previous {
+                    return Err(OutputProofError::BrokenChain(
+                        self.checkpoint.base.height,
+                        previous,
+                        self.checkpoint.base.previous,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serialized as a hex-encoded protobuf, suitable for exporting.
+impl Serialize for OutputProof {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let buf = self.into_buffer().map_err(S::Error::custom)?;
+        serializer.serialize_str(&hex::encode(&buf))
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputProof {
+    fn deserialize<D>(deserializer: D) -> Result<OutputProof, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let buf = hex::decode(&s).map_err(D::Error::custom)?;
+        OutputProof::from_buffer(&buf).map_err(D::Error::custom)
+    }
+}
@@ -25,6 +25,7 @@ use stegos_serialization::traits::*;
 
 use bitvector::BitVector;
 
+use crate::output::lock_millis;
 use crate::view_changes::*;
 use crate::*;
 use stegos_crypto::bulletproofs::BulletProof;
@@ -45,6 +46,33 @@ use stegos_crypto::protos::*;
 use stegos_crypto::CryptoError;
 include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
 
+impl ProtoConvert for LockTime {
+    type Proto = blockchain::LockTime;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::LockTime::new();
+        match self {
+            LockTime::Height(height) => proto.set_height(*height),
+            LockTime::Timestamp(timestamp) => proto.set_timestamp(lock_millis(*timestamp)),
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        match proto.lock_time {
+            Some(blockchain::LockTime_oneof_lock_time::height(height)) => {
+                Ok(LockTime::Height(height))
+            }
+            Some(blockchain::LockTime_oneof_lock_time::timestamp(timestamp)) => {
+                let timestamp = std::time::UNIX_EPOCH + std::time::Duration::from_millis(timestamp);
+                Ok(LockTime::Timestamp(timestamp))
+            }
+            None => Err(
+                ProtoError::MissingField("lock_time".to_string(), "lock_time".to_string()).into(),
+            ),
+        }
+    }
+}
+
 impl ProtoConvert for PaymentOutput {
     type Proto = blockchain::PaymentOutput;
     fn into_proto(&self) -> Self::Proto {
@@ -53,6 +81,9 @@ impl ProtoConvert for PaymentOutput {
         proto.set_cloaking_hint(self.cloaking_hint.into_proto());
         proto.set_proof(self.proof.into_proto());
         proto.set_payload(self.payload.into_proto());
+        if let Some(locked_until) = &self.locked_until {
+            proto.set_locked_until(locked_until.into_proto());
+        }
         proto
     }
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
@@ -60,15 +91,101 @@ impl ProtoConvert for PaymentOutput {
         let cloaking_hint = Pt::from_proto(proto.get_cloaking_hint())?;
         let proof = BulletProof::from_proto(proto.get_proof())?;
         let payload = EncryptedPayload::from_proto(proto.get_payload())?;
+        let locked_until = if proto.has_locked_until() {
+            Some(LockTime::from_proto(proto.get_locked_until())?)
+        } else {
+            None
+        };
         Ok(PaymentOutput {
             recipient,
             cloaking_hint,
             proof,
             payload,
+            locked_until,
+        })
+    }
+}
+
+impl ProtoConvert for HashLock {
+    type Proto = blockchain::HashLock;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::HashLock::new();
+        proto.set_hash(self.hash.into_proto());
+        proto.set_refund(self.refund.into_proto());
+        proto.set_timeout(self.timeout.into_proto());
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let hash = Hash::from_proto(proto.get_hash())?;
+        let refund = PublicKey::from_proto(proto.get_refund())?;
+        let timeout = LockTime::from_proto(proto.get_timeout())?;
+        Ok(HashLock {
+            hash,
+            refund,
+            timeout,
         })
     }
 }
 
+impl ProtoConvert for ChannelLock {
+    type Proto = blockchain::ChannelLock;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::ChannelLock::new();
+        match self {
+            ChannelLock::Funding {
+                counterparty,
+                dispute_period,
+            } => {
+                let mut funding = blockchain::ChannelFunding::new();
+                funding.set_counterparty(counterparty.into_proto());
+                funding.set_dispute_period(*dispute_period);
+                proto.set_funding(funding);
+            }
+            ChannelLock::Dispute {
+                channel,
+                sequence,
+                counterparty,
+                dispute_period,
+            } => {
+                let mut dispute = blockchain::ChannelDispute::new();
+                dispute.set_channel(channel.into_proto());
+                dispute.set_sequence(*sequence);
+                dispute.set_counterparty(counterparty.into_proto());
+                dispute.set_dispute_period(*dispute_period);
+                proto.set_dispute(dispute);
+            }
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        match proto.lock {
+            Some(blockchain::ChannelLock_oneof_lock::funding(ref funding)) => {
+                let counterparty = PublicKey::from_proto(funding.get_counterparty())?;
+                let dispute_period = funding.get_dispute_period();
+                Ok(ChannelLock::Funding {
+                    counterparty,
+                    dispute_period,
+                })
+            }
+            Some(blockchain::ChannelLock_oneof_lock::dispute(ref dispute)) => {
+                let channel = Hash::from_proto(dispute.get_channel())?;
+                let sequence = dispute.get_sequence();
+                let counterparty = PublicKey::from_proto(dispute.get_counterparty())?;
+                let dispute_period = dispute.get_dispute_period();
+                Ok(ChannelLock::Dispute {
+                    channel,
+                    sequence,
+                    counterparty,
+                    dispute_period,
+                })
+            }
+            None => Err(ProtoError::MissingField("lock".to_string(), "lock".to_string()).into()),
+        }
+    }
+}
+
 impl ProtoConvert for PublicPaymentOutput {
     type Proto = blockchain::PublicPaymentOutput;
     fn into_proto(&self) -> Self::Proto {
@@ -76,6 +193,12 @@ impl ProtoConvert for PublicPaymentOutput {
         proto.set_recipient(self.recipient.into_proto());
         proto.set_amount(self.amount);
         proto.set_serno(self.serno);
+        if let Some(hashlock) = &self.hashlock {
+            proto.set_hashlock(hashlock.into_proto());
+        }
+        if let Some(channel) = &self.channel {
+            proto.set_channel(channel.into_proto());
+        }
         proto
     }
 
@@ -83,10 +206,22 @@ impl ProtoConvert for PublicPaymentOutput {
         let recipient = PublicKey::from_proto(proto.get_recipient())?;
         let amount = proto.get_amount();
         let serno = proto.get_serno();
+        let hashlock = if proto.has_hashlock() {
+            Some(HashLock::from_proto(proto.get_hashlock())?)
+        } else {
+            None
+        };
+        let channel = if proto.has_channel() {
+            Some(ChannelLock::from_proto(proto.get_channel())?)
+        } else {
+            None
+        };
         Ok(PublicPaymentOutput {
             recipient,
             amount,
             serno,
+            hashlock,
+            channel,
         })
     }
 }
@@ -97,6 +232,12 @@ impl ProtoConvert for StakeOutput {
         let mut proto = blockchain::StakeOutput::new();
         proto.set_recipient(self.recipient.into_proto());
         proto.set_validator(self.validator.into_proto());
+        if let Some(operator) = &self.operator {
+            proto.set_operator(operator.into_proto());
+        }
+        if let Some(reward) = &self.reward {
+            proto.set_reward(reward.into_proto());
+        }
         proto.set_amount(self.amount);
         proto.set_serno(self.serno);
         proto.set_signature(self.signature.into_proto());
@@ -106,12 +247,24 @@ impl ProtoConvert for StakeOutput {
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
         let recipient = PublicKey::from_proto(proto.get_recipient())?;
         let validator = pbc::PublicKey::from_proto(proto.get_validator())?;
+        let operator = if proto.has_operator() {
+            Some(pbc::PublicKey::from_proto(proto.get_operator())?)
+        } else {
+            None
+        };
+        let reward = if proto.has_reward() {
+            Some(RewardDestination::from_proto(proto.get_reward())?)
+        } else {
+            None
+        };
         let amount = proto.get_amount();
         let serno = proto.get_serno();
         let signature = pbc::Signature::from_proto(proto.get_signature())?;
         Ok(StakeOutput {
             recipient,
             validator,
+            operator,
+            reward,
             amount,
             serno,
             signature,
@@ -119,6 +272,22 @@ impl ProtoConvert for StakeOutput {
     }
 }
 
+impl ProtoConvert for RewardDestination {
+    type Proto = blockchain::RewardDestination;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::RewardDestination::new();
+        proto.set_pkey(self.pkey.into_proto());
+        proto.set_sig(self.sig.into_proto());
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let pkey = PublicKey::from_proto(proto.get_pkey())?;
+        let sig = SchnorrSig::from_proto(proto.get_sig())?;
+        Ok(RewardDestination { pkey, sig })
+    }
+}
+
 impl ProtoConvert for Output {
     type Proto = blockchain::Output;
     fn into_proto(&self) -> Self::Proto {
@@ -184,11 +353,110 @@ impl ProtoConvert for CoinbaseTransaction {
     }
 }
 
+impl ProtoConvert for ChannelState {
+    type Proto = blockchain::ChannelState;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::ChannelState::new();
+        proto.set_channel(self.channel.into_proto());
+        proto.set_funder(self.funder.into_proto());
+        proto.set_counterparty(self.counterparty.into_proto());
+        proto.set_sequence(self.sequence);
+        proto.set_funder_balance(self.funder_balance);
+        proto.set_counterparty_balance(self.counterparty_balance);
+        proto.set_funder_sig(self.funder_sig.into_proto());
+        proto.set_counterparty_sig(self.counterparty_sig.into_proto());
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let channel = Hash::from_proto(proto.get_channel())?;
+        let funder = PublicKey::from_proto(proto.get_funder())?;
+        let counterparty = PublicKey::from_proto(proto.get_counterparty())?;
+        let sequence = proto.get_sequence();
+        let funder_balance = proto.get_funder_balance();
+        let counterparty_balance = proto.get_counterparty_balance();
+        let funder_sig = SchnorrSig::from_proto(proto.get_funder_sig())?;
+        let counterparty_sig = SchnorrSig::from_proto(proto.get_counterparty_sig())?;
+        Ok(ChannelState {
+            channel,
+            funder,
+            counterparty,
+            sequence,
+            funder_balance,
+            counterparty_balance,
+            funder_sig,
+            counterparty_sig,
+        })
+    }
+}
+
+impl ProtoConvert for ChannelWitness {
+    type Proto = blockchain::ChannelWitness;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::ChannelWitness::new();
+        match self {
+            ChannelWitness::Cooperative { txin, sig } => {
+                let mut cooperative = blockchain::CooperativeClose::new();
+                cooperative.set_txin(txin.into_proto());
+                cooperative.set_sig(sig.into_proto());
+                proto.set_cooperative(cooperative);
+            }
+            ChannelWitness::Close {
+                txin,
+                state,
+                closer,
+            } => {
+                let mut close = blockchain::UnilateralClose::new();
+                close.set_txin(txin.into_proto());
+                close.set_state(state.into_proto());
+                close.set_closer(closer.into_proto());
+                proto.set_close(close);
+            }
+            ChannelWitness::Penalty { txin, state } => {
+                let mut penalty = blockchain::ChannelPenalty::new();
+                penalty.set_txin(txin.into_proto());
+                penalty.set_state(state.into_proto());
+                proto.set_penalty(penalty);
+            }
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        match proto.witness {
+            Some(blockchain::ChannelWitness_oneof_witness::cooperative(ref cooperative)) => {
+                let txin = Hash::from_proto(cooperative.get_txin())?;
+                let sig = SchnorrSig::from_proto(cooperative.get_sig())?;
+                Ok(ChannelWitness::Cooperative { txin, sig })
+            }
+            Some(blockchain::ChannelWitness_oneof_witness::close(ref close)) => {
+                let txin = Hash::from_proto(close.get_txin())?;
+                let state = ChannelState::from_proto(close.get_state())?;
+                let closer = PublicKey::from_proto(close.get_closer())?;
+                Ok(ChannelWitness::Close {
+                    txin,
+                    state,
+                    closer,
+                })
+            }
+            Some(blockchain::ChannelWitness_oneof_witness::penalty(ref penalty)) => {
+                let txin = Hash::from_proto(penalty.get_txin())?;
+                let state = ChannelState::from_proto(penalty.get_state())?;
+                Ok(ChannelWitness::Penalty { txin, state })
+            }
+            None => {
+                Err(ProtoError::MissingField("witness".to_string(), "witness".to_string()).into())
+            }
+        }
+    }
+}
+
 impl ProtoConvert for PaymentTransaction {
     type Proto = blockchain::PaymentTransaction;
     fn into_proto(&self) -> Self::Proto {
         let mut proto = blockchain::PaymentTransaction::new();
 
+        proto.set_chain_id(self.chain_id.into_proto());
         for txin in &self.txins {
             proto.txins.push(txin.into_proto());
         }
@@ -198,10 +466,20 @@ impl ProtoConvert for PaymentTransaction {
         proto.set_gamma(self.gamma.into_proto());
         proto.set_fee(self.fee);
         proto.set_sig(self.sig.into_proto());
+        for (txin, preimage) in &self.preimages {
+            let mut preimage_proto = blockchain::Preimage::new();
+            preimage_proto.set_txin(txin.into_proto());
+            preimage_proto.set_preimage(preimage.clone());
+            proto.preimages.push(preimage_proto);
+        }
+        for witness in &self.channels {
+            proto.channels.push(witness.into_proto());
+        }
         proto
     }
 
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let chain_id = Hash::from_proto(proto.get_chain_id())?;
         let mut txins = Vec::<Hash>::with_capacity(proto.txins.len());
         for txin in proto.txins.iter() {
             txins.push(Hash::from_proto(txin)?);
@@ -213,13 +491,25 @@ impl ProtoConvert for PaymentTransaction {
         let gamma = Fr::from_proto(proto.get_gamma())?;
         let fee = proto.get_fee();
         let sig = SchnorrSig::from_proto(proto.get_sig())?;
+        let mut preimages = Vec::<(Hash, Vec<u8>)>::with_capacity(proto.preimages.len());
+        for preimage in proto.preimages.iter() {
+            let txin = Hash::from_proto(preimage.get_txin())?;
+            preimages.push((txin, preimage.get_preimage().to_vec()));
+        }
+        let mut channels = Vec::<ChannelWitness>::with_capacity(proto.channels.len());
+        for witness in proto.channels.iter() {
+            channels.push(ChannelWitness::from_proto(witness)?);
+        }
 
         Ok(PaymentTransaction {
+            chain_id,
             txins,
             txouts,
             gamma,
             fee,
             sig,
+            preimages,
+            channels,
         })
     }
 }
@@ -229,6 +519,44 @@ impl ProtoConvert for RestakeTransaction {
     fn into_proto(&self) -> Self::Proto {
         let mut proto = blockchain::RestakeTransaction::new();
 
+        proto.set_chain_id(self.chain_id.into_proto());
+        for txin in &self.txins {
+            proto.txins.push(txin.into_proto());
+        }
+        for txout in &self.txouts {
+            proto.txouts.push(txout.into_proto());
+        }
+        proto.set_signature(self.sig.into_proto());
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let chain_id = Hash::from_proto(proto.get_chain_id())?;
+        let mut txins = Vec::<Hash>::with_capacity(proto.txins.len());
+        for txin in proto.txins.iter() {
+            txins.push(Hash::from_proto(txin)?);
+        }
+        let mut txouts = Vec::<Output>::with_capacity(proto.txouts.len());
+        for txout in proto.txouts.iter() {
+            txouts.push(Output::from_proto(txout)?);
+        }
+        let sig = pbc::secure::Signature::from_proto(proto.get_signature())?;
+
+        Ok(RestakeTransaction {
+            chain_id,
+            txins,
+            txouts,
+            sig,
+        })
+    }
+}
+
+impl ProtoConvert for RevokeDelegationTransaction {
+    type Proto = blockchain::RevokeDelegationTransaction;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::RevokeDelegationTransaction::new();
+
+        proto.set_chain_id(self.chain_id.into_proto());
         for txin in &self.txins {
             proto.txins.push(txin.into_proto());
         }
@@ -240,6 +568,7 @@ impl ProtoConvert for RestakeTransaction {
     }
 
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let chain_id = Hash::from_proto(proto.get_chain_id())?;
         let mut txins = Vec::<Hash>::with_capacity(proto.txins.len());
         for txin in proto.txins.iter() {
             txins.push(Hash::from_proto(txin)?);
@@ -250,7 +579,12 @@ impl ProtoConvert for RestakeTransaction {
         }
         let sig = pbc::secure::Signature::from_proto(proto.get_signature())?;
 
-        Ok(RestakeTransaction { txins, txouts, sig })
+        Ok(RevokeDelegationTransaction {
+            chain_id,
+            txins,
+            txouts,
+            sig,
+        })
     }
 }
 
@@ -319,6 +653,9 @@ impl ProtoConvert for Transaction {
             Transaction::RestakeTransaction(restake_transaction) => {
                 proto.set_restake_transaction(restake_transaction.into_proto())
             }
+            Transaction::RevokeDelegationTransaction(revoke_delegation_transaction) => {
+                proto.set_revoke_delegation_transaction(revoke_delegation_transaction.into_proto())
+            }
             Transaction::SlashingTransaction(slashing_transaction) => {
                 proto.set_slashing_transaction(slashing_transaction.into_proto())
             }
@@ -349,6 +686,13 @@ impl ProtoConvert for Transaction {
                 let restake_transaction = RestakeTransaction::from_proto(restake_transaction)?;
                 Transaction::RestakeTransaction(restake_transaction)
             }
+            Some(blockchain::Transaction_oneof_transaction::revoke_delegation_transaction(
+                ref revoke_delegation_transaction,
+            )) => {
+                let revoke_delegation_transaction =
+                    RevokeDelegationTransaction::from_proto(revoke_delegation_transaction)?;
+                Transaction::RevokeDelegationTransaction(revoke_delegation_transaction)
+            }
             Some(blockchain::Transaction_oneof_transaction::slashing_transaction(
                 ref slashing_transaction,
             )) => {
@@ -379,6 +723,9 @@ impl ProtoConvert for BaseBlockHeader {
     fn into_proto(&self) -> Self::Proto {
         let mut proto = blockchain::BaseBlockHeader::new();
         proto.set_version(self.version);
+        if self.chain_id != Hash::zero() {
+            proto.set_chain_id(self.chain_id.into_proto());
+        }
         proto.set_previous(self.previous.into_proto());
         proto.set_height(self.height);
         proto.set_view_change(self.view_change);
@@ -394,6 +741,11 @@ impl ProtoConvert for BaseBlockHeader {
 
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
         let version = proto.get_version();
+        let chain_id = if proto.has_chain_id() {
+            Hash::from_proto(proto.get_chain_id())?
+        } else {
+            Hash::zero()
+        };
         let previous = Hash::from_proto(proto.get_previous())?;
         let height = proto.get_height();
         let view_change = proto.get_view_change();
@@ -402,6 +754,7 @@ impl ProtoConvert for BaseBlockHeader {
         let random = pbc::VRF::from_proto(proto.get_random())?;
         Ok(BaseBlockHeader {
             version,
+            chain_id,
             previous,
             height,
             view_change,
@@ -666,16 +1019,22 @@ impl ProtoConvert for ChainInfo {
     type Proto = view_changes::ChainInfo;
     fn into_proto(&self) -> Self::Proto {
         let mut proto = view_changes::ChainInfo::new();
+        proto.set_chain_id(self.chain_id.into_proto());
+        proto.set_epoch(self.epoch);
         proto.set_height(self.height);
         proto.set_last_block(self.last_block.into_proto());
         proto.set_view_change(self.view_change);
         proto
     }
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let chain_id = Hash::from_proto(proto.get_chain_id())?;
+        let epoch = proto.get_epoch();
         let height = proto.get_height();
         let view_change = proto.get_view_change();
         let last_block = Hash::from_proto(proto.get_last_block())?;
         Ok(ChainInfo {
+            chain_id,
+            epoch,
             height,
             view_change,
             last_block,
@@ -737,10 +1096,312 @@ impl ProtoConvert for SlashingProof {
     }
 }
 
+impl ProtoConvert for MerkleProof {
+    type Proto = blockchain::MerkleProof;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::MerkleProof::new();
+        proto.set_path(self.path.0);
+        for sibling in &self.siblings {
+            let mut sibling_proto = blockchain::MerkleSibling::new();
+            if let Some(hash) = sibling {
+                sibling_proto.set_hash(hash.into_proto());
+            }
+            proto.siblings.push(sibling_proto);
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let path = MerklePath(proto.get_path());
+        let mut siblings = Vec::<Option<Hash>>::with_capacity(proto.siblings.len());
+        for sibling in proto.siblings.iter() {
+            let sibling = if sibling.has_hash() {
+                Some(Hash::from_proto(sibling.get_hash())?)
+            } else {
+                None
+            };
+            siblings.push(sibling);
+        }
+        Ok(MerkleProof { path, siblings })
+    }
+}
+
+impl ProtoConvert for OutputProof {
+    type Proto = blockchain::OutputProof;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::OutputProof::new();
+        proto.set_output(self.output.into_proto());
+        match &self.inclusion {
+            OutputInclusion::MacroBlock(merkle_proof) => {
+                proto.set_macro_block(merkle_proof.into_proto())
+            }
+            OutputInclusion::MicroBlock {
+                blocks,
+                tx_id,
+                txout_id,
+            } => {
+                let mut inclusion = blockchain::MicroBlockInclusion::new();
+                for block in blocks {
+                    inclusion.blocks.push(block.into_proto());
+                }
+                inclusion.set_tx_id(*tx_id);
+                inclusion.set_txout_id(*txout_id);
+                proto.set_micro_block(inclusion);
+            }
+        }
+        proto.set_checkpoint(self.checkpoint.into_proto());
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let output = Output::from_proto(proto.get_output())?;
+        let inclusion = match proto.inclusion {
+            Some(blockchain::OutputProof_oneof_inclusion::macro_block(ref merkle_proof)) => {
+                let merkle_proof = MerkleProof::from_proto(merkle_proof)?;
+                OutputInclusion::MacroBlock(merkle_proof)
+            }
+            Some(blockchain::OutputProof_oneof_inclusion::micro_block(ref inclusion)) => {
+                let mut blocks = Vec::<MicroBlock>::with_capacity(inclusion.blocks.len());
+                for block in inclusion.blocks.iter() {
+                    blocks.push(MicroBlock::from_proto(block)?);
+                }
+                OutputInclusion::MicroBlock {
+                    blocks,
+                    tx_id: inclusion.get_tx_id(),
+                    txout_id: inclusion.get_txout_id(),
+                }
+            }
+            None => {
+                return Err(ProtoError::MissingField(
+                    "inclusion".to_string(),
+                    "inclusion".to_string(),
+                )
+                .into());
+            }
+        };
+        let checkpoint = MacroBlockHeader::from_proto(proto.get_checkpoint())?;
+        Ok(OutputProof {
+            output,
+            inclusion,
+            checkpoint,
+        })
+    }
+}
+
+impl ProtoConvert for UnsignedTransaction {
+    type Proto = blockchain::UnsignedTransaction;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::UnsignedTransaction::new();
+        proto.set_chain_id(self.chain_id.into_proto());
+        for input in &self.inputs {
+            proto.inputs.push(input.into_proto());
+        }
+        for output in &self.outputs {
+            proto.outputs.push(output.into_proto());
+        }
+        proto.set_outputs_gamma(self.outputs_gamma.into_proto());
+        proto.set_fee(self.fee);
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let chain_id = Hash::from_proto(proto.get_chain_id())?;
+        let mut inputs = Vec::<Output>::with_capacity(proto.inputs.len());
+        for input in proto.inputs.iter() {
+            inputs.push(Output::from_proto(input)?);
+        }
+        let mut outputs = Vec::<Output>::with_capacity(proto.outputs.len());
+        for output in proto.outputs.iter() {
+            outputs.push(Output::from_proto(output)?);
+        }
+        let outputs_gamma = Fr::from_proto(proto.get_outputs_gamma())?;
+        let fee = proto.get_fee();
+        Ok(UnsignedTransaction {
+            chain_id,
+            inputs,
+            outputs,
+            outputs_gamma,
+            fee,
+        })
+    }
+}
+
+impl ProtoConvert for SignedTransaction {
+    type Proto = blockchain::SignedTransaction;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::SignedTransaction::new();
+        proto.set_unsigned_hash(self.unsigned_hash.into_proto());
+        proto.set_tx(self.tx.into_proto());
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let unsigned_hash = Hash::from_proto(proto.get_unsigned_hash())?;
+        let tx = PaymentTransaction::from_proto(proto.get_tx())?;
+        Ok(SignedTransaction { unsigned_hash, tx })
+    }
+}
+
+impl ProtoConvert for ReserveProof {
+    type Proto = blockchain::ReserveProof;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::ReserveProof::new();
+        proto.set_challenge(self.challenge.clone());
+        for output_hash in &self.outputs {
+            proto.outputs.push(output_hash.into_proto());
+        }
+        proto.set_amount(self.amount);
+        proto.set_gamma(self.gamma.into_proto());
+        proto.set_sig(self.sig.into_proto());
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let challenge = proto.get_challenge().to_string();
+        let mut outputs = Vec::<Hash>::with_capacity(proto.outputs.len());
+        for output_hash in proto.outputs.iter() {
+            outputs.push(Hash::from_proto(output_hash)?);
+        }
+        let amount = proto.get_amount();
+        let gamma = Fr::from_proto(proto.get_gamma())?;
+        let sig = SchnorrSig::from_proto(proto.get_sig())?;
+        Ok(ReserveProof {
+            challenge,
+            outputs,
+            amount,
+            gamma,
+            sig,
+        })
+    }
+}
+
+impl ProtoConvert for EpochLeader {
+    type Proto = blockchain::EpochLeader;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::EpochLeader::new();
+        proto.set_height(self.height);
+        proto.set_leader(self.leader.into_proto());
+        proto.set_view_change(self.view_change);
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let height = proto.get_height();
+        let leader = pbc::PublicKey::from_proto(proto.get_leader())?;
+        let view_change = proto.get_view_change();
+        Ok(EpochLeader {
+            height,
+            leader,
+            view_change,
+        })
+    }
+}
+
+impl ProtoConvert for ValidatorUptime {
+    type Proto = blockchain::ValidatorUptime;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::ValidatorUptime::new();
+        proto.set_network_pkey(self.network_pkey.into_proto());
+        proto.set_slots(self.slots);
+        proto.set_produced(self.produced);
+        proto.set_missed(self.missed);
+        proto.set_active(self.active);
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let network_pkey = pbc::PublicKey::from_proto(proto.get_network_pkey())?;
+        let slots = proto.get_slots();
+        let produced = proto.get_produced();
+        let missed = proto.get_missed();
+        let active = proto.get_active();
+        Ok(ValidatorUptime {
+            network_pkey,
+            slots,
+            produced,
+            missed,
+            active,
+        })
+    }
+}
+
+impl ProtoConvert for EpochSummary {
+    type Proto = blockchain::EpochSummary;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = blockchain::EpochSummary::new();
+        proto.set_epoch(self.epoch);
+        proto.set_macro_block_height(self.macro_block_height);
+        proto.set_macro_block_hash(self.macro_block_hash.into_proto());
+        let since_the_epoch = self
+            .timestamp
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("time is valid");
+        let timestamp = since_the_epoch.as_secs() * 1000 + since_the_epoch.subsec_millis() as u64;
+        proto.set_timestamp(timestamp);
+        for leader in &self.leaders {
+            proto.leaders.push(leader.into_proto());
+        }
+        proto.set_view_changes(self.view_changes);
+        proto.set_transactions(self.transactions);
+        proto.set_fees(self.fees);
+        proto.set_micro_block_rewards(self.micro_block_rewards);
+        proto.set_macro_block_reward(self.macro_block_reward);
+        if let Some((winner, amount)) = &self.service_award {
+            proto.set_service_award_winner(winner.into_proto());
+            proto.set_service_award(*amount);
+        }
+        for validator in &self.validators {
+            proto.validators.push(validator.into_proto());
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let epoch = proto.get_epoch();
+        let macro_block_height = proto.get_macro_block_height();
+        let macro_block_hash = Hash::from_proto(proto.get_macro_block_hash())?;
+        let timestamp =
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(proto.get_timestamp());
+        let mut leaders = Vec::<EpochLeader>::with_capacity(proto.leaders.len());
+        for leader in proto.leaders.iter() {
+            leaders.push(EpochLeader::from_proto(leader)?);
+        }
+        let view_changes = proto.get_view_changes();
+        let transactions = proto.get_transactions();
+        let fees = proto.get_fees();
+        let micro_block_rewards = proto.get_micro_block_rewards();
+        let macro_block_reward = proto.get_macro_block_reward();
+        let service_award = if proto.has_service_award_winner() {
+            let winner = PublicKey::from_proto(proto.get_service_award_winner())?;
+            Some((winner, proto.get_service_award()))
+        } else {
+            None
+        };
+        let mut validators = Vec::<ValidatorUptime>::with_capacity(proto.validators.len());
+        for validator in proto.validators.iter() {
+            validators.push(ValidatorUptime::from_proto(validator)?);
+        }
+        Ok(EpochSummary {
+            epoch,
+            macro_block_height,
+            macro_block_hash,
+            timestamp,
+            leaders,
+            view_changes,
+            transactions,
+            fees,
+            micro_block_rewards,
+            macro_block_reward,
+            service_award,
+            validators,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::SystemTime;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     use stegos_crypto::curve1174;
     use stegos_crypto::hash::{Hash, Hashable, Hasher};
     use stegos_crypto::pbc;
@@ -754,9 +1415,26 @@ mod tests {
         r
     }
 
+    #[test]
+    fn lock_time() {
+        // Timestamps are truncated to milliseconds, as they are serialized.
+        let timestamp = UNIX_EPOCH + Duration::new(1_500_000_000, 123_456_789);
+        let truncated = UNIX_EPOCH + Duration::new(1_500_000_000, 123_000_000);
+        let lock = LockTime::timestamp(timestamp);
+        assert_eq!(lock, LockTime::Timestamp(truncated));
+        assert_eq!(roundtrip(&lock), lock);
+
+        // Locks created without the constructor have the same hash and the same expiration.
+        let untruncated = LockTime::Timestamp(timestamp);
+        assert_eq!(roundtrip(&untruncated), lock);
+        assert_eq!(Hash::digest(&untruncated), Hash::digest(&lock));
+        assert!(untruncated.is_unlocked(0, truncated));
+        assert!(!untruncated.is_unlocked(0, truncated - Duration::from_millis(1)));
+    }
+
     #[test]
     fn outputs() {
-        let (_skey1, pkey1) = curve1174::make_random_keys();
+        let (skey1, pkey1) = curve1174::make_random_keys();
         let (network_skey1, network_pkey1) = pbc::make_random_keys();
 
         let amount = 1_000_000;
@@ -764,6 +1442,48 @@ mod tests {
         let (output, _gamma) = Output::new_payment(&pkey1, amount).expect("keys are valid");
         roundtrip(&output);
 
+        let locked_until = LockTime::Height(100);
+        let (output, _gamma) =
+            PaymentOutput::new_locked(&pkey1, amount, locked_until).expect("keys are valid");
+        let output: Output = output.into();
+        assert_eq!(roundtrip(&output).locked_until(), Some(locked_until));
+
+        let output = PublicPaymentOutput::new_hashlocked(
+            &pkey1,
+            &pkey1,
+            amount,
+            Hash::digest("preimage"),
+            LockTime::Height(100),
+        );
+        let output: Output = output.into();
+        assert!(roundtrip(&output).hashlock().is_some());
+
+        let (_skey2, pkey2) = curve1174::make_random_keys();
+        let output = PublicPaymentOutput::new_channel_funding(&pkey1, &pkey2, amount, 10);
+        let channel = Hash::digest(&output);
+        let output: Output = output.into();
+        assert_eq!(roundtrip(&output).channel_lock(), output.channel_lock());
+        let output =
+            PublicPaymentOutput::new_channel_dispute(&pkey1, &pkey2, amount, channel, 2, 10);
+        let output: Output = output.into();
+        assert_eq!(roundtrip(&output).channel_lock(), output.channel_lock());
+
+        let locked_until = LockTime::timestamp(SystemTime::now());
+        let (output, _gamma) =
+            PaymentOutput::new_locked(&pkey1, amount, locked_until).expect("keys are valid");
+        let output: Output = output.into();
+        assert_eq!(roundtrip(&output).locked_until(), Some(locked_until));
+
+        let mut stake = StakeOutput::new(&pkey1, &network_skey1, &network_pkey1, amount)
+            .expect("keys are valid");
+        let reward = RewardDestination::new(&skey1, &pkey1, &network_pkey1, &pkey2);
+        stake.set_reward(Some(reward), &network_skey1);
+        let output: Output = stake.into();
+        match roundtrip(&output) {
+            Output::StakeOutput(o) => assert_eq!(o.reward_key(), pkey2),
+            _ => unreachable!(),
+        }
+
         let output = Output::new_stake(&pkey1, &network_skey1, &network_pkey1, amount)
             .expect("keys are valid");
         roundtrip(&output);
@@ -774,8 +1494,9 @@ mod tests {
     }
 
     fn mktransaction() -> PaymentTransaction {
+        let chain_id = Hash::digest("test");
         let (skey1, pkey1) = curve1174::make_random_keys();
-        let (_skey2, pkey2) = curve1174::make_random_keys();
+        let (skey2, pkey2) = curve1174::make_random_keys();
 
         let amount: i64 = 1_000_000;
         let fee: i64 = 0;
@@ -792,13 +1513,85 @@ mod tests {
 
         let outputs_gamma = gamma11;
 
-        let tx = PaymentTransaction::new(&skey1, &inputs1, &[output11], &outputs_gamma, fee)
-            .expect("keys are valid");
+        let tx = PaymentTransaction::new(
+            &chain_id,
+            &skey1,
+            &inputs1,
+            &[output11],
+            &outputs_gamma,
+            fee,
+        )
+        .expect("keys are valid");
         tx.validate(&inputs1).unwrap();
 
         let tx2 = roundtrip(&tx);
         tx2.validate(&inputs1).unwrap();
 
+        // Redeem of a hash-locked output.
+        let preimage = b"preimage".to_vec();
+        let htlc = PublicPaymentOutput::new_hashlocked(
+            &pkey1,
+            &pkey2,
+            amount,
+            Hash::digest(&preimage),
+            LockTime::Height(100),
+        );
+        let htlc_hash = Hash::digest(&htlc);
+        let inputs2 = [Output::PublicPaymentOutput(htlc)];
+        let (output21, gamma21) = Output::new_payment(&pkey2, amount).expect("keys are valid");
+        let preimages = vec![(htlc_hash, preimage)];
+        let tx3 = PaymentTransaction::with_preimages(
+            &chain_id,
+            &skey1,
+            &inputs2,
+            preimages,
+            &[output21],
+            &gamma21,
+            fee,
+        )
+        .expect("keys are valid");
+        let tx3 = roundtrip(&tx3);
+        assert_eq!(tx3.preimage(&htlc_hash), Some(&b"preimage"[..]));
+        tx3.validate(&inputs2).unwrap();
+
+        // Unilateral close of a payment channel.
+        let funding = PublicPaymentOutput::new_channel_funding(&pkey1, &pkey2, amount, 10);
+        let channel = Hash::digest(&funding);
+        let mut state = ChannelState::new(channel, pkey1, pkey2, 1, amount - 100, 100);
+        state.sign(&skey1, &pkey1);
+        state.sign(&skey2, &pkey2);
+        let inputs3 = [Output::PublicPaymentOutput(funding)];
+        let outputs3 = [
+            Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey2, 100)),
+            Output::PublicPaymentOutput(PublicPaymentOutput::new_channel_dispute(
+                &pkey1,
+                &pkey2,
+                amount - 100,
+                channel,
+                1,
+                10,
+            )),
+        ];
+        let channels = vec![ChannelWitness::Close {
+            txin: channel,
+            state,
+            closer: pkey1,
+        }];
+        let tx4 = PaymentTransaction::with_witnesses(
+            &chain_id,
+            &skey1,
+            &inputs3,
+            Vec::new(),
+            channels,
+            &outputs3,
+            &Fr::zero(),
+            fee,
+        )
+        .expect("keys are valid");
+        let tx4 = roundtrip(&tx4);
+        assert!(tx4.channel_witness(&channel).is_some());
+        tx4.validate(&inputs3).unwrap();
+
         tx
     }
 
@@ -833,13 +1626,22 @@ mod tests {
         let (skeypbc, pkeypbc) = pbc::make_random_keys();
 
         let version: u64 = 1;
+        let chain_id = Hash::digest("test");
         let height: u64 = 0;
         let timestamp = SystemTime::now();
         let view_change = 0;
         let previous = Hash::digest(&"test".to_string());
         let seed = mix(Hash::digest("random"), view_change);
         let random = pbc::make_VRF(&skeypbc, &seed);
-        let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+        let base = BaseBlockHeader::new(
+            version,
+            chain_id,
+            previous,
+            height,
+            view_change,
+            timestamp,
+            random,
+        );
         roundtrip(&base);
 
         // View Changes Proof.
@@ -849,12 +1651,12 @@ mod tests {
 
         // Transactions.
         let (tx, _inputs, _outputs) =
-            PaymentTransaction::new_test(&skey, &pkey, 300, 2, 100, 1, 100)
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 300, 2, 100, 1, 100)
                 .expect("Invalid transaction");
         let transactions: Vec<Transaction> = vec![tx.into()];
 
         let mut block = MicroBlock::new(base, view_change_proof, transactions, pkeypbc);
-        block.sign(&skeypbc, &pkeypbc);
+        block.sign(&skeypbc, &pkeypbc).unwrap();
         let block2 = roundtrip(&block);
         assert_eq!(block2.pkey, block.pkey);
         assert_eq!(block2.sig, block.sig);
@@ -885,6 +1687,7 @@ mod tests {
         let (skeypbc, pkeypbc) = pbc::make_random_keys();
 
         let version: u64 = 1;
+        let chain_id = Hash::digest("test");
         let height: u64 = 0;
         let timestamp = SystemTime::now();
         let view_change = 0;
@@ -902,7 +1705,15 @@ mod tests {
 
         let seed = mix(Hash::digest("random"), view_change);
         let random = pbc::make_VRF(&skeypbc, &seed);
-        let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+        let base = BaseBlockHeader::new(
+            version,
+            chain_id,
+            previous,
+            height,
+            view_change,
+            timestamp,
+            random,
+        );
         roundtrip(&base);
 
         let block = MacroBlock::new(
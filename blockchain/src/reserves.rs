@@ -0,0 +1,212 @@
+//! Blockchain - Proof of Reserves.
+
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::{BlockchainError, ReserveProofError};
+use crate::output::Output;
+use serde::de::{Deserialize, Deserializer, Error as SerdeError};
+use serde::ser::{Error as SerError, Serialize, Serializer};
+use std::collections::HashSet;
+use stegos_crypto::bulletproofs::simple_commit;
+use stegos_crypto::curve1174::{
+    sign_hash, validate_sig, ECp, Fr, PublicKey, SchnorrSig, SecretKey,
+};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_serialization::traits::ProtoConvert;
+
+///
+/// Proof of ownership and the total amount of a set of outputs.
+///
+/// Uses the same construction as the signature of a payment transaction which
+/// spends the outputs without creating new ones:
+///
+///     P_eff = \sum{P_i + C_i} - amount * A - gamma * G
+///
+/// The signature under `P_eff` can only be made by the owner of the outputs and
+/// only if `amount` is the sum of their amounts. Amounts of individual outputs
+/// and other outputs of the owner are not disclosed.
+///
+#[derive(Debug, Clone)]
+pub struct ReserveProof {
+    /// A challenge chosen by the auditor, prevents reusing old proofs.
+    pub challenge: String,
+    /// Hashes of the outputs.
+    pub outputs: Vec<Hash>,
+    /// The total amount of the outputs.
+    pub amount: i64,
+    /// The sum of blinding factors of the commitments.
+    pub gamma: Fr,
+    /// Signature under the effective public key.
+    pub sig: SchnorrSig,
+}
+
+impl Hashable for ReserveProof {
+    fn hash(&self, state: &mut Hasher) {
+        "ReserveProof".hash(state);
+        self.challenge.hash(state);
+        for output_hash in &self.outputs {
+            output_hash.hash(state);
+        }
+        self.amount.hash(state);
+        self.gamma.hash(state);
+    }
+}
+
+impl ReserveProof {
+    ///
+    /// Create a new proof for outputs owned by `skey`.
+    ///
+    pub fn new(
+        skey: &SecretKey,
+        outputs: &[Output],
+        challenge: String,
+    ) -> Result<Self, BlockchainError> {
+        //
+        // Compute S_eff = N * S_M + \sum{\delta_i * gamma_i},
+        // the same as for inputs of a payment transaction.
+        //
+        let mut eff_skey = Fr::zero();
+        let mut gamma = Fr::zero();
+        let mut amount: i64 = 0;
+        for output in outputs {
+            eff_skey += Fr::from(skey);
+            match output {
+                Output::PaymentOutput(o) => {
+                    let payload = o.decrypt_payload(skey)?;
+                    gamma += &payload.gamma;
+                    eff_skey += payload.delta * &payload.gamma;
+                    amount += payload.amount;
+                }
+                Output::PublicPaymentOutput(o) => amount += o.amount,
+                Output::StakeOutput(o) => amount += o.amount,
+            }
+        }
+
+        let mut proof = ReserveProof {
+            challenge,
+            outputs: outputs.iter().map(Hash::digest).collect(),
+            amount,
+            gamma,
+            sig: SchnorrSig::new(),
+        };
+        let hash = Hash::digest(&proof);
+        let eff_skey: SecretKey = eff_skey.into();
+        proof.sig = sign_hash(&hash, &eff_skey);
+        Ok(proof)
+    }
+
+    ///
+    /// Validate the proof.
+    ///
+    /// # Arguments
+    ///
+    /// * `outputs` - outputs referenced by `self.outputs`, in the same order,
+    ///    resolved from UTXO by the auditor.
+    ///
+    pub fn validate(&self, outputs: &[Output]) -> Result<(), ReserveProofError> {
+        assert_eq!(self.outputs.len(), outputs.len());
+        let mut eff_pkey = ECp::inf();
+        let mut outputs_set: HashSet<Hash> = HashSet::new();
+        for (output_hash, output) in self.outputs.iter().zip(outputs) {
+            assert_eq!(Hash::digest(output), *output_hash);
+            if !outputs_set.insert(*output_hash) {
+                return Err(ReserveProofError::DuplicateOutput(*output_hash));
+            }
+            eff_pkey += output.recipient_pkey()? + output.pedersen_commitment()?;
+        }
+        eff_pkey -= simple_commit(&self.gamma, &Fr::from(self.amount));
+
+        let eff_pkey: PublicKey = eff_pkey.into();
+        let hash = Hash::digest(self);
+        validate_sig(&hash, &self.sig, &eff_pkey)
+            .map_err(|_e| ReserveProofError::InvalidSignature(self.amount))?;
+        Ok(())
+    }
+}
+
+/// Serialized as a hex-encoded protobuf, suitable for exporting.
+impl Serialize for ReserveProof {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let buf = self.into_buffer().map_err(S::Error::custom)?;
+        serializer.serialize_str(&hex::encode(&buf))
+    }
+}
+
+impl<'de> Deserialize<'de> for ReserveProof {
+    fn deserialize<D>(deserializer: D) -> Result<ReserveProof, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let buf = hex::decode(&s).map_err(D::Error::custom)?;
+        ReserveProof::from_buffer(&buf).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::make_random_keys;
+    use stegos_crypto::pbc;
+
+    #[test]
+    fn reserve_proof() {
+        let (skey, pkey) = make_random_keys();
+        let (network_skey, network_pkey) = pbc::make_random_keys();
+        let (output1, _gamma1) = Output::new_payment(&pkey, 100).unwrap();
+        let (output2, _gamma2) = Output::new_payment(&pkey, 23).unwrap();
+        let output3 = Output::new_stake(&pkey, &network_skey, &network_pkey, 1000).unwrap();
+        let outputs = vec![output1.clone(), output2, output3];
+
+        let proof = ReserveProof::new(&skey, &outputs, "audit-1".to_string()).unwrap();
+        assert_eq!(proof.amount, 1123);
+        proof.validate(&outputs).unwrap();
+
+        let buf = proof.into_buffer().unwrap();
+        let proof2 = ReserveProof::from_buffer(&buf).unwrap();
+        assert_eq!(Hash::digest(&proof2), Hash::digest(&proof));
+        proof2.validate(&outputs).unwrap();
+
+        // Inflated amount.
+        let mut forged = proof.clone();
+        forged.amount += 1;
+        match forged.validate(&outputs) {
+            Err(ReserveProofError::InvalidSignature(1124)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // Replayed with other challenge.
+        let mut forged = proof.clone();
+        forged.challenge = "audit-2".to_string();
+        assert!(forged.validate(&outputs).is_err());
+
+        // Outputs of someone else.
+        let (other_skey, _other_pkey) = make_random_keys();
+        let outputs = vec![output1];
+        assert!(ReserveProof::new(&other_skey, &outputs, "audit-1".to_string()).is_err());
+    }
+}
@@ -86,7 +86,15 @@ impl SlashingProof {
 
         let election_result = blockchain.election_result_by_height(height)?;
 
-        let ref leader_pk = election_result.select_leader(self.block1.base.view_change);
+        let ref leader_pk = if blockchain.cfg().secret_leader_election {
+            // Any eligible validator can create a block, but only once per view change.
+            if self.block1.pkey != self.block2.pkey {
+                return Err(SlashingError::WrongLeader(self.block2.pkey, self.block1.pkey).into());
+            }
+            self.block1.pkey
+        } else {
+            election_result.select_leader(self.block1.base.view_change)
+        };
 
         pbc::check_hash(&block1_hash, &self.block1.sig, leader_pk)?;
         pbc::check_hash(&block2_hash, &self.block2.sig, leader_pk)?;
@@ -133,6 +141,8 @@ pub fn confiscate_tx(
             recipient: key,
             serno: rng.gen(),
             amount: piece,
+            hashlock: None,
+            channel: None,
         };
         if validator == our_key {
             output.amount += change
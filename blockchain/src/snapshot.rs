@@ -0,0 +1,179 @@
+//! Blockchain - UTXO Snapshots.
+
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::block::Block;
+use crate::blockchain::OutputKey;
+use crate::mmr::{MerkleMountainRange, MmrProof};
+use crate::output::Output;
+use crate::storage::ListDb;
+use failure::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::vec;
+use stegos_crypto::hash::Hash;
+
+///
+/// A point-in-time view of the UTXO set.
+///
+/// Holds a copy of the UTXO index and the micro blocks of the current epoch,
+/// which are the only blocks that can be reverted. Outputs created by finalized
+/// blocks are resolved from the shared database on demand. Blocks applied or
+/// reverted after the snapshot has been taken don't affect it.
+///
+pub struct UtxoSnapshot {
+    /// The height of the blockchain when the snapshot was taken.
+    height: u64,
+    /// UTXO index.
+    outputs: BTreeMap<Hash, OutputKey>,
+    /// Micro blocks of the current epoch by height.
+    micro_blocks: HashMap<u64, Block>,
+    /// Persistent storage for finalized blocks.
+    database: ListDb,
+}
+
+impl UtxoSnapshot {
+    pub(crate) fn new(
+        height: u64,
+        outputs: Vec<(Hash, OutputKey)>,
+        micro_blocks: Vec<(u64, Block)>,
+        database: ListDb,
+    ) -> Self {
+        UtxoSnapshot {
+            height,
+            outputs: outputs.into_iter().collect(),
+            micro_blocks: micro_blocks.into_iter().collect(),
+            database,
+        }
+    }
+
+    /// The height of the blockchain when the snapshot was taken.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Returns the number of UTXO.
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Returns true if there is no UTXO.
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Returns true if the snapshot contains unspent output.
+    pub fn contains(&self, output_hash: &Hash) -> bool {
+        self.outputs.contains_key(output_hash)
+    }
+
+    /// Returns an iterator over UTXO hashes.
+    pub fn unspent(&self) -> impl Iterator<Item = &Hash> {
+        self.outputs.keys()
+    }
+
+    /// Builds the Merkle Mountain Range of UTXO hashes in ascending order.
+    /// Its root commits to the UTXO set at the height of the snapshot.
+    pub fn utxo_tree(&self) -> MerkleMountainRange {
+        let mut tree = MerkleMountainRange::new();
+        for output_hash in self.outputs.keys() {
+            tree.push(output_hash);
+        }
+        tree
+    }
+
+    /// Returns the proof of inclusion of the UTXO into `utxo_tree()`.
+    pub fn utxo_proof(&self, tree: &MerkleMountainRange, output_hash: &Hash) -> Option<MmrProof> {
+        if !self.contains(output_hash) {
+            return None;
+        }
+        let index = self.outputs.range(..*output_hash).count();
+        tree.proof(index as u64)
+    }
+
+    /// Resolve UTXO by hash.
+    pub fn output_by_hash(&self, output_hash: &Hash) -> Result<Option<Output>, Error> {
+        match self.outputs.get(output_hash) {
+            Some(key) => {
+                let block = self.block_by_height(key.height())?;
+                Ok(key.resolve(&block))
+            }
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// Returns an iterator over all UTXO.
+    ///
+    /// Outputs are visited in the order of blocks, so every block is loaded only once.
+    /// Pruned outputs are skipped.
+    ///
+    pub fn iter(&self) -> UtxoIter {
+        let mut keys: Vec<(&Hash, &OutputKey)> = self.outputs.iter().collect();
+        keys.sort_by_key(|(_hash, key)| key.height());
+        UtxoIter {
+            snapshot: self,
+            keys: keys.into_iter(),
+            block: None,
+        }
+    }
+
+    fn block_by_height(&self, height: u64) -> Result<Block, Error> {
+        assert!(height < self.height);
+        if let Some(block) = self.micro_blocks.get(&height) {
+            return Ok(block.clone());
+        }
+        Ok(self.database.get(height)?.expect("block exists"))
+    }
+}
+
+/// An iterator over UTXO of the snapshot, see `UtxoSnapshot::iter()`.
+pub struct UtxoIter<'a> {
+    snapshot: &'a UtxoSnapshot,
+    keys: vec::IntoIter<(&'a Hash, &'a OutputKey)>,
+    /// The last loaded block and its height.
+    block: Option<(u64, Block)>,
+}
+
+impl<'a> Iterator for UtxoIter<'a> {
+    type Item = Result<(Hash, Output), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (output_hash, key) = self.keys.next()?;
+            let height = key.height();
+            match self.block {
+                Some((block_height, _)) if block_height == height => {}
+                _ => match self.snapshot.block_by_height(height) {
+                    Ok(block) => self.block = Some((height, block)),
+                    Err(e) => return Some(Err(e)),
+                },
+            }
+            let (_, block) = self.block.as_ref().unwrap();
+            if let Some(output) = key.resolve(block) {
+                return Some(Ok((*output_hash, output)));
+            }
+            // Pruned.
+        }
+    }
+}
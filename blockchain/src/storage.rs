@@ -22,8 +22,10 @@
 //! Implementation of block list on rocksdb.
 
 use byteorder::{BigEndian, ByteOrder};
-use failure::Error;
-use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use failure::{format_err, Error};
+use log::*;
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, WriteOptions, DB};
+use stegos_crypto::hash::{Hash, HASH_SIZE};
 use stegos_serialization::traits::ProtoConvert;
 use tempdir::TempDir;
 
@@ -31,15 +33,47 @@ use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
 use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use super::block::Block;
+use crate::blockchain::{OutputKey, TransactionKey};
+use crate::epoch_summary::EpochSummary;
+use crate::merkle::MerklePath;
+
+/// Key of the record with the height and the hash of the last block.
+const TIP_KEY: &[u8] = b"tip";
+
+/// Prefix of keys of epoch summaries, followed by the epoch.
+const EPOCH_SUMMARY_PREFIX: &[u8] = b"summary";
+
+/// Prefix of keys of any output ever created, followed by the hash of output.
+const OUTPUT_PREFIX: &[u8] = b"output";
+
+/// Prefix of keys of committed transactions, followed by the hash of transaction.
+const TX_PREFIX: &[u8] = b"tx";
+
+/// Prefix of keys of heights of blocks which spent outputs, followed by the hash of output.
+const SPENT_PREFIX: &[u8] = b"spent";
+
+/// Key of the record which is present if outputs, transactions and spent outputs
+/// are indexed for all blocks. Databases created by older versions have no indexes.
+const INDEXES_KEY: &[u8] = b"indexes";
+
+/// Length of keys of block records.
+const BLOCK_KEY_LEN: usize = 8;
 
 /// Database for storing Blocks in List maner.
+/// Clones share the same underlying database.
+#[derive(Clone)]
 pub struct ListDb {
     /// Guard object for temporary directory.
-    _temp_dir: Option<TempDir>,
+    _temp_dir: Option<Arc<TempDir>>,
     /// RocksDB database object.
-    database: DB,
+    database: Arc<DB>,
+    /// Refuse to modify records.
+    read_only: bool,
 }
 
 impl ListDb {
@@ -47,31 +81,62 @@ impl ListDb {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         let database = DB::open_default(path).expect("couldn't open database");
         Self {
-            database,
+            database: Arc::new(database),
             _temp_dir: None,
+            read_only: false,
         }
     }
 
+    /// Opens an existing database in the read-only mode of RocksDB.
+    /// The database can be used by a running node at the same time,
+    /// records written after opening are not visible.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let options = Options::default();
+        let database = DB::open_for_read_only(&options, path, false)?;
+        Ok(Self {
+            database: Arc::new(database),
+            _temp_dir: None,
+            read_only: true,
+        })
+    }
+
+    /// Returns true if the database is opened by `open_read_only()`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     /// Creates new testing ListDB instance.
     pub fn testing() -> Self {
         // we need to generate random string, to avoid conflicts in tests.
         let rand_string: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
         let temp_dir = TempDir::new(&rand_string).expect("couldn't create temp dir");
-        let database = DB::open_default(temp_dir.path()).expect("couldn't open temp database");;
+        let database = DB::open_default(temp_dir.path()).expect("couldn't open temp database");
 
         Self {
-            _temp_dir: Some(temp_dir),
-            database,
+            _temp_dir: Some(Arc::new(temp_dir)),
+            database: Arc::new(database),
+            read_only: false,
         }
     }
 
     pub fn insert(&self, height: u64, block: Block) -> Result<(), Error> {
-        let data = block.into_buffer().expect("couldn't serialize block.");
+        let mut batch = ListDbBatch::default();
+        batch.insert(height, &block)?;
+        self.write(batch)
+    }
 
-        let mut batch = WriteBatch::default();
-        // writebatch put fails if size exceeded u32::max, which is not our case.
-        batch.put(&Self::key_u64_to_bytes(height), &data)?;
-        self.database.write(batch)?;
+    /// Atomically apply all changes of the batch.
+    pub fn write(&self, batch: ListDbBatch) -> Result<(), Error> {
+        self.check_writable()?;
+        self.database.write(batch.batch)?;
+        Ok(())
+    }
+
+    /// Same as write(), but syncs the changes to the disk before returning.
+    pub fn write_sync(&self, batch: ListDbBatch) -> Result<(), Error> {
+        self.check_writable()?;
+        self.database
+            .write_opt(batch.batch, &Self::sync_write_options())?;
         Ok(())
     }
 
@@ -84,11 +149,89 @@ impl ListDb {
         }
     }
 
-    /// Remove record by id.
-    pub fn remove(&self, height: u64) -> Result<(), Error> {
-        let key = Self::key_u64_to_bytes(height);
-        self.database.delete(&key)?;
-        Ok(())
+    /// Returns the height and the hash of the last block, as written together with blocks.
+    /// Databases created by older versions have no such record.
+    pub fn tip(&self) -> Result<Option<(u64, Hash)>, Error> {
+        match self.database.get(TIP_KEY)? {
+            Some(buffer) => {
+                if buffer.len() != BLOCK_KEY_LEN + HASH_SIZE {
+                    return Err(format_err!("Invalid tip record: len={}", buffer.len()));
+                }
+                let height = BigEndian::read_u64(&buffer[..BLOCK_KEY_LEN]);
+                let last_block_hash = Hash::try_from_bytes(&buffer[BLOCK_KEY_LEN..])?;
+                Ok(Some((height, last_block_hash)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Get the summary of a finalized epoch.
+    pub fn epoch_summary(&self, epoch: u64) -> Result<Option<EpochSummary>, Error> {
+        match self.database.get(&Self::epoch_summary_key(epoch))? {
+            Some(buffer) => Ok(Some(EpochSummary::from_buffer(&buffer)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the location of any output ever created, including spent ones.
+    pub(crate) fn output_key(&self, output_hash: &Hash) -> Result<Option<OutputKey>, Error> {
+        let key = Self::index_key(OUTPUT_PREFIX, output_hash);
+        match self.database.get(&key)? {
+            Some(buffer) => Ok(Some(Self::decode_output_key(&buffer)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the location of a committed transaction.
+    pub(crate) fn tx_key(&self, tx_hash: &Hash) -> Result<Option<TransactionKey>, Error> {
+        let key = Self::index_key(TX_PREFIX, tx_hash);
+        match self.database.get(&key)? {
+            Some(buffer) => {
+                if buffer.len() != BLOCK_KEY_LEN + 4 {
+                    return Err(format_err!("Invalid tx record: len={}", buffer.len()));
+                }
+                let height = BigEndian::read_u64(&buffer[..BLOCK_KEY_LEN]);
+                let tx_id = BigEndian::read_u32(&buffer[BLOCK_KEY_LEN..]);
+                Ok(Some(TransactionKey { height, tx_id }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the height of the block which spent the output.
+    pub fn spent_height(&self, output_hash: &Hash) -> Result<Option<u64>, Error> {
+        let key = Self::index_key(SPENT_PREFIX, output_hash);
+        match self.database.get(&key)? {
+            Some(buffer) => {
+                if buffer.len() != BLOCK_KEY_LEN {
+                    return Err(format_err!("Invalid spent record: len={}", buffer.len()));
+                }
+                Ok(Some(BigEndian::read_u64(&buffer)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if outputs, transactions and spent outputs are indexed for all blocks.
+    pub fn has_indexes(&self) -> Result<bool, Error> {
+        Ok(self.database.get(INDEXES_KEY)?.is_some())
+    }
+
+    /// Remove records of outputs, transactions and spent outputs of all blocks.
+    pub fn drop_indexes(&self) -> Result<(), Error> {
+        self.check_writable()?;
+        let mut batch = ListDbBatch::default();
+        batch.delete(INDEXES_KEY)?;
+        for &prefix in &[OUTPUT_PREFIX, SPENT_PREFIX, TX_PREFIX] {
+            let mode = IteratorMode::From(prefix, Direction::Forward);
+            for (k, _) in self.database.iterator(mode) {
+                if !k.starts_with(prefix) {
+                    break;
+                }
+                batch.delete(&k)?;
+            }
+        }
+        self.write_sync(batch)
     }
 
     /// Create iterator that traverse fully block collection.
@@ -96,6 +239,7 @@ impl ListDb {
         let mode = IteratorMode::Start;
         self.database
             .full_iterator(mode)
+            .take_while(|(k, _)| k.len() == BLOCK_KEY_LEN)
             .map(|(_, v)| Block::from_buffer(&*v).expect("couldn't deserialize block."))
     }
 
@@ -105,32 +249,428 @@ impl ListDb {
         let mode = IteratorMode::From(&key, Direction::Forward);
         self.database
             .iterator(mode)
+            .take_while(|(k, _)| k.len() == BLOCK_KEY_LEN)
             .map(|(_, v)| Block::from_buffer(&*v).expect("couldn't deserialize block."))
     }
 
+    /// Create iterator that traverse fully block collection.
+    /// Unlike `iter()`, returns heights from the keys and doesn't panic on damaged records.
+    pub fn iter_checked(&self) -> impl Iterator<Item = (u64, Result<Block, Error>)> {
+        let mode = IteratorMode::Start;
+        self.database
+            .full_iterator(mode)
+            .take_while(|(k, _)| k.len() == BLOCK_KEY_LEN)
+            .map(|(k, v)| (BigEndian::read_u64(&k), Block::from_buffer(&*v)))
+    }
+
+    /// Check that records form a hash chain starting from the first one.
+    pub fn check(&self) -> ChainCheck {
+        let tip = match self.tip() {
+            Ok(tip) => tip,
+            Err(e) => {
+                warn!("Damaged tip record on the disk: error={}", e);
+                None
+            }
+        };
+        let mut check = ChainCheck {
+            height: 0,
+            last_block_hash: None,
+            records: 0,
+            tip,
+        };
+        for (height, block) in self.iter_checked() {
+            check.records += 1;
+            if check.records != check.height + 1 {
+                continue; // Count the rest of records after the first damaged one.
+            }
+            let block = match block {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!("Damaged block on the disk: height={}, error={}", height, e);
+                    continue;
+                }
+            };
+            let header = block.base_header();
+            if height != check.height
+                || header.height != height
+                || check
+                    .last_block_hash
+                    .map_or(false, |hash| hash != header.previous)
+            {
+                warn!(
+                    "Inconsistent block on the disk: height={}, block={}",
+                    height,
+                    Hash::digest(&block)
+                );
+                continue;
+            }
+            check.height += 1;
+            check.last_block_hash = Some(Hash::digest(&block));
+        }
+        check
+    }
+
+    /// Remove all records starting from height.
+    /// Returns the number of removed records.
+    pub fn truncate(&self, height: u64) -> Result<u64, Error> {
+        self.check_writable()?;
+        let key = Self::key_u64_to_bytes(height);
+        let mode = IteratorMode::From(&key, Direction::Forward);
+        let mut batch = ListDbBatch::default();
+        let mut removed: u64 = 0;
+        let mut damaged = false;
+        for (k, v) in self.database.iterator(mode) {
+            if k.len() != BLOCK_KEY_LEN {
+                break; // Block records go first.
+            }
+            match Block::from_buffer(&v) {
+                Ok(block) => batch.unindex(&block)?,
+                Err(_) => damaged = true,
+            }
+            batch.delete(&k)?;
+            removed += 1;
+        }
+        // Indexes of damaged blocks can't be removed, rebuild them from scratch.
+        if damaged {
+            batch.delete(INDEXES_KEY)?;
+        }
+        // Summaries of epochs finalized by removed macro blocks, the last epochs first.
+        let key = Self::epoch_summary_key(std::u64::MAX);
+        let mode = IteratorMode::From(&key, Direction::Reverse);
+        for (k, v) in self.database.iterator(mode) {
+            if !k.starts_with(EPOCH_SUMMARY_PREFIX) {
+                break;
+            }
+            match EpochSummary::from_buffer(&v) {
+                Ok(summary) if summary.macro_block_height < height => break,
+                _ => batch.delete(&k)?,
+            }
+        }
+        let last_block = if height > 0 {
+            self.get(height - 1)?
+        } else {
+            None
+        };
+        match last_block {
+            Some(block) => batch.set_tip(height, &Hash::digest(&block))?,
+            None => batch.delete(TIP_KEY)?,
+        }
+        self.write_sync(batch)?;
+        Ok(removed)
+    }
+
+    /// Compact the whole database, discarding removed and overwritten records.
+    pub fn compact(&self) {
+        Self::compact_db(&self.database);
+    }
+
+    /// Compact the database in the background every `interval` while it is open.
+    pub fn start_compaction(&self, interval: Duration) {
+        let database = Arc::downgrade(&self.database);
+        thread::Builder::new()
+            .name("db-compaction".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                match database.upgrade() {
+                    Some(database) => Self::compact_db(&database),
+                    None => break, // The database has been closed.
+                }
+            })
+            .expect("couldn't spawn compaction thread");
+    }
+
+    fn compact_db(database: &DB) {
+        debug!("Compacting the database...");
+        let started = Instant::now();
+        database.compact_range(None::<&[u8]>, None::<&[u8]>);
+        info!(
+            "Compacted the database: duration={}ms",
+            started.elapsed().as_millis()
+        );
+    }
+
+    /// Flush all pending writes to the disk.
+    pub fn flush(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Ok(());
+        }
+        // A synchronous write syncs the write-ahead log with all previous writes.
+        self.database
+            .write_opt(WriteBatch::default(), &Self::sync_write_options())?;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(format_err!("The database is opened read-only"));
+        }
+        Ok(())
+    }
+
+    fn sync_write_options() -> WriteOptions {
+        let mut options = WriteOptions::default();
+        options.set_sync(true);
+        options
+    }
+
     fn key_u64_to_bytes(len: u64) -> [u8; 8] {
         let mut bytes = [0u8; 8];
         BigEndian::write_u64(&mut bytes, len);
         bytes
     }
+
+    fn index_key(prefix: &[u8], hash: &Hash) -> Vec<u8> {
+        let mut key = Vec::with_capacity(prefix.len() + HASH_SIZE);
+        key.extend_from_slice(prefix);
+        key.extend_from_slice(hash.base_vector());
+        key
+    }
+
+    fn encode_output_key(output_key: &OutputKey) -> Vec<u8> {
+        let mut data = Vec::with_capacity(1 + BLOCK_KEY_LEN + 8);
+        match output_key {
+            OutputKey::MacroBlock { height, path } => {
+                data.push(0);
+                data.extend_from_slice(&Self::key_u64_to_bytes(*height));
+                data.extend_from_slice(&path.0.to_be_bytes());
+            }
+            OutputKey::MicroBlock {
+                height,
+                tx_id,
+                txout_id,
+            } => {
+                data.push(1);
+                data.extend_from_slice(&Self::key_u64_to_bytes(*height));
+                data.extend_from_slice(&tx_id.to_be_bytes());
+                data.extend_from_slice(&txout_id.to_be_bytes());
+            }
+        }
+        data
+    }
+
+    fn decode_output_key(buffer: &[u8]) -> Result<OutputKey, Error> {
+        let invalid = || format_err!("Invalid output record: len={}", buffer.len());
+        let (kind, buffer) = buffer.split_first().ok_or_else(invalid)?;
+        if buffer.len() < BLOCK_KEY_LEN {
+            return Err(invalid());
+        }
+        let height = BigEndian::read_u64(&buffer[..BLOCK_KEY_LEN]);
+        let buffer = &buffer[BLOCK_KEY_LEN..];
+        match (kind, buffer.len()) {
+            (0, 4) => Ok(OutputKey::MacroBlock {
+                height,
+                path: MerklePath(BigEndian::read_u32(buffer)),
+            }),
+            (1, 8) => Ok(OutputKey::MicroBlock {
+                height,
+                tx_id: BigEndian::read_u32(&buffer[..4]),
+                txout_id: BigEndian::read_u32(&buffer[4..]),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn epoch_summary_key(epoch: u64) -> Vec<u8> {
+        let mut key = Vec::with_capacity(EPOCH_SUMMARY_PREFIX.len() + BLOCK_KEY_LEN);
+        key.extend_from_slice(EPOCH_SUMMARY_PREFIX);
+        key.extend_from_slice(&Self::key_u64_to_bytes(epoch));
+        key
+    }
+}
+
+/// Result of `ListDb::check()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainCheck {
+    /// The number of records which form a consistent hash chain.
+    pub height: u64,
+    /// Hash of the last consistent record.
+    pub last_block_hash: Option<Hash>,
+    /// The total number of records.
+    pub records: u64,
+    /// The height and the hash of the last block, as written together with blocks.
+    pub tip: Option<(u64, Hash)>,
+}
+
+impl ChainCheck {
+    /// Returns true if all records are consistent.
+    pub fn is_consistent(&self) -> bool {
+        self.height == self.records
+            && self.tip.map_or(true, |(height, last_block_hash)| {
+                height == self.height && Some(last_block_hash) == self.last_block_hash
+            })
+    }
+}
+
+/// Changes to ListDb which are committed atomically by `ListDb::write()`.
+#[derive(Default)]
+pub struct ListDbBatch {
+    batch: WriteBatch,
+    len: usize,
+}
+
+impl ListDbBatch {
+    /// Add a record.
+    pub fn insert(&mut self, height: u64, block: &Block) -> Result<(), Error> {
+        let data = block.into_buffer().expect("couldn't serialize block.");
+        // writebatch put fails if size exceeded u32::max, which is not our case.
+        self.batch.put(&ListDb::key_u64_to_bytes(height), &data)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Record the height and the hash of the last block.
+    pub fn set_tip(&mut self, height: u64, last_block_hash: &Hash) -> Result<(), Error> {
+        let mut data = Vec::with_capacity(BLOCK_KEY_LEN + HASH_SIZE);
+        data.extend_from_slice(&ListDb::key_u64_to_bytes(height));
+        data.extend_from_slice(last_block_hash.base_vector());
+        self.batch.put(TIP_KEY, &data)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Add the summary of an epoch, written together with its macro block.
+    pub fn insert_epoch_summary(&mut self, summary: &EpochSummary) -> Result<(), Error> {
+        let data = summary
+            .into_buffer()
+            .expect("couldn't serialize epoch summary.");
+        self.batch
+            .put(&ListDb::epoch_summary_key(summary.epoch), &data)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Index outputs, transactions and spent outputs of the block.
+    pub fn index(&mut self, height: u64, block: &Block) -> Result<(), Error> {
+        let spent = ListDb::key_u64_to_bytes(height);
+        match block {
+            Block::MacroBlock(block) => {
+                for input_hash in &block.body.inputs {
+                    self.put(&ListDb::index_key(SPENT_PREFIX, input_hash), &spent)?;
+                }
+                for (output, path) in block.body.outputs.leafs() {
+                    let output_key = OutputKey::MacroBlock { height, path };
+                    let key = ListDb::index_key(OUTPUT_PREFIX, &Hash::digest(output.as_ref()));
+                    self.put(&key, &ListDb::encode_output_key(&output_key))?;
+                }
+            }
+            Block::MicroBlock(block) => {
+                for (tx_id, tx) in block.transactions.iter().enumerate() {
+                    let tx_id = tx_id as u32;
+                    let mut data = Vec::with_capacity(BLOCK_KEY_LEN + 4);
+                    data.extend_from_slice(&spent);
+                    data.extend_from_slice(&tx_id.to_be_bytes());
+                    self.put(&ListDb::index_key(TX_PREFIX, &Hash::digest(tx)), &data)?;
+                    for input_hash in tx.txins() {
+                        self.put(&ListDb::index_key(SPENT_PREFIX, input_hash), &spent)?;
+                    }
+                    for (txout_id, output) in tx.txouts().iter().enumerate() {
+                        let output_key = OutputKey::MicroBlock {
+                            height,
+                            tx_id,
+                            txout_id: txout_id as u32,
+                        };
+                        let key = ListDb::index_key(OUTPUT_PREFIX, &Hash::digest(output));
+                        self.put(&key, &ListDb::encode_output_key(&output_key))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove records added by `index()` for the block.
+    pub fn unindex(&mut self, block: &Block) -> Result<(), Error> {
+        match block {
+            Block::MacroBlock(block) => {
+                for input_hash in &block.body.inputs {
+                    self.delete(&ListDb::index_key(SPENT_PREFIX, input_hash))?;
+                }
+                for (output, _path) in block.body.outputs.leafs() {
+                    let key = ListDb::index_key(OUTPUT_PREFIX, &Hash::digest(output.as_ref()));
+                    self.delete(&key)?;
+                }
+            }
+            Block::MicroBlock(block) => {
+                for tx in &block.transactions {
+                    self.delete(&ListDb::index_key(TX_PREFIX, &Hash::digest(tx)))?;
+                    for input_hash in tx.txins() {
+                        self.delete(&ListDb::index_key(SPENT_PREFIX, input_hash))?;
+                    }
+                    for output in tx.txouts() {
+                        self.delete(&ListDb::index_key(OUTPUT_PREFIX, &Hash::digest(output)))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that outputs, transactions and spent outputs are indexed for all blocks.
+    pub fn set_indexes(&mut self) -> Result<(), Error> {
+        self.put(INDEXES_KEY, &[])
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.batch.put(key, value)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove record by id.
+    pub fn remove(&mut self, height: u64) -> Result<(), Error> {
+        self.delete(&ListDb::key_u64_to_bytes(height))
+    }
+
+    /// Remove record by raw key, which may be damaged.
+    fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        self.batch.delete(key)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Number of changes in the batch.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the batch has no changes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::block::{BaseBlockHeader, MacroBlock};
+    use crate::block::{BaseBlockHeader, MacroBlock, MicroBlock};
+    use crate::epoch_summary::{EpochLeader, ValidatorUptime};
+    use crate::output::Output;
+    use crate::transaction::CoinbaseTransaction;
+    use bitvector::BitVector;
     use std::time::SystemTime;
-    use stegos_crypto::hash::Hash;
+    use stegos_crypto::curve1174;
+    use stegos_crypto::curve1174::Fr;
     use stegos_crypto::pbc;
 
     fn create_block(previous: Hash) -> Block {
+        create_block_at(previous, 1)
+    }
+
+    fn create_block_at(previous: Hash, height: u64) -> Block {
         let (skey0, pkey0) = pbc::make_random_keys();
         let version: u64 = 1;
-        let epoch: u64 = 1;
         let timestamp = SystemTime::now();
 
         let random = pbc::make_VRF(&skey0, &Hash::digest("random"));
-        let base = BaseBlockHeader::new(version, previous, epoch, 0, timestamp, random);
+        let base = BaseBlockHeader::new(
+            version,
+            Hash::zero(),
+            previous,
+            height,
+            0,
+            timestamp,
+            random,
+        );
 
         let block = MacroBlock::empty(base, pkey0);
         Block::MacroBlock(block)
@@ -175,6 +715,32 @@ mod test {
             assert_eq!(Hash::digest(block), Hash::digest(&saved));
         }
     }
+
+    #[test]
+    fn batch() {
+        let previous = Hash::digest(&"test".to_string());
+        let block1 = create_block(previous);
+        let block2 = create_block(Hash::digest(&block1));
+        let block3 = create_block(Hash::digest(&block2));
+
+        let db = ListDb::testing();
+        db.insert(0, block1.clone()).unwrap();
+
+        let mut batch = ListDbBatch::default();
+        batch.remove(0).unwrap();
+        batch.insert(1, &block2).unwrap();
+        batch.insert(2, &block3).unwrap();
+        assert_eq!(batch.len(), 3);
+        // Nothing is visible until the batch is written.
+        assert!(db.get(0).unwrap().is_some());
+        assert!(db.get(1).unwrap().is_none());
+
+        db.write(batch).unwrap();
+        assert!(db.get(0).unwrap().is_none());
+        let saved: Vec<Hash> = db.iter().map(|b| Hash::digest(&b)).collect();
+        assert_eq!(saved, vec![Hash::digest(&block2), Hash::digest(&block3)]);
+    }
+
     #[test]
     fn iter_order() {
         let previous = Hash::digest(&"test".to_string());
@@ -195,4 +761,261 @@ mod test {
             assert_eq!(Hash::digest(block), Hash::digest(&saved));
         }
     }
+
+    #[test]
+    fn check_and_truncate() {
+        let db = ListDb::testing();
+        let mut previous = Hash::digest("genesis");
+        for height in 0..5 {
+            let block = create_block_at(previous, height);
+            previous = Hash::digest(&block);
+            db.insert(height, block).unwrap();
+        }
+        let check = db.check();
+        assert!(check.is_consistent());
+        assert_eq!(check.height, 5);
+        assert_eq!(check.last_block_hash, Some(previous));
+
+        // Break the hash chain at height 3.
+        let block = create_block_at(Hash::digest("fork"), 3);
+        db.insert(3, block).unwrap();
+        let check = db.check();
+        assert!(!check.is_consistent());
+        assert_eq!(check.height, 3);
+        assert_eq!(check.records, 5);
+
+        // A damaged record.
+        db.database
+            .put(&ListDb::key_u64_to_bytes(2), b"garbage")
+            .unwrap();
+        let check = db.check();
+        assert_eq!(check.height, 2);
+
+        assert_eq!(db.truncate(check.height).unwrap(), 3);
+        let check = db.check();
+        assert!(check.is_consistent());
+        assert_eq!(check.height, 2);
+        db.compact();
+    }
+
+    #[test]
+    fn epoch_summaries() {
+        let db = ListDb::testing();
+        let (_skey, pkey) = pbc::make_random_keys();
+        let (_wallet_skey, wallet_pkey) = stegos_crypto::curve1174::make_random_keys();
+        let summary = |epoch: u64, macro_block_height: u64| EpochSummary {
+            epoch,
+            macro_block_height,
+            macro_block_hash: Hash::digest(&macro_block_height),
+            timestamp: std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_500_000_000),
+            leaders: vec![EpochLeader {
+                height: macro_block_height - 1,
+                leader: pkey,
+                view_change: 1,
+            }],
+            view_changes: 1,
+            transactions: 2,
+            fees: 3,
+            micro_block_rewards: 4,
+            macro_block_reward: 5,
+            service_award: Some((wallet_pkey, 6)),
+            validators: vec![ValidatorUptime {
+                network_pkey: pkey,
+                slots: 1000,
+                produced: 1,
+                missed: 0,
+                active: true,
+            }],
+        };
+        let mut previous = Hash::digest("genesis");
+        let mut batch = ListDbBatch::default();
+        for height in 0..6 {
+            let block = create_block_at(previous, height);
+            previous = Hash::digest(&block);
+            batch.insert(height, &block).unwrap();
+        }
+        batch.insert_epoch_summary(&summary(1, 2)).unwrap();
+        batch.insert_epoch_summary(&summary(2, 4)).unwrap();
+        db.write(batch).unwrap();
+        assert_eq!(db.epoch_summary(0).unwrap(), None);
+        assert_eq!(db.epoch_summary(1).unwrap(), Some(summary(1, 2)));
+        assert_eq!(db.epoch_summary(2).unwrap(), Some(summary(2, 4)));
+        // Summaries are not blocks.
+        assert_eq!(db.iter().count(), 6);
+        assert!(db.check().is_consistent());
+
+        // Summaries of removed macro blocks are removed too.
+        assert_eq!(db.truncate(4).unwrap(), 2);
+        assert_eq!(db.epoch_summary(1).unwrap(), Some(summary(1, 2)));
+        assert_eq!(db.epoch_summary(2).unwrap(), None);
+    }
+
+    #[test]
+    fn indexes() {
+        let db = ListDb::testing();
+        let (skey, pkey) = pbc::make_random_keys();
+        let (_wallet_skey, wallet_pkey) = curve1174::make_random_keys();
+        let (output0, _gamma) = Output::new_payment(&wallet_pkey, 100).unwrap();
+        let (output1, _gamma) = Output::new_payment(&wallet_pkey, 100).unwrap();
+        let (output2, _gamma) = Output::new_payment(&wallet_pkey, 100).unwrap();
+        let output0_hash = Hash::digest(&output0);
+        let output1_hash = Hash::digest(&output1);
+        let output2_hash = Hash::digest(&output2);
+        let base = |previous: Hash, height: u64| {
+            let random = pbc::make_VRF(&skey, &Hash::digest(&height));
+            BaseBlockHeader::new(
+                1,
+                Hash::zero(),
+                previous,
+                height,
+                0,
+                SystemTime::now(),
+                random,
+            )
+        };
+        let block0 = MacroBlock::new(
+            base(Hash::digest("genesis"), 0),
+            Fr::zero(),
+            0,
+            BitVector::new(0),
+            &[],
+            &[output0],
+            pkey,
+        );
+        let block0 = Block::MacroBlock(block0);
+        let coinbase = CoinbaseTransaction {
+            txouts: vec![output1],
+            ..Default::default()
+        };
+        let tx_hash = Hash::digest(&coinbase);
+        let block1 = MicroBlock::new(
+            base(Hash::digest(&block0), 1),
+            None,
+            vec![coinbase.into()],
+            pkey,
+        );
+        let block1 = Block::MicroBlock(block1);
+        let block2 = MacroBlock::new(
+            base(Hash::digest(&block1), 2),
+            Fr::zero(),
+            0,
+            BitVector::new(0),
+            &[output0_hash],
+            &[output2],
+            pkey,
+        );
+        let block2 = Block::MacroBlock(block2);
+
+        assert!(!db.has_indexes().unwrap());
+        let mut batch = ListDbBatch::default();
+        batch.set_indexes().unwrap();
+        for (height, block) in [block0, block1, block2].iter().enumerate() {
+            batch.insert(height as u64, block).unwrap();
+            batch.index(height as u64, block).unwrap();
+        }
+        db.write(batch).unwrap();
+        assert!(db.has_indexes().unwrap());
+        // Indexes are not blocks.
+        assert_eq!(db.iter().count(), 3);
+        assert!(db.check().is_consistent());
+
+        match db.output_key(&output0_hash).unwrap() {
+            Some(OutputKey::MacroBlock { height: 0, .. }) => {}
+            key => panic!("Invalid key: {:?}", key),
+        }
+        match db.output_key(&output1_hash).unwrap() {
+            Some(OutputKey::MicroBlock {
+                height: 1,
+                tx_id: 0,
+                txout_id: 0,
+            }) => {}
+            key => panic!("Invalid key: {:?}", key),
+        }
+        match db.output_key(&output2_hash).unwrap() {
+            Some(OutputKey::MacroBlock { height: 2, .. }) => {}
+            key => panic!("Invalid key: {:?}", key),
+        }
+        match db.tx_key(&tx_hash).unwrap() {
+            Some(TransactionKey {
+                height: 1,
+                tx_id: 0,
+            }) => {}
+            key => panic!("Invalid key: {:?}", key),
+        }
+        assert_eq!(db.spent_height(&output0_hash).unwrap(), Some(2));
+        assert_eq!(db.spent_height(&output1_hash).unwrap(), None);
+
+        // Indexes of removed blocks are removed too.
+        assert_eq!(db.truncate(1).unwrap(), 2);
+        assert!(db.output_key(&output0_hash).unwrap().is_some());
+        assert!(db.output_key(&output1_hash).unwrap().is_none());
+        assert!(db.output_key(&output2_hash).unwrap().is_none());
+        assert!(db.tx_key(&tx_hash).unwrap().is_none());
+        assert_eq!(db.spent_height(&output0_hash).unwrap(), None);
+        assert!(db.has_indexes().unwrap());
+
+        db.drop_indexes().unwrap();
+        assert!(!db.has_indexes().unwrap());
+        assert!(db.output_key(&output0_hash).unwrap().is_none());
+        assert_eq!(db.iter().count(), 1);
+    }
+
+    #[test]
+    fn tip() {
+        let db = ListDb::testing();
+        assert_eq!(db.tip().unwrap(), None);
+        let block1 = create_block_at(Hash::digest("genesis"), 0);
+        let block2 = create_block_at(Hash::digest(&block1), 1);
+
+        let mut batch = ListDbBatch::default();
+        batch.insert(0, &block1).unwrap();
+        batch.set_tip(1, &Hash::digest(&block1)).unwrap();
+        db.write(batch).unwrap();
+        let mut batch = ListDbBatch::default();
+        batch.insert(1, &block2).unwrap();
+        batch.set_tip(2, &Hash::digest(&block2)).unwrap();
+        db.write(batch).unwrap();
+        assert_eq!(db.tip().unwrap(), Some((2, Hash::digest(&block2))));
+        // The tip record is not a block.
+        assert_eq!(db.iter().count(), 2);
+        assert_eq!(db.iter_starting(1).count(), 1);
+        assert!(db.check().is_consistent());
+
+        // A block written without the tip.
+        let block3 = create_block_at(Hash::digest(&block2), 2);
+        db.insert(2, block3).unwrap();
+        let check = db.check();
+        assert_eq!(check.height, 3);
+        assert!(!check.is_consistent());
+
+        assert_eq!(db.truncate(2).unwrap(), 1);
+        assert_eq!(db.tip().unwrap(), Some((2, Hash::digest(&block2))));
+        assert!(db.check().is_consistent());
+        assert_eq!(db.truncate(0).unwrap(), 2);
+        assert_eq!(db.tip().unwrap(), None);
+    }
+
+    #[test]
+    fn read_only() {
+        let temp_dir = TempDir::new("read_only").expect("couldn't create temp dir");
+        assert!(ListDb::open_read_only(temp_dir.path().join("missing")).is_err());
+
+        let block = create_block(Hash::digest("genesis"));
+        // The database is still open by the writer.
+        let writer = ListDb::new(temp_dir.path());
+        writer.insert(0, block.clone()).unwrap();
+        let db = ListDb::open_read_only(temp_dir.path()).unwrap();
+        assert!(db.is_read_only());
+        assert_eq!(
+            Hash::digest(&db.get(0).unwrap().unwrap()),
+            Hash::digest(&block)
+        );
+        assert!(db.insert(1, block.clone()).is_err());
+        assert!(db.truncate(0).is_err());
+        assert!(db.get(0).unwrap().is_some());
+        db.flush().unwrap();
+        writer
+            .insert(1, create_block(Hash::digest(&block)))
+            .unwrap();
+    }
 }
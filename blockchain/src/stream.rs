@@ -0,0 +1,403 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Streaming decoding of micro blocks.
+//!
+//! Transactions are kept in the wire format and decoded one-by-one on demand,
+//! so an invalid block is rejected before all transactions are decoded.
+//! A valid block is decoded only once, see `ValidatedMicroBlock`.
+//!
+
+use crate::block::{BaseBlockHeader, MicroBlock};
+use crate::transaction::Transaction;
+use crate::view_changes::ViewChangeProof;
+use failure::{format_err, Error};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc;
+use stegos_serialization::traits::ProtoConvert;
+
+// Field numbers from blockchain.proto.
+const BLOCK_MACRO_BLOCK: u32 = 1;
+const BLOCK_MICRO_BLOCK: u32 = 2;
+const MICRO_BLOCK_BASE: u32 = 1;
+const MICRO_BLOCK_VIEW_CHANGE_PROOF: u32 = 2;
+const MICRO_BLOCK_TRANSACTIONS: u32 = 4;
+const MICRO_BLOCK_PKEY: u32 = 5;
+const MICRO_BLOCK_SIG: u32 = 6;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, Error> {
+    let mut value: u64 = 0;
+    for i in 0..10 {
+        let byte = match data.get(i) {
+            Some(byte) => *byte,
+            None => return Err(format_err!("Unexpected end of varint")),
+        };
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(format_err!("Varint is too long"))
+}
+
+fn skip(data: &mut &[u8], len: usize) -> Result<(), Error> {
+    if data.len() < len {
+        return Err(format_err!("Unexpected end of message"));
+    }
+    *data = &data[len..];
+    Ok(())
+}
+
+/// Reads the next field of a protobuf message.
+/// Returns the field number and the payload for length-delimited fields.
+fn read_field<'a>(data: &mut &'a [u8]) -> Result<(u32, Option<&'a [u8]>), Error> {
+    let key = read_varint(data)?;
+    let field = (key >> 3) as u32;
+    match key & 0x7 {
+        WIRE_VARINT => {
+            read_varint(data)?;
+            Ok((field, None))
+        }
+        WIRE_FIXED64 => {
+            skip(data, 8)?;
+            Ok((field, None))
+        }
+        WIRE_LENGTH_DELIMITED => {
+            let len = read_varint(data)? as usize;
+            if data.len() < len {
+                return Err(format_err!("Unexpected end of message"));
+            }
+            let (payload, rest) = data.split_at(len);
+            *data = rest;
+            Ok((field, Some(payload)))
+        }
+        WIRE_FIXED32 => {
+            skip(data, 4)?;
+            Ok((field, None))
+        }
+        wire_type => Err(format_err!("Unsupported wire type: {}", wire_type)),
+    }
+}
+
+/// A micro block validated on the top of the chain by `validate_micro_block_stream()`.
+/// Pushed to the chain without validating it again, unless the chain has changed.
+pub struct ValidatedMicroBlock {
+    pub(crate) block: MicroBlock,
+    pub(crate) block_hash: Hash,
+    /// The last block of the chain at the moment of validation.
+    pub(crate) last_block: Hash,
+    /// The view change of the chain at the moment of validation.
+    pub(crate) view_change: u32,
+}
+
+impl ValidatedMicroBlock {
+    /// The decoded block.
+    pub fn block(&self) -> &MicroBlock {
+        &self.block
+    }
+
+    /// The hash of the block.
+    pub fn block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+
+    pub fn into_block(self) -> MicroBlock {
+        self.block
+    }
+}
+
+/// Returns the payload of a length-delimited field, like the generated code does.
+fn expect_payload(field: u32, payload: Option<&[u8]>) -> Result<&[u8], Error> {
+    payload.ok_or_else(|| format_err!("Unexpected wire type: field={}", field))
+}
+
+/// Appends an occurrence of a singular message field.
+/// Protobuf merges repeated occurrences, which is the same as parsing them concatenated.
+fn merge_payload(merged: &mut Option<Vec<u8>>, payload: &[u8]) {
+    merged
+        .get_or_insert_with(Vec::new)
+        .extend_from_slice(payload);
+}
+
+/// A serialized micro block with lazily decoded transactions.
+pub struct MicroBlockStream<'a> {
+    /// Decoded block without transactions.
+    header: MicroBlock,
+    /// Serialized transactions.
+    transactions: Vec<&'a [u8]>,
+    /// The size of serialized block.
+    size: usize,
+}
+
+impl<'a> MicroBlockStream<'a> {
+    /// Parses a serialized `Block`, returns None for macro blocks.
+    pub fn from_block_buffer(mut data: &'a [u8]) -> Result<Option<Self>, Error> {
+        // The last field of `oneof` wins, without merging.
+        let mut micro_block = None;
+        while !data.is_empty() {
+            match read_field(&mut data)? {
+                (BLOCK_MICRO_BLOCK, payload) => {
+                    micro_block = Some(expect_payload(BLOCK_MICRO_BLOCK, payload)?)
+                }
+                (BLOCK_MACRO_BLOCK, payload) => {
+                    expect_payload(BLOCK_MACRO_BLOCK, payload)?;
+                    micro_block = None;
+                }
+                _ => {} // ignore unknown fields.
+            }
+        }
+        match micro_block {
+            Some(payload) => Ok(Some(Self::from_buffer(payload)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses a serialized `MicroBlock`.
+    /// The result is the same as of the generated decoder, which merges repeated
+    /// occurrences of singular message fields and appends repeated fields.
+    pub fn from_buffer(buffer: &'a [u8]) -> Result<Self, Error> {
+        let mut data = buffer;
+        let mut base: Option<Vec<u8>> = None;
+        let mut view_change_proof: Option<Vec<u8>> = None;
+        let mut transactions: Vec<&'a [u8]> = Vec::new();
+        let mut pkey: Option<Vec<u8>> = None;
+        let mut sig: Option<Vec<u8>> = None;
+        while !data.is_empty() {
+            let (field, payload) = read_field(&mut data)?;
+            match field {
+                MICRO_BLOCK_BASE => merge_payload(&mut base, expect_payload(field, payload)?),
+                MICRO_BLOCK_VIEW_CHANGE_PROOF => {
+                    merge_payload(&mut view_change_proof, expect_payload(field, payload)?)
+                }
+                MICRO_BLOCK_TRANSACTIONS => transactions.push(expect_payload(field, payload)?),
+                MICRO_BLOCK_PKEY => merge_payload(&mut pkey, expect_payload(field, payload)?),
+                MICRO_BLOCK_SIG => merge_payload(&mut sig, expect_payload(field, payload)?),
+                _ => {} // ignore unknown fields.
+            }
+        }
+
+        let base = BaseBlockHeader::from_buffer(&base.unwrap_or_default())?;
+        let view_change_proof = match view_change_proof {
+            Some(payload) => Some(ViewChangeProof::from_buffer(&payload)?),
+            None => None,
+        };
+        let pkey = pbc::PublicKey::from_buffer(&pkey.unwrap_or_default())?;
+        let mut header = MicroBlock::empty(base, view_change_proof, pkey);
+        if let Some(payload) = sig {
+            header.sig = pbc::Signature::from_buffer(&payload)?;
+        }
+        Ok(MicroBlockStream {
+            header,
+            transactions,
+            size: buffer.len(),
+        })
+    }
+
+    /// Block header, `transactions` are always empty.
+    pub fn header(&self) -> &MicroBlock {
+        &self.header
+    }
+
+    /// The size of serialized block.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of transactions.
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Decodes transactions one-by-one.
+    pub fn transactions<'b>(&'b self) -> impl Iterator<Item = Result<Transaction, Error>> + 'b {
+        self.transactions
+            .iter()
+            .map(|payload| Transaction::from_buffer(payload))
+    }
+
+    /// Calculates the hash of the block, same as `Hash::digest(&MicroBlock)`.
+    pub fn hash(&self) -> Result<Hash, Error> {
+        let mut state = Hasher::new();
+        "Micro".hash(&mut state);
+        self.header.base.hash(&mut state);
+        if let Some(proof) = &self.header.view_change_proof {
+            proof.hash(&mut state);
+        }
+        let tx_count: u64 = self.transactions.len() as u64;
+        tx_count.hash(&mut state);
+        for tx in self.transactions() {
+            tx?.fullhash(&mut state);
+        }
+        self.header.pkey.hash(&mut state);
+        Ok(state.result())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::election::mix;
+    use crate::transaction::PaymentTransaction;
+    use std::time::SystemTime;
+    use stegos_crypto::curve1174;
+
+    fn test_block(height: u64, transactions: usize) -> MicroBlock {
+        let (skey, pkey) = curve1174::make_random_keys();
+        let (skeypbc, pkeypbc) = pbc::make_random_keys();
+
+        let chain_id = Hash::digest("test");
+        let previous = Hash::digest(&"test".to_string());
+        let seed = mix(Hash::digest("random"), 0);
+        let random = pbc::make_VRF(&skeypbc, &seed);
+        let base =
+            BaseBlockHeader::new(1, chain_id, previous, height, 0, SystemTime::now(), random);
+        let mut txs: Vec<Transaction> = Vec::new();
+        for _ in 0..transactions {
+            let (tx, _inputs, _outputs) =
+                PaymentTransaction::new_test(&chain_id, &skey, &pkey, 300, 2, 100, 1, 100)
+                    .expect("Invalid transaction");
+            txs.push(tx.into());
+        }
+        let mut block = MicroBlock::new(base, None, txs, pkeypbc);
+        block.sign(&skeypbc, &pkeypbc).unwrap();
+        block
+    }
+
+    /// Encodes a length-delimited field.
+    fn field(number: u32, payload: &[u8]) -> Vec<u8> {
+        fn varint(mut value: u64, out: &mut Vec<u8>) {
+            while value >= 0x80 {
+                out.push((value as u8) | 0x80);
+                value >>= 7;
+            }
+            out.push(value as u8);
+        }
+        let mut out = Vec::new();
+        varint(((number as u64) << 3) | WIRE_LENGTH_DELIMITED, &mut out);
+        varint(payload.len() as u64, &mut out);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// The stream must decode the same block as the generated decoder.
+    fn check_same_as_generated(data: &[u8]) {
+        let block = MicroBlock::from_buffer(data).unwrap();
+        let stream = MicroBlockStream::from_buffer(data).unwrap();
+        assert_eq!(stream.hash().unwrap(), Hash::digest(&block));
+        assert_eq!(stream.header().base.height, block.base.height);
+        assert_eq!(stream.header().sig, block.sig);
+        assert_eq!(stream.len(), block.transactions.len());
+        for (tx, expected) in stream.transactions().zip(block.transactions.iter()) {
+            assert_eq!(Hash::digest(&tx.unwrap()), Hash::digest(expected));
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let block = test_block(0, 3);
+        let block_hash = Hash::digest(&block);
+
+        let data = block.into_buffer().unwrap();
+        let stream = MicroBlockStream::from_buffer(&data).unwrap();
+        assert_eq!(stream.size(), data.len());
+        assert_eq!(stream.len(), 3);
+        assert!(stream.header().transactions.is_empty());
+        assert_eq!(stream.header().sig, block.sig);
+        assert_eq!(stream.hash().unwrap(), block_hash);
+        for (tx, expected) in stream.transactions().zip(block.transactions.iter()) {
+            assert_eq!(Hash::digest(&tx.unwrap()), Hash::digest(expected));
+        }
+
+        let data = Block::MicroBlock(block).into_buffer().unwrap();
+        let stream = MicroBlockStream::from_block_buffer(&data).unwrap().unwrap();
+        assert_eq!(stream.hash().unwrap(), block_hash);
+
+        // Truncated.
+        assert!(MicroBlockStream::from_block_buffer(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn duplicate_fields() {
+        let block = test_block(0, 2);
+        let data = block.into_buffer().unwrap();
+        check_same_as_generated(&data);
+
+        // A repeated singular message is merged, the last scalar wins.
+        let other = test_block(7, 1);
+        let mut duplicate_base = data.clone();
+        duplicate_base.extend(field(MICRO_BLOCK_BASE, &other.base.into_buffer().unwrap()));
+        check_same_as_generated(&duplicate_base);
+        assert_eq!(
+            MicroBlockStream::from_buffer(&duplicate_base)
+                .unwrap()
+                .header()
+                .base
+                .height,
+            7
+        );
+
+        // Repeated fields are appended, even after other fields.
+        let mut more_transactions = data.clone();
+        let tx = other.transactions[0].into_buffer().unwrap();
+        more_transactions.extend(field(MICRO_BLOCK_TRANSACTIONS, &tx));
+        more_transactions.extend(field(MICRO_BLOCK_SIG, &other.sig.into_buffer().unwrap()));
+        check_same_as_generated(&more_transactions);
+        assert_eq!(
+            MicroBlockStream::from_buffer(&more_transactions)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // Unknown fields are skipped.
+        let mut unknown = data.clone();
+        unknown.extend(field(15, b"unknown"));
+        check_same_as_generated(&unknown);
+
+        // Known fields with a wrong wire type are rejected by both.
+        let mut wrong_type = data.clone();
+        wrong_type.extend(&[(MICRO_BLOCK_BASE << 3) as u8 | WIRE_VARINT as u8, 1]);
+        assert!(MicroBlock::from_buffer(&wrong_type).is_err());
+        assert!(MicroBlockStream::from_buffer(&wrong_type).is_err());
+
+        // The last block of `oneof` wins.
+        let mut blocks = Block::MicroBlock(block).into_buffer().unwrap();
+        blocks.extend(Block::MicroBlock(other.clone()).into_buffer().unwrap());
+        let stream = MicroBlockStream::from_block_buffer(&blocks)
+            .unwrap()
+            .unwrap();
+        match Block::from_buffer(&blocks).unwrap() {
+            Block::MicroBlock(decoded) => {
+                assert_eq!(stream.hash().unwrap(), Hash::digest(&decoded));
+                assert_eq!(Hash::digest(&decoded), Hash::digest(&other));
+            }
+            Block::MacroBlock(_) => panic!("expected a micro block"),
+        }
+    }
+}
@@ -21,6 +21,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::channel::ChannelWitness;
 use crate::error::*;
 use crate::output::*;
 use crate::SlashingProof;
@@ -141,6 +142,8 @@ impl SlashingTransaction {
 /// PaymentTransaction.
 #[derive(Clone, Debug)]
 pub struct PaymentTransaction {
+    /// Identifier of the chain, see `BlockchainConfig::chain_id`.
+    pub chain_id: Hash,
     /// List of inputs.
     pub txins: Vec<Hash>,
     /// List of outputs.
@@ -151,10 +154,21 @@ pub struct PaymentTransaction {
     pub fee: i64,
     /// Transaction signature.
     pub sig: SchnorrSig,
+    /// Preimages of hash-locked inputs, revealed to redeem them.
+    /// Hash-locked inputs without a preimage are refunded.
+    pub preimages: Vec<(Hash, Vec<u8>)>,
+    /// Witnesses of payment channel inputs.
+    pub channels: Vec<ChannelWitness>,
 }
 
+/// Maximal length of a preimage of hash-locked UTXO.
+pub const MAX_PREIMAGE_LEN: usize = 64;
+
 impl Hashable for PaymentTransaction {
     fn hash(&self, state: &mut Hasher) {
+        // Sign chain, to prevent replays on other chains.
+        self.chain_id.hash(state);
+
         // Sign txins.
         let txins_count: u64 = self.txins.len() as u64;
         txins_count.hash(state);
@@ -174,17 +188,42 @@ impl Hashable for PaymentTransaction {
 
         // Sign fee.
         (self.fee as u64).hash(state);
+
+        // Sign preimages, if any.
+        if !self.preimages.is_empty() {
+            "Preimages".hash(state);
+            let preimages_count: u64 = self.preimages.len() as u64;
+            preimages_count.hash(state);
+            for (txin_hash, preimage) in &self.preimages {
+                txin_hash.hash(state);
+                (preimage.len() as u64).hash(state);
+                preimage.hash(state);
+            }
+        }
+
+        // Sign channel witnesses, if any.
+        if !self.channels.is_empty() {
+            "Channels".hash(state);
+            let channels_count: u64 = self.channels.len() as u64;
+            channels_count.hash(state);
+            for witness in &self.channels {
+                witness.hash(state);
+            }
+        }
     }
 }
 
 impl PaymentTransaction {
     pub fn dum() -> Self {
         PaymentTransaction {
+            chain_id: Hash::zero(),
             txins: Vec::new(),
             txouts: Vec::new(),
             gamma: Fr::zero(),
             fee: 0,
             sig: SchnorrSig::new(),
+            preimages: Vec::new(),
+            channels: Vec::new(),
         }
     }
 
@@ -192,6 +231,7 @@ impl PaymentTransaction {
     ///
     /// # Arguments
     ///
+    /// * `chain_id` - Identifier of the chain
     /// * `skey` - Sender's secret key
     /// * `inputs` - UXTO to spent
     /// * `outputs` - UXTO to create
@@ -199,6 +239,7 @@ impl PaymentTransaction {
     /// * `fee` - Total Fee
     ///
     pub fn new(
+        chain_id: &Hash,
         skey: &SecretKey,
         inputs: &[Output],
         outputs: &[Output],
@@ -206,14 +247,76 @@ impl PaymentTransaction {
         fee: i64,
     ) -> Result<Self, Error> {
         assert!(fee >= 0);
-        let tx = Self::unchecked(skey, inputs, outputs, outputs_gamma, fee)?;
+        let tx = Self::unchecked(chain_id, skey, inputs, outputs, outputs_gamma, fee)?;
         Ok(tx)
     }
 
     /// Same as new(), but without checks and assertions.
     pub fn unchecked(
+        chain_id: &Hash,
+        skey: &SecretKey,
+        inputs: &[Output],
+        outputs: &[Output],
+        outputs_gamma: &Fr, // = sum(outputs.gamma)
+        fee: i64,
+    ) -> Result<Self, Error> {
+        Self::with_witnesses(
+            chain_id,
+            skey,
+            inputs,
+            Vec::new(),
+            Vec::new(),
+            outputs,
+            outputs_gamma,
+            fee,
+        )
+    }
+
+    /// Create a new transaction which spends hash-locked inputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `preimages` - (input hash, preimage) for each hash-locked input to redeem,
+    ///                 other hash-locked inputs are refunded.
+    ///
+    /// See new() for other arguments.
+    ///
+    pub fn with_preimages(
+        chain_id: &Hash,
+        skey: &SecretKey,
+        inputs: &[Output],
+        preimages: Vec<(Hash, Vec<u8>)>,
+        outputs: &[Output],
+        outputs_gamma: &Fr, // = sum(outputs.gamma)
+        fee: i64,
+    ) -> Result<Self, Error> {
+        Self::with_witnesses(
+            chain_id,
+            skey,
+            inputs,
+            preimages,
+            Vec::new(),
+            outputs,
+            outputs_gamma,
+            fee,
+        )
+    }
+
+    /// Create a new transaction which spends payment channel inputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - witnesses of channel inputs. Cooperative witnesses should
+    ///                be signed by the counterparty with cosign() afterwards.
+    ///
+    /// See new() and with_preimages() for other arguments.
+    ///
+    pub fn with_witnesses(
+        chain_id: &Hash,
         skey: &SecretKey,
         inputs: &[Output],
+        preimages: Vec<(Hash, Vec<u8>)>,
+        channels: Vec<ChannelWitness>,
         outputs: &[Output],
         outputs_gamma: &Fr, // = sum(outputs.gamma)
         fee: i64,
@@ -247,11 +350,14 @@ impl PaymentTransaction {
 
         // Create a transaction body and calculate the hash.
         let mut tx = PaymentTransaction {
+            chain_id: *chain_id,
             txins,
             txouts: outputs.to_vec(),
             gamma: gamma_adj,
             fee,
             sig: SchnorrSig::new(),
+            preimages,
+            channels,
         };
 
         // Create an effective private key and sign transaction.
@@ -262,10 +368,31 @@ impl PaymentTransaction {
         Ok(tx)
     }
 
+    /// Returns the preimage revealed for the input, if any.
+    pub fn preimage(&self, txin_hash: &Hash) -> Option<&[u8]> {
+        self.preimages
+            .iter()
+            .find(|(hash, _preimage)| hash == txin_hash)
+            .map(|(_hash, preimage)| &preimage[..])
+    }
+
+    /// Add the signature of the counterparty to the cooperative close of a channel.
+    pub fn cosign(&mut self, txin_hash: &Hash, skey: &SecretKey) {
+        let tx_hash = Hasher::digest(self);
+        for witness in self.channels.iter_mut() {
+            if let ChannelWitness::Cooperative { txin, sig } = witness {
+                if txin == txin_hash {
+                    *sig = sign_hash(&tx_hash, skey);
+                }
+            }
+        }
+    }
+
     /// Create a new super-transaction.
     ///
     /// # Arguments
     ///
+    /// * `chain_id` - Identifier of the chain
     /// * `skey` - Sender's secret key to be used for partial signature
     /// * `k_val` - Sender's k seed for the partial signature
     /// * `sum_cap_k` - sum of all k*G from all participants
@@ -286,6 +413,7 @@ impl PaymentTransaction {
     /// corresponding to the cloaked recipient PublicKey in each TXIN.
     ///
     pub fn new_super_transaction(
+        chain_id: &Hash,
         skey: &SecretKey,
         k_val: &Fr,
         sum_cap_k: &ECp,
@@ -309,11 +437,14 @@ impl PaymentTransaction {
 
         // Create a transaction body and calculate the hash.
         let mut tx = PaymentTransaction {
+            chain_id: *chain_id,
             txins,
             txouts: outputs.to_vec(),
             gamma: gamma_adj.clone(),
             fee: total_fee,
             sig: SchnorrSig::new(),
+            preimages: Vec::new(),
+            channels: Vec::new(),
         };
 
         // Create an effective private key and sign transaction.
@@ -327,6 +458,7 @@ impl PaymentTransaction {
     //#[cfg(test)]
     #[doc(hidden)]
     pub fn new_test(
+        chain_id: &Hash,
         skey: &SecretKey,
         pkey: &PublicKey,
         input_amount: i64,
@@ -351,7 +483,7 @@ impl PaymentTransaction {
             outputs_gamma += gamma;
         }
 
-        match PaymentTransaction::new(&skey, &inputs, &outputs, &outputs_gamma, fee) {
+        match PaymentTransaction::new(chain_id, &skey, &inputs, &outputs, &outputs_gamma, fee) {
             Err(e) => Err(e),
             Ok(tx) => Ok((tx, inputs, outputs)),
         }
@@ -365,6 +497,8 @@ impl PaymentTransaction {
 /// RestakeTransaction.
 #[derive(Clone, Debug)]
 pub struct RestakeTransaction {
+    /// Identifier of the chain, see `BlockchainConfig::chain_id`.
+    pub chain_id: Hash,
     /// List of inputs.
     pub txins: Vec<Hash>,
     /// List of outputs.
@@ -375,6 +509,9 @@ pub struct RestakeTransaction {
 
 impl Hashable for RestakeTransaction {
     fn hash(&self, state: &mut Hasher) {
+        // Sign chain, to prevent replays on other chains.
+        self.chain_id.hash(state);
+
         // Sign txins.
         let txins_count: u64 = self.txins.len() as u64;
         txins_count.hash(state);
@@ -396,23 +533,26 @@ impl RestakeTransaction {
     ///
     /// # Arguments
     ///
+    /// * `chain_id` - Identifier of the chain
     /// * `skey` - Validator's secret key
     /// * `pkey` - Validator's public key
     /// * `inputs` - UXTO to spent
     /// * `outputs` - UXTO to create
     ///
     pub fn new(
+        chain_id: &Hash,
         skey: &pbc::SecretKey,
         pkey: &pbc::PublicKey,
         inputs: &[Output],
         outputs: &[Output],
     ) -> Result<Self, Error> {
-        let tx = Self::unchecked(skey, pkey, inputs, outputs)?;
+        let tx = Self::unchecked(chain_id, skey, pkey, inputs, outputs)?;
         Ok(tx)
     }
 
     /// Same as new(), but without checks and assertions.
     pub fn unchecked(
+        chain_id: &Hash,
         skey: &pbc::SecretKey,
         pkey: &pbc::PublicKey,
         inputs: &[Output],
@@ -485,6 +625,7 @@ impl RestakeTransaction {
             return Err(TransactionError::ImbalancedRestaking(htx).into());
         };
         let mut tx = RestakeTransaction {
+            chain_id: *chain_id,
             txins,
             txouts: outputs.to_vec(),
             sig: pbc::Signature::new(),
@@ -497,6 +638,7 @@ impl RestakeTransaction {
     /// Used only for tests.
     #[doc(hidden)]
     pub fn new_test(
+        chain_id: &Hash,
         pkey: PublicKey,
         nskey: &pbc::SecretKey,
         npkey: &pbc::PublicKey,
@@ -520,13 +662,125 @@ impl RestakeTransaction {
             outputs.push(output);
         }
 
-        match RestakeTransaction::new(&nskey, &npkey, &inputs, &outputs) {
+        match RestakeTransaction::new(chain_id, &nskey, &npkey, &inputs, &outputs) {
             Err(e) => Err(e),
             Ok(tx) => Ok((tx, inputs, outputs)),
         }
     }
 }
 
+//--------------------------------------------------------------------------------------------------
+// Revoke Delegation Transaction.
+//--------------------------------------------------------------------------------------------------
+
+/// RevokeDelegationTransaction moves delegated stakes back to their validator.
+#[derive(Clone, Debug)]
+pub struct RevokeDelegationTransaction {
+    /// Identifier of the chain, see `BlockchainConfig::chain_id`.
+    pub chain_id: Hash,
+    /// List of inputs.
+    pub txins: Vec<Hash>,
+    /// List of outputs.
+    pub txouts: Vec<Output>,
+    /// Transaction signature.
+    pub sig: pbc::Signature,
+}
+
+impl Hashable for RevokeDelegationTransaction {
+    fn hash(&self, state: &mut Hasher) {
+        "RevokeDelegation".hash(state);
+
+        // Sign chain, to prevent replays on other chains.
+        self.chain_id.hash(state);
+
+        // Sign txins.
+        let txins_count: u64 = self.txins.len() as u64;
+        txins_count.hash(state);
+        for txin_hash in &self.txins {
+            txin_hash.hash(state);
+        }
+
+        // Sign txouts.
+        let txouts_count: u64 = self.txouts.len() as u64;
+        txouts_count.hash(state);
+        for txout in &self.txouts {
+            txout.hash(state);
+        }
+    }
+}
+
+impl RevokeDelegationTransaction {
+    /// Create a new transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `chain_id` - Identifier of the chain
+    /// * `skey` - Validator's secret key
+    /// * `pkey` - Validator's public key
+    /// * `inputs` - Delegated stakes to revoke
+    ///
+    pub fn new(
+        chain_id: &Hash,
+        skey: &pbc::SecretKey,
+        pkey: &pbc::PublicKey,
+        inputs: &[Output],
+    ) -> Result<Self, Error> {
+        let mut txins: Vec<Hash> = Vec::with_capacity(inputs.len());
+        let mut amount: i64 = 0;
+        let mut owner: Option<PublicKey> = None;
+        let mut reward: Option<RewardDestination> = None;
+        let htx = Hash::digest("");
+        for txin in inputs {
+            txin.validate()?;
+            let h = Hash::digest(txin);
+            match txin {
+                Output::PaymentOutput(_) | Output::PublicPaymentOutput(_) => {
+                    return Err(TransactionError::InvalidRestakingInput(htx, h).into());
+                }
+                Output::StakeOutput(o) => {
+                    if *pkey != o.validator {
+                        return Err(TransactionError::RestakingValidatorKeyMismatch(htx, h).into());
+                    }
+                    if reward.is_none() {
+                        reward = o.reward.clone();
+                    }
+                    if !o.is_delegated() {
+                        return Err(TransactionError::NotDelegatedStake(htx, h).into());
+                    }
+                    match owner {
+                        None => owner = Some(o.recipient),
+                        Some(recipient) => {
+                            if recipient != o.recipient {
+                                return Err(TransactionError::MixedRestakingOwners(htx, h).into());
+                            }
+                        }
+                    }
+                    amount += o.amount;
+                    txins.push(h);
+                }
+            }
+        }
+        let owner = match owner {
+            Some(o) => o,
+            None => {
+                return Err(TransactionError::NoRestakingTxins(htx).into());
+            }
+        };
+        // Keep the destination of rewards, it is signed for the same recipient and validator.
+        let mut output = StakeOutput::new(&owner, skey, pkey, amount)?;
+        output.set_reward(reward, skey);
+        let mut tx = RevokeDelegationTransaction {
+            chain_id: *chain_id,
+            txins,
+            txouts: vec![output.into()],
+            sig: pbc::Signature::new(),
+        };
+        let h = Hash::digest(&tx);
+        tx.sig = pbc::sign_hash(&h, skey);
+        Ok(tx)
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Transaction (enum).
 //--------------------------------------------------------------------------------------------------
@@ -537,6 +791,7 @@ pub enum Transaction {
     CoinbaseTransaction(CoinbaseTransaction),
     PaymentTransaction(PaymentTransaction),
     RestakeTransaction(RestakeTransaction),
+    RevokeDelegationTransaction(RevokeDelegationTransaction),
     SlashingTransaction(SlashingTransaction),
     ServiceAwardTransaction(ServiceAwardTransaction),
 }
@@ -548,17 +803,32 @@ impl Transaction {
             Transaction::CoinbaseTransaction(tx) => tx.gamma.clone(),
             Transaction::PaymentTransaction(tx) => tx.gamma.clone(),
             Transaction::RestakeTransaction(_tx) => Fr::zero(),
+            Transaction::RevokeDelegationTransaction(_tx) => Fr::zero(),
             Transaction::SlashingTransaction(_tx) => Fr::zero(),
             Transaction::ServiceAwardTransaction(_tx) => Fr::zero(),
         }
     }
 
+    /// Returns the identifier of the chain, if the transaction is signed by a user.
+    #[inline]
+    pub fn chain_id(&self) -> Option<Hash> {
+        match self {
+            Transaction::CoinbaseTransaction(_tx) => None,
+            Transaction::PaymentTransaction(tx) => Some(tx.chain_id),
+            Transaction::RestakeTransaction(tx) => Some(tx.chain_id),
+            Transaction::RevokeDelegationTransaction(tx) => Some(tx.chain_id),
+            Transaction::SlashingTransaction(_tx) => None,
+            Transaction::ServiceAwardTransaction(_tx) => None,
+        }
+    }
+
     #[inline]
     pub fn fee(&self) -> i64 {
         match self {
             Transaction::CoinbaseTransaction(_tx) => 0,
             Transaction::PaymentTransaction(tx) => tx.fee,
             Transaction::RestakeTransaction(_tx) => 0,
+            Transaction::RevokeDelegationTransaction(_tx) => 0,
             Transaction::SlashingTransaction(_tx) => 0,
             Transaction::ServiceAwardTransaction(_tx) => 0,
         }
@@ -570,6 +840,7 @@ impl Transaction {
             Transaction::CoinbaseTransaction(_tx) => &[],
             Transaction::PaymentTransaction(tx) => &tx.txins,
             Transaction::RestakeTransaction(tx) => &tx.txins,
+            Transaction::RevokeDelegationTransaction(tx) => &tx.txins,
             Transaction::SlashingTransaction(tx) => &tx.txins,
             Transaction::ServiceAwardTransaction(_tx) => &[],
         }
@@ -581,16 +852,48 @@ impl Transaction {
             Transaction::CoinbaseTransaction(tx) => &tx.txouts,
             Transaction::PaymentTransaction(tx) => &tx.txouts,
             Transaction::RestakeTransaction(tx) => &tx.txouts,
+            Transaction::RevokeDelegationTransaction(tx) => &tx.txouts,
             Transaction::SlashingTransaction(tx) => &tx.txouts,
             Transaction::ServiceAwardTransaction(tx) => &tx.winner_reward,
         }
     }
 
+    /// Returns the dispute period of the channel input, if it is spent by the closer of the channel.
+    /// The period is counted from the height of the block which created the input.
+    pub fn input_dispute_period(&self, input_hash: &Hash, input: &Output) -> Option<u64> {
+        match (self, input.channel_lock()) {
+            (
+                Transaction::PaymentTransaction(tx),
+                Some(ChannelLock::Dispute { dispute_period, .. }),
+            ) => match tx.channel_witness(input_hash) {
+                Some(ChannelWitness::Penalty { .. }) => None,
+                _ => Some(*dispute_period),
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the time-lock which must be expired to spend the input by this transaction.
+    pub fn input_lock(&self, input_hash: &Hash, input: &Output) -> Option<LockTime> {
+        if let Some(hashlock) = input.hashlock() {
+            let redeemed = match self {
+                Transaction::PaymentTransaction(tx) => tx.preimage(input_hash).is_some(),
+                _ => false,
+            };
+            // Refund is allowed only after the timeout.
+            if !redeemed {
+                return Some(hashlock.timeout);
+            }
+        }
+        input.locked_until()
+    }
+
     pub fn to_type_str(&self) -> &'static str {
         match self {
             Transaction::CoinbaseTransaction(_) => "CoinbaseTransaction",
             Transaction::PaymentTransaction(_) => "PaymentTransaction",
             Transaction::RestakeTransaction(_) => "RestakeTransaction",
+            Transaction::RevokeDelegationTransaction(_) => "RevokeDelegationTransaction",
             Transaction::SlashingTransaction(_) => "SlashingTransaction",
             Transaction::ServiceAwardTransaction(_) => "ServiceAwardTransaction",
         }
@@ -602,6 +905,7 @@ impl Transaction {
             Transaction::CoinbaseTransaction(_tx) => {}
             Transaction::PaymentTransaction(tx) => tx.sig.hash(state),
             Transaction::RestakeTransaction(tx) => tx.sig.hash(state),
+            Transaction::RevokeDelegationTransaction(tx) => tx.sig.hash(state),
             Transaction::SlashingTransaction(_tx) => (),
             Transaction::ServiceAwardTransaction(_tx) => (),
         }
@@ -614,6 +918,7 @@ impl Hashable for Transaction {
             Transaction::CoinbaseTransaction(tx) => tx.hash(state),
             Transaction::PaymentTransaction(tx) => tx.hash(state),
             Transaction::RestakeTransaction(tx) => tx.hash(state),
+            Transaction::RevokeDelegationTransaction(tx) => tx.hash(state),
             Transaction::SlashingTransaction(tx) => tx.hash(state),
             Transaction::ServiceAwardTransaction(tx) => tx.hash(state),
         }
@@ -638,6 +943,12 @@ impl From<RestakeTransaction> for Transaction {
     }
 }
 
+impl From<RevokeDelegationTransaction> for Transaction {
+    fn from(tx: RevokeDelegationTransaction) -> Transaction {
+        Transaction::RevokeDelegationTransaction(tx)
+    }
+}
+
 impl From<SlashingTransaction> for Transaction {
     fn from(tx: SlashingTransaction) -> Self {
         Transaction::SlashingTransaction(tx)
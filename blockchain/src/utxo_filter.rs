@@ -0,0 +1,145 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Probabilistic filter of UTXO hashes.
+
+use byteorder::{ByteOrder, LittleEndian};
+use stegos_crypto::hash::Hash;
+
+/// The minimal number of hashes the filter is sized for.
+const MIN_CAPACITY: usize = 1024;
+
+/// Counting Bloom filter over UTXO hashes.
+///
+/// Never gives false negatives, so outputs which are not in the filter
+/// can be skipped without a lookup. Counters support removal of spent outputs.
+#[derive(Debug, Clone)]
+pub(crate) struct OutputFilter {
+    counters: Vec<u8>,
+    /// The number of counters checked for each hash.
+    hashes: u64,
+    /// The number of hashes the filter was sized for.
+    capacity: usize,
+    false_positive_rate: f64,
+    /// The number of hashes in the filter.
+    len: usize,
+}
+
+impl OutputFilter {
+    /// Create a filter with the given false positive rate for up to `capacity` hashes.
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        assert!(false_positive_rate > 0.0 && false_positive_rate < 1.0);
+        let capacity = capacity.max(MIN_CAPACITY);
+        let ln2 = std::f64::consts::LN_2;
+        let size = (-(capacity as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil();
+        let hashes = (size / capacity as f64 * ln2).round().max(1.0);
+        OutputFilter {
+            counters: vec![0; size as usize],
+            hashes: hashes as u64,
+            capacity,
+            false_positive_rate,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn false_positive_rate(&self) -> f64 {
+        self.false_positive_rate
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn insert(&mut self, hash: &Hash) {
+        for i in self.positions(hash) {
+            // Saturated counters are never decremented.
+            self.counters[i] = self.counters[i].saturating_add(1);
+        }
+        self.len += 1;
+    }
+
+    /// Remove a hash which has been inserted before.
+    pub fn remove(&mut self, hash: &Hash) {
+        for i in self.positions(hash) {
+            debug_assert!(self.counters[i] > 0);
+            if self.counters[i] < std::u8::MAX {
+                self.counters[i] -= 1;
+            }
+        }
+        self.len -= 1;
+    }
+
+    /// Returns false if the hash is definitely not in the filter.
+    pub fn may_contain(&self, hash: &Hash) -> bool {
+        self.positions(hash).all(|i| self.counters[i] > 0)
+    }
+
+    /// Positions of counters using double hashing.
+    /// The input is a cryptographic hash, so its bytes are used directly.
+    fn positions(&self, hash: &Hash) -> impl Iterator<Item = usize> {
+        let bytes = hash.base_vector();
+        let h1 = LittleEndian::read_u64(&bytes[0..8]);
+        let h2 = LittleEndian::read_u64(&bytes[8..16]) | 1;
+        let size = self.counters.len() as u64;
+        (0..self.hashes).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_remove() {
+        let mut filter = OutputFilter::new(1000, 0.01);
+        let hashes: Vec<Hash> = (0..1000).map(|_| Hash::random()).collect();
+        for hash in &hashes {
+            filter.insert(hash);
+        }
+        assert_eq!(filter.len(), hashes.len());
+        for hash in &hashes {
+            assert!(filter.may_contain(hash));
+        }
+
+        // False positive rate is close to the configured one.
+        let false_positives = (0..10000)
+            .filter(|_| filter.may_contain(&Hash::random()))
+            .count();
+        assert!(false_positives < 300, "false_positives={}", false_positives);
+
+        for hash in &hashes[..500] {
+            filter.remove(hash);
+        }
+        assert_eq!(filter.len(), 500);
+        for hash in &hashes[500..] {
+            assert!(filter.may_contain(hash));
+        }
+        let removed = hashes[..500]
+            .iter()
+            .filter(|hash| filter.may_contain(hash))
+            .count();
+        assert!(removed < 30, "removed={}", removed);
+    }
+}
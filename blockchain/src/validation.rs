@@ -25,19 +25,24 @@ use crate::block::{MacroBlock, MacroBlockHeader, MicroBlock, VERSION};
 use crate::blockchain::{Balance, Blockchain, ChainInfo};
 use crate::election::mix;
 use crate::error::{BlockError, BlockchainError, SlashingError, TransactionError};
+use crate::metrics;
 use crate::multisignature::check_multi_signature;
 use crate::output::{Output, PublicPaymentOutput};
 use crate::slashing::confiscate_tx;
+use crate::stream::{MicroBlockStream, ValidatedMicroBlock};
 use crate::transaction::{
-    CoinbaseTransaction, PaymentTransaction, RestakeTransaction, SlashingTransaction, Transaction,
+    CoinbaseTransaction, PaymentTransaction, RestakeTransaction, RevokeDelegationTransaction,
+    SlashingTransaction, Transaction, MAX_PREIMAGE_LEN,
 };
 use log::*;
+use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
 use std::time::SystemTime;
 use stegos_crypto::bulletproofs::{fee_a, simple_commit};
 use stegos_crypto::curve1174::{ECp, Fr, G};
 use stegos_crypto::hash::{Hash, Hashable, Hasher};
 use stegos_crypto::{curve1174, pbc};
+use stegos_serialization::traits::ProtoConvert;
 
 pub type StakingBalance = HashMap<pbc::PublicKey, i64>;
 
@@ -66,6 +71,11 @@ impl CoinbaseTransaction {
                     output.validate()?;
                     mined += output.pedersen_commitment()?;
                 }
+                // Rewards paid to a destination set by stakes are public to be checked by validators.
+                Output::PublicPaymentOutput(o) if o.hashlock.is_none() && o.channel.is_none() => {
+                    output.validate()?;
+                    mined += output.pedersen_commitment()?;
+                }
                 _ => {
                     return Err(
                         TransactionError::NonPaymentOutputInCoinbase(tx_hash, output_hash).into(),
@@ -100,6 +110,8 @@ impl PaymentTransaction {
         // - Inputs have not been spent by blocks.
         // - Inputs are unique.
         // - Outputs are unique.
+        // - Preimages match hash-locked inputs.
+        // - Channel witnesses match channel inputs and settlement outputs.
         // - Bulletpoofs/amounts are valid.
         // - UTXO-specific checks.
         // - Monetary balance is valid.
@@ -130,6 +142,29 @@ impl PaymentTransaction {
         //     P_eff = pedersen_commitment_diff + \sum P_i
         //
 
+        // Check preimages of hash-locked inputs.
+        let mut preimages_set: HashSet<Hash> = HashSet::new();
+        for (txin_hash, preimage) in &self.preimages {
+            let hashlock = self
+                .txins
+                .iter()
+                .position(|hash| hash == txin_hash)
+                .and_then(|i| inputs[i].hashlock());
+            let is_valid = match hashlock {
+                Some(hashlock) => {
+                    preimage.len() <= MAX_PREIMAGE_LEN && Hash::digest(preimage) == hashlock.hash
+                }
+                None => false,
+            };
+            if !is_valid || !preimages_set.insert(*txin_hash) {
+                return Err(TransactionError::InvalidPreimage(tx_hash, *txin_hash).into());
+            }
+        }
+        drop(preimages_set);
+
+        // Check witnesses of payment channel inputs.
+        self.validate_channels(&tx_hash, inputs)?;
+
         let mut eff_pkey = ECp::inf();
         let mut txin_sum = ECp::inf();
         let mut txout_sum = ECp::inf();
@@ -144,7 +179,7 @@ impl PaymentTransaction {
             txin.validate()?;
             let cmt = txin.pedersen_commitment()?;
             txin_sum += cmt;
-            eff_pkey += txin.recipient_pkey()? + cmt;
+            eff_pkey += self.txin_signer(txin_hash, txin)? + cmt;
         }
         drop(txins_set);
 
@@ -182,6 +217,52 @@ impl PaymentTransaction {
         // Transaction is valid.
         Ok(())
     }
+
+    /// Check that the sender owns the inputs of transaction.
+    ///
+    /// Only validates the signature, without bulletproofs and other expensive checks
+    /// of validate(). Used to reject forged transactions before full validation.
+    ///
+    /// # Arguments
+    ///
+    /// * - `inputs` - UTXOs referred by self.txins, in the same order as in self.txins.
+    ///
+    pub fn validate_ownership(&self, inputs: &[Output]) -> Result<(), BlockchainError> {
+        let tx_hash = Hash::digest(&self);
+
+        assert_eq!(self.txins.len(), inputs.len());
+
+        //     P_eff = \sum C_i - \sum C_o - fee * A + \sum P_i
+        let mut eff_pkey = ECp::inf();
+        for (txin_hash, txin) in self.txins.iter().zip(inputs) {
+            eff_pkey += self.txin_signer(txin_hash, txin)? + txin.pedersen_commitment()?;
+        }
+        for txout in &self.txouts {
+            eff_pkey -= txout.pedersen_commitment()?;
+        }
+        eff_pkey -= simple_commit(&self.gamma, &Fr::from(self.fee));
+        let eff_pkey: curve1174::PublicKey = eff_pkey.into();
+
+        curve1174::validate_sig(&tx_hash, &self.sig, &eff_pkey)
+            .map_err(|_e| TransactionError::InvalidSignature(tx_hash))?;
+        Ok(())
+    }
+
+    /// Returns the key which signs the input.
+    /// Hash-locked inputs without a preimage are signed by the refund key.
+    /// Channel inputs are signed by the party which settles the channel.
+    fn txin_signer(&self, txin_hash: &Hash, txin: &Output) -> Result<ECp, BlockchainError> {
+        let txin_pkey = match (txin, txin.hashlock()) {
+            (Output::PublicPaymentOutput(o), _) if o.channel.is_some() => self
+                .channel_input_signer(txin_hash, o, o.channel.as_ref().unwrap())
+                .decompress()?,
+            (_, Some(hashlock)) if self.preimage(txin_hash).is_none() => {
+                hashlock.refund.decompress()?
+            }
+            _ => txin.recipient_pkey()?,
+        };
+        Ok(txin_pkey)
+    }
 }
 
 impl RestakeTransaction {
@@ -319,6 +400,97 @@ impl RestakeTransaction {
     }
 }
 
+impl RevokeDelegationTransaction {
+    /// Validate that delegated stakes are moved back to their validator
+    /// without changing the owner and amount.
+    ///
+    /// # Arguments
+    ///
+    /// * - `inputs` - UTXOs referred by self.txins, in the same order as in self.txins.
+    ///
+    pub fn validate(&self, inputs: &[Output]) -> Result<(), BlockchainError> {
+        let tx_hash = Hash::digest(&self);
+
+        assert_eq!(self.txins.len(), inputs.len());
+
+        // Check that transaction has inputs.
+        if self.txins.is_empty() {
+            return Err(TransactionError::NoInputs(tx_hash).into());
+        }
+
+        let mut owner: Option<(curve1174::PublicKey, pbc::PublicKey)> = None;
+        let mut txin_sum: i64 = 0;
+        let mut txout_sum: i64 = 0;
+
+        let mut txins_set: HashSet<Hash> = HashSet::new();
+        for (txin_hash, txin) in self.txins.iter().zip(inputs) {
+            assert_eq!(Hash::digest(txin), *txin_hash);
+            if !txins_set.insert(*txin_hash) {
+                return Err(TransactionError::DuplicateInput(tx_hash, *txin_hash).into());
+            }
+            txin.validate()?;
+            let o = match txin {
+                Output::PaymentOutput(_) | Output::PublicPaymentOutput(_) => {
+                    return Err(TransactionError::InvalidRestakingInput(tx_hash, *txin_hash).into());
+                }
+                Output::StakeOutput(o) => o,
+            };
+            if !o.is_delegated() {
+                return Err(TransactionError::NotDelegatedStake(tx_hash, *txin_hash).into());
+            }
+            match owner {
+                None => owner = Some((o.recipient, o.validator)),
+                Some(owner) => {
+                    if owner != (o.recipient, o.validator) {
+                        return Err(
+                            TransactionError::MixedRestakingOwners(tx_hash, *txin_hash).into()
+                        );
+                    }
+                }
+            }
+            txin_sum += o.amount;
+        }
+        drop(txins_set);
+        let (_recipient, validator) = owner.expect("at least one input");
+
+        let mut txouts_set: HashSet<Hash> = HashSet::new();
+        for txout in &self.txouts {
+            let txout_hash = Hash::digest(txout);
+            if !txouts_set.insert(txout_hash) {
+                return Err(TransactionError::DuplicateOutput(tx_hash, txout_hash).into());
+            }
+            txout.validate()?;
+            let o = match txout {
+                Output::PaymentOutput(_) | Output::PublicPaymentOutput(_) => {
+                    return Err(
+                        TransactionError::InvalidRestakingOutput(tx_hash, txout_hash).into(),
+                    );
+                }
+                Output::StakeOutput(o) => o,
+            };
+            if o.is_delegated() {
+                return Err(TransactionError::DelegatedStakeOutput(tx_hash, txout_hash).into());
+            }
+            if owner != Some((o.recipient, o.validator)) {
+                return Err(TransactionError::MixedRestakingOwners(tx_hash, txout_hash).into());
+            }
+            txout_sum += o.amount;
+        }
+        drop(txouts_set);
+
+        if txin_sum != txout_sum {
+            return Err(TransactionError::InvalidMonetaryBalance(tx_hash).into());
+        }
+
+        // Check signature
+        pbc::check_hash(&tx_hash, &self.sig, &validator)
+            .map_err(|_e| TransactionError::InvalidSignature(tx_hash))?;
+
+        // Transaction is valid.
+        Ok(())
+    }
+}
+
 impl SlashingTransaction {
     pub fn validate(
         &self,
@@ -347,14 +519,22 @@ impl SlashingTransaction {
                         recipient: recipient1,
                         amount: amount1,
                         serno: _,
+                        hashlock: hashlock1,
+                        channel: channel1,
                     }),
                     Output::PublicPaymentOutput(PublicPaymentOutput {
                         recipient: recipient2,
                         amount: amount2,
                         serno: _,
+                        hashlock: hashlock2,
+                        channel: channel2,
                     }),
                 ) => {
-                    if recipient1 != recipient2 || amount1 != amount2 {
+                    if recipient1 != recipient2
+                        || amount1 != amount2
+                        || hashlock1 != hashlock2
+                        || channel1 != channel2
+                    {
                         return Err(SlashingError::IncorrectTxins(tx_hash).into());
                     }
                 }
@@ -414,6 +594,7 @@ impl MacroBlock {
 impl Blockchain {
     /// Validate that staker didn't try to spent locked stake.
     /// Validate that staker has only one key.
+    /// Validate that stakes of a validator don't pay rewards to different wallets.
     /// # Arguments
     ///
     /// * - `inputs` - UTXOs referred by self.body.txins, in the same order as in self.body.txins.
@@ -424,6 +605,7 @@ impl Blockchain {
         inputs: &[Output],
     ) -> Result<(), BlockchainError> {
         let mut staking_balance = StakingBalance::new();
+        let mut rewards: HashMap<pbc::PublicKey, curve1174::PublicKey> = HashMap::new();
         for txin in inputs {
             match txin {
                 Output::PaymentOutput(_o) => {}
@@ -440,7 +622,7 @@ impl Blockchain {
                 Output::PaymentOutput(_o) => {}
                 Output::PublicPaymentOutput(_o) => {}
                 Output::StakeOutput(o) => {
-                    if let Some(wallet) = self.validator_wallet(&o.validator) {
+                    if let Some(wallet) = self.staker_wallet(&o.validator) {
                         if wallet != o.recipient {
                             let tx_hash = Hash::digest(tx);
                             let utxo_hash = Hash::digest(txout);
@@ -453,6 +635,28 @@ impl Blockchain {
                             .into());
                         }
                     }
+                    // Stakes which are not spent by this transaction keep their destination.
+                    if let Some(reward) = &o.reward {
+                        let spent = tx.txins();
+                        let conflicts = self
+                            .reward_keys(&o.validator)
+                            .into_iter()
+                            .filter(|(output_hash, _)| !spent.contains(output_hash))
+                            .map(|(_, reward_pkey)| reward_pkey)
+                            .chain(rewards.get(&o.validator).cloned())
+                            .any(|reward_pkey| reward_pkey != reward.pkey);
+                        if conflicts {
+                            let tx_hash = Hash::digest(tx);
+                            let utxo_hash = Hash::digest(txout);
+                            return Err(TransactionError::ConflictingRewardDestination(
+                                o.validator,
+                                tx_hash,
+                                utxo_hash,
+                            )
+                            .into());
+                        }
+                        rewards.insert(o.validator, reward.pkey);
+                    }
                     // Update staking balance.
                     let stake = staking_balance.entry(o.validator).or_insert(0);
                     *stake += o.amount;
@@ -466,6 +670,39 @@ impl Blockchain {
         }
         Ok(())
     }
+    ///
+    /// Check that block rewards are paid to the destination set by stakes of the leader.
+    /// Without such destination, only cloaked outputs can be used for rewards.
+    ///
+    pub fn validate_reward_outputs<'a, I>(
+        &self,
+        height: u64,
+        block_hash: &Hash,
+        leader: &pbc::PublicKey,
+        outputs: I,
+    ) -> Result<(), BlockchainError>
+    where
+        I: IntoIterator<Item = &'a Output>,
+    {
+        let reward_pkey = self.reward_key(leader);
+        for output in outputs {
+            let is_valid = match (output, &reward_pkey) {
+                (Output::PublicPaymentOutput(o), Some(reward_pkey)) => {
+                    o.recipient == *reward_pkey && o.hashlock.is_none() && o.channel.is_none()
+                }
+                (Output::PaymentOutput(_o), None) => true,
+                _ => false,
+            };
+            if !is_valid {
+                let output_hash = Hash::digest(output);
+                return Err(
+                    BlockError::InvalidRewardRecipient(height, *block_hash, output_hash).into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
     /// Check that the stake can be unstaked.
     fn validate_staking_balance<'a, StakeIter>(
         &self,
@@ -510,6 +747,20 @@ impl Blockchain {
             .into());
         }
 
+        // Check chain identifier.
+        // Genesis blocks created before chain identifiers were introduced have zero here.
+        let chain_id = self.chain_id();
+        if header.base.chain_id != chain_id && (height > 0 || header.base.chain_id != Hash::zero())
+        {
+            return Err(BlockError::InvalidChainId(
+                height,
+                *block_hash,
+                header.base.chain_id,
+                chain_id,
+            )
+            .into());
+        }
+
         // Check height.
         if height != self.height() {
             return Err(BlockError::OutOfOrderBlock(*block_hash, height, self.height()).into());
@@ -533,6 +784,9 @@ impl Blockchain {
                 .into());
             }
 
+            // Check timestamp.
+            self.validate_timestamp(height, block_hash, header.base.timestamp)?;
+
             let leader = self.select_leader(header.base.view_change);
             let seed = mix(self.last_random(), header.base.view_change);
             if !pbc::validate_VRF_source(&header.base.random, &leader, &seed) {
@@ -543,13 +797,106 @@ impl Blockchain {
         Ok(())
     }
 
+    ///
+    /// Check that the block matches the checkpoint at its height, if any.
+    ///
+    pub fn validate_checkpoint(
+        &self,
+        height: u64,
+        block_hash: &Hash,
+    ) -> Result<(), BlockchainError> {
+        match self.checkpoint(height) {
+            Some(checkpoint) if checkpoint != block_hash => {
+                Err(BlockError::CheckpointMismatch(height, *block_hash, *checkpoint).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Check that the block timestamp is not too far ahead of the local clock
+    /// and is not earlier than the timestamp of the previous block.
+    ///
+    pub fn validate_timestamp(
+        &self,
+        height: u64,
+        block_hash: &Hash,
+        timestamp: SystemTime,
+    ) -> Result<(), BlockchainError> {
+        let now = self.clock().now();
+        if timestamp > now + self.cfg().max_time_drift {
+            return Err(BlockError::TimestampInFuture(height, *block_hash, timestamp, now).into());
+        }
+
+        let previous = self.last_block_timestamp();
+        if timestamp < previous {
+            return Err(BlockError::TimestampBeforePrevious(
+                height,
+                *block_hash,
+                timestamp,
+                previous,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the transaction was signed for this chain.
+    pub fn validate_transaction_chain(&self, tx: &Transaction) -> Result<(), BlockchainError> {
+        let chain_id = self.chain_id();
+        match tx.chain_id() {
+            Some(tx_chain_id) if tx_chain_id != chain_id => {
+                let tx_hash = Hash::digest(tx);
+                Err(TransactionError::InvalidChainId(tx_hash, tx_chain_id, chain_id).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Check that transaction fits into the configured size limits.
+    ///
+    pub fn validate_transaction_limits(&self, tx: &Transaction) -> Result<(), BlockchainError> {
+        let tx_hash = Hash::digest(tx);
+
+        // Check the number of inputs.
+        // Restaking and slashing spend all stakes of a validator at once and are not limited.
+        let limited = match tx {
+            Transaction::RestakeTransaction(_) | Transaction::SlashingTransaction(_) => false,
+            _ => true,
+        };
+        let inputs = tx.txins().len();
+        if limited && inputs > self.cfg().max_inputs_per_tx {
+            metrics::OVERSIZED_TRANSACTIONS.inc();
+            return Err(TransactionError::TooManyInputs(
+                tx_hash,
+                inputs,
+                self.cfg().max_inputs_per_tx,
+            )
+            .into());
+        }
+
+        // Check the serialized size.
+        let size = tx.into_buffer().expect("serialization never fails").len();
+        if size > self.cfg().max_tx_size {
+            metrics::OVERSIZED_TRANSACTIONS.inc();
+            return Err(
+                TransactionError::TooBigTransaction(tx_hash, size, self.cfg().max_tx_size).into(),
+            );
+        }
+
+        Ok(())
+    }
+
     ///
     /// A helper for validate_micro_block().
     ///
     fn validate_micro_block_tx(
         &self,
         tx: &Transaction,
-        _timestamp: SystemTime,
+        height: u64,
+        timestamp: SystemTime,
         leader: pbc::PublicKey,
         inputs_set: &mut HashSet<Hash>,
         outputs_set: &mut HashSet<Hash>,
@@ -557,6 +904,10 @@ impl Blockchain {
         let tx_hash = Hash::digest(&tx);
         let mut inputs: Vec<Output> = Vec::new();
 
+        // Check chain identifier and size limits.
+        self.validate_transaction_chain(tx)?;
+        self.validate_transaction_limits(tx)?;
+
         // Validate inputs.
         for input_hash in tx.txins() {
             // Check that the input can be resolved.
@@ -572,6 +923,18 @@ impl Blockchain {
                 return Err(TransactionError::MissingInput(tx_hash, input_hash.clone()).into());
             }
 
+            // Check that the time-lock of the input is expired.
+            if let Some(locked_until) = self.input_lock(tx, input_hash, &input)? {
+                if !locked_until.is_unlocked(height, timestamp) {
+                    return Err(TransactionError::LockedInput(
+                        tx_hash,
+                        input_hash.clone(),
+                        locked_until,
+                    )
+                    .into());
+                }
+            }
+
             inputs_set.insert(input_hash.clone());
             inputs.push(input);
         }
@@ -594,6 +957,7 @@ impl Blockchain {
             }
             Transaction::PaymentTransaction(tx) => tx.validate(&inputs)?,
             Transaction::RestakeTransaction(tx) => tx.validate(&inputs)?,
+            Transaction::RevokeDelegationTransaction(tx) => tx.validate(&inputs)?,
             Transaction::SlashingTransaction(tx) => tx.validate(self, leader)?,
             Transaction::ServiceAwardTransaction(_) => {
                 return Err(TransactionError::UnexpectedTxType.into())
@@ -612,15 +976,66 @@ impl Blockchain {
     ///
     /// * `block` - block to validate.
     /// * `timestamp` - current time.
-    ///                         Used to validating escrow.
     ///
     pub fn validate_micro_block(
         &self,
         block: &MicroBlock,
-        timestamp: SystemTime,
+        _timestamp: SystemTime,
     ) -> Result<(), BlockchainError> {
-        let height = block.base.height;
         let block_hash = Hash::digest(&block);
+        let block_size = block
+            .into_buffer()
+            .expect("serialization never fails")
+            .len();
+        self.validate_micro_block_header(block, &block_hash, block_size)?;
+        let transactions = block.transactions.iter().map(Ok);
+        self.validate_micro_block_transactions(block, &block_hash, transactions)?;
+        Ok(())
+    }
+
+    ///
+    /// Validate a serialized micro block without decoding all transactions at once.
+    ///
+    /// Transactions are decoded and validated one-by-one and the validation stops
+    /// on the first invalid transaction. Every transaction is decoded only once,
+    /// the returned block can be pushed by `push_validated_micro_block()`.
+    ///
+    pub fn validate_micro_block_stream(
+        &self,
+        stream: &MicroBlockStream,
+        _timestamp: SystemTime,
+    ) -> Result<ValidatedMicroBlock, BlockchainError> {
+        let header = stream.header();
+        let block_hash = stream
+            .hash()
+            .map_err(|e| BlockError::MalformedBlock(format!("{}", e)))?;
+        self.validate_micro_block_header(header, &block_hash, stream.size())?;
+        let transactions = stream
+            .transactions()
+            .map(|tx| tx.map_err(|e| BlockError::MalformedBlock(format!("{}", e)).into()));
+        let transactions =
+            self.validate_micro_block_transactions(header, &block_hash, transactions)?;
+        let mut block = header.clone();
+        block.transactions = transactions;
+        Ok(ValidatedMicroBlock {
+            block,
+            block_hash,
+            last_block: self.last_block_hash(),
+            view_change: self.view_change(),
+        })
+    }
+
+    ///
+    /// A helper for validate_micro_block() - checks everything except transactions.
+    ///
+    fn validate_micro_block_header(
+        &self,
+        block: &MicroBlock,
+        block_hash: &Hash,
+        block_size: usize,
+    ) -> Result<(), BlockchainError> {
+        let height = block.base.height;
+        let block_hash = *block_hash;
         debug!(
             "Validating a micro block: height={}, block={}",
             height, &block_hash
@@ -637,6 +1052,18 @@ impl Blockchain {
             .into());
         }
 
+        // Check chain identifier.
+        let chain_id = self.chain_id();
+        if block.base.chain_id != chain_id {
+            return Err(BlockError::InvalidChainId(
+                height,
+                block_hash,
+                block.base.chain_id,
+                chain_id,
+            )
+            .into());
+        }
+
         // Check height.
         if height != self.height() {
             return Err(BlockError::OutOfOrderBlock(block_hash, height, self.height()).into());
@@ -659,6 +1086,24 @@ impl Blockchain {
             .into());
         }
 
+        // Check timestamp.
+        self.validate_timestamp(height, &block_hash, block.base.timestamp)?;
+
+        // Check checkpoint.
+        self.validate_checkpoint(height, &block_hash)?;
+
+        // Check block size.
+        if block_size > self.cfg().max_block_size {
+            metrics::OVERSIZED_BLOCKS.inc();
+            return Err(BlockError::TooBigBlock(
+                height,
+                block_hash,
+                block_size,
+                self.cfg().max_block_size,
+            )
+            .into());
+        }
+
         // Check view change.
         if block.base.view_change < self.view_change() {
             return Err(BlockError::InvalidViewChange(
@@ -671,7 +1116,7 @@ impl Blockchain {
         } else if block.base.view_change > 0 {
             match block.view_change_proof {
                 Some(ref proof) => {
-                    let chain = ChainInfo::from_micro_block(&block);
+                    let chain = ChainInfo::from_micro_block(&block, self.epoch());
                     if let Err(e) = proof.validate(&chain, &self) {
                         return Err(
                             BlockError::InvalidViewChangeProof(height, proof.clone(), e).into()
@@ -691,37 +1136,61 @@ impl Blockchain {
         }
 
         // Check leader.
-        let leader = self.select_leader(block.base.view_change);
-        if leader != block.pkey {
-            return Err(BlockError::DifferentPublicKey(leader, block.pkey).into());
-        }
-
-        // Check signature.
-        if let Err(_e) = pbc::check_hash(&block_hash, &block.sig, &leader) {
-            return Err(BlockError::InvalidLeaderSignature(height, block_hash).into());
-        }
-        // Check block reward.
-        if let Some(Transaction::CoinbaseTransaction(tx)) = block.transactions.get(0) {
-            if tx.block_reward != self.cfg().block_reward {
-                return Err(BlockError::InvalidBlockReward(
-                    height,
-                    block_hash,
-                    tx.block_reward,
-                    self.cfg().block_reward,
-                )
-                .into());
+        let leader = if self.cfg().secret_leader_election {
+            // Eligibility is revealed by the VRF, which is checked below.
+            if !self.is_validator(&block.pkey) {
+                return Err(BlockError::LeaderIsNotValidator(height, block_hash).into());
             }
+            block.pkey
         } else {
-            // Force coinbase if reward is not zero.
-            return Err(BlockError::CoinbaseMustBeFirst(block_hash).into());
+            let leader = self.select_leader(block.base.view_change);
+            if leader != block.pkey {
+                return Err(BlockError::DifferentPublicKey(leader, block.pkey).into());
+            }
+            leader
+        };
+
+        // Check signature (skip below the last checkpoint if configured).
+        if !self.is_assumed_valid(height) {
+            if let Err(_e) = pbc::check_hash(&block_hash, &block.sig, &leader) {
+                return Err(BlockError::InvalidLeaderSignature(height, block_hash).into());
+            }
         }
 
-        // Check random.
+        // Check random (skip below the last checkpoint if configured).
         let seed = mix(self.last_random(), block.base.view_change);
-        if !pbc::validate_VRF_source(&block.base.random, &leader, &seed) {
+        if !self.is_assumed_valid(height)
+            && !pbc::validate_VRF_source(&block.base.random, &leader, &seed)
+        {
             return Err(BlockError::IncorrectRandom(height, block_hash).into());
         }
 
+        // Check eligibility in the secret leader election.
+        if self.cfg().secret_leader_election
+            && !self.is_micro_block_leader(&leader, block.base.view_change, &block.base.random)
+        {
+            return Err(BlockError::NotEligibleLeader(height, block_hash, leader).into());
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// A helper for validate_micro_block() - validates transactions in order.
+    /// Returns the validated transactions.
+    ///
+    fn validate_micro_block_transactions<T, I>(
+        &self,
+        block: &MicroBlock,
+        block_hash: &Hash,
+        transactions: I,
+    ) -> Result<Vec<T>, BlockchainError>
+    where
+        T: Borrow<Transaction>,
+        I: Iterator<Item = Result<T, BlockchainError>>,
+    {
+        let height = block.base.height;
+        let block_hash = *block_hash;
         let mut inputs_set: HashSet<Hash> = HashSet::new();
         let mut outputs_set: HashSet<Hash> = HashSet::new();
         let mut fee: i64 = 0;
@@ -730,22 +1199,46 @@ impl Blockchain {
         // Validate transactions.
         //
         let mut coinbase_fee: i64 = 0;
-        for (i, tx) in block.transactions.iter().enumerate() {
+        let mut validated: Vec<T> = Vec::new();
+        for (i, item) in transactions.enumerate() {
+            let item = item?;
+            let tx = item.borrow();
             if let Transaction::CoinbaseTransaction(tx) = tx {
                 // Coinbase transaction must be a first.
                 if i > 0 {
                     return Err(BlockError::CoinbaseMustBeFirst(block_hash).into());
                 }
+                // Check block reward.
+                let block_reward = self.block_reward(height);
+                if tx.block_reward > block_reward {
+                    return Err(BlockError::InvalidBlockReward(
+                        height,
+                        block_hash,
+                        tx.block_reward,
+                        block_reward,
+                    )
+                    .into());
+                }
+                self.validate_reward_outputs(height, &block_hash, &block.pkey, tx.txouts.iter())?;
                 coinbase_fee += tx.block_fee;
+            } else if i == 0 {
+                // Force coinbase if reward is not zero.
+                return Err(BlockError::CoinbaseMustBeFirst(block_hash).into());
             }
+            // Time-locks are checked against the block, not the local clock.
             self.validate_micro_block_tx(
                 tx,
-                timestamp,
+                height,
+                block.base.timestamp,
                 block.pkey,
                 &mut inputs_set,
                 &mut outputs_set,
             )?;
             fee += tx.fee();
+            validated.push(item);
+        }
+        if validated.is_empty() {
+            return Err(BlockError::CoinbaseMustBeFirst(block_hash).into());
         }
         if coinbase_fee != fee {
             return Err(BlockError::InvalidFee(block_hash, fee, coinbase_fee).into());
@@ -756,7 +1249,7 @@ impl Blockchain {
             height, &block_hash
         );
 
-        Ok(())
+        Ok(validated)
     }
 
     ///
@@ -785,29 +1278,35 @@ impl Blockchain {
         // Validate base header.
         self.validate_macro_block_header(&block_hash, &block.header)?;
 
+        // Check checkpoint.
+        self.validate_checkpoint(height, &block_hash)?;
+
         // Validate multi-signature (skip for genesis).
         if height > 0 {
-            // Validate signature.
-            check_multi_signature(
-                &block_hash,
-                &block.body.multisig,
-                &block.body.multisigmap,
-                self.validators(),
-                self.total_slots(),
-            )
-            .map_err(|e| BlockError::InvalidBlockSignature(e, height, block_hash))?;
+            // Validate signature (skip below the last checkpoint if configured).
+            if !self.is_assumed_valid(height) {
+                check_multi_signature(
+                    &block_hash,
+                    &block.body.multisig,
+                    &block.body.multisigmap,
+                    self.validators(),
+                    self.total_slots(),
+                )
+                .map_err(|e| BlockError::InvalidBlockSignature(e, height, block_hash))?;
+            }
 
             // Check block reward. (skip for genesis)
             let mut service_awards = self.service_awards().clone();
             let validators_activity =
                 self.epoch_activity_from_macro_block(&block.body.activity_map)?;
-            service_awards.finalize_epoch(self.cfg().service_award_per_epoch, validators_activity);
+            let award = self.service_award_per_epoch(height);
+            service_awards.finalize_epoch(award, validators_activity);
             let winner = service_awards.check_winners(block.header.base.random.rand);
 
             // calculate block reward + service award.
-            let full_reward = self.cfg().block_reward + winner.map(|(_, a)| a).unwrap_or(0);
+            let full_reward = self.block_reward(height) + winner.map(|(_, a)| a).unwrap_or(0);
 
-            if block.header.block_reward != full_reward {
+            if block.header.block_reward > full_reward {
                 return Err(BlockError::InvalidBlockReward(
                     height,
                     block_hash,
@@ -816,6 +1315,22 @@ impl Blockchain {
                 )
                 .into());
             }
+
+            // Check recipients of the service award and the block reward.
+            let mut award = winner.map(|(wallet, amount)| (self.award_recipient(&wallet), amount));
+            let mut rewards: Vec<&Output> = Vec::new();
+            for (output, _path) in block.body.outputs.leafs() {
+                let output: &Output = &output;
+                if let (Output::PublicPaymentOutput(o), Some((recipient, amount))) = (output, award)
+                {
+                    if o.recipient == recipient && o.amount == amount {
+                        award = None;
+                        continue;
+                    }
+                }
+                rewards.push(output);
+            }
+            self.validate_reward_outputs(height, &block_hash, &block.body.pkey, rewards)?;
         }
 
         let mut burned = ECp::inf();
@@ -844,6 +1359,18 @@ impl Blockchain {
                     BlockError::DuplicateBlockInput(height, block_hash, *input_hash).into(),
                 );
             }
+            // Check that the time-lock of the input is expired.
+            if let Some(locked_until) = self.macro_block_input_lock(input_hash, &input) {
+                if !locked_until.is_unlocked(height, block.header.base.timestamp) {
+                    return Err(BlockError::LockedBlockInput(
+                        height,
+                        block_hash,
+                        *input_hash,
+                        locked_until,
+                    )
+                    .into());
+                }
+            }
             input.validate()?;
             burned += input.pedersen_commitment()?;
 
@@ -951,10 +1478,14 @@ impl Blockchain {
 pub mod tests {
     use super::*;
     use crate::block::{BaseBlockHeader, MacroBlock};
+    use crate::output::LockTime;
     use crate::output::OutputError;
+    use crate::output::RewardDestination;
     use crate::output::StakeOutput;
+    use crate::{ChannelState, ChannelWitness};
     use bitvector::BitVector;
     use std::time::SystemTime;
+    use stegos_crypto::curve1174::SchnorrSig;
     use stegos_crypto::pbc;
 
     ///
@@ -962,13 +1493,14 @@ pub mod tests {
     ///
     #[test]
     pub fn no_inputs() {
+        let chain_id = Hash::digest("test");
         let (skey, pkey) = curve1174::make_random_keys();
         let amount: i64 = 1_000_000;
         let fee: i64 = amount;
         let (input, _gamma1) = Output::new_payment(&pkey, amount).expect("keys are valid");
         let inputs = [input];
-        let mut tx =
-            PaymentTransaction::new(&skey, &inputs, &[], &Fr::zero(), fee).expect("keys are valid");
+        let mut tx = PaymentTransaction::new(&chain_id, &skey, &inputs, &[], &Fr::zero(), fee)
+            .expect("keys are valid");
         tx.txins.clear(); // remove all inputs
         tx.validate(&[]).expect_err("tx is invalid");
     }
@@ -978,10 +1510,12 @@ pub mod tests {
     ///
     #[test]
     pub fn no_outputs() {
+        let chain_id = Hash::digest("test");
         // No outputs
         let (skey, pkey) = curve1174::make_random_keys();
-        let (tx, inputs, _outputs) = PaymentTransaction::new_test(&skey, &pkey, 100, 1, 0, 0, 100)
-            .expect("transaction is valid");
+        let (tx, inputs, _outputs) =
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 100, 1, 0, 0, 100)
+                .expect("transaction is valid");
         tx.validate(&inputs).expect("transaction is valid");
     }
 
@@ -990,6 +1524,7 @@ pub mod tests {
     ///
     #[test]
     pub fn payment_utxo() {
+        let chain_id = Hash::digest("test");
         let (skey0, pkey0) = curve1174::make_random_keys();
         let (skey1, pkey1) = curve1174::make_random_keys();
         let (_skey2, pkey2) = curve1174::make_random_keys();
@@ -1002,7 +1537,7 @@ pub mod tests {
         //
         {
             let (tx, inputs, _outputs) =
-                PaymentTransaction::new_test(&skey0, &pkey0, 0, 2, 0, 1, 0)
+                PaymentTransaction::new_test(&chain_id, &skey0, &pkey0, 0, 2, 0, 1, 0)
                     .expect("transaction is valid");
             tx.validate(&inputs).expect("transaction is valid");
         }
@@ -1012,7 +1547,7 @@ pub mod tests {
         //
         {
             let (tx, inputs, _outputs) =
-                PaymentTransaction::new_test(&skey0, &pkey0, 100, 2, 200, 1, 0)
+                PaymentTransaction::new_test(&chain_id, &skey0, &pkey0, 100, 2, 200, 1, 0)
                     .expect("transaction is valid");
             tx.validate(&inputs).expect("transaction is valid");
         }
@@ -1021,7 +1556,7 @@ pub mod tests {
         // Negative amount.
         //
         {
-            match PaymentTransaction::new_test(&skey0, &pkey0, 0, 1, -1, 1, 0) {
+            match PaymentTransaction::new_test(&chain_id, &skey0, &pkey0, 0, 1, -1, 1, 0) {
                 Err(e) => match e.downcast::<OutputError>().unwrap() {
                     OutputError::InvalidBulletProof(_output_hash) => {}
                     _ => panic!(),
@@ -1035,7 +1570,7 @@ pub mod tests {
         //
         {
             let (mut tx, inputs, _outputs) =
-                PaymentTransaction::new_test(&skey0, &pkey0, 100, 1, 100, 1, 0)
+                PaymentTransaction::new_test(&chain_id, &skey0, &pkey0, 100, 1, 100, 1, 0)
                     .expect("transaction is valid");
             let output = &mut tx.txouts[0];
             match output {
@@ -1064,8 +1599,9 @@ pub mod tests {
         let inputs1 = [output0.clone()];
         let (output1, gamma1) = Output::new_payment(&pkey2, amount - fee).expect("keys are valid");
         let outputs_gamma = gamma1;
-        let mut tx = PaymentTransaction::new(&skey1, &inputs1, &[output1], &outputs_gamma, fee)
-            .expect("keys are valid");
+        let mut tx =
+            PaymentTransaction::new(&chain_id, &skey1, &inputs1, &[output1], &outputs_gamma, fee)
+                .expect("keys are valid");
 
         // Validation
         tx.validate(&inputs1).expect("keys are valid");
@@ -1115,17 +1651,23 @@ pub mod tests {
         //
         // Invalid signature
         //
+        tx.validate_ownership(&inputs1)
+            .expect("transaction is valid");
         tx.sig.u = Fr::zero();
         match tx.validate(&inputs1).unwrap_err() {
             BlockchainError::TransactionError(TransactionError::InvalidSignature(_tx_hash)) => {}
             _ => panic!(),
         };
+        match tx.validate_ownership(&inputs1).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidSignature(_tx_hash)) => {}
+            _ => panic!(),
+        };
 
         //
         // Invalid gamma
         //
         let (mut tx, inputs, _outputs) =
-            PaymentTransaction::new_test(&skey0, &pkey0, 100, 2, 200, 1, 0)
+            PaymentTransaction::new_test(&chain_id, &skey0, &pkey0, 100, 2, 200, 1, 0)
                 .expect("transaction is valid");
         tx.gamma = Fr::random();
         match tx.validate(&inputs).unwrap_err() {
@@ -1142,8 +1684,9 @@ pub mod tests {
             Output::new_payment(&pkey2, amount - fee - 1).expect("keys are valid");
         let outputs = [output_invalid1];
         let outputs_gamma = gamma_invalid1;
-        let tx = PaymentTransaction::new(&skey1, &inputs1, &outputs, &outputs_gamma, fee)
-            .expect("keys are valid");
+        let tx =
+            PaymentTransaction::new(&chain_id, &skey1, &inputs1, &outputs, &outputs_gamma, fee)
+                .expect("keys are valid");
         match tx.validate(&inputs1).unwrap_err() {
             BlockchainError::TransactionError(TransactionError::InvalidMonetaryBalance(
                 _tx_hash,
@@ -1152,11 +1695,422 @@ pub mod tests {
         };
     }
 
+    ///
+    /// Tests redeem and refund of hash-locked PublicPaymentOutput.
+    ///
+    #[test]
+    pub fn hashlocked_utxo() {
+        let chain_id = Hash::digest("test");
+        let (skey0, pkey0) = curve1174::make_random_keys();
+        let (skey1, pkey1) = curve1174::make_random_keys();
+
+        let amount: i64 = 1_000_000;
+        let fee: i64 = 1;
+        let preimage = b"secret".to_vec();
+        let timeout = LockTime::Height(10);
+        let htlc = PublicPaymentOutput::new_hashlocked(
+            &pkey1,
+            &pkey0,
+            amount,
+            Hash::digest(&preimage),
+            timeout,
+        );
+        let htlc_hash = Hash::digest(&htlc);
+        let inputs = [Output::PublicPaymentOutput(htlc)];
+
+        //
+        // Redeem by the recipient.
+        //
+        let (output, gamma) = Output::new_payment(&pkey1, amount - fee).expect("keys are valid");
+        let preimages = vec![(htlc_hash, preimage.clone())];
+        let tx = PaymentTransaction::with_preimages(
+            &chain_id,
+            &skey1,
+            &inputs,
+            preimages.clone(),
+            &[output.clone()],
+            &gamma,
+            fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs).expect("transaction is valid");
+        let tx: Transaction = tx.into();
+        assert_eq!(tx.input_lock(&htlc_hash, &inputs[0]), None);
+
+        //
+        // Redeem by the sender.
+        //
+        let tx = PaymentTransaction::with_preimages(
+            &chain_id,
+            &skey0,
+            &inputs,
+            preimages,
+            &[output.clone()],
+            &gamma,
+            fee,
+        )
+        .expect("keys are valid");
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidSignature(_tx_hash)) => {}
+            _ => panic!(),
+        };
+
+        //
+        // Invalid preimage.
+        //
+        let preimages = vec![(htlc_hash, b"wrong".to_vec())];
+        let tx = PaymentTransaction::with_preimages(
+            &chain_id,
+            &skey1,
+            &inputs,
+            preimages,
+            &[output.clone()],
+            &gamma,
+            fee,
+        )
+        .expect("keys are valid");
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidPreimage(
+                _tx_hash,
+                txin_hash,
+            )) => {
+                assert_eq!(txin_hash, htlc_hash);
+            }
+            _ => panic!(),
+        };
+
+        //
+        // Refund by the sender, allowed after the timeout.
+        //
+        let (output, gamma) = Output::new_payment(&pkey0, amount - fee).expect("keys are valid");
+        let tx =
+            PaymentTransaction::new(&chain_id, &skey0, &inputs, &[output.clone()], &gamma, fee)
+                .expect("keys are valid");
+        tx.validate(&inputs).expect("transaction is valid");
+        let tx: Transaction = tx.into();
+        assert_eq!(tx.input_lock(&htlc_hash, &inputs[0]), Some(timeout));
+
+        //
+        // Refund by the recipient.
+        //
+        let tx = PaymentTransaction::new(&chain_id, &skey1, &inputs, &[output], &gamma, fee)
+            .expect("keys are valid");
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidSignature(_tx_hash)) => {}
+            _ => panic!(),
+        };
+    }
+
+    ///
+    /// Tests cooperative and unilateral close of payment channels and disputes.
+    ///
+    #[test]
+    pub fn channel_utxo() {
+        let chain_id = Hash::digest("test");
+        let (skey0, pkey0) = curve1174::make_random_keys();
+        let (skey1, pkey1) = curve1174::make_random_keys();
+
+        let amount: i64 = 1_000_000;
+        let fee: i64 = 1;
+        let dispute_period: u64 = 10;
+        let funding =
+            PublicPaymentOutput::new_channel_funding(&pkey0, &pkey1, amount, dispute_period);
+        let channel = Hash::digest(&funding);
+        let inputs = [Output::PublicPaymentOutput(funding)];
+        let signed_state = |sequence: u64, balance1: i64| {
+            let mut state =
+                ChannelState::new(channel, pkey0, pkey1, sequence, amount - balance1, balance1);
+            state.sign(&skey0, &pkey0);
+            state.sign(&skey1, &pkey1);
+            state
+        };
+
+        //
+        // Funding output can't be spent by the funder alone.
+        //
+        let output0 = Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey0, amount - fee));
+        let tx = PaymentTransaction::new(
+            &chain_id,
+            &skey0,
+            &inputs,
+            &[output0.clone()],
+            &Fr::zero(),
+            fee,
+        )
+        .expect("keys are valid");
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::MissingChannelWitness(
+                _tx_hash,
+                txin_hash,
+            )) => assert_eq!(txin_hash, channel),
+            _ => panic!(),
+        };
+
+        //
+        // Cooperative close.
+        //
+        let channels = vec![ChannelWitness::Cooperative {
+            txin: channel,
+            sig: SchnorrSig::new(),
+        }];
+        let mut tx = PaymentTransaction::with_witnesses(
+            &chain_id,
+            &skey0,
+            &inputs,
+            Vec::new(),
+            channels,
+            &[output0],
+            &Fr::zero(),
+            fee,
+        )
+        .expect("keys are valid");
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidChannelWitness(..)) => {}
+            _ => panic!(),
+        };
+        tx.cosign(&channel, &skey1);
+        tx.validate(&inputs).expect("transaction is valid");
+
+        //
+        // Unilateral close by the funder.
+        //
+        let state = signed_state(1, 300);
+        let output1 = Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey1, 300));
+        let dispute = PublicPaymentOutput::new_channel_dispute(
+            &pkey0,
+            &pkey1,
+            amount - 300 - fee,
+            channel,
+            1,
+            dispute_period,
+        );
+        let dispute_hash = Hash::digest(&dispute);
+        let dispute = Output::PublicPaymentOutput(dispute);
+        let close = |state: ChannelState, outputs: &[Output]| {
+            let channels = vec![ChannelWitness::Close {
+                txin: channel,
+                state,
+                closer: pkey0,
+            }];
+            PaymentTransaction::with_witnesses(
+                &chain_id,
+                &skey0,
+                &inputs,
+                Vec::new(),
+                channels,
+                outputs,
+                &Fr::zero(),
+                fee,
+            )
+            .expect("keys are valid")
+        };
+        let tx = close(state.clone(), &[output1.clone(), dispute.clone()]);
+        tx.validate(&inputs).expect("transaction is valid");
+
+        // The balance of the counterparty must be paid immediately.
+        let output1_short = Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey1, 299));
+        let output0_extra = Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey0, 1));
+        let tx = close(
+            state.clone(),
+            &[output1_short, output0_extra, dispute.clone()],
+        );
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidChannelSettlement(..)) => {}
+            _ => panic!(),
+        };
+
+        // The balance of the closer must be locked for the dispute period.
+        let output0 =
+            Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey0, amount - 300 - fee));
+        let tx = close(state.clone(), &[output1.clone(), output0]);
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidChannelSettlement(..)) => {}
+            _ => panic!(),
+        };
+
+        // No dispute output if the fee consumes the balance of the closer.
+        let fee_state = signed_state(2, amount - fee);
+        let output1_all =
+            Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey1, amount - fee));
+        let fee_dispute = Output::PublicPaymentOutput(PublicPaymentOutput::new_channel_dispute(
+            &pkey0,
+            &pkey1,
+            1,
+            channel,
+            2,
+            dispute_period,
+        ));
+        let tx = close(fee_state.clone(), &[output1_all.clone(), fee_dispute]);
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidChannelSettlement(..)) => {}
+            _ => panic!(),
+        };
+        let tx = close(fee_state, &[output1_all]);
+        tx.validate(&inputs).expect("transaction is valid");
+
+        // The state must be signed by both parties.
+        let mut unsigned_state = state.clone();
+        unsigned_state.counterparty_sig = SchnorrSig::new();
+        let tx = close(unsigned_state, &[output1.clone(), dispute.clone()]);
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidChannelWitness(..)) => {}
+            _ => panic!(),
+        };
+
+        //
+        // Dispute output is spent by the closer after the dispute period.
+        //
+        let dispute_inputs = [dispute];
+        let output0 =
+            Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey0, amount - 300 - 2 * fee));
+        let tx = PaymentTransaction::new(
+            &chain_id,
+            &skey0,
+            &dispute_inputs,
+            &[output0],
+            &Fr::zero(),
+            fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&dispute_inputs).expect("transaction is valid");
+        let tx: Transaction = tx.into();
+        assert_eq!(
+            tx.input_dispute_period(&dispute_hash, &dispute_inputs[0]),
+            Some(dispute_period)
+        );
+
+        //
+        // Penalty by the counterparty with a newer state.
+        //
+        let output1 =
+            Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey1, amount - 300 - 2 * fee));
+        let penalty = |state: ChannelState| {
+            let channels = vec![ChannelWitness::Penalty {
+                txin: dispute_hash,
+                state,
+            }];
+            PaymentTransaction::with_witnesses(
+                &chain_id,
+                &skey1,
+                &dispute_inputs,
+                Vec::new(),
+                channels,
+                &[output1.clone()],
+                &Fr::zero(),
+                fee,
+            )
+            .expect("keys are valid")
+        };
+        let tx = penalty(signed_state(2, 500));
+        tx.validate(&dispute_inputs).expect("transaction is valid");
+        let tx: Transaction = tx.into();
+        assert_eq!(
+            tx.input_dispute_period(&dispute_hash, &dispute_inputs[0]),
+            None
+        );
+
+        // The published state can't be used for a penalty.
+        let tx = penalty(state);
+        match tx.validate(&dispute_inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidChannelWitness(..)) => {}
+            _ => panic!(),
+        };
+    }
+
+    ///
+    /// Tests that one output can't settle several payment channels closed by the same transaction.
+    ///
+    #[test]
+    pub fn channel_utxo_shared_payout() {
+        let chain_id = Hash::digest("test");
+        let (skey0, pkey0) = curve1174::make_random_keys();
+        let (skey1, pkey1) = curve1174::make_random_keys();
+
+        let amount: i64 = 1_000_000;
+        let fee: i64 = 1;
+        let dispute_period: u64 = 10;
+        let funding1 =
+            PublicPaymentOutput::new_channel_funding(&pkey0, &pkey1, amount, dispute_period);
+        let funding2 =
+            PublicPaymentOutput::new_channel_funding(&pkey0, &pkey1, amount, dispute_period);
+        let channel1 = Hash::digest(&funding1);
+        let channel2 = Hash::digest(&funding2);
+        let inputs = [
+            Output::PublicPaymentOutput(funding1),
+            Output::PublicPaymentOutput(funding2),
+        ];
+        let signed_state = |channel: Hash| {
+            let mut state = ChannelState::new(channel, pkey0, pkey1, 1, amount - 300, 300);
+            state.sign(&skey0, &pkey0);
+            state.sign(&skey1, &pkey1);
+            state
+        };
+        let dispute = |channel: Hash, amount: i64| {
+            Output::PublicPaymentOutput(PublicPaymentOutput::new_channel_dispute(
+                &pkey0,
+                &pkey1,
+                amount,
+                channel,
+                1,
+                dispute_period,
+            ))
+        };
+        let close = |outputs: &[Output]| {
+            let channels = vec![
+                ChannelWitness::Close {
+                    txin: channel1,
+                    state: signed_state(channel1),
+                    closer: pkey0,
+                },
+                ChannelWitness::Close {
+                    txin: channel2,
+                    state: signed_state(channel2),
+                    closer: pkey0,
+                },
+            ];
+            PaymentTransaction::with_witnesses(
+                &chain_id,
+                &skey0,
+                &inputs,
+                Vec::new(),
+                channels,
+                outputs,
+                &Fr::zero(),
+                fee,
+            )
+            .expect("keys are valid")
+        };
+        let dispute1 = dispute(channel1, amount - 300 - fee);
+        let dispute2 = dispute(channel2, amount - 300);
+
+        // The counterparty must be paid once per channel.
+        let output1 = Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey1, 300));
+        let output0_extra = Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey0, 300));
+        let tx = close(&[
+            output1.clone(),
+            output0_extra,
+            dispute1.clone(),
+            dispute2.clone(),
+        ]);
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::InvalidChannelSettlement(
+                _tx_hash,
+                txin_hash,
+            )) => assert_eq!(txin_hash, channel2),
+            _ => panic!(),
+        };
+
+        let output2 = Output::PublicPaymentOutput(PublicPaymentOutput::new(&pkey1, 300));
+        let tx = close(&[output1, output2, dispute1, dispute2]);
+        tx.validate(&inputs).expect("transaction is valid");
+    }
+
     ///
     /// Tests validation of StakeOutput.
     ///
     #[test]
     pub fn stake_utxo() {
+        let chain_id = Hash::digest("test");
         let (skey1, pkey1) = curve1174::make_random_keys();
         let (nskey, npkey) = pbc::make_random_keys();
 
@@ -1170,8 +2124,9 @@ pub mod tests {
         let inputs = [input];
         let (output, outputs_gamma) =
             Output::new_payment(&pkey1, amount - fee).expect("keys are valid");
-        let tx = PaymentTransaction::new(&skey1, &inputs, &[output], &outputs_gamma, fee)
-            .expect("keys are valid");
+        let tx =
+            PaymentTransaction::new(&chain_id, &skey1, &inputs, &[output], &outputs_gamma, fee)
+                .expect("keys are valid");
         tx.validate(&inputs).expect("tx is valid");
 
         //
@@ -1182,8 +2137,9 @@ pub mod tests {
         let output =
             Output::new_stake(&pkey1, &nskey, &npkey, amount - fee).expect("keys are valid");
         let outputs_gamma = Fr::zero();
-        let tx = PaymentTransaction::new(&skey1, &inputs, &[output], &outputs_gamma, fee)
-            .expect("keys are valid");
+        let tx =
+            PaymentTransaction::new(&chain_id, &skey1, &inputs, &[output], &outputs_gamma, fee)
+                .expect("keys are valid");
         tx.validate(&inputs).expect("tx is valid");
 
         //
@@ -1197,7 +2153,7 @@ pub mod tests {
         let output = Output::StakeOutput(output);
         let outputs = [output];
         let outputs_gamma = Fr::zero();
-        let tx = PaymentTransaction::new(&skey1, &inputs, &outputs, &outputs_gamma, fee)
+        let tx = PaymentTransaction::new(&chain_id, &skey1, &inputs, &outputs, &outputs_gamma, fee)
             .expect("Invalid keys");
         match tx.validate(&inputs).unwrap_err() {
             BlockchainError::TransactionError(TransactionError::InvalidMonetaryBalance(
@@ -1216,8 +2172,9 @@ pub mod tests {
         output.amount = 0;
         let output = Output::StakeOutput(output);
         let outputs_gamma = Fr::zero();
-        let tx = PaymentTransaction::new(&skey1, &inputs, &[output], &outputs_gamma, fee)
-            .expect("keys are valid");
+        let tx =
+            PaymentTransaction::new(&chain_id, &skey1, &inputs, &[output], &outputs_gamma, fee)
+                .expect("keys are valid");
         match tx.validate(&inputs).unwrap_err() {
             BlockchainError::OutputError(OutputError::InvalidStake(_output_hash)) => {}
             e => panic!("{}", e),
@@ -1231,8 +2188,9 @@ pub mod tests {
         let output =
             Output::new_stake(&pkey1, &nskey, &npkey, amount - fee).expect("keys are valid");
         let outputs_gamma = Fr::zero();
-        let mut tx = PaymentTransaction::new(&skey1, &inputs, &[output], &outputs_gamma, fee)
-            .expect("keys are valid");
+        let mut tx =
+            PaymentTransaction::new(&chain_id, &skey1, &inputs, &[output], &outputs_gamma, fee)
+                .expect("keys are valid");
         tx.validate(&inputs).expect("tx is valid");
         let output = &mut tx.txouts[0];
         match output {
@@ -1250,8 +2208,119 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn reward_destination() {
+        let (skey1, pkey1) = curve1174::make_random_keys();
+        let (skey2, pkey2) = curve1174::make_random_keys();
+        let (nskey, npkey) = pbc::make_random_keys();
+        let chain_id = Hash::digest("test");
+        let amount: i64 = 1_000_000;
+
+        //
+        // Signed by the recipient.
+        //
+        let input = StakeOutput::new(&pkey1, &nskey, &npkey, amount).expect("keys are valid");
+        let mut output = input.clone();
+        let reward = RewardDestination::new(&skey1, &pkey1, &npkey, &pkey2);
+        output.set_reward(Some(reward), &nskey);
+        output.validate().expect("reward is valid");
+        assert_eq!(output.reward_key(), pkey2);
+        assert_eq!(input.reward_key(), pkey1);
+        let inputs = [Output::StakeOutput(input)];
+        let tx = RestakeTransaction::new(&chain_id, &nskey, &npkey, &inputs, &[output.into()])
+            .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+
+        //
+        // Signed by someone else.
+        //
+        let mut output = StakeOutput::new(&pkey1, &nskey, &npkey, amount).expect("keys are valid");
+        let reward = RewardDestination::new(&skey2, &pkey2, &npkey, &pkey2);
+        output.set_reward(Some(reward), &nskey);
+        match output.validate().unwrap_err() {
+            BlockchainError::OutputError(OutputError::InvalidRewardSignature(_output_hash)) => {}
+            e => panic!("{}", e),
+        }
+
+        //
+        // Signed for another validator.
+        //
+        let (_nskey2, npkey2) = pbc::make_random_keys();
+        let mut output = StakeOutput::new(&pkey1, &nskey, &npkey, amount).expect("keys are valid");
+        let reward = RewardDestination::new(&skey1, &pkey1, &npkey2, &pkey2);
+        output.set_reward(Some(reward), &nskey);
+        match output.validate().unwrap_err() {
+            BlockchainError::OutputError(OutputError::InvalidRewardSignature(_output_hash)) => {}
+            e => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn revoke_delegation() {
+        let chain_id = Hash::digest("test");
+        let (_skey1, pkey1) = curve1174::make_random_keys();
+        let (nskey, npkey) = pbc::make_random_keys();
+        let (_oskey, opkey) = pbc::make_random_keys();
+        let amount: i64 = 1_000_000;
+
+        //
+        // Delegated stake.
+        //
+        let input = StakeOutput::new_delegated(&pkey1, &nskey, &npkey, &opkey, amount)
+            .expect("keys are valid");
+        input.validate().expect("stake is valid");
+        assert!(input.is_delegated());
+        assert_eq!(input.consensus_key(), opkey);
+        let inputs = [Output::StakeOutput(input)];
+        let tx = RevokeDelegationTransaction::new(&chain_id, &nskey, &npkey, &inputs)
+            .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+        match &tx.txouts[0] {
+            Output::StakeOutput(o) => {
+                assert!(!o.is_delegated());
+                assert_eq!(o.consensus_key(), npkey);
+                assert_eq!(o.amount, amount);
+            }
+            _ => panic!(),
+        }
+
+        //
+        // Non-delegated stake can't be revoked.
+        //
+        let input = Output::new_stake(&pkey1, &nskey, &npkey, amount).expect("keys are valid");
+        let inputs = [input];
+        match RevokeDelegationTransaction::new(&chain_id, &nskey, &npkey, &inputs)
+            .unwrap_err()
+            .downcast::<TransactionError>()
+        {
+            Ok(TransactionError::NotDelegatedStake(_tx_hash, _output_hash)) => {}
+            e => panic!("{:?}", e),
+        }
+
+        //
+        // Output must not be delegated.
+        //
+        let input = StakeOutput::new_delegated(&pkey1, &nskey, &npkey, &opkey, amount)
+            .expect("keys are valid");
+        let inputs = [Output::StakeOutput(input)];
+        let mut tx = RevokeDelegationTransaction::new(&chain_id, &nskey, &npkey, &inputs)
+            .expect("keys are valid");
+        tx.txouts = vec![Output::StakeOutput(
+            StakeOutput::new_delegated(&pkey1, &nskey, &npkey, &opkey, amount)
+                .expect("keys are valid"),
+        )];
+        match tx.validate(&inputs).unwrap_err() {
+            BlockchainError::TransactionError(TransactionError::DelegatedStakeOutput(
+                _tx_hash,
+                _output_hash,
+            )) => {}
+            e => panic!("{}", e),
+        }
+    }
+
     #[test]
     fn test_supertransaction() {
+        let chain_id = Hash::digest("test");
         let (skey1, pkey1) = curve1174::make_random_keys();
         let (skey2, pkey2) = curve1174::make_random_keys();
         let (skey3, pkey3) = curve1174::make_random_keys();
@@ -1320,15 +2389,15 @@ pub mod tests {
 
         let err_stx = "Can't construct supertransaction";
         let mut stx1 = PaymentTransaction::new_super_transaction(
-            &skeff1, &k_val1, &sum_cap_k, &inputs, &outputs, &gamma_adj, total_fee,
+            &chain_id, &skeff1, &k_val1, &sum_cap_k, &inputs, &outputs, &gamma_adj, total_fee,
         )
         .expect(err_stx);
         let stx2 = PaymentTransaction::new_super_transaction(
-            &skeff2, &k_val2, &sum_cap_k, &inputs, &outputs, &gamma_adj, total_fee,
+            &chain_id, &skeff2, &k_val2, &sum_cap_k, &inputs, &outputs, &gamma_adj, total_fee,
         )
         .expect(err_stx);
         let stx3 = PaymentTransaction::new_super_transaction(
-            &skeff3, &k_val3, &sum_cap_k, &inputs, &outputs, &gamma_adj, total_fee,
+            &chain_id, &skeff3, &k_val3, &sum_cap_k, &inputs, &outputs, &gamma_adj, total_fee,
         )
         .expect(err_stx);
 
@@ -1347,6 +2416,7 @@ pub mod tests {
         let (nskey, npkey) = pbc::make_random_keys();
 
         let version: u64 = 1;
+        let chain_id = Hash::digest("test");
         let height: u64 = 0;
         let timestamp = SystemTime::now();
         let view_change = 0;
@@ -1360,8 +2430,15 @@ pub mod tests {
         //
         {
             let (output0, gamma0) = Output::new_payment(&pkey1, amount).unwrap();
-            let base =
-                BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+            let base = BaseBlockHeader::new(
+                version,
+                chain_id,
+                previous,
+                height,
+                view_change,
+                timestamp,
+                random,
+            );
             let inputs1 = [Hash::digest(&output0)];
             let (output1, gamma1) = Output::new_payment(&pkey2, amount).unwrap();
             let outputs1 = [output1];
@@ -1383,8 +2460,15 @@ pub mod tests {
         //
         {
             let (output0, gamma0) = Output::new_payment(&pkey1, amount).unwrap();
-            let base =
-                BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+            let base = BaseBlockHeader::new(
+                version,
+                chain_id,
+                previous,
+                height,
+                view_change,
+                timestamp,
+                random,
+            );
             let inputs1 = [Hash::digest(&output0)];
             let (output1, gamma1) = Output::new_payment(&pkey2, amount - 1).unwrap();
             let outputs1 = [output1];
@@ -1411,6 +2495,7 @@ pub mod tests {
         let (nskey, npkey) = pbc::make_random_keys();
 
         let version: u64 = 1;
+        let chain_id = Hash::digest("test");
         let height: u64 = 0;
         let timestamp = SystemTime::now();
         let view_change = 0;
@@ -1421,7 +2506,15 @@ pub mod tests {
         let random = pbc::make_VRF(&nskey, &seed);
 
         let (input, gamma0) = Output::new_payment(&pkey, amount).unwrap();
-        let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+        let base = BaseBlockHeader::new(
+            version,
+            chain_id,
+            previous,
+            height,
+            view_change,
+            timestamp,
+            random,
+        );
         let input_hashes = [Hash::digest(&input)];
         let inputs = [input];
         let (output, gamma1) = Output::new_payment(&pkey, amount).unwrap();
@@ -1456,6 +2549,7 @@ pub mod tests {
         let (nskey, npkey) = pbc::make_random_keys();
 
         let version: u64 = 1;
+        let chain_id = Hash::digest("test");
         let height: u64 = 0;
         let timestamp = SystemTime::now();
         let view_change = 0;
@@ -1477,8 +2571,15 @@ pub mod tests {
             let outputs = [output];
             let gamma = inputs_gamma - outputs_gamma;
 
-            let base =
-                BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+            let base = BaseBlockHeader::new(
+                version,
+                chain_id,
+                previous,
+                height,
+                view_change,
+                timestamp,
+                random,
+            );
             let block = MacroBlock::new(
                 base,
                 gamma,
@@ -1504,8 +2605,15 @@ pub mod tests {
             let outputs = [output];
             let gamma = inputs_gamma - outputs_gamma;
 
-            let base =
-                BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+            let base = BaseBlockHeader::new(
+                version,
+                chain_id,
+                previous,
+                height,
+                view_change,
+                timestamp,
+                random,
+            );
             let block = MacroBlock::new(
                 base,
                 gamma,
@@ -1533,8 +2641,15 @@ pub mod tests {
             let outputs = [output];
             let gamma = inputs_gamma - outputs_gamma;
 
-            let base =
-                BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+            let base = BaseBlockHeader::new(
+                version,
+                chain_id,
+                previous,
+                height,
+                view_change,
+                timestamp,
+                random,
+            );
             let block = MacroBlock::new(
                 base,
                 gamma,
@@ -1566,8 +2681,15 @@ pub mod tests {
             let outputs = [output];
             let gamma = inputs_gamma - outputs_gamma;
 
-            let base =
-                BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+            let base = BaseBlockHeader::new(
+                version,
+                chain_id,
+                previous,
+                height,
+                view_change,
+                timestamp,
+                random,
+            );
             let block = MacroBlock::new(
                 base,
                 gamma,
@@ -1589,6 +2711,7 @@ pub mod tests {
         let (nskey, npkey) = pbc::make_random_keys();
 
         let version: u64 = 1;
+        let chain_id = Hash::digest("test");
         let height: u64 = 0;
         let timestamp = SystemTime::now();
         let view_change = 0;
@@ -1599,7 +2722,15 @@ pub mod tests {
         let block_reward: i64 = output_amount - input_amount;
 
         let (input, input_gamma) = Output::new_payment(&pkey, input_amount).unwrap();
-        let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
+        let base = BaseBlockHeader::new(
+            version,
+            chain_id,
+            previous,
+            height,
+            view_change,
+            timestamp,
+            random,
+        );
         let input_hashes = [Hash::digest(&input)];
         let inputs = [input];
         let (output, output_gamma) = Output::new_payment(&pkey, output_amount).unwrap();
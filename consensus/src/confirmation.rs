@@ -0,0 +1,333 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Fast confirmation of transactions.
+//!
+//! Validators countersign hashes of transactions admitted to their mempools.
+//! Signatures of the supermajority are aggregated into a BLS multi-signature,
+//! which lets merchants accept payments before they are included into a block.
+//! An honest validator never admits conflicting transactions, so a pair of
+//! votes for transactions spending the same input proves misbehaviour.
+//!
+
+use crate::error::{ConsensusError, SignerError};
+use crate::signer::Signer;
+use bitvector::BitVector;
+use log::{debug, info};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use stegos_blockchain::{
+    check_multi_signature, check_supermajority, create_multi_signature_index, Blockchain,
+    ValidatorId, VALIDATORS_MAX,
+};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc;
+
+/// A statement countersigned by validators.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ConfirmationInfo {
+    /// Hash of the transaction.
+    pub tx_hash: Hash,
+    /// Height of the chain when the transaction was admitted to mempool.
+    pub height: u64,
+    /// Epoch of validators.
+    pub epoch: u64,
+}
+
+impl Hashable for ConfirmationInfo {
+    fn hash(&self, state: &mut Hasher) {
+        "FastConfirmation".hash(state);
+        self.tx_hash.hash(state);
+        self.height.hash(state);
+        self.epoch.hash(state);
+    }
+}
+
+/// A signature of one validator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConfirmationVote {
+    pub info: ConfirmationInfo,
+    pub validator_id: ValidatorId,
+    pub signature: pbc::Signature,
+}
+
+impl Hashable for ConfirmationVote {
+    fn hash(&self, state: &mut Hasher) {
+        self.info.hash(state);
+        self.validator_id.hash(state);
+        self.signature.hash(state);
+    }
+}
+
+impl ConfirmationVote {
+    pub fn new(info: ConfirmationInfo, validator_id: ValidatorId, skey: &pbc::SecretKey) -> Self {
+        let hash = Hash::digest(&info);
+        let signature = pbc::sign_hash(&hash, skey);
+        ConfirmationVote {
+            info,
+            validator_id,
+            signature,
+        }
+    }
+
+    /// Create a new vote signed by `signer`.
+    pub fn sign(
+        info: ConfirmationInfo,
+        validator_id: ValidatorId,
+        signer: &dyn Signer,
+    ) -> Result<Self, SignerError> {
+        let signature = signer.sign_confirmation(info)?;
+        Ok(ConfirmationVote {
+            info,
+            validator_id,
+            signature,
+        })
+    }
+
+    #[must_use]
+    pub fn validate(&self, blockchain: &Blockchain) -> Result<(), ConsensusError> {
+        let validator_id = self.validator_id;
+        if (validator_id as usize) >= blockchain.validators().len() {
+            return Err(ConsensusError::InvalidValidatorId(validator_id));
+        }
+        let hash = Hash::digest(&self.info);
+        let author = blockchain.validators()[validator_id as usize].0;
+        if let Err(_e) = pbc::check_hash(&hash, &self.signature, &author) {
+            return Err(ConsensusError::InvalidConfirmationSignature(
+                self.info.tx_hash,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A multi-signature of the supermajority of validators.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ConfirmationProof {
+    pub info: ConfirmationInfo,
+    pub multisig: pbc::Signature,
+    /// Indexes of signers in the list of validators.
+    pub signers: Vec<ValidatorId>,
+}
+
+impl Hashable for ConfirmationProof {
+    fn hash(&self, state: &mut Hasher) {
+        self.info.hash(state);
+        self.multisig.hash(state);
+    }
+}
+
+impl ConfirmationProof {
+    pub fn new<'a, I>(info: ConfirmationInfo, signatures: I) -> Self
+    where
+        I: Iterator<Item = (u32, &'a pbc::Signature)>,
+    {
+        let (multisig, multimap) = create_multi_signature_index(signatures);
+        let signers = multimap.iter().map(|id| id as ValidatorId).collect();
+        ConfirmationProof {
+            info,
+            multisig,
+            signers,
+        }
+    }
+
+    /// Checks the proof against the given validators.
+    #[must_use]
+    pub fn validate_with(
+        &self,
+        validators: &Vec<(pbc::PublicKey, i64)>,
+        total_slots: i64,
+    ) -> Result<(), ConsensusError> {
+        let mut multimap = BitVector::new(VALIDATORS_MAX);
+        for id in &self.signers {
+            if (*id as usize) >= validators.len() {
+                return Err(ConsensusError::InvalidValidatorId(*id));
+            }
+            multimap.insert(*id as usize);
+        }
+        let hash = Hash::digest(&self.info);
+        check_multi_signature(&hash, &self.multisig, &multimap, validators, total_slots)
+            .map_err(|e| ConsensusError::InvalidConfirmationProof(self.info.tx_hash, e))
+    }
+
+    /// Checks the proof against validators of the current epoch.
+    #[must_use]
+    pub fn validate(&self, blockchain: &Blockchain) -> Result<(), ConsensusError> {
+        if self.info.epoch != blockchain.epoch() {
+            return Err(ConsensusError::InvalidConfirmationEpoch(
+                self.info.epoch,
+                blockchain.epoch(),
+            ));
+        }
+        self.validate_with(blockchain.validators(), blockchain.total_slots())
+    }
+}
+
+/// Collects votes for the current epoch.
+#[derive(Debug, Default)]
+pub struct ConfirmationCollector {
+    epoch: u64,
+    /// Signatures for each statement.
+    votes: HashMap<ConfirmationInfo, BTreeMap<ValidatorId, pbc::Signature>>,
+    /// Proofs for confirmed transactions.
+    proofs: HashMap<Hash, ConfirmationProof>,
+}
+
+impl ConfirmationCollector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a proof for the transaction, if confirmed in the current epoch.
+    pub fn proof(&self, tx_hash: &Hash) -> Option<&ConfirmationProof> {
+        self.proofs.get(tx_hash)
+    }
+
+    /// Returns true if the transaction has already been confirmed.
+    pub fn is_confirmed(&self, tx_hash: &Hash) -> bool {
+        self.proofs.contains_key(tx_hash)
+    }
+
+    /// Forgets votes and proofs of previous epochs.
+    fn update_epoch(&mut self, epoch: u64) {
+        if self.epoch != epoch {
+            debug!(
+                "Reset fast confirmations: old_epoch={}, new_epoch={}",
+                self.epoch, epoch
+            );
+            self.epoch = epoch;
+            self.votes.clear();
+            self.proofs.clear();
+        }
+    }
+
+    ///
+    /// Handles a vote from a validator.
+    /// Returns a proof once the supermajority of validators has signed the same statement.
+    ///
+    pub fn handle_vote(
+        &mut self,
+        blockchain: &Blockchain,
+        vote: ConfirmationVote,
+    ) -> Result<Option<ConfirmationProof>, ConsensusError> {
+        self.update_epoch(blockchain.epoch());
+        if vote.info.epoch != blockchain.epoch() {
+            return Err(ConsensusError::InvalidConfirmationEpoch(
+                vote.info.epoch,
+                blockchain.epoch(),
+            ));
+        }
+        if self.is_confirmed(&vote.info.tx_hash) {
+            return Ok(None);
+        }
+        vote.validate(blockchain)?;
+
+        let signatures = self.votes.entry(vote.info).or_default();
+        signatures.insert(vote.validator_id, vote.signature);
+        let collected_slots: i64 = signatures
+            .keys()
+            .map(|id| blockchain.validators()[*id as usize].1)
+            .sum();
+        debug!(
+            "Collected fast confirmation votes: tx={}, collected={}, total={}",
+            vote.info.tx_hash,
+            collected_slots,
+            blockchain.total_slots()
+        );
+        if !check_supermajority(collected_slots, blockchain.total_slots()) {
+            return Ok(None);
+        }
+
+        let proof = ConfirmationProof::new(vote.info, signatures.iter().map(|(k, v)| (*k, v)));
+        let tx_hash = vote.info.tx_hash;
+        self.votes.retain(|info, _| info.tx_hash != tx_hash);
+        self.proofs.insert(tx_hash, proof.clone());
+        info!(
+            "Transaction has been confirmed by validators: tx={}, height={}, epoch={}",
+            tx_hash, vote.info.height, vote.info.epoch
+        );
+        Ok(Some(proof))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proof() {
+        let keys: Vec<_> = (0..4).map(|_| pbc::make_random_keys()).collect();
+        let validators: Vec<(pbc::PublicKey, i64)> =
+            keys.iter().map(|(_skey, pkey)| (*pkey, 1)).collect();
+        let total_slots = validators.len() as i64;
+        let info = ConfirmationInfo {
+            tx_hash: Hash::digest("tx"),
+            height: 10,
+            epoch: 2,
+        };
+        let votes: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(id, (skey, _pkey))| ConfirmationVote::new(info, id as ValidatorId, skey))
+            .collect();
+
+        // Supermajority.
+        let proof = ConfirmationProof::new(
+            info,
+            votes[..3].iter().map(|v| (v.validator_id, &v.signature)),
+        );
+        assert_eq!(proof.signers, vec![0, 1, 2]);
+        proof.validate_with(&validators, total_slots).unwrap();
+
+        // Not enough votes.
+        let proof = ConfirmationProof::new(
+            info,
+            votes[..2].iter().map(|v| (v.validator_id, &v.signature)),
+        );
+        match proof.validate_with(&validators, total_slots) {
+            Err(ConsensusError::InvalidConfirmationProof(..)) => {}
+            e => panic!("{:?}", e),
+        }
+
+        // Other statement.
+        let mut proof = ConfirmationProof::new(
+            info,
+            votes[..3].iter().map(|v| (v.validator_id, &v.signature)),
+        );
+        proof.info.height += 1;
+        match proof.validate_with(&validators, total_slots) {
+            Err(ConsensusError::InvalidConfirmationProof(..)) => {}
+            e => panic!("{:?}", e),
+        }
+
+        // Unknown signer.
+        let mut proof = ConfirmationProof::new(
+            info,
+            votes[..3].iter().map(|v| (v.validator_id, &v.signature)),
+        );
+        proof.signers.push(validators.len() as ValidatorId);
+        match proof.validate_with(&validators, total_slots) {
+            Err(ConsensusError::InvalidValidatorId(..)) => {}
+            e => panic!("{:?}", e),
+        }
+    }
+}
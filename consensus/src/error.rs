@@ -21,7 +21,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::signer::SignKind;
 use failure::Fail;
+use stegos_blockchain::MultisignatureError;
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
 
@@ -65,6 +67,21 @@ pub enum ConsensusError {
         _0, _1
     )]
     InvalidLastBlockHash(Hash, Hash),
+    #[fail(
+        display = "Received ViewChangeMessage, with other epoch: msg_epoch={}, our_epoch={}",
+        _0, _1
+    )]
+    InvalidViewChangeEpoch(u64, u64),
+    #[fail(
+        display = "Received ViewChangeMessage from another chain: msg_chain_id={}, our_chain_id={}",
+        _0, _1
+    )]
+    InvalidViewChangeChainId(Hash, Hash),
+    #[fail(
+        display = "Conflicting view change messages: validator_id={}, height={}, view_change={}",
+        _0, _1, _2
+    )]
+    ViewChangeEquivocation(u32, u64, u32),
     #[fail(
         display = "Malicious view change message found, validator_id greater than \
                    size of validators: validator_id={}",
@@ -73,4 +90,55 @@ pub enum ConsensusError {
     InvalidValidatorId(u32),
     #[fail(display = "Failed to check view change message signature.")]
     InvalidViewChangeSignature,
+    #[fail(
+        display = "Message timestamp is outside of the acceptance window: pkey={}, skew={}ms",
+        _0, _1
+    )]
+    MessageTimestampOutOfWindow(pbc::PublicKey, i64),
+    #[fail(
+        display = "Validator is penalized for messages outside of the acceptance window: pkey={}",
+        _0
+    )]
+    PenalizedValidator(pbc::PublicKey),
+    #[fail(display = "Invalid fast confirmation signature: tx={}", _0)]
+    InvalidConfirmationSignature(Hash),
+    #[fail(
+        display = "Fast confirmation from other epoch: confirmation_epoch={}, our_epoch={}",
+        _0, _1
+    )]
+    InvalidConfirmationEpoch(u64, u64),
+    #[fail(display = "Invalid fast confirmation proof: tx={}, error={}", _0, _1)]
+    InvalidConfirmationProof(Hash, MultisignatureError),
+}
+
+#[derive(Debug, Fail)]
+pub enum SignerError {
+    #[fail(
+        display = "Remote signer is unavailable: endpoint={}, error={}",
+        _0, _1
+    )]
+    Unavailable(String, String),
+    #[fail(display = "Remote signer refused to sign: {}", _0)]
+    Refused(String),
+    #[fail(display = "Invalid signature from the remote signer: pkey={}", _0)]
+    InvalidSignature(pbc::PublicKey),
+    #[fail(display = "Unexpected response from the signer.")]
+    UnexpectedResponse,
+    #[fail(
+        display = "Conflicting {} is already signed: height={}, round={}",
+        _0, _1, _2
+    )]
+    DoubleSign(SignKind, u64, u32),
+    #[fail(
+        display = "Refused to sign an old {}: height={}, max_signed_height={}",
+        _0, _1, _2
+    )]
+    StaleRequest(SignKind, u64, u64),
+    #[fail(
+        display = "Refused to sign a {} with another parent: height={}",
+        _0, _1
+    )]
+    ForkedParent(SignKind, u64),
+    #[fail(display = "Failed to save the signer state: path={}, error={}", _0, _1)]
+    StateError(String, String),
 }
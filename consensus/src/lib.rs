@@ -23,14 +23,19 @@
 
 #![deny(warnings)]
 
+pub mod confirmation;
 mod error;
 mod message;
 pub mod metrics;
+mod network_time;
 pub mod optimistic;
 pub mod protos;
+pub mod signer;
 mod state;
 
 pub use crate::error::*;
 pub use crate::message::*;
-pub use crate::optimistic::ViewChangeCollector;
+pub use crate::network_time::NetworkTime;
+pub use crate::optimistic::{ViewChangeCollector, ViewChangeCollectorInfo};
+pub use crate::signer::{LocalSigner, RemoteSigner, Signer, SignerConfig};
 pub use crate::state::*;
@@ -22,6 +22,8 @@
 // SOFTWARE.
 
 use crate::error::*;
+use crate::signer::Signer;
+use std::time::SystemTime;
 use stegos_blockchain::{MacroBlockHeader, Transaction};
 use stegos_crypto::hash::{Hash, Hashable, Hasher};
 use stegos_crypto::pbc;
@@ -83,6 +85,8 @@ pub struct ConsensusMessage {
     pub block_hash: Hash,
     /// Message Body.
     pub body: ConsensusMessageBody,
+    /// Time when this message was created.
+    pub timestamp: SystemTime,
     /// Sender of this message.
     pub pkey: pbc::PublicKey,
     /// Signature of this message.
@@ -111,34 +115,65 @@ impl ConsensusMessage {
         pkey: &pbc::PublicKey,
         body: ConsensusMessageBody,
     ) -> ConsensusMessage {
-        let mut hasher = Hasher::new();
-        height.hash(&mut hasher);
-        round.hash(&mut hasher);
-        block_hash.hash(&mut hasher);
-        body.hash(&mut hasher);
-        let hash = hasher.result();
-        let sig = pbc::sign_hash(&hash, skey);
+        let mut msg = Self::unsigned(height, round, block_hash, *pkey, body);
+        let (hash, _sig, _pkey) = msg.signature_check();
+        msg.sig = pbc::sign_hash(&hash, skey);
+        msg
+    }
+
+    ///
+    /// Create a new consensus message signed by `signer`.
+    ///
+    pub fn sign(
+        height: u64,
+        round: u32,
+        block_hash: Hash,
+        signer: &dyn Signer,
+        body: ConsensusMessageBody,
+    ) -> Result<ConsensusMessage, SignerError> {
+        let mut msg = Self::unsigned(height, round, block_hash, signer.pkey(), body);
+        msg.sig = signer.sign_consensus_message(&msg)?;
+        Ok(msg)
+    }
+
+    fn unsigned(
+        height: u64,
+        round: u32,
+        block_hash: Hash,
+        pkey: pbc::PublicKey,
+        body: ConsensusMessageBody,
+    ) -> ConsensusMessage {
+        let timestamp = SystemTime::now();
         ConsensusMessage {
             height,
             round,
             block_hash,
             body,
-            pkey: pkey.clone(),
-            sig,
+            timestamp,
+            pkey,
+            sig: pbc::Signature::zero(),
         }
     }
 
     ///
-    /// Validate signature of the message.
+    /// Signed hash, signature and signer, allows to check the signature off the event loop.
     ///
-    pub fn validate(&self) -> Result<(), ConsensusError> {
+    pub fn signature_check(&self) -> (Hash, pbc::Signature, pbc::PublicKey) {
         let mut hasher = Hasher::new();
         self.height.hash(&mut hasher);
         self.round.hash(&mut hasher);
         self.block_hash.hash(&mut hasher);
         self.body.hash(&mut hasher);
-        let hash = hasher.result();
-        if let Err(_e) = pbc::check_hash(&hash, &self.sig, &self.pkey) {
+        self.timestamp.hash(&mut hasher);
+        (hasher.result(), self.sig, self.pkey)
+    }
+
+    ///
+    /// Validate signature of the message.
+    ///
+    pub fn validate(&self) -> Result<(), ConsensusError> {
+        let (hash, sig, pkey) = self.signature_check();
+        if let Err(_e) = pbc::check_hash(&hash, &sig, &pkey) {
             return Err(ConsensusError::InvalidMessageSignature);
         }
         Ok(())
@@ -51,4 +51,49 @@ lazy_static! {
         "Current node consensus state (0 = Not in consensus, 1 = Proposing, 2 = Prevote, 3 = Propose, 4 = Commit)."
     )
     .unwrap();
+
+    pub static ref MESSAGES_OUT_OF_WINDOW: IntCounter = register_int_counter!(
+        "stegos_consensus_messages_out_of_window",
+        "The number of consensus messages dropped due to timestamp outside of the acceptance window."
+    )
+    .unwrap();
+
+    pub static ref PENALIZED_VALIDATORS: IntCounter = register_int_counter!(
+        "stegos_consensus_penalized_validators",
+        "The number of times validators were penalized for messages outside of the acceptance window."
+    )
+    .unwrap();
+
+    pub static ref NETWORK_TIME_OFFSET: IntGauge = register_int_gauge!(
+        "stegos_consensus_network_time_offset",
+        "Offset of the adjusted network time relative to the local clock, in milliseconds."
+    )
+    .unwrap();
+
+    pub static ref ROUND_DURATION: Histogram = register_histogram!(
+        "stegos_consensus_round_duration",
+        "Time from the start of a consensus round to the block commit, in seconds.",
+        vec![0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0]
+    )
+    .unwrap();
+
+    pub static ref VIEW_CHANGES: Histogram = register_histogram!(
+        "stegos_consensus_view_changes",
+        "The number of view changes per height.",
+        vec![0.0, 1.0, 2.0, 3.0, 5.0, 10.0]
+    )
+    .unwrap();
+
+    pub static ref MISSED_LEADERSHIP: IntCounterVec = register_int_counter_vec!(
+        "stegos_consensus_missed_leadership",
+        "The number of times a leader has been replaced by a view change.",
+        &["validator"]
+    )
+    .unwrap();
+
+    pub static ref VIEW_CHANGE_COLLECTED_SLOTS: IntGauge = register_int_gauge!(
+        "stegos_consensus_view_change_collected_slots",
+        "Slots of validators which have voted for the current view change."
+    )
+    .unwrap();
 }
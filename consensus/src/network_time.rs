@@ -0,0 +1,272 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Adjusted network time for consensus messages.
+//!
+
+use crate::error::ConsensusError;
+use crate::metrics;
+use log::warn;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stegos_crypto::pbc;
+
+/// Maximal number of tracked clock offsets.
+const MAX_OFFSETS: usize = 1024;
+/// Number of out-of-window messages after which a validator is penalized.
+const MAX_STRIKES: u32 = 3;
+/// For how long messages of a penalized validator are rejected.
+const PENALTY_DURATION: Duration = Duration::from_secs(300);
+
+/// Converts SystemTime into milliseconds since UNIX epoch.
+fn to_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() * 1000 + d.subsec_millis() as u64) as i64,
+        Err(e) => {
+            let d = e.duration();
+            -((d.as_secs() * 1000 + d.subsec_millis() as u64) as i64)
+        }
+    }
+}
+
+/// Shifts SystemTime by signed number of milliseconds.
+fn shift(time: SystemTime, offset: i64) -> SystemTime {
+    if offset >= 0 {
+        time + Duration::from_millis(offset as u64)
+    } else {
+        time - Duration::from_millis((-offset) as u64)
+    }
+}
+
+///
+/// Local estimation of the network time.
+///
+/// Keeps the last observed clock offset of every validator and adjusts
+/// the local clock by the median offset. Only offsets within the acceptance
+/// window are taken into account, so a single validator can't move the clock.
+/// Validators which repeatedly send messages outside of the window are penalized:
+/// all their messages are rejected for `PENALTY_DURATION`.
+///
+#[derive(Debug)]
+pub struct NetworkTime {
+    /// Maximal difference between message timestamp and network time.
+    window: Duration,
+    /// Last known clock offset in milliseconds per validator.
+    offsets: HashMap<pbc::PublicKey, i64>,
+    /// Number of out-of-window messages per validator.
+    strikes: HashMap<pbc::PublicKey, u32>,
+    /// Penalized validators and the end of their penalty.
+    penalties: HashMap<pbc::PublicKey, Instant>,
+}
+
+impl NetworkTime {
+    pub fn new(window: Duration) -> Self {
+        let offsets = HashMap::new();
+        let strikes = HashMap::new();
+        let penalties = HashMap::new();
+        NetworkTime {
+            window,
+            offsets,
+            strikes,
+            penalties,
+        }
+    }
+
+    /// Returns the acceptance window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns the median clock offset in milliseconds.
+    fn offset(&self) -> i64 {
+        if self.offsets.is_empty() {
+            return 0;
+        }
+        let mut offsets: Vec<i64> = self.offsets.values().cloned().collect();
+        offsets.sort();
+        offsets[offsets.len() / 2]
+    }
+
+    /// Returns adjusted network time.
+    pub fn now(&self) -> SystemTime {
+        shift(SystemTime::now(), self.offset())
+    }
+
+    /// Returns the difference between timestamp and network time in milliseconds.
+    fn skew(&self, timestamp: SystemTime) -> i64 {
+        to_millis(timestamp) - to_millis(self.now())
+    }
+
+    fn window_millis(&self) -> i64 {
+        (self.window.as_secs() * 1000 + self.window.subsec_millis() as u64) as i64
+    }
+
+    ///
+    /// Returns true if timestamp is within the acceptance window.
+    /// Unlike `check()`, the sender is neither penalized nor taken into account.
+    ///
+    pub fn is_within_window(&self, timestamp: SystemTime) -> bool {
+        self.skew(timestamp).abs() <= self.window_millis()
+    }
+
+    /// Returns true if messages of the validator are rejected.
+    pub fn is_penalized(&mut self, pkey: &pbc::PublicKey) -> bool {
+        match self.penalties.get(pkey) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                self.penalties.remove(pkey);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Registers an out-of-window message and penalizes the sender if needed.
+    fn strike(&mut self, pkey: &pbc::PublicKey) {
+        // The sender no longer affects network time.
+        self.offsets.remove(pkey);
+        if self.strikes.len() >= MAX_OFFSETS && !self.strikes.contains_key(pkey) {
+            self.strikes.clear();
+        }
+        let strikes = self.strikes.entry(pkey.clone()).or_insert(0);
+        *strikes += 1;
+        if *strikes < MAX_STRIKES {
+            return;
+        }
+        self.strikes.remove(pkey);
+        metrics::PENALIZED_VALIDATORS.inc();
+        warn!(
+            "Penalized validator for messages outside of the window: pkey={}, duration={:?}",
+            pkey, PENALTY_DURATION
+        );
+        self.penalties
+            .insert(pkey.clone(), Instant::now() + PENALTY_DURATION);
+    }
+
+    ///
+    /// Checks that timestamp of a message is within the acceptance window
+    /// and updates the clock offset of the sender.
+    ///
+    pub fn check(
+        &mut self,
+        pkey: &pbc::PublicKey,
+        timestamp: SystemTime,
+    ) -> Result<(), ConsensusError> {
+        if self.is_penalized(pkey) {
+            return Err(ConsensusError::PenalizedValidator(pkey.clone()));
+        }
+        let window = self.window_millis();
+        let skew = self.skew(timestamp);
+        if skew.abs() > window {
+            metrics::MESSAGES_OUT_OF_WINDOW.inc();
+            warn!(
+                "Rejected consensus message with timestamp outside of the window: pkey={}, skew={}ms, window={}ms",
+                pkey, skew, window
+            );
+            self.strike(pkey);
+            return Err(ConsensusError::MessageTimestampOutOfWindow(
+                pkey.clone(),
+                skew,
+            ));
+        }
+        let local = SystemTime::now();
+        if self.offsets.len() >= MAX_OFFSETS && !self.offsets.contains_key(pkey) {
+            return Ok(());
+        }
+        let offset = to_millis(timestamp) - to_millis(local);
+        self.offsets.insert(pkey.clone(), offset);
+        metrics::NETWORK_TIME_OFFSET.set(self.offset());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acceptance_window() {
+        let window = Duration::from_secs(60);
+        let mut time = NetworkTime::new(window);
+        let (_skey, pkey) = pbc::make_random_keys();
+
+        time.check(&pkey, SystemTime::now()).unwrap();
+        time.check(&pkey, SystemTime::now() - Duration::from_secs(30))
+            .unwrap();
+        time.check(&pkey, SystemTime::now() + Duration::from_secs(30))
+            .unwrap();
+        match time.check(&pkey, SystemTime::now() - Duration::from_secs(3600)) {
+            Err(ConsensusError::MessageTimestampOutOfWindow(p, skew)) => {
+                assert_eq!(p, pkey);
+                assert!(skew < 0);
+            }
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn penalty() {
+        let window = Duration::from_secs(60);
+        let mut time = NetworkTime::new(window);
+        let (_skey, pkey) = pbc::make_random_keys();
+        let (_skey2, pkey2) = pbc::make_random_keys();
+
+        time.check(&pkey, SystemTime::now()).unwrap();
+        time.check(&pkey2, SystemTime::now()).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        assert!(!time.is_within_window(old));
+        for _ in 0..MAX_STRIKES {
+            assert!(!time.is_penalized(&pkey));
+            match time.check(&pkey, old) {
+                Err(ConsensusError::MessageTimestampOutOfWindow(..)) => {}
+                e => panic!("{:?}", e),
+            }
+        }
+        assert!(time.is_penalized(&pkey));
+        assert!(!time.offsets.contains_key(&pkey));
+        // Valid messages of the penalized validator are rejected too.
+        match time.check(&pkey, SystemTime::now()) {
+            Err(ConsensusError::PenalizedValidator(p)) => assert_eq!(p, pkey),
+            e => panic!("{:?}", e),
+        }
+        time.check(&pkey2, SystemTime::now()).unwrap();
+
+        // The penalty expires.
+        time.penalties
+            .insert(pkey.clone(), Instant::now() - Duration::from_secs(1));
+        time.check(&pkey, SystemTime::now()).unwrap();
+    }
+
+    #[test]
+    fn median_offset() {
+        let window = Duration::from_secs(60);
+        let mut time = NetworkTime::new(window);
+        let now = SystemTime::now();
+        for offset in &[-10i64, 20, 25] {
+            let (_skey, pkey) = pbc::make_random_keys();
+            let timestamp = shift(now, offset * 1000);
+            time.check(&pkey, timestamp).unwrap();
+        }
+        let offset = time.offset();
+        assert!(offset >= 19_000 && offset <= 21_000, "offset={}", offset);
+    }
+}
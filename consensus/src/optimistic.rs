@@ -23,19 +23,32 @@
 //! View Changes implementation.
 //!
 
-use crate::error::ConsensusError;
-use log::{debug, info};
+use crate::error::{ConsensusError, SignerError};
+use crate::metrics;
+use crate::signer::Signer;
+use log::{debug, info, warn};
+use serde::de::{Deserialize, Deserializer, Error as SerdeError};
+use serde::ser::{Error as SerError, Serialize, Serializer};
+use serde_derive::Serialize;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
 use stegos_blockchain::view_changes::*;
-use stegos_blockchain::{check_supermajority, Blockchain, ChainInfo, ValidatorId};
+use stegos_blockchain::{check_supermajority, Blockchain, ChainInfo, ValidatorId, ViewCounter};
 use stegos_crypto::hash::{Hash, Hashable, Hasher};
 use stegos_crypto::pbc;
+use stegos_serialization::traits::ProtoConvert;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct ViewChangeMessage {
     pub chain: ChainInfo,
     pub validator_id: ValidatorId,
+    /// Signature of chain info, aggregated into ViewChangeProof.
     pub signature: pbc::Signature,
+    /// Time when this message was created.
+    pub timestamp: SystemTime,
+    /// Signature of chain info, timestamp and `signature`.
+    pub timestamp_sig: pbc::Signature,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -49,35 +62,175 @@ impl Hashable for ViewChangeMessage {
         self.chain.hash(state);
         self.validator_id.hash(state);
         self.signature.hash(state);
+        self.timestamp.hash(state);
+        self.timestamp_sig.hash(state);
     }
 }
 
+/// Hash of the part of ViewChangeMessage covered by `timestamp_sig`.
+pub(crate) fn timestamp_hash(
+    chain: &ChainInfo,
+    timestamp: &SystemTime,
+    signature: &pbc::Signature,
+) -> Hash {
+    let mut hasher = Hasher::new();
+    chain.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    signature.hash(&mut hasher);
+    hasher.result()
+}
+
 impl ViewChangeMessage {
     pub fn new(chain: ChainInfo, validator_id: ValidatorId, skey: &pbc::SecretKey) -> Self {
         let hash = Hash::digest(&chain);
         let signature = pbc::sign_hash(&hash, skey);
+        let timestamp = SystemTime::now();
+        let hash = timestamp_hash(&chain, &timestamp, &signature);
+        let timestamp_sig = pbc::sign_hash(&hash, skey);
         ViewChangeMessage {
             chain,
             validator_id,
             signature,
+            timestamp,
+            timestamp_sig,
         }
     }
 
-    #[must_use]
-    pub fn validate(&self, blockchain: &Blockchain) -> Result<(), ConsensusError> {
+    /// Create a new view change message signed by `signer`.
+    pub fn sign(
+        chain: ChainInfo,
+        validator_id: ValidatorId,
+        signer: &dyn Signer,
+    ) -> Result<Self, SignerError> {
+        let timestamp = SystemTime::now();
+        let (signature, timestamp_sig) = signer.sign_view_change(chain, timestamp)?;
+        Ok(ViewChangeMessage {
+            chain,
+            validator_id,
+            signature,
+            timestamp,
+            timestamp_sig,
+        })
+    }
+
+    ///
+    /// Signed hashes, signatures and signers, allows to check the signatures off the event loop.
+    ///
+    pub fn signature_checks(
+        &self,
+        blockchain: &Blockchain,
+    ) -> Result<Vec<(Hash, pbc::Signature, pbc::PublicKey)>, ConsensusError> {
         let validator_id = self.validator_id;
         if (validator_id as usize) >= blockchain.validators().len() {
             return Err(ConsensusError::InvalidValidatorId(validator_id));
         }
-        let hash = Hash::digest(&self.chain);
         let author = blockchain.validators()[validator_id as usize].0;
-        if let Err(_e) = pbc::check_hash(&hash, &self.signature, &author) {
-            return Err(ConsensusError::InvalidViewChangeSignature);
+        let hash = Hash::digest(&self.chain);
+        let timestamp_hash = timestamp_hash(&self.chain, &self.timestamp, &self.signature);
+        Ok(vec![
+            (hash, self.signature, author),
+            (timestamp_hash, self.timestamp_sig, author),
+        ])
+    }
+
+    #[must_use]
+    pub fn validate(&self, blockchain: &Blockchain) -> Result<(), ConsensusError> {
+        for (hash, sig, pkey) in self.signature_checks(blockchain)? {
+            if let Err(_e) = pbc::check_hash(&hash, &sig, &pkey) {
+                return Err(ConsensusError::InvalidViewChangeSignature);
+            }
         }
         Ok(())
     }
 }
 
+/// Serialized as a hex-encoded protobuf, suitable for exporting.
+impl Serialize for SealedViewChangeProof {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let buf = self.into_buffer().map_err(S::Error::custom)?;
+        serializer.serialize_str(&hex::encode(&buf))
+    }
+}
+
+impl<'de> Deserialize<'de> for SealedViewChangeProof {
+    fn deserialize<D>(deserializer: D) -> Result<SealedViewChangeProof, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let buf = hex::decode(&s).map_err(D::Error::custom)?;
+        SealedViewChangeProof::from_buffer(&buf).map_err(D::Error::custom)
+    }
+}
+
+/// View change messages seen at the current height, used to detect replays and equivocation.
+#[derive(Debug, Default)]
+struct ReplayCache {
+    messages: HashMap<(ValidatorId, u64, ViewCounter), ViewChangeMessage>,
+    /// Pairs of conflicting messages signed by the same validator.
+    equivocations: Vec<(ViewChangeMessage, ViewChangeMessage)>,
+}
+
+impl ReplayCache {
+    /// Remember a validated message.
+    /// Returns false if the same message has already been received.
+    fn check(&mut self, message: &ViewChangeMessage) -> Result<bool, ConsensusError> {
+        let key = (
+            message.validator_id,
+            message.chain.height,
+            message.chain.view_change,
+        );
+        match self.messages.get(&key) {
+            None => {
+                self.messages.insert(key, *message);
+                Ok(true)
+            }
+            // Signatures are deterministic, only timestamp can differ on resend.
+            Some(prev) if prev.signature == message.signature => Ok(false),
+            Some(prev) => {
+                warn!(
+                    "Conflicting view change messages: validator_id={}, height={}, view_change={}, first={:?}, second={:?}",
+                    key.0, key.1, key.2, prev.chain, message.chain
+                );
+                self.equivocations.push((*prev, *message));
+                Err(ConsensusError::ViewChangeEquivocation(key.0, key.1, key.2))
+            }
+        }
+    }
+
+    /// Forget messages for the previous heights.
+    fn prune(&mut self, height: u64) {
+        self.messages.retain(|(_, h, _), _| *h >= height);
+    }
+}
+
+/// View change message sent by this node.
+#[derive(Clone, Debug, Serialize)]
+pub struct OwnViewChangeInfo {
+    pub height: u64,
+    pub view_change: u32,
+    pub last_block: Hash,
+    pub timestamp: SystemTime,
+}
+
+/// State of `ViewChangeCollector`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ViewChangeCollectorInfo {
+    /// validator_id of this node.
+    pub validator_id: ValidatorId,
+    /// Validators which have sent a view change for the current view_change.
+    pub collected: Vec<pbc::PublicKey>,
+    pub collected_slots: i64,
+    pub total_slots: i64,
+    /// The last view change message of this node, if any.
+    pub our_message: Option<OwnViewChangeInfo>,
+    /// Validators which have signed conflicting view change messages.
+    pub equivocators: Vec<pbc::PublicKey>,
+}
+
 //Collect ViewChange for current height only.
 #[derive(Debug)]
 pub struct ViewChangeCollector {
@@ -87,16 +240,17 @@ pub struct ViewChangeCollector {
     collected_slots: i64,
     /// validator_id of current node.
     validator_id: ValidatorId,
+    /// The last view change message of this node.
+    our_message: Option<ViewChangeMessage>,
+    /// Messages received at the current height.
+    replay_cache: ReplayCache,
     pkey: pbc::PublicKey,
-    skey: pbc::SecretKey,
+    signer: Arc<dyn Signer>,
 }
 
 impl ViewChangeCollector {
-    pub fn new(
-        blockchain: &Blockchain,
-        pkey: pbc::PublicKey,
-        skey: pbc::SecretKey,
-    ) -> ViewChangeCollector {
+    pub fn new(blockchain: &Blockchain, signer: Arc<dyn Signer>) -> ViewChangeCollector {
+        let pkey = signer.pkey();
         // get validator id, by public_key
         let validator_id = blockchain
             .validators()
@@ -105,14 +259,47 @@ impl ViewChangeCollector {
             .find(|(_id, validator)| validator.0 == pkey)
             .map(|(id, _)| id as ValidatorId)
             .expect("Node is not validator");
+        metrics::VIEW_CHANGE_COLLECTED_SLOTS.set(0);
         ViewChangeCollector {
             pkey,
-            skey,
+            signer,
             collected_slots: 0,
             validator_id,
+            our_message: None,
+            replay_cache: Default::default(),
             actual_view_changes: Default::default(),
         }
     }
+
+    /// Check that the message belongs to the current height of the chain.
+    fn check_chain(
+        &self,
+        blockchain: &Blockchain,
+        message: &ViewChangeMessage,
+    ) -> Result<(), ConsensusError> {
+        if message.chain.height != blockchain.height() {
+            return Err(ConsensusError::InvalidViewChangeHeight(
+                message.chain.height,
+                blockchain.height(),
+            ));
+        }
+
+        if message.chain.epoch != blockchain.epoch() {
+            return Err(ConsensusError::InvalidViewChangeEpoch(
+                message.chain.epoch,
+                blockchain.epoch(),
+            ));
+        }
+
+        if message.chain.chain_id != blockchain.chain_id() {
+            return Err(ConsensusError::InvalidViewChangeChainId(
+                message.chain.chain_id,
+                blockchain.chain_id(),
+            ));
+        }
+        Ok(())
+    }
+
     //
     // External events
     //
@@ -121,11 +308,33 @@ impl ViewChangeCollector {
         blockchain: &Blockchain,
         message: ViewChangeMessage,
     ) -> Result<Option<ViewChangeProof>, ConsensusError> {
-        if message.chain.height != blockchain.height() {
-            return Err(ConsensusError::InvalidViewChangeHeight(
-                message.chain.height,
-                blockchain.height(),
-            ));
+        self.check_chain(blockchain, &message)?;
+        // checks if id exist, and signature.
+        message.validate(&blockchain)?;
+        self.handle_verified_message(blockchain, message)
+    }
+
+    ///
+    /// Same as `handle_message()`, for messages with signatures already checked
+    /// by `ViewChangeMessage::signature_checks()`.
+    ///
+    pub fn handle_verified_message(
+        &mut self,
+        blockchain: &Blockchain,
+        message: ViewChangeMessage,
+    ) -> Result<Option<ViewChangeProof>, ConsensusError> {
+        self.check_chain(blockchain, &message)?;
+        if (message.validator_id as usize) >= blockchain.validators().len() {
+            return Err(ConsensusError::InvalidValidatorId(message.validator_id));
+        }
+
+        self.replay_cache.prune(blockchain.height());
+        if !self.replay_cache.check(&message)? {
+            debug!(
+                "Ignore duplicate view_change message: view_change={}, validator_id={}",
+                message.chain.view_change, message.validator_id
+            );
+            return Ok(None);
         }
 
         if message.chain.last_block != blockchain.last_block_hash() {
@@ -142,9 +351,6 @@ impl ViewChangeCollector {
             ));
         }
 
-        // checks if id exist, and signature.
-        message.validate(&blockchain)?;
-
         info!(
             "Received valid view_change message: view_change={}, validator_id={},",
             message.chain.view_change, message.validator_id
@@ -153,6 +359,7 @@ impl ViewChangeCollector {
         if self.actual_view_changes.get(&id).is_none() {
             self.actual_view_changes.insert(id, message.clone());
             self.collected_slots += blockchain.validators()[id as usize].1;
+            metrics::VIEW_CHANGE_COLLECTED_SLOTS.set(self.collected_slots);
         }
         info!(
             "Collected view_changes: collected={}, total={},",
@@ -168,18 +375,107 @@ impl ViewChangeCollector {
             let proof = ViewChangeProof::new(signatures);
             self.actual_view_changes.clear();
             self.collected_slots = 0;
+            metrics::VIEW_CHANGE_COLLECTED_SLOTS.set(0);
+            let leader = blockchain.select_leader(blockchain.view_change());
+            metrics::MISSED_LEADERSHIP
+                .with_label_values(&[&leader.to_hex()])
+                .inc();
             return Ok(Some(proof));
         }
         Ok(None)
     }
 
     /// Handle block timeout, starting mooving to the next view change.
-    pub fn handle_timeout(&self, chain_info: ChainInfo) -> ViewChangeMessage {
+    pub fn handle_timeout(
+        &mut self,
+        chain_info: ChainInfo,
+    ) -> Result<ViewChangeMessage, SignerError> {
         debug!(
             "Timeout at block receiving, trying to collect view changes: validator_id = {}",
             self.validator_id
         );
         // on timeout, create view change message.
-        ViewChangeMessage::new(chain_info, self.validator_id, &self.skey)
+        let msg = ViewChangeMessage::sign(chain_info, self.validator_id, &*self.signer)?;
+        self.our_message = Some(msg);
+        Ok(msg)
+    }
+
+    /// Returns the collected view changes, for debugging.
+    pub fn info(&self, blockchain: &Blockchain) -> ViewChangeCollectorInfo {
+        let validators = blockchain.validators();
+        let mut collected: Vec<pbc::PublicKey> = self
+            .actual_view_changes
+            .keys()
+            .map(|id| validators[*id as usize].0)
+            .collect();
+        collected.sort();
+        let our_message = self.our_message.as_ref().map(|msg| OwnViewChangeInfo {
+            height: msg.chain.height,
+            view_change: msg.chain.view_change,
+            last_block: msg.chain.last_block,
+            timestamp: msg.timestamp,
+        });
+        let mut equivocators: Vec<pbc::PublicKey> = self
+            .replay_cache
+            .equivocations
+            .iter()
+            .filter_map(|(msg, _)| validators.get(msg.validator_id as usize))
+            .map(|(pkey, _)| *pkey)
+            .collect();
+        equivocators.sort();
+        equivocators.dedup();
+        ViewChangeCollectorInfo {
+            validator_id: self.validator_id,
+            collected,
+            collected_slots: self.collected_slots,
+            total_slots: blockchain.total_slots(),
+            our_message,
+            equivocators,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain_info(height: u64, view_change: ViewCounter, last_block: &str) -> ChainInfo {
+        ChainInfo {
+            chain_id: Hash::digest("testnet"),
+            epoch: 1,
+            height,
+            view_change,
+            last_block: Hash::digest(last_block),
+        }
+    }
+
+    #[test]
+    fn replay_cache() {
+        let (skey0, _pkey0) = pbc::make_random_keys();
+        let (skey1, _pkey1) = pbc::make_random_keys();
+        let mut cache = ReplayCache::default();
+
+        let msg = ViewChangeMessage::new(chain_info(10, 0, "a"), 0, &skey0);
+        assert!(cache.check(&msg).unwrap());
+        // Resent message.
+        let resent = ViewChangeMessage::new(chain_info(10, 0, "a"), 0, &skey0);
+        assert!(!cache.check(&resent).unwrap());
+        // Other validator and other view change.
+        let other = ViewChangeMessage::new(chain_info(10, 0, "a"), 1, &skey1);
+        assert!(cache.check(&other).unwrap());
+        let next = ViewChangeMessage::new(chain_info(10, 1, "a"), 0, &skey0);
+        assert!(cache.check(&next).unwrap());
+
+        // The same validator, height and view change, but other chain.
+        let conflict = ViewChangeMessage::new(chain_info(10, 0, "b"), 0, &skey0);
+        match cache.check(&conflict) {
+            Err(ConsensusError::ViewChangeEquivocation(0, 10, 0)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(cache.equivocations, vec![(msg, conflict)]);
+
+        cache.prune(11);
+        assert!(cache.messages.is_empty());
+        assert!(cache.check(&conflict).unwrap());
     }
 }
@@ -19,11 +19,16 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use failure::Error;
+use failure::{format_err, Error};
+use protobuf::Message;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use stegos_serialization::traits::*;
 
+use crate::confirmation::*;
 use crate::message::*;
 use crate::optimistic::*;
+use crate::signer::protocol::{SignerAuth, SignerFrame, SignerHello, SignerReply};
+use crate::signer::{SignRequest, SignResponse};
 use stegos_blockchain::view_changes::ViewChangeProof;
 use stegos_blockchain::*;
 use stegos_crypto::hash::Hash;
@@ -34,6 +39,24 @@ use stegos_blockchain::protos::*;
 use stegos_crypto::protos::*;
 include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
 
+/// Checks that timestamp can be encoded, i.e. it isn't before UNIX epoch.
+fn check_timestamp(timestamp: &SystemTime) -> Result<(), Error> {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| format_err!("Timestamp is before UNIX epoch: by={:?}", e.duration()))?;
+    Ok(())
+}
+
+/// Encodes timestamp, times before UNIX epoch must be rejected by `check_timestamp()`.
+fn timestamp_into_proto(timestamp: &SystemTime) -> u64 {
+    let since_the_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_the_epoch.as_secs() * 1000 + since_the_epoch.subsec_millis() as u64
+}
+
+fn timestamp_from_proto(timestamp: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(timestamp)
+}
+
 impl ProtoConvert for ConsensusMessageBody {
     type Proto = consensus::ConsensusMessageBody;
     fn into_proto(&self) -> Self::Proto {
@@ -98,6 +121,7 @@ impl ProtoConvert for ConsensusMessage {
         proto.set_round(self.round);
         proto.set_block_hash(self.block_hash.into_proto());
         proto.set_body(self.body.into_proto());
+        proto.set_timestamp(timestamp_into_proto(&self.timestamp));
         proto.set_sig(self.sig.into_proto());
         proto.set_pkey(self.pkey.into_proto());
         proto
@@ -107,6 +131,7 @@ impl ProtoConvert for ConsensusMessage {
         let round = proto.get_round();
         let block_hash = Hash::from_proto(proto.get_block_hash())?;
         let body = ConsensusMessageBody::from_proto(proto.get_body())?;
+        let timestamp = timestamp_from_proto(proto.get_timestamp());
         let sig = pbc::Signature::from_proto(proto.get_sig())?;
         let pkey = pbc::PublicKey::from_proto(proto.get_pkey())?;
         Ok(ConsensusMessage {
@@ -114,10 +139,17 @@ impl ProtoConvert for ConsensusMessage {
             round,
             block_hash,
             body,
+            timestamp,
             sig,
             pkey,
         })
     }
+
+    fn into_buffer(&self) -> Result<Vec<u8>, Error> {
+        check_timestamp(&self.timestamp)?;
+        let data = self.into_proto().write_to_bytes()?;
+        Ok(data)
+    }
 }
 impl ProtoConvert for ViewChangeMessage {
     type Proto = consensus::ViewChangeMessage;
@@ -126,19 +158,31 @@ impl ProtoConvert for ViewChangeMessage {
         proto.set_chain(self.chain.into_proto());
         proto.set_validator_id(self.validator_id);
         proto.set_signature(self.signature.into_proto());
+        proto.set_timestamp(timestamp_into_proto(&self.timestamp));
+        proto.set_timestamp_sig(self.timestamp_sig.into_proto());
         proto
     }
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
         let chain = ChainInfo::from_proto(proto.get_chain())?;
         let validator_id = proto.get_validator_id();
         let signature = pbc::Signature::from_proto(proto.get_signature())?;
+        let timestamp = timestamp_from_proto(proto.get_timestamp());
+        let timestamp_sig = pbc::Signature::from_proto(proto.get_timestamp_sig())?;
 
         Ok(ViewChangeMessage {
             chain,
             validator_id,
             signature,
+            timestamp,
+            timestamp_sig,
         })
     }
+
+    fn into_buffer(&self) -> Result<Vec<u8>, Error> {
+        check_timestamp(&self.timestamp)?;
+        let data = self.into_proto().write_to_bytes()?;
+        Ok(data)
+    }
 }
 
 impl ProtoConvert for SealedViewChangeProof {
@@ -157,6 +201,229 @@ impl ProtoConvert for SealedViewChangeProof {
     }
 }
 
+impl ProtoConvert for ConfirmationInfo {
+    type Proto = consensus::ConfirmationInfo;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = consensus::ConfirmationInfo::new();
+        proto.set_tx_hash(self.tx_hash.into_proto());
+        proto.set_height(self.height);
+        proto.set_epoch(self.epoch);
+        proto
+    }
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let tx_hash = Hash::from_proto(proto.get_tx_hash())?;
+        let height = proto.get_height();
+        let epoch = proto.get_epoch();
+        Ok(ConfirmationInfo {
+            tx_hash,
+            height,
+            epoch,
+        })
+    }
+}
+
+impl ProtoConvert for ConfirmationVote {
+    type Proto = consensus::ConfirmationVote;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = consensus::ConfirmationVote::new();
+        proto.set_info(self.info.into_proto());
+        proto.set_validator_id(self.validator_id);
+        proto.set_signature(self.signature.into_proto());
+        proto
+    }
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let info = ConfirmationInfo::from_proto(proto.get_info())?;
+        let validator_id = proto.get_validator_id();
+        let signature = pbc::Signature::from_proto(proto.get_signature())?;
+        Ok(ConfirmationVote {
+            info,
+            validator_id,
+            signature,
+        })
+    }
+}
+
+impl ProtoConvert for SignRequest {
+    type Proto = consensus::SignRequest;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = consensus::SignRequest::new();
+        match self {
+            SignRequest::MicroBlock(block) => {
+                proto.set_micro_block(block.into_proto());
+            }
+            SignRequest::MacroBlock { header, round } => {
+                let mut msg = consensus::MacroBlockSignRequest::new();
+                msg.set_header(header.into_proto());
+                msg.set_round(*round);
+                proto.set_macro_block(msg);
+            }
+            SignRequest::Consensus(msg) => {
+                proto.set_consensus(msg.into_proto());
+            }
+            SignRequest::ViewChange { chain, timestamp } => {
+                let mut msg = consensus::ViewChangeSignRequest::new();
+                msg.set_chain(chain.into_proto());
+                msg.set_timestamp(timestamp_into_proto(timestamp));
+                proto.set_view_change(msg);
+            }
+            SignRequest::Random {
+                last_random,
+                view_change,
+            } => {
+                let mut msg = consensus::RandomSignRequest::new();
+                msg.set_last_random(last_random.into_proto());
+                msg.set_view_change(*view_change);
+                proto.set_random(msg);
+            }
+            SignRequest::Confirmation(info) => {
+                proto.set_confirmation(info.into_proto());
+            }
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let request = match proto.request {
+            Some(consensus::SignRequest_oneof_request::micro_block(ref msg)) => {
+                SignRequest::MicroBlock(MicroBlock::from_proto(msg)?)
+            }
+            Some(consensus::SignRequest_oneof_request::macro_block(ref msg)) => {
+                SignRequest::MacroBlock {
+                    header: MacroBlockHeader::from_proto(msg.get_header())?,
+                    round: msg.get_round(),
+                }
+            }
+            Some(consensus::SignRequest_oneof_request::consensus(ref msg)) => {
+                SignRequest::Consensus(ConsensusMessage::from_proto(msg)?)
+            }
+            Some(consensus::SignRequest_oneof_request::view_change(ref msg)) => {
+                SignRequest::ViewChange {
+                    chain: ChainInfo::from_proto(msg.get_chain())?,
+                    timestamp: timestamp_from_proto(msg.get_timestamp()),
+                }
+            }
+            Some(consensus::SignRequest_oneof_request::random(ref msg)) => SignRequest::Random {
+                last_random: Hash::from_proto(msg.get_last_random())?,
+                view_change: msg.get_view_change(),
+            },
+            Some(consensus::SignRequest_oneof_request::confirmation(ref msg)) => {
+                SignRequest::Confirmation(ConfirmationInfo::from_proto(msg)?)
+            }
+            None => {
+                return Err(
+                    ProtoError::MissingField("request".to_string(), "request".to_string()).into(),
+                );
+            }
+        };
+        Ok(request)
+    }
+
+    fn into_buffer(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            SignRequest::Consensus(msg) => check_timestamp(&msg.timestamp)?,
+            SignRequest::ViewChange { timestamp, .. } => check_timestamp(timestamp)?,
+            _ => {}
+        }
+        let data = self.into_proto().write_to_bytes()?;
+        Ok(data)
+    }
+}
+
+impl ProtoConvert for SignerReply {
+    type Proto = consensus::SignResponse;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = consensus::SignResponse::new();
+        match self {
+            SignerReply::Signed(SignResponse::Signature(signature)) => {
+                proto.set_signature(signature.into_proto());
+            }
+            SignerReply::Signed(SignResponse::ViewChange(signature, timestamp_sig)) => {
+                let mut msg = consensus::ViewChangeSignatures::new();
+                msg.set_signature(signature.into_proto());
+                msg.set_timestamp_sig(timestamp_sig.into_proto());
+                proto.set_view_change(msg);
+            }
+            SignerReply::Signed(SignResponse::Random(random)) => {
+                proto.set_random(random.into_proto());
+            }
+            SignerReply::Refused(error) => {
+                proto.set_refused(error.clone());
+            }
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let reply = match proto.response {
+            Some(consensus::SignResponse_oneof_response::signature(ref msg)) => {
+                SignerReply::Signed(SignResponse::Signature(pbc::Signature::from_proto(msg)?))
+            }
+            Some(consensus::SignResponse_oneof_response::view_change(ref msg)) => {
+                let signature = pbc::Signature::from_proto(msg.get_signature())?;
+                let timestamp_sig = pbc::Signature::from_proto(msg.get_timestamp_sig())?;
+                SignerReply::Signed(SignResponse::ViewChange(signature, timestamp_sig))
+            }
+            Some(consensus::SignResponse_oneof_response::random(ref msg)) => {
+                SignerReply::Signed(SignResponse::Random(pbc::VRF::from_proto(msg)?))
+            }
+            Some(consensus::SignResponse_oneof_response::refused(ref error)) => {
+                SignerReply::Refused(error.clone())
+            }
+            None => {
+                return Err(ProtoError::MissingField(
+                    "response".to_string(),
+                    "response".to_string(),
+                )
+                .into());
+            }
+        };
+        Ok(reply)
+    }
+}
+
+impl ProtoConvert for SignerHello {
+    type Proto = consensus::SignerHello;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = consensus::SignerHello::new();
+        proto.set_nonce(self.nonce.into_proto());
+        proto
+    }
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let nonce = Hash::from_proto(proto.get_nonce())?;
+        Ok(SignerHello { nonce })
+    }
+}
+
+impl ProtoConvert for SignerAuth {
+    type Proto = consensus::SignerAuth;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = consensus::SignerAuth::new();
+        proto.set_mac(self.mac.into_proto());
+        proto
+    }
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let mac = Hash::from_proto(proto.get_mac())?;
+        Ok(SignerAuth { mac })
+    }
+}
+
+impl ProtoConvert for SignerFrame {
+    type Proto = consensus::SignerFrame;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = consensus::SignerFrame::new();
+        proto.set_seq(self.seq);
+        proto.set_payload(self.payload.clone());
+        proto.set_mac(self.mac.into_proto());
+        proto
+    }
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let seq = proto.get_seq();
+        let payload = proto.get_payload().to_vec();
+        let mac = Hash::from_proto(proto.get_mac())?;
+        Ok(SignerFrame { seq, payload, mac })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -180,6 +447,7 @@ mod tests {
             self.round.hash(state);
             self.block_hash.hash(state);
             self.body.hash(state);
+            self.timestamp.hash(state);
             self.pkey.hash(state);
             self.sig.hash(state);
         }
@@ -220,12 +488,13 @@ mod tests {
         let (nskey, _npkey) = pbc::make_random_keys();
 
         let version: u64 = 1;
+        let chain_id = Hash::digest("test");
         let height: u64 = 0;
         let timestamp = SystemTime::now();
         let previous = Hash::digest(&"test".to_string());
 
         let random = pbc::make_VRF(&nskey, &Hash::digest("test"));
-        let base = BaseBlockHeader::new(version, previous, height, 0, timestamp, random);
+        let base = BaseBlockHeader::new(version, chain_id, previous, height, 0, timestamp, random);
         let header = MacroBlockHeader {
             base,
             gamma: Fr::random(),
@@ -235,7 +504,7 @@ mod tests {
         };
         // Transactions.
         let (tx, _inputs, _outputs) =
-            PaymentTransaction::new_test(&skey, &pkey, 300, 2, 100, 1, 100)
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 300, 2, 100, 1, 100)
                 .expect("Invalid transaction");
         let transactions: Vec<Transaction> = vec![tx.into()];
 
@@ -254,6 +523,8 @@ mod tests {
         let (skey0, _pkey0) = pbc::make_random_keys();
 
         let chain = ChainInfo {
+            chain_id: Hash::digest("testnet"),
+            epoch: 3,
             height: 41,
             view_change: 12,
             last_block: Hash::digest("test"),
@@ -261,4 +532,33 @@ mod tests {
         let view_change_vote = ViewChangeMessage::new(chain, 1, &skey0);
         roundtrip(&view_change_vote);
     }
+
+    #[test]
+    fn timestamp_before_epoch() {
+        let (network_skey, network_pkey) = pbc::make_random_keys();
+        let mut msg = ConsensusMessage::new(
+            1,
+            1,
+            Hash::digest(&1u64),
+            &network_skey,
+            &network_pkey,
+            ConsensusMessageBody::Prevote {},
+        );
+        msg.into_buffer().unwrap();
+        msg.timestamp = UNIX_EPOCH - Duration::from_secs(1);
+        assert!(msg.into_buffer().is_err());
+        assert!(SignRequest::Consensus(msg).into_buffer().is_err());
+    }
+
+    #[test]
+    fn confirmation() {
+        let (skey, _pkey) = pbc::make_random_keys();
+        let info = ConfirmationInfo {
+            tx_hash: Hash::digest("tx"),
+            height: 10,
+            epoch: 2,
+        };
+        let vote = ConfirmationVote::new(info, 3, &skey);
+        roundtrip(&vote);
+    }
 }
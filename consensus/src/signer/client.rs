@@ -0,0 +1,184 @@
+//! Client of the remote signer.
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::protocol::*;
+use super::{SignRequest, SignResponse, Signer, SignerConfig};
+use crate::error::SignerError;
+use failure::Error;
+use log::*;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+/// An authenticated connection to the signer.
+struct Session {
+    conn: Connection,
+    nonce: Hash,
+    seq: u64,
+}
+
+struct State {
+    session: Option<Session>,
+    last_attempt: Option<Instant>,
+}
+
+/// Signs with the key stored on a remote signer.
+///
+/// Requests are sent synchronously, one at a time. A broken connection is re-established
+/// on the next request, but not more often than `reconnect_delay`. Each signature is
+/// checked against the public key of this node.
+pub struct RemoteSigner {
+    cfg: SignerConfig,
+    pkey: pbc::PublicKey,
+    state: Mutex<State>,
+}
+
+impl RemoteSigner {
+    pub fn new(cfg: SignerConfig, pkey: pbc::PublicKey) -> Self {
+        let state = State {
+            session: None,
+            last_attempt: None,
+        };
+        RemoteSigner {
+            cfg,
+            pkey,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn connect(&self) -> Result<Session, Error> {
+        let mut conn = Connection::connect(&self.cfg.endpoint, self.cfg.timeout)?;
+        let hello: SignerHello = read_message(&mut conn)?;
+        let auth = SignerAuth {
+            mac: auth_mac(&self.cfg.token, &hello.nonce),
+        };
+        write_message(&mut conn, &auth)?;
+        Ok(Session {
+            conn,
+            nonce: hello.nonce,
+            seq: 0,
+        })
+    }
+
+    fn reconnect(&self, state: &mut State) -> Result<Session, SignerError> {
+        if let Some(last_attempt) = state.last_attempt {
+            if last_attempt.elapsed() < self.cfg.reconnect_delay {
+                return Err(SignerError::Unavailable(
+                    self.cfg.endpoint.clone(),
+                    "waiting to reconnect".to_string(),
+                ));
+            }
+        }
+        state.last_attempt = Some(Instant::now());
+        debug!(
+            "Connecting to the remote signer: endpoint={}",
+            self.cfg.endpoint
+        );
+        match self.connect() {
+            Ok(session) => {
+                info!(
+                    "Connected to the remote signer: endpoint={}",
+                    self.cfg.endpoint
+                );
+                Ok(session)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to connect to the remote signer: endpoint={}, error={}",
+                    self.cfg.endpoint, e
+                );
+                Err(SignerError::Unavailable(
+                    self.cfg.endpoint.clone(),
+                    e.to_string(),
+                ))
+            }
+        }
+    }
+
+    fn request(&self, session: &mut Session, request: &SignRequest) -> Result<SignerReply, Error> {
+        session.seq += 1;
+        let frame = SignerFrame::seal(&self.cfg.token, &session.nonce, session.seq, request)?;
+        write_message(&mut session.conn, &frame)?;
+        let frame: SignerFrame = read_message(&mut session.conn)?;
+        frame.open(&self.cfg.token, &session.nonce, session.seq)
+    }
+}
+
+impl fmt::Debug for RemoteSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RemoteSigner({}, {})", self.cfg.endpoint, self.pkey)
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn pkey(&self) -> pbc::PublicKey {
+        self.pkey
+    }
+
+    fn sign(&self, request: &SignRequest) -> Result<SignResponse, SignerError> {
+        let mut state = self.state.lock().unwrap();
+        let (mut session, mut reused) = match state.session.take() {
+            Some(session) => (session, true),
+            None => (self.reconnect(&mut state)?, false),
+        };
+        let reply = loop {
+            match self.request(&mut session, request) {
+                Ok(reply) => break reply,
+                // The signer may have restarted, retry once on a new connection.
+                // Repeated requests are not double signing.
+                Err(e) if reused => {
+                    debug!("Lost connection to the remote signer: error={}", e);
+                    state.last_attempt = None;
+                    session = self.reconnect(&mut state)?;
+                    reused = false;
+                }
+                Err(e) => {
+                    warn!(
+                        "Lost connection to the remote signer: endpoint={}, error={}",
+                        self.cfg.endpoint, e
+                    );
+                    return Err(SignerError::Unavailable(
+                        self.cfg.endpoint.clone(),
+                        e.to_string(),
+                    ));
+                }
+            }
+        };
+        state.session = Some(session);
+
+        match reply {
+            SignerReply::Signed(response) => {
+                if !request.verify(&response, &self.pkey) {
+                    return Err(SignerError::InvalidSignature(self.pkey));
+                }
+                Ok(response)
+            }
+            SignerReply::Refused(error) => {
+                warn!("Remote signer refused to sign: error={}", error);
+                Err(SignerError::Refused(error))
+            }
+        }
+    }
+}
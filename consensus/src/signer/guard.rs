@@ -0,0 +1,342 @@
+//! Protection against double signing.
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{SignKind, SignRequest};
+use crate::error::SignerError;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use stegos_crypto::hash::Hash;
+
+/// The number of recent heights remembered by the guard.
+/// Requests below this window are refused.
+pub const GUARD_HISTORY: u64 = 1000;
+
+/// A signed value, as saved on the disk.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SignedRecord {
+    kind: SignKind,
+    height: u64,
+    round: u32,
+    value: Hash,
+    /// The previous block of signed blocks.
+    #[serde(default)]
+    previous: Option<Hash>,
+}
+
+/// Remembers signed blocks and votes and refuses to sign conflicting ones.
+#[derive(Default)]
+pub struct SignGuard {
+    /// Where the state is saved, None to keep it in memory only.
+    path: Option<PathBuf>,
+    /// Signed values by kind, height and round.
+    signed: BTreeMap<(SignKind, u64, u32), Hash>,
+    /// The previous block of signed blocks by kind and height.
+    parents: BTreeMap<(SignKind, u64), Hash>,
+    /// The highest signed height.
+    max_height: u64,
+}
+
+impl SignGuard {
+    /// Creates a guard which keeps its state in memory.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Opens a guard which persists its state in `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SignerError> {
+        let path = path.as_ref().to_path_buf();
+        let mut guard = SignGuard::new();
+        if path.exists() {
+            let data = fs::read(&path).map_err(|e| state_error(&path, e))?;
+            let records: Vec<SignedRecord> =
+                serde_json::from_slice(&data).map_err(|e| state_error(&path, e))?;
+            for record in records {
+                guard.max_height = guard.max_height.max(record.height);
+                guard
+                    .signed
+                    .insert((record.kind, record.height, record.round), record.value);
+                if let Some(previous) = record.previous {
+                    guard.parents.insert((record.kind, record.height), previous);
+                }
+            }
+            info!(
+                "Loaded the signer state: path={}, records={}, max_height={}",
+                path.display(),
+                guard.signed.len(),
+                guard.max_height
+            );
+        }
+        guard.path = Some(path);
+        Ok(guard)
+    }
+
+    /// Checks that the request doesn't conflict with already signed ones and remembers it.
+    /// Must be called before signing.
+    pub fn check(&mut self, request: &SignRequest) -> Result<(), SignerError> {
+        let (kind, height, round, value) = match request.slot() {
+            Some(slot) => slot,
+            None => return Ok(()),
+        };
+        match self.signed.get(&(kind, height, round)) {
+            Some(signed) if *signed == value => return Ok(()),
+            Some(signed) => {
+                error!(
+                    "Refused to double sign: kind={}, height={}, round={}, signed={}, requested={}",
+                    kind, height, round, signed, value
+                );
+                return Err(SignerError::DoubleSign(kind, height, round));
+            }
+            None => {}
+        }
+        if self.max_height.saturating_sub(height) >= GUARD_HISTORY {
+            return Err(SignerError::StaleRequest(kind, height, self.max_height));
+        }
+        // A block can't fork from the parent of blocks already signed at this height.
+        let parent = request.parent().map(|(_kind, _height, previous)| previous);
+        if let Some(previous) = parent {
+            match self.parents.get(&(kind, height)) {
+                Some(signed) if *signed != previous => {
+                    error!(
+                        "Refused to fork: kind={}, height={}, signed_previous={}, requested_previous={}",
+                        kind, height, signed, previous
+                    );
+                    return Err(SignerError::ForkedParent(kind, height));
+                }
+                _ => {}
+            }
+        }
+
+        let max_height = self.max_height;
+        let had_parent = self.parents.contains_key(&(kind, height));
+        self.signed.insert((kind, height, round), value);
+        if let Some(previous) = parent {
+            self.parents.insert((kind, height), previous);
+        }
+        self.max_height = self.max_height.max(height);
+        if let Err(e) = self.save() {
+            self.signed.remove(&(kind, height, round));
+            if !had_parent {
+                self.parents.remove(&(kind, height));
+            }
+            self.max_height = max_height;
+            return Err(e);
+        }
+        self.prune();
+        Ok(())
+    }
+
+    /// Forgets heights below the history window.
+    fn prune(&mut self) {
+        let max_height = self.max_height;
+        self.signed.retain(|(_kind, height, _round), _| {
+            max_height.saturating_sub(*height) < GUARD_HISTORY
+        });
+        self.parents
+            .retain(|(_kind, height), _| max_height.saturating_sub(*height) < GUARD_HISTORY);
+    }
+
+    /// Atomically writes the state to the disk.
+    /// The state is written to a temporary file, synced and renamed over the old one,
+    /// so a crash leaves either the old or the new state, never a partial one.
+    fn save(&self) -> Result<(), SignerError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let records: Vec<SignedRecord> = self
+            .signed
+            .iter()
+            .map(|((kind, height, round), value)| SignedRecord {
+                kind: *kind,
+                height: *height,
+                round: *round,
+                value: *value,
+                previous: self.parents.get(&(*kind, *height)).cloned(),
+            })
+            .collect();
+        let data = serde_json::to_vec(&records).expect("serializable");
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path).map_err(|e| state_error(path, e))?;
+        file.write_all(&data).map_err(|e| state_error(path, e))?;
+        file.sync_all().map_err(|e| state_error(path, e))?;
+        drop(file);
+        fs::rename(&tmp_path, path).map_err(|e| state_error(path, e))?;
+        // Persist the rename itself.
+        #[cfg(unix)]
+        {
+            let dir = match path.parent() {
+                Some(dir) if dir != Path::new("") => dir,
+                _ => Path::new("."),
+            };
+            fs::File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|e| state_error(path, e))?;
+        }
+        Ok(())
+    }
+}
+
+fn state_error<E: ToString>(path: &Path, error: E) -> SignerError {
+    SignerError::StateError(path.display().to_string(), error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::micro_block as test_block;
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use stegos_blockchain::{BaseBlockHeader, MacroBlockHeader, VERSION};
+    use stegos_crypto::curve1174::Fr;
+    use stegos_crypto::pbc;
+
+    fn micro_block(height: u64, view_change: u32, block: &str) -> SignRequest {
+        SignRequest::MicroBlock(test_block(height, view_change, "parent", block))
+    }
+
+    #[test]
+    fn double_sign() {
+        let mut guard = SignGuard::new();
+        guard
+            .check(&micro_block(10, 0, "a"))
+            .expect("first signature");
+        guard.check(&micro_block(10, 0, "a")).expect("same block");
+        guard
+            .check(&micro_block(10, 1, "b"))
+            .expect("next view change");
+        guard.check(&micro_block(11, 0, "c")).expect("next height");
+        match guard.check(&micro_block(10, 0, "b")).unwrap_err() {
+            SignerError::DoubleSign(SignKind::MicroBlock, 10, 0) => {}
+            e => panic!("{}", e),
+        }
+        // Macro blocks are tracked separately.
+        let request = SignRequest::MacroBlock {
+            header: macro_header(10, "b"),
+            round: 0,
+        };
+        guard.check(&request).expect("another kind");
+        // Requests below the history window are refused.
+        guard
+            .check(&micro_block(11 + GUARD_HISTORY, 0, "d"))
+            .expect("new height");
+        match guard.check(&micro_block(11, 1, "e")).unwrap_err() {
+            SignerError::StaleRequest(SignKind::MicroBlock, 11, _) => {}
+            e => panic!("{}", e),
+        }
+        assert_eq!(guard.signed.len(), 1);
+    }
+
+    fn macro_header(height: u64, previous: &str) -> MacroBlockHeader {
+        let (skey, _pkey) = pbc::make_deterministic_keys(previous.as_bytes());
+        let random = pbc::make_VRF(&skey, &Hash::digest(previous));
+        let base = BaseBlockHeader::new(
+            VERSION,
+            Hash::zero(),
+            Hash::digest(previous),
+            height,
+            0,
+            UNIX_EPOCH,
+            random,
+        );
+        MacroBlockHeader {
+            base,
+            gamma: Fr::zero(),
+            block_reward: 0,
+            inputs_range_hash: Hash::zero(),
+            outputs_range_hash: Hash::zero(),
+        }
+    }
+
+    #[test]
+    fn forked_parent() {
+        let mut guard = SignGuard::new();
+        let block = test_block(10, 0, "a", "x");
+        guard
+            .check(&SignRequest::MicroBlock(block))
+            .expect("first signature");
+        // The next view change must build on the same parent.
+        let block = test_block(10, 1, "a", "y");
+        guard
+            .check(&SignRequest::MicroBlock(block))
+            .expect("same parent");
+        let block = test_block(10, 2, "b", "z");
+        match guard.check(&SignRequest::MicroBlock(block)).unwrap_err() {
+            SignerError::ForkedParent(SignKind::MicroBlock, 10) => {}
+            e => panic!("{}", e),
+        }
+        // Precommits for another round of the same height keep the parent.
+        let request = SignRequest::MacroBlock {
+            header: macro_header(20, "c"),
+            round: 0,
+        };
+        guard.check(&request).expect("first round");
+        let request = SignRequest::MacroBlock {
+            header: macro_header(20, "d"),
+            round: 1,
+        };
+        match guard.check(&request).unwrap_err() {
+            SignerError::ForkedParent(SignKind::MacroBlock, 20) => {}
+            e => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn height_overflow() {
+        let mut guard = SignGuard::new();
+        guard
+            .check(&micro_block(u64::max_value(), 0, "a"))
+            .expect("max height");
+        guard
+            .check(&micro_block(u64::max_value() - 1, 0, "b"))
+            .expect("within the history window");
+        match guard
+            .check(&micro_block(u64::max_value(), 0, "c"))
+            .unwrap_err()
+        {
+            SignerError::DoubleSign(SignKind::MicroBlock, _, 0) => {}
+            e => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = tempdir::TempDir::new("guard").unwrap();
+        let path = dir.path().join("signer.state");
+        {
+            let mut guard = SignGuard::open(&path).unwrap();
+            guard.check(&micro_block(10, 0, "a")).unwrap();
+        }
+        assert!(!path.with_extension("tmp").exists());
+        let mut guard = SignGuard::open(&path).unwrap();
+        assert_eq!(guard.max_height, 10);
+        assert_eq!(guard.parents.len(), 1);
+        guard.check(&micro_block(10, 0, "a")).expect("same block");
+        match guard.check(&micro_block(10, 0, "b")).unwrap_err() {
+            SignerError::DoubleSign(SignKind::MicroBlock, 10, 0) => {}
+            e => panic!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Signing with the validator network key, locally or by a remote signer.
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+mod client;
+mod guard;
+pub(crate) mod protocol;
+mod server;
+
+pub use self::client::RemoteSigner;
+pub use self::guard::SignGuard;
+pub use self::server::SignerServer;
+
+use crate::confirmation::ConfirmationInfo;
+use crate::error::SignerError;
+use crate::message::{ConsensusMessage, ConsensusMessageBody};
+use crate::optimistic::timestamp_hash;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use stegos_blockchain::{mix, ChainInfo, MacroBlockHeader, MicroBlock};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+/// Remote signer configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SignerConfig {
+    /// Address of the remote signer, "host:port" or "unix:/path/to/socket".
+    /// Empty to sign with the key stored on this node.
+    pub endpoint: String,
+    /// Shared secret to authenticate with the remote signer.
+    pub token: String,
+    /// How long to wait for a signature.
+    pub timeout: Duration,
+    /// The minimal delay between reconnection attempts.
+    pub reconnect_delay: Duration,
+}
+
+impl Default for SignerConfig {
+    fn default() -> Self {
+        SignerConfig {
+            endpoint: String::new(),
+            token: String::new(),
+            timeout: Duration::from_secs(2),
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Kinds of signatures checked for double signing.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SignKind {
+    MicroBlock,
+    MacroBlock,
+    Proposal,
+    Prevote,
+    Precommit,
+    ViewChange,
+}
+
+impl fmt::Display for SignKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SignKind::MicroBlock => "micro block",
+            SignKind::MacroBlock => "macro block",
+            SignKind::Proposal => "proposal",
+            SignKind::Prevote => "prevote",
+            SignKind::Precommit => "precommit",
+            SignKind::ViewChange => "view change",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Something to sign with the network key.
+#[derive(Clone, Debug)]
+pub enum SignRequest {
+    /// A micro block, the signer hashes it itself.
+    MicroBlock(MicroBlock),
+    /// The header of a macro block, its hash is included into precommits.
+    MacroBlock {
+        header: MacroBlockHeader,
+        round: u32,
+    },
+    /// A message of the macro block consensus, the signature is ignored.
+    Consensus(ConsensusMessage),
+    /// A view change vote.
+    ViewChange {
+        chain: ChainInfo,
+        timestamp: SystemTime,
+    },
+    /// Randomness of a block.
+    Random { last_random: Hash, view_change: u32 },
+    /// A fast confirmation of a transaction.
+    Confirmation(ConfirmationInfo),
+}
+
+/// Signatures of a `SignRequest`.
+#[derive(Clone, Debug)]
+pub enum SignResponse {
+    Signature(pbc::Signature),
+    /// Signatures of the chain and of the timestamp.
+    ViewChange(pbc::Signature, pbc::Signature),
+    Random(pbc::VRF),
+}
+
+impl SignRequest {
+    /// Returns the kind, height, round and the signed value which must never change
+    /// for the same kind, height and round, if the request can be double signed.
+    pub fn slot(&self) -> Option<(SignKind, u64, u32, Hash)> {
+        match self {
+            SignRequest::MicroBlock(block) => Some((
+                SignKind::MicroBlock,
+                block.base.height,
+                block.base.view_change,
+                Hash::digest(block),
+            )),
+            SignRequest::MacroBlock { header, round } => Some((
+                SignKind::MacroBlock,
+                header.base.height,
+                *round,
+                Hash::digest(header),
+            )),
+            SignRequest::Consensus(msg) => {
+                let kind = match msg.body {
+                    ConsensusMessageBody::Proposal(_) => SignKind::Proposal,
+                    ConsensusMessageBody::Prevote => SignKind::Prevote,
+                    ConsensusMessageBody::Precommit(_) => SignKind::Precommit,
+                };
+                Some((kind, msg.height, msg.round, msg.block_hash))
+            }
+            SignRequest::ViewChange { chain, .. } => Some((
+                SignKind::ViewChange,
+                chain.height,
+                chain.view_change,
+                Hash::digest(chain),
+            )),
+            SignRequest::Random { .. } | SignRequest::Confirmation(_) => None,
+        }
+    }
+
+    /// Returns the kind, height and the previous block of block requests.
+    /// All blocks of the same kind signed at one height must have the same parent.
+    pub fn parent(&self) -> Option<(SignKind, u64, Hash)> {
+        match self {
+            SignRequest::MicroBlock(block) => {
+                Some((SignKind::MicroBlock, block.base.height, block.base.previous))
+            }
+            SignRequest::MacroBlock { header, .. } => Some((
+                SignKind::MacroBlock,
+                header.base.height,
+                header.base.previous,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Returns the signed hash for requests answered by a single signature.
+    fn signed_hash(&self) -> Option<Hash> {
+        match self {
+            SignRequest::MicroBlock(block) => Some(Hash::digest(block)),
+            SignRequest::MacroBlock { header, .. } => Some(Hash::digest(header)),
+            SignRequest::Consensus(msg) => Some(msg.signature_check().0),
+            SignRequest::Confirmation(info) => Some(Hash::digest(info)),
+            SignRequest::ViewChange { .. } | SignRequest::Random { .. } => None,
+        }
+    }
+
+    /// Signs the request with the secret key.
+    pub fn sign(&self, skey: &pbc::SecretKey) -> SignResponse {
+        if let Some(hash) = self.signed_hash() {
+            return SignResponse::Signature(pbc::sign_hash(&hash, skey));
+        }
+        match self {
+            SignRequest::ViewChange { chain, timestamp } => {
+                let signature = pbc::sign_hash(&Hash::digest(chain), skey);
+                let hash = timestamp_hash(chain, timestamp, &signature);
+                let timestamp_sig = pbc::sign_hash(&hash, skey);
+                SignResponse::ViewChange(signature, timestamp_sig)
+            }
+            SignRequest::Random {
+                last_random,
+                view_change,
+            } => {
+                let seed = mix(*last_random, *view_change);
+                SignResponse::Random(pbc::make_VRF(skey, &seed))
+            }
+            _ => unreachable!("signed by a single signature"),
+        }
+    }
+
+    /// Checks that the response holds valid signatures of the request.
+    pub fn verify(&self, response: &SignResponse, pkey: &pbc::PublicKey) -> bool {
+        match (self, response) {
+            (
+                SignRequest::ViewChange { chain, timestamp },
+                SignResponse::ViewChange(sig, ts_sig),
+            ) => {
+                let hash = timestamp_hash(chain, timestamp, sig);
+                pbc::check_hash(&Hash::digest(chain), sig, pkey).is_ok()
+                    && pbc::check_hash(&hash, ts_sig, pkey).is_ok()
+            }
+            (
+                SignRequest::Random {
+                    last_random,
+                    view_change,
+                },
+                SignResponse::Random(random),
+            ) => {
+                let seed = mix(*last_random, *view_change);
+                pbc::validate_VRF_source(random, pkey, &seed)
+            }
+            (request, SignResponse::Signature(sig)) => match request.signed_hash() {
+                Some(hash) => pbc::check_hash(&hash, sig, pkey).is_ok(),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Signs messages with the validator network key.
+pub trait Signer: fmt::Debug + Send + Sync {
+    /// Returns the public network key.
+    fn pkey(&self) -> pbc::PublicKey;
+
+    /// Signs the request.
+    fn sign(&self, request: &SignRequest) -> Result<SignResponse, SignerError>;
+
+    /// Signs the hash of a micro block.
+    fn sign_micro_block(&self, block: &MicroBlock) -> Result<pbc::Signature, SignerError> {
+        signature(self.sign(&SignRequest::MicroBlock(block.clone()))?)
+    }
+
+    /// Signs the hash of a macro block.
+    fn sign_macro_block(
+        &self,
+        header: &MacroBlockHeader,
+        round: u32,
+    ) -> Result<pbc::Signature, SignerError> {
+        let request = SignRequest::MacroBlock {
+            header: header.clone(),
+            round,
+        };
+        signature(self.sign(&request)?)
+    }
+
+    /// Signs a consensus message.
+    fn sign_consensus_message(
+        &self,
+        msg: &ConsensusMessage,
+    ) -> Result<pbc::Signature, SignerError> {
+        signature(self.sign(&SignRequest::Consensus(msg.clone()))?)
+    }
+
+    /// Signs a view change vote, returns the signatures of the chain and of the timestamp.
+    fn sign_view_change(
+        &self,
+        chain: ChainInfo,
+        timestamp: SystemTime,
+    ) -> Result<(pbc::Signature, pbc::Signature), SignerError> {
+        match self.sign(&SignRequest::ViewChange { chain, timestamp })? {
+            SignResponse::ViewChange(signature, timestamp_sig) => Ok((signature, timestamp_sig)),
+            _ => Err(SignerError::UnexpectedResponse),
+        }
+    }
+
+    /// Evaluates VRF of the next block.
+    fn make_random(&self, last_random: Hash, view_change: u32) -> Result<pbc::VRF, SignerError> {
+        let request = SignRequest::Random {
+            last_random,
+            view_change,
+        };
+        match self.sign(&request)? {
+            SignResponse::Random(random) => Ok(random),
+            _ => Err(SignerError::UnexpectedResponse),
+        }
+    }
+
+    /// Signs a fast confirmation.
+    fn sign_confirmation(&self, info: ConfirmationInfo) -> Result<pbc::Signature, SignerError> {
+        signature(self.sign(&SignRequest::Confirmation(info))?)
+    }
+}
+
+fn signature(response: SignResponse) -> Result<pbc::Signature, SignerError> {
+    match response {
+        SignResponse::Signature(signature) => Ok(signature),
+        _ => Err(SignerError::UnexpectedResponse),
+    }
+}
+
+/// Signs with the secret key stored on this node.
+pub struct LocalSigner {
+    skey: pbc::SecretKey,
+    pkey: pbc::PublicKey,
+}
+
+impl LocalSigner {
+    pub fn new(skey: pbc::SecretKey, pkey: pbc::PublicKey) -> Self {
+        LocalSigner { skey, pkey }
+    }
+}
+
+impl fmt::Debug for LocalSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LocalSigner({})", self.pkey)
+    }
+}
+
+impl Signer for LocalSigner {
+    fn pkey(&self) -> pbc::PublicKey {
+        self.pkey
+    }
+
+    fn sign(&self, request: &SignRequest) -> Result<SignResponse, SignerError> {
+        Ok(request.sign(&self.skey))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::protocol::Connection;
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+    use stegos_blockchain::{BaseBlockHeader, VERSION};
+
+    /// Creates an empty micro block on top of `previous`,
+    /// `block` distinguishes blocks with the same parent.
+    pub(super) fn micro_block(
+        height: u64,
+        view_change: u32,
+        previous: &str,
+        block: &str,
+    ) -> MicroBlock {
+        let (skey, pkey) = pbc::make_deterministic_keys(block.as_bytes());
+        let random = pbc::make_VRF(&skey, &Hash::digest(previous));
+        let base = BaseBlockHeader::new(
+            VERSION,
+            Hash::zero(),
+            Hash::digest(previous),
+            height,
+            view_change,
+            UNIX_EPOCH,
+            random,
+        );
+        MicroBlock::empty(base, None, pkey)
+    }
+
+    /// Starts a signer on a random local port.
+    fn start_server(skey: pbc::SecretKey, token: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = listener.local_addr().unwrap().to_string();
+        let server = Arc::new(SignerServer::new(skey, token.to_string(), SignGuard::new()));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let server = server.clone();
+                let conn = Connection::Tcp(stream.unwrap());
+                thread::spawn(move || server.serve(conn));
+            }
+        });
+        endpoint
+    }
+
+    #[test]
+    fn remote_signer() {
+        let (skey, pkey) = pbc::make_random_keys();
+        let cfg = SignerConfig {
+            endpoint: start_server(skey.clone(), "secret"),
+            token: "secret".to_string(),
+            ..Default::default()
+        };
+        let signer = RemoteSigner::new(cfg.clone(), pkey);
+
+        // The signer hashes the block itself.
+        let block = micro_block(1, 0, "genesis", "a");
+        let block_hash = Hash::digest(&block);
+        let sig = signer.sign_micro_block(&block).unwrap();
+        pbc::check_hash(&block_hash, &sig, &pkey).unwrap();
+
+        let last_random = Hash::digest("random");
+        let random = signer.make_random(last_random, 1).unwrap();
+        assert!(pbc::validate_VRF_source(
+            &random,
+            &pkey,
+            &mix(last_random, 1)
+        ));
+
+        let body = ConsensusMessageBody::Prevote;
+        let msg = ConsensusMessage::sign(2, 0, block_hash, &signer, body).unwrap();
+        msg.validate().unwrap();
+
+        // Conflicting blocks are refused, the same block can be signed again.
+        match signer
+            .sign_micro_block(&micro_block(1, 0, "genesis", "b"))
+            .unwrap_err()
+        {
+            SignerError::Refused(_) => {}
+            e => panic!("{}", e),
+        }
+        signer.sign_micro_block(&block).unwrap();
+
+        // Clients with a wrong token are disconnected.
+        let bad_cfg = SignerConfig {
+            token: "wrong".to_string(),
+            ..cfg.clone()
+        };
+        let bad = RemoteSigner::new(bad_cfg, pkey);
+        match bad
+            .sign_micro_block(&micro_block(3, 0, "b", "c"))
+            .unwrap_err()
+        {
+            SignerError::Unavailable(..) => {}
+            e => panic!("{}", e),
+        }
+
+        // Signatures by another key are rejected.
+        let (_skey, other_pkey) = pbc::make_random_keys();
+        let other = RemoteSigner::new(cfg, other_pkey);
+        match other
+            .sign_micro_block(&micro_block(4, 0, "c", "d"))
+            .unwrap_err()
+        {
+            SignerError::InvalidSignature(_) => {}
+            e => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn reconnect() {
+        let (skey, pkey) = pbc::make_random_keys();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let cfg = SignerConfig {
+            endpoint: endpoint.clone(),
+            token: "secret".to_string(),
+            reconnect_delay: Duration::from_secs(0),
+            ..Default::default()
+        };
+        let signer = RemoteSigner::new(cfg, pkey);
+        match signer.sign_confirmation(confirmation_info()).unwrap_err() {
+            SignerError::Unavailable(..) => {}
+            e => panic!("{}", e),
+        }
+
+        // The signer comes up on the same address.
+        let listener = TcpListener::bind(&endpoint).unwrap();
+        let server = Arc::new(SignerServer::new(
+            skey,
+            "secret".to_string(),
+            SignGuard::new(),
+        ));
+        thread::spawn(move || {
+            // Serve one request per connection to force reconnects.
+            for stream in listener.incoming() {
+                let mut conn = Connection::Tcp(stream.unwrap());
+                if let Ok(nonce) = server.authenticate(&mut conn) {
+                    let _ = server.serve_request(&mut conn, &nonce, 1);
+                }
+            }
+        });
+        for _ in 0..3 {
+            let sig = signer.sign_confirmation(confirmation_info()).unwrap();
+            pbc::check_hash(&Hash::digest(&confirmation_info()), &sig, &pkey).unwrap();
+        }
+    }
+
+    fn confirmation_info() -> ConfirmationInfo {
+        ConfirmationInfo {
+            tx_hash: Hash::digest("tx"),
+            height: 1,
+            epoch: 1,
+        }
+    }
+}
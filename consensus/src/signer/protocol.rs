@@ -0,0 +1,283 @@
+//! Wire protocol of the remote signer.
+//!
+//! Messages are protobufs prefixed by their length as a big-endian u32.
+//! The server greets a client with a random nonce and the client answers
+//! with a MAC of the nonce keyed by the shared token. After that, each request
+//! and response is wrapped into a frame with a sequence number and a MAC of
+//! the token, the nonce, the sequence number and the payload.
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::SignResponse;
+use failure::{bail, Error};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::utils::ct_eq;
+use stegos_serialization::traits::ProtoConvert;
+
+/// The maximal size of a message, enough for macro block proposals.
+const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+
+/// The first message from the server.
+#[derive(Debug, Clone)]
+pub(crate) struct SignerHello {
+    pub nonce: Hash,
+}
+
+/// The answer of the client to SignerHello.
+#[derive(Debug, Clone)]
+pub(crate) struct SignerAuth {
+    pub mac: Hash,
+}
+
+/// An authenticated request or response.
+#[derive(Debug, Clone)]
+pub(crate) struct SignerFrame {
+    pub seq: u64,
+    pub payload: Vec<u8>,
+    pub mac: Hash,
+}
+
+/// A response of the server.
+#[derive(Debug, Clone)]
+pub(crate) enum SignerReply {
+    Signed(SignResponse),
+    Refused(String),
+}
+
+pub(crate) fn auth_mac(token: &str, nonce: &Hash) -> Hash {
+    let mut hasher = Hasher::new();
+    "SignerAuth".hash(&mut hasher);
+    token.hash(&mut hasher);
+    nonce.hash(&mut hasher);
+    hasher.result()
+}
+
+fn frame_mac(token: &str, nonce: &Hash, seq: u64, payload: &[u8]) -> Hash {
+    let mut hasher = Hasher::new();
+    "SignerFrame".hash(&mut hasher);
+    token.hash(&mut hasher);
+    nonce.hash(&mut hasher);
+    seq.hash(&mut hasher);
+    payload.hash(&mut hasher);
+    hasher.result()
+}
+
+impl SignerFrame {
+    /// Wraps a message into an authenticated frame.
+    pub fn seal<T: ProtoConvert>(
+        token: &str,
+        nonce: &Hash,
+        seq: u64,
+        msg: &T,
+    ) -> Result<Self, Error> {
+        let payload = msg.into_buffer()?;
+        let mac = frame_mac(token, nonce, seq, &payload);
+        Ok(SignerFrame { seq, payload, mac })
+    }
+
+    /// Checks the frame and unwraps the message.
+    pub fn open<T: ProtoConvert>(&self, token: &str, nonce: &Hash, seq: u64) -> Result<T, Error> {
+        if self.seq != seq {
+            bail!("Unexpected frame: seq={}, expected={}", self.seq, seq);
+        }
+        let mac = frame_mac(token, nonce, self.seq, &self.payload);
+        if !ct_eq(self.mac.base_vector(), mac.base_vector()) {
+            bail!("Invalid frame MAC: seq={}", self.seq);
+        }
+        T::from_buffer(&self.payload)
+    }
+}
+
+/// Writes a length-prefixed message.
+pub(crate) fn write_message<T: ProtoConvert, W: Write>(w: &mut W, msg: &T) -> Result<(), Error> {
+    let data = msg.into_buffer()?;
+    assert!(data.len() <= MAX_MESSAGE_SIZE);
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(&data)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Reads a length-prefixed message.
+pub(crate) fn read_message<T: ProtoConvert, R: Read>(r: &mut R) -> Result<T, Error> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        bail!("Message is too big: size={}", len);
+    }
+    let mut data = vec![0u8; len];
+    r.read_exact(&mut data)?;
+    T::from_buffer(&data)
+}
+
+/// Returns the socket path if the endpoint is a Unix socket.
+fn unix_path(endpoint: &str) -> Option<&str> {
+    if endpoint.starts_with("unix:") {
+        Some(&endpoint["unix:".len()..])
+    } else {
+        None
+    }
+}
+
+/// A stream socket.
+pub(crate) enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    /// Connects to "host:port" or "unix:/path".
+    pub fn connect(endpoint: &str, timeout: Duration) -> Result<Self, Error> {
+        let conn = if let Some(path) = unix_path(endpoint) {
+            connect_unix(path)?
+        } else {
+            let mut last_error = None;
+            let mut stream = None;
+            for addr in endpoint.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&addr, timeout) {
+                    Ok(s) => {
+                        stream = Some(s);
+                        break;
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            match (stream, last_error) {
+                (Some(stream), _) => {
+                    stream.set_nodelay(true)?;
+                    Connection::Tcp(stream)
+                }
+                (None, Some(e)) => return Err(e.into()),
+                (None, None) => bail!("Failed to resolve: endpoint={}", endpoint),
+            }
+        };
+        conn.set_timeout(Some(timeout))?;
+        Ok(conn)
+    }
+
+    /// Sets the read and write timeouts.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+            #[cfg(unix)]
+            Connection::Unix(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// A listening socket.
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listens on "host:port" or "unix:/path".
+    pub fn bind(endpoint: &str) -> Result<Self, Error> {
+        if let Some(path) = unix_path(endpoint) {
+            return bind_unix(path);
+        }
+        Ok(Listener::Tcp(TcpListener::bind(endpoint)?))
+    }
+
+    /// Accepts a new connection.
+    pub fn accept(&self) -> io::Result<(Connection, String)> {
+        match self {
+            Listener::Tcp(l) => {
+                let (stream, addr) = l.accept()?;
+                stream.set_nodelay(true)?;
+                Ok((Connection::Tcp(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(l) => {
+                let (stream, _addr) = l.accept()?;
+                Ok((Connection::Unix(stream), "unix".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> Result<Connection, Error> {
+    Ok(Connection::Unix(UnixStream::connect(path)?))
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str) -> Result<Listener, Error> {
+    // Remove the socket left by the previous run.
+    if std::path::Path::new(path).exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(Listener::Unix(UnixListener::bind(path)?))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(path: &str) -> Result<Connection, Error> {
+    bail!("Unix sockets are not supported: path={}", path)
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &str) -> Result<Listener, Error> {
+    bail!("Unix sockets are not supported: path={}", path)
+}
@@ -0,0 +1,122 @@
+//! Remote signer.
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::protocol::*;
+use super::{SignGuard, SignRequest};
+use failure::{bail, Error};
+use log::*;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+use stegos_crypto::utils::ct_eq;
+
+/// How long to wait for a client to authenticate.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Holds the network key and signs requests of an authenticated validator node.
+/// Conflicting requests are refused by the `SignGuard`.
+pub struct SignerServer {
+    skey: pbc::SecretKey,
+    token: String,
+    guard: Mutex<SignGuard>,
+}
+
+impl SignerServer {
+    pub fn new(skey: pbc::SecretKey, token: String, guard: SignGuard) -> Self {
+        SignerServer {
+            skey,
+            token,
+            guard: Mutex::new(guard),
+        }
+    }
+
+    /// Serves clients on "host:port" or "unix:/path", never returns on success.
+    pub fn run(self, endpoint: &str) -> Result<(), Error> {
+        let listener = Listener::bind(endpoint)?;
+        info!("Listening for signing requests: endpoint={}", endpoint);
+        let server = Arc::new(self);
+        loop {
+            let (conn, peer) = listener.accept()?;
+            info!("New connection: peer={}", peer);
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.serve(conn) {
+                    info!("Connection closed: peer={}, reason={}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Serves one client until it disconnects.
+    pub(crate) fn serve(&self, mut conn: Connection) -> Result<(), Error> {
+        let nonce = self.authenticate(&mut conn)?;
+        let mut seq: u64 = 0;
+        loop {
+            seq += 1;
+            self.serve_request(&mut conn, &nonce, seq)?;
+        }
+    }
+
+    /// Sends a nonce and checks the answer of the client, returns the nonce.
+    pub(crate) fn authenticate(&self, conn: &mut Connection) -> Result<Hash, Error> {
+        let nonce = Hash::random();
+        write_message(conn, &SignerHello { nonce })?;
+        // Don't wait forever for the authentication.
+        conn.set_timeout(Some(AUTH_TIMEOUT))?;
+        let auth: SignerAuth = read_message(conn)?;
+        let mac = auth_mac(&self.token, &nonce);
+        if !ct_eq(auth.mac.base_vector(), mac.base_vector()) {
+            bail!("Authentication failed");
+        }
+        conn.set_timeout(None)?;
+        Ok(nonce)
+    }
+
+    /// Reads one request and sends the reply.
+    pub(crate) fn serve_request(
+        &self,
+        conn: &mut Connection,
+        nonce: &Hash,
+        seq: u64,
+    ) -> Result<(), Error> {
+        let frame: SignerFrame = read_message(conn)?;
+        let request: SignRequest = frame.open(&self.token, nonce, seq)?;
+        let reply = self.handle(&request);
+        let frame = SignerFrame::seal(&self.token, nonce, seq, &reply)?;
+        write_message(conn, &frame)
+    }
+
+    /// Signs the request unless it conflicts with already signed ones.
+    pub(crate) fn handle(&self, request: &SignRequest) -> SignerReply {
+        let mut guard = self.guard.lock().unwrap();
+        match guard.check(request) {
+            Ok(()) => {
+                debug!("Signed: request={:?}", request.slot());
+                SignerReply::Signed(request.sign(&self.skey))
+            }
+            Err(e) => SignerReply::Refused(e.to_string()),
+        }
+    }
+}
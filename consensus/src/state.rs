@@ -24,16 +24,22 @@
 use crate::error::*;
 use crate::message::*;
 use crate::metrics;
+use crate::signer::Signer;
 use log::*;
 use std::collections::BTreeMap;
 use std::mem;
-use stegos_blockchain::{check_supermajority, create_multi_signature, ElectionResult, MacroBlock};
+use std::sync::Arc;
+use std::time::Instant;
+use stegos_blockchain::{
+    check_supermajority, ElectionResult, MacroBlock, MultiSignatureAccumulator,
+};
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
 
 #[derive(Debug)]
 struct LockedRound {
     precommits: BTreeMap<pbc::PublicKey, pbc::Signature>,
+    multisig: MultiSignatureAccumulator,
     block: MacroBlock,
     block_proposal: MacroBlockProposal,
 }
@@ -67,8 +73,8 @@ pub struct Consensus {
     //
     // Network node keys
     //
-    /// Public key of current node.
-    skey: pbc::SecretKey,
+    /// Signs messages with the network key of current node.
+    signer: Arc<dyn Signer>,
     /// Public key of current node.
     pkey: pbc::PublicKey,
     //
@@ -108,6 +114,10 @@ pub struct Consensus {
     prevotes: BTreeMap<pbc::PublicKey, pbc::Signature>,
     /// Collected Precommits.
     precommits: BTreeMap<pbc::PublicKey, pbc::Signature>,
+    /// Precommits aggregated into the multi-signature.
+    multisig: MultiSignatureAccumulator,
+    /// Start of the current round.
+    round_started: Instant,
 
     //
     // External events
@@ -126,34 +136,35 @@ impl Consensus {
     ///
     /// * `height` - identifier of session.
     /// * `epoch` - current consensus epoch.
-    /// * `skey` - BLS Secret Key of this node.
-    /// * `pkey` - BLS Public Key of this node.
+    /// * `signer` - signer with BLS key of this node.
     /// * `starting_view_change` - blockchain view_change number.
     /// * `election_result` - result of the previous election.
     /// * `validators` - voting members of consensus.
     pub fn new(
         height: u64,
         epoch: u64,
-        skey: pbc::SecretKey,
-        pkey: pbc::PublicKey,
+        signer: Arc<dyn Signer>,
         election_result: ElectionResult,
         validators: BTreeMap<pbc::PublicKey, i64>,
     ) -> Self {
+        let pkey = signer.pkey();
         assert!(validators.contains_key(&pkey));
         let state = ConsensusState::Propose;
         debug!("New => {}({}:{})", state.name(), height, 0);
         let prevotes: BTreeMap<pbc::PublicKey, pbc::Signature> = BTreeMap::new();
         let precommits: BTreeMap<pbc::PublicKey, pbc::Signature> = BTreeMap::new();
+        let multisig = MultiSignatureAccumulator::new();
         let total_slots = validators.iter().map(|v| v.1).sum();
         let block = None;
         let block_hash = None;
         let block_proposal = None;
         let locked_round = None;
         let round = 0;
+        let round_started = Instant::now();
         let inbox: Vec<ConsensusMessage> = Vec::new();
         let outbox: Vec<ConsensusMessage> = Vec::new();
         Consensus {
-            skey,
+            signer,
             pkey,
             validators,
             total_slots,
@@ -168,6 +179,8 @@ impl Consensus {
             locked_round,
             prevotes,
             precommits,
+            multisig,
+            round_started,
             inbox,
             outbox,
         }
@@ -182,6 +195,7 @@ impl Consensus {
             .expect("expected some block_proposal");
         let locked_round = LockedRound {
             precommits: mem::replace(&mut self.precommits, BTreeMap::new()),
+            multisig: mem::replace(&mut self.multisig, MultiSignatureAccumulator::new()),
             block,
             block_proposal,
         };
@@ -193,6 +207,7 @@ impl Consensus {
         self.state = ConsensusState::Propose;
         self.prevotes.clear();
         self.precommits.clear();
+        self.multisig = MultiSignatureAccumulator::new();
         self.block = None;
         self.block_hash = None;
         self.block_proposal = None;
@@ -220,6 +235,7 @@ impl Consensus {
             self.round
         );
         self.round += 1;
+        self.round_started = Instant::now();
         // if our last state was Precommit, keep lock in the state.
         if self.state == ConsensusState::Precommit {
             self.lock()
@@ -258,14 +274,19 @@ impl Consensus {
             &block_hash
         );
         let body = ConsensusMessageBody::Proposal(block_proposal);
-        let msg = ConsensusMessage::new(
+        let msg = match ConsensusMessage::sign(
             self.height,
             self.round,
             block_hash,
-            &self.skey,
-            &self.pkey,
+            &*self.signer,
             body,
-        );
+        ) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("Failed to sign a proposal: error={}", e);
+                return;
+            }
+        };
         self.outbox.push(msg.clone());
         self.feed_message(msg).expect("message is valid");
     }
@@ -293,14 +314,19 @@ impl Consensus {
         );
         self.block = Some(block);
         let body = ConsensusMessageBody::Prevote;
-        let msg = ConsensusMessage::new(
+        let msg = match ConsensusMessage::sign(
             self.height,
             self.round,
             block_hash,
-            &self.skey,
-            &self.pkey,
+            &*self.signer,
             body,
-        );
+        ) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("Failed to sign a prevote: error={}", e);
+                return;
+            }
+        };
         self.outbox.push(msg.clone());
         self.feed_message(msg).expect("message is valid");
     }
@@ -312,6 +338,7 @@ impl Consensus {
         assert_eq!(self.state, ConsensusState::Precommit);
         assert!(self.block.is_some());
         let block_hash = self.block_hash.as_ref().unwrap().clone();
+        let header = self.block.as_ref().unwrap().header.clone();
         debug!(
             "{}({}:{}): pre-commit block={:?}",
             self.state.name(),
@@ -319,16 +346,27 @@ impl Consensus {
             self.round,
             block_hash
         );
-        let block_hash_sig = pbc::sign_hash(&block_hash, &self.skey);
+        let block_hash_sig = match self.signer.sign_macro_block(&header, self.round) {
+            Ok(sig) => sig,
+            Err(e) => {
+                error!("Failed to sign a macro block: error={}", e);
+                return;
+            }
+        };
         let body = ConsensusMessageBody::Precommit(block_hash_sig);
-        let msg = ConsensusMessage::new(
+        let msg = match ConsensusMessage::sign(
             self.height,
             self.round,
             block_hash,
-            &self.skey,
-            &self.pkey,
+            &*self.signer,
             body,
-        );
+        ) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("Failed to sign a precommit: error={}", e);
+                return;
+            }
+        };
         self.outbox.push(msg.clone());
         self.feed_message(msg).expect("message is valid");
     }
@@ -341,6 +379,15 @@ impl Consensus {
     /// * `msg` - a message to process.
     ///
     pub fn feed_message(&mut self, msg: ConsensusMessage) -> Result<(), ConsensusError> {
+        msg.validate()?;
+        self.feed_verified_message(msg)
+    }
+
+    ///
+    /// Same as `feed_message()`, for messages with the signature already checked
+    /// by `ConsensusMessage::signature_check()`.
+    ///
+    pub fn feed_verified_message(&mut self, msg: ConsensusMessage) -> Result<(), ConsensusError> {
         trace!(
             "{}({}:{}): process message: msg={:?}",
             self.state.name(),
@@ -349,8 +396,6 @@ impl Consensus {
             &msg
         );
 
-        msg.validate()?;
-
         // Check sender.
         if !self.validators.contains_key(&msg.pkey) {
             debug!(
@@ -526,6 +571,7 @@ impl Consensus {
                         // Someone proposed a request that looks like our locked.
                         let locked = self.locked_round.take().unwrap();
                         self.precommits = locked.precommits;
+                        self.multisig = locked.multisig;
                         // repeat prevote
                         self.prevote(locked.block);
                     } // don't vote for request that is different from our locked.
@@ -569,7 +615,10 @@ impl Consensus {
                     self.round,
                     &msg.pkey
                 );
-                self.precommits.insert(msg.pkey, block_hash_sig);
+                if self.precommits.insert(msg.pkey, block_hash_sig).is_none() {
+                    let id = self.validator_id(&msg.pkey);
+                    self.multisig.add(id, &block_hash_sig);
+                }
             }
         }
 
@@ -627,7 +676,8 @@ impl Consensus {
     fn process_inbox(&mut self) {
         let inbox = std::mem::replace(&mut self.inbox, Vec::new());
         for msg in inbox {
-            if let Err(e) = self.feed_message(msg) {
+            // Messages in the inbox have already been validated.
+            if let Err(e) = self.feed_verified_message(msg) {
                 warn!(
                     "{}({}:{}): failed to process message: error={:?}",
                     self.state.name(),
@@ -701,16 +751,29 @@ impl Consensus {
     pub fn commit(mut self) -> MacroBlock {
         assert!(self.should_commit());
 
-        // TODO: Use id instead of PublicKey.
-        let validators = self.validators.iter().map(|(k, v)| (*k, *v)).collect();
         let mut block = self.block.take().unwrap();
-        // Create multi-signature.
-        let (multisig, multisigmap) = create_multi_signature(&validators, &self.precommits);
+        // Precommits have already been aggregated as they arrived.
+        debug_assert_eq!(self.multisig.len(), self.precommits.len());
+        let (multisig, multisigmap) = self.multisig.finish();
         block.body.multisig = multisig;
         block.body.multisigmap = multisigmap;
+
+        let elapsed = self.round_started.elapsed();
+        metrics::ROUND_DURATION
+            .observe(elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0);
         block
     }
 
+    ///
+    /// Returns the index of the validator in the multi-signature bitmap.
+    ///
+    fn validator_id(&self, pkey: &pbc::PublicKey) -> u32 {
+        self.validators
+            .keys()
+            .position(|k| k == pkey)
+            .expect("vote from validator") as u32
+    }
+
     ///
     /// Checks that supermajority of votes has been collected.
     ///
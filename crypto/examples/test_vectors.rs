@@ -0,0 +1,51 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Writes test vectors of cryptographic primitives as JSON files.
+//!
+//! Usage: cargo run --example test_vectors -- data/test_vectors
+
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::Path;
+use stegos_crypto::test_vectors::*;
+
+fn write<T: Serialize>(dir: &Path, name: &str, vectors: Vec<T>) {
+    let path = dir.join(format!("{}.json", name));
+    let mut json = serde_json::to_string_pretty(&vectors).expect("serializable");
+    json.push('\n');
+    fs::write(&path, json).expect("writable directory");
+    println!("Wrote {} vectors to {}", vectors.len(), path.display());
+}
+
+fn main() {
+    let dir = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "data/test_vectors".to_string());
+    let dir = Path::new(&dir);
+    fs::create_dir_all(dir).expect("writable directory");
+    write(dir, "pbc_signatures", pbc_signature_vectors());
+    write(dir, "vrf", vrf_vectors());
+    write(dir, "schnorr_signatures", schnorr_signature_vectors());
+    write(dir, "pedersen_commitments", pedersen_commitment_vectors());
+    write(dir, "payloads", payload_vectors());
+}
@@ -0,0 +1,64 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use num_bigint::BigUint;
+use stegos_crypto::utils::{
+    ct_hexstr_to_bev_u8, ct_hexstr_to_lev_u8, hexstr_to_bev_u8, hexstr_to_lev_u8, u8v_to_hexstr,
+};
+
+fuzz_target!(|data: &[u8]| {
+    // Arbitrary strings must never panic.
+    if let Ok(s) = std::str::from_utf8(data) {
+        let mut x = [0u8; 32];
+        if hexstr_to_bev_u8(s, &mut x).is_ok() {
+            let y = BigUint::parse_bytes(s.as_bytes(), 16).unwrap_or_default();
+            assert_eq!(BigUint::from_bytes_be(&x), y);
+        }
+        if hexstr_to_lev_u8(s, &mut x).is_ok() {
+            let y = BigUint::parse_bytes(s.as_bytes(), 16).unwrap_or_default();
+            assert_eq!(BigUint::from_bytes_le(&x), y);
+        }
+
+        // Constant-time decoding must agree with the regular one.
+        let mut y = [0u8; 32];
+        let ok = hexstr_to_bev_u8(s, &mut x).is_ok();
+        assert_eq!(ok, ct_hexstr_to_bev_u8(s, &mut y).is_ok());
+        if ok {
+            assert_eq!(x, y);
+        }
+        let ok = hexstr_to_lev_u8(s, &mut x).is_ok();
+        assert_eq!(ok, ct_hexstr_to_lev_u8(s, &mut y).is_ok());
+        if ok {
+            assert_eq!(x, y);
+        }
+    }
+
+    // Round trip.
+    let s = u8v_to_hexstr(data);
+    let mut x = vec![0u8; data.len()];
+    hexstr_to_bev_u8(&s, &mut x).unwrap();
+    assert_eq!(&x[..], data);
+});
@@ -0,0 +1,45 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use num_bigint::BigUint;
+use stegos_crypto::utils::{ucmp_be, ucmp_le};
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    // The first byte splits the input into two numbers.
+    let split = (data[0] as usize) % data.len();
+    let (a, b) = data[1..].split_at(split.min(data.len() - 1));
+    assert_eq!(
+        ucmp_be(a, b),
+        BigUint::from_bytes_be(a).cmp(&BigUint::from_bytes_be(b))
+    );
+    assert_eq!(
+        ucmp_le(a, b),
+        BigUint::from_bytes_le(a).cmp(&BigUint::from_bytes_le(b))
+    );
+});
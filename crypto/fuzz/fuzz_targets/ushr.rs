@@ -0,0 +1,48 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use num_bigint::BigUint;
+use stegos_crypto::utils::{ushr_be, ushr_le};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    // The first two bytes are the shift.
+    let nsh = ((data[0] as usize) << 8) | (data[1] as usize);
+    let src = &data[2..];
+    let mut dst = vec![0u8; src.len()];
+    ushr_be(src, &mut dst, nsh);
+    assert_eq!(
+        BigUint::from_bytes_be(&dst),
+        BigUint::from_bytes_be(src) >> nsh
+    );
+    ushr_le(src, &mut dst, nsh);
+    assert_eq!(
+        BigUint::from_bytes_le(&dst),
+        BigUint::from_bytes_le(src) >> nsh
+    );
+});
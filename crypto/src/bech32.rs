@@ -0,0 +1,201 @@
+//! bech32.rs - Bech32 encoding with human-readable prefix and checksum (BIP-173)
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::CryptoError;
+
+/// Data characters, indexed by their 5-bit value.
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// Separator between the human-readable part and data.
+const SEPARATOR: char = '1';
+/// Checksum length in 5-bit groups.
+const CHECKSUM_LEN: usize = 6;
+/// Maximal length of an encoded string.
+const MAX_LEN: usize = 90;
+/// BCH code generator.
+const GEN: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+    for v in values {
+        let b = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ u32::from(*v);
+        for (i, g) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut v: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    v.push(0);
+    v.extend(hrp.bytes().map(|c| c & 0x1f));
+    v
+}
+
+fn create_checksum(hrp: &str, data: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; CHECKSUM_LEN]);
+    let pm = polymod(&values) ^ 1;
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    for (i, c) in checksum.iter_mut().enumerate() {
+        *c = ((pm >> (5 * (5 - i))) & 0x1f) as u8;
+    }
+    checksum
+}
+
+fn verify_checksum(hrp: &str, data: &[u8]) -> bool {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    polymod(&values) == 1
+}
+
+/// Regroup a bit stream from `from`-bit to `to`-bit values.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, CryptoError> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let maxv: u32 = (1 << to) - 1;
+    let mut out = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for v in data {
+        let v = u32::from(*v);
+        if v >> from != 0 {
+            return Err(CryptoError::InvalidAddress);
+        }
+        acc = (acc << from) | v;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & maxv) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & maxv) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & maxv) != 0 {
+        return Err(CryptoError::InvalidAddress);
+    }
+    Ok(out)
+}
+
+/// Encode binary data as a Bech32 string with the given human-readable prefix.
+pub fn encode(hrp: &str, data: &[u8]) -> String {
+    let data = convert_bits(data, 8, 5, true).expect("8-bit values always fit");
+    let checksum = create_checksum(hrp, &data);
+    let mut s = String::with_capacity(hrp.len() + 1 + data.len() + CHECKSUM_LEN);
+    s.push_str(hrp);
+    s.push(SEPARATOR);
+    for v in data.iter().chain(checksum.iter()) {
+        s.push(CHARSET[*v as usize] as char);
+    }
+    s
+}
+
+/// Decode a Bech32 string into the human-readable prefix and binary data.
+pub fn decode(s: &str) -> Result<(String, Vec<u8>), CryptoError> {
+    if s.len() > MAX_LEN || !s.is_ascii() {
+        return Err(CryptoError::InvalidAddress);
+    }
+    let has_lower = s.bytes().any(|c| c.is_ascii_lowercase());
+    let has_upper = s.bytes().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(CryptoError::InvalidAddress);
+    }
+    let s = s.to_ascii_lowercase();
+    let pos = match s.rfind(SEPARATOR) {
+        Some(pos) if pos > 0 && pos + 1 + CHECKSUM_LEN <= s.len() => pos,
+        _ => return Err(CryptoError::InvalidAddress),
+    };
+    let hrp = &s[..pos];
+    if hrp.bytes().any(|c| c < 33 || c > 126) {
+        return Err(CryptoError::InvalidAddress);
+    }
+    let mut data = Vec::with_capacity(s.len() - pos - 1);
+    for c in s[pos + 1..].bytes() {
+        match CHARSET.iter().position(|x| *x == c) {
+            Some(v) => data.push(v as u8),
+            None => return Err(CryptoError::InvalidAddress),
+        }
+    }
+    if !verify_checksum(hrp, &data) {
+        return Err(CryptoError::InvalidAddressChecksum);
+    }
+    data.truncate(data.len() - CHECKSUM_LEN);
+    let data = convert_bits(&data, 5, 8, false)?;
+    Ok((hrp.to_string(), data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_checksums() {
+        // Test vectors from BIP-173.
+        let (hrp, data) = decode("A12UEL5L").unwrap();
+        assert_eq!(hrp, "a");
+        assert!(data.is_empty());
+        let (hrp, data) = decode("abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw").unwrap();
+        assert_eq!(hrp, "abcdef");
+        assert_eq!(data.len(), 20);
+        assert!(decode("split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w").is_ok());
+    }
+
+    #[test]
+    fn invalid() {
+        // Mixed case.
+        assert!(decode("A12uEL5L").is_err());
+        // Empty human-readable part.
+        assert!(decode("1pzry9x0s0muk").is_err());
+        // Invalid data character.
+        assert!(decode("x1b4n0q5v").is_err());
+        // Too short checksum.
+        assert!(decode("li1dgmt3").is_err());
+        // Bad checksum.
+        match decode("a12uel5m") {
+            Err(CryptoError::InvalidAddressChecksum) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        for len in 0..40 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37) as u8).collect();
+            let s = encode("stt", &data);
+            let (hrp, data2) = decode(&s).unwrap();
+            assert_eq!(hrp, "stt");
+            assert_eq!(data, data2);
+            assert_eq!(decode(&s.to_uppercase()).unwrap().1, data);
+        }
+    }
+}
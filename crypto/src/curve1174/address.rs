@@ -0,0 +1,134 @@
+//! address.rs - Human-readable wallet addresses
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::PublicKey;
+use crate::bech32;
+use crate::CryptoError;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use std::str::FromStr;
+
+/// Address prefix of the main network.
+pub const MAINNET_PREFIX: &str = "stg";
+/// Address prefix of the test network.
+pub const TESTNET_PREFIX: &str = "stt";
+/// Address prefix of the development network.
+pub const DEVNET_PREFIX: &str = "str";
+/// Address prefix of local development chains.
+pub const DEV_PREFIX: &str = "dev";
+
+lazy_static! {
+    static ref NETWORK_PREFIX: RwLock<&'static str> = RwLock::new(TESTNET_PREFIX);
+}
+
+/// Returns the address prefix for the chain name.
+pub fn chain_prefix(chain: &str) -> &'static str {
+    match chain {
+        "mainnet" => MAINNET_PREFIX,
+        "testnet" => TESTNET_PREFIX,
+        "devnet" => DEVNET_PREFIX,
+        _ => DEV_PREFIX,
+    }
+}
+
+/// Returns the address prefix of the current network.
+pub fn network_prefix() -> &'static str {
+    *NETWORK_PREFIX.read()
+}
+
+/// Sets the address prefix of the current network.
+/// Must be called once on startup, before any address is encoded or parsed.
+pub fn set_network_prefix(prefix: &'static str) {
+    *NETWORK_PREFIX.write() = prefix;
+}
+
+impl PublicKey {
+    /// Convert into an address of the current network.
+    pub fn to_address(&self) -> String {
+        self.to_address_with_prefix(network_prefix())
+    }
+
+    /// Convert into an address with the given network prefix.
+    pub fn to_address_with_prefix(&self, prefix: &str) -> String {
+        bech32::encode(prefix, &self.to_bytes())
+    }
+
+    /// Try to convert from an address of the current network.
+    pub fn try_from_address(s: &str) -> Result<Self, CryptoError> {
+        let (prefix, bytes) = bech32::decode(s)?;
+        let expected = network_prefix();
+        if prefix != expected {
+            return Err(CryptoError::InvalidAddressPrefix(
+                expected.to_string(),
+                prefix,
+            ));
+        }
+        PublicKey::try_from_bytes(&bytes)
+    }
+}
+
+/// Parses either an address or a legacy hex string.
+impl FromStr for PublicKey {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 64 && s.bytes().all(|c| c.is_ascii_hexdigit()) {
+            PublicKey::try_from_hex(s)
+        } else {
+            PublicKey::try_from_address(s)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve1174::make_random_keys;
+
+    #[test]
+    fn address() {
+        let (_skey, pkey) = make_random_keys();
+        let address = pkey.to_address();
+        assert!(address.starts_with("stt1"));
+        assert_eq!(PublicKey::try_from_address(&address).unwrap(), pkey);
+        assert_eq!(PublicKey::from_str(&address).unwrap(), pkey);
+        assert_eq!(PublicKey::from_str(&pkey.to_hex()).unwrap(), pkey);
+
+        // Wrong network.
+        let address = pkey.to_address_with_prefix(MAINNET_PREFIX);
+        match PublicKey::try_from_address(&address) {
+            Err(CryptoError::InvalidAddressPrefix(_, _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // Typo.
+        let mut address = pkey.to_address().into_bytes();
+        let last = address.len() - 1;
+        address[last] = if address[last] == b'q' { b'p' } else { b'q' };
+        let address = String::from_utf8(address).unwrap();
+        match PublicKey::try_from_address(&address) {
+            Err(CryptoError::InvalidAddressChecksum) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
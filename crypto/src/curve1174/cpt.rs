@@ -23,6 +23,7 @@
 //
 
 use super::*;
+use crate::utils::{ct_eq, ct_hexstr_to_lev_u8};
 use crate::CryptoError;
 
 use clear_on_drop::clear::Clear;
@@ -35,6 +36,7 @@ use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer};
 use std::cmp::Ordering;
 use std::hash as stdhash;
+use zeroize::Zeroize;
 
 // ------------------------------------------------------------------------------------------
 // Client API - compressed points and simple fields
@@ -147,7 +149,7 @@ impl PartialOrd for Pt {
 
 // --------------------------------------------------------------------
 
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone)]
 pub struct SecretKey(Fr);
 
 impl SecretKey {
@@ -157,10 +159,23 @@ impl SecretKey {
         self.0.to_hex()
     }
 
-    /// Try to convert from hex string.
-    #[inline]
+    /// Try to convert from hex string, values are reduced modulo the group order.
+    /// Decoding is constant-time and doesn't leave copies of the key.
     pub fn try_from_hex(s: &str) -> Result<Self, CryptoError> {
-        Ok(SecretKey(Fr::try_from_hex(s, true)?))
+        let mut bytes = [0u8; 32];
+        ct_hexstr_to_lev_u8(s, &mut bytes)?;
+        let mut x = U256::from_lev_u8(bytes, true);
+        bytes.zeroize();
+        // x < 2^256 <= 2^8 * R, subtract R * 2^k for k = 7..0 in constant time.
+        for k in (0..8).rev() {
+            let mut r = (*R).0;
+            for _ in 0..k {
+                mul2(&mut r);
+            }
+            ct_sub_if_ge(&mut x.0, &r);
+        }
+        // `x` is moved into the key and zeroized on drop.
+        Ok(SecretKey(Fr::Unscaled(x)))
     }
 
     /// Convert into raw bytes.
@@ -182,6 +197,30 @@ impl fmt::Debug for SecretKey {
     }
 }
 
+impl Eq for SecretKey {}
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        let mut a = self.to_bytes();
+        let mut b = other.to_bytes();
+        let eq = ct_eq(&a, &b);
+        a.zeroize();
+        b.zeroize();
+        eq
+    }
+}
+
+impl Zeroize for SecretKey {
+    fn zeroize(&mut self) {
+        self.0.zap();
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl Hashable for SecretKey {
     fn hash(&self, state: &mut Hasher) {
         "SKey".hash(state);
@@ -206,7 +245,7 @@ impl From<Fr> for SecretKey {
 
 impl<'a> From<&'a SecretKey> for Fr {
     fn from(skey: &'a SecretKey) -> Fr {
-        skey.clone().0
+        skey.0.clone()
     }
 }
 
@@ -353,7 +392,7 @@ impl Serialize for PublicKey {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&self.to_hex())
+        serializer.serialize_str(&self.to_address())
     }
 }
 
@@ -363,7 +402,7 @@ impl<'de> Deserialize<'de> for PublicKey {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        PublicKey::try_from_hex(&s).map_err(serde::de::Error::custom)
+        s.parse().map_err(serde::de::Error::custom)
     }
 }
 
@@ -399,7 +438,7 @@ pub fn make_random_keys() -> (SecretKey, PublicKey) {
 // generate K = k*G for k = random Fr
 // generate u = k + Fr(H(K, P, msg)) * s
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SchnorrSig {
     pub u: Fr,
     pub K: Pt,
@@ -696,4 +735,22 @@ pub mod tests {
             decrypt_key(my_cloaking_seed, &encr_key).expect("Key couldn't be decrypted");
         assert!(recovered_skey == skey.to_bytes());
     }
+
+    #[test]
+    fn skey_hex_out_of_range() {
+        let skey = SecretKey::try_from_hex(CURVE_R).expect("hex is valid");
+        assert_eq!(skey, SecretKey::from(Fr::zero()));
+        let skey = SecretKey::try_from_hex(
+            "01FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF77965C4DFD307348944D45FD166C972",
+        )
+        .expect("hex is valid");
+        assert_eq!(skey, SecretKey::from(Fr::one()));
+        let max = "FF".repeat(32);
+        let skey = SecretKey::try_from_hex(&max).expect("hex is valid");
+        assert!(Fr::from(&skey).unscaled_bits() < *R);
+        assert_eq!(
+            skey,
+            SecretKey::from(Fr::try_from_hex(&max, false).unwrap())
+        );
+    }
 }
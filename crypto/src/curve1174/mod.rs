@@ -56,6 +56,9 @@ pub use self::ecpt::*;
 mod cpt; // compressed point representation
 pub use self::cpt::*;
 
+mod address; // human-readable wallet addresses
+pub use self::address::*;
+
 use crate::dicemix::ffi;
 use clear_on_drop::clear::Clear;
 
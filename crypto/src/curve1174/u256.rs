@@ -26,6 +26,7 @@ use crate::CryptoError;
 use rand::rngs::ThreadRng;
 use rand::thread_rng;
 use rand::Rng;
+use zeroize::Zeroize;
 
 // -----------------------------------------------------------------
 // U256 word chunks represent a 256-bit bignum as a little-endian u64 vector
@@ -400,16 +401,16 @@ fn add_nocarry(a: &mut [u64; 4], b: &[u64; 4]) {
     debug_assert!(0 == carry);
 }
 
-pub fn sub_noborrow(a: &mut [u64; 4], b: &[u64; 4]) {
-    fn sbb(a: u64, b: u64, borrow: &mut u64) -> u64 {
-        let (a1, a0) = split_u64(a);
-        let (b1, b0) = split_u64(b);
-        let (b, r0) = split_u64((1 << 32) + a0 - b0 - *borrow);
-        let (b, r1) = split_u64((1 << 32) + a1 - b1 - ((b == 0) as u64));
-        *borrow = (b == 0) as u64;
-        combine_u64(r1, r0)
-    }
+fn sbb(a: u64, b: u64, borrow: &mut u64) -> u64 {
+    let (a1, a0) = split_u64(a);
+    let (b1, b0) = split_u64(b);
+    let (b, r0) = split_u64((1 << 32) + a0 - b0 - *borrow);
+    let (b, r1) = split_u64((1 << 32) + a1 - b1 - ((b == 0) as u64));
+    *borrow = (b == 0) as u64;
+    combine_u64(r1, r0)
+}
 
+pub fn sub_noborrow(a: &mut [u64; 4], b: &[u64; 4]) {
     let mut borrow = 0;
     for (a, b) in a.into_iter().zip(b.iter()) {
         *a = sbb(*a, *b, &mut borrow);
@@ -417,6 +418,21 @@ pub fn sub_noborrow(a: &mut [u64; 4], b: &[u64; 4]) {
     debug_assert!(0 == borrow);
 }
 
+/// Subtract `b` from `a` if `a >= b`, without branching on the values.
+pub fn ct_sub_if_ge(a: &mut [u64; 4], b: &[u64; 4]) {
+    let mut diff = *a;
+    let mut borrow = 0;
+    for (d, b) in diff.iter_mut().zip(b.iter()) {
+        *d = sbb(*d, *b, &mut borrow);
+    }
+    // All ones if there was no borrow, i.e. a >= b.
+    let mask = borrow.wrapping_sub(1);
+    for (a, d) in a.iter_mut().zip(diff.iter()) {
+        *a = (*a & !mask) | (*d & mask);
+    }
+    diff.zeroize();
+}
+
 fn mac_digit(acc: &mut [u64], b: &[u64], c: u64) {
     #[inline]
     fn mac_with_carry(a: u64, b: u64, c: u64, carry: &mut u64) -> u64 {
@@ -580,4 +596,16 @@ pub mod tests {
             assert!(w * v == Fr::one());
         }
     }
+
+    #[test]
+    pub fn ct_sub_if_ge() {
+        let mut a = [5u64, 0, 0, 1];
+        super::ct_sub_if_ge(&mut a, &[6, 0, 0, 1]);
+        assert_eq!(a, [5, 0, 0, 1]);
+        super::ct_sub_if_ge(&mut a, &[5, 0, 0, 1]);
+        assert_eq!(a, [0, 0, 0, 0]);
+        let mut a = [0u64, 0, 0, 1];
+        super::ct_sub_if_ge(&mut a, &[1, 0, 0, 0]);
+        assert_eq!(a, [u64::max_value(), u64::max_value(), u64::max_value(), 0]);
+    }
 }
@@ -20,6 +20,7 @@
 // SOFTWARE.
 
 pub mod aont;
+pub mod bech32;
 pub mod bulletproofs;
 pub mod curve1174;
 pub mod dicemix;
@@ -28,6 +29,7 @@ pub mod hashcash;
 pub mod keying;
 pub mod pbc;
 pub mod protos;
+pub mod test_vectors;
 pub mod utils;
 
 use failure::Fail;
@@ -76,6 +78,18 @@ pub enum CryptoError {
 
     #[fail(display = "Not an AONT ciphertext")]
     InvalidAontDecryption,
+
+    /// Malformed Bech32 address.
+    #[fail(display = "Invalid address")]
+    InvalidAddress,
+
+    /// Bech32 checksum mismatch.
+    #[fail(display = "Invalid address checksum")]
+    InvalidAddressChecksum,
+
+    /// Address belongs to another network.
+    #[fail(display = "Invalid address prefix. Expected: {}, Got: {}", _0, _1)]
+    InvalidAddressPrefix(String, String),
 }
 
 impl From<hex::FromHexError> for CryptoError {
@@ -40,6 +40,7 @@
 //! --------------------------------------------------------------------------
 
 use super::*;
+use crate::utils::ct_eq;
 use crate::CryptoError;
 use clear_on_drop::clear::Clear;
 use rand::rngs::ThreadRng;
@@ -48,6 +49,7 @@ use rand::Rng;
 use std::cmp::Ordering;
 use std::hash as stdhash;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use zeroize::Zeroize;
 
 use crate::dicemix::ffi;
 
@@ -880,7 +882,13 @@ impl Hashable for SecretKey {
 impl Eq for SecretKey {}
 impl PartialEq for SecretKey {
     fn eq(&self, b: &Self) -> bool {
-        self.0 == b.0
+        ct_eq(self.base_vector(), b.base_vector())
+    }
+}
+
+impl Zeroize for SecretKey {
+    fn zeroize(&mut self) {
+        self.0.zap();
     }
 }
 
@@ -892,7 +900,7 @@ impl From<SecretKey> for Zr {
 
 impl Drop for SecretKey {
     fn drop(&mut self) {
-        self.0.zap()
+        self.zeroize();
     }
 }
 
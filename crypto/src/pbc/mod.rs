@@ -219,6 +219,7 @@ fn private_init_pairings(
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::CryptoError;
 
     #[test]
     fn check_pbc_connection() {
@@ -271,6 +272,24 @@ pub mod tests {
         assert!(secure::validate_VRF_randomness(&vrf));
         assert!(secure::validate_VRF_source(&vrf, &pkey, &hseed));
     }
+
+    #[test]
+    fn secret_key_hex() {
+        let (skey, _pkey) = secure::make_deterministic_keys(b"Testing");
+        let skey2 = secure::SecretKey::try_from_hex(&skey.to_hex()).expect("hex is valid");
+        assert_eq!(skey2, skey);
+
+        // Values not less than the group order are rejected.
+        match secure::SecretKey::try_from_hex(ORDER_FR256) {
+            Err(CryptoError::TooLarge) => {}
+            _ => panic!("order is not a valid secret key"),
+        }
+        let max = "ff".repeat(ZR_SIZE_FR256);
+        match secure::SecretKey::try_from_hex(&max) {
+            Err(CryptoError::TooLarge) => {}
+            _ => panic!("2^256-1 is not a valid secret key"),
+        }
+    }
 }
 
 // ---------------------------------------------------------------------
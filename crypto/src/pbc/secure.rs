@@ -31,6 +31,7 @@
 //! --------------------------------------------------------------------------
 
 use super::*;
+use crate::utils::{ct_eq, ct_hexstr_to_bev_u8};
 use crate::CryptoError;
 
 use crate::dicemix::ffi;
@@ -38,10 +39,12 @@ use clear_on_drop::clear::Clear;
 use rand::rngs::ThreadRng;
 use rand::thread_rng;
 use rand::Rng;
+use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer};
 use std::cmp::Ordering;
 use std::hash as stdhash;
 use std::ops::{Add, AddAssign, Neg};
+use zeroize::Zeroize;
 
 // --------------------------------------------------------------------------------
 
@@ -477,10 +480,16 @@ impl SecretKey {
         self.0.to_hex()
     }
 
-    /// Try to convert from hex string.
+    /// Try to convert from hex string, values not less than the group order are rejected.
+    /// Decoding is constant-time and doesn't leave copies of the key.
     pub fn try_from_hex(s: &str) -> Result<Self, CryptoError> {
-        let z = Zr::try_from_hex(s)?;
-        Ok(SecretKey(z))
+        let mut v = Zr::wv();
+        ct_hexstr_to_bev_u8(s, &mut v)?;
+        if ucmp_be(&v, &*ORD_FR256) != Ordering::Less {
+            v.zeroize();
+            return Err(CryptoError::TooLarge);
+        }
+        Ok(SecretKey(Zr(v)))
     }
 }
 
@@ -500,13 +509,19 @@ impl Hashable for SecretKey {
 impl Eq for SecretKey {}
 impl PartialEq for SecretKey {
     fn eq(&self, b: &Self) -> bool {
-        self.0 == b.0
+        ct_eq(self.base_vector(), b.base_vector())
+    }
+}
+
+impl Zeroize for SecretKey {
+    fn zeroize(&mut self) {
+        self.0.zap();
     }
 }
 
 impl Drop for SecretKey {
     fn drop(&mut self) {
-        self.0.zap();
+        self.zeroize();
     }
 }
 
@@ -627,6 +642,16 @@ impl Serialize for PublicKey {
     }
 }
 
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<PublicKey, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        PublicKey::try_from_hex(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 // -----------------------------------------
 
 #[derive(Clone)]
@@ -809,6 +834,25 @@ impl From<G1> for Signature {
     }
 }
 
+impl Serialize for Signature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Signature, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Signature::try_from_hex(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 // ------------------------------------------------------------------------
 // BLS Signature Generation & Checking
 
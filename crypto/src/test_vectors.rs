@@ -0,0 +1,329 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Test vectors for cryptographic primitives.
+//!
+//! Every vector is built from fixed inputs, so the expected outputs can be
+//! checked into the repository and compared against after refactoring.
+//! Vectors are kept as JSON in `data/test_vectors/`, one file per primitive,
+//! with all values hex-encoded in the same form as `to_hex()` of the
+//! corresponding type. External implementations can load these files and
+//! check their results against this one.
+//!
+//! Conventions:
+//!
+//! - keys are derived by `make_deterministic_keys(seed)` from UTF-8 bytes of `seed`;
+//! - a message is signed by its hash, which is SHA3-256 of its UTF-8 bytes;
+//! - VRF input is SHA3-256 of UTF-8 bytes of `input`;
+//! - the blinding factor of a Pedersen commitment is SHA3-256 of UTF-8 bytes
+//!   of `blinding_seed` reduced to Fr, the amount is committed on H;
+//! - payloads are encrypted by `aes_encrypt()`, which is deterministic.
+//!
+//! Run `cargo run --example test_vectors -- data/test_vectors` to regenerate the files.
+
+use crate::bulletproofs::simple_commit;
+use crate::curve1174;
+use crate::curve1174::{Fr, Pt};
+use crate::hash::Hash;
+use crate::pbc;
+use serde_derive::{Deserialize, Serialize};
+
+/// BLS signature on pbc.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PbcSignatureVector {
+    pub name: String,
+    pub seed: String,
+    pub message: String,
+    pub secret_key: String,
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// Verifiable random function on pbc.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VrfVector {
+    pub name: String,
+    pub seed: String,
+    pub input: String,
+    pub public_key: String,
+    pub rand: String,
+    pub proof: String,
+}
+
+/// Schnorr signature on curve1174.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SchnorrSignatureVector {
+    pub name: String,
+    pub seed: String,
+    pub message: String,
+    pub secret_key: String,
+    pub public_key: String,
+    /// `u` of the signature.
+    pub sig_u: String,
+    /// `K` of the signature.
+    pub sig_k: String,
+}
+
+/// Pedersen commitment on curve1174.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PedersenCommitmentVector {
+    pub name: String,
+    pub amount: i64,
+    pub blinding_seed: String,
+    pub blinding: String,
+    pub commitment: String,
+}
+
+/// Payload encrypted to a curve1174 public key.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PayloadVector {
+    pub name: String,
+    pub seed: String,
+    pub public_key: String,
+    pub plaintext: String,
+    /// Key hint `alpha * G`.
+    pub key_hint: String,
+    pub ciphertext: String,
+}
+
+const SEEDS: &[&str] = &["alice", "bob"];
+const MESSAGES: &[&str] = &["", "hello", "The quick brown fox jumps over the lazy dog"];
+
+fn message_hash(message: &str) -> Hash {
+    Hash::from_vector(message.as_bytes())
+}
+
+/// Name of a vector built from a seed and a message.
+fn vector_name(seed: &str, index: usize) -> String {
+    format!("{}_{}", seed, index)
+}
+
+pub fn pbc_signature_vectors() -> Vec<PbcSignatureVector> {
+    let mut vectors = Vec::new();
+    for seed in SEEDS {
+        let (skey, pkey) = pbc::make_deterministic_keys(seed.as_bytes());
+        for (index, message) in MESSAGES.iter().enumerate() {
+            let sig = pbc::sign_hash(&message_hash(message), &skey);
+            vectors.push(PbcSignatureVector {
+                name: vector_name(seed, index),
+                seed: seed.to_string(),
+                message: message.to_string(),
+                secret_key: skey.to_hex(),
+                public_key: pkey.to_hex(),
+                signature: sig.to_hex(),
+            });
+        }
+    }
+    vectors
+}
+
+pub fn vrf_vectors() -> Vec<VrfVector> {
+    let mut vectors = Vec::new();
+    for seed in SEEDS {
+        let (skey, pkey) = pbc::make_deterministic_keys(seed.as_bytes());
+        for (index, input) in MESSAGES.iter().enumerate() {
+            let vrf = pbc::make_VRF(&skey, &message_hash(input));
+            vectors.push(VrfVector {
+                name: vector_name(seed, index),
+                seed: seed.to_string(),
+                input: input.to_string(),
+                public_key: pkey.to_hex(),
+                rand: vrf.rand.to_hex(),
+                proof: vrf.proof.to_hex(),
+            });
+        }
+    }
+    vectors
+}
+
+pub fn schnorr_signature_vectors() -> Vec<SchnorrSignatureVector> {
+    let mut vectors = Vec::new();
+    for seed in SEEDS {
+        let (skey, pkey) = curve1174::make_deterministic_keys(seed.as_bytes());
+        for (index, message) in MESSAGES.iter().enumerate() {
+            let sig = curve1174::sign_hash(&message_hash(message), &skey);
+            vectors.push(SchnorrSignatureVector {
+                name: vector_name(seed, index),
+                seed: seed.to_string(),
+                message: message.to_string(),
+                secret_key: skey.to_hex(),
+                public_key: pkey.to_hex(),
+                sig_u: sig.u.to_hex(),
+                sig_k: sig.K.to_hex(),
+            });
+        }
+    }
+    vectors
+}
+
+pub fn pedersen_commitment_vectors() -> Vec<PedersenCommitmentVector> {
+    let amounts: &[i64] = &[0, 1, 100_000_000, i64::max_value()];
+    amounts
+        .iter()
+        .enumerate()
+        .map(|(index, &amount)| {
+            let blinding_seed = format!("blinding_{}", index);
+            let blinding = Fr::from(message_hash(&blinding_seed));
+            let commitment = simple_commit(&blinding, &Fr::from(amount));
+            PedersenCommitmentVector {
+                name: format!("amount_{}", index),
+                amount,
+                blinding_seed,
+                blinding: blinding.to_hex(),
+                commitment: Pt::from(commitment).to_hex(),
+            }
+        })
+        .collect()
+}
+
+pub fn payload_vectors() -> Vec<PayloadVector> {
+    let mut vectors = Vec::new();
+    for seed in SEEDS {
+        let (_skey, pkey) = curve1174::make_deterministic_keys(seed.as_bytes());
+        for (index, message) in MESSAGES.iter().enumerate() {
+            let payload = curve1174::aes_encrypt(message.as_bytes(), &pkey).expect("valid key");
+            vectors.push(PayloadVector {
+                name: vector_name(seed, index),
+                seed: seed.to_string(),
+                public_key: pkey.to_hex(),
+                plaintext: hex::encode(message.as_bytes()),
+                key_hint: payload.ag.to_hex(),
+                ciphertext: hex::encode(&payload.ctxt),
+            });
+        }
+    }
+    vectors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use std::fmt::Debug;
+
+    /// Golden values, checked into the repository.
+    const PBC_SIGNATURES: &str = include_str!("../data/test_vectors/pbc_signatures.json");
+    const VRF: &str = include_str!("../data/test_vectors/vrf.json");
+    const SCHNORR_SIGNATURES: &str = include_str!("../data/test_vectors/schnorr_signatures.json");
+    const PEDERSEN_COMMITMENTS: &str =
+        include_str!("../data/test_vectors/pedersen_commitments.json");
+    const PAYLOADS: &str = include_str!("../data/test_vectors/payloads.json");
+
+    /// Every vector from the file must be reproduced by the current implementation.
+    fn check_golden<T, F>(golden: &str, actual: Vec<T>, name: F) -> Vec<T>
+    where
+        T: DeserializeOwned + Debug + Eq,
+        F: Fn(&T) -> String,
+    {
+        let expected: Vec<T> = serde_json::from_str(golden).unwrap();
+        assert!(
+            !expected.is_empty(),
+            "no golden vectors, run `cargo run --example test_vectors -- data/test_vectors`"
+        );
+        assert_eq!(expected.len(), actual.len(), "the number of vectors has changed");
+        for expected in &expected {
+            let actual = actual
+                .iter()
+                .find(|actual| name(actual) == name(expected))
+                .unwrap_or_else(|| panic!("missing vector {}", name(expected)));
+            assert_eq!(actual, expected, "vector {} has changed", name(expected));
+        }
+        expected
+    }
+
+    #[test]
+    fn pbc_signatures() {
+        let vectors = check_golden(PBC_SIGNATURES, pbc_signature_vectors(), |v| v.name.clone());
+        for v in vectors.iter().chain(pbc_signature_vectors().iter()) {
+            let pkey = pbc::PublicKey::try_from_hex(&v.public_key).unwrap();
+            let sig = pbc::Signature::try_from_hex(&v.signature).unwrap();
+            pbc::check_hash(&message_hash(&v.message), &sig, &pkey).expect("valid signature");
+        }
+    }
+
+    #[test]
+    fn vrf() {
+        let vectors = check_golden(VRF, vrf_vectors(), |v| v.name.clone());
+        for v in vectors.iter().chain(vrf_vectors().iter()) {
+            let pkey = pbc::PublicKey::try_from_hex(&v.public_key).unwrap();
+            let vrf = pbc::VRF {
+                rand: Hash::try_from_hex(&v.rand).unwrap(),
+                proof: pbc::G1::try_from_hex(&v.proof).unwrap(),
+            };
+            assert!(pbc::validate_VRF_randomness(&vrf));
+            assert!(pbc::validate_VRF_source(
+                &vrf,
+                &pkey,
+                &message_hash(&v.input)
+            ));
+        }
+    }
+
+    #[test]
+    fn schnorr_signatures() {
+        let vectors = check_golden(SCHNORR_SIGNATURES, schnorr_signature_vectors(), |v| {
+            v.name.clone()
+        });
+        for v in vectors.iter().chain(schnorr_signature_vectors().iter()) {
+            let pkey = curve1174::PublicKey::try_from_hex(&v.public_key).unwrap();
+            let sig = curve1174::SchnorrSig {
+                u: Fr::try_from_hex(&v.sig_u, false).unwrap(),
+                K: Pt::try_from_hex(&v.sig_k).unwrap(),
+            };
+            curve1174::validate_sig(&message_hash(&v.message), &sig, &pkey)
+                .expect("valid signature");
+        }
+    }
+
+    #[test]
+    fn pedersen_commitments() {
+        let vectors = check_golden(PEDERSEN_COMMITMENTS, pedersen_commitment_vectors(), |v| {
+            v.name.clone()
+        });
+        for v in vectors.iter().chain(pedersen_commitment_vectors().iter()) {
+            let blinding = Fr::try_from_hex(&v.blinding, false).unwrap();
+            let commitment = simple_commit(&blinding, &Fr::from(v.amount));
+            assert_eq!(Pt::from(commitment).to_hex(), v.commitment);
+        }
+    }
+
+    #[test]
+    fn payloads() {
+        let vectors = check_golden(PAYLOADS, payload_vectors(), |v| v.name.clone());
+        for v in vectors.iter().chain(payload_vectors().iter()) {
+            let (skey, _pkey) = curve1174::make_deterministic_keys(v.seed.as_bytes());
+            let payload = curve1174::EncryptedPayload {
+                ag: Pt::try_from_hex(&v.key_hint).unwrap(),
+                ctxt: hex::decode(&v.ciphertext).unwrap(),
+            };
+            let plaintext = curve1174::aes_decrypt(&payload, &skey).unwrap();
+            assert_eq!(hex::encode(plaintext), v.plaintext);
+        }
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let vectors = schnorr_signature_vectors();
+        let json = serde_json::to_string_pretty(&vectors).unwrap();
+        let decoded: Vec<SchnorrSignatureVector> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, vectors);
+    }
+}
@@ -26,6 +26,7 @@ use crate::CryptoError;
 use hex;
 use std::cmp::Ordering;
 use std::fmt::Write;
+use zeroize::Zeroize;
 // -------------------------------------------------------------------
 // general utility functions
 
@@ -41,15 +42,13 @@ pub fn hexstr_to_bev_u8(s: &str, x: &mut [u8]) -> Result<(), CryptoError> {
     if nel < v.len() {
         return Err(CryptoError::InvalidHexLength);
     }
-    let mut ix = 0; // this seems dumb... isn't there a better way?
-    for b in v {
-        x[ix] = b;
-        ix += 1;
-    }
-    while ix < nel {
-        x[ix] = 0;
-        ix += 1;
+    // allow for shorter answer than room allotted for it.
+    // zero pad the MSB's
+    let pad = nel - v.len();
+    for elt in x[0..pad].iter_mut() {
+        *elt = 0;
     }
+    x[pad..nel].copy_from_slice(&v);
     Ok(())
 }
 
@@ -105,44 +104,133 @@ pub fn print_nbits(data: &[u8], nbits: usize) -> Result<String, std::fmt::Error>
     Ok(result)
 }
 
+/// True if all bits are zero (including an empty vector).
 pub fn is_zero_bits(v: &[u8]) -> bool {
-    v.iter().fold(false, |_, b| {
-        if *b != 0 {
-            return false;
-        }
-        true
-    })
+    v.iter().all(|b| *b == 0)
 }
 
+/// True if all bits are one (including an empty vector).
 pub fn is_one_bits(v: &[u8]) -> bool {
-    v.iter().fold(false, |_, b| {
-        if *b != 0xff {
-            return false;
-        }
-        true
-    })
+    v.iter().all(|b| *b == 0xff)
+}
+
+/// Constant-time equality of two byte vectors.
+/// Only the lengths of vectors are allowed to leak.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a
+        .iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    diff == 0
+}
+
+/// Constant-time step of comparison.
+/// Returns `x <=> y` if bytes differ, otherwise the previous result.
+#[inline]
+fn ct_cmp_step(prev: i32, x: u8, y: u8) -> i32 {
+    let (x, y) = (x as i32, y as i32);
+    let gt = ((y - x) >> 31) & 1;
+    let lt = ((x - y) >> 31) & 1;
+    let ne = -(gt | lt);
+    (prev & !ne) | ((gt - lt) & ne)
+}
+
+fn ct_ordering(res: i32) -> Ordering {
+    res.cmp(&0)
 }
 
+/// Compare two big-endian unsigned integers in constant time.
+/// Vectors of different length are compared as if zero-extended.
 pub fn ucmp_be(a: &[u8], b: &[u8]) -> Ordering {
-    for (xa, xb) in a.iter().zip(b.iter()) {
-        if *xa < *xb {
-            return Ordering::Less;
-        } else if *xa > *xb {
-            return Ordering::Greater;
+    let len = a.len().max(b.len());
+    let byte = |v: &[u8], ix: usize| -> u8 {
+        let pad = len - v.len();
+        if ix < pad {
+            0
+        } else {
+            v[ix - pad]
         }
+    };
+    // Go from the least significant byte, so the most significant difference wins.
+    let mut res = 0;
+    for ix in (0..len).rev() {
+        res = ct_cmp_step(res, byte(a, ix), byte(b, ix));
     }
-    Ordering::Equal
+    ct_ordering(res)
 }
 
+/// Compare two little-endian unsigned integers in constant time.
+/// Vectors of different length are compared as if zero-extended.
 pub fn ucmp_le(a: &[u8], b: &[u8]) -> Ordering {
-    for (xa, xb) in a.iter().zip(b.iter()).rev() {
-        if *xa < *xb {
-            return Ordering::Less;
-        } else if *xa > *xb {
-            return Ordering::Greater;
-        }
+    let len = a.len().max(b.len());
+    let byte = |v: &[u8], ix: usize| -> u8 { v.get(ix).cloned().unwrap_or(0) };
+    // Go from the least significant byte, so the most significant difference wins.
+    let mut res = 0;
+    for ix in 0..len {
+        res = ct_cmp_step(res, byte(a, ix), byte(b, ix));
+    }
+    ct_ordering(res)
+}
+
+/// Decode a hex digit without secret-dependent branches.
+/// Returns the value and a mask which is 0xff for valid digits and 0 otherwise.
+#[inline]
+fn ct_hex_nibble(c: u8) -> (u8, u8) {
+    let c = c as i16;
+    // -1 if lo <= c <= hi, 0 otherwise.
+    let in_range = |lo: i16, hi: i16| ((lo - 1 - c) & (c - hi - 1)) >> 15;
+    let digit = in_range(0x30, 0x39);
+    let lower = in_range(0x61, 0x66);
+    let upper = in_range(0x41, 0x46);
+    let value = (digit & (c - 0x30)) | (lower & (c - 0x61 + 10)) | (upper & (c - 0x41 + 10));
+    ((value & 0x0f) as u8, ((digit | lower | upper) & 0xff) as u8)
+}
+
+fn ct_hexstr_to_u8(s: &str, x: &mut [u8], big_endian: bool) -> Result<(), CryptoError> {
+    let s = s.as_bytes();
+    let nel = x.len();
+    let nbytes = (s.len() + 1) / 2;
+    if nel < nbytes {
+        return Err(CryptoError::InvalidHexLength);
+    }
+    for elt in x.iter_mut() {
+        *elt = 0;
     }
-    Ordering::Equal
+    // an odd number of hex digits has an implicit leading zero.
+    let odd = s.len() & 1;
+    let mut valid = 0xffu8;
+    for (ix, c) in s.iter().enumerate() {
+        let (nibble, ok) = ct_hex_nibble(*c);
+        valid &= ok;
+        let pos = ix + odd;
+        let byte = if big_endian {
+            nel - nbytes + pos / 2
+        } else {
+            nbytes - 1 - pos / 2
+        };
+        let shift = if pos & 1 == 0 { 4 } else { 0 };
+        x[byte] |= nibble << shift;
+    }
+    if valid != 0xff {
+        x.zeroize();
+        return Err(CryptoError::InvalidHexCharacter);
+    }
+    Ok(())
+}
+
+/// Constant-time version of `hexstr_to_bev_u8()` for secrets.
+/// The output is wiped on errors and no intermediate copies are made.
+pub fn ct_hexstr_to_bev_u8(s: &str, x: &mut [u8]) -> Result<(), CryptoError> {
+    ct_hexstr_to_u8(s, x, true)
+}
+
+/// Constant-time version of `hexstr_to_lev_u8()` for secrets.
+/// The output is wiped on errors and no intermediate copies are made.
+pub fn ct_hexstr_to_lev_u8(s: &str, x: &mut [u8]) -> Result<(), CryptoError> {
+    ct_hexstr_to_u8(s, x, false)
 }
 
 pub fn ushr_be(src: &[u8], dst: &mut [u8], nsh: usize) {
@@ -164,7 +252,8 @@ pub fn ushr_be(src: &[u8], dst: &mut [u8], nsh: usize) {
     let mut tmp = 0;
     for (elt, x) in dst[nb..len].iter_mut().zip(src[0..(len - nb)].iter()) {
         *elt = tmp | (*x >> nbits);
-        tmp = *x << lsh;
+        // `x << 8` would overflow when shifting by whole bytes.
+        tmp = if nbits == 0 { 0 } else { *x << lsh };
     }
 }
 
@@ -187,6 +276,116 @@ pub fn ushr_le(src: &[u8], dst: &mut [u8], nsh: usize) {
     let mut tmp = 0;
     for (elt, x) in dst[0..(len - nb)].iter_mut().zip(src[nb..len].iter()).rev() {
         *elt = tmp | (*x >> nbits);
-        tmp = *x << lsh;
+        // `x << 8` would overflow when shifting by whole bytes.
+        tmp = if nbits == 0 { 0 } else { *x << lsh };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigUint;
+    use proptest::prelude::*;
+
+    fn be(v: &[u8]) -> BigUint {
+        BigUint::from_bytes_be(v)
+    }
+
+    fn le(v: &[u8]) -> BigUint {
+        BigUint::from_bytes_le(v)
+    }
+
+    #[test]
+    fn zero_and_one_bits() {
+        assert!(is_zero_bits(&[]));
+        assert!(is_zero_bits(&[0, 0, 0]));
+        assert!(!is_zero_bits(&[1, 0, 0]));
+        assert!(!is_zero_bits(&[0, 0, 1]));
+        assert!(is_one_bits(&[0xff, 0xff]));
+        assert!(!is_one_bits(&[0x00, 0xff]));
+        assert!(!is_one_bits(&[0xff, 0x7f]));
+    }
+
+    #[test]
+    fn ct_hexstr() {
+        let mut x = [0xaau8; 4];
+        ct_hexstr_to_bev_u8("102", &mut x).unwrap();
+        assert_eq!(x, [0x00, 0x00, 0x01, 0x02]);
+        ct_hexstr_to_lev_u8("aBf", &mut x).unwrap();
+        assert_eq!(x, [0xbf, 0x0a, 0x00, 0x00]);
+        assert!(ct_hexstr_to_bev_u8("0102030405", &mut x).is_err());
+        for s in &["zz", "0g", "G0", "/0", ":0", "@0", "`0", " 0"] {
+            let mut x = [0xaau8; 4];
+            assert!(ct_hexstr_to_bev_u8(s, &mut x).is_err(), "{}", s);
+            assert_eq!(x, [0u8; 4]);
+        }
+    }
+
+    #[test]
+    fn short_hexstr() {
+        let mut x = [0xaau8; 4];
+        hexstr_to_bev_u8("102", &mut x).unwrap();
+        assert_eq!(x, [0x00, 0x00, 0x01, 0x02]);
+        hexstr_to_lev_u8("102", &mut x).unwrap();
+        assert_eq!(x, [0x02, 0x01, 0x00, 0x00]);
+        assert!(hexstr_to_bev_u8("0102030405", &mut x).is_err());
+        assert!(hexstr_to_lev_u8("0102030405", &mut x).is_err());
+        assert!(hexstr_to_bev_u8("zz", &mut x).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_zero_bits(v in prop::collection::vec(any::<u8>(), 0..64)) {
+            prop_assert_eq!(is_zero_bits(&v), be(&v) == BigUint::from(0u32));
+        }
+
+        #[test]
+        fn prop_one_bits(v in prop::collection::vec(any::<u8>(), 0..64)) {
+            let max = (BigUint::from(1u32) << (8 * v.len())) - BigUint::from(1u32);
+            prop_assert_eq!(is_one_bits(&v), be(&v) == max);
+        }
+
+        #[test]
+        fn prop_ucmp(a in prop::collection::vec(any::<u8>(), 0..48),
+                     b in prop::collection::vec(any::<u8>(), 0..48)) {
+            prop_assert_eq!(ucmp_be(&a, &b), be(&a).cmp(&be(&b)));
+            prop_assert_eq!(ucmp_le(&a, &b), le(&a).cmp(&le(&b)));
+            prop_assert_eq!(ucmp_be(&a, &a), Ordering::Equal);
+            prop_assert_eq!(ct_eq(&a, &b), a == b);
+            prop_assert!(ct_eq(&a, &a));
+        }
+
+        #[test]
+        fn prop_ushr(src in prop::collection::vec(any::<u8>(), 1..48), nsh in 0usize..400) {
+            let mut dst = vec![0u8; src.len()];
+            ushr_be(&src, &mut dst, nsh);
+            prop_assert_eq!(be(&dst), be(&src) >> nsh);
+            ushr_le(&src, &mut dst, nsh);
+            prop_assert_eq!(le(&dst), le(&src) >> nsh);
+        }
+
+        #[test]
+        fn prop_hexstr(v in prop::collection::vec(any::<u8>(), 0..48), extra in 0usize..8) {
+            let s = u8v_to_hexstr(&v);
+            prop_assert_eq!(&s, &hex::encode(&v));
+
+            let mut x = vec![0xaau8; v.len() + extra];
+            hexstr_to_bev_u8(&s, &mut x).unwrap();
+            prop_assert_eq!(be(&x), be(&v));
+            hexstr_to_lev_u8(&s, &mut x).unwrap();
+            prop_assert_eq!(le(&x), be(&v));
+
+            ct_hexstr_to_bev_u8(&s, &mut x).unwrap();
+            prop_assert_eq!(be(&x), be(&v));
+            ct_hexstr_to_lev_u8(&s.to_uppercase(), &mut x).unwrap();
+            prop_assert_eq!(le(&x), be(&v));
+
+            // Leading zero nibble is optional.
+            let trimmed = s.trim_start_matches('0');
+            hexstr_to_bev_u8(trimmed, &mut x).unwrap();
+            prop_assert_eq!(be(&x), be(&v));
+            ct_hexstr_to_bev_u8(trimmed, &mut x).unwrap();
+            prop_assert_eq!(be(&x), be(&v));
+        }
     }
 }
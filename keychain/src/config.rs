@@ -39,6 +39,8 @@ pub struct KeyChainConfig {
     pub network_skey_file: String,
     /// Path to PBC public key.
     pub network_pkey_file: String,
+    /// Path to spend policy definitions.
+    pub policy_file: String,
 }
 
 impl Default for KeyChainConfig {
@@ -50,6 +52,7 @@ impl Default for KeyChainConfig {
             wallet_pkey_file: "wallet.pkey".to_string(),
             network_skey_file: "network.skey".to_string(),
             network_pkey_file: "network.pkey".to_string(),
+            policy_file: "wallet.policy".to_string(),
         }
     }
 }
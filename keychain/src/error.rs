@@ -43,4 +43,8 @@ pub enum KeyError {
     InvalidKey(String, CryptoError),
     #[fail(display = "Invalid keying: skey_file={}, pkey_file={}", _0, _1)]
     InvalidKeying(String, String),
+    #[fail(display = "Recovery public key must not be zero")]
+    ZeroRecoveryKey,
+    #[fail(display = "Failed to encrypt or decrypt exported key: error={}", _0)]
+    InvalidExportedKey(CryptoError),
 }
@@ -31,6 +31,9 @@ use stegos_crypto::curve1174;
 const RECOVERY_PROMPT: &'static str = "Enter 24-word recovery phrase: ";
 const PASSWORD_PROMPT1: &'static str = "Enter password: ";
 const PASSWORD_PROMPT2: &'static str = "Enter same password again: ";
+const OLD_PASSWORD_PROMPT: &'static str = "Enter current password: ";
+const NEW_PASSWORD_PROMPT1: &'static str = "Enter new password: ";
+const NEW_PASSWORD_PROMPT2: &'static str = "Enter same new password again: ";
 
 fn fix_newline(password: &mut String) {
     if password.ends_with('\n') {
@@ -73,8 +76,19 @@ pub(crate) fn read_recovery(recovery_file: &str) -> Result<curve1174::SecretKey,
 }
 
 pub(crate) fn read_password_from_stdin(confirm: bool) -> Result<String, KeyError> {
+    prompt_password(PASSWORD_PROMPT1, PASSWORD_PROMPT2, confirm)
+}
+
+/// Ask for the current and a new password.
+pub(crate) fn read_password_change_from_stdin() -> Result<(String, String), KeyError> {
+    let old_password = prompt_password(OLD_PASSWORD_PROMPT, PASSWORD_PROMPT2, false)?;
+    let new_password = prompt_password(NEW_PASSWORD_PROMPT1, NEW_PASSWORD_PROMPT2, true)?;
+    Ok((old_password, new_password))
+}
+
+fn prompt_password(prompt1: &str, prompt2: &str, confirm: bool) -> Result<String, KeyError> {
     loop {
-        let password = prompt_password_stdout(PASSWORD_PROMPT1)
+        let password = prompt_password_stdout(prompt1)
             .map_err(|e| KeyError::InputOutputError("stdin".to_string(), e))?;
         if password.is_empty() {
             eprintln!("Password is empty. Try again.");
@@ -83,7 +97,7 @@ pub(crate) fn read_password_from_stdin(confirm: bool) -> Result<String, KeyError
         if !confirm {
             return Ok(password);
         }
-        let password2 = prompt_password_stdout(PASSWORD_PROMPT2)
+        let password2 = prompt_password_stdout(prompt2)
             .map_err(|e| KeyError::InputOutputError("stdin".to_string(), e))?;
         if password == password2 {
             return Ok(password);
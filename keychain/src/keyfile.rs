@@ -22,28 +22,27 @@
 use crate::error::KeyError;
 use crate::pem;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use stegos_crypto::curve1174;
 use stegos_crypto::pbc;
 use stegos_serialization::traits::ProtoConvert;
 
 /// PEM tag for encrypted wallet secret key.
-const WALLET_ENCRYPTED_SKEY_TAG: &'static str = "STEGOS-CURVE1174 ENCRYPTED SECRET KEY";
+pub(crate) const WALLET_ENCRYPTED_SKEY_TAG: &'static str = "STEGOS-CURVE1174 ENCRYPTED SECRET KEY";
 /// PEM tag for wallet public key.
 const WALLET_PKEY_TAG: &'static str = "STEGOS-CURVE1174 PUBLIC KEY";
 /// PEM tag for encrypted network secret key.
-const NETWORK_ENCRYPTED_SKEY_TAG: &'static str = "STEGOS-PBC ENCRYPTED SECRET KEY";
+pub(crate) const NETWORK_ENCRYPTED_SKEY_TAG: &'static str = "STEGOS-PBC ENCRYPTED SECRET KEY";
 /// PEM tag for network public key.
 const NETWORK_PKEY_TAG: &'static str = "STEGOS-PBC PUBLIC KEY";
+/// PEM tag for wallet secret key encrypted to a recovery public key.
+const WALLET_EXPORTED_SKEY_TAG: &'static str = "STEGOS-CURVE1174 EXPORTED SECRET KEY";
 
-fn load_key(path: &Path, tag: &str) -> Result<Vec<u8>, KeyError> {
-    let pem = fs::read_to_string(path)
-        .map_err(|e| KeyError::InputOutputError(path.to_string_lossy().to_string(), e))?;
-    let pem =
-        pem::parse(pem).map_err(|e| KeyError::ParseError(path.to_string_lossy().to_string(), e))?;
+fn parse_key(name: &str, pem: &str, tag: &str) -> Result<Vec<u8>, KeyError> {
+    let pem = pem::parse(pem).map_err(|e| KeyError::ParseError(name.to_string(), e))?;
     if pem.tag != tag {
         return Err(KeyError::InvalidTag(
-            path.to_string_lossy().to_string(),
+            name.to_string(),
             tag.to_string(),
             pem.tag,
         ));
@@ -51,6 +50,12 @@ fn load_key(path: &Path, tag: &str) -> Result<Vec<u8>, KeyError> {
     Ok(pem.contents)
 }
 
+fn load_key(path: &Path, tag: &str) -> Result<Vec<u8>, KeyError> {
+    let pem = fs::read_to_string(path)
+        .map_err(|e| KeyError::InputOutputError(path.to_string_lossy().to_string(), e))?;
+    parse_key(&path.to_string_lossy(), &pem, tag)
+}
+
 fn load_encrypted_key(path: &Path, tag: &str, password: &str) -> Result<Vec<u8>, KeyError> {
     let skey = load_key(path, tag)?;
     let skey = curve1174::EncryptedKey::from_buffer(&skey)
@@ -96,16 +101,54 @@ fn write_key(path: &Path, tag: &str, contents: Vec<u8>) -> Result<(), KeyError>
         .map_err(|e| KeyError::InputOutputError(path.to_string_lossy().to_string(), e))
 }
 
+fn encrypt_key(contents: &[u8], password: &str) -> Vec<u8> {
+    curve1174::encrypt_key(&password, contents)
+        .into_buffer()
+        .expect("Failed to encode encrypted payload")
+}
+
 fn write_encrypted_key(
     path: &Path,
     tag: &str,
-    contents: Vec<u8>,
+    mut contents: Vec<u8>,
     password: &str,
 ) -> Result<(), KeyError> {
-    let contents = curve1174::encrypt_key(&password, &contents)
-        .into_buffer()
-        .expect("Failed to encode encrypted payload");
-    write_key(path, tag, contents)
+    let encrypted = encrypt_key(&contents, password);
+    curve1174::zap_bytes(&mut contents);
+    write_key(path, tag, encrypted)
+}
+
+/// Path to a temporary file next to the key.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".new");
+    path.with_file_name(name)
+}
+
+/// Re-encrypt secret keys with a new password.
+///
+/// All keys are decrypted in memory first, so a wrong password leaves the files untouched.
+/// Encrypted keys are written to temporary files which then replace the originals.
+pub(crate) fn change_password(
+    keys: &[(&Path, &str)],
+    old_password: &str,
+    new_password: &str,
+) -> Result<(), KeyError> {
+    let mut encrypted = Vec::with_capacity(keys.len());
+    for (path, tag) in keys {
+        let mut contents = load_encrypted_key(path, tag, old_password)?;
+        encrypted.push(encrypt_key(&contents, new_password));
+        curve1174::zap_bytes(&mut contents);
+    }
+
+    for ((path, tag), contents) in keys.iter().zip(encrypted.into_iter()) {
+        write_key(&temp_path(path), tag, contents)?;
+    }
+    for (path, _tag) in keys {
+        fs::rename(temp_path(path), path)
+            .map_err(|e| KeyError::InputOutputError(path.to_string_lossy().to_string(), e))?;
+    }
+    Ok(())
 }
 
 pub(crate) fn write_wallet_pkey(path: &Path, pkey: &curve1174::PublicKey) -> Result<(), KeyError> {
@@ -133,3 +176,112 @@ pub(crate) fn write_network_skey(
     let contents = skey.to_bytes().to_vec();
     write_encrypted_key(path, NETWORK_ENCRYPTED_SKEY_TAG, contents, password)
 }
+
+/// Encrypt the wallet secret key to a recovery public key, PEM-encoded.
+pub(crate) fn export_wallet_skey(
+    skey: &curve1174::SecretKey,
+    recovery_pkey: &curve1174::PublicKey,
+) -> Result<String, KeyError> {
+    // A zero key produces a payload which anyone can read.
+    if *recovery_pkey == curve1174::PublicKey::zero() {
+        return Err(KeyError::ZeroRecoveryKey);
+    }
+    let mut bytes = skey.to_bytes();
+    let encrypted = curve1174::aes_encrypt(&bytes, recovery_pkey);
+    curve1174::zap_bytes(&mut bytes);
+    let contents = encrypted
+        .map_err(KeyError::InvalidExportedKey)?
+        .into_buffer()
+        .expect("Failed to encode encrypted payload");
+    let pem = pem::Pem {
+        tag: WALLET_EXPORTED_SKEY_TAG.to_string(),
+        contents,
+    };
+    Ok(pem::encode(&pem))
+}
+
+/// Decrypt the wallet secret key exported by export_wallet_skey().
+pub(crate) fn import_wallet_skey(
+    pem: &str,
+    recovery_skey: &curve1174::SecretKey,
+) -> Result<curve1174::SecretKey, KeyError> {
+    let name = "exported key";
+    let contents = parse_key(name, pem, WALLET_EXPORTED_SKEY_TAG)?;
+    let payload = curve1174::EncryptedPayload::from_buffer(&contents)
+        .map_err(|e| KeyError::InvalidPayload(name.to_string(), e))?;
+    let mut bytes =
+        curve1174::aes_decrypt(&payload, recovery_skey).map_err(KeyError::InvalidExportedKey)?;
+    let skey = curve1174::SecretKey::try_from_bytes(&bytes)
+        .map_err(|e| KeyError::InvalidKey(name.to_string(), e));
+    curve1174::zap_bytes(&mut bytes);
+    skey
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use stegos_crypto::hash::Hash;
+
+    #[test]
+    fn change_password() {
+        let dir = env::temp_dir();
+        let suffix = Hash::digest(&"change_password");
+        let wallet_skey_path = dir.join(format!("stegos-wallet-{}.skey", suffix));
+        let network_skey_path = dir.join(format!("stegos-network-{}.skey", suffix));
+
+        let (wallet_skey, _wallet_pkey) = curve1174::make_random_keys();
+        let (network_skey, _network_pkey) = pbc::make_random_keys();
+        write_wallet_skey(&wallet_skey_path, &wallet_skey, "old").unwrap();
+        write_network_skey(&network_skey_path, &network_skey, "old").unwrap();
+        let keys = [
+            (wallet_skey_path.as_path(), WALLET_ENCRYPTED_SKEY_TAG),
+            (network_skey_path.as_path(), NETWORK_ENCRYPTED_SKEY_TAG),
+        ];
+
+        // Wrong password.
+        match super::change_password(&keys, "wrong", "new") {
+            Err(KeyError::InvalidPasswordPhrase(_)) => {}
+            _ => panic!("Invalid error"),
+        }
+        assert_eq!(
+            load_wallet_skey(&wallet_skey_path, "old").unwrap(),
+            wallet_skey
+        );
+
+        super::change_password(&keys, "old", "new").unwrap();
+        assert!(load_wallet_skey(&wallet_skey_path, "old").is_err());
+        assert!(load_network_skey(&network_skey_path, "old").is_err());
+        assert_eq!(
+            load_wallet_skey(&wallet_skey_path, "new").unwrap(),
+            wallet_skey
+        );
+        assert_eq!(
+            load_network_skey(&network_skey_path, "new").unwrap(),
+            network_skey
+        );
+        assert!(!temp_path(&wallet_skey_path).exists());
+        assert!(!temp_path(&network_skey_path).exists());
+
+        fs::remove_file(&wallet_skey_path).unwrap();
+        fs::remove_file(&network_skey_path).unwrap();
+    }
+
+    #[test]
+    fn export_import() {
+        let (wallet_skey, _wallet_pkey) = curve1174::make_random_keys();
+        let (recovery_skey, recovery_pkey) = curve1174::make_random_keys();
+        let pem = export_wallet_skey(&wallet_skey, &recovery_pkey).unwrap();
+        let wallet_skey2 = import_wallet_skey(&pem, &recovery_skey).unwrap();
+        assert_eq!(wallet_skey, wallet_skey2);
+
+        // Other keys can't decrypt the export.
+        let (other_skey, _other_pkey) = curve1174::make_random_keys();
+        assert!(import_wallet_skey(&pem, &other_skey).is_err());
+
+        match export_wallet_skey(&wallet_skey, &curve1174::PublicKey::zero()) {
+            Err(KeyError::ZeroRecoveryKey) => {}
+            _ => panic!("Invalid error"),
+        }
+    }
+}
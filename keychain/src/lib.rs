@@ -27,11 +27,14 @@ pub mod pem;
 pub use config::*;
 mod input;
 mod keyfile;
+mod policy;
 mod recovery;
+pub use crate::policy::PolicyRule;
 
 use crate::error::KeyError;
 use crate::input::*;
 use crate::keyfile::*;
+use crate::policy::{load_policy, write_policy};
 use crate::recovery::wallet_skey_to_recovery;
 use failure::Error;
 use log::*;
@@ -74,14 +77,17 @@ impl KeyChain {
                 info!("Recovering keys...");
                 let wallet_skey = read_recovery(&cfg.recovery_file)?;
                 let wallet_pkey: curve1174::PublicKey = wallet_skey.clone().into();
-                info!("Recovered a wallet key: pkey={}", wallet_pkey.to_hex());
+                info!(
+                    "Recovered a wallet key: address={}",
+                    wallet_pkey.to_address()
+                );
                 (wallet_skey, wallet_pkey)
             } else {
                 debug!("Generating a new wallet key pair...");
                 let (wallet_skey, wallet_pkey) = curve1174::make_random_keys();
                 info!(
-                    "Generated a new wallet key pair: pkey={}",
-                    wallet_pkey.to_hex()
+                    "Generated a new wallet key pair: address={}",
+                    wallet_pkey.to_address()
                 );
                 (wallet_skey, wallet_pkey)
             };
@@ -164,13 +170,62 @@ impl KeyChain {
         Ok(keychain)
     }
 
+    /// Opens another wallet key pair for the same node.
+    /// Only wallet keys and the spend policy are taken from `cfg`, the network keys are shared.
+    /// A new key pair is generated if the wallet files don't exist.
+    pub fn open_wallet(&self, cfg: KeyChainConfig, password: &str) -> Result<Self, KeyError> {
+        let wallet_skey_path = Path::new(&cfg.wallet_skey_file);
+        let wallet_pkey_path = Path::new(&cfg.wallet_pkey_file);
+        let (wallet_skey, wallet_pkey) = if !wallet_skey_path.exists() && !wallet_pkey_path.exists()
+        {
+            debug!("Generating a new wallet key pair...");
+            let (wallet_skey, wallet_pkey) = curve1174::make_random_keys();
+            write_wallet_pkey(wallet_pkey_path, &wallet_pkey)?;
+            write_wallet_skey(wallet_skey_path, &wallet_skey, password)?;
+            info!(
+                "Generated a new wallet key pair: address={}, wallet_skey_file={}, wallet_pkey_file={}",
+                wallet_pkey.to_address(),
+                cfg.wallet_skey_file,
+                cfg.wallet_pkey_file
+            );
+            (wallet_skey, wallet_pkey)
+        } else {
+            let wallet_pkey = load_wallet_pkey(wallet_pkey_path)?;
+            let wallet_skey = load_wallet_skey(wallet_skey_path, password)?;
+            if let Err(_e) = curve1174::check_keying(&wallet_skey, &wallet_pkey) {
+                return Err(KeyError::InvalidKeying(
+                    cfg.wallet_skey_file,
+                    cfg.wallet_pkey_file,
+                ));
+            }
+            info!("Loaded wallet key pair: pkey={}", wallet_pkey);
+            (wallet_skey, wallet_pkey)
+        };
+        let cfg = KeyChainConfig {
+            network_skey_file: self.cfg.network_skey_file.clone(),
+            network_pkey_file: self.cfg.network_pkey_file.clone(),
+            ..cfg
+        };
+        Ok(KeyChain {
+            cfg,
+            wallet_skey,
+            wallet_pkey,
+            network_skey: self.network_skey.clone(),
+            network_pkey: self.network_pkey.clone(),
+        })
+    }
+
     /// Temporary KeyChain for tests.
     pub fn new_mem() -> Self {
         let (wallet_skey, wallet_pkey) = curve1174::make_random_keys();
         let (network_skey, network_pkey) = pbc::make_random_keys();
 
+        let cfg = KeyChainConfig {
+            policy_file: String::new(),
+            ..Default::default()
+        };
         let keychain = KeyChain {
-            cfg: Default::default(),
+            cfg,
             wallet_skey,
             wallet_pkey,
             network_skey,
@@ -188,6 +243,75 @@ impl KeyChain {
         Ok(wallet_skey_to_recovery(&wallet_skey))
     }
 
+    /// Re-encrypt secret keys on the disk with a new password.
+    pub fn change_password(&self, old_password: &str, new_password: &str) -> Result<(), KeyError> {
+        let keys = [
+            (
+                Path::new(&self.cfg.wallet_skey_file),
+                WALLET_ENCRYPTED_SKEY_TAG,
+            ),
+            (
+                Path::new(&self.cfg.network_skey_file),
+                NETWORK_ENCRYPTED_SKEY_TAG,
+            ),
+        ];
+        change_password(&keys, old_password, new_password)?;
+        info!(
+            "Changed password: wallet_skey_file={}, network_skey_file={}",
+            self.cfg.wallet_skey_file, self.cfg.network_skey_file
+        );
+        Ok(())
+    }
+
+    /// Ask for the current and a new password and re-encrypt secret keys.
+    pub fn change_password_from_stdin(&self) -> Result<(), KeyError> {
+        let (old_password, new_password) = read_password_change_from_stdin()?;
+        self.change_password(&old_password, &new_password)
+    }
+
+    /// Emergency export of the wallet secret key, encrypted to a recovery public key.
+    pub fn export_wallet_skey(
+        &self,
+        recovery_pkey: &curve1174::PublicKey,
+    ) -> Result<String, KeyError> {
+        let pem = export_wallet_skey(&self.wallet_skey, recovery_pkey)?;
+        info!(
+            "Exported wallet secret key: wallet_pkey={}, recovery_pkey={}",
+            self.wallet_pkey, recovery_pkey
+        );
+        Ok(pem)
+    }
+
+    /// Decrypt a wallet secret key exported by export_wallet_skey().
+    pub fn import_wallet_skey(
+        pem: &str,
+        recovery_skey: &curve1174::SecretKey,
+    ) -> Result<curve1174::SecretKey, KeyError> {
+        import_wallet_skey(pem, recovery_skey)
+    }
+
+    /// Load spend policy definitions.
+    pub fn spend_policy(&self) -> Result<Vec<PolicyRule>, KeyError> {
+        if self.cfg.policy_file.is_empty() {
+            return Ok(Vec::new());
+        }
+        load_policy(Path::new(&self.cfg.policy_file))
+    }
+
+    /// Replace spend policy definitions.
+    pub fn set_spend_policy(&self, rules: &[PolicyRule]) -> Result<(), KeyError> {
+        if self.cfg.policy_file.is_empty() {
+            return Ok(());
+        }
+        write_policy(Path::new(&self.cfg.policy_file), rules)?;
+        info!(
+            "Updated spend policy: policy_file={}, rules={}",
+            self.cfg.policy_file,
+            rules.len()
+        );
+        Ok(())
+    }
+
     /// Generate new secp256k1 keypair using KeyChain as seed.
     pub fn generate_secp256k1_keypair(
         &self,
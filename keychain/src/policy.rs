@@ -0,0 +1,115 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Spend policy definitions stored in the keystore.
+
+use crate::error::KeyError;
+use crate::pem;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use stegos_crypto::curve1174;
+
+/// PEM tag for spend policy definitions.
+const SPEND_POLICY_TAG: &'static str = "STEGOS WALLET SPEND POLICY";
+
+/// A rule which every spend of the wallet must satisfy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "rule")]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    /// Limit the total amount spent per UTC day, including fees.
+    DailyLimit { amount: i64 },
+    /// Only allow payments to the listed recipients.
+    AllowList {
+        recipients: Vec<curve1174::PublicKey>,
+    },
+    /// Spends above the threshold must be approved by the second key.
+    SecondApprover {
+        threshold: i64,
+        approver: curve1174::PublicKey,
+    },
+}
+
+/// Load policy definitions from the file.
+/// A missing file means that no rules are defined.
+pub(crate) fn load_policy(path: &Path) -> Result<Vec<PolicyRule>, KeyError> {
+    let name = path.to_string_lossy().to_string();
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(KeyError::InputOutputError(name, e)),
+    };
+    let pem = pem::parse(&contents).map_err(|e| KeyError::ParseError(name.clone(), e))?;
+    if pem.tag != SPEND_POLICY_TAG {
+        return Err(KeyError::InvalidTag(
+            name,
+            SPEND_POLICY_TAG.to_string(),
+            pem.tag,
+        ));
+    }
+    serde_json::from_slice(&pem.contents).map_err(|e| KeyError::InvalidPayload(name, e.into()))
+}
+
+/// Write policy definitions to the file.
+pub(crate) fn write_policy(path: &Path, rules: &[PolicyRule]) -> Result<(), KeyError> {
+    let pem = pem::Pem {
+        tag: SPEND_POLICY_TAG.to_string(),
+        contents: serde_json::to_vec(rules).expect("serializable"),
+    };
+    fs::write(path, pem::encode(&pem))
+        .map_err(|e| KeyError::InputOutputError(path.to_string_lossy().to_string(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn load_write() {
+        let path = env::temp_dir().join(format!(
+            "stegos-policy-{}",
+            stegos_crypto::hash::Hash::digest(&"test")
+        ));
+        let _ = fs::remove_file(&path);
+        assert!(load_policy(&path).unwrap().is_empty());
+
+        let (_skey, pkey) = curve1174::make_random_keys();
+        let rules = vec![
+            PolicyRule::DailyLimit { amount: 1000 },
+            PolicyRule::AllowList {
+                recipients: vec![pkey],
+            },
+            PolicyRule::SecondApprover {
+                threshold: 100,
+                approver: pkey,
+            },
+        ];
+        write_policy(&path, &rules).unwrap();
+        assert_eq!(load_policy(&path).unwrap(), rules);
+
+        fs::write(&path, "garbage").unwrap();
+        load_policy(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,339 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Operator-controlled lists of allowed and denied peers.
+//!
+//! Entries are network public keys in HEX, PeerIds or IP ranges in CIDR notation.
+//! Denied entries are always refused. Allowed entries restrict only identifiers
+//! of the same kind, e.g. allowed IP ranges don't affect public keys.
+//!
+
+use crate::config::NetworkConfig;
+use crate::kad::AccessChecker;
+use crate::metrics::REJECTED_CONNECTIONS;
+use failure::{format_err, Error};
+use ipnetwork::IpNetwork;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use log::*;
+use std::collections::HashSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use stegos_crypto::pbc;
+
+/// An entry of the access list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AccessEntry {
+    Node(pbc::PublicKey),
+    Peer(PeerId),
+    Network(IpNetwork),
+}
+
+impl AccessEntry {
+    fn parse(entry: &str) -> Result<Self, Error> {
+        if let Ok(network) = entry.parse::<IpNetwork>() {
+            return Ok(AccessEntry::Network(network));
+        }
+        if let Ok(node_id) = pbc::PublicKey::try_from_hex(entry) {
+            return Ok(AccessEntry::Node(node_id));
+        }
+        if let Ok(peer_id) = entry.parse::<PeerId>() {
+            return Ok(AccessEntry::Peer(peer_id));
+        }
+        Err(format_err!("Invalid access list entry: {}", entry))
+    }
+}
+
+/// Entries of one list, grouped by kind.
+#[derive(Debug, Clone, Default)]
+struct Entries {
+    nodes: HashSet<pbc::PublicKey>,
+    peers: HashSet<PeerId>,
+    networks: Vec<IpNetwork>,
+}
+
+impl Entries {
+    fn parse(entries: &[String]) -> Result<Self, Error> {
+        let mut result = Entries::default();
+        for entry in entries {
+            match AccessEntry::parse(entry.trim())? {
+                AccessEntry::Node(node_id) => {
+                    result.nodes.insert(node_id);
+                }
+                AccessEntry::Peer(peer_id) => {
+                    result.peers.insert(peer_id);
+                }
+                AccessEntry::Network(network) => result.networks.push(network),
+            }
+        }
+        Ok(result)
+    }
+
+    fn has_network(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// Allowed and denied nodes, peers and IP ranges.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    allow: Entries,
+    deny: Entries,
+}
+
+impl AccessList {
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self, Error> {
+        let allow = Entries::parse(allow)?;
+        let deny = Entries::parse(deny)?;
+        Ok(AccessList { allow, deny })
+    }
+
+    /// Returns true if the node with the network public key is allowed.
+    pub fn is_node_allowed(&self, node_id: &pbc::PublicKey) -> bool {
+        !self.deny.nodes.contains(node_id)
+            && (self.allow.nodes.is_empty() || self.allow.nodes.contains(node_id))
+    }
+
+    /// Returns true if the peer is allowed.
+    pub fn is_peer_allowed(&self, peer_id: &PeerId) -> bool {
+        !self.deny.peers.contains(peer_id)
+            && (self.allow.peers.is_empty() || self.allow.peers.contains(peer_id))
+    }
+
+    /// Returns true if the address is allowed.
+    /// Addresses without an IP part, e.g. DNS names, are always allowed.
+    pub fn is_address_allowed(&self, address: &Multiaddr) -> bool {
+        let ip = match address_ip(address) {
+            Some(ip) => ip,
+            None => return true,
+        };
+        !self.deny.has_network(ip) && (self.allow.networks.is_empty() || self.allow.has_network(ip))
+    }
+
+    /// Returns true if the connection with the peer is allowed.
+    /// Counts the rejected attempt otherwise.
+    pub fn check_peer(&self, peer_id: &PeerId) -> bool {
+        if !self.is_peer_allowed(peer_id) {
+            REJECTED_CONNECTIONS.with_label_values(&["peer"]).inc();
+            return false;
+        }
+        true
+    }
+
+    /// Returns true if the connection with the address is allowed.
+    /// Counts the rejected attempt otherwise.
+    pub fn check_address(&self, address: &Multiaddr) -> bool {
+        if !self.is_address_allowed(address) {
+            REJECTED_CONNECTIONS.with_label_values(&["address"]).inc();
+            return false;
+        }
+        true
+    }
+
+    /// Returns true if the connection with the node is allowed.
+    /// Counts the rejected attempt otherwise.
+    pub fn check_node(&self, node_id: &pbc::PublicKey) -> bool {
+        if !self.is_node_allowed(node_id) {
+            REJECTED_CONNECTIONS.with_label_values(&["node"]).inc();
+            return false;
+        }
+        true
+    }
+}
+
+impl AccessChecker for AccessList {
+    fn is_allowed(&self, id: &pbc::PublicKey) -> bool {
+        self.is_node_allowed(id)
+    }
+}
+
+/// Returns the IP part of the address.
+fn address_ip(address: &Multiaddr) -> Option<IpAddr> {
+    address
+        .iter()
+        .filter_map(|protocol| match protocol {
+            Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+            Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        })
+        .next()
+}
+
+/// Builds the access list from `NetworkConfig::allow`, `NetworkConfig::deny`
+/// and the entries of `NetworkConfig::access_list_file`.
+pub struct AccessListLoader {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    /// Path to the file, None if disabled.
+    path: Option<PathBuf>,
+    /// Modification time of the file at the last load.
+    modified: Option<SystemTime>,
+}
+
+impl AccessListLoader {
+    pub fn new(config: &NetworkConfig) -> Self {
+        let path = if config.access_list_file.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(&config.access_list_file))
+        };
+        AccessListLoader {
+            allow: config.allow.clone(),
+            deny: config.deny.clone(),
+            path,
+            modified: None,
+        }
+    }
+
+    /// Loads the access list, a missing file is treated as an empty one.
+    ///
+    /// Each line of the file is either `allow ENTRY` or `deny ENTRY`.
+    pub fn load(&mut self) -> Result<AccessList, Error> {
+        let mut allow = self.allow.clone();
+        let mut deny = self.deny.clone();
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None => return AccessList::new(&allow, &deny),
+        };
+        self.modified = modified(&path);
+        if !path.exists() {
+            return AccessList::new(&allow, &deny);
+        }
+        let contents = fs::read_to_string(&path)?;
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("allow"), Some(entry), None) => allow.push(entry.to_string()),
+                (Some("deny"), Some(entry), None) => deny.push(entry.to_string()),
+                _ => {
+                    return Err(format_err!(
+                        "Invalid access list: path={}, line={}",
+                        path.display(),
+                        n + 1
+                    ));
+                }
+            }
+        }
+        let access = AccessList::new(&allow, &deny)?;
+        info!(target: "stegos_network::access", "Loaded access list: path={}, allow={}, deny={}", path.display(), allow.len(), deny.len());
+        Ok(access)
+    }
+
+    /// Loads the access list again if the file has been changed since the last load.
+    pub fn reload(&mut self) -> Result<Option<AccessList>, Error> {
+        match &self.path {
+            Some(path) if modified(path) != self.modified => self.load().map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Returns the modification time of the file, None if the file is missing.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allow_and_deny() {
+        let (_skey, node_id) = pbc::make_random_keys();
+        let (_skey, other_node_id) = pbc::make_random_keys();
+        let peer_id = PeerId::random();
+        let other_peer_id = PeerId::random();
+        let local: Multiaddr = "/ip4/10.1.2.3/tcp/10055".parse().unwrap();
+        let remote: Multiaddr = "/ip4/1.2.3.4/tcp/10055".parse().unwrap();
+        let dns: Multiaddr = "/dns4/localhost/tcp/10055".parse().unwrap();
+
+        let access = AccessList::default();
+        assert!(access.is_node_allowed(&node_id));
+        assert!(access.is_peer_allowed(&peer_id));
+        assert!(access.is_address_allowed(&remote));
+
+        let deny = vec![
+            node_id.to_hex(),
+            peer_id.to_base58(),
+            "1.2.3.0/24".to_string(),
+        ];
+        let access = AccessList::new(&[], &deny).unwrap();
+        assert!(!access.is_node_allowed(&node_id));
+        assert!(access.is_node_allowed(&other_node_id));
+        assert!(!access.is_peer_allowed(&peer_id));
+        assert!(access.is_peer_allowed(&other_peer_id));
+        assert!(!access.is_address_allowed(&remote));
+        assert!(access.is_address_allowed(&local));
+        assert!(access.is_address_allowed(&dns));
+        assert!(!access.check_address(&remote));
+        assert!(access.check_peer(&other_peer_id));
+        assert!(!access.check_node(&node_id));
+
+        // Allowed ranges don't restrict other kinds of entries.
+        let allow = vec!["10.0.0.0/8".to_string()];
+        let access = AccessList::new(&allow, &["10.1.2.3".to_string()]).unwrap();
+        assert!(!access.is_address_allowed(&remote));
+        assert!(!access.is_address_allowed(&local));
+        assert!(access.is_address_allowed(&"/ip4/10.1.2.4/tcp/1".parse().unwrap()));
+        assert!(access.is_node_allowed(&node_id));
+        assert!(access.is_peer_allowed(&peer_id));
+
+        assert!(AccessList::new(&["garbage".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempdir::TempDir::new("access_list").unwrap();
+        let path = dir.path().join("access");
+        let (_skey, node_id) = pbc::make_random_keys();
+        let mut config = NetworkConfig::default();
+        config.deny = vec!["192.168.0.0/16".to_string()];
+        config.access_list_file = path.to_string_lossy().to_string();
+
+        let mut loader = AccessListLoader::new(&config);
+        let access = loader.load().unwrap();
+        assert!(access.is_node_allowed(&node_id));
+        assert!(loader.reload().unwrap().is_none());
+
+        fs::write(&path, format!("# banned\ndeny {}\n", node_id.to_hex())).unwrap();
+        let access = loader.reload().unwrap().expect("changed");
+        assert!(!access.is_node_allowed(&node_id));
+        assert!(!access.is_address_allowed(&"/ip4/192.168.1.1/tcp/1".parse().unwrap()));
+
+        fs::remove_file(&path).unwrap();
+        let access = loader.reload().unwrap().expect("changed");
+        assert!(access.is_node_allowed(&node_id));
+
+        fs::write(&path, "ban everyone\n").unwrap();
+        assert!(loader.reload().is_err());
+    }
+}
@@ -36,6 +36,8 @@ pub struct NetworkConfig {
     pub advertised_addresses: Vec<String>,
     /// Advertise local active, non-loopback addresses
     pub advertise_local_ips: bool,
+    /// Map the listen port on the gateway and advertise the external address: none, upnp, natpmp or any
+    pub port_mapping: String,
     /// DNS name of pool of seed nodes
     pub seed_pool: String,
     /// List of nodes to connect to on startup.
@@ -44,12 +46,58 @@ pub struct NetworkConfig {
     pub min_connections: usize,
     /// Maximum active connections (Don't try to open more than max_connections connections)
     pub max_connections: usize,
+    /// Keep connections to so many peers with the largest stakes, validators first, zero disables
+    pub preferred_peers: usize,
+    /// Connections to unstaked peers never dropped in favour of staked ones
+    pub min_random_peers: usize,
     /// Connection monitoring tick interval (secs)
     pub monitoring_interval: u64,
     /// HashCash complixity (nuber of 0-bits to calculate)
     pub hashcash_nbits: usize,
     /// Network readiness threshold (number of HashCash-enabled established connections)
     pub readiness_threshold: usize,
+    /// Negotiate snappy compression for gossip, DHT and delivery messages
+    pub compression: bool,
+    /// Time window to spread DHT bootstrap queries over (secs)
+    pub bootstrap_window: u64,
+    /// Path to the persistent store of known peers, empty to disable
+    pub peer_store_file: String,
+    /// Keep only nodes of staked validators in the routing table
+    pub require_stake: bool,
+    /// The number of recent DHT queries kept for diagnostics, zero disables
+    pub query_journal_size: usize,
+    /// Path to the file to append finished DHT queries to, empty to disable
+    pub query_journal_file: String,
+    /// Name of the chain, peers from other chains are rejected during handshake
+    pub chain_id: String,
+    /// Bytes of outbound messages handed over to peers before yielding to other tasks
+    pub outbound_round_size: usize,
+    /// Share of outbound bandwidth for consensus messages under congestion
+    pub consensus_share: u32,
+    /// Share of outbound bandwidth for transaction gossip under congestion
+    pub transactions_share: u32,
+    /// Share of outbound bandwidth for block sync under congestion
+    pub block_sync_share: u32,
+    /// Share of outbound bandwidth for DHT maintenance under congestion
+    pub maintenance_share: u32,
+    /// Nodes, peers and IP ranges to refuse: network public keys in HEX, PeerIds or CIDR ranges
+    pub deny: Vec<String>,
+    /// If not empty, only these nodes, peers and IP ranges are accepted, per kind of entry
+    pub allow: Vec<String>,
+    /// Path to the file with more `allow ENTRY` and `deny ENTRY` lines, re-read on change; empty to disable
+    pub access_list_file: String,
+}
+
+impl NetworkConfig {
+    /// Bandwidth shares in the order of `Lane::ALL`.
+    pub fn lane_shares(&self) -> [u32; 4] {
+        [
+            self.consensus_share,
+            self.transactions_share,
+            self.block_sync_share,
+            self.maintenance_share,
+        ]
+    }
 }
 
 /// Default values for network configuration.
@@ -61,12 +109,30 @@ impl Default for NetworkConfig {
             seed_nodes: vec![],
             advertised_addresses: vec![],
             advertise_local_ips: false,
+            port_mapping: "none".to_string(),
             bind_ip: "0.0.0.0".to_string(),
             min_connections: 8,
             max_connections: 32,
+            preferred_peers: 16,
+            min_random_peers: 4,
             monitoring_interval: 60,
             hashcash_nbits: 24,
             readiness_threshold: 2,
+            compression: true,
+            bootstrap_window: 30,
+            peer_store_file: "network.peers".to_string(),
+            require_stake: false,
+            query_journal_size: 100,
+            query_journal_file: "".to_string(),
+            chain_id: "testnet".to_string(),
+            outbound_round_size: 256 * 1024,
+            consensus_share: 50,
+            transactions_share: 25,
+            block_sync_share: 20,
+            maintenance_share: 5,
+            deny: vec![],
+            allow: vec![],
+            access_list_file: "".to_string(),
         }
     }
 }
@@ -58,18 +58,22 @@ pub struct Delivery<TSubstream> {
     // Sending queue
     send_queue: HashMap<PeerId, SmallVec<[DeliveryMessage; 16]>>,
 
+    /// Offer snappy compression to peers.
+    compression: bool,
+
     /// Marker to pin the generics.
     marker: PhantomData<TSubstream>,
 }
 
 impl<TSubstream> Delivery<TSubstream> {
     /// Creates a `Delivery`.
-    pub fn new() -> Self {
+    pub fn new(compression: bool) -> Self {
         Delivery {
             events: VecDeque::new(),
             connected_peers: HashSet::new(),
             dial_queue: ExpiringQueue::new(DIAL_TIMEOUT),
             send_queue: HashMap::new(),
+            compression,
             marker: PhantomData,
         }
     }
@@ -108,7 +112,7 @@ where
     type OutEvent = DeliveryEvent;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        DeliveryHandler::new()
+        DeliveryHandler::new(self.compression)
     }
 
     fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
@@ -102,9 +102,9 @@ where
     TSubstream: AsyncRead + AsyncWrite,
 {
     /// Builds a new `DeliveryHandler`.
-    pub fn new() -> Self {
+    pub fn new(compression: bool) -> Self {
         DeliveryHandler {
-            config: DeliveryConfig::new(),
+            config: DeliveryConfig::new(compression),
             substreams: Vec::new(),
             keep_alive: KeepAlive::Yes,
             send_queue: SmallVec::new(),
@@ -21,13 +21,14 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use bytes::{BufMut, BytesMut};
+use crate::utils::{compression, offered_protocols, ProtocolId, ProtocolVersion};
+use bytes::{BufMut, Bytes, BytesMut};
 use futures::future;
 use libp2p::core::{upgrade::Negotiated, InboundUpgrade, OutboundUpgrade, UpgradeInfo};
 use protobuf::Message;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::{io, iter};
+use std::{io, vec};
 use stegos_crypto::pbc;
 use tokio::codec::{Decoder, Encoder, Framed};
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -35,25 +36,47 @@ use unsigned_varint::codec;
 
 use super::proto::delivery_proto;
 
-/// Implementation of `ConnectionUpgrade` for the floodsub protocol.
+/// The first version of the delivery protocol.
+pub const DELIVERY_VERSION_1: ProtocolVersion = ProtocolVersion(1);
+/// Supported versions of the delivery protocol.
+const PROTOCOLS: &[ProtocolId] = &[
+    ProtocolId {
+        name: b"/stegos/delivery/1.0.0/snappy",
+        version: DELIVERY_VERSION_1,
+        compression: true,
+    },
+    ProtocolId {
+        name: b"/stegos/delivery/1.0.0",
+        version: DELIVERY_VERSION_1,
+        compression: false,
+    },
+];
+/// Maximal size of a message, the default limit of `UviBytes`.
+/// See `compression::frame_len()`.
+const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
+/// Implementation of `ConnectionUpgrade` for the delivery protocol.
 #[derive(Debug, Clone)]
-pub struct DeliveryConfig {}
+pub struct DeliveryConfig {
+    /// Offer snappy compression during protocol negotiation.
+    compression: bool,
+}
 
 impl DeliveryConfig {
     /// Builds a new `DeliveryConfig`.
     #[inline]
-    pub fn new() -> DeliveryConfig {
-        DeliveryConfig {}
+    pub fn new(compression: bool) -> DeliveryConfig {
+        DeliveryConfig { compression }
     }
 }
 
 impl UpgradeInfo for DeliveryConfig {
-    type Info = &'static [u8];
-    type InfoIter = iter::Once<Self::Info>;
+    type Info = ProtocolId;
+    type InfoIter = vec::IntoIter<Self::Info>;
 
     #[inline]
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(b"/stegos/delivery/1.0.0")
+        offered_protocols(PROTOCOLS, self.compression).into_iter()
     }
 }
 
@@ -66,13 +89,8 @@ where
     type Future = future::FutureResult<Self::Output, Self::Error>;
 
     #[inline]
-    fn upgrade_inbound(self, socket: Negotiated<TSocket>, _: Self::Info) -> Self::Future {
-        future::ok(Framed::new(
-            socket,
-            DeliveryCodec {
-                length_prefix: Default::default(),
-            },
-        ))
+    fn upgrade_inbound(self, socket: Negotiated<TSocket>, info: Self::Info) -> Self::Future {
+        future::ok(Framed::new(socket, DeliveryCodec::new(info)))
     }
 }
 
@@ -85,13 +103,8 @@ where
     type Future = future::FutureResult<Self::Output, Self::Error>;
 
     #[inline]
-    fn upgrade_outbound(self, socket: Negotiated<TSocket>, _: Self::Info) -> Self::Future {
-        future::ok(Framed::new(
-            socket,
-            DeliveryCodec {
-                length_prefix: Default::default(),
-            },
-        ))
+    fn upgrade_outbound(self, socket: Negotiated<TSocket>, info: Self::Info) -> Self::Future {
+        future::ok(Framed::new(socket, DeliveryCodec::new(info)))
     }
 }
 
@@ -99,6 +112,20 @@ where
 pub struct DeliveryCodec {
     /// The codec for encoding/decoding the length prefix of messages.
     length_prefix: codec::UviBytes,
+    /// True if frames are compressed with snappy.
+    compression: bool,
+}
+
+impl DeliveryCodec {
+    /// Creates a codec for the negotiated protocol.
+    fn new(protocol: ProtocolId) -> DeliveryCodec {
+        let mut length_prefix = codec::UviBytes::default();
+        length_prefix.set_max_len(compression::frame_len(MAX_FRAME_SIZE, protocol.compression));
+        DeliveryCodec {
+            length_prefix,
+            compression: protocol.compression,
+        }
+    }
 }
 
 impl Encoder for DeliveryCodec {
@@ -131,6 +158,14 @@ impl Encoder for DeliveryCodec {
             }
         };
 
+        if self.compression {
+            let data = proto
+                .write_to_bytes()
+                .expect("there is no situation in which the protobuf message can be invalid");
+            let data = compression::compress("delivery", &data)?;
+            return self.length_prefix.encode(Bytes::from(data), dst);
+        }
+
         let msg_size = proto.compute_size();
         // Reserve enough space for the data and the length. The length has a maximum of 32 bits,
         // which means that 5 bytes is enough for the variable-length integer.
@@ -155,7 +190,12 @@ impl Decoder for DeliveryCodec {
             Some(p) => p,
             None => return Ok(None),
         };
-        let message: delivery_proto::Message = protobuf::parse_from_bytes(&packet)?;
+        let message: delivery_proto::Message = if self.compression {
+            let packet = compression::decompress("delivery", &packet, MAX_FRAME_SIZE)?;
+            protobuf::parse_from_bytes(&packet)?
+        } else {
+            protobuf::parse_from_bytes(&packet)?
+        };
 
         let seq_no = message.get_seqno().to_vec();
 
@@ -246,7 +286,8 @@ impl Broadcast {
 
 #[cfg(test)]
 mod tests {
-    use super::{Broadcast, DeliveryCodec, DeliveryMessage, Unicast};
+    use super::{Broadcast, DeliveryCodec, DeliveryMessage, Unicast, PROTOCOLS};
+    use crate::utils::ProtocolId;
     use futures::{future, Future, Sink, Stream};
     use rand;
     use stegos_crypto::pbc;
@@ -284,6 +325,12 @@ mod tests {
     }
 
     fn test_one(msg: DeliveryMessage) {
+        for protocol in PROTOCOLS {
+            test_protocol(msg.clone(), *protocol);
+        }
+    }
+
+    fn test_protocol(msg: DeliveryMessage, protocol: ProtocolId) {
         let msg_server = msg.clone();
         let msg_client = msg.clone();
 
@@ -294,13 +341,8 @@ mod tests {
             .incoming()
             .into_future()
             .map_err(|(e, _)| e)
-            .and_then(|(c, _)| {
-                future::ok(Framed::new(
-                    c.unwrap(),
-                    DeliveryCodec {
-                        length_prefix: Default::default(),
-                    },
-                ))
+            .and_then(move |(c, _)| {
+                future::ok(Framed::new(c.unwrap(), DeliveryCodec::new(protocol)))
             })
             .and_then({
                 let msg_server = msg_server.clone();
@@ -313,14 +355,7 @@ mod tests {
             });
 
         let client = TcpStream::connect(&listener_addr)
-            .and_then(|c| {
-                future::ok(Framed::new(
-                    c,
-                    DeliveryCodec {
-                        length_prefix: Default::default(),
-                    },
-                ))
-            })
+            .and_then(move |c| future::ok(Framed::new(c, DeliveryCodec::new(protocol))))
             .and_then(|s| s.send(msg_client))
             .map(|_| ());
 
@@ -0,0 +1,322 @@
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::handler::{DirectHandler, DirectRecvEvent, DirectSendEvent};
+
+use crate::utils::ExpiringQueue;
+use futures::prelude::*;
+use libp2p::core::swarm::{
+    ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
+};
+use libp2p::core::{protocols_handler::ProtocolsHandler, Multiaddr, PeerId};
+use log::{debug, error};
+use smallvec::SmallVec;
+use std::{
+    collections::{hash_map::HashMap, hash_set::HashSet, VecDeque},
+    marker::PhantomData,
+    time::Duration,
+};
+use stegos_crypto::pbc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Timeout for resolving node's PeerId via Kademlia.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Timeout for connecting to peer.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Identifier of a message sent via `Direct`.
+pub type MessageId = u64;
+
+/// Messages waiting for a PeerId lookup or a dial.
+type PendingMessages = SmallVec<[(MessageId, Vec<u8>); 8]>;
+
+/// Network behaviour that delivers messages straight to the peer identified by
+/// network public key, without flooding or multi-hop routing.
+pub struct Direct<TSubstream> {
+    /// Events that need to be yielded to the outside when polling.
+    events: VecDeque<NetworkBehaviourAction<DirectSendEvent, DirectOutEvent>>,
+
+    /// List of peers the network is connected to
+    connected_peers: HashSet<PeerId>,
+
+    /// Resolved mapping node_id -> peer_id.
+    known_peers: HashMap<pbc::PublicKey, PeerId>,
+
+    /// Messages waiting for the PeerId lookup, keyed by recipient.
+    lookup_queue: ExpiringQueue<pbc::PublicKey, PendingMessages>,
+
+    /// Messages waiting for the connection, keyed by recipient's PeerId.
+    dial_queue: ExpiringQueue<PeerId, (pbc::PublicKey, PendingMessages)>,
+
+    /// Messages passed to connection handlers, but not yet confirmed.
+    in_flight: HashMap<MessageId, (PeerId, pbc::PublicKey)>,
+
+    /// Identifier for the next message.
+    next_message_id: MessageId,
+
+    /// Marker to pin the generics.
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> Direct<TSubstream> {
+    /// Creates a `Direct`.
+    pub fn new() -> Self {
+        Direct {
+            events: VecDeque::new(),
+            connected_peers: HashSet::new(),
+            known_peers: HashMap::new(),
+            lookup_queue: ExpiringQueue::new(LOOKUP_TIMEOUT),
+            dial_queue: ExpiringQueue::new(DIAL_TIMEOUT),
+            in_flight: HashMap::new(),
+            next_message_id: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<TSubstream> Direct<TSubstream> {
+    ///
+    /// Sends a message to the node.
+    ///
+    /// `peer_id` is the known PeerId of the node, if any. Otherwise a `Lookup` event is emitted
+    /// and the message is queued until `resolved()` is called for the node.
+    /// The outcome is reported by `Delivered` or `Failed` events with the returned id.
+    ///
+    pub fn send(
+        &mut self,
+        to: pbc::PublicKey,
+        peer_id: Option<PeerId>,
+        payload: Vec<u8>,
+    ) -> MessageId {
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+
+        if let Some(peer_id) = peer_id {
+            self.known_peers.insert(to, peer_id);
+        }
+        let peer_id = match self.known_peers.get(&to) {
+            Some(peer_id) => peer_id.clone(),
+            None => {
+                debug!(target: "stegos_network::direct", "looking up node: node_id={}, id={}", to, id);
+                if let Some(queue) = self.lookup_queue.get_mut(&to) {
+                    queue.push((id, payload));
+                    return id;
+                }
+                let mut queue = PendingMessages::new();
+                queue.push((id, payload));
+                self.lookup_queue.insert(to, queue);
+                self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    DirectOutEvent::Lookup { node_id: to },
+                ));
+                return id;
+            }
+        };
+        self.send_to_peer(to, peer_id, id, payload);
+        id
+    }
+
+    ///
+    /// Reports the result of a PeerId lookup.
+    ///
+    pub fn resolved(&mut self, node_id: pbc::PublicKey, peer_id: PeerId) {
+        if let Some(queue) = self.lookup_queue.remove(&node_id) {
+            self.known_peers.insert(node_id, peer_id.clone());
+            debug!(target: "stegos_network::direct", "node resolved: node_id={}, peer_id={}, queue_len={}", node_id, peer_id, queue.len());
+            for (id, payload) in queue {
+                self.send_to_peer(node_id, peer_id.clone(), id, payload);
+            }
+        }
+    }
+
+    fn send_to_peer(
+        &mut self,
+        to: pbc::PublicKey,
+        peer_id: PeerId,
+        id: MessageId,
+        payload: Vec<u8>,
+    ) {
+        if self.connected_peers.contains(&peer_id) {
+            debug!(target: "stegos_network::direct", "sending message to connected peer: peer_id={}, id={}", peer_id, id);
+            self.in_flight.insert(id, (peer_id.clone(), to));
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                event: DirectSendEvent::Send { id, payload },
+            });
+            return;
+        }
+
+        if let Some((_, queue)) = self.dial_queue.get_mut(&peer_id) {
+            queue.push((id, payload));
+            return;
+        }
+        debug!(target: "stegos_network::direct", "dialing peer: peer_id={}, id={}", peer_id, id);
+        let mut queue = PendingMessages::new();
+        queue.push((id, payload));
+        self.dial_queue.insert(peer_id.clone(), (to, queue));
+        self.events
+            .push_back(NetworkBehaviourAction::DialPeer { peer_id });
+    }
+
+    fn fail(&mut self, to: pbc::PublicKey, id: MessageId) {
+        self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+            DirectOutEvent::Failed { id, to },
+        ));
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for Direct<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ProtocolsHandler = DirectHandler<TSubstream>;
+    type OutEvent = DirectOutEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        DirectHandler::new()
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, id: PeerId, _: ConnectedPoint) {
+        debug!(target: "stegos_network::direct", "peer connected: peer_id={}", id);
+        self.connected_peers.insert(id.clone());
+        if let Some((to, queue)) = self.dial_queue.remove(&id) {
+            debug!(target: "stegos_network::direct", "sending queued messages: peer_id={}, queue_len={}", id, queue.len());
+            for (message_id, payload) in queue {
+                self.send_to_peer(to, id.clone(), message_id, payload);
+            }
+        }
+    }
+
+    fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
+        self.connected_peers.remove(id);
+        let failed: Vec<(MessageId, pbc::PublicKey)> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (peer_id, _))| peer_id == id)
+            .map(|(message_id, (_, to))| (*message_id, *to))
+            .collect();
+        for (message_id, to) in failed {
+            self.in_flight.remove(&message_id);
+            self.fail(to, message_id);
+        }
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        if let Some((to, queue)) = self.dial_queue.remove(peer_id) {
+            debug!(target: "stegos_network::direct", "failed to dial peer: peer_id={}, queue_len={}", peer_id, queue.len());
+            // The node might have changed its address.
+            self.known_peers.remove(&to);
+            for (message_id, _payload) in queue {
+                self.fail(to, message_id);
+            }
+        }
+    }
+
+    fn inject_node_event(&mut self, peer_id: PeerId, event: DirectRecvEvent) {
+        let event = match event {
+            DirectRecvEvent::Message(payload) => {
+                debug!(target: "stegos_network::direct", "received message: peer_id={}, size={}", peer_id, payload.len());
+                DirectOutEvent::Message { payload }
+            }
+            DirectRecvEvent::Delivered(id) => match self.in_flight.remove(&id) {
+                Some((_, to)) => DirectOutEvent::Delivered { id, to },
+                None => return,
+            },
+            DirectRecvEvent::Failed(id) => match self.in_flight.remove(&id) {
+                Some((_, to)) => DirectOutEvent::Failed { id, to },
+                None => return,
+            },
+        };
+        self.events
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut PollParameters,
+    ) -> Async<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        // Purge failed lookups.
+        loop {
+            match self.lookup_queue.poll() {
+                Ok(Async::Ready((to, Some(queue)))) => {
+                    debug!(target: "stegos_network::direct", "lookup timeout: node_id={}", to);
+                    for (id, _payload) in queue {
+                        self.fail(to, id);
+                    }
+                }
+                Ok(Async::Ready((_, None))) => {}
+                Ok(Async::NotReady) => break,
+                Err(e) => {
+                    error!(target: "stegos_network::direct", "lookup_queue timer error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        // Purge failed dialouts.
+        loop {
+            match self.dial_queue.poll() {
+                Ok(Async::Ready((peer_id, Some((to, queue))))) => {
+                    debug!(target: "stegos_network::direct", "dialout timeout: peer_id={}", peer_id);
+                    self.known_peers.remove(&to);
+                    for (id, _payload) in queue {
+                        self.fail(to, id);
+                    }
+                }
+                Ok(Async::Ready((_, None))) => {}
+                Ok(Async::NotReady) => break,
+                Err(e) => {
+                    error!(target: "stegos_network::direct", "dial_queue timer error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(event) = self.events.pop_front() {
+            return Async::Ready(event);
+        }
+
+        Async::NotReady
+    }
+}
+
+/// Event that can happen on the Direct behaviour.
+#[derive(Debug)]
+pub enum DirectOutEvent {
+    /// A message has been received.
+    Message { payload: Vec<u8> },
+    /// PeerId of the node is needed to deliver queued messages.
+    Lookup { node_id: pbc::PublicKey },
+    /// A message has been delivered to the recipient.
+    Delivered { id: MessageId, to: pbc::PublicKey },
+    /// A message could not be delivered to the recipient.
+    Failed { id: MessageId, to: pbc::PublicKey },
+}
@@ -0,0 +1,298 @@
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::behavior::MessageId;
+use super::protocol::{DirectCodec, DirectConfig};
+
+use futures::prelude::*;
+use libp2p::core::{
+    protocols_handler::{KeepAlive, ProtocolsHandlerUpgrErr, SubstreamProtocol},
+    upgrade::{InboundUpgrade, Negotiated, OutboundUpgrade},
+    ProtocolsHandler, ProtocolsHandlerEvent,
+};
+use log::{debug, trace};
+use smallvec::SmallVec;
+use std::collections::VecDeque;
+use std::{fmt, io, time::Instant};
+use tokio::codec::Framed;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::NETWORK_IDLE_TIMEOUT;
+
+/// Protocol handler that handles communication with the remote for the Direct protocol.
+///
+/// The handler will automatically open a substream with the remote for each message we send
+/// and report the outcome of the delivery.
+///
+/// It also handles messages sent by the remote.
+pub struct DirectHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    /// Configuration for the Direct protocol.
+    config: DirectConfig,
+
+    /// The active substreams.
+    substreams: Vec<SubstreamState<TSubstream>>,
+
+    // How long to keep connection open
+    keep_alive: KeepAlive,
+
+    /// Queue of values that we want to send to the remote.
+    send_queue: SmallVec<[(MessageId, Vec<u8>); 16]>,
+
+    /// Events to send upstream
+    out_events: VecDeque<DirectRecvEvent>,
+}
+
+/// State of an active substream, opened either by us or by the remote.
+enum SubstreamState<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    /// Waiting for a message from the remote.
+    WaitingInput(Framed<Negotiated<TSubstream>, DirectCodec>),
+    /// Waiting to send a message to the remote.
+    PendingSend(
+        Framed<Negotiated<TSubstream>, DirectCodec>,
+        MessageId,
+        Vec<u8>,
+    ),
+    /// Waiting to flush the substream so that the data arrives to the remote.
+    PendingFlush(Framed<Negotiated<TSubstream>, DirectCodec>, MessageId),
+    /// The substream is being closed.
+    Closing(Framed<Negotiated<TSubstream>, DirectCodec>),
+}
+
+impl<TSubstream> DirectHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    /// Builds a new `DirectHandler`.
+    pub fn new() -> Self {
+        DirectHandler {
+            config: DirectConfig::new(),
+            substreams: Vec::new(),
+            keep_alive: KeepAlive::Yes,
+            send_queue: SmallVec::new(),
+            out_events: VecDeque::new(),
+        }
+    }
+}
+
+impl<TSubstream> ProtocolsHandler for DirectHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type InEvent = DirectSendEvent;
+    type OutEvent = DirectRecvEvent;
+    type Error = io::Error;
+    type Substream = TSubstream;
+    type InboundProtocol = DirectConfig;
+    type OutboundProtocol = DirectConfig;
+    type OutboundOpenInfo = (MessageId, Vec<u8>);
+
+    #[inline]
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(self.config.clone())
+    }
+
+    fn inject_fully_negotiated_inbound(
+        &mut self,
+        protocol: <Self::InboundProtocol as InboundUpgrade<TSubstream>>::Output,
+    ) {
+        self.substreams.push(SubstreamState::WaitingInput(protocol))
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        protocol: <Self::OutboundProtocol as OutboundUpgrade<TSubstream>>::Output,
+        (id, payload): Self::OutboundOpenInfo,
+    ) {
+        self.substreams
+            .push(SubstreamState::PendingSend(protocol, id, payload))
+    }
+
+    #[inline]
+    fn inject_event(&mut self, event: Self::InEvent) {
+        match event {
+            DirectSendEvent::Send { id, payload } => {
+                self.send_queue.push((id, payload));
+            }
+        }
+    }
+
+    #[inline]
+    fn inject_dial_upgrade_error(
+        &mut self,
+        (id, _payload): Self::OutboundOpenInfo,
+        error: ProtocolsHandlerUpgrErr<
+            <Self::OutboundProtocol as OutboundUpgrade<Self::Substream>>::Error,
+        >,
+    ) {
+        debug!(target: "stegos_network::direct", "failed to open substream: id={}, error={}", id, error);
+        self.out_events.push_back(DirectRecvEvent::Failed(id));
+    }
+
+    #[inline]
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.keep_alive
+    }
+
+    fn poll(
+        &mut self,
+    ) -> Poll<
+        ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent>,
+        io::Error,
+    > {
+        if let Some(event) = self.out_events.pop_front() {
+            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(event)));
+        }
+
+        if !self.send_queue.is_empty() {
+            let message = self.send_queue.remove(0);
+            return Ok(Async::Ready(
+                ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                    info: message,
+                    protocol: SubstreamProtocol::new(self.config.clone()),
+                },
+            ));
+        }
+
+        for n in (0..self.substreams.len()).rev() {
+            let mut substream = self.substreams.swap_remove(n);
+            loop {
+                substream = match substream {
+                    SubstreamState::WaitingInput(mut substream) => match substream.poll() {
+                        Ok(Async::Ready(Some(payload))) => {
+                            self.substreams
+                                .push(SubstreamState::WaitingInput(substream));
+                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                                DirectRecvEvent::Message(payload),
+                            )));
+                        }
+                        Ok(Async::Ready(None)) => SubstreamState::Closing(substream),
+                        Ok(Async::NotReady) => {
+                            self.substreams
+                                .push(SubstreamState::WaitingInput(substream));
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => {
+                            debug!(target: "stegos_network::direct", "error reading from substream: error={}", e);
+                            SubstreamState::Closing(substream)
+                        }
+                    },
+                    SubstreamState::PendingSend(mut substream, id, payload) => {
+                        match substream.start_send(payload) {
+                            Ok(AsyncSink::Ready) => SubstreamState::PendingFlush(substream, id),
+                            Ok(AsyncSink::NotReady(payload)) => {
+                                self.substreams
+                                    .push(SubstreamState::PendingSend(substream, id, payload));
+                                return Ok(Async::NotReady);
+                            }
+                            Err(e) => {
+                                debug!(target: "stegos_network::direct", "error sending to substream: id={}, error={}", id, e);
+                                self.substreams.push(SubstreamState::Closing(substream));
+                                return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                                    DirectRecvEvent::Failed(id),
+                                )));
+                            }
+                        }
+                    }
+                    SubstreamState::PendingFlush(mut substream, id) => {
+                        match substream.poll_complete() {
+                            Ok(Async::Ready(())) => {
+                                self.substreams.push(SubstreamState::Closing(substream));
+                                return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                                    DirectRecvEvent::Delivered(id),
+                                )));
+                            }
+                            Ok(Async::NotReady) => {
+                                self.substreams
+                                    .push(SubstreamState::PendingFlush(substream, id));
+                                return Ok(Async::NotReady);
+                            }
+                            Err(e) => {
+                                debug!(target: "stegos_network::direct", "error flushing substream: id={}, error={}", id, e);
+                                self.substreams.push(SubstreamState::Closing(substream));
+                                return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                                    DirectRecvEvent::Failed(id),
+                                )));
+                            }
+                        }
+                    }
+                    SubstreamState::Closing(mut substream) => match substream.close() {
+                        Ok(Async::Ready(())) => {
+                            self.substreams.shrink_to_fit();
+                            break;
+                        }
+                        Ok(Async::NotReady) => {
+                            self.substreams.push(SubstreamState::Closing(substream));
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => {
+                            trace!(target: "stegos_network::direct", "failure closing substream: {}", e);
+                            break;
+                        }
+                    },
+                }
+            }
+        }
+
+        if self.substreams.is_empty() {
+            self.keep_alive = KeepAlive::Until(Instant::now() + NETWORK_IDLE_TIMEOUT);
+        } else {
+            self.keep_alive = KeepAlive::Yes;
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[derive(Debug)]
+pub enum DirectRecvEvent {
+    /// A message has been received from the remote.
+    Message(Vec<u8>),
+    /// A message has been flushed to the remote.
+    Delivered(MessageId),
+    /// A message could not be sent to the remote.
+    Failed(MessageId),
+}
+
+#[derive(Debug)]
+pub enum DirectSendEvent {
+    Send { id: MessageId, payload: Vec<u8> },
+}
+
+impl<TSubstream> fmt::Debug for DirectHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("DirectHandler")
+            .field("substreams", &self.substreams.len())
+            .field("send_queue", &self.send_queue.len())
+            .field("out queue", &self.out_events.len())
+            .finish()
+    }
+}
@@ -0,0 +1,160 @@
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use bytes::{Bytes, BytesMut};
+use futures::future;
+use libp2p::core::{upgrade::Negotiated, InboundUpgrade, OutboundUpgrade, UpgradeInfo};
+use std::{io, iter};
+use tokio::codec::{Decoder, Encoder, Framed};
+use tokio::io::{AsyncRead, AsyncWrite};
+use unsigned_varint::codec;
+
+/// Maximal size of a message.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Implementation of `ConnectionUpgrade` for the direct delivery protocol.
+#[derive(Debug, Clone)]
+pub struct DirectConfig {}
+
+impl DirectConfig {
+    /// Builds a new `DirectConfig`.
+    #[inline]
+    pub fn new() -> DirectConfig {
+        DirectConfig {}
+    }
+}
+
+impl UpgradeInfo for DirectConfig {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    #[inline]
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(b"/stegos/direct/1.0.0")
+    }
+}
+
+impl<TSocket> InboundUpgrade<TSocket> for DirectConfig
+where
+    TSocket: AsyncRead + AsyncWrite,
+{
+    type Output = Framed<Negotiated<TSocket>, DirectCodec>;
+    type Error = io::Error;
+    type Future = future::FutureResult<Self::Output, Self::Error>;
+
+    #[inline]
+    fn upgrade_inbound(self, socket: Negotiated<TSocket>, _: Self::Info) -> Self::Future {
+        future::ok(Framed::new(socket, DirectCodec::new()))
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for DirectConfig
+where
+    TSocket: AsyncRead + AsyncWrite,
+{
+    type Output = Framed<Negotiated<TSocket>, DirectCodec>;
+    type Error = io::Error;
+    type Future = future::FutureResult<Self::Output, Self::Error>;
+
+    #[inline]
+    fn upgrade_outbound(self, socket: Negotiated<TSocket>, _: Self::Info) -> Self::Future {
+        future::ok(Framed::new(socket, DirectCodec::new()))
+    }
+}
+
+/// Implementation of `tokio_codec::Codec`.
+///
+/// Payloads are opaque: they are signed and encrypted for the recipient by the caller.
+pub struct DirectCodec {
+    /// The codec for encoding/decoding the length prefix of messages.
+    length_prefix: codec::UviBytes,
+}
+
+impl DirectCodec {
+    fn new() -> Self {
+        let mut length_prefix = codec::UviBytes::default();
+        length_prefix.set_max_len(MAX_MESSAGE_SIZE);
+        DirectCodec { length_prefix }
+    }
+}
+
+impl Encoder for DirectCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.length_prefix.encode(Bytes::from(item), dst)
+    }
+}
+
+impl Decoder for DirectCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.length_prefix.decode(src)? {
+            Some(packet) => Ok(Some(packet.to_vec())),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirectCodec;
+    use futures::{future, Future, Sink, Stream};
+    use rand;
+    use tokio::codec::Framed;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn correct_transfer() {
+        let msg: Vec<u8> = (0..1024).map(|_| rand::random::<u8>()).collect();
+        let msg_server = msg.clone();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(c, _)| future::ok(Framed::new(c.unwrap(), DirectCodec::new())))
+            .and_then(move |s| {
+                s.into_future().map_err(|(err, _)| err).map(move |(v, _)| {
+                    assert_eq!(v.unwrap(), msg_server);
+                    ()
+                })
+            });
+
+        let client = TcpStream::connect(&listener_addr)
+            .and_then(|c| future::ok(Framed::new(c, DirectCodec::new())))
+            .and_then(|s| s.send(msg))
+            .map(|_| ());
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(server.select(client).map_err(|_| panic!()))
+            .unwrap();
+    }
+}
@@ -21,8 +21,13 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use super::peer_store::PeerStore;
 use crate::delivery::Unicast;
-use crate::kad::{kbucket::KBucketsPeerId, Kademlia, KademliaOut, NodeInfo};
+use crate::kad::{
+    kbucket::Distance, AccessChecker, BucketInfo, Kademlia, KademliaOut, NodeInfo, QueryJournal,
+    QueryRecord, StakeChecker,
+};
+use crate::ncp::PexEntry;
 use crate::utils::LruBimap;
 use futures::prelude::*;
 use libp2p::core::swarm::{
@@ -33,6 +38,8 @@ use log::*;
 use lru_time_cache::LruCache;
 use std::cmp;
 use std::collections::{HashSet, VecDeque};
+use std::error;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use stegos_crypto::pbc;
 use stegos_crypto::utils::u8v_to_hexstr;
@@ -47,6 +54,8 @@ const MIN_CLOSEST_PEERS: usize = 10;
 const DELIVERY_REPLICATION: usize = 5;
 // TTL for known nodes
 const NODES_TTL: Duration = Duration::from_secs(15 * 60);
+// How often save known peers to disk
+const PEER_STORE_FLUSH_INTERVAL: u64 = 60;
 
 pub enum DiscoveryOutEvent {
     DialPeer { peer_id: PeerId },
@@ -76,17 +85,47 @@ pub struct Discovery<TSubstream> {
     delay_between_queries: Duration,
     /// Delay to next monitoring check
     next_connection_check: Delay,
+    /// Known peers, persisted across restarts
+    peer_store: PeerStore,
+    /// Delay to the next flush of the peer store
+    next_store_flush: Delay,
+    /// Nodes to dial as soon as their peer ids are discovered
+    prewarm_nodes: HashSet<pbc::PublicKey>,
+    /// Outbound queues have a backlog, postpone DHT maintenance queries
+    congested: bool,
 }
 
 impl<TSubstream> Discovery<TSubstream>
 where
     TSubstream: AsyncRead + AsyncWrite,
 {
-    pub fn new(local_node_id: pbc::PublicKey) -> Self {
+    pub fn new(
+        local_node_id: pbc::PublicKey,
+        local_skey: pbc::SecretKey,
+        compression: bool,
+        bootstrap_window: Duration,
+        peer_store: PeerStore,
+    ) -> Self {
+        let mut kademlia = Kademlia::without_init(local_node_id.clone());
+        kademlia.set_compression(compression);
+        kademlia.set_bootstrap_window(bootstrap_window);
+        kademlia.set_network_skey(local_skey);
+        let mut known_nodes = LruBimap::<pbc::PublicKey, PeerId>::with_expiry_duration(NODES_TTL);
+        // Restore peers known from the previous runs.
+        for (node_id, entry) in peer_store.iter() {
+            if *node_id == local_node_id || entry.addresses.is_empty() {
+                continue;
+            }
+            kademlia.set_peer_id(node_id, entry.peer_id.clone());
+            for addr in entry.addresses.iter() {
+                kademlia.add_not_connected_address(node_id, addr.clone());
+            }
+            known_nodes.insert(node_id.clone(), entry.peer_id.clone());
+        }
         Discovery {
-            my_id: local_node_id.clone(),
-            kademlia: Kademlia::without_init(local_node_id),
-            known_nodes: LruBimap::<pbc::PublicKey, PeerId>::with_expiry_duration(NODES_TTL),
+            my_id: local_node_id,
+            kademlia,
+            known_nodes,
             out_events: VecDeque::new(),
             connected_peers: HashSet::new(),
             next_query: Delay::new(Instant::now() + Duration::from_secs(30)),
@@ -98,14 +137,31 @@ where
                 Duration::from_secs(60 * 15),
                 100_000,
             ),
+            peer_store,
+            next_store_flush: Delay::new(
+                Instant::now() + Duration::from_secs(PEER_STORE_FLUSH_INTERVAL),
+            ),
+            prewarm_nodes: HashSet::new(),
+            congested: false,
         }
     }
 
-    pub fn change_network_key(&mut self, new_pkey: pbc::PublicKey) {
+    pub fn change_network_key(&mut self, new_pkey: pbc::PublicKey, new_skey: pbc::SecretKey) {
         self.kademlia.change_id(new_pkey.clone());
+        self.kademlia.set_network_skey(new_skey);
         self.my_id = new_pkey;
     }
 
+    /// Restricts the routing table to nodes of staked validators.
+    pub fn set_stake_checker(&mut self, stake_checker: Arc<dyn StakeChecker>) {
+        self.kademlia.set_stake_checker(stake_checker);
+    }
+
+    /// Keeps nodes refused by the operator out of the routing table.
+    pub fn set_access_checker(&mut self, access_checker: Arc<dyn AccessChecker>) {
+        self.kademlia.set_access_checker(access_checker);
+    }
+
     /// Sets peer_id to the corresponging node_id
     pub fn set_peer_id(&mut self, node_id: &pbc::PublicKey, peer_id: PeerId) {
         self.kademlia.set_peer_id(node_id, peer_id);
@@ -126,6 +182,129 @@ where
         self.known_nodes.insert(node_id, peer_id);
     }
 
+    /// Returns the node of the peer, if known.
+    pub fn node_of_peer(&mut self, peer_id: &PeerId) -> Option<pbc::PublicKey> {
+        self.known_nodes.get_by_value(peer_id).cloned()
+    }
+
+    /// Returns PeerId of the node, if known.
+    pub fn lookup(&mut self, node_id: &pbc::PublicKey) -> Option<PeerId> {
+        if let Some(peer_id) = self.known_nodes.get_by_key(node_id) {
+            return Some(peer_id.clone());
+        }
+        self.kademlia
+            .get_node(node_id)
+            .and_then(|node_info| node_info.peer_id())
+    }
+
+    /// Connects to the nodes in advance, e.g. to newly elected validators.
+    /// Nodes with unknown peer ids are looked up in the DHT and dialed once discovered.
+    pub fn prewarm(&mut self, node_ids: Vec<pbc::PublicKey>) {
+        self.prewarm_nodes.clear();
+        for node_id in node_ids {
+            if node_id == self.my_id {
+                continue;
+            }
+            match self.lookup(&node_id) {
+                Some(peer_id) => self.dial_prewarm(&node_id, peer_id),
+                None => {
+                    debug!(target: "stegos_network::discovery", "Looking up node to prewarm: node_id={}", node_id);
+                    self.kademlia.find_node(node_id.clone());
+                    self.prewarm_nodes.insert(node_id);
+                }
+            }
+        }
+    }
+
+    fn dial_prewarm(&mut self, node_id: &pbc::PublicKey, peer_id: PeerId) {
+        if self.connected_peers.contains(&peer_id) || !self.peer_store.can_dial(&peer_id) {
+            return;
+        }
+        debug!(target: "stegos_network::discovery", "Prewarming connection: node_id={}, peer_id={}", node_id, peer_id);
+        self.out_events
+            .push_back(DiscoveryOutEvent::DialPeer { peer_id });
+    }
+
+    /// Returns a sample of validators to share with peers.
+    pub fn pex_sample(&mut self, limit: usize) -> Vec<PexEntry> {
+        self.kademlia
+            .address_records(limit)
+            .into_iter()
+            .map(|(peer_id, record)| PexEntry { peer_id, record })
+            .collect()
+    }
+
+    /// Merges validators received from a peer into the routing table and the peer store.
+    /// Entries must be verified by the caller.
+    pub fn add_pex_entries(&mut self, from: &PeerId, entries: Vec<PexEntry>) {
+        for PexEntry { peer_id, record } in entries {
+            let node_id = record.node_id;
+            if node_id == self.my_id {
+                continue;
+            }
+            // Peer ids are not signed by validators, don't let peers replace the known ones.
+            match self.lookup(&node_id) {
+                Some(known) if known != peer_id => {
+                    debug!(target: "stegos_network::discovery", "Ignoring PEX entry with conflicting peer id: from={}, node_id={}, peer_id={}, known_peer_id={}", from, node_id, peer_id, known);
+                    continue;
+                }
+                _ => {}
+            }
+            self.kademlia.set_peer_id(&node_id, peer_id.clone());
+            let addresses = record.addrs.clone();
+            if !self.kademlia.update_address_record(record) {
+                continue;
+            }
+            debug!(target: "stegos_network::discovery", "Discovered peer via PEX: from={}, node_id={}, peer_id={}, addresses={:?}", from, node_id, peer_id, addresses);
+            self.known_nodes.insert(node_id, peer_id.clone());
+            self.peer_store
+                .add_addresses(&node_id, &peer_id, &addresses);
+            if self.prewarm_nodes.remove(&node_id) {
+                self.dial_prewarm(&node_id, peer_id);
+            }
+        }
+    }
+
+    /// Records a protocol violation by the peer.
+    pub fn on_misbehavior(&mut self, peer_id: &PeerId) {
+        self.peer_store.on_misbehavior(peer_id);
+    }
+
+    /// Postpones periodic DHT queries while higher-priority traffic is queued.
+    pub fn set_congested(&mut self, congested: bool) {
+        self.congested = congested;
+    }
+
+    /// Saves the routing table to the peer store.
+    pub fn flush(&mut self) {
+        for (node_id, peer_id, addresses) in self.kademlia.known_nodes() {
+            self.peer_store
+                .add_addresses(&node_id, &peer_id, &addresses);
+        }
+        if let Err(e) = self.peer_store.save() {
+            error!(target: "stegos_network::discovery", "Failed to save peer store: {}", e);
+        }
+    }
+
+    /// Starts a DHT query to discover the node.
+    /// Returns a snapshot of the routing table.
+    pub fn dump_buckets(&mut self) -> Vec<BucketInfo> {
+        self.kademlia.dump_buckets()
+    }
+
+    /// Returns recent DHT queries.
+    pub fn query_journal(&self) -> Vec<QueryRecord> {
+        self.kademlia.query_journal()
+    }
+
+    pub fn set_query_journal(&mut self, journal: QueryJournal) {
+        self.kademlia.set_query_journal(journal);
+    }
+
+    pub fn find_node(&mut self, node_id: &pbc::PublicKey) {
+        self.kademlia.find_node(node_id.clone());
+    }
+
     pub fn deliver_unicast(&mut self, to: &pbc::PublicKey, payload: Vec<u8>) {
         let mut message = Unicast {
             to: to.clone(),
@@ -239,15 +418,40 @@ where
     }
 
     fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
-        self.kademlia.addresses_of_peer(peer_id)
+        let mut addresses = self.kademlia.addresses_of_peer(peer_id);
+        for addr in self.peer_store.addresses_of_peer(peer_id) {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses
     }
 
     fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
         debug!(target: "stegos_network::discovery", "new peer connected: peer_id={}", peer_id);
         self.connected_peers.insert(peer_id.clone());
+        let address = match &endpoint {
+            ConnectedPoint::Dialer { address } => Some(address),
+            ConnectedPoint::Listener { .. } => None,
+        };
+        self.peer_store.on_success(&peer_id, address);
         NetworkBehaviour::inject_connected(&mut self.kademlia, peer_id, endpoint)
     }
 
+    fn inject_addr_reach_failure(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        addr: &Multiaddr,
+        error: &dyn error::Error,
+    ) {
+        NetworkBehaviour::inject_addr_reach_failure(&mut self.kademlia, peer_id, addr, error)
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.peer_store.on_failure(peer_id);
+        NetworkBehaviour::inject_dial_failure(&mut self.kademlia, peer_id)
+    }
+
     fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint) {
         debug!(target: "stegos_network::discovery", "peer disconnected: peer_id={}", peer_id);
         self.connected_peers.remove(peer_id);
@@ -277,77 +481,111 @@ where
         }
 
         // Process results of Kademlia discovery
-        match self.kademlia.poll(params) {
-            Async::Ready(NetworkBehaviourAction::GenerateEvent(action)) => {
-                trace!(target: "stegos_network::discovery", "Event from Kademlia: {:?}", action);
-                match action {
-                    KademliaOut::FindNodeResult {
-                        ref key,
-                        ref closer_peers,
-                    } => {
-                        debug!(
-                            target: "stegos_network::discovery",
-                            "Kademlia query for {} yielded {} results",
-                            u8v_to_hexstr(key.as_bytes()),
-                            closer_peers.len()
-                        );
-                    }
-                    KademliaOut::GetProvidersResult {
-                        ref key,
-                        closer_peers: _,
-                        ref provider_peers,
-                    } => {
-                        debug!(target: "stegos_network::discovery", "Got providers: key={} num_providers={}", u8v_to_hexstr(key.as_bytes()), provider_peers.len());
-                    }
-                    KademliaOut::Discovered {
-                        ref peer_id,
-                        ref node_id,
-                        ref addresses,
-                        ty,
-                    } => {
-                        if peer_id.is_some() {
-                            let peer_id = peer_id.clone().unwrap();
-                            self.known_nodes.insert(node_id.clone(), peer_id.clone());
-                            debug!(target: "stegos_network::discovery",
-                                "Discovered peer: node_id={}, peer_id={}, addresses={:?}, connected={:?}",
-                                node_id, peer_id, addresses, ty
+        loop {
+            match self.kademlia.poll(params) {
+                Async::Ready(NetworkBehaviourAction::GenerateEvent(action)) => {
+                    trace!(target: "stegos_network::discovery", "Event from Kademlia: {:?}", action);
+                    match action {
+                        KademliaOut::FindNodeResult {
+                            ref key,
+                            ref closer_peers,
+                        } => {
+                            debug!(
+                                target: "stegos_network::discovery",
+                                "Kademlia query for {} yielded {} results",
+                                u8v_to_hexstr(key.as_bytes()),
+                                closer_peers.len()
                             );
-                            if addresses.len() > 0 {
-                                self.kademlia.set_peer_id(node_id, peer_id.clone());
-                                for addr in addresses.iter() {
-                                    if self.connected_peers.contains(&peer_id) {
-                                        self.kademlia.add_connected_address(node_id, addr.clone());
-                                    } else {
-                                        self.kademlia
-                                            .add_not_connected_address(node_id, addr.clone());
+                        }
+                        KademliaOut::GetProvidersResult {
+                            ref key,
+                            closer_peers: _,
+                            ref provider_peers,
+                        } => {
+                            debug!(target: "stegos_network::discovery", "Got providers: key={} num_providers={}", u8v_to_hexstr(key.as_bytes()), provider_peers.len());
+                        }
+                        KademliaOut::QueryTimedOut {
+                            ref key,
+                            reason,
+                            ref closer_peers,
+                            ref provider_peers,
+                        } => {
+                            debug!(target: "stegos_network::discovery", "Kademlia query timed out: key={}, reason={}, closer_peers={}, providers={}", u8v_to_hexstr(key.as_bytes()), reason, closer_peers.len(), provider_peers.len());
+                        }
+                        KademliaOut::FindNodeByWalletResult {
+                            ref wallet_pkey,
+                            ref node_ids,
+                        } => {
+                            debug!(target: "stegos_network::discovery", "Got nodes by wallet: wallet_pkey={}, node_ids={:?}", wallet_pkey, node_ids);
+                        }
+                        KademliaOut::RateLimited {
+                            ref peer_id,
+                            violation,
+                        } => {
+                            debug!(target: "stegos_network::discovery", "Peer exceeded rate limits: peer_id={}, violation={:?}", peer_id, violation);
+                            self.peer_store.on_misbehavior(peer_id);
+                        }
+                        KademliaOut::Discovered {
+                            ref peer_id,
+                            ref node_id,
+                            ref addresses,
+                            ty,
+                        } => {
+                            if peer_id.is_some() {
+                                let peer_id = peer_id.clone().unwrap();
+                                self.known_nodes.insert(node_id.clone(), peer_id.clone());
+                                debug!(target: "stegos_network::discovery",
+                                    "Discovered peer: node_id={}, peer_id={}, addresses={:?}, connected={:?}",
+                                    node_id, peer_id, addresses, ty
+                                );
+                                if addresses.len() > 0 {
+                                    self.peer_store.add_addresses(node_id, &peer_id, addresses);
+                                    self.kademlia.set_peer_id(node_id, peer_id.clone());
+                                    for addr in addresses.iter() {
+                                        if self.connected_peers.contains(&peer_id) {
+                                            self.kademlia
+                                                .add_connected_address(node_id, addr.clone());
+                                        } else {
+                                            self.kademlia
+                                                .add_not_connected_address(node_id, addr.clone());
+                                        }
                                     }
                                 }
+                                if self.prewarm_nodes.remove(node_id) {
+                                    self.dial_prewarm(node_id, peer_id);
+                                }
+                            } else {
+                                debug!(target: "stegos_network::discovery",
+                                    "Discovered peer: node_id={}, peer_id=Unknown, addresses={:?} connected={:?}",
+                                    node_id, addresses, ty
+                                );
                             }
-                        } else {
-                            debug!(target: "stegos_network::discovery",
-                                "Discovered peer: node_id={}, peer_id=Unknown, addresses={:?} connected={:?}",
-                                node_id, addresses, ty
-                            );
                         }
                     }
+                    return Async::Ready(NetworkBehaviourAction::GenerateEvent(
+                        DiscoveryOutEvent::KadEvent { event: action },
+                    ));
                 }
-                return Async::Ready(NetworkBehaviourAction::GenerateEvent(
-                    DiscoveryOutEvent::KadEvent { event: action },
-                ));
-            }
-            Async::Ready(NetworkBehaviourAction::DialAddress { address }) => {
-                return Async::Ready(NetworkBehaviourAction::DialAddress { address });
-            }
-            Async::Ready(NetworkBehaviourAction::DialPeer { peer_id }) => {
-                return Async::Ready(NetworkBehaviourAction::DialPeer { peer_id });
-            }
-            Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event }) => {
-                return Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event });
-            }
-            Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
-                return Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address });
+                Async::Ready(NetworkBehaviourAction::DialAddress { address }) => {
+                    return Async::Ready(NetworkBehaviourAction::DialAddress { address });
+                }
+                Async::Ready(NetworkBehaviourAction::DialPeer { peer_id })
+                    if !self.peer_store.can_dial(&peer_id) =>
+                {
+                    debug!(target: "stegos_network::discovery", "Skip dialing peer in backoff: peer_id={}", peer_id);
+                    continue;
+                }
+                Async::Ready(NetworkBehaviourAction::DialPeer { peer_id }) => {
+                    return Async::Ready(NetworkBehaviourAction::DialPeer { peer_id });
+                }
+                Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event }) => {
+                    return Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event });
+                }
+                Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
+                    return Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address });
+                }
+                Async::NotReady => break,
             }
-            Async::NotReady => (),
         }
         // Check if we are connected to enough closes peers
         loop {
@@ -369,7 +607,9 @@ where
                         if let Some(node_info) = self.kademlia.get_node(&node) {
                             match node_info.peer_id() {
                                 Some(p) => {
-                                    if !self.connected_peers.contains(&p) {
+                                    if !self.connected_peers.contains(&p)
+                                        && self.peer_store.can_dial(&p)
+                                    {
                                         debug!(target: "stegos_network::discovery", "connecting to known closest peer: {}, distance: {}", p, &my_id.distance_with(node));
                                         self.out_events.push_back(DiscoveryOutEvent::DialPeer {
                                             peer_id: p.clone(),
@@ -389,10 +629,32 @@ where
                 }
             }
         }
+        // Flush the peer store to disk
+        loop {
+            match self.next_store_flush.poll() {
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(_)) => {
+                    self.next_store_flush
+                        .reset(Instant::now() + Duration::from_secs(PEER_STORE_FLUSH_INTERVAL));
+                    if let Err(e) = self.peer_store.save() {
+                        error!(target: "stegos_network::discovery", "Failed to save peer store: {}", e);
+                    }
+                }
+                Err(err) => {
+                    warn!(target: "stegos_network::discovery", "peer store timer error: {}", err);
+                    break;
+                }
+            }
+        }
         // Initiate new shake of DHT network
         loop {
             match self.next_query.poll() {
                 Ok(Async::NotReady) => break,
+                Ok(Async::Ready(_)) if self.congested => {
+                    debug!(target: "stegos_network::discovery", "Outbound queues are congested, postponing DHT query");
+                    self.next_query
+                        .reset(Instant::now() + self.delay_between_queries);
+                }
                 Ok(Async::Ready(_)) => {
                     debug!(target: "stegos_network::discovery", "Shooting at DHT to gather nodes information");
                     let random_node_id = pbc::make_random_keys().1;
@@ -23,5 +23,7 @@
 
 mod behavior;
 mod metrics;
+mod peer_store;
 
 pub use behavior::{Discovery, DiscoveryOutEvent};
+pub use peer_store::PeerStore;
@@ -0,0 +1,344 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Persistent store of known peers.
+//!
+//! Keeps addresses of peers discovered via Kademlia across restarts and
+//! applies an exponential backoff to peers which can't be dialed.
+//!
+
+use failure::{format_err, Error};
+use libp2p::{Multiaddr, PeerId};
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stegos_crypto::pbc;
+
+/// The maximal number of stored peers.
+const MAX_PEERS: usize = 1024;
+/// The maximal number of stored addresses per peer.
+const MAX_ADDRESSES: usize = 8;
+/// The initial dial backoff.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// The maximal dial backoff.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Information about a known peer.
+#[derive(Debug, Clone)]
+pub struct PeerEntry {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    /// The last time when the peer was connected, seconds since UNIX epoch.
+    pub last_success: u64,
+    /// The number of failed dials since the last success.
+    pub failures: u32,
+    /// Don't dial the peer before this time.
+    next_dial: Instant,
+}
+
+impl PeerEntry {
+    fn new(peer_id: PeerId) -> Self {
+        PeerEntry {
+            peer_id,
+            addresses: Vec::new(),
+            last_success: 0,
+            failures: 0,
+            next_dial: Instant::now(),
+        }
+    }
+}
+
+/// Returns the dial backoff after the given number of failures.
+fn backoff(failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::from_secs(0);
+    }
+    let shift = std::cmp::min(failures - 1, 16);
+    std::cmp::min(MIN_BACKOFF * (1u32 << shift), MAX_BACKOFF)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Known peers, persisted to disk.
+pub struct PeerStore {
+    /// Path to the file, None for in-memory store.
+    path: Option<PathBuf>,
+    peers: HashMap<pbc::PublicKey, PeerEntry>,
+    node_ids: HashMap<PeerId, pbc::PublicKey>,
+    /// True if there are changes which are not saved yet.
+    dirty: bool,
+}
+
+impl PeerStore {
+    /// Creates an in-memory store.
+    pub fn new() -> Self {
+        PeerStore {
+            path: None,
+            peers: HashMap::new(),
+            node_ids: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Loads the store from the file, a missing file is treated as an empty store.
+    ///
+    /// Each line of the file is `NODE_ID PEER_ID LAST_SUCCESS FAILURES [ADDRESS...]`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut store = PeerStore::new();
+        store.path = Some(path.to_path_buf());
+        if !path.exists() {
+            return Ok(store);
+        }
+        let contents = fs::read_to_string(path)?;
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = Self::parse_line(line)
+                .map_err(|e| format_err!("Invalid peer store: line={}, error={}", n + 1, e))?;
+            store.insert(entry.0, entry.1);
+        }
+        store.dirty = false;
+        info!(target: "stegos_network::discovery", "Loaded peer store: path={}, peers={}", path.display(), store.len());
+        Ok(store)
+    }
+
+    fn parse_line(line: &str) -> Result<(pbc::PublicKey, PeerEntry), Error> {
+        let mut fields = line.split_whitespace();
+        let mut next = || fields.next().ok_or_else(|| format_err!("missing field"));
+        let node_id = pbc::PublicKey::try_from_hex(next()?)?;
+        let peer_id = next()?
+            .parse::<PeerId>()
+            .map_err(|_| format_err!("invalid peer id"))?;
+        let mut entry = PeerEntry::new(peer_id);
+        entry.last_success = next()?.parse()?;
+        entry.failures = next()?.parse()?;
+        for addr in fields {
+            entry.addresses.push(addr.parse()?);
+        }
+        Ok((node_id, entry))
+    }
+
+    /// Writes the store to the file if there are any changes.
+    pub fn save(&mut self) -> Result<(), Error> {
+        let path = match (&self.path, self.dirty) {
+            (Some(path), true) => path,
+            _ => return Ok(()),
+        };
+        let mut contents = String::new();
+        for (node_id, entry) in &self.peers {
+            contents.push_str(&format!(
+                "{} {} {} {}",
+                node_id.to_hex(),
+                entry.peer_id.to_base58(),
+                entry.last_success,
+                entry.failures
+            ));
+            for addr in &entry.addresses {
+                contents.push(' ');
+                contents.push_str(&addr.to_string());
+            }
+            contents.push('\n');
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)?;
+        self.dirty = false;
+        debug!(target: "stegos_network::discovery", "Saved peer store: path={}, peers={}", path.display(), self.peers.len());
+        Ok(())
+    }
+
+    /// The number of known peers.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Iterates over all known peers.
+    pub fn iter(&self) -> impl Iterator<Item = (&pbc::PublicKey, &PeerEntry)> {
+        self.peers.iter()
+    }
+
+    fn insert(&mut self, node_id: pbc::PublicKey, entry: PeerEntry) {
+        if !self.peers.contains_key(&node_id) && self.peers.len() >= MAX_PEERS {
+            self.evict();
+        }
+        if let Some(old) = self.peers.get(&node_id) {
+            self.node_ids.remove(&old.peer_id);
+        }
+        self.node_ids.insert(entry.peer_id.clone(), node_id);
+        self.peers.insert(node_id, entry);
+        self.dirty = true;
+    }
+
+    /// Removes the least useful peer.
+    fn evict(&mut self) {
+        let worst = self
+            .peers
+            .iter()
+            .max_by_key(|(_, e)| (e.failures, std::cmp::Reverse(e.last_success)))
+            .map(|(node_id, _)| *node_id);
+        if let Some(node_id) = worst {
+            if let Some(entry) = self.peers.remove(&node_id) {
+                self.node_ids.remove(&entry.peer_id);
+            }
+        }
+    }
+
+    /// Records addresses of a discovered peer.
+    pub fn add_addresses(
+        &mut self,
+        node_id: &pbc::PublicKey,
+        peer_id: &PeerId,
+        addresses: &[Multiaddr],
+    ) {
+        let mut entry = match self.peers.get(node_id) {
+            Some(entry) if &entry.peer_id == peer_id => entry.clone(),
+            _ => PeerEntry::new(peer_id.clone()),
+        };
+        let mut changed = false;
+        for addr in addresses {
+            if !entry.addresses.contains(addr) {
+                if entry.addresses.len() >= MAX_ADDRESSES {
+                    entry.addresses.remove(0);
+                }
+                entry.addresses.push(addr.clone());
+                changed = true;
+            }
+        }
+        if changed || !self.peers.contains_key(node_id) {
+            self.insert(*node_id, entry);
+        }
+    }
+
+    /// Records a successful connection.
+    pub fn on_success(&mut self, peer_id: &PeerId, address: Option<&Multiaddr>) {
+        let node_id = match self.node_ids.get(peer_id) {
+            Some(node_id) => *node_id,
+            None => return,
+        };
+        if let Some(address) = address {
+            self.add_addresses(&node_id, peer_id, &[address.clone()]);
+        }
+        if let Some(entry) = self.peers.get_mut(&node_id) {
+            entry.last_success = now_secs();
+            entry.failures = 0;
+            entry.next_dial = Instant::now();
+            self.dirty = true;
+        }
+    }
+
+    /// Records a failed dial and schedules the next attempt.
+    pub fn on_failure(&mut self, peer_id: &PeerId) {
+        let node_id = match self.node_ids.get(peer_id) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        if let Some(entry) = self.peers.get_mut(node_id) {
+            entry.failures = entry.failures.saturating_add(1);
+            let delay = backoff(entry.failures);
+            entry.next_dial = Instant::now() + delay;
+            self.dirty = true;
+            debug!(target: "stegos_network::discovery", "Dial failed: peer_id={}, failures={}, backoff={:?}", peer_id, entry.failures, delay);
+        }
+    }
+
+    /// Records a protocol violation by the peer.
+    /// It's penalized like a failed dial, so the peer is dialed less often and evicted first.
+    pub fn on_misbehavior(&mut self, peer_id: &PeerId) {
+        debug!(target: "stegos_network::discovery", "Peer misbehaved: peer_id={}", peer_id);
+        self.on_failure(peer_id);
+    }
+
+    /// Returns false if the peer is in backoff.
+    pub fn can_dial(&self, peer_id: &PeerId) -> bool {
+        self.node_ids
+            .get(peer_id)
+            .and_then(|node_id| self.peers.get(node_id))
+            .map(|entry| entry.next_dial <= Instant::now())
+            .unwrap_or(true)
+    }
+
+    /// Returns stored addresses of the peer.
+    pub fn addresses_of_peer(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.node_ids
+            .get(peer_id)
+            .and_then(|node_id| self.peers.get(node_id))
+            .map(|entry| entry.addresses.clone())
+            .unwrap_or_else(Vec::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persistence_and_backoff() {
+        let dir = tempdir::TempDir::new("peer_store").unwrap();
+        let path = dir.path().join("peers");
+        let (_skey, node_id) = pbc::make_random_keys();
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/10055".parse().unwrap();
+
+        let mut store = PeerStore::load(&path).unwrap();
+        assert_eq!(store.len(), 0);
+        store.add_addresses(&node_id, &peer_id, &[addr.clone()]);
+        assert_eq!(store.addresses_of_peer(&peer_id), vec![addr.clone()]);
+        assert!(store.can_dial(&peer_id));
+
+        store.on_failure(&peer_id);
+        assert!(!store.can_dial(&peer_id));
+        store.on_failure(&peer_id);
+        store.save().unwrap();
+
+        let mut store = PeerStore::load(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.addresses_of_peer(&peer_id), vec![addr.clone()]);
+        let (_, entry) = store.iter().next().unwrap();
+        assert_eq!(entry.failures, 2);
+        assert_eq!(entry.last_success, 0);
+
+        store.on_success(&peer_id, None);
+        assert!(store.can_dial(&peer_id));
+        let (_, entry) = store.iter().next().unwrap();
+        assert_eq!(entry.failures, 0);
+        assert!(entry.last_success > 0);
+    }
+
+    #[test]
+    fn backoff_is_exponential() {
+        assert_eq!(backoff(0), Duration::from_secs(0));
+        assert_eq!(backoff(1), MIN_BACKOFF);
+        assert_eq!(backoff(2), MIN_BACKOFF * 2);
+        assert_eq!(backoff(3), MIN_BACKOFF * 4);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+}
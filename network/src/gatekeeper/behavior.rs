@@ -32,9 +32,10 @@ use log::*;
 use lru_time_cache::LruCache;
 use rand::{seq::SliceRandom, thread_rng};
 use std::error;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     marker::PhantomData,
     thread,
 };
@@ -43,6 +44,7 @@ use tokio::io::{AsyncRead, AsyncWrite};
 
 use super::handler::{GatekeeperHandler, GatekeeperSendEvent};
 use super::protocol::GatekeeperMessage;
+use crate::access::AccessList;
 use crate::config::NetworkConfig;
 use crate::utils::{ExpiringQueue, PeerIdKey};
 
@@ -61,8 +63,12 @@ const SOLVER_THREADS: usize = 4;
 pub struct Gatekeeper<TSubstream> {
     /// Events that need to be yielded to the outside when polling.
     events: VecDeque<NetworkBehaviourAction<GatekeeperSendEvent, GatekeeperOutEvent>>,
-    /// List of connected peers
-    connected_peers: HashSet<PeerId>,
+    /// Connected peers with their remote addresses
+    connected_peers: HashMap<PeerId, Multiaddr>,
+    /// Connected peers refused by the access list
+    denied_peers: HashSet<PeerId>,
+    /// Operator-controlled allow/deny lists
+    access: Arc<AccessList>,
     /// Peers we should be connected to
     desired_peers: HashSet<PeerId>,
     /// Addresses we are trying to keep connected to
@@ -91,13 +97,15 @@ pub struct Gatekeeper<TSubstream> {
     hashcash_nbits: usize,
     /// Netwrok readyness threshold
     readiness_threshold: usize,
+    /// Name of our chain, peers from other chains are rejected
+    chain_id: String,
     /// Marker to pin the generics.
     marker: PhantomData<TSubstream>,
 }
 
 impl<TSubstream> Gatekeeper<TSubstream> {
     /// Creates a NetworkBehaviour for Gatekeeper.
-    pub fn new(config: &NetworkConfig) -> Self {
+    pub fn new(config: &NetworkConfig, access: Arc<AccessList>) -> Self {
         let mut desired_addesses: HashSet<Multiaddr> = HashSet::new();
         let mut events: VecDeque<NetworkBehaviourAction<GatekeeperSendEvent, GatekeeperOutEvent>> =
             VecDeque::new();
@@ -110,6 +118,9 @@ impl<TSubstream> Gatekeeper<TSubstream> {
         for addr in addrs.iter() {
             debug!(target: "stegos_network::gatekeeper", "dialing peer with address {}", addr);
             match addr.parse::<Multiaddr>() {
+                Ok(ref maddr) if !access.check_address(maddr) => {
+                    warn!(target: "stegos_network::gatekeeper", "seed node is refused by the access list: address={}", addr)
+                }
                 Ok(maddr) => {
                     events.push_back(NetworkBehaviourAction::DialAddress {
                         address: maddr.clone(),
@@ -126,7 +137,9 @@ impl<TSubstream> Gatekeeper<TSubstream> {
 
         Gatekeeper {
             events,
-            connected_peers: HashSet::new(),
+            connected_peers: HashMap::new(),
+            denied_peers: HashSet::new(),
+            access,
             desired_peers: HashSet::new(),
             desired_addesses,
             pending_out_peers: ExpiringQueue::new(HANDSHAKE_STEP_TIMEOUT),
@@ -146,6 +159,7 @@ impl<TSubstream> Gatekeeper<TSubstream> {
             puzzles_queue: VecDeque::new(),
             hashcash_nbits: config.hashcash_nbits,
             readiness_threshold: config.readiness_threshold,
+            chain_id: config.chain_id.clone(),
             marker: PhantomData,
         }
     }
@@ -154,13 +168,54 @@ impl<TSubstream> Gatekeeper<TSubstream> {
         self.unlocked_peers.len() >= self.readiness_threshold
     }
 
+    /// Replaces the access list.
+    /// Returns connected peers which are not allowed anymore.
+    pub fn set_access_list(&mut self, access: Arc<AccessList>) -> Vec<PeerId> {
+        self.desired_peers
+            .retain(|peer_id| access.is_peer_allowed(peer_id));
+        self.desired_addesses
+            .retain(|address| access.is_address_allowed(address));
+        self.access = access;
+        let mut denied = Vec::new();
+        for (peer_id, address) in self.connected_peers.iter() {
+            if self.denied_peers.contains(peer_id) {
+                continue;
+            }
+            if !self.access.check_peer(peer_id) || !self.access.check_address(address) {
+                denied.push(peer_id.clone());
+            }
+        }
+        for peer_id in denied.iter() {
+            self.deny(peer_id);
+        }
+        denied
+    }
+
+    /// Stops negotiations with the peer refused by the access list.
+    fn deny(&mut self, peer_id: &PeerId) {
+        debug!(target: "stegos_network::gatekeeper", "peer is refused by the access list: peer_id={}", peer_id);
+        self.denied_peers.insert(peer_id.clone());
+        self.desired_peers.remove(peer_id);
+        self.pending_out_peers.remove(peer_id);
+        self.pending_in_peers.remove(peer_id);
+        self.unlocked_peers.remove(&peer_id.clone().into());
+    }
+
     pub fn dial_peer(&mut self, peer_id: PeerId) {
+        if !self.access.check_peer(&peer_id) {
+            debug!(target: "stegos_network::gatekeeper", "not dialing peer refused by the access list: peer_id={}", peer_id);
+            return;
+        }
         self.desired_peers.insert(peer_id.clone());
         self.events
             .push_back(NetworkBehaviourAction::DialPeer { peer_id });
     }
 
     pub fn dial_address(&mut self, address: Multiaddr) {
+        if !self.access.check_address(&address) {
+            debug!(target: "stegos_network::gatekeeper", "not dialing address refused by the access list: address={}", address);
+            return;
+        }
         self.desired_addesses.insert(address.clone());
         self.events
             .push_back(NetworkBehaviourAction::DialAddress { address });
@@ -190,7 +245,35 @@ impl<TSubstream> Gatekeeper<TSubstream> {
         })
     }
 
-    fn handle_unlock_request(&mut self, peer_id: PeerId, proof: Option<HashCashProof>) {
+    fn handle_unlock_request(
+        &mut self,
+        peer_id: PeerId,
+        proof: Option<HashCashProof>,
+        chain_id: String,
+    ) {
+        if self.denied_peers.contains(&peer_id) {
+            debug!(target: "stegos_network::gatekeeper", "unlock request from refused peer: peer_id={}", peer_id);
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                event: GatekeeperSendEvent::Send(GatekeeperMessage::PermitReply {
+                    connection_allowed: false,
+                }),
+            });
+            return;
+        }
+
+        if chain_id != self.chain_id {
+            debug!(target: "stegos_network::gatekeeper", "unlock request from another chain: peer_id={}, chain_id={}, our_chain_id={}", peer_id, chain_id, self.chain_id);
+            self.pending_in_peers.remove(&peer_id);
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                event: GatekeeperSendEvent::Send(GatekeeperMessage::PermitReply {
+                    connection_allowed: false,
+                }),
+            });
+            return;
+        }
+
         if self.unlocked_peers.contains_key(&peer_id.clone().into()) {
             debug!(target: "stegos_network::gatekeeper", "unlock request from already unlocked peer: peer_id={}", peer_id);
             self.pending_in_peers
@@ -274,6 +357,7 @@ impl<TSubstream> Gatekeeper<TSubstream> {
                     peer_id: peer_id.clone(),
                     event: GatekeeperSendEvent::Send(GatekeeperMessage::UnlockRequest {
                         proof: Some(proof),
+                        chain_id: self.chain_id.clone(),
                     }),
                 });
                 self.pending_out_peers
@@ -307,7 +391,18 @@ where
 
     fn inject_connected(&mut self, id: PeerId, cp: ConnectedPoint) {
         debug!(target: "stegos_network::gatekeeper", "peer connected: peer_id={}, endpoint={}", id, cp.display());
-        self.connected_peers.insert(id.clone());
+        let address = match &cp {
+            ConnectedPoint::Dialer { address } => address.clone(),
+            ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr.clone(),
+        };
+        self.connected_peers.insert(id.clone(), address.clone());
+        if !self.access.check_peer(&id) || !self.access.check_address(&address) {
+            self.deny(&id);
+            self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+                GatekeeperOutEvent::Rejected { peer_id: id },
+            ));
+            return;
+        }
         // FIXME: use LRU cache for dialing addresses/peers
         if let ConnectedPoint::Dialer { address } = cp {
             if self.desired_addesses.contains(&address) {
@@ -333,6 +428,7 @@ where
     fn inject_disconnected(&mut self, id: &PeerId, cp: ConnectedPoint) {
         debug!(target: "stegos_network::gatekeeper", "peer disconnected: peer_id={}, endpoint={}", id, cp.display());
         self.connected_peers.remove(id);
+        let denied = self.denied_peers.remove(id);
         self.pending_out_peers.remove(&id.clone().into());
         self.pending_in_peers.remove(&id.clone().into());
         self.events.push_back(NetworkBehaviourAction::GenerateEvent(
@@ -341,6 +437,9 @@ where
             },
         ));
         if let ConnectedPoint::Dialer { address } = cp {
+            if denied {
+                return;
+            }
             if self.desired_peers.contains(id) || self.desired_addesses.contains(&address) {
                 debug!(target: "stegos_network::gatekeeper", "re-connecting to peer/addr: peer_id={}, addr={}", id, address);
                 self.events
@@ -387,8 +486,8 @@ where
         // Process received Gatekeeper message (passed from Handler as Custom(message))
         debug!(target: "stegos_network::gatekeeper", "Received a message: {:?}", event);
         match event {
-            GatekeeperMessage::UnlockRequest { proof } => {
-                self.handle_unlock_request(propagation_source, proof)
+            GatekeeperMessage::UnlockRequest { proof, chain_id } => {
+                self.handle_unlock_request(propagation_source, proof, chain_id)
             }
             GatekeeperMessage::ChallengeReply { seed, nbits } => {
                 self.handle_challenge_reply(propagation_source, seed, nbits)
@@ -500,6 +599,7 @@ where
                         peer_id,
                         event: GatekeeperSendEvent::Send(GatekeeperMessage::UnlockRequest {
                             proof,
+                            chain_id: self.chain_id.clone(),
                         }),
                     })
                 }
@@ -529,11 +629,14 @@ where
                             nbits: puzzle.0.nbits,
                             count: answer,
                         };
-                        if self.connected_peers.contains(&peer_id) {
+                        if self.connected_peers.contains_key(&peer_id) {
                             self.events.push_back(NetworkBehaviourAction::SendEvent {
                                 peer_id,
                                 event: GatekeeperSendEvent::Send(
-                                    GatekeeperMessage::UnlockRequest { proof: Some(proof) },
+                                    GatekeeperMessage::UnlockRequest {
+                                        proof: Some(proof),
+                                        chain_id: self.chain_id.clone(),
+                                    },
                                 ),
                             })
                         } else {
@@ -612,6 +715,10 @@ pub enum GatekeeperOutEvent {
     Disconnected {
         peer_id: PeerId,
     },
+    /// The peer is refused by the access list, the connection must be closed.
+    Rejected {
+        peer_id: PeerId,
+    },
     Solve {
         peer_id: PeerId,
         seed: Vec<u8>,
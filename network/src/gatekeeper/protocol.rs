@@ -103,8 +103,9 @@ impl Encoder for GatekeeperCodec {
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
         let proto = match item {
-            GatekeeperMessage::UnlockRequest { proof } => {
+            GatekeeperMessage::UnlockRequest { proof, chain_id } => {
                 let mut msg_typ = gatekeeper_proto::UnlockRequest::new();
+                msg_typ.set_chain_id(chain_id);
                 if let Some(proof) = proof {
                     let mut proof_proto = gatekeeper_proto::HashcashProof::new();
                     proof_proto.set_seed(proof.seed);
@@ -175,7 +176,8 @@ impl Decoder for GatekeeperCodec {
                 } else {
                     None
                 };
-                Ok(Some(GatekeeperMessage::UnlockRequest { proof }))
+                let chain_id = unlock_request_msg.get_chain_id().to_string();
+                Ok(Some(GatekeeperMessage::UnlockRequest { proof, chain_id }))
             }
             Some(Message_oneof_typ::challenge_reply(reply_msg)) => {
                 Ok(Some(GatekeeperMessage::ChallengeReply {
@@ -203,9 +205,17 @@ impl Decoder for GatekeeperCodec {
 /// Message that we can send to a peer or received from a peer.
 #[derive(Debug, Clone, PartialEq)]
 pub enum GatekeeperMessage {
-    UnlockRequest { proof: Option<HashCashProof> },
-    ChallengeReply { seed: Vec<u8>, nbits: usize },
-    PermitReply { connection_allowed: bool },
+    UnlockRequest {
+        proof: Option<HashCashProof>,
+        chain_id: String,
+    },
+    ChallengeReply {
+        seed: Vec<u8>,
+        nbits: usize,
+    },
+    PermitReply {
+        connection_allowed: bool,
+    },
 }
 
 #[cfg(test)]
@@ -218,7 +228,10 @@ mod tests {
 
     #[test]
     fn correct_transfer() {
-        let unlock_request_null = GatekeeperMessage::UnlockRequest { proof: None };
+        let unlock_request_null = GatekeeperMessage::UnlockRequest {
+            proof: None,
+            chain_id: "testnet".to_string(),
+        };
         test_one(unlock_request_null);
 
         let proof = HashCashProof {
@@ -226,7 +239,10 @@ mod tests {
             nbits: rand::random::<usize>(),
             count: rand::random::<i64>(),
         };
-        let unlock_request_proof = GatekeeperMessage::UnlockRequest { proof: Some(proof) };
+        let unlock_request_proof = GatekeeperMessage::UnlockRequest {
+            proof: Some(proof),
+            chain_id: "mainnet".to_string(),
+        };
         test_one(unlock_request_proof);
 
         let challenge_reply = GatekeeperMessage::ChallengeReply {
@@ -21,6 +21,58 @@
 use arrayvec::ArrayVec;
 use libp2p::core::Multiaddr;
 use std::{fmt, time::Duration, time::Instant};
+use stegos_crypto::hash::{Hash, Hasher};
+use stegos_crypto::pbc;
+
+/// Addresses announced by a node, signed by its network key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressRecord {
+    /// Announcing node.
+    pub node_id: pbc::PublicKey,
+    /// Increases with every announcement. Records with lower numbers are outdated.
+    pub seq: u64,
+    /// Addresses of the node.
+    pub addrs: Vec<Multiaddr>,
+    /// Signature of node_id, seq and addrs.
+    pub signature: pbc::Signature,
+}
+
+impl AddressRecord {
+    /// Creates and signs a new record.
+    pub fn new(
+        skey: &pbc::SecretKey,
+        node_id: pbc::PublicKey,
+        seq: u64,
+        addrs: Vec<Multiaddr>,
+    ) -> AddressRecord {
+        let hash = Self::hash(&node_id, seq, &addrs);
+        let signature = pbc::sign_hash(&hash, skey);
+        AddressRecord {
+            node_id,
+            seq,
+            addrs,
+            signature,
+        }
+    }
+
+    fn hash(node_id: &pbc::PublicKey, seq: u64, addrs: &[Multiaddr]) -> Hash {
+        let mut hasher = Hasher::new();
+        hasher.input(&node_id.to_bytes()[..]);
+        hasher.input(&seq.to_le_bytes());
+        for addr in addrs {
+            let bytes = addr.to_vec();
+            hasher.input(&(bytes.len() as u64).to_le_bytes());
+            hasher.input(&bytes);
+        }
+        hasher.result()
+    }
+
+    /// Checks the signature of the record.
+    pub fn is_valid(&self) -> bool {
+        let hash = Self::hash(&self.node_id, self.seq, &self.addrs);
+        pbc::check_hash(&hash, &self.signature, &self.node_id).is_ok()
+    }
+}
 
 /// List of addresses of a peer.
 #[derive(Clone)]
@@ -30,6 +82,8 @@ pub struct Addresses {
     addrs: ArrayVec<[(Multiaddr, Option<Instant>); 6]>,
     /// Time-to-live for addresses we're not connected to.
     expiration: Duration,
+    /// The newest signed announcement of the node.
+    record: Option<AddressRecord>,
 }
 
 impl Addresses {
@@ -44,6 +98,7 @@ impl Addresses {
         Addresses {
             addrs: ArrayVec::new(),
             expiration: ttl,
+            record: None,
         }
     }
 
@@ -120,6 +175,13 @@ impl Addresses {
             return;
         }
 
+        // Unsigned hints must not resurrect addresses the node has stopped announcing.
+        if let Some(record) = &self.record {
+            if !record.addrs.contains(&addr) {
+                return;
+            }
+        }
+
         // Do a cleanup pass.
         let now = Instant::now();
         self.addrs.retain(move |(_, exp)| {
@@ -143,6 +205,35 @@ impl Addresses {
     }
 }
 
+impl Addresses {
+    /// Returns the newest signed announcement, if any.
+    pub fn record(&self) -> Option<&AddressRecord> {
+        self.record.as_ref()
+    }
+
+    /// Replaces announced addresses with a newer signed record.
+    ///
+    /// The caller must check the signature. Returns false if the record is not newer than the
+    /// current one, which rejects replays and rollbacks to old addresses.
+    pub fn update_record(&mut self, record: AddressRecord) -> bool {
+        if let Some(current) = &self.record {
+            if record.seq <= current.seq {
+                return false;
+            }
+        }
+
+        // Forget addresses which are no longer announced, unless we are connected to them.
+        self.addrs
+            .retain(|(a, exp)| exp.is_none() || record.addrs.contains(a));
+        let addrs = record.addrs.clone();
+        self.record = Some(record);
+        for addr in addrs {
+            self.insert_not_connected(addr);
+        }
+        true
+    }
+}
+
 impl Default for Addresses {
     fn default() -> Self {
         Addresses::new()
@@ -159,9 +250,10 @@ impl fmt::Debug for Addresses {
 
 #[cfg(test)]
 mod tests {
-    use super::Addresses;
+    use super::{AddressRecord, Addresses};
     use libp2p::core::multiaddr;
     use std::{iter, thread, time::Duration};
+    use stegos_crypto::pbc;
 
     #[test]
     fn insert_connected_after_not_connected() {
@@ -254,4 +346,42 @@ mod tests {
             previous_loop_count = Some(num);
         }
     }
+
+    #[test]
+    fn signed_records() {
+        let (skey, pkey) = pbc::make_random_keys();
+        let old: libp2p::core::Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        let new: libp2p::core::Multiaddr = "/ip4/6.7.8.9/tcp/5".parse().unwrap();
+
+        let mut addrs = Addresses::new();
+        addrs.insert_not_connected(old.clone());
+
+        let record1 = AddressRecord::new(&skey, pkey, 1, vec![new.clone()]);
+        assert!(record1.is_valid());
+        assert!(addrs.update_record(record1.clone()));
+        assert_eq!(addrs.iter().cloned().collect::<Vec<_>>(), vec![new.clone()]);
+
+        // Unsigned hints can't bring back the old address.
+        addrs.insert_not_connected(old.clone());
+        assert_eq!(addrs.iter().count(), 1);
+
+        // Replays and rollbacks are rejected.
+        let record0 = AddressRecord::new(&skey, pkey, 0, vec![old.clone()]);
+        assert!(!addrs.update_record(record0));
+        assert!(!addrs.update_record(record1.clone()));
+        assert_eq!(addrs.record(), Some(&record1));
+
+        // Tampered records fail the signature check.
+        let mut forged = AddressRecord::new(&skey, pkey, 2, vec![new.clone()]);
+        forged.addrs = vec![old.clone()];
+        assert!(!forged.is_valid());
+        let (_other_skey, other_pkey) = pbc::make_random_keys();
+        let mut forged = AddressRecord::new(&skey, pkey, 2, vec![new.clone()]);
+        forged.node_id = other_pkey;
+        assert!(!forged.is_valid());
+
+        let record2 = AddressRecord::new(&skey, pkey, 2, vec![old.clone(), new.clone()]);
+        assert!(addrs.update_record(record2));
+        assert_eq!(addrs.iter().count(), 2);
+    }
 }
@@ -19,12 +19,19 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use super::addresses::Addresses;
+use super::addresses::{AddressRecord, Addresses};
 use super::handler::{KademliaHandler, KademliaHandlerEvent, KademliaHandlerIn, KademliaRequestId};
-use super::kbucket::{KBucketsTable, Update};
-use super::metrics::{KBUCKET_TABLE_SIZE, PEER_TABLE_SIZE};
-use super::protocol::{KadConnectionType, KadPeer};
-use super::query::{QueryConfig, QueryState, QueryStatePollOut, QueryTarget};
+use super::journal::{QueryJournal, QueryRecord};
+use super::kbucket::{AccessChecker, Diversity, KBucketsTable, StakeChecker, Subnet, Update};
+use super::metrics::{
+    KBUCKET_MAX_PER_SUBNET, KBUCKET_SUBNETS, KBUCKET_SUBNET_EVICTIONS, KBUCKET_TABLE_SIZE,
+    KBUCKET_UNSTAKED_SIZE, PEER_TABLE_SIZE, QUERY_DEDUP_HITS, REMOTE_REQUESTS_DROPPED,
+    REMOTE_REQUESTS_SIZE,
+};
+use super::protocol::{KadConnectionType, KadPeer, KademliaProtocolConfig};
+use super::query::{QueryConfig, QueryState, QueryStatePollOut, QueryTarget, QueryTimeout};
+use super::ratelimit::{RateLimitViolation, RemoteRequests};
+use super::wallet_record::WalletRecord;
 use fnv::{FnvHashMap, FnvHashSet};
 use futures::{prelude::*, stream};
 use libp2p::core::swarm::{
@@ -34,21 +41,35 @@ use libp2p::core::{protocols_handler::ProtocolsHandler, Multiaddr, PeerId};
 use libp2p::multihash::Multihash;
 use log::{debug, trace};
 use lru_time_cache::LruCache;
-use rand;
+use rand::{self, seq::SliceRandom};
+use serde_derive::Serialize;
 use smallvec::SmallVec;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::vec::IntoIter as VecIntoIter;
 use std::{cmp::Ordering, error, marker::PhantomData, time::Duration, time::Instant};
+use stegos_crypto::curve1174;
 use stegos_crypto::pbc;
 use stegos_crypto::utils::u8v_to_hexstr;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::timer::Interval;
+use tokio::timer::{Delay, Interval};
 
-use crate::utils::IntoMultihash;
+use crate::utils::{IntoMultihash, ProtocolVersion};
 
 // Buckets will be treated as expired, if they weren't touch during 5 minutes
 const BUCKET_EXPIRATION_PERIOD: u64 = 5 * 60;
 // At which interval update metrics (secs)
 const METRICS_UPDATE_INTERVAL: u64 = 1;
+// At which interval re-check stakes of known nodes (secs)
+const STAKES_REFRESH_INTERVAL: u64 = 10;
+// How many empty buckets to refresh after changing node's id
+const REKEY_REFRESH_BUCKETS: usize = 8;
+// Maximum number of initialization queries running at the same time
+const MAX_ACTIVE_INIT_QUERIES: usize = 8;
+// Number of initialization queries in a full bootstrap
+const BOOTSTRAP_QUERIES: u32 = 512;
+// Default time window to spread a full bootstrap over (secs)
+const DEFAULT_BOOTSTRAP_WINDOW: u64 = 30;
 
 /// Network behaviour that handles Kademlia.
 pub struct Kademlia<TSubstream> {
@@ -60,16 +81,33 @@ pub struct Kademlia<TSubstream> {
     /// Mapping PeerId -> pbc::PublicKey (we use Vec<u8> here, 'cause PeerId doesn't implement Ord)
     known_peers: LruCache<Vec<u8>, pbc::PublicKey>,
 
-    /// All the iterative queries we are currently performing, with their ID. The last parameter
-    /// is the list of accumulated providers for `GET_PROVIDERS` queries.
-    active_queries: FnvHashMap<QueryId, (QueryState, QueryPurpose, Vec<pbc::PublicKey>)>,
+    /// All the iterative queries we are currently performing, with their ID. The second parameter
+    /// is the list of callers waiting for the query, the last one is the list of accumulated
+    /// providers for `GET_PROVIDERS` queries.
+    active_queries: FnvHashMap<QueryId, (QueryState, Vec<QueryPurpose>, Vec<pbc::PublicKey>)>,
 
     /// List of queries to start once we are inside `poll()`.
-    queries_to_starts: SmallVec<[(QueryId, QueryTarget, QueryPurpose); 8]>,
+    queries_to_starts: SmallVec<[(QueryId, QueryTarget, Vec<QueryPurpose>); 8]>,
+
+    /// Minimal delay between starts of two initialization queries.
+    init_query_interval: Duration,
+
+    /// When the next initialization query can be started.
+    next_init_query: Instant,
+
+    /// Wakes up `poll()` when the next initialization query can be started.
+    init_query_timer: Delay,
+
+    /// In-flight (active or waiting to start) queries by their target.
+    /// Used to attach new callers to an existing query instead of starting a duplicate one.
+    queries_by_target: FnvHashMap<QueryTarget, QueryId>,
 
     /// List of peers the swarm is connected to.
     connected_peers: FnvHashSet<PeerId>,
 
+    /// Protocol versions negotiated with connected peers.
+    protocol_versions: FnvHashMap<PeerId, ProtocolVersion>,
+
     /// Contains a list of peer IDs which we are not connected to, and an RPC query to send to them
     /// once they connect.
     pending_rpcs: SmallVec<[(pbc::PublicKey, KademliaHandlerIn<QueryId>); 8]>,
@@ -78,7 +116,8 @@ pub struct Kademlia<TSubstream> {
     next_query_id: QueryId,
 
     /// Requests received by a remote that we should fulfill as soon as possible.
-    remote_requests: SmallVec<[(PeerId, KademliaRequestId, QueryTarget); 4]>,
+    /// Bounded and rate limited per peer, served in round-robin.
+    remote_requests: RemoteRequests<(KademliaRequestId, QueryTarget)>,
 
     /// List of values and peers that are providing them.
     ///
@@ -90,6 +129,10 @@ pub struct Kademlia<TSubstream> {
     /// `values_providers`.
     providing_keys: FnvHashSet<Multihash>,
 
+    /// Verified bindings of wallet keys to the nodes providing them, including ours.
+    /// When a bucket overflows, the least recently updated keys of other nodes are evicted.
+    wallet_records: KBucketsTable<Multihash, SmallVec<[WalletRecord; 4]>>,
+
     /// Interval to send `ADD_PROVIDER` messages to everyone.
     refresh_add_providers: stream::Fuse<Interval>,
 
@@ -103,6 +146,12 @@ pub struct Kademlia<TSubstream> {
     /// Timeout for each individual RPC query.
     rpc_timeout: Duration,
 
+    /// Overall deadline of an iterative query.
+    query_timeout: Duration,
+
+    /// An iterative query is abandoned if no RPC succeeds for so long.
+    stall_timeout: Duration,
+
     /// Events to return when polling.
     queued_events: SmallVec<[NetworkBehaviourAction<KademliaHandlerIn<QueryId>, KademliaOut>; 32]>,
 
@@ -112,6 +161,21 @@ pub struct Kademlia<TSubstream> {
     /// When metrics were updated last time
     metrics_last_update: Instant,
 
+    /// When stakes of known nodes were checked last time
+    stakes_last_refresh: Instant,
+
+    /// Configuration of the protocol upgrade for new connections.
+    protocol_config: KademliaProtocolConfig,
+
+    /// Network key used to sign announcements of our addresses.
+    network_skey: Option<pbc::SecretKey>,
+
+    /// The newest signed announcement of our addresses.
+    local_record: Option<AddressRecord>,
+
+    /// Recent queries, for diagnostics.
+    journal: QueryJournal,
+
     /// Marker to pin the generics.
     marker: PhantomData<TSubstream>,
 }
@@ -143,6 +207,36 @@ impl NodeInfo {
     }
 }
 
+/// Snapshot of a non-empty k-bucket, returned by `Kademlia::dump_buckets()`.
+#[derive(Clone, Debug, Serialize)]
+pub struct BucketInfo {
+    /// Index of the bucket, i.e. the distance to the local node minus one.
+    pub index: usize,
+    /// Last time any of the entries in this bucket was updated.
+    pub latest_update: SystemTime,
+    /// True if some node is waiting for a free slot in this bucket.
+    pub has_pending: bool,
+    /// Number of distinct IP subnets of the entries.
+    pub subnets: usize,
+    /// Entries, ordered from oldest to newest.
+    pub entries: Vec<BucketEntry>,
+}
+
+/// A single entry of the routing table.
+#[derive(Clone, Debug, Serialize)]
+pub struct BucketEntry {
+    pub node_id: pbc::PublicKey,
+    /// Base58-encoded PeerId, if known.
+    pub peer_id: Option<String>,
+    pub addresses: Vec<String>,
+    pub connected: bool,
+    pub last_update: SystemTime,
+    /// Kademlia protocol version negotiated with the connected node.
+    pub kad_version: Option<ProtocolVersion>,
+    /// Gossip protocol version negotiated with the connected node.
+    pub pubsub_version: Option<ProtocolVersion>,
+}
+
 /// Opaque type. Each query that we start gets a unique number.
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct QueryId(usize);
@@ -156,6 +250,19 @@ enum QueryPurpose {
     UserRequest,
     /// We should add an `ADD_PROVIDER` message to the peers of the outcome.
     AddProvider(Multihash),
+    /// The user looks for nodes controlling the wallet key.
+    WalletLookup(curve1174::PublicKey),
+}
+
+impl QueryPurpose {
+    fn name(&self) -> &'static str {
+        match self {
+            QueryPurpose::Initialization => "initialization",
+            QueryPurpose::UserRequest => "user_request",
+            QueryPurpose::AddProvider(_) => "add_provider",
+            QueryPurpose::WalletLookup(_) => "wallet_lookup",
+        }
+    }
 }
 
 impl<TSubstream> Kademlia<TSubstream> {
@@ -181,9 +288,39 @@ impl<TSubstream> Kademlia<TSubstream> {
     }
 
     /// Change node's id (pbc::PublicKey)
+    ///
+    /// Known nodes are moved to the buckets matching the new id. Instead of a full
+    /// bootstrap, only a lookup of the new id and of a few most distant empty buckets
+    /// is started.
     pub fn change_id(&mut self, new_id: pbc::PublicKey) {
         self.kbuckets = self.kbuckets.new_table(new_id.clone());
+        self.wallet_records = self
+            .wallet_records
+            .new_table(new_id.clone().into_multihash());
         self.my_id = new_id;
+
+        let my_hash = self.kbuckets.my_id().into_multihash();
+        let empty_buckets: Vec<usize> = self
+            .kbuckets
+            .buckets()
+            .enumerate()
+            .filter(|(_, b)| b.num_entries() == 0)
+            .map(|(n, _)| n)
+            .collect();
+        self.start_query(
+            QueryTarget::FindPeer(my_hash.clone()),
+            QueryPurpose::Initialization,
+        );
+        for n in empty_buckets.into_iter().rev().take(REKEY_REFRESH_BUCKETS) {
+            let random_hash = match gen_random_hash(&my_hash, n) {
+                Ok(p) => p,
+                Err(()) => continue,
+            };
+            self.start_query(
+                QueryTarget::FindPeer(random_hash),
+                QueryPurpose::Initialization,
+            );
+        }
     }
 
     #[inline]
@@ -218,6 +355,7 @@ impl<TSubstream> Kademlia<TSubstream> {
         if let Some(node_info) = self.kbuckets.entry_mut(node_id) {
             node_info.addresses.insert_connected(address);
         }
+        self.update_subnet(node_id);
     }
 
     /// Adds a known address for the given `PeerId`. We are not connected or don't know whether we
@@ -226,6 +364,129 @@ impl<TSubstream> Kademlia<TSubstream> {
         if let Some(node_info) = self.kbuckets.entry_mut(node_id) {
             node_info.addresses.insert_not_connected(address);
         }
+        self.update_subnet(node_id);
+    }
+
+    /// Updates the IP subnet of the node from its addresses.
+    /// The node is evicted from the routing table if its bucket has too many nodes from the subnet.
+    fn update_subnet(&mut self, node_id: &pbc::PublicKey) {
+        let subnet = match self.kbuckets.get(node_id) {
+            Some(node_info) => node_info
+                .addresses
+                .iter()
+                .filter_map(Subnet::from_multiaddr)
+                .next(),
+            None => return,
+        };
+        if !self.kbuckets.set_subnet(node_id, subnet) {
+            debug!(target: "stegos_network::kad", "evicted node from an overrepresented subnet: node_id={}, subnet={:?}", node_id, subnet);
+            KBUCKET_SUBNET_EVICTIONS.inc();
+        }
+    }
+
+    /// Queues a request from the remote peer, unless the peer exceeded rate limits.
+    /// Violations are reported upwards to penalize the peer.
+    fn queue_remote_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: KademliaRequestId,
+        target: QueryTarget,
+    ) {
+        let result =
+            self.remote_requests
+                .push(peer_id.clone(), (request_id, target), Instant::now());
+        if let Err(violation) = result {
+            debug!(target: "stegos_network::kad", "dropped request from peer: peer_id={}, violation={:?}", peer_id, violation);
+            REMOTE_REQUESTS_DROPPED.inc();
+            self.queued_events
+                .push(NetworkBehaviourAction::GenerateEvent(
+                    KademliaOut::RateLimited { peer_id, violation },
+                ));
+        }
+    }
+
+    /// Returns address diversity of the routing table.
+    pub fn diversity(&self) -> Diversity {
+        self.kbuckets.diversity()
+    }
+
+    /// Sets the network key used to sign announcements of our addresses.
+    ///
+    /// Must match the current node id, otherwise announcements are not sent.
+    pub fn set_network_skey(&mut self, skey: pbc::SecretKey) {
+        self.network_skey = Some(skey);
+        self.local_record = None;
+    }
+
+    /// Applies a signed announcement of addresses received from the network.
+    /// Returns false if the record is invalid or older than the known one.
+    pub fn update_address_record(&mut self, record: AddressRecord) -> bool {
+        if record.node_id == self.my_id || !record.is_valid() {
+            return false;
+        }
+        let node_id = record.node_id;
+        let updated = match self.kbuckets.entry_mut(&node_id) {
+            Some(node_info) => node_info.addresses.update_record(record),
+            None => false,
+        };
+        if updated {
+            self.update_subnet(&node_id);
+        }
+        updated
+    }
+
+    /// Re-signs announcement of our addresses if they have changed.
+    fn refresh_local_record(&mut self, parameters: &mut PollParameters<'_>) {
+        let skey = match &self.network_skey {
+            Some(skey) => skey,
+            None => return,
+        };
+        let addrs: Vec<Multiaddr> = parameters.external_addresses().cloned().collect();
+        let prev_seq = match &self.local_record {
+            Some(r) if r.node_id == self.my_id && r.addrs == addrs => return,
+            Some(r) if r.node_id == self.my_id => r.seq,
+            _ => 0,
+        };
+        // Use wall clock as sequence to survive restarts, but never go back.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let seq = std::cmp::max(now, prev_seq + 1);
+        let record = AddressRecord::new(skey, self.my_id.clone(), seq, addrs);
+        debug!(target: "stegos_network::kad", "announcing new addresses: seq={}, addrs={:?}", seq, record.addrs);
+        self.local_record = Some(record);
+    }
+
+    /// Sets the time window to spread initialization queries of a full bootstrap over.
+    pub fn set_bootstrap_window(&mut self, window: Duration) {
+        self.init_query_interval = window / BOOTSTRAP_QUERIES;
+    }
+
+    /// Restricts the routing table to nodes of staked validators.
+    /// Other nodes are kept in a small separate table and never returned to other peers.
+    pub fn set_stake_checker(&mut self, stake_checker: Arc<dyn StakeChecker>) {
+        self.kbuckets.set_stake_checker(stake_checker);
+    }
+
+    /// Keeps nodes refused by the operator out of the routing table.
+    pub fn set_access_checker(&mut self, access_checker: Arc<dyn AccessChecker>) {
+        self.kbuckets.set_access_checker(access_checker);
+    }
+
+    /// Replaces the journal of queries.
+    pub fn set_query_journal(&mut self, journal: QueryJournal) {
+        self.journal = journal;
+    }
+
+    /// Returns recent queries, see `QueryJournal::records()`.
+    pub fn query_journal(&self) -> Vec<QueryRecord> {
+        self.journal.records()
+    }
+
+    /// Enables or disables snappy compression for new connections.
+    pub fn set_compression(&mut self, compression: bool) {
+        self.protocol_config = KademliaProtocolConfig::new(compression);
     }
 
     /// Inner implementation of the constructors.
@@ -235,25 +496,41 @@ impl<TSubstream> Kademlia<TSubstream> {
         let mut behaviour = Kademlia {
             my_id: local_node_id.clone(),
             kbuckets: KBucketsTable::new(
-                local_node_id,
+                local_node_id.clone(),
                 Duration::from_secs(BUCKET_EXPIRATION_PERIOD),
             ),
             known_peers: LruCache::<Vec<u8>, pbc::PublicKey>::with_capacity(512 * (20 + 1)), // Total size of kBucketsTable
             queued_events: SmallVec::new(),
             queries_to_starts: SmallVec::new(),
             active_queries: Default::default(),
+            init_query_interval: Duration::from_secs(DEFAULT_BOOTSTRAP_WINDOW) / BOOTSTRAP_QUERIES,
+            next_init_query: Instant::now(),
+            init_query_timer: Delay::new(Instant::now()),
+            queries_by_target: Default::default(),
             connected_peers: Default::default(),
+            protocol_versions: Default::default(),
             pending_rpcs: SmallVec::with_capacity(parallelism),
             next_query_id: QueryId(0),
-            remote_requests: SmallVec::new(),
+            remote_requests: RemoteRequests::new(),
             values_providers: FnvHashMap::default(),
             providing_keys: FnvHashSet::default(),
+            wallet_records: KBucketsTable::new(
+                local_node_id.into_multihash(),
+                Duration::from_secs(BUCKET_EXPIRATION_PERIOD),
+            ),
             refresh_add_providers: Interval::new_interval(Duration::from_secs(60)).fuse(), // TODO: constant
             parallelism,
             num_results: 20,
             rpc_timeout: Duration::from_secs(8),
+            query_timeout: Duration::from_secs(60),
+            stall_timeout: Duration::from_secs(20),
             add_provider: SmallVec::new(),
             metrics_last_update: Instant::now(),
+            stakes_last_refresh: Instant::now(),
+            protocol_config: Default::default(),
+            network_skey: None,
+            local_record: None,
+            journal: QueryJournal::disabled(),
             marker: PhantomData,
         };
 
@@ -290,7 +567,14 @@ impl<TSubstream> Kademlia<TSubstream> {
                     .kbuckets
                     .find_closest_with_self(&key)
                     .take(self.num_results)
-                    .map(|node_id| build_kad_peer(node_id, parameters, &self.kbuckets))
+                    .map(|node_id| {
+                        build_kad_peer(
+                            node_id,
+                            parameters,
+                            &self.kbuckets,
+                            self.local_record.as_ref(),
+                        )
+                    })
                     .collect();
                 trace!(target: "stegos_network::kad", "sending FindNodeRes with: {:#?}", closer_peers);
                 KademliaHandlerIn::FindNodeRes {
@@ -303,7 +587,14 @@ impl<TSubstream> Kademlia<TSubstream> {
                     .kbuckets
                     .find_closest_with_self(&key)
                     .take(self.num_results)
-                    .map(|node_id| build_kad_peer(node_id, parameters, &self.kbuckets))
+                    .map(|node_id| {
+                        build_kad_peer(
+                            node_id,
+                            parameters,
+                            &self.kbuckets,
+                            self.local_record.as_ref(),
+                        )
+                    })
                     .collect();
 
                 let provider_peers = self
@@ -311,7 +602,17 @@ impl<TSubstream> Kademlia<TSubstream> {
                     .get(&key)
                     .into_iter()
                     .flat_map(|peers| peers)
-                    .map(|node_id| build_kad_peer(node_id.clone(), parameters, &self.kbuckets))
+                    .map(|node_id| {
+                        let mut peer = build_kad_peer(
+                            node_id.clone(),
+                            parameters,
+                            &self.kbuckets,
+                            self.local_record.as_ref(),
+                        );
+                        peer.wallet_record =
+                            find_wallet_record(&self.wallet_records, &key, node_id);
+                        peer
+                    })
                     .collect();
 
                 KademliaHandlerIn::GetProvidersRes {
@@ -343,6 +644,82 @@ impl<TSubstream> Kademlia<TSubstream> {
         self.kbuckets.size()
     }
 
+    /// Returns a snapshot of all non-empty buckets of the routing table.
+    pub fn dump_buckets(&mut self) -> Vec<BucketInfo> {
+        let now = SystemTime::now();
+        let to_system_time = |instant: Instant| now - instant.elapsed();
+        let protocol_versions = &self.protocol_versions;
+        self.kbuckets
+            .buckets()
+            .enumerate()
+            .filter(|(_index, bucket)| bucket.num_entries() > 0)
+            .map(|(index, bucket)| BucketInfo {
+                index,
+                latest_update: to_system_time(bucket.latest_update()),
+                has_pending: bucket.has_pending(),
+                subnets: bucket.num_subnets(),
+                entries: bucket
+                    .entries()
+                    .map(|(node_id, node_info, connected, last_update)| BucketEntry {
+                        node_id: node_id.clone(),
+                        peer_id: node_info.peer_id.as_ref().map(|p| p.to_base58()),
+                        addresses: node_info.addresses.iter().map(|a| a.to_string()).collect(),
+                        connected,
+                        last_update: to_system_time(last_update),
+                        kad_version: node_info
+                            .peer_id
+                            .as_ref()
+                            .and_then(|p| protocol_versions.get(p).cloned()),
+                        pubsub_version: None,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Returns nodes of the routing table with known peer ids and addresses.
+    pub fn known_nodes(&mut self) -> Vec<(pbc::PublicKey, PeerId, Vec<Multiaddr>)> {
+        let mut nodes = Vec::new();
+        for bucket in self.kbuckets.buckets() {
+            for (node_id, node_info, _connected, _last_update) in bucket.entries() {
+                if let Some(peer_id) = &node_info.peer_id {
+                    let addresses: Vec<Multiaddr> = node_info.addresses.iter().cloned().collect();
+                    if !addresses.is_empty() {
+                        nodes.push((node_id.clone(), peer_id.clone(), addresses));
+                    }
+                }
+            }
+        }
+        nodes
+    }
+
+    /// Returns signed address records of up to `limit` random nodes from the routing table.
+    /// Nodes we are connected to are preferred.
+    pub fn address_records(&mut self, limit: usize) -> Vec<(PeerId, AddressRecord)> {
+        let mut connected = Vec::new();
+        let mut disconnected = Vec::new();
+        for bucket in self.kbuckets.buckets() {
+            for (_node_id, node_info, is_connected, _last_update) in bucket.entries() {
+                if let (Some(peer_id), Some(record)) =
+                    (&node_info.peer_id, node_info.addresses.record())
+                {
+                    let entry = (peer_id.clone(), record.clone());
+                    if is_connected {
+                        connected.push(entry);
+                    } else {
+                        disconnected.push(entry);
+                    }
+                }
+            }
+        }
+        let mut rng = rand::thread_rng();
+        connected.shuffle(&mut rng);
+        disconnected.shuffle(&mut rng);
+        connected.append(&mut disconnected);
+        connected.truncate(limit);
+        connected
+    }
+
     /// Starts an iterative `GET_PROVIDERS` request.
     #[inline]
     pub fn get_providers(&mut self, key: Multihash) {
@@ -358,10 +735,14 @@ impl<TSubstream> Kademlia<TSubstream> {
     /// The actual meaning of *providing* the value of a key is not defined, and is specific to
     /// the value whose key is the hash.
     pub fn add_providing(&mut self, key: pbc::PublicKey) {
-        self.providing_keys.insert(key.clone().into_multihash());
+        self.start_providing(key.into_multihash());
+    }
+
+    fn start_providing(&mut self, key: Multihash) {
+        self.providing_keys.insert(key.clone());
         let providers = self
             .values_providers
-            .entry(key.into_multihash())
+            .entry(key)
             .or_insert_with(Default::default);
         let my_id = self.kbuckets.my_id();
         if !providers.iter().any(|k| k == my_id) {
@@ -372,6 +753,56 @@ impl<TSubstream> Kademlia<TSubstream> {
         self.refresh_add_providers = Interval::new(Instant::now(), Duration::from_secs(60)).fuse();
     }
 
+    /// Announces that the local node controls the wallet key of the record.
+    ///
+    /// The record is attached to our `ADD_PROVIDER` messages and to `GET_PROVIDERS` responses,
+    /// so other nodes can verify the binding without trusting the DHT.
+    /// Returns false if the record is invalid or made for another node.
+    pub fn publish_wallet(&mut self, record: WalletRecord) -> bool {
+        if record.node_id != self.my_id || !record.is_valid() {
+            return false;
+        }
+        let key = record.key();
+        self.insert_wallet_record(&key, record);
+        self.start_providing(key);
+        true
+    }
+
+    /// Starts looking for network nodes controlling the wallet key.
+    ///
+    /// This will eventually produce `KademliaOut::FindNodeByWalletResult` with the nodes whose
+    /// binding signatures have been verified.
+    pub fn find_node_by_wallet(&mut self, wallet_pkey: curve1174::PublicKey) {
+        self.start_query(
+            QueryTarget::GetProviders(wallet_pkey.into_multihash()),
+            QueryPurpose::WalletLookup(wallet_pkey),
+        );
+    }
+
+    /// Stores a binding received from the network.
+    /// Returns false if the record doesn't match the key or its signature is invalid.
+    fn insert_wallet_record(&mut self, key: &Multihash, record: WalletRecord) -> bool {
+        if record.key() != *key || !record.is_valid() {
+            debug!(target: "stegos_network::kad", "invalid wallet record: {:?}", record);
+            return false;
+        }
+        let my_id = self.my_id;
+        let records = match self.wallet_records.entry_mut_or_evict(key, |_, records| {
+            !records.iter().any(|r| r.node_id == my_id)
+        }) {
+            Some(records) => records,
+            None => {
+                debug!(target: "stegos_network::kad", "no room for wallet record: {:?}", record);
+                return false;
+            }
+        };
+        match records.iter_mut().find(|r| r.node_id == record.node_id) {
+            Some(r) => *r = record,
+            None => records.push(record),
+        }
+        true
+    }
+
     /// Cancels a registration done with `add_providing`.
     ///
     /// There doesn't exist any "remove provider" message to broadcast on the network, therefore we
@@ -393,10 +824,33 @@ impl<TSubstream> Kademlia<TSubstream> {
     }
 
     /// Internal function that starts a query.
+    ///
+    /// If a query with the same target is already in flight, the purpose is attached to it
+    /// and results are delivered to every caller once the query completes.
     fn start_query(&mut self, target: QueryTarget, purpose: QueryPurpose) {
+        if let Some(&query_id) = self.queries_by_target.get(&target) {
+            let purposes = if let Some((_, purposes, _)) = self.active_queries.get_mut(&query_id) {
+                Some(purposes)
+            } else {
+                self.queries_to_starts
+                    .iter_mut()
+                    .find(|(id, _, _)| *id == query_id)
+                    .map(|(_, _, purposes)| purposes)
+            };
+            if let Some(purposes) = purposes {
+                debug!(target: "stegos_network::kad", "Attaching to in-flight query: query_id={:?}, purpose={:?}", query_id, purpose);
+                QUERY_DEDUP_HITS.inc();
+                if !purposes.contains(&purpose) {
+                    purposes.push(purpose);
+                }
+                return;
+            }
+        }
         let query_id = self.next_query_id;
         self.next_query_id.0 += 1;
-        self.queries_to_starts.push((query_id, target, purpose));
+        self.queries_by_target.insert(target.clone(), query_id);
+        self.queries_to_starts
+            .push((query_id, target, vec![purpose]));
     }
 }
 
@@ -408,7 +862,7 @@ where
     type OutEvent = KademliaOut;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        KademliaHandler::dial_and_listen()
+        KademliaHandler::with_config(self.protocol_config)
     }
 
     fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
@@ -452,9 +906,11 @@ where
         }
 
         if let ConnectedPoint::Dialer { address } = endpoint {
+            let node_id = node_id.clone();
             if let Some(node_info) = self.kbuckets.entry_mut(&node_id) {
                 node_info.addresses.insert_connected(address);
             }
+            self.update_subnet(&node_id);
         }
     }
 
@@ -486,6 +942,7 @@ where
             Some(id) => id,
             None => return,
         };
+        self.journal.node_failed(node_id, "dial failure");
         for query in self.active_queries.values_mut() {
             query.0.inject_rpc_error(node_id);
         }
@@ -494,12 +951,15 @@ where
     fn inject_disconnected(&mut self, id: &PeerId, old_endpoint: ConnectedPoint) {
         let was_in = self.connected_peers.remove(id);
         debug_assert!(was_in);
+        self.protocol_versions.remove(id);
+        self.remote_requests.remove_peer(id);
         let peer_id = id.clone().into_bytes();
         let node_id = match self.known_peers.get(&peer_id) {
             Some(id) => id,
             None => return,
         };
 
+        self.journal.node_failed(&node_id, "disconnected");
         for (query, _, _) in self.active_queries.values_mut() {
             query.inject_rpc_error(&node_id);
         }
@@ -541,17 +1001,22 @@ where
         }
 
         if let ConnectedPoint::Dialer { address } = new_endpoint {
+            let node_id = node_id.clone();
             if let Some(node_info) = self.kbuckets.entry_mut(&node_id) {
                 node_info.addresses.insert_connected(address);
             }
+            self.update_subnet(&node_id);
         }
     }
 
     fn inject_node_event(&mut self, source: PeerId, event: KademliaHandlerEvent<QueryId>) {
         match event {
+            KademliaHandlerEvent::ProtocolVersion { version } => {
+                debug!(target: "stegos_network::kad", "negotiated protocol: peer_id={}, version={}", source, version);
+                self.protocol_versions.insert(source, version);
+            }
             KademliaHandlerEvent::FindNodeReq { key, request_id } => {
-                self.remote_requests
-                    .push((source, request_id, QueryTarget::FindPeer(key)));
+                self.queue_remote_request(source, request_id, QueryTarget::FindPeer(key));
                 return;
             }
             KademliaHandlerEvent::FindNodeRes {
@@ -561,6 +1026,11 @@ where
                 // It is possible that we obtain a response for a query that has finished, which is
                 // why we may not find an entry in `self.active_queries`.
                 for peer in closer_peers.iter() {
+                    if let Some(record) = &peer.record {
+                        if record.node_id == peer.node_id {
+                            self.update_address_record(record.clone());
+                        }
+                    }
                     let peer_id = match &peer.peer_id {
                         Some(p) => Some(p.clone()),
                         None => None,
@@ -579,6 +1049,7 @@ where
                     let peer_key = source.into_bytes();
                     let my_id = self.my_id;
                     if let Some(node_id) = self.known_peers.get(&peer_key) {
+                        self.journal.rpc_succeeded(user_data.0, &node_id);
                         query.inject_rpc_result(
                             &node_id,
                             closer_peers.into_iter().filter_map(|kp| {
@@ -593,8 +1064,7 @@ where
                 }
             }
             KademliaHandlerEvent::GetProvidersReq { key, request_id } => {
-                self.remote_requests
-                    .push((source, request_id, QueryTarget::GetProviders(key)));
+                self.queue_remote_request(source, request_id, QueryTarget::GetProviders(key));
                 return;
             }
             KademliaHandlerEvent::GetProvidersRes {
@@ -603,6 +1073,11 @@ where
                 user_data,
             } => {
                 for peer in closer_peers.iter().chain(provider_peers.iter()) {
+                    if let Some(record) = &peer.record {
+                        if record.node_id == peer.node_id {
+                            self.update_address_record(record.clone());
+                        }
+                    }
                     let peer_id = match &peer.peer_id {
                         Some(p) => Some(p.clone()),
                         None => None,
@@ -620,12 +1095,27 @@ where
 
                 // It is possible that we obtain a response for a query that has finished, which is
                 // why we may not find an entry in `self.active_queries`.
+                let key = match self.active_queries.get(&user_data) {
+                    Some((query, _, _)) => match query.target() {
+                        QueryTarget::GetProviders(key) => Some(key.clone()),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                if let Some(key) = key {
+                    for peer in provider_peers.iter() {
+                        if let Some(wallet_record) = &peer.wallet_record {
+                            self.insert_wallet_record(&key, wallet_record.clone());
+                        }
+                    }
+                }
                 if let Some((query, _, providers)) = self.active_queries.get_mut(&user_data) {
                     for peer in provider_peers {
                         providers.push(peer.node_id);
                     }
                     let peer_key = source.into_bytes();
                     if let Some(node_id) = self.known_peers.get(&peer_key) {
+                        self.journal.rpc_succeeded(user_data.0, &node_id);
                         query.inject_rpc_result(
                             &node_id,
                             closer_peers.into_iter().map(|kp| kp.node_id),
@@ -633,17 +1123,24 @@ where
                     }
                 }
             }
-            KademliaHandlerEvent::QueryError { user_data, .. } => {
+            KademliaHandlerEvent::QueryError { user_data, error } => {
                 // It is possible that we obtain a response for a query that has finished, which is
                 // why we may not find an entry in `self.active_queries`.
                 if let Some((query, _, _)) = self.active_queries.get_mut(&user_data) {
                     let peer_key = source.into_bytes();
                     if let Some(node_id) = self.known_peers.get(&peer_key) {
+                        self.journal
+                            .rpc_failed(user_data.0, &node_id, &error.to_string());
                         query.inject_rpc_error(&node_id)
                     }
                 }
             }
             KademliaHandlerEvent::AddProvider { key, provider_peer } => {
+                if let Some(record) = &provider_peer.record {
+                    if record.node_id == provider_peer.node_id {
+                        self.update_address_record(record.clone());
+                    }
+                }
                 let peer_id = match provider_peer.peer_id {
                     Some(p) => Some(p.clone()),
                     None => None,
@@ -657,6 +1154,9 @@ where
                             ty: provider_peer.connection_ty,
                         },
                     ));
+                if let Some(wallet_record) = provider_peer.wallet_record {
+                    self.insert_wallet_record(&key, wallet_record);
+                }
                 self.add_provider.push((key, provider_peer.node_id));
                 return;
             }
@@ -676,7 +1176,18 @@ where
         if self.metrics_last_update.elapsed() > Duration::from_secs(METRICS_UPDATE_INTERVAL) {
             self.metrics_last_update = Instant::now();
             KBUCKET_TABLE_SIZE.set(self.kbuckets.size() as i64);
+            let diversity = self.kbuckets.diversity();
+            KBUCKET_SUBNETS.set(diversity.subnets as i64);
+            KBUCKET_MAX_PER_SUBNET.set(diversity.max_per_subnet as i64);
             PEER_TABLE_SIZE.set(self.known_peers.len() as i64);
+            KBUCKET_UNSTAKED_SIZE.set(self.kbuckets.unstaked_size() as i64);
+            REMOTE_REQUESTS_SIZE.set(self.remote_requests.len() as i64);
+            self.remote_requests.prune(Instant::now());
+        }
+        // Validators change every epoch.
+        if self.stakes_last_refresh.elapsed() > Duration::from_secs(STAKES_REFRESH_INTERVAL) {
+            self.stakes_last_refresh = Instant::now();
+            self.kbuckets.refresh_stakes();
         }
         // Flush the changes to the topology that we want to make.
         for (key, provider) in self.add_provider.drain() {
@@ -694,6 +1205,9 @@ where
         }
         self.add_provider.shrink_to_fit();
 
+        // Re-sign announcement of our addresses if needed.
+        self.refresh_local_record(parameters);
+
         // Handle `refresh_add_providers`.
         match self.refresh_add_providers.poll() {
             Ok(Async::NotReady) => {}
@@ -708,14 +1222,42 @@ where
         }
 
         // Start queries that are waiting to start.
+        // User requests are started immediately, initialization queries are capped
+        // and spread over the bootstrap window.
         let table_size = self.ktable_size();
-        for (query_id, query_target, query_purpose) in self.queries_to_starts.drain() {
+        let now = Instant::now();
+        let mut active_init_queries = self
+            .active_queries
+            .values()
+            .filter(|(_, purposes, _)| is_background(purposes))
+            .count();
+        let mut delayed_init_queries = false;
+        let mut i = 0;
+        while i < self.queries_to_starts.len() {
+            if is_background(&self.queries_to_starts[i].2) {
+                if active_init_queries >= MAX_ACTIVE_INIT_QUERIES {
+                    i += 1;
+                    continue;
+                } else if now < self.next_init_query {
+                    delayed_init_queries = true;
+                    i += 1;
+                    continue;
+                }
+                active_init_queries += 1;
+                self.next_init_query = now + self.init_query_interval;
+            }
+            let (query_id, query_target, query_purposes) = self.queries_to_starts.remove(i);
             debug!(target: "stegos_network::kad", "Starting query: query_id={:?}, target={}, table_size={}", query_id, u8v_to_hexstr(query_target.as_hash().as_bytes()), table_size);
             let known_closest_peers = self
                 .kbuckets
                 .find_closest(&query_target.as_hash())
                 .take(self.num_results);
             trace!(target: "stegos_network::kad", "Known peers for query: query_id={:?}, known_closest_peers={:#?}", query_id, known_closest_peers);
+            let purposes = query_purposes
+                .iter()
+                .map(|p| p.name().to_string())
+                .collect();
+            self.journal.start(query_id.0, &query_target, purposes);
             self.active_queries.insert(
                 query_id,
                 (
@@ -724,18 +1266,23 @@ where
                         parallelism: self.parallelism,
                         num_results: self.num_results,
                         rpc_timeout: self.rpc_timeout,
+                        query_timeout: self.query_timeout,
+                        stall_timeout: self.stall_timeout,
                         known_closest_peers,
                     }),
-                    query_purpose,
+                    query_purposes,
                     Vec::new(), // TODO: insert ourselves if we provide the data?
                 ),
             );
         }
-        self.queries_to_starts.shrink_to_fit();
+        if delayed_init_queries {
+            self.init_query_timer.reset(self.next_init_query);
+            // Register the current task to be woken up.
+            let _ = self.init_query_timer.poll();
+        }
 
         // Handle remote queries.
-        if !self.remote_requests.is_empty() {
-            let (peer_id, request_id, query) = self.remote_requests.remove(0);
+        if let Some((peer_id, (request_id, query))) = self.remote_requests.pop() {
             let result = self.build_result(query, request_id, parameters);
             return Async::Ready(NetworkBehaviourAction::SendEvent {
                 peer_id,
@@ -751,14 +1298,30 @@ where
             self.queued_events.shrink_to_fit();
 
             // If iterating finds a query that is finished, stores it here and stops looping.
-            let mut finished_query = None;
+            // The second element is set if the query was abandoned.
+            let mut finished_query: Option<(QueryId, Option<QueryTimeout>)> = None;
             let mut nodes_without_peerids: Vec<pbc::PublicKey> = Vec::new();
 
-            'queries_iter: for (&query_id, (query, _, _)) in self.active_queries.iter_mut() {
+            // User requests are polled before initialization queries.
+            let mut query_ids: Vec<(bool, QueryId)> = self
+                .active_queries
+                .iter()
+                .map(|(&query_id, (_, purposes, _))| (is_background(purposes), query_id))
+                .collect();
+            query_ids.sort_by_key(|(background, _)| *background);
+            'queries_iter: for (_, query_id) in query_ids {
+                let query = match self.active_queries.get_mut(&query_id) {
+                    Some((query, _, _)) => query,
+                    None => continue,
+                };
                 loop {
                     match query.poll() {
                         Async::Ready(QueryStatePollOut::Finished) => {
-                            finished_query = Some(query_id);
+                            finished_query = Some((query_id, None));
+                            break 'queries_iter;
+                        }
+                        Async::Ready(QueryStatePollOut::TimedOut { reason }) => {
+                            finished_query = Some((query_id, Some(reason)));
                             break 'queries_iter;
                         }
                         Async::Ready(QueryStatePollOut::SendRpc {
@@ -767,6 +1330,7 @@ where
                         }) => {
                             debug!(target: "stegos_network::kad", "got request to connect: node_id={}, target={}",
                                 node_id, u8v_to_hexstr(query_target.as_hash().as_bytes()));
+                            self.journal.rpc_sent(query_id.0, node_id);
                             let rpc = query_target.to_rpc_request(query_id);
                             let target_peer = {
                                 match self.kbuckets.get(&node_id) {
@@ -797,6 +1361,7 @@ where
                             }
                         }
                         Async::Ready(QueryStatePollOut::CancelRpc { node_id }) => {
+                            self.journal.rpc_failed(query_id.0, node_id, "timeout");
                             // We don't cancel if the RPC has already been sent out.
                             self.pending_rpcs.retain(|(id, _)| id != node_id);
                         }
@@ -807,58 +1372,99 @@ where
 
             if !nodes_without_peerids.is_empty() {
                 for node in nodes_without_peerids.iter() {
+                    self.journal.node_failed(node, "unknown peer_id");
                     for (query, _, _) in self.active_queries.values_mut() {
                         query.inject_rpc_error(&node);
                     }
                 }
             }
 
-            if let Some(finished_query) = finished_query {
-                let (query, purpose, provider_peers) = self
+            if let Some((finished_query, timeout)) = finished_query {
+                let (query, purposes, provider_peers) = self
                     .active_queries
                     .remove(&finished_query)
                     .expect("finished_query was gathered when iterating active_queries; QED.");
-                match purpose {
-                    QueryPurpose::Initialization => {}
-                    QueryPurpose::UserRequest => {
-                        let event = match query.target().clone() {
-                            QueryTarget::FindPeer(key) => {
-                                debug_assert!(provider_peers.is_empty());
-                                KademliaOut::FindNodeResult {
-                                    key,
-                                    closer_peers: query.into_closest_peers().collect(),
+                let target = query.target().clone();
+                self.queries_by_target.remove(&target);
+                let closer_peers: Vec<pbc::PublicKey> = query.into_closest_peers().collect();
+                if let Some(reason) = timeout {
+                    self.journal.timed_out(finished_query.0, reason);
+                }
+                self.journal
+                    .finish(finished_query.0, closer_peers.len(), provider_peers.len());
+                for purpose in purposes {
+                    match purpose {
+                        QueryPurpose::Initialization => {}
+                        QueryPurpose::UserRequest => {
+                            let event = match (target.clone(), timeout) {
+                                (_, Some(reason)) => KademliaOut::QueryTimedOut {
+                                    key: target.as_hash(),
+                                    reason,
+                                    closer_peers: closer_peers.clone(),
+                                    provider_peers: provider_peers.clone(),
+                                },
+                                (QueryTarget::FindPeer(key), None) => {
+                                    debug_assert!(provider_peers.is_empty());
+                                    KademliaOut::FindNodeResult {
+                                        key,
+                                        closer_peers: closer_peers.clone(),
+                                    }
+                                }
+                                (QueryTarget::GetProviders(key), None) => {
+                                    KademliaOut::GetProvidersResult {
+                                        key,
+                                        closer_peers: closer_peers.clone(),
+                                        provider_peers: provider_peers.clone(),
+                                    }
                                 }
-                            }
-                            QueryTarget::GetProviders(key) => KademliaOut::GetProvidersResult {
-                                key,
-                                closer_peers: query.into_closest_peers().collect(),
-                                provider_peers,
-                            },
-                        };
-
-                        break Async::Ready(NetworkBehaviourAction::GenerateEvent(event));
-                    }
-                    QueryPurpose::AddProvider(key) => {
-                        for closest in query.into_closest_peers() {
-                            let node_info = match self.kbuckets.get(&closest) {
-                                Some(n) => n,
-                                None => continue,
                             };
-                            if let Some(peer_id) = &node_info.peer_id {
-                                let event = NetworkBehaviourAction::SendEvent {
-                                    peer_id: peer_id.clone(),
-                                    event: KademliaHandlerIn::AddProvider {
-                                        key: key.clone(),
-                                        provider_peer: build_kad_peer(
-                                            self.my_id.clone(),
-                                            parameters,
-                                            &self.kbuckets,
-                                        ),
-                                    },
+                            self.queued_events
+                                .push(NetworkBehaviourAction::GenerateEvent(event));
+                        }
+                        QueryPurpose::AddProvider(key) => {
+                            let wallet_record =
+                                find_wallet_record(&self.wallet_records, &key, &self.my_id);
+                            for closest in closer_peers.iter() {
+                                let node_info = match self.kbuckets.get(closest) {
+                                    Some(n) => n,
+                                    None => continue,
                                 };
-                                self.queued_events.push(event);
+                                if let Some(peer_id) = &node_info.peer_id {
+                                    let event = NetworkBehaviourAction::SendEvent {
+                                        peer_id: peer_id.clone(),
+                                        event: KademliaHandlerIn::AddProvider {
+                                            key: key.clone(),
+                                            provider_peer: KadPeer {
+                                                wallet_record: wallet_record.clone(),
+                                                ..build_kad_peer(
+                                                    self.my_id.clone(),
+                                                    parameters,
+                                                    &self.kbuckets,
+                                                    self.local_record.as_ref(),
+                                                )
+                                            },
+                                        },
+                                    };
+                                    self.queued_events.push(event);
+                                }
                             }
                         }
+                        QueryPurpose::WalletLookup(wallet_pkey) => {
+                            let key = wallet_pkey.into_multihash();
+                            let node_ids = self
+                                .wallet_records
+                                .get(&key)
+                                .into_iter()
+                                .flat_map(|records| records)
+                                .map(|r| r.node_id)
+                                .collect();
+                            let event = KademliaOut::FindNodeByWalletResult {
+                                wallet_pkey,
+                                node_ids,
+                            };
+                            self.queued_events
+                                .push(NetworkBehaviourAction::GenerateEvent(event));
+                        }
                     }
                 }
             } else {
@@ -900,6 +1506,35 @@ pub enum KademliaOut {
         /// List of peers ordered from closest to furthest away.
         closer_peers: Vec<pbc::PublicKey>,
     },
+
+    /// An iterative query was abandoned, see `QueryTimeout`.
+    /// Replaces `FindNodeResult` or `GetProvidersResult` and carries the partial results.
+    QueryTimedOut {
+        /// The key that we looked for in the query.
+        key: Multihash,
+        /// Why the query was abandoned.
+        reason: QueryTimeout,
+        /// Peers that answered, ordered from closest to furthest away.
+        closer_peers: Vec<pbc::PublicKey>,
+        /// Providers found so far, for `GET_PROVIDERS` queries.
+        provider_peers: Vec<pbc::PublicKey>,
+    },
+
+    /// Result of `find_node_by_wallet`.
+    FindNodeByWalletResult {
+        /// The wallet key that we looked for.
+        wallet_pkey: curve1174::PublicKey,
+        /// Nodes with a verified binding to the wallet key.
+        node_ids: Vec<pbc::PublicKey>,
+    },
+
+    /// A request from the peer was dropped because the peer exceeded rate limits.
+    RateLimited {
+        /// The misbehaving peer.
+        peer_id: PeerId,
+        /// Which limit was exceeded.
+        violation: RateLimitViolation,
+    },
 }
 
 // Generates a random `Multihash (SHA3-512)` that belongs to the given bucket.
@@ -935,6 +1570,23 @@ fn gen_random_hash(my_id: &Multihash, bucket_num: usize) -> Result<Multihash, ()
     Ok(random_hash)
 }
 
+/// Returns true if nobody but the initialization process waits for the query.
+fn is_background(purposes: &[QueryPurpose]) -> bool {
+    purposes.iter().all(|p| *p == QueryPurpose::Initialization)
+}
+
+/// Returns the binding of the wallet key to the node, if known.
+fn find_wallet_record(
+    wallet_records: &KBucketsTable<Multihash, SmallVec<[WalletRecord; 4]>>,
+    key: &Multihash,
+    node_id: &pbc::PublicKey,
+) -> Option<WalletRecord> {
+    wallet_records
+        .get(key)
+        .and_then(|records| records.iter().find(|r| r.node_id == *node_id))
+        .cloned()
+}
+
 /// Builds a `KadPeer` struct corresponding to the given `NodeId`.
 /// The `PeerId` can be the same as the local one.
 ///
@@ -943,15 +1595,17 @@ fn build_kad_peer(
     node_id: pbc::PublicKey,
     parameters: &mut PollParameters<'_>,
     kbuckets: &KBucketsTable<pbc::PublicKey, NodeInfo>,
+    local_record: Option<&AddressRecord>,
 ) -> KadPeer {
     let is_self = node_id == *kbuckets.my_id();
 
-    let (peer_id, multiaddrs, connection_ty) = if is_self {
+    let (peer_id, multiaddrs, connection_ty, record) = if is_self {
         let addrs = parameters.external_addresses().map(|v| v.clone()).collect();
         (
             Some(parameters.local_peer_id().clone()),
             addrs,
             KadConnectionType::Connected,
+            local_record.filter(|r| r.node_id == node_id).cloned(),
         )
     } else if let Some(node_info) = kbuckets.get(&node_id) {
         let connected = if node_info.addresses.is_connected() {
@@ -971,10 +1625,11 @@ fn build_kad_peer(
             peer_id,
             node_info.addresses.iter().cloned().collect(),
             connected,
+            node_info.addresses.record().cloned(),
         )
     } else {
         // TODO: there's also pending connection
-        (None, Vec::new(), KadConnectionType::NotConnected)
+        (None, Vec::new(), KadConnectionType::NotConnected, None)
     };
 
     KadPeer {
@@ -982,5 +1637,7 @@ fn build_kad_peer(
         peer_id,
         multiaddrs,
         connection_ty,
+        record,
+        wallet_record: None,
     }
 }
@@ -34,6 +34,7 @@ use libp2p::multihash::Multihash;
 use std::{error, fmt, io, time::Instant};
 use tokio::io::{AsyncRead, AsyncWrite};
 
+use crate::utils::ProtocolVersion;
 use crate::NETWORK_IDLE_TIMEOUT;
 
 /// Protocol handler that handles Kademlia communications with the remote.
@@ -60,6 +61,12 @@ where
 
     /// Until when to keep the connection alive.
     keep_alive: KeepAlive,
+
+    /// The latest protocol version negotiated with the remote.
+    remote_version: Option<ProtocolVersion>,
+
+    /// True if `remote_version` has changed and must be reported.
+    report_version: bool,
 }
 
 /// State of an active substream, opened either by us or by the remote.
@@ -139,6 +146,12 @@ where
 /// Event produced by the Kademlia handler.
 #[derive(Debug)]
 pub enum KademliaHandlerEvent<TUserData> {
+    /// The remote has negotiated a protocol version different from the previous one.
+    ProtocolVersion {
+        /// Negotiated version.
+        version: ProtocolVersion,
+    },
+
     /// Request for the list of nodes whose IDs are the closest to `key`. The number of nodes
     /// returned is not specified, but should be around 20.
     FindNodeReq {
@@ -314,7 +327,7 @@ where
     /// incoming connections.
     #[inline]
     pub fn dial_only() -> Self {
-        KademliaHandler::with_allow_listening(false)
+        KademliaHandler::with_allow_listening(false, Default::default())
     }
 
     /// Create a `KademliaHandler` that only allows sending messages but also receive incoming
@@ -323,16 +336,32 @@ where
     /// The `Default` trait implementation wraps around this function.
     #[inline]
     pub fn dial_and_listen() -> Self {
-        KademliaHandler::with_allow_listening(true)
+        KademliaHandler::with_allow_listening(true, Default::default())
+    }
+
+    /// Same as `dial_and_listen()`, but with the custom protocol configuration.
+    #[inline]
+    pub fn with_config(config: KademliaProtocolConfig) -> Self {
+        KademliaHandler::with_allow_listening(true, config)
     }
 
-    fn with_allow_listening(allow_listening: bool) -> Self {
+    fn with_allow_listening(allow_listening: bool, config: KademliaProtocolConfig) -> Self {
         KademliaHandler {
-            config: Default::default(),
+            config,
             allow_listening,
             next_connec_unique_id: UniqueConnecId(0),
             substreams: Vec::new(),
             keep_alive: KeepAlive::Yes,
+            remote_version: None,
+            report_version: false,
+        }
+    }
+
+    /// Remembers the protocol version negotiated for a new substream.
+    fn on_negotiated(&mut self, version: ProtocolVersion) {
+        if self.remote_version != Some(version) {
+            self.remote_version = Some(version);
+            self.report_version = true;
         }
     }
 }
@@ -375,6 +404,8 @@ where
         protocol: <Self::OutboundProtocol as OutboundUpgrade<TSubstream>>::Output,
         (msg, user_data): Self::OutboundOpenInfo,
     ) {
+        let (protocol, version) = protocol;
+        self.on_negotiated(version);
         self.substreams
             .push(SubstreamState::OutPendingSend(protocol, msg, user_data));
     }
@@ -389,6 +420,8 @@ where
             EitherOutput::First(p) => p,
             EitherOutput::Second(p) => void::unreachable(p),
         };
+        let (protocol, version) = protocol;
+        self.on_negotiated(version);
 
         debug_assert!(self.allow_listening);
         let connec_unique_id = self.next_connec_unique_id;
@@ -500,6 +533,14 @@ where
         ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent>,
         io::Error,
     > {
+        if self.report_version {
+            self.report_version = false;
+            if let Some(version) = self.remote_version {
+                let event = KademliaHandlerEvent::ProtocolVersion { version };
+                return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(event)));
+            }
+        }
+
         // We remove each element from `substreams` one by one and add them back.
         for n in (0..self.substreams.len()).rev() {
             let mut substream = self.substreams.swap_remove(n);
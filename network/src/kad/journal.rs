@@ -0,0 +1,302 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Journal of recent Kademlia queries, for diagnostics of discovery failures.
+
+use super::query::{QueryTarget, QueryTimeout};
+use log::*;
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use stegos_crypto::pbc;
+use stegos_crypto::utils::u8v_to_hexstr;
+
+/// A single RPC sent by a query.
+#[derive(Clone, Debug, Serialize)]
+pub struct RpcRecord {
+    pub node_id: pbc::PublicKey,
+    /// Time between the request and the response, None if failed or not answered yet.
+    pub latency_ms: Option<u64>,
+    /// The reason of failure.
+    pub error: Option<String>,
+    #[serde(skip)]
+    sent: Instant,
+}
+
+/// A query with all its RPCs.
+#[derive(Clone, Debug, Serialize)]
+pub struct QueryRecord {
+    pub query_id: usize,
+    /// "find_peer" or "get_providers".
+    pub kind: &'static str,
+    /// Hex-encoded multihash of the target.
+    pub target: String,
+    /// Why the query was started.
+    pub purposes: Vec<String>,
+    pub started: SystemTime,
+    /// Duration of the query, None if the query is still running.
+    pub duration_ms: Option<u64>,
+    pub rpcs: Vec<RpcRecord>,
+    /// The number of the closest peers found.
+    pub closer_peers: usize,
+    /// The number of providers found by `get_providers` queries.
+    pub providers: usize,
+    /// Why the query was abandoned, None if it finished normally.
+    pub timeout: Option<QueryTimeout>,
+    #[serde(skip)]
+    started_at: Instant,
+}
+
+impl QueryRecord {
+    /// A line of the journal file.
+    fn to_line(&self) -> String {
+        let started = self
+            .started
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut line = format!(
+            "{} {} {} {} {}ms closer={} providers={}",
+            started,
+            self.query_id,
+            self.kind,
+            self.target,
+            self.duration_ms.unwrap_or(0),
+            self.closer_peers,
+            self.providers
+        );
+        if let Some(timeout) = &self.timeout {
+            line.push_str(&format!(" timeout={}", timeout));
+        }
+        for rpc in &self.rpcs {
+            match (&rpc.latency_ms, &rpc.error) {
+                (Some(latency_ms), _) => {
+                    line.push_str(&format!(" {}:{}ms", rpc.node_id, latency_ms))
+                }
+                (None, Some(error)) => line.push_str(&format!(" {}:{:?}", rpc.node_id, error)),
+                (None, None) => line.push_str(&format!(" {}:pending", rpc.node_id)),
+            }
+        }
+        line
+    }
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+/// Ring buffer of finished queries, optionally appended to a file.
+pub struct QueryJournal {
+    /// The maximal number of finished queries kept in memory, zero disables the journal.
+    capacity: usize,
+    /// Path to the journal file, None to keep the journal in memory only.
+    path: Option<PathBuf>,
+    active: HashMap<usize, QueryRecord>,
+    finished: VecDeque<QueryRecord>,
+}
+
+impl QueryJournal {
+    pub fn new(capacity: usize, path: Option<PathBuf>) -> Self {
+        QueryJournal {
+            capacity,
+            path,
+            active: HashMap::new(),
+            finished: VecDeque::new(),
+        }
+    }
+
+    /// Disabled journal.
+    pub fn disabled() -> Self {
+        Self::new(0, None)
+    }
+
+    pub fn start(&mut self, query_id: usize, target: &QueryTarget, purposes: Vec<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let kind = match target {
+            QueryTarget::FindPeer(_) => "find_peer",
+            QueryTarget::GetProviders(_) => "get_providers",
+        };
+        let record = QueryRecord {
+            query_id,
+            kind,
+            target: u8v_to_hexstr(target.as_hash().as_bytes()),
+            purposes,
+            started: SystemTime::now(),
+            duration_ms: None,
+            rpcs: Vec::new(),
+            closer_peers: 0,
+            providers: 0,
+            timeout: None,
+            started_at: Instant::now(),
+        };
+        self.active.insert(query_id, record);
+    }
+
+    pub fn rpc_sent(&mut self, query_id: usize, node_id: &pbc::PublicKey) {
+        if let Some(record) = self.active.get_mut(&query_id) {
+            record.rpcs.push(RpcRecord {
+                node_id: *node_id,
+                latency_ms: None,
+                error: None,
+                sent: Instant::now(),
+            });
+        }
+    }
+
+    /// Returns the outstanding RPC to the node.
+    fn pending_rpc<'a>(
+        record: &'a mut QueryRecord,
+        node_id: &pbc::PublicKey,
+    ) -> Option<&'a mut RpcRecord> {
+        record
+            .rpcs
+            .iter_mut()
+            .rev()
+            .find(|rpc| rpc.node_id == *node_id && rpc.latency_ms.is_none() && rpc.error.is_none())
+    }
+
+    pub fn rpc_succeeded(&mut self, query_id: usize, node_id: &pbc::PublicKey) {
+        if let Some(record) = self.active.get_mut(&query_id) {
+            if let Some(rpc) = Self::pending_rpc(record, node_id) {
+                rpc.latency_ms = Some(elapsed_ms(rpc.sent));
+            }
+        }
+    }
+
+    pub fn rpc_failed(&mut self, query_id: usize, node_id: &pbc::PublicKey, error: &str) {
+        if let Some(record) = self.active.get_mut(&query_id) {
+            if let Some(rpc) = Self::pending_rpc(record, node_id) {
+                rpc.error = Some(error.to_string());
+            }
+        }
+    }
+
+    /// Fails outstanding RPCs to the node in all queries.
+    pub fn node_failed(&mut self, node_id: &pbc::PublicKey, error: &str) {
+        for record in self.active.values_mut() {
+            if let Some(rpc) = Self::pending_rpc(record, node_id) {
+                rpc.error = Some(error.to_string());
+            }
+        }
+    }
+
+    /// Marks the query as abandoned, must be followed by `finish()`.
+    pub fn timed_out(&mut self, query_id: usize, reason: QueryTimeout) {
+        if let Some(record) = self.active.get_mut(&query_id) {
+            record.timeout = Some(reason);
+        }
+    }
+
+    pub fn finish(&mut self, query_id: usize, closer_peers: usize, providers: usize) {
+        let mut record = match self.active.remove(&query_id) {
+            Some(record) => record,
+            None => return,
+        };
+        record.duration_ms = Some(elapsed_ms(record.started_at));
+        record.closer_peers = closer_peers;
+        record.providers = providers;
+        if let Some(path) = &self.path {
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| writeln!(f, "{}", record.to_line()));
+            if let Err(e) = result {
+                error!(target: "stegos_network::kad", "Failed to write query journal: path={}, error={}", path.display(), e);
+            }
+        }
+        if self.finished.len() >= self.capacity {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(record);
+    }
+
+    /// Finished queries from oldest to newest, followed by running queries.
+    pub fn records(&self) -> Vec<QueryRecord> {
+        let mut active: Vec<QueryRecord> = self.active.values().cloned().collect();
+        active.sort_by_key(|record| record.query_id);
+        self.finished.iter().cloned().chain(active).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::IntoMultihash;
+
+    #[test]
+    fn ring_buffer() {
+        let (_skey, node1) = pbc::make_random_keys();
+        let (_skey, node2) = pbc::make_random_keys();
+        let target = QueryTarget::FindPeer(node1.into_multihash());
+        let mut journal = QueryJournal::new(2, None);
+
+        journal.start(1, &target, vec!["UserRequest".to_string()]);
+        journal.rpc_sent(1, &node1);
+        journal.rpc_sent(1, &node2);
+        journal.rpc_succeeded(1, &node1);
+        journal.node_failed(&node2, "disconnected");
+        let records = journal.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].duration_ms, None);
+        journal.finish(1, 1, 0);
+
+        let records = journal.records();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].duration_ms.is_some());
+        assert!(records[0].rpcs[0].latency_ms.is_some());
+        assert_eq!(records[0].rpcs[1].error, Some("disconnected".to_string()));
+
+        for query_id in 2..5 {
+            journal.start(query_id, &target, Vec::new());
+            journal.finish(query_id, 0, 0);
+        }
+        let ids: Vec<usize> = journal.records().iter().map(|r| r.query_id).collect();
+        assert_eq!(ids, vec![3, 4]);
+
+        let mut journal = QueryJournal::disabled();
+        journal.start(1, &target, Vec::new());
+        journal.finish(1, 0, 0);
+        assert!(journal.records().is_empty());
+    }
+
+    #[test]
+    fn timed_out() {
+        let (_skey, node1) = pbc::make_random_keys();
+        let target = QueryTarget::FindPeer(node1.into_multihash());
+        let mut journal = QueryJournal::new(2, None);
+
+        journal.start(1, &target, Vec::new());
+        journal.rpc_sent(1, &node1);
+        journal.timed_out(1, QueryTimeout::Stalled);
+        journal.finish(1, 0, 0);
+        let records = journal.records();
+        assert_eq!(records[0].timeout, Some(QueryTimeout::Stalled));
+        assert!(records[0].to_line().contains(" timeout=stalled "));
+    }
+}
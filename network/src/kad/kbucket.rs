@@ -27,20 +27,114 @@
 //! If the local ID has `N` bits, then the k-buckets table contains `N` *buckets* each containing
 //! a constant number of entries. Storing a key in the k-buckets table adds it to the bucket
 //! corresponding to its distance with the reference key.
+//!
+//! To make eclipse attacks harder, a bucket keeps at most `MAX_NODES_PER_SUBNET` nodes from
+//! the same IP subnet. When the limit is exceeded, the most recently added node is evicted.
+//!
+//! To resist Sybil attacks, the table can be restricted to staked nodes by a `StakeChecker`.
+//! Other nodes are then kept in a small separate table and never returned by `find_closest()`.
+//!
+//! Keys are ordered by a `Distance` metric. Keys implementing `XorDigest` get the XOR metric
+//! over their digests.
 
 use crate::utils::IntoMultihash;
 use arrayvec::ArrayVec;
-use bigint::U512;
+use libp2p::core::multiaddr::{Multiaddr, Protocol};
 use libp2p::core::PeerId;
 use libp2p::multihash::Multihash;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::slice::IterMut as SliceIterMut;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::vec::IntoIter as VecIntoIter;
+use stegos_crypto::curve1174;
 use stegos_crypto::pbc;
 
 /// Maximum number of nodes in a bucket.
 pub const MAX_NODES_PER_BUCKET: usize = 20;
 
+/// Maximum number of nodes from the same IP subnet in a bucket.
+pub const MAX_NODES_PER_SUBNET: usize = 4;
+
+/// Maximum number of unstaked nodes, see `KBucketsTable::set_stake_checker()`.
+pub const MAX_UNSTAKED_NODES: usize = 64;
+
+/// Decides which nodes are allowed into the k-buckets.
+pub trait StakeChecker<TPeerId = pbc::PublicKey>: Send + Sync {
+    /// Returns true if the node belongs to a validator with an active stake.
+    fn is_staked(&self, id: &TPeerId) -> bool;
+
+    /// Returns the active stake of the node, zero if unknown.
+    fn stake(&self, _id: &TPeerId) -> i64 {
+        0
+    }
+
+    /// Returns true if the node holds a slot in the current epoch.
+    fn is_validator(&self, _id: &TPeerId) -> bool {
+        false
+    }
+}
+
+impl<TPeerId> fmt::Debug for dyn StakeChecker<TPeerId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StakeChecker")
+    }
+}
+
+/// Keeps nodes refused by the operator out of the k-buckets.
+pub trait AccessChecker<TPeerId = pbc::PublicKey>: Send + Sync {
+    /// Returns true if the node is allowed into the k-buckets.
+    fn is_allowed(&self, id: &TPeerId) -> bool;
+}
+
+impl<TPeerId> fmt::Debug for dyn AccessChecker<TPeerId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AccessChecker")
+    }
+}
+
+/// IP subnet of a node: /16 for IPv4 and /32 for IPv6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subnet {
+    Ipv4([u8; 2]),
+    Ipv6([u8; 4]),
+}
+
+impl Subnet {
+    /// Returns the subnet of the address.
+    ///
+    /// Returns `None` for addresses without an IP part and for loopback, private and
+    /// link-local addresses, which are not limited.
+    pub fn from_multiaddr(addr: &Multiaddr) -> Option<Subnet> {
+        for protocol in addr.iter() {
+            match protocol {
+                Protocol::Ip4(ip) => {
+                    if ip.is_loopback()
+                        || ip.is_private()
+                        || ip.is_link_local()
+                        || ip.is_unspecified()
+                    {
+                        return None;
+                    }
+                    let octets = ip.octets();
+                    return Some(Subnet::Ipv4([octets[0], octets[1]]));
+                }
+                Protocol::Ip6(ip) => {
+                    if ip.is_loopback() || ip.is_unspecified() {
+                        return None;
+                    }
+                    let octets = ip.octets();
+                    return Some(Subnet::Ipv6([octets[0], octets[1], octets[2], octets[3]]));
+                }
+                _ => continue,
+            }
+        }
+        None
+    }
+}
+
 /// Table of k-buckets.
 #[derive(Debug, Clone)]
 pub struct KBucketsTable<TPeerId, TVal> {
@@ -50,6 +144,19 @@ pub struct KBucketsTable<TPeerId, TVal> {
     tables: Vec<KBucket<TPeerId, TVal>>,
     /// The timeout when trying to reach the first node after which we consider it unresponsive.
     unresponsive_timeout: Duration,
+    /// If set, only staked nodes are added to the buckets.
+    stake_checker: Option<Arc<dyn StakeChecker<TPeerId>>>,
+    /// Nodes rejected by `stake_checker`, ordered from oldest to newest.
+    unstaked: Vec<UnstakedNode<TPeerId, TVal>>,
+    /// If set, nodes refused by the operator are never added to the table.
+    access_checker: Option<Arc<dyn AccessChecker<TPeerId>>>,
+}
+
+/// A node kept outside of the buckets.
+#[derive(Debug, Clone)]
+struct UnstakedNode<TPeerId, TVal> {
+    node: Node<TPeerId, TVal>,
+    connected: bool,
 }
 
 /// An individual table that stores peers or values.
@@ -80,6 +187,25 @@ struct Node<TPeerId, TVal> {
     id: TPeerId,
     /// Value associated to it.
     value: TVal,
+    /// Last time this node was added or updated.
+    last_update: Instant,
+    /// Time when this node was added to the table.
+    added: Instant,
+    /// IP subnet of the node, if known.
+    subnet: Option<Subnet>,
+}
+
+impl<TPeerId, TVal> Node<TPeerId, TVal> {
+    fn new(id: TPeerId, value: TVal) -> Self {
+        let now = Instant::now();
+        Node {
+            id,
+            value,
+            last_update: now,
+            added: now,
+            subnet: None,
+        }
+    }
 }
 
 impl<TPeerId, TVal> KBucket<TPeerId, TVal> {
@@ -89,98 +215,156 @@ impl<TPeerId, TVal> KBucket<TPeerId, TVal> {
     fn flush(&mut self, timeout: Duration) {
         if let Some((pending_node, instant)) = self.pending_node.take() {
             if instant.elapsed() >= timeout {
-                let _ = self.nodes.remove(0);
+                self.remove(0);
+                let subnet = pending_node.subnet;
                 self.nodes.push(pending_node);
+                if let Some(subnet) = subnet {
+                    self.enforce_subnet_limit(subnet);
+                }
             } else {
                 self.pending_node = Some((pending_node, instant));
             }
         }
     }
+
+    /// Removes the node at the given position.
+    fn remove(&mut self, pos: usize) {
+        self.nodes.remove(pos);
+        if pos < self.first_connected_pos {
+            self.first_connected_pos -= 1;
+        }
+    }
+
+    /// Returns the number of nodes from the given subnet.
+    fn subnet_count(&self, subnet: Subnet) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| node.subnet == Some(subnet))
+            .count()
+    }
+
+    /// Evicts a node from the subnet if it has more than `MAX_NODES_PER_SUBNET` nodes.
+    /// Long-lived nodes are preferred, so the most recently added node is evicted.
+    ///
+    /// Returns the position of the evicted node.
+    fn enforce_subnet_limit(&mut self, subnet: Subnet) -> Option<usize> {
+        if self.subnet_count(subnet) <= MAX_NODES_PER_SUBNET {
+            return None;
+        }
+        let pos = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.subnet == Some(subnet))
+            .max_by_key(|(_, node)| node.added)
+            .map(|(pos, _)| pos)
+            .expect("subnet has nodes");
+        self.remove(pos);
+        Some(pos)
+    }
 }
 
-/// Trait that must be implemented on types that can be used as an identifier in a k-bucket.
-pub trait KBucketsPeerId<TOther = Self>: IntoMultihash + Clone {
-    /// Computes the XOR of this value and another one. The lower the closer.
+/// Metric used to assign keys to k-buckets.
+pub trait Distance<TOther = Self>: Clone {
+    /// Computes the distance between this key and another one. The lower the closer.
+    /// Returns zero for equal keys.
     fn distance_with(&self, other: &TOther) -> u32;
 
-    /// Returns then number of bits that are necessary to store the distance between peer IDs.
+    /// Returns then number of bits that are necessary to store the distance between keys.
     /// Used for pre-allocations.
     ///
     /// > **Note**: Returning 0 would lead to a panic.
     fn max_distance() -> usize;
 }
 
-impl KBucketsPeerId for PeerId {
-    #[inline]
-    fn distance_with(&self, other: &Self) -> u32 {
-        Multihash::distance_with(self.as_ref(), other.as_ref())
-    }
+/// Keys compared by the XOR of their digests.
+pub trait XorDigest {
+    /// Maximal number of bits in the digest.
+    const DIGEST_BITS: usize;
 
-    #[inline]
-    fn max_distance() -> usize {
-        <Multihash as KBucketsPeerId>::max_distance()
-    }
+    /// Returns the digest as a big-endian number.
+    /// Shorter digests are padded by zeros on the left.
+    fn xor_digest(&self) -> Cow<'_, [u8]>;
 }
 
-impl KBucketsPeerId<Multihash> for PeerId {
+impl<T, TOther> Distance<TOther> for T
+where
+    T: XorDigest + Clone,
+    TOther: XorDigest,
+{
     #[inline]
-    fn distance_with(&self, other: &Multihash) -> u32 {
-        Multihash::distance_with(self.as_ref(), other)
+    fn distance_with(&self, other: &TOther) -> u32 {
+        xor_distance(&self.xor_digest(), &other.xor_digest())
     }
 
     #[inline]
     fn max_distance() -> usize {
-        <Multihash as KBucketsPeerId>::max_distance()
+        std::cmp::max(T::DIGEST_BITS, TOther::DIGEST_BITS)
     }
 }
 
-impl KBucketsPeerId for pbc::PublicKey {
-    #[inline]
-    fn distance_with(&self, other: &Self) -> u32 {
-        Multihash::distance_with(
-            &self.clone().into_multihash(),
-            &other.clone().into_multihash(),
-        )
+/// Returns the number of significant bits in the XOR of two big-endian numbers.
+pub fn xor_distance(a: &[u8], b: &[u8]) -> u32 {
+    let len = std::cmp::max(a.len(), b.len());
+    let byte = |digest: &[u8], pos: usize| -> u8 {
+        let padding = len - digest.len();
+        if pos < padding {
+            0
+        } else {
+            digest[pos - padding]
+        }
+    };
+    for pos in 0..len {
+        let xor = byte(a, pos) ^ byte(b, pos);
+        if xor != 0 {
+            return ((len - pos) * 8) as u32 - xor.leading_zeros();
+        }
     }
+    0
+}
+
+impl XorDigest for Multihash {
+    // Note that we don't compare the hash functions because there's no chance of collision
+    // of the same value hashed with two different hash functions.
+    const DIGEST_BITS: usize = 512;
 
     #[inline]
-    fn max_distance() -> usize {
-        <Multihash as KBucketsPeerId>::max_distance()
+    fn xor_digest(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.digest())
     }
 }
 
-impl KBucketsPeerId<Multihash> for pbc::PublicKey {
-    #[inline]
-    fn distance_with(&self, other: &Multihash) -> u32 {
-        Multihash::distance_with(&self.clone().into_multihash(), other)
-    }
+impl XorDigest for PeerId {
+    const DIGEST_BITS: usize = 512;
 
     #[inline]
-    fn max_distance() -> usize {
-        <Multihash as KBucketsPeerId>::max_distance()
+    fn xor_digest(&self) -> Cow<'_, [u8]> {
+        let hash: &Multihash = self.as_ref();
+        Cow::Borrowed(hash.digest())
     }
 }
 
-impl KBucketsPeerId for Multihash {
+impl XorDigest for pbc::PublicKey {
+    const DIGEST_BITS: usize = 512;
+
     #[inline]
-    fn distance_with(&self, other: &Self) -> u32 {
-        // Note that we don't compare the hash functions because there's no chance of collision
-        // of the same value hashed with two different hash functions.
-        let my_hash = U512::from(self.digest());
-        let other_hash = U512::from(other.digest());
-        let xor = my_hash ^ other_hash;
-        512 - xor.leading_zeros()
+    fn xor_digest(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.clone().into_multihash().digest().to_vec())
     }
+}
+
+impl XorDigest for curve1174::PublicKey {
+    const DIGEST_BITS: usize = 512;
 
     #[inline]
-    fn max_distance() -> usize {
-        512
+    fn xor_digest(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.clone().into_multihash().digest().to_vec())
     }
 }
 
 impl<TPeerId, TVal> KBucketsTable<TPeerId, TVal>
 where
-    TPeerId: KBucketsPeerId + PartialEq,
+    TPeerId: Distance + PartialEq,
     TVal: Clone + Default,
 {
     /// Builds a new routing table.
@@ -196,6 +380,142 @@ where
                 })
                 .collect(),
             unresponsive_timeout,
+            stake_checker: None,
+            unstaked: Vec::new(),
+            access_checker: None,
+        }
+    }
+
+    /// Keeps nodes refused by the operator out of the table.
+    /// Such nodes are removed from the table immediately.
+    pub fn set_access_checker(&mut self, access_checker: Arc<dyn AccessChecker<TPeerId>>) {
+        for table in self.tables.iter_mut() {
+            table.flush(self.unresponsive_timeout);
+            let mut pos = 0;
+            while pos < table.nodes.len() {
+                if access_checker.is_allowed(&table.nodes[pos].id) {
+                    pos += 1;
+                } else {
+                    table.remove(pos);
+                }
+            }
+            let pending_allowed = match &table.pending_node {
+                Some((node, _)) => access_checker.is_allowed(&node.id),
+                None => true,
+            };
+            if !pending_allowed {
+                table.pending_node = None;
+            }
+        }
+        self.unstaked
+            .retain(|elem| access_checker.is_allowed(&elem.node.id));
+        self.access_checker = Some(access_checker);
+    }
+
+    /// Returns false if the node is refused by the operator.
+    fn is_allowed(&self, id: &TPeerId) -> bool {
+        match &self.access_checker {
+            Some(access_checker) => access_checker.is_allowed(id),
+            None => true,
+        }
+    }
+
+    /// Restricts the buckets to staked nodes.
+    pub fn set_stake_checker(&mut self, stake_checker: Arc<dyn StakeChecker<TPeerId>>) {
+        self.stake_checker = Some(stake_checker);
+        self.refresh_stakes();
+    }
+
+    /// Moves nodes between the buckets and the unstaked table according to their stakes.
+    pub fn refresh_stakes(&mut self) {
+        let stake_checker = match &self.stake_checker {
+            Some(stake_checker) => stake_checker.clone(),
+            None => return,
+        };
+
+        let mut unstaked = Vec::new();
+        for table in self.tables.iter_mut() {
+            table.flush(self.unresponsive_timeout);
+            let mut pos = 0;
+            while pos < table.nodes.len() {
+                if stake_checker.is_staked(&table.nodes[pos].id) {
+                    pos += 1;
+                    continue;
+                }
+                let connected = pos >= table.first_connected_pos;
+                let node = table.nodes[pos].clone();
+                table.remove(pos);
+                unstaked.push(UnstakedNode { node, connected });
+            }
+        }
+
+        let mut pos = 0;
+        while pos < self.unstaked.len() {
+            let id = self.unstaked[pos].node.id.clone();
+            if !stake_checker.is_staked(&id) {
+                pos += 1;
+                continue;
+            }
+            let table = match self.bucket_num(&id) {
+                Some(n) => &mut self.tables[n],
+                None => {
+                    self.unstaked.remove(pos);
+                    continue;
+                }
+            };
+            if table.nodes.is_full() {
+                pos += 1;
+                continue;
+            }
+            let UnstakedNode { node, connected } = self.unstaked.remove(pos);
+            if connected {
+                table.nodes.push(node);
+            } else {
+                table.nodes.insert(table.first_connected_pos, node);
+                table.first_connected_pos += 1;
+            }
+        }
+
+        for UnstakedNode { node, connected } in unstaked {
+            self.insert_unstaked(node, connected);
+        }
+    }
+
+    /// Returns true if the node is allowed into the buckets.
+    fn is_staked(&self, id: &TPeerId) -> bool {
+        match &self.stake_checker {
+            Some(stake_checker) => stake_checker.is_staked(id),
+            None => true,
+        }
+    }
+
+    /// Returns the position of the node in the unstaked table.
+    fn unstaked_pos(&self, id: &TPeerId) -> Option<usize> {
+        self.unstaked.iter().position(|elem| elem.node.id == *id)
+    }
+
+    /// Adds a node to the unstaked table, evicting the oldest one if the table is full.
+    /// Disconnected nodes are evicted first. Returns the position of the node.
+    fn insert_unstaked(&mut self, node: Node<TPeerId, TVal>, connected: bool) -> usize {
+        if self.unstaked.len() >= MAX_UNSTAKED_NODES {
+            let evict = self
+                .unstaked
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, elem)| (elem.connected, elem.node.last_update))
+                .map(|(pos, _)| pos)
+                .expect("table is not empty");
+            self.unstaked.remove(evict);
+        }
+        self.unstaked.push(UnstakedNode { node, connected });
+        self.unstaked.len() - 1
+    }
+
+    /// Returns true if the node is in one of the buckets.
+    fn in_buckets(&self, id: &TPeerId) -> bool {
+        match self.bucket_num(id) {
+            Some(n) => self.tables[n].nodes.iter().any(|elem| elem.id == *id),
+            None => false,
         }
     }
 
@@ -221,26 +541,64 @@ where
         self.tables.iter().map(|t| t.nodes.len()).sum()
     }
 
+    /// Returns the number of nodes kept outside of the buckets.
+    #[inline]
+    pub fn unstaked_size(&self) -> usize {
+        self.unstaked.len()
+    }
+
     /// Returns the ID of the local node.
     #[inline]
     pub fn my_id(&self) -> &TPeerId {
         &self.my_id
     }
 
-    /// Returns new table based on new id and values from this table
+    /// Returns new table based on new id and entries from this table.
+    ///
+    /// Every node is re-inserted into the bucket matching its distance to the new id,
+    /// keeping its value, connection status and last update time. When a bucket of the
+    /// new table overflows, connected nodes are preferred over disconnected ones and
+    /// older nodes over newer ones.
     pub fn new_table(&mut self, new_id: TPeerId) -> Self {
         let mut new_table = KBucketsTable::new(new_id, self.unresponsive_timeout);
-        for p in self.dump() {
-            let entry: &mut TVal = match new_table.entry_mut(&p) {
-                Some(e) => e,
-                None => continue,
-            };
+        new_table.stake_checker = self.stake_checker.clone();
+        new_table.access_checker = self.access_checker.clone();
+        new_table.unstaked = self
+            .unstaked
+            .iter()
+            .filter(|elem| elem.node.id != new_table.my_id)
+            .cloned()
+            .collect();
+
+        let mut nodes = Vec::with_capacity(self.size());
+        for table in self.tables.iter_mut() {
+            table.flush(self.unresponsive_timeout);
+            for (pos, node) in table.nodes.iter().enumerate() {
+                nodes.push((node.clone(), pos >= table.first_connected_pos));
+            }
+        }
+        // Connected nodes first, then from oldest to newest.
+        nodes.sort_by(|(n1, c1), (n2, c2)| c2.cmp(c1).then(n1.last_update.cmp(&n2.last_update)));
 
-            let val: TVal = match self.get(&p) {
-                Some(v) => (*v).clone(),
+        for (node, connected) in nodes {
+            let table = match new_table.bucket_num(&node.id) {
+                Some(n) => &mut new_table.tables[n],
                 None => continue,
             };
-            *entry = val;
+            if table.nodes.is_full() {
+                continue;
+            }
+            if let Some(subnet) = node.subnet {
+                if table.subnet_count(subnet) >= MAX_NODES_PER_SUBNET {
+                    continue;
+                }
+            }
+            if connected {
+                table.nodes.push(node);
+            } else {
+                table.nodes.insert(table.first_connected_pos, node);
+                table.first_connected_pos += 1;
+            }
         }
         new_table
     }
@@ -260,7 +618,8 @@ where
             }
         }
 
-        None
+        self.unstaked_pos(id)
+            .map(|pos| &self.unstaked[pos].node.value)
     }
 
     /// Returns the value associated to a node, if any is present.
@@ -280,16 +639,36 @@ where
             }
         }
 
-        None
+        self.unstaked
+            .iter_mut()
+            .find(|elem| elem.node.id == *id)
+            .map(|elem| &mut elem.node.value)
     }
 
     /// Returns the value associated to a node if any is present. Otherwise, tries to add the
     /// node to the table in a disconnected state and return its value. Returns `None` if `id` is
-    /// the local peer, or if the table is full.
+    /// the local peer, if the table is full, or if the node is refused by the operator.
+    ///
+    /// Unstaked nodes are added to the unstaked table, which is never full.
     pub fn entry_mut(&mut self, id: &TPeerId) -> Option<&mut TVal>
     where
         TVal: Default,
     {
+        if !self.is_allowed(id) {
+            return None;
+        }
+        if self.bucket_num(id).is_some() && !self.in_buckets(id) {
+            if let Some(pos) = self.unstaked_pos(id) {
+                let node = &mut self.unstaked[pos].node;
+                node.last_update = Instant::now();
+                return Some(&mut node.value);
+            }
+            if !self.is_staked(id) {
+                let node = Node::new(id.clone(), Default::default());
+                let pos = self.insert_unstaked(node, false);
+                return Some(&mut self.unstaked[pos].node.value);
+            }
+        }
         if let Some((bucket, entry)) = self.entry_mut_inner(id) {
             Some(&mut self.tables[bucket].nodes[entry].value)
         } else {
@@ -297,6 +676,35 @@ where
         }
     }
 
+    /// Same as `entry_mut`, but if the bucket is full, makes room for the node by evicting
+    /// the least recently updated node accepted by `can_evict`.
+    ///
+    /// Returns `None` if `id` is the local peer, or if no node can be evicted.
+    pub fn entry_mut_or_evict<F>(&mut self, id: &TPeerId, can_evict: F) -> Option<&mut TVal>
+    where
+        TVal: Default,
+        F: Fn(&TPeerId, &TVal) -> bool,
+    {
+        if !self.is_allowed(id) {
+            return None;
+        }
+        if let Some(n) = self.bucket_num(id) {
+            let table = &mut self.tables[n];
+            table.flush(self.unresponsive_timeout);
+            if table.nodes.is_full() && !table.nodes.iter().any(|elem| elem.id == *id) {
+                let evict = table
+                    .nodes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, elem)| can_evict(&elem.id, &elem.value))
+                    .min_by_key(|(_, elem)| elem.last_update)
+                    .map(|(pos, _)| pos)?;
+                table.remove(evict);
+            }
+        }
+        self.entry_mut(id)
+    }
+
     /// Apparently non-lexical lifetimes still aren't working properly in some situations, so we
     /// delegate `entry_mut` to this method that returns an index within `self.tables` and the
     /// node index within that table.
@@ -313,16 +721,14 @@ where
 
         if let Some(pos) = table.nodes.iter().position(|elem| elem.id == *id) {
             table.latest_update = Instant::now();
+            table.nodes[pos].last_update = table.latest_update;
             return Some((bucket_num, pos));
         }
 
         if !table.nodes.is_full() {
             table.nodes.insert(
                 table.first_connected_pos,
-                Node {
-                    id: id.clone(),
-                    value: Default::default(),
-                },
+                Node::new(id.clone(), Default::default()),
             );
             table.first_connected_pos += 1;
             table.latest_update = Instant::now();
@@ -341,6 +747,23 @@ where
     where
         TVal: Default,
     {
+        if !self.is_allowed(id) {
+            return Update::Discarded;
+        }
+        if self.bucket_num(id).is_some() && !self.in_buckets(id) {
+            if let Some(pos) = self.unstaked_pos(id) {
+                let elem = &mut self.unstaked[pos];
+                elem.node.last_update = Instant::now();
+                elem.connected = true;
+                return Update::Updated;
+            }
+            if !self.is_staked(id) {
+                let node = Node::new(id.clone(), Default::default());
+                self.insert_unstaked(node, true);
+                return Update::Added;
+            }
+        }
+
         let table = match self.bucket_num(&id) {
             Some(n) => &mut self.tables[n],
             None => return Update::FailSelfUpdate,
@@ -349,6 +772,7 @@ where
         table.flush(self.unresponsive_timeout);
 
         if let Some(pos) = table.nodes.iter().position(|elem| elem.id == *id) {
+            table.nodes[pos].last_update = Instant::now();
             // Node is already in the table; move it over `first_connected_pos` if necessary.
             // We do a `saturating_sub(1)`, because if `first_connected_pos` is 0 then
             // `pos < first_connected_pos` can never be true anyway.
@@ -363,10 +787,7 @@ where
             // Node is not in the table yet, but there's plenty of space for it.
             table.nodes.insert(
                 table.first_connected_pos,
-                Node {
-                    id: id.clone(),
-                    value: Default::default(),
-                },
+                Node::new(id.clone(), Default::default()),
             );
             table.latest_update = Instant::now();
             Update::Added
@@ -374,10 +795,7 @@ where
             // Node is not in the table yet, but there could be room for it if we drop the first
             // element. However we first add the node to add to `pending_node` and try to reconnect
             // to the oldest node.
-            let pending_node = Node {
-                id: id.clone(),
-                value: Default::default(),
-            };
+            let pending_node = Node::new(id.clone(), Default::default());
             table.pending_node = Some((pending_node, Instant::now()));
             Update::Pending(&table.nodes[0].id)
         } else {
@@ -386,11 +804,84 @@ where
         }
     }
 
+    /// Sets the IP subnet of the node.
+    ///
+    /// If the bucket of the node then has more than `MAX_NODES_PER_SUBNET` nodes from the
+    /// subnet, the most recently added ones are evicted. Returns false if the node is not
+    /// in the table (anymore).
+    pub fn set_subnet(&mut self, id: &TPeerId, subnet: Option<Subnet>) -> bool {
+        // Unstaked nodes are not limited.
+        if let Some(pos) = self.unstaked_pos(id) {
+            self.unstaked[pos].node.subnet = subnet;
+            return true;
+        }
+
+        let table = match self.bucket_num(&id) {
+            Some(n) => &mut self.tables[n],
+            None => return false,
+        };
+
+        table.flush(self.unresponsive_timeout);
+
+        let pos = match table.nodes.iter().position(|elem| elem.id == *id) {
+            Some(pos) => pos,
+            None => {
+                // Checked when the pending node gets into the bucket.
+                if let Some((pending_node, _)) = &mut table.pending_node {
+                    if pending_node.id == *id {
+                        pending_node.subnet = subnet;
+                    }
+                }
+                return false;
+            }
+        };
+
+        table.nodes[pos].subnet = subnet;
+        match subnet {
+            Some(subnet) => table.enforce_subnet_limit(subnet) != Some(pos),
+            None => true,
+        }
+    }
+
+    /// Returns address diversity of the table.
+    pub fn diversity(&self) -> Diversity {
+        let mut subnets = HashMap::new();
+        let mut max_per_bucket = 0;
+        let mut unknown = 0;
+        for table in &self.tables {
+            let mut bucket_subnets = HashMap::new();
+            for node in &table.nodes {
+                match node.subnet {
+                    Some(subnet) => {
+                        *subnets.entry(subnet).or_insert(0) += 1;
+                        *bucket_subnets.entry(subnet).or_insert(0) += 1;
+                    }
+                    None => unknown += 1,
+                }
+            }
+            max_per_bucket = bucket_subnets
+                .values()
+                .cloned()
+                .fold(max_per_bucket, std::cmp::max);
+        }
+        Diversity {
+            subnets: subnets.len(),
+            max_per_subnet: subnets.values().cloned().max().unwrap_or(0),
+            max_per_bucket_subnet: max_per_bucket,
+            unknown,
+        }
+    }
+
     /// Reports that we are now disconnected from the given node.
     ///
     /// This does *not* remove the node from the k-buckets, but moves it underneath the nodes we
     /// are still connected to.
     pub fn set_disconnected(&mut self, id: &TPeerId) {
+        if let Some(pos) = self.unstaked_pos(id) {
+            self.unstaked[pos].connected = false;
+            return;
+        }
+
         let table = match self.bucket_num(&id) {
             Some(n) => &mut self.tables[n],
             None => return,
@@ -415,7 +906,7 @@ where
     /// Finds the `num` nodes closest to `id`, ordered by distance.
     pub fn find_closest<TOther>(&mut self, id: &TOther) -> VecIntoIter<TPeerId>
     where
-        TPeerId: Clone + KBucketsPeerId<TOther>,
+        TPeerId: Clone + Distance<TOther>,
     {
         // TODO: optimize
         let mut out = Vec::new();
@@ -435,7 +926,7 @@ where
     /// Same as `find_closest`, but includes the local peer as well.
     pub fn find_closest_with_self<TOther>(&mut self, id: &TOther) -> VecIntoIter<TPeerId>
     where
-        TPeerId: Clone + KBucketsPeerId<TOther>,
+        TPeerId: Clone + Distance<TOther>,
     {
         // TODO: optimize
         let mut intermediate: Vec<_> = self.find_closest(id).collect();
@@ -451,18 +942,19 @@ where
         }
         intermediate.into_iter()
     }
+}
 
-    // Returns all ids stored in the table
-    fn dump(&mut self) -> VecIntoIter<TPeerId> {
-        let mut out = Vec::new();
-        for table in self.tables.iter_mut() {
-            table.flush(self.unresponsive_timeout);
-            for node in table.nodes.iter() {
-                out.push(node.id.clone());
-            }
-        }
-        out.into_iter()
-    }
+/// Address diversity of a table, see `KBucketsTable::diversity()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Diversity {
+    /// Number of distinct subnets.
+    pub subnets: usize,
+    /// Maximum number of nodes from one subnet in the whole table.
+    pub max_per_subnet: usize,
+    /// Maximum number of nodes from one subnet in a single bucket.
+    pub max_per_bucket_subnet: usize,
+    /// Number of nodes with unknown or unlimited subnet.
+    pub unknown: usize,
 }
 
 /// Return value of the `set_connected()` method.
@@ -530,17 +1022,258 @@ impl<'a, TPeerId, TVal> Bucket<'a, TPeerId, TVal> {
     pub fn latest_update(&self) -> Instant {
         self.0.latest_update
     }
+
+    /// Returns the number of distinct known subnets of nodes in this bucket.
+    pub fn num_subnets(&self) -> usize {
+        let subnets: HashSet<_> = self.0.nodes.iter().filter_map(|node| node.subnet).collect();
+        subnets.len()
+    }
+
+    /// Returns entries of this bucket as (id, value, connected, last update),
+    /// ordered from oldest to newest.
+    ///
+    /// Does **not** include the pending node.
+    pub fn entries(&self) -> impl Iterator<Item = (&TPeerId, &TVal, bool, Instant)> + '_ {
+        let first_connected_pos = self.0.first_connected_pos;
+        self.0.nodes.iter().enumerate().map(move |(pos, node)| {
+            (
+                &node.id,
+                &node.value,
+                pos >= first_connected_pos,
+                node.last_update,
+            )
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::kad::kbucket::{KBucketsPeerId, KBucketsTable, Update, MAX_NODES_PER_BUCKET};
+    use crate::kad::kbucket::{
+        xor_distance, AccessChecker, Distance, KBucketsTable, StakeChecker, Subnet, Update,
+        XorDigest, MAX_NODES_PER_BUCKET, MAX_NODES_PER_SUBNET, MAX_UNSTAKED_NODES,
+    };
+    use libp2p::core::multiaddr::Multiaddr;
     use libp2p::multihash::{Hash, Multihash};
     use rand::random;
+    use std::borrow::Cow;
+    use std::collections::HashSet;
+    use std::sync::{Arc, RwLock};
     use std::thread;
     use std::time::Duration;
     use stegos_crypto::pbc;
 
+    struct Stakes(RwLock<HashSet<pbc::PublicKey>>);
+
+    impl StakeChecker for Stakes {
+        fn is_staked(&self, id: &pbc::PublicKey) -> bool {
+            self.0.read().unwrap().contains(id)
+        }
+    }
+
+    struct Denied(HashSet<pbc::PublicKey>);
+
+    impl AccessChecker for Denied {
+        fn is_allowed(&self, id: &pbc::PublicKey) -> bool {
+            !self.0.contains(id)
+        }
+    }
+
+    /// Key with an 8-bit digest, the bucket of TestKey(n) is the highest set bit of n.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct TestKey(u8);
+
+    impl XorDigest for TestKey {
+        const DIGEST_BITS: usize = 8;
+
+        fn xor_digest(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(vec![self.0])
+        }
+    }
+
+    fn bucket_sizes<TVal: Clone + Default>(table: &mut KBucketsTable<TestKey, TVal>) -> Vec<usize> {
+        table.buckets().map(|b| b.num_entries()).collect()
+    }
+
+    #[test]
+    fn xor_distance_metric() {
+        assert_eq!(xor_distance(&[0], &[0]), 0);
+        assert_eq!(xor_distance(&[0], &[1]), 1);
+        assert_eq!(xor_distance(&[0x80], &[0]), 8);
+        assert_eq!(xor_distance(&[0x80], &[0x7f]), 8);
+        assert_eq!(xor_distance(&[0x41], &[0x40]), 1);
+        // Shorter digests are padded on the left.
+        assert_eq!(xor_distance(&[0, 1], &[1]), 0);
+        assert_eq!(xor_distance(&[1, 0], &[0]), 9);
+        assert_eq!(xor_distance(&[0xff, 0], &[]), 16);
+        // Symmetric.
+        assert_eq!(xor_distance(&[3], &[0x10]), xor_distance(&[0x10], &[3]));
+
+        assert_eq!(TestKey(0).distance_with(&TestKey(0xff)), 8);
+        assert_eq!(TestKey(0x10).distance_with(&TestKey(0x11)), 1);
+        assert_eq!(<TestKey as Distance>::max_distance(), 8);
+        assert_eq!(<Multihash as Distance>::max_distance(), 512);
+    }
+
+    #[test]
+    fn generic_insertion() {
+        let mut table = KBucketsTable::<_, u32>::new(TestKey(0), Duration::from_secs(5));
+        assert_eq!(table.buckets().len(), 8);
+        assert!(table.entry_mut(&TestKey(0)).is_none());
+
+        for (n, key) in [1u8, 2, 3, 0x80].iter().enumerate() {
+            *table.entry_mut(&TestKey(*key)).unwrap() = n as u32;
+        }
+        assert_eq!(bucket_sizes(&mut table), vec![1, 2, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(table.size(), 4);
+        assert_eq!(table.get(&TestKey(3)), Some(&2));
+        assert_eq!(table.get(&TestKey(4)), None);
+
+        // Existing entries are updated in place.
+        *table.entry_mut(&TestKey(3)).unwrap() += 10;
+        assert_eq!(table.get(&TestKey(3)), Some(&12));
+        assert_eq!(table.size(), 4);
+        match table.set_connected(&TestKey(2)) {
+            Update::Updated => (),
+            _ => panic!(),
+        }
+        match table.set_connected(&TestKey(4)) {
+            Update::Added => (),
+            _ => panic!(),
+        }
+        assert_eq!(bucket_sizes(&mut table), vec![1, 2, 1, 0, 0, 0, 0, 1]);
+
+        // A full bucket rejects new nodes.
+        for key in 0x80..0x80 + MAX_NODES_PER_BUCKET as u8 {
+            table.entry_mut(&TestKey(key));
+        }
+        assert_eq!(
+            table.buckets().nth(7).unwrap().num_entries(),
+            MAX_NODES_PER_BUCKET
+        );
+        assert!(table.entry_mut(&TestKey(0xff)).is_none());
+        assert_eq!(table.get(&TestKey(0x80)), Some(&3));
+    }
+
+    #[test]
+    fn generic_eviction() {
+        let mut table = KBucketsTable::<_, u32>::new(TestKey(0), Duration::from_secs(5));
+        for n in 0..MAX_NODES_PER_BUCKET {
+            *table.entry_mut(&TestKey(0x80 + n as u8)).unwrap() = n as u32;
+        }
+        thread::sleep(Duration::from_millis(10));
+        // Refresh the second oldest node.
+        table.entry_mut(&TestKey(0x81));
+
+        // The least recently updated node accepted by the filter is evicted.
+        let newcomer = TestKey(0xff);
+        assert!(table.entry_mut(&newcomer).is_none());
+        *table
+            .entry_mut_or_evict(&newcomer, |_, value| *value != 0)
+            .unwrap() = 100;
+        assert_eq!(table.size(), MAX_NODES_PER_BUCKET);
+        assert_eq!(table.get(&newcomer), Some(&100));
+        assert_eq!(table.get(&TestKey(0x80)), Some(&0));
+        assert_eq!(table.get(&TestKey(0x81)), Some(&1));
+        assert_eq!(table.get(&TestKey(0x82)), None);
+
+        // Existing nodes don't evict anyone.
+        assert_eq!(
+            table.entry_mut_or_evict(&newcomer, |_, _| true),
+            Some(&mut 100)
+        );
+        assert_eq!(table.size(), MAX_NODES_PER_BUCKET);
+
+        // Nothing to evict.
+        assert!(table
+            .entry_mut_or_evict(&TestKey(0xfe), |_, _| false)
+            .is_none());
+        assert!(table.entry_mut_or_evict(&TestKey(0), |_, _| true).is_none());
+        assert_eq!(table.get(&TestKey(0xfe)), None);
+    }
+
+    #[test]
+    fn generic_expiration() {
+        let timeout = Duration::from_millis(100);
+        let mut table = KBucketsTable::<_, ()>::new(TestKey(0), timeout);
+        table.entry_mut(&TestKey(1));
+        table.entry_mut(&TestKey(0x80));
+        assert_eq!(table.find_closest(&TestKey(0)).count(), 2);
+
+        // Buckets which haven't been updated recently are ignored.
+        thread::sleep(timeout * 2);
+        assert_eq!(table.find_closest(&TestKey(0)).count(), 0);
+        table.entry_mut(&TestKey(0x81));
+        let closest = table.find_closest(&TestKey(0)).collect::<Vec<_>>();
+        assert_eq!(closest, vec![TestKey(0x80), TestKey(0x81)]);
+        assert_eq!(table.size(), 3);
+
+        // An unresponsive node is replaced by the pending one.
+        let mut table = KBucketsTable::<_, ()>::new(TestKey(0), timeout);
+        for n in 0..MAX_NODES_PER_BUCKET {
+            let key = TestKey(0x80 + n as u8);
+            match table.set_connected(&key) {
+                Update::Added => (),
+                _ => panic!(),
+            }
+            table.set_disconnected(&key);
+        }
+        let pending = TestKey(0xff);
+        match table.set_connected(&pending) {
+            Update::Pending(to_ping) => assert_eq!(*to_ping, TestKey(0x80)),
+            _ => panic!(),
+        }
+        assert!(table.get(&pending).is_none());
+        // The oldest node responds in time.
+        match table.set_connected(&TestKey(0x80)) {
+            Update::Updated => (),
+            _ => panic!(),
+        }
+        assert!(table.buckets().nth(7).unwrap().has_pending());
+        thread::sleep(timeout * 2);
+        assert!(!table.buckets().nth(7).unwrap().has_pending());
+        assert!(table.get_mut(&pending).is_some());
+        assert!(table.get(&TestKey(0x80)).is_some());
+        assert!(table.get(&TestKey(0x81)).is_none());
+        assert_eq!(table.size(), MAX_NODES_PER_BUCKET);
+    }
+
+    #[test]
+    fn generic_find_closest_ordering() {
+        let my_id = TestKey(0);
+        let mut table = KBucketsTable::<_, ()>::new(my_id, Duration::from_secs(5));
+        for key in 1..=255u8 {
+            table.entry_mut(&TestKey(key));
+        }
+        // Buckets 0..4 are complete, others are limited.
+        let full = MAX_NODES_PER_BUCKET;
+        assert_eq!(
+            bucket_sizes(&mut table),
+            vec![1, 2, 4, 8, 16, full, full, full]
+        );
+
+        for target in &[TestKey(0), TestKey(0x2a), TestKey(0x80), TestKey(0xff)] {
+            let closest = table.find_closest(target).collect::<Vec<_>>();
+            assert_eq!(closest.len(), table.size());
+            assert!(!closest.contains(&my_id));
+            for pair in closest.windows(2) {
+                assert!(pair[0].distance_with(target) <= pair[1].distance_with(target));
+            }
+            if table.get(target).is_some() {
+                assert_eq!(closest[0], *target);
+            }
+
+            let with_self = table.find_closest_with_self(target).collect::<Vec<_>>();
+            assert_eq!(with_self.len(), closest.len() + 1);
+            assert_eq!(with_self.iter().filter(|k| **k == my_id).count(), 1);
+            for pair in with_self.windows(2) {
+                assert!(pair[0].distance_with(target) <= pair[1].distance_with(target));
+            }
+        }
+        let closest = table.find_closest(&my_id).take(4).collect::<Vec<_>>();
+        assert_eq!(closest[0], TestKey(1));
+        assert_eq!(&closest[1..3], &[TestKey(2), TestKey(3)]);
+    }
+
     #[test]
     fn basic_closest() {
         let my_id = Multihash::random(Hash::SHA2256);
@@ -566,6 +1299,71 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bucket_entries() {
+        let my_id = Multihash::random(Hash::SHA2256);
+        let id1 = Multihash::random(Hash::SHA2256);
+        let id2 = Multihash::random(Hash::SHA2256);
+
+        let mut table = KBucketsTable::<_, ()>::new(my_id, Duration::from_secs(5));
+        table.entry_mut(&id1);
+        match table.set_connected(&id2) {
+            Update::Added => (),
+            _ => panic!(),
+        }
+
+        let mut entries = Vec::new();
+        for bucket in table.buckets() {
+            for (id, _value, connected, _last_update) in bucket.entries() {
+                entries.push((id.clone(), connected));
+            }
+        }
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains(&(id1.clone(), false)));
+        assert!(entries.contains(&(id2.clone(), true)));
+
+        table.set_disconnected(&id2);
+        let connected = table
+            .buckets()
+            .flat_map(|b| b.entries().map(|(_, _, c, _)| c).collect::<Vec<_>>())
+            .any(|c| c);
+        assert!(!connected);
+    }
+
+    #[test]
+    fn new_table_keeps_entries() {
+        let my_id = Multihash::random(Hash::SHA2256);
+        let new_id = Multihash::random(Hash::SHA2256);
+        let id1 = Multihash::random(Hash::SHA2256);
+        let id2 = Multihash::random(Hash::SHA2256);
+
+        let mut table = KBucketsTable::<_, u32>::new(my_id.clone(), Duration::from_secs(5));
+        *table.entry_mut(&id1).unwrap() = 1;
+        match table.set_connected(&id2) {
+            Update::Added => (),
+            _ => panic!(),
+        }
+        *table.entry_mut(&new_id).unwrap() = 3;
+
+        let mut new_table = table.new_table(new_id.clone());
+        assert_eq!(new_table.my_id(), &new_id);
+        assert_eq!(new_table.size(), 2);
+        assert_eq!(new_table.get(&id1), Some(&1));
+        assert!(new_table.get(&new_id).is_none());
+
+        let mut entries = Vec::new();
+        for bucket in new_table.buckets() {
+            for (id, _value, connected, _last_update) in bucket.entries() {
+                entries.push((id.clone(), connected));
+            }
+        }
+        assert!(entries.contains(&(id1.clone(), false)));
+        assert!(entries.contains(&(id2.clone(), true)));
+
+        let res = new_table.find_closest(&id2).collect::<Vec<_>>();
+        assert_eq!(res[0], id2);
+    }
+
     #[test]
     fn update_time_last_refresh() {
         let my_id = Multihash::random(Hash::SHA2256);
@@ -665,6 +1463,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn subnet_from_multiaddr() {
+        let subnet = |addr: &str| Subnet::from_multiaddr(&addr.parse::<Multiaddr>().unwrap());
+        assert_eq!(subnet("/ip4/8.8.4.4/tcp/10203"), Some(Subnet::Ipv4([8, 8])));
+        assert_eq!(
+            subnet("/ip4/8.8.8.8/tcp/10203"),
+            subnet("/ip4/8.8.4.4/tcp/1")
+        );
+        assert_ne!(
+            subnet("/ip4/8.9.8.8/tcp/10203"),
+            subnet("/ip4/8.8.8.8/tcp/10203")
+        );
+        assert_eq!(
+            subnet("/ip6/2001:db8:1::1/tcp/10203"),
+            Some(Subnet::Ipv6([0x20, 0x01, 0x0d, 0xb8]))
+        );
+        assert_eq!(subnet("/ip4/127.0.0.1/tcp/10203"), None);
+        assert_eq!(subnet("/ip4/192.168.1.1/tcp/10203"), None);
+        assert_eq!(subnet("/ip6/::1/tcp/10203"), None);
+        assert_eq!(subnet("/dns4/example.com/tcp/10203"), None);
+    }
+
+    #[test]
+    fn subnet_limit() {
+        let my_id = Multihash::random(Hash::SHA2256);
+        let ids = (0..MAX_NODES_PER_SUBNET + 2)
+            .map(|n| {
+                let mut id = my_id.clone().into_bytes();
+                id[2] ^= 0x80; // Flip the first bit so that we get in the most distant bucket.
+                id[33] = id[33].wrapping_add(n as u8);
+                Multihash::from_bytes(id).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let subnet = Some(Subnet::Ipv4([8, 8]));
+        let other_subnet = Some(Subnet::Ipv4([8, 9]));
+
+        let mut table = KBucketsTable::<_, ()>::new(my_id, Duration::from_secs(5));
+        for id in &ids[..MAX_NODES_PER_SUBNET] {
+            table.entry_mut(id);
+            // Make sure that nodes have different lifetimes.
+            thread::sleep(Duration::from_millis(10));
+            assert!(table.set_subnet(id, subnet));
+        }
+        let diversity = table.diversity();
+        assert_eq!(diversity.subnets, 1);
+        assert_eq!(diversity.max_per_bucket_subnet, MAX_NODES_PER_SUBNET);
+
+        // The newest node from the same subnet is evicted.
+        let newcomer = &ids[MAX_NODES_PER_SUBNET];
+        table.entry_mut(newcomer);
+        assert!(!table.set_subnet(newcomer, subnet));
+        assert!(table.get(newcomer).is_none());
+        for id in &ids[..MAX_NODES_PER_SUBNET] {
+            assert!(table.get(id).is_some());
+        }
+
+        // Other subnets are not affected.
+        let other = &ids[MAX_NODES_PER_SUBNET + 1];
+        table.entry_mut(other);
+        assert!(table.set_subnet(other, other_subnet));
+        let diversity = table.diversity();
+        assert_eq!(diversity.subnets, 2);
+        assert_eq!(diversity.max_per_subnet, MAX_NODES_PER_SUBNET);
+        assert_eq!(diversity.unknown, 0);
+        assert_eq!(table.buckets().nth(255).unwrap().num_subnets(), 2);
+
+        // Moving a node to a crowded subnet evicts the newest node of the subnet.
+        assert!(table.set_subnet(&ids[0], other_subnet));
+        thread::sleep(Duration::from_millis(10));
+        table.entry_mut(newcomer);
+        assert!(table.set_subnet(newcomer, subnet));
+        assert!(table.set_subnet(other, subnet));
+        assert!(table.get(newcomer).is_none());
+        assert!(table.get(other).is_some());
+    }
+
     #[test]
     fn self_distance_zero() {
         let a = Multihash::random(Hash::SHA2256);
@@ -749,4 +1623,82 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn unstaked_nodes() {
+        let (_, my_id) = pbc::make_random_keys();
+        let (_, staked_id) = pbc::make_random_keys();
+        let (_, unstaked_id) = pbc::make_random_keys();
+        let stakes = Arc::new(Stakes(RwLock::new(HashSet::new())));
+        stakes.0.write().unwrap().insert(staked_id);
+
+        let mut table = KBucketsTable::<_, ()>::new(my_id, Duration::from_secs(5));
+        table.entry_mut(&unstaked_id);
+        assert_eq!(table.size(), 1);
+        table.set_stake_checker(stakes.clone());
+        assert_eq!(table.size(), 0);
+        assert_eq!(table.unstaked_size(), 1);
+
+        assert!(table.entry_mut(&staked_id).is_some());
+        match table.set_connected(&unstaked_id) {
+            Update::Updated => (),
+            _ => panic!(),
+        }
+        assert!(table.get(&unstaked_id).is_some());
+        let closest = table.find_closest(&unstaked_id).collect::<Vec<_>>();
+        assert_eq!(closest, vec![staked_id]);
+
+        // The unstaked table is limited.
+        for _ in 0..MAX_UNSTAKED_NODES {
+            let (_, id) = pbc::make_random_keys();
+            assert!(table.entry_mut(&id).is_some());
+        }
+        assert_eq!(table.unstaked_size(), MAX_UNSTAKED_NODES);
+        // Connected nodes are evicted last.
+        assert!(table.get(&unstaked_id).is_some());
+
+        // The node has staked.
+        stakes.0.write().unwrap().insert(unstaked_id);
+        table.refresh_stakes();
+        assert_eq!(table.size(), 2);
+        assert_eq!(table.unstaked_size(), MAX_UNSTAKED_NODES - 1);
+        let closest = table.find_closest(&unstaked_id).collect::<Vec<_>>();
+        assert_eq!(closest[0], unstaked_id);
+    }
+
+    #[test]
+    fn denied_nodes() {
+        let (_, my_id) = pbc::make_random_keys();
+        let (_, allowed_id) = pbc::make_random_keys();
+        let (_, denied_id) = pbc::make_random_keys();
+        let (_, unstaked_id) = pbc::make_random_keys();
+        let stakes = Arc::new(Stakes(RwLock::new(HashSet::new())));
+        stakes.0.write().unwrap().insert(allowed_id);
+        stakes.0.write().unwrap().insert(denied_id);
+
+        let mut table = KBucketsTable::<_, ()>::new(my_id, Duration::from_secs(5));
+        table.set_stake_checker(stakes);
+        assert!(table.entry_mut(&allowed_id).is_some());
+        assert!(table.entry_mut(&denied_id).is_some());
+        assert!(table.entry_mut(&unstaked_id).is_some());
+        assert_eq!(table.size(), 2);
+        assert_eq!(table.unstaked_size(), 1);
+
+        let mut denied = HashSet::new();
+        denied.insert(denied_id);
+        denied.insert(unstaked_id);
+        table.set_access_checker(Arc::new(Denied(denied)));
+        assert_eq!(table.size(), 1);
+        assert_eq!(table.unstaked_size(), 0);
+        assert!(table.get(&denied_id).is_none());
+
+        assert!(table.entry_mut(&denied_id).is_none());
+        match table.set_connected(&unstaked_id) {
+            Update::Discarded => (),
+            _ => panic!(),
+        }
+        assert!(table.entry_mut(&allowed_id).is_some());
+        let closest = table.find_closest(&denied_id).collect::<Vec<_>>();
+        assert_eq!(closest, vec![allowed_id]);
+    }
 }
@@ -27,4 +27,39 @@ lazy_static! {
         register_int_gauge!("stegos_kad_kbutcket_table_size", "Size of k-buckets table.").unwrap();
     pub static ref PEER_TABLE_SIZE: IntGauge =
         register_int_gauge!("stegos_kad_peer_table_size", "Size of k-buckets table.").unwrap();
+    pub static ref KBUCKET_SUBNETS: IntGauge = register_int_gauge!(
+        "stegos_kad_kbucket_subnets",
+        "Number of distinct IP subnets in k-buckets table."
+    )
+    .unwrap();
+    pub static ref KBUCKET_MAX_PER_SUBNET: IntGauge = register_int_gauge!(
+        "stegos_kad_kbucket_max_per_subnet",
+        "Maximum number of nodes from one IP subnet in k-buckets table."
+    )
+    .unwrap();
+    pub static ref KBUCKET_SUBNET_EVICTIONS: IntCounter = register_int_counter!(
+        "stegos_kad_kbucket_subnet_evictions",
+        "Number of nodes evicted from k-buckets table to keep IP subnets diverse."
+    )
+    .unwrap();
+    pub static ref KBUCKET_UNSTAKED_SIZE: IntGauge = register_int_gauge!(
+        "stegos_kad_kbucket_unstaked_size",
+        "Number of unstaked nodes kept outside of k-buckets table."
+    )
+    .unwrap();
+    pub static ref QUERY_DEDUP_HITS: IntCounter = register_int_counter!(
+        "stegos_kad_query_dedup_hits",
+        "Number of queries attached to an in-flight query with the same target."
+    )
+    .unwrap();
+    pub static ref REMOTE_REQUESTS_SIZE: IntGauge = register_int_gauge!(
+        "stegos_kad_remote_requests_size",
+        "Number of inbound requests waiting to be answered."
+    )
+    .unwrap();
+    pub static ref REMOTE_REQUESTS_DROPPED: IntCounter = register_int_counter!(
+        "stegos_kad_remote_requests_dropped",
+        "Number of inbound requests dropped because of per-peer rate limits."
+    )
+    .unwrap();
 }
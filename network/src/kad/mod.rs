@@ -23,9 +23,14 @@
 //!
 #![allow(dead_code)]
 
-pub use self::behaviour::{Kademlia, KademliaOut, NodeInfo};
-pub use self::kbucket::KBucketsPeerId;
+pub use self::addresses::AddressRecord;
+pub use self::behaviour::{BucketEntry, BucketInfo, Kademlia, KademliaOut, NodeInfo};
+pub use self::journal::{QueryJournal, QueryRecord, RpcRecord};
+pub use self::kbucket::{AccessChecker, Distance, StakeChecker, XorDigest};
 pub use self::protocol::KadConnectionType;
+pub use self::query::QueryTimeout;
+pub use self::ratelimit::RateLimitViolation;
+pub use self::wallet_record::WalletRecord;
 
 pub mod handler;
 pub mod kbucket;
@@ -34,5 +39,8 @@ pub mod protocol;
 mod addresses;
 mod behaviour;
 mod dht_proto;
+mod journal;
 mod metrics;
 mod query;
+mod ratelimit;
+mod wallet_record;
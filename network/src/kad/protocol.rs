@@ -27,7 +27,10 @@
 //! The `Stream` component is used to poll the underlying transport, and the `Sink` component is
 //! used to send messages.
 
+use super::addresses::AddressRecord;
 use super::dht_proto;
+use super::wallet_record::WalletRecord;
+use crate::utils::{compression, offered_protocols, ProtocolId, ProtocolVersion};
 use bytes::BytesMut;
 use futures::{future, sink, stream, Sink, Stream};
 use libp2p::core::{
@@ -37,8 +40,10 @@ use libp2p::multihash::Multihash;
 use protobuf::{self, Message};
 use std::convert::TryFrom;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
-use std::iter;
+use std::vec;
+use stegos_crypto::curve1174;
 use stegos_crypto::pbc;
+use stegos_serialization::traits::ProtoConvert;
 use tokio::codec::Framed;
 use tokio::io::{AsyncRead, AsyncWrite};
 use unsigned_varint::codec;
@@ -97,6 +102,10 @@ pub struct KadPeer {
     pub multiaddrs: Vec<Multiaddr>,
     /// How the sender is connected to that remote.
     pub connection_ty: KadConnectionType,
+    /// The newest signed announcement of addresses of the peer known to the sender.
+    pub record: Option<AddressRecord>,
+    /// Binding of the requested wallet key to the peer, for provider peers.
+    pub wallet_record: Option<WalletRecord>,
 }
 
 impl KadPeer {
@@ -127,11 +136,50 @@ impl KadPeer {
 
         let connection_ty = peer.get_connection().into();
 
+        let record = if peer.has_record() {
+            let mut record = peer.take_record();
+            let mut addrs = Vec::with_capacity(record.get_addrs().len());
+            for addr in record.take_addrs().into_iter() {
+                let as_ma = Multiaddr::try_from(addr)
+                    .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+                addrs.push(as_ma);
+            }
+            let signature = pbc::Signature::try_from_bytes(record.get_signature())
+                .map_err(|_| IoError::new(IoErrorKind::InvalidData, "invalid record signature"))?;
+            Some(AddressRecord {
+                node_id,
+                seq: record.get_seq(),
+                addrs,
+                signature,
+            })
+        } else {
+            None
+        };
+
+        let wallet_record = if peer.has_wallet_record() {
+            let wallet_record = peer.get_wallet_record();
+            let wallet_pkey = curve1174::PublicKey::try_from_bytes(wallet_record.get_wallet_pkey())
+                .map_err(|_| IoError::new(IoErrorKind::InvalidData, "invalid wallet key"))?;
+            let signature = curve1174::SchnorrSig::from_buffer(wallet_record.get_signature())
+                .map_err(|_| {
+                    IoError::new(IoErrorKind::InvalidData, "invalid wallet record signature")
+                })?;
+            Some(WalletRecord {
+                wallet_pkey,
+                node_id,
+                signature,
+            })
+        } else {
+            None
+        };
+
         Ok(KadPeer {
             node_id,
             peer_id,
             multiaddrs: addrs,
             connection_ty,
+            record,
+            wallet_record,
         })
     }
 }
@@ -147,6 +195,25 @@ impl Into<dht_proto::dht::Message_Peer> for KadPeer {
             out.mut_addrs().push(addr.to_vec());
         }
         out.set_connection(self.connection_ty.into());
+        if let Some(record) = self.record {
+            let mut proto = dht_proto::dht::AddressRecord::new();
+            proto.set_seq(record.seq);
+            for addr in record.addrs {
+                proto.mut_addrs().push(addr.to_vec());
+            }
+            proto.set_signature(record.signature.to_bytes().to_vec());
+            out.set_record(proto);
+        }
+        if let Some(wallet_record) = self.wallet_record {
+            let mut proto = dht_proto::dht::WalletRecord::new();
+            proto.set_wallet_pkey(wallet_record.wallet_pkey.to_bytes().to_vec());
+            let signature = wallet_record
+                .signature
+                .into_buffer()
+                .expect("signature is serializable");
+            proto.set_signature(signature);
+            out.set_wallet_record(proto);
+        }
         out
     }
 }
@@ -157,15 +224,43 @@ impl Into<dht_proto::dht::Message_Peer> for KadPeer {
 //       only one request, then we can change the output of the `InboundUpgrade` and
 //       `OutboundUpgrade` to be just a single message
 #[derive(Debug, Default, Copy, Clone)]
-pub struct KademliaProtocolConfig;
+pub struct KademliaProtocolConfig {
+    /// Offer snappy compression during protocol negotiation.
+    compression: bool,
+}
+
+impl KademliaProtocolConfig {
+    /// Builds a new `KademliaProtocolConfig`.
+    pub fn new(compression: bool) -> Self {
+        KademliaProtocolConfig { compression }
+    }
+}
+
+/// The first version of the Kademlia protocol.
+pub const KAD_VERSION_1: ProtocolVersion = ProtocolVersion(1);
+/// Supported versions of the Kademlia protocol.
+const PROTOCOLS: &[ProtocolId] = &[
+    ProtocolId {
+        name: b"/stegos/kad/1.0.0/snappy",
+        version: KAD_VERSION_1,
+        compression: true,
+    },
+    ProtocolId {
+        name: b"/stegos/kad/1.0.0",
+        version: KAD_VERSION_1,
+        compression: false,
+    },
+];
+/// Maximal size of a message, see `compression::frame_len()`.
+const MAX_FRAME_SIZE: usize = 4096;
 
 impl UpgradeInfo for KademliaProtocolConfig {
-    type Info = &'static [u8];
-    type InfoIter = iter::Once<Self::Info>;
+    type Info = ProtocolId;
+    type InfoIter = vec::IntoIter<Self::Info>;
 
     #[inline]
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(b"/stegos/kad/1.0.0")
+        offered_protocols(PROTOCOLS, self.compression).into_iter()
     }
 }
 
@@ -173,29 +268,29 @@ impl<C> InboundUpgrade<C> for KademliaProtocolConfig
 where
     C: AsyncRead + AsyncWrite,
 {
-    type Output = KadInStreamSink<Negotiated<C>>;
+    type Output = (KadInStreamSink<Negotiated<C>>, ProtocolVersion);
     type Future = future::FutureResult<Self::Output, IoError>;
     type Error = IoError;
 
     #[inline]
-    fn upgrade_inbound(self, incoming: Negotiated<C>, _: Self::Info) -> Self::Future {
+    fn upgrade_inbound(self, incoming: Negotiated<C>, info: Self::Info) -> Self::Future {
         let mut codec = codec::UviBytes::default();
-        codec.set_max_len(4096);
-
-        future::ok(
-            Framed::new(incoming, codec)
-                .from_err::<IoError>()
-                .with::<_, fn(_) -> _, _>(|response| -> Result<_, IoError> {
-                    let proto_struct = resp_msg_to_proto(response);
-                    proto_struct
-                        .write_to_bytes()
-                        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err.to_string()))
-                })
-                .and_then::<fn(_) -> _, _>(|bytes: BytesMut| {
-                    let request = protobuf::parse_from_bytes(&bytes)?;
-                    proto_to_req_msg(request)
-                }),
-        )
+        codec.set_max_len(compression::frame_len(MAX_FRAME_SIZE, info.compression));
+
+        let (encode, decode): (
+            fn(KadResponseMsg) -> Result<Vec<u8>, IoError>,
+            fn(BytesMut) -> Result<KadRequestMsg, IoError>,
+        ) = if info.compression {
+            (encode_response_compressed, decode_request_compressed)
+        } else {
+            (encode_response, decode_request)
+        };
+
+        let sink = Framed::new(incoming, codec)
+            .from_err::<IoError>()
+            .with::<_, fn(_) -> _, _>(encode)
+            .and_then::<fn(_) -> _, _>(decode);
+        future::ok((sink, info.version))
     }
 }
 
@@ -203,33 +298,74 @@ impl<C> OutboundUpgrade<C> for KademliaProtocolConfig
 where
     C: AsyncRead + AsyncWrite,
 {
-    type Output = KadOutStreamSink<Negotiated<C>>;
+    type Output = (KadOutStreamSink<Negotiated<C>>, ProtocolVersion);
     type Future = future::FutureResult<Self::Output, IoError>;
     type Error = IoError;
 
     #[inline]
-    fn upgrade_outbound(self, incoming: Negotiated<C>, _: Self::Info) -> Self::Future {
+    fn upgrade_outbound(self, incoming: Negotiated<C>, info: Self::Info) -> Self::Future {
         let mut codec = codec::UviBytes::default();
-        codec.set_max_len(4096);
-
-        future::ok(
-            Framed::new(incoming, codec)
-                .from_err::<IoError>()
-                .with::<_, fn(_) -> _, _>(|request| -> Result<_, IoError> {
-                    let proto_struct = req_msg_to_proto(request);
-                    match proto_struct.write_to_bytes() {
-                        Ok(msg) => Ok(msg),
-                        Err(err) => Err(IoError::new(IoErrorKind::Other, err.to_string())),
-                    }
-                })
-                .and_then::<fn(_) -> _, _>(|bytes: BytesMut| {
-                    let response = protobuf::parse_from_bytes(&bytes)?;
-                    proto_to_resp_msg(response)
-                }),
-        )
+        codec.set_max_len(compression::frame_len(MAX_FRAME_SIZE, info.compression));
+
+        let (encode, decode): (
+            fn(KadRequestMsg) -> Result<Vec<u8>, IoError>,
+            fn(BytesMut) -> Result<KadResponseMsg, IoError>,
+        ) = if info.compression {
+            (encode_request_compressed, decode_response_compressed)
+        } else {
+            (encode_request, decode_response)
+        };
+
+        let sink = Framed::new(incoming, codec)
+            .from_err::<IoError>()
+            .with::<_, fn(_) -> _, _>(encode)
+            .and_then::<fn(_) -> _, _>(decode);
+        future::ok((sink, info.version))
     }
 }
 
+fn encode_response(response: KadResponseMsg) -> Result<Vec<u8>, IoError> {
+    let proto_struct = resp_msg_to_proto(response);
+    proto_struct
+        .write_to_bytes()
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err.to_string()))
+}
+
+fn encode_response_compressed(response: KadResponseMsg) -> Result<Vec<u8>, IoError> {
+    compression::compress("kad", &encode_response(response)?)
+}
+
+fn decode_request(bytes: BytesMut) -> Result<KadRequestMsg, IoError> {
+    let request = protobuf::parse_from_bytes(&bytes)?;
+    proto_to_req_msg(request)
+}
+
+fn decode_request_compressed(bytes: BytesMut) -> Result<KadRequestMsg, IoError> {
+    let bytes = compression::decompress("kad", &bytes, MAX_FRAME_SIZE)?;
+    decode_request(BytesMut::from(bytes))
+}
+
+fn encode_request(request: KadRequestMsg) -> Result<Vec<u8>, IoError> {
+    let proto_struct = req_msg_to_proto(request);
+    proto_struct
+        .write_to_bytes()
+        .map_err(|err| IoError::new(IoErrorKind::Other, err.to_string()))
+}
+
+fn encode_request_compressed(request: KadRequestMsg) -> Result<Vec<u8>, IoError> {
+    compression::compress("kad", &encode_request(request)?)
+}
+
+fn decode_response(bytes: BytesMut) -> Result<KadResponseMsg, IoError> {
+    let response = protobuf::parse_from_bytes(&bytes)?;
+    proto_to_resp_msg(response)
+}
+
+fn decode_response_compressed(bytes: BytesMut) -> Result<KadResponseMsg, IoError> {
+    let bytes = compression::decompress("kad", &bytes, MAX_FRAME_SIZE)?;
+    decode_response(BytesMut::from(bytes))
+}
+
 /// Sink of responses and stream of requests.
 pub type KadInStreamSink<S> = stream::AndThen<
     sink::With<
@@ -480,13 +616,16 @@ fn proto_to_resp_msg(mut message: dht_proto::dht::Message) -> Result<KadResponse
 #[cfg(test)]
 mod tests {
     use super::{proto_to_req_msg, proto_to_resp_msg, req_msg_to_proto, resp_msg_to_proto};
+    use crate::kad::addresses::AddressRecord;
     use crate::kad::protocol::{KadConnectionType, KadPeer, KadRequestMsg, KadResponseMsg};
+    use crate::kad::wallet_record::WalletRecord;
     use bytes::BytesMut;
     use futures::{future, Future, Sink, Stream};
     use libp2p::core::PeerId;
     use libp2p::multihash::{encode, Hash, Multihash};
     use protobuf::Message;
     use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+    use stegos_crypto::curve1174;
     use stegos_crypto::pbc;
     use tokio::codec::Framed;
     use tokio::net::{TcpListener, TcpStream};
@@ -512,6 +651,8 @@ mod tests {
                 peer_id: Some(PeerId::random()),
                 multiaddrs: vec!["/ip4/9.1.2.3/udp/23".parse().unwrap()],
                 connection_ty: KadConnectionType::Connected,
+                record: None,
+                wallet_record: None,
             },
         });
 
@@ -523,6 +664,24 @@ mod tests {
                 peer_id: Some(PeerId::random()),
                 multiaddrs: vec!["/ip4/100.101.102.103/tcp/20105".parse().unwrap()],
                 connection_ty: KadConnectionType::Connected,
+                record: None,
+                wallet_record: None,
+            }],
+        });
+        let (skey, pkey) = pbc::make_random_keys();
+        test_one_res(KadResponseMsg::FindNode {
+            closer_peers: vec![KadPeer {
+                node_id: pkey,
+                peer_id: Some(PeerId::random()),
+                multiaddrs: vec!["/ip4/100.101.102.103/tcp/20105".parse().unwrap()],
+                connection_ty: KadConnectionType::Connected,
+                record: Some(AddressRecord::new(
+                    &skey,
+                    pkey,
+                    42,
+                    vec!["/ip4/100.101.102.104/tcp/20105".parse().unwrap()],
+                )),
+                wallet_record: None,
             }],
         });
         test_one_res(KadResponseMsg::GetProviders {
@@ -531,12 +690,28 @@ mod tests {
                 peer_id: Some(PeerId::random()),
                 multiaddrs: vec!["/ip4/100.101.102.103/tcp/20105".parse().unwrap()],
                 connection_ty: KadConnectionType::Connected,
+                record: None,
+                wallet_record: None,
             }],
             provider_peers: vec![KadPeer {
                 node_id: pbc::PublicKey::from(pbc::G2::generator()),
                 peer_id: Some(PeerId::random()),
                 multiaddrs: vec!["/ip4/200.201.202.203/tcp/1999".parse().unwrap()],
                 connection_ty: KadConnectionType::NotConnected,
+                record: None,
+                wallet_record: None,
+            }],
+        });
+        let (wallet_skey, wallet_pkey) = curve1174::make_random_keys();
+        test_one_res(KadResponseMsg::GetProviders {
+            closer_peers: vec![],
+            provider_peers: vec![KadPeer {
+                node_id: pkey,
+                peer_id: Some(PeerId::random()),
+                multiaddrs: vec!["/ip4/200.201.202.203/tcp/1999".parse().unwrap()],
+                connection_ty: KadConnectionType::Connected,
+                record: None,
+                wallet_record: Some(WalletRecord::new(&wallet_skey, wallet_pkey, pkey)),
             }],
         });
 
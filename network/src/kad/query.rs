@@ -25,11 +25,13 @@
 //! closer to the target.
 
 use super::handler::KademliaHandlerIn;
-use super::kbucket::KBucketsPeerId;
+use super::kbucket::Distance;
 use futures::prelude::*;
 use libp2p::multihash::Multihash;
 use log::debug;
+use serde_derive::Serialize;
 use smallvec::SmallVec;
+use std::fmt;
 use std::time::{Duration, Instant};
 use stegos_crypto::pbc;
 use stegos_crypto::utils::u8v_to_hexstr;
@@ -68,6 +70,15 @@ pub struct QueryState {
 
     /// Timeout for each individual RPC query.
     rpc_timeout: Duration,
+
+    /// Fires when the query runs out of time.
+    deadline: Delay,
+
+    /// The query is abandoned if no RPC succeeds for so long.
+    stall_timeout: Duration,
+
+    /// Fires when the query makes no progress for `stall_timeout`.
+    stall_timer: Delay,
 }
 
 /// Configuration for a query.
@@ -88,6 +99,12 @@ pub struct QueryConfig<TIter> {
 
     /// Timeout for each individual RPC query.
     pub rpc_timeout: Duration,
+
+    /// Overall deadline of the query.
+    pub query_timeout: Duration,
+
+    /// The query is abandoned if no RPC succeeds for so long.
+    pub stall_timeout: Duration,
 }
 
 /// Stage of the query.
@@ -120,6 +137,7 @@ impl QueryState {
         let target = config.target;
         closest_peers.sort_by_key(|e| target.as_hash().distance_with(&e.0.into_multihash()));
         closest_peers.dedup_by(|a, b| a.0 == b.0);
+        let now = Instant::now();
 
         QueryState {
             target,
@@ -130,6 +148,9 @@ impl QueryState {
             parallelism: config.parallelism,
             num_results: config.num_results,
             rpc_timeout: config.rpc_timeout,
+            deadline: Delay::new(now + config.query_timeout),
+            stall_timeout: config.stall_timeout,
+            stall_timer: Delay::new(now + config.stall_timeout),
         }
     }
 
@@ -165,6 +186,7 @@ impl QueryState {
             if peer_id == result_source {
                 if let state @ QueryPeerState::InProgress(_) = state {
                     *state = QueryPeerState::Succeeded;
+                    self.stall_timer.reset(Instant::now() + self.stall_timeout);
                 }
             }
         }
@@ -302,8 +324,27 @@ impl QueryState {
         }
     }
 
+    /// Returns the reason to abandon the query, if any.
+    /// Timer errors only happen on shutdown and are ignored.
+    fn poll_timeout(&mut self) -> Option<QueryTimeout> {
+        if let Ok(Async::Ready(_)) = self.deadline.poll() {
+            return Some(QueryTimeout::Deadline);
+        }
+        if let Ok(Async::Ready(_)) = self.stall_timer.poll() {
+            return Some(QueryTimeout::Stalled);
+        }
+        None
+    }
+
     /// Polls this individual query.
     pub fn poll(&mut self) -> Async<QueryStatePollOut<'_>> {
+        // Give up if the query takes too long or has stopped making progress.
+        if let Some(reason) = self.poll_timeout() {
+            debug!(target: "stegos_network::kad", "query timed out: target={}, reason={}",
+                u8v_to_hexstr(self.target.as_hash().as_bytes()), reason);
+            return Async::Ready(QueryStatePollOut::TimedOut { reason });
+        }
+
         // While iterating over peers, count the number of queries currently being processed.
         // This is used to not go over the limit of parallel requests.
         // If this is still 0 at the end of the function, that means the query is finished.
@@ -391,7 +432,7 @@ impl QueryState {
     /// Consumes the query and returns the known closest peers.
     ///
     /// > **Note**: This can be called at any time, but you normally only do that once the query
-    /// >           is finished.
+    /// >           is finished or timed out.
     pub fn into_closest_peers(self) -> impl Iterator<Item = pbc::PublicKey> {
         self.closest_peers
             .into_iter()
@@ -441,10 +482,37 @@ pub enum QueryStatePollOut<'a> {
         /// The target.
         node_id: &'a pbc::PublicKey,
     },
+
+    /// The query has been abandoned.
+    ///
+    /// Results gathered so far can be obtained the same way as for `Finished`.
+    TimedOut {
+        /// Why the query was abandoned.
+        reason: QueryTimeout,
+    },
+}
+
+/// Why a query was abandoned before finishing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryTimeout {
+    /// The overall deadline has passed.
+    Deadline,
+    /// No RPC succeeded for too long.
+    Stalled,
+}
+
+impl fmt::Display for QueryTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryTimeout::Deadline => write!(f, "deadline"),
+            QueryTimeout::Stalled => write!(f, "stalled"),
+        }
+    }
 }
 
 /// What we're aiming for with our query.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum QueryTarget {
     /// Finding a peer.
     FindPeer(Multihash),
@@ -491,7 +559,7 @@ enum QueryPeerState {
 
 #[cfg(test)]
 mod tests {
-    use super::{QueryConfig, QueryState, QueryStatePollOut, QueryTarget};
+    use super::{QueryConfig, QueryState, QueryStatePollOut, QueryTarget, QueryTimeout};
     use futures::{self, prelude::*, try_ready};
     use libp2p::multihash::{Hash, Multihash};
     use std::{iter, sync::Arc, sync::Mutex, thread, time::Duration};
@@ -511,6 +579,8 @@ mod tests {
             parallelism: 3,
             num_results: 100,
             rpc_timeout: Duration::from_secs(10),
+            query_timeout: Duration::from_secs(60),
+            stall_timeout: Duration::from_secs(30),
         });
 
         tokio::run(futures::future::poll_fn(move || {
@@ -536,6 +606,8 @@ mod tests {
             parallelism: 3,
             num_results: 100,
             rpc_timeout: Duration::from_secs(10),
+            query_timeout: Duration::from_secs(60),
+            stall_timeout: Duration::from_secs(30),
         })));
 
         // Let's do a first polling round to obtain the `SendRpc` request.
@@ -580,6 +652,8 @@ mod tests {
             parallelism: 3,
             num_results: 100,
             rpc_timeout: Duration::from_millis(100),
+            query_timeout: Duration::from_secs(60),
+            stall_timeout: Duration::from_secs(30),
         })));
 
         // Let's do a first polling round to obtain the `SendRpc` request.
@@ -617,4 +691,81 @@ mod tests {
             }
         }));
     }
+
+    #[test]
+    fn stall_works() {
+        let (_, random_id) = pbc::make_random_keys();
+        let random_target = Multihash::random(Hash::SHA3512);
+        let target = QueryTarget::FindPeer(random_target);
+
+        let mut query = QueryState::new(QueryConfig {
+            target,
+            known_closest_peers: iter::once(random_id.clone()),
+            parallelism: 3,
+            num_results: 100,
+            rpc_timeout: Duration::from_secs(10),
+            query_timeout: Duration::from_secs(60),
+            stall_timeout: Duration::from_millis(100),
+        });
+
+        // The peer neither answers nor fails, the query gives up without waiting for the RPC.
+        let mut sent = false;
+        tokio::run(futures::future::poll_fn(move || loop {
+            match try_ready!(Ok(query.poll())) {
+                QueryStatePollOut::SendRpc { node_id, .. } if node_id == &random_id && !sent => {
+                    sent = true;
+                }
+                QueryStatePollOut::TimedOut { reason } => {
+                    assert!(sent);
+                    assert_eq!(reason, QueryTimeout::Stalled);
+                    return Ok(Async::Ready(()));
+                }
+                _ => panic!(),
+            }
+        }));
+    }
+
+    #[test]
+    fn deadline_works() {
+        let (_, random_id) = pbc::make_random_keys();
+        let (_, random_id2) = pbc::make_random_keys();
+        let random_target = Multihash::random(Hash::SHA3512);
+        let target = QueryTarget::FindPeer(random_target);
+
+        let query = Arc::new(Mutex::new(QueryState::new(QueryConfig {
+            target,
+            known_closest_peers: iter::once(random_id.clone()),
+            parallelism: 3,
+            num_results: 100,
+            rpc_timeout: Duration::from_secs(10),
+            query_timeout: Duration::from_millis(200),
+            stall_timeout: Duration::from_secs(30),
+        })));
+
+        // The first peer answers, the second one doesn't.
+        tokio::run(futures::future::poll_fn({
+            let query = query.clone();
+            let random_id = random_id.clone();
+            move || loop {
+                let mut query = query.lock().unwrap();
+                let reply = match try_ready!(Ok(query.poll())) {
+                    QueryStatePollOut::SendRpc { node_id, .. } if node_id == &random_id => true,
+                    QueryStatePollOut::SendRpc { node_id, .. } if node_id == &random_id2 => false,
+                    QueryStatePollOut::TimedOut { reason } => {
+                        assert_eq!(reason, QueryTimeout::Deadline);
+                        return Ok(Async::Ready(()));
+                    }
+                    _ => panic!(),
+                };
+                if reply {
+                    query.inject_rpc_result(&random_id, iter::once(random_id2.clone()));
+                }
+            }
+        }));
+
+        // Partial results are available.
+        let query = Arc::try_unwrap(query).unwrap().into_inner().unwrap();
+        let closest: Vec<pbc::PublicKey> = query.into_closest_peers().collect();
+        assert_eq!(closest, vec![random_id]);
+    }
 }
@@ -0,0 +1,298 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Per-peer rate limiting and fair scheduling of inbound Kademlia RPCs.
+
+use fnv::FnvHashMap;
+use libp2p::core::PeerId;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// The maximal number of requests a peer can send in a burst.
+pub const REMOTE_REQUESTS_BURST: u32 = 32;
+/// The number of requests a peer can send per second after the burst is exhausted.
+pub const REMOTE_REQUESTS_PER_SEC: u32 = 8;
+/// The maximal number of queued requests from a single peer.
+pub const MAX_REMOTE_REQUESTS_PER_PEER: usize = 16;
+/// The maximal number of queued requests from all peers.
+pub const MAX_REMOTE_REQUESTS: usize = 256;
+
+/// Why a request from a peer was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitViolation {
+    /// The peer sends requests faster than allowed.
+    TooFrequent,
+    /// The peer has too many requests waiting to be answered.
+    TooManyQueued,
+}
+
+/// Token bucket of a single peer.
+struct TokenBucket {
+    /// Available tokens, in thousandths of a request.
+    millitokens: u64,
+    /// When the bucket was refilled last time.
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant) -> Self {
+        TokenBucket {
+            millitokens: REMOTE_REQUESTS_BURST as u64 * 1000,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated);
+        let elapsed_ms = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+        let refill = elapsed_ms * REMOTE_REQUESTS_PER_SEC as u64;
+        self.millitokens = (self.millitokens + refill).min(REMOTE_REQUESTS_BURST as u64 * 1000);
+        self.updated = now;
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.millitokens < 1000 {
+            return false;
+        }
+        self.millitokens -= 1000;
+        true
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.millitokens == REMOTE_REQUESTS_BURST as u64 * 1000
+    }
+}
+
+/// Bounded queue of requests received from remote peers.
+///
+/// Each peer has its own queue and token bucket. Queues are served in round-robin,
+/// so a peer flooding us with requests can't delay answers to other peers.
+pub struct RemoteRequests<T> {
+    /// Pending requests by peer.
+    queues: FnvHashMap<PeerId, VecDeque<T>>,
+    /// Peers with pending requests, in order of service.
+    order: VecDeque<PeerId>,
+    /// Rate limits by peer.
+    buckets: FnvHashMap<PeerId, TokenBucket>,
+    /// Total number of pending requests.
+    len: usize,
+}
+
+impl<T> RemoteRequests<T> {
+    pub fn new() -> Self {
+        RemoteRequests {
+            queues: FnvHashMap::default(),
+            order: VecDeque::new(),
+            buckets: FnvHashMap::default(),
+            len: 0,
+        }
+    }
+
+    /// The number of pending requests.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Queues a request from the peer.
+    /// If the queue is full, the oldest request of the peer with the most pending requests is dropped.
+    pub fn push(
+        &mut self,
+        peer_id: PeerId,
+        request: T,
+        now: Instant,
+    ) -> Result<(), RateLimitViolation> {
+        let bucket = self
+            .buckets
+            .entry(peer_id.clone())
+            .or_insert_with(|| TokenBucket::new(now));
+        if !bucket.try_take(now) {
+            return Err(RateLimitViolation::TooFrequent);
+        }
+        let queued = self.queues.get(&peer_id).map(|q| q.len()).unwrap_or(0);
+        if queued >= MAX_REMOTE_REQUESTS_PER_PEER {
+            return Err(RateLimitViolation::TooManyQueued);
+        }
+        if self.len >= MAX_REMOTE_REQUESTS {
+            let (longest, longest_len) = self
+                .queues
+                .iter()
+                .map(|(peer_id, queue)| (peer_id.clone(), queue.len()))
+                .max_by_key(|(_, len)| *len)
+                .expect("queue is full");
+            if longest_len <= queued + 1 {
+                return Err(RateLimitViolation::TooManyQueued);
+            }
+            self.queues
+                .get_mut(&longest)
+                .expect("peer exists")
+                .pop_front();
+            self.len -= 1;
+        }
+        let queue = self
+            .queues
+            .entry(peer_id.clone())
+            .or_insert_with(VecDeque::new);
+        if queue.is_empty() {
+            self.order.push_back(peer_id);
+        }
+        queue.push_back(request);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Takes the next request to answer.
+    pub fn pop(&mut self) -> Option<(PeerId, T)> {
+        while let Some(peer_id) = self.order.pop_front() {
+            let request = match self.queues.get_mut(&peer_id) {
+                Some(queue) => queue.pop_front(),
+                None => None,
+            };
+            let request = match request {
+                Some(request) => request,
+                None => {
+                    self.queues.remove(&peer_id);
+                    continue;
+                }
+            };
+            self.len -= 1;
+            if self.queues[&peer_id].is_empty() {
+                self.queues.remove(&peer_id);
+            } else {
+                self.order.push_back(peer_id.clone());
+            }
+            return Some((peer_id, request));
+        }
+        None
+    }
+
+    /// Drops pending requests of the disconnected peer.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        if let Some(queue) = self.queues.remove(peer_id) {
+            self.len -= queue.len();
+            self.order.retain(|p| p != peer_id);
+        }
+    }
+
+    /// Forgets rate limits of peers which haven't sent requests for a while.
+    pub fn prune(&mut self, now: Instant) {
+        let queues = &self.queues;
+        self.buckets
+            .retain(|peer_id, bucket| queues.contains_key(peer_id) || !bucket.is_full(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Time needed to refill a single token.
+    fn refill_interval() -> Duration {
+        Duration::from_millis(1000 / REMOTE_REQUESTS_PER_SEC as u64)
+    }
+
+    #[test]
+    fn rate_limit() {
+        let peer = PeerId::random();
+        let mut requests: RemoteRequests<u32> = RemoteRequests::new();
+        let now = Instant::now();
+        for i in 0..REMOTE_REQUESTS_BURST {
+            if i as usize >= MAX_REMOTE_REQUESTS_PER_PEER {
+                assert_eq!(
+                    requests.push(peer.clone(), i, now),
+                    Err(RateLimitViolation::TooManyQueued)
+                );
+            } else {
+                requests.push(peer.clone(), i, now).unwrap();
+            }
+        }
+        assert_eq!(
+            requests.push(peer.clone(), 0, now),
+            Err(RateLimitViolation::TooFrequent)
+        );
+        assert_eq!(requests.len(), MAX_REMOTE_REQUESTS_PER_PEER);
+
+        // Drain the queue and wait for a token.
+        while let Some(_) = requests.pop() {}
+        assert_eq!(
+            requests.push(peer.clone(), 0, now),
+            Err(RateLimitViolation::TooFrequent)
+        );
+        let now = now + refill_interval();
+        requests.push(peer.clone(), 0, now).unwrap();
+    }
+
+    #[test]
+    fn round_robin() {
+        let peer1 = PeerId::random();
+        let peer2 = PeerId::random();
+        let mut requests: RemoteRequests<u32> = RemoteRequests::new();
+        let now = Instant::now();
+        for i in 0..3 {
+            requests.push(peer1.clone(), i, now).unwrap();
+        }
+        requests.push(peer2.clone(), 10, now).unwrap();
+        assert_eq!(requests.pop(), Some((peer1.clone(), 0)));
+        assert_eq!(requests.pop(), Some((peer2.clone(), 10)));
+        assert_eq!(requests.pop(), Some((peer1.clone(), 1)));
+        assert_eq!(requests.pop(), Some((peer1.clone(), 2)));
+        assert_eq!(requests.pop(), None);
+        assert!(requests.is_empty());
+
+        requests.push(peer1.clone(), 0, now).unwrap();
+        requests.push(peer2.clone(), 1, now).unwrap();
+        requests.remove_peer(&peer1);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests.pop(), Some((peer2.clone(), 1)));
+    }
+
+    #[test]
+    fn bounded() {
+        let mut requests: RemoteRequests<u32> = RemoteRequests::new();
+        let now = Instant::now();
+        let flooders: Vec<PeerId> = (0..MAX_REMOTE_REQUESTS / MAX_REMOTE_REQUESTS_PER_PEER)
+            .map(|_| PeerId::random())
+            .collect();
+        for peer in &flooders {
+            for i in 0..MAX_REMOTE_REQUESTS_PER_PEER {
+                requests.push(peer.clone(), i as u32, now).unwrap();
+            }
+        }
+        assert_eq!(requests.len(), MAX_REMOTE_REQUESTS);
+
+        // A new peer still gets its request queued.
+        let honest = PeerId::random();
+        requests.push(honest.clone(), 100, now).unwrap();
+        assert_eq!(requests.len(), MAX_REMOTE_REQUESTS);
+        let answered: Vec<(PeerId, u32)> = (0..flooders.len() + 1)
+            .filter_map(|_| requests.pop())
+            .collect();
+        assert!(answered.contains(&(honest, 100)));
+    }
+}
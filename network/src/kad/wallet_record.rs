@@ -0,0 +1,124 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Binding of wallet keys to network nodes, published via the provider mechanism.
+
+use crate::utils::IntoMultihash;
+use libp2p::multihash::Multihash;
+use std::fmt;
+use stegos_crypto::curve1174;
+use stegos_crypto::hash::{Hash, Hasher};
+use stegos_crypto::pbc;
+
+/// Statement that a wallet key is controlled by a network node, signed by the wallet key.
+#[derive(Clone)]
+pub struct WalletRecord {
+    /// Wallet key.
+    pub wallet_pkey: curve1174::PublicKey,
+    /// Network node controlling the wallet.
+    pub node_id: pbc::PublicKey,
+    /// Signature of wallet_pkey and node_id made by the wallet key.
+    pub signature: curve1174::SchnorrSig,
+}
+
+impl WalletRecord {
+    /// Creates and signs a new record.
+    pub fn new(
+        wallet_skey: &curve1174::SecretKey,
+        wallet_pkey: curve1174::PublicKey,
+        node_id: pbc::PublicKey,
+    ) -> WalletRecord {
+        let hash = Self::hash(&wallet_pkey, &node_id);
+        let signature = curve1174::sign_hash(&hash, wallet_skey);
+        WalletRecord {
+            wallet_pkey,
+            node_id,
+            signature,
+        }
+    }
+
+    fn hash(wallet_pkey: &curve1174::PublicKey, node_id: &pbc::PublicKey) -> Hash {
+        let mut hasher = Hasher::new();
+        hasher.input(b"WalletRecord");
+        hasher.input(&wallet_pkey.to_bytes());
+        hasher.input(&node_id.to_bytes()[..]);
+        hasher.result()
+    }
+
+    /// DHT key under which the node provides this wallet.
+    pub fn key(&self) -> Multihash {
+        self.wallet_pkey.into_multihash()
+    }
+
+    /// Checks the signature of the record.
+    pub fn is_valid(&self) -> bool {
+        let hash = Self::hash(&self.wallet_pkey, &self.node_id);
+        curve1174::validate_sig(&hash, &self.signature, &self.wallet_pkey).is_ok()
+    }
+}
+
+impl fmt::Debug for WalletRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WalletRecord(wallet_pkey={}, node_id={})",
+            self.wallet_pkey, self.node_id
+        )
+    }
+}
+
+impl PartialEq for WalletRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.wallet_pkey == other.wallet_pkey
+            && self.node_id == other.node_id
+            && Hash::digest(&self.signature) == Hash::digest(&other.signature)
+    }
+}
+
+impl Eq for WalletRecord {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature() {
+        let (wallet_skey, wallet_pkey) = curve1174::make_random_keys();
+        let (_network_skey, network_pkey) = pbc::make_random_keys();
+        let record = WalletRecord::new(&wallet_skey, wallet_pkey, network_pkey);
+        assert!(record.is_valid());
+        assert_eq!(record.key(), wallet_pkey.into_multihash());
+
+        // Someone else's wallet key.
+        let (_other_skey, other_pkey) = curve1174::make_random_keys();
+        let mut forged = record.clone();
+        forged.wallet_pkey = other_pkey;
+        assert!(!forged.is_valid());
+
+        // Someone else's node.
+        let (_other_skey, other_pkey) = pbc::make_random_keys();
+        let mut forged = record.clone();
+        forged.node_id = other_pkey;
+        assert!(!forged.is_valid());
+    }
+}
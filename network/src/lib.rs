@@ -22,29 +22,35 @@
 // SOFTWARE.
 #![allow(dead_code)]
 
+mod access;
 mod config;
 mod delivery;
+mod direct;
 mod discovery;
 mod gatekeeper;
 mod kad;
 mod libp2p_network;
 pub mod loopback;
+mod metrics;
+mod nat;
 mod ncp;
 mod pubsub;
 mod utils;
 
 use failure::{Error, Fail};
-use futures::sync::mpsc;
+use futures::sync::{mpsc, oneshot};
 use std::fmt;
+use std::sync::Arc;
 use stegos_crypto::pbc;
 
 pub use self::config::*;
-pub use self::kad::KBucketsPeerId;
+pub use self::kad::{BucketEntry, BucketInfo, QueryRecord, RpcRecord};
+pub use self::kad::{Distance, StakeChecker, XorDigest};
 pub use self::libp2p_network::Libp2pNetwork;
 pub use self::libp2p_network::NETWORK_IDLE_TIMEOUT;
 pub use self::libp2p_network::NETWORK_READY_TOKEN;
 pub use self::libp2p_network::NETWORK_STATUS_TOPIC;
-pub use self::utils::IntoMultihash;
+pub use self::utils::{IntoMultihash, Lane, ProtocolVersion};
 
 pub type Network = Box<dyn NetworkProvider + Send>;
 
@@ -55,9 +61,16 @@ where
     /// Subscribe to topic, returns Stream<Vec<u8>> of messages incoming to topic
     fn subscribe(&self, topic: &str) -> Result<mpsc::UnboundedReceiver<Vec<u8>>, Error>;
 
+    /// Unsubscribe from topic, drops all streams returned by subscribe() for this topic
+    fn unsubscribe(&self, topic: &str) -> Result<(), Error>;
+
     /// Published message to topic
     fn publish(&self, topic: &str, data: Vec<u8>) -> Result<(), Error>;
 
+    /// Published message to topic, even if we are not subscribed to it.
+    /// Used for shards of a topic, where each node follows only a subset of shards.
+    fn publish_unsubscribed(&self, topic: &str, data: Vec<u8>) -> Result<(), Error>;
+
     /// Subscribe to unicast messages, returns Stream<Vec<u8>> of messages incoming to topic
     fn subscribe_unicast(
         &self,
@@ -67,9 +80,40 @@ where
     /// Send unicast message to peer identified by network public key
     fn send(&self, dest: pbc::PublicKey, protocol_id: &str, data: Vec<u8>) -> Result<(), Error>;
 
+    /// Send unicast message straight to peer identified by network public key, without relays.
+    /// Returns a future which is resolved when the message is delivered or delivery fails.
+    fn send_direct(
+        &self,
+        dest: pbc::PublicKey,
+        protocol_id: &str,
+        data: Vec<u8>,
+    ) -> Result<oneshot::Receiver<DirectDelivery>, Error>;
+
+    /// Returns a snapshot of the Kademlia routing table.
+    fn dump_buckets(&self) -> Result<oneshot::Receiver<Vec<BucketInfo>>, Error>;
+
+    /// Returns recent Kademlia queries, see `NetworkConfig::query_journal_size`.
+    fn query_journal(&self) -> Result<oneshot::Receiver<Vec<QueryRecord>>, Error>;
+
+    /// Stop accepting new work and save the routing table.
+    /// Returns a future which is resolved when the network is stopped.
+    fn shutdown(&self) -> Result<oneshot::Receiver<()>, Error>;
+
     /// Helper for cloning boxed object
     fn box_clone(&self) -> Network;
 
+    /// Sets the source of stakes used to prefer connections to validators.
+    /// Also keeps unstaked nodes out of the routing table if `require_stake` is enabled.
+    fn set_stake_checker(&self, stake_checker: Arc<dyn StakeChecker>) -> Result<(), Error>;
+
+    /// Assigns outbound messages of the topic or unicast protocol to the priority lane.
+    /// Unassigned traffic goes to `Lane::Transactions`.
+    fn set_lane(&self, topic_or_protocol: &str, lane: Lane) -> Result<(), Error>;
+
+    /// Connects to the nodes in advance, looking them up in the DHT if needed.
+    /// Used to dial co-validators of a new epoch before the first round starts.
+    fn prewarm_connections(&self, node_ids: Vec<pbc::PublicKey>) -> Result<(), Error>;
+
     /// Change network keys
     fn change_network_keys(
         &self,
@@ -84,6 +128,15 @@ pub struct UnicastMessage {
     pub data: Vec<u8>,
 }
 
+/// Outcome of `NetworkProvider::send_direct()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectDelivery {
+    /// The message has been handed over to the recipient.
+    Delivered,
+    /// The recipient is unreachable.
+    Failed,
+}
+
 impl Clone for Network {
     fn clone(&self) -> Network {
         self.box_clone()
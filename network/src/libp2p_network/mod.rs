@@ -23,7 +23,7 @@
 
 use failure::{format_err, Error};
 use futures::prelude::*;
-use futures::sync::mpsc;
+use futures::sync::{mpsc, oneshot};
 use ipnetwork::IpNetwork;
 use libp2p::{
     core::swarm::NetworkBehaviourEventProcess,
@@ -37,20 +37,29 @@ use pnet::datalink;
 use protobuf::Message as ProtoMessage;
 use smallvec::SmallVec;
 use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use stegos_crypto::hash::{Hashable, Hasher};
 use stegos_crypto::pbc;
 use stegos_crypto::utils::u8v_to_hexstr;
 use stegos_keychain::KeyChain;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Interval;
 
+use crate::access::{AccessList, AccessListLoader};
 use crate::config::NetworkConfig;
 use crate::delivery::{Delivery, DeliveryEvent, DeliveryMessage};
-use crate::discovery::{Discovery, DiscoveryOutEvent};
+use crate::direct::{Direct, DirectOutEvent, MessageId};
+use crate::discovery::{Discovery, DiscoveryOutEvent, PeerStore};
 use crate::gatekeeper::{Gatekeeper, GatekeeperOutEvent, PeerEvent};
-use crate::ncp::{Ncp, NcpOutEvent};
+use crate::kad::{KademliaOut, QueryJournal, StakeChecker};
+use crate::nat::{self, PortMapping};
+use crate::ncp::{Ncp, NcpOutEvent, MAX_PEX_ENTRIES};
 use crate::pubsub::{Floodsub, FloodsubEvent, TopicBuilder, TopicHash};
-use crate::{Network, NetworkProvider, UnicastMessage};
+use crate::utils::{Lane, LaneQueue};
+use crate::{BucketInfo, DirectDelivery, Network, NetworkProvider, QueryRecord, UnicastMessage};
 
 mod proto;
 use self::proto::unicast_proto;
@@ -67,6 +76,8 @@ pub const NETWORK_STATUS_TOPIC: &'static str = "stegos-network-status";
 pub const NETWORK_READY_TOKEN: &'static [u8] = &[1, 0, 0, 0];
 
 const UNICAST_TOPIC: &'static str = "stegos-unicast";
+// How often to check the access list file for changes
+const ACCESS_LIST_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
 const IBE_ID: &'static [u8] = &[105u8, 13, 185, 148, 68, 76, 69, 155];
 
 impl Libp2pNetwork {
@@ -90,6 +101,14 @@ impl NetworkProvider for Libp2pNetwork {
         Ok(rx)
     }
 
+    /// Unsubscribe from topic
+    fn unsubscribe(&self, topic: &str) -> Result<(), Error> {
+        let topic: String = topic.clone().into();
+        let msg = ControlMessage::Unsubscribe { topic };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(())
+    }
+
     /// Published message to topic
     fn publish(&self, topic: &str, data: Vec<u8>) -> Result<(), Error> {
         let topic: String = topic.clone().into();
@@ -101,6 +120,16 @@ impl NetworkProvider for Libp2pNetwork {
         Ok(())
     }
 
+    /// Published message to topic, even if we are not subscribed to it
+    fn publish_unsubscribed(&self, topic: &str, data: Vec<u8>) -> Result<(), Error> {
+        let msg = ControlMessage::PublishUnsubscribed {
+            topic: topic.to_string(),
+            data,
+        };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(())
+    }
+
     // Subscribe to unicast messages
     fn subscribe_unicast(
         &self,
@@ -128,6 +157,49 @@ impl NetworkProvider for Libp2pNetwork {
         Ok(())
     }
 
+    // Send direct message to public key, bypassing relays
+    fn send_direct(
+        &self,
+        to: pbc::PublicKey,
+        protocol_id: &str,
+        data: Vec<u8>,
+    ) -> Result<oneshot::Receiver<DirectDelivery>, Error> {
+        let protocol_id: String = protocol_id.clone().into();
+        let (tx, rx) = oneshot::channel();
+        let msg = ControlMessage::SendDirect {
+            to,
+            protocol_id,
+            data,
+            result: tx,
+        };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    // Dump Kademlia routing table
+    fn dump_buckets(&self) -> Result<oneshot::Receiver<Vec<BucketInfo>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = ControlMessage::DumpBuckets { result: tx };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    // Dump recent Kademlia queries
+    fn query_journal(&self) -> Result<oneshot::Receiver<Vec<QueryRecord>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = ControlMessage::QueryJournal { result: tx };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    // Stop the network
+    fn shutdown(&self) -> Result<oneshot::Receiver<()>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = ControlMessage::Shutdown { result: tx };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
     // Clone self as a box
     fn box_clone(&self) -> Network {
         Box::new((*self).clone())
@@ -143,6 +215,25 @@ impl NetworkProvider for Libp2pNetwork {
         self.control_tx.unbounded_send(msg)?;
         Ok(())
     }
+
+    fn set_stake_checker(&self, stake_checker: Arc<dyn StakeChecker>) -> Result<(), Error> {
+        let msg = ControlMessage::SetStakeChecker { stake_checker };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(())
+    }
+
+    fn prewarm_connections(&self, node_ids: Vec<pbc::PublicKey>) -> Result<(), Error> {
+        let msg = ControlMessage::PrewarmConnections { node_ids };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(())
+    }
+
+    fn set_lane(&self, topic_or_protocol: &str, lane: Lane) -> Result<(), Error> {
+        let name = topic_or_protocol.to_string();
+        let msg = ControlMessage::SetLane { name, lane };
+        self.control_tx.unbounded_send(msg)?;
+        Ok(())
+    }
 }
 
 fn new_service(
@@ -163,16 +254,31 @@ fn new_service(
             .expect("converting from raw key should never fail"),
     ));
 
+    let port_mapping: PortMapping = config.port_mapping.parse()?;
+
     let local_pub_key = local_key.public();
     let peer_id = local_pub_key.clone().into_peer_id();
 
+    let mut access_loader = AccessListLoader::new(config);
+    let access = Arc::new(access_loader.load()?);
+
     // Set up a an encrypted DNS-enabled TCP Transport over the Mplex and Yamux protocols
     let transport = libp2p::build_development_transport(local_key);
 
     // Create a Swarm to manage peers and events
     let mut swarm = {
-        let behaviour =
-            Libp2pBehaviour::new(config, keychain, local_pub_key.clone().into_peer_id());
+        let peer_store = if config.peer_store_file.is_empty() {
+            PeerStore::new()
+        } else {
+            PeerStore::load(&config.peer_store_file)?
+        };
+        let behaviour = Libp2pBehaviour::new(
+            config,
+            keychain,
+            local_pub_key.clone().into_peer_id(),
+            peer_store,
+            access,
+        );
 
         libp2p::Swarm::new(transport, behaviour, peer_id)
     };
@@ -189,23 +295,86 @@ fn new_service(
     info!("Listening on {:?}", addr);
 
     let (control_tx, mut control_rx) = mpsc::unbounded::<ControlMessage>();
+    let mut lanes: HashMap<String, Lane> = HashMap::new();
+    let mut outbound =
+        LaneQueue::<ControlMessage>::new(config.lane_shares(), config.outbound_round_size);
+    let outbound_round_size = config.outbound_round_size;
+
+    // Advertise the external address assigned by the gateway.
+    let mapping_tx = control_tx.clone();
+    nat::start(port_mapping, config.bind_port, move |address| {
+        let msg = ControlMessage::AddExternalAddress { address };
+        mapping_tx.unbounded_send(msg).ok(); // ignore errors.
+    });
+
+    let mut access_reload = Interval::new_interval(ACCESS_LIST_RELOAD_INTERVAL);
+
     let service = futures::future::poll_fn(move || -> Result<_, ()> {
         trace!("Swarm poll fn");
+        loop {
+            match access_reload.poll() {
+                Ok(Async::Ready(Some(_))) => match access_loader.reload() {
+                    Ok(Some(access)) => swarm.set_access_list(Arc::new(access)),
+                    Ok(None) => {}
+                    Err(e) => error!("Failed to reload access list: {}", e),
+                },
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(e) => {
+                    error!("Access list timer error: {}", e);
+                    break;
+                }
+            }
+        }
+
         loop {
             match control_rx.poll() {
-                Ok(Async::Ready(Some(msg))) => swarm.process_event(msg),
+                Ok(Async::Ready(Some(ControlMessage::Shutdown { result }))) => {
+                    info!("Shutting down network");
+                    swarm.discovery.flush();
+                    result.send(()).ok(); // ignore errors.
+                    return Ok(Async::Ready(()));
+                }
+                Ok(Async::Ready(Some(ControlMessage::AddExternalAddress { address }))) => {
+                    Swarm::add_external_address(&mut swarm, address);
+                }
+                Ok(Async::Ready(Some(ControlMessage::SetLane { name, lane }))) => {
+                    debug!("Outbound lane assigned: name={}, lane={}", name, lane);
+                    lanes.insert(name, lane);
+                }
+                Ok(Async::Ready(Some(msg))) => match outbound_lane(&lanes, &msg) {
+                    Some((lane, size)) => outbound.push(lane, size, msg),
+                    None => swarm.process_event(msg),
+                },
                 Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
                 Ok(Async::NotReady) => break,
                 Err(_e) => error!("Error in control channel"),
             }
         }
 
+        // Hand over one round of outbound messages, in the order of lanes.
+        let mut round_size = 0;
+        while round_size < outbound_round_size {
+            match outbound.pop() {
+                Some((_lane, size, msg)) => {
+                    swarm.process_event(msg);
+                    round_size += size;
+                }
+                None => break,
+            }
+        }
+        // Don't add DHT maintenance traffic on top of a backlog.
+        swarm.discovery.set_congested(!outbound.is_empty());
+
         loop {
             match swarm.poll().expect("Error while polling swarm") {
                 Async::Ready(Some(_)) => {}
                 Async::Ready(None) | Async::NotReady => break,
             }
         }
+        if !outbound.is_empty() {
+            // Let the swarm flush the queued data, then continue with the next round.
+            futures::task::current().notify();
+        }
         trace!("Finished Swarm poll!");
         Ok(Async::NotReady)
     });
@@ -219,6 +388,7 @@ pub struct Libp2pBehaviour<TSubstream: AsyncRead + AsyncWrite> {
     ncp: Ncp<TSubstream>,
     gatekeeper: Gatekeeper<TSubstream>,
     delivery: Delivery<TSubstream>,
+    direct: Direct<TSubstream>,
     discovery: Discovery<TSubstream>,
     #[behaviour(ignore)]
     consumers: HashMap<TopicHash, SmallVec<[mpsc::UnboundedSender<Vec<u8>>; 3]>>,
@@ -232,26 +402,57 @@ pub struct Libp2pBehaviour<TSubstream: AsyncRead + AsyncWrite> {
     topics_map: HashMap<TopicHash, String>,
     #[behaviour(ignore)]
     connected_peers: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    direct_results: HashMap<MessageId, oneshot::Sender<DirectDelivery>>,
+    #[behaviour(ignore)]
+    require_stake: bool,
+    #[behaviour(ignore)]
+    access: Arc<AccessList>,
 }
 
 impl<TSubstream> Libp2pBehaviour<TSubstream>
 where
     TSubstream: AsyncRead + AsyncWrite,
 {
-    pub fn new(config: &NetworkConfig, keychain: &KeyChain, peer_id: PeerId) -> Self {
+    pub fn new(
+        config: &NetworkConfig,
+        keychain: &KeyChain,
+        peer_id: PeerId,
+        peer_store: PeerStore,
+        access: Arc<AccessList>,
+    ) -> Self {
         let mut behaviour = Libp2pBehaviour {
-            floodsub: Floodsub::new(peer_id.clone()),
+            floodsub: Floodsub::new(peer_id.clone(), config.compression),
             ncp: Ncp::new(config, keychain),
-            gatekeeper: Gatekeeper::new(config),
-            delivery: Delivery::new(),
-            discovery: Discovery::new(keychain.network_pkey.clone()),
+            gatekeeper: Gatekeeper::new(config, access.clone()),
+            delivery: Delivery::new(config.compression),
+            direct: Direct::new(),
+            discovery: Discovery::new(
+                keychain.network_pkey.clone(),
+                keychain.network_skey.clone(),
+                config.compression,
+                Duration::from_secs(config.bootstrap_window),
+                peer_store,
+            ),
             consumers: HashMap::new(),
             unicast_consumers: HashMap::new(),
             my_pkey: keychain.network_pkey.clone(),
             my_skey: keychain.network_skey.clone(),
             topics_map: HashMap::new(),
             connected_peers: HashSet::new(),
+            direct_results: HashMap::new(),
+            require_stake: config.require_stake,
+            access: access.clone(),
+        };
+        behaviour.discovery.set_access_checker(access);
+        let journal_path = if config.query_journal_file.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(&config.query_journal_file))
         };
+        behaviour
+            .discovery
+            .set_query_journal(QueryJournal::new(config.query_journal_size, journal_path));
         let unicast_topic = TopicBuilder::new(UNICAST_TOPIC).build();
         behaviour.floodsub.subscribe(unicast_topic);
         info!(target: "stegos_network::delivery", "Network endpoints: node_id={}, peer_id={}", keychain.network_pkey, peer_id);
@@ -284,6 +485,13 @@ where
                     .or_insert(SmallVec::new())
                     .push(handler);
             }
+            ControlMessage::Unsubscribe { topic } => {
+                let floodsub_topic = TopicBuilder::new(topic.clone()).build();
+                let topic_hash = floodsub_topic.hash();
+                self.topics_map.remove(&topic_hash);
+                self.consumers.remove(&topic_hash);
+                self.floodsub.unsubscribe(floodsub_topic);
+            }
             ControlMessage::Publish { topic, data } => {
                 debug!(target: "stegos_network::pubsub",
                     "Sending broadcast message: topic={}, size={}",
@@ -293,13 +501,34 @@ where
                 let floodsub_topic = TopicBuilder::new(topic).build();
                 self.floodsub.publish(floodsub_topic, data)
             }
+            ControlMessage::PublishUnsubscribed { topic, data } => {
+                debug!(target: "stegos_network::pubsub",
+                    "Sending broadcast message: topic={}, size={}",
+                    topic,
+                    data.len(),
+                );
+                let floodsub_topic = TopicBuilder::new(topic).build();
+                self.floodsub.publish_unsubscribed(floodsub_topic, data)
+            }
             ControlMessage::ChangeNetworkKeys { new_pkey, new_skey } => {
                 debug!(target: "stegos_network::libp2p_network","changing network key: from={}, to={}", self.my_pkey, new_pkey);
-                self.ncp.change_network_key(new_pkey.clone());
-                self.discovery.change_network_key(new_pkey.clone());
+                self.ncp
+                    .change_network_key(new_pkey.clone(), new_skey.clone());
+                self.discovery
+                    .change_network_key(new_pkey.clone(), new_skey.clone());
                 self.my_pkey = new_pkey;
                 self.my_skey = new_skey;
             }
+            ControlMessage::SetStakeChecker { stake_checker } => {
+                self.ncp.set_stake_checker(stake_checker.clone());
+                if self.require_stake {
+                    debug!(target: "stegos_network::libp2p_network", "routing table is restricted to staked nodes");
+                    self.discovery.set_stake_checker(stake_checker);
+                }
+            }
+            ControlMessage::PrewarmConnections { node_ids } => {
+                self.discovery.prewarm(node_ids);
+            }
             ControlMessage::SubscribeUnicast {
                 protocol_id,
                 consumer,
@@ -353,12 +582,73 @@ where
                     self.discovery.deliver_unicast(&to, msg);
                 }
             }
+            ControlMessage::SendDirect {
+                to,
+                protocol_id,
+                data,
+                result,
+            } => {
+                debug!(target: "stegos_network::direct",
+                    "Sending direct message: to={}, protocol={}, size={}",
+                    to,
+                    protocol_id,
+                    data.len(),
+                );
+                let payload = UnicastPayload {
+                    from: self.my_pkey.clone(),
+                    to: to.clone(),
+                    protocol_id,
+                    data,
+                };
+                let msg = encode_unicast(payload, &self.my_skey);
+                let peer_id = self.discovery.lookup(&to);
+                let id = self.direct.send(to, peer_id, msg);
+                self.direct_results.insert(id, result);
+            }
+            ControlMessage::DumpBuckets { result } => {
+                let mut buckets = self.discovery.dump_buckets();
+                for entry in buckets.iter_mut().flat_map(|b| b.entries.iter_mut()) {
+                    entry.pubsub_version = entry
+                        .peer_id
+                        .as_ref()
+                        .and_then(|p| p.parse::<PeerId>().ok())
+                        .and_then(|p| self.floodsub.protocol_version(&p));
+                }
+                result.send(buckets).ok(); // ignore errors.
+            }
+            ControlMessage::QueryJournal { result } => {
+                result.send(self.discovery.query_journal()).ok(); // ignore errors.
+            }
+            ControlMessage::Shutdown { .. } => unreachable!("handled by the service"),
+            ControlMessage::AddExternalAddress { .. } => {
+                unreachable!("handled by the service")
+            }
+            ControlMessage::SetLane { .. } => unreachable!("handled by the service"),
         }
     }
 
     fn shutdown(&mut self, peer_id: &PeerId) {
         self.ncp.terminate(peer_id.clone());
     }
+
+    /// Applies the new access list, disconnecting peers which are not allowed anymore.
+    fn set_access_list(&mut self, access: Arc<AccessList>) {
+        let mut denied = self.gatekeeper.set_access_list(access.clone());
+        let connected_peers: Vec<PeerId> = self.connected_peers.iter().cloned().collect();
+        for peer_id in connected_peers {
+            if let Some(node_id) = self.discovery.node_of_peer(&peer_id) {
+                if !denied.contains(&peer_id) && !access.check_node(&node_id) {
+                    denied.push(peer_id);
+                }
+            }
+        }
+        self.discovery.set_access_checker(access.clone());
+        self.access = access;
+        for peer_id in denied {
+            debug!(target: "stegos_network::libp2p_network", "disconnecting peer refused by the access list: peer_id={}", peer_id);
+            self.shutdown(&peer_id);
+        }
+    }
 }
 
 impl<TSubstream> NetworkBehaviourEventProcess<NcpOutEvent> for Libp2pBehaviour<TSubstream>
@@ -384,6 +674,13 @@ where
                 peer_id,
                 addresses,
             } => {
+                if !self.access.check_node(&node_id) {
+                    debug!(target: "stegos_network::discovery", "node is refused by the access list: node_id={}, peer_id={}", node_id, peer_id);
+                    if self.connected_peers.contains(&peer_id) {
+                        self.shutdown(&peer_id);
+                    }
+                    return;
+                }
                 debug!(target: "stegos_network::discovery", "discovered node: node_id={}, peer_id={}", node_id, peer_id);
                 self.discovery.add_node(node_id.clone(), peer_id.clone());
                 if addresses.len() > 0 {
@@ -400,6 +697,16 @@ where
                     }
                 }
             }
+            NcpOutEvent::PexRequired { peer_ids } => {
+                let entries = self.discovery.pex_sample(MAX_PEX_ENTRIES);
+                self.ncp.send_pex(peer_ids, entries);
+            }
+            NcpOutEvent::PexReceived { peer_id, entries } => {
+                self.discovery.add_pex_entries(&peer_id, entries);
+            }
+            NcpOutEvent::InvalidPex { peer_id } => {
+                self.discovery.on_misbehavior(&peer_id);
+            }
         }
     }
 }
@@ -541,6 +848,9 @@ where
             GatekeeperOutEvent::Message { .. } => {}
             GatekeeperOutEvent::Connected { .. } => {}
             GatekeeperOutEvent::Disconnected { .. } => {}
+            GatekeeperOutEvent::Rejected { peer_id } => {
+                self.shutdown(&peer_id);
+            }
         }
     }
 }
@@ -559,11 +869,90 @@ where
                 debug!(target: "stegos_network::delivery", "delivering paylod: node_id={}, peer_id={}", message.to, next_hop);
                 self.delivery.deliver_unicast(&next_hop, message);
             } // _ => {}
+            DiscoveryOutEvent::KadEvent {
+                event:
+                    KademliaOut::Discovered {
+                        node_id,
+                        peer_id: Some(peer_id),
+                        ..
+                    },
+            } => {
+                self.direct.resolved(node_id, peer_id);
+            }
             DiscoveryOutEvent::KadEvent { .. } => {}
         }
     }
 }
 
+impl<TSubstream> NetworkBehaviourEventProcess<DirectOutEvent> for Libp2pBehaviour<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    fn inject_event(&mut self, event: DirectOutEvent) {
+        match event {
+            DirectOutEvent::Lookup { node_id } => {
+                debug!(target: "stegos_network::direct", "looking up node in DHT: node_id={}", node_id);
+                self.discovery.find_node(&node_id);
+            }
+            DirectOutEvent::Delivered { id, to } => {
+                debug!(target: "stegos_network::direct", "message delivered: to={}, id={}", to, id);
+                if let Some(result) = self.direct_results.remove(&id) {
+                    result.send(DirectDelivery::Delivered).ok(); // ignore errors.
+                }
+            }
+            DirectOutEvent::Failed { id, to } => {
+                debug!(target: "stegos_network::direct", "message delivery failed: to={}, id={}", to, id);
+                if let Some(result) = self.direct_results.remove(&id) {
+                    result.send(DirectDelivery::Failed).ok(); // ignore errors.
+                }
+            }
+            DirectOutEvent::Message { payload } => {
+                let (payload, signature, rval) = match decode_unicast(payload) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        error!(target: "stegos_network::direct", "Failure decoding direct message: {}", e);
+                        return;
+                    }
+                };
+                if payload.to != self.my_pkey {
+                    debug!(target: "stegos_network::direct", "direct message for other node: to={}", payload.to);
+                    return;
+                }
+                let payload = match decrypt_message(&self.my_skey, payload, signature, rval) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        debug!(target: "stegos_network::direct", "bad direct message received: {}", e);
+                        return;
+                    }
+                };
+                debug!(target: "stegos_network::direct",
+                    "Received direct message: from={}, protocol={} size={}",
+                    payload.from,
+                    payload.protocol_id,
+                    payload.data.len()
+                );
+                let msg = UnicastMessage {
+                    from: payload.from,
+                    data: payload.data,
+                };
+                self.unicast_consumers
+                    .entry(payload.protocol_id)
+                    .or_insert(SmallVec::new())
+                    .retain({
+                        move |c| {
+                            if let Err(e) = c.unbounded_send(msg.clone()) {
+                                error!(target: "stegos_network::direct", "Error sending data to consumer: {}", e);
+                                false
+                            } else {
+                                true
+                            }
+                        }
+                    })
+            }
+        }
+    }
+}
+
 impl<TSubstream> NetworkBehaviourEventProcess<DeliveryEvent> for Libp2pBehaviour<TSubstream>
 where
     TSubstream: AsyncRead + AsyncWrite,
@@ -638,16 +1027,23 @@ where
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum ControlMessage {
     Subscribe {
         topic: String,
         handler: mpsc::UnboundedSender<Vec<u8>>,
     },
+    Unsubscribe {
+        topic: String,
+    },
     Publish {
         topic: String,
         data: Vec<u8>,
     },
+    PublishUnsubscribed {
+        topic: String,
+        data: Vec<u8>,
+    },
     SendUnicast {
         to: pbc::PublicKey,
         protocol_id: String,
@@ -657,10 +1053,57 @@ pub enum ControlMessage {
         protocol_id: String,
         consumer: mpsc::UnboundedSender<UnicastMessage>,
     },
+    SendDirect {
+        to: pbc::PublicKey,
+        protocol_id: String,
+        data: Vec<u8>,
+        result: oneshot::Sender<DirectDelivery>,
+    },
     ChangeNetworkKeys {
         new_pkey: pbc::PublicKey,
         new_skey: pbc::SecretKey,
     },
+    SetStakeChecker {
+        stake_checker: Arc<dyn StakeChecker>,
+    },
+    PrewarmConnections {
+        node_ids: Vec<pbc::PublicKey>,
+    },
+    DumpBuckets {
+        result: oneshot::Sender<Vec<BucketInfo>>,
+    },
+    QueryJournal {
+        result: oneshot::Sender<Vec<QueryRecord>>,
+    },
+    Shutdown {
+        result: oneshot::Sender<()>,
+    },
+    AddExternalAddress {
+        address: Multiaddr,
+    },
+    SetLane {
+        name: String,
+        lane: Lane,
+    },
+}
+
+/// Returns the priority lane and the size of outbound messages, None for the rest of control messages.
+fn outbound_lane(lanes: &HashMap<String, Lane>, msg: &ControlMessage) -> Option<(Lane, usize)> {
+    let lane_of = |name: &str| lanes.get(name).cloned().unwrap_or(Lane::Transactions);
+    match msg {
+        ControlMessage::Publish { topic, data } => Some((lane_of(topic), data.len())),
+        ControlMessage::SendUnicast {
+            protocol_id, data, ..
+        } => Some((lane_of(protocol_id), data.len())),
+        ControlMessage::SendDirect {
+            protocol_id, data, ..
+        } => Some((lane_of(protocol_id), data.len())),
+        ControlMessage::PrewarmConnections { node_ids } => Some((
+            Lane::Maintenance,
+            node_ids.len() * mem::size_of::<pbc::PublicKey>(),
+        )),
+        _ => None,
+    }
 }
 
 fn my_external_address(config: &NetworkConfig) -> Vec<Multiaddr> {
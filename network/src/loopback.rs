@@ -21,9 +21,12 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 #![allow(dead_code)]
-use crate::{Network, NetworkProvider, UnicastMessage};
+use crate::{
+    BucketInfo, DirectDelivery, Lane, Network, NetworkProvider, QueryRecord, StakeChecker,
+    UnicastMessage,
+};
 use failure::Error;
-use futures::sync::mpsc;
+use futures::sync::{mpsc, oneshot};
 use log::*;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
@@ -53,6 +56,11 @@ impl NetworkProvider for LoopbackNetwork {
         Ok(rx)
     }
 
+    fn unsubscribe(&self, stopic: &str) -> Result<(), Error> {
+        self.state.lock().unwrap().consumers.remove(stopic);
+        Ok(())
+    }
+
     fn subscribe_unicast(
         &self,
         stopic: &str,
@@ -81,6 +89,30 @@ impl NetworkProvider for LoopbackNetwork {
         Ok(())
     }
 
+    fn send_direct(
+        &self,
+        to: pbc::PublicKey,
+        protocol_id: &str,
+        data: Vec<u8>,
+    ) -> Result<oneshot::Receiver<DirectDelivery>, Error> {
+        self.send(to, protocol_id, data)?;
+        let (tx, rx) = oneshot::channel();
+        tx.send(DirectDelivery::Delivered).ok();
+        Ok(rx)
+    }
+
+    fn dump_buckets(&self) -> Result<oneshot::Receiver<Vec<BucketInfo>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        tx.send(Vec::new()).ok();
+        Ok(rx)
+    }
+
+    fn query_journal(&self) -> Result<oneshot::Receiver<Vec<QueryRecord>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        tx.send(Vec::new()).ok();
+        Ok(rx)
+    }
+
     fn publish(&self, topic: &str, data: Vec<u8>) -> Result<(), Error> {
         trace!("Received publish for topic = {}", topic);
         let topic: String = topic.to_string();
@@ -89,6 +121,10 @@ impl NetworkProvider for LoopbackNetwork {
         Ok(())
     }
 
+    fn publish_unsubscribed(&self, topic: &str, data: Vec<u8>) -> Result<(), Error> {
+        self.publish(topic, data)
+    }
+
     fn change_network_keys(
         &self,
         _new_pkey: pbc::PublicKey,
@@ -97,6 +133,24 @@ impl NetworkProvider for LoopbackNetwork {
         Ok(())
     }
 
+    fn set_stake_checker(&self, _stake_checker: Arc<dyn StakeChecker>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn prewarm_connections(&self, _node_ids: Vec<pbc::PublicKey>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_lane(&self, _topic_or_protocol: &str, _lane: Lane) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<oneshot::Receiver<()>, Error> {
+        let (tx, rx) = oneshot::channel();
+        tx.send(()).ok();
+        Ok(rx)
+    }
+
     // Clone self as a box
     fn box_clone(&self) -> Network {
         Box::new((*self).clone())
@@ -150,6 +204,25 @@ impl Loopback {
         }
     }
 
+    /// Take the oldest message sent by the node, if any.
+    pub fn pop_message(&mut self) -> Option<MessageFromNode> {
+        self.state.lock().unwrap().queue.pop_front()
+    }
+
+    /// True if the node is subscribed to the broadcast topic.
+    pub fn is_subscribed(&self, topic: &str) -> bool {
+        self.state.lock().unwrap().consumers.contains_key(topic)
+    }
+
+    /// True if the node is subscribed to the unicast topic.
+    pub fn is_subscribed_unicast(&self, topic: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .unicast_consumers
+            .contains_key(topic)
+    }
+
     pub fn assert_broadcast<M: ProtoConvert + Debug + PartialEq>(&mut self, topic: &str, data: M) {
         let ref mut state = self.state.lock().unwrap();
         if let MessageFromNode::Publish {
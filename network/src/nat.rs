@@ -0,0 +1,229 @@
+//! Port mapping on the local gateway via UPnP IGD or NAT-PMP.
+
+//
+// MIT License
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use failure::{bail, format_err, Error};
+use libp2p::multiaddr::{Multiaddr, Protocol};
+use log::*;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// Requested lifetime of the port mapping, renewed at the half of it.
+const LEASE_DURATION: Duration = Duration::from_secs(3600);
+/// Delay before the next attempt if the gateway has not been found.
+const RETRY_INTERVAL: Duration = Duration::from_secs(300);
+/// Description of the port mapping shown by the gateway.
+const MAPPING_DESCRIPTION: &'static str = "stegos";
+/// How many times to wait for NAT-PMP response.
+const NATPMP_ATTEMPTS: usize = 40;
+/// Delay between NAT-PMP response polls.
+const NATPMP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Protocol used to map the listen port on the gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortMapping {
+    None,
+    Upnp,
+    NatPmp,
+    /// UPnP with NAT-PMP fallback.
+    Any,
+}
+
+impl FromStr for PortMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "none" => Ok(PortMapping::None),
+            "upnp" => Ok(PortMapping::Upnp),
+            "natpmp" => Ok(PortMapping::NatPmp),
+            "any" => Ok(PortMapping::Any),
+            _ => Err(format_err!("Invalid port_mapping: {}", s)),
+        }
+    }
+}
+
+/// An external address assigned by the gateway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mapping {
+    external_ip: Ipv4Addr,
+    external_port: u16,
+}
+
+impl Mapping {
+    fn to_multiaddr(&self) -> Multiaddr {
+        let mut addr = Multiaddr::from(Protocol::Ip4(self.external_ip));
+        addr.push(Protocol::Tcp(self.external_port));
+        addr
+    }
+}
+
+///
+/// Map the local TCP port on the gateway in background.
+///
+/// `on_mapped` is called with the external address every time it is discovered or changed.
+///
+pub(crate) fn start<F>(method: PortMapping, local_port: u16, on_mapped: F)
+where
+    F: Fn(Multiaddr) + Send + 'static,
+{
+    if method == PortMapping::None {
+        return;
+    }
+    thread::Builder::new()
+        .name("port-mapping".to_string())
+        .spawn(move || {
+            let mut announced: Option<Multiaddr> = None;
+            loop {
+                match map_port(method, local_port) {
+                    Ok(mapping) => {
+                        let addr = mapping.to_multiaddr();
+                        if announced.as_ref() != Some(&addr) {
+                            info!(
+                                "Mapped port on the gateway: local_port={}, external_address={}",
+                                local_port, addr
+                            );
+                            on_mapped(addr.clone());
+                            announced = Some(addr);
+                        }
+                        thread::sleep(LEASE_DURATION / 2);
+                    }
+                    Err(e) => {
+                        warn!("Failed to map port on the gateway: error={}", e);
+                        thread::sleep(RETRY_INTERVAL);
+                    }
+                }
+            }
+        })
+        .expect("failed to spawn port mapping thread");
+}
+
+fn map_port(method: PortMapping, local_port: u16) -> Result<Mapping, Error> {
+    match method {
+        PortMapping::Upnp => map_upnp(local_port),
+        PortMapping::NatPmp => map_natpmp(local_port),
+        PortMapping::Any => map_upnp(local_port).or_else(|e| {
+            debug!("UPnP failed, trying NAT-PMP: error={}", e);
+            map_natpmp(local_port)
+        }),
+        PortMapping::None => unreachable!(),
+    }
+}
+
+fn map_upnp(local_port: u16) -> Result<Mapping, Error> {
+    let gateway = igd::search_gateway(Default::default())?;
+    let local_ip = local_ip_for(gateway.addr)?;
+    let external_ip = gateway.get_external_ip()?;
+    gateway.add_port(
+        igd::PortMappingProtocol::TCP,
+        local_port,
+        SocketAddrV4::new(local_ip, local_port),
+        LEASE_DURATION.as_secs() as u32,
+        MAPPING_DESCRIPTION,
+    )?;
+    Ok(Mapping {
+        external_ip,
+        external_port: local_port,
+    })
+}
+
+fn map_natpmp(local_port: u16) -> Result<Mapping, Error> {
+    let mut natpmp = natpmp::Natpmp::new().map_err(natpmp_error)?;
+    natpmp.send_public_address_request().map_err(natpmp_error)?;
+    let external_ip = match natpmp_response(&mut natpmp)? {
+        natpmp::Response::Gateway(response) => *response.public_address(),
+        response => bail!("Unexpected NAT-PMP response: {:?}", response),
+    };
+    natpmp
+        .send_port_mapping_request(
+            natpmp::Protocol::TCP,
+            local_port,
+            local_port,
+            LEASE_DURATION.as_secs() as u32,
+        )
+        .map_err(natpmp_error)?;
+    let external_port = match natpmp_response(&mut natpmp)? {
+        natpmp::Response::TCP(response) => response.public_port(),
+        response => bail!("Unexpected NAT-PMP response: {:?}", response),
+    };
+    Ok(Mapping {
+        external_ip,
+        external_port,
+    })
+}
+
+fn natpmp_response(natpmp: &mut natpmp::Natpmp) -> Result<natpmp::Response, Error> {
+    for _ in 0..NATPMP_ATTEMPTS {
+        match natpmp.read_response_or_retry() {
+            Ok(response) => return Ok(response),
+            Err(natpmp::Error::NATPMP_TRYAGAIN) => thread::sleep(NATPMP_POLL_INTERVAL),
+            Err(e) => return Err(natpmp_error(e)),
+        }
+    }
+    bail!("NAT-PMP gateway didn't respond")
+}
+
+fn natpmp_error(e: natpmp::Error) -> Error {
+    format_err!("NAT-PMP error: {:?}", e)
+}
+
+/// Local address of the interface facing the gateway.
+fn local_ip_for(gateway: SocketAddrV4) -> Result<Ipv4Addr, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(gateway)?;
+    match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(ip) => bail!("Unexpected local address: {}", ip),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_port_mapping() {
+        assert_eq!(PortMapping::from_str("none").unwrap(), PortMapping::None);
+        assert_eq!(PortMapping::from_str("").unwrap(), PortMapping::None);
+        assert_eq!(PortMapping::from_str("upnp").unwrap(), PortMapping::Upnp);
+        assert_eq!(
+            PortMapping::from_str("natpmp").unwrap(),
+            PortMapping::NatPmp
+        );
+        assert_eq!(PortMapping::from_str("any").unwrap(), PortMapping::Any);
+        assert!(PortMapping::from_str("pcp").is_err());
+    }
+
+    #[test]
+    fn mapping_multiaddr() {
+        let mapping = Mapping {
+            external_ip: Ipv4Addr::new(203, 0, 113, 7),
+            external_port: 10203,
+        };
+        let expected: Multiaddr = "/ip4/203.0.113.7/tcp/10203".parse().unwrap();
+        assert_eq!(mapping.to_multiaddr(), expected);
+    }
+}
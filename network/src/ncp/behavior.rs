@@ -32,9 +32,10 @@ use lru_time_cache::LruCache;
 use rand::{thread_rng, Rng};
 use smallvec::SmallVec;
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, HashSet, VecDeque},
     marker::PhantomData,
-    time::{Duration, Instant},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 use stegos_crypto::pbc;
 use stegos_keychain::KeyChain;
@@ -42,7 +43,10 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::timer::Delay;
 
 use crate::config::NetworkConfig;
+use crate::kad::StakeChecker;
 use crate::ncp::handler::NcpHandler;
+use crate::ncp::peering::{select_evictions, select_preferred, PeerPriority};
+use crate::ncp::pex::{PexEntry, PexMessage};
 use crate::ncp::protocol::{GetPeersResponse, NcpMessage, PeerInfo};
 use crate::utils::ExpiringQueue;
 
@@ -57,6 +61,8 @@ const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 pub struct Ncp<TSubstream> {
     /// Out network key
     node_id: pbc::PublicKey,
+    /// Secret key to sign PEX messages
+    network_skey: pbc::SecretKey,
     /// Queue of internal events
     events: VecDeque<NcpEvent>,
     /// Events that need to be yielded to the outside when polling.
@@ -75,6 +81,14 @@ pub struct Ncp<TSubstream> {
     delay_between_monitor_events: Duration,
     /// Seed nodes (we keep them in case we were too long offline and need to restart the net)
     seed_nodes: Vec<Multiaddr>,
+    /// Time of the last accepted PEX message from connected peers
+    last_pex: HashMap<PeerId, Instant>,
+    /// Source of stakes used to prefer connections to validators
+    stake_checker: Option<Arc<dyn StakeChecker>>,
+    /// Number of connections kept to peers with the largest stakes
+    preferred_peers: usize,
+    /// Number of connections to other peers never dropped in favour of preferred ones
+    min_random_peers: usize,
     /// Marker to pin the generics.
     marker: PhantomData<TSubstream>,
 }
@@ -92,6 +106,7 @@ impl<TSubstream> Ncp<TSubstream> {
             .collect();
         Ncp {
             node_id: keychain.network_pkey.clone(),
+            network_skey: keychain.network_skey.clone(),
             events: VecDeque::new(),
             out_events: VecDeque::new(),
             connected_peers: ExpiringQueue::new(IDLE_TIMEOUT),
@@ -108,12 +123,17 @@ impl<TSubstream> Ncp<TSubstream> {
             ),
             delay_between_monitor_events: Duration::from_secs(config.monitoring_interval),
             seed_nodes,
+            last_pex: HashMap::new(),
+            stake_checker: None,
+            preferred_peers: config.preferred_peers,
+            min_random_peers: config.min_random_peers,
             marker: PhantomData,
         }
     }
 
-    pub fn change_network_key(&mut self, new_pkey: pbc::PublicKey) {
+    pub fn change_network_key(&mut self, new_pkey: pbc::PublicKey, new_skey: pbc::SecretKey) {
         self.node_id = new_pkey;
+        self.network_skey = new_skey;
         // Update all connected peers with our new network key
         for p in self.connected_peers.keys() {
             self.events
@@ -121,6 +141,105 @@ impl<TSubstream> Ncp<TSubstream> {
         }
     }
 
+    /// Sends a signed sample of validators to the peers, see `NcpOutEvent::PexRequired`.
+    pub fn send_pex(&mut self, peer_ids: Vec<PeerId>, entries: Vec<PexEntry>) {
+        if entries.is_empty() {
+            return;
+        }
+        let pex = PexMessage::new(&self.network_skey, self.node_id.clone(), entries);
+        for peer_id in peer_ids {
+            self.events.push_back(NcpEvent::SendPex {
+                peer_id,
+                pex: pex.clone(),
+            });
+        }
+    }
+
+    /// Checks a PEX message received from the peer.
+    fn on_pex(&mut self, peer_id: PeerId, pex: PexMessage) {
+        // Peers send one sample per monitoring interval, ignore the excess without verification.
+        if let Some(last_pex) = self.last_pex.get(&peer_id) {
+            if last_pex.elapsed() < self.delay_between_monitor_events / 2 {
+                debug!(target: "stegos_network::ncp", "PEX message is too frequent: peer_id={}", peer_id.to_base58());
+                return;
+            }
+        }
+        let node_id_matches = match self.known_peers.get(peer_id.as_bytes()) {
+            Some((node_id, _)) => *node_id == pex.node_id,
+            None => true,
+        };
+        if !node_id_matches || !pex.is_valid(SystemTime::now()) {
+            debug!(target: "stegos_network::ncp", "invalid PEX message: peer_id={}, node_id={}", peer_id.to_base58(), pex.node_id);
+            self.out_events
+                .push_back(NcpOutEvent::InvalidPex { peer_id });
+            return;
+        }
+        debug!(target: "stegos_network::ncp", "received PEX message: peer_id={}, entries={}", peer_id.to_base58(), pex.entries.len());
+        self.last_pex.insert(peer_id.clone(), Instant::now());
+        self.out_events.push_back(NcpOutEvent::PexReceived {
+            peer_id,
+            entries: pex.entries,
+        });
+    }
+
+    /// Sets the source of stakes used to prefer connections to validators.
+    pub fn set_stake_checker(&mut self, stake_checker: Arc<dyn StakeChecker>) {
+        self.stake_checker = Some(stake_checker);
+    }
+
+    /// Dials preferred peers, dropping the weakest connections if the limit is reached.
+    fn connect_preferred_peers(&mut self, local_peer_id: &PeerId) {
+        let stake_checker = match &self.stake_checker {
+            Some(stake_checker) if self.preferred_peers > 0 => stake_checker.clone(),
+            _ => return,
+        };
+        let candidates: HashMap<PeerId, PeerPriority> = self
+            .known_peers
+            .peek_iter()
+            .filter_map(|(peer_bytes, (node_id, _addresses))| {
+                let peer_id = PeerId::from_bytes(peer_bytes.clone()).ok()?;
+                if peer_id == *local_peer_id {
+                    return None;
+                }
+                Some((peer_id, PeerPriority::of(&*stake_checker, node_id)))
+            })
+            .collect();
+        let preferred = select_preferred(
+            candidates
+                .iter()
+                .map(|(p, priority)| (p.clone(), *priority)),
+            self.preferred_peers,
+        );
+        let preferred_set: HashSet<PeerId> = preferred.iter().cloned().collect();
+        let connected = self.connected_peers.keys().map(|p| {
+            let priority = candidates.get(p).cloned().unwrap_or_default();
+            (p.clone(), priority)
+        });
+        let mut evictions =
+            select_evictions(connected, &preferred_set, self.min_random_peers).into_iter();
+
+        let mut connections = self.connected_peers.len();
+        for peer_id in preferred {
+            if self.connected_peers.contains_key(&peer_id) {
+                continue;
+            }
+            if connections >= self.max_connections {
+                match evictions.next() {
+                    Some(evicted) => {
+                        debug!(target: "stegos_network::ncp", "dropping connection in favour of a staked peer: peer_id={}, preferred={}", evicted.to_base58(), peer_id.to_base58());
+                        self.events
+                            .push_back(NcpEvent::Terminate { peer_id: evicted });
+                    }
+                    None => break,
+                }
+            } else {
+                connections += 1;
+            }
+            debug!(target: "stegos_network::ncp", "dialing staked peer: peer_id={}", peer_id.to_base58());
+            self.out_events.push_back(NcpOutEvent::DialPeer { peer_id });
+        }
+    }
+
     // Terminate connection to peer
     pub fn terminate(&mut self, peer_id: PeerId) {
         debug!(target: "stegos_network::ncp", "terminating connection with peer: peer_id={}", peer_id);
@@ -157,12 +276,16 @@ where
         self.out_events.push_back(NcpOutEvent::Connected {
             peer_id: id.clone(),
         });
+        self.out_events.push_back(NcpOutEvent::PexRequired {
+            peer_ids: vec![id.clone()],
+        });
         self.connected_peers.insert(id, Instant::now());
     }
 
     fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
         debug!(target: "stegos_network::ncp", "peer disconnected: peer_id={}", id.to_base58());
         self.connected_peers.remove(id);
+        self.last_pex.remove(id);
         self.out_events.push_back(NcpOutEvent::Disconnected {
             peer_id: id.clone(),
         });
@@ -194,6 +317,9 @@ where
             NcpRecvEvent::Recv(NcpMessage::Pong) => {
                 debug!(target: "stegos_network::ncp", "received pong request: peer_id={}", propagation_source.to_base58());
             }
+            NcpRecvEvent::Recv(NcpMessage::Pex { pex }) => {
+                self.on_pex(propagation_source, pex);
+            }
         }
     }
 
@@ -233,6 +359,14 @@ where
                             .push_back(NcpEvent::SendPing { peer_id: p.clone() });
                         let _ = self.known_peers.get(p.as_bytes());
                     }
+                    // share known validators with neighbors
+                    if !self.connected_peers.is_empty() {
+                        let peer_ids = self.connected_peers.keys().cloned().collect();
+                        self.out_events
+                            .push_back(NcpOutEvent::PexRequired { peer_ids });
+                    }
+                    let local_peer_id = poll_parameters.local_peer_id().clone();
+                    self.connect_preferred_peers(&local_peer_id);
                     if self.connected_peers.len() >= self.max_connections {
                         // Already have max connected_peers
                         continue;
@@ -404,6 +538,13 @@ where
                         event: NcpSendEvent::Send(NcpMessage::Pong),
                     });
                 }
+                NcpEvent::SendPex { peer_id, pex } => {
+                    debug!(target: "stegos_network::ncp", "sending PEX message: to_peer={}, entries={}", peer_id.to_base58(), pex.entries.len());
+                    return Async::Ready(NetworkBehaviourAction::SendEvent {
+                        peer_id,
+                        event: NcpSendEvent::Send(NcpMessage::Pex { pex }),
+                    });
+                }
                 NcpEvent::Terminate { peer_id } => {
                     debug!(target: "stegos_network::ncp", "sending terminate to handler: peer_id={}", peer_id.to_base58());
                     return Async::Ready(NetworkBehaviourAction::SendEvent {
@@ -432,6 +573,8 @@ pub enum NcpEvent {
     SendPing { peer_id: PeerId },
     /// Send Pong reply to the peer
     SendPong { peer_id: PeerId },
+    /// Send signed sample of validators to the peer
+    SendPex { peer_id: PeerId, pex: PexMessage },
     /// Terminate connection to peer
     Terminate { peer_id: PeerId },
 }
@@ -459,6 +602,19 @@ pub enum NcpOutEvent {
     Disconnected {
         peer_id: PeerId,
     },
+    /// Signed sample of validators should be sent to the peers, see `Ncp::send_pex()`.
+    PexRequired {
+        peer_ids: Vec<PeerId>,
+    },
+    /// Verified sample of validators received from the peer.
+    PexReceived {
+        peer_id: PeerId,
+        entries: Vec<PexEntry>,
+    },
+    /// The peer sent an invalid PEX message.
+    InvalidPex {
+        peer_id: PeerId,
+    },
 }
 
 /// Event passed to protocol handler
@@ -23,8 +23,11 @@
 
 mod behavior;
 mod handler;
+mod peering;
+mod pex;
 mod proto;
 mod protocol;
 
 pub use self::behavior::{Ncp, NcpOutEvent};
+pub use self::pex::{PexEntry, PexMessage, MAX_PEX_ENTRIES};
 pub use self::protocol::PeerInfo;
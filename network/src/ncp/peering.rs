@@ -0,0 +1,150 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Stake-weighted peering.
+//!
+//! Consensus messages are gossiped over direct connections, so every link between
+//! validators saves a hop. NCP keeps connections to the peers with the largest stakes,
+//! validators of the current epoch first, and leaves the remaining slots to random peers
+//! so that nodes without a stake stay connected to the network.
+
+use libp2p::core::PeerId;
+use std::collections::HashSet;
+use stegos_crypto::pbc;
+
+use crate::kad::StakeChecker;
+
+/// Preference of a peer when choosing connections, the greater is the better.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PeerPriority {
+    /// Holds a slot in the current epoch.
+    validator: bool,
+    /// Active stake.
+    stake: i64,
+}
+
+impl PeerPriority {
+    pub fn new(validator: bool, stake: i64) -> Self {
+        PeerPriority { validator, stake }
+    }
+
+    /// Looks up the priority of the node.
+    pub fn of(stake_checker: &dyn StakeChecker, node_id: &pbc::PublicKey) -> Self {
+        Self::new(
+            stake_checker.is_validator(node_id),
+            stake_checker.stake(node_id),
+        )
+    }
+
+    /// Returns true if the peer should be kept connected.
+    pub fn is_preferred(&self) -> bool {
+        self.validator || self.stake > 0
+    }
+}
+
+/// Returns up to `count` preferred peers, the best first.
+pub fn select_preferred<I>(candidates: I, count: usize) -> Vec<PeerId>
+where
+    I: IntoIterator<Item = (PeerId, PeerPriority)>,
+{
+    let mut preferred: Vec<(PeerId, PeerPriority)> = candidates
+        .into_iter()
+        .filter(|(_, priority)| priority.is_preferred())
+        .collect();
+    preferred.sort_by(|(_, a), (_, b)| b.cmp(a));
+    preferred.truncate(count);
+    preferred.into_iter().map(|(peer_id, _)| peer_id).collect()
+}
+
+/// Returns connected peers which can be dropped in favour of preferred ones, the weakest first.
+/// At least `min_random` peers outside of the preferred set are kept.
+pub fn select_evictions<I>(
+    connected: I,
+    preferred: &HashSet<PeerId>,
+    min_random: usize,
+) -> Vec<PeerId>
+where
+    I: IntoIterator<Item = (PeerId, PeerPriority)>,
+{
+    let mut others: Vec<(PeerId, PeerPriority)> = connected
+        .into_iter()
+        .filter(|(peer_id, _)| !preferred.contains(peer_id))
+        .collect();
+    others.sort_by_key(|(_, priority)| *priority);
+    let count = others.len().saturating_sub(min_random);
+    others.truncate(count);
+    others.into_iter().map(|(peer_id, _)| peer_id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferred_order() {
+        let validator = PeerId::random();
+        let large = PeerId::random();
+        let small = PeerId::random();
+        let unstaked = PeerId::random();
+        let candidates = vec![
+            (unstaked.clone(), PeerPriority::new(false, 0)),
+            (small.clone(), PeerPriority::new(false, 10)),
+            (large.clone(), PeerPriority::new(false, 1000)),
+            (validator.clone(), PeerPriority::new(true, 100)),
+        ];
+
+        let preferred = select_preferred(candidates.clone(), 10);
+        assert_eq!(preferred, vec![validator.clone(), large.clone(), small]);
+
+        let preferred = select_preferred(candidates, 2);
+        assert_eq!(preferred, vec![validator, large]);
+    }
+
+    #[test]
+    fn eviction() {
+        let validator = PeerId::random();
+        let staked = PeerId::random();
+        let random1 = PeerId::random();
+        let random2 = PeerId::random();
+        let connected = vec![
+            (validator.clone(), PeerPriority::new(true, 100)),
+            (staked.clone(), PeerPriority::new(false, 10)),
+            (random1.clone(), PeerPriority::new(false, 0)),
+            (random2.clone(), PeerPriority::new(false, 0)),
+        ];
+        let mut preferred = HashSet::new();
+        preferred.insert(validator.clone());
+
+        // The weakest peers outside of the preferred set go first.
+        let evicted = select_evictions(connected.clone(), &preferred, 1);
+        assert_eq!(evicted.len(), 2);
+        assert!(evicted.contains(&random1) || evicted.contains(&random2));
+        assert!(!evicted.contains(&validator));
+        assert!(!evicted.contains(&staked));
+
+        // Random peers are kept to preserve connectivity.
+        preferred.insert(staked.clone());
+        assert!(select_evictions(connected.clone(), &preferred, 2).is_empty());
+        assert_eq!(select_evictions(connected, &preferred, 1).len(), 1);
+    }
+}
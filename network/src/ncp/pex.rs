@@ -0,0 +1,176 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Signed Peer Exchange (PEX).
+//!
+//! Connected peers periodically share a sample of validators from their routing tables.
+//! Every entry carries the address record signed by the validator itself, and the whole
+//! sample is signed by the sender, so a peer can only vouch for what the validators announced.
+
+use libp2p::core::PeerId;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stegos_crypto::hash::{Hash, Hasher};
+use stegos_crypto::pbc;
+
+use crate::kad::AddressRecord;
+
+/// Maximum number of entries in a single message.
+pub const MAX_PEX_ENTRIES: usize = 16;
+
+/// Messages older than this are treated as replayed.
+pub const PEX_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Validator known to the sender.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PexEntry {
+    /// Libp2p identity of the validator.
+    pub peer_id: PeerId,
+    /// Addresses, signed by the validator.
+    pub record: AddressRecord,
+}
+
+/// Sample of validators, signed by the network key of the sender.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PexMessage {
+    /// Network key of the sender.
+    pub node_id: pbc::PublicKey,
+    /// Creation time, in milliseconds since UNIX epoch.
+    pub timestamp: u64,
+    /// Shared validators.
+    pub entries: Vec<PexEntry>,
+    /// Signature of node_id, timestamp and entries.
+    pub signature: pbc::Signature,
+}
+
+impl PexMessage {
+    /// Creates and signs a new message. Extra entries are dropped.
+    pub fn new(
+        skey: &pbc::SecretKey,
+        node_id: pbc::PublicKey,
+        mut entries: Vec<PexEntry>,
+    ) -> PexMessage {
+        entries.truncate(MAX_PEX_ENTRIES);
+        let timestamp = now_millis(SystemTime::now());
+        let hash = Self::hash(&node_id, timestamp, &entries);
+        let signature = pbc::sign_hash(&hash, skey);
+        PexMessage {
+            node_id,
+            timestamp,
+            entries,
+            signature,
+        }
+    }
+
+    fn hash(node_id: &pbc::PublicKey, timestamp: u64, entries: &[PexEntry]) -> Hash {
+        let mut hasher = Hasher::new();
+        hasher.input(&node_id.to_bytes()[..]);
+        hasher.input(&timestamp.to_le_bytes());
+        for entry in entries {
+            let peer_id = entry.peer_id.as_bytes();
+            hasher.input(&(peer_id.len() as u64).to_le_bytes());
+            hasher.input(peer_id);
+            // The signature of the record covers its addresses.
+            hasher.input(&entry.record.node_id.to_bytes()[..]);
+            hasher.input(&entry.record.seq.to_le_bytes());
+            hasher.input(&entry.record.signature.to_bytes()[..]);
+        }
+        hasher.result()
+    }
+
+    /// Checks the size, the age and both levels of signatures of the message.
+    pub fn is_valid(&self, now: SystemTime) -> bool {
+        if self.entries.len() > MAX_PEX_ENTRIES {
+            return false;
+        }
+        let now = now_millis(now);
+        if self.timestamp.saturating_sub(now) > PEX_MAX_AGE.as_millis() as u64
+            || now.saturating_sub(self.timestamp) > PEX_MAX_AGE.as_millis() as u64
+        {
+            return false;
+        }
+        let hash = Self::hash(&self.node_id, self.timestamp, &self.entries);
+        if pbc::check_hash(&hash, &self.signature, &self.node_id).is_err() {
+            return false;
+        }
+        self.entries.iter().all(|entry| entry.record.is_valid())
+    }
+}
+
+fn now_millis(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> PexEntry {
+        let (skey, pkey) = pbc::make_random_keys();
+        let addr = "/ip4/1.2.3.4/tcp/10203".parse().unwrap();
+        PexEntry {
+            peer_id: PeerId::random(),
+            record: AddressRecord::new(&skey, pkey, 1, vec![addr]),
+        }
+    }
+
+    #[test]
+    fn signed_message() {
+        let (skey, pkey) = pbc::make_random_keys();
+        let now = SystemTime::now();
+
+        let msg = PexMessage::new(&skey, pkey, vec![entry(), entry()]);
+        assert!(msg.is_valid(now));
+
+        // Oversized samples are truncated.
+        let entries = (0..MAX_PEX_ENTRIES + 1).map(|_| entry()).collect();
+        let msg = PexMessage::new(&skey, pkey, entries);
+        assert_eq!(msg.entries.len(), MAX_PEX_ENTRIES);
+        assert!(msg.is_valid(now));
+
+        // Replays are rejected.
+        let msg = PexMessage::new(&skey, pkey, vec![entry()]);
+        assert!(!msg.is_valid(now + PEX_MAX_AGE + Duration::from_secs(1)));
+
+        // The sender can't change the entries.
+        let mut forged = PexMessage::new(&skey, pkey, vec![entry()]);
+        forged.entries.push(entry());
+        assert!(!forged.is_valid(now));
+        let mut forged = PexMessage::new(&skey, pkey, vec![entry()]);
+        forged.entries[0].peer_id = PeerId::random();
+        assert!(!forged.is_valid(now));
+
+        // Nor forge the addresses of validators.
+        let mut other = entry();
+        other.record.addrs = vec!["/ip4/6.7.8.9/tcp/10203".parse().unwrap()];
+        let forged = PexMessage::new(&skey, pkey, vec![other]);
+        assert!(!forged.is_valid(now));
+
+        // Nor impersonate other nodes.
+        let (_other_skey, other_pkey) = pbc::make_random_keys();
+        let mut forged = PexMessage::new(&skey, pkey, vec![entry()]);
+        forged.node_id = other_pkey;
+        assert!(!forged.is_valid(now));
+    }
+}
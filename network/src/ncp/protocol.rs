@@ -33,7 +33,9 @@ use tokio::codec::{Decoder, Encoder, Framed};
 use tokio::io::{AsyncRead, AsyncWrite};
 use unsigned_varint::codec;
 
+use super::pex::{PexEntry, PexMessage};
 use super::proto::ncp_proto;
+use crate::kad::AddressRecord;
 
 /// Implementation of `ConnectionUpgrade` for the floodsub protocol.
 #[derive(Debug, Clone)]
@@ -138,6 +140,31 @@ impl Encoder for NcpCodec {
                 msg.set_field_type(ncp_proto::Message_MessageType::PONG);
                 msg
             }
+            NcpMessage::Pex { pex } => {
+                let mut msg = ncp_proto::Message::new();
+                msg.set_field_type(ncp_proto::Message_MessageType::PEX);
+
+                let mut proto = ncp_proto::Message_Pex::new();
+                proto.set_node_id(pex.node_id.to_bytes().to_vec());
+                proto.set_timestamp(pex.timestamp);
+                for entry in pex.entries.into_iter() {
+                    let mut record = ncp_proto::Message_AddressRecord::new();
+                    record.set_node_id(entry.record.node_id.to_bytes().to_vec());
+                    record.set_seq(entry.record.seq);
+                    for addr in entry.record.addrs.into_iter() {
+                        record.mut_addrs().push(addr.to_vec());
+                    }
+                    record.set_signature(entry.record.signature.to_bytes().to_vec());
+                    let mut pex_entry = ncp_proto::Message_PexEntry::new();
+                    pex_entry.set_peer_id(entry.peer_id.into_bytes());
+                    pex_entry.set_record(record);
+                    proto.mut_entries().push(pex_entry);
+                }
+                proto.set_signature(pex.signature.to_bytes().to_vec());
+                msg.set_pex(proto);
+
+                msg
+            }
         };
 
         let msg_size = proto.compute_size();
@@ -203,10 +230,57 @@ impl Decoder for NcpCodec {
             }
             ncp_proto::Message_MessageType::PING => Ok(Some(NcpMessage::Ping)),
             ncp_proto::Message_MessageType::PONG => Ok(Some(NcpMessage::Pong)),
+            ncp_proto::Message_MessageType::PEX => {
+                let pex = decode_pex(message.get_pex())?;
+                Ok(Some(NcpMessage::Pex { pex }))
+            }
         }
     }
 }
 
+fn decode_pex(proto: &ncp_proto::Message_Pex) -> Result<PexMessage, io::Error> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("bad protobuf encoding, failed to decode {}", what),
+        )
+    };
+    let node_id =
+        pbc::PublicKey::try_from_bytes(proto.get_node_id()).map_err(|_| invalid("node_id"))?;
+    let signature =
+        pbc::Signature::try_from_bytes(proto.get_signature()).map_err(|_| invalid("signature"))?;
+    let mut entries = Vec::with_capacity(proto.get_entries().len());
+    for entry in proto.get_entries().iter() {
+        let peer_id =
+            PeerId::from_bytes(entry.get_peer_id().to_vec()).map_err(|_| invalid("peer_id"))?;
+        let record = entry.get_record();
+        let record_node_id =
+            pbc::PublicKey::try_from_bytes(record.get_node_id()).map_err(|_| invalid("node_id"))?;
+        let mut addrs = Vec::with_capacity(record.get_addrs().len());
+        for addr in record.get_addrs().iter() {
+            // Addresses are signed, so they can't be skipped.
+            addrs.push(Multiaddr::try_from(addr.to_vec()).map_err(|_| invalid("address"))?);
+        }
+        let record_signature = pbc::Signature::try_from_bytes(record.get_signature())
+            .map_err(|_| invalid("signature"))?;
+        entries.push(PexEntry {
+            peer_id,
+            record: AddressRecord {
+                node_id: record_node_id,
+                seq: record.get_seq(),
+                addrs,
+                signature: record_signature,
+            },
+        });
+    }
+    Ok(PexMessage {
+        node_id,
+        timestamp: proto.get_timestamp(),
+        entries,
+        signature,
+    })
+}
+
 /// Message that we can send to a peer or received from a peer.
 #[derive(Debug, Clone, PartialEq)]
 pub enum NcpMessage {
@@ -214,6 +288,7 @@ pub enum NcpMessage {
     GetPeersResponse { response: GetPeersResponse },
     Ping,
     Pong,
+    Pex { pex: PexMessage },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -241,6 +316,8 @@ impl PeerInfo {
 #[cfg(test)]
 mod tests {
     use super::{GetPeersResponse, NcpCodec, NcpMessage, PeerInfo};
+    use crate::kad::AddressRecord;
+    use crate::ncp::pex::{PexEntry, PexMessage};
     use futures::{future, Future, Sink, Stream};
     use libp2p::core::PeerId;
     use stegos_crypto::pbc;
@@ -272,6 +349,24 @@ mod tests {
         };
 
         test_one(msg);
+
+        let (skey, pkey) = pbc::make_random_keys();
+        let (validator_skey, validator_pkey) = pbc::make_random_keys();
+        let record = AddressRecord::new(
+            &validator_skey,
+            validator_pkey,
+            3,
+            vec![
+                "/ip4/1.2.3.4/tcp/1111".parse().unwrap(),
+                "/ip6/::1/tcp/1231".parse().unwrap(),
+            ],
+        );
+        let entries = vec![PexEntry {
+            peer_id: PeerId::random(),
+            record,
+        }];
+        let pex = PexMessage::new(&skey, pkey, entries);
+        test_one(NcpMessage::Pex { pex });
     }
 
     fn test_one(msg: NcpMessage) {
@@ -24,6 +24,7 @@ use super::protocol::{
     FloodsubMessage, FloodsubRpc, FloodsubSubscription, FloodsubSubscriptionAction,
 };
 use super::topic::{Topic, TopicHash};
+use crate::utils::ProtocolVersion;
 
 use futures::prelude::*;
 use libp2p::core::swarm::{
@@ -78,16 +79,22 @@ pub struct Floodsub<TSubstream> {
     /// Tracking incoming message rate for peers
     incoming_rates: HashMap<PeerId, RollingRateCounter>,
 
+    /// Protocol versions negotiated with connected peers.
+    protocol_versions: HashMap<PeerId, ProtocolVersion>,
+
     /// Metrics uodate delay (update metrics at this interval)
     metrics_update_delay: Delay,
 
+    /// Offer snappy compression to peers.
+    compression: bool,
+
     /// Marker to pin the generics.
     marker: PhantomData<TSubstream>,
 }
 
 impl<TSubstream> Floodsub<TSubstream> {
     /// Creates a `Floodsub`.
-    pub fn new(local_peer_id: PeerId) -> Self {
+    pub fn new(local_peer_id: PeerId, compression: bool) -> Self {
         Floodsub {
             events: VecDeque::new(),
             local_peer_id,
@@ -100,7 +107,9 @@ impl<TSubstream> Floodsub<TSubstream> {
                 1_000_000,
             ),
             incoming_rates: HashMap::new(),
+            protocol_versions: HashMap::new(),
             metrics_update_delay: Delay::new(Instant::now() + METRICS_UPDATE_INTERVAL),
+            compression,
             marker: PhantomData,
         }
     }
@@ -136,6 +145,37 @@ impl<TSubstream> Floodsub<TSubstream> {
         true
     }
 
+    /// Unsubscribes from a topic.
+    ///
+    /// Returns true if we were subscribed to this topic.
+    pub fn unsubscribe(&mut self, topic: Topic) -> bool {
+        let pos = match self
+            .subscribed_topics
+            .iter()
+            .position(|t| t.hash() == topic.hash())
+        {
+            Some(pos) => pos,
+            None => return false,
+        };
+
+        self.subscribed_topics.remove(pos);
+
+        for peer in self.unlocked_remotes.keys() {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: peer.clone(),
+                event: FloodsubSendEvent::Publish(FloodsubRpc {
+                    messages: Vec::new(),
+                    subscriptions: vec![FloodsubSubscription {
+                        topic: topic.hash().clone(),
+                        action: FloodsubSubscriptionAction::Unsubscribe,
+                    }],
+                }),
+            });
+        }
+
+        true
+    }
+
     /// Publishes a message to the network.
     ///
     /// > **Note**: Doesn't do anything if we're not subscribed to the topic.
@@ -150,6 +190,23 @@ impl<TSubstream> Floodsub<TSubstream> {
         &mut self,
         topic: impl IntoIterator<Item = impl Into<TopicHash>>,
         data: impl Into<Vec<u8>>,
+    ) {
+        self.publish_inner(topic, data, true)
+    }
+
+    /// Publishes a message to peers subscribed to the topic,
+    /// even if we're not subscribed to the topic ourselves.
+    ///
+    /// Used for shards of a topic, where each node follows only a subset of shards.
+    pub fn publish_unsubscribed(&mut self, topic: impl Into<TopicHash>, data: impl Into<Vec<u8>>) {
+        self.publish_inner(iter::once(topic), data, false)
+    }
+
+    fn publish_inner(
+        &mut self,
+        topic: impl IntoIterator<Item = impl Into<TopicHash>>,
+        data: impl Into<Vec<u8>>,
+        check_self_subscription: bool,
     ) {
         let mut message = FloodsubMessage {
             source: self.local_peer_id.clone(),
@@ -162,10 +219,11 @@ impl<TSubstream> Floodsub<TSubstream> {
         };
 
         // Don't publish the message if we're not subscribed ourselves to any of the topics.
-        if !self
-            .subscribed_topics
-            .iter()
-            .any(|t| message.topics.iter().any(|u| t.hash() == u))
+        if check_self_subscription
+            && !self
+                .subscribed_topics
+                .iter()
+                .any(|t| message.topics.iter().any(|u| t.hash() == u))
         {
             return;
         }
@@ -239,6 +297,11 @@ impl<TSubstream> Floodsub<TSubstream> {
         }
     }
 
+    /// Returns the protocol version negotiated with the connected peer.
+    pub fn protocol_version(&self, peer_id: &PeerId) -> Option<ProtocolVersion> {
+        self.protocol_versions.get(peer_id).cloned()
+    }
+
     pub fn enable_incoming(&mut self, peer_id: &PeerId) {
         debug!(target: "stegos_network::gatekeeper", "enabling pubsub listener: peer_id={}", peer_id);
         if !self.connected_peers.contains(peer_id) {
@@ -266,7 +329,7 @@ where
     type OutEvent = FloodsubEvent;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        FloodsubHandler::new()
+        FloodsubHandler::new(self.compression)
     }
 
     fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
@@ -284,9 +347,17 @@ where
         debug_assert!(was_in);
         self.allowed_remotes.remove(id);
         self.unlocked_remotes.remove(id);
+        self.protocol_versions.remove(id);
     }
 
     fn inject_node_event(&mut self, propagation_source: PeerId, event: FloodsubRecvEvent) {
+        // Versions are negotiated before the peer is unlocked by the gatekeeper.
+        if let FloodsubRecvEvent::ProtocolVersion(version) = event {
+            debug!(target: "stegos_network::pubsub", "negotiated protocol: peer_id={}, version={}", propagation_source, version);
+            self.protocol_versions.insert(propagation_source, version);
+            return;
+        }
+
         self.incoming_rates
             .entry(propagation_source.clone())
             .or_insert(RollingRateCounter::new(PUBSUB_SAMPLES))
@@ -393,6 +464,7 @@ where
                     });
                 }
             }
+            FloodsubRecvEvent::ProtocolVersion(_) => unreachable!("handled above"),
         }
     }
 
@@ -466,4 +538,6 @@ pub enum FloodsubSendEvent {
 /// Event received from handler
 pub enum FloodsubRecvEvent {
     Message(FloodsubRpc),
+    /// The remote has negotiated a protocol version different from the previous one.
+    ProtocolVersion(ProtocolVersion),
 }
@@ -34,6 +34,7 @@ use std::{fmt, io, time::Instant};
 use tokio::codec::Framed;
 use tokio::io::{AsyncRead, AsyncWrite};
 
+use crate::utils::ProtocolVersion;
 use crate::NETWORK_IDLE_TIMEOUT;
 
 /// Protocol handler that handles communication with the remote for the floodsub protocol.
@@ -56,6 +57,8 @@ where
     send_queue: SmallVec<[FloodsubRpc; 16]>,
     /// Events to send upstream
     out_events: VecDeque<FloodsubRecvEvent>,
+    /// The latest protocol version negotiated with the remote.
+    remote_version: Option<ProtocolVersion>,
 }
 
 /// State of an active substream, opened either by us or by the remote.
@@ -93,13 +96,23 @@ where
     TSubstream: AsyncRead + AsyncWrite,
 {
     /// Builds a new `FloodsubHandler`.
-    pub fn new() -> Self {
+    pub fn new(compression: bool) -> Self {
         FloodsubHandler {
-            config: FloodsubConfig::new(),
+            config: FloodsubConfig::new(compression),
             substreams: Vec::new(),
             keep_alive: KeepAlive::Yes,
             send_queue: SmallVec::new(),
             out_events: VecDeque::new(),
+            remote_version: None,
+        }
+    }
+
+    /// Reports the protocol version negotiated for a new substream, if changed.
+    fn on_negotiated(&mut self, version: ProtocolVersion) {
+        if self.remote_version != Some(version) {
+            self.remote_version = Some(version);
+            self.out_events
+                .push_back(FloodsubRecvEvent::ProtocolVersion(version));
         }
     }
 }
@@ -125,6 +138,8 @@ where
         &mut self,
         protocol: <Self::InboundProtocol as InboundUpgrade<TSubstream>>::Output,
     ) {
+        let (protocol, version) = protocol;
+        self.on_negotiated(version);
         self.substreams.push(SubstreamState::WaitingInput(protocol))
     }
 
@@ -133,6 +148,8 @@ where
         protocol: <Self::OutboundProtocol as OutboundUpgrade<TSubstream>>::Output,
         message: Self::OutboundOpenInfo,
     ) {
+        let (protocol, version) = protocol;
+        self.on_negotiated(version);
         self.substreams
             .push(SubstreamState::PendingSend(protocol, message))
     }
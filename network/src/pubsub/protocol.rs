@@ -20,37 +20,60 @@
 
 use crate::pubsub::proto::pubsub_proto as rpc_proto;
 use crate::pubsub::topic::TopicHash;
+use crate::utils::{compression, offered_protocols, ProtocolId, ProtocolVersion};
 
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use futures::future;
 use libp2p::core::{upgrade::Negotiated, InboundUpgrade, OutboundUpgrade, PeerId, UpgradeInfo};
 use protobuf::Message as ProtobufMessage;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::{io, iter};
+use std::{io, vec};
 use tokio::codec::{Decoder, Encoder, Framed};
 use tokio::io::{AsyncRead, AsyncWrite};
 use unsigned_varint::codec;
 
+/// The first version of the floodsub protocol.
+pub const PUBSUB_VERSION_1: ProtocolVersion = ProtocolVersion(1);
+/// Supported versions of the floodsub protocol.
+const PROTOCOLS: &[ProtocolId] = &[
+    ProtocolId {
+        name: b"/floodsub/1.0.0/snappy",
+        version: PUBSUB_VERSION_1,
+        compression: true,
+    },
+    ProtocolId {
+        name: b"/floodsub/1.0.0",
+        version: PUBSUB_VERSION_1,
+        compression: false,
+    },
+];
+/// Maximal size of a message, the default limit of `UviBytes`.
+/// See `compression::frame_len()`.
+const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
 /// Implementation of `ConnectionUpgrade` for the floodsub protocol.
 #[derive(Debug, Clone)]
-pub struct FloodsubConfig {}
+pub struct FloodsubConfig {
+    /// Offer snappy compression during protocol negotiation.
+    compression: bool,
+}
 
 impl FloodsubConfig {
     /// Builds a new `FloodsubConfig`.
     #[inline]
-    pub fn new() -> FloodsubConfig {
-        FloodsubConfig {}
+    pub fn new(compression: bool) -> FloodsubConfig {
+        FloodsubConfig { compression }
     }
 }
 
 impl UpgradeInfo for FloodsubConfig {
-    type Info = &'static [u8];
-    type InfoIter = iter::Once<Self::Info>;
+    type Info = ProtocolId;
+    type InfoIter = vec::IntoIter<Self::Info>;
 
     #[inline]
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(b"/floodsub/1.0.0")
+        offered_protocols(PROTOCOLS, self.compression).into_iter()
     }
 }
 
@@ -58,18 +81,13 @@ impl<TSocket> InboundUpgrade<TSocket> for FloodsubConfig
 where
     TSocket: AsyncRead + AsyncWrite,
 {
-    type Output = Framed<Negotiated<TSocket>, FloodsubCodec>;
+    type Output = (Framed<Negotiated<TSocket>, FloodsubCodec>, ProtocolVersion);
     type Error = io::Error;
     type Future = future::FutureResult<Self::Output, Self::Error>;
 
     #[inline]
-    fn upgrade_inbound(self, socket: Negotiated<TSocket>, _: Self::Info) -> Self::Future {
-        future::ok(Framed::new(
-            socket,
-            FloodsubCodec {
-                length_prefix: Default::default(),
-            },
-        ))
+    fn upgrade_inbound(self, socket: Negotiated<TSocket>, info: Self::Info) -> Self::Future {
+        future::ok((Framed::new(socket, FloodsubCodec::new(info)), info.version))
     }
 }
 
@@ -77,18 +95,13 @@ impl<TSocket> OutboundUpgrade<TSocket> for FloodsubConfig
 where
     TSocket: AsyncRead + AsyncWrite,
 {
-    type Output = Framed<Negotiated<TSocket>, FloodsubCodec>;
+    type Output = (Framed<Negotiated<TSocket>, FloodsubCodec>, ProtocolVersion);
     type Error = io::Error;
     type Future = future::FutureResult<Self::Output, Self::Error>;
 
     #[inline]
-    fn upgrade_outbound(self, socket: Negotiated<TSocket>, _: Self::Info) -> Self::Future {
-        future::ok(Framed::new(
-            socket,
-            FloodsubCodec {
-                length_prefix: Default::default(),
-            },
-        ))
+    fn upgrade_outbound(self, socket: Negotiated<TSocket>, info: Self::Info) -> Self::Future {
+        future::ok((Framed::new(socket, FloodsubCodec::new(info)), info.version))
     }
 }
 
@@ -96,6 +109,20 @@ where
 pub struct FloodsubCodec {
     /// The codec for encoding/decoding the length prefix of messages.
     length_prefix: codec::UviBytes,
+    /// True if frames are compressed with snappy.
+    compression: bool,
+}
+
+impl FloodsubCodec {
+    /// Creates a codec for the negotiated protocol.
+    fn new(protocol: ProtocolId) -> FloodsubCodec {
+        let mut length_prefix = codec::UviBytes::default();
+        length_prefix.set_max_len(compression::frame_len(MAX_FRAME_SIZE, protocol.compression));
+        FloodsubCodec {
+            length_prefix,
+            compression: protocol.compression,
+        }
+    }
 }
 
 impl Encoder for FloodsubCodec {
@@ -127,6 +154,14 @@ impl Encoder for FloodsubCodec {
             proto.mut_subscriptions().push(subscription);
         }
 
+        if self.compression {
+            let data = proto
+                .write_to_bytes()
+                .expect("there is no situation in which the protobuf message can be invalid");
+            let data = compression::compress("floodsub", &data)?;
+            return self.length_prefix.encode(Bytes::from(data), dst);
+        }
+
         let msg_size = proto.compute_size();
         // Reserve enough space for the data and the length. The length has a maximum of 32 bits,
         // which means that 5 bytes is enough for the variable-length integer.
@@ -152,7 +187,12 @@ impl Decoder for FloodsubCodec {
             None => return Ok(None),
         };
 
-        let mut rpc: rpc_proto::RPC = protobuf::parse_from_bytes(&packet)?;
+        let mut rpc: rpc_proto::RPC = if self.compression {
+            let packet = compression::decompress("floodsub", &packet, MAX_FRAME_SIZE)?;
+            protobuf::parse_from_bytes(&packet)?
+        } else {
+            protobuf::parse_from_bytes(&packet)?
+        };
 
         let mut messages = Vec::with_capacity(rpc.get_publish().len());
         for mut publish in rpc.take_publish().into_iter() {
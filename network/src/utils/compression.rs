@@ -0,0 +1,106 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Snappy compression for protocol frames.
+
+use super::metrics::{RATIO, RAW_BYTES, WIRE_BYTES};
+use std::io;
+
+fn update_metrics(protocol: &str, raw: usize, wire: usize) {
+    let raw_bytes = RAW_BYTES.with_label_values(&[protocol]);
+    let wire_bytes = WIRE_BYTES.with_label_values(&[protocol]);
+    raw_bytes.inc_by(raw as i64);
+    wire_bytes.inc_by(wire as i64);
+    if wire_bytes.get() > 0 {
+        let ratio = raw_bytes.get() as f64 / wire_bytes.get() as f64;
+        RATIO.with_label_values(&[protocol]).set(ratio);
+    }
+}
+
+/// Returns the limit of frames on the wire for messages of up to `max_len` bytes.
+///
+/// Compressed frames are decompressed up to `max_len` bytes,
+/// so compressed and plain variants of a protocol carry the same messages.
+pub fn frame_len(max_len: usize, compression: bool) -> usize {
+    if compression {
+        snap::max_compress_len(max_len)
+    } else {
+        max_len
+    }
+}
+
+/// Compress a frame.
+pub fn compress(protocol: &str, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let compressed = snap::Encoder::new()
+        .compress_vec(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    update_metrics(protocol, data.len(), compressed.len());
+    Ok(compressed)
+}
+
+/// Decompress a frame, refusing to inflate it beyond `max_len` bytes.
+pub fn decompress(protocol: &str, data: &[u8], max_len: usize) -> Result<Vec<u8>, io::Error> {
+    let len = snap::decompress_len(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "decompressed frame is too big: len={}, max={}",
+                len, max_len
+            ),
+        ));
+    }
+    let decompressed = snap::Decoder::new()
+        .decompress_vec(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    update_metrics(protocol, decompressed.len(), data.len());
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data = vec![42u8; 10000];
+        let compressed = compress("test", &data).unwrap();
+        assert!(compressed.len() < data.len());
+        let decompressed = decompress("test", &compressed, data.len()).unwrap();
+        assert_eq!(decompressed, data);
+        assert!(decompress("test", &compressed, data.len() - 1).is_err());
+        assert!(decompress("test", b"garbage", data.len()).is_err());
+    }
+
+    #[test]
+    fn incompressible() {
+        // Random data fits into the frame limit after compression.
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let compressed = compress("test", &data).unwrap();
+        assert!(compressed.len() > data.len());
+        assert!(compressed.len() <= frame_len(data.len(), true));
+        assert_eq!(frame_len(data.len(), false), data.len());
+        assert_eq!(decompress("test", &compressed, data.len()).unwrap(), data);
+    }
+}
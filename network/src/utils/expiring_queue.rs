@@ -54,6 +54,10 @@ where
         self.entries.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
     pub fn insert(&mut self, key: K, value: V) {
         if let Some((cache_key, _)) = self.entries.remove(&key) {
             self.expirations.remove(&cache_key);
@@ -71,6 +75,10 @@ where
         self.entries.contains_key(key)
     }
 
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.entries.get_mut(key).map(|(_, v)| v)
+    }
+
     pub fn reset(&mut self, key: &K, timeout: Duration) {
         if let Some((queue_key, _)) = self.entries.get(key) {
             self.expirations.reset(queue_key, timeout);
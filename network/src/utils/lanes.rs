@@ -0,0 +1,257 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Priority lanes for outbound traffic.
+//!
+//! Outbound messages are queued per lane and drained with deficit round robin:
+//! every round visits the lanes in the order of priority and lets each one send
+//! up to its share of the round, so bulk traffic can't starve consensus while
+//! lower lanes still make progress under congestion.
+
+use lazy_static::lazy_static;
+use prometheus::*;
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+
+lazy_static! {
+    static ref QUEUE_DEPTH: IntGaugeVec = register_int_gauge_vec!(
+        "stegos_network_outbound_queue_depth",
+        "Number of outbound messages waiting in the lane.",
+        &["lane"]
+    )
+    .unwrap();
+    static ref QUEUE_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "stegos_network_outbound_queue_bytes",
+        "Size of outbound messages waiting in the lane.",
+        &["lane"]
+    )
+    .unwrap();
+    static ref SENT_BYTES: IntCounterVec = register_int_counter_vec!(
+        "stegos_network_outbound_sent_bytes",
+        "Size of outbound messages dispatched from the lane.",
+        &["lane"]
+    )
+    .unwrap();
+}
+
+/// Class of outbound traffic, in the order of decreasing priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lane {
+    /// Consensus messages, view changes, sealed blocks.
+    Consensus,
+    /// Transaction gossip.
+    Transactions,
+    /// Chain loader requests and responses.
+    BlockSync,
+    /// DHT and connection maintenance.
+    Maintenance,
+}
+
+const NUM_LANES: usize = 4;
+
+impl Lane {
+    /// All lanes, in the order of priority.
+    pub const ALL: [Lane; NUM_LANES] = [
+        Lane::Consensus,
+        Lane::Transactions,
+        Lane::BlockSync,
+        Lane::Maintenance,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            Lane::Consensus => 0,
+            Lane::Transactions => 1,
+            Lane::BlockSync => 2,
+            Lane::Maintenance => 3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Lane::Consensus => "consensus",
+            Lane::Transactions => "transactions",
+            Lane::BlockSync => "block_sync",
+            Lane::Maintenance => "maintenance",
+        }
+    }
+}
+
+impl fmt::Display for Lane {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Outbound messages queued by lanes.
+pub struct LaneQueue<T> {
+    queues: [VecDeque<(usize, T)>; NUM_LANES],
+    /// Bytes added to the lane's deficit on every round.
+    quantum: [usize; NUM_LANES],
+    /// Bytes the lane is allowed to send in the current round.
+    deficit: [usize; NUM_LANES],
+    /// The lane being served.
+    current: usize,
+    /// The current lane has already got its quantum for this round.
+    topped_up: bool,
+}
+
+impl<T> LaneQueue<T> {
+    /// Creates a queue which splits `round_size` bytes between lanes proportionally to `shares`,
+    /// given in the order of `Lane::ALL`.
+    pub fn new(shares: [u32; NUM_LANES], round_size: usize) -> Self {
+        let total = cmp::max(shares.iter().map(|s| *s as u64).sum::<u64>(), 1);
+        let mut quantum = [0usize; NUM_LANES];
+        for (q, share) in quantum.iter_mut().zip(shares.iter()) {
+            // Every lane sends at least something, otherwise it would be starved.
+            *q = cmp::max((round_size as u64 * *share as u64 / total) as usize, 1);
+        }
+        LaneQueue {
+            queues: Default::default(),
+            quantum,
+            deficit: [0; NUM_LANES],
+            current: 0,
+            topped_up: false,
+        }
+    }
+
+    /// Queues a message of `size` bytes.
+    pub fn push(&mut self, lane: Lane, size: usize, item: T) {
+        self.queues[lane.index()].push_back((size, item));
+        QUEUE_DEPTH.with_label_values(&[lane.name()]).inc();
+        QUEUE_BYTES
+            .with_label_values(&[lane.name()])
+            .add(size as i64);
+    }
+
+    /// Takes the next message to send.
+    pub fn pop(&mut self) -> Option<(Lane, usize, T)> {
+        if self.is_empty() {
+            return None;
+        }
+        loop {
+            let i = self.current;
+            match self.queues[i].front() {
+                Some((size, _)) if *size <= self.deficit[i] => {
+                    let (size, item) = self.queues[i].pop_front().unwrap();
+                    self.deficit[i] -= size;
+                    let lane = Lane::ALL[i];
+                    QUEUE_DEPTH.with_label_values(&[lane.name()]).dec();
+                    QUEUE_BYTES
+                        .with_label_values(&[lane.name()])
+                        .sub(size as i64);
+                    SENT_BYTES
+                        .with_label_values(&[lane.name()])
+                        .inc_by(size as i64);
+                    return Some((lane, size, item));
+                }
+                Some(_) if !self.topped_up => {
+                    self.deficit[i] += self.quantum[i];
+                    self.topped_up = true;
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    // Idle lanes don't accumulate credit.
+                    self.deficit[i] = 0;
+                }
+            }
+            self.current = (i + 1) % NUM_LANES;
+            self.topped_up = false;
+        }
+    }
+
+    /// Number of messages waiting in the lane.
+    pub fn len(&self, lane: Lane) -> usize {
+        self.queues[lane.index()].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|q| q.is_empty())
+    }
+}
+
+impl<T> Drop for LaneQueue<T> {
+    fn drop(&mut self) {
+        for lane in Lane::ALL.iter() {
+            let queue = &self.queues[lane.index()];
+            let bytes: usize = queue.iter().map(|(size, _)| size).sum();
+            QUEUE_DEPTH
+                .with_label_values(&[lane.name()])
+                .sub(queue.len() as i64);
+            QUEUE_BYTES
+                .with_label_values(&[lane.name()])
+                .sub(bytes as i64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &mut LaneQueue<u32>, count: usize) -> Vec<Lane> {
+        (0..count).map(|_| queue.pop().unwrap().0).collect()
+    }
+
+    #[test]
+    fn priority_order() {
+        let mut queue = LaneQueue::new([1, 1, 1, 1], 400);
+        queue.push(Lane::Maintenance, 10, 0);
+        queue.push(Lane::BlockSync, 10, 0);
+        queue.push(Lane::Transactions, 10, 0);
+        queue.push(Lane::Consensus, 10, 0);
+        assert_eq!(drain(&mut queue, 4), Lane::ALL.to_vec());
+        assert!(queue.pop().is_none());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn shares() {
+        let mut queue = LaneQueue::new([3, 1, 0, 0], 400);
+        for i in 0..100 {
+            queue.push(Lane::Consensus, 100, i);
+            queue.push(Lane::Transactions, 100, i);
+        }
+        let lanes = drain(&mut queue, 40);
+        let consensus = lanes.iter().filter(|l| **l == Lane::Consensus).count();
+        assert_eq!(consensus, 30);
+        assert_eq!(queue.len(Lane::Consensus), 70);
+        assert_eq!(queue.len(Lane::Transactions), 90);
+    }
+
+    #[test]
+    fn no_starvation() {
+        // The block doesn't fit into a single round of the lane with the lowest share.
+        let mut queue = LaneQueue::new([99, 0, 1, 0], 1000);
+        queue.push(Lane::BlockSync, 100, 0);
+        for i in 0..1000 {
+            queue.push(Lane::Consensus, 10, i);
+        }
+        let lanes = drain(&mut queue, 1001);
+        let position = lanes.iter().position(|l| *l == Lane::BlockSync).unwrap();
+        assert!(position < 1000, "block sync is starved");
+        assert!(queue.is_empty());
+    }
+}
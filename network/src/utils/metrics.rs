@@ -0,0 +1,44 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use lazy_static::lazy_static;
+use prometheus::*;
+
+lazy_static! {
+    pub static ref RAW_BYTES: IntCounterVec = register_int_counter_vec!(
+        "stegos_network_compression_raw_bytes",
+        "Size of frames before compression (or after decompression).",
+        &["protocol"]
+    )
+    .unwrap();
+    pub static ref WIRE_BYTES: IntCounterVec = register_int_counter_vec!(
+        "stegos_network_compression_wire_bytes",
+        "Size of compressed frames sent or received.",
+        &["protocol"]
+    )
+    .unwrap();
+    pub static ref RATIO: GaugeVec = register_gauge_vec!(
+        "stegos_network_compression_ratio",
+        "Overall compression ratio (raw bytes / wire bytes).",
+        &["protocol"]
+    )
+    .unwrap();
+}
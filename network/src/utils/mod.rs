@@ -21,12 +21,18 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod compression;
 mod expiring_queue;
+mod lanes;
 mod lru_bimap;
+mod metrics;
 mod multihash;
 mod peer_id_key;
+mod protocol;
 
 pub use self::expiring_queue::ExpiringQueue;
+pub use self::lanes::{Lane, LaneQueue};
 pub use self::lru_bimap::LruBimap;
 pub use self::multihash::IntoMultihash;
 pub use self::peer_id_key::PeerIdKey;
+pub use self::protocol::{offered_protocols, ProtocolId, ProtocolVersion};
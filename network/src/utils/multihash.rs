@@ -23,6 +23,7 @@
 
 use libp2p::core::PeerId;
 use libp2p::multihash::{encode, Hash::SHA3512, Multihash};
+use stegos_crypto::curve1174;
 use stegos_crypto::pbc;
 
 pub trait IntoMultihash {
@@ -35,6 +36,12 @@ impl IntoMultihash for pbc::PublicKey {
     }
 }
 
+impl IntoMultihash for curve1174::PublicKey {
+    fn into_multihash(self) -> Multihash {
+        encode(SHA3512, &self.to_bytes()).expect("should never fail")
+    }
+}
+
 impl IntoMultihash for PeerId {
     fn into_multihash(self) -> Multihash {
         std::convert::Into::into(self)
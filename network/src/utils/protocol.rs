@@ -0,0 +1,112 @@
+//
+// MIT License
+//
+// Copyright (c) 2018-2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde_derive::Serialize;
+use std::fmt;
+
+/// Version of a wire protocol, negotiated per substream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct ProtocolVersion(pub u16);
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// Protocol name offered during substream negotiation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProtocolId {
+    /// Name on the wire, e.g. `/stegos/kad/1.0.0/snappy`.
+    pub name: &'static [u8],
+    /// Version of messages exchanged over the substream.
+    pub version: ProtocolVersion,
+    /// True if frames are compressed with snappy.
+    pub compression: bool,
+}
+
+impl AsRef<[u8]> for ProtocolId {
+    fn as_ref(&self) -> &[u8] {
+        self.name
+    }
+}
+
+///
+/// Returns protocols to offer during negotiation.
+///
+/// The dialer proposes protocols in order and the listener accepts the first one it knows,
+/// so the highest version goes first and the compressed variant precedes the plain one.
+///
+pub fn offered_protocols(supported: &[ProtocolId], compression: bool) -> Vec<ProtocolId> {
+    let mut protocols: Vec<ProtocolId> = supported
+        .iter()
+        .filter(|p| compression || !p.compression)
+        .cloned()
+        .collect();
+    protocols.sort_by(|a, b| {
+        b.version
+            .cmp(&a.version)
+            .then(b.compression.cmp(&a.compression))
+    });
+    protocols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROTOCOLS: &[ProtocolId] = &[
+        ProtocolId {
+            name: b"/test/1.0.0",
+            version: ProtocolVersion(1),
+            compression: false,
+        },
+        ProtocolId {
+            name: b"/test/1.0.0/snappy",
+            version: ProtocolVersion(1),
+            compression: true,
+        },
+        ProtocolId {
+            name: b"/test/2.0.0",
+            version: ProtocolVersion(2),
+            compression: false,
+        },
+    ];
+
+    #[test]
+    fn preference_order() {
+        let names: Vec<&[u8]> = offered_protocols(PROTOCOLS, true)
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        let expected: Vec<&[u8]> = vec![b"/test/2.0.0", b"/test/1.0.0/snappy", b"/test/1.0.0"];
+        assert_eq!(names, expected);
+
+        let names: Vec<&[u8]> = offered_protocols(PROTOCOLS, false)
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        let expected: Vec<&[u8]> = vec![b"/test/2.0.0", b"/test/1.0.0"];
+        assert_eq!(names, expected);
+    }
+}
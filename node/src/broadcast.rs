@@ -0,0 +1,312 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Broadcasting of local transactions.
+//!
+//! Transactions submitted via the API are sent directly to a random subset of validators
+//! in addition to the gossip topic. Unconfirmed transactions are re-broadcast every few
+//! blocks until they are committed or a conflicting transaction is observed.
+//!
+
+use futures::sync::oneshot;
+use futures::{Async, Future};
+use log::*;
+use rand::seq::SliceRandom;
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use stegos_blockchain::Transaction;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+use stegos_network::{DirectDelivery, Network};
+
+/// Unicast protocol used to send transactions to validators.
+pub const TX_BROADCAST_TOPIC: &'static str = "tx_broadcast";
+
+/// Sent when a conflicting transaction spending the same inputs is observed.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionConflict {
+    /// The local transaction.
+    pub tx_hash: Hash,
+    /// The transaction which spends the same input.
+    pub conflicting_tx_hash: Hash,
+    /// The input spent by both transactions.
+    pub input: Hash,
+    /// True if the conflicting transaction has been committed.
+    pub committed: bool,
+}
+
+struct PendingTransaction {
+    /// Inputs of the transaction.
+    txins: Vec<Hash>,
+    /// Serialized transaction.
+    data: Vec<u8>,
+    /// Height of the last broadcast.
+    last_broadcast: u64,
+    /// Validators which have received the transaction.
+    acked: HashSet<pbc::PublicKey>,
+    /// Pending deliveries.
+    deliveries: Vec<(pbc::PublicKey, oneshot::Receiver<DirectDelivery>)>,
+}
+
+/// Tracks local transactions until they are committed.
+pub(crate) struct TxBroadcaster {
+    /// The number of validators to send a transaction to.
+    fanout: usize,
+    /// Re-broadcast unconfirmed transactions every N blocks.
+    rebroadcast_blocks: u64,
+    /// Unconfirmed local transactions.
+    pending: HashMap<Hash, PendingTransaction>,
+    /// Inputs of unconfirmed local transactions.
+    inputs: HashMap<Hash, Hash>,
+}
+
+impl TxBroadcaster {
+    pub fn new(fanout: usize, rebroadcast_blocks: u64) -> Self {
+        TxBroadcaster {
+            fanout,
+            rebroadcast_blocks,
+            pending: HashMap::new(),
+            inputs: HashMap::new(),
+        }
+    }
+
+    /// Start tracking a local transaction.
+    /// Returns false if the transaction is already tracked.
+    pub fn track(&mut self, tx_hash: Hash, tx: &Transaction, data: Vec<u8>, height: u64) -> bool {
+        if self.pending.contains_key(&tx_hash) {
+            return false;
+        }
+        let txins = tx.txins().to_vec();
+        for input in &txins {
+            self.inputs.insert(*input, tx_hash);
+        }
+        let pending = PendingTransaction {
+            txins,
+            data,
+            last_broadcast: height,
+            acked: HashSet::new(),
+            deliveries: Vec::new(),
+        };
+        self.pending.insert(tx_hash, pending);
+        true
+    }
+
+    /// Send the transaction to a random subset of validators which haven't received it yet.
+    pub fn broadcast(
+        &mut self,
+        network: &Network,
+        tx_hash: &Hash,
+        validators: &[pbc::PublicKey],
+        height: u64,
+    ) {
+        let pending = match self.pending.get_mut(tx_hash) {
+            Some(pending) => pending,
+            None => return,
+        };
+        pending.last_broadcast = height;
+        let candidates: Vec<pbc::PublicKey> = validators
+            .iter()
+            .filter(|pkey| !pending.acked.contains(pkey))
+            .cloned()
+            .collect();
+        let peers = candidates.choose_multiple(&mut rand::thread_rng(), self.fanout);
+        for peer in peers {
+            match network.send_direct(*peer, TX_BROADCAST_TOPIC, pending.data.clone()) {
+                Ok(rx) => pending.deliveries.push((*peer, rx)),
+                Err(e) => error!("Failed to send transaction: tx={}, error={}", tx_hash, e),
+            }
+        }
+        debug!(
+            "Broadcast transaction: tx={}, height={}, acked={}, sent={}",
+            tx_hash,
+            height,
+            pending.acked.len(),
+            pending.deliveries.len()
+        );
+    }
+
+    /// Returns the list of transactions which should be re-broadcast at this height.
+    pub fn due(&self, height: u64) -> Vec<Hash> {
+        self.pending
+            .iter()
+            .filter(|(_, p)| height >= p.last_broadcast + self.rebroadcast_blocks)
+            .map(|(tx_hash, _)| *tx_hash)
+            .collect()
+    }
+
+    /// Serialized transaction, used for re-broadcasting to the gossip topic.
+    pub fn data(&self, tx_hash: &Hash) -> Option<&Vec<u8>> {
+        self.pending.get(tx_hash).map(|p| &p.data)
+    }
+
+    /// Check a transaction received from the network against local ones.
+    pub fn on_transaction(&self, tx_hash: &Hash, tx: &Transaction) -> Vec<TransactionConflict> {
+        let mut conflicts = Vec::new();
+        for input in tx.txins() {
+            match self.inputs.get(input) {
+                Some(local_tx_hash) if local_tx_hash != tx_hash => {
+                    warn!(
+                        "Observed a conflicting transaction: tx={}, conflicting_tx={}, input={}",
+                        local_tx_hash, tx_hash, input
+                    );
+                    conflicts.push(TransactionConflict {
+                        tx_hash: *local_tx_hash,
+                        conflicting_tx_hash: *tx_hash,
+                        input: *input,
+                        committed: false,
+                    });
+                }
+                _ => {}
+            }
+        }
+        conflicts
+    }
+
+    /// Forget committed transactions and report conflicting ones.
+    /// `tx_hashes` maps hashes of spent inputs to hashes of spending transactions.
+    pub fn on_block_added(
+        &mut self,
+        input_hashes: &[Hash],
+        tx_hashes: &HashMap<Hash, Hash>,
+    ) -> Vec<TransactionConflict> {
+        let mut conflicts = Vec::new();
+        for input in input_hashes {
+            let local_tx_hash = match self.inputs.get(input) {
+                Some(tx_hash) => *tx_hash,
+                None => continue,
+            };
+            match tx_hashes.get(input) {
+                Some(tx_hash) if *tx_hash == local_tx_hash => {
+                    debug!("Transaction committed: tx={}", local_tx_hash);
+                }
+                spent_by => {
+                    // Macro blocks don't carry transaction hashes.
+                    let conflicting_tx_hash = spent_by.cloned().unwrap_or(Hash::zero());
+                    warn!(
+                        "Transaction conflicts with a committed one: tx={}, conflicting_tx={}, input={}",
+                        local_tx_hash, conflicting_tx_hash, input
+                    );
+                    conflicts.push(TransactionConflict {
+                        tx_hash: local_tx_hash,
+                        conflicting_tx_hash,
+                        input: *input,
+                        committed: true,
+                    });
+                }
+            }
+            self.forget(&local_tx_hash);
+        }
+        conflicts
+    }
+
+    /// Returns the number of validators which have received the transaction.
+    pub fn acked(&self, tx_hash: &Hash) -> Option<usize> {
+        self.pending.get(tx_hash).map(|p| p.acked.len())
+    }
+
+    /// Process delivery reports.
+    pub fn poll(&mut self) {
+        for (tx_hash, pending) in self.pending.iter_mut() {
+            let deliveries = std::mem::replace(&mut pending.deliveries, Vec::new());
+            for (peer, mut rx) in deliveries {
+                match rx.poll() {
+                    Ok(Async::Ready(DirectDelivery::Delivered)) => {
+                        trace!("Transaction delivered: tx={}, peer={}", tx_hash, peer);
+                        pending.acked.insert(peer);
+                    }
+                    Ok(Async::Ready(DirectDelivery::Failed)) | Err(_) => {
+                        debug!(
+                            "Failed to deliver transaction: tx={}, peer={}",
+                            tx_hash, peer
+                        );
+                    }
+                    Ok(Async::NotReady) => pending.deliveries.push((peer, rx)),
+                }
+            }
+        }
+    }
+
+    fn forget(&mut self, tx_hash: &Hash) {
+        if let Some(pending) = self.pending.remove(tx_hash) {
+            for input in pending.txins {
+                self.inputs.remove(&input);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stegos_blockchain::PaymentTransaction;
+    use stegos_crypto::curve1174::make_random_keys;
+
+    #[test]
+    fn conflicts() {
+        let chain_id = Hash::digest("test");
+        let (skey, pkey) = make_random_keys();
+        let mut broadcaster = TxBroadcaster::new(4, 5);
+
+        let (tx1, _inputs1, _outputs1) =
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 100, 2, 200, 1, 0)
+                .expect("transaction valid");
+        let tx1: Transaction = tx1.into();
+        let tx_hash1 = Hash::digest(&tx1);
+        assert!(broadcaster.track(tx_hash1, &tx1, Vec::new(), 10));
+        assert!(!broadcaster.track(tx_hash1, &tx1, Vec::new(), 10));
+        assert!(broadcaster.on_transaction(&tx_hash1, &tx1).is_empty());
+
+        // Re-broadcast.
+        assert!(broadcaster.due(14).is_empty());
+        assert_eq!(broadcaster.due(15), vec![tx_hash1]);
+
+        // A transaction spending the same input.
+        let (tx2, _inputs2, _outputs2) =
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 300, 1, 100, 3, 0)
+                .expect("transaction valid");
+        let tx2: Transaction = tx2.into();
+        let tx_hash2 = Hash::digest(&tx2);
+        let input = tx1.txins()[0];
+        let mut tx_hashes = HashMap::new();
+        tx_hashes.insert(input, tx_hash2);
+        let conflicts = broadcaster.on_block_added(&[input], &tx_hashes);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].tx_hash, tx_hash1);
+        assert_eq!(conflicts[0].conflicting_tx_hash, tx_hash2);
+        assert_eq!(conflicts[0].input, input);
+        assert!(conflicts[0].committed);
+        assert!(broadcaster.acked(&tx_hash1).is_none());
+        assert!(broadcaster.due(100).is_empty());
+
+        // Committed.
+        assert!(broadcaster.track(tx_hash2, &tx2, Vec::new(), 10));
+        let input_hashes = tx2.txins().to_vec();
+        let mut tx_hashes = HashMap::new();
+        for input in &input_hashes {
+            tx_hashes.insert(*input, tx_hash2);
+        }
+        assert!(broadcaster
+            .on_block_added(&input_hashes, &tx_hashes)
+            .is_empty());
+        assert!(broadcaster.acked(&tx_hash2).is_none());
+    }
+}
@@ -23,12 +23,50 @@
 
 use serde_derive::{Deserialize, Serialize};
 use std::time::Duration;
-use stegos_blockchain::BlockchainConfig;
+use stegos_blockchain::{BlockchainConfig, Checkpoint, EmissionConfig};
+use stegos_consensus::SignerConfig;
+use stegos_crypto::pbc;
+
+/// Subsystems run by the node.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeRole {
+    /// Full chain and consensus, if elected.
+    Validator,
+    /// Full chain without consensus.
+    Full,
+    /// Block headers and wallet scanning, blocks are downloaded from `remote_nodes`.
+    Light,
+}
+
+impl NodeRole {
+    /// Returns true if the node participates in consensus when elected.
+    pub fn runs_consensus(&self) -> bool {
+        *self == NodeRole::Validator
+    }
+
+    /// Returns true if the node keeps and validates the full chain.
+    pub fn keeps_full_chain(&self) -> bool {
+        *self != NodeRole::Light
+    }
+}
+
+impl Default for NodeRole {
+    fn default() -> Self {
+        NodeRole::Validator
+    }
+}
 
 /// Chain configuration.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct ChainConfig {
+    /// Subsystems run by the node.
+    pub role: NodeRole,
+    /// Trusted full nodes to download blocks from, light role only.
+    pub remote_nodes: Vec<pbc::PublicKey>,
+    /// Name of the chain, blocks of other chains are rejected.
+    pub chain_id: String,
     /// How long wait for transactions before starting to create a new block.
     pub tx_wait_timeout: Duration,
     /// How long wait for micro blocks.
@@ -47,8 +85,8 @@ pub struct ChainConfig {
     pub max_utxo_in_mempool: usize,
     /// Loader will send maximum N epoch at time.
     pub chain_loader_speed_in_epoch: u64,
-    /// Fixed reward per block.
-    pub block_reward: i64,
+    /// Emission schedule of block rewards.
+    pub emission: EmissionConfig,
     /// Fixed fee for payment transactions.
     pub payment_fee: i64,
     /// Fixed fee for the stake transactions.
@@ -61,6 +99,70 @@ pub struct ChainConfig {
     pub min_stake_amount: i64,
     /// Minimal interval between loader runs.
     pub loader_timeout: Duration,
+    /// The number of blocks requested at once during sync.
+    pub loader_request_size: u64,
+    /// The maximal number of requests waiting for a response from one peer.
+    pub loader_in_flight_per_peer: usize,
+    /// Sync doesn't request blocks more than N blocks ahead of the last applied block.
+    pub loader_max_pending_blocks: u64,
+    /// How long wait for a response before requesting blocks from another peer.
+    pub loader_request_timeout: Duration,
+    /// The maximal number of blocks waiting for their parents.
+    pub max_orphan_blocks: usize,
+    /// How long keep blocks waiting for their parents.
+    pub orphan_block_timeout: Duration,
+    /// Maximal size of a serialized micro block in bytes.
+    pub max_block_size: usize,
+    /// Maximal size of a serialized transaction in bytes.
+    pub max_tx_size: usize,
+    /// Maximal number of inputs in a transaction.
+    pub max_inputs_per_tx: usize,
+    /// The number of shards of the transaction topic, see `BlockchainConfig::tx_topic_shards`.
+    pub tx_topic_shards: u32,
+    /// The number of transaction shards relayed by non-validators.
+    pub relay_tx_shards: u32,
+    /// Maximal difference between timestamps of consensus messages and network time.
+    pub message_timestamp_window: Duration,
+    /// The number of threads checking signatures of consensus messages, zero checks inline.
+    pub verifier_threads: usize,
+    /// Maintain secondary indexes for block explorers.
+    pub explorer_index: bool,
+    /// Trusted block hashes, blocks contradicting them are rejected.
+    pub checkpoints: Vec<Checkpoint>,
+    /// Skip verification of block signatures up to the last checkpoint.
+    pub assume_valid: bool,
+    /// Validators privately check their eligibility to create micro blocks using VRF.
+    /// Must be the same for all nodes in the network.
+    pub secret_leader_election: bool,
+    /// The expected number of eligible validators per view change in the secret leader election.
+    pub secret_leader_candidates: i64,
+    /// False positive rate of the in-memory filter of UTXO hashes, zero disables the filter.
+    pub utxo_filter_false_positive_rate: f64,
+    /// Maximal difference between a block timestamp and the local clock.
+    pub max_time_drift: Duration,
+    /// Countersign transactions admitted to mempool to provide fast confirmations.
+    pub fast_confirmation: bool,
+    /// The number of validators to send local transactions to.
+    pub tx_broadcast_fanout: usize,
+    /// Re-broadcast unconfirmed local transactions every N blocks.
+    pub tx_rebroadcast_blocks: u64,
+    /// Relay transactions along a random stem path before publishing them (Dandelion++).
+    pub dandelion: bool,
+    /// The probability to publish a stem transaction instead of relaying it further.
+    pub dandelion_fluff_probability: f64,
+    /// How long wait for a stem transaction to be published before publishing it.
+    pub dandelion_embargo: Duration,
+    /// Validate micro blocks bigger than this size without decoding all transactions at once.
+    pub stream_validation_threshold: usize,
+    /// How often validators publish the status of their chain, zero disables.
+    pub chain_status_interval: Duration,
+    /// Path to the file with the consensus state saved on shutdown, empty to disable.
+    pub consensus_state_file: String,
+    /// Enter the safe mode after N rounds without blocks if the supermajority of stake
+    /// is not reachable, zero disables. Requires chain statuses.
+    pub partition_missed_rounds: u32,
+    /// Sign blocks and consensus messages by a remote signer holding the network key.
+    pub signer: SignerConfig,
 }
 
 impl Default for ChainConfig {
@@ -72,6 +174,9 @@ impl Default for ChainConfig {
         let blockchain_default: BlockchainConfig = Default::default();
 
         ChainConfig {
+            role: NodeRole::default(),
+            remote_nodes: Vec::new(),
+            chain_id: blockchain_default.chain_id.clone(),
             tx_wait_timeout,
             micro_block_timeout,
             macro_block_timeout,
@@ -81,27 +186,72 @@ impl Default for ChainConfig {
             max_utxo_in_block: 1000,
             max_utxo_in_mempool: 10000,
             chain_loader_speed_in_epoch: 10,
-            block_reward: 40_000_000, // 40 STG
-            payment_fee: 1_000,       // 0.001 STG
-            stake_fee: 0,             // free
+            emission: blockchain_default.emission,
+            payment_fee: 1_000, // 0.001 STG
+            stake_fee: 0,       // free
             max_slot_count: blockchain_default.max_slot_count,
             min_stake_amount: blockchain_default.min_stake_amount,
             loader_timeout: Duration::from_millis(500),
+            loader_request_size: 50,
+            loader_in_flight_per_peer: 2,
+            loader_max_pending_blocks: 500,
+            loader_request_timeout: Duration::from_secs(10),
+            max_orphan_blocks: 100,
+            orphan_block_timeout: Duration::from_secs(300),
             awards_difficulty: 3,
+            max_block_size: blockchain_default.max_block_size,
+            max_tx_size: blockchain_default.max_tx_size,
+            max_inputs_per_tx: blockchain_default.max_inputs_per_tx,
+            tx_topic_shards: blockchain_default.tx_topic_shards,
+            relay_tx_shards: 1,
+            message_timestamp_window: Duration::from_secs(60),
+            verifier_threads: 2,
+            explorer_index: blockchain_default.explorer_index,
+            checkpoints: blockchain_default.checkpoints,
+            assume_valid: blockchain_default.assume_valid,
+            secret_leader_election: blockchain_default.secret_leader_election,
+            secret_leader_candidates: blockchain_default.secret_leader_candidates,
+            utxo_filter_false_positive_rate: blockchain_default.utxo_filter_false_positive_rate,
+            max_time_drift: blockchain_default.max_time_drift,
+            fast_confirmation: false,
+            tx_broadcast_fanout: 4,
+            tx_rebroadcast_blocks: 5,
+            dandelion: false,
+            dandelion_fluff_probability: 0.1,
+            dandelion_embargo: Duration::from_secs(30),
+            stream_validation_threshold: 512 * 1024, // 512 KB
+            chain_status_interval: Duration::from_secs(10),
+            consensus_state_file: "consensus.state".to_string(),
+            partition_missed_rounds: 3,
+            signer: SignerConfig::default(),
         }
     }
 }
 
 impl Into<BlockchainConfig> for ChainConfig {
     fn into(self) -> BlockchainConfig {
-        let service_award_per_epoch = self.block_reward / 2 * self.blocks_in_epoch as i64;
+        let service_award_per_epoch =
+            self.emission.initial_reward / 2 * self.blocks_in_epoch as i64;
         BlockchainConfig {
+            chain_id: self.chain_id,
             awards_difficulty: self.awards_difficulty,
             max_slot_count: self.max_slot_count,
             min_stake_amount: self.min_stake_amount,
             stake_epochs: self.stake_epochs,
-            block_reward: self.block_reward,
+            blocks_in_epoch: self.blocks_in_epoch,
+            emission: self.emission,
             service_award_per_epoch,
+            max_block_size: self.max_block_size,
+            max_tx_size: self.max_tx_size,
+            max_inputs_per_tx: self.max_inputs_per_tx,
+            tx_topic_shards: self.tx_topic_shards,
+            explorer_index: self.explorer_index,
+            checkpoints: self.checkpoints,
+            assume_valid: self.assume_valid,
+            secret_leader_election: self.secret_leader_election,
+            secret_leader_candidates: self.secret_leader_candidates,
+            utxo_filter_false_positive_rate: self.utxo_filter_false_positive_rate,
+            max_time_drift: self.max_time_drift,
         }
     }
 }
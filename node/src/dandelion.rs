@@ -0,0 +1,210 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Dandelion++ relay of transactions.
+//!
+//! Instead of publishing a local transaction to the gossip topic, the node sends it to
+//! a single stem relay. Every relay forwards the transaction to its own stem relay or,
+//! with the fluff probability, publishes it to the gossip topic. Stem relays are
+//! re-selected every epoch and every inbound peer is always routed to the same relay.
+//!
+//! Every node keeps stem transactions until the embargo expires. If the transaction
+//! hasn't been observed in the gossip topic by that time, the node publishes it itself,
+//! so a transaction dropped by a malicious relay still reaches the network.
+//!
+
+use log::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+/// Unicast protocol used to send transactions along the stem.
+pub const DANDELION_STEM_TOPIC: &'static str = "tx_stem";
+
+/// How often the embargo timers are checked.
+pub(crate) const DANDELION_TIMER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of stem relays chosen per epoch.
+const STEM_RELAYS: usize = 2;
+
+struct StemTransaction {
+    /// Serialized transaction.
+    data: Vec<u8>,
+    /// The transaction is published by this node after this moment.
+    embargo: Instant,
+}
+
+/// State of the stem phase.
+pub(crate) struct Dandelion {
+    /// The probability to publish an incoming stem transaction instead of relaying it.
+    fluff_probability: f64,
+    /// The minimal time to wait for the transaction to be published by others.
+    embargo: Duration,
+    /// Stem relays of the current epoch.
+    relays: Vec<pbc::PublicKey>,
+    /// Stem relays assigned to inbound peers.
+    routes: HashMap<pbc::PublicKey, pbc::PublicKey>,
+    /// Transactions in the stem phase.
+    stempool: HashMap<Hash, StemTransaction>,
+}
+
+impl Dandelion {
+    pub fn new(fluff_probability: f64, embargo: Duration) -> Self {
+        Dandelion {
+            fluff_probability: fluff_probability.max(0.0).min(1.0),
+            embargo,
+            relays: Vec::new(),
+            routes: HashMap::new(),
+            stempool: HashMap::new(),
+        }
+    }
+
+    /// Choose new stem relays among the candidates.
+    pub fn update_relays(&mut self, candidates: &[pbc::PublicKey]) {
+        self.relays = candidates
+            .choose_multiple(&mut rand::thread_rng(), STEM_RELAYS)
+            .cloned()
+            .collect();
+        self.routes.clear();
+        debug!("Updated stem relays: relays={:?}", self.relays);
+    }
+
+    /// Returns true if an incoming stem transaction should be published
+    /// to the gossip topic instead of being relayed further.
+    pub fn fluff(&self) -> bool {
+        rand::thread_rng().gen_bool(self.fluff_probability)
+    }
+
+    /// Returns the stem relay for a transaction received from the peer,
+    /// or from the local API if `from` is None.
+    pub fn route(&mut self, from: Option<&pbc::PublicKey>) -> Option<pbc::PublicKey> {
+        let from = match from {
+            Some(from) => from,
+            None => return self.relays.first().cloned(),
+        };
+        if let Some(relay) = self.routes.get(from) {
+            return Some(*relay);
+        }
+        let candidates: Vec<pbc::PublicKey> = self
+            .relays
+            .iter()
+            .filter(|relay| *relay != from)
+            .cloned()
+            .collect();
+        let relay = *candidates.choose(&mut rand::thread_rng())?;
+        self.routes.insert(*from, relay);
+        Some(relay)
+    }
+
+    /// Start the embargo timer for a transaction.
+    /// Returns false if the transaction is already in the stem phase.
+    pub fn stem(&mut self, tx_hash: Hash, data: Vec<u8>, now: Instant) -> bool {
+        if self.stempool.contains_key(&tx_hash) {
+            return false;
+        }
+        // Random delay makes the node which publishes the transaction unpredictable.
+        let jitter = rand::thread_rng().gen_range(0, self.embargo.as_millis() as u64 / 2 + 1);
+        let embargo = now + self.embargo + Duration::from_millis(jitter);
+        self.stempool
+            .insert(tx_hash, StemTransaction { data, embargo });
+        true
+    }
+
+    /// Returns true if the transaction is in the stem phase.
+    pub fn contains(&self, tx_hash: &Hash) -> bool {
+        self.stempool.contains_key(tx_hash)
+    }
+
+    /// Forget a transaction which has been published to the gossip topic.
+    pub fn on_fluff(&mut self, tx_hash: &Hash) {
+        if self.stempool.remove(tx_hash).is_some() {
+            trace!("Stem transaction has been published: tx={}", tx_hash);
+        }
+    }
+
+    /// Returns transactions with the expired embargo, which must be published by this node.
+    pub fn expired(&mut self, now: Instant) -> Vec<(Hash, Vec<u8>)> {
+        let expired: Vec<Hash> = self
+            .stempool
+            .iter()
+            .filter(|(_, stem)| stem.embargo <= now)
+            .map(|(tx_hash, _)| *tx_hash)
+            .collect();
+        expired
+            .into_iter()
+            .map(|tx_hash| {
+                let stem = self.stempool.remove(&tx_hash).unwrap();
+                (tx_hash, stem.data)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stem_and_fluff() {
+        let (_skey1, pkey1) = pbc::make_random_keys();
+        let (_skey2, pkey2) = pbc::make_random_keys();
+        let (_skey3, pkey3) = pbc::make_random_keys();
+
+        let mut dandelion = Dandelion::new(0.0, Duration::from_secs(10));
+        assert_eq!(dandelion.route(None), None);
+        assert_eq!(dandelion.route(Some(&pkey1)), None);
+
+        dandelion.update_relays(&[pkey1, pkey2]);
+        let local = dandelion.route(None).expect("relay");
+        assert!(local == pkey1 || local == pkey2);
+        // The same relay for the same inbound peer, never back to the sender.
+        let relay = dandelion.route(Some(&pkey3)).expect("relay");
+        for _ in 0..10 {
+            assert_eq!(dandelion.route(Some(&pkey3)), Some(relay));
+        }
+        assert_eq!(dandelion.route(Some(&pkey1)), Some(pkey2));
+        assert_eq!(dandelion.route(Some(&pkey2)), Some(pkey1));
+
+        assert!(!dandelion.fluff());
+        let dandelion = Dandelion::new(1.0, Duration::from_secs(10));
+        assert!(dandelion.fluff());
+        let mut dandelion = Dandelion::new(0.1, Duration::from_secs(10));
+
+        // Embargo.
+        let now = Instant::now();
+        let tx_hash1 = Hash::digest("tx1");
+        let tx_hash2 = Hash::digest("tx2");
+        assert!(dandelion.stem(tx_hash1, vec![1], now));
+        assert!(!dandelion.stem(tx_hash1, vec![1], now));
+        assert!(dandelion.stem(tx_hash2, vec![2], now));
+        assert!(dandelion.contains(&tx_hash1));
+        assert!(dandelion.expired(now + Duration::from_secs(9)).is_empty());
+        dandelion.on_fluff(&tx_hash2);
+        assert!(!dandelion.contains(&tx_hash2));
+        let expired = dandelion.expired(now + Duration::from_secs(16));
+        assert_eq!(expired, vec![(tx_hash1, vec![1])]);
+        assert!(!dandelion.contains(&tx_hash1));
+    }
+}
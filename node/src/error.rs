@@ -85,6 +85,8 @@ pub enum NodeBlockError {
         _0, _1, _2, _3
     )]
     OutdatedBlock(u64, Hash, SystemTime, SystemTime),
+    #[fail(display = "Block is too big: size={}, max_size={}", _0, _1)]
+    TooBigBlockBuffer(usize, usize),
 }
 
 #[derive(Debug, Fail)]
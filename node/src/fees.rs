@@ -0,0 +1,225 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Fee estimation.
+//!
+//! Transactions admitted to mempool are tracked until they are included into a block.
+//! Confirmation delays are collected per fee density bucket and decay with every block.
+//! Transactions still waiting in mempool longer than the target count as failures.
+//!
+
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use stegos_blockchain::Transaction;
+use stegos_crypto::hash::Hash;
+use stegos_serialization::traits::ProtoConvert;
+
+/// The maximal confirmation target, in blocks.
+pub const MAX_TARGET_BLOCKS: u64 = 32;
+/// Fee density of the first bucket, in tokens per KB.
+const MIN_BUCKET_DENSITY: f64 = 10.0;
+/// Ratio between fee densities of adjacent buckets.
+const BUCKET_SPACING: f64 = 1.5;
+/// The number of fee density buckets.
+const BUCKETS: usize = 40;
+/// Historical data is multiplied by this factor on every block.
+const DECAY: f64 = 0.995;
+/// The share of transactions which must be confirmed within the target.
+const SUCCESS_THRESHOLD: f64 = 0.85;
+/// The minimal weight of data points required for an estimate.
+const MIN_SAMPLES: f64 = 2.0;
+/// Stop tracking waiting transactions after this number of blocks.
+const MAX_TRACKED_BLOCKS: u64 = 4 * MAX_TARGET_BLOCKS;
+
+/// Fee suggestion for a confirmation target.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeEstimate {
+    /// The number of blocks the transaction is expected to be included within.
+    pub target_blocks: u64,
+    /// Fee per KB of a serialized transaction, None if there is not enough data.
+    pub fee_per_kb: Option<i64>,
+    /// The number of transactions waiting in mempool.
+    pub mempool_transactions: usize,
+}
+
+/// Returns the fee per KB of the serialized transaction.
+pub(crate) fn fee_density(tx: &Transaction) -> f64 {
+    let size = tx.into_buffer().expect("serialization never fails").len();
+    tx.fee() as f64 * 1024.0 / size.max(1) as f64
+}
+
+fn bucket_index(density: f64) -> usize {
+    if density < MIN_BUCKET_DENSITY {
+        return 0;
+    }
+    let index = (density / MIN_BUCKET_DENSITY).ln() / BUCKET_SPACING.ln();
+    (index as usize).min(BUCKETS - 1)
+}
+
+fn bucket_density(index: usize) -> f64 {
+    MIN_BUCKET_DENSITY * BUCKET_SPACING.powi(index as i32)
+}
+
+/// Confirmation statistics of one fee density bucket.
+#[derive(Clone)]
+struct Bucket {
+    /// Confirmed transactions.
+    total: f64,
+    /// Transactions confirmed within i + 1 blocks.
+    confirmed: Vec<f64>,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Bucket {
+            total: 0.0,
+            confirmed: vec![0.0; MAX_TARGET_BLOCKS as usize],
+        }
+    }
+
+    fn decay(&mut self) {
+        self.total *= DECAY;
+        for confirmed in self.confirmed.iter_mut() {
+            *confirmed *= DECAY;
+        }
+    }
+
+    fn add(&mut self, blocks: u64) {
+        self.total += 1.0;
+        for target in blocks.max(1)..=MAX_TARGET_BLOCKS {
+            self.confirmed[(target - 1) as usize] += 1.0;
+        }
+    }
+}
+
+/// Estimates fees from the confirmation times of recent transactions.
+pub(crate) struct FeeEstimator {
+    buckets: Vec<Bucket>,
+    /// Transactions waiting in mempool: tx_hash => (height at admission, bucket).
+    waiting: HashMap<Hash, (u64, usize)>,
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        FeeEstimator {
+            buckets: vec![Bucket::new(); BUCKETS],
+            waiting: HashMap::new(),
+        }
+    }
+
+    /// Called when a transaction is admitted to mempool.
+    pub fn on_transaction(&mut self, tx_hash: Hash, fee_density: f64, height: u64) {
+        self.waiting
+            .insert(tx_hash, (height, bucket_index(fee_density)));
+    }
+
+    /// Called when a micro block is applied.
+    pub fn on_block(&mut self, height: u64, tx_hashes: &[Hash]) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.decay();
+        }
+        for tx_hash in tx_hashes {
+            if let Some((admitted, index)) = self.waiting.remove(tx_hash) {
+                let blocks = height.saturating_sub(admitted) + 1;
+                self.buckets[index].add(blocks);
+            }
+        }
+        self.waiting
+            .retain(|_tx_hash, (admitted, _index)| *admitted + MAX_TRACKED_BLOCKS > height);
+    }
+
+    /// Returns the lowest fee density which had enough transactions
+    /// confirmed within `target_blocks` blocks.
+    pub fn estimate_fee(&self, target_blocks: u64, height: u64) -> FeeEstimate {
+        let target_blocks = target_blocks.max(1).min(MAX_TARGET_BLOCKS);
+
+        // Waiting transactions which have already missed the target.
+        let mut failed = vec![0.0; BUCKETS];
+        for (admitted, index) in self.waiting.values() {
+            if height.saturating_sub(*admitted) >= target_blocks {
+                failed[*index] += 1.0;
+            }
+        }
+
+        // Merge buckets from the most expensive one until there are enough samples.
+        let mut found: Option<usize> = None;
+        let mut total = 0.0;
+        let mut confirmed = 0.0;
+        for index in (0..BUCKETS).rev() {
+            let bucket = &self.buckets[index];
+            total += bucket.total + failed[index];
+            confirmed += bucket.confirmed[(target_blocks - 1) as usize];
+            if total < MIN_SAMPLES {
+                continue;
+            }
+            if confirmed / total < SUCCESS_THRESHOLD {
+                break;
+            }
+            found = Some(index);
+            total = 0.0;
+            confirmed = 0.0;
+        }
+
+        FeeEstimate {
+            target_blocks,
+            fee_per_kb: found.map(|index| bucket_density(index).ceil() as i64),
+            mempool_transactions: self.waiting.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_fee() {
+        let mut fees = FeeEstimator::new();
+        assert_eq!(fees.estimate_fee(1, 0).fee_per_kb, None);
+
+        // Expensive transactions are confirmed by the next block,
+        // cheap transactions wait for 10 blocks.
+        let mut cheap: Vec<Hash> = Vec::new();
+        for height in 0..100u64 {
+            let expensive = Hash::digest(&format!("expensive{}", height));
+            fees.on_transaction(expensive, 10_000.0, height);
+            let tx_hash = Hash::digest(&format!("cheap{}", height));
+            fees.on_transaction(tx_hash, 100.0, height);
+            cheap.push(tx_hash);
+            let mut included = vec![expensive];
+            if height >= 9 {
+                included.push(cheap[(height - 9) as usize]);
+            }
+            fees.on_block(height, &included);
+        }
+
+        let fast = fees.estimate_fee(1, 100).fee_per_kb.expect("enough data");
+        let slow = fees.estimate_fee(20, 100).fee_per_kb.expect("enough data");
+        assert!(fast > 100 && fast <= 10_000);
+        assert!(slow <= 100);
+        assert_eq!(fees.estimate_fee(0, 100).target_blocks, 1);
+        assert_eq!(
+            fees.estimate_fee(1000, 100).target_blocks,
+            MAX_TARGET_BLOCKS
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Fork choice between micro blocks at the same height.
+
+use stegos_crypto::hash::Hash;
+
+/// A micro block competing for a height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ForkCandidate {
+    /// Hash of the block.
+    pub hash: Hash,
+    /// VRF output of the block, which can't be ground by the leader.
+    pub random: Hash,
+    /// The number of view changes before the leader has created the block.
+    pub view_change: u32,
+    /// True if the block has view_change == 0 or a valid view change proof.
+    pub proven: bool,
+}
+
+/// The outcome of the fork choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ForkChoice {
+    /// Both candidates are the same block.
+    Same,
+    /// Keep the local block.
+    Local,
+    /// Switch to the remote block.
+    Remote,
+}
+
+///
+/// Choose between two micro blocks at the same height.
+///
+/// A block with a higher view_change and a valid proof wins.
+/// Blocks with the same view_change are ordered by VRF output and then by hash, the lowest wins.
+/// Different VRF outputs are possible only when the secret leader election
+/// allows several validators to create a block for the same view_change.
+/// The rule is symmetric, so all nodes converge to the same block
+/// regardless of the order of arrival.
+///
+pub(crate) fn choose_fork(local: &ForkCandidate, remote: &ForkCandidate) -> ForkChoice {
+    if local.hash == remote.hash {
+        return ForkChoice::Same;
+    }
+    match (local.proven, remote.proven) {
+        (true, false) => return ForkChoice::Local,
+        (false, true) => return ForkChoice::Remote,
+        // Keep the local block until a valid one arrives.
+        (false, false) => return ForkChoice::Local,
+        (true, true) => {}
+    }
+    if remote.view_change != local.view_change {
+        if remote.view_change > local.view_change {
+            return ForkChoice::Remote;
+        }
+        return ForkChoice::Local;
+    }
+    if (remote.random, remote.hash) < (local.random, local.hash) {
+        ForkChoice::Remote
+    } else {
+        ForkChoice::Local
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(hash: &str, view_change: u32, proven: bool) -> ForkCandidate {
+        ForkCandidate {
+            hash: Hash::digest(hash),
+            // The same leader produces the same VRF.
+            random: Hash::zero(),
+            view_change,
+            proven,
+        }
+    }
+
+    /// Returns (low, high) candidates with the given view_changes.
+    fn ordered(view_change1: u32, view_change2: u32) -> (ForkCandidate, ForkCandidate) {
+        let a = candidate("a", view_change1, true);
+        let b = candidate("b", view_change2, true);
+        if a.hash < b.hash {
+            (a, b)
+        } else {
+            (
+                ForkCandidate {
+                    view_change: view_change1,
+                    ..b
+                },
+                ForkCandidate {
+                    view_change: view_change2,
+                    ..a
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn same_block() {
+        let a = candidate("a", 1, true);
+        assert_eq!(choose_fork(&a, &a), ForkChoice::Same);
+        let unproven = ForkCandidate { proven: false, ..a };
+        assert_eq!(choose_fork(&a, &unproven), ForkChoice::Same);
+    }
+
+    #[test]
+    fn higher_view_change() {
+        // Higher view_change wins regardless of hashes.
+        for &(low_vc, high_vc) in &[(0, 1), (1, 2), (0, 5)] {
+            let (low_hash, high_hash) = ordered(low_vc, high_vc);
+            assert_eq!(choose_fork(&low_hash, &high_hash), ForkChoice::Remote);
+            assert_eq!(choose_fork(&high_hash, &low_hash), ForkChoice::Local);
+            let (low_hash, high_hash) = ordered(high_vc, low_vc);
+            assert_eq!(choose_fork(&low_hash, &high_hash), ForkChoice::Local);
+            assert_eq!(choose_fork(&high_hash, &low_hash), ForkChoice::Remote);
+        }
+    }
+
+    #[test]
+    fn invalid_proof() {
+        let local = candidate("a", 0, true);
+        let remote = candidate("b", 3, false);
+        assert_eq!(choose_fork(&local, &remote), ForkChoice::Local);
+        assert_eq!(choose_fork(&remote, &local), ForkChoice::Remote);
+
+        // Neither is valid - keep the local block.
+        let local = candidate("a", 2, false);
+        let remote = candidate("b", 3, false);
+        assert_eq!(choose_fork(&local, &remote), ForkChoice::Local);
+        assert_eq!(choose_fork(&remote, &local), ForkChoice::Local);
+    }
+
+    #[test]
+    fn lowest_hash() {
+        for &view_change in &[0, 1, 10] {
+            let (low, high) = ordered(view_change, view_change);
+            assert_eq!(choose_fork(&low, &high), ForkChoice::Local);
+            assert_eq!(choose_fork(&high, &low), ForkChoice::Remote);
+        }
+    }
+
+    #[test]
+    fn lowest_random() {
+        // VRF output takes precedence over hash.
+        let (low, high) = ordered(0, 0);
+        let low = ForkCandidate {
+            random: Hash::digest("high"),
+            ..low
+        };
+        let high = ForkCandidate {
+            random: Hash::digest("low"),
+            ..high
+        };
+        let (low, high) = if high.random < low.random {
+            (high, low)
+        } else {
+            (low, high)
+        };
+        assert_eq!(choose_fork(&low, &high), ForkChoice::Local);
+        assert_eq!(choose_fork(&high, &low), ForkChoice::Remote);
+
+        // Higher view_change still wins.
+        let high = ForkCandidate {
+            view_change: 1,
+            ..high
+        };
+        assert_eq!(choose_fork(&low, &high), ForkChoice::Remote);
+    }
+
+    /// All nodes choose the same block regardless of the order of arrival.
+    #[test]
+    fn symmetric() {
+        let candidates = [
+            candidate("a", 0, true),
+            candidate("b", 0, true),
+            candidate("c", 1, true),
+            candidate("d", 1, true),
+            candidate("e", 2, false),
+        ];
+        for x in &candidates {
+            for y in &candidates {
+                let winner = match choose_fork(x, y) {
+                    ForkChoice::Same => continue,
+                    ForkChoice::Local => x,
+                    ForkChoice::Remote => y,
+                };
+                let winner2 = match choose_fork(y, x) {
+                    ForkChoice::Same => unreachable!(),
+                    ForkChoice::Local => y,
+                    ForkChoice::Remote => x,
+                };
+                assert_eq!(winner, winner2);
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Aggregated status of the node, used for health checks.
+//!
+
+use serde_derive::Serialize;
+use std::fs;
+use std::time::Duration;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+use stegos_network::BucketInfo;
+
+/// Status of all subsystems of the node.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    pub network_pkey: pbc::PublicKey,
+    pub epoch: u64,
+    pub height: u64,
+    pub view_change: u32,
+    pub last_block_hash: Hash,
+    pub last_macro_block_height: u64,
+    pub last_macro_block_hash: Hash,
+    /// True if the node has caught up with the network.
+    pub synchronized: bool,
+    /// None if this node is not a validator in the current epoch.
+    pub validator: Option<ValidatorStatus>,
+    pub peers: PeerCounts,
+    /// The number of transactions in mempool.
+    pub mempool_transactions: usize,
+    /// True if a network partition has been detected and the node refuses to lead.
+    pub safe_mode: bool,
+    pub resources: ResourceUsage,
+}
+
+/// Status of this node as a validator.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidatorStatus {
+    pub slots: i64,
+    pub total_slots: i64,
+    /// True if consensus is enabled by the role of the node.
+    pub consensus: bool,
+    pub facilitator: bool,
+}
+
+/// Nodes in the routing table.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeerCounts {
+    pub known: usize,
+    pub connected: usize,
+}
+
+impl PeerCounts {
+    pub fn from_buckets(buckets: &[BucketInfo]) -> Self {
+        let mut counts = PeerCounts::default();
+        for entry in buckets.iter().flat_map(|bucket| bucket.entries.iter()) {
+            counts.known += 1;
+            if entry.connected {
+                counts.connected += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// Resources used by the process.
+/// Fields are None if they are not supported on this platform.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceUsage {
+    /// Seconds since the node was started.
+    pub uptime: u64,
+    /// Resident set size in bytes.
+    pub memory: Option<u64>,
+    pub threads: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl ResourceUsage {
+    pub fn current(uptime: Duration) -> Self {
+        let proc_status = fs::read_to_string("/proc/self/status").ok();
+        let field = |name: &str| {
+            proc_status
+                .as_ref()
+                .and_then(|status| parse_proc_status(status, name))
+        };
+        let open_files = fs::read_dir("/proc/self/fd")
+            .ok()
+            .map(|entries| entries.count() as u64);
+        ResourceUsage {
+            uptime: uptime.as_secs(),
+            memory: field("VmRSS:").map(|kb| kb * 1024),
+            threads: field("Threads:"),
+            open_files,
+        }
+    }
+}
+
+/// Returns the numeric value of a field of /proc/self/status.
+fn parse_proc_status(status: &str, name: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with(name))
+        .and_then(|line| line[name.len()..].split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_status() {
+        let status = "Name:\tstegos\nVmRSS:\t   10240 kB\nThreads:\t12\n";
+        assert_eq!(parse_proc_status(status, "VmRSS:"), Some(10240));
+        assert_eq!(parse_proc_status(status, "Threads:"), Some(12));
+        assert_eq!(parse_proc_status(status, "VmSwap:"), None);
+        assert_eq!(parse_proc_status(status, "Name:"), None);
+    }
+}
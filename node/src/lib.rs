@@ -21,22 +21,51 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod broadcast;
 mod config;
+mod dandelion;
 mod error;
+mod fees;
+mod fork_choice;
+mod health;
+mod light;
 mod loader;
 mod mempool;
 pub mod metrics;
+mod orphans;
+mod partition;
 mod proposal;
 pub mod protos;
+mod shards;
+mod shutdown;
+mod staked;
+mod status;
 #[cfg(test)]
 mod test;
 mod validation;
-pub use crate::config::ChainConfig;
+mod verifier;
+pub use crate::broadcast::TransactionConflict;
+use crate::broadcast::{TxBroadcaster, TX_BROADCAST_TOPIC};
+pub use crate::config::{ChainConfig, NodeRole};
+use crate::dandelion::{Dandelion, DANDELION_STEM_TOPIC, DANDELION_TIMER_INTERVAL};
 use crate::error::*;
-use crate::loader::ChainLoaderMessage;
+pub use crate::fees::FeeEstimate;
+use crate::fees::FeeEstimator;
+use crate::fork_choice::{choose_fork, ForkCandidate, ForkChoice};
+pub use crate::health::{NodeStatus, PeerCounts, ResourceUsage, ValidatorStatus};
+pub use crate::light::LightNodeService;
+use crate::loader::{ChainLoader, ChainLoaderMessage};
 use crate::mempool::Mempool;
+use crate::orphans::OrphanPool;
+use crate::partition::PartitionDetector;
+use crate::shards::TxShards;
+use crate::shutdown::ConsensusState;
+use crate::staked::StakedNodes;
+pub use crate::status::PeerStatus;
+use crate::status::{ChainStatus, StatusTracker, CHAIN_STATUS_TOPIC};
 use crate::validation::*;
-use failure::Error;
+use crate::verifier::{SignatureCheck, SignatureVerifier};
+use failure::{bail, Error};
 use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::sync::oneshot;
 use futures::{task, Async, Future, Poll, Stream};
@@ -46,19 +75,31 @@ use protobuf;
 use protobuf::Message;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
+pub use stegos_blockchain::FinalityCommitment;
+pub use stegos_blockchain::RandomBeacon;
 use stegos_blockchain::*;
+use stegos_consensus::confirmation::{
+    ConfirmationCollector, ConfirmationInfo, ConfirmationProof, ConfirmationVote,
+};
 use stegos_consensus::optimistic::{SealedViewChangeProof, ViewChangeCollector, ViewChangeMessage};
-use stegos_consensus::{self as consensus, Consensus, ConsensusMessage};
+use stegos_consensus::{
+    self as consensus, Consensus, ConsensusMessage, LocalSigner, NetworkTime, RemoteSigner, Signer,
+    ViewChangeCollectorInfo,
+};
+use stegos_crypto::curve1174::{PublicKey, SecretKey};
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
 use stegos_keychain::KeyChain;
 use stegos_network::Network;
 use stegos_network::UnicastMessage;
+use stegos_network::{BucketInfo, Lane, QueryRecord};
 use stegos_serialization::traits::ProtoConvert;
-use tokio_timer::{clock, Delay};
+use tokio_timer::{clock, Delay, Interval};
 
 // ----------------------------------------------------------------
 // Public API.
@@ -69,6 +110,10 @@ use tokio_timer::{clock, Delay};
 pub struct Node {
     outbox: UnboundedSender<NodeMessage>,
     network: Network,
+    /// The total number of transaction shards.
+    tx_topic_shards: u32,
+    /// Local transactions are sent along the stem instead of the gossip topic.
+    dandelion: bool,
 }
 
 impl Node {
@@ -76,9 +121,15 @@ impl Node {
     pub fn send_transaction(&self, tx: Transaction) -> Result<(), Error> {
         let proto = tx.into_proto();
         let data = proto.write_to_bytes()?;
-        self.network.publish(&TX_TOPIC, data.clone())?;
+        let topic = shards::tx_topic(
+            shards::tx_shard(&Hash::digest(&tx), self.tx_topic_shards),
+            self.tx_topic_shards,
+        );
+        if !self.dandelion {
+            self.network.publish_unsubscribed(&topic, data.clone())?;
+        }
         info!("Sent transaction to the network: tx={}", Hash::digest(&tx));
-        let msg = NodeMessage::Transaction(data);
+        let msg = NodeMessage::LocalTransaction(data);
         self.outbox.unbounded_send(msg)?;
         Ok(())
     }
@@ -91,6 +142,15 @@ impl Node {
         rx
     }
 
+    /// Stop accepting new work and flush the state to the disk.
+    /// The returned future is resolved when the node is stopped.
+    pub fn shutdown(&self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let msg = NodeMessage::Shutdown(tx);
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+
     /// Subscribe to block changes.
     pub fn subscribe_block_added(&self) -> UnboundedReceiver<BlockAdded> {
         let (tx, rx) = unbounded();
@@ -107,6 +167,22 @@ impl Node {
         rx
     }
 
+    /// Subscribe to commitments of irreversible macro blocks.
+    pub fn subscribe_finality_changed(&self) -> UnboundedReceiver<FinalityCommitment> {
+        let (tx, rx) = unbounded();
+        let msg = NodeMessage::SubscribeFinalityChanged(tx);
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+
+    /// Subscribe to the verifiable randomness of new blocks.
+    pub fn subscribe_random_beacon(&self) -> UnboundedReceiver<RandomBeacon> {
+        let (tx, rx) = unbounded();
+        let msg = NodeMessage::SubscribeRandomBeacon(tx);
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+
     /// Subscribe to UTXO changes.
     pub fn subscribe_outputs_changed(&self) -> UnboundedReceiver<OutputsChanged> {
         let (tx, rx) = unbounded();
@@ -115,6 +191,32 @@ impl Node {
         rx
     }
 
+    /// Subscribe to conflicts of transactions sent via this node.
+    pub fn subscribe_transaction_conflicts(&self) -> UnboundedReceiver<TransactionConflict> {
+        let (tx, rx) = unbounded();
+        let msg = NodeMessage::SubscribeTransactionConflicts(tx);
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+
+    /// Find unspent outputs of the wallet key, to restore the state of a wallet opened at runtime.
+    /// Returns outputs together with the epoch of their creation and a subscription to
+    /// UTXO changes which starts exactly after the recovered state.
+    pub fn recover_wallet(
+        &self,
+        wallet_skey: SecretKey,
+        wallet_pkey: PublicKey,
+    ) -> oneshot::Receiver<(Vec<(Output, u64)>, UnboundedReceiver<OutputsChanged>)> {
+        let (tx, rx) = oneshot::channel();
+        let msg = NodeMessage::RecoverWallet {
+            wallet_skey,
+            wallet_pkey,
+            tx,
+        };
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+
     /// Revert the latest block.
     pub fn pop_block(&self) {
         let msg = NodeMessage::PopBlock;
@@ -122,6 +224,12 @@ impl Node {
     }
 }
 
+/// Event loop serving `Node`, one implementation per `NodeRole`.
+pub trait NodeRoleService: Future<Item = (), Error = ()> + Send {
+    /// Invoked when network is ready.
+    fn init(&mut self) -> Result<(), Error>;
+}
+
 ///
 /// RPC requests.
 ///
@@ -130,7 +238,80 @@ impl Node {
 #[serde(rename_all = "snake_case")]
 pub enum NodeRequest {
     ElectionInfo {},
+    LeaderSelection {
+        epoch: u64,
+        view_change: u32,
+    },
     EscrowInfo {},
+    OutputProof {
+        utxo: Hash,
+    },
+    Inspect {
+        query: InspectorQuery,
+    },
+    Explore {
+        query: ExplorerQuery,
+    },
+    TransactionStatus {
+        tx_hash: Hash,
+        /// Inputs of the transaction, used to detect conflicting spends.
+        #[serde(default)]
+        txins: Vec<Hash>,
+    },
+    StakesByWallet {
+        wallet_pkey: PublicKey,
+    },
+    StakeUnlockHeight {
+        utxo: Hash,
+    },
+    ValidatorStake {
+        network_pkey: pbc::PublicKey,
+    },
+    RoutingTable {},
+    /// Recent queries of the DHT, for diagnostics of peer discovery.
+    QueryJournal {},
+    PeerStatuses {},
+    FastConfirmation {
+        tx_hash: Hash,
+    },
+    VerifyFastConfirmation {
+        proof: ConfirmationProof,
+    },
+    EstimateFee {
+        /// The number of blocks the transaction should be included within.
+        target_blocks: u64,
+    },
+    /// State of the micro block view change.
+    ViewChangeInfo {},
+    /// Apply a view change proof obtained out-of-band.
+    SubmitViewChangeProof {
+        proof: SealedViewChangeProof,
+    },
+    /// Check a proof of reserves against the current UTXO.
+    ValidateReserveProof {
+        proof: ReserveProof,
+    },
+    /// Prepare an unsigned micro block on the top of the chain.
+    BlockTemplate {},
+    /// Validate, apply and broadcast a signed micro block.
+    SubmitBlock {
+        block: MicroBlock,
+    },
+    /// Summary of a finalized epoch.
+    EpochSummary {
+        epoch: u64,
+    },
+    /// Aggregated status of the node for health checks.
+    Status {},
+    /// Verifiable randomness of a block, the last block by default.
+    RandomBeacon {
+        #[serde(default)]
+        height: Option<u64>,
+    },
+    /// Check the database, roll back damaged blocks and compact it.
+    RepairDatabase {},
+    /// The last irreversible block.
+    FinalityInfo {},
 }
 
 ///
@@ -141,7 +322,119 @@ pub enum NodeRequest {
 #[serde(rename_all = "snake_case")]
 pub enum NodeResponse {
     ElectionInfo(ElectionInfo),
+    LeaderSelection(LeaderSelectionAudit),
     EscrowInfo(EscrowInfo),
+    OutputProof {
+        utxo: Hash,
+        /// None if the output is unknown or its epoch is not finalized yet.
+        proof: Option<OutputProof>,
+    },
+    Inspect(InspectorResponse),
+    Explore(ExplorerResponse),
+    TransactionStatus {
+        tx_hash: Hash,
+        status: TransactionStatus,
+        /// True if the transaction is in the mempool of this node.
+        in_mempool: bool,
+    },
+    StakesByWallet {
+        wallet_pkey: PublicKey,
+        /// The current height.
+        height: u64,
+        stakes: Vec<WalletStakeInfo>,
+    },
+    StakeUnlockHeight {
+        utxo: Hash,
+        /// None if the output is not an unspent stake.
+        unlock_height: Option<u64>,
+    },
+    ValidatorStake {
+        network_pkey: pbc::PublicKey,
+        summary: ValidatorStakeSummary,
+    },
+    RoutingTable {
+        buckets: Vec<BucketInfo>,
+    },
+    QueryJournal {
+        queries: Vec<QueryRecord>,
+    },
+    PeerStatuses {
+        /// The current height.
+        height: u64,
+        statuses: Vec<PeerStatus>,
+    },
+    FastConfirmation {
+        tx_hash: Hash,
+        /// None if the transaction hasn't been confirmed by validators in the current epoch.
+        proof: Option<ConfirmationProof>,
+    },
+    FastConfirmationVerified {
+        tx_hash: Hash,
+    },
+    FeeEstimate(FeeEstimate),
+    ViewChangeInfo {
+        height: u64,
+        view_change: u32,
+        last_block: Hash,
+        /// None if this node is not a validator.
+        collector: Option<ViewChangeCollectorInfo>,
+    },
+    ViewChangeProofSubmitted {
+        height: u64,
+        view_change: u32,
+        /// False if the proof didn't change the chain.
+        applied: bool,
+    },
+    ReserveProofValidated {
+        challenge: String,
+        /// The total amount of unspent outputs.
+        amount: i64,
+        /// The height of UTXO used for validation.
+        height: u64,
+    },
+    BlockTemplate {
+        height: u64,
+        view_change: u32,
+        /// The expected leader of the block.
+        leader: pbc::PublicKey,
+        /// Hash to sign by the network key of `block.pkey`.
+        block_hash: Hash,
+        /// Unsigned block, a hex-encoded protobuf.
+        block: MicroBlock,
+    },
+    BlockSubmitted {
+        height: u64,
+        block_hash: Hash,
+    },
+    EpochSummary {
+        epoch: u64,
+        /// None if the epoch hasn't been finalized yet.
+        summary: Option<EpochSummary>,
+    },
+    Status(NodeStatus),
+    RandomBeacon {
+        height: u64,
+        /// None if the block doesn't exist yet.
+        beacon: Option<RandomBeacon>,
+    },
+    DatabaseRepaired {
+        /// The height after the repair.
+        height: u64,
+        /// The number of reverted or removed blocks.
+        removed_blocks: u64,
+        /// True if damaged blocks can't be reverted online and will be rolled back on restart.
+        restart_required: bool,
+    },
+    FinalityInfo {
+        /// The current height.
+        height: u64,
+        /// The height of the last irreversible block.
+        finalized_height: u64,
+        commitment: Option<FinalityCommitment>,
+    },
+    Error {
+        error: String,
+    },
 }
 
 /// Send when height is changed.
@@ -163,22 +456,34 @@ pub struct EpochChanged {
     pub epoch: u64,
     pub facilitator: pbc::PublicKey,
     pub validators: Vec<(pbc::PublicKey, i64)>,
+    /// Randomness used for the election of this epoch.
+    pub random: Hash,
+    /// Winner and amount of the service award paid at the start of this epoch.
+    pub service_award: Option<(PublicKey, i64)>,
+    /// Service award budget left for the next epochs.
+    pub service_awards_budget: i64,
 }
 
 /// Send when outputs created and/or pruned.
 #[derive(Debug, Clone)]
 pub struct OutputsChanged {
     pub epoch: u64,
+    /// Height of the applied or reverted block.
+    pub height: u64,
+    /// Timestamp of the block.
+    pub timestamp: SystemTime,
+    /// True if the block has been reverted.
+    pub reverted: bool,
     pub inputs: Vec<Output>,
     pub outputs: Vec<Output>,
+    /// Transaction hash for each created or pruned output, if known.
+    pub tx_hashes: HashMap<Hash, Hash>,
 }
 
 // ----------------------------------------------------------------
 // Internal Implementation.
 // ----------------------------------------------------------------
 
-/// Topic used for sending transactions.
-const TX_TOPIC: &'static str = "tx";
 /// Topic used for consensus.
 const CONSENSUS_TOPIC: &'static str = "consensus";
 /// Topic for ViewChange message.
@@ -187,6 +492,10 @@ pub const VIEW_CHANGE_TOPIC: &'static str = "view_changes";
 pub const VIEW_CHANGE_DIRECT: &'static str = "view_changes_direct";
 /// Topic used for sending sealed blocks.
 const SEALED_BLOCK_TOPIC: &'static str = "block";
+// Topic for votes of fast confirmation.
+const FAST_CONFIRMATION_TOPIC: &'static str = "fast_confirmation";
+/// The maximal number of messages waiting for the signature verifier.
+const MAX_PENDING_VERIFICATIONS: usize = 10_000;
 
 #[derive(Debug)]
 pub enum NodeMessage {
@@ -195,12 +504,22 @@ pub enum NodeMessage {
     //
     SubscribeBlockAdded(UnboundedSender<BlockAdded>),
     SubscribeEpochChanged(UnboundedSender<EpochChanged>),
+    SubscribeFinalityChanged(UnboundedSender<FinalityCommitment>),
+    SubscribeRandomBeacon(UnboundedSender<RandomBeacon>),
     SubscribeOutputsChanged(UnboundedSender<OutputsChanged>),
+    SubscribeTransactionConflicts(UnboundedSender<TransactionConflict>),
+    LocalTransaction(Vec<u8>),
     PopBlock,
     Request {
         request: NodeRequest,
         tx: oneshot::Sender<NodeResponse>,
     },
+    Shutdown(oneshot::Sender<()>),
+    RecoverWallet {
+        wallet_skey: SecretKey,
+        wallet_pkey: PublicKey,
+        tx: oneshot::Sender<(Vec<(Output, u64)>, UnboundedReceiver<OutputsChanged>)>,
+    },
     //
     // Network Events
     //
@@ -209,7 +528,18 @@ pub enum NodeMessage {
     Block(Vec<u8>),
     ViewChangeMessage(Vec<u8>),
     ViewChangeProofMessage(UnicastMessage),
+    ConfirmationVote(Vec<u8>),
     ChainLoaderMessage(UnicastMessage),
+    ChainStatus(Vec<u8>),
+    ChainStatusTimer,
+    StemTransaction(UnicastMessage),
+    DandelionTimer,
+}
+
+/// A message waiting for the signature verifier.
+enum PendingMessage {
+    Consensus(ConsensusMessage),
+    ViewChange(ViewChangeMessage),
 }
 
 enum BlockTimer {
@@ -239,6 +569,23 @@ enum Validation {
 }
 use Validation::*;
 
+/// Returns transactions of outputs and inputs and hashes of all transactions of the block.
+fn micro_block_tx_hashes(block: &MicroBlock) -> (HashMap<Hash, Hash>, Vec<Hash>) {
+    let mut tx_hashes: HashMap<Hash, Hash> = HashMap::new();
+    let mut block_txs: Vec<Hash> = Vec::with_capacity(block.transactions.len());
+    for tx in &block.transactions {
+        let tx_hash = Hash::digest(tx);
+        for input_hash in tx.txins() {
+            tx_hashes.insert(*input_hash, tx_hash);
+        }
+        for output in tx.txouts() {
+            tx_hashes.insert(Hash::digest(output), tx_hash);
+        }
+        block_txs.push(tx_hash);
+    }
+    (tx_hashes, block_txs)
+}
+
 pub struct NodeService {
     /// Config.
     cfg: ChainConfig,
@@ -246,13 +593,27 @@ pub struct NodeService {
     chain: Blockchain,
     /// Key Chain.
     keys: KeyChain,
+    /// Signs blocks and consensus messages with the network key.
+    signer: Arc<dyn Signer>,
 
+    /// A time when the node was started.
+    started_clock: Instant,
     /// A time when loader was started the last time
     last_sync_clock: Instant,
+    /// Block synchronization state.
+    loader: ChainLoader,
+    /// Blocks received before their parents.
+    orphans: OrphanPool,
 
     /// Memory pool of pending transactions.
     mempool: Mempool,
 
+    /// Fee statistics of recent blocks and mempool.
+    fees: FeeEstimator,
+
+    /// Subscriptions to the transaction shards.
+    tx_shards: TxShards,
+
     /// Consensus state.
     validation: Validation,
 
@@ -262,17 +623,57 @@ pub struct NodeService {
     /// Cheating detection.
     cheating_proofs: HashMap<pbc::PublicKey, SlashingProof>,
 
+    /// Fast confirmations of transactions.
+    confirmations: ConfirmationCollector,
+
+    /// Broadcasting of local transactions.
+    broadcaster: TxBroadcaster,
+
+    /// Stem phase of transaction relay, None if disabled.
+    dandelion: Option<Dandelion>,
+
+    /// Nodes allowed into the routing table.
+    staked_nodes: StakedNodes,
+
+    /// Adjusted network time used to check timestamps of consensus messages.
+    network_time: NetworkTime,
+
+    /// Checks signatures of consensus messages off the event loop.
+    verifier: SignatureVerifier,
+    /// Messages waiting for the verifier, in the order of arrival.
+    pending_verifications: VecDeque<(oneshot::Receiver<bool>, PendingMessage)>,
+
+    /// The latest chain statuses of validators.
+    statuses: StatusTracker,
+
+    /// Switches the node to the safe mode when it is cut off from the supermajority of stake.
+    partition: PartitionDetector,
+
+    /// Set by shutdown, no new work is accepted.
+    stopped: bool,
+
     //
     // Communication with environment.
     //
     /// Network interface.
     network: Network,
+    /// Pending requests answered by the network service.
+    network_requests: Vec<(
+        Box<dyn Future<Item = NodeResponse, Error = oneshot::Canceled> + Send>,
+        oneshot::Sender<NodeResponse>,
+    )>,
     /// Triggered when height is changed.
     on_block_added: Vec<UnboundedSender<BlockAdded>>,
     /// Triggered when epoch is changed.
     on_epoch_changed: Vec<UnboundedSender<EpochChanged>>,
+    /// Triggered when a macro block becomes irreversible.
+    on_finality_changed: Vec<UnboundedSender<FinalityCommitment>>,
+    /// Triggered when a block with new randomness is added.
+    on_random_beacon: Vec<UnboundedSender<RandomBeacon>>,
     /// Triggered when outputs created and/or pruned.
     on_outputs_changed: Vec<UnboundedSender<OutputsChanged>>,
+    /// Triggered when a conflicting transaction is observed.
+    on_transaction_conflict: Vec<UnboundedSender<TransactionConflict>>,
     /// Aggregated stream of events.
     events: Box<Stream<Item = NodeMessage, Error = ()> + Send>,
 }
@@ -285,9 +686,31 @@ impl NodeService {
         keys: KeyChain,
         network: Network,
     ) -> Result<(Self, Node), Error> {
+        if !cfg.role.keeps_full_chain() {
+            bail!(
+                "Light nodes are run by LightNodeService: role={:?}",
+                cfg.role
+            );
+        }
         let (outbox, inbox) = unbounded();
+        let started_clock = clock::now();
         let last_sync_clock = clock::now();
+        let loader = ChainLoader::new();
+        let orphans = OrphanPool::new(cfg.max_orphan_blocks, cfg.orphan_block_timeout);
         let mempool = Mempool::new();
+        let fees = FeeEstimator::new();
+        let tx_topic_shards = chain.cfg().tx_topic_shards;
+        let mut tx_shards = TxShards::new(tx_topic_shards, cfg.relay_tx_shards);
+
+        let signer: Arc<dyn Signer> = if cfg.signer.endpoint.is_empty() {
+            Arc::new(LocalSigner::new(
+                keys.network_skey.clone(),
+                keys.network_pkey,
+            ))
+        } else {
+            info!("Using the remote signer: endpoint={}", cfg.signer.endpoint);
+            Arc::new(RemoteSigner::new(cfg.signer.clone(), keys.network_pkey))
+        };
 
         let last_block_clock = clock::now();
         let validation = if chain.blocks_in_epoch() < cfg.blocks_in_epoch {
@@ -296,10 +719,48 @@ impl NodeService {
             MacroBlockAuditor
         };
         let cheating_proofs = HashMap::new();
+        let confirmations = ConfirmationCollector::new();
+        let broadcaster = TxBroadcaster::new(cfg.tx_broadcast_fanout, cfg.tx_rebroadcast_blocks);
+        let dandelion = if cfg.dandelion {
+            Some(Dandelion::new(
+                cfg.dandelion_fluff_probability,
+                cfg.dandelion_embargo,
+            ))
+        } else {
+            None
+        };
+        let staked_nodes = StakedNodes::default();
+        network.set_stake_checker(Arc::new(staked_nodes.clone()))?;
+        // Keep validator-critical messages ahead of bulk block sync under congestion,
+        // transaction gossip uses the default lane.
+        for topic in &[
+            CONSENSUS_TOPIC,
+            VIEW_CHANGE_TOPIC,
+            VIEW_CHANGE_DIRECT,
+            SEALED_BLOCK_TOPIC,
+            FAST_CONFIRMATION_TOPIC,
+            CHAIN_STATUS_TOPIC,
+        ] {
+            network.set_lane(topic, Lane::Consensus)?;
+        }
+        network.set_lane(loader::CHAIN_LOADER_TOPIC, Lane::BlockSync)?;
+        let network_time = NetworkTime::new(cfg.message_timestamp_window);
+        let verifier = SignatureVerifier::new(cfg.verifier_threads);
+        let statuses = StatusTracker::new(cfg.chain_status_interval * 3);
+        // Reachability of validators is measured by their chain statuses.
+        let partition_missed_rounds = if cfg.chain_status_interval > Duration::from_secs(0) {
+            cfg.partition_missed_rounds
+        } else {
+            0
+        };
+        let partition = PartitionDetector::new(partition_missed_rounds);
 
         let on_block_added = Vec::<UnboundedSender<BlockAdded>>::new();
         let on_epoch_changed = Vec::<UnboundedSender<EpochChanged>>::new();
+        let on_finality_changed = Vec::<UnboundedSender<FinalityCommitment>>::new();
+        let on_random_beacon = Vec::<UnboundedSender<RandomBeacon>>::new();
         let on_outputs_changed = Vec::<UnboundedSender<OutputsChanged>>::new();
+        let on_transaction_conflict = Vec::<UnboundedSender<TransactionConflict>>::new();
 
         let mut streams = Vec::<Box<Stream<Item = NodeMessage, Error = ()> + Send>>::new();
 
@@ -307,26 +768,32 @@ impl NodeService {
         streams.push(Box::new(inbox));
 
         // Transaction Requests
-        let transaction_rx = network
-            .subscribe(&TX_TOPIC)?
-            .map(|m| NodeMessage::Transaction(m));
-        streams.push(Box::new(transaction_rx));
+        let is_validator = cfg.role.runs_consensus() && chain.is_validator(&keys.network_pkey);
+        tx_shards.update(&network, &keys.network_pkey, is_validator)?;
 
-        // Consensus Requests
-        let consensus_rx = network
-            .subscribe(&CONSENSUS_TOPIC)?
-            .map(|m| NodeMessage::Consensus(m));
-        streams.push(Box::new(consensus_rx));
-
-        let view_change_rx = network
-            .subscribe(&VIEW_CHANGE_TOPIC)?
-            .map(|m| NodeMessage::ViewChangeMessage(m));
-        streams.push(Box::new(view_change_rx));
+        // Transactions sent directly by other nodes.
+        let tx_broadcast_rx = network
+            .subscribe_unicast(TX_BROADCAST_TOPIC)?
+            .map(|m| NodeMessage::Transaction(m.data));
+        streams.push(Box::new(tx_broadcast_rx));
 
-        let view_change_unicast_rx = network
-            .subscribe_unicast(&VIEW_CHANGE_DIRECT)?
-            .map(|m| NodeMessage::ViewChangeProofMessage(m));
-        streams.push(Box::new(view_change_unicast_rx));
+        // Consensus Requests
+        if cfg.role.runs_consensus() {
+            let consensus_rx = network
+                .subscribe(&CONSENSUS_TOPIC)?
+                .map(|m| NodeMessage::Consensus(m));
+            streams.push(Box::new(consensus_rx));
+
+            let view_change_rx = network
+                .subscribe(&VIEW_CHANGE_TOPIC)?
+                .map(|m| NodeMessage::ViewChangeMessage(m));
+            streams.push(Box::new(view_change_rx));
+
+            let view_change_unicast_rx = network
+                .subscribe_unicast(&VIEW_CHANGE_DIRECT)?
+                .map(|m| NodeMessage::ViewChangeProofMessage(m));
+            streams.push(Box::new(view_change_unicast_rx));
+        }
 
         // Sealed blocks broadcast topic.
         let block_rx = network
@@ -334,33 +801,85 @@ impl NodeService {
             .map(|m| NodeMessage::Block(m));
         streams.push(Box::new(block_rx));
 
+        // Fast confirmation votes.
+        let confirmation_rx = network
+            .subscribe(&FAST_CONFIRMATION_TOPIC)?
+            .map(|m| NodeMessage::ConfirmationVote(m));
+        streams.push(Box::new(confirmation_rx));
+
         // Chain loader messages.
         let requests_rx = network
             .subscribe_unicast(loader::CHAIN_LOADER_TOPIC)?
             .map(NodeMessage::ChainLoaderMessage);
         streams.push(Box::new(requests_rx));
 
+        // Chain statuses of validators.
+        let status_rx = network
+            .subscribe(CHAIN_STATUS_TOPIC)?
+            .map(NodeMessage::ChainStatus);
+        streams.push(Box::new(status_rx));
+        if cfg.chain_status_interval > Duration::from_secs(0) {
+            let status_timer = Interval::new_interval(cfg.chain_status_interval)
+                .map(|_i| NodeMessage::ChainStatusTimer)
+                .map_err(|_e| ()); // ignore transient timer errors
+            streams.push(Box::new(status_timer));
+        }
+
+        // Transactions in the stem phase.
+        if cfg.dandelion {
+            let stem_rx = network
+                .subscribe_unicast(DANDELION_STEM_TOPIC)?
+                .map(NodeMessage::StemTransaction);
+            streams.push(Box::new(stem_rx));
+            let dandelion_timer = Interval::new_interval(DANDELION_TIMER_INTERVAL)
+                .map(|_i| NodeMessage::DandelionTimer)
+                .map_err(|_e| ()); // ignore transient timer errors
+            streams.push(Box::new(dandelion_timer));
+        }
+
         let events = select_all(streams);
 
         let service = NodeService {
             cfg,
+            started_clock,
             last_sync_clock,
+            loader,
+            orphans,
             chain,
             keys,
+            signer,
             mempool,
+            fees,
+            tx_shards,
             validation,
             last_block_clock,
             cheating_proofs,
+            confirmations,
+            broadcaster,
+            dandelion,
+            staked_nodes,
+            network_time,
+            verifier,
+            pending_verifications: VecDeque::new(),
+            statuses,
+            partition,
+            stopped: false,
+            network_requests: Vec::new(),
             network: network.clone(),
             on_block_added,
             on_epoch_changed,
+            on_finality_changed,
+            on_random_beacon,
             on_outputs_changed,
+            on_transaction_conflict,
             events,
         };
 
         let handler = Node {
             outbox,
             network: network.clone(),
+            tx_topic_shards,
+            dandelion: service.dandelion.is_some(),
         };
 
         Ok((service, handler))
@@ -368,11 +887,69 @@ impl NodeService {
 
     /// Invoked when network is ready.
     pub fn init(&mut self) -> Result<(), Error> {
+        self.restore_consensus_state()?;
+        self.update_stem_relays();
+        self.update_staked_nodes();
+        self.prewarm_validators();
         self.update_validation_status();
         self.request_history()?;
         Ok(())
     }
 
+    /// Restores the view change saved by the previous shutdown.
+    fn restore_consensus_state(&mut self) -> Result<(), Error> {
+        if self.cfg.consensus_state_file.is_empty() {
+            return Ok(());
+        }
+        let state = match ConsensusState::load(&self.cfg.consensus_state_file)? {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        match state.actual_proof(&self.chain) {
+            Some(proof) => {
+                info!(
+                    "Restored consensus state: height={}, view_change={}",
+                    state.height, state.view_change
+                );
+                self.chain.set_view_change(state.view_change, proof);
+            }
+            None => debug!(
+                "Ignored outdated consensus state: height={}, view_change={}",
+                state.height, state.view_change
+            ),
+        }
+        Ok(())
+    }
+
+    /// Stops accepting new work and flushes the state to the disk.
+    fn handle_shutdown(&mut self, tx: oneshot::Sender<()>) -> Result<(), Error> {
+        info!(
+            "Shutting down: height={}, view_change={}, mempool={}",
+            self.chain.height(),
+            self.chain.view_change(),
+            self.mempool.len()
+        );
+        self.stopped = true;
+        let saved = self.save_consensus_state();
+        let flushed = self.chain.flush();
+        tx.send(()).ok(); // ignore errors.
+        saved.and(flushed)
+    }
+
+    /// Saves the view change to be restored after restart.
+    fn save_consensus_state(&self) -> Result<(), Error> {
+        if self.cfg.consensus_state_file.is_empty() {
+            return Ok(());
+        }
+        let state = ConsensusState::new(&self.chain);
+        state.save(&self.cfg.consensus_state_file)?;
+        debug!(
+            "Saved consensus state: height={}, view_change={}",
+            state.height, state.view_change
+        );
+        Ok(())
+    }
+
     /// Handle incoming transactions received from network.
     fn handle_transaction(&mut self, tx: Transaction) -> Result<(), Error> {
         let tx_hash = Hash::digest(&tx);
@@ -384,10 +961,20 @@ impl NodeService {
             tx.fee()
         );
 
+        // The transaction has left the stem phase.
+        if let Some(dandelion) = &mut self.dandelion {
+            dandelion.on_fluff(&tx_hash);
+        }
+
+        // Check for double-spends of local transactions.
+        let conflicts = self.broadcaster.on_transaction(&tx_hash, &tx);
+        self.notify_transaction_conflicts(conflicts);
+
         // Check that transaction has proper type.
         match &tx {
             Transaction::PaymentTransaction(_tx) => {}
             Transaction::RestakeTransaction(_tx) => {}
+            Transaction::RevokeDelegationTransaction(_tx) => {}
             _ => return Err(NodeTransactionError::InvalidType(tx_hash).into()),
         };
 
@@ -409,7 +996,7 @@ impl NodeService {
         }
 
         // Validate transaction.
-        let timestamp = SystemTime::now();
+        let timestamp = self.chain.block_timestamp();
         validate_external_transaction(
             &tx,
             &self.mempool,
@@ -421,11 +1008,282 @@ impl NodeService {
 
         // Queue to mempool.
         info!("Transaction is valid, adding to mempool: tx={}", &tx_hash);
+        self.fees
+            .on_transaction(tx_hash, fees::fee_density(&tx), self.chain.height());
         self.mempool.push_tx(tx_hash, tx);
         metrics::MEMPOOL_TRANSACTIONS.set(self.mempool.len() as i64);
         metrics::MEMPOOL_INPUTS.set(self.mempool.inputs_len() as i64);
         metrics::MEMPOOL_OUTPUTS.set(self.mempool.inputs_len() as i64);
 
+        self.vote_fast_confirmation(tx_hash)
+    }
+
+    /// Handle transactions submitted via the API.
+    fn handle_local_transaction(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        let tx = Transaction::from_buffer(&data)?;
+        let tx_hash = Hash::digest(&tx);
+        let txins = tx.txins().to_vec();
+        let result = self.handle_transaction(tx.clone());
+        if result.is_err() && !self.mempool.contains_tx(&tx_hash) {
+            return result;
+        }
+        let height = self.chain.height();
+        if self.broadcaster.track(tx_hash, &tx, data.clone(), height) {
+            if self.dandelion.is_some() {
+                self.stem_transaction(tx_hash, data, None);
+            } else {
+                self.broadcast_transaction(&tx_hash);
+            }
+        }
+        // Transactions which are already committed are reported immediately.
+        if let TransactionStatus::Conflicted { input, .. } =
+            self.chain.tx_status(&tx_hash, &txins)?
+        {
+            let conflicts = self.broadcaster.on_block_added(&[input], &HashMap::new());
+            self.notify_transaction_conflicts(conflicts);
+        }
+        result
+    }
+
+    /// Send a local transaction directly to a random subset of validators.
+    fn broadcast_transaction(&mut self, tx_hash: &Hash) {
+        let validators: Vec<pbc::PublicKey> = self
+            .chain
+            .validators()
+            .iter()
+            .map(|(pkey, _)| *pkey)
+            .filter(|pkey| *pkey != self.keys.network_pkey)
+            .collect();
+        let height = self.chain.height();
+        self.broadcaster
+            .broadcast(&self.network, tx_hash, &validators, height);
+    }
+
+    /// Handle transactions received along the stem.
+    fn handle_stem_transaction(&mut self, msg: UnicastMessage) -> Result<(), Error> {
+        let tx = Transaction::from_buffer(&msg.data)?;
+        let tx_hash = Hash::digest(&tx);
+        let dandelion = match &mut self.dandelion {
+            Some(dandelion) => dandelion,
+            None => return Ok(()),
+        };
+        if dandelion.contains(&tx_hash) || self.mempool.contains_tx(&tx_hash) {
+            return Ok(());
+        }
+        debug!(
+            "Received stem transaction: tx={}, from={}",
+            tx_hash, msg.from
+        );
+        if dandelion.fluff() {
+            return self.fluff_transaction(tx_hash, tx, msg.data);
+        }
+        validate_external_transaction(
+            &tx,
+            &self.mempool,
+            &self.chain,
+            self.chain.block_timestamp(),
+            self.cfg.payment_fee,
+            self.cfg.stake_fee,
+        )?;
+        self.stem_transaction(tx_hash, msg.data, Some(msg.from));
+        Ok(())
+    }
+
+    /// Send a transaction to the stem relay and start the embargo timer.
+    fn stem_transaction(&mut self, tx_hash: Hash, data: Vec<u8>, from: Option<pbc::PublicKey>) {
+        let dandelion = self.dandelion.as_mut().expect("dandelion is enabled");
+        if !dandelion.stem(tx_hash, data.clone(), clock::now()) {
+            return;
+        }
+        let relay = match dandelion.route(from.as_ref()) {
+            Some(relay) => relay,
+            None => {
+                // No relays yet, the transaction is published when the embargo expires.
+                warn!("No stem relays available: tx={}", tx_hash);
+                return;
+            }
+        };
+        debug!(
+            "Sent transaction along the stem: tx={}, relay={}",
+            tx_hash, relay
+        );
+        if let Err(e) = self.network.send(relay, DANDELION_STEM_TOPIC, data) {
+            error!(
+                "Failed to send stem transaction: tx={}, error={}",
+                tx_hash, e
+            );
+        }
+    }
+
+    /// Publish a transaction to the gossip topic.
+    fn fluff_transaction(
+        &mut self,
+        tx_hash: Hash,
+        tx: Transaction,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        if !self.mempool.contains_tx(&tx_hash) {
+            self.handle_transaction(tx)?;
+        }
+        info!("Publishing stem transaction: tx={}", tx_hash);
+        let topic = shards::tx_topic(
+            shards::tx_shard(&tx_hash, self.chain.cfg().tx_topic_shards),
+            self.chain.cfg().tx_topic_shards,
+        );
+        self.network.publish_unsubscribed(&topic, data)?;
+        // Local transactions are also sent directly to validators.
+        self.broadcast_transaction(&tx_hash);
+        Ok(())
+    }
+
+    /// Publish transactions which have not left the stem phase in time.
+    fn handle_dandelion_timer(&mut self) -> Result<(), Error> {
+        let expired = match &mut self.dandelion {
+            Some(dandelion) => dandelion.expired(clock::now()),
+            None => return Ok(()),
+        };
+        for (tx_hash, data) in expired {
+            debug!("Stem embargo expired: tx={}", tx_hash);
+            let result = Transaction::from_buffer(&data)
+                .and_then(|tx| self.fluff_transaction(tx_hash, tx, data));
+            if let Err(e) = result {
+                warn!(
+                    "Failed to publish stem transaction: tx={}, error={}",
+                    tx_hash, e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Choose new stem relays among validators.
+    fn update_stem_relays(&mut self) {
+        let dandelion = match &mut self.dandelion {
+            Some(dandelion) => dandelion,
+            None => return,
+        };
+        let network_pkey = self.keys.network_pkey;
+        let candidates: Vec<pbc::PublicKey> = self
+            .chain
+            .validators()
+            .iter()
+            .map(|(pkey, _)| *pkey)
+            .filter(|pkey| *pkey != network_pkey)
+            .collect();
+        dandelion.update_relays(&candidates);
+    }
+
+    /// Share stakes of nodes with the network layer.
+    fn update_staked_nodes(&mut self) {
+        let staked: HashMap<pbc::PublicKey, i64> = self
+            .chain
+            .escrow_info()
+            .validators
+            .into_iter()
+            .filter(|info| info.active_stake > 0)
+            .map(|info| (info.network_pkey, info.active_stake))
+            .collect();
+        let validators: HashSet<pbc::PublicKey> = self
+            .chain
+            .validators()
+            .iter()
+            .map(|(pkey, _stake)| *pkey)
+            .collect();
+        self.staked_nodes.update(staked, validators);
+    }
+
+    /// Connects to co-validators of the new epoch before the first round starts.
+    fn prewarm_validators(&mut self) {
+        // Don't dial validators of past epochs during sync.
+        if !self.is_validator() || !self.is_synchronized() {
+            return;
+        }
+        let validators: Vec<pbc::PublicKey> = self
+            .chain
+            .validators()
+            .iter()
+            .map(|(network_pkey, _slots)| *network_pkey)
+            .filter(|network_pkey| *network_pkey != self.keys.network_pkey)
+            .collect();
+        debug!(
+            "Prewarming connections to validators: epoch={}, count={}",
+            self.chain.epoch(),
+            validators.len()
+        );
+        if let Err(e) = self.network.prewarm_connections(validators) {
+            error!("Failed to prewarm connections: {}", e);
+        }
+    }
+
+    /// Re-broadcast local transactions which are still not committed.
+    fn rebroadcast_transactions(&mut self) {
+        let height = self.chain.height();
+        for tx_hash in self.broadcaster.due(height) {
+            info!(
+                "Re-broadcasting unconfirmed transaction: tx={}, height={}, acked={}",
+                tx_hash,
+                height,
+                self.broadcaster.acked(&tx_hash).unwrap_or(0)
+            );
+            if let Some(data) = self.broadcaster.data(&tx_hash) {
+                let topic = shards::tx_topic(
+                    shards::tx_shard(&tx_hash, self.chain.cfg().tx_topic_shards),
+                    self.chain.cfg().tx_topic_shards,
+                );
+                if let Err(e) = self.network.publish_unsubscribed(&topic, data.clone()) {
+                    error!("Failed to publish transaction: tx={}, error={}", tx_hash, e);
+                }
+            }
+            self.broadcast_transaction(&tx_hash);
+        }
+    }
+
+    /// Notify subscribers about conflicting transactions.
+    fn notify_transaction_conflicts(&mut self, conflicts: Vec<TransactionConflict>) {
+        for conflict in conflicts {
+            self.on_transaction_conflict
+                .retain(move |ch| ch.unbounded_send(conflict.clone()).is_ok());
+        }
+    }
+
+    /// Countersign a transaction admitted to mempool.
+    fn vote_fast_confirmation(&mut self, tx_hash: Hash) -> Result<(), Error> {
+        if !self.cfg.fast_confirmation
+            || !self.cfg.role.runs_consensus()
+            || self.confirmations.is_confirmed(&tx_hash)
+        {
+            return Ok(());
+        }
+        let validator_id = match self
+            .chain
+            .validators()
+            .iter()
+            .position(|(pkey, _)| *pkey == self.keys.network_pkey)
+        {
+            Some(id) => id as u32,
+            None => return Ok(()),
+        };
+        let info = ConfirmationInfo {
+            tx_hash,
+            height: self.chain.height(),
+            epoch: self.chain.epoch(),
+        };
+        let vote = ConfirmationVote::sign(info, validator_id, &*self.signer)?;
+        debug!(
+            "Sending a fast confirmation vote: tx={}, height={}, validator_id={}",
+            tx_hash, info.height, validator_id
+        );
+        self.network
+            .publish(FAST_CONFIRMATION_TOPIC, vote.into_buffer()?)?;
+        self.handle_confirmation_vote(vote)
+    }
+
+    /// Handle a vote for fast confirmation from a validator.
+    fn handle_confirmation_vote(&mut self, vote: ConfirmationVote) -> Result<(), Error> {
+        if !self.cfg.fast_confirmation {
+            return Ok(());
+        }
+        self.confirmations.handle_vote(&self.chain, vote)?;
         Ok(())
     }
 
@@ -461,59 +1319,110 @@ impl NodeService {
         let previous_block = self.chain.block_by_height(height - 1)?;
         let mut election_result = self.chain.election_result();
         election_result.random = previous_block.base_header().random;
-        let leader = election_result.select_leader(remote_view_change);
-        if leader != remote.pkey {
-            return Err(BlockError::DifferentPublicKey(leader, remote.pkey).into());
+        let leader = if self.chain.cfg().secret_leader_election {
+            let seed = mix(election_result.random.rand, remote_view_change);
+            if !pbc::validate_VRF_source(&remote.base.random, &remote.pkey, &seed) {
+                return Err(BlockError::IncorrectRandom(height, remote_hash).into());
+            }
+            if !election_result.is_secret_leader(
+                &remote.pkey,
+                &remote.base.random,
+                self.chain.cfg().secret_leader_candidates,
+            ) {
+                return Err(BlockError::NotEligibleLeader(height, remote_hash, remote.pkey).into());
+            }
+            remote.pkey
+        } else {
+            let leader = election_result.select_leader(remote_view_change);
+            if leader != remote.pkey {
+                return Err(BlockError::DifferentPublicKey(leader, remote.pkey).into());
+            }
+            leader
+        };
+
+        // Check the proof of the remote block.
+        let chain = ChainInfo::from_micro_block(&remote, self.chain.epoch());
+        let remote_proven = match remote.view_change_proof {
+            _ if remote_view_change == 0 => true,
+            Some(ref proof) => match proof.validate(&chain, &self.chain) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(
+                        "Invalid view change proof in a fork: height={}, block={}, view_change={}, error={}",
+                        height, remote_hash, remote_view_change, e
+                    );
+                    false
+                }
+            },
+            None => false,
         };
+        let local_candidate = ForkCandidate {
+            hash: local_hash,
+            random: local.base.random.rand,
+            view_change: local.base.view_change,
+            // Applied blocks have been validated.
+            proven: true,
+        };
+        let remote_candidate = ForkCandidate {
+            hash: remote_hash,
+            random: remote.base.random.rand,
+            view_change: remote_view_change,
+            proven: remote_proven,
+        };
+        let choice = choose_fork(&local_candidate, &remote_candidate);
+
+        if choice == ForkChoice::Same {
+            debug!(
+                "Skip a duplicate block with the same hash: height={}, block={}, current_height={}, last_block={}",
+                height, remote_hash, self.chain.height(), self.chain.last_block_hash(),
+            );
+            return Err(ForkError::Canceled);
+        }
 
         // check multiple blocks with same view_change
         if remote_view_change == local.base.view_change {
-            if remote_hash == local_hash {
-                debug!(
-                    "Skip a duplicate block with the same hash: height={}, block={}, current_height={}, last_block={}",
-                    height, remote_hash, self.chain.height(), self.chain.last_block_hash(),
-                );
-                return Err(ForkError::Canceled);
-            }
-
-            warn!("Two micro-blocks from the same leader detected: height={}, local_block={}, remote_block={}, local_previous={}, remote_previous={}, local_view_change={}, remote_view_change={}, current_height={}, last_block={}",
-                  height,
-                  local_hash,
-                  remote_hash,
-                  local.base.previous,
-                  remote.base.previous,
-                  local.base.view_change,
-                  remote.base.view_change,
-                  self.chain.height(),
-                  self.chain.last_block_hash());
+            if remote.pkey == local.pkey {
+                warn!("Two micro-blocks from the same leader detected: height={}, local_block={}, remote_block={}, local_previous={}, remote_previous={}, local_view_change={}, remote_view_change={}, current_height={}, last_block={}",
+                      height,
+                      local_hash,
+                      remote_hash,
+                      local.base.previous,
+                      remote.base.previous,
+                      local.base.view_change,
+                      remote.base.view_change,
+                      self.chain.height(),
+                      self.chain.last_block_hash());
 
-            metrics::CHEATS.inc();
+                metrics::CHEATS.inc();
 
-            let proof = SlashingProof::new_unchecked(remote.clone(), local);
+                let proof = SlashingProof::new_unchecked(remote.clone(), local);
 
-            if let Some(_proof) = self.cheating_proofs.insert(leader, proof) {
-                debug!("Cheater was already detected: cheater = {}", leader);
+                if let Some(_proof) = self.cheating_proofs.insert(leader, proof) {
+                    debug!("Cheater was already detected: cheater = {}", leader);
+                }
+            } else {
+                // Possible only with the secret leader election.
+                debug!("Two micro-blocks from different eligible leaders: height={}, local_block={}, remote_block={}, local_leader={}, remote_leader={}",
+                       height,
+                       local_hash,
+                       remote_hash,
+                       local.pkey,
+                       remote.pkey);
             }
 
-            return Err(ForkError::Canceled);
-        } else if remote_view_change <= local.base.view_change {
-            debug!(
-                "Found a fork with lower view_change, sending blocks: pkey={}",
-                leader
-            );
-
-            self.send_blocks(leader, height)?;
-            return Err(ForkError::Canceled);
+            if choice == ForkChoice::Local {
+                return Err(ForkError::Canceled);
+            }
+            // All nodes must converge to the block with the lowest random and hash.
+            self.rollback(height)?;
+            self.update_validation_status();
+            return Ok(());
         }
 
-        // Check the proof.
-        let chain = ChainInfo::from_micro_block(&remote);
-        let sealed_proof = match remote.view_change_proof {
-            Some(ref proof) => SealedViewChangeProof {
-                proof: proof.clone(),
-                chain,
-            },
-            None => {
+        if choice == ForkChoice::Local {
+            if remote_view_change > local_candidate.view_change
+                && remote.view_change_proof.is_none()
+            {
                 return Err(BlockError::NoProofWasFound(
                     height,
                     remote_hash,
@@ -521,9 +1430,21 @@ impl NodeService {
                     0,
                 )
                 .into());
+            } else if remote_view_change < local_candidate.view_change {
+                debug!(
+                    "Found a fork with lower view_change, sending blocks: pkey={}",
+                    leader
+                );
+                self.send_blocks(leader, height)?;
             }
-        };
+            return Err(ForkError::Canceled);
+        }
+
         // seal proof, and try to resolve fork.
+        let sealed_proof = SealedViewChangeProof {
+            proof: remote.view_change_proof.clone().expect("proven"),
+            chain,
+        };
         return self.try_rollback(remote.pkey, sealed_proof);
     }
 
@@ -552,6 +1473,8 @@ impl NodeService {
                 }
             };
             ChainInfo {
+                chain_id: local.base.chain_id,
+                epoch: self.chain.epoch(),
                 height: local.base.height,
                 last_block: local.base.previous,
                 view_change: local.base.view_change,
@@ -609,8 +1532,6 @@ impl NodeService {
             return Err(BlockError::InvalidViewChangeProof(height, proof.proof, e).into());
         }
 
-        metrics::FORKS.inc();
-
         warn!(
             "A fork detected: height={}, local_previous={}, remote_previous={}, local_view_change={}, remote_view_change={}, current_height={}, last_block={}",
             height,
@@ -621,26 +1542,95 @@ impl NodeService {
             self.chain.height(),
             self.chain.last_block_hash());
 
+        self.rollback(height)?;
+
+        self.chain
+            .set_view_change(proof.chain.view_change + 1, proof.proof);
+        self.update_validation_status();
+        Ok(())
+    }
+
+    /// Revert micro blocks down to the height.
+    fn rollback(&mut self, height: u64) -> Result<(), Error> {
+        // Never revert blocks protected by checkpoints.
+        self.chain.validate_rollback(height)?;
+
+        metrics::FORKS.inc();
+
         // Truncate the blockchain.
         while self.chain.height() > height {
             let (inputs, outputs) = self.chain.pop_micro_block()?;
             self.last_block_clock = clock::now();
             let msg = OutputsChanged {
                 epoch: self.chain.epoch(),
+                height: self.chain.height(),
+                timestamp: self.chain.clock().now(),
+                reverted: true,
                 inputs,
                 outputs,
+                tx_hashes: HashMap::new(),
             };
             self.on_outputs_changed
                 .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
         }
         assert_eq!(height, self.chain.height());
-
-        self.chain
-            .set_view_change(proof.chain.view_change + 1, proof.proof);
-        self.update_validation_status();
         Ok(())
     }
 
+    /// Process orphan blocks whose parents have been applied.
+    fn process_orphans(&mut self) -> Result<(), Error> {
+        self.orphans.prune(clock::now());
+        loop {
+            let children = self.orphans.take_children(&self.chain.last_block_hash());
+            if children.is_empty() {
+                return Ok(());
+            }
+            let height = self.chain.height();
+            for block in children {
+                debug!(
+                    "Processing an orphan block: height={}, block={}",
+                    block.base_header().height,
+                    Hash::digest(&block)
+                );
+                self.handle_block(block)?;
+            }
+            if self.chain.height() == height {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle a serialized block received from the network.
+    fn handle_block_buffer(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        validate_block_buffer(&data, self.chain.cfg().max_block_size)?;
+        if data.len() > self.cfg.stream_validation_threshold {
+            if let Some(stream) = MicroBlockStream::from_block_buffer(&data)? {
+                // Validate blocks on the top of the chain transaction-by-transaction,
+                // an invalid block is rejected before all transactions are decoded.
+                let base = &stream.header().base;
+                if base.height == self.chain.height()
+                    && base.previous == self.chain.last_block_hash()
+                {
+                    debug!(
+                        "Validating a large micro block: height={}, size={}, transactions={}",
+                        base.height,
+                        stream.size(),
+                        stream.len()
+                    );
+                    let timestamp = self.chain.clock().now();
+                    match self.chain.validate_micro_block_stream(&stream, timestamp) {
+                        Ok(validated) => return self.apply_validated_micro_block(validated),
+                        // Outdated view changes are answered by handle_block().
+                        Err(BlockchainError::BlockError(BlockError::InvalidViewChange(..))) => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+        let block = Block::from_buffer(&data)?;
+        self.handle_block(block)
+    }
+
     /// Handle incoming blocks received from network.
     fn handle_block(&mut self, block: Block) -> Result<(), Error> {
         let block_hash = Hash::digest(&block);
@@ -666,14 +1656,15 @@ impl NodeService {
             );
             return Ok(());
         } else if block_height > self.chain.height() {
-            // An orphan block from later epochs - ignore.
-            warn!("Skipped an orphan block from the future: height={}, block={}, current_height={}, last_block={}",
+            // An orphan block from later epochs - keep until the parent arrives.
+            warn!("Received an orphan block from the future: height={}, block={}, current_height={}, last_block={}",
                   block_height,
                   block_hash,
                   self.chain.height(),
                   self.chain.last_block_hash()
             );
-            self.request_history()?;
+            self.orphans.insert(block_hash, block, clock::now());
+            self.on_future_block(block_height)?;
             return Ok(());
         }
         // A block from the current epoch.
@@ -735,6 +1726,10 @@ impl NodeService {
 
         assert_eq!(block_height, self.chain.height());
         let view_change = block.base_header().view_change;
+        let sender = match block {
+            Block::MicroBlock(ref block) if self.chain.cfg().secret_leader_election => block.pkey,
+            _ => self.chain.select_leader(view_change),
+        };
         let r = match block {
             Block::MacroBlock(block) => self.apply_macro_block(block),
             Block::MicroBlock(block) => self.apply_micro_block(block),
@@ -749,16 +1744,17 @@ impl NodeService {
             match e.downcast::<BlockchainError>() {
                 Ok(BlockchainError::BlockError(BlockError::InvalidPreviousHash(..))) => {
                     // A potential fork - request history from that node.
-                    let from = self.chain.select_leader(view_change);
-                    self.request_history_from(from)?;
+                    self.request_history_from(sender)?;
                 }
                 Ok(BlockchainError::BlockError(BlockError::InvalidViewChange(..))) => {
                     assert!(self.chain.view_change() > 0);
                     assert!(view_change < self.chain.view_change());
-                    let leader = self.chain.select_leader(view_change);
+                    let leader = sender;
                     warn!("Discarded a block with lesser view_change: block_view_change={}, our_view_change={}",
                           view_change, self.chain.view_change());
                     let chain_info = ChainInfo {
+                        chain_id: self.chain.chain_id(),
+                        epoch: self.chain.epoch(),
                         height: self.chain.height(),
                         // correct information about proof, to refer previous on view_change;
                         view_change: self.chain.view_change() - 1,
@@ -816,6 +1812,7 @@ impl NodeService {
             }
         }
 
+        let finalized_height = self.chain.finalized_height();
         let (inputs, outputs) = self.chain.push_macro_block(block, timestamp)?;
 
         if !was_synchronized && self.is_synchronized() {
@@ -827,16 +1824,30 @@ impl NodeService {
             metrics::SYNCHRONIZED.set(1);
         }
 
-        let msg = EpochChanged {
-            epoch: self.chain.epoch(),
-            validators: self.chain.validators().clone(),
-            facilitator: self.chain.facilitator().clone(),
-        };
+        let msg = self.epoch_changed();
         self.on_epoch_changed
             .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+        if self.chain.finalized_height() > finalized_height {
+            if let Some(msg) = self.chain.finality_commitment().cloned() {
+                self.on_finality_changed
+                    .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+            }
+        }
         self.cheating_proofs.clear();
+        self.update_stem_relays();
+        self.update_staked_nodes();
+        self.prewarm_validators();
 
-        self.on_block_added(height, view_change, hash, timestamp, inputs, outputs);
+        let tx_hashes = HashMap::new();
+        self.on_block_added(
+            height,
+            view_change,
+            hash,
+            timestamp,
+            inputs,
+            outputs,
+            tx_hashes,
+        );
         self.update_validation_status();
 
         Ok(())
@@ -845,23 +1856,56 @@ impl NodeService {
     /// Try to apply a new micro block into the blockchain.
     fn apply_micro_block(&mut self, block: MicroBlock) -> Result<(), Error> {
         let hash = Hash::digest(&block);
-        let timestamp = block.base.timestamp;
-        let height = block.base.height;
-        let view_change = block.base.view_change;
+        self.check_micro_block_order(&hash)?;
+        let (tx_hashes, block_txs) = micro_block_tx_hashes(&block);
+        let base = block.base.clone();
+        let (inputs, outputs) = self.chain.push_micro_block(block, base.timestamp)?;
+        self.on_micro_block_added(&base, hash, inputs, outputs, tx_hashes, &block_txs);
+        Ok(())
+    }
 
-        // Check for the correct block order.
+    /// Apply a micro block validated by `validate_micro_block_stream()`.
+    fn apply_validated_micro_block(&mut self, validated: ValidatedMicroBlock) -> Result<(), Error> {
+        let hash = *validated.block_hash();
+        self.check_micro_block_order(&hash)?;
+        let (tx_hashes, block_txs) = micro_block_tx_hashes(validated.block());
+        let base = validated.block().base.clone();
+        let (inputs, outputs) = self
+            .chain
+            .push_validated_micro_block(validated, base.timestamp)?;
+        self.on_micro_block_added(&base, hash, inputs, outputs, tx_hashes, &block_txs);
+        Ok(())
+    }
+
+    /// Check for the correct block order.
+    fn check_micro_block_order(&self, hash: &Hash) -> Result<(), Error> {
         match &self.validation {
-            MicroBlockAuditor | MicroBlockValidator { .. } => {}
-            _ => {
-                return Err(NodeBlockError::ExpectedMicroBlock(self.chain.height(), hash).into());
-            }
+            MicroBlockAuditor | MicroBlockValidator { .. } => Ok(()),
+            _ => Err(NodeBlockError::ExpectedMicroBlock(self.chain.height(), *hash).into()),
         }
+    }
 
-        let (inputs, outputs) = self.chain.push_micro_block(block, timestamp)?;
-        self.on_block_added(height, view_change, hash, timestamp, inputs, outputs);
+    fn on_micro_block_added(
+        &mut self,
+        base: &BaseBlockHeader,
+        hash: Hash,
+        inputs: Vec<Output>,
+        outputs: Vec<Output>,
+        tx_hashes: HashMap<Hash, Hash>,
+        block_txs: &[Hash],
+    ) {
+        self.fees.on_block(base.height, block_txs);
+        consensus::metrics::VIEW_CHANGES.observe(f64::from(base.view_change));
+        self.on_block_added(
+            base.height,
+            base.view_change,
+            hash,
+            base.timestamp,
+            inputs,
+            outputs,
+            tx_hashes,
+        );
         self.update_validation_status();
-
-        Ok(())
     }
 
     fn on_block_added(
@@ -872,6 +1916,7 @@ impl NodeService {
         timestamp: SystemTime,
         inputs: Vec<Output>,
         outputs: Vec<Output>,
+        tx_hashes: HashMap<Hash, Hash>,
     ) {
         // Remove old transactions from the mempool.
         let input_hashes: Vec<Hash> = inputs.iter().map(|o| Hash::digest(o)).collect();
@@ -881,18 +1926,29 @@ impl NodeService {
         metrics::MEMPOOL_INPUTS.set(self.mempool.inputs_len() as i64);
         metrics::MEMPOOL_OUTPUTS.set(self.mempool.inputs_len() as i64);
 
+        // Track local transactions.
+        let conflicts = self.broadcaster.on_block_added(&input_hashes, &tx_hashes);
+        self.notify_transaction_conflicts(conflicts);
+        self.rebroadcast_transactions();
+
         // Notify subscribers.
         let msg = OutputsChanged {
             epoch: self.chain.epoch(),
+            height,
+            timestamp,
+            reverted: false,
             inputs,
             outputs,
+            tx_hashes,
         };
         self.on_outputs_changed
             .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
 
         self.last_block_clock = clock::now();
+        self.partition.on_block();
+        self.check_partition();
 
-        let local_timestamp = metrics::time_to_timestamp_ms(SystemTime::now());
+        let local_timestamp = metrics::time_to_timestamp_ms(self.chain.clock().now());
         let remote_timestamp = metrics::time_to_timestamp_ms(timestamp);
         let lag = local_timestamp - remote_timestamp;
         metrics::BLOCK_REMOTE_TIMESTAMP.set(remote_timestamp);
@@ -911,6 +1967,19 @@ impl NodeService {
         };
         self.on_block_added
             .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+
+        if !self.on_random_beacon.is_empty() {
+            match self.chain.random_at(height) {
+                Ok(Some(beacon)) => self
+                    .on_random_beacon
+                    .retain(move |ch| ch.unbounded_send(beacon.clone()).is_ok()),
+                Ok(None) => unreachable!("block exists"),
+                Err(e) => error!(
+                    "Failed to get random beacon: height={}, error={}",
+                    height, e
+                ),
+            }
+        }
     }
 
     /// Handler for NodeMessage::SubscribeHeight.
@@ -919,18 +1988,47 @@ impl NodeService {
         Ok(())
     }
 
-    /// Handler for NodeMessage::SubscribeEpoch.
-    fn handle_subscribe_epoch(&mut self, tx: UnboundedSender<EpochChanged>) -> Result<(), Error> {
-        let msg = EpochChanged {
+    /// Creates EpochChanged notification for the current epoch.
+    fn epoch_changed(&self) -> EpochChanged {
+        EpochChanged {
             epoch: self.chain.epoch(),
             validators: self.chain.validators().clone(),
             facilitator: self.chain.facilitator().clone(),
-        };
+            random: self.chain.last_random(),
+            service_award: self.chain.last_service_award(),
+            service_awards_budget: self.chain.service_awards_budget(),
+        }
+    }
+
+    /// Handler for NodeMessage::SubscribeEpoch.
+    fn handle_subscribe_epoch(&mut self, tx: UnboundedSender<EpochChanged>) -> Result<(), Error> {
+        let msg = self.epoch_changed();
         tx.unbounded_send(msg).ok(); // ignore error.
         self.on_epoch_changed.push(tx);
         Ok(())
     }
 
+    /// Handler for NodeMessage::SubscribeFinalityChanged.
+    fn handle_subscribe_finality(
+        &mut self,
+        tx: UnboundedSender<FinalityCommitment>,
+    ) -> Result<(), Error> {
+        if let Some(msg) = self.chain.finality_commitment() {
+            tx.unbounded_send(msg.clone()).ok(); // ignore error.
+        }
+        self.on_finality_changed.push(tx);
+        Ok(())
+    }
+
+    /// Handler for NodeMessage::SubscribeRandomBeacon.
+    fn handle_subscribe_random_beacon(
+        &mut self,
+        tx: UnboundedSender<RandomBeacon>,
+    ) -> Result<(), Error> {
+        self.on_random_beacon.push(tx);
+        Ok(())
+    }
+
     /// Handler for NodeMessage::SubscribeOutputs.
     fn handle_subscribe_outputs(
         &mut self,
@@ -940,20 +2038,190 @@ impl NodeService {
         Ok(())
     }
 
+    /// Handler for NodeMessage::SubscribeTransactionConflicts.
+    fn handle_subscribe_transaction_conflicts(
+        &mut self,
+        tx: UnboundedSender<TransactionConflict>,
+    ) -> Result<(), Error> {
+        self.on_transaction_conflict.push(tx);
+        Ok(())
+    }
+
+    fn handle_recover_wallet(
+        &mut self,
+        wallet_skey: SecretKey,
+        wallet_pkey: PublicKey,
+        tx: oneshot::Sender<(Vec<(Output, u64)>, UnboundedReceiver<OutputsChanged>)>,
+    ) -> Result<(), Error> {
+        let outputs = self.chain.recover_wallet(&wallet_skey, &wallet_pkey)?;
+        debug!(
+            "Recovered wallet: wallet_pkey={}, outputs={}",
+            wallet_pkey,
+            outputs.len()
+        );
+        let (outputs_tx, outputs_rx) = unbounded();
+        self.on_outputs_changed.push(outputs_tx);
+        tx.send((outputs, outputs_rx)).ok(); // ignore errors.
+        Ok(())
+    }
+
+    /// Publish the status of our chain, validators only.
+    fn send_chain_status(&mut self) -> Result<(), Error> {
+        let validators: Vec<pbc::PublicKey> =
+            self.chain.validators().iter().map(|(k, _)| *k).collect();
+        self.statuses.retain(&validators);
+        if !self.is_validator() {
+            return Ok(());
+        }
+        let status = ChainStatus::new(
+            &self.keys.network_skey,
+            &self.keys.network_pkey,
+            self.chain.epoch(),
+            self.chain.height(),
+            self.chain.last_block_hash(),
+            self.chain.view_change(),
+            self.network_time.now(),
+        );
+        trace!(
+            "Sending chain status: epoch={}, height={}, last_block={}",
+            status.epoch,
+            status.height,
+            status.last_block_hash
+        );
+        self.network
+            .publish(CHAIN_STATUS_TOPIC, status.into_buffer()?)?;
+        Ok(())
+    }
+
+    /// Handle a status of the chain received from a validator.
+    fn handle_chain_status(&mut self, status: ChainStatus) -> Result<(), Error> {
+        if status.pkey == self.keys.network_pkey || !self.chain.is_validator(&status.pkey) {
+            return Ok(());
+        }
+        status.validate()?;
+        self.network_time.check(&status.pkey, status.timestamp)?;
+        if !self.statuses.update(status.clone()) {
+            return Ok(());
+        }
+        debug!(
+            "Received chain status: from={}, epoch={}, height={}, last_block={}, view_change={}",
+            status.pkey, status.epoch, status.height, status.last_block_hash, status.view_change
+        );
+
+        let height = self.chain.height();
+        if status.height > height {
+            self.loader
+                .on_remote_height(Some(status.pkey), status.height);
+            if let Some((pkey, best_height)) = self.statuses.behind(height) {
+                info!(
+                    "We are behind the network: our_height={}, best_height={}, peer={}",
+                    height, best_height, pkey
+                );
+                self.request_history_from(pkey)?;
+            }
+        } else if status.height == height && status.last_block_hash != self.chain.last_block_hash()
+        {
+            warn!(
+                "Chain head differs from a validator: height={}, our_block={}, their_block={}, peer={}",
+                height,
+                self.chain.last_block_hash(),
+                status.last_block_hash,
+                status.pkey
+            );
+        }
+        Ok(())
+    }
+
+    /// Switches to the safe mode and back depending on reachability of validators.
+    fn check_partition(&mut self) {
+        let mut reachable: Vec<pbc::PublicKey> = self
+            .statuses
+            .peer_statuses()
+            .into_iter()
+            .map(|s| s.pkey)
+            .collect();
+        reachable.push(self.keys.network_pkey);
+        if let Some(safe_mode) = self.partition.update(self.chain.validators(), &reachable) {
+            metrics::SAFE_MODE.set(safe_mode as i64);
+        }
+        metrics::REACHABLE_STAKE.set(self.partition.reachable_percent());
+        metrics::MISSED_ROUNDS.set(self.partition.missed_rounds() as i64);
+    }
+
+    /// Returns the latest chain statuses of validators.
+    pub(crate) fn peer_statuses(&self) -> Vec<PeerStatus> {
+        self.statuses.peer_statuses()
+    }
+
+    /// Handler for NodeRequest::RoutingTable.
+    fn handle_routing_table_request(
+        &mut self,
+        tx: oneshot::Sender<NodeResponse>,
+    ) -> Result<(), Error> {
+        let rx = self
+            .network
+            .dump_buckets()?
+            .map(|buckets| NodeResponse::RoutingTable { buckets });
+        self.network_requests.push((Box::new(rx), tx));
+        Ok(())
+    }
+
+    /// Handler for NodeRequest::QueryJournal.
+    fn handle_query_journal_request(
+        &mut self,
+        tx: oneshot::Sender<NodeResponse>,
+    ) -> Result<(), Error> {
+        let rx = self
+            .network
+            .query_journal()?
+            .map(|queries| NodeResponse::QueryJournal { queries });
+        self.network_requests.push((Box::new(rx), tx));
+        Ok(())
+    }
+
+    /// Handler for NodeRequest::Status.
+    fn handle_status_request(&mut self, tx: oneshot::Sender<NodeResponse>) -> Result<(), Error> {
+        let validator = self
+            .chain
+            .validators()
+            .iter()
+            .find(|(pkey, _slots)| *pkey == self.keys.network_pkey)
+            .map(|(pkey, slots)| ValidatorStatus {
+                slots: *slots,
+                total_slots: self.chain.total_slots(),
+                consensus: self.cfg.role.runs_consensus(),
+                facilitator: self.chain.facilitator() == pkey,
+            });
+        let uptime = clock::now().duration_since(self.started_clock);
+        let mut status = NodeStatus {
+            network_pkey: self.keys.network_pkey,
+            epoch: self.chain.epoch(),
+            height: self.chain.height(),
+            view_change: self.chain.view_change(),
+            last_block_hash: self.chain.last_block_hash(),
+            last_macro_block_height: self.chain.last_macro_block_height(),
+            last_macro_block_hash: self.chain.last_macro_block_hash(),
+            synchronized: self.is_synchronized(),
+            validator,
+            peers: PeerCounts::default(),
+            mempool_transactions: self.mempool.len(),
+            safe_mode: self.partition.safe_mode(),
+            resources: ResourceUsage::current(uptime),
+        };
+        // Peers are counted by the network service.
+        let rx = self.network.dump_buckets()?.map(move |buckets| {
+            status.peers = PeerCounts::from_buckets(&buckets);
+            NodeResponse::Status(status)
+        });
+        self.network_requests.push((Box::new(rx), tx));
+        Ok(())
+    }
+
     /// Handler for NodeMessage::PopBlock.
     fn handle_pop_block(&mut self) -> Result<(), Error> {
         warn!("Received a request to revert the latest block");
         if self.chain.blocks_in_epoch() > 1 {
-            let (inputs, outputs) = self.chain.pop_micro_block()?;
-            self.last_block_clock = clock::now();
-            let msg = OutputsChanged {
-                epoch: self.chain.epoch(),
-                inputs,
-                outputs,
-            };
-            self.on_outputs_changed
-                .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
-            self.update_validation_status()
+            self.revert_micro_block()?;
         } else {
             error!(
                 "Attempt to revert a macro block: height={}",
@@ -963,6 +2231,76 @@ impl NodeService {
         Ok(())
     }
 
+    /// Reverts the latest micro block and notifies subscribers.
+    fn revert_micro_block(&mut self) -> Result<(), Error> {
+        let (inputs, outputs) = self.chain.pop_micro_block()?;
+        self.last_block_clock = clock::now();
+        let msg = OutputsChanged {
+            epoch: self.chain.epoch(),
+            height: self.chain.height(),
+            timestamp: self.chain.clock().now(),
+            reverted: true,
+            inputs,
+            outputs,
+            tx_hashes: HashMap::new(),
+        };
+        self.on_outputs_changed
+            .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+        self.update_validation_status();
+        Ok(())
+    }
+
+    /// Handler for NodeRequest::RepairDatabase.
+    fn repair_database(&mut self) -> Result<NodeResponse, Error> {
+        warn!("Received a request to repair the database");
+        let old_height = self.chain.height();
+        let mut restart_required = false;
+        if let Some(damaged_height) = self.chain.check_database() {
+            warn!(
+                "Found a damaged block on the disk: height={}, current_height={}",
+                damaged_height, old_height
+            );
+            // Damaged micro blocks of the current epoch are reverted in place,
+            // anything deeper is rolled back by the integrity check on the next start.
+            while self.chain.height() > damaged_height && self.chain.blocks_in_epoch() > 1 {
+                let height = self.chain.height();
+                if !self.chain.is_block_readable(height - 1)
+                    || !self.chain.is_block_readable(height - 2)
+                {
+                    break;
+                }
+                if let Err(e) = self.revert_micro_block() {
+                    error!("Failed to revert a damaged block: error={}", e);
+                    break;
+                }
+            }
+            if self.chain.height() > damaged_height {
+                restart_required = true;
+            } else {
+                let removed = self.chain.truncate_database()?;
+                debug!(
+                    "Removed leftovers from the disk: removed_blocks={}",
+                    removed
+                );
+            }
+        }
+        if !restart_required {
+            self.chain.compact_database();
+        }
+        let height = self.chain.height();
+        info!(
+            "Repaired the database: height={}, removed_blocks={}, restart_required={}",
+            height,
+            old_height - height,
+            restart_required
+        );
+        Ok(NodeResponse::DatabaseRepaired {
+            height,
+            removed_blocks: old_height - height,
+            restart_required,
+        })
+    }
+
     /// Send block to network.
     fn send_block(&mut self, block: Block) -> Result<(), Error> {
         let block_hash = Hash::digest(&block);
@@ -983,13 +2321,13 @@ impl NodeService {
 
     /// Called when a leader for the next micro block has changed.
     fn on_micro_block_leader_changed(&mut self) {
+        let is_leader = self.is_micro_block_leader();
         let block_timer = match &mut self.validation {
             MicroBlockValidator { block_timer, .. } => block_timer,
             _ => panic!("Expected MicroBlockValidator State"),
         };
 
-        let leader = self.chain.leader();
-        if leader == self.keys.network_pkey {
+        if is_leader {
             info!(
                 "I'm leader, collecting transactions for the next micro block: height={}, view_change={}, last_block={}",
                 self.chain.height(),
@@ -1006,12 +2344,21 @@ impl NodeService {
                 clock::now()
             };
             std::mem::replace(block_timer, BlockTimer::Propose(Delay::new(deadline)));
+        } else if self.chain.cfg().secret_leader_election {
+            info!("I'm validator, waiting for the next micro block from a secret leader: height={}, view_change={}, last_block={}",
+                  self.chain.height(),
+                  self.chain.view_change(),
+                  self.chain.last_block_hash());
+            consensus::metrics::CONSENSUS_ROLE
+                .set(consensus::metrics::ConsensusRole::Validator as i64);
+            let deadline = clock::now() + self.cfg.micro_block_timeout;
+            std::mem::replace(block_timer, BlockTimer::ViewChange(Delay::new(deadline)));
         } else {
             info!("I'm validator, waiting for the next micro block: height={}, view_change={}, last_block={}, leader={}",
                   self.chain.height(),
                   self.chain.view_change(),
                   self.chain.last_block_hash(),
-                  leader);
+                  self.chain.leader());
             consensus::metrics::CONSENSUS_ROLE
                 .set(consensus::metrics::ConsensusRole::Validator as i64);
             let deadline = clock::now() + self.cfg.micro_block_timeout;
@@ -1021,6 +2368,27 @@ impl NodeService {
         task::current().notify();
     }
 
+    /// Returns true if this node can create the next micro block.
+    /// With the secret leader election, evaluates VRF to check eligibility.
+    fn is_micro_block_leader(&self) -> bool {
+        if !self.chain.cfg().secret_leader_election {
+            return self.chain.leader() == self.keys.network_pkey;
+        }
+        let view_change = self.chain.view_change();
+        let random = match self
+            .signer
+            .make_random(self.chain.last_random(), view_change)
+        {
+            Ok(random) => random,
+            Err(e) => {
+                error!("Failed to evaluate VRF: error={}", e);
+                return false;
+            }
+        };
+        self.chain
+            .is_micro_block_leader(&self.keys.network_pkey, view_change, &random)
+    }
+
     /// Called when a leader for the next macro block has changed.
     fn on_macro_block_leader_changed(&mut self) {
         let (block_timer, consensus) = match &mut self.validation {
@@ -1064,14 +2432,38 @@ impl NodeService {
         task::current().notify();
     }
 
+    /// Returns true if this node is elected and participates in consensus.
+    fn is_validator(&self) -> bool {
+        self.cfg.role.runs_consensus() && self.chain.is_validator(&self.keys.network_pkey)
+    }
+
     ///
     /// Change validation status after applying a new block or performing a view change.
     ///
     fn update_validation_status(&mut self) {
+        // Validators follow all transaction shards to build blocks.
+        let is_validator = self.is_validator();
+        if !is_validator
+            && self.chain.blocks_in_epoch() == 0
+            && self.chain.is_validator(&self.keys.network_pkey)
+        {
+            warn!(
+                "Elected as validator, but consensus is disabled by role: role={:?}, epoch={}",
+                self.cfg.role,
+                self.chain.epoch()
+            );
+        }
+        if let Err(e) = self
+            .tx_shards
+            .update(&self.network, &self.keys.network_pkey, is_validator)
+        {
+            error!("Failed to update transaction shards: {}", e);
+        }
+
         if self.chain.blocks_in_epoch() < self.cfg.blocks_in_epoch {
             // Expected Micro Block.
             let _prev = std::mem::replace(&mut self.validation, MicroBlockAuditor);
-            if !self.chain.is_validator(&self.keys.network_pkey) {
+            if !is_validator {
                 info!("I'm auditor, waiting for the next micro block: height={}, view_change={}, last_block={}",
                       self.chain.height(),
                       self.chain.view_change(),
@@ -1082,11 +2474,7 @@ impl NodeService {
                 return;
             }
 
-            let view_change_collector = ViewChangeCollector::new(
-                &self.chain,
-                self.keys.network_pkey,
-                self.keys.network_skey.clone(),
-            );
+            let view_change_collector = ViewChangeCollector::new(&self.chain, self.signer.clone());
 
             self.validation = MicroBlockValidator {
                 view_change_collector,
@@ -1097,7 +2485,7 @@ impl NodeService {
         } else {
             // Expected Macro Block.
             let prev = std::mem::replace(&mut self.validation, MacroBlockAuditor);
-            if !self.chain.is_validator(&self.keys.network_pkey) {
+            if !is_validator {
                 info!(
                     "I'm auditor, waiting for the next macro block: height={}, last_block={}",
                     self.chain.height(),
@@ -1111,8 +2499,7 @@ impl NodeService {
             let mut consensus = Consensus::new(
                 self.chain.height() as u64,
                 self.chain.epoch() + 1,
-                self.keys.network_skey.clone(),
-                self.keys.network_pkey.clone(),
+                self.signer.clone(),
                 self.chain.election_result(),
                 self.chain.validators().iter().cloned().collect(),
             );
@@ -1124,7 +2511,15 @@ impl NodeService {
             } = prev
             {
                 for msg in future_consensus_messages {
-                    if let Err(e) = consensus.feed_message(msg) {
+                    // Drop messages which became too old while buffered.
+                    if !self.network_time.is_within_window(msg.timestamp) {
+                        debug!(
+                            "Dropped outdated future consensus message: pkey={}",
+                            msg.pkey
+                        );
+                        continue;
+                    }
+                    if let Err(e) = consensus.feed_verified_message(msg) {
                         debug!("Error in future consensus message: {}", e);
                     }
                 }
@@ -1145,10 +2540,35 @@ impl NodeService {
     /// Handles incoming consensus requests received from network.
     ///
     fn handle_consensus_message(&mut self, msg: ConsensusMessage) -> Result<(), Error> {
-        let consensus = match &mut self.validation {
+        match &self.validation {
+            MicroBlockAuditor | MacroBlockAuditor => {
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let checks = vec![msg.signature_check()];
+        self.verify_message(checks, PendingMessage::Consensus(msg));
+        Ok(())
+    }
+
+    ///
+    /// Handles a consensus message with a valid signature.
+    ///
+    fn on_consensus_message_verified(&mut self, msg: ConsensusMessage) -> Result<(), Error> {
+        // The state could change while the signature was being checked.
+        match &self.validation {
             MicroBlockAuditor | MacroBlockAuditor => {
                 return Ok(());
             }
+            _ => {}
+        }
+
+        // Check timestamp before the message can shift network time.
+        self.network_time.check(&msg.pkey, msg.timestamp)?;
+
+        let consensus = match &mut self.validation {
+            MicroBlockAuditor | MacroBlockAuditor => unreachable!(),
             MicroBlockValidator {
                 future_consensus_messages,
                 ..
@@ -1162,11 +2582,56 @@ impl NodeService {
         };
 
         // Feed message into consensus module.
-        consensus.feed_message(msg)?;
+        consensus.feed_verified_message(msg)?;
         self.handle_consensus_events();
         Ok(())
     }
 
+    ///
+    /// Checks signatures of a message on the verifier threads.
+    ///
+    fn verify_message(&mut self, checks: Vec<SignatureCheck>, msg: PendingMessage) {
+        if self.pending_verifications.len() >= MAX_PENDING_VERIFICATIONS {
+            warn!(
+                "Dropped a message: too many messages waiting for verification: len={}",
+                self.pending_verifications.len()
+            );
+            return;
+        }
+        let rx = self.verifier.verify(checks);
+        self.pending_verifications.push_back((rx, msg));
+        self.poll_verifications();
+    }
+
+    ///
+    /// Processes messages with checked signatures, in the order of arrival.
+    ///
+    fn poll_verifications(&mut self) {
+        while let Some((rx, _msg)) = self.pending_verifications.front_mut() {
+            let valid = match rx.poll() {
+                Ok(Async::Ready(valid)) => valid,
+                Ok(Async::NotReady) => break,
+                Err(_) => false, // the worker has panicked.
+            };
+            let (_rx, msg) = self.pending_verifications.pop_front().unwrap();
+            let result = match (valid, msg) {
+                (true, PendingMessage::Consensus(msg)) => self.on_consensus_message_verified(msg),
+                (true, PendingMessage::ViewChange(msg)) => {
+                    self.on_view_change_message_verified(msg)
+                }
+                (false, PendingMessage::Consensus(_)) => {
+                    Err(consensus::ConsensusError::InvalidMessageSignature.into())
+                }
+                (false, PendingMessage::ViewChange(_)) => {
+                    Err(consensus::ConsensusError::InvalidViewChangeSignature.into())
+                }
+            };
+            if let Err(e) = result {
+                error!("Error: {}", e);
+            }
+        }
+    }
+
     fn handle_consensus_events(&mut self) {
         let consensus = match &mut self.validation {
             MacroBlockValidator { consensus, .. } => consensus,
@@ -1209,7 +2674,7 @@ impl NodeService {
 
     /// True if the node is synchronized with the network.
     fn is_synchronized(&self) -> bool {
-        let timestamp = SystemTime::now();
+        let timestamp = self.chain.clock().now();
         let block_timestamp = self.chain.last_macro_block_timestamp();
         block_timestamp
             + self.cfg.micro_block_timeout * (self.cfg.blocks_in_epoch as u32)
@@ -1236,15 +2701,27 @@ impl NodeService {
         std::mem::replace(block_timer, BlockTimer::ViewChange(Delay::new(deadline)));
         task::current().notify();
 
+        if self.partition.safe_mode() {
+            warn!(
+                "Safe mode: refusing to propose a macro block: height={}, view_change={}",
+                self.chain.height(),
+                consensus.round()
+            );
+            return Ok(());
+        }
+
         // Propose a new block.
+        let random = self
+            .signer
+            .make_random(self.chain.last_random(), consensus.round())?;
         let (block, block_proposal) = proposal::create_macro_block_proposal(
             &self.chain,
             consensus.round(),
-            self.cfg.block_reward,
+            self.chain.block_reward(self.chain.height()),
             &self.keys.wallet_pkey,
-            &self.keys.network_skey,
             &self.keys.network_pkey,
-        );
+            random,
+        )?;
         let block_hash = Hash::digest(&block);
         consensus.propose(block_hash, block_proposal);
         consensus.prevote(block);
@@ -1280,6 +2757,8 @@ impl NodeService {
         // Go to the next round.
         metrics::MACRO_BLOCK_VIEW_CHANGES.inc();
         consensus.next_round();
+        self.partition.on_round_timeout();
+        self.check_partition();
         self.on_macro_block_leader_changed();
         self.handle_consensus_events();
 
@@ -1304,8 +2783,162 @@ impl NodeService {
         return Ok(());
     }
 
+    /// State of the view change collector.
+    fn view_change_info(&self) -> NodeResponse {
+        let collector = match &self.validation {
+            MicroBlockValidator {
+                view_change_collector,
+                ..
+            } => Some(view_change_collector.info(&self.chain)),
+            _ => None,
+        };
+        NodeResponse::ViewChangeInfo {
+            height: self.chain.height(),
+            view_change: self.chain.view_change(),
+            last_block: self.chain.last_block_hash(),
+            collector,
+        }
+    }
+
+    /// Check that all outputs of a proof of reserves are unspent and the proof is valid.
+    fn validate_reserve_proof(&self, proof: ReserveProof) -> NodeResponse {
+        let mut outputs = Vec::with_capacity(proof.outputs.len());
+        for output_hash in &proof.outputs {
+            match self.chain.output_by_hash(output_hash) {
+                Ok(Some(output)) => outputs.push(output),
+                Err(e) => {
+                    return NodeResponse::Error {
+                        error: format!("{}", e),
+                    };
+                }
+                Ok(None) => {
+                    let e = ReserveProofError::MissingOutput(*output_hash);
+                    return NodeResponse::Error {
+                        error: format!("{}", e),
+                    };
+                }
+            }
+        }
+        if let Err(e) = proof.validate(&outputs) {
+            return NodeResponse::Error {
+                error: format!("{}", e),
+            };
+        }
+        info!(
+            "Validated reserve proof: challenge={}, outputs={}, amount={}",
+            proof.challenge,
+            proof.outputs.len(),
+            proof.amount
+        );
+        NodeResponse::ReserveProofValidated {
+            challenge: proof.challenge,
+            amount: proof.amount,
+            height: self.chain.height(),
+        }
+    }
+
+    /// Apply a view change proof submitted via API.
+    fn submit_view_change_proof(&mut self, proof: SealedViewChangeProof) -> NodeResponse {
+        debug!("Submitted sealed view change proof: proof = {:?}", proof);
+        // The leader after the view change has the blocks.
+        let pkey = self.chain.select_leader(proof.chain.view_change + 1);
+        let applied = match self.try_rollback(pkey, proof) {
+            Ok(()) => true,
+            Err(ForkError::Canceled) => false,
+            Err(ForkError::Error(e)) => {
+                return NodeResponse::Error {
+                    error: format!("{}", e),
+                }
+            }
+        };
+        NodeResponse::ViewChangeProofSubmitted {
+            height: self.chain.height(),
+            view_change: self.chain.view_change(),
+            applied,
+        }
+    }
+
+    /// Handler for NodeRequest::BlockTemplate.
+    fn block_template(&mut self) -> NodeResponse {
+        if self.chain.blocks_in_epoch() >= self.cfg.blocks_in_epoch {
+            return NodeResponse::Error {
+                error: "Waiting for a macro block".to_string(),
+            };
+        }
+        if self.partition.safe_mode() {
+            return NodeResponse::Error {
+                error: "Node is in safe mode: network partition detected".to_string(),
+            };
+        }
+        match self.prepare_micro_block() {
+            Ok(block) => NodeResponse::BlockTemplate {
+                height: block.base.height,
+                view_change: block.base.view_change,
+                leader: self.chain.leader(),
+                block_hash: Hash::digest(&block),
+                block,
+            },
+            Err(e) => NodeResponse::Error {
+                error: format!("{}", e),
+            },
+        }
+    }
+
+    /// Handler for NodeRequest::SubmitBlock.
+    fn submit_block(&mut self, block: MicroBlock) -> NodeResponse {
+        let block_hash = Hash::digest(&block);
+        let height = block.base.height;
+        info!(
+            "Submitted a micro block: height={}, view_change={}, block={}, transactions={}",
+            height,
+            block.base.view_change,
+            block_hash,
+            block.transactions.len()
+        );
+        if height != self.chain.height() || block.base.previous != self.chain.last_block_hash() {
+            return NodeResponse::Error {
+                error: format!(
+                    "Block is not on the top of the chain: height={}, previous={}, our_height={}, last_block={}",
+                    height,
+                    block.base.previous,
+                    self.chain.height(),
+                    self.chain.last_block_hash()
+                ),
+            };
+        }
+        // Fully validates the block, including the leader and its signature.
+        let block2 = block.clone();
+        if let Err(e) = self.apply_micro_block(block) {
+            return NodeResponse::Error {
+                error: format!("{}", e),
+            };
+        }
+        if let Err(e) = self.send_block(Block::MicroBlock(block2)) {
+            error!(
+                "Failed to send submitted block: block={}, error={}",
+                block_hash, e
+            );
+        }
+        NodeResponse::BlockSubmitted { height, block_hash }
+    }
+
     /// Handle incoming view_change message from the network.
     fn handle_view_change_message(&mut self, msg: ViewChangeMessage) -> Result<(), Error> {
+        match &self.validation {
+            MicroBlockValidator { .. } => {}
+            _ => {
+                // Ignore message.
+                return Ok(());
+            }
+        }
+
+        let checks = msg.signature_checks(&self.chain)?;
+        self.verify_message(checks, PendingMessage::ViewChange(msg));
+        Ok(())
+    }
+
+    /// Handle a view_change message with valid signatures.
+    fn on_view_change_message_verified(&mut self, msg: ViewChangeMessage) -> Result<(), Error> {
         let view_change_collector = match &mut self.validation {
             MicroBlockValidator {
                 view_change_collector,
@@ -1317,7 +2950,20 @@ impl NodeService {
             }
         };
 
-        if let Some(proof) = view_change_collector.handle_message(&self.chain, msg)? {
+        // Validators could change while signatures were being checked.
+        if msg.chain.epoch != self.chain.epoch() {
+            return Err(consensus::ConsensusError::InvalidViewChangeEpoch(
+                msg.chain.epoch,
+                self.chain.epoch(),
+            )
+            .into());
+        }
+
+        // Check timestamp before the message can shift network time.
+        let author = self.chain.validators()[msg.validator_id as usize].0;
+        self.network_time.check(&author, msg.timestamp)?;
+
+        if let Some(proof) = view_change_collector.handle_verified_message(&self.chain, msg)? {
             debug!(
                 "Received enough messages for change leader: height={}, view_change={}, last_block={}",
                 self.chain.height(), self.chain.view_change(), self.chain.last_block_hash(),
@@ -1360,10 +3006,12 @@ impl NodeService {
 
         // Send a view_change message.
         let chain_info = ChainInfo::from_blockchain(&self.chain);
-        let msg = view_change_collector.handle_timeout(chain_info);
+        let msg = view_change_collector.handle_timeout(chain_info)?;
         self.network
             .publish(VIEW_CHANGE_TOPIC, msg.into_buffer()?)?;
         metrics::MICRO_BLOCK_VIEW_CHANGES.inc();
+        self.partition.on_round_timeout();
+        self.check_partition();
         debug!(
             "Sent a view change to the network: height={}, view_change={}, last_block={}",
             self.chain.height(),
@@ -1387,18 +3035,64 @@ impl NodeService {
             MicroBlockValidator { .. } => {}
             _ => panic!("Expected MicroBlockValidator State"),
         };
-        assert!(self.chain.leader() == self.keys.network_pkey);
+        assert!(self.is_micro_block_leader());
+
+        if self.partition.safe_mode() {
+            warn!(
+                "Safe mode: refusing to create a micro block: height={}, view_change={}",
+                self.chain.height(),
+                self.chain.view_change()
+            );
+            // Let other validators perform a view change.
+            let block_timer = match &mut self.validation {
+                MicroBlockValidator { block_timer, .. } => block_timer,
+                _ => unreachable!(),
+            };
+            let deadline = clock::now() + self.cfg.micro_block_timeout;
+            std::mem::replace(block_timer, BlockTimer::ViewChange(Delay::new(deadline)));
+            task::current().notify();
+            return Ok(());
+        }
         assert!(self.chain.blocks_in_epoch() < self.cfg.blocks_in_epoch);
 
         let height = self.chain.height();
         let previous = self.chain.last_block_hash();
         let view_change = self.chain.view_change();
-        let view_change_proof = self.chain.view_change_proof().clone();
         debug!(
             "Creating a new micro block: height={}, view_change={}, last_block={}",
             height, view_change, previous
         );
 
+        let mut block = self.prepare_micro_block()?;
+        let block_hash = Hash::digest(&block);
+
+        // Sign block.
+        block.sig = self.signer.sign_micro_block(&block)?;
+
+        info!(
+            "Created a micro block: height={}, view_change={}, block={}, transactions={}",
+            height,
+            view_change,
+            &block_hash,
+            block.transactions.len(),
+        );
+
+        let block2 = block.clone();
+        self.apply_micro_block(block)
+            .expect("created a valid block");
+        self.send_block(Block::MicroBlock(block2))
+            .expect("failed to send sealed micro block");
+
+        Ok(())
+    }
+
+    ///
+    /// Prepare an unsigned micro block from the mempool.
+    ///
+    fn prepare_micro_block(&mut self) -> Result<MicroBlock, Error> {
+        let previous = self.chain.last_block_hash();
+        let view_change = self.chain.view_change();
+        let view_change_proof = self.chain.view_change_proof().clone();
         for (cheater, proof) in &self.cheating_proofs {
             // the cheater was already punished, so we keep proofs for rollback case,
             // but avoid punish them second time.
@@ -1412,38 +3106,25 @@ impl NodeService {
         }
 
         // Create a new micro block from the mempool.
-        let mut block = self.mempool.create_block(
+        let random = self
+            .signer
+            .make_random(self.chain.last_random(), view_change)?;
+        let block = self.mempool.create_block(
             previous,
             VERSION,
+            self.chain.chain_id(),
             self.chain.height(),
-            self.cfg.block_reward,
+            self.chain.block_timestamp(),
+            self.chain.block_reward(self.chain.height()),
             &self.keys,
-            self.chain.last_random(),
+            self.chain.reward_key(&self.keys.network_pkey).as_ref(),
+            random,
             view_change,
             view_change_proof,
             self.cfg.max_utxo_in_block,
-        );
-
-        let block_hash = Hash::digest(&block);
-
-        // Sign block.
-        block.sign(&self.keys.network_skey, &self.keys.network_pkey);
-
-        info!(
-            "Created a micro block: height={}, view_change={}, block={}, transactions={}",
-            height,
-            view_change,
-            &block_hash,
-            block.transactions.len(),
-        );
-
-        let block2 = block.clone();
-        self.apply_micro_block(block)
-            .expect("created a valid block");
-        self.send_block(Block::MicroBlock(block2))
-            .expect("failed to send sealed micro block");
-
-        Ok(())
+            self.cfg.max_block_size,
+        )?;
+        Ok(block)
     }
 
     ///
@@ -1466,12 +3147,43 @@ impl NodeService {
     }
 }
 
+impl NodeRoleService for NodeService {
+    fn init(&mut self) -> Result<(), Error> {
+        NodeService::init(self)
+    }
+}
+
+impl NodeService {
+    /// Drains events after shutdown, requests are answered with an error.
+    fn poll_stopped(&mut self) -> Poll<(), ()> {
+        loop {
+            match self.events.poll().expect("all errors are already handled") {
+                Async::Ready(Some(NodeMessage::Request { tx, .. })) => {
+                    let error = "Node is shutting down".to_string();
+                    tx.send(NodeResponse::Error { error }).ok(); // ignore errors.
+                }
+                Async::Ready(Some(NodeMessage::Shutdown(tx))) => {
+                    tx.send(()).ok(); // ignore errors.
+                }
+                // Drop new work.
+                Async::Ready(Some(_)) => {}
+                Async::Ready(None) => unreachable!(), // never happens
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
 // Event loop.
 impl Future for NodeService {
     type Item = ();
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.stopped {
+            return self.poll_stopped();
+        }
+
         // Poll timers first.
         let result = match &mut self.validation {
             MicroBlockAuditor
@@ -1516,6 +3228,9 @@ impl Future for NodeService {
         if let Err(e) = result {
             error!("Error: {}", e);
         }
+        if let Err(e) = self.poll_loader_timer() {
+            error!("Error: {}", e);
+        }
 
         // Poll other events.
         loop {
@@ -1524,18 +3239,187 @@ impl Future for NodeService {
                     let result: Result<(), Error> = match event {
                         NodeMessage::SubscribeBlockAdded(tx) => self.handle_block_added(tx),
                         NodeMessage::SubscribeEpochChanged(tx) => self.handle_subscribe_epoch(tx),
+                        NodeMessage::SubscribeFinalityChanged(tx) => {
+                            self.handle_subscribe_finality(tx)
+                        }
+                        NodeMessage::SubscribeRandomBeacon(tx) => {
+                            self.handle_subscribe_random_beacon(tx)
+                        }
                         NodeMessage::SubscribeOutputsChanged(tx) => {
                             self.handle_subscribe_outputs(tx)
                         }
+                        NodeMessage::SubscribeTransactionConflicts(tx) => {
+                            self.handle_subscribe_transaction_conflicts(tx)
+                        }
+                        NodeMessage::LocalTransaction(msg) => self.handle_local_transaction(msg),
                         NodeMessage::PopBlock => self.handle_pop_block(),
+                        NodeMessage::RecoverWallet {
+                            wallet_skey,
+                            wallet_pkey,
+                            tx,
+                        } => self.handle_recover_wallet(wallet_skey, wallet_pkey, tx),
+                        NodeMessage::Shutdown(tx) => self.handle_shutdown(tx),
+                        NodeMessage::Request {
+                            request: NodeRequest::RoutingTable {},
+                            tx,
+                        } => self.handle_routing_table_request(tx),
+                        NodeMessage::Request {
+                            request: NodeRequest::QueryJournal {},
+                            tx,
+                        } => self.handle_query_journal_request(tx),
+                        NodeMessage::Request {
+                            request: NodeRequest::Status {},
+                            tx,
+                        } => self.handle_status_request(tx),
                         NodeMessage::Request { request, tx } => {
                             let response = match request {
                                 NodeRequest::ElectionInfo {} => {
                                     NodeResponse::ElectionInfo(self.chain.election_info())
                                 }
+                                NodeRequest::LeaderSelection { epoch, view_change } => {
+                                    match self.chain.audit_leader(epoch, view_change) {
+                                        Some(audit) => NodeResponse::LeaderSelection(audit),
+                                        None => NodeResponse::Error {
+                                            error: format!(
+                                                "Leader selection is not available for epoch {}, current epoch is {}",
+                                                epoch,
+                                                self.chain.epoch()
+                                            ),
+                                        },
+                                    }
+                                }
                                 NodeRequest::EscrowInfo {} => {
                                     NodeResponse::EscrowInfo(self.chain.escrow_info())
                                 }
+                                NodeRequest::OutputProof { utxo } => {
+                                    match self.chain.output_proof(&utxo) {
+                                        Ok(proof) => NodeResponse::OutputProof { utxo, proof },
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::TransactionStatus { tx_hash, txins } => {
+                                    match self.chain.tx_status(&tx_hash, &txins) {
+                                        Ok(status) => {
+                                            let in_mempool = self.mempool.contains_tx(&tx_hash);
+                                            NodeResponse::TransactionStatus {
+                                                tx_hash,
+                                                status,
+                                                in_mempool,
+                                            }
+                                        }
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::StakesByWallet { wallet_pkey } => {
+                                    NodeResponse::StakesByWallet {
+                                        wallet_pkey,
+                                        height: self.chain.height(),
+                                        stakes: self.chain.stakes_by_wallet(&wallet_pkey),
+                                    }
+                                }
+                                NodeRequest::StakeUnlockHeight { utxo } => {
+                                    match self.chain.stake_unlock_height(&utxo) {
+                                        Ok(unlock_height) => NodeResponse::StakeUnlockHeight {
+                                            utxo,
+                                            unlock_height,
+                                        },
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::ValidatorStake { network_pkey } => {
+                                    let summary = self.chain.validator_stake_summary(&network_pkey);
+                                    NodeResponse::ValidatorStake {
+                                        network_pkey,
+                                        summary,
+                                    }
+                                }
+                                NodeRequest::Inspect { query } => {
+                                    match self.chain.inspect(&query) {
+                                        Ok(response) => NodeResponse::Inspect(response),
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::PeerStatuses {} => NodeResponse::PeerStatuses {
+                                    height: self.chain.height(),
+                                    statuses: self.peer_statuses(),
+                                },
+                                NodeRequest::FastConfirmation { tx_hash } => {
+                                    let proof = self.confirmations.proof(&tx_hash).cloned();
+                                    NodeResponse::FastConfirmation { tx_hash, proof }
+                                }
+                                NodeRequest::ViewChangeInfo {} => self.view_change_info(),
+                                NodeRequest::SubmitViewChangeProof { proof } => {
+                                    self.submit_view_change_proof(proof)
+                                }
+                                NodeRequest::EstimateFee { target_blocks } => {
+                                    NodeResponse::FeeEstimate(
+                                        self.fees.estimate_fee(target_blocks, self.chain.height()),
+                                    )
+                                }
+                                NodeRequest::ValidateReserveProof { proof } => {
+                                    self.validate_reserve_proof(proof)
+                                }
+                                NodeRequest::BlockTemplate {} => self.block_template(),
+                                NodeRequest::SubmitBlock { block } => self.submit_block(block),
+                                NodeRequest::FinalityInfo {} => NodeResponse::FinalityInfo {
+                                    height: self.chain.height(),
+                                    finalized_height: self.chain.finalized_height(),
+                                    commitment: self.chain.finality_commitment().cloned(),
+                                },
+                                NodeRequest::EpochSummary { epoch } => {
+                                    match self.chain.epoch_summary(epoch) {
+                                        Ok(summary) => {
+                                            NodeResponse::EpochSummary { epoch, summary }
+                                        }
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::RandomBeacon { height } => {
+                                    let height = height.unwrap_or(self.chain.height() - 1);
+                                    match self.chain.random_at(height) {
+                                        Ok(beacon) => NodeResponse::RandomBeacon { height, beacon },
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::VerifyFastConfirmation { proof } => {
+                                    match proof.validate(&self.chain) {
+                                        Ok(()) => NodeResponse::FastConfirmationVerified {
+                                            tx_hash: proof.info.tx_hash,
+                                        },
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::Explore { query } => {
+                                    match self.chain.explore(&query) {
+                                        Ok(response) => NodeResponse::Explore(response),
+                                        Err(e) => NodeResponse::Error {
+                                            error: format!("{}", e),
+                                        },
+                                    }
+                                }
+                                NodeRequest::RepairDatabase {} => match self.repair_database() {
+                                    Ok(response) => response,
+                                    Err(e) => NodeResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                },
+                                NodeRequest::RoutingTable {}
+                                | NodeRequest::QueryJournal {}
+                                | NodeRequest::Status {} => unreachable!("handled above"),
                             };
                             tx.send(response).ok(); // ignore errors.
                             Ok(())
@@ -1546,21 +3430,76 @@ impl Future for NodeService {
                             .and_then(|msg| self.handle_consensus_message(msg)),
                         NodeMessage::ViewChangeMessage(msg) => ViewChangeMessage::from_buffer(&msg)
                             .and_then(|msg| self.handle_view_change_message(msg)),
+                        NodeMessage::ConfirmationVote(msg) => ConfirmationVote::from_buffer(&msg)
+                            .and_then(|msg| self.handle_confirmation_vote(msg)),
                         NodeMessage::ViewChangeProofMessage(msg) => {
                             SealedViewChangeProof::from_buffer(&msg.data)
                                 .and_then(|proof| self.handle_view_change_direct(proof, msg.from))
                         }
-                        NodeMessage::Block(msg) => {
-                            Block::from_buffer(&msg).and_then(|msg| self.handle_block(msg))
-                        }
+                        NodeMessage::Block(msg) => self.handle_block_buffer(msg),
                         NodeMessage::ChainLoaderMessage(msg) => {
                             ChainLoaderMessage::from_buffer(&msg.data)
                                 .and_then(|data| self.handle_chain_loader_message(msg.from, data))
                         }
+                        NodeMessage::ChainStatus(msg) => ChainStatus::from_buffer(&msg)
+                            .and_then(|msg| self.handle_chain_status(msg)),
+                        NodeMessage::ChainStatusTimer => {
+                            self.check_partition();
+                            self.send_chain_status()
+                        }
+                        NodeMessage::StemTransaction(msg) => self.handle_stem_transaction(msg),
+                        NodeMessage::DandelionTimer => self.handle_dandelion_timer(),
                     };
                     if let Err(e) = result {
                         error!("Error: {}", e);
                     }
+                    if self.stopped {
+                        return self.poll_stopped();
+                    }
+                }
+                Async::Ready(None) => unreachable!(), // never happens
+                Async::NotReady => break,
+            }
+        }
+
+        // Process messages checked by the signature verifier.
+        self.poll_verifications();
+
+        if let Err(e) = self.process_orphans() {
+            error!("Error: {}", e);
+        }
+
+        let network_requests = std::mem::replace(&mut self.network_requests, Vec::new());
+        for (mut rx, tx) in network_requests {
+            match rx.poll() {
+                Ok(Async::Ready(response)) => {
+                    tx.send(response).ok(); // ignore errors.
+                }
+                Ok(Async::NotReady) => self.network_requests.push((rx, tx)),
+                Err(_) => {
+                    let error = "Network is not available".to_string();
+                    tx.send(NodeResponse::Error { error }).ok(); // ignore errors.
+                }
+            }
+        }
+
+        // Process delivery reports of local transactions.
+        self.broadcaster.poll();
+
+        // Poll transaction shards.
+        // Transactions from all shards are merged into mempool for block building.
+        loop {
+            match self
+                .tx_shards
+                .poll()
+                .expect("all errors are already handled")
+            {
+                Async::Ready(Some(msg)) => {
+                    let result =
+                        Transaction::from_buffer(&msg).and_then(|msg| self.handle_transaction(msg));
+                    if let Err(e) = result {
+                        error!("Error: {}", e);
+                    }
                 }
                 Async::Ready(None) => unreachable!(), // never happens
                 Async::NotReady => return Ok(Async::NotReady),
@@ -0,0 +1,1191 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Light Node.
+//!
+//! Light nodes keep block headers of the current epoch and outputs of local wallets only.
+//! Blocks are downloaded from the trusted `remote_nodes`, headers are checked for
+//! consistency and against checkpoints. Macro blocks are checked against the multisignature
+//! of validators, which are elected from stakes replayed from all blocks.
+//! Signatures of micro blocks are not verified.
+//!
+
+use crate::error::NodeBlockError;
+use crate::loader::{
+    ChainLoader, ChainLoaderMessage, RequestBlocks, ResponseBlocks, CHAIN_LOADER_TOPIC,
+};
+use crate::validation::validate_block_buffer;
+use crate::{
+    metrics, BlockAdded, ChainConfig, EpochChanged, Node, NodeMessage, NodeResponse,
+    NodeRoleService, OutputsChanged, TransactionConflict, SEALED_BLOCK_TOPIC,
+};
+use failure::{bail, Error};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll, Stream};
+use futures_stream_select_all_send::select_all;
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+use stegos_blockchain::election::select_validators_slots;
+use stegos_blockchain::{
+    check_multi_signature, BaseBlockHeader, Block, BlockError, ElectionResult, MacroBlock,
+    MicroBlock, Output, RandomBeacon, StakeReplay, VERSION,
+};
+use stegos_crypto::curve1174::{PublicKey, SecretKey};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc;
+use stegos_keychain::KeyChain;
+use stegos_network::{Lane, Network};
+use stegos_serialization::traits::ProtoConvert;
+use tokio_timer::{clock, Interval};
+
+/// A block of the current epoch.
+#[derive(Debug, Clone)]
+struct HeaderInfo {
+    hash: Hash,
+    base: BaseBlockHeader,
+    /// Network key of the leader.
+    leader: pbc::PublicKey,
+}
+
+///
+/// Headers of the last macro block and micro blocks on the top of it.
+///
+struct HeaderChain {
+    /// Hash of the chain name.
+    chain_id: Hash,
+    /// The number of blocks per epoch.
+    blocks_in_epoch: u64,
+    /// Trusted block hashes by height.
+    checkpoints: HashMap<u64, Hash>,
+    /// Skip verification of macro block signatures below the last checkpoint.
+    assume_valid: bool,
+    /// Minimal stake amount of validators.
+    min_stake_amount: i64,
+    /// Maximal number of slots for election.
+    max_slot_count: i64,
+    /// The number of applied macro blocks.
+    epoch: u64,
+    /// The last macro block followed by micro blocks.
+    blocks: Vec<HeaderInfo>,
+    /// Stakes of validators.
+    stakes: StakeReplay,
+    /// Validators of the current epoch.
+    election_result: ElectionResult,
+}
+
+impl HeaderChain {
+    fn new(cfg: &ChainConfig) -> Self {
+        let checkpoints = cfg
+            .checkpoints
+            .iter()
+            .map(|c| (c.height, c.block_hash))
+            .collect();
+        HeaderChain {
+            chain_id: Hash::digest(&cfg.chain_id),
+            blocks_in_epoch: cfg.blocks_in_epoch,
+            checkpoints,
+            assume_valid: cfg.assume_valid,
+            min_stake_amount: cfg.min_stake_amount,
+            max_slot_count: cfg.max_slot_count,
+            epoch: 0,
+            blocks: Vec::new(),
+            stakes: StakeReplay::new(cfg.stake_epochs),
+            election_result: ElectionResult::default(),
+        }
+    }
+
+    /// The height of the next block.
+    fn height(&self) -> u64 {
+        self.blocks.last().map_or(0, |b| b.base.height + 1)
+    }
+
+    fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    fn last_macro_block_height(&self) -> u64 {
+        self.blocks.first().map_or(0, |b| b.base.height)
+    }
+
+    fn last_block_hash(&self) -> Hash {
+        self.blocks.last().map_or(Hash::zero(), |b| b.hash)
+    }
+
+    fn last(&self) -> Option<&HeaderInfo> {
+        self.blocks.last()
+    }
+
+    /// Randomness of the last block.
+    fn last_random(&self) -> Hash {
+        match self.blocks.last() {
+            Some(b) => b.base.random.rand,
+            // The genesis block is created from the initial randomness of election.
+            None => ElectionResult::default().random.rand,
+        }
+    }
+
+    /// Hash of a block of the current epoch.
+    fn block_hash(&self, height: u64) -> Option<Hash> {
+        let first = self.blocks.first()?.base.height;
+        if height < first {
+            return None;
+        }
+        self.blocks.get((height - first) as usize).map(|b| b.hash)
+    }
+
+    ///
+    /// Checks that the block can be added on the top of the chain.
+    ///
+    fn validate(&self, block: &Block) -> Result<(), Error> {
+        let block_hash = Hash::digest(block);
+        let base = block.base_header();
+        let height = base.height;
+
+        if base.version != VERSION {
+            return Err(
+                BlockError::InvalidBlockVersion(height, block_hash, base.version, VERSION).into(),
+            );
+        }
+
+        // Genesis blocks created before chain identifiers were introduced have zero here.
+        if base.chain_id != self.chain_id && (height > 0 || base.chain_id != Hash::zero()) {
+            return Err(BlockError::InvalidChainId(
+                height,
+                block_hash,
+                base.chain_id,
+                self.chain_id,
+            )
+            .into());
+        }
+
+        if height != self.height() {
+            return Err(BlockError::OutOfOrderBlock(block_hash, height, self.height()).into());
+        }
+
+        if let Some(last) = self.blocks.last() {
+            if base.previous != last.hash {
+                return Err(BlockError::InvalidPreviousHash(
+                    height,
+                    block_hash,
+                    base.previous,
+                    last.hash,
+                )
+                .into());
+            }
+            if base.timestamp < last.base.timestamp {
+                return Err(BlockError::TimestampBeforePrevious(
+                    height,
+                    block_hash,
+                    base.timestamp,
+                    last.base.timestamp,
+                )
+                .into());
+            }
+        }
+
+        match self.checkpoints.get(&height) {
+            Some(checkpoint) if *checkpoint != block_hash => {
+                return Err(BlockError::CheckpointMismatch(height, block_hash, *checkpoint).into());
+            }
+            _ => {}
+        }
+
+        let expect_macro =
+            height == 0 || height - self.last_macro_block_height() >= self.blocks_in_epoch;
+        match block {
+            Block::MacroBlock(block) => {
+                if !expect_macro {
+                    return Err(NodeBlockError::ExpectedMicroBlock(height, block_hash).into());
+                }
+                Self::validate_macro_block_body(&block_hash, block)?;
+                // The block is signed by validators of the finished epoch, genesis is trusted.
+                if height > 0 && !self.is_assumed_valid(height) {
+                    check_multi_signature(
+                        &block_hash,
+                        &block.body.multisig,
+                        &block.body.multisigmap,
+                        &self.election_result.validators,
+                        self.max_slot_count,
+                    )
+                    .map_err(|e| BlockError::InvalidBlockSignature(e, height, block_hash))?;
+                }
+            }
+            Block::MicroBlock(_) => {
+                if expect_macro {
+                    return Err(NodeBlockError::ExpectedMacroBlock(height, block_hash).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Checks that inputs and outputs match the header, which is covered by the block hash.
+    ///
+    fn validate_macro_block_body(block_hash: &Hash, block: &MacroBlock) -> Result<(), Error> {
+        let height = block.header.base.height;
+
+        let mut hasher = Hasher::new();
+        let inputs_count: u64 = block.body.inputs.len() as u64;
+        inputs_count.hash(&mut hasher);
+        for input_hash in &block.body.inputs {
+            input_hash.hash(&mut hasher);
+        }
+        let inputs_range_hash = hasher.result();
+        if block.header.inputs_range_hash != inputs_range_hash {
+            return Err(BlockError::InvalidBlockInputsHash(
+                height,
+                *block_hash,
+                block.header.inputs_range_hash,
+                inputs_range_hash,
+            )
+            .into());
+        }
+
+        let outputs_range_hash = *block.body.outputs.roothash();
+        if block.header.outputs_range_hash != outputs_range_hash {
+            return Err(BlockError::InvalidBlockOutputsHash(
+                height,
+                *block_hash,
+                block.header.outputs_range_hash,
+                outputs_range_hash,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if signatures of the block are not verified, see `ChainConfig::assume_valid`.
+    fn is_assumed_valid(&self, height: u64) -> bool {
+        self.assume_valid
+            && self
+                .checkpoints
+                .keys()
+                .max()
+                .map_or(false, |last| height < *last)
+    }
+
+    /// Adds a validated block with its inputs and outputs.
+    /// Validators of the next epoch are elected after each macro block.
+    fn push(&mut self, info: HeaderInfo, is_macro: bool, inputs: &[Hash], outputs: &[Output]) {
+        assert_eq!(info.base.height, self.height());
+        self.stakes
+            .apply(info.base.height, self.epoch, inputs, outputs);
+        if is_macro {
+            self.stakes.checkpoint();
+            self.blocks.clear();
+            self.epoch += 1;
+            let stakers = self
+                .stakes
+                .get_stakers_majority(self.epoch, self.min_stake_amount);
+            self.election_result =
+                select_validators_slots(stakers, info.base.random, self.max_slot_count);
+        }
+        self.blocks.push(info);
+    }
+
+    /// Removes the last micro block.
+    fn pop(&mut self) -> Result<HeaderInfo, Error> {
+        if self.blocks.len() < 2 {
+            bail!(
+                "Can't revert a macro block: height={}",
+                self.last_macro_block_height()
+            );
+        }
+        let height = self.height() - 1;
+        if let Some(checkpoint) = self.checkpoints.get(&height) {
+            bail!(
+                "Can't revert a block protected by a checkpoint: height={}, block={}",
+                height,
+                checkpoint
+            );
+        }
+        self.stakes.rollback(height);
+        Ok(self.blocks.pop().expect("checked above"))
+    }
+}
+
+///
+/// Unspent outputs of local wallets.
+///
+struct WalletScanner {
+    /// Keys of wallets.
+    keys: Vec<(SecretKey, PublicKey)>,
+    /// Unspent outputs with the epoch of their creation.
+    outputs: HashMap<Hash, (Output, u64)>,
+}
+
+impl WalletScanner {
+    fn new() -> Self {
+        WalletScanner {
+            keys: Vec::new(),
+            outputs: HashMap::new(),
+        }
+    }
+
+    /// Starts watching outputs of the key. Returns false if the key is already known.
+    fn add_key(&mut self, skey: SecretKey, pkey: PublicKey) -> bool {
+        if self.keys.iter().any(|(_, known)| *known == pkey) {
+            return false;
+        }
+        self.keys.push((skey, pkey));
+        true
+    }
+
+    fn is_watched(&self, output: &Output) -> bool {
+        self.keys
+            .iter()
+            .any(|(skey, pkey)| output.is_my_utxo(skey, pkey))
+    }
+
+    /// Unspent outputs of the key.
+    fn wallet_outputs(&self, skey: &SecretKey, pkey: &PublicKey) -> Vec<(Output, u64)> {
+        self.outputs
+            .values()
+            .filter(|(output, _epoch)| output.is_my_utxo(skey, pkey))
+            .cloned()
+            .collect()
+    }
+
+    ///
+    /// Applies inputs and outputs of a block.
+    /// Returns spent and created outputs of wallets.
+    ///
+    fn apply(
+        &mut self,
+        epoch: u64,
+        inputs: &[Hash],
+        outputs: &[Output],
+    ) -> (Vec<(Output, u64)>, Vec<Output>) {
+        let input_set: HashSet<&Hash> = inputs.iter().collect();
+        let mut created: Vec<Output> = Vec::new();
+        for output in outputs {
+            let output_hash = Hash::digest(output);
+            // Outputs spent by the same block are never seen by wallets.
+            if input_set.contains(&output_hash) || !self.is_watched(output) {
+                continue;
+            }
+            self.outputs.insert(output_hash, (output.clone(), epoch));
+            created.push(output.clone());
+        }
+        let spent: Vec<(Output, u64)> = inputs
+            .iter()
+            .filter_map(|input_hash| self.outputs.remove(input_hash))
+            .collect();
+        (spent, created)
+    }
+
+    /// Reverts `apply()`.
+    fn revert(&mut self, spent: &[(Output, u64)], created: &[Output]) {
+        for output in created {
+            self.outputs.remove(&Hash::digest(output));
+        }
+        for (output, epoch) in spent {
+            self.outputs
+                .insert(Hash::digest(output), (output.clone(), *epoch));
+        }
+    }
+}
+
+///
+/// Event loop of `NodeRole::Light`.
+///
+pub struct LightNodeService {
+    /// Config.
+    cfg: ChainConfig,
+    /// Headers of the current epoch.
+    chain: HeaderChain,
+    /// Outputs of local wallets.
+    scanner: WalletScanner,
+    /// Changes of wallet outputs made by each micro block, (spent, created).
+    undo: Vec<(Vec<(Output, u64)>, Vec<Output>)>,
+    /// Block synchronization state.
+    loader: ChainLoader,
+    /// Asks remote nodes for new blocks.
+    sync_timer: Interval,
+    /// Set by shutdown, no new work is accepted.
+    stopped: bool,
+
+    //
+    // Communication with environment.
+    //
+    /// Network interface.
+    network: Network,
+    /// Triggered when height is changed.
+    on_block_added: Vec<UnboundedSender<BlockAdded>>,
+    /// Triggered when epoch is changed.
+    on_epoch_changed: Vec<UnboundedSender<EpochChanged>>,
+    /// Triggered when a block with new randomness is added.
+    on_random_beacon: Vec<UnboundedSender<RandomBeacon>>,
+    /// Triggered when outputs of wallets created and/or pruned.
+    on_outputs_changed: Vec<UnboundedSender<OutputsChanged>>,
+    /// Never triggered, light nodes have no mempool.
+    on_transaction_conflict: Vec<UnboundedSender<TransactionConflict>>,
+    /// Aggregated stream of events.
+    events: Box<Stream<Item = NodeMessage, Error = ()> + Send>,
+}
+
+impl LightNodeService {
+    /// Constructor.
+    pub fn new(
+        cfg: ChainConfig,
+        genesis: MacroBlock,
+        keys: &KeyChain,
+        network: Network,
+    ) -> Result<(Self, Node), Error> {
+        if cfg.role.keeps_full_chain() {
+            bail!("Full nodes are run by NodeService: role={:?}", cfg.role);
+        }
+        if cfg.remote_nodes.is_empty() {
+            bail!("Light nodes need at least one node in chain.remote_nodes");
+        }
+        let (outbox, inbox) = unbounded();
+        let chain = HeaderChain::new(&cfg);
+        let mut scanner = WalletScanner::new();
+        scanner.add_key(keys.wallet_skey.clone(), keys.wallet_pkey);
+        let loader = ChainLoader::new();
+        let sync_timer = Interval::new_interval(cfg.loader_request_timeout);
+        let tx_topic_shards = cfg.tx_topic_shards;
+        network.set_lane(CHAIN_LOADER_TOPIC, Lane::BlockSync)?;
+
+        let mut streams = Vec::<Box<Stream<Item = NodeMessage, Error = ()> + Send>>::new();
+
+        // Control messages
+        streams.push(Box::new(inbox));
+
+        // Sealed blocks broadcast topic, used only to learn the height of the network.
+        let block_rx = network
+            .subscribe(&SEALED_BLOCK_TOPIC)?
+            .map(|m| NodeMessage::Block(m));
+        streams.push(Box::new(block_rx));
+
+        // Chain loader messages.
+        let loader_rx = network
+            .subscribe_unicast(CHAIN_LOADER_TOPIC)?
+            .map(NodeMessage::ChainLoaderMessage);
+        streams.push(Box::new(loader_rx));
+
+        let events = select_all(streams);
+
+        let mut service = LightNodeService {
+            cfg,
+            chain,
+            scanner,
+            undo: Vec::new(),
+            loader,
+            sync_timer,
+            stopped: false,
+            network: network.clone(),
+            on_block_added: Vec::new(),
+            on_epoch_changed: Vec::new(),
+            on_random_beacon: Vec::new(),
+            on_outputs_changed: Vec::new(),
+            on_transaction_conflict: Vec::new(),
+            events,
+        };
+        service.apply_block(Block::MacroBlock(genesis))?;
+
+        // Transactions are published directly, light nodes don't relay the stem.
+        let handler = Node {
+            outbox,
+            network: network.clone(),
+            tx_topic_shards,
+            dandelion: false,
+        };
+
+        Ok((service, handler))
+    }
+
+    /// Hash of the chain name.
+    pub fn chain_id(&self) -> Hash {
+        self.chain.chain_id
+    }
+
+    ///
+    /// Starts scanning blocks for the wallet key and returns its unspent outputs
+    /// with the epoch of their creation.
+    /// Blocks applied before the key was added are not scanned again.
+    ///
+    pub fn recover_wallet(
+        &mut self,
+        wallet_skey: &SecretKey,
+        wallet_pkey: &PublicKey,
+    ) -> Vec<(Output, u64)> {
+        if self.scanner.add_key(wallet_skey.clone(), *wallet_pkey) && self.chain.height() > 1 {
+            warn!(
+                "Outputs of the wallet in earlier blocks are unknown: wallet_pkey={}, height={}",
+                wallet_pkey,
+                self.chain.height()
+            );
+        }
+        self.scanner.wallet_outputs(wallet_skey, wallet_pkey)
+    }
+
+    fn is_synchronized(&self) -> bool {
+        self.chain.height() >= self.loader.target_height()
+    }
+
+    ///
+    /// Asks remote nodes for our last block.
+    /// Responses tell the height of remote nodes, which starts downloading if we are behind.
+    ///
+    fn probe_remote_nodes(&mut self) -> Result<(), Error> {
+        let starting_height = self.chain.height() - 1;
+        for pkey in &self.cfg.remote_nodes {
+            let msg = ChainLoaderMessage::Request(RequestBlocks::new(starting_height, 1));
+            self.network
+                .send(*pkey, CHAIN_LOADER_TOPIC, msg.into_buffer()?)?;
+        }
+        self.request_blocks()
+    }
+
+    /// Fills windows of in-flight requests while we are behind.
+    fn request_blocks(&mut self) -> Result<(), Error> {
+        let peers = self.cfg.remote_nodes.clone();
+        while let Some((from, starting_height, count)) =
+            self.loader
+                .next_request(self.chain.height(), &peers, &self.cfg, clock::now())
+        {
+            debug!(
+                "Requesting blocks: from={}, starting_height={}, count={}, target_height={}, our_height={}",
+                from,
+                starting_height,
+                count,
+                self.loader.target_height(),
+                self.chain.height()
+            );
+            let msg = ChainLoaderMessage::Request(RequestBlocks::new(starting_height, count));
+            self.network
+                .send(from, CHAIN_LOADER_TOPIC, msg.into_buffer()?)?;
+        }
+        Ok(())
+    }
+
+    fn handle_chain_loader_message(
+        &mut self,
+        pkey: pbc::PublicKey,
+        msg: ChainLoaderMessage,
+    ) -> Result<(), Error> {
+        match msg {
+            ChainLoaderMessage::Request(_) => {
+                debug!("Light nodes don't feed blocks: from={}", pkey);
+                Ok(())
+            }
+            ChainLoaderMessage::Response(r) => self.handle_response_blocks(pkey, r),
+        }
+    }
+
+    fn handle_response_blocks(
+        &mut self,
+        pkey: pbc::PublicKey,
+        response: ResponseBlocks,
+    ) -> Result<(), Error> {
+        if !self.cfg.remote_nodes.contains(&pkey) {
+            warn!("Ignored blocks from an untrusted node: from={}", pkey);
+            return Ok(());
+        }
+        debug!(
+            "Received blocks: from={}, starting_height={}, num_blocks={}, remote_height={}",
+            pkey,
+            response.starting_height,
+            response.blocks.len(),
+            response.height,
+        );
+
+        let our_height = self.chain.height();
+        if !self
+            .loader
+            .on_response(pkey, response, our_height, &self.cfg)
+        {
+            debug!("Received unsolicited blocks: from={}", pkey);
+        }
+
+        self.apply_downloaded_blocks();
+        self.request_blocks()
+    }
+
+    /// Sealed blocks are not trusted, they only tell that the network has moved on.
+    fn handle_block_buffer(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        validate_block_buffer(&data, self.cfg.max_block_size)?;
+        let block = Block::from_buffer(&data)?;
+        let height = block.base_header().height;
+        if height >= self.chain.height() {
+            self.loader.on_remote_height(None, height + 1);
+            self.request_blocks()?;
+        }
+        Ok(())
+    }
+
+    /// Validates and applies downloaded blocks in order.
+    fn apply_downloaded_blocks(&mut self) {
+        while let Some((pkey, block)) = self.loader.pop_ready(self.chain.height()) {
+            let height = block.base_header().height;
+            if let Err(e) = self.handle_block(block) {
+                error!(
+                    "Failed to apply a downloaded block: from={}, height={}, error={}",
+                    pkey, height, e
+                );
+                self.loader.on_invalid_block(pkey, height);
+                break;
+            }
+        }
+        debug!(
+            "Sync status: our_height={}, target_height={}, in_flight={}, pending={}",
+            self.chain.height(),
+            self.loader.target_height(),
+            self.loader.in_flight(),
+            self.loader.pending()
+        );
+    }
+
+    fn handle_block(&mut self, block: Block) -> Result<(), Error> {
+        let block_hash = Hash::digest(&block);
+        let height = block.base_header().height;
+
+        if height < self.chain.height() {
+            // Blocks of finalized epochs are never reverted.
+            match self.chain.block_hash(height) {
+                Some(hash)
+                    if hash != block_hash && height > self.chain.last_macro_block_height() =>
+                {
+                    warn!(
+                        "A fork detected: height={}, local_block={}, remote_block={}",
+                        height, hash, block_hash
+                    );
+                    self.rollback(height)?;
+                }
+                _ => return Ok(()),
+            }
+        } else if block.base_header().previous != self.chain.last_block_hash()
+            && self.chain.height() - 1 > self.chain.last_macro_block_height()
+        {
+            // Our last micro block has been replaced, download it again.
+            let height = self.chain.height() - 1;
+            warn!(
+                "A fork detected: height={}, local_block={}, remote_previous={}",
+                height,
+                self.chain.last_block_hash(),
+                block.base_header().previous
+            );
+            return self.rollback(height);
+        }
+
+        self.apply_block(block)
+    }
+
+    fn apply_block(&mut self, block: Block) -> Result<(), Error> {
+        self.chain.validate(&block)?;
+        match block {
+            Block::MacroBlock(block) => self.apply_macro_block(block),
+            Block::MicroBlock(block) => self.apply_micro_block(block),
+        }
+        Ok(())
+    }
+
+    fn apply_macro_block(&mut self, block: MacroBlock) {
+        let hash = Hash::digest(&block);
+        let previous_random = self.chain.last_random();
+        let outputs: Vec<Output> = block
+            .body
+            .outputs
+            .leafs()
+            .into_iter()
+            .map(|(output, _path)| output.as_ref().clone())
+            .collect();
+        let (spent, created) = self
+            .scanner
+            .apply(self.chain.epoch(), &block.body.inputs, &outputs);
+        let info = HeaderInfo {
+            hash,
+            base: block.header.base,
+            leader: block.body.pkey,
+        };
+        self.chain.push(info, true, &block.body.inputs, &outputs);
+        self.undo.clear();
+        info!(
+            "Applied a macro block: height={}, block={}, epoch={}",
+            self.chain.height() - 1,
+            hash,
+            self.chain.epoch()
+        );
+
+        let msg = self.epoch_changed();
+        self.on_epoch_changed
+            .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+
+        let spent = spent.into_iter().map(|(output, _epoch)| output).collect();
+        self.on_block_added(previous_random, spent, created, HashMap::new());
+    }
+
+    fn apply_micro_block(&mut self, block: MicroBlock) {
+        let hash = Hash::digest(&block);
+        let previous_random = self.chain.last_random();
+        let mut tx_hashes: HashMap<Hash, Hash> = HashMap::new();
+        let mut inputs: Vec<Hash> = Vec::new();
+        let mut outputs: Vec<Output> = Vec::new();
+        for tx in &block.transactions {
+            let tx_hash = Hash::digest(tx);
+            for input_hash in tx.txins() {
+                tx_hashes.insert(*input_hash, tx_hash);
+                inputs.push(*input_hash);
+            }
+            for output in tx.txouts() {
+                tx_hashes.insert(Hash::digest(output), tx_hash);
+                outputs.push(output.clone());
+            }
+        }
+        let (spent, created) = self.scanner.apply(self.chain.epoch(), &inputs, &outputs);
+        let info = HeaderInfo {
+            hash,
+            base: block.base,
+            leader: block.pkey,
+        };
+        self.chain.push(info, false, &inputs, &outputs);
+        self.undo.push((spent.clone(), created.clone()));
+        debug!(
+            "Applied a micro block: height={}, block={}",
+            self.chain.height() - 1,
+            hash
+        );
+
+        let spent = spent.into_iter().map(|(output, _epoch)| output).collect();
+        self.on_block_added(previous_random, spent, created, tx_hashes);
+    }
+
+    fn on_block_added(
+        &mut self,
+        previous_random: Hash,
+        inputs: Vec<Output>,
+        outputs: Vec<Output>,
+        tx_hashes: HashMap<Hash, Hash>,
+    ) {
+        let last = self.chain.last().expect("block is added").clone();
+        let height = last.base.height;
+
+        let msg = OutputsChanged {
+            epoch: self.chain.epoch(),
+            height,
+            timestamp: last.base.timestamp,
+            reverted: false,
+            inputs,
+            outputs,
+            tx_hashes,
+        };
+        self.on_outputs_changed
+            .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+
+        let local_timestamp = metrics::time_to_timestamp_ms(SystemTime::now());
+        let remote_timestamp = metrics::time_to_timestamp_ms(last.base.timestamp);
+        let msg = BlockAdded {
+            height,
+            view_change: last.base.view_change,
+            hash: last.hash,
+            lag: local_timestamp - remote_timestamp,
+            local_timestamp,
+            remote_timestamp,
+            synchronized: self.is_synchronized(),
+            epoch: self.chain.epoch(),
+        };
+        self.on_block_added
+            .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+
+        let beacon = RandomBeacon::new(
+            height,
+            last.hash,
+            last.base.view_change,
+            last.leader,
+            previous_random,
+            &last.base.random,
+        );
+        self.on_random_beacon
+            .retain(move |ch| ch.unbounded_send(beacon.clone()).is_ok());
+    }
+
+    /// Revert micro blocks down to the height.
+    fn rollback(&mut self, height: u64) -> Result<(), Error> {
+        while self.chain.height() > height {
+            self.revert_micro_block()?;
+        }
+        self.loader.on_rollback(height);
+        Ok(())
+    }
+
+    fn revert_micro_block(&mut self) -> Result<(), Error> {
+        let reverted = self.chain.pop()?;
+        let (spent, created) = self.undo.pop().expect("each micro block has undo");
+        self.scanner.revert(&spent, &created);
+        info!(
+            "Reverted a micro block: height={}, block={}",
+            reverted.base.height, reverted.hash
+        );
+        let msg = OutputsChanged {
+            epoch: self.chain.epoch(),
+            height: self.chain.height(),
+            timestamp: SystemTime::now(),
+            reverted: true,
+            inputs: created,
+            outputs: spent.into_iter().map(|(output, _epoch)| output).collect(),
+            tx_hashes: HashMap::new(),
+        };
+        self.on_outputs_changed
+            .retain(move |ch| ch.unbounded_send(msg.clone()).is_ok());
+        Ok(())
+    }
+
+    ///
+    /// Creates EpochChanged notification for the current epoch.
+    /// Light nodes don't track awards, they are unknown.
+    ///
+    fn epoch_changed(&self) -> EpochChanged {
+        EpochChanged {
+            epoch: self.chain.epoch(),
+            validators: self.chain.election_result.validators.clone(),
+            facilitator: self.chain.election_result.facilitator,
+            random: self.chain.last_random(),
+            service_award: None,
+            service_awards_budget: 0,
+        }
+    }
+
+    /// Handler for NodeMessage::SubscribeEpoch.
+    fn handle_subscribe_epoch(&mut self, tx: UnboundedSender<EpochChanged>) -> Result<(), Error> {
+        let msg = self.epoch_changed();
+        tx.unbounded_send(msg).ok(); // ignore error.
+        self.on_epoch_changed.push(tx);
+        Ok(())
+    }
+
+    /// Handler for NodeMessage::PopBlock.
+    fn handle_pop_block(&mut self) -> Result<(), Error> {
+        warn!("Received a request to revert the latest block");
+        let height = self.chain.height() - 1;
+        self.rollback(height)
+    }
+
+    /// Handler for NodeMessage::RecoverWallet.
+    fn handle_recover_wallet(
+        &mut self,
+        wallet_skey: SecretKey,
+        wallet_pkey: PublicKey,
+        tx: oneshot::Sender<(Vec<(Output, u64)>, UnboundedReceiver<OutputsChanged>)>,
+    ) -> Result<(), Error> {
+        let outputs = self.recover_wallet(&wallet_skey, &wallet_pkey);
+        debug!(
+            "Recovered wallet: wallet_pkey={}, outputs={}",
+            wallet_pkey,
+            outputs.len()
+        );
+        let (outputs_tx, outputs_rx) = unbounded();
+        self.on_outputs_changed.push(outputs_tx);
+        tx.send((outputs, outputs_rx)).ok(); // ignore errors.
+        Ok(())
+    }
+
+    fn handle_shutdown(&mut self, tx: oneshot::Sender<()>) -> Result<(), Error> {
+        info!("Shutting down: height={}", self.chain.height());
+        self.stopped = true;
+        tx.send(()).ok(); // ignore errors.
+        Ok(())
+    }
+
+    /// Drains events after shutdown, requests are answered with an error.
+    fn poll_stopped(&mut self) -> Poll<(), ()> {
+        loop {
+            match self.events.poll().expect("all errors are already handled") {
+                Async::Ready(Some(NodeMessage::Request { tx, .. })) => {
+                    let error = "Node is shutting down".to_string();
+                    tx.send(NodeResponse::Error { error }).ok(); // ignore errors.
+                }
+                Async::Ready(Some(NodeMessage::Shutdown(tx))) => {
+                    tx.send(()).ok(); // ignore errors.
+                }
+                // Drop new work.
+                Async::Ready(Some(_)) => {}
+                Async::Ready(None) => unreachable!(), // never happens
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl NodeRoleService for LightNodeService {
+    fn init(&mut self) -> Result<(), Error> {
+        info!(
+            "Downloading blocks from remote nodes: height={}, remote_nodes={}",
+            self.chain.height(),
+            self.cfg.remote_nodes.len()
+        );
+        self.network
+            .prewarm_connections(self.cfg.remote_nodes.clone())?;
+        self.probe_remote_nodes()
+    }
+}
+
+// Event loop.
+impl Future for LightNodeService {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.stopped {
+            return self.poll_stopped();
+        }
+
+        // Poll timers first.
+        loop {
+            match self.sync_timer.poll() {
+                Ok(Async::Ready(Some(_))) => {
+                    if let Err(e) = self.probe_remote_nodes() {
+                        error!("Error: {}", e);
+                    }
+                }
+                Ok(Async::Ready(None)) => unreachable!(), // never happens
+                // Ignore transient timer errors.
+                Ok(Async::NotReady) | Err(_) => break,
+            }
+        }
+        if self.loader.poll_timer() && self.loader.expire(clock::now()) {
+            if let Err(e) = self.request_blocks() {
+                error!("Error: {}", e);
+            }
+        }
+
+        // Poll other events.
+        loop {
+            match self.events.poll().expect("all errors are already handled") {
+                Async::Ready(Some(event)) => {
+                    let result: Result<(), Error> = match event {
+                        NodeMessage::SubscribeBlockAdded(tx) => {
+                            self.on_block_added.push(tx);
+                            Ok(())
+                        }
+                        NodeMessage::SubscribeEpochChanged(tx) => self.handle_subscribe_epoch(tx),
+                        NodeMessage::SubscribeRandomBeacon(tx) => {
+                            self.on_random_beacon.push(tx);
+                            Ok(())
+                        }
+                        NodeMessage::SubscribeOutputsChanged(tx) => {
+                            self.on_outputs_changed.push(tx);
+                            Ok(())
+                        }
+                        NodeMessage::SubscribeTransactionConflicts(tx) => {
+                            self.on_transaction_conflict.push(tx);
+                            Ok(())
+                        }
+                        // Published to the network by Node::send_transaction().
+                        NodeMessage::LocalTransaction(_msg) => Ok(()),
+                        NodeMessage::PopBlock => self.handle_pop_block(),
+                        NodeMessage::RecoverWallet {
+                            wallet_skey,
+                            wallet_pkey,
+                            tx,
+                        } => self.handle_recover_wallet(wallet_skey, wallet_pkey, tx),
+                        NodeMessage::Shutdown(tx) => self.handle_shutdown(tx),
+                        NodeMessage::Request { tx, .. } => {
+                            let error = "Not supported by light nodes".to_string();
+                            tx.send(NodeResponse::Error { error }).ok(); // ignore errors.
+                            Ok(())
+                        }
+                        NodeMessage::Block(msg) => self.handle_block_buffer(msg),
+                        NodeMessage::ChainLoaderMessage(msg) => {
+                            ChainLoaderMessage::from_buffer(&msg.data)
+                                .and_then(|data| self.handle_chain_loader_message(msg.from, data))
+                        }
+                        // Light nodes don't subscribe to other topics.
+                        NodeMessage::Transaction(_)
+                        | NodeMessage::Consensus(_)
+                        | NodeMessage::ViewChangeMessage(_)
+                        | NodeMessage::ViewChangeProofMessage(_)
+                        | NodeMessage::ConfirmationVote(_)
+                        | NodeMessage::ChainStatus(_)
+                        | NodeMessage::ChainStatusTimer
+                        | NodeMessage::StemTransaction(_)
+                        | NodeMessage::DandelionTimer => Ok(()),
+                    };
+                    if let Err(e) = result {
+                        error!("Error: {}", e);
+                    }
+                    if self.stopped {
+                        return self.poll_stopped();
+                    }
+                }
+                Async::Ready(None) => unreachable!(), // never happens
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use stegos_blockchain::{MultiSignatureAccumulator, PublicPaymentOutput};
+    use stegos_crypto::curve1174;
+
+    fn base_header(
+        chain_id: Hash,
+        previous: Hash,
+        height: u64,
+        timestamp: SystemTime,
+        skey: &pbc::SecretKey,
+    ) -> BaseBlockHeader {
+        let random = pbc::make_VRF(skey, &Hash::digest(&height));
+        BaseBlockHeader::new(VERSION, chain_id, previous, height, 0, timestamp, random)
+    }
+
+    fn header_info(block: &Block, leader: pbc::PublicKey) -> HeaderInfo {
+        HeaderInfo {
+            hash: Hash::digest(block),
+            base: block.base_header().clone(),
+            leader,
+        }
+    }
+
+    #[test]
+    fn header_chain() {
+        let mut cfg = ChainConfig::default();
+        cfg.blocks_in_epoch = 2;
+        let chain_id = Hash::digest(&cfg.chain_id);
+        let (skey, pkey) = pbc::make_random_keys();
+        let (_wallet_skey, wallet_pkey) = curve1174::make_random_keys();
+        let timestamp = SystemTime::now();
+        let mut chain = HeaderChain::new(&cfg);
+
+        // Genesis, which elects the validator.
+        let base = base_header(chain_id, Hash::zero(), 0, timestamp, &skey);
+        let genesis = Block::MacroBlock(MacroBlock::empty(base, pkey));
+        let stake = Output::new_stake(&wallet_pkey, &skey, &pkey, cfg.min_stake_amount).unwrap();
+        chain.validate(&genesis).unwrap();
+        chain.push(header_info(&genesis, pkey), true, &[], &[stake]);
+        assert_eq!(chain.height(), 1);
+        assert_eq!(chain.epoch(), 1);
+        assert_eq!(chain.election_result.validators.len(), 1);
+        assert_eq!(chain.election_result.validators[0].0, pkey);
+
+        // Micro blocks follow the macro block.
+        let base = base_header(chain_id, chain.last_block_hash(), 1, timestamp, &skey);
+        let block = Block::MacroBlock(MacroBlock::empty(base.clone(), pkey));
+        match chain
+            .validate(&block)
+            .unwrap_err()
+            .downcast::<NodeBlockError>()
+        {
+            Ok(NodeBlockError::ExpectedMicroBlock(1, _)) => {}
+            e => panic!("unexpected result: {:?}", e),
+        }
+        let block = Block::MicroBlock(MicroBlock::empty(base, None, pkey));
+        chain.validate(&block).unwrap();
+        chain.push(header_info(&block, pkey), false, &[], &[]);
+
+        // Wrong previous hash and timestamp.
+        let base = base_header(chain_id, Hash::digest("fork"), 2, timestamp, &skey);
+        let block = Block::MicroBlock(MicroBlock::empty(base, None, pkey));
+        match chain.validate(&block).unwrap_err().downcast::<BlockError>() {
+            Ok(BlockError::InvalidPreviousHash(2, ..)) => {}
+            e => panic!("unexpected result: {:?}", e),
+        }
+        let previous = chain.last_block_hash();
+        let early = timestamp - Duration::from_secs(1);
+        let base = base_header(chain_id, previous, 2, early, &skey);
+        let block = Block::MicroBlock(MicroBlock::empty(base, None, pkey));
+        match chain.validate(&block).unwrap_err().downcast::<BlockError>() {
+            Ok(BlockError::TimestampBeforePrevious(2, ..)) => {}
+            e => panic!("unexpected result: {:?}", e),
+        }
+
+        // A macro block closes the epoch.
+        let base = base_header(chain_id, previous, 2, timestamp, &skey);
+        let block = Block::MicroBlock(MicroBlock::empty(base.clone(), None, pkey));
+        match chain
+            .validate(&block)
+            .unwrap_err()
+            .downcast::<NodeBlockError>()
+        {
+            Ok(NodeBlockError::ExpectedMacroBlock(2, _)) => {}
+            e => panic!("unexpected result: {:?}", e),
+        }
+        let mut macro_block = MacroBlock::empty(base, pkey);
+        macro_block.body.inputs.push(Hash::digest("input"));
+        let block = Block::MacroBlock(macro_block.clone());
+        match chain.validate(&block).unwrap_err().downcast::<BlockError>() {
+            Ok(BlockError::InvalidBlockInputsHash(2, ..)) => {}
+            e => panic!("unexpected result: {:?}", e),
+        }
+        macro_block.body.inputs.clear();
+        let block = Block::MacroBlock(macro_block.clone());
+        match chain.validate(&block).unwrap_err().downcast::<BlockError>() {
+            Ok(BlockError::InvalidBlockSignature(_, 2, _)) => {}
+            e => panic!("unexpected result: {:?}", e),
+        }
+        let mut multisig = MultiSignatureAccumulator::new();
+        multisig.add(0, &pbc::sign_hash(&Hash::digest(&macro_block), &skey));
+        let (sig, sigmap) = multisig.finish();
+        macro_block.body.multisig = sig;
+        macro_block.body.multisigmap = sigmap;
+        let block = Block::MacroBlock(macro_block);
+        chain.validate(&block).unwrap();
+
+        // Only micro blocks can be reverted.
+        assert_eq!(chain.pop().unwrap().base.height, 1);
+        assert!(chain.pop().is_err());
+        assert_eq!(chain.height(), 1);
+    }
+
+    #[test]
+    fn wallet_scanner() {
+        let (skey1, pkey1) = curve1174::make_random_keys();
+        let (skey2, pkey2) = curve1174::make_random_keys();
+        let (_skey3, pkey3) = curve1174::make_random_keys();
+        let mut scanner = WalletScanner::new();
+        assert!(scanner.add_key(skey1.clone(), pkey1));
+        assert!(!scanner.add_key(skey1.clone(), pkey1));
+
+        let output1: Output = PublicPaymentOutput::new(&pkey1, 10).into();
+        let output2: Output = PublicPaymentOutput::new(&pkey2, 20).into();
+        let output3: Output = PublicPaymentOutput::new(&pkey3, 30).into();
+        let (spent, created) = scanner.apply(0, &[], &[output1.clone(), output2.clone()]);
+        assert!(spent.is_empty());
+        assert_eq!(created.len(), 1);
+        assert_eq!(Hash::digest(&created[0]), Hash::digest(&output1));
+        assert_eq!(scanner.wallet_outputs(&skey1, &pkey1).len(), 1);
+
+        // Outputs of other keys are not tracked before the key is added.
+        assert!(scanner.add_key(skey2.clone(), pkey2));
+        assert!(scanner.wallet_outputs(&skey2, &pkey2).is_empty());
+
+        // Spend the output and revert the spending.
+        let (spent, created) = scanner.apply(1, &[Hash::digest(&output1)], &[output3]);
+        assert_eq!(spent.len(), 1);
+        assert_eq!(spent[0].1, 0);
+        assert!(created.is_empty());
+        assert!(scanner.wallet_outputs(&skey1, &pkey1).is_empty());
+        scanner.revert(&spent, &created);
+        let outputs = scanner.wallet_outputs(&skey1, &pkey1);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].1, 0);
+    }
+}
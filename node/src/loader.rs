@@ -19,42 +19,59 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::config::ChainConfig;
 use crate::NodeService;
 use failure::{format_err, Error};
+use futures::{Async, Future};
 use log::*;
 use rand::seq::IteratorRandom;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Instant;
 use stegos_blockchain::Block;
 use stegos_crypto::hash::{Hashable, Hasher};
 use stegos_crypto::pbc;
 use stegos_serialization::traits::ProtoConvert;
-use tokio_timer::clock;
+use tokio_timer::{clock, Delay};
+
+/// Peers which failed this many requests in a row are not used for sync.
+const MAX_PEER_FAILURES: u32 = 3;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct RequestBlocks {
     pub starting_height: u64,
+    /// The number of requested blocks, zero means as many as the peer is willing to send.
+    pub count: u64,
 }
 
 impl Hashable for RequestBlocks {
     fn hash(&self, state: &mut Hasher) {
         self.starting_height.hash(state);
+        self.count.hash(state);
     }
 }
 
 impl RequestBlocks {
-    pub fn new(starting_height: u64) -> RequestBlocks {
-        Self { starting_height }
+    pub fn new(starting_height: u64, count: u64) -> RequestBlocks {
+        Self {
+            starting_height,
+            count,
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ResponseBlocks {
+    /// The height of the sender.
     pub height: u64,
+    /// The height of the first requested block.
+    pub starting_height: u64,
     pub blocks: Vec<Block>,
 }
 
 impl Hashable for ResponseBlocks {
     fn hash(&self, state: &mut Hasher) {
         self.height.hash(state);
+        self.starting_height.hash(state);
         for block in &self.blocks {
             block.hash(state);
         }
@@ -62,8 +79,12 @@ impl Hashable for ResponseBlocks {
 }
 
 impl ResponseBlocks {
-    pub fn new(height: u64, blocks: Vec<Block>) -> ResponseBlocks {
-        Self { height, blocks }
+    pub fn new(height: u64, starting_height: u64, blocks: Vec<Block>) -> ResponseBlocks {
+        Self {
+            height,
+            starting_height,
+            blocks,
+        }
     }
 }
 
@@ -91,6 +112,294 @@ impl Hashable for ChainLoaderMessage {
 /// Unicast topic for loading blocks.
 pub const CHAIN_LOADER_TOPIC: &'static str = "chain-loader";
 
+/// A range of blocks requested from a peer.
+#[derive(Debug, Clone)]
+struct InFlightRequest {
+    starting_height: u64,
+    count: u64,
+    deadline: Instant,
+}
+
+///
+/// Block synchronization state.
+///
+/// Ranges of blocks are requested from several peers in parallel, limited by
+/// a per-peer window of in-flight requests. Received blocks are buffered until
+/// all preceding blocks have been applied. New ranges are requested only while
+/// the buffer has room, so a slow validation throttles downloading.
+///
+pub(crate) struct ChainLoader {
+    /// The best height announced by peers.
+    target_height: u64,
+    /// The next height to be requested.
+    next_height: u64,
+    /// Heights reported by peers.
+    peer_heights: HashMap<pbc::PublicKey, u64>,
+    /// Requests waiting for responses, per peer.
+    in_flight: HashMap<pbc::PublicKey, Vec<InFlightRequest>>,
+    /// Ranges which must be requested again, (starting_height, count).
+    retry: VecDeque<(u64, u64)>,
+    /// Downloaded blocks waiting for the preceding blocks.
+    pending: BTreeMap<u64, (pbc::PublicKey, Block)>,
+    /// The number of failed requests in a row, per peer.
+    failures: HashMap<pbc::PublicKey, u32>,
+    /// Fires on the earliest deadline of in-flight requests.
+    timer: Option<Delay>,
+}
+
+impl ChainLoader {
+    pub(crate) fn new() -> Self {
+        ChainLoader {
+            target_height: 0,
+            next_height: 0,
+            peer_heights: HashMap::new(),
+            in_flight: HashMap::new(),
+            retry: VecDeque::new(),
+            pending: BTreeMap::new(),
+            failures: HashMap::new(),
+            timer: None,
+        }
+    }
+
+    /// The best height announced by peers.
+    pub(crate) fn target_height(&self) -> u64 {
+        self.target_height
+    }
+
+    /// The number of requests waiting for responses.
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.values().map(Vec::len).sum()
+    }
+
+    /// The number of downloaded blocks waiting for the preceding blocks.
+    pub(crate) fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Records the height announced by a peer or learned from a block.
+    pub(crate) fn on_remote_height(&mut self, pkey: Option<pbc::PublicKey>, height: u64) {
+        if let Some(pkey) = pkey {
+            let peer_height = self.peer_heights.entry(pkey).or_insert(0);
+            *peer_height = std::cmp::max(*peer_height, height);
+        }
+        if height > self.target_height {
+            debug!(
+                "Updated sync target: old_target={}, new_target={}",
+                self.target_height, height
+            );
+            self.target_height = height;
+        }
+    }
+
+    fn is_usable(&self, pkey: &pbc::PublicKey) -> bool {
+        self.failures.get(pkey).cloned().unwrap_or(0) < MAX_PEER_FAILURES
+    }
+
+    fn has_in_flight(&self, pkey: &pbc::PublicKey) -> bool {
+        self.in_flight.get(pkey).map_or(false, |r| !r.is_empty())
+    }
+
+    fn register(
+        &mut self,
+        pkey: pbc::PublicKey,
+        starting_height: u64,
+        count: u64,
+        deadline: Instant,
+    ) {
+        let request = InFlightRequest {
+            starting_height,
+            count,
+            deadline,
+        };
+        self.next_height = std::cmp::max(self.next_height, starting_height + count);
+        self.in_flight.entry(pkey).or_default().push(request);
+        self.update_timer();
+    }
+
+    ///
+    /// Chooses the next range to download and the peer to download it from.
+    /// Returns None if the window is full or there is nothing to request.
+    ///
+    pub(crate) fn next_request(
+        &mut self,
+        our_height: u64,
+        peers: &[pbc::PublicKey],
+        cfg: &ChainConfig,
+        now: Instant,
+    ) -> Option<(pbc::PublicKey, u64, u64)> {
+        if self.next_height < our_height {
+            self.next_height = our_height;
+        }
+        // Skip ranges which have been applied in the meantime.
+        while let Some(&(starting_height, count)) = self.retry.front() {
+            if starting_height + count > our_height {
+                break;
+            }
+            self.retry.pop_front();
+        }
+        let (starting_height, count, is_retry) = match self.retry.front() {
+            Some(&(starting_height, count)) => (starting_height, count, true),
+            None => {
+                if self.next_height >= self.target_height {
+                    return None;
+                }
+                let count = std::cmp::min(
+                    cfg.loader_request_size,
+                    self.target_height - self.next_height,
+                );
+                (self.next_height, count, false)
+            }
+        };
+        // Backpressure: don't download too far ahead of the validated chain.
+        if starting_height >= our_height + cfg.loader_max_pending_blocks {
+            return None;
+        }
+
+        let pkey = peers
+            .iter()
+            .filter(|pkey| self.is_usable(pkey))
+            .filter(|pkey| {
+                self.peer_heights
+                    .get(pkey)
+                    .map_or(true, |height| *height > starting_height)
+            })
+            .map(|pkey| (self.in_flight.get(pkey).map_or(0, Vec::len), pkey))
+            .filter(|(in_flight, _)| *in_flight < cfg.loader_in_flight_per_peer)
+            .min_by_key(|(in_flight, _)| *in_flight)
+            .map(|(_, pkey)| *pkey)?;
+
+        if is_retry {
+            self.retry.pop_front();
+        }
+        self.register(
+            pkey,
+            starting_height,
+            count,
+            now + cfg.loader_request_timeout,
+        );
+        Some((pkey, starting_height, count))
+    }
+
+    ///
+    /// Handles a response from a peer.
+    /// Returns false if the response doesn't match any request.
+    ///
+    pub(crate) fn on_response(
+        &mut self,
+        pkey: pbc::PublicKey,
+        response: ResponseBlocks,
+        our_height: u64,
+        cfg: &ChainConfig,
+    ) -> bool {
+        self.on_remote_height(Some(pkey), response.height);
+
+        let request = self.in_flight.get_mut(&pkey).and_then(|requests| {
+            let pos = requests
+                .iter()
+                .position(|r| r.starting_height == response.starting_height)?;
+            Some(requests.remove(pos))
+        });
+        self.update_timer();
+
+        let max_height = our_height + cfg.loader_max_pending_blocks;
+        let mut received = 0;
+        for block in response.blocks {
+            let height = block.base_header().height;
+            if height >= max_height {
+                break;
+            }
+            self.pending.insert(height, (pkey, block));
+            received += 1;
+        }
+
+        let request = match request {
+            Some(request) => request,
+            None => return false,
+        };
+        self.failures.remove(&pkey);
+        // Request the missing tail again from someone else.
+        if received < request.count {
+            let starting_height = request.starting_height + received;
+            if starting_height < self.target_height {
+                let count = request.count - received;
+                self.retry.push_back((starting_height, count));
+            }
+        }
+        true
+    }
+
+    ///
+    /// Expires requests which weren't answered in time.
+    /// Returns true if some requests have been expired.
+    ///
+    pub(crate) fn expire(&mut self, now: Instant) -> bool {
+        let mut expired = false;
+        for (pkey, requests) in self.in_flight.iter_mut() {
+            let mut i = 0;
+            while i < requests.len() {
+                if requests[i].deadline > now {
+                    i += 1;
+                    continue;
+                }
+                let request = requests.remove(i);
+                warn!(
+                    "Timed out waiting for blocks: from={}, starting_height={}, count={}",
+                    pkey, request.starting_height, request.count
+                );
+                *self.failures.entry(*pkey).or_insert(0) += 1;
+                if request.starting_height < self.target_height {
+                    self.retry
+                        .push_back((request.starting_height, request.count));
+                }
+                expired = true;
+            }
+        }
+        self.update_timer();
+        expired
+    }
+
+    /// Takes the next downloaded block which can be applied to the chain.
+    pub(crate) fn pop_ready(&mut self, our_height: u64) -> Option<(pbc::PublicKey, Block)> {
+        let height = *self.pending.keys().next()?;
+        if height > our_height {
+            return None;
+        }
+        self.pending.remove(&height)
+    }
+
+    /// Discards downloaded blocks starting from an invalid one.
+    pub(crate) fn on_invalid_block(&mut self, pkey: pbc::PublicKey, height: u64) {
+        *self.failures.entry(pkey).or_insert(0) += 1;
+        let dropped = self.pending.split_off(&height);
+        if let Some((&last, _)) = dropped.iter().next_back() {
+            self.retry.push_back((height, last - height + 1));
+        }
+    }
+
+    /// Discards downloaded blocks after the chain has been reverted to the height.
+    pub(crate) fn on_rollback(&mut self, height: u64) {
+        self.pending.split_off(&height);
+        self.retry.clear();
+        self.next_height = height;
+    }
+
+    fn update_timer(&mut self) {
+        let deadline = self.in_flight.values().flatten().map(|r| r.deadline).min();
+        self.timer = deadline.map(Delay::new);
+    }
+
+    /// Returns true if some in-flight requests have reached their deadline.
+    pub(crate) fn poll_timer(&mut self) -> bool {
+        match &mut self.timer {
+            Some(timer) => match timer.poll().unwrap() {
+                Async::Ready(()) => true,
+                Async::NotReady => false,
+            },
+            None => false,
+        }
+    }
+}
+
 impl NodeService {
     /// Choose a master node to download blocks from.
     fn choose_master(&self) -> Option<pbc::PublicKey> {
@@ -112,6 +421,16 @@ impl NodeService {
         return Some(master);
     }
 
+    /// Peers which can be used to download blocks.
+    fn sync_peers(&self) -> Vec<pbc::PublicKey> {
+        self.chain
+            .validators()
+            .iter()
+            .map(|(k, _)| *k)
+            .filter(|key| self.keys.network_pkey != *key)
+            .collect()
+    }
+
     pub fn request_history(&mut self) -> Result<(), Error> {
         let from = if self.is_synchronized() {
             // Try to download history from the leader.
@@ -125,14 +444,23 @@ impl NodeService {
         self.request_history_from(from)
     }
 
+    ///
+    /// Asks a peer for blocks of the current epoch and beyond.
+    /// The response tells the height of the peer, which starts parallel download
+    /// if we are behind.
+    ///
     pub fn request_history_from(&mut self, from: pbc::PublicKey) -> Result<(), Error> {
+        if self.loader.has_in_flight(&from) {
+            debug!("Already waiting for blocks: from={}", &from);
+            return self.request_blocks();
+        }
         let elapsed = clock::now().duration_since(self.last_sync_clock);
         if elapsed < self.cfg.loader_timeout {
             debug!(
                 "Throttling loader: elapsed={:?}, min_interval={:?}",
                 elapsed, self.cfg.loader_timeout
             );
-            return Ok(());
+            return self.request_blocks();
         }
 
         let start_height = self.chain.last_macro_block_height();
@@ -142,10 +470,53 @@ impl NodeService {
             start_height,
             self.chain.height()
         );
-        let msg = ChainLoaderMessage::Request(RequestBlocks::new(start_height));
+        let msg = ChainLoaderMessage::Request(RequestBlocks::new(start_height, 0));
         self.last_sync_clock = clock::now();
+        self.loader.register(
+            from,
+            start_height,
+            self.cfg.blocks_in_epoch * self.cfg.chain_loader_speed_in_epoch,
+            self.last_sync_clock + self.cfg.loader_request_timeout,
+        );
         self.network
-            .send(from, CHAIN_LOADER_TOPIC, msg.into_buffer()?)
+            .send(from, CHAIN_LOADER_TOPIC, msg.into_buffer()?)?;
+        self.request_blocks()
+    }
+
+    /// Called when a block from the future has been received.
+    pub(crate) fn on_future_block(&mut self, height: u64) -> Result<(), Error> {
+        self.loader.on_remote_height(None, height + 1);
+        self.request_history()
+    }
+
+    /// Fills windows of in-flight requests while we are behind.
+    fn request_blocks(&mut self) -> Result<(), Error> {
+        let peers = self.sync_peers();
+        while let Some((from, starting_height, count)) =
+            self.loader
+                .next_request(self.chain.height(), &peers, &self.cfg, clock::now())
+        {
+            debug!(
+                "Requesting blocks: from={}, starting_height={}, count={}, target_height={}, our_height={}",
+                from,
+                starting_height,
+                count,
+                self.loader.target_height(),
+                self.chain.height()
+            );
+            let msg = ChainLoaderMessage::Request(RequestBlocks::new(starting_height, count));
+            self.network
+                .send(from, CHAIN_LOADER_TOPIC, msg.into_buffer()?)?;
+        }
+        Ok(())
+    }
+
+    /// Handles expired requests.
+    pub(crate) fn poll_loader_timer(&mut self) -> Result<(), Error> {
+        if self.loader.poll_timer() && self.loader.expire(clock::now()) {
+            self.request_blocks()?;
+        }
+        Ok(())
     }
 
     fn handle_request_blocks(
@@ -161,18 +532,34 @@ impl NodeService {
             return Ok(());
         }
 
-        self.send_blocks(pkey, starting_height)
+        let max_count = self.cfg.blocks_in_epoch * self.cfg.chain_loader_speed_in_epoch;
+        let count = if request.count == 0 || request.count > max_count {
+            max_count
+        } else {
+            request.count
+        };
+        self.send_blocks_range(pkey, starting_height, count)
     }
 
     pub fn send_blocks(&mut self, pkey: pbc::PublicKey, starting_height: u64) -> Result<(), Error> {
+        let count = self.cfg.blocks_in_epoch * self.cfg.chain_loader_speed_in_epoch;
+        self.send_blocks_range(pkey, starting_height, count)
+    }
+
+    fn send_blocks_range(
+        &mut self,
+        pkey: pbc::PublicKey,
+        starting_height: u64,
+        count: u64,
+    ) -> Result<(), Error> {
         assert!(starting_height < self.chain.height());
-        // Send one epoch.
-        let blocks = self.chain.blocks_range(
-            starting_height,
-            self.cfg.blocks_in_epoch * self.cfg.chain_loader_speed_in_epoch,
-        );
+        let blocks = self.chain.blocks_range(starting_height, count);
         info!("Feeding blocks: to={}, num_blocks={}", pkey, blocks.len());
-        let msg = ChainLoaderMessage::Response(ResponseBlocks::new(self.chain.height(), blocks));
+        let msg = ChainLoaderMessage::Response(ResponseBlocks::new(
+            self.chain.height(),
+            starting_height,
+            blocks,
+        ));
         self.network
             .send(pkey, CHAIN_LOADER_TOPIC, msg.into_buffer()?)?;
         Ok(())
@@ -184,28 +571,45 @@ impl NodeService {
         response: ResponseBlocks,
     ) -> Result<(), Error> {
         info!(
-            "Received blocks: from={}, num_blocks={}, remote_height={}",
+            "Received blocks: from={}, starting_height={}, num_blocks={}, remote_height={}",
             pkey,
+            response.starting_height,
             response.blocks.len(),
             response.height,
         );
 
-        let initial_height = self.chain.height();
-        for block in response.blocks {
-            // Fail on the first error.
-            self.handle_block(block)?;
+        let our_height = self.chain.height();
+        if !self
+            .loader
+            .on_response(pkey, response, our_height, &self.cfg)
+        {
+            debug!("Received unsolicited blocks: from={}", pkey);
         }
 
-        //
-        // Request more blocks in the follwing cases:
-        // a) The timestamp of the latest keyblock is oudated (see is_synchronized()).
-        // b) At least two blocks have been applied.
-        //
-        if !self.is_synchronized() || (self.chain.height() >= initial_height + 2) {
-            //self.request_history()?;
-        }
+        self.apply_downloaded_blocks();
+        self.request_blocks()
+    }
 
-        Ok(())
+    /// Validates and applies downloaded blocks in order.
+    fn apply_downloaded_blocks(&mut self) {
+        while let Some((pkey, block)) = self.loader.pop_ready(self.chain.height()) {
+            let height = block.base_header().height;
+            if let Err(e) = self.handle_block(block) {
+                error!(
+                    "Failed to apply a downloaded block: from={}, height={}, error={}",
+                    pkey, height, e
+                );
+                self.loader.on_invalid_block(pkey, height);
+                break;
+            }
+        }
+        debug!(
+            "Sync status: our_height={}, target_height={}, in_flight={}, pending={}",
+            self.chain.height(),
+            self.loader.target_height(),
+            self.loader.in_flight(),
+            self.loader.pending()
+        );
     }
 
     pub fn handle_chain_loader_message(
@@ -219,3 +623,89 @@ impl NodeService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config() -> ChainConfig {
+        let mut cfg = ChainConfig::default();
+        cfg.loader_request_size = 10;
+        cfg.loader_in_flight_per_peer = 2;
+        cfg.loader_max_pending_blocks = 100;
+        cfg.loader_request_timeout = Duration::from_secs(10);
+        cfg
+    }
+
+    #[test]
+    fn windows() {
+        let cfg = config();
+        let (_, pkey1) = pbc::make_random_keys();
+        let (_, pkey2) = pbc::make_random_keys();
+        let peers = [pkey1, pkey2];
+        let now = Instant::now();
+        let mut loader = ChainLoader::new();
+
+        // Nothing to do until we know that we are behind.
+        assert!(loader.next_request(0, &peers, &cfg, now).is_none());
+
+        loader.on_remote_height(None, 100);
+        let mut requests = Vec::new();
+        while let Some(request) = loader.next_request(0, &peers, &cfg, now) {
+            requests.push(request);
+        }
+        assert_eq!(requests.len(), 2 * cfg.loader_in_flight_per_peer);
+        for (i, (_, starting_height, count)) in requests.iter().enumerate() {
+            assert_eq!(*starting_height, i as u64 * 10);
+            assert_eq!(*count, 10);
+        }
+        for pkey in &peers {
+            assert_eq!(loader.in_flight[pkey].len(), cfg.loader_in_flight_per_peer);
+        }
+
+        // Timeouts move ranges to other peers.
+        assert!(!loader.expire(now));
+        assert!(loader.expire(now + cfg.loader_request_timeout));
+        assert_eq!(loader.in_flight(), 0);
+        assert_eq!(loader.retry.len(), 4);
+        let (_, starting_height, _) = loader.next_request(0, &peers, &cfg, now).unwrap();
+        assert_eq!(starting_height, 0);
+
+        // Peers are not used after several failures.
+        let mut loader = ChainLoader::new();
+        loader.on_remote_height(None, 100);
+        loader.failures.insert(pkey1, MAX_PEER_FAILURES);
+        while let Some((pkey, _, _)) = loader.next_request(0, &peers, &cfg, now) {
+            assert_eq!(pkey, pkey2);
+        }
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut cfg = config();
+        cfg.loader_max_pending_blocks = 25;
+        cfg.loader_in_flight_per_peer = 100;
+        let (_, pkey) = pbc::make_random_keys();
+        let peers = [pkey];
+        let now = Instant::now();
+        let mut loader = ChainLoader::new();
+        loader.on_remote_height(Some(pkey), 1000);
+
+        let mut heights = Vec::new();
+        while let Some((_, starting_height, _)) = loader.next_request(0, &peers, &cfg, now) {
+            heights.push(starting_height);
+        }
+        assert_eq!(heights, vec![0, 10, 20]);
+
+        // More blocks are requested once the chain has grown.
+        let (_, starting_height, _) = loader.next_request(10, &peers, &cfg, now).unwrap();
+        assert_eq!(starting_height, 30);
+
+        // Peers are not asked for blocks they don't have.
+        let (_, pkey2) = pbc::make_random_keys();
+        loader.on_remote_height(Some(pkey2), 20);
+        let peers = [pkey2];
+        assert!(loader.next_request(30, &peers, &cfg, now).is_none());
+    }
+}
@@ -28,10 +28,14 @@ use std::collections::HashSet;
 use std::time::SystemTime;
 use stegos_blockchain::view_changes::ViewChangeProof;
 use stegos_blockchain::*;
-use stegos_crypto::curve1174::Fr;
+use stegos_crypto::curve1174::{Fr, PublicKey};
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
 use stegos_keychain::KeyChain;
+use stegos_serialization::traits::ProtoConvert;
+
+/// Space in micro block reserved for the header and the coinbase transaction.
+const MICRO_BLOCK_RESERVED_SIZE: usize = 16 * 1024;
 
 /// Memory Pool of Transactions.
 pub struct Mempool {
@@ -153,27 +157,30 @@ impl Mempool {
 
     ///
     /// Process transactions in mempool and create a new monetary block.
+    /// Rewards are paid publicly to `reward_pkey` if stakes of the leader set it,
+    /// and to the wallet of `keychain` otherwise.
     ///
     pub fn create_block(
         &mut self,
         previous: Hash,
         version: u64,
+        chain_id: Hash,
         height: u64,
+        timestamp: SystemTime,
         block_reward: i64,
         keychain: &KeyChain,
-        last_random: Hash,
+        reward_pkey: Option<&PublicKey>,
+        random: pbc::VRF,
         view_change: u32,
         view_change_proof: Option<ViewChangeProof>,
         max_utxo_in_block: usize,
-    ) -> MicroBlock {
-        let timestamp = SystemTime::now();
-        let seed = mix(last_random, view_change);
-        let random = pbc::make_VRF(&keychain.network_skey, &seed);
-
+        max_block_size: usize,
+    ) -> Result<MicroBlock, BlockBuilderError> {
         //
         // Mempool Transactions.
         //
         let mut utxo_in_block: usize = 2;
+        let mut block_size: usize = MICRO_BLOCK_RESERVED_SIZE;
         let mut block_fee: i64 = 0;
         let mut transactions: Vec<Transaction> = Vec::new();
         // Reserve a place for coinbase.
@@ -187,6 +194,7 @@ impl Mempool {
             match tx {
                 Transaction::PaymentTransaction(_tx) => {}
                 Transaction::RestakeTransaction(_tx) => {}
+                Transaction::RevokeDelegationTransaction(_tx) => {}
                 Transaction::SlashingTransaction(_tx) => {}
                 Transaction::CoinbaseTransaction(_) | Transaction::ServiceAwardTransaction(_) => {
                     panic!("Invalid transaction type in mempool: tx={:?}", tx_hash)
@@ -198,9 +206,16 @@ impl Mempool {
                 break;
             }
 
+            // Check the maximum size of block.
+            let tx_size = tx.into_buffer().expect("serialization never fails").len();
+            if block_size + tx_size > max_block_size {
+                break;
+            }
+
             debug!("Processing transaction: hash={}", &tx_hash);
             transactions.push(tx.clone());
             block_fee += tx.fee();
+            block_size += tx_size;
             utxo_in_block += tx.txins().len();
             utxo_in_block += tx.txouts().len();
         }
@@ -225,10 +240,18 @@ impl Mempool {
                 }
 
                 let data = PaymentPayloadData::Comment(format!("Block {}", comment));
-                let (output_fee, gamma_fee) =
-                    PaymentOutput::with_payload(&keychain.wallet_pkey, amount, data.clone())
-                        .expect("invalid keys");
-                gamma -= gamma_fee;
+                let output_fee: Output = match reward_pkey {
+                    Some(reward_pkey) => PublicPaymentOutput::new(reward_pkey, amount).into(),
+                    None => {
+                        let (output_fee, gamma_fee) = PaymentOutput::with_payload(
+                            &keychain.wallet_pkey,
+                            amount,
+                            data.clone(),
+                        )?;
+                        gamma -= gamma_fee;
+                        output_fee.into()
+                    }
+                };
 
                 info!(
                     "Created {} UTXO: hash={}, amount={}, data={:?}",
@@ -237,7 +260,7 @@ impl Mempool {
                     amount,
                     data
                 );
-                txouts.push(Output::PaymentOutput(output_fee));
+                txouts.push(output_fee);
             }
 
             let coinbase = CoinbaseTransaction {
@@ -254,8 +277,17 @@ impl Mempool {
         }
 
         // Create a new micro block.
-        let base = BaseBlockHeader::new(version, previous, height, view_change, timestamp, random);
-        MicroBlock::new(base, view_change_proof, transactions, keychain.network_pkey)
+        let base = BaseBlockHeader::new(
+            version,
+            chain_id,
+            previous,
+            height,
+            view_change,
+            timestamp,
+            random,
+        );
+        let block = MicroBlock::new(base, view_change_proof, transactions, keychain.network_pkey);
+        Ok(block)
     }
 }
 
@@ -266,14 +298,15 @@ mod test {
 
     #[test]
     fn basic() {
+        let chain_id = Hash::digest("test");
         let (skey, pkey) = make_random_keys();
         let mut mempool = Mempool::new();
 
         let (tx1, inputs1, outputs1) =
-            PaymentTransaction::new_test(&skey, &pkey, 100, 2, 200, 1, 0)
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 100, 2, 200, 1, 0)
                 .expect("transaction valid");
         let (tx2, inputs2, outputs2) =
-            PaymentTransaction::new_test(&skey, &pkey, 300, 1, 100, 3, 0)
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 300, 1, 100, 3, 0)
                 .expect("transaction valid");
         let tx_hash1 = Hash::digest(&tx1);
         let tx_hash2 = Hash::digest(&tx2);
@@ -333,11 +366,13 @@ mod test {
 
     #[test]
     pub fn partial_pruning1() {
+        let chain_id = Hash::digest("test");
         let (skey, pkey) = make_random_keys();
         let mut mempool = Mempool::new();
 
-        let (tx, inputs, outputs) = PaymentTransaction::new_test(&skey, &pkey, 100, 2, 100, 2, 0)
-            .expect("transaction valid");
+        let (tx, inputs, outputs) =
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 100, 2, 100, 2, 0)
+                .expect("transaction valid");
         let tx_hash = Hash::digest(&tx);
         mempool.push_tx(tx_hash.clone(), tx.clone().into());
         mempool.prune(&vec![Hash::digest(&inputs[0])], &vec![]);
@@ -354,11 +389,13 @@ mod test {
 
     #[test]
     pub fn partial_pruning2() {
+        let chain_id = Hash::digest("test");
         let (skey, pkey) = make_random_keys();
         let mut mempool = Mempool::new();
 
-        let (tx, inputs, outputs) = PaymentTransaction::new_test(&skey, &pkey, 100, 2, 100, 2, 0)
-            .expect("transaction valid");
+        let (tx, inputs, outputs) =
+            PaymentTransaction::new_test(&chain_id, &skey, &pkey, 100, 2, 100, 2, 0)
+                .expect("transaction valid");
         let tx_hash = Hash::digest(&tx);
         mempool.push_tx(tx_hash.clone(), tx.clone().into());
         mempool.prune(&vec![], &vec![Hash::digest(&outputs[0])]);
@@ -375,19 +412,44 @@ mod test {
 
     #[test]
     fn create_block() {
+        let chain_id = Hash::digest("test");
         let keys = KeyChain::new_mem();
         let max_utxo_in_block: usize = 9;
         let mut mempool = Mempool::new();
 
-        let (tx1, _inputs1, _outputs1) =
-            PaymentTransaction::new_test(&keys.wallet_skey, &keys.wallet_pkey, 3, 2, 2, 1, 4)
-                .expect("transaction valid");
-        let (tx2, _inputs2, _outputs2) =
-            PaymentTransaction::new_test(&keys.wallet_skey, &keys.wallet_pkey, 6, 1, 2, 2, 2)
-                .expect("transaction valid");
-        let (tx3, _inputs3, _outputs3) =
-            PaymentTransaction::new_test(&keys.wallet_skey, &keys.wallet_pkey, 6, 1, 2, 2, 2)
-                .expect("transaction valid");
+        let (tx1, _inputs1, _outputs1) = PaymentTransaction::new_test(
+            &chain_id,
+            &keys.wallet_skey,
+            &keys.wallet_pkey,
+            3,
+            2,
+            2,
+            1,
+            4,
+        )
+        .expect("transaction valid");
+        let (tx2, _inputs2, _outputs2) = PaymentTransaction::new_test(
+            &chain_id,
+            &keys.wallet_skey,
+            &keys.wallet_pkey,
+            6,
+            1,
+            2,
+            2,
+            2,
+        )
+        .expect("transaction valid");
+        let (tx3, _inputs3, _outputs3) = PaymentTransaction::new_test(
+            &chain_id,
+            &keys.wallet_skey,
+            &keys.wallet_pkey,
+            6,
+            1,
+            2,
+            2,
+            2,
+        )
+        .expect("transaction valid");
 
         let tx_hash1 = Hash::digest(&tx1);
         let tx_hash2 = Hash::digest(&tx2);
@@ -401,17 +463,23 @@ mod test {
         let height = 0;
         let view_change = 0;
         let reward = 10;
-        let block = mempool.create_block(
-            previous,
-            version,
-            height,
-            reward,
-            &keys,
-            Hash::digest("test"),
-            view_change,
-            None,
-            max_utxo_in_block,
-        );
+        let block = mempool
+            .create_block(
+                previous,
+                version,
+                chain_id,
+                height,
+                SystemTime::now(),
+                reward,
+                &keys,
+                None,
+                pbc::make_VRF(&keys.network_skey, &Hash::digest("test")),
+                view_change,
+                None,
+                max_utxo_in_block,
+                BlockchainConfig::default().max_block_size,
+            )
+            .expect("block is valid");
 
         assert_eq!(block.transactions.len(), 3);
         if let Transaction::CoinbaseTransaction(tx) = &block.transactions[0] {
@@ -57,6 +57,29 @@ lazy_static! {
         "The number of duplicate blocks for the same slot detected"
     )
     .unwrap();
+    pub static ref ORPHAN_BLOCKS: IntCounter = register_int_counter!(
+        "stegos_orphan_blocks",
+        "The number of blocks received before their parents"
+    )
+    .unwrap();
+    pub static ref ORPHAN_BLOCKS_PROCESSED: IntCounter = register_int_counter!(
+        "stegos_orphan_blocks_processed",
+        "The number of orphan blocks processed after their parents have arrived"
+    )
+    .unwrap();
+    pub static ref ORPHAN_BLOCKS_EXPIRED: IntCounter = register_int_counter!(
+        "stegos_orphan_blocks_expired",
+        "The number of orphan blocks dropped by timeout"
+    )
+    .unwrap();
+    pub static ref ORPHAN_POOL_SIZE: IntGauge =
+        register_int_gauge!("stegos_orphan_pool_size", "The number of blocks in the orphan pool.").unwrap();
+    pub static ref SAFE_MODE: IntGauge =
+        register_int_gauge!("stegos_safe_mode", "Flag that the node has detected a network partition and refuses to lead.").unwrap();
+    pub static ref REACHABLE_STAKE: IntGauge =
+        register_int_gauge!("stegos_reachable_stake_percent", "The share of slots held by validators which have recently sent their chain status.").unwrap();
+    pub static ref MISSED_ROUNDS: IntGauge =
+        register_int_gauge!("stegos_missed_rounds", "The number of consecutive view change timeouts since the last block.").unwrap();
     pub static ref SYNCHRONIZED: IntGauge =
         register_int_gauge!("stegos_synchronized", "Flag that the node is synchornized with the network.").unwrap();
     pub static ref BLOCK_REMOTE_TIMESTAMP: IntGauge =
@@ -0,0 +1,197 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Orphan blocks.
+
+use crate::metrics;
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use stegos_blockchain::Block;
+use stegos_crypto::hash::Hash;
+
+///
+/// Blocks which have arrived before their parents.
+///
+/// The pool is bounded by the number of blocks and by their age.
+/// When the pool is full, the oldest block is evicted.
+///
+pub(crate) struct OrphanPool {
+    /// Orphans by hash, with the time of arrival.
+    blocks: HashMap<Hash, (Block, Instant)>,
+    /// Orphans by the hash of the previous block.
+    by_previous: HashMap<Hash, Vec<Hash>>,
+    /// Hashes of orphans in order of arrival.
+    order: VecDeque<Hash>,
+    /// The maximal number of orphans.
+    max_blocks: usize,
+    /// How long keep orphans.
+    timeout: Duration,
+}
+
+impl OrphanPool {
+    pub(crate) fn new(max_blocks: usize, timeout: Duration) -> Self {
+        OrphanPool {
+            blocks: HashMap::new(),
+            by_previous: HashMap::new(),
+            order: VecDeque::new(),
+            max_blocks,
+            timeout,
+        }
+    }
+
+    /// The number of orphans in the pool.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Adds a block whose parent is not known yet.
+    /// Returns false if the block is already in the pool.
+    pub(crate) fn insert(&mut self, block_hash: Hash, block: Block, now: Instant) -> bool {
+        if self.max_blocks == 0 || self.blocks.contains_key(&block_hash) {
+            return false;
+        }
+        while self.blocks.len() >= self.max_blocks {
+            let oldest = self.order.front().cloned().expect("pool is not empty");
+            debug!("Evicted an orphan block: block={}", oldest);
+            self.remove(&oldest);
+        }
+        let previous = block.base_header().previous;
+        self.by_previous
+            .entry(previous)
+            .or_default()
+            .push(block_hash);
+        self.order.push_back(block_hash);
+        self.blocks.insert(block_hash, (block, now));
+        metrics::ORPHAN_BLOCKS.inc();
+        metrics::ORPHAN_POOL_SIZE.set(self.blocks.len() as i64);
+        true
+    }
+
+    /// Removes and returns orphans which refer to the given block.
+    pub(crate) fn take_children(&mut self, previous: &Hash) -> Vec<Block> {
+        let hashes = match self.by_previous.remove(previous) {
+            Some(hashes) => hashes,
+            None => return Vec::new(),
+        };
+        let mut children = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            if let Some((block, _)) = self.blocks.remove(&hash) {
+                self.order.retain(|h| *h != hash);
+                children.push(block);
+            }
+        }
+        metrics::ORPHAN_BLOCKS_PROCESSED.inc_by(children.len() as i64);
+        metrics::ORPHAN_POOL_SIZE.set(self.blocks.len() as i64);
+        children
+    }
+
+    /// Removes orphans older than the timeout.
+    pub(crate) fn prune(&mut self, now: Instant) {
+        while let Some(hash) = self.order.front().cloned() {
+            let arrived = self.blocks[&hash].1;
+            if arrived + self.timeout > now {
+                break;
+            }
+            debug!("Expired an orphan block: block={}", hash);
+            self.remove(&hash);
+            metrics::ORPHAN_BLOCKS_EXPIRED.inc();
+        }
+    }
+
+    fn remove(&mut self, hash: &Hash) {
+        self.order.retain(|h| h != hash);
+        let (block, _) = match self.blocks.remove(hash) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let previous = block.base_header().previous;
+        if let Some(hashes) = self.by_previous.get_mut(&previous) {
+            hashes.retain(|h| h != hash);
+            if hashes.is_empty() {
+                self.by_previous.remove(&previous);
+            }
+        }
+        metrics::ORPHAN_POOL_SIZE.set(self.blocks.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use stegos_blockchain::{BaseBlockHeader, MicroBlock};
+    use stegos_crypto::pbc;
+
+    fn block(previous: Hash, height: u64) -> (Hash, Block) {
+        let (skey, pkey) = pbc::make_random_keys();
+        let random = pbc::make_VRF(&skey, &previous);
+        let base = BaseBlockHeader::new(
+            1,
+            Hash::zero(),
+            previous,
+            height,
+            0,
+            SystemTime::now(),
+            random,
+        );
+        let block = Block::MicroBlock(MicroBlock::empty(base, None, pkey));
+        (Hash::digest(&block), block)
+    }
+
+    #[test]
+    fn orphans() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let mut pool = OrphanPool::new(2, timeout);
+        let parent = Hash::digest("parent");
+        let (hash1, block1) = block(parent, 10);
+        let (hash2, block2) = block(hash1, 11);
+        let (hash3, block3) = block(hash2, 12);
+
+        assert!(pool.insert(hash1, block1.clone(), now));
+        assert!(!pool.insert(hash1, block1, now));
+        assert!(pool.insert(hash2, block2, now));
+        assert_eq!(pool.len(), 2);
+
+        // Children are returned once the parent is known.
+        assert!(pool.take_children(&hash2).is_empty());
+        let children = pool.take_children(&parent);
+        assert_eq!(children.len(), 1);
+        assert_eq!(Hash::digest(&children[0]), hash1);
+        assert_eq!(pool.len(), 1);
+
+        // The oldest block is evicted when the pool is full.
+        let (hash4, block4) = block(parent, 10);
+        assert!(pool.insert(hash3, block3, now + Duration::from_secs(1)));
+        assert!(pool.insert(hash4, block4, now + Duration::from_secs(2)));
+        assert_eq!(pool.len(), 2);
+        assert!(pool.take_children(&hash1).is_empty());
+
+        // Old blocks are expired.
+        pool.prune(now + timeout + Duration::from_secs(1));
+        assert_eq!(pool.len(), 1);
+        assert!(pool.take_children(&hash2).is_empty());
+        assert_eq!(pool.take_children(&parent).len(), 1);
+        assert_eq!(pool.len(), 0);
+    }
+}
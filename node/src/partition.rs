@@ -0,0 +1,169 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Detection of network partitions.
+//!
+//! A validator which can't see a supermajority of stake must not create blocks,
+//! otherwise it would build a minority fork. The validator is considered partitioned
+//! when consensus has been stuck for several rounds and fresh chain statuses
+//! have been received from validators holding less than 2/3 of slots.
+//! In the safe mode the node keeps serving requests and queuing transactions,
+//! but refuses to lead.
+//!
+
+use log::*;
+use stegos_blockchain::check_supermajority;
+use stegos_crypto::pbc;
+
+/// Tracks reachability of validators and switches the node to the safe mode.
+pub(crate) struct PartitionDetector {
+    /// The number of rounds without blocks before checking reachability, zero disables.
+    max_missed_rounds: u32,
+    /// Consecutive view change timeouts since the last block.
+    missed_rounds: u32,
+    /// Slots of validators seen recently.
+    reachable_slots: i64,
+    /// Slots of all validators.
+    total_slots: i64,
+    safe_mode: bool,
+}
+
+impl PartitionDetector {
+    pub fn new(max_missed_rounds: u32) -> Self {
+        PartitionDetector {
+            max_missed_rounds,
+            missed_rounds: 0,
+            reachable_slots: 0,
+            total_slots: 0,
+            safe_mode: false,
+        }
+    }
+
+    /// Returns true if the node must not create blocks.
+    pub fn safe_mode(&self) -> bool {
+        self.safe_mode
+    }
+
+    pub fn missed_rounds(&self) -> u32 {
+        self.missed_rounds
+    }
+
+    /// Returns the share of slots held by reachable validators, in percents.
+    pub fn reachable_percent(&self) -> i64 {
+        if self.total_slots == 0 {
+            return 100;
+        }
+        self.reachable_slots * 100 / self.total_slots
+    }
+
+    /// Called when a block has been applied.
+    pub fn on_block(&mut self) {
+        self.missed_rounds = 0;
+    }
+
+    /// Called when a round has timed out without a block.
+    pub fn on_round_timeout(&mut self) {
+        self.missed_rounds += 1;
+    }
+
+    ///
+    /// Recalculates reachable stake using the list of validators which
+    /// have recently sent their chain status, including this node itself.
+    ///
+    /// Returns Some(safe_mode) if the mode has changed.
+    ///
+    pub fn update(
+        &mut self,
+        validators: &[(pbc::PublicKey, i64)],
+        reachable: &[pbc::PublicKey],
+    ) -> Option<bool> {
+        self.total_slots = validators.iter().map(|(_, slots)| *slots).sum();
+        self.reachable_slots = validators
+            .iter()
+            .filter(|(pkey, _)| reachable.contains(pkey))
+            .map(|(_, slots)| *slots)
+            .sum();
+
+        let partitioned = self.max_missed_rounds > 0
+            && self.missed_rounds >= self.max_missed_rounds
+            && self.total_slots > 0
+            && !check_supermajority(self.reachable_slots, self.total_slots);
+        if partitioned == self.safe_mode {
+            return None;
+        }
+        self.safe_mode = partitioned;
+        if partitioned {
+            error!(
+                "Network partition detected, entering safe mode: missed_rounds={}, reachable_slots={}, total_slots={}",
+                self.missed_rounds, self.reachable_slots, self.total_slots
+            );
+        } else {
+            info!(
+                "Network partition healed, leaving safe mode: missed_rounds={}, reachable_slots={}, total_slots={}",
+                self.missed_rounds, self.reachable_slots, self.total_slots
+            );
+        }
+        Some(partitioned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_mode() {
+        let (_skey1, pkey1) = pbc::make_random_keys();
+        let (_skey2, pkey2) = pbc::make_random_keys();
+        let (_skey3, pkey3) = pbc::make_random_keys();
+        let validators = vec![(pkey1, 1), (pkey2, 1), (pkey3, 1)];
+        let mut detector = PartitionDetector::new(2);
+
+        // Consensus is not stuck.
+        assert_eq!(detector.update(&validators, &[pkey1]), None);
+        assert_eq!(detector.reachable_percent(), 33);
+        detector.on_round_timeout();
+        assert_eq!(detector.update(&validators, &[pkey1]), None);
+        assert!(!detector.safe_mode());
+
+        // Stuck and cut off from the supermajority.
+        detector.on_round_timeout();
+        assert_eq!(detector.missed_rounds(), 2);
+        assert_eq!(detector.update(&validators, &[pkey1]), Some(true));
+        assert!(detector.safe_mode());
+        assert_eq!(detector.update(&validators, &[pkey1]), None);
+
+        // The supermajority is reachable again.
+        assert_eq!(detector.update(&validators, &[pkey1, pkey2]), Some(false));
+        assert!(!detector.safe_mode());
+
+        // A block has been received.
+        assert_eq!(detector.update(&validators, &[pkey1]), Some(true));
+        detector.on_block();
+        assert_eq!(detector.update(&validators, &[pkey1]), Some(false));
+
+        // Disabled.
+        let mut detector = PartitionDetector::new(0);
+        detector.on_round_timeout();
+        assert_eq!(detector.update(&validators, &[pkey1]), None);
+    }
+}
@@ -25,11 +25,10 @@ use crate::config::ChainConfig;
 use crate::error::*;
 use failure::Error;
 use log::*;
-use std::time::SystemTime;
 use stegos_blockchain::{
-    mix, BaseBlockHeader, BlockError, Blockchain, CoinbaseTransaction, MacroBlock, Output,
-    PaymentOutput, PaymentPayloadData, PublicPaymentOutput, ServiceAwardTransaction, Transaction,
-    VERSION,
+    BaseBlockHeader, BlockBuilderError, BlockError, Blockchain, CoinbaseTransaction, MacroBlock,
+    Output, PaymentOutput, PaymentPayloadData, PublicPaymentOutput, ServiceAwardTransaction,
+    Transaction, VERSION,
 };
 use stegos_consensus::MacroBlockProposal;
 use stegos_crypto::curve1174;
@@ -41,17 +40,23 @@ pub fn create_macro_block_proposal(
     view_change: u32,
     mut block_reward: i64,
     recipient_pkey: &curve1174::PublicKey,
-    network_skey: &pbc::SecretKey,
     network_pkey: &pbc::PublicKey,
-) -> (MacroBlock, MacroBlockProposal) {
-    let timestamp = SystemTime::now();
-    let seed = mix(chain.last_random(), view_change);
-    let random = pbc::make_VRF(&network_skey, &seed);
+    random: pbc::VRF,
+) -> Result<(MacroBlock, MacroBlockProposal), BlockBuilderError> {
+    let timestamp = chain.block_timestamp();
 
     let previous = chain.last_block_hash();
     let height = chain.height();
     let epoch = chain.epoch() + 1;
-    let base = BaseBlockHeader::new(VERSION, previous, height, view_change, timestamp, random);
+    let base = BaseBlockHeader::new(
+        VERSION,
+        chain.chain_id(),
+        previous,
+        height,
+        view_change,
+        timestamp,
+        random,
+    );
     debug!(
         "Creating a new macro block proposal: height={}, view_change={}, epoch={}",
         height,
@@ -64,9 +69,18 @@ pub fn create_macro_block_proposal(
     // Coinbase.
     let coinbase_tx = {
         let data = PaymentPayloadData::Comment("Block reward".to_string());
-        let (output, gamma) =
-            PaymentOutput::with_payload(&recipient_pkey, block_reward, data.clone())
-                .expect("invalid keys");
+        let (output, gamma): (Output, curve1174::Fr) = match chain.reward_key(network_pkey) {
+            // Rewards redirected by stakes are public to be checked by validators.
+            Some(reward_pkey) => (
+                PublicPaymentOutput::new(&reward_pkey, block_reward).into(),
+                curve1174::Fr::zero(),
+            ),
+            None => {
+                let (output, gamma) =
+                    PaymentOutput::with_payload(&recipient_pkey, block_reward, data.clone())?;
+                (output.into(), gamma)
+            }
+        };
 
         info!(
             "Created reward UTXO: hash={}, amount={}, data={:?}",
@@ -79,7 +93,7 @@ pub fn create_macro_block_proposal(
             block_reward,
             block_fee: 0,
             gamma: -gamma,
-            txouts: vec![output.into()],
+            txouts: vec![output],
         }
     };
 
@@ -87,7 +101,7 @@ pub fn create_macro_block_proposal(
 
     // Add tx if winner found.
     if let Some((k, reward)) = winner {
-        let output = PublicPaymentOutput::new(&k, reward);
+        let output = PublicPaymentOutput::new(&chain.award_recipient(&k), reward);
         let tx = ServiceAwardTransaction {
             winner_reward: vec![output.into()],
         };
@@ -101,8 +115,7 @@ pub fn create_macro_block_proposal(
         block_reward,
         activity_map,
         network_pkey.clone(),
-    )
-    .expect("Invalid block");
+    )?;
     let block_hash = Hash::digest(&block);
 
     // Create block proposal.
@@ -116,7 +129,7 @@ pub fn create_macro_block_proposal(
         height, view_change, epoch, block_hash
     );
 
-    (block, block_proposal)
+    Ok((block, block_proposal))
 }
 
 ///
@@ -147,7 +160,7 @@ pub fn validate_proposed_macro_block(
     //
     let block_timestamp = block_proposal.header.base.timestamp;
     let last_block_timestamp = chain.last_macro_block_timestamp();
-    let current_timestamp = SystemTime::now();
+    let current_timestamp = chain.clock().now();
     if block_timestamp <= last_block_timestamp {
         return Err(NodeBlockError::OutdatedBlock(
             height,
@@ -183,33 +196,37 @@ pub fn validate_proposed_macro_block(
     //
 
     let mut tx_len = 1;
+    let leader = chain.select_leader(block_proposal.header.base.view_change);
     // Coinbase.
-    if let Some(Transaction::CoinbaseTransaction(tx)) = block_proposal.transactions.get(0) {
-        tx.validate()?;
-        if tx.block_reward != cfg.block_reward {
-            return Err(BlockError::InvalidBlockReward(
-                height,
-                block_hash.clone(),
-                tx.block_reward,
-                cfg.block_reward,
-            )
-            .into());
-        }
+    let mut block_reward =
+        if let Some(Transaction::CoinbaseTransaction(tx)) = block_proposal.transactions.get(0) {
+            tx.validate()?;
+            chain.validate_reward_outputs(height, block_hash, &leader, tx.txouts.iter())?;
+            let max_block_reward = chain.block_reward(height);
+            if tx.block_reward > max_block_reward {
+                return Err(BlockError::InvalidBlockReward(
+                    height,
+                    block_hash.clone(),
+                    tx.block_reward,
+                    max_block_reward,
+                )
+                .into());
+            }
 
-        if tx.block_fee != 0 {
-            return Err(NodeBlockError::InvalidBlockFee(
-                height,
-                block_hash.clone(),
-                tx.block_fee,
-                0,
-            )
-            .into());
-        }
-    } else {
-        // Force coinbase if reward is not zero.
-        return Err(BlockError::CoinbaseMustBeFirst(block_hash.clone()).into());
-    }
-    let mut block_reward = cfg.block_reward;
+            if tx.block_fee != 0 {
+                return Err(NodeBlockError::InvalidBlockFee(
+                    height,
+                    block_hash.clone(),
+                    tx.block_fee,
+                    0,
+                )
+                .into());
+            }
+            tx.block_reward
+        } else {
+            // Force coinbase if reward is not zero.
+            return Err(BlockError::CoinbaseMustBeFirst(block_hash.clone()).into());
+        };
 
     // Add tx if winner found.
     if let Some((k, reward)) = winner {
@@ -226,11 +243,12 @@ pub fn validate_proposed_macro_block(
             let ref output = tx.winner_reward[0];
 
             if let Output::PublicPaymentOutput(out) = output {
-                if out.recipient != k {
+                let recipient = chain.award_recipient(&k);
+                if out.recipient != recipient {
                     return Err(BlockError::AwardDifferentWinner(
                         block_hash.clone(),
                         out.recipient,
-                        k,
+                        recipient,
                     )
                     .into());
                 }
@@ -256,7 +274,6 @@ pub fn validate_proposed_macro_block(
     }
 
     // Re-create original block.
-    let leader = chain.select_leader(block_proposal.header.base.view_change);
     let block = MacroBlock::from_transactions(
         block_proposal.header.base.clone(),
         &block_proposal.transactions,
@@ -24,22 +24,32 @@
 use stegos_serialization::traits::*;
 // link protobuf dependencies
 use stegos_blockchain::protos::*;
+use stegos_crypto::protos::*;
 include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
 
 use crate::loader::{ChainLoaderMessage, RequestBlocks, ResponseBlocks};
+use crate::status::ChainStatus;
 use failure::{format_err, Error};
 use protobuf::RepeatedField;
+use std::time::{Duration, UNIX_EPOCH};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
 
 impl ProtoConvert for RequestBlocks {
     type Proto = loader::RequestBlocks;
     fn into_proto(&self) -> Self::Proto {
         let mut proto = loader::RequestBlocks::new();
         proto.set_starting_height(self.starting_height);
+        proto.set_count(self.count);
         proto
     }
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
         let starting_height = proto.get_starting_height();
-        Ok(RequestBlocks { starting_height })
+        let count = proto.get_count();
+        Ok(RequestBlocks {
+            starting_height,
+            count,
+        })
     }
 }
 
@@ -48,19 +58,25 @@ impl ProtoConvert for ResponseBlocks {
     fn into_proto(&self) -> Self::Proto {
         let mut proto = loader::ResponseBlocks::new();
         proto.set_height(self.height);
+        proto.set_starting_height(self.starting_height);
         let blocks: Vec<_> = self.blocks.iter().map(ProtoConvert::into_proto).collect();
         proto.set_blocks(RepeatedField::from_vec(blocks));
         proto
     }
     fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
         let height = proto.get_height();
+        let starting_height = proto.get_starting_height();
         let blocks: Result<Vec<_>, _> = proto
             .get_blocks()
             .iter()
             .map(ProtoConvert::from_proto)
             .collect();
         let blocks = blocks?;
-        Ok(ResponseBlocks { height, blocks })
+        Ok(ResponseBlocks {
+            height,
+            starting_height,
+            blocks,
+        })
     }
 }
 
@@ -91,10 +107,48 @@ impl ProtoConvert for ChainLoaderMessage {
     }
 }
 
+impl ProtoConvert for ChainStatus {
+    type Proto = status::ChainStatus;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = status::ChainStatus::new();
+        proto.set_pkey(self.pkey.into_proto());
+        proto.set_epoch(self.epoch);
+        proto.set_height(self.height);
+        proto.set_last_block_hash(self.last_block_hash.into_proto());
+        proto.set_view_change(self.view_change);
+        let since_the_epoch = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .expect("time is valid");
+        let timestamp = since_the_epoch.as_secs() * 1000 + since_the_epoch.subsec_millis() as u64;
+        proto.set_timestamp(timestamp);
+        proto.set_sig(self.sig.into_proto());
+        proto
+    }
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let pkey = pbc::PublicKey::from_proto(proto.get_pkey())?;
+        let epoch = proto.get_epoch();
+        let height = proto.get_height();
+        let last_block_hash = Hash::from_proto(proto.get_last_block_hash())?;
+        let view_change = proto.get_view_change();
+        let timestamp = UNIX_EPOCH + Duration::from_millis(proto.get_timestamp());
+        let sig = pbc::Signature::from_proto(proto.get_sig())?;
+        Ok(ChainStatus {
+            pkey,
+            epoch,
+            height,
+            last_block_hash,
+            view_change,
+            timestamp,
+            sig,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use stegos_crypto::hash::{Hash, Hashable};
+    use stegos_crypto::hash::Hashable;
 
     fn roundtrip<T>(x: &T) -> T
     where
@@ -107,7 +161,19 @@ mod tests {
 
     #[test]
     fn chain_loader() {
-        let request = ChainLoaderMessage::Request(RequestBlocks::new(1));
+        let request = ChainLoaderMessage::Request(RequestBlocks::new(1, 10));
         roundtrip(&request);
+
+        let response = ChainLoaderMessage::Response(ResponseBlocks::new(10, 1, Vec::new()));
+        roundtrip(&response);
+    }
+
+    #[test]
+    fn chain_status() {
+        let (skey, pkey) = pbc::make_random_keys();
+        let hash = Hash::digest("block");
+        let status = ChainStatus::new(&skey, &pkey, 1, 10, hash, 2, std::time::SystemTime::now());
+        let status2 = roundtrip(&status);
+        status2.validate().expect("signature is valid");
     }
 }
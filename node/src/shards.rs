@@ -0,0 +1,172 @@
+//! Sharding of transaction relay topics.
+
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use failure::Error;
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::{Async, Poll, Stream};
+use log::*;
+use std::collections::{BTreeMap, BTreeSet};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+use stegos_network::Network;
+
+/// Topic used for sending transactions.
+pub(crate) const TX_TOPIC: &'static str = "tx";
+
+/// Returns the topic of the transaction shard.
+pub(crate) fn tx_topic(shard: u32, shards: u32) -> String {
+    if shards <= 1 {
+        // Keep compatibility with non-sharded relay.
+        TX_TOPIC.to_string()
+    } else {
+        format!("{}.{}", TX_TOPIC, shard)
+    }
+}
+
+/// Returns the shard of the transaction, based on the prefix of its hash.
+pub(crate) fn tx_shard(tx_hash: &Hash, shards: u32) -> u32 {
+    let bytes = tx_hash.base_vector();
+    let prefix = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    prefix % shards.max(1)
+}
+
+/// Returns the set of shards to follow.
+///
+/// Validators follow all shards in order to collect all transactions for blocks.
+/// Other nodes relay a subset of shards, which is chosen deterministically by the network key.
+pub(crate) fn shards_to_follow(
+    network_pkey: &pbc::PublicKey,
+    is_validator: bool,
+    shards: u32,
+    relay_shards: u32,
+) -> BTreeSet<u32> {
+    let shards = shards.max(1);
+    if is_validator || relay_shards >= shards {
+        return (0..shards).collect();
+    }
+    let first = tx_shard(&Hash::digest(network_pkey), shards);
+    (0..relay_shards.max(1))
+        .map(|i| (first + i) % shards)
+        .collect()
+}
+
+/// Subscriptions to the transaction shards.
+pub(crate) struct TxShards {
+    /// The total number of shards.
+    shards: u32,
+    /// The number of shards to follow for non-validators.
+    relay_shards: u32,
+    /// Active subscriptions.
+    subscriptions: BTreeMap<u32, UnboundedReceiver<Vec<u8>>>,
+}
+
+impl TxShards {
+    pub(crate) fn new(shards: u32, relay_shards: u32) -> Self {
+        TxShards {
+            shards: shards.max(1),
+            relay_shards,
+            subscriptions: BTreeMap::new(),
+        }
+    }
+
+    /// Update subscriptions according to the role of the node.
+    pub(crate) fn update(
+        &mut self,
+        network: &Network,
+        network_pkey: &pbc::PublicKey,
+        is_validator: bool,
+    ) -> Result<(), Error> {
+        let wanted = shards_to_follow(network_pkey, is_validator, self.shards, self.relay_shards);
+
+        let unwanted: Vec<u32> = self
+            .subscriptions
+            .keys()
+            .filter(|shard| !wanted.contains(shard))
+            .cloned()
+            .collect();
+        for shard in unwanted {
+            let topic = tx_topic(shard, self.shards);
+            self.subscriptions.remove(&shard);
+            network.unsubscribe(&topic)?;
+            debug!("Unsubscribed from transaction shard: topic={}", topic);
+        }
+
+        for shard in wanted {
+            if self.subscriptions.contains_key(&shard) {
+                continue;
+            }
+            let topic = tx_topic(shard, self.shards);
+            let rx = network.subscribe(&topic)?;
+            self.subscriptions.insert(shard, rx);
+            debug!("Subscribed to transaction shard: topic={}", topic);
+        }
+
+        Ok(())
+    }
+}
+
+/// Merged stream of transactions from all subscribed shards.
+impl Stream for TxShards {
+    type Item = Vec<u8>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        for rx in self.subscriptions.values_mut() {
+            match rx.poll()? {
+                Async::Ready(Some(msg)) => return Ok(Async::Ready(Some(msg))),
+                Async::Ready(None) | Async::NotReady => {}
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sharding() {
+        let (_skey, pkey) = pbc::make_random_keys();
+
+        // Non-sharded relay.
+        assert_eq!(tx_topic(0, 1), TX_TOPIC);
+        assert_eq!(tx_shard(&Hash::digest("test"), 1), 0);
+        let follow = shards_to_follow(&pkey, false, 1, 1);
+        assert_eq!(follow.into_iter().collect::<Vec<u32>>(), vec![0]);
+
+        // Validators follow all shards.
+        let follow = shards_to_follow(&pkey, true, 8, 2);
+        assert_eq!(follow.len(), 8);
+
+        // Relays follow a subset of shards.
+        let follow = shards_to_follow(&pkey, false, 8, 2);
+        assert_eq!(follow.len(), 2);
+        assert_eq!(follow, shards_to_follow(&pkey, false, 8, 2));
+        for i in 0..100u64 {
+            let shard = tx_shard(&Hash::digest(&i), 8);
+            assert!(shard < 8);
+            assert_eq!(tx_topic(shard, 8), format!("tx.{}", shard));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! State persisted by a graceful shutdown.
+//!
+//! The view change counter is not a part of the blockchain, so a restarted
+//! validator would return to the first view of the current round. The state is
+//! saved on shutdown and restored on start if the chain hasn't moved since.
+//!
+
+use failure::{format_err, Error};
+use std::fs;
+use std::path::Path;
+use stegos_blockchain::view_changes::ViewChangeProof;
+use stegos_blockchain::{Blockchain, ChainInfo};
+use stegos_crypto::hash::Hash;
+use stegos_serialization::traits::ProtoConvert;
+
+/// Consensus state of a stopped node.
+#[derive(Debug, Clone)]
+pub(crate) struct ConsensusState {
+    /// The height of the chain.
+    pub height: u64,
+    /// The hash of the last block.
+    pub last_block_hash: Hash,
+    /// The current view change.
+    pub view_change: u32,
+    /// The proof of the current view change.
+    pub proof: Option<ViewChangeProof>,
+}
+
+impl ConsensusState {
+    /// Captures the consensus state of the chain.
+    pub fn new(chain: &Blockchain) -> Self {
+        ConsensusState {
+            height: chain.height(),
+            last_block_hash: chain.last_block_hash(),
+            view_change: chain.view_change(),
+            proof: chain.view_change_proof().clone(),
+        }
+    }
+
+    /// Returns the view change proof if the state can be applied to the chain.
+    pub fn actual_proof(&self, chain: &Blockchain) -> Option<ViewChangeProof> {
+        if self.height != chain.height()
+            || self.last_block_hash != chain.last_block_hash()
+            || self.view_change <= chain.view_change()
+        {
+            return None;
+        }
+        let proof = self.proof.as_ref()?;
+        let chain_info = ChainInfo {
+            chain_id: chain.chain_id(),
+            epoch: chain.epoch(),
+            height: self.height,
+            view_change: self.view_change - 1,
+            last_block: self.last_block_hash,
+        };
+        proof.validate(&chain_info, chain).ok()?;
+        Some(proof.clone())
+    }
+
+    /// Writes the state to the file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let proof = match &self.proof {
+            Some(proof) => hex::encode(proof.into_buffer()?),
+            None => String::new(),
+        };
+        let contents = format!(
+            "{} {} {} {}\n",
+            self.height,
+            self.last_block_hash.to_hex(),
+            self.view_change,
+            proof
+        );
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Reads the state from the file, returns None if the file doesn't exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, Error> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        let mut fields = contents.split_whitespace();
+        let mut next = || {
+            fields
+                .next()
+                .ok_or(format_err!("truncated consensus state"))
+        };
+        let height = next()?.parse()?;
+        let last_block_hash = Hash::try_from_hex(next()?)?;
+        let view_change = next()?.parse()?;
+        let proof = match next() {
+            Ok(proof) => Some(ViewChangeProof::from_buffer(&hex::decode(proof)?)?),
+            Err(_) => None,
+        };
+        Ok(Some(ConsensusState {
+            height,
+            last_block_hash,
+            view_change,
+            proof,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::pbc;
+    use tempdir::TempDir;
+
+    #[test]
+    fn save_load() {
+        let dir = TempDir::new("consensus_state").unwrap();
+        let path = dir.path().join("consensus.state");
+        assert!(ConsensusState::load(&path).unwrap().is_none());
+
+        let (skey, _pkey) = pbc::make_random_keys();
+        let signature = pbc::sign_hash(&Hash::digest("test"), &skey);
+        let proof = ViewChangeProof::new([(0u32, &signature)].iter().cloned());
+        let state = ConsensusState {
+            height: 10,
+            last_block_hash: Hash::digest("block"),
+            view_change: 2,
+            proof: Some(proof.clone()),
+        };
+        state.save(&path).unwrap();
+        let loaded = ConsensusState::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.height, 10);
+        assert_eq!(loaded.last_block_hash, Hash::digest("block"));
+        assert_eq!(loaded.view_change, 2);
+        assert_eq!(loaded.proof, Some(proof));
+
+        let state = ConsensusState {
+            proof: None,
+            ..state
+        };
+        state.save(&path).unwrap();
+        let loaded = ConsensusState::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.proof, None);
+    }
+}
@@ -0,0 +1,71 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Network keys of nodes with an active stake, shared with the network layer.
+//!
+
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use stegos_crypto::pbc;
+use stegos_network::StakeChecker;
+
+#[derive(Default)]
+struct Stakes {
+    /// Active stakes.
+    staked: HashMap<pbc::PublicKey, i64>,
+    /// Validators of the current epoch.
+    validators: HashSet<pbc::PublicKey>,
+}
+
+/// Stakes of nodes updated by the node on every epoch.
+#[derive(Clone, Default)]
+pub(crate) struct StakedNodes(Arc<RwLock<Stakes>>);
+
+impl StakedNodes {
+    pub fn update(
+        &self,
+        staked: HashMap<pbc::PublicKey, i64>,
+        validators: HashSet<pbc::PublicKey>,
+    ) {
+        debug!(
+            "Updated staked nodes: count={}, validators={}",
+            staked.len(),
+            validators.len()
+        );
+        *self.0.write().unwrap() = Stakes { staked, validators };
+    }
+}
+
+impl StakeChecker for StakedNodes {
+    fn is_staked(&self, id: &pbc::PublicKey) -> bool {
+        self.0.read().unwrap().staked.contains_key(id)
+    }
+
+    fn stake(&self, id: &pbc::PublicKey) -> i64 {
+        self.0.read().unwrap().staked.get(id).cloned().unwrap_or(0)
+    }
+
+    fn is_validator(&self, id: &pbc::PublicKey) -> bool {
+        self.0.read().unwrap().validators.contains(id)
+    }
+}
@@ -0,0 +1,207 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Exchange of chain heads between validators.
+//!
+//! Validators periodically publish a signed status of their chain, which is
+//! used to detect that the node is behind the network.
+//!
+
+use failure::{format_err, Error};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc;
+
+/// Topic used for sending statuses of the chain.
+pub const CHAIN_STATUS_TOPIC: &'static str = "chain_status";
+
+/// A signed status of the chain of a validator.
+#[derive(Debug, Clone)]
+pub struct ChainStatus {
+    pub pkey: pbc::PublicKey,
+    pub epoch: u64,
+    pub height: u64,
+    pub last_block_hash: Hash,
+    pub view_change: u32,
+    pub timestamp: SystemTime,
+    pub sig: pbc::Signature,
+}
+
+impl Hashable for ChainStatus {
+    fn hash(&self, state: &mut Hasher) {
+        "ChainStatus".hash(state);
+        self.pkey.hash(state);
+        self.epoch.hash(state);
+        self.height.hash(state);
+        self.last_block_hash.hash(state);
+        self.view_change.hash(state);
+        self.timestamp.hash(state);
+    }
+}
+
+impl ChainStatus {
+    /// Creates a new signed status.
+    pub fn new(
+        skey: &pbc::SecretKey,
+        pkey: &pbc::PublicKey,
+        epoch: u64,
+        height: u64,
+        last_block_hash: Hash,
+        view_change: u32,
+        timestamp: SystemTime,
+    ) -> Self {
+        let mut status = ChainStatus {
+            pkey: *pkey,
+            epoch,
+            height,
+            last_block_hash,
+            view_change,
+            timestamp,
+            sig: pbc::Signature::zero(),
+        };
+        let hash = Hash::digest(&status);
+        status.sig = pbc::sign_hash(&hash, skey);
+        status
+    }
+
+    /// Checks the signature.
+    pub fn validate(&self) -> Result<(), Error> {
+        let hash = Hash::digest(self);
+        pbc::check_hash(&hash, &self.sig, &self.pkey)
+            .map_err(|_| format_err!("Invalid signature of chain status: pkey={}", self.pkey))
+    }
+}
+
+/// The latest known status of a peer.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStatus {
+    pub pkey: pbc::PublicKey,
+    pub epoch: u64,
+    pub height: u64,
+    pub last_block_hash: Hash,
+    pub view_change: u32,
+    /// Seconds since the status was received.
+    pub age: u64,
+}
+
+/// The latest statuses received from peers.
+pub(crate) struct StatusTracker {
+    /// Statuses older than this are ignored.
+    ttl: Duration,
+    statuses: HashMap<pbc::PublicKey, (ChainStatus, SystemTime)>,
+}
+
+impl StatusTracker {
+    pub fn new(ttl: Duration) -> Self {
+        StatusTracker {
+            ttl,
+            statuses: HashMap::new(),
+        }
+    }
+
+    /// Records a status, returns false if it is older than the known one.
+    pub fn update(&mut self, status: ChainStatus) -> bool {
+        if let Some((known, _)) = self.statuses.get(&status.pkey) {
+            if known.timestamp >= status.timestamp {
+                return false;
+            }
+        }
+        self.statuses
+            .insert(status.pkey, (status, SystemTime::now()));
+        true
+    }
+
+    /// Removes statuses of peers which are not in the list.
+    pub fn retain(&mut self, peers: &[pbc::PublicKey]) {
+        self.statuses.retain(|pkey, _| peers.contains(pkey));
+    }
+
+    /// Returns fresh statuses of peers.
+    pub fn peer_statuses(&self) -> Vec<PeerStatus> {
+        let now = SystemTime::now();
+        let mut statuses: Vec<PeerStatus> = self
+            .statuses
+            .values()
+            .filter_map(|(status, received)| {
+                let age = now.duration_since(*received).unwrap_or_default();
+                if age > self.ttl {
+                    return None;
+                }
+                Some(PeerStatus {
+                    pkey: status.pkey,
+                    epoch: status.epoch,
+                    height: status.height,
+                    last_block_hash: status.last_block_hash,
+                    view_change: status.view_change,
+                    age: age.as_secs(),
+                })
+            })
+            .collect();
+        statuses.sort_by(|a, b| b.height.cmp(&a.height));
+        statuses
+    }
+
+    /// Returns the best height reported by peers, if it is above ours.
+    pub fn behind(&self, our_height: u64) -> Option<(pbc::PublicKey, u64)> {
+        self.peer_statuses()
+            .into_iter()
+            .filter(|s| s.height > our_height)
+            .map(|s| (s.pkey, s.height))
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracker() {
+        let (skey1, pkey1) = pbc::make_random_keys();
+        let (skey2, pkey2) = pbc::make_random_keys();
+        let hash = Hash::digest("block");
+        let now = SystemTime::now();
+        let mut tracker = StatusTracker::new(Duration::from_secs(30));
+
+        let status1 = ChainStatus::new(&skey1, &pkey1, 1, 10, hash, 0, now);
+        status1.validate().expect("valid");
+        let mut invalid = status1.clone();
+        invalid.height = 100;
+        assert!(invalid.validate().is_err());
+
+        assert!(tracker.update(status1.clone()));
+        assert!(!tracker.update(status1.clone()));
+        assert_eq!(tracker.behind(10), None);
+        assert_eq!(tracker.behind(9), Some((pkey1, 10)));
+
+        let status2 = ChainStatus::new(&skey2, &pkey2, 1, 12, hash, 1, now);
+        assert!(tracker.update(status2));
+        assert_eq!(tracker.behind(9), Some((pkey2, 12)));
+        assert_eq!(tracker.peer_statuses().len(), 2);
+
+        tracker.retain(&[pkey1]);
+        assert_eq!(tracker.peer_statuses().len(), 1);
+        assert_eq!(tracker.behind(9), Some((pkey1, 10)));
+    }
+}
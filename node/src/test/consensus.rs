@@ -579,6 +579,7 @@ fn out_of_order_micro_block() {
 
         let base = BaseBlockHeader::new(
             version,
+            leader.node_service.chain.chain_id(),
             last_block_hash,
             height,
             round + 1,
@@ -743,6 +743,7 @@ fn out_of_order_keyblock_proposal() {
         let leader_pk = s.nodes[0].node_service.chain.leader();
 
         let proposal = {
+            let chain_id = s.nodes[0].node_service.chain.chain_id();
             let previous = s.nodes[0].node_service.chain.last_block_hash();
             let last_random = s.nodes[0].node_service.chain.last_random();
             let leader_node = s.node(&leader_pk).unwrap();
@@ -751,7 +752,9 @@ fn out_of_order_keyblock_proposal() {
             let timestamp = SystemTime::now();
             let seed = mix(last_random, round);
             let random = pbc::make_VRF(&leader_node.node_service.keys.network_skey, &seed);
-            let base = BaseBlockHeader::new(version, previous, height, round, timestamp, random);
+            let base = BaseBlockHeader::new(
+                version, chain_id, previous, height, round, timestamp, random,
+            );
             let leader = leader_node.node_service.keys.network_pkey;
             let block = MacroBlock::empty(base, leader);
             let block_hash = Hash::digest(&block);
@@ -825,6 +828,7 @@ fn micro_block_without_signature() {
         let random = pbc::make_VRF(&leader.node_service.keys.network_skey, &seed);
         let base = BaseBlockHeader::new(
             version,
+            leader.node_service.chain.chain_id(),
             last_block_hash,
             height,
             round + 1,
@@ -849,6 +853,77 @@ fn micro_block_without_signature() {
     });
 }
 
+// CASE block template:
+// Nodes [A, B, C]
+//
+// 1. Node A is the leader, an external tool requests a block template from A.
+// 2. The template is signed outside of the node and submitted back to A.
+//
+// Asserts that the unsigned template is rejected, the signed one is applied and broadcasted.
+#[test]
+fn block_template() {
+    let config = SandboxConfig {
+        num_nodes: 3,
+        ..Default::default()
+    };
+
+    Sandbox::start(config, |mut s| {
+        s.poll();
+
+        let height = s.nodes[0].node_service.chain.height();
+        let leader_pk = s.nodes[0].node_service.chain.leader();
+        let leader = s.node(&leader_pk).unwrap();
+
+        let (block_hash, mut block) = match leader.node_service.block_template() {
+            NodeResponse::BlockTemplate {
+                height: template_height,
+                leader,
+                block_hash,
+                block,
+                ..
+            } => {
+                assert_eq!(template_height, height);
+                assert_eq!(leader, leader_pk);
+                assert_eq!(block_hash, Hash::digest(&block));
+                (block_hash, block)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        match leader.node_service.submit_block(block.clone()) {
+            NodeResponse::Error { .. } => {}
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert_eq!(leader.node_service.chain.height(), height);
+
+        block
+            .sign(&leader.node_service.keys.network_skey, &leader_pk)
+            .unwrap();
+        match leader.node_service.submit_block(block) {
+            NodeResponse::BlockSubmitted {
+                height: submitted_height,
+                block_hash: submitted_hash,
+            } => {
+                assert_eq!(submitted_height, height);
+                assert_eq!(submitted_hash, block_hash);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert_eq!(leader.node_service.chain.height(), height + 1);
+        let block: Block = leader.network_service.get_broadcast(SEALED_BLOCK_TOPIC);
+
+        let mut r = s.split(&[leader_pk]);
+        for node in r.parts.1.iter_mut() {
+            node.network_service
+                .receive_broadcast(crate::SEALED_BLOCK_TOPIC, block.clone())
+        }
+        r.parts.1.poll();
+        r.parts
+            .1
+            .for_each(|node| assert_eq!(node.chain.height(), height + 1));
+    });
+}
+
 // CASE partition:
 // Nodes [A, B, C, D]
 //
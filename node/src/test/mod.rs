@@ -26,6 +26,9 @@ pub use stegos_network::loopback::Loopback;
 mod consensus;
 mod microblocks;
 mod requests;
+mod router;
+mod scenarios;
+use self::router::Router;
 use crate::*;
 use assert_matches::assert_matches;
 use log::Level;
@@ -57,6 +60,7 @@ pub struct Sandbox<'timer> {
     nodes_keychains: Vec<KeyChain>,
     timer: &'timer mut Timer<TestTimer>,
     config: ChainConfig,
+    router: Router,
 }
 
 impl<'timer> Sandbox<'timer> {
@@ -67,7 +71,12 @@ impl<'timer> Sandbox<'timer> {
         start_test(|timer| {
             let _ = simple_logger::init_with_level(Level::Trace);
             let num_nodes = cfg.num_nodes;
-            let cfg = cfg.chain;
+            let mut cfg = cfg.chain;
+            // Statuses are not checked by tests, don't flood the loopback queues.
+            cfg.chain_status_interval = Duration::from_secs(0);
+            cfg.consensus_state_file = String::new();
+            // Process messages synchronously to keep tests deterministic.
+            cfg.verifier_threads = 0;
             let timestamp = SystemTime::now();
             let nodes_keychains: Vec<_> = (0..num_nodes).map(|_num| KeyChain::new_mem()).collect();
             let genesis = stegos_blockchain::genesis(
@@ -85,6 +94,7 @@ impl<'timer> Sandbox<'timer> {
                 nodes_keychains,
                 timer,
                 config: cfg,
+                router: Default::default(),
             };
             for node in sandbox.nodes.iter() {
                 assert_eq!(node.node_service.chain.height(), 1);
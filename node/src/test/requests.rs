@@ -23,6 +23,8 @@
 
 use super::*;
 use crate::*;
+use futures::sync::oneshot;
+use futures::{Async, Future};
 
 #[test]
 fn request_on_timeout() {
@@ -64,3 +66,43 @@ fn request_on_timeout() {
             .filter_broadcast(&[crate::SEALED_BLOCK_TOPIC]);
     });
 }
+
+#[test]
+fn status() {
+    let config = SandboxConfig {
+        num_nodes: 3,
+        ..Default::default()
+    };
+
+    Sandbox::start(config, |mut s| {
+        s.poll();
+
+        let (tx, mut rx) = oneshot::channel();
+        s.nodes[0]
+            .node_service
+            .handle_status_request(tx)
+            .expect("request is handled");
+        s.poll();
+        let status = match rx.poll() {
+            Ok(Async::Ready(NodeResponse::Status(status))) => status,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        let node = &s.nodes[0].node_service;
+        assert_eq!(status.network_pkey, node.keys.network_pkey);
+        assert_eq!(status.epoch, node.chain.epoch());
+        assert_eq!(status.height, node.chain.height());
+        assert_eq!(status.last_block_hash, node.chain.last_block_hash());
+        assert_eq!(
+            status.last_macro_block_height,
+            node.chain.last_macro_block_height()
+        );
+        assert_eq!(status.mempool_transactions, 0);
+        let validator = status.validator.expect("all nodes are validators");
+        assert!(validator.slots > 0);
+        assert_eq!(validator.total_slots, node.chain.total_slots());
+        // The loopback network has no routing table.
+        assert_eq!(status.peers.known, 0);
+        assert_eq!(status.peers.connected, 0);
+    });
+}
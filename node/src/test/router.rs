@@ -0,0 +1,202 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! In-memory network between sandbox nodes.
+
+use super::*;
+use std::collections::{HashSet, VecDeque};
+use std::mem;
+use std::time::Instant;
+use stegos_network::loopback::MessageFromNode;
+use tokio_timer::clock;
+
+/// The maximal number of delivery rounds without advancing time.
+const MAX_ROUTE_ITERATIONS: usize = 1000;
+/// Time step of `Sandbox::run_until()`.
+const ROUTE_STEP_MS: u64 = 100;
+
+/// Delivers messages between nodes of a sandbox.
+#[derive(Default)]
+pub struct Router {
+    /// Delay of every message.
+    latency: Duration,
+    /// Nodes separated from the rest of the network.
+    partition: HashSet<pbc::PublicKey>,
+    /// Nodes which are neither polled nor receive messages.
+    crashed: HashSet<pbc::PublicKey>,
+    /// Messages in flight as (delivery time, sender, message).
+    in_flight: VecDeque<(Instant, pbc::PublicKey, MessageFromNode)>,
+}
+
+impl Router {
+    /// True if messages from `from` reach `to`.
+    fn is_reachable(&self, from: &pbc::PublicKey, to: &pbc::PublicKey) -> bool {
+        !self.crashed.contains(to) && self.partition.contains(from) == self.partition.contains(to)
+    }
+
+    /// Deliver a message, returns true if at least one node has received it.
+    fn deliver(
+        &self,
+        nodes: &mut [NodeSandbox],
+        from: pbc::PublicKey,
+        msg: MessageFromNode,
+    ) -> bool {
+        let mut delivered = false;
+        match msg {
+            MessageFromNode::Publish { topic, data } => {
+                for node in nodes.iter_mut() {
+                    let to = node.node_service.keys.network_pkey;
+                    if to == from
+                        || !self.is_reachable(&from, &to)
+                        || !node.network_service.is_subscribed(&topic)
+                    {
+                        continue;
+                    }
+                    node.network_service
+                        .receive_broadcast_raw(&topic, data.clone());
+                    delivered = true;
+                }
+            }
+            MessageFromNode::SendUnicast {
+                to,
+                protocol_id,
+                data,
+            } => {
+                if !self.is_reachable(&from, &to) {
+                    return false;
+                }
+                let node = nodes
+                    .iter_mut()
+                    .find(|node| node.node_service.keys.network_pkey == to);
+                if let Some(node) = node {
+                    if node.network_service.is_subscribed_unicast(&protocol_id) {
+                        node.network_service
+                            .receive_unicast_raw(from, &protocol_id, data);
+                        delivered = true;
+                    }
+                }
+            }
+        }
+        delivered
+    }
+}
+
+impl<'timer> Sandbox<'timer> {
+    /// Set the delay of every message.
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.router.latency = latency;
+    }
+
+    /// Drop all messages between the specified nodes and the rest of the network.
+    pub fn partition(&mut self, nodes: &[pbc::PublicKey]) {
+        self.router.partition = nodes.iter().cloned().collect();
+    }
+
+    /// Restore connectivity after `partition()`.
+    pub fn heal(&mut self) {
+        self.router.partition.clear();
+    }
+
+    /// Stop the node. Messages which haven't been sent yet are lost,
+    /// but messages already in flight are still delivered.
+    pub fn crash(&mut self, pkey: &pbc::PublicKey) {
+        self.router.crashed.insert(*pkey);
+        let node = self.node(pkey).expect("node exists");
+        while let Some(_msg) = node.network_service.pop_message() {}
+    }
+
+    /// Returns true if the node has not crashed.
+    pub fn is_alive(&self, pkey: &pbc::PublicKey) -> bool {
+        !self.router.crashed.contains(pkey)
+    }
+
+    /// Poll nodes and deliver due messages until the network calms down.
+    pub fn route(&mut self) {
+        for _ in 0..MAX_ROUTE_ITERATIONS {
+            for node in self.nodes.iter_mut() {
+                if !self
+                    .router
+                    .crashed
+                    .contains(&node.node_service.keys.network_pkey)
+                {
+                    node.poll();
+                }
+            }
+
+            // Collect messages sent by nodes.
+            let now = clock::now();
+            let mut sent = false;
+            for node in self.nodes.iter_mut() {
+                let from = node.node_service.keys.network_pkey;
+                while let Some(msg) = node.network_service.pop_message() {
+                    self.router
+                        .in_flight
+                        .push_back((now + self.router.latency, from, msg));
+                    sent = true;
+                }
+            }
+
+            // Deliver due messages.
+            let mut delivered = false;
+            let in_flight = mem::replace(&mut self.router.in_flight, VecDeque::new());
+            for (deadline, from, msg) in in_flight {
+                if deadline > now {
+                    self.router.in_flight.push_back((deadline, from, msg));
+                    continue;
+                }
+                delivered |= self.router.deliver(&mut self.nodes, from, msg);
+            }
+
+            if !sent && !delivered {
+                return;
+            }
+        }
+        panic!("The network didn't calm down");
+    }
+
+    /// Emulate the network for the specified duration.
+    #[allow(dead_code)]
+    pub fn run_for(&mut self, duration: Duration) {
+        self.run_until(duration, |_| false);
+    }
+
+    /// Emulate the network until the condition holds or the timeout expires.
+    /// Returns true if the condition holds.
+    pub fn run_until<F>(&mut self, timeout: Duration, mut condition: F) -> bool
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        let step = Duration::from_millis(ROUTE_STEP_MS);
+        let mut elapsed = Duration::from_secs(0);
+        loop {
+            self.route();
+            if condition(&*self) {
+                return true;
+            }
+            if elapsed >= timeout {
+                return false;
+            }
+            let step = std::cmp::min(step, timeout - elapsed);
+            self.wait(step);
+            elapsed += step;
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! End-to-end scenarios over the in-memory network.
+
+use super::*;
+use crate::*;
+use std::time::Duration;
+use stegos_crypto::pbc;
+
+/// Returns the leader of the macro block, if the node is validating a macro block.
+fn macro_block_leader(node: &NodeSandbox) -> Option<pbc::PublicKey> {
+    match &node.node_service.validation {
+        MacroBlockValidator { consensus, .. } => Some(consensus.leader()),
+        _ => None,
+    }
+}
+
+/// Checks that all alive nodes have the same chain.
+fn assert_alive_synchronized(s: &Sandbox) {
+    let mut alive = s
+        .nodes
+        .iter()
+        .filter(|node| s.is_alive(&node.node_service.keys.network_pkey));
+    let first = alive.next().expect("at least one alive node");
+    for node in alive {
+        assert_eq!(
+            node.node_service.chain.height(),
+            first.node_service.chain.height()
+        );
+        assert_eq!(
+            node.node_service.chain.last_block_hash(),
+            first.node_service.chain.last_block_hash()
+        );
+    }
+}
+
+#[test]
+fn micro_blocks() {
+    let config = SandboxConfig {
+        num_nodes: 4,
+        ..Default::default()
+    };
+
+    Sandbox::start(config, |mut s| {
+        s.set_latency(Duration::from_millis(50));
+        let height = s.first().node_service.chain.height();
+        let timeout = (s.cfg().tx_wait_timeout + s.cfg().micro_block_timeout) * 3;
+        assert!(s.run_until(timeout, |s| s.nodes.iter().all(|node| node
+            .node_service
+            .chain
+            .height()
+            >= height + 2)));
+        s.route();
+        assert_alive_synchronized(&s);
+    });
+}
+
+#[test]
+fn leader_crash_during_macro_block() {
+    let mut cfg: ChainConfig = Default::default();
+    cfg.blocks_in_epoch = 2;
+    let config = SandboxConfig {
+        chain: cfg,
+        num_nodes: 4,
+        ..Default::default()
+    };
+
+    Sandbox::start(config, |mut s| {
+        s.set_latency(Duration::from_millis(10));
+        let epoch = s.first().node_service.chain.epoch();
+
+        // Produce micro blocks until validators start to agree on the macro block.
+        let timeout = (s.cfg().tx_wait_timeout + s.cfg().micro_block_timeout)
+            * (s.cfg().blocks_in_epoch as u32);
+        assert!(s.run_until(timeout, |s| s
+            .nodes
+            .iter()
+            .all(|node| macro_block_leader(node).is_some())));
+
+        // The leader has sent the proposal and crashes.
+        let leader = macro_block_leader(s.first()).unwrap();
+        s.crash(&leader);
+        assert!(!s.is_alive(&leader));
+
+        // The rest of validators must agree on the macro block without the leader.
+        let timeout = s.cfg().macro_block_timeout * 4;
+        assert!(s.run_until(timeout, |s| s
+            .nodes
+            .iter()
+            .filter(|node| s.is_alive(&node.node_service.keys.network_pkey))
+            .all(|node| node.node_service.chain.epoch() > epoch)));
+        s.route();
+        assert_alive_synchronized(&s);
+    });
+}
+
+#[test]
+fn partition_and_heal() {
+    let config = SandboxConfig {
+        num_nodes: 4,
+        ..Default::default()
+    };
+
+    Sandbox::start(config, |mut s| {
+        s.route();
+        let height = s.first().node_service.chain.height();
+
+        // Separate a node which is not the next leader.
+        let leader = s.leader();
+        let isolated = s
+            .nodes
+            .iter()
+            .map(|node| node.node_service.keys.network_pkey)
+            .find(|pkey| pkey != &leader)
+            .unwrap();
+        s.partition(&[isolated]);
+
+        let timeout = (s.cfg().tx_wait_timeout + s.cfg().micro_block_timeout) * 2;
+        assert!(s.run_until(timeout, |s| {
+            s.nodes
+                .iter()
+                .filter(|node| node.node_service.keys.network_pkey != isolated)
+                .all(|node| node.node_service.chain.height() > height)
+        }));
+        assert_eq!(
+            s.node_ref(&isolated).unwrap().node_service.chain.height(),
+            height
+        );
+
+        // The isolated node catches up after the partition is healed.
+        s.heal();
+        let timeout = (s.cfg().tx_wait_timeout + s.cfg().micro_block_timeout) * 3;
+        assert!(s.run_until(timeout, |s| {
+            s.nodes
+                .iter()
+                .all(|node| node.node_service.chain.height() > height + 1)
+        }));
+        s.route();
+        assert_alive_synchronized(&s);
+    });
+}
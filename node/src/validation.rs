@@ -28,6 +28,21 @@ use std::time::SystemTime;
 use stegos_blockchain::{Blockchain, Output, Transaction, TransactionError};
 use stegos_crypto::hash::Hash;
 
+/// The size of the `Block` wrapper around a serialized micro or macro block:
+/// a field tag and a varint length.
+const BLOCK_BUFFER_OVERHEAD: usize = 1 + 10;
+
+///
+/// Check the size of a serialized block before decoding it.
+///
+pub(crate) fn validate_block_buffer(data: &[u8], max_block_size: usize) -> Result<(), Error> {
+    let max_size = max_block_size + BLOCK_BUFFER_OVERHEAD;
+    if data.len() > max_size {
+        return Err(NodeBlockError::TooBigBlockBuffer(data.len(), max_size).into());
+    }
+    Ok(())
+}
+
 ///
 /// Validate transaction.
 ///
@@ -35,7 +50,7 @@ pub(crate) fn validate_external_transaction(
     tx: &Transaction,
     mempool: &Mempool,
     chain: &Blockchain,
-    _timestamp: SystemTime,
+    timestamp: SystemTime,
     payment_fee: i64,
     stake_fee: i64,
 ) -> Result<(), Error> {
@@ -59,6 +74,10 @@ pub(crate) fn validate_external_transaction(
         return Err(NodeTransactionError::TooLowFee(tx_hash, min_fee, tx.fee()).into());
     }
 
+    // Check chain identifier and size limits.
+    chain.validate_transaction_chain(tx)?;
+    chain.validate_transaction_limits(tx)?;
+
     let mut inputs: Vec<Output> = Vec::new();
 
     // TODO: allow transaction with overlapping inputs/outputs in mempool.
@@ -79,9 +98,27 @@ pub(crate) fn validate_external_transaction(
             return Err(TransactionError::MissingInput(tx_hash, input_hash.clone()).into());
         }
 
+        // Check that the input can be spent in the next block.
+        // chain.height() is the height of the next block, `timestamp` is its timestamp.
+        if let Some(locked_until) = chain.input_lock(tx, input_hash, &input)? {
+            if !locked_until.is_unlocked(chain.height(), timestamp) {
+                return Err(TransactionError::LockedInput(
+                    tx_hash,
+                    input_hash.clone(),
+                    locked_until,
+                )
+                .into());
+            }
+        }
+
         inputs.push(input);
     }
 
+    // Check that the sender owns the inputs before any expensive checks.
+    if let Transaction::PaymentTransaction(tx) = tx {
+        tx.validate_ownership(&inputs)?;
+    }
+
     // Check for overlapping outputs in mempool.
     for output in tx.txouts() {
         let output_hash = Hash::digest(output);
@@ -95,6 +132,7 @@ pub(crate) fn validate_external_transaction(
     // Check the monetary balance, Bulletpoofs/amounts and signature.
     match tx {
         Transaction::RestakeTransaction(tx) => tx.validate(&inputs)?,
+        Transaction::RevokeDelegationTransaction(tx) => tx.validate(&inputs)?,
         Transaction::PaymentTransaction(tx) => tx.validate(&inputs)?,
         Transaction::SlashingTransaction(..)
         | Transaction::CoinbaseTransaction(..)
@@ -130,6 +168,7 @@ mod test {
         let genesis = genesis(&[keychain.clone()], stake, amount + stake, timestamp);
         let chain =
             Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        let chain_id = chain.chain_id();
         let mut inputs: Vec<Output> = Vec::new();
         let mut stakes: Vec<Output> = Vec::new();
         for output_hash in chain.unspent() {
@@ -159,7 +198,8 @@ mod test {
             let outputs: Vec<Output> = vec![output1, output2];
             let outputs_gamma = gamma1 + gamma2;
             let tx =
-                PaymentTransaction::new(&skey, &inputs, &outputs, &outputs_gamma, fee).unwrap();
+                PaymentTransaction::new(&chain_id, &skey, &inputs, &outputs, &outputs_gamma, fee)
+                    .unwrap();
             validate_external_transaction(
                 &tx.into(),
                 &mempool,
@@ -177,22 +217,76 @@ mod test {
         {
             let fee = payment_fee + 1;
             let (output, gamma) = Output::new_payment(&pkey, amount - fee).unwrap();
-            let tx = PaymentTransaction::new(&skey, &inputs, &[output], &gamma, fee)
+            let tx = PaymentTransaction::new(&chain_id, &skey, &inputs, &[output], &gamma, fee)
                 .unwrap()
                 .into();
             validate_external_transaction(&tx, &mempool, &chain, timestamp, payment_fee, stake_fee)
                 .expect("transaction is valid");
         }
 
+        //
+        // Replay of a transaction signed for another chain.
+        //
+        {
+            let fee = payment_fee;
+            let other_chain_id = Hash::digest("other");
+            let (output, gamma) = Output::new_payment(&pkey, amount - fee).unwrap();
+            let mut tx =
+                PaymentTransaction::new(&other_chain_id, &skey, &inputs, &[output], &gamma, fee)
+                    .unwrap();
+            let e = validate_external_transaction(
+                &tx.clone().into(),
+                &mempool,
+                &chain,
+                timestamp,
+                payment_fee,
+                stake_fee,
+            )
+            .expect_err("transaction is not valid");
+            match e.downcast::<BlockchainError>().expect("proper error") {
+                BlockchainError::TransactionError(TransactionError::InvalidChainId(
+                    tx_hash,
+                    got,
+                    expected,
+                )) => {
+                    assert_eq!(tx_hash, Hash::digest(&tx));
+                    assert_eq!(got, other_chain_id);
+                    assert_eq!(expected, chain_id);
+                }
+                _ => panic!(),
+            }
+
+            // The chain identifier is hashed and signed.
+            let other_tx_hash = Hash::digest(&tx);
+            tx.chain_id = chain_id;
+            assert_ne!(Hash::digest(&tx), other_tx_hash);
+            let e = validate_external_transaction(
+                &tx.clone().into(),
+                &mempool,
+                &chain,
+                timestamp,
+                payment_fee,
+                stake_fee,
+            )
+            .expect_err("transaction is not valid");
+            match e.downcast::<BlockchainError>().expect("proper error") {
+                BlockchainError::TransactionError(TransactionError::InvalidSignature(tx_hash)) => {
+                    assert_eq!(tx_hash, Hash::digest(&tx));
+                }
+                _ => panic!(),
+            }
+        }
+
         //
         // Fee < expected.
         //
         {
             let fee = payment_fee - 1;
             let (output, gamma) = Output::new_payment(&pkey, amount - fee).unwrap();
-            let tx = PaymentTransaction::unchecked(&skey, &inputs, &[output], &gamma, fee)
-                .unwrap()
-                .into();
+            let tx =
+                PaymentTransaction::unchecked(&chain_id, &skey, &inputs, &[output], &gamma, fee)
+                    .unwrap()
+                    .into();
             let e = validate_external_transaction(
                 &tx,
                 &mempool,
@@ -220,9 +314,10 @@ mod test {
             let (input, _inputs_gamma) = Output::new_payment(&pkey, amount).unwrap();
             let (output, outputs_gamma) = Output::new_payment(&pkey, amount - fee).unwrap();
             let missing = Hash::digest(&input);
-            let tx = PaymentTransaction::new(&skey, &[input], &[output], &outputs_gamma, fee)
-                .unwrap()
-                .into();
+            let tx =
+                PaymentTransaction::new(&chain_id, &skey, &[input], &[output], &outputs_gamma, fee)
+                    .unwrap()
+                    .into();
             let e = validate_external_transaction(
                 &tx,
                 &mempool,
@@ -240,6 +335,34 @@ mod test {
             }
         }
 
+        //
+        // Inputs are not owned by the sender.
+        //
+        {
+            let fee = payment_fee;
+            let (output, outputs_gamma) = Output::new_payment(&pkey, amount - fee).unwrap();
+            let mut tx =
+                PaymentTransaction::new(&chain_id, &skey, &inputs, &[output], &outputs_gamma, fee)
+                    .unwrap();
+            tx.sig.u = Fr::zero();
+            let tx: Transaction = tx.into();
+            let e = validate_external_transaction(
+                &tx,
+                &mempool,
+                &chain,
+                timestamp,
+                payment_fee,
+                stake_fee,
+            )
+            .expect_err("transaction is not valid");
+            match e.downcast::<BlockchainError>().expect("proper error") {
+                BlockchainError::TransactionError(TransactionError::InvalidSignature(tx_hash)) => {
+                    assert_eq!(tx_hash, Hash::digest(&tx));
+                }
+                _ => panic!(),
+            }
+        }
+
         //
         // TX hash is unique.
         // Claimed input in mempool.
@@ -251,7 +374,7 @@ mod test {
             let input_hashes: Vec<Hash> = inputs.iter().map(|o| Hash::digest(o)).collect();
             let output_hashes: Vec<Hash> = outputs.iter().map(|o| Hash::digest(o)).collect();
             let tx: Transaction =
-                PaymentTransaction::new(&skey, &inputs, &outputs, &outputs_gamma, fee)
+                PaymentTransaction::new(&chain_id, &skey, &inputs, &outputs, &outputs_gamma, fee)
                     .unwrap()
                     .into();
             mempool.push_tx(Hash::digest(&tx), tx.clone());
@@ -276,7 +399,7 @@ mod test {
             // Claimed input in mempool.
             let tx2 = {
                 let (output2, outputs2_gamma) = Output::new_payment(&pkey, amount - fee).unwrap();
-                PaymentTransaction::new(&skey, &inputs, &[output2], &outputs2_gamma, fee)
+                PaymentTransaction::new(&chain_id, &skey, &inputs, &[output2], &outputs2_gamma, fee)
                     .unwrap()
                     .into()
             };
@@ -318,9 +441,10 @@ mod test {
             let fee = stake_fee;
             let output =
                 Output::new_stake(&pkey, &validator_skey, &validator_pkey, amount - fee).unwrap();
-            let tx = PaymentTransaction::new(&skey, &inputs, &[output], &Fr::zero(), fee)
-                .unwrap()
-                .into();
+            let tx =
+                PaymentTransaction::new(&chain_id, &skey, &inputs, &[output], &Fr::zero(), fee)
+                    .unwrap()
+                    .into();
             validate_external_transaction(&tx, &mempool, &chain, timestamp, payment_fee, stake_fee)
                 .expect("transaction is valid");
         }
@@ -338,9 +462,16 @@ mod test {
             let output2 = Output::StakeOutput(output2);
             let outputs: Vec<Output> = vec![output1, output2];
             let outputs_gamma = gamma1;
-            let tx = PaymentTransaction::unchecked(&skey, &inputs, &outputs, &outputs_gamma, fee)
-                .unwrap()
-                .into();
+            let tx = PaymentTransaction::unchecked(
+                &chain_id,
+                &skey,
+                &inputs,
+                &outputs,
+                &outputs_gamma,
+                fee,
+            )
+            .unwrap()
+            .into();
             let e = validate_external_transaction(
                 &tx,
                 &mempool,
@@ -363,9 +494,16 @@ mod test {
             timestamp += Duration::from_millis(1);
             let fee = payment_fee;
             let (output, outputs_gamma) = Output::new_payment(&pkey, stake - fee).unwrap();
-            let tx = PaymentTransaction::unchecked(&skey, &stakes, &[output], &outputs_gamma, fee)
-                .unwrap()
-                .into();
+            let tx = PaymentTransaction::unchecked(
+                &chain_id,
+                &skey,
+                &stakes,
+                &[output],
+                &outputs_gamma,
+                fee,
+            )
+            .unwrap()
+            .into();
             let e = validate_external_transaction(
                 &tx,
                 &mempool,
@@ -397,9 +535,10 @@ mod test {
             let output =
                 Output::new_stake(&pkey, &keychain.network_skey, &keychain.network_pkey, stake)
                     .unwrap();
-            let tx = PaymentTransaction::unchecked(&skey, &stakes, &[output], &Fr::zero(), 0)
-                .unwrap()
-                .into();
+            let tx =
+                PaymentTransaction::unchecked(&chain_id, &skey, &stakes, &[output], &Fr::zero(), 0)
+                    .unwrap()
+                    .into();
             validate_external_transaction(&tx, &mempool, &chain, timestamp, payment_fee, 0)
                 .expect("transaction is valid");
         }
@@ -413,14 +552,22 @@ mod test {
             let outputs: Vec<Output> = vec![output];
             let output_hashes: Vec<Hash> = outputs.iter().map(|o| Hash::digest(o)).collect();
             // Claim output in mempool.
-            let claim_tx = PaymentTransaction::unchecked(&skey, &[], &outputs, &outputs_gamma, fee)
-                .unwrap()
-                .into();
+            let claim_tx =
+                PaymentTransaction::unchecked(&chain_id, &skey, &[], &outputs, &outputs_gamma, fee)
+                    .unwrap()
+                    .into();
             mempool.push_tx(Hash::digest(&claim_tx), claim_tx);
 
-            let tx = PaymentTransaction::unchecked(&skey, &inputs, &outputs, &outputs_gamma, fee)
-                .unwrap()
-                .into();
+            let tx = PaymentTransaction::unchecked(
+                &chain_id,
+                &skey,
+                &inputs,
+                &outputs,
+                &outputs_gamma,
+                fee,
+            )
+            .unwrap()
+            .into();
             let e = validate_external_transaction(
                 &tx,
                 &mempool,
@@ -440,4 +587,141 @@ mod test {
             mempool.prune(&[], &output_hashes);
         }
     }
+
+    #[test]
+    fn test_transaction_limits() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let payment_fee: i64 = 1;
+        let stake_fee: i64 = 0;
+        let amount: i64 = 10000;
+        let timestamp = SystemTime::now();
+        let keychain = KeyChain::new_mem();
+        let mempool = Mempool::new();
+        let mut cfg: BlockchainConfig = Default::default();
+        cfg.max_inputs_per_tx = 1;
+        cfg.max_tx_size = 1024;
+        let stake: i64 = cfg.min_stake_amount;
+        let genesis = genesis(&[keychain.clone()], stake, amount + stake, timestamp);
+        let chain =
+            Blockchain::testing(cfg, genesis, timestamp).expect("Failed to create blockchain");
+        let chain_id = chain.chain_id();
+        let skey = &keychain.wallet_skey;
+        let pkey = &keychain.wallet_pkey;
+
+        //
+        // Too many inputs.
+        //
+        {
+            let fee = payment_fee;
+            let (input1, _gamma1) = Output::new_payment(&pkey, 1).unwrap();
+            let (input2, _gamma2) = Output::new_payment(&pkey, amount - 1).unwrap();
+            let (output, outputs_gamma) = Output::new_payment(&pkey, amount - fee).unwrap();
+            let tx: Transaction = PaymentTransaction::new(
+                &chain_id,
+                &skey,
+                &[input1, input2],
+                &[output],
+                &outputs_gamma,
+                fee,
+            )
+            .unwrap()
+            .into();
+            let e = validate_external_transaction(
+                &tx,
+                &mempool,
+                &chain,
+                timestamp,
+                payment_fee,
+                stake_fee,
+            )
+            .expect_err("transaction is not valid");
+            match e.downcast::<BlockchainError>().expect("proper error") {
+                BlockchainError::TransactionError(TransactionError::TooManyInputs(
+                    tx_hash,
+                    inputs,
+                    max_inputs,
+                )) => {
+                    assert_eq!(tx_hash, Hash::digest(&tx));
+                    assert_eq!(inputs, 2);
+                    assert_eq!(max_inputs, 1);
+                }
+                _ => panic!(),
+            }
+        }
+
+        //
+        // Too big transaction.
+        //
+        {
+            let fee = 2 * payment_fee;
+            let (input, _inputs_gamma) = Output::new_payment(&pkey, amount).unwrap();
+            let (output1, gamma1) = Output::new_payment(&pkey, 1).unwrap();
+            let (output2, gamma2) = Output::new_payment(&pkey, amount - fee - 1).unwrap();
+            let outputs: Vec<Output> = vec![output1, output2];
+            let outputs_gamma = gamma1 + gamma2;
+            let tx: Transaction =
+                PaymentTransaction::new(&chain_id, &skey, &[input], &outputs, &outputs_gamma, fee)
+                    .unwrap()
+                    .into();
+            let e = validate_external_transaction(
+                &tx,
+                &mempool,
+                &chain,
+                timestamp,
+                payment_fee,
+                stake_fee,
+            )
+            .expect_err("transaction is not valid");
+            match e.downcast::<BlockchainError>().expect("proper error") {
+                BlockchainError::TransactionError(TransactionError::TooBigTransaction(
+                    tx_hash,
+                    _size,
+                    max_size,
+                )) => {
+                    assert_eq!(tx_hash, Hash::digest(&tx));
+                    assert_eq!(max_size, 1024);
+                }
+                _ => panic!(),
+            }
+        }
+
+        //
+        // Restaking is not limited by the number of inputs.
+        //
+        {
+            let network_skey = &keychain.network_skey;
+            let network_pkey = &keychain.network_pkey;
+            let input1 = Output::new_stake(&pkey, network_skey, network_pkey, 1).unwrap();
+            let input2 = Output::new_stake(&pkey, network_skey, network_pkey, 2).unwrap();
+            let output = Output::new_stake(&pkey, network_skey, network_pkey, 3).unwrap();
+            let tx: Transaction = RestakeTransaction::new(
+                &chain_id,
+                network_skey,
+                network_pkey,
+                &[input1, input2],
+                &[output],
+            )
+            .unwrap()
+            .into();
+            chain
+                .validate_transaction_limits(&tx)
+                .expect("restaking is not limited");
+        }
+    }
+
+    #[test]
+    fn block_buffer_size() {
+        let max_block_size = 1024;
+        let data = vec![0u8; max_block_size + BLOCK_BUFFER_OVERHEAD];
+        validate_block_buffer(&data, max_block_size).expect("block fits");
+        let data = vec![0u8; max_block_size + BLOCK_BUFFER_OVERHEAD + 1];
+        let e = validate_block_buffer(&data, max_block_size).expect_err("block is too big");
+        match e.downcast::<NodeBlockError>().expect("proper error") {
+            NodeBlockError::TooBigBlockBuffer(size, max_size) => {
+                assert_eq!(size, max_block_size + BLOCK_BUFFER_OVERHEAD + 1);
+                assert_eq!(max_size, max_block_size + BLOCK_BUFFER_OVERHEAD);
+            }
+            _ => panic!(),
+        }
+    }
 }
@@ -0,0 +1,147 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! A pool of threads checking signatures of network messages off the event loop.
+//!
+
+use futures::sync::oneshot;
+use log::*;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+/// Signed hash, signature and signer.
+pub(crate) type SignatureCheck = (Hash, pbc::Signature, pbc::PublicKey);
+
+/// The maximal number of jobs taken by a worker at once.
+const MAX_BATCH_SIZE: usize = 16;
+
+struct Job {
+    checks: Vec<SignatureCheck>,
+    tx: oneshot::Sender<bool>,
+}
+
+impl Job {
+    fn run(self) {
+        let valid = self
+            .checks
+            .iter()
+            .all(|(hash, sig, pkey)| pbc::check_hash(hash, sig, pkey).is_ok());
+        self.tx.send(valid).ok(); // ignore errors, the message was dropped.
+    }
+}
+
+/// Checks signatures on worker threads, results are delivered via futures.
+pub(crate) struct SignatureVerifier {
+    /// None if signatures are checked inline.
+    jobs: Option<Sender<Job>>,
+}
+
+impl SignatureVerifier {
+    ///
+    /// Spawn `threads` workers, zero checks signatures inline.
+    /// Workers exit when the verifier is dropped.
+    ///
+    pub fn new(threads: usize) -> Self {
+        if threads == 0 {
+            return SignatureVerifier { jobs: None };
+        }
+        let (tx, rx) = channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..threads {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("sig-verifier-{}", i))
+                .spawn(move || worker(rx))
+                .expect("failed to spawn a thread");
+        }
+        SignatureVerifier { jobs: Some(tx) }
+    }
+
+    /// Resolves to true if all signatures are valid.
+    pub fn verify(&self, checks: Vec<SignatureCheck>) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        let job = Job { checks, tx };
+        match &self.jobs {
+            Some(jobs) => {
+                if let Err(e) = jobs.send(job) {
+                    // All workers have panicked.
+                    e.0.run();
+                }
+            }
+            None => job.run(),
+        }
+        rx
+    }
+}
+
+fn worker(jobs: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        // Take a batch of jobs to not contend on the lock for every message.
+        let batch = {
+            let jobs = jobs.lock().unwrap();
+            let job = match jobs.recv() {
+                Ok(job) => job,
+                Err(_) => break, // the verifier was dropped.
+            };
+            let mut batch = vec![job];
+            while batch.len() < MAX_BATCH_SIZE {
+                match jobs.try_recv() {
+                    Ok(job) => batch.push(job),
+                    Err(_) => break,
+                }
+            }
+            batch
+        };
+        trace!("Checking signatures: jobs={}", batch.len());
+        for job in batch {
+            job.run();
+        }
+    }
+    debug!("Signature verifier stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+
+    #[test]
+    fn verify() {
+        let (skey, pkey) = pbc::make_random_keys();
+        let hash = Hash::digest("message");
+        let sig = pbc::sign_hash(&hash, &skey);
+        let other_hash = Hash::digest("other");
+
+        for threads in &[0, 2] {
+            let verifier = SignatureVerifier::new(*threads);
+            let valid = verifier.verify(vec![(hash, sig, pkey)]);
+            let invalid = verifier.verify(vec![(hash, sig, pkey), (other_hash, sig, pkey)]);
+            let empty = verifier.verify(Vec::new());
+            assert!(valid.wait().unwrap());
+            assert!(!invalid.wait().unwrap());
+            assert!(empty.wait().unwrap());
+        }
+    }
+}
@@ -25,7 +25,7 @@ use simple_logger;
 use std::fs;
 use std::process;
 use std::time::SystemTime;
-use stegos_blockchain::{genesis, Block, BlockchainConfig};
+use stegos_blockchain::{Block, BlockchainConfig, GenesisSpec};
 use stegos_keychain::KeyChain;
 use stegos_keychain::KeyChainConfig;
 use stegos_serialization::traits::ProtoConvert;
@@ -62,8 +62,17 @@ fn main() {
                 .help("Stake per each validator.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("chain")
+                .long("chain")
+                .value_name("NAME")
+                .help("Chain id of the genesis specification.")
+                .takes_value(true),
+        )
         .get_matches();
 
+    let chain = args.value_of("chain").unwrap_or("dev");
+
     let keys = if let Some(keys) = args.value_of("keys") {
         match keys.parse::<i32>() {
             Ok(keys) => {
@@ -132,6 +141,7 @@ fn main() {
             wallet_pkey_file: format!("wallet{:02}.pkey", i + 1),
             network_skey_file: format!("network{:02}.skey", i + 1),
             network_pkey_file: format!("network{:02}.pkey", i + 1),
+            policy_file: String::new(),
         };
 
         let keychain = match KeyChain::new(cfg) {
@@ -147,7 +157,13 @@ fn main() {
 
     info!("Generating genesis blocks...");
     let timestamp = SystemTime::now();
-    let genesis_block = genesis(&keychains, stake, coins, timestamp);
+    let spec = GenesisSpec::generate(chain, &keychains, stake, coins, timestamp);
+    let spec_data = spec.to_toml();
+    fs::write("genesis.toml", &spec_data).expect("failed to write genesis specification");
+    // The first validator signs the specification.
+    let signature = GenesisSpec::sign_file(spec_data.as_bytes(), &keychains[0].network_skey);
+    fs::write("genesis.toml.sig", signature.to_hex()).expect("failed to write signature");
+    let genesis_block = spec.genesis_block().expect("valid genesis specification");
     let block_data = Block::MacroBlock(genesis_block).into_buffer().unwrap();
     fs::write("genesis.bin", &block_data).expect("failed to write genesis block");
     info!("Done");
@@ -0,0 +1,130 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use clap::{crate_version, App, Arg};
+use log::*;
+use simple_logger;
+use std::process;
+use stegos_consensus::signer::{SignGuard, SignerServer};
+use stegos_keychain::KeyChain;
+use stegos_keychain::KeyChainConfig;
+
+fn main() {
+    simple_logger::init_with_level(log::Level::Info).unwrap_or_default();
+
+    let args = App::new("Stegos Remote Signer")
+        .version(crate_version!())
+        .author("Stegos AG <info@stegos.com>")
+        .about("Signs blocks and consensus messages on behalf of a validator.")
+        .arg(
+            Arg::with_name("network-skey")
+                .long("network-skey")
+                .value_name("FILE")
+                .help("Path to the network secret key.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("network-pkey")
+                .long("network-pkey")
+                .value_name("FILE")
+                .help("Path to the network public key.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("password-file")
+                .long("password-file")
+                .value_name("FILE")
+                .help("Path to the password file, '-' to read from the terminal.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .short("l")
+                .long("listen")
+                .value_name("ENDPOINT")
+                .help("Address to listen on, host:port or unix:/path/to/socket.")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("token")
+                .short("t")
+                .long("token")
+                .value_name("TOKEN")
+                .help("Shared secret to authenticate nodes.")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("state")
+                .short("s")
+                .long("state")
+                .value_name("FILE")
+                .help("Path to the file with signed heights used to prevent double signing.")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let default = KeyChainConfig::default();
+    let cfg = KeyChainConfig {
+        password_file: args
+            .value_of("password-file")
+            .unwrap_or(&default.password_file)
+            .to_string(),
+        network_skey_file: args
+            .value_of("network-skey")
+            .unwrap_or(&default.network_skey_file)
+            .to_string(),
+        network_pkey_file: args
+            .value_of("network-pkey")
+            .unwrap_or(&default.network_pkey_file)
+            .to_string(),
+        ..default
+    };
+    let keychain = match KeyChain::new(cfg) {
+        Ok(k) => k,
+        Err(e) => {
+            eprintln!("Failed to load keys: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let state = args.value_of("state").unwrap_or("signer.state");
+    let guard = match SignGuard::open(state) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Failed to load the signer state: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let endpoint = args.value_of("listen").unwrap();
+    let token = args.value_of("token").unwrap().to_string();
+    info!(
+        "Starting the signer: endpoint={}, pkey={}",
+        endpoint, keychain.network_pkey
+    );
+    let server = SignerServer::new(keychain.network_skey, token, guard);
+    if let Err(e) = server.run(endpoint) {
+        eprintln!("Signer failed: {}", e);
+        process::exit(1);
+    }
+}
@@ -23,38 +23,52 @@ use stegos::*;
 
 use atty;
 use clap;
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use dirs;
-use failure::Error;
+use failure::{bail, format_err, Error};
 use futures::stream::Stream;
 use futures::Future;
 use hyper::server::Server;
 use hyper::service::service_fn_ok;
 use log::*;
+use std::fs;
+use std::io::{self, BufRead};
 use std::path::PathBuf;
 use std::process;
-use std::time::SystemTime;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use stegos_api::WebSocketAPI;
-use stegos_blockchain::Blockchain;
+use stegos_blockchain::{
+    compare_state, export_chain, import_chain, ArchiveReader, Blockchain, InspectorQuery, Output,
+    StorageConfig, UnsignedTransaction,
+};
+use stegos_crypto::curve1174;
+use stegos_crypto::hash::Hash;
 use stegos_keychain::*;
 use stegos_network::{Libp2pNetwork, NETWORK_STATUS_TOPIC};
-use stegos_node::NodeService;
+use stegos_node::{LightNodeService, NodeRoleService, NodeService};
+use stegos_serialization::traits::ProtoConvert;
 use stegos_txpool::TransactionPoolService;
-use stegos_wallet::WalletService;
+use stegos_wallet::{WalletManagerService, WalletService};
 use tokio::runtime::Runtime;
 
 use crate::console::*;
+use crate::money::format_money;
 use crate::report_metrics;
+use crate::shutdown::{shutdown_service, Shutdown};
+use crate::telemetry::TelemetryService;
+use crate::webhooks::WebhookService;
 
-fn load_configuration_file(args: &ArgMatches<'_>) -> Result<config::Config, Error> {
+fn load_configuration_file(args: &ArgMatches<'_>) -> Result<toml::Value, Error> {
     // Use --config argument for configuration.
     if let Some(cfg_path) = args.value_of_os("config") {
-        let cfg = config::from_file(cfg_path)?;
+        let cfg = config::read_file(cfg_path)?;
         return Ok(cfg);
     }
 
     // Use $PWD/stegos.toml for configuration.
-    match config::from_file(consts::CONFIG_FILE_NAME) {
+    match config::read_file(consts::CONFIG_FILE_NAME) {
         Ok(cfg) => return Ok(cfg),
         Err(config::ConfigError::NotFoundError) => {} // fall through.
         Err(e) => return Err(e.into()),
@@ -64,32 +78,34 @@ fn load_configuration_file(args: &ArgMatches<'_>) -> Result<config::Config, Erro
     let cfg_path = dirs::config_dir()
         .unwrap_or(PathBuf::from(r"."))
         .join(PathBuf::from(consts::CONFIG_FILE_NAME));
-    match config::from_file(cfg_path) {
+    match config::read_file(cfg_path) {
         Ok(cfg) => return Ok(cfg),
         Err(config::ConfigError::NotFoundError) => {} // fall through.
         Err(e) => return Err(e.into()),
     }
 
-    Ok(Default::default())
+    Ok(toml::Value::Table(Default::default()))
 }
 
 pub fn load_configuration(args: &ArgMatches<'_>) -> Result<config::Config, Error> {
-    let mut cfg = load_configuration_file(args)?;
+    let local = load_configuration_file(args)?;
+    let mut chain = config::chain_name(&local);
     // Override global.chain via ENV.
-    if let Ok(chain) = std::env::var("STEGOS_CHAIN") {
-        cfg.general.chain = chain;
+    if let Ok(env_chain) = std::env::var("STEGOS_CHAIN") {
+        chain = env_chain;
     }
 
     // Override global.chain via command-line.
-    if let Some(chain) = args.value_of("chain") {
-        cfg.general.chain = chain.to_string();
-    }
-    // Use default SRV record for the chain
-    if cfg.general.chain != "dev" && cfg.network.seed_pool == "" {
-        cfg.network.seed_pool =
-            format!("_stegos._tcp.{}.aws.stegos.com", cfg.general.chain).to_string();
+    if let Some(arg_chain) = args.value_of("chain") {
+        chain = arg_chain.to_string();
     }
 
+    // Apply settings of the built-in chain.
+    let mut cfg = config::for_chain(&chain, local)?;
+    // Bind blocks and network handshakes to the chain.
+    cfg.chain.chain_id = cfg.general.chain.clone();
+    cfg.network.chain_id = cfg.general.chain.clone();
+
     // Password options.
     if let Some(password_file) = args.value_of("password-file") {
         cfg.keychain.password_file = password_file.to_string();
@@ -100,6 +116,11 @@ pub fn load_configuration(args: &ArgMatches<'_>) -> Result<config::Config, Error
         cfg.keychain.recovery_file = recovery_file.to_string();
     }
 
+    // Skip verification of signatures below the last checkpoint.
+    if args.is_present("assume-valid") {
+        cfg.chain.assume_valid = true;
+    }
+
     Ok(cfg)
 }
 
@@ -113,6 +134,7 @@ fn run() -> Result<(), Error> {
         env!("VERSION_COMMIT"),
         env!("VERSION_DATE")
     );
+    let chain_help = format!("Specify chain to use: {}", chains::names().join(", "));
 
     let args = App::new(name)
         .version(&version[..])
@@ -157,16 +179,124 @@ fn run() -> Result<(), Error> {
                 .short("n")
                 .long("chain")
                 .value_name("NAME")
-                .help("Specify chain to use: testnet or dev")
+                .help(&chain_help)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("assume-valid")
+                .long("assume-valid")
+                .help("Don't verify block signatures below the last checkpoint")
+                .long_help(
+                    "Don't verify block signatures below the last checkpoint \
+                     from the 'chain.checkpoints' configuration option. \
+                     Speeds up the initial synchronization.",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Inspect the state of a stopped node")
+                .long_about(
+                    "Evaluate queries against the database of a stopped node. \
+                     Reads queries from stdin if no QUERY is specified.",
+                )
+                .arg(
+                    Arg::with_name("compare")
+                        .long("compare")
+                        .value_name("DIR")
+                        .help("Compare state hashes with another database")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("query")
+                        .value_name("QUERY")
+                        .help("output UTXO | block HEIGHT | escrow NETWORK_PKEY | state HEIGHT")
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sign")
+                .about("Sign a payment exported by 'offline export'")
+                .long_about(
+                    "Sign a payment transaction on a machine which holds the wallet keys. \
+                     Doesn't connect to the network. Use 'offline import' to broadcast \
+                     the signed transaction.",
+                )
+                .arg(
+                    Arg::with_name("unsigned")
+                        .value_name("UNSIGNED_FILE")
+                        .help("Path to the unsigned transaction")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("signed")
+                        .value_name("SIGNED_FILE")
+                        .help("Path to save the signed transaction")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-chain")
+                .about("Export blocks of a stopped node to an archive")
+                .arg(
+                    Arg::with_name("height")
+                        .long("height")
+                        .value_name("HEIGHT")
+                        .help("Export blocks below HEIGHT only")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .value_name("ARCHIVE_FILE")
+                        .help("Path to save the archive")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-chain")
+                .about("Import blocks from an archive into a stopped node")
+                .long_about(
+                    "Import blocks from an archive made by 'export-chain'. \
+                     Blocks which are already in the database are compared with the archive.",
+                )
+                .arg(
+                    Arg::with_name("trusted")
+                        .long("trusted")
+                        .help("Don't verify block signatures, trust the archive"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .value_name("ARCHIVE_FILE")
+                        .help("Path to the archive")
+                        .required(true),
+                ),
+        )
         .get_matches();
 
     // Parse configuration
     let mut cfg = load_configuration(&args)?;
+    // Encode and parse wallet addresses with the prefix of the chain.
+    curve1174::set_network_prefix(curve1174::chain_prefix(&cfg.general.chain));
 
     // Initialize logger
-    initialize_logger(&cfg)?;
+    let logging = Arc::new(initialize_logger(&cfg)?);
+
+    // Run inspector instead of the node
+    if let Some(args) = args.subcommand_matches("inspect") {
+        return inspect(cfg, args);
+    }
+
+    // Sign a transaction instead of running the node
+    if let Some(args) = args.subcommand_matches("sign") {
+        return sign(cfg, args);
+    }
+
+    // Export or import the chain instead of running the node
+    if let Some(args) = args.subcommand_matches("export-chain") {
+        return export_chain_archive(cfg, args);
+    }
+    if let Some(args) = args.subcommand_matches("import-chain") {
+        return import_chain_archive(cfg, args);
+    }
 
     // Print welcome message
     info!("{} {}", name, version);
@@ -195,29 +325,100 @@ fn run() -> Result<(), Error> {
         rt.spawn(hyper_service);
     }
 
-    // Initialize blockchain
-    let genesis = initialize_genesis(&cfg)?;
-    let timestamp = SystemTime::now();
-    let chain = Blockchain::new(cfg.chain.clone().into(), cfg.storage, genesis, timestamp)?;
-    let wallet_persistent_state =
-        chain.recover_wallet(&keychain.wallet_skey, &keychain.wallet_pkey)?;
-
     // Initialize node
-    let (mut node_service, node) =
-        NodeService::new(cfg.chain.clone(), chain, keychain.clone(), network.clone())?;
-
-    // Initialize TransactionPool.
-    let txpool_service = TransactionPoolService::new(&keychain, network.clone(), node.clone());
+    let genesis = initialize_genesis(&cfg)?;
+    let (mut node_service, node, chain_id, wallet_persistent_state, txpool_service) =
+        if cfg.chain.role.keeps_full_chain() {
+            // Initialize blockchain
+            let timestamp = SystemTime::now();
+            let chain = Blockchain::new(cfg.chain.clone().into(), cfg.storage, genesis, timestamp)?;
+            let wallet_persistent_state =
+                chain.recover_wallet(&keychain.wallet_skey, &keychain.wallet_pkey)?;
+            let chain_id = chain.chain_id();
+            let (node_service, node) =
+                NodeService::new(cfg.chain.clone(), chain, keychain.clone(), network.clone())?;
+
+            // Initialize TransactionPool.
+            let txpool_service =
+                TransactionPoolService::new(&keychain, network.clone(), node.clone());
+            let node_service: Box<dyn NodeRoleService> = Box::new(node_service);
+            (
+                node_service,
+                node,
+                chain_id,
+                wallet_persistent_state,
+                Some(txpool_service),
+            )
+        } else {
+            // Light nodes keep neither the chain nor the transaction pool.
+            let (mut node_service, node) =
+                LightNodeService::new(cfg.chain.clone(), genesis, &keychain, network.clone())?;
+            let wallet_persistent_state =
+                node_service.recover_wallet(&keychain.wallet_skey, &keychain.wallet_pkey);
+            let chain_id = node_service.chain_id();
+            let node_service: Box<dyn NodeRoleService> = Box::new(node_service);
+            (node_service, node, chain_id, wallet_persistent_state, None)
+        };
 
     // Initialize Wallet.
     let (wallet_service, wallet) = WalletService::new(
+        &cfg.wallet,
+        chain_id,
         keychain.clone(),
         network.clone(),
         node.clone(),
         cfg.chain.payment_fee,
         cfg.chain.stake_fee,
         cfg.chain.stake_epochs,
+        cfg.chain.max_inputs_per_tx,
         wallet_persistent_state,
+    )?;
+
+    // Initialize named wallets.
+    let (wallet_manager_service, wallets) = WalletManagerService::new(
+        &cfg.wallet,
+        chain_id,
+        keychain.clone(),
+        network.clone(),
+        node.clone(),
+        cfg.chain.payment_fee,
+        cfg.chain.stake_fee,
+        cfg.chain.stake_epochs,
+        cfg.chain.max_inputs_per_tx,
+    );
+
+    // Initialize opt-in telemetry.
+    let telemetry_service = if cfg.telemetry.endpoint != "" {
+        Some(TelemetryService::new(
+            cfg.telemetry.clone(),
+            version.clone(),
+            cfg.general.chain.clone(),
+            keychain.network_pkey.clone(),
+            keychain.network_skey.clone(),
+            node.clone(),
+        )?)
+    } else {
+        None
+    };
+
+    // Initialize wallet webhooks.
+    let webhook_service = if !cfg.webhooks.hooks.is_empty() {
+        Some(WebhookService::new(
+            cfg.webhooks.clone(),
+            keychain.wallet_pkey.clone(),
+            wallet.clone(),
+        )?)
+    } else {
+        None
+    };
+
+    // Initialize graceful shutdown.
+    let (shutdown, shutdown_requests) = Shutdown::new();
+    let shutdown_service = shutdown_service(
+        shutdown_requests,
+        node.clone(),
+        network.clone(),
+        Duration::from_secs(cfg.general.shutdown_timeout),
     );
 
     // Don't initialize REPL if stdin is not a TTY device
@@ -228,13 +429,21 @@ fn run() -> Result<(), Error> {
             network.clone(),
             wallet.clone(),
             node.clone(),
+            shutdown,
         )?)
     } else {
         None
     };
 
     // Start WebSocket API server.
-    WebSocketAPI::spawn(cfg.api, rt.executor(), wallet.clone(), node.clone())?;
+    WebSocketAPI::spawn(
+        cfg.api,
+        rt.executor(),
+        wallet.clone(),
+        wallets,
+        node.clone(),
+        logging.clone(),
+    )?;
 
     // Start all services when network is ready.
     let executor = rt.executor();
@@ -247,20 +456,170 @@ fn run() -> Result<(), Error> {
             // TODO: how to handle errors here?
             node_service.init().expect("shit happens");
             executor.spawn(node_service);
-            executor.spawn(txpool_service);
+            if let Some(txpool_service) = txpool_service {
+                executor.spawn(txpool_service);
+            }
             executor.spawn(wallet_service);
+            executor.spawn(wallet_manager_service);
             if let Some(console_service) = console_service {
                 executor.spawn(console_service);
             }
+            if let Some(telemetry_service) = telemetry_service {
+                executor.spawn(telemetry_service);
+            }
+            if let Some(webhook_service) = webhook_service {
+                executor.spawn(webhook_service);
+            }
 
             Ok(())
         });
     rt.spawn(network_ready_future);
 
-    // Start main event loop
-    rt.block_on(network_service)
-        .expect("errors are handled earlier");
+    // Start main event loop, exit when the network is stopped or the shutdown deadline expires.
+    rt.block_on(
+        network_service
+            .select(shutdown_service)
+            .map(drop)
+            .map_err(drop),
+    )
+    .expect("errors are handled earlier");
+
+    Ok(())
+}
+
+fn open_chain(
+    cfg: &config::Config,
+    database_path: &str,
+    read_only: bool,
+) -> Result<Blockchain, Error> {
+    let genesis = initialize_genesis(cfg)?;
+    let storage_cfg = StorageConfig {
+        database_path: database_path.to_string(),
+        ..cfg.storage.clone()
+    };
+    let timestamp = SystemTime::now();
+    let chain_cfg = cfg.chain.clone().into();
+    let chain = if read_only {
+        Blockchain::open_read_only(chain_cfg, storage_cfg, genesis, timestamp)
+            .map_err(|e| format_err!("Failed to open the database {}: {}", database_path, e))?
+    } else {
+        Blockchain::new(chain_cfg, storage_cfg, genesis, timestamp)?
+    };
+    Ok(chain)
+}
+
+fn inspect_one(chain: &Blockchain, other: Option<&Blockchain>, query: &str) -> Result<(), Error> {
+    let query = InspectorQuery::from_str(query)?;
+    let output = match (other, query) {
+        (Some(other), InspectorQuery::StateHash { height }) => {
+            serde_yaml::to_string(&compare_state(chain, other, height)?)?
+        }
+        (Some(_), _) => bail!("--compare can be used only with state queries"),
+        (None, query) => serde_yaml::to_string(&chain.inspect(&query)?)?,
+    };
+    println!("{}\n...\n", output);
+    Ok(())
+}
+
+fn inspect(cfg: config::Config, args: &ArgMatches<'_>) -> Result<(), Error> {
+    let chain = open_chain(&cfg, &cfg.storage.database_path, true)?;
+    let other = match args.value_of("compare") {
+        Some(database_path) => Some(open_chain(&cfg, database_path, true)?),
+        None => None,
+    };
 
+    if let Some(query) = args.values_of("query") {
+        let query: Vec<&str> = query.collect();
+        return inspect_one(&chain, other.as_ref(), &query.join(" "));
+    }
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Err(e) = inspect_one(&chain, other.as_ref(), &line) {
+            println!("Error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn export_chain_archive(cfg: config::Config, args: &ArgMatches<'_>) -> Result<(), Error> {
+    let output = args.value_of("output").unwrap();
+    let height = match args.value_of("height") {
+        Some(height) => Some(u64::from_str(height)?),
+        None => None,
+    };
+    let chain = open_chain(&cfg, &cfg.storage.database_path, false)?;
+    let writer = io::BufWriter::new(fs::File::create(output)?);
+    let header = export_chain(&chain, height, writer)?;
+    println!(
+        "Exported {} blocks to '{}', last_block={}",
+        header.blocks, output, header.last_block
+    );
+    Ok(())
+}
+
+fn import_chain_archive(mut cfg: config::Config, args: &ArgMatches<'_>) -> Result<(), Error> {
+    let input = args.value_of("input").unwrap();
+    let archive = ArchiveReader::new(io::BufReader::new(fs::File::open(input)?))?;
+    if args.is_present("trusted") {
+        if let Some(checkpoint) = archive.header().checkpoint() {
+            cfg.chain.checkpoints.push(checkpoint);
+            cfg.chain.assume_valid = true;
+        }
+    }
+    let mut chain = open_chain(&cfg, &cfg.storage.database_path, false)?;
+    let applied = import_chain(&mut chain, archive)?;
+    println!(
+        "Imported {} blocks from '{}', height={}",
+        applied,
+        input,
+        chain.height()
+    );
+    Ok(())
+}
+
+fn sign(cfg: config::Config, args: &ArgMatches<'_>) -> Result<(), Error> {
+    let keychain = KeyChain::new(cfg.keychain.clone())?;
+    let unsigned_file = args.value_of("unsigned").unwrap();
+    let signed_file = args.value_of("signed").unwrap();
+
+    let unsigned = UnsignedTransaction::from_buffer(&fs::read(unsigned_file)?)?;
+    let mut spent: i64 = 0;
+    for input in &unsigned.inputs {
+        match input {
+            Output::PaymentOutput(o) => spent += o.decrypt_payload(&keychain.wallet_skey)?.amount,
+            _ => bail!("Unsupported input: utxo={}", Hash::digest(input)),
+        }
+    }
+    let mut change: i64 = 0;
+    for output in &unsigned.outputs {
+        if let Output::PaymentOutput(o) = output {
+            if let Ok(payload) = o.decrypt_payload(&keychain.wallet_skey) {
+                change += payload.amount;
+            }
+        }
+    }
+    println!("Unsigned transaction: {}", Hash::digest(&unsigned));
+    println!(
+        "Spending {} in {} inputs, sending {}, change {}, fee {}",
+        format_money(spent),
+        unsigned.inputs.len(),
+        format_money(spent - change - unsigned.fee),
+        format_money(change),
+        format_money(unsigned.fee)
+    );
+
+    let signed = unsigned.sign(&keychain.wallet_skey)?;
+    fs::write(signed_file, signed.into_buffer()?)?;
+    println!(
+        "Saved signed transaction {} to '{}'",
+        Hash::digest(&signed.tx),
+        signed_file
+    );
     Ok(())
 }
 
@@ -47,19 +47,16 @@ const LOG_CONFIG_NAME: &'static str = "stegos-log4rs.toml";
 pub fn load_configuration(folder: &str) -> Result<config::Config, Error> {
     let mut path = PathBuf::from(folder);
     path.push(CONFIG_NAME);
-    let mut cfg = config::from_file(path)?;
+    let local = config::read_file(path)?;
+    let mut chain = config::chain_name(&local);
 
     // Override global.chain via ENV.
-    if let Ok(chain) = std::env::var("STEGOS_CHAIN") {
-        cfg.general.chain = chain;
-    }
-
-    // Use default SRV record for the chain
-    if cfg.general.chain != "dev" && cfg.network.seed_pool == "" {
-        cfg.network.seed_pool =
-            format!("_stegos._tcp.{}.aws.stegos.com", cfg.general.chain).to_string();
+    if let Ok(env_chain) = std::env::var("STEGOS_CHAIN") {
+        chain = env_chain;
     }
 
+    // Apply settings of the built-in chain.
+    let cfg = config::for_chain(&chain, local)?;
     Ok(cfg)
 }
 
@@ -218,6 +215,7 @@ fn run() -> Result<(), Error> {
     )?;
 
     let generator_configs = recover_generator(&chain, node_configs)?;
+    let chain_id = chain.chain_id();
 
     info!("Starting node service.");
     // Initialize node
@@ -236,14 +234,17 @@ fn run() -> Result<(), Error> {
         info!("Starting wallet with generator.");
         // Initialize Wallet.
         let (wallet_service, wallet) = WalletService::new(
+            &cfg.wallet,
+            chain_id,
             keychain.clone(),
             network.clone(),
             node.clone(),
             cfg.chain.payment_fee,
             cfg.chain.stake_fee,
             cfg.chain.stake_epochs,
+            cfg.chain.max_inputs_per_tx,
             wallet_persistent_state,
-        );
+        )?;
         rt.spawn(wallet_service);
 
         let bot = Generator::new(wallet, cfg.general.generate_txs, mode, true);
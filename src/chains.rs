@@ -0,0 +1,69 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Chain specifications built into the binary.
+//!
+//! Every chain consists of the genesis block and a fragment of stegos.toml with the settings
+//! shared by all its nodes, e.g. bootstrap peers. Chains are selected by name at runtime
+//! and included by the Cargo features of the same name.
+
+/// Built-in chain.
+#[derive(Debug)]
+pub struct ChainSpec {
+    /// Name of the chain, also used as the chain id.
+    pub name: &'static str,
+    /// Serialized genesis block.
+    pub genesis: &'static [u8],
+    /// Configuration overridden by the local stegos.toml.
+    pub config: &'static str,
+}
+
+/// Chains included into this build.
+pub const CHAINS: &[ChainSpec] = &[
+    #[cfg(feature = "testnet")]
+    ChainSpec {
+        name: "testnet",
+        genesis: include_bytes!("../chains/testnet/genesis.bin"),
+        config: include_str!("../chains/testnet/chain.toml"),
+    },
+    #[cfg(feature = "devnet")]
+    ChainSpec {
+        name: "devnet",
+        genesis: include_bytes!("../chains/devnet/genesis.bin"),
+        config: include_str!("../chains/devnet/chain.toml"),
+    },
+    #[cfg(feature = "dev")]
+    ChainSpec {
+        name: "dev",
+        genesis: include_bytes!("../chains/dev/genesis.bin"),
+        config: include_str!("../chains/dev/chain.toml"),
+    },
+];
+
+/// Returns the built-in chain with the given name.
+pub fn find(name: &str) -> Option<&'static ChainSpec> {
+    CHAINS.iter().find(|spec| spec.name == name)
+}
+
+/// Returns names of the built-in chains.
+pub fn names() -> Vec<&'static str> {
+    CHAINS.iter().map(|spec| spec.name).collect()
+}
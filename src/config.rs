@@ -21,8 +21,13 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::chains::{self, ChainSpec};
+use crate::logging::LogFormat;
+use crate::telemetry::TelemetryConfig;
+use crate::webhooks::WebhooksConfig;
 use failure::Fail;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
 use std::io::ErrorKind;
@@ -35,6 +40,7 @@ use stegos_crypto::curve1174::PublicKey;
 use stegos_keychain::KeyChainConfig;
 use stegos_network::NetworkConfig;
 use stegos_node::ChainConfig;
+use stegos_wallet::WalletConfig;
 use toml;
 
 /// Configuration root
@@ -59,6 +65,12 @@ pub struct Config {
     pub storage: StorageConfig,
     /// WebSocket API configuration.
     pub api: WebSocketConfig,
+    /// Wallet configuration.
+    pub wallet: WalletConfig,
+    /// Telemetry configuration.
+    pub telemetry: TelemetryConfig,
+    /// Wallet webhooks configuration.
+    pub webhooks: WebhooksConfig,
 }
 
 /// Default values for global configuration.
@@ -71,6 +83,9 @@ impl Default for Config {
             keychain: Default::default(),
             storage: Default::default(),
             api: Default::default(),
+            wallet: Default::default(),
+            telemetry: Default::default(),
+            webhooks: Default::default(),
         }
     }
 }
@@ -81,21 +96,37 @@ impl Default for Config {
 pub struct GeneralConfig {
     /// Chain name.
     pub chain: String,
+    /// Genesis specification file, used instead of the built-in genesis if not empty.
+    /// A detached signature is read from the same path with ".sig" appended.
+    pub genesis_file: String,
+    /// Network public key of the party which signs the genesis specification.
+    pub genesis_authority: String,
     /// Log4RS configuration file
     pub log4rs_config: String,
+    /// Console log format, used if log4rs_config is not found.
+    pub log_format: LogFormat,
+    /// Log levels by target, e.g. "stegos_network::kad" = "debug".
+    pub log_levels: BTreeMap<String, String>,
     /// Prometheus exporter endpoint
     pub prometheus_endpoint: String,
     /// Start transaction generator to some receivers.
     pub generate_txs: Vec<PublicKey>,
+    /// Deadline for a graceful shutdown (secs).
+    pub shutdown_timeout: u64,
 }
 
 impl Default for GeneralConfig {
     fn default() -> Self {
         GeneralConfig {
             chain: "testnet".to_string(),
+            genesis_file: "".to_string(),
+            genesis_authority: "".to_string(),
             log4rs_config: "stegos-log4rs.toml".to_string(),
+            log_format: LogFormat::Text,
+            log_levels: BTreeMap::new(),
             prometheus_endpoint: "".to_string(),
             generate_txs: Vec::new(),
+            shutdown_timeout: 30,
         }
     }
 }
@@ -112,6 +143,17 @@ pub enum ConfigError {
     /// Caused by parse errors.
     #[fail(display = "Failed to parse configuration file: {}.", _0)]
     ParseError(toml::de::Error),
+    /// Caused by inconsistent settings.
+    #[fail(display = "Invalid configuration: {}.", _0)]
+    InvalidValue(String),
+}
+
+/// Checks settings which can't be checked by the parser.
+fn validate(cfg: &Config) -> Result<(), ConfigError> {
+    cfg.chain
+        .emission
+        .validate()
+        .map_err(|e| ConfigError::InvalidValue(e.to_string()))
 }
 
 ///
@@ -126,6 +168,24 @@ pub enum ConfigError {
 /// Returns ConfigError on error.
 ///
 pub fn from_file<P: AsRef<Path>>(cfg_path: P) -> Result<Config, ConfigError> {
+    let value = read_file(cfg_path)?;
+    let cfg: Config = value.try_into().map_err(ConfigError::ParseError)?;
+    validate(&cfg)?;
+    Ok(cfg)
+}
+
+///
+/// Read configuration file as a TOML document, without applying defaults.
+///
+/// # Arguments
+///
+/// * `cfg_path` - A path to configuration file
+///
+/// # Errors
+///
+/// Returns ConfigError on error.
+///
+pub fn read_file<P: AsRef<Path>>(cfg_path: P) -> Result<toml::Value, ConfigError> {
     // Open configuration file
     let mut f = match File::open(cfg_path) {
         // The file is readable
@@ -144,8 +204,165 @@ pub fn from_file<P: AsRef<Path>>(cfg_path: P) -> Result<Config, ConfigError> {
     drop(f);
 
     // Deserialize TOML and return result
-    match toml::from_str(&contents) {
-        Ok(cfg) => Ok(cfg),
-        Err(e) => Err(ConfigError::ParseError(e)),
+    toml::from_str(&contents).map_err(ConfigError::ParseError)
+}
+
+/// Settings which must be the same for all nodes of a chain, as (section, key).
+const CHAIN_PARAMETERS: &[(&str, &str)] = &[("chain", "tx_topic_shards")];
+
+///
+/// Build configuration from the settings of a built-in chain and the local settings.
+///
+/// # Arguments
+///
+/// * `spec` - The chain specification
+/// * `local` - Local settings, take precedence over the chain
+///
+/// # Errors
+///
+/// Returns ConfigError on error.
+///
+pub fn with_chain(spec: &ChainSpec, local: toml::Value) -> Result<Config, ConfigError> {
+    let mut value: toml::Value = toml::from_str(spec.config).map_err(ConfigError::ParseError)?;
+    // Parameters shared by all nodes of the chain can't be changed locally.
+    for (section, key) in CHAIN_PARAMETERS {
+        let chain_value = value.get(section).and_then(|s| s.get(key));
+        let local_value = local.get(section).and_then(|s| s.get(key));
+        if let (Some(chain_value), Some(local_value)) = (chain_value, local_value) {
+            if chain_value != local_value {
+                return Err(ConfigError::InvalidValue(format!(
+                    "{}.{} is a parameter of the '{}' chain: expected={}, got={}",
+                    section, key, spec.name, chain_value, local_value
+                )));
+            }
+        }
+    }
+    merge(&mut value, local);
+    value.try_into().map_err(ConfigError::ParseError)
+}
+
+///
+/// Build configuration for the chain, applying the settings of the built-in chain if any.
+///
+/// # Arguments
+///
+/// * `chain` - Name of the chain, a custom chain needs `general.genesis_file`
+/// * `local` - Local settings, see `read_file()`
+///
+/// # Errors
+///
+/// Returns ConfigError on error.
+///
+pub fn for_chain(chain: &str, local: toml::Value) -> Result<Config, ConfigError> {
+    let mut cfg: Config = match chains::find(chain) {
+        Some(spec) => with_chain(spec, local)?,
+        None => local.try_into().map_err(ConfigError::ParseError)?,
+    };
+    cfg.general.chain = chain.to_string();
+    validate(&cfg)?;
+    Ok(cfg)
+}
+
+/// Returns the chain name from local settings, see `read_file()`.
+pub fn chain_name(local: &toml::Value) -> String {
+    local
+        .get("general")
+        .and_then(|general| general.get("chain"))
+        .and_then(|chain| chain.as_str())
+        .map(|chain| chain.to_string())
+        .unwrap_or_else(|| GeneralConfig::default().chain)
+}
+
+/// Overlays tables of `local` on top of `base`, recursively.
+fn merge(base: &mut toml::Value, local: toml::Value) {
+    match (base, local) {
+        (toml::Value::Table(base), toml::Value::Table(local)) => {
+            for (key, value) in local {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, local) => *base = local,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_settings() {
+        let spec = ChainSpec {
+            name: "test",
+            genesis: &[],
+            config: r#"
+                [network]
+                seed_pool = "_stegos._tcp.test"
+                seed_nodes = ["/ip4/127.0.0.1/tcp/10050"]
+                [chain]
+                tx_broadcast_fanout = 8
+            "#,
+        };
+        let local: toml::Value = toml::from_str(
+            r#"
+            [network]
+            seed_nodes = []
+            bind_port = 10055
+            "#,
+        )
+        .unwrap();
+
+        let cfg = with_chain(&spec, local).unwrap();
+        // Chain settings.
+        assert_eq!(cfg.network.seed_pool, "_stegos._tcp.test");
+        assert_eq!(cfg.chain.tx_broadcast_fanout, 8);
+        // Local settings take precedence.
+        assert!(cfg.network.seed_nodes.is_empty());
+        assert_eq!(cfg.network.bind_port, 10055);
+        // Defaults.
+        assert_eq!(cfg.network.max_connections, 32);
+    }
+
+    #[test]
+    fn chain_parameters() {
+        let spec = ChainSpec {
+            name: "test",
+            genesis: &[],
+            config: r#"
+                [chain]
+                tx_topic_shards = 4
+            "#,
+        };
+        let cfg = with_chain(&spec, toml::Value::Table(Default::default())).unwrap();
+        assert_eq!(cfg.chain.tx_topic_shards, 4);
+
+        let local: toml::Value = toml::from_str("[chain]\ntx_topic_shards = 4").unwrap();
+        let cfg = with_chain(&spec, local).unwrap();
+        assert_eq!(cfg.chain.tx_topic_shards, 4);
+
+        let local: toml::Value = toml::from_str("[chain]\ntx_topic_shards = 8").unwrap();
+        match with_chain(&spec, local) {
+            Err(ConfigError::InvalidValue(_)) => {}
+            e => panic!("{:?}", e.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn invalid_emission() {
+        let cfg: Config = toml::from_str(
+            r#"
+            [chain.emission]
+            decay_percent = 150
+            "#,
+        )
+        .unwrap();
+        match validate(&cfg) {
+            Err(ConfigError::InvalidValue(_)) => {}
+            e => panic!("{:?}", e),
+        }
     }
 }
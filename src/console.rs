@@ -23,6 +23,7 @@ use crate::config::GeneralConfig;
 use crate::consts;
 use crate::generator::{Generator, GeneratorMode};
 use crate::money::{format_money, parse_money};
+use crate::shutdown::Shutdown;
 use dirs;
 use failure::Error;
 use futures::sync::mpsc::UnboundedReceiver;
@@ -34,15 +35,21 @@ use log::*;
 use regex::Regex;
 use rustyline as rl;
 use std::fmt;
+use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::thread;
+use stegos_blockchain::InspectorQuery;
 use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
 use stegos_network::Network;
 use stegos_network::UnicastMessage;
 use stegos_node::{Node, NodeRequest, NodeResponse};
+use stegos_serialization::traits::ProtoConvert;
 use stegos_wallet::{
-    PaymentInfo, PaymentPayloadData, Wallet, WalletNotification, WalletRequest, WalletResponse,
+    ExportFormat, PaymentDirection, PaymentInfo, PaymentPayloadData, PaymentRecipient,
+    SignedTransaction, Wallet, WalletNotification, WalletRequest, WalletResponse,
 };
 
 // ----------------------------------------------------------------
@@ -57,11 +64,15 @@ use stegos_wallet::{
 
 lazy_static! {
     /// Regex to parse "pay" command.
-    static ref PAY_COMMAND_RE: Regex = Regex::new(r"\s*(?P<recipient>[0-9a-f]+)\s+(?P<amount>[0-9\.]{1,19})(\s+(?P<comment>.+))?\s*$").unwrap();
+    static ref PAY_COMMAND_RE: Regex = Regex::new(r"\s*(?P<recipient>[0-9A-Za-z]+)\s+(?P<amount>[0-9\.]{1,19})(\s+(?P<comment>.+))?\s*$").unwrap();
     /// Regex to parse "msg" command.
-    static ref MSG_COMMAND_RE: Regex = Regex::new(r"\s*(?P<recipient>[0-9a-f]+)\s+(?P<msg>.+)$").unwrap();
+    static ref MSG_COMMAND_RE: Regex = Regex::new(r"\s*(?P<recipient>[0-9A-Za-z]+)\s+(?P<msg>.+)$").unwrap();
     /// Regex to parse "stake/unstake" command.
     static ref STAKE_COMMAND_RE: Regex = Regex::new(r"\s*(?P<amount>[0-9\.]{1,19})\s*$").unwrap();
+    /// Regex to parse "delegate" command.
+    static ref DELEGATE_COMMAND_RE: Regex = Regex::new(r"\s*(?P<operator>[0-9a-f]+)\s+(?P<amount>[0-9\.]{1,19})\s*$").unwrap();
+    /// Regex to parse "offline export" command.
+    static ref OFFLINE_EXPORT_COMMAND_RE: Regex = Regex::new(r"\s*(?P<file>\S+)\s+(?P<recipient>[0-9A-Za-z]+)\s+(?P<amount>[0-9\.]{1,19})(\s+(?P<comment>.+))?\s*$").unwrap();
     /// Regex to parse "publish" command.
     static ref PUBLISH_COMMAND_RE: Regex = Regex::new(r"\s*(?P<topic>[0-9A-Za-z]+)\s+(?P<msg>.*)$").unwrap();
     /// Regex to parse "send" command.
@@ -86,12 +97,16 @@ pub struct ConsoleService {
     wallet_response: Option<oneshot::Receiver<WalletResponse>>,
     /// Node RPC responses.
     node_response: Option<oneshot::Receiver<NodeResponse>>,
+    /// A file to save the next exported unsigned transaction or history.
+    export_file: Option<PathBuf>,
     /// A channel to receive message from stdin thread.
     stdin: Receiver<String>,
     /// A thread used for readline.
     stdin_th: thread::JoinHandle<()>,
     /// A channel to receive unicast messages
     unicast_rx: UnboundedReceiver<UnicastMessage>,
+    /// Graceful shutdown.
+    shutdown: Shutdown,
 }
 
 impl ConsoleService {
@@ -101,11 +116,13 @@ impl ConsoleService {
         network: Network,
         wallet: Wallet,
         node: Node,
+        shutdown: Shutdown,
     ) -> Result<ConsoleService, Error> {
         let (tx, rx) = channel::<String>(1);
         let wallet_notifications = wallet.subscribe();
         let wallet_response = None;
         let node_response = None;
+        let export_file = None;
         let stdin_th = thread::spawn(move || Self::readline_thread_f(tx));
         let stdin = rx;
         let unicast_rx = network.subscribe_unicast(CONSOLE_PROTOCOL_ID)?;
@@ -126,10 +143,12 @@ impl ConsoleService {
             wallet_notifications,
             wallet_response,
             node_response,
+            export_file,
             stdin,
             stdin_th,
             unicast_rx,
             node,
+            shutdown,
         };
         Ok(service)
     }
@@ -183,21 +202,67 @@ impl ConsoleService {
     fn help() {
         println!("Usage:");
         println!("pay WALLET_PUBKEY AMOUNT [COMMENT] - send money");
+        println!("paymany WALLET_PUBKEY AMOUNT [COMMENT], ... - send money to several recipients");
         println!("spay WALLET_PUBKEY AMOUNT [COMMENT] - send money using ValueShuffle");
         println!("msg WALLET_PUBKEY MESSAGE - send a message via blockchain");
         println!("stake AMOUNT - stake money");
         println!("unstake [AMOUNT] - unstake money");
         println!("restake - restake all available stakes");
+        println!(
+            "delegate OPERATOR_PUBKEY AMOUNT - stake money and delegate validation to operator"
+        );
+        println!("revoke - revoke delegation of all delegated stakes");
+        println!("reward WALLET_PUBKEY - pay block rewards of all stakes to another wallet");
+        println!("reward reset - pay block rewards of all stakes to this wallet");
+        println!(
+            "offline export FILE WALLET_PUBKEY AMOUNT [COMMENT] - save a payment for offline signing"
+        );
+        println!("offline import FILE - broadcast a payment signed by 'stegos sign'");
+        println!("freeze UTXO - exclude an output from coin selection");
+        println!("unfreeze UTXO - return a frozen output to coin selection");
         println!("show version - print version information");
         println!("show keys - print keys");
         println!("show balance - print balance");
         println!("show utxo - print unspent outputs");
+        println!("show history [OFFSET [LIMIT]] - print confirmed payments, newest first");
+        println!("show invoice INVOICE_ID - print payments for an invoice");
+        println!("export history csv|json FILE [incoming|outgoing] - save confirmed payments for accounting");
+        println!("invoice AMOUNT [MEMO] - issue an invoice payable to this wallet");
+        println!("pay invoice INVOICE - pay an invoice issued by another wallet");
+        println!("show invoices - print invoices issued by this wallet");
+        println!("watch pkey WALLET_PUBKEY - track public payments to a key");
+        println!("watch utxo UTXO [AMOUNT] - track an expected deposit");
+        println!("show deposits - print unspent deposits found by the watch list");
+        println!("show policy - print spend policy rules and the amount spent today");
+        println!("approve UNSIGNED_HASH - sign an approval for a payment held by another wallet");
+        println!("show status - print the status of the node");
         println!("show election - print leader election state");
+        println!("show leader EPOCH VIEW_CHANGE - print how the leader was selected");
+        println!("show viewchange - print collected view changes for the current height");
+        println!("show epoch EPOCH - print the summary of a finalized epoch");
+        println!("show finality - print the last irreversible block");
         println!("show escrow - print escrow");
+        println!("show fee [TARGET_BLOCKS] - suggest a fee to be confirmed within N blocks");
+        println!("show stakes - print stakes of this wallet and when they unlock");
+        println!("show unlock UTXO - print the height when a stake unlocks");
+        println!("show validator NETWORK_PUBKEY - print stake summary of a validator");
         println!("show recovery - print recovery information");
+        println!("passwd - change the password of secret keys");
+        println!(
+            "export key RECOVERY_PUBKEY - print the wallet secret key encrypted to a recovery key"
+        );
+        println!("show proof UTXO - print an exportable proof of a payment");
+        println!("inspect output UTXO - print an output, including spent ones");
+        println!("inspect block HEIGHT - decode and print a block");
+        println!("inspect escrow NETWORK_PKEY - print escrow entries of a validator");
+        println!("inspect state HEIGHT - print the state hash at height");
         println!("net publish TOPIC MESSAGE - publish a network message via floodsub");
         println!("net send NETWORK_PUBKEY MESSAGE - send a network message via unicast");
+        println!("net buckets - print the Kademlia routing table");
+        println!("net queries - print recent Kademlia queries");
+        println!("net statuses - print the latest chain statuses of validators");
         println!("db pop block - revert the latest block");
+        println!("db repair - roll back damaged blocks and compact the database");
         println!("generator start LIST_OF_WALLETS_ADDRESSES - start transaction generator");
         println!("generator stop - stop transaction generator");
         println!();
@@ -219,15 +284,36 @@ impl ConsoleService {
 
     fn help_pay() {
         println!("Usage: pay WALLET_PUBKEY AMOUNT [COMMENT]");
-        println!(" - WALLET_PUBKEY recipient's wallet public key in HEX format");
+        println!(" - WALLET_PUBKEY recipient's wallet address");
         println!(" - AMOUNT amount in tokens");
         println!(" - COMMENT purpose of payment");
         println!();
     }
 
+    fn help_paymany() {
+        println!(
+            "Usage: paymany WALLET_PUBKEY AMOUNT [COMMENT], WALLET_PUBKEY AMOUNT [COMMENT], ..."
+        );
+        println!(" - WALLET_PUBKEY recipient's wallet address");
+        println!(" - AMOUNT amount in tokens");
+        println!(" - COMMENT purpose of payment, without commas");
+        println!();
+    }
+
+    fn help_offline() {
+        println!("Usage: offline export FILE WALLET_PUBKEY AMOUNT [COMMENT]");
+        println!("       offline import FILE");
+        println!(" - FILE path to the unsigned (export) or signed (import) transaction");
+        println!(" - WALLET_PUBKEY recipient's wallet address");
+        println!(" - AMOUNT amount in tokens");
+        println!(" - COMMENT purpose of payment");
+        println!("Use 'stegos sign UNSIGNED_FILE SIGNED_FILE' to sign on an offline machine.");
+        println!();
+    }
+
     fn help_spay() {
         println!("Usage: spay WALLET_PUBKEY AMOUNT [COMMENT]");
-        println!(" - WALLET_PUBKEY recipient's wallet public key in HEX format");
+        println!(" - WALLET_PUBKEY recipient's wallet address");
         println!(" - AMOUNT amount in tokens");
         println!(" - COMMENT purpose of payment");
         println!();
@@ -246,9 +332,16 @@ impl ConsoleService {
         println!();
     }
 
+    fn help_delegate() {
+        println!("Usage: delegate OPERATOR_PUBKEY AMOUNT");
+        println!(" - OPERATOR_PUBKEY operator's network public key in HEX format");
+        println!(" - AMOUNT amount to stake into escrow, in tokens");
+        println!();
+    }
+
     fn help_msg() {
         println!("Usage: msg WALLET_PUBKEY MESSAGE");
-        println!(" - WALLET_PUBKEY recipient's public key in HEX format");
+        println!(" - WALLET_PUBKEY recipient's wallet address");
         println!(" - MESSAGE some message");
         println!();
     }
@@ -267,7 +360,25 @@ impl ConsoleService {
 
     /// Called when line is typed on standard input.
     fn on_input(&mut self, msg: &str) -> bool {
-        if msg.starts_with("net publish ") {
+        if msg == "net buckets" {
+            let request = NodeRequest::RoutingTable {};
+            self.node_response = Some(self.node.request(request));
+        } else if msg == "net queries" {
+            let request = NodeRequest::QueryJournal {};
+            self.node_response = Some(self.node.request(request));
+        } else if msg == "net statuses" {
+            let request = NodeRequest::PeerStatuses {};
+            self.node_response = Some(self.node.request(request));
+        } else if msg == "show status" {
+            let request = NodeRequest::Status {};
+            self.node_response = Some(self.node.request(request));
+        } else if msg == "show viewchange" {
+            let request = NodeRequest::ViewChangeInfo {};
+            self.node_response = Some(self.node.request(request));
+        } else if msg == "show finality" {
+            let request = NodeRequest::FinalityInfo {};
+            self.node_response = Some(self.node.request(request));
+        } else if msg.starts_with("net publish ") {
             let caps = match PUBLISH_COMMAND_RE.captures(&msg[12..]) {
                 Some(c) => c,
                 None => {
@@ -307,6 +418,35 @@ impl ConsoleService {
                 .send(recipient, "console", msg.as_bytes().to_vec())
                 .unwrap();
             return true;
+        } else if msg.starts_with("pay invoice ") {
+            let invoice = msg[12..].trim();
+            if invoice.is_empty() {
+                Self::help();
+                return true;
+            }
+            let invoice = invoice.to_string();
+            let request = WalletRequest::PayInvoice { invoice };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("invoice ") {
+            let mut args = msg[8..].trim().splitn(2, ' ');
+            let amount = match parse_money(args.next().unwrap_or_default()) {
+                Ok(amount) => amount,
+                Err(e) => {
+                    println!("{}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let memo = args.next().unwrap_or_default().trim().to_string();
+            let request = WalletRequest::CreateInvoice {
+                amount,
+                memo,
+                lifetime: None,
+            };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg == "show invoices" {
+            let request = WalletRequest::InvoicesInfo {};
+            self.wallet_response = Some(self.wallet.request(request));
         } else if msg.starts_with("pay ") {
             let caps = match PAY_COMMAND_RE.captures(&msg[4..]) {
                 Some(c) => c,
@@ -317,7 +457,7 @@ impl ConsoleService {
             };
 
             let recipient = caps.name("recipient").unwrap().as_str();
-            let recipient = match PublicKey::try_from_hex(recipient) {
+            let recipient = match PublicKey::from_str(recipient) {
                 Ok(r) => r,
                 Err(e) => {
                     println!("Invalid wallet public key '{}': {}", recipient, e);
@@ -343,7 +483,7 @@ impl ConsoleService {
             info!(
                 "Sending {} STG to {}",
                 format_money(amount),
-                recipient.to_hex()
+                recipient.to_address()
             );
             let request = WalletRequest::Payment {
                 recipient,
@@ -351,6 +491,121 @@ impl ConsoleService {
                 comment,
             };
             self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("paymany ") {
+            let mut recipients: Vec<PaymentRecipient> = Vec::new();
+            for payment in msg[8..].split(',') {
+                let caps = match PAY_COMMAND_RE.captures(payment) {
+                    Some(c) => c,
+                    None => {
+                        Self::help_paymany();
+                        return true;
+                    }
+                };
+
+                let recipient = caps.name("recipient").unwrap().as_str();
+                let recipient = match PublicKey::from_str(recipient) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        println!("Invalid wallet public key '{}': {}", recipient, e);
+                        Self::help_paymany();
+                        return true;
+                    }
+                };
+                let amount = caps.name("amount").unwrap().as_str();
+                let amount = match parse_money(amount) {
+                    Ok(amount) => amount,
+                    Err(e) => {
+                        println!("{}", e);
+                        Self::help_paymany();
+                        return true;
+                    }
+                };
+                let comment = if let Some(m) = caps.name("comment") {
+                    m.as_str().to_string()
+                } else {
+                    String::new()
+                };
+
+                info!(
+                    "Sending {} STG to {}",
+                    format_money(amount),
+                    recipient.to_address()
+                );
+                recipients.push(PaymentRecipient {
+                    recipient,
+                    amount,
+                    comment,
+                    memo: None,
+                });
+            }
+            let request = WalletRequest::PayMany { recipients };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("offline export ") {
+            let caps = match OFFLINE_EXPORT_COMMAND_RE.captures(&msg[15..]) {
+                Some(c) => c,
+                None => {
+                    Self::help_offline();
+                    return true;
+                }
+            };
+
+            let file = PathBuf::from(caps.name("file").unwrap().as_str());
+            let recipient = caps.name("recipient").unwrap().as_str();
+            let recipient = match PublicKey::from_str(recipient) {
+                Ok(r) => r,
+                Err(e) => {
+                    println!("Invalid wallet public key '{}': {}", recipient, e);
+                    Self::help_offline();
+                    return true;
+                }
+            };
+            let amount = caps.name("amount").unwrap().as_str();
+            let amount = match parse_money(amount) {
+                Ok(amount) => amount,
+                Err(e) => {
+                    println!("{}", e);
+                    Self::help_offline();
+                    return true;
+                }
+            };
+            let comment = if let Some(m) = caps.name("comment") {
+                m.as_str().to_string()
+            } else {
+                String::new()
+            };
+
+            info!(
+                "Exporting payment of {} STG to {}",
+                format_money(amount),
+                recipient.to_address()
+            );
+            let recipients = vec![PaymentRecipient {
+                recipient,
+                amount,
+                comment,
+                memo: None,
+            }];
+            let request = WalletRequest::ExportPayment { recipients };
+            self.export_file = Some(file);
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("offline import ") {
+            let file = msg[15..].trim();
+            if file.is_empty() {
+                Self::help_offline();
+                return true;
+            }
+            let signed = match fs::read(file)
+                .map_err(Error::from)
+                .and_then(|buf| SignedTransaction::from_buffer(&buf))
+            {
+                Ok(signed) => signed,
+                Err(e) => {
+                    println!("Failed to load signed transaction from '{}': {}", file, e);
+                    return true;
+                }
+            };
+            let request = WalletRequest::ImportSignedTransaction { signed };
+            self.wallet_response = Some(self.wallet.request(request));
         } else if msg.starts_with("spay ") {
             let caps = match PAY_COMMAND_RE.captures(&msg[5..]) {
                 Some(c) => c,
@@ -361,7 +616,7 @@ impl ConsoleService {
             };
 
             let recipient = caps.name("recipient").unwrap().as_str();
-            let recipient = match PublicKey::try_from_hex(recipient) {
+            let recipient = match PublicKey::from_str(recipient) {
                 Ok(r) => r,
                 Err(e) => {
                     println!("Invalid wallet public key '{}': {}", recipient, e);
@@ -387,7 +642,7 @@ impl ConsoleService {
             info!(
                 "Sending {} to {} via ValueShuffle",
                 format_money(amount),
-                recipient.to_hex()
+                recipient.to_address()
             );
             let request = WalletRequest::SecurePayment {
                 recipient,
@@ -405,7 +660,7 @@ impl ConsoleService {
             };
 
             let recipient = caps.name("recipient").unwrap().as_str();
-            let recipient = match PublicKey::try_from_hex(recipient) {
+            let recipient = match PublicKey::from_str(recipient) {
                 Ok(r) => r,
                 Err(e) => {
                     println!("Invalid wallet public key '{}': {}", recipient, e);
@@ -417,7 +672,7 @@ impl ConsoleService {
             let comment = caps.name("msg").unwrap().as_str().to_string();
             assert!(comment.len() > 0);
 
-            info!("Sending message to {}", recipient.to_hex());
+            info!("Sending message to {}", recipient.to_address());
             let request = WalletRequest::Payment {
                 recipient,
                 amount,
@@ -475,6 +730,65 @@ impl ConsoleService {
         } else if msg == "restake" {
             let request = WalletRequest::RestakeAll {};
             self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("delegate ") {
+            let caps = match DELEGATE_COMMAND_RE.captures(&msg[9..]) {
+                Some(c) => c,
+                None => {
+                    Self::help_delegate();
+                    return true;
+                }
+            };
+
+            let operator = caps.name("operator").unwrap().as_str();
+            let operator = match pbc::PublicKey::try_from_hex(operator) {
+                Ok(operator) => operator,
+                Err(e) => {
+                    println!("Invalid operator public key '{}': {}", operator, e);
+                    Self::help_delegate();
+                    return true;
+                }
+            };
+            let amount = caps.name("amount").unwrap().as_str();
+            let amount = match parse_money(amount) {
+                Ok(amount) => amount,
+                Err(e) => {
+                    println!("{}", e);
+                    Self::help_delegate();
+                    return true;
+                }
+            };
+
+            info!(
+                "Delegating {} STG to operator {}",
+                format_money(amount),
+                operator
+            );
+            let request = WalletRequest::Delegate { operator, amount };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg == "revoke" {
+            info!("Revoking delegation of all delegated stakes");
+            let request = WalletRequest::RevokeDelegation {};
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("reward ") {
+            let reward = msg[7..].trim();
+            let reward = if reward == "reset" {
+                None
+            } else {
+                match PublicKey::from_str(reward) {
+                    Ok(reward) => Some(reward),
+                    Err(e) => {
+                        println!("Invalid wallet public key '{}': {}", reward, e);
+                        Self::help();
+                        return true;
+                    }
+                }
+            };
+            match reward {
+                Some(reward) => info!("Paying block rewards to {}", reward),
+                None => info!("Paying block rewards to this wallet"),
+            }
+            let request = WalletRequest::SetRewardDestination { reward };
+            self.wallet_response = Some(self.wallet.request(request));
         } else if msg.starts_with("generator ") {
             let subcommand = &msg[10..];
             if subcommand.starts_with("stop") {
@@ -484,7 +798,7 @@ impl ConsoleService {
                 let mut keys = Vec::new();
                 let keys_str = &subcommand[6..];
                 for key in keys_str.split(',') {
-                    let recipient = match PublicKey::try_from_hex(key.trim()) {
+                    let recipient = match PublicKey::from_str(key.trim()) {
                         Ok(r) => r,
                         Err(e) => {
                             println!("Invalid wallet public key '{}': {}", key, e);
@@ -528,18 +842,250 @@ impl ConsoleService {
         } else if msg == "show election" {
             let request = NodeRequest::ElectionInfo {};
             self.node_response = Some(self.node.request(request));
+        } else if msg.starts_with("show leader ") {
+            let args: Result<Vec<u64>, _> = msg[12..]
+                .split_whitespace()
+                .map(|arg| arg.parse())
+                .collect();
+            let (epoch, view_change) = match args.as_ref().map(|args| args.as_slice()) {
+                Ok([epoch, view_change]) if *view_change <= u32::max_value() as u64 => {
+                    (*epoch, *view_change as u32)
+                }
+                _ => {
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = NodeRequest::LeaderSelection { epoch, view_change };
+            self.node_response = Some(self.node.request(request));
+        } else if msg.starts_with("show epoch ") {
+            let epoch = match msg[11..].trim().parse::<u64>() {
+                Ok(epoch) => epoch,
+                Err(_) => {
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = NodeRequest::EpochSummary { epoch };
+            self.node_response = Some(self.node.request(request));
+        } else if msg == "show fee" || msg.starts_with("show fee ") {
+            let target_blocks = match msg[8..].trim() {
+                "" => 1,
+                arg => match arg.parse::<u64>() {
+                    Ok(target_blocks) => target_blocks,
+                    Err(_) => {
+                        Self::help();
+                        return true;
+                    }
+                },
+            };
+            let request = NodeRequest::EstimateFee { target_blocks };
+            self.node_response = Some(self.node.request(request));
         } else if msg == "show escrow" {
             let request = NodeRequest::EscrowInfo {};
             self.node_response = Some(self.node.request(request));
+        } else if msg == "show stakes" {
+            let request = WalletRequest::StakingInfo {};
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("show unlock ") {
+            let utxo = match Hash::try_from_hex(msg[12..].trim()) {
+                Ok(utxo) => utxo,
+                Err(e) => {
+                    println!("Invalid UTXO hash: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = NodeRequest::StakeUnlockHeight { utxo };
+            self.node_response = Some(self.node.request(request));
+        } else if msg.starts_with("show validator ") {
+            let network_pkey = match pbc::PublicKey::try_from_hex(msg[15..].trim()) {
+                Ok(network_pkey) => network_pkey,
+                Err(e) => {
+                    println!("Invalid network public key: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = NodeRequest::ValidatorStake { network_pkey };
+            self.node_response = Some(self.node.request(request));
         } else if msg == "show utxo" {
             let request = WalletRequest::UnspentInfo {};
             self.wallet_response = Some(self.wallet.request(request));
+        } else if msg == "show history" || msg.starts_with("show history ") {
+            let args: Result<Vec<usize>, _> = msg[12..]
+                .split_whitespace()
+                .map(|arg| arg.parse())
+                .collect();
+            let (offset, limit) = match args.as_ref().map(|args| args.as_slice()) {
+                Ok([]) => (0, 50),
+                Ok([offset]) => (*offset, 50),
+                Ok([offset, limit]) => (*offset, *limit),
+                _ => {
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = WalletRequest::HistoryInfo { offset, limit };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("export history ") {
+            let args: Vec<&str> = msg[15..].split_whitespace().collect();
+            let (format, file, direction) = match args.as_slice() {
+                [format, file] => (*format, *file, None),
+                [format, file, "incoming"] => (*format, *file, Some(PaymentDirection::Incoming)),
+                [format, file, "outgoing"] => (*format, *file, Some(PaymentDirection::Outgoing)),
+                _ => {
+                    Self::help();
+                    return true;
+                }
+            };
+            let format = match format {
+                "csv" => ExportFormat::Csv,
+                "json" => ExportFormat::Json,
+                _ => {
+                    Self::help();
+                    return true;
+                }
+            };
+            self.export_file = Some(PathBuf::from(file));
+            let request = WalletRequest::ExportHistory {
+                format,
+                from: None,
+                to: None,
+                direction,
+            };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("show invoice ") {
+            let invoice_id = msg[13..].trim();
+            if invoice_id.is_empty() {
+                Self::help();
+                return true;
+            }
+            let invoice_id = invoice_id.to_string();
+            let request = WalletRequest::InvoiceInfo { invoice_id };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("watch pkey ") {
+            let pkey = match PublicKey::from_str(msg[11..].trim()) {
+                Ok(pkey) => pkey,
+                Err(e) => {
+                    println!("Invalid wallet public key: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = WalletRequest::WatchPublicKey { pkey };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("watch utxo ") {
+            let args: Vec<&str> = msg[11..].split_whitespace().collect();
+            let (utxo, amount) = match &args[..] {
+                [utxo] => (*utxo, None),
+                [utxo, amount] => (*utxo, Some(*amount)),
+                _ => {
+                    Self::help();
+                    return true;
+                }
+            };
+            let utxo = match Hash::try_from_hex(utxo) {
+                Ok(utxo) => utxo,
+                Err(e) => {
+                    println!("Invalid UTXO hash: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let amount = match amount.map(parse_money) {
+                None => None,
+                Some(Ok(amount)) => Some(amount),
+                Some(Err(e)) => {
+                    println!("{}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = WalletRequest::WatchOutput { utxo, amount };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg == "show deposits" {
+            let request = WalletRequest::DepositsInfo {};
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg == "show policy" {
+            let request = WalletRequest::SpendPolicy {};
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("approve ") {
+            let unsigned_hash = match Hash::try_from_hex(msg[8..].trim()) {
+                Ok(unsigned_hash) => unsigned_hash,
+                Err(e) => {
+                    println!("Invalid transaction hash: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = WalletRequest::ApprovePayment { unsigned_hash };
+            self.wallet_response = Some(self.wallet.request(request));
         } else if msg == "show recovery" {
             let request = WalletRequest::GetRecovery {};
             self.wallet_response = Some(self.wallet.request(request));
+        } else if msg == "passwd" {
+            let request = WalletRequest::ChangePassword {};
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("export key ") {
+            let recovery_pkey = match PublicKey::from_str(msg[11..].trim()) {
+                Ok(recovery_pkey) => recovery_pkey,
+                Err(e) => {
+                    println!("Invalid recovery public key: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = WalletRequest::ExportWalletKey { recovery_pkey };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("freeze ") || msg.starts_with("unfreeze ") {
+            let (freeze, utxo) = if msg.starts_with("freeze ") {
+                (true, &msg[7..])
+            } else {
+                (false, &msg[9..])
+            };
+            let utxo = match Hash::try_from_hex(utxo.trim()) {
+                Ok(utxo) => utxo,
+                Err(e) => {
+                    println!("Invalid UTXO hash: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = if freeze {
+                WalletRequest::FreezeOutput { utxo }
+            } else {
+                WalletRequest::UnfreezeOutput { utxo }
+            };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("show proof ") {
+            let utxo = match Hash::try_from_hex(msg[11..].trim()) {
+                Ok(utxo) => utxo,
+                Err(e) => {
+                    println!("Invalid UTXO hash: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = WalletRequest::PaymentProof { utxo };
+            self.wallet_response = Some(self.wallet.request(request));
+        } else if msg.starts_with("inspect ") {
+            let query = match InspectorQuery::from_str(&msg[8..]) {
+                Ok(query) => query,
+                Err(e) => {
+                    println!("Invalid query: {}", e);
+                    Self::help();
+                    return true;
+                }
+            };
+            let request = NodeRequest::Inspect { query };
+            self.node_response = Some(self.node.request(request));
         } else if msg == "db pop block" {
             self.node.pop_block();
             return true;
+        } else if msg == "db repair" {
+            let request = NodeRequest::RepairDatabase {};
+            self.node_response = Some(self.node.request(request));
         } else {
             Self::help();
             return true;
@@ -547,14 +1093,40 @@ impl ConsoleService {
         return false; // keep stdin parked until result is received.
     }
 
-    fn on_exit(&self) {
-        std::process::exit(0);
+    fn on_exit(&self) -> Poll<(), ()> {
+        self.shutdown.trigger();
+        Ok(Async::Ready(()))
     }
 
     fn on_node_response(&mut self, info: NodeResponse) {
         let output = match info {
             NodeResponse::EscrowInfo(info) => serde_yaml::to_string(&[info]),
             NodeResponse::ElectionInfo(info) => serde_yaml::to_string(&[info]),
+            NodeResponse::LeaderSelection(info) => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::OutputProof { .. } => serde_yaml::to_string(&[info]),
+            NodeResponse::Inspect(info) => serde_yaml::to_string(&[info]),
+            NodeResponse::Explore(info) => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::TransactionStatus { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::StakesByWallet { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::StakeUnlockHeight { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::ValidatorStake { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::RoutingTable { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::QueryJournal { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::PeerStatuses { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::FastConfirmation { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::FastConfirmationVerified { .. } => serde_yaml::to_string(&[info]),
+            NodeResponse::FeeEstimate(info) => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::ViewChangeInfo { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::ViewChangeProofSubmitted { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::ReserveProofValidated { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::BlockTemplate { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::BlockSubmitted { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::EpochSummary { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::Status(_) => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::RandomBeacon { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::DatabaseRepaired { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::FinalityInfo { .. } => serde_yaml::to_string(&[info]),
+            info @ NodeResponse::Error { .. } => serde_yaml::to_string(&[info]),
         }
         .map_err(|_| fmt::Error)
         .unwrap();
@@ -575,16 +1147,84 @@ impl ConsoleService {
                     }
                 }
             }
+            WalletNotification::Confirmed(_) => {}
             WalletNotification::Spent(_) => {}
             WalletNotification::Staked(_) => {}
             WalletNotification::Unstaked(_) => {}
             WalletNotification::BalanceChanged { balance } => {
                 info!("Balance is {} STG", format_money(balance));
             }
+            WalletNotification::DepositReceived(info) => {
+                info!(
+                    "Deposit received: utxo={}, height={}",
+                    info.utxo, info.height
+                );
+            }
+            WalletNotification::DepositConfirmed(info) => {
+                info!(
+                    "Deposit confirmed: utxo={}, confirmations={}",
+                    info.utxo, info.confirmations
+                );
+            }
+            WalletNotification::DepositSpent(info) => {
+                info!("Deposit spent: utxo={}", info.utxo);
+            }
+            WalletNotification::DepositReverted(info) => {
+                warn!("Deposit reverted: utxo={}", info.utxo);
+            }
+            WalletNotification::InvoicePaid(issued) => {
+                info!(
+                    "Invoice paid: invoice_id={}, received={} STG",
+                    issued.invoice.invoice_id,
+                    format_money(issued.received)
+                );
+            }
+            WalletNotification::PaymentsFinalized { height, entries } => {
+                info!(
+                    "Payments became irreversible: height={}, count={}",
+                    height,
+                    entries.len()
+                );
+            }
+            WalletNotification::TransactionConflict(conflict) => {
+                warn!(
+                    "Transaction {} conflicts with {}, committed={}",
+                    conflict.tx_hash, conflict.conflicting_tx_hash, conflict.committed
+                );
+            }
         }
     }
 
     fn on_wallet_response(&mut self, response: WalletResponse) {
+        if let Some(file) = self.export_file.take() {
+            if let WalletResponse::UnsignedTransaction {
+                unsigned_hash,
+                unsigned,
+            } = &response
+            {
+                match unsigned
+                    .into_buffer()
+                    .and_then(|buf| fs::write(&file, buf).map_err(Error::from))
+                {
+                    Ok(()) => println!(
+                        "Saved unsigned transaction {} to '{}'",
+                        unsigned_hash,
+                        file.display()
+                    ),
+                    Err(e) => println!("Failed to save '{}': {}", file.display(), e),
+                }
+                self.stdin_th.thread().unpark();
+                return;
+            }
+            if let WalletResponse::HistoryExported { count, content, .. } = &response {
+                match fs::write(&file, content) {
+                    Ok(()) => println!("Saved {} payments to '{}'", count, file.display()),
+                    Err(e) => println!("Failed to save '{}': {}", file.display(), e),
+                }
+                self.stdin_th.thread().unpark();
+                return;
+            }
+        }
         let output = serde_yaml::to_string(&[response])
             .map_err(|_| fmt::Error)
             .unwrap();
@@ -606,7 +1246,7 @@ impl Future for ConsoleService {
                         self.stdin_th.thread().unpark();
                     }
                 }
-                Ok(Async::Ready(None)) => self.on_exit(),
+                Ok(Async::Ready(None)) => return self.on_exit(),
                 Ok(Async::NotReady) => break, // fall through
                 Err(()) => panic!(),
             }
@@ -617,7 +1257,7 @@ impl Future for ConsoleService {
                 Ok(Async::Ready(Some(notification))) => {
                     self.on_wallet_notification(notification);
                 }
-                Ok(Async::Ready(None)) => self.on_exit(),
+                Ok(Async::Ready(None)) => return self.on_exit(),
                 Ok(Async::NotReady) => break, // fall through
                 Err(()) => panic!("Wallet failure"),
             }
@@ -654,7 +1294,7 @@ impl Future for ConsoleService {
                         String::from_utf8_lossy(&msg.data)
                     );
                 }
-                Ok(Async::Ready(None)) => self.on_exit(),
+                Ok(Async::Ready(None)) => return self.on_exit(),
                 Ok(Async::NotReady) => break, // fall through
                 Err(()) => panic!("Unicast failure"),
             }
@@ -140,7 +140,7 @@ impl Generator {
     /// Wait for wallet initialization.
     fn handle_wait_init(&mut self, info: WalletResponse) {
         match info {
-            WalletResponse::BalanceInfo { balance } => self.try_init(balance),
+            WalletResponse::BalanceInfo { balance, .. } => self.try_init(balance),
             WalletResponse::Error { error } => {
                 debug!("Error on requesting balance: error:{}", error);
                 self.state =
@@ -19,70 +19,48 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod chains;
 pub mod config;
 pub mod console;
 pub mod consts;
 pub mod generator;
+pub mod logging;
 pub mod money;
+pub mod shutdown;
+pub mod telemetry;
+pub mod webhooks;
 
+use crate::logging::Logging;
 use failure::format_err;
 use failure::Error;
 use hyper::{Body, Request, Response};
 use log::*;
-use log4rs::append::console::ConsoleAppender;
-use log4rs::config::{Appender, Config as LogConfig, Logger, Root};
-use log4rs::encode::pattern::PatternEncoder;
-use log4rs::{Error as LogError, Handle as LogHandle};
+use log4rs::Error as LogError;
 use prometheus::{self, Encoder};
 use resolve::{config::DnsConfig, record::Srv, resolver};
 use std::path::Path;
 use std::time::SystemTime;
-use stegos_blockchain::{Block, MacroBlock};
+use stegos_blockchain::{Block, GenesisSpec, MacroBlock};
 use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
 use stegos_serialization::traits::*;
 
-pub fn initialize_logger(cfg: &config::Config) -> Result<LogHandle, LogError> {
-    // Try to load log4rs config file
-    let path = Path::new(&cfg.general.log4rs_config);
-    if !cfg.general.log4rs_config.is_empty() && path.is_file() {
-        match log4rs::load_config_file(path, Default::default()) {
-            Ok(config) => return Ok(log4rs::init_config(config)?),
-            Err(e) => {
-                error!("Failed to read log4rs config file: {}", e);
-                println!("Failed to read log4rs config file: {}", e);
-            }
-        }
-    };
-
-    let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)(local)} {h({l})} [{M}] {m}{n}",
-        )))
-        .build();
-    let config = LogConfig::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .logger(Logger::builder().build("stegos", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_blockchain", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_crypto", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_consensus", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_keychain", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_node", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_network", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_txpool", LevelFilter::Info))
-        .logger(Logger::builder().build("stegos_wallet", LevelFilter::Info))
-        .build(Root::builder().appender("stdout").build(LevelFilter::Warn))
-        .expect("console logger should never fail");
-
-    Ok(log4rs::init_config(config)?)
+pub fn initialize_logger(cfg: &config::Config) -> Result<Logging, LogError> {
+    Logging::init(cfg)
 }
 
 pub fn initialize_genesis(cfg: &config::Config) -> Result<MacroBlock, Error> {
-    let genesis: &[u8] = match cfg.general.chain.as_ref() {
-        "dev" => include_bytes!("../chains/dev/genesis.bin"),
-        "testnet" => include_bytes!("../chains/testnet/genesis.bin"),
-        "devnet" => include_bytes!("../chains/devnet/genesis.bin"),
-        chain @ _ => {
-            return Err(format_err!("Unknown chain: {}", chain));
+    if !cfg.general.genesis_file.is_empty() {
+        return load_genesis(cfg);
+    }
+    let genesis: &[u8] = match chains::find(&cfg.general.chain) {
+        Some(spec) => spec.genesis,
+        None => {
+            return Err(format_err!(
+                "Unknown chain: {}, built-in chains: {}",
+                cfg.general.chain,
+                chains::names().join(", ")
+            ));
         }
     };
     let genesis = Block::from_buffer(genesis).expect("Invalid genesis");
@@ -92,6 +70,29 @@ pub fn initialize_genesis(cfg: &config::Config) -> Result<MacroBlock, Error> {
     Ok(genesis)
 }
 
+/// Loads the genesis block from a signed genesis specification.
+fn load_genesis(cfg: &config::Config) -> Result<MacroBlock, Error> {
+    let authority = pbc::PublicKey::try_from_hex(&cfg.general.genesis_authority)
+        .map_err(|e| format_err!("Invalid genesis authority: {}", e))?;
+    let path = Path::new(&cfg.general.genesis_file);
+    let signature_path = format!("{}.sig", cfg.general.genesis_file);
+    let spec = GenesisSpec::load(path, Path::new(&signature_path), &authority)?;
+    if spec.chain_id != cfg.general.chain {
+        return Err(format_err!(
+            "Genesis specification is for another chain: expected={}, got={}",
+            cfg.general.chain,
+            spec.chain_id
+        ));
+    }
+    let genesis = spec.genesis_block()?;
+    let hash = Hash::digest(&genesis);
+    info!(
+        "Using genesis={} for '{}' chain from {}",
+        hash, cfg.general.chain, cfg.general.genesis_file
+    );
+    Ok(genesis)
+}
+
 pub fn resolve_pool(cfg: &mut config::Config) -> Result<(), Error> {
     if cfg.network.seed_pool == "" {
         return Ok(());
@@ -0,0 +1,254 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use failure::{format_err, Error};
+use log::*;
+use log4rs::append::console::ConsoleAppender;
+use log4rs::config::{Appender, Config as LogConfig, Logger, Root};
+use log4rs::encode::json::JsonEncoder;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::Encode;
+use log4rs::{Error as LogError, Handle};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use stegos_api::LogControl;
+
+/// Log targets enabled by default.
+const DEFAULT_TARGETS: &[&str] = &[
+    "stegos",
+    "stegos_blockchain",
+    "stegos_crypto",
+    "stegos_consensus",
+    "stegos_keychain",
+    "stegos_node",
+    "stegos_network",
+    "stegos_txpool",
+    "stegos_wallet",
+];
+
+/// Output format of the console logger.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line, suitable for ELK and similar collectors.
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+/// Current log levels.
+#[derive(Debug, Clone)]
+struct LogLevels {
+    /// Level of the root logger.
+    root: LevelFilter,
+    /// Levels set by configuration.
+    defaults: BTreeMap<String, LevelFilter>,
+    /// Levels changed at runtime.
+    overrides: BTreeMap<String, LevelFilter>,
+}
+
+impl LogLevels {
+    fn new(cfg: &BTreeMap<String, String>) -> Result<Self, Error> {
+        let mut defaults: BTreeMap<String, LevelFilter> = DEFAULT_TARGETS
+            .iter()
+            .map(|target| (target.to_string(), LevelFilter::Info))
+            .collect();
+        for (target, level) in cfg {
+            defaults.insert(target.clone(), parse_level(level)?);
+        }
+        Ok(LogLevels {
+            root: LevelFilter::Warn,
+            defaults,
+            overrides: BTreeMap::new(),
+        })
+    }
+
+    /// Returns effective levels of all configured targets.
+    fn effective(&self) -> BTreeMap<String, LevelFilter> {
+        let mut levels = self.defaults.clone();
+        levels.extend(self.overrides.iter().map(|(t, l)| (t.clone(), *l)));
+        levels
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, Error> {
+    LevelFilter::from_str(level).map_err(|_| format_err!("Invalid log level: {}", level))
+}
+
+/// Builds log4rs configuration for the console logger.
+fn build_config(format: LogFormat, levels: &LogLevels) -> LogConfig {
+    let encoder: Box<dyn Encode> = match format {
+        LogFormat::Text => Box::new(PatternEncoder::new(
+            "{d(%Y-%m-%d %H:%M:%S)(local)} {h({l})} [{M}] {m}{n}",
+        )),
+        LogFormat::Json => Box::new(JsonEncoder::new()),
+    };
+    let stdout = ConsoleAppender::builder().encoder(encoder).build();
+    let mut config =
+        LogConfig::builder().appender(Appender::builder().build("stdout", Box::new(stdout)));
+    for (target, level) in levels.effective() {
+        config = config.logger(Logger::builder().build(target, level));
+    }
+    config
+        .build(Root::builder().appender("stdout").build(levels.root))
+        .expect("console logger should never fail")
+}
+
+/// Node-wide logging subsystem.
+pub struct Logging {
+    /// log4rs handle used to apply new configuration.
+    handle: Handle,
+    /// Output format.
+    format: LogFormat,
+    /// Current levels, None if logging is configured by log4rs config file.
+    levels: Option<Mutex<LogLevels>>,
+}
+
+impl Logging {
+    /// Initializes the global logger.
+    pub fn init(cfg: &crate::config::Config) -> Result<Logging, LogError> {
+        let format = cfg.general.log_format;
+
+        // Try to load log4rs config file
+        let path = Path::new(&cfg.general.log4rs_config);
+        if !cfg.general.log4rs_config.is_empty() && path.is_file() {
+            match log4rs::load_config_file(path, Default::default()) {
+                Ok(config) => {
+                    let handle = log4rs::init_config(config)?;
+                    return Ok(Logging {
+                        handle,
+                        format,
+                        levels: None,
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to read log4rs config file: {}", e);
+                    println!("Failed to read log4rs config file: {}", e);
+                }
+            }
+        };
+
+        let levels = match LogLevels::new(&cfg.general.log_levels) {
+            Ok(levels) => levels,
+            Err(e) => {
+                println!("Ignoring log levels from configuration: {}", e);
+                LogLevels::new(&BTreeMap::new()).expect("defaults are valid")
+            }
+        };
+        let handle = log4rs::init_config(build_config(format, &levels))?;
+        Ok(Logging {
+            handle,
+            format,
+            levels: Some(Mutex::new(levels)),
+        })
+    }
+
+    fn update<F>(&self, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut LogLevels) -> Result<(), Error>,
+    {
+        let levels = match self.levels {
+            Some(ref levels) => levels,
+            None => {
+                return Err("Log levels are managed by log4rs config file".to_string());
+            }
+        };
+        let mut levels = levels.lock().unwrap();
+        f(&mut levels).map_err(|e| e.to_string())?;
+        self.handle.set_config(build_config(self.format, &levels));
+        Ok(())
+    }
+}
+
+impl LogControl for Logging {
+    fn set_level(&self, target: &str, level: &str) -> Result<(), String> {
+        self.update(|levels| {
+            let level = parse_level(level)?;
+            if target.is_empty() {
+                levels.root = level;
+            } else {
+                levels.overrides.insert(target.to_string(), level);
+            }
+            info!("Changed log level: target={}, level={}", target, level);
+            Ok(())
+        })
+    }
+
+    fn reset_level(&self, target: &str) -> Result<(), String> {
+        self.update(|levels| {
+            if levels.overrides.remove(target).is_none() {
+                return Err(format_err!("Log level is not overridden: {}", target));
+            }
+            info!("Reset log level: target={}", target);
+            Ok(())
+        })
+    }
+
+    fn levels(&self) -> (String, BTreeMap<String, String>) {
+        match self.levels {
+            Some(ref levels) => {
+                let levels = levels.lock().unwrap();
+                let effective = levels
+                    .effective()
+                    .into_iter()
+                    .map(|(t, l)| (t, l.to_string()))
+                    .collect();
+                (levels.root.to_string(), effective)
+            }
+            None => (max_level().to_string(), BTreeMap::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_levels() {
+        let mut cfg = BTreeMap::new();
+        cfg.insert("stegos_network::kad".to_string(), "debug".to_string());
+        let mut levels = LogLevels::new(&cfg).unwrap();
+        assert_eq!(levels.root, LevelFilter::Warn);
+        assert_eq!(levels.defaults["stegos_node"], LevelFilter::Info);
+        assert_eq!(levels.defaults["stegos_network::kad"], LevelFilter::Debug);
+
+        levels
+            .overrides
+            .insert("stegos_node".to_string(), LevelFilter::Trace);
+        let effective = levels.effective();
+        assert_eq!(effective["stegos_node"], LevelFilter::Trace);
+        assert_eq!(effective["stegos_wallet"], LevelFilter::Info);
+
+        cfg.insert("stegos".to_string(), "loud".to_string());
+        assert!(LogLevels::new(&cfg).is_err());
+        assert_eq!(parse_level("TRACE").unwrap(), LevelFilter::Trace);
+    }
+}
@@ -0,0 +1,93 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Graceful shutdown of all services.
+
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::{future, Future, Stream};
+use log::*;
+use std::time::Duration;
+use stegos_network::Network;
+use stegos_node::Node;
+use tokio_timer::Timeout;
+
+/// A token to request a graceful shutdown of the node.
+#[derive(Clone, Debug)]
+pub struct Shutdown(UnboundedSender<()>);
+
+impl Shutdown {
+    /// Creates a new token and a stream of shutdown requests.
+    pub fn new() -> (Shutdown, UnboundedReceiver<()>) {
+        let (tx, rx) = unbounded();
+        (Shutdown(tx), rx)
+    }
+
+    /// Requests a graceful shutdown.
+    pub fn trigger(&self) {
+        self.0.unbounded_send(()).ok(); // ignore errors.
+    }
+}
+
+///
+/// Waits for a shutdown request or SIGINT and stops services in order:
+///
+/// 1. Node stops accepting transactions, blocks and consensus messages,
+///    saves the consensus state and flushes the blockchain storage.
+/// 2. Network saves the routing table and closes the swarm.
+///
+/// The whole procedure is bounded by `timeout`.
+///
+pub fn shutdown_service(
+    requests: UnboundedReceiver<()>,
+    node: Node,
+    network: Network,
+    timeout: Duration,
+) -> impl Future<Item = (), Error = ()> {
+    let signals = tokio_signal::ctrl_c()
+        .flatten_stream()
+        .map(|()| info!("Received interrupt signal"))
+        .map_err(|e| error!("Failed to handle signals: {}", e));
+    requests
+        .select(signals)
+        .into_future()
+        .map_err(drop)
+        .and_then(move |_| {
+            info!("Shutting down: timeout={}s", timeout.as_secs());
+            let stopped = node
+                .shutdown()
+                .map_err(|_e| error!("Node has gone"))
+                .and_then(move |()| {
+                    info!("Node stopped");
+                    future::result(network.shutdown())
+                        .and_then(|rx| rx.map_err(|e| e.into()))
+                        .map_err(|e| error!("Failed to stop network: {}", e))
+                })
+                .map(|()| info!("Network stopped"));
+            Timeout::new(stopped, timeout).then(|result| {
+                if let Err(e) = result {
+                    if e.is_elapsed() {
+                        warn!("Shutdown deadline exceeded");
+                    }
+                }
+                Ok(())
+            })
+        })
+}
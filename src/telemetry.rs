@@ -0,0 +1,251 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Opt-in telemetry.
+//!
+//! Periodically reports the status of the node to a telemetry server.
+//! Reports are signed by the network key, so the server can tell validators apart.
+
+use failure::Error;
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll, Stream};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+use stegos_node::{Node, NodeRequest, NodeResponse, NodeStatus};
+use tokio_timer::Interval;
+
+/// HTTP header with the network key of the node.
+pub const NETWORK_KEY_HEADER: &'static str = "X-Stegos-Network-Key";
+/// HTTP header with the signature of the request body.
+pub const SIGNATURE_HEADER: &'static str = "X-Stegos-Signature";
+
+/// Telemetry configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// URL of the telemetry server, telemetry is disabled if empty.
+    pub endpoint: String,
+    /// Name of the node shown by the telemetry server.
+    pub name: String,
+    /// How often to send reports (secs).
+    pub interval: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            endpoint: "".to_string(),
+            name: "".to_string(),
+            interval: 60,
+        }
+    }
+}
+
+/// Status report sent to the telemetry server as JSON.
+#[derive(Serialize, Debug, Clone)]
+pub struct TelemetryReport {
+    pub name: String,
+    pub version: String,
+    pub chain: String,
+    /// UNIX timestamp in seconds.
+    pub timestamp: u64,
+    /// Height, peers, consensus participation and resources of the node.
+    pub status: NodeStatus,
+}
+
+impl TelemetryReport {
+    /// Serialize the report and sign it by the network key.
+    /// Returns the request body and the signature of its hash.
+    pub fn sign(&self, network_skey: &pbc::SecretKey) -> Result<(Vec<u8>, pbc::Signature), Error> {
+        let body = serde_json::to_vec(self)?;
+        let sig = pbc::sign_hash(&Hash::digest(&body), network_skey);
+        Ok((body, sig))
+    }
+}
+
+/// Sends reports to the telemetry server.
+pub struct TelemetryService {
+    cfg: TelemetryConfig,
+    endpoint: Uri,
+    version: String,
+    chain: String,
+    network_pkey: pbc::PublicKey,
+    network_skey: pbc::SecretKey,
+    node: Node,
+    client: Client<HttpConnector>,
+    /// Report timer.
+    timer: Interval,
+    /// Pending request of the node status.
+    status_request: Option<oneshot::Receiver<NodeResponse>>,
+}
+
+impl TelemetryService {
+    pub fn new(
+        cfg: TelemetryConfig,
+        version: String,
+        chain: String,
+        network_pkey: pbc::PublicKey,
+        network_skey: pbc::SecretKey,
+        node: Node,
+    ) -> Result<Self, Error> {
+        let endpoint: Uri = cfg.endpoint.parse()?;
+        info!(
+            "Sending telemetry: endpoint={}, interval={}s",
+            endpoint, cfg.interval
+        );
+        let client = Client::new();
+        let timer = Interval::new_interval(Duration::from_secs(cfg.interval));
+        Ok(TelemetryService {
+            cfg,
+            endpoint,
+            version,
+            chain,
+            network_pkey,
+            network_skey,
+            node,
+            client,
+            timer,
+            status_request: None,
+        })
+    }
+
+    fn send_report(&self, status: NodeStatus) -> Result<(), Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("valid time")
+            .as_secs();
+        let report = TelemetryReport {
+            name: self.cfg.name.clone(),
+            version: self.version.clone(),
+            chain: self.chain.clone(),
+            timestamp,
+            status,
+        };
+        let (body, sig) = report.sign(&self.network_skey)?;
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.endpoint.clone())
+            .header("Content-Type", "application/json")
+            .header(NETWORK_KEY_HEADER, self.network_pkey.to_hex())
+            .header(SIGNATURE_HEADER, sig.to_hex())
+            .body(Body::from(body))?;
+        let endpoint = self.endpoint.clone();
+        let response = self
+            .client
+            .request(request)
+            .map(move |response| {
+                if !response.status().is_success() {
+                    warn!(
+                        "Telemetry report rejected: endpoint={}, status={}",
+                        endpoint,
+                        response.status()
+                    );
+                }
+            })
+            .map_err(|e| warn!("Failed to send telemetry report: {}", e));
+        tokio::spawn(response);
+        debug!("Sent telemetry report: height={}", report.status.height);
+        Ok(())
+    }
+}
+
+impl Future for TelemetryService {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.timer.poll() {
+                Ok(Async::Ready(Some(_instant))) => {
+                    // Skip the report if the node hasn't answered the previous request yet.
+                    if self.status_request.is_none() {
+                        let rx = self.node.request(NodeRequest::Status {});
+                        self.status_request = Some(rx);
+                    }
+                }
+                Ok(Async::Ready(None)) => unreachable!(), // never happens
+                Ok(Async::NotReady) => break,
+                Err(e) => {
+                    // Ignore transient timer errors.
+                    warn!("Telemetry timer error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(mut rx) = self.status_request.take() {
+            match rx.poll() {
+                Ok(Async::Ready(NodeResponse::Status(status))) => {
+                    if let Err(e) = self.send_report(status) {
+                        error!("Failed to create telemetry report: {}", e);
+                    }
+                }
+                Ok(Async::Ready(_)) => unreachable!(),
+                Ok(Async::NotReady) => self.status_request = Some(rx),
+                Err(_) => return Ok(Async::Ready(())), // the node is stopped
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use stegos_node::{PeerCounts, ResourceUsage};
+
+    #[test]
+    fn signed_report() {
+        let (skey, pkey) = pbc::make_random_keys();
+        let report = TelemetryReport {
+            name: "test".to_string(),
+            version: "0.0.0".to_string(),
+            chain: "dev".to_string(),
+            timestamp: 0,
+            status: NodeStatus {
+                network_pkey: pkey,
+                epoch: 1,
+                height: 10,
+                view_change: 0,
+                last_block_hash: Hash::digest("block"),
+                last_macro_block_height: 5,
+                last_macro_block_hash: Hash::digest("macro"),
+                synchronized: true,
+                validator: None,
+                peers: PeerCounts::default(),
+                mempool_transactions: 0,
+                safe_mode: false,
+                resources: ResourceUsage::current(Duration::from_secs(1)),
+            },
+        };
+        let (body, sig) = report.sign(&skey).unwrap();
+        pbc::check_hash(&Hash::digest(&body), &sig, &pkey).expect("valid signature");
+        let (_skey2, pkey2) = pbc::make_random_keys();
+        assert!(pbc::check_hash(&Hash::digest(&body), &sig, &pkey2).is_err());
+    }
+}
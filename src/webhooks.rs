@@ -0,0 +1,369 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Wallet webhooks.
+//!
+//! Posts payment events of the wallet to configured URLs as JSON.
+//! The body is authenticated by HMAC-SHA3-256 with the secret shared with the receiver.
+//! Failed deliveries are retried with exponential backoff.
+
+use failure::Error;
+use futures::future::{self, Loop};
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::{Async, Future, Poll, Stream};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::{Hash, Hasher};
+use stegos_wallet::{PaymentInfo, Wallet, WalletNotification};
+use tokio_timer::Delay;
+
+/// HTTP header with the event type.
+pub const EVENT_HEADER: &'static str = "X-Stegos-Webhook-Event";
+/// HTTP header with HMAC-SHA3-256 of the request body, hex-encoded.
+pub const SIGNATURE_HEADER: &'static str = "X-Stegos-Webhook-Signature";
+
+/// Maximal exponent of the retry backoff.
+const MAX_BACKOFF_SHIFT: u32 = 10;
+
+/// Webhooks configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    /// Receivers of wallet events, webhooks are disabled if empty.
+    pub hooks: Vec<WebhookConfig>,
+    /// Maximal number of delivery attempts for each event.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt (secs).
+    pub retry_delay: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig {
+            hooks: Vec::new(),
+            max_attempts: 5,
+            retry_delay: 5,
+        }
+    }
+}
+
+/// A receiver of wallet events.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// URL to post events to.
+    pub url: String,
+    /// Secret shared with the receiver, used to authenticate events.
+    pub secret: String,
+    /// Events to post, all events if empty.
+    pub events: Vec<WebhookEvent>,
+}
+
+/// Wallet events posted to webhooks.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A payment was received.
+    PaymentReceived,
+    /// A received payment reached `wallet.deposit_confirmations` blocks.
+    PaymentConfirmed,
+    /// A payment was spent.
+    PaymentSpent,
+}
+
+impl WebhookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::PaymentReceived => "payment_received",
+            WebhookEvent::PaymentConfirmed => "payment_confirmed",
+            WebhookEvent::PaymentSpent => "payment_spent",
+        }
+    }
+}
+
+/// Body of the request sent to webhooks as JSON.
+#[derive(Serialize, Debug, Clone)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    /// UNIX timestamp in seconds.
+    pub timestamp: u64,
+    /// Public key of the wallet.
+    pub wallet: PublicKey,
+    pub payment: PaymentInfo,
+}
+
+impl WebhookPayload {
+    /// Convert a wallet notification into a payload, if it is posted to webhooks.
+    pub fn from_notification(
+        wallet: PublicKey,
+        notification: WalletNotification,
+    ) -> Option<WebhookPayload> {
+        let (event, payment) = match notification {
+            WalletNotification::Received(info) => (WebhookEvent::PaymentReceived, info),
+            WalletNotification::Confirmed(info) => (WebhookEvent::PaymentConfirmed, info),
+            WalletNotification::Spent(info) => (WebhookEvent::PaymentSpent, info),
+            _ => return None,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("valid time")
+            .as_secs();
+        Some(WebhookPayload {
+            event,
+            timestamp,
+            wallet,
+            payment,
+        })
+    }
+}
+
+/// HMAC (RFC 2104) with SHA3-256.
+pub fn hmac_sha3_256(key: &[u8], message: &[u8]) -> Hash {
+    // Block size of SHA3-256 in bytes.
+    const BLOCK_SIZE: usize = 136;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        let mut hasher = Hasher::new();
+        hasher.input(key);
+        let key = hasher.result();
+        block[..key.base_vector().len()].copy_from_slice(key.base_vector());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Hasher::new();
+    inner.input(block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    inner.input(message);
+    let mut outer = Hasher::new();
+    outer.input(block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(inner.result().base_vector());
+    outer.result()
+}
+
+/// Delay before the retry after `attempt` failed attempts.
+fn backoff(retry_delay: Duration, attempt: u32) -> Duration {
+    retry_delay * (1 << (attempt - 1).min(MAX_BACKOFF_SHIFT))
+}
+
+/// A configured receiver of wallet events.
+struct Webhook {
+    uri: Uri,
+    secret: Vec<u8>,
+    events: Vec<WebhookEvent>,
+}
+
+impl Webhook {
+    fn accepts(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Posts wallet events to webhooks.
+pub struct WebhookService {
+    hooks: Vec<Webhook>,
+    max_attempts: u32,
+    retry_delay: Duration,
+    wallet_pkey: PublicKey,
+    client: Client<HttpConnector>,
+    notifications: UnboundedReceiver<WalletNotification>,
+}
+
+impl WebhookService {
+    pub fn new(cfg: WebhooksConfig, wallet_pkey: PublicKey, wallet: Wallet) -> Result<Self, Error> {
+        let mut hooks = Vec::with_capacity(cfg.hooks.len());
+        for hook in cfg.hooks {
+            let uri: Uri = hook.url.parse()?;
+            info!(
+                "Posting wallet events: url={}, events={:?}",
+                uri, hook.events
+            );
+            hooks.push(Webhook {
+                uri,
+                secret: hook.secret.into_bytes(),
+                events: hook.events,
+            });
+        }
+        let client = Client::new();
+        let notifications = wallet.subscribe();
+        Ok(WebhookService {
+            hooks,
+            max_attempts: cfg.max_attempts.max(1),
+            retry_delay: Duration::from_secs(cfg.retry_delay),
+            wallet_pkey,
+            client,
+            notifications,
+        })
+    }
+
+    fn on_notification(&self, notification: WalletNotification) -> Result<(), Error> {
+        let payload = match WebhookPayload::from_notification(self.wallet_pkey, notification) {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        let body = serde_json::to_vec(&payload)?;
+        for hook in &self.hooks {
+            if !hook.accepts(payload.event) {
+                continue;
+            }
+            let signature = hmac_sha3_256(&hook.secret, &body);
+            let delivery = deliver(
+                self.client.clone(),
+                hook.uri.clone(),
+                payload.event,
+                body.clone(),
+                signature,
+                self.max_attempts,
+                self.retry_delay,
+            );
+            tokio::spawn(delivery);
+        }
+        Ok(())
+    }
+}
+
+/// Post the event, retrying with exponential backoff until it is accepted.
+fn deliver(
+    client: Client<HttpConnector>,
+    uri: Uri,
+    event: WebhookEvent,
+    body: Vec<u8>,
+    signature: Hash,
+    max_attempts: u32,
+    retry_delay: Duration,
+) -> impl Future<Item = (), Error = ()> {
+    future::loop_fn(1u32, move |attempt| {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri.clone())
+            .header("Content-Type", "application/json")
+            .header(EVENT_HEADER, event.name())
+            .header(SIGNATURE_HEADER, signature.to_hex())
+            .body(Body::from(body.clone()))
+            .expect("valid request");
+        let uri = uri.clone();
+        client.request(request).then(move |result| {
+            let error = match result {
+                Ok(ref response) if response.status().is_success() => {
+                    debug!("Posted wallet event: url={}, event={:?}", uri, event);
+                    return future::Either::A(future::ok(Loop::Break(())));
+                }
+                Ok(response) => format!("status={}", response.status()),
+                Err(e) => e.to_string(),
+            };
+            if attempt >= max_attempts {
+                error!(
+                    "Failed to post wallet event: url={}, event={:?}, attempts={}, error={}",
+                    uri, event, attempt, error
+                );
+                return future::Either::A(future::ok(Loop::Break(())));
+            }
+            let delay = backoff(retry_delay, attempt);
+            warn!(
+                "Failed to post wallet event, retrying: url={}, event={:?}, attempt={}, delay={:?}, error={}",
+                uri, event, attempt, delay, error
+            );
+            let retry = Delay::new(Instant::now() + delay)
+                .then(move |_| Ok::<_, ()>(Loop::Continue(attempt + 1)));
+            future::Either::B(retry)
+        })
+    })
+}
+
+impl Future for WebhookService {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.notifications.poll() {
+                Ok(Async::Ready(Some(notification))) => {
+                    if let Err(e) = self.on_notification(notification) {
+                        error!("Failed to create webhook payload: {}", e);
+                    }
+                }
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())), // the wallet is stopped
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(()) => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stegos_blockchain::PaymentPayloadData;
+
+    #[test]
+    fn hmac() {
+        let mac = hmac_sha3_256(b"secret", br#"{"event":"test"}"#);
+        assert_eq!(
+            mac.to_hex(),
+            "986a9755b6ce792866157a23c74e6d201132bd5e453ac56dfb73b2f32901da74"
+        );
+        // Keys longer than the block are hashed first.
+        let mac = hmac_sha3_256(&[b'k'; 200], b"body");
+        assert_eq!(
+            mac.to_hex(),
+            "a0d010e89607eda50e6c637c77f0034256334de48aa8e21f5bd294fc0f0b80f8"
+        );
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let delay = Duration::from_secs(5);
+        assert_eq!(backoff(delay, 1), Duration::from_secs(5));
+        assert_eq!(backoff(delay, 2), Duration::from_secs(10));
+        assert_eq!(backoff(delay, 4), Duration::from_secs(40));
+        assert_eq!(backoff(delay, 100), Duration::from_secs(5 << 10));
+    }
+
+    #[test]
+    fn payload() {
+        let (_skey, pkey) = stegos_crypto::curve1174::make_random_keys();
+        let info = PaymentInfo {
+            utxo: Hash::digest("utxo"),
+            amount: 100,
+            data: PaymentPayloadData::Comment("test".to_string()),
+        };
+        let balance = WalletNotification::BalanceChanged { balance: 100 };
+        assert!(WebhookPayload::from_notification(pkey, balance).is_none());
+        let confirmed = WalletNotification::Confirmed(info.clone());
+        let payload = WebhookPayload::from_notification(pkey, confirmed).unwrap();
+        assert_eq!(payload.event, WebhookEvent::PaymentConfirmed);
+        assert_eq!(payload.payment, info);
+        let json: serde_json::Value = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["event"], "payment_confirmed");
+        assert_eq!(json["payment"]["amount"], 100);
+
+        let hook = Webhook {
+            uri: "http://localhost/".parse().unwrap(),
+            secret: Vec::new(),
+            events: vec![WebhookEvent::PaymentSpent],
+        };
+        assert!(hook.accepts(WebhookEvent::PaymentSpent));
+        assert!(!hook.accepts(WebhookEvent::PaymentReceived));
+    }
+}
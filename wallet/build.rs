@@ -2,8 +2,13 @@ use stegos_serialization::build_script;
 
 fn main() {
     build_script::build_protobuf(
-        "protos",
+        "protos/valueshuffle.proto",
         "valueshuffle",
         &["stegos_crypto", "stegos_blockchain"],
-    )
+    );
+    build_script::build_protobuf(
+        "protos/channels.proto",
+        "channels",
+        &["stegos_crypto", "stegos_blockchain"],
+    );
 }
@@ -21,19 +21,31 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub use crate::invoice::{Invoice, InvoiceStatus, IssuedInvoice};
+pub use crate::policy::PaymentApproval;
 use futures::sync::mpsc::unbounded;
 use futures::sync::mpsc::UnboundedReceiver;
 use futures::sync::mpsc::UnboundedSender;
 use futures::sync::oneshot;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use std::time::SystemTime;
+pub use stegos_blockchain::FinalityCommitment;
+pub use stegos_blockchain::OutputProof;
+pub use stegos_blockchain::PaymentMemo;
 pub use stegos_blockchain::PaymentPayloadData;
+pub use stegos_blockchain::ReserveProof;
+pub use stegos_blockchain::SignedTransaction;
 pub use stegos_blockchain::StakeInfo;
+pub use stegos_blockchain::UnsignedTransaction;
+pub use stegos_blockchain::WalletStakeInfo;
 use stegos_crypto::curve1174::PublicKey;
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
+pub use stegos_keychain::PolicyRule;
 use stegos_node::EpochChanged;
 use stegos_node::OutputsChanged;
+pub use stegos_node::TransactionConflict;
 
 #[derive(Serialize, Clone, Debug, PartialEq, Eq)]
 pub struct PaymentInfo {
@@ -43,6 +55,134 @@ pub struct PaymentInfo {
     pub data: PaymentPayloadData,
 }
 
+/// Hash-locked output of an atomic swap.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SwapInfo {
+    pub utxo: Hash,
+    pub amount: i64,
+    pub recipient: PublicKey,
+    pub refund: PublicKey,
+    pub hashlock: Hash,
+    /// Refund is allowed after this height or timestamp.
+    pub timeout: String,
+    /// True if this wallet is the sender and can refund the output.
+    pub is_refundable: bool,
+}
+
+/// Status of a payment channel.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelStatus {
+    /// Waiting for the other party to accept the channel.
+    Proposed,
+    /// Waiting for the funding output to be confirmed.
+    Opening,
+    Open,
+    /// Waiting for the settlement transaction to be confirmed.
+    Closing,
+    /// Closed unilaterally, the balance of the closer is locked for the dispute period.
+    Disputed,
+    Closed,
+}
+
+/// Payment channel of this wallet.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub channel: Hash,
+    pub status: ChannelStatus,
+    pub funder: PublicKey,
+    pub counterparty: PublicKey,
+    /// Network key of the other party.
+    pub peer: pbc::PublicKey,
+    pub capacity: i64,
+    /// The sequence number of the latest state signed by both parties.
+    pub sequence: u64,
+    /// The balance of this wallet in the latest state.
+    pub balance: i64,
+    pub dispute_period: u64,
+}
+
+/// Direction of a payment in the history.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// File format of the exported payment history.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// A confirmed payment recorded in the wallet history.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Timestamp of the block which confirmed the payment.
+    pub timestamp: SystemTime,
+    pub direction: PaymentDirection,
+    /// Received amount for incoming payments.
+    /// Spent amount, including the fee, for outgoing payments.
+    pub amount: i64,
+    pub comment: String,
+    /// Invoice paid by this payment, if any.
+    #[serde(default)]
+    pub invoice_id: Option<String>,
+    /// None for outputs created by macro blocks.
+    pub tx_hash: Option<Hash>,
+    /// Height of the block which confirmed the payment.
+    pub height: u64,
+    /// True if the block has been finalized by a macro block and can't be reverted.
+    #[serde(default)]
+    pub irreversible: bool,
+}
+
+/// A deposit tracked by the watch list.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DepositInfo {
+    pub utxo: Hash,
+    /// Recipient of a public payment.
+    pub recipient: Option<PublicKey>,
+    /// None if the output is cloaked and the expected amount is unknown.
+    pub amount: Option<i64>,
+    /// Height of the block which created the output.
+    pub height: u64,
+    pub confirmations: u64,
+    /// True if the deposit has the required number of confirmations.
+    pub confirmed: bool,
+}
+
+/// A recipient of a batch payment.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PaymentRecipient {
+    pub recipient: PublicKey,
+    pub amount: i64,
+    #[serde(default)]
+    pub comment: String,
+    /// Typed metadata, sent instead of the plain comment if present.
+    #[serde(default)]
+    pub memo: Option<PaymentMemo>,
+}
+
+impl PaymentRecipient {
+    /// Payload data for the output of this recipient.
+    pub fn data(&self) -> PaymentPayloadData {
+        match &self.memo {
+            Some(memo) => {
+                let mut memo = memo.clone();
+                if memo.comment.is_empty() {
+                    memo.comment = self.comment.clone();
+                }
+                PaymentPayloadData::Memo(memo)
+            }
+            None => PaymentPayloadData::Comment(self.comment.clone()),
+        }
+    }
+}
+
 ///
 /// Out-of-band notifications.
 ///
@@ -50,11 +190,28 @@ pub struct PaymentInfo {
 #[serde(tag = "notification")]
 #[serde(rename_all = "snake_case")]
 pub enum WalletNotification {
-    BalanceChanged { balance: i64 },
+    BalanceChanged {
+        balance: i64,
+    },
     Received(PaymentInfo),
+    /// A received payment reached `deposit_confirmations` blocks.
+    Confirmed(PaymentInfo),
     Spent(PaymentInfo),
     Staked(StakeInfo),
     Unstaked(StakeInfo),
+    DepositReceived(DepositInfo),
+    DepositConfirmed(DepositInfo),
+    DepositSpent(DepositInfo),
+    DepositReverted(DepositInfo),
+    TransactionConflict(TransactionConflict),
+    /// An invoice issued by this wallet has been paid in full.
+    InvoicePaid(IssuedInvoice),
+    /// Payments became irreversible.
+    PaymentsFinalized {
+        /// The height of the last irreversible block.
+        height: u64,
+        entries: Vec<HistoryEntry>,
+    },
 }
 
 ///
@@ -69,6 +226,21 @@ pub enum WalletRequest {
         amount: i64,
         comment: String,
     },
+    PayMany {
+        recipients: Vec<PaymentRecipient>,
+    },
+    /// Prepare a payment to be signed offline.
+    ExportPayment {
+        recipients: Vec<PaymentRecipient>,
+    },
+    /// Sign a payment prepared by an online wallet.
+    SignTransaction {
+        unsigned: UnsignedTransaction,
+    },
+    /// Broadcast a payment signed offline.
+    ImportSignedTransaction {
+        signed: SignedTransaction,
+    },
     SecurePayment {
         recipient: PublicKey,
         amount: i64,
@@ -85,10 +257,191 @@ pub enum WalletRequest {
     },
     UnstakeAll {},
     RestakeAll {},
+    Delegate {
+        operator: pbc::PublicKey,
+        amount: i64,
+    },
+    RevokeDelegation {},
+    /// Pay block rewards of stakes to another wallet, or back to this one if None.
+    SetRewardDestination {
+        reward: Option<PublicKey>,
+    },
     KeysInfo {},
     BalanceInfo {},
     UnspentInfo {},
+    FreezeOutput {
+        utxo: Hash,
+    },
+    UnfreezeOutput {
+        utxo: Hash,
+    },
     GetRecovery {},
+    /// Re-encrypt secret keys with a new password, asked from stdin.
+    ChangePassword {},
+    /// Export the wallet secret key encrypted to a recovery public key.
+    ExportWalletKey {
+        recovery_pkey: PublicKey,
+    },
+    PaymentProof {
+        utxo: Hash,
+    },
+    StakingInfo {},
+    /// Unspent outputs and history of payments for the invoice.
+    InvoiceInfo {
+        invoice_id: String,
+    },
+    /// Issue an invoice payable to this wallet.
+    CreateInvoice {
+        amount: i64,
+        #[serde(default)]
+        memo: String,
+        /// Lifetime in seconds, the configured default if omitted.
+        #[serde(default)]
+        lifetime: Option<u64>,
+    },
+    /// Pay an encoded invoice.
+    PayInvoice {
+        invoice: String,
+    },
+    /// Invoices issued by this wallet.
+    InvoicesInfo {},
+    /// Payment history, newest first.
+    HistoryInfo {
+        #[serde(default)]
+        offset: usize,
+        #[serde(default = "default_history_limit")]
+        limit: usize,
+    },
+    /// Export payment history for accounting, oldest first.
+    ExportHistory {
+        format: ExportFormat,
+        /// Inclusive lower bound of block timestamps, in seconds since UNIX epoch.
+        #[serde(default)]
+        from: Option<u64>,
+        /// Exclusive upper bound of block timestamps, in seconds since UNIX epoch.
+        #[serde(default)]
+        to: Option<u64>,
+        /// Only incoming or outgoing payments.
+        #[serde(default)]
+        direction: Option<PaymentDirection>,
+    },
+    /// Track public payments to the key.
+    WatchPublicKey {
+        pkey: PublicKey,
+    },
+    /// Track the output with the given hash, e.g. a deposit announced by a customer.
+    WatchOutput {
+        utxo: Hash,
+        #[serde(default)]
+        amount: Option<i64>,
+    },
+    /// Unspent deposits found by the watch list.
+    DepositsInfo {},
+    /// Spend policy rules and the amount spent today.
+    SpendPolicy {},
+    /// Replace spend policy rules in the keystore.
+    SetSpendPolicy {
+        rules: Vec<PolicyRule>,
+    },
+    /// Approve a payment held by another wallet, as the second approver.
+    ApprovePayment {
+        unsigned_hash: Hash,
+    },
+    /// Sign and send a payment held for approval.
+    ImportApproval {
+        approval: PaymentApproval,
+    },
+    /// Prove ownership and the total amount of outputs to an auditor.
+    ProveReserves {
+        challenge: String,
+        /// Outputs to include, all unspent outputs if empty.
+        #[serde(default)]
+        utxos: Vec<Hash>,
+    },
+    /// Lock money in a hashed time-lock contract, the first step of an atomic swap.
+    InitiateSwap {
+        recipient: PublicKey,
+        amount: i64,
+        /// SHA3-256 of the secret, a new secret is generated if omitted.
+        #[serde(default)]
+        hashlock: Option<Hash>,
+        /// Refund is allowed after this number of blocks.
+        timeout: u64,
+    },
+    /// Redeem a hash-locked output by revealing the secret.
+    RedeemSwap {
+        utxo: Hash,
+        /// Hex-encoded secret.
+        preimage: String,
+    },
+    /// Refund a hash-locked output after the timeout.
+    RefundSwap {
+        utxo: Hash,
+    },
+    /// Hash-locked outputs of this wallet.
+    SwapsInfo {},
+    /// Propose a payment channel funded by this wallet.
+    /// The funding transaction is sent when the other party accepts the channel.
+    OpenChannel {
+        counterparty: PublicKey,
+        /// Network key of the other party, used to exchange channel states.
+        peer: pbc::PublicKey,
+        amount: i64,
+        /// The number of blocks to wait for a dispute after an unilateral close.
+        dispute_period: u64,
+    },
+    /// Pay to the other party of a payment channel off-chain.
+    ChannelPay {
+        channel: Hash,
+        amount: i64,
+    },
+    /// Settle a payment channel on-chain.
+    CloseChannel {
+        channel: Hash,
+        /// Close without the other party, using the latest state signed by both parties.
+        #[serde(default)]
+        unilateral: bool,
+    },
+    /// Payment channels of this wallet.
+    ChannelsInfo {},
+}
+
+impl WalletRequest {
+    /// Returns true if the request needs the secret key of the wallet.
+    pub fn needs_secret_key(&self) -> bool {
+        match self {
+            WalletRequest::Payment { .. }
+            | WalletRequest::PayMany { .. }
+            | WalletRequest::PayInvoice { .. }
+            | WalletRequest::SignTransaction { .. }
+            | WalletRequest::SecurePayment { .. }
+            | WalletRequest::Stake { .. }
+            | WalletRequest::Unstake { .. }
+            | WalletRequest::UnstakeAll {}
+            | WalletRequest::RestakeAll {}
+            | WalletRequest::Delegate { .. }
+            | WalletRequest::RevokeDelegation {}
+            | WalletRequest::SetRewardDestination { .. }
+            | WalletRequest::GetRecovery {}
+            | WalletRequest::ChangePassword {}
+            | WalletRequest::ExportWalletKey { .. }
+            | WalletRequest::SetSpendPolicy { .. }
+            | WalletRequest::ApprovePayment { .. }
+            | WalletRequest::ImportApproval { .. }
+            | WalletRequest::ProveReserves { .. }
+            | WalletRequest::InitiateSwap { .. }
+            | WalletRequest::RedeemSwap { .. }
+            | WalletRequest::RefundSwap { .. }
+            | WalletRequest::OpenChannel { .. }
+            | WalletRequest::ChannelPay { .. }
+            | WalletRequest::CloseChannel { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+fn default_history_limit() -> usize {
+    50
 }
 
 ///
@@ -105,9 +458,18 @@ pub enum WalletResponse {
     ValueShuffleStarted {
         session_id: Hash,
     },
+    UnsignedTransaction {
+        unsigned_hash: Hash,
+        unsigned: UnsignedTransaction,
+    },
+    SignedTransaction {
+        signed: SignedTransaction,
+    },
     TransactionCommitted(TransactionCommitted),
     BalanceInfo {
         balance: i64,
+        /// The part of balance excluded from coin selection.
+        frozen: i64,
     },
     KeysInfo {
         wallet_pkey: PublicKey,
@@ -120,6 +482,110 @@ pub enum WalletResponse {
     Recovery {
         recovery: String,
     },
+    PasswordChanged {},
+    WalletKeyExported {
+        /// PEM-encoded secret key, encrypted to the recovery public key.
+        pem: String,
+    },
+    OutputFrozen {
+        utxo: Hash,
+    },
+    OutputUnfrozen {
+        utxo: Hash,
+    },
+    PaymentProof {
+        utxo: Hash,
+        amount: i64,
+        data: PaymentPayloadData,
+        /// Hash of the macro block the proof is anchored to.
+        checkpoint: Hash,
+        proof: OutputProof,
+    },
+    StakingInfo {
+        /// The current height.
+        height: u64,
+        stakes: Vec<WalletStakeInfo>,
+    },
+    InvoiceInfo {
+        invoice_id: String,
+        unspent: Vec<PaymentInfo>,
+        history: Vec<HistoryEntry>,
+    },
+    InvoiceCreated {
+        invoice: Invoice,
+        /// Compact URI to share with the payer, e.g. as a QR code.
+        encoded: String,
+    },
+    InvoicesInfo {
+        invoices: Vec<IssuedInvoice>,
+    },
+    HistoryInfo {
+        /// The total number of recorded payments.
+        total: usize,
+        entries: Vec<HistoryEntry>,
+    },
+    HistoryExported {
+        format: ExportFormat,
+        /// Suggested file name.
+        filename: String,
+        /// The number of exported payments.
+        count: usize,
+        /// Contents of the file.
+        content: String,
+    },
+    Watching {},
+    DepositsInfo {
+        /// Height of the last processed block.
+        height: u64,
+        deposits: Vec<DepositInfo>,
+    },
+    SpendPolicy {
+        rules: Vec<PolicyRule>,
+        /// Amount spent since the start of the UTC day.
+        spent_today: i64,
+    },
+    PaymentApproval {
+        approval: PaymentApproval,
+    },
+    ReserveProof {
+        amount: i64,
+        proof: ReserveProof,
+    },
+    SwapInitiated {
+        tx_hash: Hash,
+        fee: i64,
+        utxo: Hash,
+        hashlock: Hash,
+        /// Hex-encoded secret, if generated by the wallet.
+        preimage: Option<String>,
+        /// Refund is allowed starting from this height.
+        timeout: u64,
+    },
+    SwapsInfo {
+        /// The current height.
+        height: u64,
+        swaps: Vec<SwapInfo>,
+    },
+    ChannelProposed {
+        channel: Hash,
+        capacity: i64,
+        /// Fee of the funding transaction.
+        fee: i64,
+    },
+    ChannelUpdated {
+        channel: Hash,
+        sequence: u64,
+        /// The balance of this wallet after the update.
+        balance: i64,
+    },
+    ChannelClosing {
+        channel: Hash,
+        tx_hash: Hash,
+        unilateral: bool,
+    },
+    ChannelsInfo {
+        channels: Vec<ChannelInfo>,
+    },
     Error {
         error: String,
     },
@@ -138,6 +604,60 @@ pub enum TransactionCommitted {
 ///
 /// Events.
 ///
+///
+/// Requests to manage named wallets, opened at runtime.
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "request")]
+#[serde(rename_all = "snake_case")]
+pub enum WalletsRequest {
+    /// Open a named wallet, generating its keys on the first use.
+    AddWallet { name: String, password: String },
+    /// Close a named wallet. Keys and other files of the wallet are kept on the disk.
+    RemoveWallet { name: String },
+    /// Named wallets opened in this node.
+    ListWallets {},
+}
+
+///
+/// Responses to requests to manage named wallets.
+///
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "response")]
+#[serde(rename_all = "snake_case")]
+pub enum WalletsResponse {
+    WalletAdded {
+        name: String,
+        wallet_pkey: PublicKey,
+    },
+    WalletRemoved {
+        name: String,
+    },
+    WalletsInfo {
+        wallets: Vec<NamedWalletInfo>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// A named wallet opened in this node.
+#[derive(Debug, Clone, Serialize)]
+pub struct NamedWalletInfo {
+    pub name: String,
+    pub wallet_pkey: PublicKey,
+}
+
+#[derive(Debug)]
+pub(crate) enum WalletsEvent {
+    Request {
+        request: WalletsRequest,
+        tx: oneshot::Sender<WalletsResponse>,
+    },
+    /// An event for the named wallet.
+    Forward { name: String, event: WalletEvent },
+}
+
 #[derive(Debug)]
 pub(crate) enum WalletEvent {
     //
@@ -156,6 +676,12 @@ pub(crate) enum WalletEvent {
     //
     NodeOutputsChanged(OutputsChanged),
     NodeEpochChanged(EpochChanged),
+    NodeTransactionConflict(TransactionConflict),
+    NodeFinalityChanged(FinalityCommitment),
+    ResendTransactions,
+    CompoundRewards,
+    ChurnOutputs,
+    ChannelMessage(pbc::PublicKey, Vec<u8>),
 }
 
 #[derive(Debug, Clone)]
@@ -180,3 +706,42 @@ impl Wallet {
         rx
     }
 }
+
+/// API of named wallets, see `WalletManagerService`.
+#[derive(Debug, Clone)]
+pub struct Wallets {
+    pub(crate) outbox: UnboundedSender<WalletsEvent>,
+}
+
+impl Wallets {
+    /// Execute a request to manage wallets.
+    pub fn request(&self, request: WalletsRequest) -> oneshot::Receiver<WalletsResponse> {
+        let (tx, rx) = oneshot::channel();
+        let msg = WalletsEvent::Request { request, tx };
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+
+    /// Subscribe for changes of the named wallet.
+    /// The stream ends immediately if the wallet is not opened, or when it is removed.
+    pub fn subscribe(&self, name: String) -> UnboundedReceiver<WalletNotification> {
+        let (tx, rx) = unbounded();
+        let event = WalletEvent::Subscribe { tx };
+        let msg = WalletsEvent::Forward { name, event };
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+
+    /// Execute a Wallet Request on the named wallet.
+    pub fn wallet_request(
+        &self,
+        name: String,
+        request: WalletRequest,
+    ) -> oneshot::Receiver<WalletResponse> {
+        let (tx, rx) = oneshot::channel();
+        let event = WalletEvent::Request { request, tx };
+        let msg = WalletsEvent::Forward { name, event };
+        self.outbox.unbounded_send(msg).expect("connected");
+        rx
+    }
+}
@@ -23,12 +23,13 @@
 
 use crate::error::*;
 
-/// Find appropriate inputs.
+/// Find appropriate inputs, at most `max_inputs` of them.
 pub(crate) fn find_utxo<'a, I, T>(
     unspent_iter: I,
     sum: i64,
     fee: i64,
     fee_change: i64,
+    max_inputs: usize,
 ) -> Result<(Vec<&'a T>, i64, i64), WalletError>
 where
     I: IntoIterator<Item = (&'a T, i64)>,
@@ -36,6 +37,7 @@ where
     assert!(sum >= 0);
     assert!(fee >= 0);
     assert!(fee_change >= 0);
+    assert!(max_inputs > 0);
     let mut sorted: Vec<(i64, &T)> = Vec::new();
     for (output, amount) in unspent_iter {
         if amount == sum + fee {
@@ -54,35 +56,48 @@ where
     sorted.sort_by_key(|(amount, _output)| *amount);
 
     // Try to spend without a change.
-    let mut spent: Vec<&T> = Vec::new();
-    let mut change: i64 = sum + fee;
-    for (amount, output) in sorted.iter() {
-        change -= *amount;
-        spent.push(*output);
-        if change <= 0 {
-            break;
-        }
-    }
+    let (spent, change) = spend(sorted.iter(), sum + fee, max_inputs);
     if change == 0 {
         return Ok((spent, fee, 0));
     }
 
     // Try to spend with a change.
-    spent.clear();
-    let mut change: i64 = sum + fee_change;
-    for (amount, output) in sorted.iter() {
+    let (spent, change) = spend(sorted.iter(), sum + fee_change, max_inputs);
+    if change <= 0 {
+        return Ok((spent, fee_change, -change));
+    }
+
+    // Too many small outputs - spend the largest ones.
+    let (spent, change) = spend(sorted.iter().rev(), sum + fee_change, max_inputs);
+    if change <= 0 {
+        return Ok((spent, fee_change, -change));
+    }
+
+    let total: i64 = sorted.iter().map(|(amount, _output)| amount).sum();
+    if total >= sum + fee_change {
+        return Err(WalletError::TooManyInputs(max_inputs));
+    }
+    Err(WalletError::NotEnoughMoney)
+}
+
+/// Spends outputs in the given order until `sum` is covered or `max_inputs` are spent.
+/// Returns spent outputs and the remainder, which is negative if there is a change.
+fn spend<'a, 'b, I, T>(sorted: I, sum: i64, max_inputs: usize) -> (Vec<&'a T>, i64)
+where
+    'a: 'b,
+    T: 'a,
+    I: Iterator<Item = &'b (i64, &'a T)>,
+{
+    let mut spent: Vec<&T> = Vec::new();
+    let mut change: i64 = sum;
+    for (amount, output) in sorted.take(max_inputs) {
         change -= *amount;
         spent.push(*output);
         if change <= 0 {
             break;
         }
     }
-
-    if change > 0 {
-        return Err(WalletError::NotEnoughMoney);
-    }
-
-    return Ok((spent, fee_change, -change));
+    (spent, change)
 }
 
 #[cfg(test)]
@@ -102,17 +117,20 @@ pub mod tests {
 
         const FEE: i64 = 1;
         const FEE_CHANGE: i64 = 2 * FEE;
+        const MAX_INPUTS: usize = 100;
 
         // Without change.
         let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
-        let (spent, fee, change) = find_utxo(unspent_iter, 49, FEE, FEE_CHANGE).unwrap();
+        let (spent, fee, change) =
+            find_utxo(unspent_iter, 49, FEE, FEE_CHANGE, MAX_INPUTS).unwrap();
         assert_eq!(spent, vec![&Hash::digest(&50i64)]);
         assert_eq!(fee, FEE);
         assert_eq!(change, 0);
 
         // Without change.
         let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
-        let (spent, fee, change) = find_utxo(unspent_iter, 13 - FEE, FEE, FEE_CHANGE).unwrap();
+        let (spent, fee, change) =
+            find_utxo(unspent_iter, 13 - FEE, FEE, FEE_CHANGE, MAX_INPUTS).unwrap();
         assert_eq!(
             spent,
             vec![
@@ -126,7 +144,8 @@ pub mod tests {
 
         // Without change.
         let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
-        let (spent, fee, change) = find_utxo(unspent_iter, 163 - FEE, FEE, FEE_CHANGE).unwrap();
+        let (spent, fee, change) =
+            find_utxo(unspent_iter, 163 - FEE, FEE, FEE_CHANGE, MAX_INPUTS).unwrap();
         assert_eq!(
             spent,
             vec![
@@ -142,7 +161,7 @@ pub mod tests {
 
         // With change.
         let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
-        let (spent, fee, change) = find_utxo(unspent_iter, 5, FEE, FEE_CHANGE).unwrap();
+        let (spent, fee, change) = find_utxo(unspent_iter, 5, FEE, FEE_CHANGE, MAX_INPUTS).unwrap();
         assert_eq!(
             spent,
             vec![
@@ -156,7 +175,8 @@ pub mod tests {
 
         // With zero change.
         let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
-        let (spent, fee, change) = find_utxo(unspent_iter, 161, FEE, FEE_CHANGE).unwrap();
+        let (spent, fee, change) =
+            find_utxo(unspent_iter, 161, FEE, FEE_CHANGE, MAX_INPUTS).unwrap();
         assert_eq!(
             spent,
             vec![
@@ -172,9 +192,23 @@ pub mod tests {
 
         // NotEnoughMoney
         let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
-        match find_utxo(unspent_iter, 164, FEE, FEE_CHANGE) {
+        match find_utxo(unspent_iter, 164, FEE, FEE_CHANGE, MAX_INPUTS) {
             Err(WalletError::NotEnoughMoney) => {}
             _ => panic!(),
         };
+
+        // Limited number of inputs, with change.
+        let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
+        let (spent, fee, change) = find_utxo(unspent_iter, 100, FEE, FEE_CHANGE, 2).unwrap();
+        assert_eq!(spent, vec![&Hash::digest(&100i64), &Hash::digest(&50i64)]);
+        assert_eq!(fee, FEE_CHANGE);
+        assert_eq!(change, 48);
+
+        // TooManyInputs
+        let unspent_iter = unspent.iter().map(|(h, a)| (h, *a));
+        match find_utxo(unspent_iter, 155, FEE, FEE_CHANGE, 2) {
+            Err(WalletError::TooManyInputs(2)) => {}
+            _ => panic!(),
+        };
     }
 }
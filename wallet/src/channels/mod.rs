@@ -0,0 +1,500 @@
+//! mod.rs - Payment Channels.
+
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Off-chain payment channels between two wallets.
+//!
+//! The funder proposes a channel with the initial state signed by itself,
+//! and sends the funding transaction when the other party returns this state
+//! signed by both parties. Each payment is a new state with an incremented
+//! sequence number, signed by the payer and acknowledged by the payee.
+//! States are exchanged using unicast messages and are saved to disk
+//! before they are signed or acknowledged, so a restarted wallet can still
+//! settle or dispute the channel with the latest state.
+//!
+
+mod protos;
+
+use crate::api::{ChannelInfo, ChannelStatus};
+use crate::error::WalletError;
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use stegos_blockchain::{
+    ChannelLock, ChannelState, PaymentPayloadData, PaymentTransaction, PublicPaymentOutput,
+    UnsignedTransaction,
+};
+use stegos_crypto::curve1174::{PublicKey, SecretKey};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+use stegos_serialization::traits::ProtoConvert;
+
+/// Unicast topic for channel messages.
+pub(crate) const CHANNELS_TOPIC: &'static str = "channels";
+
+#[derive(Debug, Clone)]
+pub(crate) enum ChannelMessage {
+    /// Proposal to open a channel, with the initial state signed by the funder.
+    Open {
+        funding: PublicPaymentOutput,
+        state: ChannelState,
+    },
+    /// The initial state signed by both parties.
+    Accept { state: ChannelState },
+    /// A new state signed by the payer.
+    Update { state: ChannelState },
+    /// The new state signed by both parties.
+    Ack { state: ChannelState },
+    /// Cooperative close signed by the funder, to be cosigned and sent by the counterparty.
+    Close { tx: PaymentTransaction },
+}
+
+/// A payment channel of this wallet.
+pub(crate) struct Channel {
+    /// Funding output, its hash is the identifier of the channel.
+    pub funding: PublicPaymentOutput,
+    /// Network key of the other party.
+    pub peer: pbc::PublicKey,
+    /// The latest state signed by both parties.
+    pub state: ChannelState,
+    /// A state sent to the other party, waiting for its signature.
+    pub pending: Option<ChannelState>,
+    /// Funding transaction, signed and sent when the other party accepts the channel.
+    pub funding_tx: Option<(
+        UnsignedTransaction,
+        Vec<(PublicKey, i64, PaymentPayloadData)>,
+    )>,
+    pub status: ChannelStatus,
+    /// Dispute output of an unilateral close and the height when it can be spent by the closer.
+    pub dispute: Option<(Hash, PublicPaymentOutput, u64)>,
+}
+
+impl Channel {
+    /// Create a new channel funded by this wallet.
+    /// The initial state assigns all money to the funder.
+    pub fn propose(funding: PublicPaymentOutput, peer: pbc::PublicKey, skey: &SecretKey) -> Self {
+        let channel = Hash::digest(&funding);
+        let counterparty = match &funding.channel {
+            Some(ChannelLock::Funding { counterparty, .. }) => *counterparty,
+            _ => panic!("funding output"),
+        };
+        let mut state = ChannelState::new(
+            channel,
+            funding.recipient,
+            counterparty,
+            0,
+            funding.amount,
+            0,
+        );
+        state.sign(skey, &funding.recipient);
+        Channel {
+            funding,
+            peer,
+            state,
+            pending: None,
+            funding_tx: None,
+            status: ChannelStatus::Proposed,
+            dispute: None,
+        }
+    }
+
+    /// Accept a channel proposed by the other party.
+    pub fn accept(
+        funding: PublicPaymentOutput,
+        mut state: ChannelState,
+        peer: pbc::PublicKey,
+        skey: &SecretKey,
+        pkey: &PublicKey,
+    ) -> Result<Self, WalletError> {
+        let channel = Hash::digest(&funding);
+        let is_valid = match &funding.channel {
+            Some(ChannelLock::Funding { counterparty, .. }) => {
+                counterparty == pkey
+                    && funding.validate().is_ok()
+                    && state.channel == channel
+                    && state.funder == funding.recipient
+                    && state.counterparty == *pkey
+                    && state.sequence == 0
+                    && state.funder_balance == funding.amount
+                    && state.counterparty_balance == 0
+                    && state.validate_sig(&funding.recipient).is_ok()
+            }
+            _ => false,
+        };
+        if !is_valid {
+            return Err(WalletError::InvalidChannelState(channel));
+        }
+        state.sign(skey, pkey);
+        Ok(Channel {
+            funding,
+            peer,
+            state,
+            pending: None,
+            funding_tx: None,
+            status: ChannelStatus::Opening,
+            dispute: None,
+        })
+    }
+
+    /// Returns the identifier of the channel.
+    pub fn id(&self) -> Hash {
+        self.state.channel
+    }
+
+    pub fn dispute_period(&self) -> u64 {
+        match &self.funding.channel {
+            Some(ChannelLock::Funding { dispute_period, .. }) => *dispute_period,
+            _ => panic!("funding output"),
+        }
+    }
+
+    /// Process the initial state signed by the other party.
+    pub fn on_accept(&mut self, state: ChannelState) -> Result<(), WalletError> {
+        if self.status != ChannelStatus::Proposed
+            || Hash::digest(&state) != Hash::digest(&self.state)
+            || state.validate().is_err()
+        {
+            return Err(WalletError::InvalidChannelState(self.id()));
+        }
+        self.state = state;
+        self.status = ChannelStatus::Opening;
+        Ok(())
+    }
+
+    /// Create a new state which pays `amount` to the other party, signed by this wallet.
+    pub fn new_update(
+        &self,
+        skey: &SecretKey,
+        pkey: &PublicKey,
+        amount: i64,
+    ) -> Result<ChannelState, WalletError> {
+        if amount <= 0 {
+            return Err(WalletError::NegativeAmount(amount));
+        }
+        if self.status != ChannelStatus::Open {
+            return Err(WalletError::ChannelNotOpen(self.id()));
+        }
+        if self.pending.is_some() {
+            return Err(WalletError::ChannelBusy(self.id()));
+        }
+        let balance = self.state.balance(pkey).expect("party of channel");
+        if balance < amount {
+            return Err(WalletError::NotEnoughMoney);
+        }
+        let mut state = self.state.clone();
+        state.sequence += 1;
+        if pkey == &state.funder {
+            state.funder_balance -= amount;
+            state.counterparty_balance += amount;
+        } else {
+            state.funder_balance += amount;
+            state.counterparty_balance -= amount;
+        }
+        state.sign(skey, pkey);
+        Ok(state)
+    }
+
+    /// Process a new state signed by the other party.
+    /// Returns the amount received by this wallet.
+    pub fn on_update(
+        &mut self,
+        mut state: ChannelState,
+        skey: &SecretKey,
+        pkey: &PublicKey,
+    ) -> Result<i64, WalletError> {
+        let other = self.state.other(pkey).expect("party of channel");
+        let old_balance = self.state.balance(pkey).expect("party of channel");
+        let received = state.balance(pkey).unwrap_or(0) - old_balance;
+        if self.status != ChannelStatus::Open
+            || self.pending.is_some()
+            || state.channel != self.state.channel
+            || state.funder != self.state.funder
+            || state.counterparty != self.state.counterparty
+            || state.sequence != self.state.sequence + 1
+            || state.funder_balance < 0
+            || state.counterparty_balance < 0
+            || state.funder_balance.checked_add(state.counterparty_balance)
+                != Some(self.funding.amount)
+            || received <= 0
+            || state.validate_sig(other).is_err()
+        {
+            return Err(WalletError::InvalidChannelState(self.id()));
+        }
+        state.sign(skey, pkey);
+        self.state = state;
+        Ok(received)
+    }
+
+    /// Process the pending state signed by the other party.
+    pub fn on_ack(&mut self, state: ChannelState) -> Result<(), WalletError> {
+        let is_valid = match &self.pending {
+            Some(pending) => {
+                Hash::digest(&state) == Hash::digest(pending) && state.validate().is_ok()
+            }
+            None => false,
+        };
+        if !is_valid {
+            return Err(WalletError::InvalidChannelState(self.id()));
+        }
+        self.pending = None;
+        self.state = state;
+        Ok(())
+    }
+
+    pub fn to_info(&self, pkey: &PublicKey) -> ChannelInfo {
+        ChannelInfo {
+            channel: self.id(),
+            status: self.status,
+            funder: self.state.funder,
+            counterparty: self.state.counterparty,
+            peer: self.peer,
+            capacity: self.funding.amount,
+            sequence: self.state.sequence,
+            balance: self.state.balance(pkey).unwrap_or(0),
+            dispute_period: self.dispute_period(),
+        }
+    }
+}
+
+/// Persistent payment channels of this wallet, by the hash of the funding output.
+pub(crate) struct Channels {
+    /// Path to the file, one channel in HEX-encoded protobuf per line.
+    /// Empty path disables persistence.
+    path: PathBuf,
+    channels: HashMap<Hash, Channel>,
+}
+
+impl Channels {
+    /// Load channels from the file.
+    /// A missing file is treated as an empty list.
+    pub fn load(path: &str) -> Result<Self, WalletError> {
+        let path = PathBuf::from(path);
+        let mut channels = HashMap::new();
+        if path.as_os_str().is_empty() {
+            return Ok(Channels { path, channels });
+        }
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(WalletError::ChannelsIOError(
+                    path.to_string_lossy().to_string(),
+                    e.to_string(),
+                ));
+            }
+        };
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let channel = hex::decode(line)
+                .ok()
+                .and_then(|buf| Channel::from_buffer(&buf).ok())
+                .ok_or_else(|| {
+                    WalletError::InvalidChannels(path.to_string_lossy().to_string(), lineno + 1)
+                })?;
+            if channel.status == ChannelStatus::Proposed {
+                // The funding transaction is not saved and hasn't been sent yet.
+                warn!(
+                    "Dropped unaccepted payment channel: channel={}",
+                    channel.id()
+                );
+                continue;
+            }
+            channels.insert(channel.id(), channel);
+        }
+        debug!("Loaded channels: path={:?}, count={}", path, channels.len());
+        Ok(Channels { path, channels })
+    }
+
+    pub fn get(&self, channel_id: &Hash) -> Option<&Channel> {
+        self.channels.get(channel_id)
+    }
+
+    pub fn get_mut(&mut self, channel_id: &Hash) -> Option<&mut Channel> {
+        self.channels.get_mut(channel_id)
+    }
+
+    pub fn contains_key(&self, channel_id: &Hash) -> bool {
+        self.channels.contains_key(channel_id)
+    }
+
+    pub fn insert(&mut self, channel_id: Hash, channel: Channel) {
+        self.channels.insert(channel_id, channel);
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Channel> {
+        self.channels.values()
+    }
+
+    /// Write all channels to the file, replacing it atomically.
+    pub fn save(&self) -> Result<(), WalletError> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let mut contents = String::new();
+        for channel in self.channels.values() {
+            let buf = channel.into_buffer().expect("serializable");
+            contents.push_str(&hex::encode(&buf));
+            contents.push('\n');
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(contents.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp, &self.path))
+            .map_err(|e| {
+                WalletError::ChannelsIOError(self.path.to_string_lossy().to_string(), e.to_string())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use stegos_crypto::curve1174::make_random_keys;
+
+    #[test]
+    fn updates() {
+        let (skey0, pkey0) = make_random_keys();
+        let (skey1, pkey1) = make_random_keys();
+        let (_network_skey, network_pkey) = pbc::make_random_keys();
+        let funding = PublicPaymentOutput::new_channel_funding(&pkey0, &pkey1, 1000, 10);
+
+        // Open.
+        let mut channel0 = Channel::propose(funding.clone(), network_pkey, &skey0);
+        let msg = ChannelMessage::Open {
+            funding,
+            state: channel0.state.clone(),
+        };
+        let msg = ChannelMessage::from_buffer(&msg.into_buffer().unwrap()).unwrap();
+        let (funding, state) = match msg {
+            ChannelMessage::Open { funding, state } => (funding, state),
+            _ => panic!(),
+        };
+        let mut channel1 = Channel::accept(funding, state, network_pkey, &skey1, &pkey1).unwrap();
+        assert_eq!(channel1.id(), channel0.id());
+        channel0.on_accept(channel1.state.clone()).unwrap();
+        assert_eq!(channel0.status, ChannelStatus::Opening);
+
+        // Updates are allowed when the funding output is confirmed.
+        assert_eq!(
+            channel0.new_update(&skey0, &pkey0, 100).unwrap_err(),
+            WalletError::ChannelNotOpen(channel0.id())
+        );
+        channel0.status = ChannelStatus::Open;
+        channel1.status = ChannelStatus::Open;
+
+        // Pay from the funder.
+        let state = channel0.new_update(&skey0, &pkey0, 100).unwrap();
+        channel0.pending = Some(state.clone());
+        assert_eq!(
+            channel0.new_update(&skey0, &pkey0, 100).unwrap_err(),
+            WalletError::ChannelBusy(channel0.id())
+        );
+        assert_eq!(channel1.on_update(state, &skey1, &pkey1).unwrap(), 100);
+        channel0.on_ack(channel1.state.clone()).unwrap();
+        assert_eq!(channel0.state.sequence, 1);
+        assert_eq!(channel0.to_info(&pkey0).balance, 900);
+        assert_eq!(channel1.to_info(&pkey1).balance, 100);
+
+        // Pay back.
+        let state = channel1.new_update(&skey1, &pkey1, 40).unwrap();
+        channel1.pending = Some(state.clone());
+        assert_eq!(channel0.on_update(state, &skey0, &pkey0).unwrap(), 40);
+        channel1.on_ack(channel0.state.clone()).unwrap();
+        assert_eq!(channel0.to_info(&pkey0).balance, 940);
+        assert_eq!(channel1.to_info(&pkey1).balance, 60);
+
+        // Payer can't spend more than its balance.
+        assert_eq!(
+            channel1.new_update(&skey1, &pkey1, 61).unwrap_err(),
+            WalletError::NotEnoughMoney
+        );
+
+        // Updates which decrease the balance of the payee are rejected.
+        let mut state = channel0.state.clone();
+        state.sequence += 1;
+        state.funder_balance += 10;
+        state.counterparty_balance -= 10;
+        state.sign(&skey0, &pkey0);
+        assert_eq!(
+            channel1.on_update(state, &skey1, &pkey1).unwrap_err(),
+            WalletError::InvalidChannelState(channel1.id())
+        );
+    }
+
+    #[test]
+    fn save_load() {
+        let path = env::temp_dir().join(format!("stegos-channels-{}", Hash::digest(&"test")));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+
+        let (skey0, pkey0) = make_random_keys();
+        let (skey1, pkey1) = make_random_keys();
+        let (_network_skey, network_pkey) = pbc::make_random_keys();
+        let funding = PublicPaymentOutput::new_channel_funding(&pkey0, &pkey1, 1000, 10);
+        let proposed = Channel::propose(funding.clone(), network_pkey, &skey0);
+        let mut channel = Channel::accept(
+            funding,
+            proposed.state.clone(),
+            network_pkey,
+            &skey1,
+            &pkey1,
+        )
+        .unwrap();
+        channel.status = ChannelStatus::Open;
+        channel.pending = Some(channel.state.clone());
+        let channel_id = channel.id();
+
+        let mut channels = Channels::load(&path).expect("missing file is ok");
+        assert!(!channels.contains_key(&channel_id));
+        channels.insert(channel_id, channel);
+        channels.save().unwrap();
+
+        let mut channels = Channels::load(&path).expect("file is valid");
+        let loaded = channels.get(&channel_id).expect("saved");
+        assert_eq!(loaded.status, ChannelStatus::Open);
+        assert_eq!(loaded.peer, network_pkey);
+        assert_eq!(Hash::digest(&loaded.state), Hash::digest(&proposed.state));
+        assert!(loaded.state.validate().is_ok());
+        assert!(loaded.pending.is_some());
+        assert!(loaded.dispute.is_none());
+
+        // Channels which were never accepted are dropped.
+        channels.insert(channel_id, proposed);
+        channels.save().unwrap();
+        let channels = Channels::load(&path).expect("file is valid");
+        assert!(!channels.contains_key(&channel_id));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+//! protos.rs - Payment Channels Protobuf Encoding.
+
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{Channel, ChannelMessage};
+use crate::api::ChannelStatus;
+use failure::{format_err, Error};
+use stegos_serialization::traits::*;
+
+// link protobuf dependencies
+use stegos_blockchain::protos::*;
+include!(concat!(env!("OUT_DIR"), "/channels/mod.rs"));
+
+use stegos_blockchain::{ChannelState, PaymentTransaction, PublicPaymentOutput};
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc;
+
+impl ProtoConvert for ChannelMessage {
+    type Proto = channels::ChannelMessage;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = channels::ChannelMessage::new();
+        match self {
+            ChannelMessage::Open { funding, state } => {
+                let mut body = channels::ChannelOpen::new();
+                body.set_funding(funding.into_proto());
+                body.set_state(state.into_proto());
+                proto.set_open(body);
+            }
+            ChannelMessage::Accept { state } => proto.set_accept(state.into_proto()),
+            ChannelMessage::Update { state } => proto.set_update(state.into_proto()),
+            ChannelMessage::Ack { state } => proto.set_ack(state.into_proto()),
+            ChannelMessage::Close { tx } => proto.set_close(tx.into_proto()),
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let msg = match proto.body {
+            Some(channels::ChannelMessage_oneof_body::open(ref body)) => ChannelMessage::Open {
+                funding: PublicPaymentOutput::from_proto(body.get_funding())?,
+                state: ChannelState::from_proto(body.get_state())?,
+            },
+            Some(channels::ChannelMessage_oneof_body::accept(ref state)) => {
+                ChannelMessage::Accept {
+                    state: ChannelState::from_proto(state)?,
+                }
+            }
+            Some(channels::ChannelMessage_oneof_body::update(ref state)) => {
+                ChannelMessage::Update {
+                    state: ChannelState::from_proto(state)?,
+                }
+            }
+            Some(channels::ChannelMessage_oneof_body::ack(ref state)) => ChannelMessage::Ack {
+                state: ChannelState::from_proto(state)?,
+            },
+            Some(channels::ChannelMessage_oneof_body::close(ref tx)) => ChannelMessage::Close {
+                tx: PaymentTransaction::from_proto(tx)?,
+            },
+            None => {
+                return Err(
+                    ProtoError::MissingField("body".to_string(), "body".to_string()).into(),
+                );
+            }
+        };
+        Ok(msg)
+    }
+}
+
+impl ProtoConvert for Channel {
+    type Proto = channels::ChannelRecord;
+    fn into_proto(&self) -> Self::Proto {
+        let mut proto = channels::ChannelRecord::new();
+        proto.set_funding(self.funding.into_proto());
+        proto.set_peer(self.peer.into_proto());
+        proto.set_state(self.state.into_proto());
+        if let Some(pending) = &self.pending {
+            proto.set_pending(pending.into_proto());
+        }
+        let status = match self.status {
+            ChannelStatus::Proposed => 0,
+            ChannelStatus::Opening => 1,
+            ChannelStatus::Open => 2,
+            ChannelStatus::Closing => 3,
+            ChannelStatus::Disputed => 4,
+            ChannelStatus::Closed => 5,
+        };
+        proto.set_status(status);
+        if let Some((utxo, output, unlock_height)) = &self.dispute {
+            let mut dispute = channels::ChannelDispute::new();
+            dispute.set_utxo(utxo.into_proto());
+            dispute.set_output(output.into_proto());
+            dispute.set_unlock_height(*unlock_height);
+            proto.set_dispute(dispute);
+        }
+        proto
+    }
+
+    fn from_proto(proto: &Self::Proto) -> Result<Self, Error> {
+        let funding = PublicPaymentOutput::from_proto(proto.get_funding())?;
+        let peer = pbc::PublicKey::from_proto(proto.get_peer())?;
+        let state = ChannelState::from_proto(proto.get_state())?;
+        let pending = if proto.has_pending() {
+            Some(ChannelState::from_proto(proto.get_pending())?)
+        } else {
+            None
+        };
+        let status = match proto.get_status() {
+            0 => ChannelStatus::Proposed,
+            1 => ChannelStatus::Opening,
+            2 => ChannelStatus::Open,
+            3 => ChannelStatus::Closing,
+            4 => ChannelStatus::Disputed,
+            5 => ChannelStatus::Closed,
+            status => return Err(format_err!("Invalid channel status: {}", status)),
+        };
+        let dispute = if proto.has_dispute() {
+            let dispute = proto.get_dispute();
+            Some((
+                Hash::from_proto(dispute.get_utxo())?,
+                PublicPaymentOutput::from_proto(dispute.get_output())?,
+                dispute.get_unlock_height(),
+            ))
+        } else {
+            None
+        };
+        Ok(Channel {
+            funding,
+            peer,
+            state,
+            pending,
+            funding_tx: None,
+            status,
+            dispute,
+        })
+    }
+}
@@ -0,0 +1,215 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Automatic self-spends which make chain analysis harder.
+//!
+//! Random outputs of the wallet are periodically spent back to the wallet and
+//! split into random amounts. Such transactions have standard numbers of outputs,
+//! which match a regular payment with a change. Regular payments can be padded
+//! to the same numbers of outputs by splitting the change.
+//!
+
+use failure::Error;
+use log::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+use stegos_blockchain::*;
+use stegos_crypto::curve1174::{Fr, PublicKey};
+use stegos_crypto::hash::Hash;
+
+/// Standard numbers of outputs of self-spends.
+const OUTPUT_BUCKETS: [usize; 2] = [2, 4];
+/// The maximal number of outputs spent by one self-spend.
+const MAX_INPUTS: usize = 4;
+/// Budget of fees is counted over this period.
+const BUDGET_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns the smallest standard number of outputs which is not less than `n`.
+/// Returns the largest bucket if `n` exceeds it.
+pub(crate) fn output_bucket(n: usize) -> usize {
+    OUTPUT_BUCKETS
+        .iter()
+        .cloned()
+        .find(|bucket| *bucket >= n)
+        .unwrap_or(OUTPUT_BUCKETS[OUTPUT_BUCKETS.len() - 1])
+}
+
+/// Returns a random delay from 1/2 to 3/2 of the average interval.
+pub(crate) fn churn_delay<R: Rng>(interval: Duration, rng: &mut R) -> Duration {
+    let millis = interval.as_secs() * 1000 + u64::from(interval.subsec_millis());
+    let millis = std::cmp::max(millis, 2);
+    Duration::from_millis(rng.gen_range(millis / 2, millis + millis / 2))
+}
+
+/// Splits the amount into `parts` random positive amounts.
+pub(crate) fn split_amount<R: Rng>(amount: i64, parts: usize, rng: &mut R) -> Vec<i64> {
+    assert!(parts > 0);
+    assert!(amount >= parts as i64);
+    // Every part gets at least one token, the rest is distributed by random weights.
+    let rest = (amount - parts as i64) as i128;
+    let weights: Vec<i128> = (0..parts)
+        .map(|_| i128::from(rng.gen_range(1u32, 1000u32)))
+        .collect();
+    let total_weight: i128 = weights.iter().sum();
+    let mut amounts: Vec<i64> = weights
+        .iter()
+        .map(|weight| 1 + (rest * weight / total_weight) as i64)
+        .collect();
+    let remainder = amount - amounts.iter().sum::<i64>();
+    amounts[parts - 1] += remainder;
+    amounts
+}
+
+/// Fees spent on self-spends.
+pub(crate) struct ChurnBudget {
+    /// The maximal fees per day.
+    limit: i64,
+    /// Spent fees by time.
+    spent: VecDeque<(SystemTime, i64)>,
+}
+
+impl ChurnBudget {
+    pub(crate) fn new(limit: i64) -> Self {
+        ChurnBudget {
+            limit,
+            spent: VecDeque::new(),
+        }
+    }
+
+    /// Returns the fees spent during the last day.
+    pub(crate) fn spent(&mut self, now: SystemTime) -> i64 {
+        while let Some((timestamp, _fee)) = self.spent.front() {
+            match now.duration_since(*timestamp) {
+                Ok(elapsed) if elapsed >= BUDGET_PERIOD => {
+                    self.spent.pop_front();
+                }
+                _ => break,
+            }
+        }
+        self.spent.iter().map(|(_timestamp, fee)| fee).sum()
+    }
+
+    /// Returns true if the fee fits into the budget.
+    pub(crate) fn allows(&mut self, fee: i64, now: SystemTime) -> bool {
+        self.spent(now) + fee <= self.limit
+    }
+
+    /// Records the spent fee.
+    pub(crate) fn spend(&mut self, fee: i64, now: SystemTime) {
+        self.spent.push_back((now, fee));
+    }
+}
+
+/// Create a self-spend of a few random outputs.
+/// Returns None if there is nothing to spend.
+pub(crate) fn create_churn_transaction<R: Rng>(
+    sender_pkey: &PublicKey,
+    unspent: &[(&PaymentOutput, i64)],
+    payment_fee: i64,
+    rng: &mut R,
+) -> Result<Option<(Vec<Output>, Vec<Output>, Fr, i64)>, Error> {
+    if unspent.is_empty() {
+        return Ok(None);
+    }
+    let num_inputs = rng.gen_range(1, std::cmp::min(unspent.len(), MAX_INPUTS) + 1);
+    let inputs: Vec<&(&PaymentOutput, i64)> = unspent.choose_multiple(rng, num_inputs).collect();
+    let total: i64 = inputs.iter().map(|(_output, amount)| amount).sum();
+    let num_outputs = output_bucket(num_inputs);
+    let fee = payment_fee * num_outputs as i64;
+    if total - fee < num_outputs as i64 {
+        debug!(
+            "Outputs are too small to churn: inputs={}, amount={}, fee={}",
+            num_inputs, total, fee
+        );
+        return Ok(None);
+    }
+
+    let mut outputs: Vec<Output> = Vec::with_capacity(num_outputs);
+    let mut gamma = Fr::zero();
+    for amount in split_amount(total - fee, num_outputs, rng) {
+        // Looks like a change of a regular payment.
+        let data = PaymentPayloadData::Comment("Change".to_string());
+        let (output, output_gamma) = PaymentOutput::with_payload(sender_pkey, amount, data)?;
+        debug!(
+            "Created churn UTXO: hash={}, amount={}",
+            Hash::digest(&output),
+            amount
+        );
+        outputs.push(Output::PaymentOutput(output));
+        gamma += output_gamma;
+    }
+    let inputs: Vec<Output> = inputs
+        .into_iter()
+        .map(|(output, _amount)| Output::PaymentOutput((*output).clone()))
+        .collect();
+    Ok(Some((inputs, outputs, gamma, fee)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::thread_rng;
+
+    #[test]
+    fn buckets() {
+        assert_eq!(output_bucket(1), 2);
+        assert_eq!(output_bucket(2), 2);
+        assert_eq!(output_bucket(3), 4);
+        assert_eq!(output_bucket(MAX_INPUTS), 4);
+    }
+
+    #[test]
+    fn random_amounts_and_delays() {
+        let mut rng = thread_rng();
+        for parts in 1..5 {
+            for &amount in &[parts as i64, 1000, std::i64::MAX / 2] {
+                let amounts = split_amount(amount, parts, &mut rng);
+                assert_eq!(amounts.len(), parts);
+                assert_eq!(amounts.iter().sum::<i64>(), amount);
+                assert!(amounts.iter().all(|amount| *amount > 0));
+            }
+        }
+
+        let interval = Duration::from_secs(60);
+        for _ in 0..100 {
+            let delay = churn_delay(interval, &mut rng);
+            assert!(delay >= interval / 2 && delay < interval * 3 / 2);
+        }
+    }
+
+    #[test]
+    fn budget() {
+        let now = SystemTime::now();
+        let mut budget = ChurnBudget::new(10);
+        assert!(budget.allows(10, now));
+        budget.spend(6, now);
+        assert!(budget.allows(4, now));
+        assert!(!budget.allows(5, now));
+        assert!(budget.allows(10, now + BUDGET_PERIOD));
+        assert_eq!(budget.spent(now + BUDGET_PERIOD), 0);
+
+        let mut disabled = ChurnBudget::new(0);
+        assert!(!disabled.allows(1, now));
+    }
+}
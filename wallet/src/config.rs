@@ -0,0 +1,90 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Wallet configuration.
+//!
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Wallet Configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WalletConfig {
+    /// Path to the list of outputs excluded from coin selection.
+    pub frozen_outputs_file: String,
+    /// Path to the payment history.
+    pub history_file: String,
+    /// Path to the list of watched public keys and outputs.
+    pub watch_file: String,
+    /// Path to the list of issued invoices.
+    pub invoices_file: String,
+    /// Path to the states of payment channels.
+    pub channels_file: String,
+    /// Default lifetime of issued invoices (secs).
+    pub invoice_lifetime: u64,
+    /// Number of confirmations to consider a watched deposit or a received payment as final.
+    pub deposit_confirmations: u64,
+    /// Reject all requests which use the secret key.
+    pub watch_only: bool,
+    /// Automatically stake block rewards received by this wallet.
+    pub compound_rewards: bool,
+    /// Minimal amount of unspent rewards to stake.
+    pub compound_threshold: i64,
+    /// How often check unspent rewards (secs).
+    pub compound_interval: u64,
+    /// Don't stake rewards above this total stake, 0 means no limit.
+    pub compound_max_stake: i64,
+    /// Periodically spend random outputs back to this wallet to hinder chain analysis.
+    pub churn_outputs: bool,
+    /// Average interval between self-spends, randomized by ±50% (secs).
+    pub churn_interval: u64,
+    /// The maximal fees spent on self-spends per day, 0 disables self-spends.
+    pub churn_budget: i64,
+    /// Split the change of payments to pad the number of outputs to a standard bucket.
+    pub pad_outputs: bool,
+    /// Directory with keys and files of named wallets, one subdirectory per wallet.
+    pub wallets_dir: String,
+}
+
+impl Default for WalletConfig {
+    fn default() -> Self {
+        WalletConfig {
+            frozen_outputs_file: "wallet.frozen".to_string(),
+            history_file: "wallet.history".to_string(),
+            watch_file: "wallet.watch".to_string(),
+            invoices_file: "wallet.invoices".to_string(),
+            channels_file: "wallet.channels".to_string(),
+            invoice_lifetime: 24 * 60 * 60,
+            deposit_confirmations: 6,
+            watch_only: false,
+            compound_rewards: false,
+            compound_threshold: 100_000_000, // 100 STG
+            compound_interval: 600,
+            compound_max_stake: 0,
+            churn_outputs: false,
+            churn_interval: 60 * 60,
+            churn_budget: 10_000_000, // 10 STG
+            pad_outputs: false,
+            wallets_dir: "wallets".to_string(),
+        }
+    }
+}
@@ -22,12 +22,27 @@
 // SOFTWARE.
 
 use failure::Fail;
+use stegos_blockchain::LockTime;
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::Hash;
 #[derive(Debug, Fail, PartialEq, Eq)]
 pub enum WalletError {
     #[fail(display = "Not enough money.")]
     NotEnoughMoney,
+    #[fail(
+        display = "The payment needs more inputs than allowed in a transaction: max_inputs={}",
+        _0
+    )]
+    TooManyInputs(usize),
     #[fail(display = "Negative amount: amount={}", _0)]
     NegativeAmount(i64),
+    #[fail(display = "No recipients")]
+    NoRecipients,
+    #[fail(
+        display = "Unknown transaction for offline signing: unsigned_hash={}",
+        _0
+    )]
+    UnknownUnsignedTransaction(Hash),
     #[fail(display = "Insufficient stake: min={}, got={}.", _0, _1)]
     InsufficientStake(i64, i64),
     #[fail(display = "Incorrect TXIN type")]
@@ -36,4 +51,99 @@ pub enum WalletError {
     InvalidUTXOData,
     #[fail(display = "Nothing to re-stake")]
     NothingToRestake,
+    #[fail(display = "Nothing to revoke")]
+    NothingToRevoke,
+    #[fail(display = "Payment is unknown or not finalized yet: utxo={}", _0)]
+    PaymentNotFinalized(Hash),
+    #[fail(display = "Payment doesn't belong to this wallet: utxo={}", _0)]
+    NotMyPayment(Hash),
+    #[fail(display = "Output doesn't belong to this wallet: utxo={}", _0)]
+    UnknownOutput(Hash),
+    #[fail(display = "Output is already frozen: utxo={}", _0)]
+    AlreadyFrozen(Hash),
+    #[fail(display = "Output is not frozen: utxo={}", _0)]
+    NotFrozen(Hash),
+    #[fail(display = "Failed to access frozen outputs: path={}, error={}", _0, _1)]
+    FrozenOutputsIOError(String, String),
+    #[fail(display = "Invalid frozen outputs file: path={}, line={}", _0, _1)]
+    InvalidFrozenOutputs(String, usize),
+    #[fail(display = "Failed to access wallet history: path={}, error={}", _0, _1)]
+    HistoryIOError(String, String),
+    #[fail(display = "Invalid wallet history file: path={}, line={}", _0, _1)]
+    InvalidHistory(String, usize),
+    #[fail(display = "Failed to access watch list: path={}, error={}", _0, _1)]
+    WatchListIOError(String, String),
+    #[fail(display = "Invalid watch list file: path={}, line={}", _0, _1)]
+    InvalidWatchList(String, usize),
+    #[fail(display = "Operation is not permitted for watch-only wallet")]
+    WatchOnly,
+    #[fail(
+        display = "Daily spend limit exceeded: limit={}, spent={}, amount={}",
+        _0, _1, _2
+    )]
+    DailyLimitExceeded(i64, i64, i64),
+    #[fail(display = "Recipient is not in the allow list: recipient={}", _0)]
+    RecipientNotAllowed(PublicKey),
+    #[fail(
+        display = "Payment requires approval: unsigned_hash={}, approver={}",
+        _0, _1
+    )]
+    ApprovalRequired(Hash, PublicKey),
+    #[fail(display = "Invalid payment approval: unsigned_hash={}", _0)]
+    InvalidApproval(Hash),
+    #[fail(display = "Unknown payment for approval: unsigned_hash={}", _0)]
+    UnknownApproval(Hash),
+    #[fail(display = "Invalid invoice: {}", _0)]
+    InvalidInvoice(String),
+    #[fail(display = "Invoice has expired: invoice_id={}", _0)]
+    InvoiceExpired(String),
+    #[fail(display = "Failed to access invoices: path={}, error={}", _0, _1)]
+    InvoicesIOError(String, String),
+    #[fail(display = "Invalid invoices file: path={}, line={}", _0, _1)]
+    InvalidInvoices(String, usize),
+    #[fail(display = "Unknown hash-locked output: utxo={}", _0)]
+    UnknownSwap(Hash),
+    #[fail(display = "Invalid preimage of hash-locked output: utxo={}", _0)]
+    InvalidPreimage(Hash),
+    #[fail(
+        display = "Hash-locked output can't be refunded yet: utxo={}, timeout={}",
+        _0, _1
+    )]
+    SwapNotExpired(Hash, LockTime),
+    #[fail(display = "Unknown payment channel: channel={}", _0)]
+    UnknownChannel(Hash),
+    #[fail(display = "Payment channel is not open: channel={}", _0)]
+    ChannelNotOpen(Hash),
+    #[fail(
+        display = "Payment channel has an unacknowledged update: channel={}",
+        _0
+    )]
+    ChannelBusy(Hash),
+    #[fail(display = "Invalid state of payment channel: channel={}", _0)]
+    InvalidChannelState(Hash),
+    #[fail(
+        display = "Only the funder can close a payment channel cooperatively: channel={}",
+        _0
+    )]
+    NotChannelFunder(Hash),
+    #[fail(
+        display = "Failed to access payment channels: path={}, error={}",
+        _0, _1
+    )]
+    ChannelsIOError(String, String),
+    #[fail(display = "Invalid payment channels file: path={}, line={}", _0, _1)]
+    InvalidChannels(String, usize),
+    #[fail(
+        display = "Invalid wallet name, expected up to 32 letters, digits, '-' or '_': name={}",
+        _0
+    )]
+    InvalidWalletName(String),
+    #[fail(display = "Wallet is already opened: name={}", _0)]
+    WalletAlreadyOpened(String),
+    #[fail(display = "Unknown wallet: name={}", _0)]
+    UnknownWallet(String),
+    #[fail(display = "Node has disconnected")]
+    NodeDisconnected,
+    #[fail(display = "Unexpected response from the node")]
+    UnexpectedNodeResponse,
 }
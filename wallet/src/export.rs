@@ -0,0 +1,264 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Export of the payment history for accounting.
+//!
+
+use crate::api::{ExportFormat, HistoryEntry, PaymentDirection};
+use serde_derive::Serialize;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Columns of exported files, in order.
+/// The schema is stable: new columns may only be appended.
+pub const EXPORT_COLUMNS: &[&str] = &[
+    "timestamp",
+    "height",
+    "direction",
+    "amount",
+    "amount_formatted",
+    "comment",
+    "invoice_id",
+    "tx_hash",
+];
+
+/// Number of raw units in one STG.
+const MONEY_UNIT: i64 = 1_000_000;
+
+/// Selects payments to export.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExportFilter {
+    /// Inclusive lower bound of block timestamps, in seconds since UNIX epoch.
+    pub from: Option<u64>,
+    /// Exclusive upper bound of block timestamps, in seconds since UNIX epoch.
+    pub to: Option<u64>,
+    pub direction: Option<PaymentDirection>,
+}
+
+impl ExportFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        let timestamp = unix_timestamp(entry.timestamp);
+        self.from.map_or(true, |from| timestamp >= from)
+            && self.to.map_or(true, |to| timestamp < to)
+            && self.direction.map_or(true, |d| d == entry.direction)
+    }
+}
+
+/// A row of the exported file, fields are ordered as `EXPORT_COLUMNS`.
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    timestamp: u64,
+    height: u64,
+    direction: PaymentDirection,
+    amount: i64,
+    amount_formatted: String,
+    comment: &'a str,
+    invoice_id: Option<&'a str>,
+    tx_hash: Option<String>,
+}
+
+impl<'a> ExportRow<'a> {
+    fn new(entry: &'a HistoryEntry) -> Self {
+        ExportRow {
+            timestamp: unix_timestamp(entry.timestamp),
+            height: entry.height,
+            direction: entry.direction,
+            amount: entry.amount,
+            amount_formatted: format_amount(entry.amount),
+            comment: &entry.comment,
+            invoice_id: entry.invoice_id.as_ref().map(String::as_str),
+            tx_hash: entry.tx_hash.as_ref().map(|h| h.to_hex()),
+        }
+    }
+
+    fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let direction = match self.direction {
+            PaymentDirection::Incoming => "incoming",
+            PaymentDirection::Outgoing => "outgoing",
+        };
+        let fields = [
+            self.timestamp.to_string(),
+            self.height.to_string(),
+            direction.to_string(),
+            self.amount.to_string(),
+            self.amount_formatted.clone(),
+            csv_escape(self.comment),
+            csv_escape(self.invoice_id.unwrap_or("")),
+            self.tx_hash.clone().unwrap_or_default(),
+        ];
+        write!(out, "{}\r\n", fields.join(","))
+    }
+}
+
+/// Writes matching payments to `out`, row by row, in the order of `entries`.
+/// Returns the number of exported payments.
+pub(crate) fn export_history<'a, I, W>(
+    entries: I,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    out: &mut W,
+) -> io::Result<usize>
+where
+    I: Iterator<Item = &'a HistoryEntry>,
+    W: Write,
+{
+    let mut count = 0;
+    match format {
+        ExportFormat::Csv => write!(out, "{}\r\n", EXPORT_COLUMNS.join(","))?,
+        ExportFormat::Json => write!(out, "[")?,
+    }
+    for entry in entries.filter(|entry| filter.matches(entry)) {
+        let row = ExportRow::new(entry);
+        match format {
+            ExportFormat::Csv => row.write_csv(out)?,
+            ExportFormat::Json => {
+                if count > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "\n")?;
+                serde_json::to_writer(&mut *out, &row)?;
+            }
+        }
+        count += 1;
+    }
+    if let ExportFormat::Json = format {
+        write!(out, "\n]\n")?;
+    }
+    Ok(count)
+}
+
+fn unix_timestamp(timestamp: SystemTime) -> u64 {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats raw units as STG with all six decimal digits, e.g. "-1.500000".
+fn format_amount(amount: i64) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    let amount = amount.abs();
+    format!("{}{}.{:06}", sign, amount / MONEY_UNIT, amount % MONEY_UNIT)
+}
+
+/// Quotes the field if it contains separators, quotes or line breaks (RFC 4180).
+fn csv_escape(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use stegos_crypto::hash::Hash;
+
+    fn entry(secs: u64, direction: PaymentDirection, amount: i64, comment: &str) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            direction,
+            amount,
+            comment: comment.to_string(),
+            invoice_id: None,
+            tx_hash: None,
+            height: secs,
+            irreversible: false,
+        }
+    }
+
+    fn export(entries: &[HistoryEntry], filter: &ExportFilter, format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        export_history(entries.iter(), filter, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        let mut paid = entry(10, PaymentDirection::Incoming, 1_500_000, "order #1");
+        paid.invoice_id = Some("INV-1".to_string());
+        paid.tx_hash = Some(Hash::digest(&1u64));
+        let entries = vec![
+            paid,
+            entry(20, PaymentDirection::Outgoing, 42, "rent, \"May\""),
+        ];
+        let csv = export(&entries, &ExportFilter::default(), ExportFormat::Csv);
+        let expected = format!(
+            "timestamp,height,direction,amount,amount_formatted,comment,invoice_id,tx_hash\r\n\
+             10,10,incoming,1500000,1.500000,order #1,INV-1,{}\r\n\
+             20,20,outgoing,42,0.000042,\"rent, \"\"May\"\"\",,\r\n",
+            Hash::digest(&1u64).to_hex()
+        );
+        assert_eq!(csv, expected);
+    }
+
+    #[test]
+    fn json() {
+        let entries = vec![entry(10, PaymentDirection::Incoming, 1_000_000, "")];
+        let json = export(&entries, &ExportFilter::default(), ExportFormat::Json);
+        let rows: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let row = &rows[0];
+        assert_eq!(row.as_object().unwrap().len(), EXPORT_COLUMNS.len());
+        assert_eq!(row["timestamp"], 10);
+        assert_eq!(row["direction"], "incoming");
+        assert_eq!(row["amount"], 1_000_000);
+        assert_eq!(row["amount_formatted"], "1.000000");
+        assert_eq!(row["invoice_id"], serde_json::Value::Null);
+
+        let json = export(&[], &ExportFilter::default(), ExportFormat::Json);
+        let rows: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(rows.as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn filters() {
+        let entries = vec![
+            entry(10, PaymentDirection::Incoming, 1, ""),
+            entry(20, PaymentDirection::Outgoing, 2, ""),
+            entry(30, PaymentDirection::Incoming, 3, ""),
+        ];
+        let count = |filter: ExportFilter| {
+            export_history(entries.iter(), &filter, ExportFormat::Csv, &mut io::sink()).unwrap()
+        };
+        assert_eq!(count(ExportFilter::default()), 3);
+        let range = ExportFilter {
+            from: Some(10),
+            to: Some(30),
+            direction: None,
+        };
+        assert_eq!(count(range), 2);
+        let incoming = ExportFilter {
+            direction: Some(PaymentDirection::Incoming),
+            ..Default::default()
+        };
+        assert_eq!(count(incoming), 2);
+        let outgoing_since = ExportFilter {
+            from: Some(21),
+            to: None,
+            direction: Some(PaymentDirection::Outgoing),
+        };
+        assert_eq!(count(outgoing_since), 0);
+        assert_eq!(format_amount(-1_500_000), "-1.500000");
+    }
+}
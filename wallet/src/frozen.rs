@@ -0,0 +1,162 @@
+//
+// Copyright (c) 2018 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Outputs excluded from coin selection.
+//!
+
+use crate::error::WalletError;
+use log::*;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use stegos_crypto::hash::Hash;
+
+/// A persistent set of UTXOs which must not be spent automatically.
+pub(crate) struct FrozenOutputs {
+    /// Path to the file, one UTXO hash in HEX per line.
+    /// Empty path disables persistence.
+    path: PathBuf,
+    /// Frozen UTXOs.
+    outputs: HashSet<Hash>,
+}
+
+impl FrozenOutputs {
+    /// Load the list of frozen outputs from the file.
+    /// A missing file is treated as an empty list.
+    pub fn load(path: &str) -> Result<Self, WalletError> {
+        let path = PathBuf::from(path);
+        let mut outputs = HashSet::new();
+        if path.as_os_str().is_empty() {
+            return Ok(FrozenOutputs { path, outputs });
+        }
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(WalletError::FrozenOutputsIOError(
+                    path.to_string_lossy().to_string(),
+                    e.to_string(),
+                ));
+            }
+        };
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let utxo = Hash::try_from_hex(line).map_err(|_e| {
+                WalletError::InvalidFrozenOutputs(path.to_string_lossy().to_string(), lineno + 1)
+            })?;
+            outputs.insert(utxo);
+        }
+        debug!(
+            "Loaded frozen outputs: path={:?}, count={}",
+            path,
+            outputs.len()
+        );
+        Ok(FrozenOutputs { path, outputs })
+    }
+
+    /// Returns true if the output is frozen.
+    pub fn contains(&self, utxo: &Hash) -> bool {
+        self.outputs.contains(utxo)
+    }
+
+    /// Exclude the output from coin selection.
+    pub fn freeze(&mut self, utxo: Hash) -> Result<(), WalletError> {
+        if !self.outputs.insert(utxo) {
+            return Err(WalletError::AlreadyFrozen(utxo));
+        }
+        self.save()
+    }
+
+    /// Return the output back to coin selection.
+    pub fn unfreeze(&mut self, utxo: &Hash) -> Result<(), WalletError> {
+        if !self.outputs.remove(utxo) {
+            return Err(WalletError::NotFrozen(*utxo));
+        }
+        self.save()
+    }
+
+    /// Forget about the spent output.
+    pub fn prune(&mut self, utxo: &Hash) {
+        if self.outputs.remove(utxo) {
+            info!("Frozen output has been spent: utxo={}", utxo);
+            if let Err(e) = self.save() {
+                error!("{}", e);
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), WalletError> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let mut contents = String::new();
+        for utxo in &self.outputs {
+            contents.push_str(&utxo.to_hex());
+            contents.push('\n');
+        }
+        fs::write(&self.path, contents).map_err(|e| {
+            WalletError::FrozenOutputsIOError(
+                self.path.to_string_lossy().to_string(),
+                e.to_string(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn freeze_unfreeze() {
+        let path = env::temp_dir().join(format!("stegos-frozen-{}", Hash::digest(&"test")));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+
+        let utxo1 = Hash::digest(&1u64);
+        let utxo2 = Hash::digest(&2u64);
+
+        let mut frozen = FrozenOutputs::load(&path).expect("missing file is ok");
+        assert!(!frozen.contains(&utxo1));
+        frozen.freeze(utxo1).unwrap();
+        frozen.freeze(utxo2).unwrap();
+        frozen.freeze(utxo1).unwrap_err();
+        frozen.unfreeze(&utxo2).unwrap();
+        frozen.unfreeze(&utxo2).unwrap_err();
+
+        // Reload from disk.
+        let mut frozen = FrozenOutputs::load(&path).expect("file is valid");
+        assert!(frozen.contains(&utxo1));
+        assert!(!frozen.contains(&utxo2));
+        frozen.prune(&utxo1);
+        let frozen = FrozenOutputs::load(&path).expect("file is valid");
+        assert!(!frozen.contains(&utxo1));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
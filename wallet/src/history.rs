@@ -0,0 +1,388 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Persistent history of incoming and outgoing payments.
+//!
+
+use crate::api::{HistoryEntry, PaymentDirection};
+use crate::error::WalletError;
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use stegos_crypto::hash::Hash;
+
+/// A persistent log of payments, ordered by height.
+pub(crate) struct WalletHistory {
+    /// Path to the file, one entry in JSON per line.
+    /// Empty path disables persistence.
+    path: PathBuf,
+    /// Recorded payments, oldest first.
+    entries: Vec<HistoryEntry>,
+    /// Positions in `entries` by invoice_id.
+    by_invoice: HashMap<String, Vec<usize>>,
+    /// Comments and invoices of sent but not yet confirmed payments, by tx_hash.
+    pending: HashMap<Hash, (String, Option<String>)>,
+    /// The height of the last irreversible block.
+    finalized_height: u64,
+}
+
+impl WalletHistory {
+    /// Load the history from the file.
+    /// A missing file is treated as an empty history.
+    pub fn load(path: &str) -> Result<Self, WalletError> {
+        let path = PathBuf::from(path);
+        let mut entries: Vec<HistoryEntry> = Vec::new();
+        let by_invoice = HashMap::new();
+        let pending = HashMap::new();
+        if path.as_os_str().is_empty() {
+            return Ok(WalletHistory {
+                path,
+                entries,
+                by_invoice,
+                pending,
+                finalized_height: 0,
+            });
+        }
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(WalletError::HistoryIOError(
+                    path.to_string_lossy().to_string(),
+                    e.to_string(),
+                ));
+            }
+        };
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let entry: HistoryEntry = serde_json::from_str(line).map_err(|_e| {
+                WalletError::InvalidHistory(path.to_string_lossy().to_string(), lineno + 1)
+            })?;
+            entries.push(entry);
+        }
+        debug!("Loaded history: path={:?}, count={}", path, entries.len());
+        let mut history = WalletHistory {
+            path,
+            entries,
+            by_invoice,
+            pending,
+            finalized_height: 0,
+        };
+        history.reindex();
+        Ok(history)
+    }
+
+    /// Rebuild the index by invoice_id.
+    fn reindex(&mut self) {
+        self.by_invoice.clear();
+        for (pos, entry) in self.entries.iter().enumerate() {
+            if let Some(invoice_id) = &entry.invoice_id {
+                self.by_invoice
+                    .entry(invoice_id.clone())
+                    .or_insert_with(Vec::new)
+                    .push(pos);
+            }
+        }
+    }
+
+    /// Remember the comment and the invoice of a sent payment until it is confirmed.
+    pub fn set_pending(&mut self, tx_hash: Hash, comment: String, invoice_id: Option<String>) {
+        self.pending.insert(tx_hash, (comment, invoice_id));
+    }
+
+    /// Take the comment and the invoice of a confirmed outgoing payment.
+    pub fn take_pending(&mut self, tx_hash: &Hash) -> (String, Option<String>) {
+        self.pending.remove(tx_hash).unwrap_or_default()
+    }
+
+    /// Append payments confirmed by the block at `height`.
+    /// Blocks which have already been recorded are skipped.
+    pub fn record(&mut self, height: u64, entries: Vec<HistoryEntry>) -> Result<(), WalletError> {
+        if let Some(last) = self.entries.last() {
+            if last.height >= height {
+                debug!(
+                    "Block is already in history: height={}, last_height={}",
+                    height, last.height
+                );
+                return Ok(());
+            }
+        }
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut entries = entries;
+        for entry in &mut entries {
+            entry.irreversible = entry.height <= self.finalized_height;
+        }
+        for entry in &entries {
+            info!(
+                "Recorded payment: direction={:?}, amount={}, tx_hash={:?}, height={}",
+                entry.direction, entry.amount, entry.tx_hash, entry.height
+            );
+        }
+        self.append(&entries)?;
+        for entry in entries {
+            if let Some(invoice_id) = &entry.invoice_id {
+                self.by_invoice
+                    .entry(invoice_id.clone())
+                    .or_insert_with(Vec::new)
+                    .push(self.entries.len());
+            }
+            self.entries.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Forget payments confirmed by reverted blocks, starting from `height`.
+    pub fn rollback(&mut self, height: u64) -> Result<(), WalletError> {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.height < height);
+        if self.entries.len() == len {
+            return Ok(());
+        }
+        info!(
+            "Reverted payments from history: height={}, count={}",
+            height,
+            len - self.entries.len()
+        );
+        self.reindex();
+        self.save()
+    }
+
+    /// Mark payments confirmed up to `height` as irreversible.
+    /// Returns newly finalized payments, oldest first.
+    pub fn finalize(&mut self, height: u64) -> Vec<HistoryEntry> {
+        if height < self.finalized_height {
+            return Vec::new();
+        }
+        self.finalized_height = height;
+        let mut finalized = Vec::new();
+        for entry in self.entries.iter_mut().rev() {
+            if entry.irreversible {
+                break;
+            }
+            if entry.height <= height {
+                entry.irreversible = true;
+                finalized.push(entry.clone());
+            }
+        }
+        finalized.reverse();
+        finalized
+    }
+
+    /// The total number of recorded payments.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Iterate over all payments, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    /// Return up to `limit` payments, newest first, skipping the first `offset`.
+    pub fn page(&self, offset: usize, limit: usize) -> Vec<HistoryEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Return tx_hash and amount of outgoing payments confirmed since the timestamp.
+    pub fn outgoing_since(&self, since: SystemTime) -> Vec<(Option<Hash>, i64)> {
+        self.entries
+            .iter()
+            .rev()
+            .take_while(|entry| entry.timestamp >= since)
+            .filter(|entry| entry.direction == PaymentDirection::Outgoing)
+            .map(|entry| (entry.tx_hash, entry.amount))
+            .collect()
+    }
+
+    /// Return payments for the invoice, oldest first.
+    pub fn by_invoice(&self, invoice_id: &str) -> Vec<HistoryEntry> {
+        match self.by_invoice.get(invoice_id) {
+            Some(positions) => positions
+                .iter()
+                .map(|pos| self.entries[*pos].clone())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn io_error(&self, e: io::Error) -> WalletError {
+        WalletError::HistoryIOError(self.path.to_string_lossy().to_string(), e.to_string())
+    }
+
+    fn serialize(entries: &[HistoryEntry]) -> String {
+        let mut contents = String::new();
+        for entry in entries {
+            contents.push_str(&serde_json::to_string(entry).expect("serializable"));
+            contents.push('\n');
+        }
+        contents
+    }
+
+    fn append(&self, entries: &[HistoryEntry]) -> Result<(), WalletError> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let contents = Self::serialize(entries);
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|e| self.io_error(e))
+    }
+
+    fn save(&self) -> Result<(), WalletError> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let contents = Self::serialize(&self.entries);
+        fs::write(&self.path, contents).map_err(|e| self.io_error(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(height: u64, direction: PaymentDirection, amount: i64) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: UNIX_EPOCH + Duration::from_secs(height),
+            direction,
+            amount,
+            comment: format!("block {}", height),
+            invoice_id: Some(format!("INV-{}", height % 2)),
+            tx_hash: Some(Hash::digest(&height)),
+            height,
+            irreversible: false,
+        }
+    }
+
+    #[test]
+    fn record_rollback() {
+        let path = env::temp_dir().join(format!("stegos-history-{}", Hash::digest(&"test")));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+
+        let mut history = WalletHistory::load(&path).expect("missing file is ok");
+        assert_eq!(history.len(), 0);
+        history
+            .record(1, vec![entry(1, PaymentDirection::Incoming, 100)])
+            .unwrap();
+        history
+            .record(
+                2,
+                vec![
+                    entry(2, PaymentDirection::Outgoing, 10),
+                    entry(2, PaymentDirection::Incoming, 5),
+                ],
+            )
+            .unwrap();
+        // Already recorded.
+        history
+            .record(2, vec![entry(2, PaymentDirection::Incoming, 5)])
+            .unwrap();
+        history
+            .record(3, vec![entry(3, PaymentDirection::Incoming, 1)])
+            .unwrap();
+        assert_eq!(history.len(), 4);
+
+        // Reload from disk.
+        let mut history = WalletHistory::load(&path).expect("file is valid");
+        assert_eq!(history.len(), 4);
+        let page = history.page(0, 2);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0], entry(3, PaymentDirection::Incoming, 1));
+        assert_eq!(page[1], entry(2, PaymentDirection::Incoming, 5));
+        let page = history.page(3, 10);
+        assert_eq!(page, vec![entry(1, PaymentDirection::Incoming, 100)]);
+        assert!(history.page(4, 10).is_empty());
+        let invoice = history.by_invoice("INV-1");
+        assert_eq!(invoice.len(), 2);
+        assert_eq!(invoice[0].height, 1);
+        assert_eq!(invoice[1].height, 3);
+        assert!(history.by_invoice("INV-2").is_empty());
+
+        // Revert the last two blocks.
+        history.rollback(2).unwrap();
+        assert_eq!(history.len(), 1);
+        let history = WalletHistory::load(&path).expect("file is valid");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.page(0, 10)[0].height, 1);
+        assert_eq!(history.by_invoice("INV-1").len(), 1);
+        assert!(history.by_invoice("INV-0").is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn finalize() {
+        let mut history = WalletHistory::load("").expect("persistence is disabled");
+        for height in 1..4 {
+            history
+                .record(height, vec![entry(height, PaymentDirection::Incoming, 1)])
+                .unwrap();
+        }
+        assert!(history.iter().all(|entry| !entry.irreversible));
+
+        let finalized = history.finalize(2);
+        let heights: Vec<u64> = finalized.iter().map(|entry| entry.height).collect();
+        assert_eq!(heights, vec![1, 2]);
+        assert!(finalized.iter().all(|entry| entry.irreversible));
+        assert!(!history.page(0, 1)[0].irreversible);
+        assert!(history.finalize(1).is_empty());
+        assert!(history.finalize(2).is_empty());
+
+        // Blocks below the finalized height are recorded as irreversible.
+        let mut history = WalletHistory::load("").expect("persistence is disabled");
+        history.finalize(5);
+        history
+            .record(4, vec![entry(4, PaymentDirection::Outgoing, 1)])
+            .unwrap();
+        history
+            .record(6, vec![entry(6, PaymentDirection::Outgoing, 1)])
+            .unwrap();
+        let page = history.page(0, 10);
+        assert!(!page[0].irreversible);
+        assert!(page[1].irreversible);
+        let finalized = history.finalize(6);
+        assert_eq!(finalized.len(), 1);
+        assert_eq!(finalized[0].height, 6);
+    }
+}
@@ -0,0 +1,454 @@
+//! Wallet - Payment Invoices.
+
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Invoices are issued by the recipient and encoded as a URI, suitable for QR codes:
+//!
+//! ```text
+//! stegos:ADDRESS?amount=AMOUNT&invoice=ID&expiry=UNIX_SECS[&memo=TEXT]
+//! ```
+//!
+//! The payer sends the invoice id in the payment memo, so the recipient can match
+//! incoming payments to issued invoices. Payment outputs are cloaked, therefore
+//! invoices use the wallet address instead of one-time derived addresses.
+//!
+
+use crate::error::WalletError;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+
+/// URI scheme of encoded invoices.
+const INVOICE_SCHEME: &str = "stegos:";
+/// Length of generated invoice identifiers, in bytes.
+const INVOICE_ID_LEN: usize = 8;
+
+/// A request for payment issued by the recipient.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    pub invoice_id: String,
+    pub recipient: PublicKey,
+    pub amount: i64,
+    #[serde(default)]
+    pub memo: String,
+    /// The invoice can't be paid after this time.
+    pub expiry: SystemTime,
+}
+
+impl Invoice {
+    /// Encode as a compact URI.
+    pub fn encode(&self) -> String {
+        let expiry = self
+            .expiry
+            .duration_since(UNIX_EPOCH)
+            .expect("time is after UNIX epoch")
+            .as_secs();
+        let mut uri = format!(
+            "{}{}?amount={}&invoice={}&expiry={}",
+            INVOICE_SCHEME,
+            self.recipient.to_address(),
+            self.amount,
+            percent_encode(&self.invoice_id),
+            expiry
+        );
+        if !self.memo.is_empty() {
+            uri.push_str("&memo=");
+            uri.push_str(&percent_encode(&self.memo));
+        }
+        uri
+    }
+
+    /// Parse an invoice encoded by `encode()`.
+    pub fn decode(uri: &str) -> Result<Self, WalletError> {
+        let invalid = |reason: &str| WalletError::InvalidInvoice(reason.to_string());
+        let uri = uri.trim();
+        if !uri.starts_with(INVOICE_SCHEME) {
+            return Err(invalid("missing scheme"));
+        }
+        let uri = &uri[INVOICE_SCHEME.len()..];
+        let (address, query) = match uri.find('?') {
+            Some(pos) => (&uri[..pos], &uri[pos + 1..]),
+            None => return Err(invalid("missing parameters")),
+        };
+        let recipient = PublicKey::from_str(address).map_err(|_e| invalid("invalid address"))?;
+
+        let mut amount: Option<i64> = None;
+        let mut invoice_id: Option<String> = None;
+        let mut expiry: Option<u64> = None;
+        let mut memo = String::new();
+        for param in query.split('&') {
+            let (key, value) = match param.find('=') {
+                Some(pos) => (&param[..pos], &param[pos + 1..]),
+                None => return Err(invalid("invalid parameter")),
+            };
+            match key {
+                "amount" => amount = Some(value.parse().map_err(|_e| invalid("invalid amount"))?),
+                "invoice" => invoice_id = Some(percent_decode(value)?),
+                "expiry" => expiry = Some(value.parse().map_err(|_e| invalid("invalid expiry"))?),
+                "memo" => memo = percent_decode(value)?,
+                // Ignore unknown parameters for forward compatibility.
+                _ => {}
+            }
+        }
+
+        let amount = amount.ok_or_else(|| invalid("missing amount"))?;
+        if amount <= 0 {
+            return Err(invalid("invalid amount"));
+        }
+        let invoice_id = invoice_id.ok_or_else(|| invalid("missing invoice"))?;
+        if invoice_id.is_empty() {
+            return Err(invalid("missing invoice"));
+        }
+        let expiry = expiry.ok_or_else(|| invalid("missing expiry"))?;
+        Ok(Invoice {
+            invoice_id,
+            recipient,
+            amount,
+            memo,
+            expiry: UNIX_EPOCH + Duration::from_secs(expiry),
+        })
+    }
+
+    /// Returns true if the invoice can't be paid anymore.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        now > self.expiry
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Result<String, WalletError> {
+    let invalid = || WalletError::InvalidInvoice("invalid escape sequence".to_string());
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_e| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_e| invalid())
+}
+
+/// Status of an issued invoice.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    Open,
+    Paid,
+    Expired,
+}
+
+/// An invoice issued by this wallet and payments received for it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IssuedInvoice {
+    pub invoice: Invoice,
+    /// Total amount received for the invoice.
+    pub received: i64,
+    /// Outputs which paid the invoice.
+    pub utxos: Vec<Hash>,
+    /// Calculated on request, not persisted.
+    #[serde(skip_deserializing)]
+    pub status: Option<InvoiceStatus>,
+}
+
+impl IssuedInvoice {
+    fn status(&self, now: SystemTime) -> InvoiceStatus {
+        if self.received >= self.invoice.amount {
+            InvoiceStatus::Paid
+        } else if self.invoice.is_expired(now) {
+            InvoiceStatus::Expired
+        } else {
+            InvoiceStatus::Open
+        }
+    }
+}
+
+/// A persistent list of invoices issued by this wallet.
+pub(crate) struct IssuedInvoices {
+    /// Path to the file, one invoice in JSON per line.
+    /// Empty path disables persistence.
+    path: PathBuf,
+    /// Invoices by invoice_id.
+    invoices: HashMap<String, IssuedInvoice>,
+}
+
+impl IssuedInvoices {
+    /// Load issued invoices from the file.
+    /// A missing file is treated as an empty list.
+    pub fn load(path: &str) -> Result<Self, WalletError> {
+        let mut issued = IssuedInvoices {
+            path: PathBuf::from(path),
+            invoices: HashMap::new(),
+        };
+        if issued.path.as_os_str().is_empty() {
+            return Ok(issued);
+        }
+
+        let contents = match fs::read_to_string(&issued.path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(issued.io_error(e)),
+        };
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invoice: IssuedInvoice = serde_json::from_str(line).map_err(|_e| {
+                WalletError::InvalidInvoices(issued.path.to_string_lossy().to_string(), lineno + 1)
+            })?;
+            issued
+                .invoices
+                .insert(invoice.invoice.invoice_id.clone(), invoice);
+        }
+        debug!(
+            "Loaded invoices: path={:?}, count={}",
+            issued.path,
+            issued.invoices.len()
+        );
+        Ok(issued)
+    }
+
+    /// Issue a new invoice.
+    pub fn issue(
+        &mut self,
+        recipient: PublicKey,
+        amount: i64,
+        memo: String,
+        lifetime: Duration,
+        now: SystemTime,
+    ) -> Result<Invoice, WalletError> {
+        if amount <= 0 {
+            return Err(WalletError::NegativeAmount(amount));
+        }
+        let mut hasher = Hasher::new();
+        recipient.hash(&mut hasher);
+        amount.hash(&mut hasher);
+        memo.hash(&mut hasher);
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        since_epoch.as_secs().hash(&mut hasher);
+        (since_epoch.subsec_nanos() as u64).hash(&mut hasher);
+        (self.invoices.len() as u64).hash(&mut hasher);
+        let invoice_id = hex::encode(&hasher.result().base_vector()[..INVOICE_ID_LEN]);
+
+        let invoice = Invoice {
+            invoice_id: invoice_id.clone(),
+            recipient,
+            amount,
+            memo,
+            expiry: now + lifetime,
+        };
+        info!(
+            "Issued invoice: invoice_id={}, amount={}",
+            invoice_id, amount
+        );
+        let issued = IssuedInvoice {
+            invoice: invoice.clone(),
+            received: 0,
+            utxos: Vec::new(),
+            status: None,
+        };
+        self.invoices.insert(invoice_id, issued);
+        self.save()?;
+        Ok(invoice)
+    }
+
+    ///
+    /// Account a payment for the invoice.
+    ///
+    /// Returns the invoice if it has just been paid in full.
+    ///
+    pub fn on_payment(
+        &mut self,
+        invoice_id: &str,
+        utxo: Hash,
+        amount: i64,
+        now: SystemTime,
+    ) -> Option<IssuedInvoice> {
+        let issued = self.invoices.get_mut(invoice_id)?;
+        if issued.utxos.contains(&utxo) {
+            return None; // Already accounted, e.g. on recovery.
+        }
+        let was_paid = issued.received >= issued.invoice.amount;
+        issued.received += amount;
+        issued.utxos.push(utxo);
+        info!(
+            "Received payment for invoice: invoice_id={}, utxo={}, amount={}, received={}",
+            invoice_id, utxo, amount, issued.received
+        );
+        let status = issued.status(now);
+        let paid = if !was_paid && status == InvoiceStatus::Paid {
+            let mut issued = issued.clone();
+            issued.status = Some(status);
+            Some(issued)
+        } else {
+            None
+        };
+        if let Err(e) = self.save() {
+            error!("{}", e);
+        }
+        paid
+    }
+
+    /// Returns issued invoices with their current status, newest first.
+    pub fn list(&self, now: SystemTime) -> Vec<IssuedInvoice> {
+        let mut invoices: Vec<IssuedInvoice> = self
+            .invoices
+            .values()
+            .map(|issued| {
+                let mut issued = issued.clone();
+                issued.status = Some(issued.status(now));
+                issued
+            })
+            .collect();
+        invoices.sort_by(|a, b| b.invoice.expiry.cmp(&a.invoice.expiry));
+        invoices
+    }
+
+    fn io_error(&self, e: io::Error) -> WalletError {
+        WalletError::InvoicesIOError(self.path.to_string_lossy().to_string(), e.to_string())
+    }
+
+    fn save(&self) -> Result<(), WalletError> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let mut contents = String::new();
+        for issued in self.invoices.values() {
+            contents.push_str(&serde_json::to_string(issued).expect("serializable"));
+            contents.push('\n');
+        }
+        fs::write(&self.path, contents).map_err(|e| self.io_error(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use stegos_crypto::curve1174::make_random_keys;
+
+    #[test]
+    fn encode_decode() {
+        let (_skey, recipient) = make_random_keys();
+        let invoice = Invoice {
+            invoice_id: "0123abcd".to_string(),
+            recipient,
+            amount: 1_500_000,
+            memo: "Order #42: coffee & beans".to_string(),
+            expiry: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+        };
+        let uri = invoice.encode();
+        assert!(uri.starts_with("stegos:stt1"));
+        assert!(!uri.contains(' '));
+        assert_eq!(Invoice::decode(&uri).unwrap(), invoice);
+
+        let invoice = Invoice {
+            memo: String::new(),
+            ..invoice
+        };
+        let uri = invoice.encode();
+        assert!(!uri.contains("memo="));
+        assert_eq!(Invoice::decode(&uri).unwrap(), invoice);
+
+        let uri = uri.replace("amount=1500000", "amount=-1");
+        assert!(Invoice::decode(&uri).is_err());
+        assert!(Invoice::decode("bitcoin:abc?amount=1").is_err());
+        let uri = format!("stegos:{}?amount=1&expiry=1", recipient.to_address());
+        assert!(Invoice::decode(&uri).is_err());
+    }
+
+    #[test]
+    fn issue_and_pay() {
+        let path = env::temp_dir().join(format!("stegos-invoices-{}", Hash::digest(&"test")));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+
+        let (_skey, recipient) = make_random_keys();
+        let now = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let lifetime = Duration::from_secs(3600);
+        let mut issued = IssuedInvoices::load(&path).expect("missing file is ok");
+        let invoice = issued
+            .issue(recipient, 100, "memo".to_string(), lifetime, now)
+            .unwrap();
+        let invoice2 = issued
+            .issue(recipient, 100, "memo".to_string(), lifetime, now)
+            .unwrap();
+        assert_ne!(invoice.invoice_id, invoice2.invoice_id);
+
+        let utxo1 = Hash::digest(&1u64);
+        let utxo2 = Hash::digest(&2u64);
+        assert!(issued
+            .on_payment(&invoice.invoice_id, utxo1, 60, now)
+            .is_none());
+        // Recovery doesn't account the same output twice.
+        assert!(issued
+            .on_payment(&invoice.invoice_id, utxo1, 60, now)
+            .is_none());
+        assert!(issued.on_payment("unknown", utxo2, 60, now).is_none());
+        let paid = issued
+            .on_payment(&invoice.invoice_id, utxo2, 40, now)
+            .expect("paid");
+        assert_eq!(paid.received, 100);
+        assert_eq!(paid.status, Some(InvoiceStatus::Paid));
+
+        // Reload from disk.
+        let issued = IssuedInvoices::load(&path).expect("file is valid");
+        let later = now + lifetime * 2;
+        let list = issued.list(later);
+        assert_eq!(list.len(), 2);
+        for issued in list {
+            if issued.invoice.invoice_id == invoice.invoice_id {
+                assert_eq!(issued.status, Some(InvoiceStatus::Paid));
+            } else {
+                assert_eq!(issued.status, Some(InvoiceStatus::Expired));
+            }
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -25,20 +25,42 @@
 
 mod api;
 mod change;
+mod channels;
+mod churn;
+mod config;
 mod error;
+mod export;
+mod frozen;
+mod history;
+mod invoice;
+mod manager;
 mod metrics;
+mod policy;
 mod transaction;
 mod valueshuffle;
+mod watch;
 
 #[cfg(test)]
 mod tests;
 
 pub use crate::api::*;
+use crate::channels::{Channel, ChannelMessage, Channels, CHANNELS_TOPIC};
+use crate::churn::{churn_delay, create_churn_transaction, ChurnBudget};
+pub use crate::config::*;
 use crate::error::WalletError;
+use crate::export::{export_history, ExportFilter};
+use crate::frozen::FrozenOutputs;
+use crate::history::WalletHistory;
+use crate::invoice::IssuedInvoices;
+pub use crate::manager::WalletManagerService;
+use crate::policy::SpendPolicy;
+pub use crate::policy::{Spend, SpendRule};
 use crate::transaction::*;
 use crate::valueshuffle::ValueShuffle;
+use crate::watch::{DepositEvent, WatchList};
 use failure::Error;
 use futures::sync::mpsc::unbounded;
+use futures::sync::mpsc::UnboundedReceiver;
 use futures::sync::mpsc::UnboundedSender;
 use futures::sync::oneshot;
 use futures::Async;
@@ -48,14 +70,23 @@ use futures::Stream;
 use futures_stream_select_all_send::select_all;
 use log::*;
 use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
 use stegos_blockchain::*;
 use stegos_crypto::curve1174::PublicKey;
 use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc;
 use stegos_keychain::KeyChain;
 use stegos_network::Network;
 use stegos_node::EpochChanged;
 use stegos_node::Node;
+use stegos_node::NodeRequest;
+use stegos_node::NodeResponse;
 use stegos_node::OutputsChanged;
+use stegos_serialization::traits::ProtoConvert;
+use tokio_timer::{Delay, Interval};
+
+/// How often check the status of unprocessed transactions and resend them.
+const RESEND_TX_INTERVAL: Duration = Duration::from_secs(30);
 
 struct PaymentValue {
     output: PaymentOutput,
@@ -86,6 +117,7 @@ impl StakeValue {
             amount: self.output.amount,
             active_until_epoch: self.active_until_epoch,
             is_active,
+            operator: self.output.operator,
         }
     }
 }
@@ -128,14 +160,42 @@ impl From<Transaction> for SavedTransaction {
 }
 
 pub struct WalletService {
+    /// Identifier of the chain.
+    chain_id: Hash,
     /// Keys.
     keys: KeyChain,
     /// Current Epoch.
     epoch: u64,
     /// Unspent Payment UXTO.
     payments: HashMap<Hash, PaymentValue>,
+    /// Heights of received payments which are not confirmed yet.
+    unconfirmed: HashMap<Hash, u64>,
     /// Unspent Stake UTXO.
     stakes: HashMap<Hash, StakeValue>,
+    /// Unspent hash-locked UTXO of atomic swaps, sent or received.
+    swaps: HashMap<Hash, PublicPaymentOutput>,
+    /// Payment channels, by the hash of the funding output.
+    channels: Channels,
+    /// Unspent public UTXO received from channel settlements.
+    public_payments: HashMap<Hash, PublicPaymentOutput>,
+    /// Public UTXO with a sent spending transaction.
+    claimed: HashSet<Hash>,
+    /// UTXO excluded from coin selection.
+    frozen: FrozenOutputs,
+    /// Unspent block rewards received since startup.
+    rewards: HashSet<Hash>,
+    /// Unspent payments by invoice_id.
+    invoices: HashMap<String, HashSet<Hash>>,
+    /// Invoices issued by this wallet.
+    issued_invoices: IssuedInvoices,
+    /// History of confirmed payments.
+    history: WalletHistory,
+    /// Watched public keys, expected outputs and found deposits.
+    watch: WatchList,
+    /// Reject all requests which use the secret key.
+    watch_only: bool,
+    /// Configuration.
+    cfg: WalletConfig,
     /// ValueShuffle State.
     vs: ValueShuffle,
 
@@ -145,17 +205,47 @@ pub struct WalletService {
     stake_fee: i64,
     /// Lifetime of stake.
     stake_epochs: u64,
+    /// Maximal number of inputs in a transaction.
+    max_inputs_per_tx: usize,
 
     /// Node API.
     node: Node,
+    /// Network API.
+    network: Network,
+
+    /// Payments exported for offline signing, by hash.
+    offline_transactions: HashMap<Hash, UnsignedTransaction>,
+
+    /// Rules evaluated before signing payments.
+    policy: SpendPolicy,
+    /// Payments waiting for the second approver, by hash.
+    pending_approvals: HashMap<
+        Hash,
+        (
+            UnsignedTransaction,
+            Vec<(PublicKey, i64, PaymentPayloadData)>,
+        ),
+    >,
 
     /// Map of inputs of transaction interests, that we wait for.
     transactions_interest: HashMap<Hash, Hash>,
 
+    /// Fees spent on self-spends.
+    churn_budget: ChurnBudget,
+
     /// Set of unprocessed transactions, with pending sender.
     unprocessed_transactions:
         HashMap<Hash, (SavedTransaction, Vec<oneshot::Sender<WalletResponse>>)>,
 
+    /// Pending requests to the node on behalf of API callers.
+    node_requests: Vec<(
+        oneshot::Receiver<NodeResponse>,
+        oneshot::Sender<WalletResponse>,
+    )>,
+
+    /// Pending requests for status of unprocessed transactions.
+    status_requests: HashMap<Hash, oneshot::Receiver<NodeResponse>>,
+
     /// Triggered when state has changed.
     subscribers: Vec<UnboundedSender<WalletNotification>>,
 
@@ -166,15 +256,48 @@ pub struct WalletService {
 impl WalletService {
     /// Create a new wallet.
     pub fn new(
+        cfg: &WalletConfig,
+        chain_id: Hash,
+        keys: KeyChain,
+        network: Network,
+        node: Node,
+        payment_fee: i64,
+        stake_fee: i64,
+        stake_epochs: u64,
+        max_inputs_per_tx: usize,
+        persistent_state: Vec<(Output, u64)>,
+    ) -> Result<(Self, Wallet), Error> {
+        let outputs_changed = node.subscribe_outputs_changed();
+        Self::with_outputs(
+            cfg,
+            chain_id,
+            keys,
+            network,
+            node,
+            payment_fee,
+            stake_fee,
+            stake_epochs,
+            max_inputs_per_tx,
+            persistent_state,
+            outputs_changed,
+        )
+    }
+
+    /// Create a new wallet, which is subscribed to UTXO changes following `persistent_state`.
+    pub(crate) fn with_outputs(
+        cfg: &WalletConfig,
+        chain_id: Hash,
         keys: KeyChain,
         network: Network,
         node: Node,
         payment_fee: i64,
         stake_fee: i64,
         stake_epochs: u64,
+        max_inputs_per_tx: usize,
         persistent_state: Vec<(Output, u64)>,
-    ) -> (Self, Wallet) {
-        info!("My wallet key: {}", keys.wallet_pkey.to_hex());
+        outputs_changed: UnboundedReceiver<OutputsChanged>,
+    ) -> Result<(Self, Wallet), Error> {
+        info!("My wallet address: {}", keys.wallet_pkey.to_address());
         debug!("My network key: {}", keys.network_pkey.to_hex());
         //
         // State.
@@ -182,7 +305,22 @@ impl WalletService {
         let epoch = 0;
         let payments: HashMap<Hash, PaymentValue> = HashMap::new();
         let stakes: HashMap<Hash, StakeValue> = HashMap::new();
+        let swaps: HashMap<Hash, PublicPaymentOutput> = HashMap::new();
+        let channels = Channels::load(&cfg.channels_file)?;
+        let public_payments: HashMap<Hash, PublicPaymentOutput> = HashMap::new();
+        let claimed: HashSet<Hash> = HashSet::new();
+        let frozen = FrozenOutputs::load(&cfg.frozen_outputs_file)?;
+        let rewards: HashSet<Hash> = HashSet::new();
+        let invoices: HashMap<String, HashSet<Hash>> = HashMap::new();
+        let issued_invoices = IssuedInvoices::load(&cfg.invoices_file)?;
+        let history = WalletHistory::load(&cfg.history_file)?;
+        let watch = WatchList::load(&cfg.watch_file, cfg.deposit_confirmations)?;
+        let watch_only = cfg.watch_only;
+        if watch_only {
+            info!("Wallet is in watch-only mode");
+        }
         let vs = ValueShuffle::new(
+            chain_id,
             keys.wallet_skey.clone(),
             keys.wallet_pkey.clone(),
             keys.network_pkey.clone(),
@@ -190,8 +328,16 @@ impl WalletService {
             node.clone(),
         );
 
+        let offline_transactions = HashMap::new();
+        let policy = SpendPolicy::new(keys.spend_policy()?);
+        if !policy.definitions().is_empty() {
+            info!("Loaded spend policy: rules={}", policy.definitions().len());
+        }
+        let pending_approvals = HashMap::new();
         let transactions_interest = HashMap::new();
         let unprocessed_transactions = HashMap::new();
+        let node_requests = Vec::new();
+        let status_requests = HashMap::new();
 
         //
         // Subscriptions.
@@ -213,28 +359,101 @@ impl WalletService {
             .map(|epoch| WalletEvent::NodeEpochChanged(epoch));
         events.push(Box::new(node_epochs));
 
+        // Irreversible blocks.
+        let node_finality = node
+            .subscribe_finality_changed()
+            .map(|commitment| WalletEvent::NodeFinalityChanged(commitment));
+        events.push(Box::new(node_finality));
+
         // UTXO changes.
-        let node_outputs = node
-            .subscribe_outputs_changed()
-            .map(|outputs| WalletEvent::NodeOutputsChanged(outputs));
+        let node_outputs = outputs_changed.map(|outputs| WalletEvent::NodeOutputsChanged(outputs));
         events.push(Box::new(node_outputs));
 
+        // Conflicts of sent transactions.
+        let node_conflicts = node
+            .subscribe_transaction_conflicts()
+            .map(|conflict| WalletEvent::NodeTransactionConflict(conflict));
+        events.push(Box::new(node_conflicts));
+
+        // Resend timer.
+        let resend_timer = Interval::new_interval(RESEND_TX_INTERVAL)
+            .map(|_i| WalletEvent::ResendTransactions)
+            .map_err(|_e| ()); // ignore transient timer errors
+        events.push(Box::new(resend_timer));
+
+        // Payment channels.
+        let channel_messages = network
+            .subscribe_unicast(CHANNELS_TOPIC)?
+            .map(|m| WalletEvent::ChannelMessage(m.from, m.data));
+        events.push(Box::new(channel_messages));
+
+        // Staking of block rewards.
+        if cfg.compound_rewards && !watch_only {
+            info!(
+                "Compounding rewards: threshold={}, interval={}s, max_stake={}",
+                cfg.compound_threshold, cfg.compound_interval, cfg.compound_max_stake
+            );
+            let compound_timer = Interval::new_interval(Duration::from_secs(cfg.compound_interval))
+                .map(|_i| WalletEvent::CompoundRewards)
+                .map_err(|_e| ()); // ignore transient timer errors
+            events.push(Box::new(compound_timer));
+        }
+
+        // Self-spends at random intervals.
+        if cfg.churn_outputs && cfg.churn_budget > 0 && !watch_only {
+            info!(
+                "Churning outputs: interval={}s, budget={}",
+                cfg.churn_interval, cfg.churn_budget
+            );
+            let interval = Duration::from_secs(cfg.churn_interval);
+            let churn_timer = futures::stream::unfold((), move |()| {
+                let delay = churn_delay(interval, &mut rand::thread_rng());
+                let timer = Delay::new(Instant::now() + delay);
+                Some(timer.map(|()| (WalletEvent::ChurnOutputs, ())))
+            })
+            .map_err(|_e| ()); // ignore transient timer errors
+            events.push(Box::new(churn_timer));
+        }
+        let churn_budget = ChurnBudget::new(cfg.churn_budget);
+
         let events = select_all(events);
 
         let mut service = WalletService {
+            chain_id,
             epoch,
             keys,
             payments,
+            unconfirmed: HashMap::new(),
             stakes,
+            swaps,
+            channels,
+            public_payments,
+            claimed,
+            frozen,
+            rewards,
+            invoices,
+            issued_invoices,
+            history,
+            watch,
+            watch_only,
+            cfg: cfg.clone(),
             vs,
             payment_fee,
             stake_fee,
             stake_epochs,
+            max_inputs_per_tx,
             node,
+            network,
             subscribers,
             events,
+            offline_transactions,
+            policy,
+            pending_approvals,
             transactions_interest,
+            churn_budget,
             unprocessed_transactions,
+            node_requests,
+            status_requests,
         };
 
         // Recover state.
@@ -248,7 +467,7 @@ impl WalletService {
 
         let api = Wallet { outbox };
 
-        (service, api)
+        Ok((service, api))
     }
 
     /// Send money.
@@ -259,18 +478,630 @@ impl WalletService {
         comment: String,
     ) -> Result<(Hash, i64), Error> {
         let data = PaymentPayloadData::Comment(comment);
-        let unspent_iter = self.payments.values().map(|v| (&v.output, v.amount));
-        let (inputs, outputs, gamma, fee) = create_payment_transaction(
+        self.pay_many(&[(recipient.clone(), amount, data)])
+    }
+
+    /// Send money to several recipients using a single transaction.
+    fn pay_many(
+        &mut self,
+        recipients: &[(PublicKey, i64, PaymentPayloadData)],
+    ) -> Result<(Hash, i64), Error> {
+        let unsigned = self.prepare_payment(recipients)?;
+        self.authorize_and_send(unsigned, recipients)
+    }
+
+    /// Sign and send a payment, or hold it if the spend policy requires an approval.
+    fn authorize_and_send(
+        &mut self,
+        unsigned: UnsignedTransaction,
+        recipients: &[(PublicKey, i64, PaymentPayloadData)],
+    ) -> Result<(Hash, i64), Error> {
+        let unsigned_hash = Hash::digest(&unsigned);
+        if let Err(e) = self.authorize(unsigned_hash, recipients, unsigned.fee, None) {
+            if let WalletError::ApprovalRequired(..) = e {
+                info!(
+                    "Payment is held for approval: unsigned_hash={}",
+                    unsigned_hash
+                );
+                self.pending_approvals
+                    .insert(unsigned_hash, (unsigned, recipients.to_vec()));
+            }
+            return Err(e.into());
+        }
+        self.sign_and_send(unsigned, recipients)
+    }
+
+    /// Sign and broadcast a payment authorized by the spend policy.
+    fn sign_and_send(
+        &mut self,
+        unsigned: UnsignedTransaction,
+        recipients: &[(PublicKey, i64, PaymentPayloadData)],
+    ) -> Result<(Hash, i64), Error> {
+        let amount = self.spent_amount(recipients, unsigned.fee);
+
+        // Transaction TXINs can generally have different keying for each one
+        let tx = PaymentTransaction::new(
+            &self.chain_id,
+            &self.keys.wallet_skey,
+            &unsigned.inputs,
+            &unsigned.outputs,
+            &unsigned.outputs_gamma,
+            unsigned.fee,
+        )?;
+        let (tx_hash, fee) = self.send_payment(tx)?;
+        self.policy.on_sent(tx_hash, SystemTime::now(), amount);
+
+        let comments: Vec<&str> = recipients
+            .iter()
+            .map(|(_recipient, _amount, data)| data.comment())
+            .filter(|comment| !comment.is_empty())
+            .collect();
+        let invoice_id = recipients
+            .iter()
+            .filter_map(|(_recipient, _amount, data)| data.invoice_id())
+            .next()
+            .map(str::to_string);
+        self.history
+            .set_pending(tx_hash, comments.join("; "), invoice_id);
+        Ok((tx_hash, fee))
+    }
+
+    /// Amount sent to others, including the fee.
+    fn spent_amount(&self, recipients: &[(PublicKey, i64, PaymentPayloadData)], fee: i64) -> i64 {
+        let sent: i64 = recipients
+            .iter()
+            .filter(|(recipient, _amount, _data)| *recipient != self.keys.wallet_pkey)
+            .map(|(_recipient, amount, _data)| amount)
+            .sum();
+        sent + fee
+    }
+
+    /// Check a payment against the spend policy before signing.
+    fn authorize(
+        &mut self,
+        unsigned_hash: Hash,
+        recipients: &[(PublicKey, i64, PaymentPayloadData)],
+        fee: i64,
+        approver: Option<PublicKey>,
+    ) -> Result<(), WalletError> {
+        let amount = self.spent_amount(recipients, fee);
+        let spent_today = self.policy.spent_today(&self.history, SystemTime::now());
+        let recipients: Vec<(PublicKey, i64)> = recipients
+            .iter()
+            .map(|(recipient, amount, _data)| (*recipient, *amount))
+            .collect();
+        let spend = Spend {
+            unsigned_hash,
+            sender: self.keys.wallet_pkey,
+            recipients: &recipients,
+            amount,
+            spent_today,
+            approver,
+        };
+        self.policy.check(&spend)
+    }
+
+    /// Add a custom rule to the spend policy.
+    pub fn add_spend_rule(&mut self, rule: Box<dyn SpendRule>) {
+        self.policy.add_rule(rule);
+    }
+
+    /// Sign and send a payment held for approval.
+    fn import_approval(&mut self, approval: PaymentApproval) -> Result<(Hash, i64), Error> {
+        approval.validate()?;
+        let (unsigned, recipients) = match self.pending_approvals.remove(&approval.unsigned_hash) {
+            Some(pending) => pending,
+            None => return Err(WalletError::UnknownApproval(approval.unsigned_hash).into()),
+        };
+        if let Err(e) = self.authorize(
+            approval.unsigned_hash,
+            &recipients,
+            unsigned.fee,
+            Some(approval.approver),
+        ) {
+            if let WalletError::ApprovalRequired(..) = e {
+                // Approved by a wrong key, keep waiting for the right one.
+                self.pending_approvals
+                    .insert(approval.unsigned_hash, (unsigned, recipients));
+            }
+            return Err(e.into());
+        }
+        info!(
+            "Payment approved: unsigned_hash={}, approver={}",
+            approval.unsigned_hash,
+            approval.approver.to_address()
+        );
+        self.sign_and_send(unsigned, &recipients)
+    }
+
+    /// Replace spend policy rules in the keystore.
+    fn set_spend_policy(&mut self, rules: Vec<PolicyRule>) -> Result<(), Error> {
+        self.keys.set_spend_policy(&rules)?;
+        self.policy.set_definitions(rules);
+        Ok(())
+    }
+
+    /// Create a proof of reserves for the outputs, or for all unspent outputs if empty.
+    fn prove_reserves(&self, challenge: String, utxos: &[Hash]) -> Result<ReserveProof, Error> {
+        let mut utxos = utxos.to_vec();
+        if utxos.is_empty() {
+            utxos.extend(self.payments.keys());
+            utxos.extend(self.stakes.keys());
+            utxos.sort();
+        }
+        let mut outputs = Vec::with_capacity(utxos.len());
+        for utxo in &utxos {
+            let output = if let Some(value) = self.payments.get(utxo) {
+                Output::PaymentOutput(value.output.clone())
+            } else if let Some(value) = self.stakes.get(utxo) {
+                Output::StakeOutput(value.output.clone())
+            } else {
+                return Err(WalletError::UnknownOutput(*utxo).into());
+            };
+            outputs.push(output);
+        }
+        let proof = ReserveProof::new(&self.keys.wallet_skey, &outputs, challenge)?;
+        info!(
+            "Created reserve proof: challenge={}, outputs={}, amount={}",
+            proof.challenge,
+            proof.outputs.len(),
+            proof.amount
+        );
+        Ok(proof)
+    }
+
+    /// Lock money in a hashed time-lock contract for `timeout` blocks.
+    /// A new secret is generated if `hashlock` is not set.
+    fn initiate_swap(
+        &mut self,
+        recipient: PublicKey,
+        amount: i64,
+        hashlock: Option<Hash>,
+        timeout: u64,
+    ) -> Result<WalletResponse, Error> {
+        let (hashlock, preimage) = match hashlock {
+            Some(hashlock) => (hashlock, None),
+            None => {
+                let preimage = Hash::random().to_bytes().to_vec();
+                (Hash::digest(&preimage), Some(preimage))
+            }
+        };
+        let timeout = self.watch.height() + timeout;
+        let unspent_iter = self.unspent_payments();
+        let (inputs, outputs, outputs_gamma, fee) = create_swap_transaction(
             &self.keys.wallet_pkey,
-            recipient,
+            &recipient,
+            hashlock,
+            LockTime::Height(timeout),
             unspent_iter,
+            self.max_inputs_per_tx,
             amount,
             self.payment_fee,
-            data,
         )?;
+        let utxo = Hash::digest(&outputs[0]);
+        let unsigned = UnsignedTransaction {
+            chain_id: self.chain_id,
+            inputs,
+            outputs,
+            outputs_gamma,
+            fee,
+        };
+        let data = PaymentPayloadData::Comment("Atomic swap".to_string());
+        let (tx_hash, fee) = self.authorize_and_send(unsigned, &[(recipient, amount, data)])?;
+        Ok(WalletResponse::SwapInitiated {
+            tx_hash,
+            fee,
+            utxo,
+            hashlock,
+            preimage: preimage.map(hex::encode),
+            timeout,
+        })
+    }
 
-        // Transaction TXINs can generally have different keying for each one
-        let tx = PaymentTransaction::new(&self.keys.wallet_skey, &inputs, &outputs, &gamma, fee)?;
+    /// Redeem a hash-locked output if `preimage` is set, or refund it otherwise.
+    fn spend_swap(&mut self, utxo: Hash, preimage: Option<Vec<u8>>) -> Result<(Hash, i64), Error> {
+        let htlc = self
+            .swaps
+            .get(&utxo)
+            .ok_or(WalletError::UnknownSwap(utxo))?;
+        let hashlock = htlc.hashlock.as_ref().expect("hash-locked output");
+        match preimage {
+            Some(ref preimage) => {
+                if htlc.recipient != self.keys.wallet_pkey {
+                    return Err(WalletError::UnknownSwap(utxo).into());
+                }
+                if Hash::digest(preimage) != hashlock.hash {
+                    return Err(WalletError::InvalidPreimage(utxo).into());
+                }
+            }
+            None => {
+                if hashlock.refund != self.keys.wallet_pkey {
+                    return Err(WalletError::UnknownSwap(utxo).into());
+                }
+                let height = self.watch.height() + 1;
+                if !hashlock.timeout.is_unlocked(height, SystemTime::now()) {
+                    return Err(WalletError::SwapNotExpired(utxo, hashlock.timeout).into());
+                }
+            }
+        }
+        let tx = create_swap_spending_transaction(
+            &self.chain_id,
+            &self.keys.wallet_skey,
+            &self.keys.wallet_pkey,
+            htlc,
+            preimage,
+            self.payment_fee,
+        )?;
+        self.send_payment(tx)
+    }
+
+    fn swaps_info(&self) -> WalletResponse {
+        let mut swaps: Vec<SwapInfo> = self
+            .swaps
+            .iter()
+            .map(|(utxo, htlc)| {
+                let hashlock = htlc.hashlock.as_ref().expect("hash-locked output");
+                SwapInfo {
+                    utxo: *utxo,
+                    amount: htlc.amount,
+                    recipient: htlc.recipient,
+                    refund: hashlock.refund,
+                    hashlock: hashlock.hash,
+                    timeout: hashlock.timeout.to_string(),
+                    is_refundable: hashlock.refund == self.keys.wallet_pkey,
+                }
+            })
+            .collect();
+        swaps.sort_by_key(|swap| swap.utxo);
+        WalletResponse::SwapsInfo {
+            height: self.watch.height(),
+            swaps,
+        }
+    }
+
+    /// Propose a payment channel to `counterparty`.
+    fn open_channel(
+        &mut self,
+        counterparty: PublicKey,
+        peer: pbc::PublicKey,
+        amount: i64,
+        dispute_period: u64,
+    ) -> Result<WalletResponse, Error> {
+        let unspent_iter = self.unspent_payments();
+        let (inputs, outputs, outputs_gamma, fee) = create_channel_funding_transaction(
+            &self.keys.wallet_pkey,
+            &counterparty,
+            dispute_period,
+            unspent_iter,
+            self.max_inputs_per_tx,
+            amount,
+            self.payment_fee,
+        )?;
+        let funding = match &outputs[0] {
+            Output::PublicPaymentOutput(o) => o.clone(),
+            _ => unreachable!(),
+        };
+        let unsigned = UnsignedTransaction {
+            chain_id: self.chain_id,
+            inputs,
+            outputs,
+            outputs_gamma,
+            fee,
+        };
+        let data = PaymentPayloadData::Comment("Payment channel".to_string());
+        let recipients = vec![(counterparty, amount, data)];
+        self.authorize(Hash::digest(&unsigned), &recipients, fee, None)?;
+
+        let mut channel = Channel::propose(funding, peer, &self.keys.wallet_skey);
+        let msg = ChannelMessage::Open {
+            funding: channel.funding.clone(),
+            state: channel.state.clone(),
+        };
+        self.send_channel_message(&peer, msg)?;
+        channel.funding_tx = Some((unsigned, recipients));
+        let channel_id = channel.id();
+        info!(
+            "Proposed a payment channel: channel={}, amount={}, dispute_period={}",
+            channel_id, amount, dispute_period
+        );
+        self.channels.insert(channel_id, channel);
+        Ok(WalletResponse::ChannelProposed {
+            channel: channel_id,
+            capacity: amount,
+            fee,
+        })
+    }
+
+    /// Pay `amount` to the other party of a payment channel.
+    fn channel_pay(&mut self, channel_id: Hash, amount: i64) -> Result<WalletResponse, Error> {
+        let channel = self
+            .channels
+            .get_mut(&channel_id)
+            .ok_or(WalletError::UnknownChannel(channel_id))?;
+        let state = channel.new_update(&self.keys.wallet_skey, &self.keys.wallet_pkey, amount)?;
+        channel.pending = Some(state.clone());
+        let peer = channel.peer;
+        let sequence = state.sequence;
+        let balance = state.balance(&self.keys.wallet_pkey).expect("party");
+        self.channels.save()?;
+        info!(
+            "Paid via channel: channel={}, sequence={}, amount={}",
+            channel_id, sequence, amount
+        );
+        self.send_channel_message(&peer, ChannelMessage::Update { state })?;
+        Ok(WalletResponse::ChannelUpdated {
+            channel: channel_id,
+            sequence,
+            balance,
+        })
+    }
+
+    /// Settle a payment channel on-chain using the latest state.
+    fn close_channel(
+        &mut self,
+        channel_id: Hash,
+        unilateral: bool,
+    ) -> Result<WalletResponse, Error> {
+        let channel = self
+            .channels
+            .get(&channel_id)
+            .ok_or(WalletError::UnknownChannel(channel_id))?;
+        if channel.status != ChannelStatus::Open {
+            return Err(WalletError::ChannelNotOpen(channel_id).into());
+        }
+        let tx = create_channel_close_transaction(
+            &self.chain_id,
+            &self.keys.wallet_skey,
+            &self.keys.wallet_pkey,
+            &channel.funding,
+            &channel.state,
+            unilateral,
+            self.payment_fee,
+        )?;
+        let peer = channel.peer;
+        let tx_hash = if unilateral {
+            self.send_payment(tx)?.0
+        } else {
+            // The counterparty cosigns and sends the transaction.
+            let tx_hash = Hash::digest(&tx);
+            self.send_channel_message(&peer, ChannelMessage::Close { tx })?;
+            tx_hash
+        };
+        let channel = self.channels.get_mut(&channel_id).expect("exists");
+        channel.status = ChannelStatus::Closing;
+        self.channels.save()?;
+        info!(
+            "Closing a payment channel: channel={}, tx={}, unilateral={}",
+            channel_id, tx_hash, unilateral
+        );
+        Ok(WalletResponse::ChannelClosing {
+            channel: channel_id,
+            tx_hash,
+            unilateral,
+        })
+    }
+
+    fn channels_info(&self) -> WalletResponse {
+        let mut channels: Vec<ChannelInfo> = self
+            .channels
+            .values()
+            .map(|channel| channel.to_info(&self.keys.wallet_pkey))
+            .collect();
+        channels.sort_by_key(|channel| channel.channel);
+        WalletResponse::ChannelsInfo { channels }
+    }
+
+    fn send_channel_message(
+        &self,
+        peer: &pbc::PublicKey,
+        msg: ChannelMessage,
+    ) -> Result<(), Error> {
+        let msg = msg.into_buffer()?;
+        self.network.send(peer.clone(), CHANNELS_TOPIC, msg)?;
+        Ok(())
+    }
+
+    /// Process a message from the other party of a payment channel.
+    fn on_channel_message(&mut self, from: pbc::PublicKey, msg: Vec<u8>) -> Result<(), Error> {
+        let msg = ChannelMessage::from_buffer(&msg)?;
+        let channel_id = match &msg {
+            ChannelMessage::Open { funding, .. } => Hash::digest(funding),
+            ChannelMessage::Accept { state }
+            | ChannelMessage::Update { state }
+            | ChannelMessage::Ack { state } => state.channel,
+            ChannelMessage::Close { tx } => match tx.channels.first() {
+                Some(witness) => *witness.txin(),
+                None => return Err(WalletError::InvalidChannelState(Hash::digest(tx)).into()),
+            },
+        };
+        if let ChannelMessage::Open { funding, state } = msg {
+            if self.channels.contains_key(&channel_id) {
+                return Err(WalletError::InvalidChannelState(channel_id).into());
+            }
+            let channel = Channel::accept(
+                funding,
+                state,
+                from,
+                &self.keys.wallet_skey,
+                &self.keys.wallet_pkey,
+            )?;
+            let state = channel.state.clone();
+            info!(
+                "Accepted a payment channel: channel={}, capacity={}",
+                channel_id, channel.funding.amount
+            );
+            self.channels.insert(channel_id, channel);
+            self.channels.save()?;
+            return self.send_channel_message(&from, ChannelMessage::Accept { state });
+        }
+
+        let channel = self
+            .channels
+            .get_mut(&channel_id)
+            .filter(|channel| channel.peer == from)
+            .ok_or(WalletError::UnknownChannel(channel_id))?;
+        match msg {
+            ChannelMessage::Open { .. } => unreachable!(),
+            ChannelMessage::Accept { state } => {
+                channel.on_accept(state)?;
+                let (unsigned, recipients) = channel
+                    .funding_tx
+                    .take()
+                    .ok_or(WalletError::InvalidChannelState(channel_id))?;
+                self.channels.save()?;
+                let (tx_hash, _fee) = self.sign_and_send(unsigned, &recipients)?;
+                info!(
+                    "Funded a payment channel: channel={}, tx={}",
+                    channel_id, tx_hash
+                );
+            }
+            ChannelMessage::Update { state } => {
+                let received =
+                    channel.on_update(state, &self.keys.wallet_skey, &self.keys.wallet_pkey)?;
+                let state = channel.state.clone();
+                self.channels.save()?;
+                info!(
+                    "Received via channel: channel={}, sequence={}, amount={}",
+                    channel_id, state.sequence, received
+                );
+                self.send_channel_message(&from, ChannelMessage::Ack { state })?;
+            }
+            ChannelMessage::Ack { state } => {
+                channel.on_ack(state)?;
+                debug!(
+                    "Channel state confirmed: channel={}, sequence={}",
+                    channel_id, channel.state.sequence
+                );
+                self.channels.save()?;
+            }
+            ChannelMessage::Close { mut tx } => {
+                if channel.status != ChannelStatus::Open {
+                    return Err(WalletError::ChannelNotOpen(channel_id).into());
+                }
+                // Check that the transaction pays our balance of the latest state.
+                let balance = channel
+                    .state
+                    .balance(&self.keys.wallet_pkey)
+                    .expect("party of channel");
+                let received: i64 = tx
+                    .txouts
+                    .iter()
+                    .filter_map(|output| self.my_payment(output))
+                    .map(|(amount, _data)| amount)
+                    .sum();
+                if tx.txins != [channel_id] || received < balance {
+                    return Err(WalletError::InvalidChannelState(channel_id).into());
+                }
+                tx.cosign(&channel_id, &self.keys.wallet_skey);
+                let (tx_hash, _fee) = self.send_payment(tx)?;
+                if let Some(channel) = self.channels.get_mut(&channel_id) {
+                    channel.status = ChannelStatus::Closing;
+                }
+                self.channels.save()?;
+                info!(
+                    "Cosigned a channel close: channel={}, tx={}",
+                    channel_id, tx_hash
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn save_channels(&self) {
+        if let Err(e) = self.channels.save() {
+            error!("{}", e);
+        }
+    }
+
+    /// Spend public outputs of settled channels:
+    /// penalize outdated unilateral closes, claim expired disputes and sweep public payments.
+    fn settle_channels(&mut self) {
+        let height = self.watch.height() + 1;
+        let mut claims: Vec<(Hash, PublicPaymentOutput, Option<ChannelState>)> = Vec::new();
+        for (hash, output) in &self.public_payments {
+            claims.push((*hash, output.clone(), None));
+        }
+        for channel in self.channels.values() {
+            let (hash, output, unlock_height) = match &channel.dispute {
+                Some(dispute) => dispute,
+                None => continue,
+            };
+            let sequence = match &output.channel {
+                Some(ChannelLock::Dispute { sequence, .. }) => *sequence,
+                _ => unreachable!(),
+            };
+            if output.recipient == self.keys.wallet_pkey {
+                if height >= *unlock_height {
+                    claims.push((*hash, output.clone(), None));
+                }
+            } else if channel.state.sequence > sequence {
+                warn!(
+                    "Outdated channel state was published: channel={}, sequence={}, latest={}",
+                    channel.id(),
+                    sequence,
+                    channel.state.sequence
+                );
+                claims.push((*hash, output.clone(), Some(channel.state.clone())));
+            }
+        }
+
+        for (hash, output, penalty) in claims {
+            if self.claimed.contains(&hash) {
+                continue;
+            }
+            let tx = create_public_spending_transaction(
+                &self.chain_id,
+                &self.keys.wallet_skey,
+                &self.keys.wallet_pkey,
+                &output,
+                penalty,
+                self.payment_fee,
+            );
+            match tx.and_then(|tx| self.send_payment(tx)) {
+                Ok((tx_hash, _fee)) => {
+                    info!("Claimed a public output: utxo={}, tx={}", hash, tx_hash);
+                    self.claimed.insert(hash);
+                }
+                Err(e) => error!(
+                    "Failed to claim a public output: utxo={}, error={}",
+                    hash, e
+                ),
+            }
+        }
+    }
+
+    fn spend_policy_info(&mut self) -> WalletResponse {
+        WalletResponse::SpendPolicy {
+            rules: self.policy.definitions().to_vec(),
+            spent_today: self.policy.spent_today(&self.history, SystemTime::now()),
+        }
+    }
+
+    /// Choose inputs and create outputs for a payment, without signing.
+    fn prepare_payment(
+        &self,
+        recipients: &[(PublicKey, i64, PaymentPayloadData)],
+    ) -> Result<UnsignedTransaction, Error> {
+        let unspent_iter = self.unspent_payments();
+        let (inputs, outputs, outputs_gamma, fee) = create_payment_transaction(
+            &self.keys.wallet_pkey,
+            recipients,
+            unspent_iter,
+            self.max_inputs_per_tx,
+            self.payment_fee,
+            self.cfg.pad_outputs,
+        )?;
+        Ok(UnsignedTransaction {
+            chain_id: self.chain_id,
+            inputs,
+            outputs,
+            outputs_gamma,
+            fee,
+        })
+    }
+
+    /// Broadcast a signed payment transaction.
+    fn send_payment(&mut self, tx: PaymentTransaction) -> Result<(Hash, i64), Error> {
         let tx_hash = Hash::digest(&tx);
         let fee = tx.fee;
         let tx: Transaction = tx.into();
@@ -284,6 +1115,50 @@ impl WalletService {
         Ok((tx_hash, fee))
     }
 
+    /// Prepare a payment to be signed offline.
+    /// The transaction is kept until the signature is imported back.
+    fn export_payment(
+        &mut self,
+        recipients: &[(PublicKey, i64, PaymentPayloadData)],
+    ) -> Result<(Hash, UnsignedTransaction), Error> {
+        let unsigned = self.prepare_payment(recipients)?;
+        let unsigned_hash = Hash::digest(&unsigned);
+        self.authorize(unsigned_hash, recipients, unsigned.fee, None)?;
+        info!(
+            "Exported a payment for offline signing: unsigned_hash={}, inputs={}, outputs={}, fee={}",
+            unsigned_hash,
+            unsigned.inputs.len(),
+            unsigned.outputs.len(),
+            unsigned.fee
+        );
+        self.offline_transactions
+            .insert(unsigned_hash, unsigned.clone());
+        Ok((unsigned_hash, unsigned))
+    }
+
+    /// Sign a payment prepared by another wallet.
+    fn sign_offline(&self, unsigned: UnsignedTransaction) -> Result<SignedTransaction, Error> {
+        let signed = unsigned.sign(&self.keys.wallet_skey)?;
+        info!(
+            "Signed a payment offline: unsigned_hash={}, tx={}",
+            signed.unsigned_hash,
+            Hash::digest(&signed.tx)
+        );
+        Ok(signed)
+    }
+
+    /// Broadcast a payment signed offline.
+    fn import_signed(&mut self, signed: SignedTransaction) -> Result<(Hash, i64), Error> {
+        match self.offline_transactions.get(&signed.unsigned_hash) {
+            Some(unsigned) => signed.validate(unsigned)?,
+            None => {
+                return Err(WalletError::UnknownUnsignedTransaction(signed.unsigned_hash).into())
+            }
+        }
+        self.offline_transactions.remove(&signed.unsigned_hash);
+        self.send_payment(signed.tx)
+    }
+
     fn add_transaction_interest(&mut self, tx: SavedTransaction) {
         debug!("Add transaction in interest list: tx = {:?}", tx);
         let tx_hash = Hash::digest(&tx);
@@ -314,18 +1189,22 @@ impl WalletService {
         amount: i64,
         comment: String,
     ) -> Result<Hash, Error> {
-        let unspent_iter = self.payments.values().map(|v| (&v.output, v.amount));
+        let unspent_iter = self.unspent_payments();
         let (inputs, outputs, fee) = create_vs_payment_transaction(
             &self.keys.wallet_pkey,
             recipient,
             unspent_iter,
+            self.max_inputs_per_tx,
             amount,
             self.payment_fee,
-            comment,
+            comment.clone(),
         )?;
-        self.vs.queue_transaction(&inputs, &outputs, fee)?;
         let saved_tx = SavedTransaction::ValueShuffle(inputs.iter().map(|(h, _)| *h).collect());
         let hash = Hash::digest(&saved_tx);
+        // ValueShuffle payments can't be held for approval.
+        let recipients = [(*recipient, amount, PaymentPayloadData::Comment(comment))];
+        self.authorize(hash, &recipients, fee, None)?;
+        self.vs.queue_transaction(&inputs, &outputs, fee)?;
         metrics::WALLET_CREATEAD_SECURE_PAYMENTS
             .with_label_values(&[&self.keys.wallet_pkey.to_hex()])
             .inc();
@@ -335,13 +1214,31 @@ impl WalletService {
 
     /// Stake money into the escrow.
     fn stake(&self, amount: i64) -> Result<(Hash, i64), Error> {
-        let unspent_iter = self.payments.values().map(|v| (&v.output, v.amount));
+        self.stake_to(None, amount)
+    }
+
+    /// Stake money into the escrow and delegate validation rights to the operator.
+    fn delegate(&self, operator_pkey: &pbc::PublicKey, amount: i64) -> Result<(Hash, i64), Error> {
+        self.stake_to(Some(operator_pkey), amount)
+    }
+
+    fn stake_to(
+        &self,
+        operator_pkey: Option<&pbc::PublicKey>,
+        amount: i64,
+    ) -> Result<(Hash, i64), Error> {
+        let unspent_iter = self.unspent_payments();
+        let reward = self.reward_destination();
         let tx = create_staking_transaction(
+            &self.chain_id,
             &self.keys.wallet_skey,
             &self.keys.wallet_pkey,
             &self.keys.network_pkey,
             &self.keys.network_skey,
+            operator_pkey,
+            reward.as_ref(),
             unspent_iter,
+            self.max_inputs_per_tx,
             amount,
             self.payment_fee,
             self.stake_fee,
@@ -355,13 +1252,15 @@ impl WalletService {
     /// Unstake money from the escrow.
     /// NOTE: amount must include PAYMENT_FEE.
     fn unstake(&self, amount: i64) -> Result<(Hash, i64), Error> {
-        let unspent_iter = self.stakes.values().map(|v| &v.output);
+        let unspent_iter = self.unspent_stakes();
         let tx = create_unstaking_transaction(
+            &self.chain_id,
             &self.keys.wallet_skey,
             &self.keys.wallet_pkey,
             &self.keys.network_pkey,
             &self.keys.network_skey,
             unspent_iter,
+            self.max_inputs_per_tx,
             amount,
             self.payment_fee,
             self.stake_fee,
@@ -374,40 +1273,114 @@ impl WalletService {
 
     /// Unstake all of the money from the escrow.
     fn unstake_all(&self) -> Result<(Hash, i64), Error> {
-        let mut amount: i64 = 0;
-        for val in self.stakes.values() {
-            amount += val.output.amount;
-        }
+        let amount: i64 = self.unspent_stakes().map(|o| o.amount).sum();
         self.unstake(amount)
     }
 
     /// Restake all available stakes (even if not expired).
     fn restake_all(&mut self) -> Result<(Hash, i64), Error> {
         assert_eq!(self.stake_fee, 0);
-        if self.stakes.is_empty() {
+        let stakes: Vec<&StakeOutput> = self.unspent_stakes().collect();
+        if stakes.is_empty() {
             return Err(WalletError::NothingToRestake.into());
         }
 
-        let stakes = self.stakes.values().map(|val| &val.output);
+        let reward = self.reward_destination();
         let tx = create_restaking_transaction(
+            &self.chain_id,
             &self.keys.wallet_skey,
             &self.keys.wallet_pkey,
             &self.keys.network_pkey,
             &self.keys.network_skey,
-            stakes,
+            reward.as_ref(),
+            stakes.into_iter(),
         )?;
         let tx_hash = Hash::digest(&tx);
         self.node.send_transaction(tx.into())?;
         Ok((tx_hash, 0))
     }
 
+    /// Returns the destination of block rewards of stakes of this wallet, if overridden.
+    fn reward_destination(&self) -> Option<RewardDestination> {
+        self.stakes
+            .values()
+            .filter_map(|val| val.output.reward.clone())
+            .next()
+    }
+
+    /// Pay block rewards of all stakes to another wallet.
+    /// Rewards are paid to this wallet again if `reward` is None.
+    fn set_reward_destination(&mut self, reward: Option<PublicKey>) -> Result<(Hash, i64), Error> {
+        assert_eq!(self.stake_fee, 0);
+        let stakes: Vec<&StakeOutput> = self.unspent_stakes().collect();
+        if stakes.is_empty() {
+            return Err(WalletError::NothingToRestake.into());
+        }
+
+        let reward = reward
+            .filter(|reward_pkey| reward_pkey != &self.keys.wallet_pkey)
+            .map(|reward_pkey| {
+                RewardDestination::new(
+                    &self.keys.wallet_skey,
+                    &self.keys.wallet_pkey,
+                    &self.keys.network_pkey,
+                    &reward_pkey,
+                )
+            });
+        let tx = create_restaking_transaction(
+            &self.keys.wallet_skey,
+            &self.keys.wallet_pkey,
+            &self.keys.network_pkey,
+            &self.keys.network_skey,
+            reward.as_ref(),
+            stakes.into_iter(),
+        )?;
+        let tx_hash = Hash::digest(&tx);
+        info!(
+            "Changed destination of block rewards: reward={:?}, tx={}",
+            reward.as_ref().map(|reward| reward.pkey),
+            tx_hash
+        );
+        self.node.send_transaction(tx.into())?;
+        Ok((tx_hash, 0))
+    }
+
+    /// Revoke delegation of all delegated stakes.
+    fn revoke_delegation(&mut self) -> Result<(Hash, i64), Error> {
+        let stakes: Vec<Output> = self
+            .stakes
+            .values()
+            .filter(|val| val.output.is_delegated())
+            .map(|val| Output::StakeOutput(val.output.clone()))
+            .collect();
+        if stakes.is_empty() {
+            return Err(WalletError::NothingToRevoke.into());
+        }
+
+        let tx = RevokeDelegationTransaction::new(
+            &self.chain_id,
+            &self.keys.network_skey,
+            &self.keys.network_pkey,
+            &stakes,
+        )?;
+        let tx_hash = Hash::digest(&tx);
+        info!(
+            "Created a revoke delegation transaction: hash={}, inputs={}",
+            tx_hash,
+            tx.txins.len()
+        );
+        self.node.send_transaction(tx.into())?;
+        Ok((tx_hash, 0))
+    }
+
     /// Re-stake expiring stakes.
     fn restake_expiring(&mut self) -> Result<(), Error> {
         assert_eq!(self.stake_fee, 0);
         let epoch = self.epoch;
+        let frozen = &self.frozen;
         let stakes: Vec<&StakeOutput> = self.stakes.iter().filter_map(|(hash, val)|
                 // Re-stake in the last epoch where stake is valid.
-                if val.active_until_epoch <= epoch {
+                if val.active_until_epoch <= epoch && !frozen.contains(hash) {
                     info!("Expiring stake: utxo={}, amount={}, active_until_epoch={}, epoch={}",
                            hash, val.output.amount, val.active_until_epoch, epoch);
                     Some(&val.output)
@@ -420,11 +1393,14 @@ impl WalletService {
             return Ok(()); // Nothing to re-stake.
         }
 
+        let reward = self.reward_destination();
         let tx = create_restaking_transaction(
+            &self.chain_id,
             &self.keys.wallet_skey,
             &self.keys.wallet_pkey,
             &self.keys.network_pkey,
             &self.keys.network_skey,
+            reward.as_ref(),
             stakes.into_iter(),
         )?;
         self.node.send_transaction(tx.into())?;
@@ -432,6 +1408,113 @@ impl WalletService {
     }
 
     /// Get actual balance.
+    /// Stake unspent block rewards once they exceed the threshold.
+    fn compound_rewards(&mut self) -> Result<(), Error> {
+        let frozen = &self.frozen;
+        let payments = &self.payments;
+        let transactions_interest = &self.transactions_interest;
+        // Skip rewards spent by unconfirmed transactions.
+        let rewards: Vec<(&PaymentOutput, i64)> = self
+            .rewards
+            .iter()
+            .filter(|hash| !frozen.contains(hash) && !transactions_interest.contains_key(*hash))
+            .filter_map(|hash| payments.get(hash))
+            .map(|val| (&val.output, val.amount))
+            .collect();
+        let total: i64 = rewards.iter().map(|(_output, amount)| amount).sum();
+        let staked: i64 = self.stakes.values().map(|val| val.output.amount).sum();
+        let amount = match compound_amount(
+            total,
+            staked,
+            self.cfg.compound_threshold,
+            self.cfg.compound_max_stake,
+            self.payment_fee,
+            self.stake_fee,
+        ) {
+            Some(amount) => amount,
+            None => return Ok(()), // Nothing to stake.
+        };
+
+        info!(
+            "Staking block rewards: rewards={}, amount={}, staked={}",
+            total, amount, staked
+        );
+        let reward = self.reward_destination();
+        let tx = create_staking_transaction(
+            &self.chain_id,
+            &self.keys.wallet_skey,
+            &self.keys.wallet_pkey,
+            &self.keys.network_pkey,
+            &self.keys.network_skey,
+            None,
+            reward.as_ref(),
+            rewards.into_iter(),
+            self.max_inputs_per_tx,
+            amount,
+            self.payment_fee,
+            self.stake_fee,
+        )?;
+        let tx: Transaction = tx.into();
+        self.node.send_transaction(tx.clone())?;
+        self.add_transaction_interest(tx.into());
+        Ok(())
+    }
+
+    /// Spend a few random outputs back to this wallet.
+    fn churn_outputs(&mut self) -> Result<(), Error> {
+        let frozen = &self.frozen;
+        let transactions_interest = &self.transactions_interest;
+        // Time-locked outputs and outputs of unconfirmed transactions are skipped.
+        let unspent: Vec<(&PaymentOutput, i64)> = self
+            .payments
+            .iter()
+            .filter(|(hash, _val)| {
+                !frozen.contains(hash) && !transactions_interest.contains_key(*hash)
+            })
+            .filter(|(_hash, val)| val.output.locked_until.is_none())
+            .map(|(_hash, val)| (&val.output, val.amount))
+            .collect();
+        let mut rng = rand::thread_rng();
+        let (inputs, outputs, gamma, fee) = match create_churn_transaction(
+            &self.keys.wallet_pkey,
+            &unspent,
+            self.payment_fee,
+            &mut rng,
+        )? {
+            Some(tx) => tx,
+            None => return Ok(()), // Nothing to churn.
+        };
+        let now = SystemTime::now();
+        if !self.churn_budget.allows(fee, now) {
+            debug!(
+                "Skipped self-spend over the budget: fee={}, spent={}, budget={}",
+                fee,
+                self.churn_budget.spent(now),
+                self.cfg.churn_budget
+            );
+            return Ok(());
+        }
+
+        let tx = PaymentTransaction::new(
+            &self.chain_id,
+            &self.keys.wallet_skey,
+            &inputs,
+            &outputs,
+            &gamma,
+            fee,
+        )?;
+        let (tx_hash, fee) = self.send_payment(tx)?;
+        self.churn_budget.spend(fee, now);
+        info!(
+            "Sent a self-spend: hash={}, inputs={}, outputs={}, fee={}",
+            tx_hash,
+            inputs.len(),
+            outputs.len(),
+            fee
+        );
+        Ok(())
+    }
+
     fn balance(&self) -> i64 {
         let mut balance: i64 = 0;
         for val in self.payments.values() {
@@ -440,17 +1523,118 @@ impl WalletService {
         balance
     }
 
+    /// Get the part of balance excluded from coin selection.
+    fn frozen_balance(&self) -> i64 {
+        let mut balance: i64 = 0;
+        for (hash, val) in &self.payments {
+            if self.frozen.contains(hash) {
+                balance += val.amount;
+            }
+        }
+        balance
+    }
+
+    /// Payment UTXO available for coin selection.
+    /// Time-locked UTXO are skipped until they can be spent in the next block.
+    fn unspent_payments(&self) -> impl Iterator<Item = (&PaymentOutput, i64)> {
+        let frozen = &self.frozen;
+        let height = self.watch.height() + 1;
+        let timestamp = SystemTime::now();
+        self.payments
+            .iter()
+            .filter(move |(hash, _val)| !frozen.contains(hash))
+            .filter(move |(_hash, val)| match val.output.locked_until {
+                Some(locked_until) => locked_until.is_unlocked(height, timestamp),
+                None => true,
+            })
+            .map(|(_hash, val)| (&val.output, val.amount))
+    }
+
+    /// Stake UTXO available for coin selection.
+    fn unspent_stakes(&self) -> impl Iterator<Item = &StakeOutput> {
+        let frozen = &self.frozen;
+        self.stakes
+            .iter()
+            .filter(move |(hash, _val)| !frozen.contains(hash))
+            .map(|(_hash, val)| &val.output)
+    }
+
+    /// Exclude UTXO from coin selection.
+    fn freeze(&mut self, utxo: Hash) -> Result<(), WalletError> {
+        if !self.payments.contains_key(&utxo) && !self.stakes.contains_key(&utxo) {
+            return Err(WalletError::UnknownOutput(utxo));
+        }
+        self.frozen.freeze(utxo)?;
+        info!("Frozen: utxo={}", utxo);
+        Ok(())
+    }
+
+    /// Return UTXO back to coin selection.
+    fn unfreeze(&mut self, utxo: Hash) -> Result<(), WalletError> {
+        self.frozen.unfreeze(&utxo)?;
+        info!("Unfrozen: utxo={}", utxo);
+        Ok(())
+    }
+
     /// Called when outputs registered and/or pruned.
-    fn on_outputs_changed(&mut self, epoch: u64, inputs: Vec<Output>, outputs: Vec<Output>) {
+    fn on_outputs_changed(&mut self, changed: OutputsChanged) {
+        if let Err(e) = self.update_history(&changed) {
+            error!("Failed to update history: {}", e);
+        }
+        for event in self.watch.on_outputs_changed(&changed) {
+            let notification = match event {
+                DepositEvent::Received(deposit) => WalletNotification::DepositReceived(deposit),
+                DepositEvent::Confirmed(deposit) => WalletNotification::DepositConfirmed(deposit),
+                DepositEvent::Spent(deposit) => WalletNotification::DepositSpent(deposit),
+                DepositEvent::Reverted(deposit) => WalletNotification::DepositReverted(deposit),
+            };
+            self.notify(notification);
+        }
+
+        let OutputsChanged {
+            epoch,
+            height,
+            reverted,
+            inputs,
+            outputs,
+            tx_hashes,
+            ..
+        } = changed;
         let saved_balance = self.balance();
 
         self.find_committed_txs(&inputs);
+        for input in &inputs {
+            let input_hash = Hash::digest(input);
+            self.rewards.remove(&input_hash);
+            self.unconfirmed.remove(&input_hash);
+        }
         for input in inputs {
             self.on_output_pruned(epoch, input);
         }
 
+        for output in &outputs {
+            let output_hash = Hash::digest(output);
+            // Outputs created by macro blocks have no transaction.
+            if !reverted
+                && !tx_hashes.contains_key(&output_hash)
+                && self.my_payment(output).is_some()
+            {
+                debug!("Received block reward: utxo={}", output_hash);
+                self.rewards.insert(output_hash);
+            }
+        }
         for output in outputs {
+            let output_hash = Hash::digest(&output);
             self.on_output_created(epoch, output);
+            if !reverted && self.payments.contains_key(&output_hash) {
+                self.unconfirmed.insert(output_hash, height);
+            }
+        }
+        if !reverted {
+            self.confirm_payments(height);
+        }
+        if !self.watch_only {
+            self.settle_channels();
         }
 
         let balance = self.balance();
@@ -463,6 +1647,166 @@ impl WalletService {
         }
     }
 
+    /// Notify about received payments which reached the required number of confirmations.
+    fn confirm_payments(&mut self, height: u64) {
+        let required = self.cfg.deposit_confirmations;
+        let confirmed: Vec<Hash> = self
+            .unconfirmed
+            .iter()
+            .filter(|(_, &received)| height + 1 >= received + required)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in confirmed {
+            self.unconfirmed.remove(&hash);
+            if let Some(value) = self.payments.get(&hash) {
+                let info = value.to_info();
+                info!("Confirmed: utxo={}, amount={}", hash, info.amount);
+                self.notify(WalletNotification::Confirmed(info));
+            }
+        }
+    }
+
+    /// Decrypt the amount and the payload of a payment output of this wallet.
+    fn my_payment(&self, output: &Output) -> Option<(i64, PaymentPayloadData)> {
+        if !output.is_my_utxo(&self.keys.wallet_skey, &self.keys.wallet_pkey) {
+            return None;
+        }
+        match output {
+            Output::PaymentOutput(o) => o
+                .decrypt_payload(&self.keys.wallet_skey)
+                .ok()
+                .map(|PaymentPayload { amount, data, .. }| (amount, data)),
+            _ => None,
+        }
+    }
+
+    /// Record confirmed payments or forget reverted ones.
+    fn update_history(&mut self, changed: &OutputsChanged) -> Result<(), WalletError> {
+        if changed.reverted {
+            return self.history.rollback(changed.height);
+        }
+
+        // Group outputs of this wallet by transactions, keeping the block order.
+        // tx_hash => (received, spent, comments, invoice_id).
+        let mut order: Vec<Option<Hash>> = Vec::new();
+        let mut payments: HashMap<Option<Hash>, (i64, i64, Vec<String>, Option<String>)> =
+            HashMap::new();
+        for input in &changed.inputs {
+            if let Some((amount, _data)) = self.my_payment(input) {
+                let tx_hash = changed.tx_hashes.get(&Hash::digest(input)).cloned();
+                let payment = payments.entry(tx_hash).or_insert_with(|| {
+                    order.push(tx_hash);
+                    (0, 0, Vec::new(), None)
+                });
+                payment.1 += amount;
+            }
+        }
+        for output in &changed.outputs {
+            if let Some((amount, data)) = self.my_payment(output) {
+                let tx_hash = changed.tx_hashes.get(&Hash::digest(output)).cloned();
+                let payment = payments.entry(tx_hash).or_insert_with(|| {
+                    order.push(tx_hash);
+                    (0, 0, Vec::new(), None)
+                });
+                payment.0 += amount;
+                if !data.comment().is_empty() {
+                    payment.2.push(data.comment().to_string());
+                }
+                if payment.3.is_none() {
+                    payment.3 = data.invoice_id().map(str::to_string);
+                }
+            }
+        }
+
+        let mut entries = Vec::with_capacity(order.len());
+        for tx_hash in order {
+            let (received, spent, comments, invoice_id) = payments.remove(&tx_hash).unwrap();
+            let (direction, amount, comment, invoice_id) = if spent > 0 {
+                let (comment, sent_invoice_id) = match tx_hash {
+                    Some(ref tx_hash) => self.history.take_pending(tx_hash),
+                    None => (String::new(), None),
+                };
+                let invoice_id = sent_invoice_id.or(invoice_id);
+                (
+                    PaymentDirection::Outgoing,
+                    spent - received,
+                    comment,
+                    invoice_id,
+                )
+            } else {
+                let comment = comments.join("; ");
+                (PaymentDirection::Incoming, received, comment, invoice_id)
+            };
+            entries.push(HistoryEntry {
+                timestamp: changed.timestamp,
+                direction,
+                amount,
+                comment,
+                invoice_id,
+                tx_hash,
+                height: changed.height,
+                irreversible: false,
+            });
+        }
+        self.history.record(changed.height, entries)
+    }
+
+    /// Unspent outputs and history of payments for the invoice.
+    fn invoice_info(&self, invoice_id: String) -> WalletResponse {
+        let unspent: Vec<PaymentInfo> = match self.invoices.get(&invoice_id) {
+            Some(utxos) => utxos
+                .iter()
+                .filter_map(|utxo| self.payments.get(utxo))
+                .map(|value| value.to_info())
+                .collect(),
+            None => Vec::new(),
+        };
+        let history = self.history.by_invoice(&invoice_id);
+        WalletResponse::InvoiceInfo {
+            invoice_id,
+            unspent,
+            history,
+        }
+    }
+
+    /// Issue an invoice payable to this wallet.
+    fn create_invoice(
+        &mut self,
+        amount: i64,
+        memo: String,
+        lifetime: Option<u64>,
+    ) -> Result<WalletResponse, Error> {
+        let lifetime = Duration::from_secs(lifetime.unwrap_or(self.cfg.invoice_lifetime));
+        let invoice = self.issued_invoices.issue(
+            self.keys.wallet_pkey,
+            amount,
+            memo,
+            lifetime,
+            SystemTime::now(),
+        )?;
+        let encoded = invoice.encode();
+        Ok(WalletResponse::InvoiceCreated { invoice, encoded })
+    }
+
+    /// Pay an invoice issued by another wallet.
+    fn pay_invoice(&mut self, encoded: &str) -> Result<(Hash, i64), Error> {
+        let invoice = Invoice::decode(encoded)?;
+        if invoice.is_expired(SystemTime::now()) {
+            return Err(WalletError::InvoiceExpired(invoice.invoice_id).into());
+        }
+        info!(
+            "Paying invoice: invoice_id={}, recipient={}, amount={}",
+            invoice.invoice_id, invoice.recipient, invoice.amount
+        );
+        let memo = PaymentMemo {
+            invoice_id: Some(invoice.invoice_id),
+            comment: invoice.memo,
+            ..Default::default()
+        };
+        let data = PaymentPayloadData::Memo(memo);
+        self.pay_many(&[(invoice.recipient, invoice.amount, data)])
+    }
+
     /// Called when UTXO is created.
     fn on_output_created(&mut self, epoch: u64, output: Output) {
         if !output.is_my_utxo(&self.keys.wallet_skey, &self.keys.wallet_pkey) {
@@ -479,6 +1823,20 @@ impl WalletService {
                         "Received: utxo={}, amount={}, data={:?}",
                         hash, amount, data
                     );
+                    if let Some(invoice_id) = data.invoice_id() {
+                        self.invoices
+                            .entry(invoice_id.to_string())
+                            .or_insert_with(HashSet::new)
+                            .insert(hash);
+                        if let Some(paid) = self.issued_invoices.on_payment(
+                            invoice_id,
+                            hash,
+                            amount,
+                            SystemTime::now(),
+                        ) {
+                            self.notify(WalletNotification::InvoicePaid(paid));
+                        }
+                    }
                     let value = PaymentValue {
                         output: o,
                         amount,
@@ -490,9 +1848,51 @@ impl WalletService {
                     self.notify(WalletNotification::Received(info));
                 }
             }
-            Output::PublicPaymentOutput(_o) => {
-                unimplemented!();
-            }
+            Output::PublicPaymentOutput(o) => match o.hashlock {
+                Some(ref hashlock) => {
+                    info!(
+                        "Hash-locked: utxo={}, amount={}, hashlock={}, timeout={}",
+                        hash, o.amount, hashlock.hash, hashlock.timeout
+                    );
+                    let missing = self.swaps.insert(hash, o);
+                    assert!(missing.is_none(), "Inconsistent wallet state");
+                }
+                None => match o.channel {
+                    Some(ChannelLock::Funding { .. }) => {
+                        info!("Channel opened: channel={}, amount={}", hash, o.amount);
+                        match self.channels.get_mut(&hash) {
+                            Some(channel) => channel.status = ChannelStatus::Open,
+                            None => warn!("Unknown channel: channel={}", hash),
+                        }
+                        self.save_channels();
+                    }
+                    Some(ChannelLock::Dispute {
+                        channel: channel_id,
+                        sequence,
+                        dispute_period,
+                        ..
+                    }) => {
+                        info!(
+                            "Channel disputed: channel={}, utxo={}, amount={}, sequence={}",
+                            channel_id, hash, o.amount, sequence
+                        );
+                        let unlock_height = self.watch.height().saturating_add(dispute_period);
+                        match self.channels.get_mut(&channel_id) {
+                            Some(channel) => {
+                                channel.status = ChannelStatus::Disputed;
+                                channel.dispute = Some((hash, o, unlock_height));
+                            }
+                            None => warn!("Unknown channel: channel={}", channel_id),
+                        }
+                        self.save_channels();
+                    }
+                    None => {
+                        info!("Received public: utxo={}, amount={}", hash, o.amount);
+                        let missing = self.public_payments.insert(hash, o);
+                        assert!(missing.is_none(), "Inconsistent wallet state");
+                    }
+                },
+            },
             Output::StakeOutput(o) => {
                 let active_until_epoch = epoch + self.stake_epochs;
                 info!(
@@ -529,19 +1929,16 @@ impl WalletService {
     fn find_committed_txs(&mut self, pruned_inputs: &[Output]) {
         let hash_set: HashSet<Hash> = pruned_inputs.iter().map(Hash::digest).collect();
         for input in &hash_set {
-            if let Some(tx_hash) = self.transactions_interest.get(input) {
-                let (tx, senders) = self
+            if let Some(tx_hash) = self.transactions_interest.get(input).cloned() {
+                let (tx, _senders) = self
                     .unprocessed_transactions
-                    .remove(tx_hash)
+                    .get(&tx_hash)
                     .expect("Transaction not found in set.");
 
-                let mut conflict = false;
-                for input_hash in tx.txins() {
-                    self.transactions_interest.remove(input_hash).unwrap();
-                    if !hash_set.contains(input_hash) {
-                        conflict = true;
-                    }
-                }
+                let conflict = tx
+                    .txins()
+                    .iter()
+                    .any(|input_hash| !hash_set.contains(input_hash));
 
                 let commited = if conflict {
                     warn!("Conflicted transaction processed.");
@@ -552,34 +1949,123 @@ impl WalletService {
                 } else {
                     TransactionCommitted::Committed {}
                 };
+                self.finalize_transaction(&tx_hash, commited);
+            }
+        }
+    }
 
-                match tx {
-                    SavedTransaction::Regular(_) => {
-                        metrics::WALLET_COMMITTED_PAYMENTS
-                            .with_label_values(&[&self.keys.wallet_pkey.to_hex()])
-                            .inc();
-                    }
-                    SavedTransaction::ValueShuffle(_) => {
-                        metrics::WALLET_COMMITTED_SECURE_PAYMENTS
-                            .with_label_values(&[&self.keys.wallet_pkey.to_hex()])
-                            .inc();
-                    }
+    /// Removes transaction from the list of unprocessed and notifies waiters.
+    fn finalize_transaction(&mut self, tx_hash: &Hash, commited: TransactionCommitted) {
+        let (tx, senders) = self
+            .unprocessed_transactions
+            .remove(tx_hash)
+            .expect("Transaction not found in set.");
+        self.status_requests.remove(tx_hash);
+        for input_hash in tx.txins() {
+            self.transactions_interest.remove(input_hash).unwrap();
+        }
+
+        match tx {
+            SavedTransaction::Regular(_) => {
+                metrics::WALLET_COMMITTED_PAYMENTS
+                    .with_label_values(&[&self.keys.wallet_pkey.to_hex()])
+                    .inc();
+            }
+            SavedTransaction::ValueShuffle(_) => {
+                metrics::WALLET_COMMITTED_SECURE_PAYMENTS
+                    .with_label_values(&[&self.keys.wallet_pkey.to_hex()])
+                    .inc();
+            }
+        };
+        let msg = WalletResponse::TransactionCommitted(commited);
+        // send notification about committed transaction, drop errors if found.
+        senders
+            .into_iter()
+            .for_each(move |ch| drop(ch.send(msg.clone())));
+    }
+
+    /// Requests the status of unprocessed transactions from the node.
+    fn resend_transactions(&mut self) {
+        for (tx_hash, (tx, _senders)) in &self.unprocessed_transactions {
+            // ValueShuffle transactions are handled by ValueShuffle itself.
+            if let SavedTransaction::Regular(_) = tx {
+                if self.status_requests.contains_key(tx_hash) {
+                    continue;
+                }
+                let request = NodeRequest::TransactionStatus {
+                    tx_hash: *tx_hash,
+                    txins: tx.txins().to_vec(),
                 };
-                let msg = WalletResponse::TransactionCommitted(commited);
-                // send notification about committed transaction, drop errors if found.
-                senders
-                    .into_iter()
-                    .for_each(move |ch| drop(ch.send(msg.clone())));
+                let rx = self.node.request(request);
+                self.status_requests.insert(*tx_hash, rx);
             }
         }
     }
 
+    /// Called when the node has replied with a status of unprocessed transaction.
+    fn on_tx_status(&mut self, tx_hash: Hash, status: TransactionStatus, in_mempool: bool) {
+        match status {
+            TransactionStatus::Committed { height, .. } => {
+                debug!(
+                    "Transaction has been committed: tx={}, height={}",
+                    tx_hash, height
+                );
+                self.finalize_transaction(&tx_hash, TransactionCommitted::Committed {});
+            }
+            TransactionStatus::Conflicted { input, height, .. } => {
+                warn!(
+                    "Transaction conflicts with already committed one: tx={}, input={}, height={}",
+                    tx_hash, input, height
+                );
+                let commited = TransactionCommitted::ConflictTransactionCommitted {
+                    conflicted_output: input,
+                };
+                self.finalize_transaction(&tx_hash, commited);
+            }
+            TransactionStatus::Unknown if in_mempool => {
+                debug!("Transaction is still in mempool: tx={}", tx_hash);
+            }
+            TransactionStatus::Unknown => {
+                let tx = match self.unprocessed_transactions.get(&tx_hash) {
+                    Some((SavedTransaction::Regular(tx), _)) => tx.clone(),
+                    _ => return,
+                };
+                info!("Resending transaction: tx={}", tx_hash);
+                if let Err(e) = self.node.send_transaction(tx) {
+                    error!("Failed to resend transaction: tx={}, error={}", tx_hash, e);
+                }
+            }
+        }
+    }
+
+    /// Called when a conflicting transaction is observed by the node.
+    fn on_transaction_conflict(&mut self, conflict: TransactionConflict) {
+        if !self
+            .unprocessed_transactions
+            .contains_key(&conflict.tx_hash)
+        {
+            return;
+        }
+        warn!(
+            "Observed a conflicting transaction: tx={}, conflicting_tx={}, input={}, committed={}",
+            conflict.tx_hash, conflict.conflicting_tx_hash, conflict.input, conflict.committed
+        );
+        if conflict.committed {
+            let commited = TransactionCommitted::ConflictTransactionCommitted {
+                conflicted_output: conflict.input,
+            };
+            self.finalize_transaction(&conflict.tx_hash, commited);
+        }
+        self.notify(WalletNotification::TransactionConflict(conflict));
+    }
+
     /// Called when UTXO is spent.
     fn on_output_pruned(&mut self, _epoch: u64, output: Output) {
         if !output.is_my_utxo(&self.keys.wallet_skey, &self.keys.wallet_pkey) {
             return;
         }
         let hash = Hash::digest(&output);
+        self.frozen.prune(&hash);
 
         match output {
             Output::PaymentOutput(o) => {
@@ -587,6 +2073,14 @@ impl WalletService {
                     o.decrypt_payload(&self.keys.wallet_skey)
                 {
                     info!("Spent: utxo={}, amount={}, data={:?}", hash, amount, data);
+                    if let Some(invoice_id) = data.invoice_id() {
+                        if let Some(utxos) = self.invoices.get_mut(invoice_id) {
+                            utxos.remove(&hash);
+                            if utxos.is_empty() {
+                                self.invoices.remove(invoice_id);
+                            }
+                        }
+                    }
                     match self.payments.remove(&hash) {
                         Some(value) => {
                             let info = value.to_info();
@@ -596,9 +2090,43 @@ impl WalletService {
                     }
                 }
             }
-            Output::PublicPaymentOutput(_o) => {
-                unimplemented!();
-            }
+            Output::PublicPaymentOutput(o) => match o.hashlock {
+                Some(_) => {
+                    info!("Hash-lock spent: utxo={}, amount={}", hash, o.amount);
+                    if self.swaps.remove(&hash).is_none() {
+                        panic!("Inconsistent wallet state");
+                    }
+                }
+                None => {
+                    self.claimed.remove(&hash);
+                    match o.channel {
+                        Some(ChannelLock::Funding { .. }) => {
+                            info!("Channel settled: channel={}", hash);
+                            if let Some(channel) = self.channels.get_mut(&hash) {
+                                channel.status = ChannelStatus::Closed;
+                            }
+                            self.save_channels();
+                        }
+                        Some(ChannelLock::Dispute {
+                            channel: channel_id,
+                            ..
+                        }) => {
+                            info!("Dispute spent: channel={}, utxo={}", channel_id, hash);
+                            if let Some(channel) = self.channels.get_mut(&channel_id) {
+                                channel.status = ChannelStatus::Closed;
+                                channel.dispute = None;
+                            }
+                            self.save_channels();
+                        }
+                        None => {
+                            info!("Public spent: utxo={}, amount={}", hash, o.amount);
+                            if self.public_payments.remove(&hash).is_none() {
+                                panic!("Inconsistent wallet state");
+                            }
+                        }
+                    }
+                }
+            },
             Output::StakeOutput(o) => {
                 info!("Unstaked: utxo={}, amount={}", hash, o.amount);
                 match self.stakes.remove(&hash) {
@@ -612,14 +2140,80 @@ impl WalletService {
         }
     }
 
+    /// Request a proof of existence for a payment, including already spent ones.
+    fn payment_proof(&mut self, utxo: Hash, sender: oneshot::Sender<WalletResponse>) {
+        let rx = self.node.request(NodeRequest::OutputProof { utxo });
+        self.node_requests.push((rx, sender));
+    }
+
+    /// Request stakes of this wallet from the escrow.
+    fn staking_info(&mut self, sender: oneshot::Sender<WalletResponse>) {
+        let wallet_pkey = self.keys.wallet_pkey;
+        let rx = self
+            .node
+            .request(NodeRequest::StakesByWallet { wallet_pkey });
+        self.node_requests.push((rx, sender));
+    }
+
+    /// Called when the node has replied with a proof of output.
+    fn on_output_proof(
+        &self,
+        utxo: Hash,
+        proof: Option<OutputProof>,
+    ) -> Result<WalletResponse, Error> {
+        let proof = proof.ok_or(WalletError::PaymentNotFinalized(utxo))?;
+        if !proof
+            .output
+            .is_my_utxo(&self.keys.wallet_skey, &self.keys.wallet_pkey)
+        {
+            return Err(WalletError::NotMyPayment(utxo).into());
+        }
+        let PaymentPayload { amount, data, .. } = match proof.output {
+            Output::PaymentOutput(ref o) => o.decrypt_payload(&self.keys.wallet_skey)?,
+            _ => return Err(WalletError::NotMyPayment(utxo).into()),
+        };
+        info!(
+            "Created payment proof: utxo={}, amount={}, data={:?}",
+            utxo, amount, data
+        );
+        Ok(WalletResponse::PaymentProof {
+            utxo,
+            amount,
+            data,
+            checkpoint: proof.checkpoint_hash(),
+            proof,
+        })
+    }
+
     fn on_epoch_changed(&mut self, epoch: u64) {
         self.epoch = epoch;
 
+        if self.watch_only {
+            return;
+        }
         if let Err(e) = self.restake_expiring() {
             error!("Failed to re-stake: {}", e);
         }
     }
 
+    /// Mark payments confirmed by irreversible blocks.
+    fn on_finality_changed(&mut self, commitment: FinalityCommitment) {
+        let entries = self.history.finalize(commitment.height);
+        if entries.is_empty() {
+            return;
+        }
+        info!(
+            "Payments became irreversible: height={}, block={}, count={}",
+            commitment.height,
+            commitment.block_hash,
+            entries.len()
+        );
+        self.notify(WalletNotification::PaymentsFinalized {
+            height: commitment.height,
+            entries,
+        });
+    }
+
     fn notify(&mut self, notification: WalletNotification) {
         self.subscribers
             .retain(move |tx| tx.unbounded_send(notification.clone()).is_ok());
@@ -653,12 +2247,56 @@ impl Future for WalletService {
             match self.events.poll().expect("all errors are already handled") {
                 Async::Ready(Some(event)) => match event {
                     WalletEvent::Request { request, tx } => {
+                        if self.watch_only && request.needs_secret_key() {
+                            let response = WalletResponse::Error {
+                                error: format!("{}", WalletError::WatchOnly),
+                            };
+                            tx.send(response).ok(); // ignore errors.
+                            continue;
+                        }
                         let response = match request {
                             WalletRequest::Payment {
                                 recipient,
                                 amount,
                                 comment,
                             } => self.payment(&recipient, amount, comment).into(),
+                            WalletRequest::PayMany { recipients } => {
+                                let recipients: Vec<(PublicKey, i64, PaymentPayloadData)> =
+                                    recipients
+                                        .iter()
+                                        .map(|r| (r.recipient, r.amount, r.data()))
+                                        .collect();
+                                self.pay_many(&recipients).into()
+                            }
+                            WalletRequest::ExportPayment { recipients } => {
+                                let recipients: Vec<(PublicKey, i64, PaymentPayloadData)> =
+                                    recipients
+                                        .iter()
+                                        .map(|r| (r.recipient, r.amount, r.data()))
+                                        .collect();
+                                match self.export_payment(&recipients) {
+                                    Ok((unsigned_hash, unsigned)) => {
+                                        WalletResponse::UnsignedTransaction {
+                                            unsigned_hash,
+                                            unsigned,
+                                        }
+                                    }
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::SignTransaction { unsigned } => {
+                                match self.sign_offline(unsigned) {
+                                    Ok(signed) => WalletResponse::SignedTransaction { signed },
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::ImportSignedTransaction { signed } => {
+                                self.import_signed(signed).into()
+                            }
                             WalletRequest::SecurePayment {
                                 recipient,
                                 amount,
@@ -679,12 +2317,32 @@ impl Future for WalletService {
                             WalletRequest::Unstake { amount } => self.unstake(amount).into(),
                             WalletRequest::UnstakeAll {} => self.unstake_all().into(),
                             WalletRequest::RestakeAll {} => self.restake_all().into(),
+                            WalletRequest::Delegate { operator, amount } => {
+                                self.delegate(&operator, amount).into()
+                            }
+                            WalletRequest::RevokeDelegation {} => self.revoke_delegation().into(),
+                            WalletRequest::SetRewardDestination { reward } => {
+                                self.set_reward_destination(reward).into()
+                            }
                             WalletRequest::KeysInfo {} => WalletResponse::KeysInfo {
                                 wallet_pkey: self.keys.wallet_pkey,
                                 network_pkey: self.keys.network_pkey,
                             },
                             WalletRequest::BalanceInfo {} => WalletResponse::BalanceInfo {
                                 balance: self.balance(),
+                                frozen: self.frozen_balance(),
+                            },
+                            WalletRequest::FreezeOutput { utxo } => match self.freeze(utxo) {
+                                Ok(()) => WalletResponse::OutputFrozen { utxo },
+                                Err(e) => WalletResponse::Error {
+                                    error: format!("{}", e),
+                                },
+                            },
+                            WalletRequest::UnfreezeOutput { utxo } => match self.unfreeze(utxo) {
+                                Ok(()) => WalletResponse::OutputUnfrozen { utxo },
+                                Err(e) => WalletResponse::Error {
+                                    error: format!("{}", e),
+                                },
                             },
                             WalletRequest::UnspentInfo {} => {
                                 let epoch = self.epoch;
@@ -706,26 +2364,284 @@ impl Future for WalletService {
                                     error: format!("{}", e),
                                 },
                             },
+                            WalletRequest::ChangePassword {} => {
+                                match self.keys.change_password_from_stdin() {
+                                    Ok(()) => WalletResponse::PasswordChanged {},
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::ExportWalletKey { recovery_pkey } => {
+                                match self.keys.export_wallet_skey(&recovery_pkey) {
+                                    Ok(pem) => WalletResponse::WalletKeyExported { pem },
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::PaymentProof { utxo } => {
+                                self.payment_proof(utxo, tx);
+                                continue;
+                            }
+                            WalletRequest::StakingInfo {} => {
+                                self.staking_info(tx);
+                                continue;
+                            }
+                            WalletRequest::InvoiceInfo { invoice_id } => {
+                                self.invoice_info(invoice_id)
+                            }
+                            WalletRequest::CreateInvoice {
+                                amount,
+                                memo,
+                                lifetime,
+                            } => match self.create_invoice(amount, memo, lifetime) {
+                                Ok(response) => response,
+                                Err(e) => WalletResponse::Error {
+                                    error: format!("{}", e),
+                                },
+                            },
+                            WalletRequest::PayInvoice { invoice } => {
+                                self.pay_invoice(&invoice).into()
+                            }
+                            WalletRequest::InvoicesInfo {} => WalletResponse::InvoicesInfo {
+                                invoices: self.issued_invoices.list(SystemTime::now()),
+                            },
+                            WalletRequest::HistoryInfo { offset, limit } => {
+                                WalletResponse::HistoryInfo {
+                                    total: self.history.len(),
+                                    entries: self.history.page(offset, limit),
+                                }
+                            }
+                            WalletRequest::ExportHistory {
+                                format,
+                                from,
+                                to,
+                                direction,
+                            } => {
+                                let filter = ExportFilter {
+                                    from,
+                                    to,
+                                    direction,
+                                };
+                                let mut content = Vec::new();
+                                let count = export_history(
+                                    self.history.iter(),
+                                    &filter,
+                                    format,
+                                    &mut content,
+                                )
+                                .expect("writing to memory never fails");
+                                let extension = match format {
+                                    ExportFormat::Csv => "csv",
+                                    ExportFormat::Json => "json",
+                                };
+                                WalletResponse::HistoryExported {
+                                    format,
+                                    filename: format!("history.{}", extension),
+                                    count,
+                                    content: String::from_utf8(content).expect("valid UTF-8"),
+                                }
+                            }
+                            WalletRequest::WatchPublicKey { pkey } => {
+                                match self.watch.watch_pkey(pkey) {
+                                    Ok(()) => WalletResponse::Watching {},
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::WatchOutput { utxo, amount } => {
+                                match self.watch.watch_output(utxo, amount) {
+                                    Ok(()) => WalletResponse::Watching {},
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::DepositsInfo {} => WalletResponse::DepositsInfo {
+                                height: self.watch.height(),
+                                deposits: self.watch.deposits(),
+                            },
+                            WalletRequest::SpendPolicy {} => self.spend_policy_info(),
+                            WalletRequest::SetSpendPolicy { rules } => {
+                                match self.set_spend_policy(rules) {
+                                    Ok(()) => self.spend_policy_info(),
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::ApprovePayment { unsigned_hash } => {
+                                info!("Approved a payment: unsigned_hash={}", unsigned_hash);
+                                let approval = PaymentApproval::new(
+                                    unsigned_hash,
+                                    &self.keys.wallet_skey,
+                                    &self.keys.wallet_pkey,
+                                );
+                                WalletResponse::PaymentApproval { approval }
+                            }
+                            WalletRequest::ImportApproval { approval } => {
+                                self.import_approval(approval).into()
+                            }
+                            WalletRequest::ProveReserves { challenge, utxos } => {
+                                match self.prove_reserves(challenge, &utxos) {
+                                    Ok(proof) => WalletResponse::ReserveProof {
+                                        amount: proof.amount,
+                                        proof,
+                                    },
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::InitiateSwap {
+                                recipient,
+                                amount,
+                                hashlock,
+                                timeout,
+                            } => match self.initiate_swap(recipient, amount, hashlock, timeout) {
+                                Ok(response) => response,
+                                Err(e) => WalletResponse::Error {
+                                    error: format!("{}", e),
+                                },
+                            },
+                            WalletRequest::RedeemSwap { utxo, preimage } => {
+                                match hex::decode(&preimage) {
+                                    Ok(preimage) => self.spend_swap(utxo, Some(preimage)).into(),
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("Invalid preimage: {}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::RefundSwap { utxo } => {
+                                self.spend_swap(utxo, None).into()
+                            }
+                            WalletRequest::SwapsInfo {} => self.swaps_info(),
+                            WalletRequest::OpenChannel {
+                                counterparty,
+                                peer,
+                                amount,
+                                dispute_period,
+                            } => {
+                                match self.open_channel(counterparty, peer, amount, dispute_period)
+                                {
+                                    Ok(response) => response,
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::ChannelPay { channel, amount } => {
+                                match self.channel_pay(channel, amount) {
+                                    Ok(response) => response,
+                                    Err(e) => WalletResponse::Error {
+                                        error: format!("{}", e),
+                                    },
+                                }
+                            }
+                            WalletRequest::CloseChannel {
+                                channel,
+                                unilateral,
+                            } => match self.close_channel(channel, unilateral) {
+                                Ok(response) => response,
+                                Err(e) => WalletResponse::Error {
+                                    error: format!("{}", e),
+                                },
+                            },
+                            WalletRequest::ChannelsInfo {} => self.channels_info(),
                         };
                         tx.send(response).ok(); // ignore errors.
                     }
                     WalletEvent::Subscribe { tx } => {
                         self.subscribers.push(tx);
                     }
-                    WalletEvent::NodeOutputsChanged(OutputsChanged {
-                        epoch,
-                        inputs,
-                        outputs,
-                    }) => {
-                        self.on_outputs_changed(epoch, inputs, outputs);
+                    WalletEvent::NodeOutputsChanged(changed) => {
+                        self.on_outputs_changed(changed);
                     }
                     WalletEvent::NodeEpochChanged(EpochChanged { epoch, .. }) => {
                         self.on_epoch_changed(epoch);
                     }
+                    WalletEvent::NodeTransactionConflict(conflict) => {
+                        self.on_transaction_conflict(conflict);
+                    }
+                    WalletEvent::NodeFinalityChanged(commitment) => {
+                        self.on_finality_changed(commitment);
+                    }
+                    WalletEvent::CompoundRewards => {
+                        if let Err(e) = self.compound_rewards() {
+                            error!("Failed to stake rewards: {}", e);
+                        }
+                    }
+                    WalletEvent::ChurnOutputs => {
+                        if let Err(e) = self.churn_outputs() {
+                            error!("Failed to churn outputs: {}", e);
+                        }
+                    }
+                    WalletEvent::ResendTransactions => {
+                        self.resend_transactions();
+                    }
+                    WalletEvent::ChannelMessage(from, msg) => {
+                        if let Err(e) = self.on_channel_message(from, msg) {
+                            error!("Invalid channel message: from={}, error={}", from, e);
+                        }
+                    }
                 },
                 Async::Ready(None) => unreachable!(), // never happens
-                Async::NotReady => return Ok(Async::NotReady),
+                Async::NotReady => break,
             }
         }
+
+        let node_requests = std::mem::replace(&mut self.node_requests, Vec::new());
+        for (mut rx, tx) in node_requests {
+            let response: Result<WalletResponse, Error> = match rx.poll() {
+                Ok(Async::Ready(NodeResponse::OutputProof { utxo, proof })) => {
+                    self.on_output_proof(utxo, proof)
+                }
+                Ok(Async::Ready(NodeResponse::StakesByWallet { height, stakes, .. })) => {
+                    Ok(WalletResponse::StakingInfo { height, stakes })
+                }
+                Ok(Async::Ready(NodeResponse::Error { error })) => {
+                    Ok(WalletResponse::Error { error })
+                }
+                Ok(Async::Ready(_)) => Err(WalletError::UnexpectedNodeResponse.into()),
+                Ok(Async::NotReady) => {
+                    self.node_requests.push((rx, tx));
+                    continue;
+                }
+                Err(_) => Err(WalletError::NodeDisconnected.into()),
+            };
+            let response = response.unwrap_or_else(|e| WalletResponse::Error {
+                error: format!("{}", e),
+            });
+            tx.send(response).ok(); // ignore errors.
+        }
+
+        let status_requests = std::mem::replace(&mut self.status_requests, HashMap::new());
+        for (tx_hash, mut rx) in status_requests {
+            let error: Error = match rx.poll() {
+                Ok(Async::Ready(NodeResponse::TransactionStatus {
+                    status, in_mempool, ..
+                })) => {
+                    if self.unprocessed_transactions.contains_key(&tx_hash) {
+                        self.on_tx_status(tx_hash, status, in_mempool);
+                    }
+                    continue;
+                }
+                Ok(Async::Ready(_)) => WalletError::UnexpectedNodeResponse.into(),
+                Ok(Async::NotReady) => {
+                    self.status_requests.insert(tx_hash, rx);
+                    continue;
+                }
+                Err(_) => WalletError::NodeDisconnected.into(),
+            };
+            // The status will be requested again on the next resend.
+            error!(
+                "Failed to get transaction status: tx={}, error={}",
+                tx_hash, error
+            );
+        }
+
+        Ok(Async::NotReady)
     }
 }
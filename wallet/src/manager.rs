@@ -0,0 +1,346 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Named wallets, opened and closed at runtime.
+//!
+
+use crate::api::*;
+use crate::config::WalletConfig;
+use crate::error::WalletError;
+use crate::WalletService;
+use failure::{format_err, Error};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver};
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll, Stream};
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use stegos_blockchain::Output;
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::Hash;
+use stegos_keychain::{KeyChain, KeyChainConfig};
+use stegos_network::Network;
+use stegos_node::{Node, OutputsChanged};
+
+/// Maximum length of wallet names.
+const MAX_WALLET_NAME_LEN: usize = 32;
+
+type RecoveredState = (Vec<(Output, u64)>, UnboundedReceiver<OutputsChanged>);
+
+struct NamedWallet {
+    service: WalletService,
+    api: Wallet,
+    wallet_pkey: PublicKey,
+}
+
+/// A wallet waiting for the node to recover its outputs.
+struct PendingWallet {
+    keys: KeyChain,
+    state: oneshot::Receiver<RecoveredState>,
+    tx: oneshot::Sender<WalletsResponse>,
+}
+
+/// Manages named wallets, each with its own keys, history and other files.
+///
+/// Named wallets share the network keys, the node and the network with the default wallet.
+/// Their services are polled by this service, so closing a wallet just drops its service.
+pub struct WalletManagerService {
+    /// Default configuration, the paths are replaced for every wallet.
+    cfg: WalletConfig,
+    /// Identifier of the chain.
+    chain_id: Hash,
+    /// Keys of the node.
+    keys: KeyChain,
+    network: Network,
+    node: Node,
+    payment_fee: i64,
+    stake_fee: i64,
+    stake_epochs: u64,
+    max_inputs_per_tx: usize,
+    /// Opened wallets, by name.
+    wallets: HashMap<String, NamedWallet>,
+    /// Wallets waiting for the recovery of their state, by name.
+    pending: HashMap<String, PendingWallet>,
+    /// Incoming requests.
+    events: UnboundedReceiver<WalletsEvent>,
+}
+
+impl WalletManagerService {
+    pub fn new(
+        cfg: &WalletConfig,
+        chain_id: Hash,
+        keys: KeyChain,
+        network: Network,
+        node: Node,
+        payment_fee: i64,
+        stake_fee: i64,
+        stake_epochs: u64,
+        max_inputs_per_tx: usize,
+    ) -> (Self, Wallets) {
+        let (outbox, events) = unbounded();
+        let service = WalletManagerService {
+            cfg: cfg.clone(),
+            chain_id,
+            keys,
+            network,
+            node,
+            payment_fee,
+            stake_fee,
+            stake_epochs,
+            max_inputs_per_tx,
+            wallets: HashMap::new(),
+            pending: HashMap::new(),
+            events,
+        };
+        (service, Wallets { outbox })
+    }
+
+    fn wallet_dir(&self, name: &str) -> PathBuf {
+        Path::new(&self.cfg.wallets_dir).join(name)
+    }
+
+    /// Start opening the wallet, the response is sent when its state is recovered.
+    fn add_wallet(&mut self, name: String, password: String, tx: oneshot::Sender<WalletsResponse>) {
+        match self.open_keys(&name, &password) {
+            Ok(keys) => {
+                let state = self
+                    .node
+                    .recover_wallet(keys.wallet_skey.clone(), keys.wallet_pkey.clone());
+                let pending = PendingWallet { keys, state, tx };
+                self.pending.insert(name, pending);
+            }
+            Err(e) => {
+                error!("Failed to open wallet: name={}, error={}", name, e);
+                let error = format!("{}", e);
+                tx.send(WalletsResponse::Error { error }).ok(); // ignore errors.
+            }
+        }
+    }
+
+    fn open_keys(&self, name: &str, password: &str) -> Result<KeyChain, Error> {
+        if !is_valid_name(name) {
+            return Err(WalletError::InvalidWalletName(name.to_string()).into());
+        }
+        if self.wallets.contains_key(name) || self.pending.contains_key(name) {
+            return Err(WalletError::WalletAlreadyOpened(name.to_string()).into());
+        }
+        let dir = self.wallet_dir(name);
+        fs::create_dir_all(&dir)?;
+        let keys = self.keys.open_wallet(keychain_config(&dir), password)?;
+        info!(
+            "Opening wallet: name={}, wallet_pkey={}",
+            name, keys.wallet_pkey
+        );
+        Ok(keys)
+    }
+
+    /// Create the service of a wallet with the recovered state.
+    fn on_recovered(
+        &mut self,
+        name: String,
+        keys: KeyChain,
+        (persistent_state, outputs_changed): RecoveredState,
+    ) -> Result<PublicKey, Error> {
+        let cfg = wallet_config(&self.cfg, &self.wallet_dir(&name));
+        let wallet_pkey = keys.wallet_pkey.clone();
+        let (service, api) = WalletService::with_outputs(
+            &cfg,
+            self.chain_id,
+            keys,
+            self.network.clone(),
+            self.node.clone(),
+            self.payment_fee,
+            self.stake_fee,
+            self.stake_epochs,
+            self.max_inputs_per_tx,
+            persistent_state,
+            outputs_changed,
+        )?;
+        info!("Opened wallet: name={}, wallet_pkey={}", name, wallet_pkey);
+        let wallet = NamedWallet {
+            service,
+            api,
+            wallet_pkey,
+        };
+        self.wallets.insert(name, wallet);
+        Ok(wallet_pkey)
+    }
+
+    fn remove_wallet(&mut self, name: String) -> Result<(), Error> {
+        if self.wallets.remove(&name).is_none() {
+            match self.pending.remove(&name) {
+                Some(pending) => {
+                    let error = format!("Wallet was removed: name={}", name);
+                    pending.tx.send(WalletsResponse::Error { error }).ok(); // ignore errors.
+                }
+                None => return Err(WalletError::UnknownWallet(name).into()),
+            }
+        }
+        info!("Closed wallet: name={}", name);
+        Ok(())
+    }
+
+    fn list_wallets(&self) -> Vec<NamedWalletInfo> {
+        let mut wallets: Vec<NamedWalletInfo> = self
+            .wallets
+            .iter()
+            .map(|(name, wallet)| NamedWalletInfo {
+                name: name.clone(),
+                wallet_pkey: wallet.wallet_pkey,
+            })
+            .collect();
+        wallets.sort_by(|a, b| a.name.cmp(&b.name));
+        wallets
+    }
+
+    fn on_request(&mut self, request: WalletsRequest, tx: oneshot::Sender<WalletsResponse>) {
+        let response = match request {
+            WalletsRequest::AddWallet { name, password } => {
+                return self.add_wallet(name, password, tx);
+            }
+            WalletsRequest::RemoveWallet { name } => match self.remove_wallet(name.clone()) {
+                Ok(()) => WalletsResponse::WalletRemoved { name },
+                Err(e) => WalletsResponse::Error {
+                    error: format!("{}", e),
+                },
+            },
+            WalletsRequest::ListWallets {} => WalletsResponse::WalletsInfo {
+                wallets: self.list_wallets(),
+            },
+        };
+        tx.send(response).ok(); // ignore errors.
+    }
+
+    fn forward(&mut self, name: String, event: WalletEvent) {
+        match self.wallets.get(&name) {
+            Some(wallet) => {
+                wallet.api.outbox.unbounded_send(event).ok(); // ignore errors.
+            }
+            None => {
+                if let WalletEvent::Request { tx, .. } = event {
+                    let error = format!("{}", WalletError::UnknownWallet(name));
+                    tx.send(WalletResponse::Error { error }).ok(); // ignore errors.
+                }
+                // Subscriptions are dropped, closing the stream.
+            }
+        }
+    }
+}
+
+/// Letters, digits, '-' and '_', so names are safe to use as directory names.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_WALLET_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn path_in(dir: &Path, file: &str) -> String {
+    dir.join(file).to_string_lossy().to_string()
+}
+
+fn keychain_config(dir: &Path) -> KeyChainConfig {
+    KeyChainConfig {
+        wallet_skey_file: path_in(dir, "wallet.skey"),
+        wallet_pkey_file: path_in(dir, "wallet.pkey"),
+        policy_file: path_in(dir, "wallet.policy"),
+        ..Default::default()
+    }
+}
+
+fn wallet_config(cfg: &WalletConfig, dir: &Path) -> WalletConfig {
+    WalletConfig {
+        frozen_outputs_file: path_in(dir, "wallet.frozen"),
+        history_file: path_in(dir, "wallet.history"),
+        watch_file: path_in(dir, "wallet.watch"),
+        invoices_file: path_in(dir, "wallet.invoices"),
+        channels_file: path_in(dir, "wallet.channels"),
+        ..cfg.clone()
+    }
+}
+
+impl Future for WalletManagerService {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.events.poll().expect("all errors are already handled") {
+                Async::Ready(Some(WalletsEvent::Request { request, tx })) => {
+                    self.on_request(request, tx)
+                }
+                Async::Ready(Some(WalletsEvent::Forward { name, event })) => {
+                    self.forward(name, event)
+                }
+                Async::Ready(None) => return Ok(Async::Ready(())), // all handles are dropped.
+                Async::NotReady => break,
+            }
+        }
+
+        let names: Vec<String> = self.pending.keys().cloned().collect();
+        for name in names {
+            let state = match self.pending.get_mut(&name).unwrap().state.poll() {
+                Ok(Async::Ready(state)) => Ok(state),
+                Ok(Async::NotReady) => continue,
+                Err(_) => Err(format_err!("Node is shutting down")),
+            };
+            let PendingWallet { keys, tx, .. } = self.pending.remove(&name).unwrap();
+            let response =
+                match state.and_then(|state| self.on_recovered(name.clone(), keys, state)) {
+                    Ok(wallet_pkey) => WalletsResponse::WalletAdded { name, wallet_pkey },
+                    Err(e) => {
+                        error!("Failed to open wallet: name={}, error={}", name, e);
+                        WalletsResponse::Error {
+                            error: format!("{}", e),
+                        }
+                    }
+                };
+            tx.send(response).ok(); // ignore errors.
+        }
+
+        for wallet in self.wallets.values_mut() {
+            match wallet.service.poll() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(())) | Err(()) => unreachable!(), // never happens
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wallet_names() {
+        assert!(is_valid_name("savings"));
+        assert!(is_valid_name("cold-storage_2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("../wallet"));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name(&"x".repeat(MAX_WALLET_NAME_LEN + 1)));
+    }
+}
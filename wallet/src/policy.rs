@@ -0,0 +1,340 @@
+//! Wallet - Spend Authorization Policy.
+
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::WalletError;
+use crate::history::WalletHistory;
+use serde::de::{Deserialize, Deserializer, Error as SerdeError};
+use serde::ser::{Error as SerError, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stegos_crypto::curve1174::{sign_hash, validate_sig, PublicKey, SchnorrSig, SecretKey};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_keychain::PolicyRule;
+use stegos_serialization::traits::ProtoConvert;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A payment to be authorized before signing.
+#[derive(Debug, Clone)]
+pub struct Spend<'a> {
+    /// Hash of the unsigned transaction.
+    pub unsigned_hash: Hash,
+    /// The key of this wallet.
+    pub sender: PublicKey,
+    /// Recipients and amounts, without the change.
+    pub recipients: &'a [(PublicKey, i64)],
+    /// Spent amount, including the fee.
+    pub amount: i64,
+    /// Amount already spent since the start of the current UTC day.
+    pub spent_today: i64,
+    /// The key which approved this payment, if any.
+    pub approver: Option<PublicKey>,
+}
+
+/// A rule evaluated for every payment before signing.
+pub trait SpendRule: Send {
+    fn check(&self, spend: &Spend) -> Result<(), WalletError>;
+}
+
+/// Limits the total amount spent per UTC day.
+struct DailyLimit {
+    amount: i64,
+}
+
+impl SpendRule for DailyLimit {
+    fn check(&self, spend: &Spend) -> Result<(), WalletError> {
+        if spend.spent_today + spend.amount > self.amount {
+            return Err(WalletError::DailyLimitExceeded(
+                self.amount,
+                spend.spent_today,
+                spend.amount,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Allows payments only to the listed recipients.
+struct AllowList {
+    recipients: HashSet<PublicKey>,
+}
+
+impl SpendRule for AllowList {
+    fn check(&self, spend: &Spend) -> Result<(), WalletError> {
+        for (recipient, _amount) in spend.recipients {
+            // Payments to self are used to split and merge outputs.
+            if *recipient != spend.sender && !self.recipients.contains(recipient) {
+                return Err(WalletError::RecipientNotAllowed(*recipient));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Requires a signature of the approver for payments above the threshold.
+struct SecondApprover {
+    threshold: i64,
+    approver: PublicKey,
+}
+
+impl SpendRule for SecondApprover {
+    fn check(&self, spend: &Spend) -> Result<(), WalletError> {
+        if spend.amount > self.threshold && spend.approver != Some(self.approver) {
+            return Err(WalletError::ApprovalRequired(
+                spend.unsigned_hash,
+                self.approver,
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn new_rule(definition: &PolicyRule) -> Box<dyn SpendRule> {
+    match definition {
+        PolicyRule::DailyLimit { amount } => Box::new(DailyLimit { amount: *amount }),
+        PolicyRule::AllowList { recipients } => Box::new(AllowList {
+            recipients: recipients.iter().cloned().collect(),
+        }),
+        PolicyRule::SecondApprover {
+            threshold,
+            approver,
+        } => Box::new(SecondApprover {
+            threshold: *threshold,
+            approver: *approver,
+        }),
+    }
+}
+
+/// Rules evaluated before signing a payment.
+pub(crate) struct SpendPolicy {
+    /// Rules defined in the keystore.
+    definitions: Vec<PolicyRule>,
+    rules: Vec<Box<dyn SpendRule>>,
+    /// Rules added via API, kept on reloading the definitions.
+    custom: Vec<Box<dyn SpendRule>>,
+    /// Sent payments which are not in the history yet, by tx_hash.
+    pending: HashMap<Hash, (SystemTime, i64)>,
+}
+
+impl SpendPolicy {
+    pub fn new(definitions: Vec<PolicyRule>) -> Self {
+        let mut policy = SpendPolicy {
+            definitions: Vec::new(),
+            rules: Vec::new(),
+            custom: Vec::new(),
+            pending: HashMap::new(),
+        };
+        policy.set_definitions(definitions);
+        policy
+    }
+
+    /// Replace rules defined in the keystore.
+    pub fn set_definitions(&mut self, definitions: Vec<PolicyRule>) {
+        self.rules = definitions.iter().map(new_rule).collect();
+        self.definitions = definitions;
+    }
+
+    pub fn definitions(&self) -> &[PolicyRule] {
+        &self.definitions
+    }
+
+    pub fn add_rule(&mut self, rule: Box<dyn SpendRule>) {
+        self.custom.push(rule);
+    }
+
+    /// Check the payment against all rules.
+    pub fn check(&self, spend: &Spend) -> Result<(), WalletError> {
+        for rule in self.rules.iter().chain(self.custom.iter()) {
+            rule.check(spend)?;
+        }
+        Ok(())
+    }
+
+    /// Account a sent payment until it appears in the history.
+    pub fn on_sent(&mut self, tx_hash: Hash, timestamp: SystemTime, amount: i64) {
+        self.pending.insert(tx_hash, (timestamp, amount));
+    }
+
+    /// Amount spent since the start of the UTC day.
+    pub fn spent_today(&mut self, history: &WalletHistory, now: SystemTime) -> i64 {
+        let since = day_start(now);
+        let mut spent: i64 = 0;
+        for (tx_hash, amount) in history.outgoing_since(since) {
+            if let Some(tx_hash) = tx_hash {
+                self.pending.remove(&tx_hash);
+            }
+            spent += amount;
+        }
+        self.pending
+            .retain(|_tx_hash, (timestamp, _amount)| *timestamp >= since);
+        spent + self.pending.values().map(|(_, amount)| amount).sum::<i64>()
+    }
+}
+
+fn day_start(now: SystemTime) -> SystemTime {
+    let since_epoch = now
+        .duration_since(UNIX_EPOCH)
+        .expect("time is after UNIX epoch")
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(since_epoch - since_epoch % SECONDS_PER_DAY)
+}
+
+/// A signature of the second approver for a payment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentApproval {
+    pub unsigned_hash: Hash,
+    pub approver: PublicKey,
+    #[serde(serialize_with = "serialize_signature")]
+    #[serde(deserialize_with = "deserialize_signature")]
+    pub signature: SchnorrSig,
+}
+
+impl PaymentApproval {
+    pub fn new(unsigned_hash: Hash, skey: &SecretKey, pkey: &PublicKey) -> Self {
+        let signature = sign_hash(&approval_hash(&unsigned_hash), skey);
+        PaymentApproval {
+            unsigned_hash,
+            approver: *pkey,
+            signature,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), WalletError> {
+        validate_sig(
+            &approval_hash(&self.unsigned_hash),
+            &self.signature,
+            &self.approver,
+        )
+        .map_err(|_| WalletError::InvalidApproval(self.unsigned_hash))
+    }
+}
+
+fn approval_hash(unsigned_hash: &Hash) -> Hash {
+    let mut hasher = Hasher::new();
+    "PaymentApproval".hash(&mut hasher);
+    unsigned_hash.hash(&mut hasher);
+    hasher.result()
+}
+
+/// Serialized as a hex-encoded protobuf.
+fn serialize_signature<S>(signature: &SchnorrSig, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let buf = signature.into_buffer().map_err(S::Error::custom)?;
+    serializer.serialize_str(&hex::encode(&buf))
+}
+
+fn deserialize_signature<'de, D>(deserializer: D) -> Result<SchnorrSig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let buf = hex::decode(&s).map_err(D::Error::custom)?;
+    SchnorrSig::from_buffer(&buf).map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::make_random_keys;
+
+    #[test]
+    fn rules() {
+        let (_skey, sender) = make_random_keys();
+        let (_skey1, recipient1) = make_random_keys();
+        let (_skey2, recipient2) = make_random_keys();
+        let (approver_skey, approver_pkey) = make_random_keys();
+        let unsigned_hash = Hash::digest("unsigned");
+
+        let policy = SpendPolicy::new(vec![
+            PolicyRule::DailyLimit { amount: 100 },
+            PolicyRule::AllowList {
+                recipients: vec![recipient1],
+            },
+            PolicyRule::SecondApprover {
+                threshold: 50,
+                approver: approver_pkey,
+            },
+        ]);
+        let recipients = [(recipient1, 10), (sender, 5)];
+        let mut spend = Spend {
+            unsigned_hash,
+            sender,
+            recipients: &recipients,
+            amount: 16,
+            spent_today: 0,
+            approver: None,
+        };
+        policy.check(&spend).unwrap();
+
+        spend.spent_today = 90;
+        assert_eq!(
+            policy.check(&spend),
+            Err(WalletError::DailyLimitExceeded(100, 90, 16))
+        );
+        spend.spent_today = 0;
+
+        let recipients = [(recipient2, 10)];
+        spend.recipients = &recipients;
+        assert_eq!(
+            policy.check(&spend),
+            Err(WalletError::RecipientNotAllowed(recipient2))
+        );
+
+        let recipients = [(recipient1, 60)];
+        spend.recipients = &recipients;
+        spend.amount = 61;
+        assert_eq!(
+            policy.check(&spend),
+            Err(WalletError::ApprovalRequired(unsigned_hash, approver_pkey))
+        );
+
+        let approval = PaymentApproval::new(unsigned_hash, &approver_skey, &approver_pkey);
+        approval.validate().unwrap();
+        let json = serde_json::to_string(&approval).unwrap();
+        let approval2: PaymentApproval = serde_json::from_str(&json).unwrap();
+        approval2.validate().unwrap();
+        spend.approver = Some(approval2.approver);
+        policy.check(&spend).unwrap();
+
+        let forged = PaymentApproval {
+            unsigned_hash: Hash::digest("other"),
+            ..approval
+        };
+        assert_eq!(
+            forged.validate(),
+            Err(WalletError::InvalidApproval(forged.unsigned_hash))
+        );
+    }
+
+    #[test]
+    fn test_day_start() {
+        let now = UNIX_EPOCH + Duration::from_secs(3 * SECONDS_PER_DAY + 100);
+        assert_eq!(
+            day_start(now),
+            UNIX_EPOCH + Duration::from_secs(3 * SECONDS_PER_DAY)
+        );
+    }
+}
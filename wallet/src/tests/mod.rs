@@ -104,3 +104,19 @@ Error {
     error: String,
 },
 */
+
+#[test]
+fn response_balance_info() {
+    let response = WalletResponse::BalanceInfo {
+        balance: 100,
+        frozen: 10,
+    };
+    compare(
+        response,
+        json!({
+            "response": "balance_info",
+            "balance": 100,
+            "frozen": 10,
+        }),
+    );
+}
@@ -22,6 +22,7 @@
 // SOFTWARE.
 
 use crate::change::*;
+use crate::churn::{output_bucket, split_amount};
 use crate::error::*;
 use crate::valueshuffle::ProposedUTXO;
 use failure::Error;
@@ -29,6 +30,7 @@ use log::*;
 use stegos_blockchain::*;
 use stegos_crypto::curve1174::Fr;
 use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::curve1174::SchnorrSig;
 use stegos_crypto::curve1174::SecretKey;
 use stegos_crypto::hash::Hash;
 use stegos_crypto::pbc;
@@ -38,6 +40,7 @@ pub(crate) fn create_vs_payment_transaction<'a, UnspentIter>(
     sender_pkey: &PublicKey,
     recipient: &PublicKey,
     unspent_iter: UnspentIter,
+    max_inputs: usize,
     amount: i64,
     payment_fee: i64,
     data: String,
@@ -65,7 +68,7 @@ where
     trace!("Checking for available funds in the wallet...");
     let fee = payment_fee;
     let fee_change = fee + payment_fee;
-    let (inputs, fee, change) = find_utxo(unspent_iter, amount, fee, fee_change)?;
+    let (inputs, fee, change) = find_utxo(unspent_iter, amount, fee, fee_change, max_inputs)?;
     let inputs: Vec<Output> = inputs
         .into_iter()
         .map(|o| Output::PaymentOutput(o.clone()))
@@ -142,27 +145,35 @@ where
     Ok((inputs_pairs, outputs, fee))
 }
 
-/// Create a new payment transaction.
+/// Create a new payment transaction with one output per recipient.
+/// With `pad_outputs`, the change is split to pad the number of outputs to a standard bucket.
 pub(crate) fn create_payment_transaction<'a, UnspentIter>(
     sender_pkey: &PublicKey,
-    recipient: &PublicKey,
+    recipients: &[(PublicKey, i64, PaymentPayloadData)],
     unspent_iter: UnspentIter,
-    amount: i64,
+    max_inputs: usize,
     payment_fee: i64,
-    data: PaymentPayloadData,
+    pad_outputs: bool,
 ) -> Result<(Vec<Output>, Vec<Output>, Fr, i64), Error>
 where
     UnspentIter: Iterator<Item = (&'a PaymentOutput, i64)>,
 {
-    if amount < 0 {
-        return Err(WalletError::NegativeAmount(amount).into());
+    if recipients.is_empty() {
+        return Err(WalletError::NoRecipients.into());
+    }
+    let mut amount: i64 = 0;
+    for (_recipient, recipient_amount, data) in recipients {
+        if *recipient_amount < 0 {
+            return Err(WalletError::NegativeAmount(*recipient_amount).into());
+        }
+        data.validate()?;
+        amount += recipient_amount;
     }
-
-    data.validate()?;
 
     debug!(
-        "Creating a payment transaction: recipient={:?}, amount={}",
-        recipient, amount
+        "Creating a payment transaction: recipients={}, amount={}",
+        recipients.len(),
+        amount
     );
 
     //
@@ -170,9 +181,15 @@ where
     //
 
     trace!("Checking for available funds in the wallet...");
-    let fee = payment_fee;
-    let fee_change = fee + payment_fee;
-    let (inputs, fee, change) = find_utxo(unspent_iter, amount, fee, fee_change)?;
+    let change_outputs = if pad_outputs {
+        let num_outputs = std::cmp::max(output_bucket(recipients.len() + 1), recipients.len() + 1);
+        num_outputs - recipients.len()
+    } else {
+        1
+    };
+    let fee = payment_fee * (recipients.len() as i64);
+    let fee_change = fee + payment_fee * (change_outputs as i64);
+    let (inputs, fee, change) = find_utxo(unspent_iter, amount, fee, fee_change, max_inputs)?;
     let inputs: Vec<Output> = inputs
         .into_iter()
         .map(|o| Output::PaymentOutput(o.clone()))
@@ -180,8 +197,8 @@ where
     assert!(!inputs.is_empty());
 
     debug!(
-        "Transaction preview: recipient={:?}, amount={}, withdrawn={}, change={}, fee={}",
-        recipient,
+        "Transaction preview: recipients={}, amount={}, withdrawn={}, change={}, fee={}",
+        recipients.len(),
         amount,
         amount + change + fee,
         change,
@@ -195,37 +212,191 @@ where
     // Create outputs
     //
 
-    let mut outputs: Vec<Output> = Vec::<Output>::with_capacity(2);
+    let mut outputs: Vec<Output> = Vec::<Output>::with_capacity(recipients.len() + change_outputs);
+    let mut gamma = Fr::zero();
 
-    // Create an output for payment
-    trace!("Creating change UTXO...");
-    let (output1, gamma1) = PaymentOutput::with_payload(recipient, amount, data.clone())?;
-    let output1_hash = Hash::digest(&output1);
+    // Create an output for each payment
+    for (recipient, amount, data) in recipients {
+        trace!("Creating payment UTXO...");
+        let (output, output_gamma) = PaymentOutput::with_payload(recipient, *amount, data.clone())?;
+        info!(
+            "Created payment UTXO: hash={}, recipient={}, amount={}, data={:?}",
+            Hash::digest(&output),
+            recipient,
+            amount,
+            data
+        );
+        outputs.push(Output::PaymentOutput(output));
+        gamma += output_gamma;
+    }
+
+    if change > 0 {
+        // Create outputs for change, every output gets at least one token.
+        let change_outputs = std::cmp::min(change_outputs as i64, change) as usize;
+        for change in split_amount(change, change_outputs, &mut rand::thread_rng()) {
+            trace!("Creating change UTXO...");
+            let data = PaymentPayloadData::Comment("Change".to_string());
+            let (output, output_gamma) =
+                PaymentOutput::with_payload(sender_pkey, change, data.clone())?;
+            info!(
+                "Created change UTXO: hash={}, recipient={}, change={}, data={:?}",
+                Hash::digest(&output),
+                sender_pkey,
+                change,
+                data
+            );
+            outputs.push(Output::PaymentOutput(output));
+            gamma += output_gamma;
+        }
+    }
+
+    info!(
+        "Created payment transaction: recipients={}, amount={}, withdrawn={}, change={}, fee={}",
+        recipients.len(),
+        amount,
+        amount + change + fee,
+        change,
+        fee
+    );
+
+    Ok((inputs, outputs, gamma, fee))
+}
+
+/// Create a new transaction which locks money in a hashed time-lock contract.
+pub(crate) fn create_swap_transaction<'a, UnspentIter>(
+    sender_pkey: &PublicKey,
+    recipient: &PublicKey,
+    hash: Hash,
+    timeout: LockTime,
+    unspent_iter: UnspentIter,
+    max_inputs: usize,
+    amount: i64,
+    payment_fee: i64,
+) -> Result<(Vec<Output>, Vec<Output>, Fr, i64), Error>
+where
+    UnspentIter: Iterator<Item = (&'a PaymentOutput, i64)>,
+{
+    debug!(
+        "Creating a swap transaction: recipient={}, amount={}, hashlock={}, timeout={}",
+        recipient, amount, hash, timeout
+    );
+    let output = PublicPaymentOutput::new_hashlocked(recipient, sender_pkey, amount, hash, timeout);
+    info!(
+        "Created hash-locked UTXO: hash={}, recipient={}, amount={}, hashlock={}, timeout={}",
+        Hash::digest(&output),
+        recipient,
+        amount,
+        hash,
+        timeout
+    );
+    create_public_output_transaction(
+        sender_pkey,
+        output,
+        unspent_iter,
+        max_inputs,
+        payment_fee,
+        "swap",
+    )
+}
+
+/// Create a new transaction which funds a payment channel.
+pub(crate) fn create_channel_funding_transaction<'a, UnspentIter>(
+    sender_pkey: &PublicKey,
+    counterparty: &PublicKey,
+    dispute_period: u64,
+    unspent_iter: UnspentIter,
+    max_inputs: usize,
+    amount: i64,
+    payment_fee: i64,
+) -> Result<(Vec<Output>, Vec<Output>, Fr, i64), Error>
+where
+    UnspentIter: Iterator<Item = (&'a PaymentOutput, i64)>,
+{
+    debug!(
+        "Creating a channel funding transaction: counterparty={}, amount={}, dispute_period={}",
+        counterparty, amount, dispute_period
+    );
+    let output =
+        PublicPaymentOutput::new_channel_funding(sender_pkey, counterparty, amount, dispute_period);
     info!(
-        "Created payment UTXO: hash={}, recipient={}, amount={}, data={:?}",
-        output1_hash, recipient, amount, data
+        "Created channel funding UTXO: hash={}, counterparty={}, amount={}, dispute_period={}",
+        Hash::digest(&output),
+        counterparty,
+        amount,
+        dispute_period
     );
-    outputs.push(Output::PaymentOutput(output1));
-    let mut gamma = gamma1;
+    create_public_output_transaction(
+        sender_pkey,
+        output,
+        unspent_iter,
+        max_inputs,
+        payment_fee,
+        "funding",
+    )
+}
+
+/// Create a new transaction which creates `output` as the first output.
+fn create_public_output_transaction<'a, UnspentIter>(
+    sender_pkey: &PublicKey,
+    output: PublicPaymentOutput,
+    unspent_iter: UnspentIter,
+    max_inputs: usize,
+    payment_fee: i64,
+    kind: &str,
+) -> Result<(Vec<Output>, Vec<Output>, Fr, i64), Error>
+where
+    UnspentIter: Iterator<Item = (&'a PaymentOutput, i64)>,
+{
+    let amount = output.amount;
+    if amount <= 0 {
+        return Err(WalletError::NegativeAmount(amount).into());
+    }
+
+    //
+    // Find inputs
+    //
+
+    trace!("Checking for available funds in the wallet...");
+    let fee = payment_fee;
+    let fee_change = fee + payment_fee;
+    let (inputs, fee, change) = find_utxo(unspent_iter, amount, fee, fee_change, max_inputs)?;
+    let inputs: Vec<Output> = inputs
+        .into_iter()
+        .map(|o| Output::PaymentOutput(o.clone()))
+        .collect();
+    assert!(!inputs.is_empty());
+
+    //
+    // Create outputs
+    //
+
+    let mut outputs: Vec<Output> = Vec::<Output>::with_capacity(2);
+    let mut gamma = Fr::zero();
+
+    // Public outputs have no gamma.
+    let recipient = output.recipient;
+    outputs.push(Output::PublicPaymentOutput(output));
 
     if change > 0 {
         // Create an output for change
         trace!("Creating change UTXO...");
         let data = PaymentPayloadData::Comment("Change".to_string());
-        let (output2, gamma2) = PaymentOutput::with_payload(sender_pkey, change, data.clone())?;
+        let (output, output_gamma) =
+            PaymentOutput::with_payload(sender_pkey, change, data.clone())?;
         info!(
             "Created change UTXO: hash={}, recipient={}, change={}, data={:?}",
-            Hash::digest(&output2),
+            Hash::digest(&output),
             sender_pkey,
             change,
             data
         );
-        outputs.push(Output::PaymentOutput(output2));
-        gamma += gamma2;
+        outputs.push(Output::PaymentOutput(output));
+        gamma += output_gamma;
     }
 
     info!(
-        "Created payment transaction: recipient={}, amount={}, withdrawn={}, change={}, fee={}",
+        "Created {} transaction: recipient={}, amount={}, withdrawn={}, change={}, fee={}",
+        kind,
         recipient,
         amount,
         amount + change + fee,
@@ -236,13 +407,205 @@ where
     Ok((inputs, outputs, gamma, fee))
 }
 
+/// Create a new transaction which redeems a hash-locked output if `preimage` is set,
+/// or refunds it otherwise.
+pub(crate) fn create_swap_spending_transaction(
+    chain_id: &Hash,
+    sender_skey: &SecretKey,
+    sender_pkey: &PublicKey,
+    htlc: &PublicPaymentOutput,
+    preimage: Option<Vec<u8>>,
+    payment_fee: i64,
+) -> Result<PaymentTransaction, Error> {
+    let htlc_hash = Hash::digest(htlc);
+    let amount = htlc.amount - payment_fee;
+    if amount <= 0 {
+        return Err(WalletError::NotEnoughMoney.into());
+    }
+
+    let inputs = [Output::PublicPaymentOutput(htlc.clone())];
+    let data = PaymentPayloadData::Comment("Atomic swap".to_string());
+    let (output, outputs_gamma) = PaymentOutput::with_payload(sender_pkey, amount, data)?;
+    let outputs = [Output::PaymentOutput(output)];
+    let is_redeem = preimage.is_some();
+    let preimages = match preimage {
+        Some(preimage) => vec![(htlc_hash, preimage)],
+        None => Vec::new(),
+    };
+    let tx = PaymentTransaction::with_preimages(
+        chain_id,
+        sender_skey,
+        &inputs,
+        preimages,
+        &outputs,
+        &outputs_gamma,
+        payment_fee,
+    )?;
+
+    info!(
+        "Created swap {} transaction: utxo={}, amount={}, fee={}",
+        if is_redeem { "redeem" } else { "refund" },
+        htlc_hash,
+        amount,
+        payment_fee
+    );
+
+    Ok(tx)
+}
+
+/// Create a new transaction which settles a payment channel according to `state`.
+///
+/// A cooperative close is created by the funder and must be cosigned by the counterparty.
+/// An unilateral close pays the balance of the other party immediately
+/// and locks the balance of the closer for the dispute period.
+pub(crate) fn create_channel_close_transaction(
+    chain_id: &Hash,
+    sender_skey: &SecretKey,
+    sender_pkey: &PublicKey,
+    funding: &PublicPaymentOutput,
+    state: &ChannelState,
+    unilateral: bool,
+    payment_fee: i64,
+) -> Result<PaymentTransaction, Error> {
+    let channel = Hash::digest(funding);
+    let dispute_period = match &funding.channel {
+        Some(ChannelLock::Funding { dispute_period, .. }) => *dispute_period,
+        _ => panic!("funding output"),
+    };
+    let other = state.other(sender_pkey).expect("party of channel");
+    let balance = state.balance(sender_pkey).expect("party of channel") - payment_fee;
+    let other_balance = state.balance(other).expect("party of channel");
+    if balance <= 0 {
+        return Err(WalletError::NotEnoughMoney.into());
+    }
+
+    let inputs = [Output::PublicPaymentOutput(funding.clone())];
+    let mut outputs: Vec<Output> = Vec::with_capacity(2);
+    let mut outputs_gamma = Fr::zero();
+    let witness = if unilateral {
+        if other_balance > 0 {
+            let output = PublicPaymentOutput::new(other, other_balance);
+            outputs.push(Output::PublicPaymentOutput(output));
+        }
+        let output = PublicPaymentOutput::new_channel_dispute(
+            sender_pkey,
+            other,
+            balance,
+            channel,
+            state.sequence,
+            dispute_period,
+        );
+        outputs.push(Output::PublicPaymentOutput(output));
+        ChannelWitness::Close {
+            txin: channel,
+            state: state.clone(),
+            closer: *sender_pkey,
+        }
+    } else {
+        if sender_pkey != &state.funder {
+            return Err(WalletError::NotChannelFunder(channel).into());
+        }
+        let data = PaymentPayloadData::Comment("Payment channel".to_string());
+        if other_balance > 0 {
+            let (output, gamma) = PaymentOutput::with_payload(other, other_balance, data.clone())?;
+            outputs.push(Output::PaymentOutput(output));
+            outputs_gamma += gamma;
+        }
+        let (output, gamma) = PaymentOutput::with_payload(sender_pkey, balance, data)?;
+        outputs.push(Output::PaymentOutput(output));
+        outputs_gamma += gamma;
+        ChannelWitness::Cooperative {
+            txin: channel,
+            sig: SchnorrSig::new(),
+        }
+    };
+
+    let tx = PaymentTransaction::with_witnesses(
+        chain_id,
+        sender_skey,
+        &inputs,
+        Vec::new(),
+        vec![witness],
+        &outputs,
+        &outputs_gamma,
+        payment_fee,
+    )?;
+
+    info!(
+        "Created {} channel close transaction: channel={}, sequence={}, balance={}, fee={}",
+        if unilateral {
+            "unilateral"
+        } else {
+            "cooperative"
+        },
+        channel,
+        state.sequence,
+        balance,
+        payment_fee
+    );
+
+    Ok(tx)
+}
+
+/// Create a new transaction which moves a public output of this wallet to a regular payment.
+/// If `penalty` is set, spends the dispute output of the other party using a newer state.
+pub(crate) fn create_public_spending_transaction(
+    chain_id: &Hash,
+    sender_skey: &SecretKey,
+    sender_pkey: &PublicKey,
+    output: &PublicPaymentOutput,
+    penalty: Option<ChannelState>,
+    payment_fee: i64,
+) -> Result<PaymentTransaction, Error> {
+    let output_hash = Hash::digest(output);
+    let amount = output.amount - payment_fee;
+    if amount <= 0 {
+        return Err(WalletError::NotEnoughMoney.into());
+    }
+
+    let inputs = [Output::PublicPaymentOutput(output.clone())];
+    let data = PaymentPayloadData::Comment("Payment channel".to_string());
+    let (payment, outputs_gamma) = PaymentOutput::with_payload(sender_pkey, amount, data)?;
+    let outputs = [Output::PaymentOutput(payment)];
+    let channels = match penalty {
+        Some(state) => vec![ChannelWitness::Penalty {
+            txin: output_hash,
+            state,
+        }],
+        None => Vec::new(),
+    };
+    let tx = PaymentTransaction::with_witnesses(
+        chain_id,
+        sender_skey,
+        &inputs,
+        Vec::new(),
+        channels,
+        &outputs,
+        &outputs_gamma,
+        payment_fee,
+    )?;
+
+    info!(
+        "Created public output spending transaction: utxo={}, amount={}, fee={}",
+        output_hash, amount, payment_fee
+    );
+
+    Ok(tx)
+}
+
 /// Create a new staking transaction.
+/// If `operator_pkey` is set, the stake is delegated to this operator.
+/// If `reward` is set, block rewards of the stake are paid to this destination.
 pub(crate) fn create_staking_transaction<'a, UnspentIter>(
+    chain_id: &Hash,
     sender_skey: &SecretKey,
     sender_pkey: &PublicKey,
     validator_pkey: &pbc::PublicKey,
     validator_skey: &pbc::SecretKey,
+    operator_pkey: Option<&pbc::PublicKey>,
+    reward: Option<&RewardDestination>,
     unspent_iter: UnspentIter,
+    max_inputs: usize,
     amount: i64,
     payment_fee: i64,
     stake_fee: i64,
@@ -258,8 +621,8 @@ where
     }
 
     debug!(
-        "Creating a staking transaction: validator={:?}, amount={}",
-        validator_pkey, amount
+        "Creating a staking transaction: validator={:?}, operator={:?}, amount={}",
+        validator_pkey, operator_pkey, amount
     );
 
     //
@@ -269,7 +632,7 @@ where
     trace!("Checking for available funds in the wallet...");
     let fee = stake_fee;
     let fee_change = fee + payment_fee;
-    let (inputs, fee, change) = find_utxo(unspent_iter, amount, fee, fee_change)?;
+    let (inputs, fee, change) = find_utxo(unspent_iter, amount, fee, fee_change, max_inputs)?;
     let inputs: Vec<Output> = inputs
         .into_iter()
         .map(|o| Output::PaymentOutput(o.clone()))
@@ -297,12 +660,26 @@ where
 
     // Create an output for staking.
     trace!("Creating stake UTXO...");
-    let output1 = Output::new_stake(sender_pkey, validator_skey, validator_pkey, amount)?;
+    let mut output1 = match operator_pkey {
+        Some(operator_pkey) => StakeOutput::new_delegated(
+            sender_pkey,
+            validator_skey,
+            validator_pkey,
+            operator_pkey,
+            amount,
+        )?,
+        None => StakeOutput::new(sender_pkey, validator_skey, validator_pkey, amount)?,
+    };
+    if let Some(reward) = reward {
+        output1.set_reward(Some(reward.clone()), validator_skey);
+    }
+    let output1 = Output::StakeOutput(output1);
     info!(
-        "Created stake UTXO: hash={}, recipient={}, validator={}, amount={}",
+        "Created stake UTXO: hash={}, recipient={}, validator={}, operator={:?}, amount={}",
         Hash::digest(&output1),
         sender_pkey,
         validator_pkey,
+        operator_pkey,
         amount
     );
     outputs.push(output1);
@@ -323,7 +700,7 @@ where
     }
 
     trace!("Signing transaction...");
-    let tx = PaymentTransaction::new(&sender_skey, &inputs, &outputs, &gamma, fee)?;
+    let tx = PaymentTransaction::new(chain_id, &sender_skey, &inputs, &outputs, &gamma, fee)?;
     let tx_hash = Hash::digest(&tx);
     info!(
         "Signed stake transaction: hash={}, validator={}, stake={}, withdrawn={}, change={}, fee={}",
@@ -341,11 +718,13 @@ where
 /// Create a new unstaking transaction.
 /// NOTE: amount must include PAYMENT_FEE.
 pub(crate) fn create_unstaking_transaction<'a, UnspentIter>(
+    chain_id: &Hash,
     sender_skey: &SecretKey,
     sender_pkey: &PublicKey,
     validator_pkey: &pbc::PublicKey,
     validator_skey: &pbc::SecretKey,
     unspent_iter: UnspentIter,
+    max_inputs: usize,
     amount: i64,
     payment_fee: i64,
     stake_fee: i64,
@@ -369,8 +748,13 @@ where
     trace!("Checking for staked money in the wallet...");
     let unspent_iter = unspent_iter.map(|o| (o, o.amount));
     let amount = amount - payment_fee;
-    let (inputs, fee, change) =
-        find_utxo(unspent_iter, amount, payment_fee, payment_fee + stake_fee)?;
+    let (inputs, fee, change) = find_utxo(
+        unspent_iter,
+        amount,
+        payment_fee,
+        payment_fee + stake_fee,
+        max_inputs,
+    )?;
     let inputs: Vec<Output> = inputs
         .into_iter()
         .map(|o| Output::StakeOutput(o.clone()))
@@ -421,7 +805,7 @@ where
     }
 
     trace!("Signing transaction...");
-    let tx = PaymentTransaction::new(&sender_skey, &inputs, &outputs, &gamma, fee)?;
+    let tx = PaymentTransaction::new(chain_id, &sender_skey, &inputs, &outputs, &gamma, fee)?;
     let tx_hash = Hash::digest(&tx);
     info!(
         "Signed unstake transaction: hash={}, validator={}, unstake={}, stake={}, fee={}",
@@ -431,12 +815,39 @@ where
     Ok(tx)
 }
 
+/// Amount of block rewards to stake, or None if the threshold is not reached.
+pub(crate) fn compound_amount(
+    rewards: i64,
+    staked: i64,
+    threshold: i64,
+    max_stake: i64,
+    payment_fee: i64,
+    stake_fee: i64,
+) -> Option<i64> {
+    if rewards < threshold {
+        return None;
+    }
+    // Stake all rewards without a change.
+    let mut amount = rewards - stake_fee;
+    if max_stake > 0 && staked + amount > max_stake {
+        // Stake up to the limit and pay the change back.
+        amount = std::cmp::min(max_stake - staked, rewards - stake_fee - payment_fee);
+    }
+    if amount <= payment_fee {
+        return None;
+    }
+    Some(amount)
+}
+
 /// Create a restaking transaction.
+/// Block rewards of new stakes are paid to `reward`, if set.
 pub(crate) fn create_restaking_transaction<'a, UnspentIter>(
+    chain_id: &Hash,
     _sender_skey: &SecretKey,
     sender_pkey: &PublicKey,
     validator_pkey: &pbc::PublicKey,
     validator_skey: &pbc::SecretKey,
+    reward: Option<&RewardDestination>,
     stakes_iter: UnspentIter,
 ) -> Result<RestakeTransaction, Error>
 where
@@ -459,7 +870,19 @@ where
         inputs.push(Output::StakeOutput(input.clone()));
 
         trace!("Creating StakeUTXO...");
-        let output = Output::new_stake(sender_pkey, validator_skey, validator_pkey, input.amount)?;
+        // Keep delegation of the original stake.
+        let mut output = match input.operator {
+            Some(ref operator_pkey) => StakeOutput::new_delegated(
+                sender_pkey,
+                validator_skey,
+                validator_pkey,
+                operator_pkey,
+                input.amount,
+            )?,
+            None => StakeOutput::new(sender_pkey, validator_skey, validator_pkey, input.amount)?,
+        };
+        output.set_reward(reward.cloned(), validator_skey);
+        let output = Output::StakeOutput(output);
         debug!(
             "Stake: hash={}, validator={}, amount={}",
             Hash::digest(&output),
@@ -470,7 +893,7 @@ where
     }
 
     trace!("Signing transaction...");
-    let tx = RestakeTransaction::new(validator_skey, validator_pkey, &inputs, &outputs)?;
+    let tx = RestakeTransaction::new(chain_id, validator_skey, validator_pkey, &inputs, &outputs)?;
     let tx_hash = Hash::digest(&tx);
     info!(
         "Created a restaking transaction: hash={}, inputs={}, outputs={}",
@@ -488,9 +911,331 @@ pub mod tests {
     use stegos_crypto::curve1174::make_random_keys;
     use stegos_crypto::pbc;
 
+    const MAX_INPUTS: usize = 100;
+
+    #[test]
+    fn compound_rewards() {
+        // Below the threshold.
+        assert_eq!(compound_amount(99, 0, 100, 0, 1, 0), None);
+        // All rewards.
+        assert_eq!(compound_amount(100, 0, 100, 0, 1, 0), Some(100));
+        assert_eq!(compound_amount(100, 0, 100, 0, 1, 2), Some(98));
+        // Limited by max_stake.
+        assert_eq!(compound_amount(100, 950, 100, 1000, 1, 0), Some(50));
+        assert_eq!(compound_amount(100, 901, 100, 1000, 1, 0), Some(99));
+        assert_eq!(compound_amount(100, 1000, 100, 1000, 1, 0), None);
+        assert_eq!(compound_amount(100, 1100, 100, 1000, 1, 0), None);
+    }
+
+    /// Check payment to several recipients.
+    #[test]
+    fn multi_payment_transactions() {
+        let chain_id = Hash::digest("test");
+        let payment_fee: i64 = 1;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let (skey, pkey) = make_random_keys();
+        let (skey1, pkey1) = make_random_keys();
+        let (skey2, pkey2) = make_random_keys();
+
+        let (input, _gamma) = PaymentOutput::new(&pkey, 100).expect("keys are valid");
+        let inputs = [Output::PaymentOutput(input.clone())];
+        let unspent = vec![(&input, 100)];
+
+        let recipients = [
+            (pkey1, 10, PaymentPayloadData::Comment("first".to_string())),
+            (pkey2, 20, PaymentPayloadData::Comment("second".to_string())),
+        ];
+        let (txins, txouts, gamma, fee) = create_payment_transaction(
+            &pkey,
+            &recipients,
+            unspent.into_iter(),
+            MAX_INPUTS,
+            payment_fee,
+            false,
+        )
+        .expect("tx is created");
+        assert_eq!(fee, 3 * payment_fee);
+        assert_eq!(txouts.len(), 3);
+        let tx = PaymentTransaction::new(&chain_id, &skey, &txins, &txouts, &gamma, fee)
+            .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+
+        let expected = [
+            (&skey1, 10, "first"),
+            (&skey2, 20, "second"),
+            (&skey, 67, "Change"),
+        ];
+        for (output, (skey, expected_amount, comment)) in txouts.iter().zip(expected.iter()) {
+            match output {
+                Output::PaymentOutput(o) => {
+                    let PaymentPayload { amount, data, .. } =
+                        o.decrypt_payload(skey).expect("key is valid");
+                    assert_eq!(amount, *expected_amount);
+                    assert_eq!(data, PaymentPayloadData::Comment(comment.to_string()));
+                }
+                _ => panic!("invalid tx"),
+            }
+        }
+
+        // No recipients.
+        let e = create_payment_transaction(
+            &pkey,
+            &[],
+            vec![(&input, 100)].into_iter(),
+            MAX_INPUTS,
+            payment_fee,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.downcast::<WalletError>().unwrap(),
+            WalletError::NoRecipients
+        );
+
+        // Negative amount.
+        let recipients = [
+            (pkey1, 10, PaymentPayloadData::Comment(String::new())),
+            (pkey2, -1, PaymentPayloadData::Comment(String::new())),
+        ];
+        let e = create_payment_transaction(
+            &pkey,
+            &recipients,
+            vec![(&input, 100)].into_iter(),
+            MAX_INPUTS,
+            payment_fee,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.downcast::<WalletError>().unwrap(),
+            WalletError::NegativeAmount(-1)
+        );
+    }
+
+    /// Check padding of payments to standard numbers of outputs.
+    #[test]
+    fn padded_payment() {
+        let payment_fee = 1;
+        let chain_id = Hash::digest("test");
+        let (skey, pkey) = make_random_keys();
+        let (_skey1, pkey1) = make_random_keys();
+        let (input, _gamma) = PaymentOutput::new(&pkey, 100).expect("keys are valid");
+        let inputs = [Output::PaymentOutput(input.clone())];
+
+        for (num_recipients, num_outputs) in &[(1, 2), (2, 4), (3, 4), (4, 5)] {
+            let recipients: Vec<_> = (0..*num_recipients)
+                .map(|_| (pkey1, 10, PaymentPayloadData::Comment(String::new())))
+                .collect();
+            let unspent = vec![(&input, 100)];
+            let (txins, txouts, gamma, fee) = create_payment_transaction(
+                &pkey,
+                &recipients,
+                unspent.into_iter(),
+                MAX_INPUTS,
+                payment_fee,
+                true,
+            )
+            .expect("tx is created");
+            assert_eq!(txouts.len(), *num_outputs);
+            assert_eq!(fee, payment_fee * (*num_outputs as i64));
+            let tx = PaymentTransaction::new(&chain_id, &skey, &txins, &txouts, &gamma, fee)
+                .expect("keys are valid");
+            tx.validate(&inputs).expect("tx is valid");
+        }
+
+        // No change - nothing to pad.
+        let recipients = [(pkey1, 99, PaymentPayloadData::Comment(String::new()))];
+        let (_txins, txouts, _gamma, fee) = create_payment_transaction(
+            &pkey,
+            &recipients,
+            vec![(&input, 100)].into_iter(),
+            MAX_INPUTS,
+            payment_fee,
+            true,
+        )
+        .expect("tx is created");
+        assert_eq!(txouts.len(), 1);
+        assert_eq!(fee, payment_fee);
+    }
+
+    /// Check initiating, redeeming and refunding of an atomic swap.
+    #[test]
+    fn swap_transactions() {
+        let chain_id = Hash::digest("test");
+        let payment_fee: i64 = 1;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let (skey, pkey) = make_random_keys();
+        let (skey1, pkey1) = make_random_keys();
+
+        let (input, _gamma) = PaymentOutput::new(&pkey, 100).expect("keys are valid");
+        let inputs = [Output::PaymentOutput(input.clone())];
+        let unspent = vec![(&input, 100)];
+
+        let preimage = b"secret".to_vec();
+        let hash = Hash::digest(&preimage);
+        let timeout = LockTime::Height(10);
+        let (txins, txouts, gamma, fee) = create_swap_transaction(
+            &pkey,
+            &pkey1,
+            hash,
+            timeout,
+            unspent.into_iter(),
+            MAX_INPUTS,
+            50,
+            payment_fee,
+        )
+        .expect("tx is created");
+        assert_eq!(txouts.len(), 2);
+        let tx = PaymentTransaction::new(&chain_id, &skey, &txins, &txouts, &gamma, fee)
+            .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+        let htlc = match &txouts[0] {
+            Output::PublicPaymentOutput(o) => o.clone(),
+            _ => panic!("invalid tx"),
+        };
+        assert_eq!(htlc.amount, 50);
+        let inputs = [Output::PublicPaymentOutput(htlc.clone())];
+
+        // Redeem by the recipient.
+        let tx = create_swap_spending_transaction(
+            &chain_id,
+            &skey1,
+            &pkey1,
+            &htlc,
+            Some(preimage.clone()),
+            payment_fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+        assert_eq!(tx.preimage(&Hash::digest(&htlc)), Some(&preimage[..]));
+
+        // Refund by the sender.
+        let tx =
+            create_swap_spending_transaction(&chain_id, &skey, &pkey, &htlc, None, payment_fee)
+                .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+        let tx: Transaction = tx.into();
+        assert_eq!(
+            tx.input_lock(&Hash::digest(&htlc), &inputs[0]),
+            Some(timeout)
+        );
+    }
+
+    /// Check funding and settlement of a payment channel.
+    #[test]
+    fn channel_transactions() {
+        let chain_id = Hash::digest("test");
+        let payment_fee: i64 = 1;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let (skey, pkey) = make_random_keys();
+        let (skey1, pkey1) = make_random_keys();
+
+        let (input, _gamma) = PaymentOutput::new(&pkey, 100).expect("keys are valid");
+        let inputs = [Output::PaymentOutput(input.clone())];
+        let unspent = vec![(&input, 100)];
+
+        let (txins, txouts, gamma, fee) = create_channel_funding_transaction(
+            &pkey,
+            &pkey1,
+            10,
+            unspent.into_iter(),
+            MAX_INPUTS,
+            50,
+            payment_fee,
+        )
+        .expect("tx is created");
+        let tx = PaymentTransaction::new(&chain_id, &skey, &txins, &txouts, &gamma, fee)
+            .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+        let funding = match &txouts[0] {
+            Output::PublicPaymentOutput(o) => o.clone(),
+            _ => panic!("invalid tx"),
+        };
+        let channel = Hash::digest(&funding);
+        let inputs = [Output::PublicPaymentOutput(funding.clone())];
+        let mut state = ChannelState::new(channel, pkey, pkey1, 1, 30, 20);
+        state.sign(&skey, &pkey);
+        state.sign(&skey1, &pkey1);
+
+        // Cooperative close.
+        let mut tx = create_channel_close_transaction(
+            &chain_id,
+            &skey,
+            &pkey,
+            &funding,
+            &state,
+            false,
+            payment_fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs).expect_err("not cosigned");
+        tx.cosign(&channel, &skey1);
+        tx.validate(&inputs).expect("tx is valid");
+        create_channel_close_transaction(
+            &chain_id,
+            &skey1,
+            &pkey1,
+            &funding,
+            &state,
+            false,
+            payment_fee,
+        )
+        .expect_err("only the funder");
+
+        // Unilateral close by the counterparty.
+        let tx = create_channel_close_transaction(
+            &chain_id,
+            &skey1,
+            &pkey1,
+            &funding,
+            &state,
+            true,
+            payment_fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+        let dispute = match &tx.txouts[1] {
+            Output::PublicPaymentOutput(o) => o.clone(),
+            _ => panic!("invalid tx"),
+        };
+        assert_eq!(dispute.amount, 20 - payment_fee);
+        let inputs = [Output::PublicPaymentOutput(dispute.clone())];
+
+        // Claim after the dispute period.
+        let tx = create_public_spending_transaction(
+            &chain_id,
+            &skey1,
+            &pkey1,
+            &dispute,
+            None,
+            payment_fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+
+        // Penalty with a newer state.
+        let mut state = ChannelState::new(channel, pkey, pkey1, 2, 40, 10);
+        state.sign(&skey, &pkey);
+        state.sign(&skey1, &pkey1);
+        let tx = create_public_spending_transaction(
+            &chain_id,
+            &skey,
+            &pkey,
+            &dispute,
+            Some(state),
+            payment_fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs).expect("tx is valid");
+    }
+
     /// Check transaction signing and validation.
     #[test]
     fn unstaking_transactions() {
+        let chain_id = Hash::digest("test");
         let payment_fee: i64 = 1;
         let stake_fee: i64 = 1;
         assert!(payment_fee > 0 && stake_fee > 0);
@@ -509,11 +1254,13 @@ pub mod tests {
 
         // Unstake all of the money.
         let tx = create_unstaking_transaction(
+            &chain_id,
             &skey,
             &pkey,
             &validator_pkey,
             &validator_skey,
             unspent.iter(),
+            MAX_INPUTS,
             stake,
             payment_fee,
             stake_fee,
@@ -533,11 +1280,13 @@ pub mod tests {
         // Unstake part of the money.
         let unstake = stake / 2;
         let tx = create_unstaking_transaction(
+            &chain_id,
             &skey,
             &pkey,
             &validator_pkey,
             &validator_skey,
             unspent.iter(),
+            MAX_INPUTS,
             unstake,
             payment_fee,
             stake_fee,
@@ -562,11 +1311,13 @@ pub mod tests {
 
         // Try to unstake less than PAYMENT_FEE.
         let e = create_unstaking_transaction(
+            &chain_id,
             &skey,
             &pkey,
             &validator_pkey,
             &validator_skey,
             unspent.iter(),
+            MAX_INPUTS,
             payment_fee - 1,
             payment_fee,
             stake_fee,
@@ -579,11 +1330,13 @@ pub mod tests {
 
         // Try to unstake PAYMENT_FEE.
         let e = create_unstaking_transaction(
+            &chain_id,
             &skey,
             &pkey,
             &validator_pkey,
             &validator_skey,
             unspent.iter(),
+            MAX_INPUTS,
             payment_fee,
             payment_fee,
             stake_fee,
@@ -597,11 +1350,13 @@ pub mod tests {
         // Try to re-stake zero.
         let unstake = stake - stake_fee;
         let e = create_unstaking_transaction(
+            &chain_id,
             &skey,
             &pkey,
             &validator_pkey,
             &validator_skey,
             unspent.iter(),
+            MAX_INPUTS,
             unstake,
             payment_fee,
             stake_fee,
@@ -618,11 +1373,13 @@ pub mod tests {
         // Try to re-stake PAYMENT_FEE.
         let unstake = stake - payment_fee - stake_fee;
         let e = create_unstaking_transaction(
+            &chain_id,
             &skey,
             &pkey,
             &validator_pkey,
             &validator_skey,
             unspent.iter(),
+            MAX_INPUTS,
             unstake,
             payment_fee,
             stake_fee,
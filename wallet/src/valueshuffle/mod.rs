@@ -165,6 +165,8 @@ enum State {
 
 /// ValueShuffle Service.
 pub struct ValueShuffle {
+    /// Identifier of the chain.
+    chain_id: Hash,
     /// Wallet's Curve1174 Secret Key.
     skey: SecretKey,
     /// Faciliator's PBC public key
@@ -323,6 +325,7 @@ impl ValueShuffle {
 
     /// Create a new ValueShuffle instance.
     pub fn new(
+        chain_id: Hash,
         skey: SecretKey,
         pkey: PublicKey,
         participant_pkey: ParticipantID,
@@ -372,6 +375,7 @@ impl ValueShuffle {
         let events = select_all(events);
 
         ValueShuffle {
+            chain_id,
             skey: skey.clone(),
             facilitator_pkey,
             state,
@@ -1569,7 +1573,14 @@ impl ValueShuffle {
         let outputs = map_to_outputs(utxos);
 
         PaymentTransaction::new_super_transaction(
-            my_skey, my_k, K_val, &inputs, &outputs, gamma_adj, total_fee,
+            &self.chain_id,
+            my_skey,
+            my_k,
+            K_val,
+            &inputs,
+            &outputs,
+            gamma_adj,
+            total_fee,
         )
         .expect("Can't construct the super-transaction")
     }
@@ -0,0 +1,346 @@
+//
+// Copyright (c) 2019 Stegos AG
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//!
+//! Watch-only tracking of deposits.
+//!
+//! Payment outputs are cloaked and their payload can be decrypted only with the secret key,
+//! so there is no view key. Deposits are detected using public data only:
+//! public payments to watched public keys and outputs with expected hashes.
+//!
+
+use crate::api::DepositInfo;
+use crate::error::WalletError;
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use stegos_blockchain::Output;
+use stegos_crypto::curve1174::PublicKey;
+use stegos_crypto::hash::Hash;
+use stegos_node::OutputsChanged;
+
+/// Changes of tracked deposits, produced by `WatchList::on_outputs_changed()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DepositEvent {
+    /// A new deposit appeared in the blockchain.
+    Received(DepositInfo),
+    /// A deposit reached the required number of confirmations.
+    Confirmed(DepositInfo),
+    /// A deposit has been spent.
+    Spent(DepositInfo),
+    /// A block with a deposit has been reverted.
+    Reverted(DepositInfo),
+}
+
+/// A persistent list of watched public keys and expected outputs.
+pub(crate) struct WatchList {
+    /// Path to the file. Empty path disables persistence.
+    /// One entry per line: "pkey HEX" or "utxo HEX [AMOUNT]".
+    path: PathBuf,
+    /// Recipients of watched public payments.
+    pkeys: HashSet<PublicKey>,
+    /// Expected outputs with amounts, if known.
+    expected: HashMap<Hash, Option<i64>>,
+    /// Number of confirmations to consider a deposit as final.
+    confirmations: u64,
+    /// Height of the last applied block.
+    height: u64,
+    /// Unspent deposits.
+    deposits: HashMap<Hash, DepositInfo>,
+}
+
+impl WatchList {
+    /// Load the watch list from the file.
+    /// A missing file is treated as an empty list.
+    pub fn load(path: &str, confirmations: u64) -> Result<Self, WalletError> {
+        let path = PathBuf::from(path);
+        let mut watch = WatchList {
+            path,
+            pkeys: HashSet::new(),
+            expected: HashMap::new(),
+            confirmations,
+            height: 0,
+            deposits: HashMap::new(),
+        };
+        if watch.path.as_os_str().is_empty() {
+            return Ok(watch);
+        }
+
+        let contents = match fs::read_to_string(&watch.path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(watch.io_error(e)),
+        };
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                WalletError::InvalidWatchList(watch.path.to_string_lossy().to_string(), lineno + 1)
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match &fields[..] {
+                ["pkey", pkey] => {
+                    let pkey = pkey.parse::<PublicKey>().map_err(|_e| invalid())?;
+                    watch.pkeys.insert(pkey);
+                }
+                ["utxo", utxo] => {
+                    let utxo = Hash::try_from_hex(utxo).map_err(|_e| invalid())?;
+                    watch.expected.insert(utxo, None);
+                }
+                ["utxo", utxo, amount] => {
+                    let utxo = Hash::try_from_hex(utxo).map_err(|_e| invalid())?;
+                    let amount: i64 = amount.parse().map_err(|_e| invalid())?;
+                    watch.expected.insert(utxo, Some(amount));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        debug!(
+            "Loaded watch list: path={:?}, pkeys={}, outputs={}",
+            watch.path,
+            watch.pkeys.len(),
+            watch.expected.len()
+        );
+        Ok(watch)
+    }
+
+    /// Watch public payments to the key.
+    pub fn watch_pkey(&mut self, pkey: PublicKey) -> Result<(), WalletError> {
+        if self.pkeys.insert(pkey) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Watch the output with the given hash.
+    pub fn watch_output(&mut self, utxo: Hash, amount: Option<i64>) -> Result<(), WalletError> {
+        if self.expected.insert(utxo, amount) != Some(amount) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Height of the last applied block.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Unspent deposits, sorted by height.
+    pub fn deposits(&self) -> Vec<DepositInfo> {
+        let mut deposits: Vec<DepositInfo> = self.deposits.values().cloned().collect();
+        deposits.sort_by_key(|d| (d.height, d.utxo));
+        deposits
+    }
+
+    /// Returns a deposit if the output matches the watch list.
+    fn match_output(&self, output: &Output, height: u64) -> Option<DepositInfo> {
+        let utxo = Hash::digest(output);
+        let (recipient, amount) = match output {
+            Output::PublicPaymentOutput(o) if self.pkeys.contains(&o.recipient) => {
+                (Some(o.recipient), Some(o.amount))
+            }
+            _ => match self.expected.get(&utxo) {
+                Some(amount) => (None, *amount),
+                None => return None,
+            },
+        };
+        Some(DepositInfo {
+            utxo,
+            recipient,
+            amount,
+            height,
+            confirmations: 1,
+            confirmed: false,
+        })
+    }
+
+    /// Process a block added to or reverted from the blockchain.
+    pub fn on_outputs_changed(&mut self, changed: &OutputsChanged) -> Vec<DepositEvent> {
+        let mut events = Vec::new();
+        if changed.reverted {
+            // Forget deposits created by the reverted block.
+            for output in &changed.inputs {
+                let utxo = Hash::digest(output);
+                if let Some(deposit) = self.deposits.remove(&utxo) {
+                    warn!("Deposit reverted: utxo={}", utxo);
+                    events.push(DepositEvent::Reverted(deposit));
+                }
+            }
+            self.height = changed.height.saturating_sub(1);
+            // Recover deposits spent in the reverted block.
+            for output in &changed.outputs {
+                if let Some(mut deposit) = self.match_output(output, 0) {
+                    // The original height is unknown, treat as confirmed.
+                    deposit.height = self.height;
+                    deposit.confirmations = self.confirmations;
+                    deposit.confirmed = true;
+                    self.deposits.insert(deposit.utxo, deposit);
+                }
+            }
+            return events;
+        }
+
+        self.height = changed.height;
+        for input in &changed.inputs {
+            let utxo = Hash::digest(input);
+            if let Some(deposit) = self.deposits.remove(&utxo) {
+                info!("Deposit spent: utxo={}", utxo);
+                events.push(DepositEvent::Spent(deposit));
+            }
+        }
+        for output in &changed.outputs {
+            if let Some(deposit) = self.match_output(output, changed.height) {
+                if self.deposits.contains_key(&deposit.utxo) {
+                    continue;
+                }
+                info!(
+                    "Deposit received: utxo={}, amount={:?}, height={}",
+                    deposit.utxo, deposit.amount, deposit.height
+                );
+                events.push(DepositEvent::Received(deposit.clone()));
+                self.deposits.insert(deposit.utxo, deposit);
+            }
+        }
+
+        // Update confirmations.
+        let height = self.height;
+        let required = self.confirmations;
+        for deposit in self.deposits.values_mut() {
+            deposit.confirmations = height.saturating_sub(deposit.height) + 1;
+            if !deposit.confirmed && deposit.confirmations >= required {
+                deposit.confirmed = true;
+                info!(
+                    "Deposit confirmed: utxo={}, confirmations={}",
+                    deposit.utxo, deposit.confirmations
+                );
+                events.push(DepositEvent::Confirmed(deposit.clone()));
+            }
+        }
+        events
+    }
+
+    fn io_error(&self, e: io::Error) -> WalletError {
+        WalletError::WatchListIOError(self.path.to_string_lossy().to_string(), e.to_string())
+    }
+
+    fn save(&self) -> Result<(), WalletError> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let mut contents = String::new();
+        for pkey in &self.pkeys {
+            contents.push_str(&format!("pkey {}\n", pkey.to_hex()));
+        }
+        for (utxo, amount) in &self.expected {
+            match amount {
+                Some(amount) => contents.push_str(&format!("utxo {} {}\n", utxo.to_hex(), amount)),
+                None => contents.push_str(&format!("utxo {}\n", utxo.to_hex())),
+            }
+        }
+        fs::write(&self.path, contents).map_err(|e| self.io_error(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::env;
+    use std::time::SystemTime;
+    use stegos_blockchain::PublicPaymentOutput;
+    use stegos_crypto::curve1174::make_random_keys;
+
+    fn changed(
+        height: u64,
+        reverted: bool,
+        inputs: Vec<Output>,
+        outputs: Vec<Output>,
+    ) -> OutputsChanged {
+        OutputsChanged {
+            epoch: 0,
+            height,
+            timestamp: SystemTime::now(),
+            reverted,
+            inputs,
+            outputs,
+            tx_hashes: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn deposits() {
+        let path = env::temp_dir().join(format!("stegos-watch-{}", Hash::digest(&"test")));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+
+        let (_skey, pkey) = make_random_keys();
+        let (_skey2, pkey2) = make_random_keys();
+        let public: Output = PublicPaymentOutput::new(&pkey, 100).into();
+        let expected: Output = PublicPaymentOutput::new(&pkey2, 50).into();
+        let other: Output = PublicPaymentOutput::new(&pkey2, 10).into();
+
+        let mut watch = WatchList::load(&path, 2).expect("missing file is ok");
+        watch.watch_pkey(pkey).unwrap();
+        watch
+            .watch_output(Hash::digest(&expected), Some(50))
+            .unwrap();
+
+        // Reload from disk.
+        let mut watch = WatchList::load(&path, 2).expect("file is valid");
+        let events = watch.on_outputs_changed(&changed(
+            1,
+            false,
+            vec![],
+            vec![public.clone(), expected.clone(), other],
+        ));
+        assert_eq!(events.len(), 2);
+        assert_eq!(watch.deposits().len(), 2);
+        assert!(watch.deposits().iter().all(|d| !d.confirmed));
+
+        let events = watch.on_outputs_changed(&changed(2, false, vec![expected.clone()], vec![]));
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            DepositEvent::Spent(d) => assert_eq!(d.amount, Some(50)),
+            e => panic!("unexpected event: {:?}", e),
+        }
+        match &events[1] {
+            DepositEvent::Confirmed(d) => {
+                assert_eq!(d.recipient, Some(pkey));
+                assert_eq!(d.confirmations, 2);
+            }
+            e => panic!("unexpected event: {:?}", e),
+        }
+
+        // Revert both blocks.
+        let events = watch.on_outputs_changed(&changed(2, true, vec![], vec![expected.clone()]));
+        assert!(events.is_empty());
+        assert_eq!(watch.deposits().len(), 2);
+        let events = watch.on_outputs_changed(&changed(1, true, vec![public, expected], vec![]));
+        assert_eq!(events.len(), 2);
+        assert!(watch.deposits().is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+}